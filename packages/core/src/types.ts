@@ -59,12 +59,23 @@ export const SandboxConstraints = z.object({
 });
 export type SandboxConstraints = z.infer<typeof SandboxConstraints>;
 
+export const ExitReason = z.enum([
+  'completed',
+  'oom_killed',
+  'timeout',
+  'crashed',
+  'preempted',
+  'quarantined',
+]);
+export type ExitReason = z.infer<typeof ExitReason>;
+
 export const SandboxResult = z.object({
   id: z.string(),
   provider: SandboxProvider,
   stdout: z.string(),
   stderr: z.string(),
   exitCode: z.number(),
+  exitReason: ExitReason.optional(), // Why the workload stopped, beyond its exit code
   duration: z.number(),
   cost: z.number(),
   files: z.record(z.string()).optional(),
@@ -150,6 +161,7 @@ export const SecurityEventType = z.enum([
   'resource_limit',
   'suspicious_behavior',
   'policy_violation',
+  'sandbox_escape',
   'quarantine',
   'compliance_check'
 ]);
@@ -1,22 +1,41 @@
+import { randomUUID } from 'crypto';
+import axios from 'axios';
 import {
   EdgeAgentStatus,
   EdgeAgentMetrics,
+  EdgeBatchAck,
+  EdgeBatchDelivery,
+  EdgeStream,
   EdgeTelemetryClient,
   LogEntry,
   SandboxRunTelemetry,
 } from '@sandstorm/telemetry';
 import { TelemetryRelay } from './types';
 
+// Unacknowledged batches kept per stream while the collector is unreachable
+const MAX_PENDING_BATCHES = 100;
+
+interface StreamQueue<T> {
+  // Items not yet put in a batch
+  buffer: T[];
+  // Numbered batches awaiting acknowledgment, oldest first
+  pending: { sequence: number; items: T[] }[];
+  nextSequence: number;
+  batchSize: number;
+  sending: boolean;
+  send(items: T[], delivery: EdgeBatchDelivery): Promise<EdgeBatchAck | undefined>;
+}
+
+// Sends telemetry in numbered batches and keeps each batch until the
+// collector acknowledges it, so batches lost while offline are resent exactly
+// once when connectivity returns
 export class CloudTelemetryRelay implements TelemetryRelay {
   private client: EdgeTelemetryClient;
-  private buffer: {
-    status: EdgeAgentStatus[];
-    metrics: EdgeAgentMetrics[];
-    logs: LogEntry[];
-  } = {
-    status: [],
-    metrics: [],
-    logs: [],
+  private sessionId = randomUUID();
+  private streams: {
+    status: StreamQueue<EdgeAgentStatus>;
+    metrics: StreamQueue<EdgeAgentMetrics>;
+    logs: StreamQueue<LogEntry>;
   };
   
   constructor(
@@ -33,86 +52,119 @@ export class CloudTelemetryRelay implements TelemetryRelay {
       apiKey: config.apiKey,
       agentId: config.agentId,
     });
+    this.streams = {
+      status: this.queue(config.batchSize || 10, (items, delivery) => this.client.sendStatus(items, delivery)),
+      metrics: this.queue(config.batchSize || 50, (items, delivery) => this.client.sendMetrics(items, delivery)),
+      logs: this.queue(config.batchSize || 100, (items, delivery) => this.client.sendLogs(items, delivery)),
+    };
     
     // Start periodic flush
     setInterval(() => this.flush(), config.flushInterval || 30000);
   }
   
   async sendStatus(status: EdgeAgentStatus): Promise<void> {
-    this.buffer.status.push(status);
-    
-    if (this.buffer.status.length >= (this.config.batchSize || 10)) {
-      await this.flushStatus();
-    }
+    await this.enqueue('status', [status]);
   }
   
   async sendMetrics(metrics: EdgeAgentMetrics): Promise<void> {
-    this.buffer.metrics.push(metrics);
-    
-    if (this.buffer.metrics.length >= (this.config.batchSize || 50)) {
-      await this.flushMetrics();
-    }
+    await this.enqueue('metrics', [metrics]);
   }
   
   async sendLogs(logs: LogEntry[]): Promise<void> {
-    this.buffer.logs.push(...logs);
-    
-    if (this.buffer.logs.length >= (this.config.batchSize || 100)) {
-      await this.flushLogs();
-    }
+    await this.enqueue('logs', logs);
   }
 
   async sendSandboxRun(payload: { telemetry: SandboxRunTelemetry }): Promise<void> {
     await this.client.sendSandboxRun(payload.telemetry);
   }
 
+  private queue<T>(
+    batchSize: number,
+    send: StreamQueue<T>['send'],
+  ): StreamQueue<T> {
+    return { buffer: [], pending: [], nextSequence: 1, batchSize, sending: false, send };
+  }
+
+  private async enqueue<T>(stream: EdgeStream, items: T[]): Promise<void> {
+    const queue = this.streams[stream] as unknown as StreamQueue<T>;
+    queue.buffer.push(...items);
+    
+    if (queue.buffer.length >= queue.batchSize) {
+      await this.flushStream(stream);
+    }
+  }
+
   private async flush(): Promise<void> {
     await Promise.allSettled([
-      this.flushStatus(),
-      this.flushMetrics(),
-      this.flushLogs(),
+      this.flushStream('status'),
+      this.flushStream('metrics'),
+      this.flushStream('logs'),
     ]);
   }
   
-  private async flushStatus(): Promise<void> {
-    if (this.buffer.status.length === 0) return;
-    
-    const items = this.buffer.status.splice(0);
+  private async flushStream(stream: EdgeStream): Promise<void> {
+    const queue = this.streams[stream] as StreamQueue<unknown>;
+    if (queue.buffer.length > 0) {
+      queue.pending.push({ sequence: queue.nextSequence++, items: queue.buffer.splice(0) });
+      if (queue.pending.length > MAX_PENDING_BATCHES) {
+        // The oldest batch is lost; the collector would wait for it forever
+        queue.pending.shift();
+        this.startSession();
+      }
+    }
+    if (queue.sending) return;
     
+    queue.sending = true;
+    let resynced = false;
     try {
-      await this.client.sendStatus(items);
-    } catch (error) {
-      // Re-add items to buffer on failure
-      this.buffer.status.unshift(...items.slice(-10)); // Keep only last 10
-      console.error('Failed to send status telemetry:', error);
+      while (queue.pending.length > 0) {
+        const batch = queue.pending[0];
+        try {
+          const ack = await queue.send(batch.items, { sessionId: this.sessionId, sequence: batch.sequence });
+          this.acknowledge(queue, ack?.acknowledged ?? batch.sequence);
+        } catch (error) {
+          if (!resynced && axios.isAxiosError(error) && error.response?.status === 409) {
+            resynced = true;
+            await this.resync(stream);
+            continue;
+          }
+          console.error(`Failed to send ${stream} telemetry:`, error);
+          return;
+        }
+      }
+    } finally {
+      queue.sending = false;
     }
   }
-  
-  private async flushMetrics(): Promise<void> {
-    if (this.buffer.metrics.length === 0) return;
-    
-    const items = this.buffer.metrics.splice(0);
-    
-    try {
-      await this.client.sendMetrics(items);
-    } catch (error) {
-      // Re-add items to buffer on failure
-      this.buffer.metrics.unshift(...items.slice(-50)); // Keep only last 50
-      console.error('Failed to send metrics telemetry:', error);
+
+  private acknowledge(queue: StreamQueue<unknown>, acknowledged: number): void {
+    while (queue.pending.length > 0 && queue.pending[0].sequence <= acknowledged) {
+      queue.pending.shift();
     }
   }
-  
-  private async flushLogs(): Promise<void> {
-    if (this.buffer.logs.length === 0) return;
-    
-    const items = this.buffer.logs.splice(0);
-    
-    try {
-      await this.client.sendLogs(items);
-    } catch (error) {
-      // Re-add items to buffer on failure
-      this.buffer.logs.unshift(...items.slice(-100)); // Keep only last 100
-      console.error('Failed to send logs telemetry:', error);
+
+  // Drop what the collector already stored, after it refused a batch out of
+  // order. If the batches it still needs are gone, start a new session.
+  private async resync(stream: EdgeStream): Promise<void> {
+    const queue = this.streams[stream] as StreamQueue<unknown>;
+    const cursor = (await this.client.getCursors()).find(
+      (cursor) => cursor.stream === stream && cursor.sessionId === this.sessionId,
+    );
+    const acknowledged = cursor?.sequence ?? 0;
+    this.acknowledge(queue, acknowledged);
+    if (queue.pending.length > 0 && queue.pending[0].sequence !== acknowledged + 1) {
+      this.startSession();
+    }
+  }
+
+  // Number every stream's pending batches from 1 under a new session
+  private startSession(): void {
+    this.sessionId = randomUUID();
+    for (const queue of Object.values(this.streams) as StreamQueue<unknown>[]) {
+      queue.pending.forEach((batch, index) => {
+        batch.sequence = index + 1;
+      });
+      queue.nextSequence = queue.pending.length + 1;
     }
   }
   
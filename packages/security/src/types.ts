@@ -10,6 +10,7 @@ export const SecurityEventType = z.enum([
   'resource_limit',
   'suspicious_behavior',
   'policy_violation',
+  'sandbox_escape',
   'quarantine',
   'compliance_check'
 ]);
@@ -1,6 +1,11 @@
 import axios, { AxiosInstance } from 'axios';
 import {
   EdgeAgentMetrics,
+  EdgeBatchAck,
+  EdgeBatchAckSchema,
+  EdgeBatchDelivery,
+  EdgeStreamCursor,
+  EdgeStreamCursorSchema,
   EdgeMetricsBatchSchema,
   EdgeAgentStatus,
   EdgeStatusBatchSchema,
@@ -34,31 +39,51 @@ export class EdgeTelemetryClient {
     this.agentId = options.agentId;
   }
 
-  async sendStatus(items: EdgeAgentStatus[]): Promise<void> {
-    if (items.length === 0) return;
+  // Batches sent with a delivery position are stored at most once, in order;
+  // the collector rejects one past the next expected with 409 Conflict
+  async sendStatus(items: EdgeAgentStatus[], delivery?: EdgeBatchDelivery): Promise<EdgeBatchAck | undefined> {
+    if (items.length === 0) return undefined;
     const payload = EdgeStatusBatchSchema.parse({
       items,
       timestamp: new Date().toISOString(),
+      ...delivery,
     });
-    await this.client.post('/v1/edge/status', payload);
+    return this.postBatch('/v1/edge/status', payload);
   }
 
-  async sendMetrics(items: EdgeAgentMetrics[]): Promise<void> {
-    if (items.length === 0) return;
+  async sendMetrics(items: EdgeAgentMetrics[], delivery?: EdgeBatchDelivery): Promise<EdgeBatchAck | undefined> {
+    if (items.length === 0) return undefined;
     const payload = EdgeMetricsBatchSchema.parse({
       items,
       timestamp: new Date().toISOString(),
+      ...delivery,
     });
-    await this.client.post('/v1/edge/metrics', payload);
+    return this.postBatch('/v1/edge/metrics', payload);
   }
 
-  async sendLogs(items: LogEntry[]): Promise<void> {
-    if (items.length === 0) return;
+  async sendLogs(items: LogEntry[], delivery?: EdgeBatchDelivery): Promise<EdgeBatchAck | undefined> {
+    if (items.length === 0) return undefined;
     const payload = EdgeLogBatchSchema.parse({
       items,
       timestamp: new Date().toISOString(),
+      ...delivery,
     });
-    await this.client.post('/v1/edge/logs', payload);
+    return this.postBatch('/v1/edge/logs', payload);
+  }
+
+  // High-water marks of this agent's sequenced streams
+  async getCursors(): Promise<EdgeStreamCursor[]> {
+    if (!this.agentId) {
+      throw new Error('getCursors needs the client to be created with an agentId');
+    }
+    const response = await this.client.get(`/v1/edge/agents/${encodeURIComponent(this.agentId)}/cursors`);
+    return EdgeStreamCursorSchema.array().parse(response.data);
+  }
+
+  private async postBatch(path: string, payload: unknown): Promise<EdgeBatchAck | undefined> {
+    const response = await this.client.post(path, payload);
+    const ack = EdgeBatchAckSchema.safeParse(response.data);
+    return ack.success ? ack.data : undefined;
   }
 
   async sendSandboxRun(run: SandboxRunTelemetry): Promise<void> {
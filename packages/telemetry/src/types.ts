@@ -88,22 +88,47 @@ export const EdgeAgentMetricsSchema = z.object({
 });
 export type EdgeAgentMetrics = z.infer<typeof EdgeAgentMetricsSchema>;
 
+// Position of a batch in its stream, for acknowledged delivery. Each agent
+// session numbers every stream from 1.
+export const EdgeBatchDeliverySchema = z.object({
+  sessionId: z.string().min(1).max(64),
+  sequence: z.number().int().positive(),
+});
+export type EdgeBatchDelivery = z.infer<typeof EdgeBatchDeliverySchema>;
+
+export const EdgeBatchAckSchema = z.object({
+  acknowledged: z.number().int().nullable(),
+  duplicate: z.boolean(),
+});
+export type EdgeBatchAck = z.infer<typeof EdgeBatchAckSchema>;
+
+export const EdgeStreamSchema = z.enum(['status', 'metrics', 'logs']);
+export type EdgeStream = z.infer<typeof EdgeStreamSchema>;
+
+export const EdgeStreamCursorSchema = z.object({
+  stream: EdgeStreamSchema,
+  sessionId: z.string(),
+  sequence: z.number().int(),
+  updatedAt: z.string().datetime(),
+});
+export type EdgeStreamCursor = z.infer<typeof EdgeStreamCursorSchema>;
+
 export const EdgeStatusBatchSchema = z.object({
   items: z.array(EdgeAgentStatusSchema),
   timestamp: z.string().datetime(),
-});
+}).merge(EdgeBatchDeliverySchema.partial());
 export type EdgeStatusBatch = z.infer<typeof EdgeStatusBatchSchema>;
 
 export const EdgeMetricsBatchSchema = z.object({
   items: z.array(EdgeAgentMetricsSchema),
   timestamp: z.string().datetime(),
-});
+}).merge(EdgeBatchDeliverySchema.partial());
 export type EdgeMetricsBatch = z.infer<typeof EdgeMetricsBatchSchema>;
 
 export const EdgeLogBatchSchema = z.object({
   items: z.array(LogEntrySchema),
   timestamp: z.string().datetime(),
-});
+}).merge(EdgeBatchDeliverySchema.partial());
 export type EdgeLogBatch = z.infer<typeof EdgeLogBatchSchema>;
 
 export const SandboxRunTelemetrySchema = z.object({
@@ -122,6 +147,10 @@ export const SandboxRunTelemetrySchema = z.object({
   networkRxBytes: z.number().nullable().optional(),
   networkTxBytes: z.number().nullable().optional(),
   agentId: z.string().optional(),
+  // Tenant billed for the run; the collector uses `default` when unset
+  tenant: z.string().optional(),
+  // Attribution labels (team, project, job, ...) usage reports can group by
+  labels: z.record(z.string()).optional(),
   timestamp: z.string().datetime(),
   spec: z.any().optional(),
   result: z.any().optional(),
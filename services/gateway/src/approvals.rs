@@ -0,0 +1,716 @@
+//! Runs that need a person's sign-off. A run request matching one of the
+//! rules in `GATEWAY_APPROVAL_RULES` creates nothing: it is held as a
+//! pending approval until an approver approves it, which starts the run,
+//! rejects it, or it expires. Each step is kept on the approval as an audit
+//! record. Approvers are known by the bearer token `GATEWAY_APPROVERS` gives
+//! them, not by the user they claim to be.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path as FsPath, PathBuf};
+use tokio::{fs, sync::RwLock};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::otlp::TraceContext;
+use crate::recording::ANONYMOUS_USER;
+use crate::runtime::GvisorNetwork;
+use crate::{launch, security, AppState, RunSandboxRequest, RunSandboxResponse};
+
+const DEFAULT_TTL_SECS: i64 = 3600;
+
+/// What makes a run wait for an approver
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalRule {
+    /// Writable host mounts, or mounts of sensitive paths or sockets
+    PrivilegedMounts,
+    /// Any host mount
+    HostPaths,
+    /// gVisor's `host` network stack
+    OpenNetwork,
+    /// A language image or template snapshot missing from
+    /// `GATEWAY_APPROVAL_IMAGES`
+    UnlistedImage,
+}
+
+impl std::str::FromStr for ApprovalRule {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "privileged_mounts" => Ok(Self::PrivilegedMounts),
+            "host_paths" => Ok(Self::HostPaths),
+            "open_network" => Ok(Self::OpenNetwork),
+            "unlisted_image" => Ok(Self::UnlistedImage),
+            other => anyhow::bail!("unknown approval rule '{}'", other),
+        }
+    }
+}
+
+/// A rule a run request matched, and what in it matched
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ApprovalReason {
+    pub rule: ApprovalRule,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalStatus {
+    Pending,
+    Approved,
+    Rejected,
+    /// Nobody decided before `expires_at`
+    Expired,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Requested,
+    Approved,
+    Rejected,
+    Expired,
+    /// The approved run's sandbox started
+    Started,
+    /// The approved run failed to start
+    Failed,
+}
+
+/// One step in an approval's life
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuditRecord {
+    pub at: DateTime<Utc>,
+    /// User that took the step, or `gateway`
+    pub actor: String,
+    pub action: AuditAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// A run request held for an approver
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Approval {
+    pub id: Uuid,
+    /// Run the request starts once approved
+    pub run_id: Uuid,
+    pub status: ApprovalStatus,
+    pub reasons: Vec<ApprovalReason>,
+    pub request: RunSandboxRequest,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub requested_by: String,
+    /// Approver whose token the request came with. `requested_by` is only
+    /// what the caller claims, so this is what `OwnRequest` checks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_by_approver: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Sandbox the approved run started in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox_id: Option<Uuid>,
+    pub audit: Vec<AuditRecord>,
+}
+
+impl Approval {
+    fn record(&mut self, actor: &str, action: AuditAction, comment: Option<String>) {
+        self.audit.push(AuditRecord {
+            at: Utc::now(),
+            actor: actor.to_string(),
+            action,
+            comment,
+        });
+    }
+}
+
+/// Which run requests need approving, and who may approve them
+#[derive(Debug, Clone, Default)]
+pub struct ApprovalPolicy {
+    pub rules: Vec<ApprovalRule>,
+    /// Language images (`sandstorm/python`) and template snapshot IDs runs
+    /// may use without approval under `unlisted_image`
+    pub images: Vec<String>,
+    /// Who may decide; nobody when empty
+    pub approvers: Vec<Approver>,
+    pub ttl: chrono::Duration,
+}
+
+/// A user who may decide on held runs, and the token they decide with
+#[derive(Clone, PartialEq)]
+pub struct Approver {
+    pub name: String,
+    pub token: String,
+}
+
+impl std::fmt::Debug for Approver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Approver").field("name", &self.name).finish_non_exhaustive()
+    }
+}
+
+/// Compare tokens in time independent of where they differ
+fn same_token(presented: &str, token: &str) -> bool {
+    presented.len() == token.len()
+        && presented
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |differ, (a, b)| differ | (a ^ b))
+            == 0
+}
+
+impl ApprovalPolicy {
+    /// Comma-separated `GATEWAY_APPROVAL_RULES`, `GATEWAY_APPROVAL_IMAGES`
+    /// and `GATEWAY_APPROVERS` (`name=token`), with approvals expiring after
+    /// `GATEWAY_APPROVAL_TTL_SECS` (default an hour). Rules without
+    /// approvers are refused, since nothing they hold could be approved.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let list = |name: &str| -> Vec<String> {
+            std::env::var(name)
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        let rules = list("GATEWAY_APPROVAL_RULES")
+            .iter()
+            .map(|rule| rule.parse())
+            .collect::<anyhow::Result<Vec<ApprovalRule>>>()?;
+        let images = list("GATEWAY_APPROVAL_IMAGES");
+        if rules.contains(&ApprovalRule::UnlistedImage) && images.is_empty() {
+            anyhow::bail!("the unlisted_image approval rule needs GATEWAY_APPROVAL_IMAGES");
+        }
+        let approvers = list("GATEWAY_APPROVERS")
+            .iter()
+            .map(|entry| {
+                let (name, token) = entry
+                    .split_once('=')
+                    .map(|(name, token)| (name.trim(), token.trim()))
+                    .filter(|(name, token)| !name.is_empty() && !token.is_empty() && *name != ANONYMOUS_USER)
+                    .ok_or_else(|| anyhow::anyhow!("invalid approver {:?}, expected name=token", entry))?;
+                Ok(Approver {
+                    name: name.to_string(),
+                    token: token.to_string(),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if !rules.is_empty() && approvers.is_empty() {
+            anyhow::bail!("GATEWAY_APPROVAL_RULES needs GATEWAY_APPROVERS to decide on the runs it holds");
+        }
+        let ttl_secs = match std::env::var("GATEWAY_APPROVAL_TTL_SECS") {
+            Ok(value) => value
+                .parse::<i64>()
+                .ok()
+                .filter(|secs| *secs > 0)
+                .ok_or_else(|| anyhow::anyhow!("GATEWAY_APPROVAL_TTL_SECS must be a positive number of seconds"))?,
+            Err(_) => DEFAULT_TTL_SECS,
+        };
+        Ok(Self {
+            rules,
+            images,
+            approvers,
+            ttl: chrono::Duration::seconds(ttl_secs),
+        })
+    }
+
+    /// The approver whose token the caller presents as a bearer token
+    pub fn approver(&self, headers: &HeaderMap) -> Option<&str> {
+        let presented = headers
+            .get(axum::http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))?;
+        self.approvers
+            .iter()
+            .find(|approver| same_token(presented.trim(), &approver.token))
+            .map(|approver| approver.name.as_str())
+    }
+
+    /// Rules a run request matches
+    pub fn reasons(&self, req: &RunSandboxRequest) -> Vec<ApprovalReason> {
+        let mounts = req.mounts.as_deref().unwrap_or_default();
+        let mut reasons = Vec::new();
+        for rule in &self.rules {
+            let detail = match rule {
+                ApprovalRule::PrivilegedMounts => {
+                    let privileged: Vec<_> = mounts
+                        .iter()
+                        .filter(|mount| security::is_privileged_mount(&mount.source, mount.read_only))
+                        .map(|mount| mount.source.as_str())
+                        .collect();
+                    (!privileged.is_empty()).then(|| format!("privileged host mounts: {}", privileged.join(", ")))
+                }
+                ApprovalRule::HostPaths => (!mounts.is_empty()).then(|| {
+                    let sources: Vec<_> = mounts.iter().map(|mount| mount.source.as_str()).collect();
+                    format!("host mounts: {}", sources.join(", "))
+                }),
+                ApprovalRule::OpenNetwork => (req.gvisor.network == Some(GvisorNetwork::Host))
+                    .then(|| "gVisor host network".to_string()),
+                ApprovalRule::UnlistedImage => {
+                    let image = match req.template_snapshot {
+                        Some(snapshot) => snapshot.to_string(),
+                        None => format!("sandstorm/{}", req.language),
+                    };
+                    (!self.images.contains(&image)).then(|| format!("image {} is not allow-listed", image))
+                }
+            };
+            if let Some(detail) = detail {
+                reasons.push(ApprovalReason { rule: *rule, detail });
+            }
+        }
+        reasons
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecisionError {
+    #[error("Approval {0} not found")]
+    NotFound(Uuid),
+    #[error("Approval is already {0:?}")]
+    Decided(ApprovalStatus),
+    #[error("Deciding on runs needs an approver's token")]
+    Unauthenticated,
+    #[error("{0} may not approve runs")]
+    NotApprover(String),
+    #[error("Runs can't be approved by whoever requested them")]
+    OwnRequest,
+    #[error("Failed to save approvals: {0}")]
+    Storage(anyhow::Error),
+}
+
+impl DecisionError {
+    fn status(&self) -> StatusCode {
+        match self {
+            DecisionError::NotFound(_) => StatusCode::NOT_FOUND,
+            DecisionError::Decided(_) => StatusCode::CONFLICT,
+            DecisionError::Unauthenticated => StatusCode::UNAUTHORIZED,
+            DecisionError::NotApprover(_) | DecisionError::OwnRequest => StatusCode::FORBIDDEN,
+            DecisionError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Approvals past and pending, persisted as JSON in `<root>/approvals.json`
+#[derive(Debug)]
+pub struct Approvals {
+    root: PathBuf,
+    pub policy: ApprovalPolicy,
+    approvals: RwLock<HashMap<Uuid, Approval>>,
+}
+
+impl Approvals {
+    /// Approvals stored under `GATEWAY_APPROVALS_PATH` (default
+    /// `./data/approvals`), under [`ApprovalPolicy::from_env`]
+    pub async fn from_env() -> anyhow::Result<Self> {
+        let root = std::env::var("GATEWAY_APPROVALS_PATH").unwrap_or_else(|_| "./data/approvals".to_string());
+        Self::open(root, ApprovalPolicy::from_env()?).await
+    }
+
+    pub async fn open<P: AsRef<FsPath>>(root: P, policy: ApprovalPolicy) -> anyhow::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root).await?;
+
+        let index = root.join("approvals.json");
+        let approvals: Vec<Approval> = match fs::read(&index).await {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|e| anyhow::anyhow!("failed to load {}: {}", index.display(), e))?,
+            Err(_) => Vec::new(),
+        };
+        Ok(Self {
+            root,
+            policy,
+            approvals: RwLock::new(approvals.into_iter().map(|approval| (approval.id, approval)).collect()),
+        })
+    }
+
+    /// Hold a run request for an approver, for the rules it matched
+    pub async fn hold(
+        &self,
+        request: RunSandboxRequest,
+        reasons: Vec<ApprovalReason>,
+        run_id: Uuid,
+        tenant: Option<String>,
+        requested_by: String,
+        requested_by_approver: Option<String>,
+    ) -> anyhow::Result<Approval> {
+        let now = Utc::now();
+        let mut approval = Approval {
+            id: Uuid::new_v4(),
+            run_id,
+            status: ApprovalStatus::Pending,
+            reasons,
+            request,
+            tenant,
+            requested_by: requested_by.clone(),
+            requested_by_approver,
+            created_at: now,
+            expires_at: now + self.policy.ttl,
+            sandbox_id: None,
+            audit: Vec::new(),
+        };
+        approval.record(&requested_by, AuditAction::Requested, None);
+
+        let mut approvals = self.approvals.write().await;
+        approvals.insert(approval.id, approval.clone());
+        self.save(&approvals).await?;
+        info!(approval_id = %approval.id, %run_id, reasons = ?approval.reasons, "Run held for approval");
+        Ok(approval)
+    }
+
+    /// Approvals, newest first, optionally only those in one state
+    pub async fn list(&self, status: Option<ApprovalStatus>) -> anyhow::Result<Vec<Approval>> {
+        self.expire().await?;
+        let mut approvals: Vec<_> = self
+            .approvals
+            .read()
+            .await
+            .values()
+            .filter(|approval| status.is_none_or(|status| approval.status == status))
+            .cloned()
+            .collect();
+        approvals.sort_by_key(|approval| std::cmp::Reverse(approval.created_at));
+        Ok(approvals)
+    }
+
+    pub async fn get(&self, id: Uuid) -> anyhow::Result<Option<Approval>> {
+        self.expire().await?;
+        Ok(self.approvals.read().await.get(&id).cloned())
+    }
+
+    /// Approve or reject a pending approval as `user`, an approver's name.
+    /// Nobody may decide when no approvers are configured. Approvers can't
+    /// approve runs they requested with their own token; a run requested
+    /// without one can't be tied to an approver, so any of them may.
+    pub async fn decide(
+        &self,
+        id: Uuid,
+        user: &str,
+        approve: bool,
+        comment: Option<String>,
+    ) -> Result<Approval, DecisionError> {
+        if user == ANONYMOUS_USER {
+            return Err(DecisionError::Unauthenticated);
+        }
+        if !self.policy.approvers.iter().any(|approver| approver.name == user) {
+            return Err(DecisionError::NotApprover(user.to_string()));
+        }
+        self.expire().await.map_err(DecisionError::Storage)?;
+
+        let mut approvals = self.approvals.write().await;
+        let approval = approvals.get_mut(&id).ok_or(DecisionError::NotFound(id))?;
+        if approval.status != ApprovalStatus::Pending {
+            return Err(DecisionError::Decided(approval.status));
+        }
+        if approve && approval.requested_by_approver.as_deref() == Some(user) {
+            return Err(DecisionError::OwnRequest);
+        }
+        let (status, action) = if approve {
+            (ApprovalStatus::Approved, AuditAction::Approved)
+        } else {
+            (ApprovalStatus::Rejected, AuditAction::Rejected)
+        };
+        approval.status = status;
+        approval.record(user, action, comment);
+        let approval = approval.clone();
+        self.save(&approvals).await.map_err(DecisionError::Storage)?;
+        info!(approval_id = %id, run_id = %approval.run_id, user, status = ?status, "Run approval decided");
+        Ok(approval)
+    }
+
+    /// Record how an approved run's start went
+    pub async fn started(&self, id: Uuid, result: Result<Uuid, String>) {
+        let mut approvals = self.approvals.write().await;
+        let Some(approval) = approvals.get_mut(&id) else {
+            return;
+        };
+        match result {
+            Ok(sandbox_id) => {
+                approval.sandbox_id = Some(sandbox_id);
+                approval.record("gateway", AuditAction::Started, Some(sandbox_id.to_string()));
+            }
+            Err(e) => approval.record("gateway", AuditAction::Failed, Some(e)),
+        }
+        if let Err(e) = self.save(&approvals).await {
+            warn!(approval_id = %id, "Failed to save approvals: {:#}", e);
+        }
+    }
+
+    /// Expire pending approvals nobody decided in time
+    async fn expire(&self) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let due = |approval: &Approval| approval.status == ApprovalStatus::Pending && approval.expires_at <= now;
+        if !self.approvals.read().await.values().any(due) {
+            return Ok(());
+        }
+
+        let mut approvals = self.approvals.write().await;
+        for approval in approvals.values_mut().filter(|approval| due(approval)) {
+            approval.status = ApprovalStatus::Expired;
+            approval.record("gateway", AuditAction::Expired, None);
+            info!(approval_id = %approval.id, run_id = %approval.run_id, "Run approval expired");
+        }
+        self.save(&approvals).await
+    }
+
+    async fn save(&self, approvals: &HashMap<Uuid, Approval>) -> anyhow::Result<()> {
+        let mut sorted: Vec<_> = approvals.values().collect();
+        sorted.sort_by_key(|approval| approval.created_at);
+        // Write then rename so a crash never leaves a truncated file behind
+        let path = self.root.join("approvals.json");
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&sorted)?).await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ApprovalDecision {
+    /// Why, for the audit record
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApprovalQuery {
+    status: Option<ApprovalStatus>,
+}
+
+fn storage_error(e: anyhow::Error) -> (StatusCode, String) {
+    error_response(DecisionError::Storage(e))
+}
+
+fn error_response(e: DecisionError) -> (StatusCode, String) {
+    (e.status(), e.to_string())
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/approvals",
+    tag = "approvals",
+    params(("status" = Option<ApprovalStatus>, Query, description = "Only approvals in this state")),
+    responses((status = 200, description = "Approvals, newest first", body = [Approval]))
+)]
+pub async fn list_approvals(
+    State(state): State<AppState>,
+    Query(query): Query<ApprovalQuery>,
+) -> Result<Json<Vec<Approval>>, (StatusCode, String)> {
+    state.approvals.list(query.status).await.map(Json).map_err(storage_error)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/approvals/{id}",
+    tag = "approvals",
+    params(("id" = Uuid, Path, description = "Approval ID")),
+    responses(
+        (status = 200, description = "The approval and its audit records", body = Approval),
+        (status = 404, description = "No such approval"),
+    )
+)]
+pub async fn get_approval(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Approval>, (StatusCode, String)> {
+    match state.approvals.get(id).await.map_err(storage_error)? {
+        Some(approval) => Ok(Json(approval)),
+        None => Err(error_response(DecisionError::NotFound(id))),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/approvals/{id}/approve",
+    tag = "approvals",
+    params(
+        ("id" = Uuid, Path, description = "Approval ID"),
+        ("Authorization" = String, Header, description = "`Bearer` and the approver's token"),
+    ),
+    request_body = ApprovalDecision,
+    responses(
+        (status = 200, description = "Approved; the run's sandbox is running", body = RunSandboxResponse),
+        (status = 401, description = "No approver's token"),
+        (status = 403, description = "The approver may not approve this run"),
+        (status = 404, description = "No such approval"),
+        (status = 409, description = "Already decided, or expired"),
+    )
+)]
+pub async fn approve_run(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(decision): Json<ApprovalDecision>,
+) -> Result<Json<RunSandboxResponse>, axum::response::Response> {
+    let user = state.approvals.policy.approver(&headers).unwrap_or(ANONYMOUS_USER).to_string();
+    let approval = state
+        .approvals
+        .decide(id, &user, true, decision.comment)
+        .await
+        .map_err(|e| error_response(e).into_response())?;
+
+    // Spans of the run join the approver's trace
+    let trace = TraceContext::from_headers(&headers, approval.run_id);
+    let started = launch(
+        &state,
+        approval.request,
+        approval.run_id,
+        approval.tenant,
+        trace,
+        approval.requested_by,
+    )
+    .await;
+    let outcome = match &started {
+        Ok(response) => Ok(response.sandbox_id),
+        Err(e) => Err(e.to_string()),
+    };
+    state.approvals.started(id, outcome).await;
+    started.map(Json).map_err(|e| e.response(state.debug_errors))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/approvals/{id}/reject",
+    tag = "approvals",
+    params(
+        ("id" = Uuid, Path, description = "Approval ID"),
+        ("Authorization" = String, Header, description = "`Bearer` and the approver's token"),
+    ),
+    request_body = ApprovalDecision,
+    responses(
+        (status = 200, description = "Rejected; nothing runs", body = Approval),
+        (status = 401, description = "No approver's token"),
+        (status = 403, description = "The approver may not decide on runs"),
+        (status = 404, description = "No such approval"),
+        (status = 409, description = "Already decided, or expired"),
+    )
+)]
+pub async fn reject_run(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(decision): Json<ApprovalDecision>,
+) -> Result<Json<Approval>, (StatusCode, String)> {
+    let user = state.approvals.policy.approver(&headers).unwrap_or(ANONYMOUS_USER).to_string();
+    state
+        .approvals
+        .decide(id, &user, false, decision.comment)
+        .await
+        .map(Json)
+        .map_err(error_response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(value: serde_json::Value) -> RunSandboxRequest {
+        let mut base = serde_json::json!({
+            "code": "print('hi')",
+            "language": "python",
+            "isolation_level": "standard",
+        });
+        base.as_object_mut().unwrap().extend(value.as_object().unwrap().clone());
+        serde_json::from_value(base).unwrap()
+    }
+
+    #[tokio::test]
+    async fn holds_matching_runs_until_decided() {
+        let root = std::env::temp_dir().join(format!("sandstorm-approvals-{}", Uuid::new_v4()));
+        let policy = ApprovalPolicy {
+            rules: vec![ApprovalRule::PrivilegedMounts, ApprovalRule::OpenNetwork, ApprovalRule::UnlistedImage],
+            images: vec!["sandstorm/python".to_string()],
+            approvers: ["alice", "bob"]
+                .map(|name| Approver {
+                    name: name.to_string(),
+                    token: format!("{}-token", name),
+                })
+                .to_vec(),
+            ttl: chrono::Duration::seconds(60),
+        };
+        let approvals = Approvals::open(&root, policy).await.unwrap();
+
+        assert!(approvals.policy.reasons(&request(serde_json::json!({}))).is_empty());
+
+        let sensitive = request(serde_json::json!({
+            "language": "ruby",
+            "gvisor": { "network": "host" },
+            "mounts": [
+                { "source": "/data", "destination": "/data", "read_only": true },
+                { "source": "/var/run/docker.sock", "destination": "/docker.sock", "read_only": true },
+            ],
+        }));
+        let reasons = approvals.policy.reasons(&sensitive);
+        let rules: Vec<_> = reasons.iter().map(|reason| reason.rule).collect();
+        assert_eq!(rules, [ApprovalRule::PrivilegedMounts, ApprovalRule::OpenNetwork, ApprovalRule::UnlistedImage]);
+        let hold = || approvals.hold(sensitive.clone(), reasons.clone(), Uuid::new_v4(), None, "carol".into(), Some("bob".into()));
+
+        let held = hold().await.unwrap();
+        assert_eq!(held.status, ApprovalStatus::Pending);
+        let mut headers = HeaderMap::new();
+        assert_eq!(approvals.policy.approver(&headers), None);
+        headers.insert("authorization", "Bearer alice-token".parse().unwrap());
+        assert_eq!(approvals.policy.approver(&headers), Some("alice"));
+        headers.insert("authorization", "Bearer alice-tokem".parse().unwrap());
+        assert_eq!(approvals.policy.approver(&headers), None);
+        assert!(matches!(
+            approvals.decide(held.id, ANONYMOUS_USER, true, None).await,
+            Err(DecisionError::Unauthenticated)
+        ));
+        assert!(matches!(
+            approvals.decide(held.id, "mallory", true, None).await,
+            Err(DecisionError::NotApprover(_))
+        ));
+        assert!(matches!(approvals.decide(held.id, "bob", true, None).await, Err(DecisionError::OwnRequest)));
+
+        let approved = approvals.decide(held.id, "alice", true, Some("checked".into())).await.unwrap();
+        assert_eq!(approved.status, ApprovalStatus::Approved);
+        assert!(matches!(
+            approvals.decide(held.id, "alice", false, None).await,
+            Err(DecisionError::Decided(ApprovalStatus::Approved))
+        ));
+        let sandbox_id = Uuid::new_v4();
+        approvals.started(held.id, Ok(sandbox_id)).await;
+
+        // Undecided approvals expire, and everything survives a restart
+        let stale = hold().await.unwrap();
+        approvals.approvals.write().await.get_mut(&stale.id).unwrap().expires_at = Utc::now();
+        let stale = approvals.get(stale.id).await.unwrap().unwrap();
+        assert_eq!(stale.status, ApprovalStatus::Expired);
+
+        let reopened = Approvals::open(&root, approvals.policy.clone()).await.unwrap();
+        let approved = reopened.get(held.id).await.unwrap().unwrap();
+        assert_eq!(approved.sandbox_id, Some(sandbox_id));
+        let actions: Vec<_> = approved.audit.iter().map(|record| record.action).collect();
+        assert_eq!(actions, [AuditAction::Requested, AuditAction::Approved, AuditAction::Started]);
+        assert_eq!(reopened.list(Some(ApprovalStatus::Expired)).await.unwrap().len(), 1);
+
+        // Without approvers nobody may decide, anonymous requests included
+        let policy = ApprovalPolicy {
+            approvers: Vec::new(),
+            ..approvals.policy.clone()
+        };
+        let unguarded = Approvals::open(&root, policy).await.unwrap();
+        let held = unguarded
+            .hold(sensitive.clone(), reasons.clone(), Uuid::new_v4(), None, ANONYMOUS_USER.into(), None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            unguarded.decide(held.id, ANONYMOUS_USER, true, None).await,
+            Err(DecisionError::Unauthenticated)
+        ));
+        assert!(matches!(
+            unguarded.decide(held.id, "alice", true, None).await,
+            Err(DecisionError::NotApprover(_))
+        ));
+        fs::remove_dir_all(root).await.unwrap();
+    }
+}
@@ -0,0 +1,519 @@
+//! Benchmarks the registered runtimes on a standard workload suite, at each
+//! isolation level they support, so operators can compare cold starts,
+//! execution latency and throughput before picking a runtime.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use sandstorm_types::telemetry::{BenchmarkComparison, BenchmarkResult, LatencySummary};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::{sync::RwLock, task::JoinSet};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::runtime::{
+    ExecOptions, ExecutionMode, IsolationLevel, RuntimeRegistry, RuntimeType, SandboxConfig,
+    SandboxRuntime,
+};
+use crate::AppState;
+
+/// Sandboxes started per workload, runtime and isolation level by default
+const DEFAULT_ITERATIONS: u32 = 3;
+const MAX_ITERATIONS: u32 = 20;
+/// Executions run at once in a warm sandbox to measure throughput
+const THROUGHPUT_EXECS: usize = 4;
+/// Limit for each workload execution
+const EXEC_TIMEOUT: Duration = Duration::from_secs(120);
+/// Benchmark sandboxes stop on their own after this long if the gateway
+/// fails to destroy them
+const SANDBOX_TIMEOUT_MS: u64 = 600_000;
+
+const ISOLATION_LEVELS: [IsolationLevel; 3] = [
+    IsolationLevel::Standard,
+    IsolationLevel::Strong,
+    IsolationLevel::Maximum,
+];
+
+/// A workload of the standard suite
+#[derive(Debug, Clone, Copy)]
+pub struct Workload {
+    pub name: &'static str,
+    pub language: &'static str,
+    pub command: &'static [&'static str],
+}
+
+pub const SUITE: &[Workload] = &[
+    Workload {
+        name: "python-startup",
+        language: "python",
+        command: &["python3", "-c", "pass"],
+    },
+    Workload {
+        name: "python-cpu",
+        language: "python",
+        command: &["python3", "-c", "sum(i * i for i in range(2_000_000))"],
+    },
+    Workload {
+        name: "node-startup",
+        language: "javascript",
+        command: &["node", "-e", "0"],
+    },
+    Workload {
+        name: "shell-io",
+        language: "shell",
+        command: &[
+            "sh",
+            "-c",
+            "dd if=/dev/zero of=/tmp/sandstorm-bench bs=1M count=64 conv=fsync 2>/dev/null && rm /tmp/sandstorm-bench",
+        ],
+    },
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BenchmarkRequest {
+    /// Runtimes to benchmark (default: every registered one). Hosted
+    /// providers bill for benchmark sandboxes like any other.
+    pub runtimes: Option<Vec<RuntimeType>>,
+    /// Isolation levels to benchmark (default: every level a runtime
+    /// supports)
+    pub isolation_levels: Option<Vec<IsolationLevel>>,
+    /// Workloads of the suite to run (default: all)
+    pub workloads: Option<Vec<String>>,
+    pub iterations: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BenchmarkStatus {
+    Running,
+    Completed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkRun {
+    id: Uuid,
+    status: BenchmarkStatus,
+    request: BenchmarkRequest,
+    started_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    /// Workload, runtime and isolation level combinations to measure
+    planned: usize,
+    results: Vec<BenchmarkResult>,
+}
+
+/// Benchmark runs since the gateway started
+#[derive(Debug)]
+pub struct Benchmarks {
+    http: reqwest::Client,
+    telemetry_url: Option<String>,
+    runs: RwLock<HashMap<Uuid, BenchmarkRun>>,
+}
+
+impl Benchmarks {
+    pub fn new(telemetry_url: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            telemetry_url: telemetry_url.map(|url| url.trim_end_matches('/').to_string()),
+            runs: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Results are stored in the collector at `GATEWAY_TELEMETRY_URL`
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("GATEWAY_TELEMETRY_URL").ok())
+    }
+
+    async fn get(&self, id: Uuid) -> Option<BenchmarkRun> {
+        self.runs.read().await.get(&id).cloned()
+    }
+
+    async fn record(&self, id: Uuid, result: BenchmarkResult) {
+        if let Some(run) = self.runs.write().await.get_mut(&id) {
+            run.results.push(result);
+        }
+    }
+
+    async fn finish(&self, id: Uuid) {
+        let mut runs = self.runs.write().await;
+        let Some(run) = runs.get_mut(&id) else {
+            return;
+        };
+        run.status = BenchmarkStatus::Completed;
+        run.finished_at = Some(Utc::now());
+
+        let Some(telemetry_url) = self.telemetry_url.clone() else {
+            return;
+        };
+        let results = run.results.clone();
+        let http = self.http.clone();
+        tokio::spawn(async move {
+            let stored = http
+                .post(format!("{}/api/telemetry/benchmarks", telemetry_url))
+                .json(&results)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = stored {
+                warn!("Failed to store results of benchmark {}: {}", id, e);
+            }
+        });
+    }
+}
+
+/// Workload, runtime and isolation level combinations a request asks for
+async fn plan(
+    registry: &RuntimeRegistry,
+    request: &BenchmarkRequest,
+) -> Result<Vec<(Workload, Arc<dyn SandboxRuntime>, IsolationLevel)>, String> {
+    let workloads: Vec<Workload> = match &request.workloads {
+        Some(names) => names
+            .iter()
+            .map(|name| {
+                SUITE
+                    .iter()
+                    .find(|workload| workload.name == name)
+                    .copied()
+                    .ok_or_else(|| format!("unknown workload {:?}", name))
+            })
+            .collect::<Result<_, _>>()?,
+        None => SUITE.to_vec(),
+    };
+    let runtime_types = match &request.runtimes {
+        Some(runtime_types) => runtime_types.clone(),
+        None => registry.list().await,
+    };
+    let mut runtimes = Vec::new();
+    for runtime_type in runtime_types {
+        let runtime = registry
+            .get(runtime_type)
+            .await
+            .map_err(|_| format!("runtime {:?} is not registered", runtime_type))?;
+        runtimes.push(runtime);
+    }
+    let levels = request
+        .isolation_levels
+        .clone()
+        .unwrap_or_else(|| ISOLATION_LEVELS.to_vec());
+
+    let mut plan = Vec::new();
+    for workload in &workloads {
+        for runtime in &runtimes {
+            for level in levels
+                .iter()
+                .filter(|level| runtime.supports_isolation_level(**level))
+            {
+                plan.push((*workload, runtime.clone(), *level));
+            }
+        }
+    }
+    if plan.is_empty() {
+        return Err("no registered runtime supports the requested isolation levels".to_string());
+    }
+    Ok(plan)
+}
+
+/// Start sandboxes for a workload one after another, timing each start and
+/// a run of the workload, then measure throughput in the last one
+pub async fn measure(
+    registry: &RuntimeRegistry,
+    runtime: Arc<dyn SandboxRuntime>,
+    isolation_level: IsolationLevel,
+    workload: Workload,
+    iterations: u32,
+    benchmark_id: Uuid,
+) -> BenchmarkResult {
+    let command: Vec<String> = workload.command.iter().map(|arg| arg.to_string()).collect();
+    let options = ExecOptions {
+        timeout_ms: Some(EXEC_TIMEOUT.as_millis() as u64),
+        ..Default::default()
+    };
+    let mut cold_starts = Vec::new();
+    let mut execs = Vec::new();
+    let mut execs_per_sec = None;
+    let mut failures = 0;
+    let mut error = None;
+
+    for iteration in 0..iterations {
+        let config = SandboxConfig {
+            id: Uuid::new_v4(),
+            image: format!("sandstorm/{}", workload.language),
+            // Idle until the benchmark execs into it
+            command: vec!["sleep".to_string(), (SANDBOX_TIMEOUT_MS / 1000).to_string()],
+            environment: HashMap::new(),
+            cpu_limit: None,
+            memory_limit: None,
+            timeout: Some(SANDBOX_TIMEOUT_MS),
+            isolation_level,
+            runtime_preference: Some(runtime.runtime_type()),
+            working_dir: Some("/workspace".to_string()),
+            mounts: Vec::new(),
+            execution_mode: ExecutionMode::Standard,
+            scratch_size: None,
+            sysctls: HashMap::new(),
+            gvisor: Default::default(),
+            firecracker: Default::default(),
+            arch: None,
+            rootfs_layers: Vec::new(),
+            ttl_seconds: None,
+        };
+        let demand = registry.demand(None, None);
+        if !registry.reserve(config.id, &runtime, demand).await {
+            failures += 1;
+            error = Some("no host capacity for a benchmark sandbox".to_string());
+            continue;
+        }
+
+        let started = Instant::now();
+        let sandbox_id = match runtime.create(&config).await {
+            Ok(sandbox_id) => sandbox_id,
+            Err(e) => {
+                registry.release(config.id).await;
+                failures += 1;
+                error = Some(format!("failed to start sandbox: {:#}", e));
+                continue;
+            }
+        };
+        cold_starts.push(started.elapsed().as_secs_f64() * 1000.0);
+        registry.rekey(config.id, sandbox_id).await;
+
+        let started = Instant::now();
+        match runtime
+            .exec(sandbox_id, command.clone(), None, &options)
+            .await
+        {
+            Ok(result) if result.exit_code == 0 => {
+                execs.push(started.elapsed().as_secs_f64() * 1000.0);
+                if iteration + 1 == iterations {
+                    execs_per_sec = throughput(&runtime, sandbox_id, &command, &options).await;
+                }
+            }
+            Ok(result) => {
+                failures += 1;
+                error = Some(format!("workload exited with {}", result.exit_code));
+            }
+            Err(e) => {
+                failures += 1;
+                error = Some(format!("failed to run workload: {:#}", e));
+            }
+        }
+
+        if let Err(e) = runtime.destroy(sandbox_id).await {
+            warn!(%sandbox_id, "Failed to destroy benchmark sandbox: {:#}", e);
+        }
+        registry.release(sandbox_id).await;
+    }
+
+    BenchmarkResult {
+        benchmark_id,
+        runtime: runtime.runtime_type(),
+        isolation_level,
+        workload: workload.name.to_string(),
+        iterations,
+        failures,
+        cold_start_ms: LatencySummary::of(&cold_starts),
+        exec_ms: LatencySummary::of(&execs),
+        execs_per_sec,
+        error,
+        created_at: Utc::now(),
+    }
+}
+
+/// Workload executions per second with several running at once in a
+/// sandbox; `None` if any of them failed
+async fn throughput(
+    runtime: &Arc<dyn SandboxRuntime>,
+    sandbox_id: Uuid,
+    command: &[String],
+    options: &ExecOptions,
+) -> Option<f64> {
+    let started = Instant::now();
+    let mut execs = JoinSet::new();
+    for _ in 0..THROUGHPUT_EXECS {
+        let runtime = runtime.clone();
+        let command = command.to_vec();
+        let options = options.clone();
+        execs.spawn(async move { runtime.exec(sandbox_id, command, None, &options).await });
+    }
+    while let Some(exec) = execs.join_next().await {
+        match exec {
+            Ok(Ok(result)) if result.exit_code == 0 => {}
+            _ => return None,
+        }
+    }
+    Some(THROUGHPUT_EXECS as f64 / started.elapsed().as_secs_f64())
+}
+
+async fn execute(
+    state: AppState,
+    id: Uuid,
+    request: BenchmarkRequest,
+    plan: Vec<(Workload, Arc<dyn SandboxRuntime>, IsolationLevel)>,
+) {
+    let iterations = request.iterations.unwrap_or(DEFAULT_ITERATIONS);
+    // One combination at a time, so measurements don't compete for the host
+    for (workload, runtime, isolation_level) in plan {
+        let result = measure(
+            &state.runtime_registry,
+            runtime,
+            isolation_level,
+            workload,
+            iterations,
+            id,
+        )
+        .await;
+        info!(
+            benchmark_id = %id,
+            workload = workload.name,
+            runtime = ?result.runtime,
+            isolation_level = ?isolation_level,
+            failures = result.failures,
+            "Benchmark measured"
+        );
+        state.benchmarks.record(id, result).await;
+    }
+    state.benchmarks.finish(id).await;
+    info!(benchmark_id = %id, "Benchmark finished");
+}
+
+/// Start a benchmark in the background; one runs at a time
+pub async fn create_benchmark(
+    State(state): State<AppState>,
+    Json(request): Json<BenchmarkRequest>,
+) -> Result<(StatusCode, Json<BenchmarkRun>), (StatusCode, String)> {
+    let iterations = request.iterations.unwrap_or(DEFAULT_ITERATIONS);
+    if !(1..=MAX_ITERATIONS).contains(&iterations) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("iterations must be between 1 and {}", MAX_ITERATIONS),
+        ));
+    }
+    let plan = plan(&state.runtime_registry, &request)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let run = BenchmarkRun {
+        id: Uuid::new_v4(),
+        status: BenchmarkStatus::Running,
+        request: request.clone(),
+        started_at: Utc::now(),
+        finished_at: None,
+        planned: plan.len(),
+        results: Vec::new(),
+    };
+    {
+        let mut runs = state.benchmarks.runs.write().await;
+        if let Some(running) = runs
+            .values()
+            .find(|run| run.status == BenchmarkStatus::Running)
+        {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("benchmark {} is still running", running.id),
+            ));
+        }
+        runs.insert(run.id, run.clone());
+    }
+
+    info!(benchmark_id = %run.id, combinations = run.planned, "Benchmark started");
+    tokio::spawn(execute(state.clone(), run.id, request, plan));
+    Ok((StatusCode::ACCEPTED, Json(run)))
+}
+
+/// Benchmark runs, newest first
+pub async fn list_benchmarks(State(state): State<AppState>) -> Json<Vec<BenchmarkRun>> {
+    let mut runs: Vec<BenchmarkRun> = state
+        .benchmarks
+        .runs
+        .read()
+        .await
+        .values()
+        .cloned()
+        .collect();
+    runs.sort_by_key(|run| std::cmp::Reverse(run.started_at));
+    Json(runs)
+}
+
+pub async fn get_benchmark(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<BenchmarkRun>, StatusCode> {
+    state
+        .benchmarks
+        .get(id)
+        .await
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Runtimes and isolation levels ranked on each workload of a benchmark,
+/// from the results measured so far
+pub async fn benchmark_report(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<BenchmarkComparison>>, StatusCode> {
+    let run = state
+        .benchmarks
+        .get(id)
+        .await
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(BenchmarkComparison::from_results(&run.results)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::mock::{MockBehavior, MockRuntime};
+
+    #[tokio::test]
+    async fn measures_cold_starts_execs_and_throughput() {
+        let registry = RuntimeRegistry::new();
+        let runtime: Arc<dyn SandboxRuntime> = Arc::new(MockRuntime::new(MockBehavior {
+            delay_ms: 20,
+            ..Default::default()
+        }));
+        registry.register(runtime.clone()).await.unwrap();
+
+        let result = measure(
+            &registry,
+            runtime.clone(),
+            IsolationLevel::Strong,
+            SUITE[0],
+            2,
+            Uuid::new_v4(),
+        )
+        .await;
+        assert_eq!(result.runtime, RuntimeType::Mock);
+        assert_eq!(result.failures, 0);
+        assert!(result.cold_start_ms.is_some());
+        assert!(result.exec_ms.unwrap().min >= 20.0);
+        assert!(result.execs_per_sec.unwrap() > 0.0);
+        assert_eq!(runtime.active_sandboxes().await, 0);
+
+        let failing: Arc<dyn SandboxRuntime> = Arc::new(MockRuntime::new(MockBehavior {
+            delay_ms: 1,
+            exit_code: 1,
+            ..Default::default()
+        }));
+        let result = measure(
+            &registry,
+            failing,
+            IsolationLevel::Strong,
+            SUITE[0],
+            2,
+            Uuid::new_v4(),
+        )
+        .await;
+        assert_eq!(result.failures, 2);
+        assert!(result.exec_ms.is_none());
+        assert_eq!(result.error.as_deref(), Some("workload exited with 1"));
+    }
+}
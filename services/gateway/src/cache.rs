@@ -0,0 +1,268 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::runtime::SandboxResult;
+
+/// What a sandbox was started from; execs in sandboxes started the same way
+/// share cache entries
+#[derive(Debug, Clone)]
+struct SandboxIdentity {
+    image: String,
+    code_hash: String,
+}
+
+#[derive(Debug)]
+struct Entry {
+    result: SandboxResult,
+    inserted: Instant,
+    size: usize,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    by_key: HashMap<String, Entry>,
+    total_bytes: usize,
+}
+
+/// Content-addressed cache of exec results, keyed by the sandbox's image and
+/// code plus the command and environment of the exec
+#[derive(Debug)]
+pub struct ResultCache {
+    enabled: bool,
+    ttl: Duration,
+    max_entries: usize,
+    max_bytes: usize,
+    sandboxes: RwLock<HashMap<Uuid, SandboxIdentity>>,
+    entries: RwLock<Entries>,
+}
+
+impl ResultCache {
+    pub fn new(ttl: Duration, max_entries: usize, max_bytes: usize) -> Self {
+        Self {
+            enabled: true,
+            ttl,
+            max_entries,
+            max_bytes,
+            sandboxes: RwLock::new(HashMap::new()),
+            entries: RwLock::new(Entries::default()),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::new(Duration::ZERO, 0, 0)
+        }
+    }
+
+    /// Enabled by `GATEWAY_RESULT_CACHE=true`, sized by
+    /// `GATEWAY_RESULT_CACHE_TTL_SECS` (default 3600),
+    /// `GATEWAY_RESULT_CACHE_MAX_ENTRIES` (default 1000) and
+    /// `GATEWAY_RESULT_CACHE_MAX_BYTES` (default 64 MiB)
+    pub fn from_env() -> Self {
+        let enabled = std::env::var("GATEWAY_RESULT_CACHE")
+            .map(|value| matches!(value.as_str(), "1" | "true" | "yes" | "on"))
+            .unwrap_or(false);
+        if !enabled {
+            return Self::disabled();
+        }
+
+        let var = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        Self::new(
+            Duration::from_secs(var("GATEWAY_RESULT_CACHE_TTL_SECS", 3600)),
+            var("GATEWAY_RESULT_CACHE_MAX_ENTRIES", 1000) as usize,
+            var("GATEWAY_RESULT_CACHE_MAX_BYTES", 64 * 1024 * 1024) as usize,
+        )
+    }
+
+    /// Remember what a sandbox was started from
+    pub async fn track(&self, sandbox_id: Uuid, image: &str, code: &str) {
+        if !self.enabled {
+            return;
+        }
+        self.sandboxes.write().await.insert(
+            sandbox_id,
+            SandboxIdentity {
+                image: image.to_string(),
+                code_hash: format!("{:x}", Sha256::digest(code.as_bytes())),
+            },
+        );
+    }
+
+    pub async fn forget(&self, sandbox_id: Uuid) {
+        self.sandboxes.write().await.remove(&sandbox_id);
+    }
+
+    /// Cache key for an exec, or `None` when caching is off or the sandbox's
+    /// origin is unknown (e.g. resumed from a snapshot)
+    pub async fn key(
+        &self,
+        sandbox_id: Uuid,
+        command: &[String],
+        environment: Option<&HashMap<String, String>>,
+    ) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+        let identity = self.sandboxes.read().await.get(&sandbox_id).cloned()?;
+
+        let mut env_hash = Sha256::new();
+        let mut vars: Vec<_> = environment.into_iter().flatten().collect();
+        vars.sort();
+        for (key, value) in vars {
+            env_hash.update(key.as_bytes());
+            env_hash.update([0]);
+            env_hash.update(value.as_bytes());
+            env_hash.update([0]);
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(identity.image.as_bytes());
+        hasher.update([0]);
+        for arg in command {
+            hasher.update(arg.as_bytes());
+            hasher.update([0]);
+        }
+        hasher.update([0]);
+        hasher.update(identity.code_hash.as_bytes());
+        hasher.update(env_hash.finalize());
+        Some(format!("{:x}", hasher.finalize()))
+    }
+
+    pub async fn get(&self, key: &str) -> Option<SandboxResult> {
+        let entries = self.entries.read().await;
+        let entry = entries.by_key.get(key)?;
+        if entry.inserted.elapsed() > self.ttl {
+            return None;
+        }
+        debug!(key, "Result cache hit");
+        Some(entry.result.clone())
+    }
+
+    /// Store a result; only successful runs small enough to fit are kept
+    pub async fn insert(&self, key: String, result: &SandboxResult) {
+        let size = result.stdout.len() + result.stderr.len();
+        if result.exit_code != 0 || size > self.max_bytes || self.max_entries == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.write().await;
+        if let Some(previous) = entries.by_key.remove(&key) {
+            entries.total_bytes -= previous.size;
+        }
+
+        // Drop expired entries, then the oldest ones until the new one fits
+        let ttl = self.ttl;
+        let expired: Vec<_> = entries
+            .by_key
+            .iter()
+            .filter(|(_, entry)| entry.inserted.elapsed() > ttl)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            if let Some(entry) = entries.by_key.remove(&key) {
+                entries.total_bytes -= entry.size;
+            }
+        }
+        while entries.by_key.len() >= self.max_entries || entries.total_bytes + size > self.max_bytes {
+            let Some(oldest) = entries
+                .by_key
+                .iter()
+                .min_by_key(|(_, entry)| entry.inserted)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            if let Some(entry) = entries.by_key.remove(&oldest) {
+                entries.total_bytes -= entry.size;
+            }
+        }
+
+        entries.total_bytes += size;
+        entries.by_key.insert(
+            key,
+            Entry {
+                result: result.clone(),
+                inserted: Instant::now(),
+                size,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{ExitReason, ResourceUsage};
+
+    fn result(stdout: &str, exit_code: i32) -> SandboxResult {
+        SandboxResult {
+            id: Uuid::new_v4(),
+            exit_code,
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: Vec::new(),
+            duration_ms: 10,
+            resource_usage: ResourceUsage {
+                cpu_usage_seconds: 0.01,
+                memory_usage_bytes: 0,
+                network_rx_bytes: 0,
+                network_tx_bytes: 0,
+            },
+            exit_reason: ExitReason::Completed,
+        }
+    }
+
+    #[tokio::test]
+    async fn keys_match_for_identical_sandboxes() {
+        let cache = ResultCache::new(Duration::from_secs(60), 10, 1024);
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        cache.track(a, "sandstorm/python", "print(1)").await;
+        cache.track(b, "sandstorm/python", "print(1)").await;
+        cache.track(c, "sandstorm/python", "print(2)").await;
+
+        let command = vec!["ls".to_string()];
+        let env = HashMap::from([("A".to_string(), "1".to_string())]);
+        let key_a = cache.key(a, &command, Some(&env)).await.unwrap();
+
+        assert_eq!(cache.key(b, &command, Some(&env)).await.unwrap(), key_a);
+        assert_ne!(cache.key(c, &command, Some(&env)).await.unwrap(), key_a);
+        assert_ne!(cache.key(a, &command, None).await.unwrap(), key_a);
+        assert!(cache.key(Uuid::new_v4(), &command, None).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn evicts_oldest_and_skips_failures() {
+        let cache = ResultCache::new(Duration::from_secs(60), 2, 1024);
+
+        cache.insert("one".into(), &result("1", 0)).await;
+        cache.insert("two".into(), &result("2", 0)).await;
+        cache.insert("three".into(), &result("3", 0)).await;
+        cache.insert("failed".into(), &result("", 1)).await;
+
+        assert!(cache.get("one").await.is_none());
+        assert_eq!(cache.get("two").await.unwrap().stdout, b"2");
+        assert_eq!(cache.get("three").await.unwrap().stdout, b"3");
+        assert!(cache.get("failed").await.is_none());
+
+        let oversized = "x".repeat(2048);
+        cache.insert("big".into(), &result(&oversized, 0)).await;
+        assert!(cache.get("big").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_misses() {
+        let cache = ResultCache::new(Duration::ZERO, 10, 1024);
+        cache.insert("key".into(), &result("out", 0)).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(cache.get("key").await.is_none());
+    }
+}
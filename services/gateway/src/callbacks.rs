@@ -0,0 +1,359 @@
+//! Completion callbacks for runs started with a `callback_url`. Once the
+//! run's sandbox finishes, because its command exited, it ran past its
+//! timeout or it was destroyed, the gateway POSTs the run's result to the
+//! URL, so clients needn't poll its status. Each delivery is signed like
+//! the security monitor's signal batches and retried with backoff until
+//! the receiver takes it.
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use ring::hmac;
+use sandstorm_types::security::{SIGNATURE_HEADER, SIGNATURE_TIMESTAMP_HEADER};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::metrics::GatewayMetrics;
+use crate::runtime::{ExitReason, SandboxResult, SandboxRuntime, SandboxState, SandboxStatus};
+use crate::AppState;
+
+/// Header naming a delivery; retries of it send the same ID, so receivers
+/// can drop duplicates
+pub const DELIVERY_HEADER: &str = "x-sandstorm-delivery";
+
+/// How often a run is checked for having finished
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a run without a timeout of its own may go before it's
+/// reported as timed out
+const DEFAULT_WATCH: Duration = Duration::from_secs(3600);
+
+/// How long past its timeout a run may take to be stopped by its runtime
+/// before the gateway reports it timed out
+const TIMEOUT_GRACE: Duration = Duration::from_secs(5);
+
+/// Output sent with a result; the rest is left to `/logs`
+const MAX_OUTPUT_BYTES: u64 = 1024 * 1024;
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 6;
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// What a callback receives: the run's result, with its run ID
+#[derive(Debug, Clone, Serialize)]
+pub struct RunCompletion {
+    pub run_id: Uuid,
+    #[serde(flatten)]
+    pub result: SandboxResult,
+    pub finished_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    url: String,
+    run_id: Uuid,
+    started: Instant,
+}
+
+/// Callbacks of runs whose sandboxes are still going, by the ID each
+/// sandbox started with
+#[derive(Debug)]
+pub struct Callbacks {
+    http: reqwest::Client,
+    key: Option<hmac::Key>,
+    max_attempts: u32,
+    pending: Mutex<HashMap<Uuid, Pending>>,
+}
+
+impl Callbacks {
+    /// Deliveries are signed with `GATEWAY_CALLBACK_SIGNING_KEY`, without
+    /// which runs can't ask for callbacks, and tried
+    /// `GATEWAY_CALLBACK_MAX_ATTEMPTS` times (default 6)
+    pub fn from_env() -> anyhow::Result<Self> {
+        let max_attempts = match std::env::var("GATEWAY_CALLBACK_MAX_ATTEMPTS") {
+            Ok(value) => value.parse().context("GATEWAY_CALLBACK_MAX_ATTEMPTS")?,
+            Err(_) => DEFAULT_MAX_ATTEMPTS,
+        };
+        if max_attempts == 0 {
+            anyhow::bail!("GATEWAY_CALLBACK_MAX_ATTEMPTS must be at least 1");
+        }
+        let key = std::env::var("GATEWAY_CALLBACK_SIGNING_KEY").ok();
+        Ok(Self::new(key.as_deref().map(str::as_bytes), max_attempts))
+    }
+
+    pub fn new(key: Option<&[u8]>, max_attempts: u32) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            key: key.map(|key| hmac::Key::new(hmac::HMAC_SHA256, key)),
+            max_attempts,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check a run request's callback URL
+    pub fn check(&self, url: &str) -> anyhow::Result<()> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| anyhow::anyhow!("invalid callback_url: {}", e))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            anyhow::bail!("callback_url must be an http(s) URL");
+        }
+        if self.key.is_none() {
+            anyhow::bail!("callback_url needs GATEWAY_CALLBACK_SIGNING_KEY");
+        }
+        Ok(())
+    }
+
+    /// The callback of a run, for the one delivery it gets
+    async fn take(&self, sandbox_id: Uuid) -> Option<Pending> {
+        self.pending.lock().await.remove(&sandbox_id)
+    }
+
+    /// Deliver a run's result in the background
+    fn deliver(&self, pending: Pending, result: SandboxResult, metrics: GatewayMetrics) {
+        let Some(key) = self.key.clone() else {
+            return;
+        };
+        let completion = RunCompletion {
+            run_id: pending.run_id,
+            result,
+            finished_at: Utc::now(),
+        };
+        let (http, max_attempts) = (self.http.clone(), self.max_attempts);
+        tokio::spawn(async move {
+            let delivered = send(&http, &key, &pending.url, &completion, max_attempts).await;
+            metrics.callback_delivered(delivered);
+            if delivered {
+                info!(run_id = %completion.run_id, url = %pending.url, "Run completion delivered");
+            }
+        });
+    }
+}
+
+/// POST a completion until the receiver takes it, a `4xx` other than `429`
+/// says it never will, or the attempts run out. Returns whether it was
+/// taken.
+async fn send(
+    http: &reqwest::Client,
+    key: &hmac::Key,
+    url: &str,
+    completion: &RunCompletion,
+    max_attempts: u32,
+) -> bool {
+    let Ok(body) = serde_json::to_vec(completion) else {
+        return false;
+    };
+    let delivery = Uuid::new_v4().to_string();
+    let mut backoff = FIRST_BACKOFF;
+    for attempt in 1..=max_attempts {
+        let timestamp = Utc::now().timestamp();
+        let result = http
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(DELIVERY_HEADER, &delivery)
+            .header(SIGNATURE_TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, sign(key, timestamp, &body))
+            .body(body.clone())
+            .send()
+            .await;
+        let error = match result {
+            Ok(response) if response.status().is_success() => return true,
+            Ok(response) if response.status().is_client_error() && response.status() != 429 => {
+                warn!(
+                    run_id = %completion.run_id,
+                    "Callback to {} refused with {}; not retrying",
+                    url,
+                    response.status()
+                );
+                return false;
+            }
+            Ok(response) => response.status().to_string(),
+            Err(e) => e.to_string(),
+        };
+        warn!(run_id = %completion.run_id, attempt, "Callback to {} failed: {}", url, error);
+        if attempt < max_attempts {
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+    false
+}
+
+/// `sha256=<hex>` HMAC of `<timestamp>.<body>`
+fn sign(key: &hmac::Key, timestamp: i64, body: &[u8]) -> String {
+    let mut context = hmac::Context::with_key(key);
+    context.update(timestamp.to_string().as_bytes());
+    context.update(b".");
+    context.update(body);
+    let hex: String = context
+        .sign()
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256={}", hex)
+}
+
+/// Watch a run's sandbox and deliver its result to `url` once it finishes,
+/// following it through preemption. A run still going past its timeout, or
+/// an hour without one, is reported as timed out.
+pub async fn spawn(state: &AppState, sandbox_id: Uuid, run_id: Uuid, url: String, timeout: Option<u64>) {
+    let started = Instant::now();
+    let pending = Pending {
+        url,
+        run_id,
+        started,
+    };
+    state.callbacks.pending.lock().await.insert(sandbox_id, pending);
+    let state = state.clone();
+    tokio::spawn(async move {
+        let deadline = started
+            + timeout
+                .map(|ms| Duration::from_millis(ms) + TIMEOUT_GRACE)
+                .unwrap_or(DEFAULT_WATCH);
+        let exit_reason = loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            // Destroying the sandbox delivers the result itself
+            if !state.callbacks.pending.lock().await.contains_key(&sandbox_id) {
+                return;
+            }
+
+            // Preempted runs can't finish until they are resumed
+            if let Some(current) = state.preemption.locate(sandbox_id).await {
+                if let Some(runtime) = state.runtime_registry.runtime_of(current).await {
+                    match runtime.status(current).await {
+                        Ok(status) if finished(&status) => {
+                            let Some(pending) = state.callbacks.take(sandbox_id).await else {
+                                return;
+                            };
+                            let result =
+                                result_of(runtime.as_ref(), current, sandbox_id, &pending, status, None).await;
+                            state.callbacks.deliver(pending, result, state.metrics.clone());
+                            return;
+                        }
+                        Ok(_) => {}
+                        // Gone without being destroyed through the gateway
+                        Err(_) => break ExitReason::Crashed,
+                    }
+                }
+            }
+            if Instant::now() >= deadline {
+                break ExitReason::Timeout;
+            }
+        };
+
+        if let Some(pending) = state.callbacks.take(sandbox_id).await {
+            let result = unfinished(sandbox_id, &pending, exit_reason);
+            state.callbacks.deliver(pending, result, state.metrics.clone());
+        }
+    });
+}
+
+/// Deliver the result of a run whose sandbox is being destroyed, before it
+/// goes. `current` is the sandbox holding the run, if it isn't preempted;
+/// `reason` is reported if the run hadn't finished.
+pub async fn destroyed(
+    state: &AppState,
+    sandbox_id: Uuid,
+    current: Option<(Uuid, &dyn SandboxRuntime)>,
+    reason: ExitReason,
+) {
+    let Some(pending) = state.callbacks.take(sandbox_id).await else {
+        return;
+    };
+    let status = match current {
+        Some((id, runtime)) => runtime.status(id).await.ok().map(|status| (id, runtime, status)),
+        None => None,
+    };
+    let result = match status {
+        Some((id, runtime, status)) => {
+            let reason = (!finished(&status)).then_some(reason);
+            result_of(runtime, id, sandbox_id, &pending, status, reason).await
+        }
+        None => unfinished(sandbox_id, &pending, reason),
+    };
+    state.callbacks.deliver(pending, result, state.metrics.clone());
+}
+
+fn finished(status: &SandboxStatus) -> bool {
+    matches!(status.state, SandboxState::Stopped | SandboxState::Failed) || status.exit_code.is_some()
+}
+
+/// Result of a run from its sandbox's status and output, reported under
+/// the ID the run's sandbox started with
+async fn result_of(
+    runtime: &dyn SandboxRuntime,
+    current: Uuid,
+    sandbox_id: Uuid,
+    pending: &Pending,
+    status: SandboxStatus,
+    exit_reason: Option<ExitReason>,
+) -> SandboxResult {
+    let mut stdout = Vec::new();
+    match runtime.logs(current, false).await {
+        Ok(output) => {
+            if let Err(e) = output.take(MAX_OUTPUT_BYTES).read_to_end(&mut stdout).await {
+                warn!("Failed to read the output of sandbox {}: {}", current, e);
+            }
+        }
+        Err(e) => warn!("Failed to read the output of sandbox {}: {:#}", current, e),
+    }
+    let duration_ms = match (status.started_at, status.finished_at) {
+        (Some(started), Some(finished)) => (finished - started).num_milliseconds().max(0) as u64,
+        _ => pending.started.elapsed().as_millis() as u64,
+    };
+    SandboxResult {
+        id: sandbox_id,
+        exit_code: status.exit_code.unwrap_or(-1),
+        stdout,
+        stderr: Vec::new(),
+        duration_ms,
+        resource_usage: status.resource_usage,
+        exit_reason: exit_reason.or(status.exit_reason).unwrap_or_default(),
+    }
+}
+
+/// Result of a run that never got to finish
+fn unfinished(sandbox_id: Uuid, pending: &Pending, exit_reason: ExitReason) -> SandboxResult {
+    SandboxResult {
+        id: sandbox_id,
+        exit_code: -1,
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+        duration_ms: pending.started.elapsed().as_millis() as u64,
+        resource_usage: Default::default(),
+        exit_reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_the_timestamp_and_body() {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"secret");
+        let signature = sign(&key, 1_700_000_000, br#"{"run_id":"x"}"#);
+        let hex = signature.strip_prefix("sha256=").unwrap();
+        assert_eq!(hex.len(), 64);
+
+        let bytes: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        assert!(hmac::verify(&key, br#"1700000000.{"run_id":"x"}"#, &bytes).is_ok());
+        assert_ne!(signature, sign(&key, 1_700_000_001, br#"{"run_id":"x"}"#));
+
+        let callbacks = Callbacks::new(None, 1);
+        assert!(callbacks.check("https://example.com/done").is_err());
+        let callbacks = Callbacks::new(Some(b"secret"), 1);
+        assert!(callbacks.check("https://example.com/done").is_ok());
+        assert!(callbacks.check("ftp://example.com/done").is_err());
+        assert!(callbacks.check("not a url").is_err());
+    }
+}
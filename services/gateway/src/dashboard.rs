@@ -0,0 +1,70 @@
+//! One response with everything the web dashboard shows for a sandbox, so
+//! it talks to the gateway alone instead of fanning out to the telemetry
+//! collector and security monitor from the browser.
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use sandstorm_types::security::SecurityEvent;
+use sandstorm_types::telemetry::SandboxRun;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::provenance::RiskSummary;
+use crate::{status_of, AppState, SandboxStatusResponse};
+
+const DEFAULT_EVENTS: usize = 20;
+const MAX_EVENTS: usize = 200;
+const DEFAULT_WINDOW_HOURS: u32 = 24;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DashboardQuery {
+    /// Latest security events to include (default 20, at most 200)
+    events: Option<usize>,
+    /// Hours of events the risk score covers (default 24)
+    window_hours: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SandboxDashboard {
+    sandbox_id: Uuid,
+    run_id: Option<Uuid>,
+    /// `None` once no runtime has the sandbox, e.g. after it was destroyed
+    status: Option<SandboxStatusResponse>,
+    /// `None` when the security monitor couldn't be asked
+    risk: Option<RiskSummary>,
+    /// Newest first
+    security_events: Vec<SecurityEvent>,
+    /// Telemetry recorded for the sandbox's runs
+    runs: Vec<SandboxRun>,
+    /// Services that could not be queried; their sections are empty
+    unavailable: Vec<String>,
+}
+
+/// Status, risk score, latest security events and telemetry history of a
+/// sandbox. Always answers; sections whose service is down come back empty
+/// and the service is named in `unavailable`.
+pub async fn sandbox_dashboard(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<DashboardQuery>,
+) -> Json<SandboxDashboard> {
+    let events = query.events.unwrap_or(DEFAULT_EVENTS).clamp(1, MAX_EVENTS);
+    let window_hours = query.window_hours.unwrap_or(DEFAULT_WINDOW_HOURS).max(1);
+
+    let (status, overview) = tokio::join!(
+        status_of(&state, id),
+        state.provenance.overview(id, events, window_hours),
+    );
+
+    Json(SandboxDashboard {
+        sandbox_id: id,
+        run_id: state.run_ledger.get(id).await,
+        status: status.ok(),
+        risk: overview.risk,
+        security_events: overview.security_events,
+        runs: overview.runs,
+        unavailable: overview.unavailable,
+    })
+}
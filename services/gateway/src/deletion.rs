@@ -0,0 +1,539 @@
+//! Coordinated data deletion, as for a GDPR erasure request. A deletion
+//! names a tenant or a sandbox and is carried out by the telemetry
+//! collector, then the snapshot vault, then the security monitor, each
+//! asked about every sandbox the ones before it found. What each service
+//! removed and kept is gathered into a signed deletion certificate.
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use ring::hmac;
+use sandstorm_types::deletion::{DeletionRequest, ServiceDeletion};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path as FsPath, PathBuf};
+use std::time::Duration;
+use tokio::{fs, sync::RwLock};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::recording::user_from_headers;
+use crate::AppState;
+
+/// A service data is deleted from, in the order deletions visit them
+#[derive(Debug, Clone)]
+pub struct DeletionService {
+    pub name: &'static str,
+    /// Base URL, or `None` when the service isn't configured
+    pub url: Option<String>,
+    pub path: &'static str,
+    pub admin_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletionStatus {
+    /// Every service deleted its data
+    Completed,
+    /// Some services failed; see `failed`, and retry
+    Partial,
+}
+
+/// A service that couldn't carry out a deletion
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct FailedDeletion {
+    pub service: String,
+    pub error: String,
+}
+
+/// What a deletion removed from each service, and what they kept
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeletionCertificate {
+    pub id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Every sandbox the deletion covered, requested or found through the
+    /// tenant
+    pub sandbox_ids: Vec<String>,
+    pub requested_by: String,
+    pub requested_at: DateTime<Utc>,
+    /// When the last service answered
+    pub completed_at: DateTime<Utc>,
+    pub status: DeletionStatus,
+    pub services: Vec<ServiceDeletion>,
+    #[serde(default)]
+    pub failed: Vec<FailedDeletion>,
+    /// `sha256=<hex>` HMAC under `GATEWAY_DELETION_SIGNING_KEY` of the
+    /// certificate serialized without this field
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// A deletion to carry out; one of the two is required
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateDeletionRequest {
+    #[serde(default)]
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub sandbox_id: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeletionError {
+    #[error("Deletion {0} not found")]
+    NotFound(Uuid),
+    #[error("Deletion {0} already completed")]
+    Completed(Uuid),
+    #[error("{0}")]
+    Invalid(String),
+    #[error("Deletions need GATEWAY_DELETION_SIGNING_KEY to sign their certificates")]
+    Unsigned,
+    #[error("Failed to save deletions: {0}")]
+    Storage(anyhow::Error),
+}
+
+impl DeletionError {
+    fn status(&self) -> StatusCode {
+        match self {
+            DeletionError::NotFound(_) => StatusCode::NOT_FOUND,
+            DeletionError::Completed(_) => StatusCode::CONFLICT,
+            DeletionError::Invalid(_) => StatusCode::BAD_REQUEST,
+            DeletionError::Unsigned => StatusCode::SERVICE_UNAVAILABLE,
+            DeletionError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Deletions carried out, persisted as JSON in `<root>/deletions.json`
+#[derive(Debug)]
+pub struct Deletions {
+    root: PathBuf,
+    http: reqwest::Client,
+    key: Option<hmac::Key>,
+    services: Vec<DeletionService>,
+    certificates: RwLock<HashMap<Uuid, DeletionCertificate>>,
+}
+
+impl Deletions {
+    /// Certificates are stored under `GATEWAY_DELETIONS_PATH` (default
+    /// `./data/deletions`) and signed with `GATEWAY_DELETION_SIGNING_KEY`,
+    /// without which deletions are refused. Services are reached at
+    /// `GATEWAY_TELEMETRY_URL`, `GATEWAY_SNAPSHOT_VAULT_URL` and
+    /// `GATEWAY_SECURITY_MONITOR_URL` with the admin tokens in
+    /// `GATEWAY_TELEMETRY_ADMIN_TOKEN`, `GATEWAY_SNAPSHOT_VAULT_ADMIN_TOKEN`
+    /// and `GATEWAY_SECURITY_MONITOR_ADMIN_TOKEN`.
+    pub async fn from_env() -> anyhow::Result<Self> {
+        let root = std::env::var("GATEWAY_DELETIONS_PATH").unwrap_or_else(|_| "./data/deletions".to_string());
+        let key = std::env::var("GATEWAY_DELETION_SIGNING_KEY").ok();
+        let service = |name, url: &str, path, token: &str| DeletionService {
+            name,
+            url: std::env::var(url)
+                .ok()
+                .map(|value| value.trim_end_matches('/').to_string()),
+            path,
+            admin_token: std::env::var(token).ok(),
+        };
+        let services = vec![
+            service(
+                "telemetry-collector",
+                "GATEWAY_TELEMETRY_URL",
+                "/api/deletions",
+                "GATEWAY_TELEMETRY_ADMIN_TOKEN",
+            ),
+            service(
+                "snapshot-vault",
+                "GATEWAY_SNAPSHOT_VAULT_URL",
+                "/v1/deletions",
+                "GATEWAY_SNAPSHOT_VAULT_ADMIN_TOKEN",
+            ),
+            service(
+                "security-monitor",
+                "GATEWAY_SECURITY_MONITOR_URL",
+                "/api/deletions",
+                "GATEWAY_SECURITY_MONITOR_ADMIN_TOKEN",
+            ),
+        ];
+        Self::open(root, key.as_deref().map(str::as_bytes), services).await
+    }
+
+    pub async fn open<P: AsRef<FsPath>>(
+        root: P,
+        key: Option<&[u8]>,
+        services: Vec<DeletionService>,
+    ) -> anyhow::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root).await?;
+
+        let index = root.join("deletions.json");
+        let certificates: Vec<DeletionCertificate> = match fs::read(&index).await {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|e| anyhow::anyhow!("failed to load {}: {}", index.display(), e))?,
+            Err(_) => Vec::new(),
+        };
+        Ok(Self {
+            root,
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(60))
+                .build()
+                .unwrap_or_default(),
+            key: key.map(|key| hmac::Key::new(hmac::HMAC_SHA256, key)),
+            services,
+            certificates: RwLock::new(
+                certificates
+                    .into_iter()
+                    .map(|certificate| (certificate.id, certificate))
+                    .collect(),
+            ),
+        })
+    }
+
+    /// Delete a tenant's or a sandbox's data from every service
+    pub async fn delete(
+        &self,
+        tenant: Option<String>,
+        sandbox_id: Option<String>,
+        requested_by: String,
+    ) -> Result<DeletionCertificate, DeletionError> {
+        let tenant = tenant.filter(|tenant| !tenant.is_empty());
+        let sandbox_ids: Vec<String> = sandbox_id.into_iter().filter(|id| !id.is_empty()).collect();
+        if tenant.is_none() && sandbox_ids.is_empty() {
+            return Err(DeletionError::Invalid("a deletion needs a tenant or sandbox_id".to_string()));
+        }
+        if self.key.is_none() {
+            return Err(DeletionError::Unsigned);
+        }
+
+        let now = Utc::now();
+        let mut certificate = DeletionCertificate {
+            id: Uuid::new_v4(),
+            tenant,
+            sandbox_ids,
+            requested_by,
+            requested_at: now,
+            completed_at: now,
+            status: DeletionStatus::Partial,
+            services: Vec::new(),
+            failed: Vec::new(),
+            signature: None,
+        };
+        self.carry_out(&mut certificate, &self.services).await;
+        self.store(&certificate).await?;
+        info!(
+            deletion_id = %certificate.id,
+            tenant = ?certificate.tenant,
+            sandboxes = certificate.sandbox_ids.len(),
+            status = ?certificate.status,
+            "Deleted data on request"
+        );
+        Ok(certificate)
+    }
+
+    /// Ask the services a partial deletion failed at again
+    pub async fn retry(&self, id: Uuid) -> Result<DeletionCertificate, DeletionError> {
+        if self.key.is_none() {
+            return Err(DeletionError::Unsigned);
+        }
+        let mut certificate = self.get(id).await.ok_or(DeletionError::NotFound(id))?;
+        if certificate.status == DeletionStatus::Completed {
+            return Err(DeletionError::Completed(id));
+        }
+
+        let failed: Vec<_> = self
+            .services
+            .iter()
+            .filter(|service| certificate.failed.iter().any(|failed| failed.service == service.name))
+            .cloned()
+            .collect();
+        certificate.failed.clear();
+        self.carry_out(&mut certificate, &failed).await;
+        self.store(&certificate).await?;
+        info!(deletion_id = %id, status = ?certificate.status, "Retried deletion");
+        Ok(certificate)
+    }
+
+    /// Certificates, newest first
+    pub async fn list(&self) -> Vec<DeletionCertificate> {
+        let mut certificates: Vec<_> = self.certificates.read().await.values().cloned().collect();
+        certificates.sort_by_key(|certificate| std::cmp::Reverse(certificate.requested_at));
+        certificates
+    }
+
+    pub async fn get(&self, id: Uuid) -> Option<DeletionCertificate> {
+        self.certificates.read().await.get(&id).cloned()
+    }
+
+    /// Ask each service in turn to delete the certificate's data, and sign
+    /// the outcome
+    async fn carry_out(&self, certificate: &mut DeletionCertificate, services: &[DeletionService]) {
+        let mut sandbox_ids: BTreeSet<String> = certificate.sandbox_ids.iter().cloned().collect();
+        for service in services {
+            let request = DeletionRequest {
+                tenant: certificate.tenant.clone(),
+                sandbox_ids: sandbox_ids.iter().cloned().collect(),
+            };
+            match self.request(service, &request).await {
+                Ok(deletion) => {
+                    sandbox_ids.extend(deletion.sandbox_ids.iter().cloned());
+                    certificate.services.retain(|done| done.service != deletion.service);
+                    certificate.services.push(deletion);
+                }
+                Err(e) => {
+                    warn!(deletion_id = %certificate.id, service = service.name, "Deletion failed: {:#}", e);
+                    certificate.failed.push(FailedDeletion {
+                        service: service.name.to_string(),
+                        error: format!("{:#}", e),
+                    });
+                }
+            }
+        }
+
+        certificate.sandbox_ids = sandbox_ids.into_iter().collect();
+        certificate.completed_at = Utc::now();
+        certificate.status = if certificate.failed.is_empty() {
+            DeletionStatus::Completed
+        } else {
+            DeletionStatus::Partial
+        };
+        certificate.signature = None;
+        if let Some(key) = &self.key {
+            certificate.signature = Some(sign(key, certificate));
+        }
+    }
+
+    async fn request(&self, service: &DeletionService, request: &DeletionRequest) -> anyhow::Result<ServiceDeletion> {
+        let Some(url) = &service.url else {
+            anyhow::bail!("{} is not configured", service.name);
+        };
+        let mut builder = self.http.post(format!("{}{}", url, service.path)).json(request);
+        if let Some(token) = &service.admin_token {
+            builder = builder.bearer_auth(token);
+        }
+        let response = builder.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("{} answered {}: {}", service.name, status, body);
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn store(&self, certificate: &DeletionCertificate) -> Result<(), DeletionError> {
+        let mut certificates = self.certificates.write().await;
+        certificates.insert(certificate.id, certificate.clone());
+        self.save(&certificates).await.map_err(DeletionError::Storage)
+    }
+
+    async fn save(&self, certificates: &HashMap<Uuid, DeletionCertificate>) -> anyhow::Result<()> {
+        let mut sorted: Vec<_> = certificates.values().collect();
+        sorted.sort_by_key(|certificate| certificate.requested_at);
+        // Write then rename so a crash never leaves a truncated file behind
+        let path = self.root.join("deletions.json");
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&sorted)?).await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+}
+
+/// `sha256=<hex>` HMAC of the certificate without its signature
+fn sign(key: &hmac::Key, certificate: &DeletionCertificate) -> String {
+    let unsigned = DeletionCertificate {
+        signature: None,
+        ..certificate.clone()
+    };
+    let body = serde_json::to_vec(&unsigned).unwrap_or_default();
+    let hex: String = hmac::sign(key, &body)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256={}", hex)
+}
+
+fn error_response(e: DeletionError) -> (StatusCode, String) {
+    (e.status(), e.to_string())
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/deletions",
+    tag = "deletions",
+    params(("X-Sandstorm-User" = Option<String>, Header, description = "Who asked for the deletion")),
+    request_body = CreateDeletionRequest,
+    responses(
+        (status = 200, description = "Every service deleted the data", body = DeletionCertificate),
+        (status = 207, description = "Some services failed; retry the deletion", body = DeletionCertificate),
+        (status = 400, description = "Neither a tenant nor a sandbox"),
+        (status = 503, description = "GATEWAY_DELETION_SIGNING_KEY is unset"),
+    )
+)]
+pub async fn create_deletion(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<CreateDeletionRequest>,
+) -> Result<(StatusCode, Json<DeletionCertificate>), (StatusCode, String)> {
+    let certificate = state
+        .deletions
+        .delete(request.tenant, request.sandbox_id, user_from_headers(&headers))
+        .await
+        .map_err(error_response)?;
+    Ok((response_status(&certificate), Json(certificate)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/deletions",
+    tag = "deletions",
+    responses((status = 200, description = "Deletion certificates, newest first", body = [DeletionCertificate]))
+)]
+pub async fn list_deletions(State(state): State<AppState>) -> Json<Vec<DeletionCertificate>> {
+    Json(state.deletions.list().await)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/deletions/{id}",
+    tag = "deletions",
+    params(("id" = Uuid, Path, description = "Deletion ID")),
+    responses(
+        (status = 200, description = "The deletion's certificate", body = DeletionCertificate),
+        (status = 404, description = "No such deletion"),
+    )
+)]
+pub async fn get_deletion(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<DeletionCertificate>, (StatusCode, String)> {
+    state
+        .deletions
+        .get(id)
+        .await
+        .map(Json)
+        .ok_or_else(|| error_response(DeletionError::NotFound(id)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/deletions/{id}/retry",
+    tag = "deletions",
+    params(("id" = Uuid, Path, description = "Deletion ID")),
+    responses(
+        (status = 200, description = "Every service has now deleted the data", body = DeletionCertificate),
+        (status = 207, description = "Some services still failed", body = DeletionCertificate),
+        (status = 404, description = "No such deletion"),
+        (status = 409, description = "The deletion already completed"),
+    )
+)]
+pub async fn retry_deletion(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<(StatusCode, Json<DeletionCertificate>), (StatusCode, String)> {
+    let certificate = state.deletions.retry(id).await.map_err(error_response)?;
+    Ok((response_status(&certificate), Json(certificate)))
+}
+
+fn response_status(certificate: &DeletionCertificate) -> StatusCode {
+    match certificate.status {
+        DeletionStatus::Completed => StatusCode::OK,
+        DeletionStatus::Partial => StatusCode::MULTI_STATUS,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::post, Router};
+    use std::collections::BTreeMap;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    #[tokio::test]
+    async fn passes_found_sandboxes_on_and_retries_failed_services() {
+        // A collector that finds the tenant's sandbox, and a monitor that
+        // fails until it has been asked once
+        let monitor_up = Arc::new(AtomicBool::new(false));
+        let up = monitor_up.clone();
+        let app = Router::new()
+            .route(
+                "/collector",
+                post(|Json(request): Json<DeletionRequest>| async move {
+                    assert_eq!(request.tenant.as_deref(), Some("acme"));
+                    Json(ServiceDeletion {
+                        service: "telemetry-collector".to_string(),
+                        removed: BTreeMap::from([("sandbox_runs".to_string(), 2)]),
+                        retained: Vec::new(),
+                        sandbox_ids: vec!["sb-1".to_string()],
+                        completed_at: Utc::now(),
+                    })
+                }),
+            )
+            .route(
+                "/monitor",
+                post(move |headers: HeaderMap, Json(request): Json<DeletionRequest>| async move {
+                    assert_eq!(request.sandbox_ids, ["sb-1"]);
+                    assert_eq!(headers["authorization"], "Bearer monitor-token");
+                    if !up.swap(true, Ordering::SeqCst) {
+                        return Err(StatusCode::SERVICE_UNAVAILABLE);
+                    }
+                    Ok(Json(ServiceDeletion {
+                        service: "security-monitor".to_string(),
+                        removed: BTreeMap::from([("security_events".to_string(), 5)]),
+                        retained: Vec::new(),
+                        sandbox_ids: request.sandbox_ids,
+                        completed_at: Utc::now(),
+                    }))
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let service = |name, path, token: Option<&str>| DeletionService {
+            name,
+            url: Some(url.clone()),
+            path,
+            admin_token: token.map(str::to_string),
+        };
+        let root = std::env::temp_dir().join(format!("sandstorm-deletions-{}", Uuid::new_v4()));
+        let services = vec![
+            service("telemetry-collector", "/collector", None),
+            service("security-monitor", "/monitor", Some("monitor-token")),
+        ];
+        let deletions = Deletions::open(&root, Some(b"secret"), services.clone()).await.unwrap();
+
+        assert!(matches!(
+            deletions.delete(None, None, "alice".into()).await,
+            Err(DeletionError::Invalid(_))
+        ));
+
+        let partial = deletions.delete(Some("acme".into()), None, "alice".into()).await.unwrap();
+        assert_eq!(partial.status, DeletionStatus::Partial);
+        assert_eq!(partial.sandbox_ids, ["sb-1"]);
+        assert_eq!(partial.services.len(), 1);
+        assert_eq!(partial.failed[0].service, "security-monitor");
+
+        let completed = deletions.retry(partial.id).await.unwrap();
+        assert_eq!(completed.status, DeletionStatus::Completed);
+        assert!(completed.failed.is_empty());
+        let services_done: Vec<_> = completed.services.iter().map(|done| done.service.as_str()).collect();
+        assert_eq!(services_done, ["telemetry-collector", "security-monitor"]);
+        assert!(matches!(deletions.retry(partial.id).await, Err(DeletionError::Completed(_))));
+
+        // The signature covers the certificate, and it survives a restart
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"secret");
+        assert_eq!(completed.signature.as_deref(), Some(sign(&key, &completed).as_str()));
+        let mut tampered = completed.clone();
+        tampered.services[1].removed.insert("security_events".to_string(), 0);
+        assert_ne!(completed.signature.as_deref(), Some(sign(&key, &tampered).as_str()));
+
+        let reopened = Deletions::open(&root, Some(b"secret"), services).await.unwrap();
+        assert_eq!(reopened.get(partial.id).await.unwrap().signature, completed.signature);
+        fs::remove_dir_all(root).await.unwrap();
+    }
+}
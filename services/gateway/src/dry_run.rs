@@ -0,0 +1,201 @@
+//! Dry runs of sandbox requests, for CI pipelines checking configurations
+//! before they run anything. A run request is resolved as far as it would
+//! be for real (runtime, boot images, template layers, network policy,
+//! host demand) without creating a sandbox, reserving capacity, fetching
+//! layers or reporting security events.
+
+use axum::{extract::State, http::HeaderMap, Json};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::approvals::ApprovalReason;
+use crate::ownership::tenant_from_headers;
+use crate::provenance::run_id_from_headers;
+use crate::quota::{self, Admission};
+use crate::runtime::{capacity::Demand, Arch, RuntimeType, SandboxConfig};
+use crate::{
+    blocking_rules, check_request, downgrade, sandbox_config, scan, security, AppState,
+    RunSandboxRequest,
+};
+
+#[derive(Debug, Serialize)]
+pub struct ValidationReport {
+    /// Whether the request would be admitted: nothing denied it
+    pub valid: bool,
+    /// The configuration the sandbox would be created with
+    pub config: SandboxConfig,
+    /// Runtime that would run the sandbox
+    pub runtime: Option<RuntimeType>,
+    /// Kernel and root filesystem images a Firecracker VM would boot from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boot_images: Option<BootImages>,
+    /// Host resources the sandbox would be charged
+    pub demand: Demand,
+    /// Whether the run would wait for host capacity, or preempt
+    /// lower-priority work, if started now
+    pub queued: bool,
+    pub denials: Vec<Denial>,
+    /// What the admission scan found in the code, if anything
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub findings: Vec<scan::Finding>,
+    /// What would be admitted but reported to the security monitor
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// Approval rules the run matches; it would wait for an approver
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub approval_required: Vec<ApprovalReason>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BootImages {
+    pub kernel: Option<Uuid>,
+    pub rootfs: Option<Uuid>,
+}
+
+/// A check the request fails
+#[derive(Debug, Serialize)]
+pub struct Denial {
+    /// `request`, `quota`, `scan`, `template`, `network`, `runtime` or
+    /// `image`
+    pub check: &'static str,
+    pub reason: String,
+}
+
+impl Denial {
+    fn new(check: &'static str, reason: impl std::fmt::Display) -> Self {
+        Self {
+            check,
+            reason: format!("{:#}", reason),
+        }
+    }
+}
+
+/// Resolve a run request without running it. Denied requests get a report
+/// too, listing every check they fail.
+pub async fn validate_sandbox(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(mut req): Json<RunSandboxRequest>,
+) -> Json<ValidationReport> {
+    let run_id = run_id_from_headers(&headers).unwrap_or_else(Uuid::new_v4);
+    let tenant = tenant_from_headers(&headers);
+    let registry = &state.runtime_registry;
+    let mut denials = Vec::new();
+    let mut warnings = Vec::new();
+
+    if let Err(e) = check_request(&state, &req) {
+        denials.push(Denial::new("request", e));
+    }
+    let approval_required = state.approvals.policy.reasons(&req);
+
+    let quota_tenant = tenant.as_deref().unwrap_or(quota::DEFAULT_TENANT);
+    if let Some(usage) = state.quotas.usage(quota_tenant).await {
+        match quota::admission(&usage) {
+            Admission::Reject => denials.push(Denial::new(
+                "quota",
+                format!("Tenant {} has used up its {} quota", usage.tenant, usage.month),
+            )),
+            Admission::Downgrade => {
+                warnings.push(format!(
+                    "Tenant {} has used up its {} quota; the run would be downgraded",
+                    usage.tenant, usage.month
+                ));
+                downgrade(&mut req);
+            }
+            Admission::Allow => {}
+        }
+    }
+
+    let findings = match &state.code_scanner {
+        Some(scanner) => scanner.scan(&req.language, &req.code),
+        None => Vec::new(),
+    };
+    if scan::verdict(&findings) == Some(scan::ScanAction::Block) {
+        denials.push(Denial::new(
+            "scan",
+            format!("Code blocked by scan rules: {}", blocking_rules(&findings)),
+        ));
+    }
+
+    let rootfs_layers = match (req.template_snapshot, &state.vault) {
+        (Some(template), Some(vault)) => {
+            match state.layers.locate(vault, template, tenant.as_deref()).await {
+                Ok(layers) => layers,
+                Err(e) => {
+                    denials.push(Denial::new("template", e));
+                    Vec::new()
+                }
+            }
+        }
+        _ => Vec::new(),
+    };
+
+    let config = sandbox_config(&req, Uuid::new_v4(), run_id, rootfs_layers);
+    if let Some(event) = security::network_violation(&config, Some(run_id)) {
+        denials.push(Denial::new("network", event.message));
+    }
+
+    // A runtime that can't take the sandbox for lack of room now would
+    // still run it once there is some
+    let demand = registry.demand(req.cpu_limit, req.memory_limit);
+    let mut queued = false;
+    let selected = match registry.select_runtime(&config, req.optimize_for, demand).await {
+        Ok(runtime) => Ok(runtime),
+        Err(e) => {
+            queued = true;
+            registry
+                .select_runtime(&config, req.optimize_for, Demand::default())
+                .await
+                .map_err(|_| e)
+        }
+    };
+    let runtime = match selected {
+        Ok(runtime) => {
+            if let Err(e) = runtime.validate(&config) {
+                denials.push(Denial::new("runtime", e));
+            }
+            Some(runtime)
+        }
+        Err(e) => {
+            queued = false;
+            denials.push(Denial::new("runtime", e));
+            None
+        }
+    };
+
+    let mut boot_images = None;
+    if let Some(runtime) = &runtime {
+        let runtime_type = runtime.runtime_type();
+        if runtime_type == RuntimeType::Firecracker {
+            if let Some(arch) = config.arch.or_else(Arch::host) {
+                match state.images.select(arch, &req.language).await {
+                    Ok(selection) => {
+                        boot_images = Some(BootImages {
+                            kernel: selection.kernel.map(|(id, _)| id),
+                            rootfs: selection.rootfs.map(|(id, _)| id),
+                        })
+                    }
+                    Err(e) => denials.push(Denial::new("image", e)),
+                }
+            }
+        }
+        if let Some(event) =
+            security::privileged_mounts(config.id, runtime_type, Some(run_id), &config)
+        {
+            warnings.push(event.message);
+        }
+    }
+
+    Json(ValidationReport {
+        valid: denials.is_empty(),
+        runtime: runtime.map(|runtime| runtime.runtime_type()),
+        config,
+        boot_images,
+        demand,
+        queued,
+        denials,
+        findings,
+        warnings,
+        approval_required,
+    })
+}
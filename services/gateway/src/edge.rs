@@ -0,0 +1,223 @@
+//! Dispatches runs to edge agents. Agents are picked from the telemetry
+//! collector's capacity feed, least loaded first, counting the runs this
+//! gateway has in flight on each, which the agents' heartbeats may not show
+//! yet.
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::State,
+    http::{HeaderMap, HeaderValue, StatusCode},
+    Json,
+};
+use sandstorm_types::telemetry::{EdgeCapacity, EdgeCapacityFeed};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::AppState;
+
+/// How long a fetched capacity feed is used before asking again
+const FEED_TTL: Duration = Duration::from_secs(10);
+
+/// Edge agents answer `/run` once the sandbox has finished
+const RUN_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Names the agent a dispatched run went to
+pub const EDGE_AGENT_HEADER: &str = "x-sandstorm-edge-agent";
+
+#[derive(Debug, thiserror::Error)]
+pub enum DispatchError {
+    #[error("GATEWAY_TELEMETRY_URL is not set")]
+    Unconfigured,
+    #[error("Edge capacity feed unavailable: {0:#}")]
+    Feed(anyhow::Error),
+    #[error("No edge agent can take the run")]
+    NoAgent,
+    #[error("Edge agent {agent} failed the run: {error:#}")]
+    Agent { agent: String, error: anyhow::Error },
+}
+
+impl DispatchError {
+    fn status(&self) -> StatusCode {
+        match self {
+            DispatchError::Agent { .. } => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct EdgeDispatcher {
+    http: reqwest::Client,
+    telemetry_url: Option<String>,
+    feed: RwLock<Option<(Instant, Vec<EdgeCapacity>)>>,
+    /// Runs this gateway sent to each agent that haven't finished
+    in_flight: RwLock<HashMap<String, u32>>,
+}
+
+impl EdgeDispatcher {
+    pub fn new(telemetry_url: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            telemetry_url: telemetry_url.map(|url| url.trim_end_matches('/').to_string()),
+            feed: RwLock::new(None),
+            in_flight: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Capacity comes from the collector at `GATEWAY_TELEMETRY_URL`
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("GATEWAY_TELEMETRY_URL").ok())
+    }
+
+    /// Agents as of the latest feed, fetched again once it's `FEED_TTL` old
+    async fn agents(&self) -> Result<Vec<EdgeCapacity>, DispatchError> {
+        if let Some((fetched_at, agents)) = self.feed.read().await.as_ref() {
+            if fetched_at.elapsed() < FEED_TTL {
+                return Ok(agents.clone());
+            }
+        }
+
+        let telemetry_url = self.telemetry_url.as_deref().ok_or(DispatchError::Unconfigured)?;
+        let feed = self
+            .fetch_feed(telemetry_url)
+            .await
+            .map_err(DispatchError::Feed)?;
+        *self.feed.write().await = Some((Instant::now(), feed.agents.clone()));
+        Ok(feed.agents)
+    }
+
+    async fn fetch_feed(&self, telemetry_url: &str) -> Result<EdgeCapacityFeed> {
+        Ok(self
+            .http
+            .get(format!("{}/v1/edge/capacity", telemetry_url))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    /// Run a sandbox spec on the least-loaded agent. Returns the agent and
+    /// its result.
+    pub async fn dispatch(
+        &self,
+        spec: &serde_json::Value,
+    ) -> Result<(String, serde_json::Value), DispatchError> {
+        let agents = self.agents().await?;
+        let (agent, endpoint) = {
+            let mut in_flight = self.in_flight.write().await;
+            let agent = least_loaded(&agents, &in_flight).ok_or(DispatchError::NoAgent)?;
+            let endpoint = agent.endpoint.clone().ok_or(DispatchError::NoAgent)?;
+            *in_flight.entry(agent.agent_id.clone()).or_default() += 1;
+            (agent.agent_id.clone(), endpoint)
+        };
+        info!(agent_id = %agent, "Dispatching run to edge agent");
+
+        let result = self.run_on(&endpoint, spec).await;
+
+        let mut in_flight = self.in_flight.write().await;
+        if let Some(count) = in_flight.get_mut(&agent) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                in_flight.remove(&agent);
+            }
+        }
+        drop(in_flight);
+
+        match result {
+            Ok(result) => Ok((agent, result)),
+            Err(error) => Err(DispatchError::Agent { agent, error }),
+        }
+    }
+
+    async fn run_on(&self, endpoint: &str, spec: &serde_json::Value) -> Result<serde_json::Value> {
+        let response = self
+            .http
+            .post(format!("{}/run", endpoint.trim_end_matches('/')))
+            .timeout(RUN_TIMEOUT)
+            .json(spec)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("{}: {}", status, body);
+        }
+        response.json().await.context("invalid run result")
+    }
+}
+
+/// Schedulable agent with the lowest load, counting each run in flight on
+/// it as one more sandbox
+pub fn least_loaded<'a>(
+    agents: &'a [EdgeCapacity],
+    in_flight: &HashMap<String, u32>,
+) -> Option<&'a EdgeCapacity> {
+    let load = |agent: &EdgeCapacity| {
+        agent.load() + in_flight.get(&agent.agent_id).copied().unwrap_or(0) as f64
+    };
+    agents
+        .iter()
+        .filter(|agent| agent.schedulable && agent.endpoint.is_some())
+        .min_by(|a, b| load(a).total_cmp(&load(b)))
+}
+
+pub async fn run_on_edge(
+    State(state): State<AppState>,
+    Json(spec): Json<serde_json::Value>,
+) -> Result<(HeaderMap, Json<serde_json::Value>), (StatusCode, String)> {
+    let (agent, result) = state.edge.dispatch(&spec).await.map_err(|e| {
+        warn!("{}", e);
+        (e.status(), e.to_string())
+    })?;
+    let mut headers = HeaderMap::new();
+    if let Ok(value) = HeaderValue::from_str(&agent) {
+        headers.insert(EDGE_AGENT_HEADER, value);
+    }
+    Ok((headers, Json(result)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn agent(id: &str, running: u32, cpu_headroom: f64, schedulable: bool) -> EdgeCapacity {
+        EdgeCapacity {
+            agent_id: id.to_string(),
+            agent_name: None,
+            endpoint: Some(format!("http://{}:8080", id)),
+            status: "running".to_string(),
+            queue_depth: 0,
+            running,
+            cpu_headroom_percent: Some(cpu_headroom),
+            memory_headroom_percent: None,
+            last_heartbeat: Utc::now(),
+            schedulable,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn picks_the_least_loaded_schedulable_agent() {
+        let agents = [
+            agent("busy", 3, 50.0, true),
+            agent("idle-but-stale", 0, 90.0, false),
+            agent("quiet", 1, 20.0, true),
+            agent("quieter", 1, 60.0, true),
+        ];
+        let mut in_flight = HashMap::new();
+        assert_eq!(least_loaded(&agents, &in_flight).unwrap().agent_id, "quieter");
+
+        // Runs already sent there count until they finish
+        in_flight.insert("quieter".to_string(), 2);
+        assert_eq!(least_loaded(&agents, &in_flight).unwrap().agent_id, "quiet");
+
+        assert!(least_loaded(&agents[1..2], &HashMap::new()).is_none());
+    }
+}
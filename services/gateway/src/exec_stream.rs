@@ -0,0 +1,537 @@
+//! Execs whose output is wanted while they run, over a WebSocket or a
+//! chunked HTTP response.
+//!
+//! Over a WebSocket the client sends the exec request as the first (text)
+//! message; the gateway answers with the command's output as binary
+//! messages, each prefixed with the stream it came from, then a text message
+//! with how the command exited, and closes the socket. Closing the socket
+//! early kills the command. A request with `stream_stdin` keeps the
+//! command's stdin open after any `stdin` it carries: binary messages
+//! prefixed with [`STDIN_CHANNEL`] are written to it, and one with nothing
+//! after the prefix closes it.
+//!
+//! Over HTTP the exec request is the body, and the response is one JSON
+//! object per line: output as it comes, then how the command exited.
+//!
+//! Either way a command that goes quiet for [`HEARTBEAT_INTERVAL`] gets a
+//! heartbeat sent on its behalf (a ping over a WebSocket), so proxies keep
+//! the connection open and clients can tell it's still running.
+
+use axum::{
+    body::Body,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, State,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio::sync::mpsc;
+use tokio_util::io::ReaderStream;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use sandstorm_types::security::QuarantineMode;
+
+use crate::recording::Recorder;
+use crate::runtime::{
+    ExecOutput, ExitReason, ResourceUsage, SandboxResult, SandboxRuntime, UnsupportedExecOptions,
+};
+use crate::{exec_recorder, exec_trace_id, security, AppState, ExecRequest};
+
+/// First byte of a binary message carrying stdin
+pub const STDIN_CHANNEL: u8 = 0;
+/// First byte of a binary message carrying stdout
+pub const STDOUT_CHANNEL: u8 = 1;
+/// First byte of a binary message carrying stderr
+pub const STDERR_CHANNEL: u8 = 2;
+
+/// Output chunks buffered between the command and a slow client before
+/// the command is held up
+const OUTPUT_BUFFER: usize = 64;
+
+/// Stdin chunks buffered between the client and a command that isn't
+/// reading before the client is held up
+const INPUT_BUFFER: usize = 64;
+
+/// Bytes of JSON lines buffered for a slow HTTP client before the command
+/// is held up
+const LINE_BUFFER: usize = 64 * 1024;
+
+/// How long a command can go without output before a heartbeat is sent
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize)]
+struct StreamRequest {
+    #[serde(flatten)]
+    exec: ExecRequest,
+    /// Keep stdin open for [`STDIN_CHANNEL`] messages
+    #[serde(default)]
+    stream_stdin: bool,
+}
+
+/// The last message of a session
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Closing {
+    /// The command finished, or was given up on
+    Exit {
+        exit_code: i32,
+        exit_reason: ExitReason,
+        duration_ms: u64,
+        resource_usage: ResourceUsage,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        recording_id: Option<Uuid>,
+    },
+    /// The command couldn't be run; `status` is what the exec endpoint
+    /// would have answered
+    Error { status: u16, error: String },
+}
+
+impl Closing {
+    fn exit(result: &SandboxResult, recording_id: Option<Uuid>) -> Self {
+        Closing::Exit {
+            exit_code: result.exit_code,
+            exit_reason: result.exit_reason,
+            duration_ms: result.duration_ms,
+            resource_usage: result.resource_usage.clone(),
+            recording_id,
+        }
+    }
+
+    fn unfinished(exit_reason: ExitReason, duration_ms: u64) -> Self {
+        Closing::Exit {
+            exit_code: -1,
+            exit_reason,
+            duration_ms,
+            resource_usage: ResourceUsage::default(),
+            recording_id: None,
+        }
+    }
+
+    fn error(status: StatusCode, error: impl std::fmt::Display) -> Self {
+        Closing::Error {
+            status: status.as_u16(),
+            error: error.to_string(),
+        }
+    }
+}
+
+/// A line of an HTTP exec stream before the last
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Progress<'a> {
+    Stdout { data: Cow<'a, str> },
+    Stderr { data: Cow<'a, str> },
+    Heartbeat { elapsed_ms: u64 },
+}
+
+/// The client went away
+struct Gone;
+
+/// What the client sent during a session
+enum Incoming {
+    Stdin(Vec<u8>),
+    Other,
+    Gone,
+}
+
+/// The client's end of a session
+enum Peer {
+    Socket(Box<WebSocket>),
+    /// The body of a chunked HTTP response, written a JSON line at a time
+    Lines(DuplexStream),
+}
+
+impl Peer {
+    async fn output(&mut self, chunk: &ExecOutput) -> Result<(), Gone> {
+        match self {
+            Peer::Socket(socket) => {
+                let (channel, data) = match chunk {
+                    ExecOutput::Stdout(data) => (STDOUT_CHANNEL, data),
+                    ExecOutput::Stderr(data) => (STDERR_CHANNEL, data),
+                };
+                let mut message = Vec::with_capacity(data.len() + 1);
+                message.push(channel);
+                message.extend_from_slice(data);
+                socket.send(Message::Binary(message)).await.map_err(|_| Gone)
+            }
+            Peer::Lines(lines) => {
+                let progress = match chunk {
+                    ExecOutput::Stdout(data) => Progress::Stdout {
+                        data: String::from_utf8_lossy(data),
+                    },
+                    ExecOutput::Stderr(data) => Progress::Stderr {
+                        data: String::from_utf8_lossy(data),
+                    },
+                };
+                write_line(lines, &progress).await
+            }
+        }
+    }
+
+    async fn heartbeat(&mut self, elapsed: Duration) -> Result<(), Gone> {
+        match self {
+            Peer::Socket(socket) => socket.send(Message::Ping(Vec::new())).await.map_err(|_| Gone),
+            Peer::Lines(lines) => {
+                let elapsed_ms = elapsed.as_millis() as u64;
+                write_line(lines, &Progress::Heartbeat { elapsed_ms }).await
+            }
+        }
+    }
+
+    /// The next message from the client. An HTTP client has nothing more
+    /// to send, and hanging up is noticed at the next line written.
+    async fn recv(&mut self) -> Incoming {
+        match self {
+            Peer::Socket(socket) => match socket.recv().await {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => Incoming::Gone,
+                Some(Ok(Message::Binary(data))) if data.first() == Some(&STDIN_CHANNEL) => {
+                    Incoming::Stdin(data[1..].to_vec())
+                }
+                // Pings are answered by the socket itself
+                Some(Ok(_)) => Incoming::Other,
+            },
+            Peer::Lines(_) => std::future::pending().await,
+        }
+    }
+
+    async fn close(self, closing: Closing) {
+        match self {
+            Peer::Socket(mut socket) => {
+                if let Ok(text) = serde_json::to_string(&closing) {
+                    socket.send(Message::Text(text)).await.ok();
+                }
+                socket.send(Message::Close(None)).await.ok();
+            }
+            Peer::Lines(mut lines) => {
+                if write_line(&mut lines, &closing).await.is_ok() {
+                    lines.shutdown().await.ok();
+                }
+            }
+        }
+    }
+}
+
+async fn write_line(lines: &mut DuplexStream, value: &impl Serialize) -> Result<(), Gone> {
+    let mut line = serde_json::to_vec(value).map_err(|_| Gone)?;
+    line.push(b'\n');
+    lines.write_all(&line).await.map_err(|_| Gone)
+}
+
+/// How the exec went
+enum Attempt {
+    Finished(anyhow::Result<SandboxResult>),
+    TimedOut,
+    /// The client went away; the command was killed
+    Disconnected,
+}
+
+pub async fn exec_stream(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<Response, StatusCode> {
+    // Preempted sandboxes can't run anything until they are resumed
+    let id = state.preemption.locate(id).await.ok_or(StatusCode::CONFLICT)?;
+    Ok(ws.on_upgrade(move |socket| session(state, id, headers, socket)))
+}
+
+async fn session(state: AppState, id: Uuid, headers: HeaderMap, mut socket: WebSocket) {
+    let request = match socket.recv().await {
+        Some(Ok(Message::Text(text))) => serde_json::from_str::<StreamRequest>(&text)
+            .map_err(|e| Closing::error(StatusCode::BAD_REQUEST, e)),
+        Some(Ok(_)) => Err(Closing::error(
+            StatusCode::BAD_REQUEST,
+            "The first message must be the exec request as JSON text",
+        )),
+        _ => return,
+    };
+    let mut peer = Peer::Socket(Box::new(socket));
+    let closing = match request {
+        Ok(req) => run(&state, id, &headers, &mut peer, req).await,
+        Err(closing) => Some(closing),
+    };
+    if let Some(closing) = closing {
+        peer.close(closing).await;
+    }
+}
+
+/// An exec streamed as newline-delimited JSON in a chunked response
+pub async fn exec_lines(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(req): Json<ExecRequest>,
+) -> Result<Response, StatusCode> {
+    // Preempted sandboxes can't run anything until they are resumed
+    let id = state.preemption.locate(id).await.ok_or(StatusCode::CONFLICT)?;
+    req.options.validate().map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let (lines, body) = tokio::io::duplex(LINE_BUFFER);
+    tokio::spawn(async move {
+        let mut peer = Peer::Lines(lines);
+        let req = StreamRequest {
+            exec: req,
+            stream_stdin: false,
+        };
+        if let Some(closing) = run(&state, id, &headers, &mut peer, req).await {
+            peer.close(closing).await;
+        }
+    });
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson"),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        Body::from_stream(ReaderStream::new(body)),
+    )
+        .into_response())
+}
+
+/// Run the exec, streaming its output, and say how it ended unless the
+/// client has gone
+async fn run(
+    state: &AppState,
+    id: Uuid,
+    headers: &HeaderMap,
+    peer: &mut Peer,
+    StreamRequest { exec: req, stream_stdin }: StreamRequest,
+) -> Option<Closing> {
+    if let Err(e) = req.options.validate() {
+        return Some(Closing::error(StatusCode::BAD_REQUEST, e));
+    }
+    if state.quarantines.mode(id).await == Some(QuarantineMode::Freeze) {
+        return Some(Closing::unfinished(ExitReason::Quarantined, 0));
+    }
+
+    let mut recorder =
+        exec_recorder(state, id, headers, &req.command, req.options.stdin.as_deref()).await;
+    let trace_id = exec_trace_id(state, id, headers).await;
+
+    let Some(runtime) = state.runtime_registry.runtime_of(id).await else {
+        return Some(Closing::error(
+            StatusCode::NOT_FOUND,
+            format!("Sandbox {} not found", id),
+        ));
+    };
+    let runtime_type = runtime.runtime_type();
+    let started = Instant::now();
+    let attempt = attempt(peer, &runtime, id, &req, stream_stdin, recorder.as_mut()).await;
+    let elapsed = started.elapsed();
+    match attempt {
+        Attempt::Finished(Ok(result)) => {
+            state.metrics.exec_finished(
+                runtime_type,
+                result.exit_reason,
+                elapsed.as_secs_f64(),
+                trace_id.as_deref(),
+            );
+            if let Some(event) = security::shell_exec(
+                id,
+                runtime_type,
+                state.run_ledger.get(id).await,
+                &req.command,
+                result.exit_code,
+            ) {
+                let observed = state.preemption.resumed_from(id).await.unwrap_or(id);
+                let monitoring = state.observer.security_monitoring(observed).await;
+                state.security.report(event, monitoring);
+            }
+            if result.exit_reason != ExitReason::Completed {
+                warn!(sandbox_id = %id, exit_code = result.exit_code, exit_reason = ?result.exit_reason, "Exec did not complete");
+            }
+            let recording_id = recorder.map(|recorder| {
+                let recording_id = recorder.id();
+                state.recordings.store(recorder, Some(result.exit_code));
+                recording_id
+            });
+            Some(Closing::exit(&result, recording_id))
+        }
+        Attempt::Finished(Err(e)) if e.is::<UnsupportedExecOptions>() => {
+            error!("Invalid exec options for sandbox {}: {}", id, e);
+            Some(Closing::error(StatusCode::BAD_REQUEST, e))
+        }
+        Attempt::Finished(Err(e)) => {
+            error!("Failed to exec in sandbox {}: {}", id, e);
+            Some(Closing::error(StatusCode::INTERNAL_SERVER_ERROR, e))
+        }
+        Attempt::TimedOut => {
+            error!("Exec in sandbox {} timed out after {}ms", id, elapsed.as_millis());
+            // A quarantine freezing the sandbox mid-exec stalls it
+            let exit_reason = match state.quarantines.mode(id).await {
+                Some(QuarantineMode::Freeze) => ExitReason::Quarantined,
+                _ => ExitReason::Timeout,
+            };
+            state.metrics.exec_finished(
+                runtime_type,
+                exit_reason,
+                elapsed.as_secs_f64(),
+                trace_id.as_deref(),
+            );
+            Some(Closing::unfinished(exit_reason, elapsed.as_millis() as u64))
+        }
+        Attempt::Disconnected => {
+            warn!(sandbox_id = %id, "Exec stream closed by the client, command killed");
+            None
+        }
+    }
+}
+
+/// Run the exec on the sandbox's runtime, forwarding output to the client
+/// as it comes and, with `stream_stdin`, stdin to the command
+async fn attempt(
+    peer: &mut Peer,
+    runtime: &Arc<dyn SandboxRuntime>,
+    id: Uuid,
+    req: &ExecRequest,
+    stream_stdin: bool,
+    mut recorder: Option<&mut Recorder>,
+) -> Attempt {
+    let (sender, mut receiver) = mpsc::channel(OUTPUT_BUFFER);
+    // Stdin the command hasn't taken yet, starting with the request's own;
+    // an empty chunk closes it. The socket isn't read while this is full,
+    // but output keeps flowing so a command can't stall the session.
+    let mut pending = VecDeque::new();
+    let (mut stdin, input) = if stream_stdin {
+        if let Some(text) = &req.options.stdin {
+            pending.push_back(text.clone().into_bytes());
+        }
+        let (stdin, input) = mpsc::channel(1);
+        (Some(stdin), Some(input))
+    } else {
+        (None, None)
+    };
+    let exec = runtime.exec_streaming(
+        id,
+        req.command.clone(),
+        req.environment.clone(),
+        &req.options,
+        input,
+        sender,
+    );
+    tokio::pin!(exec);
+    let timeout = async {
+        match req.options.timeout_ms {
+            Some(ms) => tokio::time::sleep(Duration::from_millis(ms)).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(timeout);
+    let started = Instant::now();
+    let mut heartbeat = tokio::time::interval_at(
+        tokio::time::Instant::now() + HEARTBEAT_INTERVAL,
+        HEARTBEAT_INTERVAL,
+    );
+
+    let outcome = loop {
+        tokio::select! {
+            outcome = &mut exec => break outcome,
+            Some(chunk) = receiver.recv() => {
+                if forward(peer, chunk, recorder.as_deref_mut()).await.is_err() {
+                    return Attempt::Disconnected;
+                }
+                heartbeat.reset();
+            }
+            permit = reserve(&stdin), if !pending.is_empty() => match (permit, pending.pop_front()) {
+                (Some(permit), Some(chunk)) if !chunk.is_empty() => {
+                    permit.send(chunk);
+                }
+                // Closed by the client, or by the command; the rest goes nowhere
+                _ => {
+                    stdin = None;
+                    pending.clear();
+                }
+            },
+            incoming = peer.recv(), if pending.len() < INPUT_BUFFER => match incoming {
+                Incoming::Gone => return Attempt::Disconnected,
+                Incoming::Stdin(data) => {
+                    if stdin.is_some() {
+                        if let Some(recorder) = recorder.as_deref_mut() {
+                            recorder.input(&data);
+                        }
+                        pending.push_back(data);
+                    }
+                }
+                Incoming::Other => {}
+            },
+            _ = heartbeat.tick() => {
+                if peer.heartbeat(started.elapsed()).await.is_err() {
+                    return Attempt::Disconnected;
+                }
+            }
+            _ = &mut timeout => return Attempt::TimedOut,
+        }
+    };
+
+    // Output sent just before the command exited
+    while let Ok(chunk) = receiver.try_recv() {
+        if forward(peer, chunk, recorder.as_deref_mut()).await.is_err() {
+            return Attempt::Disconnected;
+        }
+    }
+    Attempt::Finished(outcome)
+}
+
+/// Room for a chunk of stdin, or `None` once stdin is closed
+async fn reserve(stdin: &Option<mpsc::Sender<Vec<u8>>>) -> Option<mpsc::OwnedPermit<Vec<u8>>> {
+    stdin.clone()?.reserve_owned().await.ok()
+}
+
+async fn forward(
+    peer: &mut Peer,
+    chunk: ExecOutput,
+    recorder: Option<&mut Recorder>,
+) -> Result<(), Gone> {
+    if let Some(recorder) = recorder {
+        let (ExecOutput::Stdout(data) | ExecOutput::Stderr(data)) = &chunk;
+        recorder.output(data);
+    }
+    peer.output(&chunk).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn http_streams_are_json_lines() {
+        let (lines, mut body) = tokio::io::duplex(LINE_BUFFER);
+        let mut peer = Peer::Lines(lines);
+        assert!(peer.output(&ExecOutput::Stdout(b"building\n".to_vec())).await.is_ok());
+        assert!(peer.heartbeat(Duration::from_secs(15)).await.is_ok());
+        assert!(peer.output(&ExecOutput::Stderr(b"\xffwarning".to_vec())).await.is_ok());
+        peer.close(Closing::unfinished(ExitReason::Timeout, 30_000)).await;
+
+        let mut text = String::new();
+        body.read_to_string(&mut text).await.unwrap();
+        let lines: Vec<serde_json::Value> =
+            text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["type"], "stdout");
+        assert_eq!(lines[0]["data"], "building\n");
+        assert_eq!(lines[1]["type"], "heartbeat");
+        assert_eq!(lines[1]["elapsed_ms"], 15_000);
+        // Output that isn't UTF-8 is passed on as best it can be
+        assert_eq!(lines[2]["data"], "\u{fffd}warning");
+        assert_eq!(lines[3]["type"], "exit");
+        assert_eq!(lines[3]["exit_reason"], "timeout");
+    }
+
+    #[tokio::test]
+    async fn hung_up_http_clients_are_gone() {
+        let (lines, body) = tokio::io::duplex(LINE_BUFFER);
+        let mut peer = Peer::Lines(lines);
+        drop(body);
+        assert!(peer.heartbeat(HEARTBEAT_INTERVAL).await.is_err());
+    }
+}
@@ -0,0 +1,200 @@
+//! Snapshots of a run's filesystem once its command exits, for runs started
+//! with `auto_snapshot_on_exit`. The root filesystem (not memory) goes to
+//! the vault, and the run's status names the snapshot, so an agent can
+//! carry on from where the run left off.
+
+use sandstorm_types::snapshot::SnapshotUpload;
+use sandstorm_vault_client::{sha256_hex, Delivery};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::runtime::{SandboxRuntime, SandboxState};
+use crate::security::provider_name;
+use crate::AppState;
+
+/// How often a run is checked for its command having exited
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a run without a timeout of its own is watched
+const DEFAULT_WATCH: Duration = Duration::from_secs(3600);
+
+/// Where a run's exit snapshot stands
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ExitSnapshot {
+    /// The command is still running, or its filesystem is being uploaded
+    Pending,
+    /// In the vault as `snapshot_id`
+    Stored { snapshot_id: Uuid },
+    /// The vault was unreachable; the upload waits in the spool as
+    /// `spool_id` and gets a snapshot ID when it is replayed
+    Spooled { spool_id: Uuid },
+    Failed { error: String },
+}
+
+#[derive(Debug, Default)]
+pub struct ExitSnapshots {
+    snapshots: RwLock<HashMap<Uuid, ExitSnapshot>>,
+}
+
+impl ExitSnapshots {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn get(&self, sandbox_id: Uuid) -> Option<ExitSnapshot> {
+        self.snapshots.read().await.get(&sandbox_id).cloned()
+    }
+
+    /// Stop tracking a destroyed sandbox. A snapshot still being taken is
+    /// dropped from the status when it finishes.
+    pub async fn forget(&self, sandbox_id: Uuid) {
+        self.snapshots.write().await.remove(&sandbox_id);
+    }
+
+    async fn set(&self, sandbox_id: Uuid, snapshot: ExitSnapshot) {
+        if let Some(current) = self.snapshots.write().await.get_mut(&sandbox_id) {
+            *current = snapshot;
+        }
+    }
+}
+
+/// Watch a run's sandbox and snapshot it once its command exits
+pub async fn spawn(
+    state: AppState,
+    runtime: Arc<dyn SandboxRuntime>,
+    sandbox_id: Uuid,
+    run_id: Uuid,
+    timeout: Option<u64>,
+    tenant: Option<String>,
+) {
+    state
+        .exit_snapshots
+        .snapshots
+        .write()
+        .await
+        .insert(sandbox_id, ExitSnapshot::Pending);
+    tokio::spawn(async move {
+        let watch = timeout.map(Duration::from_millis).unwrap_or(DEFAULT_WATCH);
+        let snapshot = match wait_for_exit(&state, runtime.as_ref(), sandbox_id, watch).await {
+            Ok(current) => take(&state, runtime.as_ref(), current, run_id, tenant.as_deref()).await,
+            Err(error) => ExitSnapshot::Failed { error },
+        };
+        state.exit_snapshots.set(sandbox_id, snapshot).await;
+    });
+}
+
+/// Wait for a run's command to exit. Returns the sandbox now holding the
+/// run, which differs from `sandbox_id` if it was preempted and resumed.
+async fn wait_for_exit(
+    state: &AppState,
+    runtime: &dyn SandboxRuntime,
+    sandbox_id: Uuid,
+    watch: Duration,
+) -> Result<Uuid, String> {
+    let deadline = Instant::now() + watch;
+    loop {
+        if Instant::now() >= deadline {
+            return Err("the command was still running when the gateway stopped waiting".to_string());
+        }
+
+        // Preempted runs can't exit until they are resumed
+        if let Some(current) = state.preemption.locate(sandbox_id).await {
+            let status = runtime
+                .status(current)
+                .await
+                .map_err(|_| "the sandbox was destroyed before its command exited".to_string())?;
+            match status.state {
+                SandboxState::Failed => return Err("the sandbox failed".to_string()),
+                SandboxState::Stopped => return Ok(current),
+                _ if status.exit_code.is_some() => return Ok(current),
+                _ => {}
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Upload a sandbox's filesystem to the vault as an exit snapshot
+pub async fn take(
+    state: &AppState,
+    runtime: &dyn SandboxRuntime,
+    sandbox_id: Uuid,
+    run_id: Uuid,
+    tenant: Option<&str>,
+) -> ExitSnapshot {
+    let Some(vault) = &state.vault else {
+        return ExitSnapshot::Failed {
+            error: "GATEWAY_SNAPSHOT_VAULT_URL is not set".to_string(),
+        };
+    };
+    let filesystem = match runtime.export_filesystem(sandbox_id).await {
+        Ok(filesystem) => filesystem,
+        Err(e) => {
+            warn!("Failed to export the filesystem of sandbox {}: {:#}", sandbox_id, e);
+            return ExitSnapshot::Failed {
+                error: format!("{:#}", e),
+            };
+        }
+    };
+
+    let upload = SnapshotUpload {
+        sandbox_id: sandbox_id.to_string(),
+        provider: provider_name(runtime.runtime_type()),
+        filesystem_hash: sha256_hex(&filesystem),
+        memory_hash: None,
+        metadata: Some(serde_json::json!({ "kind": "exit", "archive": "tar" })),
+        format: None,
+        run_id: Some(run_id),
+        pinned: false,
+        base_layer: None,
+    };
+    match vault.upload_snapshot(upload, filesystem, tenant).await {
+        Ok(Delivery::Stored(metadata)) => {
+            info!(%sandbox_id, %run_id, snapshot_id = %metadata.id, "Stored exit snapshot");
+            ExitSnapshot::Stored {
+                snapshot_id: metadata.id,
+            }
+        }
+        Ok(Delivery::Spooled(spool_id)) => ExitSnapshot::Spooled { spool_id },
+        Err(e) => {
+            warn!("Failed to upload the exit snapshot of sandbox {}: {:#}", sandbox_id, e);
+            ExitSnapshot::Failed {
+                error: format!("{:#}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_tracks_sandboxes_until_destroyed() {
+        let snapshots = ExitSnapshots::new();
+        let id = Uuid::new_v4();
+        snapshots.snapshots.write().await.insert(id, ExitSnapshot::Pending);
+
+        let stored = ExitSnapshot::Stored {
+            snapshot_id: Uuid::new_v4(),
+        };
+        snapshots.set(id, stored.clone()).await;
+        assert_eq!(snapshots.get(id).await, Some(stored));
+        assert_eq!(
+            serde_json::to_value(ExitSnapshot::Pending).unwrap(),
+            serde_json::json!({ "status": "pending" })
+        );
+
+        // A snapshot finishing after its sandbox was destroyed isn't kept
+        snapshots.forget(id).await;
+        snapshots.set(id, ExitSnapshot::Pending).await;
+        assert_eq!(snapshots.get(id).await, None);
+    }
+}
@@ -0,0 +1,266 @@
+//! Vulnerability scans of root filesystem images. A root filesystem is
+//! scanned in the background once it is registered, and again on request as
+//! the scanner's advisories move on. With `GATEWAY_IMAGE_SCAN_BLOCK` set,
+//! images with findings at or above that severity, or not yet scanned, are
+//! never booted.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::process::Command;
+
+use crate::runtime::command;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Unknown,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl std::str::FromStr for Severity {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "unknown" => Ok(Self::Unknown),
+            "low" => Ok(Self::Low),
+            "medium" => Ok(Self::Medium),
+            "high" => Ok(Self::High),
+            "critical" => Ok(Self::Critical),
+            other => anyhow::bail!("unknown severity '{}'", other),
+        }
+    }
+}
+
+/// A known vulnerability in a package installed in an image
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Vulnerability {
+    /// Advisory ID, usually a CVE
+    pub id: String,
+    pub package: String,
+    pub installed_version: String,
+    /// First version with the fix, if there is one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fixed_version: Option<String>,
+    pub severity: Severity,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanStatus {
+    /// Waiting for the scanner
+    Pending,
+    Scanned,
+    /// The scanner couldn't read the image
+    Failed,
+}
+
+/// The latest vulnerability scan of an image
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageScan {
+    pub status: ScanStatus,
+    pub scanner: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scanned_at: Option<DateTime<Utc>>,
+    /// Findings by severity
+    #[serde(default)]
+    pub counts: BTreeMap<Severity, usize>,
+    #[serde(default)]
+    pub vulnerabilities: Vec<Vulnerability>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ImageScan {
+    pub fn pending(scanner: &str) -> Self {
+        Self {
+            status: ScanStatus::Pending,
+            scanner: scanner.to_string(),
+            scanned_at: None,
+            counts: BTreeMap::new(),
+            vulnerabilities: Vec::new(),
+            error: None,
+        }
+    }
+
+    pub fn finished(scanner: &str, result: anyhow::Result<Vec<Vulnerability>>) -> Self {
+        let mut scan = Self::pending(scanner);
+        scan.scanned_at = Some(Utc::now());
+        match result {
+            Ok(vulnerabilities) => {
+                for vulnerability in &vulnerabilities {
+                    *scan.counts.entry(vulnerability.severity).or_default() += 1;
+                }
+                scan.status = ScanStatus::Scanned;
+                scan.vulnerabilities = vulnerabilities;
+            }
+            Err(e) => {
+                scan.status = ScanStatus::Failed;
+                scan.error = Some(format!("{:#}", e));
+            }
+        }
+        scan
+    }
+
+    /// Whether the image may be booted under a policy blocking findings of
+    /// `threshold` or worse; only a finished scan clears an image
+    pub fn allows(&self, threshold: Severity) -> bool {
+        self.status == ScanStatus::Scanned && self.counts.keys().all(|severity| *severity < threshold)
+    }
+}
+
+/// Finds known vulnerabilities in the packages of a root filesystem image
+#[async_trait]
+pub trait VulnerabilityScanner: Send + Sync + std::fmt::Debug {
+    /// Name recorded with each scan
+    fn name(&self) -> &str;
+
+    async fn scan(&self, image: &Path) -> anyhow::Result<Vec<Vulnerability>>;
+}
+
+/// Scans ext4 images with `trivy vm`
+#[derive(Debug)]
+pub struct TrivyScanner {
+    binary: PathBuf,
+}
+
+impl TrivyScanner {
+    pub fn new(binary: impl Into<PathBuf>) -> Self {
+        Self { binary: binary.into() }
+    }
+}
+
+#[async_trait]
+impl VulnerabilityScanner for TrivyScanner {
+    fn name(&self) -> &str {
+        "trivy"
+    }
+
+    async fn scan(&self, image: &Path) -> anyhow::Result<Vec<Vulnerability>> {
+        let mut cmd = Command::new(&self.binary);
+        cmd.args(["vm", "--scanners", "vuln", "--format", "json", "--quiet"]).arg(image);
+        let output = command::run(&mut cmd, "scan image for vulnerabilities").await?;
+        parse_trivy(&output.stdout)
+    }
+}
+
+/// Vulnerabilities in a `trivy --format json` report
+fn parse_trivy(report: &[u8]) -> anyhow::Result<Vec<Vulnerability>> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Report {
+        #[serde(default)]
+        results: Vec<Target>,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Target {
+        #[serde(default)]
+        vulnerabilities: Option<Vec<Finding>>,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Finding {
+        #[serde(rename = "VulnerabilityID")]
+        vulnerability_id: String,
+        pkg_name: String,
+        installed_version: String,
+        #[serde(default)]
+        fixed_version: Option<String>,
+        severity: String,
+        #[serde(default)]
+        title: Option<String>,
+    }
+
+    let report: Report = serde_json::from_slice(report)
+        .map_err(|e| anyhow::anyhow!("unreadable trivy report: {}", e))?;
+    Ok(report
+        .results
+        .into_iter()
+        .flat_map(|target| target.vulnerabilities.unwrap_or_default())
+        .map(|finding| Vulnerability {
+            id: finding.vulnerability_id,
+            package: finding.pkg_name,
+            installed_version: finding.installed_version,
+            fixed_version: finding.fixed_version.filter(|version| !version.is_empty()),
+            severity: finding.severity.parse().unwrap_or(Severity::Unknown),
+            title: finding.title,
+        })
+        .collect())
+}
+
+/// Which scanner root filesystems go through, and what it blocks
+#[derive(Debug, Default)]
+pub struct ScanSettings {
+    pub scanner: Option<Arc<dyn VulnerabilityScanner>>,
+    /// Severity at which findings keep an image from booting
+    pub block: Option<Severity>,
+}
+
+impl ScanSettings {
+    /// Scanner named by `GATEWAY_IMAGE_SCANNER` (only `trivy`, run from
+    /// `GATEWAY_TRIVY_PATH`, default `trivy`), blocking at
+    /// `GATEWAY_IMAGE_SCAN_BLOCK`
+    pub fn from_env() -> anyhow::Result<Self> {
+        let scanner: Option<Arc<dyn VulnerabilityScanner>> = match std::env::var("GATEWAY_IMAGE_SCANNER") {
+            Ok(name) if name == "trivy" => Some(Arc::new(TrivyScanner::new(
+                std::env::var("GATEWAY_TRIVY_PATH").unwrap_or_else(|_| "trivy".to_string()),
+            ))),
+            Ok(name) => anyhow::bail!("unknown GATEWAY_IMAGE_SCANNER '{}'", name),
+            Err(_) => None,
+        };
+        let block = match std::env::var("GATEWAY_IMAGE_SCAN_BLOCK") {
+            Ok(severity) => Some(severity.parse::<Severity>()?),
+            Err(_) => None,
+        };
+        if block.is_some() && scanner.is_none() {
+            anyhow::bail!("GATEWAY_IMAGE_SCAN_BLOCK needs GATEWAY_IMAGE_SCANNER");
+        }
+        Ok(Self { scanner, block })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_trivy_reports_and_applies_the_block_threshold() {
+        let report = br#"{
+            "SchemaVersion": 2,
+            "Results": [
+                {"Target": "rootfs.ext4 (debian 12.5)", "Vulnerabilities": [
+                    {"VulnerabilityID": "CVE-2024-2961", "PkgName": "libc6", "InstalledVersion": "2.36-9",
+                     "FixedVersion": "2.36-9+deb12u7", "Severity": "HIGH", "Title": "glibc: iconv overflow"},
+                    {"VulnerabilityID": "CVE-2023-0001", "PkgName": "zlib1g", "InstalledVersion": "1.2.13",
+                     "FixedVersion": "", "Severity": "NEGLIGIBLE"}
+                ]},
+                {"Target": "Python", "Vulnerabilities": null}
+            ]
+        }"#;
+        let vulnerabilities = parse_trivy(report).unwrap();
+        assert_eq!(vulnerabilities.len(), 2);
+        assert_eq!(vulnerabilities[0].severity, Severity::High);
+        assert_eq!(vulnerabilities[1].severity, Severity::Unknown);
+        assert_eq!(vulnerabilities[1].fixed_version, None);
+
+        let scan = ImageScan::finished("trivy", Ok(vulnerabilities));
+        assert_eq!(scan.counts[&Severity::High], 1);
+        assert!(scan.allows(Severity::Critical));
+        assert!(!scan.allows(Severity::High));
+
+        assert!(!ImageScan::pending("trivy").allows(Severity::Critical));
+        let failed = ImageScan::finished("trivy", Err(anyhow::anyhow!("no such file")));
+        assert_eq!(failed.status, ScanStatus::Failed);
+        assert!(!failed.allows(Severity::Critical));
+    }
+}
@@ -0,0 +1,721 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+use tokio::{fs, sync::RwLock};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::image_scan::{self, ImageScan, ScanSettings, Severity, VulnerabilityScanner};
+use crate::runtime::{arch, Arch};
+use crate::AppState;
+
+/// Newest verified images of each name kept by a garbage collection
+const DEFAULT_KEEP: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageKind {
+    Kernel,
+    Rootfs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageStatus {
+    /// The file matched its checksum and signature when last verified
+    Verified,
+    /// The file changed or disappeared since it was registered; it is never
+    /// booted
+    Failed,
+}
+
+/// A Firecracker kernel or root filesystem produced by the image build
+/// pipeline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BootImage {
+    pub id: Uuid,
+    pub kind: ImageKind,
+    /// Build name, e.g. `vmlinux-6.1` or `python-3.12`
+    pub name: String,
+    pub arch: Arch,
+    pub path: PathBuf,
+    /// Hex SHA-256 of the file
+    pub sha256: String,
+    /// Base64 Ed25519 signature of the file's SHA-256 digest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Language runtimes a root filesystem includes; empty for kernels and
+    /// for root filesystems that suit any language
+    #[serde(default)]
+    pub languages: Vec<String>,
+    pub size_bytes: u64,
+    pub status: ImageStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub registered_at: DateTime<Utc>,
+    pub verified_at: DateTime<Utc>,
+    /// Latest vulnerability scan of a root filesystem, when a scanner is
+    /// configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scan: Option<ImageScan>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegisterImageRequest {
+    pub kind: ImageKind,
+    pub name: String,
+    /// Defaults to the gateway host's architecture
+    #[serde(default)]
+    pub arch: Option<Arch>,
+    /// Absolute path of the file on the gateway host
+    pub path: PathBuf,
+    pub sha256: String,
+    #[serde(default)]
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub languages: Vec<String>,
+}
+
+/// Kernel and root filesystem a VM boots from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootSelection {
+    pub kernel: Option<(Uuid, PathBuf)>,
+    pub rootfs: Option<(Uuid, PathBuf)>,
+}
+
+/// Registered kernels and root filesystems, persisted as JSON in
+/// `<root>/images.json`. Images are verified against their checksum, and
+/// their signature when signing keys are configured, before they can be
+/// booted.
+#[derive(Debug)]
+pub struct ImageRegistry {
+    root: PathBuf,
+    /// Ed25519 public keys image signatures are checked against; signatures
+    /// are optional when there are none
+    signing_keys: Vec<Vec<u8>>,
+    images: RwLock<HashMap<Uuid, BootImage>>,
+    /// Images each running VM booted from, which are never collected
+    in_use: RwLock<HashMap<Uuid, Vec<Uuid>>>,
+    scanner: Option<Arc<dyn VulnerabilityScanner>>,
+    /// Root filesystems with findings this severe or worse, or without a
+    /// finished scan, are never booted
+    scan_block: Option<Severity>,
+}
+
+impl ImageRegistry {
+    /// Index under `GATEWAY_IMAGES_PATH` (default `./data/images`), with
+    /// signatures checked against the comma-separated base64 Ed25519 public
+    /// keys in `GATEWAY_IMAGE_SIGNING_KEYS`, and root filesystems scanned
+    /// as [`ScanSettings::from_env`] configures
+    pub async fn from_env() -> anyhow::Result<Self> {
+        let root = std::env::var("GATEWAY_IMAGES_PATH").unwrap_or_else(|_| "./data/images".to_string());
+        let signing_keys = std::env::var("GATEWAY_IMAGE_SIGNING_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(|key| {
+                let key = base64::engine::general_purpose::STANDARD
+                    .decode(key)
+                    .map_err(|e| anyhow::anyhow!("invalid image signing key {:?}: {}", key, e))?;
+                if key.len() != 32 {
+                    anyhow::bail!("image signing keys must be 32-byte Ed25519 public keys");
+                }
+                Ok(key)
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let scanning = ScanSettings::from_env()?;
+        Ok(Self::open(root, signing_keys).await?.with_scanner(scanning))
+    }
+
+    pub async fn open<P: AsRef<FsPath>>(root: P, signing_keys: Vec<Vec<u8>>) -> anyhow::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root).await?;
+
+        let index = root.join("images.json");
+        let images: Vec<BootImage> = match fs::read(&index).await {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|e| anyhow::anyhow!("failed to load {}: {}", index.display(), e))?,
+            Err(_) => Vec::new(),
+        };
+
+        info!("Loaded {} boot image(s)", images.len());
+        Ok(Self {
+            root,
+            signing_keys,
+            images: RwLock::new(images.into_iter().map(|image| (image.id, image)).collect()),
+            in_use: RwLock::new(HashMap::new()),
+            scanner: None,
+            scan_block: None,
+        })
+    }
+
+    /// Scan root filesystems for vulnerabilities, blocking those with
+    /// findings at the settings' severity or worse when it is set
+    pub fn with_scanner(mut self, settings: ScanSettings) -> Self {
+        self.scanner = settings.scanner;
+        self.scan_block = settings.block;
+        self
+    }
+
+    /// Verify a built image and add it. Images that don't match their
+    /// checksum or signature are refused.
+    pub async fn register(&self, request: RegisterImageRequest) -> Result<BootImage, String> {
+        if request.name.trim().is_empty() {
+            return Err("name must not be empty".to_string());
+        }
+        if !request.path.is_absolute() {
+            return Err("path must be absolute".to_string());
+        }
+        if request.kind == ImageKind::Kernel && !request.languages.is_empty() {
+            return Err("only root filesystems include language runtimes".to_string());
+        }
+
+        let arch = match request.arch {
+            Some(arch) => arch,
+            None => arch::detect().map_err(|e| format!("arch must be set: {}", e))?,
+        };
+        let now = Utc::now();
+        let mut image = BootImage {
+            id: Uuid::new_v4(),
+            kind: request.kind,
+            name: request.name,
+            arch,
+            path: request.path,
+            sha256: request.sha256.to_lowercase(),
+            signature: request.signature,
+            languages: request.languages.iter().map(|language| language.to_lowercase()).collect(),
+            size_bytes: 0,
+            status: ImageStatus::Verified,
+            error: None,
+            registered_at: now,
+            verified_at: now,
+            scan: None,
+        };
+        if image.kind == ImageKind::Rootfs {
+            image.scan = self.scanner.as_ref().map(|scanner| ImageScan::pending(scanner.name()));
+        }
+        image.size_bytes = self.check(&image).await?;
+
+        let mut images = self.images.write().await;
+        images.insert(image.id, image.clone());
+        self.save(&images).await.map_err(|e| e.to_string())?;
+        info!(image_id = %image.id, kind = ?image.kind, name = %image.name, "Boot image registered");
+        Ok(image)
+    }
+
+    pub async fn list(&self) -> Vec<BootImage> {
+        let mut images: Vec<_> = self.images.read().await.values().cloned().collect();
+        images.sort_by_key(|image| image.registered_at);
+        images
+    }
+
+    pub async fn get(&self, id: Uuid) -> Option<BootImage> {
+        self.images.read().await.get(&id).cloned()
+    }
+
+    /// Check an image's file again, e.g. after the host was tampered with.
+    /// An image that no longer matches is marked failed and not booted.
+    pub async fn verify(&self, id: Uuid) -> anyhow::Result<Option<BootImage>> {
+        let Some(mut image) = self.get(id).await else {
+            return Ok(None);
+        };
+        match self.check(&image).await {
+            Ok(size) => {
+                image.size_bytes = size;
+                image.status = ImageStatus::Verified;
+                image.error = None;
+            }
+            Err(e) => {
+                warn!(image_id = %id, "Boot image failed verification: {}", e);
+                image.status = ImageStatus::Failed;
+                image.error = Some(e);
+            }
+        }
+        image.verified_at = Utc::now();
+
+        let mut images = self.images.write().await;
+        if !images.contains_key(&id) {
+            return Ok(None);
+        }
+        images.insert(id, image.clone());
+        self.save(&images).await?;
+        Ok(Some(image))
+    }
+
+    /// Scan a root filesystem for vulnerabilities and record what was
+    /// found. Fails for kernels and when no scanner is configured.
+    pub async fn scan(&self, id: Uuid) -> Result<Option<BootImage>, String> {
+        let Some(scanner) = &self.scanner else {
+            return Err("no vulnerability scanner is configured".to_string());
+        };
+        let Some(image) = self.get(id).await else {
+            return Ok(None);
+        };
+        if image.kind != ImageKind::Rootfs {
+            return Err("only root filesystems are scanned".to_string());
+        }
+        let result = scanner.scan(&image.path).await;
+        if let Err(e) = &result {
+            warn!(image_id = %id, "Vulnerability scan failed: {:#}", e);
+        }
+        let scan = ImageScan::finished(scanner.name(), result);
+        info!(image_id = %id, status = ?scan.status, findings = scan.vulnerabilities.len(), "Boot image scanned");
+
+        let mut images = self.images.write().await;
+        let Some(image) = images.get_mut(&id) else {
+            return Ok(None);
+        };
+        image.scan = Some(scan);
+        let image = image.clone();
+        self.save(&images).await.map_err(|e| e.to_string())?;
+        Ok(Some(image))
+    }
+
+    /// Root filesystems without a finished scan, such as those registered
+    /// before a scanner was configured or while the gateway went down
+    pub async fn unscanned(&self) -> Vec<Uuid> {
+        if self.scanner.is_none() {
+            return Vec::new();
+        }
+        self.images
+            .read()
+            .await
+            .values()
+            .filter(|image| image.kind == ImageKind::Rootfs)
+            .filter(|image| image.scan.as_ref().is_none_or(|scan| scan.status == image_scan::ScanStatus::Pending))
+            .map(|image| image.id)
+            .collect()
+    }
+
+    /// Whether the scan policy lets an image boot
+    fn cleared(&self, image: &BootImage) -> bool {
+        match (self.scan_block, image.kind) {
+            (Some(threshold), ImageKind::Rootfs) => {
+                image.scan.as_ref().is_some_and(|scan| scan.allows(threshold))
+            }
+            _ => true,
+        }
+    }
+
+    /// Unregister an image and delete its file. Fails while a VM runs from
+    /// it.
+    pub async fn delete(&self, id: Uuid) -> Result<bool, String> {
+        let in_use = self.in_use.read().await;
+        if in_use.values().any(|images| images.contains(&id)) {
+            return Err(format!("image {} is in use by a running sandbox", id));
+        }
+        let mut images = self.images.write().await;
+        let Some(image) = images.remove(&id) else {
+            return Ok(false);
+        };
+        self.save(&images).await.map_err(|e| e.to_string())?;
+        remove_file(&image).await;
+        Ok(true)
+    }
+
+    /// Remove failed images, and verified ones older than the newest `keep`
+    /// of the same kind, name and architecture, unless a running VM uses
+    /// them. Returns the images removed.
+    pub async fn collect_garbage(&self, keep: usize) -> anyhow::Result<Vec<BootImage>> {
+        let in_use = self.in_use.read().await;
+        let mut images = self.images.write().await;
+
+        let mut groups: HashMap<(ImageKind, &str, Arch), Vec<&BootImage>> = HashMap::new();
+        for image in images.values().filter(|image| image.status == ImageStatus::Verified) {
+            groups
+                .entry((image.kind, image.name.as_str(), image.arch))
+                .or_default()
+                .push(image);
+        }
+        let mut expired: Vec<Uuid> = images
+            .values()
+            .filter(|image| image.status == ImageStatus::Failed)
+            .map(|image| image.id)
+            .collect();
+        for group in groups.values_mut() {
+            group.sort_by_key(|image| std::cmp::Reverse(image.registered_at));
+            expired.extend(group.iter().skip(keep).map(|image| image.id));
+        }
+        expired.retain(|id| !in_use.values().any(|images| images.contains(id)));
+
+        let removed: Vec<BootImage> = expired.iter().filter_map(|id| images.remove(id)).collect();
+        if !removed.is_empty() {
+            self.save(&images).await?;
+        }
+        drop(images);
+        for image in &removed {
+            remove_file(image).await;
+        }
+        Ok(removed)
+    }
+
+    /// Newest verified kernel and root filesystem for a VM on `arch`
+    /// running `language`, preferring root filesystems built for the
+    /// language over generic ones and skipping those the scan policy
+    /// blocks. `None` where no image of that kind is registered for the
+    /// architecture.
+    pub async fn select(&self, arch: Arch, language: &str) -> Result<BootSelection, String> {
+        let images = self.images.read().await;
+        let newest = |kind: ImageKind, matches: &dyn Fn(&BootImage) -> bool| {
+            images
+                .values()
+                .filter(|image| image.kind == kind && image.arch == arch)
+                .filter(|image| image.status == ImageStatus::Verified && matches(image))
+                .filter(|image| self.cleared(image))
+                .max_by_key(|image| image.registered_at)
+                .map(|image| (image.id, image.path.clone()))
+        };
+        let registered = |kind: ImageKind| {
+            images
+                .values()
+                .any(|image| image.kind == kind && image.arch == arch)
+        };
+
+        let language = language.to_lowercase();
+        let kernel = newest(ImageKind::Kernel, &|_| true);
+        let rootfs = newest(ImageKind::Rootfs, &|image| image.languages.contains(&language))
+            .or_else(|| newest(ImageKind::Rootfs, &|image| image.languages.is_empty()));
+
+        if kernel.is_none() && registered(ImageKind::Kernel) {
+            return Err(format!("no verified kernel for {}", arch));
+        }
+        if rootfs.is_none() && registered(ImageKind::Rootfs) {
+            let blocked = images.values().any(|image| {
+                image.kind == ImageKind::Rootfs
+                    && image.arch == arch
+                    && image.status == ImageStatus::Verified
+                    && !self.cleared(image)
+            });
+            if blocked {
+                return Err(format!(
+                    "no root filesystem for {} on {} passes the vulnerability scan policy",
+                    language, arch
+                ));
+            }
+            return Err(format!("no verified root filesystem for {} on {}", language, arch));
+        }
+        Ok(BootSelection { kernel, rootfs })
+    }
+
+    /// Record the images a VM booted from, so they aren't collected
+    pub async fn pin(&self, sandbox_id: Uuid, selection: &BootSelection) {
+        let ids = [&selection.kernel, &selection.rootfs]
+            .into_iter()
+            .flatten()
+            .map(|(id, _)| *id)
+            .collect();
+        self.in_use.write().await.insert(sandbox_id, ids);
+    }
+
+    pub async fn unpin(&self, sandbox_id: Uuid) {
+        self.in_use.write().await.remove(&sandbox_id);
+    }
+
+    /// Check an image's file against its checksum and signature, returning
+    /// its size
+    async fn check(&self, image: &BootImage) -> Result<u64, String> {
+        let path = image.path.clone();
+        let (digest, size) = tokio::task::spawn_blocking(move || {
+            let mut file = std::fs::File::open(&path)?;
+            let mut hasher = Sha256::new();
+            let size = std::io::copy(&mut file, &mut hasher)?;
+            Ok::<_, std::io::Error>((hasher.finalize(), size))
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("failed to read {}: {}", image.path.display(), e))?;
+
+        let actual: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        if actual != image.sha256 {
+            return Err(format!("checksum mismatch: expected {}, found {}", image.sha256, actual));
+        }
+
+        match &image.signature {
+            Some(signature) if !self.signing_keys.is_empty() => {
+                let signature = base64::engine::general_purpose::STANDARD
+                    .decode(signature)
+                    .map_err(|_| "malformed signature".to_string())?;
+                let trusted = self.signing_keys.iter().any(|key| {
+                    ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, key)
+                        .verify(&digest, &signature)
+                        .is_ok()
+                });
+                if !trusted {
+                    return Err("signature does not match a trusted signing key".to_string());
+                }
+            }
+            None if !self.signing_keys.is_empty() => return Err("image is not signed".to_string()),
+            _ => {}
+        }
+        Ok(size)
+    }
+
+    async fn save(&self, images: &HashMap<Uuid, BootImage>) -> anyhow::Result<()> {
+        let mut sorted: Vec<_> = images.values().collect();
+        sorted.sort_by_key(|image| image.registered_at);
+        // Write then rename so a crash never leaves a truncated index behind
+        let path = self.root.join("images.json");
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&sorted)?).await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+}
+
+/// Scan images in the background, one after another
+pub fn spawn_scans(registry: Arc<ImageRegistry>, ids: Vec<Uuid>) {
+    if ids.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        for id in ids {
+            if let Err(e) = registry.scan(id).await {
+                warn!(image_id = %id, "Failed to record vulnerability scan: {}", e);
+            }
+        }
+    });
+}
+
+async fn remove_file(image: &BootImage) {
+    if let Err(e) = fs::remove_file(&image.path).await {
+        warn!(image_id = %image.id, "Failed to remove {}: {}", image.path.display(), e);
+    }
+}
+
+pub async fn register_image(
+    State(state): State<AppState>,
+    Json(request): Json<RegisterImageRequest>,
+) -> Result<(StatusCode, Json<BootImage>), (StatusCode, String)> {
+    let image = state
+        .images
+        .register(request)
+        .await
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    if image.scan.is_some() {
+        spawn_scans(state.images.clone(), vec![image.id]);
+    }
+    Ok((StatusCode::CREATED, Json(image)))
+}
+
+pub async fn list_images(State(state): State<AppState>) -> Json<Vec<BootImage>> {
+    Json(state.images.list().await)
+}
+
+pub async fn get_image(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<BootImage>, StatusCode> {
+    state.images.get(id).await.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn verify_image(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<BootImage>, (StatusCode, String)> {
+    match state.images.verify(id).await {
+        Ok(Some(image)) => Ok(Json(image)),
+        Ok(None) => Err((StatusCode::NOT_FOUND, format!("Image {} not found", id))),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+pub async fn scan_image(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<BootImage>, (StatusCode, String)> {
+    match state.images.scan(id).await {
+        Ok(Some(image)) => Ok(Json(image)),
+        Ok(None) => Err((StatusCode::NOT_FOUND, format!("Image {} not found", id))),
+        Err(e) => Err((StatusCode::UNPROCESSABLE_ENTITY, e)),
+    }
+}
+
+pub async fn delete_image(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match state.images.delete(id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err((StatusCode::NOT_FOUND, format!("Image {} not found", id))),
+        Err(e) => Err((StatusCode::CONFLICT, e)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GcQuery {
+    keep: Option<usize>,
+}
+
+pub async fn collect_garbage(
+    State(state): State<AppState>,
+    Query(query): Query<GcQuery>,
+) -> Result<Json<Vec<BootImage>>, (StatusCode, String)> {
+    let removed = state
+        .images
+        .collect_garbage(query.keep.unwrap_or(DEFAULT_KEEP).max(1))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    info!("Collected {} boot image(s)", removed.len());
+    Ok(Json(removed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::KeyPair;
+
+    fn sha256(contents: &[u8]) -> String {
+        Sha256::digest(contents).iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn rootfs(path: &FsPath, contents: &[u8], languages: &[&str]) -> RegisterImageRequest {
+        RegisterImageRequest {
+            kind: ImageKind::Rootfs,
+            name: "rootfs".to_string(),
+            arch: Some(Arch::X86_64),
+            path: path.to_path_buf(),
+            sha256: sha256(contents),
+            signature: None,
+            languages: languages.iter().map(|language| language.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn verifies_selects_and_collects_images() {
+        let root = std::env::temp_dir().join(format!("sandstorm-images-{}", Uuid::new_v4()));
+        fs::create_dir_all(&root).await.unwrap();
+        let registry = ImageRegistry::open(&root, Vec::new()).await.unwrap();
+
+        let generic = root.join("generic.ext4");
+        fs::write(&generic, b"generic").await.unwrap();
+        let python = root.join("python.ext4");
+        fs::write(&python, b"python").await.unwrap();
+
+        let mut wrong = rootfs(&generic, b"generic", &[]);
+        wrong.sha256 = sha256(b"other");
+        assert!(registry.register(wrong).await.unwrap_err().contains("checksum"));
+
+        let generic = registry.register(rootfs(&generic, b"generic", &[])).await.unwrap();
+        let python = registry.register(rootfs(&python, b"python", &["Python"])).await.unwrap();
+
+        let selection = registry.select(Arch::X86_64, "python").await.unwrap();
+        assert_eq!(selection.kernel, None);
+        assert_eq!(selection.rootfs.unwrap().0, python.id);
+        let selection = registry.select(Arch::X86_64, "node").await.unwrap();
+        assert_eq!(selection.rootfs.clone().unwrap().0, generic.id);
+        assert_eq!(registry.select(Arch::Aarch64, "node").await.unwrap().rootfs, None);
+
+        // A tampered file is no longer booted
+        fs::write(&python.path, b"tampered").await.unwrap();
+        let failed = registry.verify(python.id).await.unwrap().unwrap();
+        assert_eq!(failed.status, ImageStatus::Failed);
+        assert_eq!(registry.select(Arch::X86_64, "python").await.unwrap().rootfs.unwrap().0, generic.id);
+
+        // Failed images are collected, images in use are not
+        registry.pin(Uuid::new_v4(), &selection).await;
+        let removed = registry.collect_garbage(0).await.unwrap();
+        assert_eq!(removed.iter().map(|image| image.id).collect::<Vec<_>>(), [python.id]);
+        assert!(registry.delete(generic.id).await.is_err());
+
+        let reopened = ImageRegistry::open(&root, Vec::new()).await.unwrap();
+        assert_eq!(reopened.list().await.len(), 1);
+        fs::remove_dir_all(root).await.unwrap();
+    }
+
+    /// Finds a critical CVE in images whose file names say so
+    #[derive(Debug)]
+    struct FakeScanner;
+
+    #[async_trait::async_trait]
+    impl VulnerabilityScanner for FakeScanner {
+        fn name(&self) -> &str {
+            "fake"
+        }
+
+        async fn scan(&self, image: &FsPath) -> anyhow::Result<Vec<image_scan::Vulnerability>> {
+            if !image.to_string_lossy().contains("vulnerable") {
+                return Ok(Vec::new());
+            }
+            Ok(vec![image_scan::Vulnerability {
+                id: "CVE-2024-3094".to_string(),
+                package: "liblzma5".to_string(),
+                installed_version: "5.6.0-0.2".to_string(),
+                fixed_version: Some("5.6.1+really5.4.5-1".to_string()),
+                severity: Severity::Critical,
+                title: None,
+            }])
+        }
+    }
+
+    #[tokio::test]
+    async fn scan_policy_blocks_vulnerable_and_unscanned_images() {
+        let root = std::env::temp_dir().join(format!("sandstorm-images-{}", Uuid::new_v4()));
+        fs::create_dir_all(&root).await.unwrap();
+        let registry = ImageRegistry::open(&root, Vec::new())
+            .await
+            .unwrap()
+            .with_scanner(ScanSettings {
+                scanner: Some(Arc::new(FakeScanner)),
+                block: Some(Severity::Critical),
+            });
+
+        let clean = root.join("clean.ext4");
+        fs::write(&clean, b"clean").await.unwrap();
+        let vulnerable = root.join("vulnerable.ext4");
+        fs::write(&vulnerable, b"vulnerable").await.unwrap();
+
+        let clean = registry.register(rootfs(&clean, b"clean", &[])).await.unwrap();
+        assert_eq!(clean.scan.unwrap().status, image_scan::ScanStatus::Pending);
+        // Nothing boots before its scan finishes
+        let blocked = registry.select(Arch::X86_64, "python").await.unwrap_err();
+        assert!(blocked.contains("vulnerability scan"));
+        assert_eq!(registry.unscanned().await, [clean.id]);
+
+        registry.scan(clean.id).await.unwrap();
+        let vulnerable = registry.register(rootfs(&vulnerable, b"vulnerable", &[])).await.unwrap();
+        let scanned = registry.scan(vulnerable.id).await.unwrap().unwrap().scan.unwrap();
+        assert_eq!(scanned.counts[&Severity::Critical], 1);
+
+        // The newer image has a critical CVE, so the older clean one boots
+        let selection = registry.select(Arch::X86_64, "python").await.unwrap();
+        assert_eq!(selection.rootfs.unwrap().0, clean.id);
+        assert!(registry.unscanned().await.is_empty());
+        fs::remove_dir_all(root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn requires_trusted_signatures_when_keys_are_configured() {
+        let root = std::env::temp_dir().join(format!("sandstorm-images-{}", Uuid::new_v4()));
+        fs::create_dir_all(&root).await.unwrap();
+        let path = root.join("rootfs.ext4");
+        fs::write(&path, b"rootfs").await.unwrap();
+
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key_pair = ring::signature::Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let registry = ImageRegistry::open(&root, vec![key_pair.public_key().as_ref().to_vec()])
+            .await
+            .unwrap();
+
+        let unsigned = rootfs(&path, b"rootfs", &[]);
+        assert!(registry.register(unsigned.clone()).await.is_err());
+
+        let digest = Sha256::digest(b"rootfs");
+        let signed = RegisterImageRequest {
+            signature: Some(base64::engine::general_purpose::STANDARD.encode(key_pair.sign(&digest))),
+            ..unsigned
+        };
+        assert_eq!(registry.register(signed).await.unwrap().status, ImageStatus::Verified);
+        fs::remove_dir_all(root).await.unwrap();
+    }
+}
@@ -0,0 +1,729 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path as FsPath, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::{fs, sync::RwLock};
+use tracing::{error, info, warn};
+use sandstorm_types::recording::SessionKind;
+use uuid::Uuid;
+
+use crate::exit_snapshot::{self, ExitSnapshot};
+use crate::observability;
+use crate::otlp::TraceContext;
+use crate::ownership::tenant_from_headers;
+use crate::recording::Recorder;
+use crate::runtime::{sysctl, SandboxRuntime, SandboxState};
+use crate::{start_sandbox, AppState, RunSandboxRequest, Started};
+
+/// Runs kept per job
+const HISTORY_LIMIT: usize = 100;
+/// How often the scheduler looks for due jobs
+const TICK: Duration = Duration::from_secs(1);
+/// How often a running job's sandbox is checked for completion
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Limit for runs whose template has no timeout
+const DEFAULT_RUN_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// What to do when a job fires while its previous run is still going
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlapPolicy {
+    /// Record the new run as skipped
+    #[default]
+    Skip,
+    /// Start the new run alongside the old one
+    Allow,
+    /// Cancel the running one, then start the new run
+    Replace,
+}
+
+/// A recurring sandbox run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    id: Uuid,
+    name: String,
+    /// Cron expression, evaluated in UTC
+    schedule: String,
+    template: RunSandboxRequest,
+    overlap_policy: OverlapPolicy,
+    enabled: bool,
+    /// Receives every finished run as JSON
+    callback_url: Option<String>,
+    /// Tenant that created the job, whose quota its runs count against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+    created_at: DateTime<Utc>,
+    next_run_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobTrigger {
+    Schedule,
+    Manual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobRunStatus {
+    Running,
+    Succeeded,
+    Failed,
+    TimedOut,
+    Skipped,
+    Cancelled,
+}
+
+/// One execution of a job. Its ID is also the gateway run ID, so provenance
+/// lookups work for job sandboxes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRun {
+    id: Uuid,
+    job_id: Uuid,
+    trigger: JobTrigger,
+    status: JobRunStatus,
+    sandbox_id: Option<Uuid>,
+    scheduled_for: DateTime<Utc>,
+    started_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    exit_code: Option<i32>,
+    error: Option<String>,
+    /// Filesystem snapshot taken when the command exited, for templates
+    /// with `auto_snapshot_on_exit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exit_snapshot: Option<ExitSnapshot>,
+}
+
+impl JobRun {
+    fn new(job_id: Uuid, trigger: JobTrigger, scheduled_for: DateTime<Utc>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            job_id,
+            trigger,
+            status: JobRunStatus::Running,
+            sandbox_id: None,
+            scheduled_for,
+            started_at: Utc::now(),
+            finished_at: None,
+            exit_code: None,
+            error: None,
+            exit_snapshot: None,
+        }
+    }
+
+    fn finish(&mut self, status: JobRunStatus, exit_code: Option<i32>, error: Option<String>) {
+        self.status = status;
+        self.exit_code = exit_code;
+        self.error = error;
+        self.finished_at = Some(Utc::now());
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateJobRequest {
+    name: String,
+    schedule: String,
+    template: RunSandboxRequest,
+    #[serde(default)]
+    overlap_policy: OverlapPolicy,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+    callback_url: Option<String>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug)]
+struct ActiveRun {
+    run_id: Uuid,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Job definitions and run history, persisted as JSON under one directory
+/// per job (`<root>/<job id>/job.json` and `runs.json`)
+#[derive(Debug)]
+pub struct JobScheduler {
+    root: PathBuf,
+    http: reqwest::Client,
+    jobs: RwLock<HashMap<Uuid, Job>>,
+    history: RwLock<HashMap<Uuid, Vec<JobRun>>>,
+    active: RwLock<HashMap<Uuid, Vec<ActiveRun>>>,
+}
+
+impl JobScheduler {
+    /// Jobs are stored under `GATEWAY_JOBS_PATH` (default `./data/jobs`)
+    pub async fn from_env() -> anyhow::Result<Self> {
+        let root = std::env::var("GATEWAY_JOBS_PATH").unwrap_or_else(|_| "./data/jobs".to_string());
+        Self::open(root).await
+    }
+
+    pub async fn open<P: AsRef<FsPath>>(root: P) -> anyhow::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root).await?;
+
+        let now = Utc::now();
+        let mut jobs = HashMap::new();
+        let mut history = HashMap::new();
+        let mut dir = fs::read_dir(&root).await?;
+        while let Some(item) = dir.next_entry().await? {
+            let job_path = item.path().join("job.json");
+            if fs::metadata(&job_path).await.is_err() {
+                continue;
+            }
+
+            let mut job: Job = serde_json::from_slice(&fs::read(&job_path).await?)
+                .map_err(|e| anyhow::anyhow!("failed to load {}: {}", job_path.display(), e))?;
+            // Runs missed while the gateway was down are not caught up
+            job.next_run_at = if job.enabled { next_run(&job.schedule, now) } else { None };
+
+            let mut runs: Vec<JobRun> = match fs::read(item.path().join("runs.json")).await {
+                Ok(contents) => serde_json::from_slice(&contents)?,
+                Err(_) => Vec::new(),
+            };
+            for run in runs.iter_mut().filter(|run| run.status == JobRunStatus::Running) {
+                run.finish(JobRunStatus::Failed, None, Some("gateway restarted during the run".into()));
+            }
+
+            history.insert(job.id, runs);
+            jobs.insert(job.id, job);
+        }
+
+        info!("Loaded {} scheduled job(s)", jobs.len());
+        Ok(Self {
+            root,
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            jobs: RwLock::new(jobs),
+            history: RwLock::new(history),
+            active: RwLock::new(HashMap::new()),
+        })
+    }
+
+    async fn create(&self, request: CreateJobRequest, tenant: Option<String>) -> Result<Job, String> {
+        let now = Utc::now();
+        let next = next_run(&request.schedule, now)
+            .ok_or_else(|| format!("invalid or never-firing schedule '{}'", request.schedule))?;
+        if let Some(url) = &request.callback_url {
+            reqwest::Url::parse(url).map_err(|e| format!("invalid callback_url: {}", e))?;
+        }
+        // Job runs report through the job's own callback
+        if request.template.callback_url.is_some() {
+            return Err("the template can't set callback_url; set it on the job".to_string());
+        }
+        sysctl::validate(&request.template.sysctls).map_err(|e| e.to_string())?;
+
+        let job = Job {
+            id: Uuid::new_v4(),
+            name: request.name,
+            schedule: request.schedule,
+            template: request.template,
+            overlap_policy: request.overlap_policy,
+            enabled: request.enabled,
+            callback_url: request.callback_url,
+            tenant,
+            created_at: now,
+            next_run_at: request.enabled.then_some(next),
+        };
+
+        self.save(&job).await.map_err(|e| e.to_string())?;
+        self.jobs.write().await.insert(job.id, job.clone());
+        self.history.write().await.insert(job.id, Vec::new());
+        Ok(job)
+    }
+
+    async fn list(&self) -> Vec<Job> {
+        let mut jobs: Vec<_> = self.jobs.read().await.values().cloned().collect();
+        jobs.sort_by_key(|job| job.created_at);
+        jobs
+    }
+
+    async fn get(&self, id: Uuid) -> Option<Job> {
+        self.jobs.read().await.get(&id).cloned()
+    }
+
+    async fn delete(&self, id: Uuid) -> anyhow::Result<bool> {
+        if self.jobs.write().await.remove(&id).is_none() {
+            return Ok(false);
+        }
+        self.history.write().await.remove(&id);
+        self.cancel_active(id).await;
+
+        let dir = self.root.join(id.to_string());
+        if fs::metadata(&dir).await.is_ok() {
+            fs::remove_dir_all(dir).await?;
+        }
+        Ok(true)
+    }
+
+    async fn set_enabled(&self, id: Uuid, enabled: bool) -> anyhow::Result<Option<Job>> {
+        let job = {
+            let mut jobs = self.jobs.write().await;
+            let Some(job) = jobs.get_mut(&id) else {
+                return Ok(None);
+            };
+            job.enabled = enabled;
+            job.next_run_at = if enabled { next_run(&job.schedule, Utc::now()) } else { None };
+            job.clone()
+        };
+        self.save(&job).await?;
+        Ok(Some(job))
+    }
+
+    async fn runs(&self, job_id: Uuid) -> Option<Vec<JobRun>> {
+        let mut runs = self.history.read().await.get(&job_id).cloned()?;
+        runs.reverse();
+        Some(runs)
+    }
+
+    /// Jobs whose next run is due, with the time they were due; their next
+    /// run is advanced past `now`
+    async fn due(&self, now: DateTime<Utc>) -> Vec<(Job, DateTime<Utc>)> {
+        let mut due = Vec::new();
+        {
+            let mut jobs = self.jobs.write().await;
+            for job in jobs.values_mut() {
+                match job.next_run_at {
+                    Some(next) if job.enabled && next <= now => {
+                        job.next_run_at = next_run(&job.schedule, now);
+                        due.push((job.clone(), next));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        for (job, _) in &due {
+            if let Err(e) = self.save(job).await {
+                error!("Failed to persist job {}: {}", job.id, e);
+            }
+        }
+        due
+    }
+
+    /// Insert or update a run in its job's history
+    async fn record(&self, run: &JobRun) {
+        let runs = {
+            let mut history = self.history.write().await;
+            let Some(runs) = history.get_mut(&run.job_id) else {
+                // Job was deleted while the run was in progress
+                return;
+            };
+            match runs.iter_mut().find(|existing| existing.id == run.id) {
+                Some(existing) => *existing = run.clone(),
+                None => runs.push(run.clone()),
+            }
+            if runs.len() > HISTORY_LIMIT {
+                let excess = runs.len() - HISTORY_LIMIT;
+                runs.drain(..excess);
+            }
+            runs.clone()
+        };
+
+        let path = self.root.join(run.job_id.to_string()).join("runs.json");
+        if let Err(e) = write_json(&path, &runs).await {
+            error!("Failed to persist runs for job {}: {}", run.job_id, e);
+        }
+    }
+
+    async fn cancel_active(&self, job_id: Uuid) {
+        if let Some(runs) = self.active.write().await.remove(&job_id) {
+            for run in runs {
+                info!(job_id = %job_id, run_id = %run.run_id, "Cancelling job run");
+                run.cancelled.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    async fn save(&self, job: &Job) -> anyhow::Result<()> {
+        let dir = self.root.join(job.id.to_string());
+        fs::create_dir_all(&dir).await?;
+        write_json(&dir.join("job.json"), job).await
+    }
+
+    /// POST a finished run to the job's callback URL
+    fn notify(&self, job: &Job, run: &JobRun) {
+        let Some(url) = job.callback_url.clone() else {
+            return;
+        };
+        let http = self.http.clone();
+        let body = serde_json::json!({ "job_id": job.id, "job_name": job.name, "run": run });
+
+        tokio::spawn(async move {
+            let result = http
+                .post(&url)
+                .json(&body)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                warn!("Job callback to {} failed: {}", url, e);
+            }
+        });
+    }
+}
+
+/// Next time a cron expression fires after `after`. Standard five-field
+/// expressions get a leading seconds field; `@hourly` style shorthands work too.
+fn next_run(schedule: &str, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let expression = if schedule.split_whitespace().count() == 5 {
+        format!("0 {}", schedule)
+    } else {
+        schedule.to_string()
+    };
+    cron::Schedule::from_str(&expression).ok()?.after(&after).next()
+}
+
+async fn write_json<T: Serialize>(path: &FsPath, value: &T) -> anyhow::Result<()> {
+    // Write then rename so a crash never leaves a truncated file behind
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(value)?).await?;
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+/// Start the loop that fires due jobs
+pub fn spawn(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TICK);
+        loop {
+            interval.tick().await;
+            for (job, scheduled_for) in state.jobs.due(Utc::now()).await {
+                tokio::spawn(execute(state.clone(), job, JobTrigger::Schedule, scheduled_for));
+            }
+        }
+    });
+}
+
+/// Run a job once, honouring its overlap policy
+async fn execute(state: AppState, job: Job, trigger: JobTrigger, scheduled_for: DateTime<Utc>) {
+    let scheduler = &state.jobs;
+    let mut run = JobRun::new(job.id, trigger, scheduled_for);
+    let cancelled = Arc::new(AtomicBool::new(false));
+
+    {
+        let mut active = scheduler.active.write().await;
+        let running = active.entry(job.id).or_default();
+        if !running.is_empty() {
+            match job.overlap_policy {
+                OverlapPolicy::Skip => {
+                    drop(active);
+                    run.finish(
+                        JobRunStatus::Skipped,
+                        None,
+                        Some("previous run still in progress".into()),
+                    );
+                    scheduler.record(&run).await;
+                    scheduler.notify(&job, &run);
+                    return;
+                }
+                OverlapPolicy::Replace => {
+                    for previous in running.drain(..) {
+                        previous.cancelled.store(true, Ordering::SeqCst);
+                    }
+                }
+                OverlapPolicy::Allow => {}
+            }
+        }
+        running.push(ActiveRun {
+            run_id: run.id,
+            cancelled: cancelled.clone(),
+        });
+    }
+
+    info!(job_id = %job.id, run_id = %run.id, "Starting job run");
+    scheduler.record(&run).await;
+
+    let observability = job.template.observability;
+    let span_start = Utc::now();
+    let started = start_sandbox(&state, job.template.clone(), run.id, job.tenant.clone()).await;
+    let trace = state.observer.run_span(
+        &observability,
+        TraceContext::new(run.id),
+        span_start,
+        run.id,
+        started.as_ref().map(|started| started.sandbox_id).map_err(ToString::to_string),
+    );
+    match started {
+        Ok(Started { sandbox_id, runtime, command, .. }) => {
+            run.sandbox_id = Some(sandbox_id);
+            scheduler.record(&run).await;
+            let recorder = Recorder::start(
+                sandbox_id,
+                Some(run.id),
+                format!("job:{}", job.id),
+                SessionKind::Run,
+                command,
+            );
+            observability::watch(&state, sandbox_id, observability, trace, runtime.clone(), Some(recorder)).await;
+
+            let timeout = job
+                .template
+                .timeout
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_RUN_TIMEOUT);
+            let (status, exit_code, error) =
+                wait_for_exit(&state, runtime.as_ref(), sandbox_id, timeout, &cancelled).await;
+
+            // The sandbox may have been preempted and resumed under a new ID
+            if let Some(current) = state.preemption.locate(sandbox_id).await {
+                let exited = status == JobRunStatus::Succeeded || exit_code.is_some();
+                if job.template.auto_snapshot_on_exit && exited {
+                    run.exit_snapshot =
+                        Some(exit_snapshot::take(&state, runtime.as_ref(), current, run.id, None).await);
+                }
+                if let Err(e) = runtime.destroy(current).await {
+                    warn!("Failed to destroy job sandbox {}: {}", current, e);
+                }
+                state.runtime_registry.release(current).await;
+                state.tenant_quotas.release(current);
+                state.result_cache.forget(current).await;
+                state.preemption.release(current).await;
+                state.security.sandbox_destroyed(current, observability.security_monitoring);
+            }
+            state.tenant_quotas.release(sandbox_id);
+            state.preemption.release(sandbox_id).await;
+            state.observer.forget(sandbox_id).await;
+            run.finish(status, exit_code, error);
+        }
+        Err(e) => run.finish(JobRunStatus::Failed, None, Some(e.to_string())),
+    }
+
+    if let Some(running) = scheduler.active.write().await.get_mut(&job.id) {
+        running.retain(|active| active.run_id != run.id);
+    }
+    info!(job_id = %job.id, run_id = %run.id, status = ?run.status, "Job run finished");
+    scheduler.record(&run).await;
+    scheduler.notify(&job, &run);
+}
+
+async fn wait_for_exit(
+    state: &AppState,
+    runtime: &dyn SandboxRuntime,
+    sandbox_id: Uuid,
+    timeout: Duration,
+    cancelled: &AtomicBool,
+) -> (JobRunStatus, Option<i32>, Option<String>) {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if cancelled.load(Ordering::SeqCst) {
+            return (JobRunStatus::Cancelled, None, Some("cancelled".into()));
+        }
+        if Instant::now() >= deadline {
+            return (JobRunStatus::TimedOut, None, None);
+        }
+
+        // Preempted runs wait, counting against their timeout, until resumed
+        let Some(current) = state.preemption.locate(sandbox_id).await else {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            continue;
+        };
+
+        match runtime.status(current).await {
+            Ok(status) => match (status.exit_code, status.state) {
+                (Some(0), _) => return (JobRunStatus::Succeeded, Some(0), None),
+                (Some(code), _) => return (JobRunStatus::Failed, Some(code), None),
+                // Local runtimes don't report exit codes; a cleanly stopped
+                // container counts as success
+                (None, SandboxState::Stopped) => return (JobRunStatus::Succeeded, None, None),
+                (None, SandboxState::Failed) => {
+                    return (JobRunStatus::Failed, None, Some("sandbox failed".into()))
+                }
+                _ => {}
+            },
+            Err(e) => return (JobRunStatus::Failed, None, Some(e.to_string())),
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+pub async fn create_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<CreateJobRequest>,
+) -> Result<(StatusCode, Json<Job>), (StatusCode, String)> {
+    // Nobody is around to approve a scheduled run
+    let reasons = state.approvals.policy.reasons(&request.template);
+    if !reasons.is_empty() {
+        let details: Vec<_> = reasons.iter().map(|reason| reason.detail.as_str()).collect();
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("the template needs approval to run: {}", details.join("; ")),
+        ));
+    }
+    let job = state
+        .jobs
+        .create(request, tenant_from_headers(&headers))
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    info!(job_id = %job.id, schedule = %job.schedule, "Job created");
+    Ok((StatusCode::CREATED, Json(job)))
+}
+
+pub async fn list_jobs(State(state): State<AppState>) -> Json<Vec<Job>> {
+    Json(state.jobs.list().await)
+}
+
+pub async fn get_job(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Job>, StatusCode> {
+    state.jobs.get(id).await.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn delete_job(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, StatusCode> {
+    match state.jobs.delete(id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Failed to delete job {}: {}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn pause_job(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Job>, StatusCode> {
+    set_enabled(&state, id, false).await
+}
+
+pub async fn resume_job(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Job>, StatusCode> {
+    set_enabled(&state, id, true).await
+}
+
+async fn set_enabled(state: &AppState, id: Uuid, enabled: bool) -> Result<Json<Job>, StatusCode> {
+    match state.jobs.set_enabled(id, enabled).await {
+        Ok(Some(job)) => Ok(Json(job)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Failed to update job {}: {}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Run a job now, outside its schedule
+pub async fn trigger_job(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, StatusCode> {
+    let job = state.jobs.get(id).await.ok_or(StatusCode::NOT_FOUND)?;
+    tokio::spawn(execute(state.clone(), job, JobTrigger::Manual, Utc::now()));
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn list_job_runs(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<JobRun>>, StatusCode> {
+    state.jobs.runs(id).await.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn template() -> RunSandboxRequest {
+        serde_json::from_value(serde_json::json!({
+            "code": "print('tick')",
+            "language": "python",
+            "isolation_level": "standard",
+        }))
+        .unwrap()
+    }
+
+    fn request(schedule: &str) -> CreateJobRequest {
+        CreateJobRequest {
+            name: "nightly".into(),
+            schedule: schedule.into(),
+            template: template(),
+            overlap_policy: OverlapPolicy::Skip,
+            enabled: true,
+            callback_url: None,
+        }
+    }
+
+    #[test]
+    fn parses_five_field_and_shorthand_schedules() {
+        let after = Utc.with_ymd_and_hms(2025, 1, 1, 10, 30, 0).unwrap();
+
+        assert_eq!(
+            next_run("*/15 * * * *", after),
+            Some(Utc.with_ymd_and_hms(2025, 1, 1, 10, 45, 0).unwrap())
+        );
+        assert_eq!(
+            next_run("@daily", after),
+            Some(Utc.with_ymd_and_hms(2025, 1, 2, 0, 0, 0).unwrap())
+        );
+        assert_eq!(next_run("not a schedule", after), None);
+    }
+
+    #[tokio::test]
+    async fn jobs_and_history_survive_restart() {
+        let root = std::env::temp_dir().join(format!("sandstorm-jobs-{}", Uuid::new_v4()));
+        let scheduler = JobScheduler::open(&root).await.unwrap();
+
+        assert!(scheduler.create(request("every day"), None).await.is_err());
+        let job = scheduler.create(request("0 3 * * *"), Some("acme".into())).await.unwrap();
+
+        let mut run = JobRun::new(job.id, JobTrigger::Manual, Utc::now());
+        scheduler.record(&run).await;
+        run.finish(JobRunStatus::Succeeded, Some(0), None);
+        scheduler.record(&run).await;
+
+        let reopened = JobScheduler::open(&root).await.unwrap();
+        let runs = reopened.runs(job.id).await.unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].status, JobRunStatus::Succeeded);
+        let job = reopened.get(job.id).await.unwrap();
+        assert!(job.next_run_at.is_some());
+        assert_eq!(job.tenant.as_deref(), Some("acme"));
+
+        assert!(reopened.delete(job.id).await.unwrap());
+        assert!(JobScheduler::open(&root).await.unwrap().list().await.is_empty());
+        fs::remove_dir_all(root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn due_advances_next_run() {
+        let root = std::env::temp_dir().join(format!("sandstorm-jobs-{}", Uuid::new_v4()));
+        let scheduler = JobScheduler::open(&root).await.unwrap();
+        let job = scheduler.create(request("* * * * *"), None).await.unwrap();
+        let first = job.next_run_at.unwrap();
+
+        assert!(scheduler.due(first - chrono::Duration::seconds(1)).await.is_empty());
+        let due = scheduler.due(first).await;
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].1, first);
+        assert_eq!(
+            scheduler.get(job.id).await.unwrap().next_run_at,
+            Some(first + chrono::Duration::minutes(1))
+        );
+
+        scheduler.set_enabled(job.id, false).await.unwrap();
+        assert!(scheduler.due(first + chrono::Duration::hours(1)).await.is_empty());
+        fs::remove_dir_all(root).await.unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+//! Root filesystems restored from layered vault snapshots. A run started
+//! from a `template_snapshot` gets the snapshot's stack of layers (an OS, a
+//! language runtime, a project), which the runtime stacks with overlayfs.
+//! Each layer is downloaded and unpacked once into `GATEWAY_LAYER_DIR`
+//! (default `/var/lib/sandstorm/layers`) and shared read-only by every
+//! sandbox built on it.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::runtime::command;
+use crate::vault::VaultClient;
+
+#[derive(Debug)]
+pub struct LayerCache {
+    dir: PathBuf,
+}
+
+impl LayerCache {
+    pub fn from_env() -> Self {
+        let dir = std::env::var("GATEWAY_LAYER_DIR")
+            .unwrap_or_else(|_| "/var/lib/sandstorm/layers".to_string());
+        Self::new(dir)
+    }
+
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Where a layer is unpacked
+    fn path(&self, layer: Uuid) -> PathBuf {
+        self.dir.join(layer.to_string())
+    }
+
+    /// Unpacked directories of a snapshot's layers, lowest first, fetching
+    /// any not yet on this host
+    pub async fn prepare(
+        &self,
+        vault: &VaultClient,
+        snapshot_id: Uuid,
+        tenant: Option<&str>,
+    ) -> Result<Vec<String>> {
+        let stack = vault
+            .snapshot_layers(snapshot_id, tenant)
+            .await
+            .with_context(|| format!("Failed to list the layers of snapshot {}", snapshot_id))?;
+
+        let mut layers = Vec::with_capacity(stack.len());
+        for layer in stack {
+            let path = self.path(layer.id);
+            if !path.is_dir() {
+                let blob = vault.download_snapshot(layer.id, tenant).await?;
+                self.unpack(layer.id, &blob).await?;
+                info!(layer = %layer.id, bytes = blob.len(), "Unpacked root filesystem layer");
+            }
+            layers.push(path.to_string_lossy().into_owned());
+        }
+        Ok(layers)
+    }
+
+    /// Where a snapshot's layers are or would be unpacked, lowest first,
+    /// without fetching any
+    pub async fn locate(
+        &self,
+        vault: &VaultClient,
+        snapshot_id: Uuid,
+        tenant: Option<&str>,
+    ) -> Result<Vec<String>> {
+        let stack = vault
+            .snapshot_layers(snapshot_id, tenant)
+            .await
+            .with_context(|| format!("Failed to list the layers of snapshot {}", snapshot_id))?;
+        Ok(stack
+            .into_iter()
+            .map(|layer| self.path(layer.id).to_string_lossy().into_owned())
+            .collect())
+    }
+
+    /// Unpack a layer's tar archive beside its final path and move it into
+    /// place, so a half-unpacked layer is never used. Whoever finishes
+    /// first wins when two starts fetch the same layer.
+    async fn unpack(&self, layer: Uuid, blob: &[u8]) -> Result<()> {
+        let partial = self.dir.join(format!("{}.{}.partial", layer, Uuid::new_v4()));
+        tokio::fs::create_dir_all(&partial).await?;
+        let unpacked = untar(&partial, blob).await;
+        let moved = match unpacked {
+            Ok(()) => tokio::fs::rename(&partial, self.path(layer)).await.map_err(Into::into),
+            Err(e) => Err(e),
+        };
+        if moved.is_err() {
+            tokio::fs::remove_dir_all(&partial).await.ok();
+            if self.path(layer).is_dir() {
+                return Ok(());
+            }
+        }
+        moved.with_context(|| format!("Failed to unpack layer {}", layer))
+    }
+}
+
+/// Extract a tar archive into `dir`, keeping numeric owners
+async fn untar(dir: &Path, archive: &[u8]) -> Result<()> {
+    let file = dir.with_extension("tar");
+    tokio::fs::write(&file, archive).await?;
+    let mut cmd = Command::new("tar");
+    cmd.arg("-C").arg(dir).args(["--numeric-owner", "-xf"]).arg(&file);
+    let result = command::run(&mut cmd, "unpack root filesystem layer").await;
+    tokio::fs::remove_file(&file).await.ok();
+    result.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn unpacked_layers_replace_nothing_already_in_place() {
+        let dir = std::env::temp_dir().join(format!("sandstorm-layers-{}", Uuid::new_v4()));
+        let source = dir.join("source");
+        std::fs::create_dir_all(source.join("usr/bin")).unwrap();
+        std::fs::write(source.join("usr/bin/python3"), b"python").unwrap();
+        let archive = command::tar(&source).await.unwrap();
+
+        let cache = LayerCache::new(dir.join("layers"));
+        let layer = Uuid::new_v4();
+        cache.unpack(layer, &archive).await.unwrap();
+        assert_eq!(
+            std::fs::read(cache.path(layer).join("usr/bin/python3")).unwrap(),
+            b"python"
+        );
+
+        // A second unpack of the same layer keeps the first
+        std::fs::write(cache.path(layer).join("marker"), b"").unwrap();
+        cache.unpack(layer, &archive).await.unwrap();
+        assert!(cache.path(layer).join("marker").exists());
+        assert_eq!(std::fs::read_dir(dir.join("layers")).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+//! Leases on vault snapshots the gateway may resume, so the vault's garbage
+//! collection can't delete them first. Template snapshots named in
+//! `GATEWAY_TEMPLATE_SNAPSHOTS` are leased for as long as the gateway runs;
+//! restores lease their snapshot until the sandbox is up.
+
+use anyhow::{Context, Result};
+use sandstorm_types::snapshot::{LeaseRequest, SnapshotLease, TENANT_HEADER};
+use std::{sync::Arc, time::Duration};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Lease length for template snapshots, renewed well before it lapses
+const TEMPLATE_LEASE_TTL: Duration = Duration::from_secs(900);
+const TEMPLATE_RENEW_INTERVAL: Duration = Duration::from_secs(300);
+/// Lease length for a restore; the lease is released as soon as it's done
+pub const RESTORE_LEASE_TTL: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone)]
+pub struct SnapshotLeases {
+    http: reqwest::Client,
+    vault_url: Option<String>,
+    /// Names this gateway as the lease holder
+    holder: String,
+}
+
+impl SnapshotLeases {
+    /// Leases from the vault at `GATEWAY_SNAPSHOT_VAULT_URL`, held as
+    /// `GATEWAY_INSTANCE_ID` (default `gateway-<hostname>`)
+    pub fn from_env() -> Self {
+        let holder = std::env::var("GATEWAY_INSTANCE_ID").unwrap_or_else(|_| {
+            let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "local".to_string());
+            format!("gateway-{}", host)
+        });
+        Self::new(std::env::var("GATEWAY_SNAPSHOT_VAULT_URL").ok(), holder)
+    }
+
+    pub fn new(vault_url: Option<String>, holder: String) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            vault_url: vault_url.map(|url| url.trim_end_matches('/').to_string()),
+            holder,
+        }
+    }
+
+    fn vault_url(&self) -> Result<&str> {
+        self.vault_url
+            .as_deref()
+            .context("GATEWAY_SNAPSHOT_VAULT_URL is not set")
+    }
+
+    fn with_tenant(request: reqwest::RequestBuilder, tenant: Option<&str>) -> reqwest::RequestBuilder {
+        match tenant {
+            Some(tenant) => request.header(TENANT_HEADER, tenant),
+            None => request,
+        }
+    }
+
+    fn request(&self, purpose: &str, ttl: Duration) -> LeaseRequest {
+        LeaseRequest {
+            holder: self.holder.clone(),
+            purpose: purpose.to_string(),
+            ttl_secs: Some(ttl.as_secs()),
+        }
+    }
+
+    /// Lease a snapshot for `ttl`
+    pub async fn acquire(
+        &self,
+        snapshot_id: Uuid,
+        tenant: Option<&str>,
+        purpose: &str,
+        ttl: Duration,
+    ) -> Result<SnapshotLease> {
+        let url = format!("{}/v1/snapshots/{}/leases", self.vault_url()?, snapshot_id);
+        let lease = Self::with_tenant(self.http.post(url), tenant)
+            .json(&self.request(purpose, ttl))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(lease)
+    }
+
+    /// Extend a lease to `ttl` from now; fails once it has lapsed
+    pub async fn renew(&self, lease: &SnapshotLease, ttl: Duration) -> Result<SnapshotLease> {
+        let url = format!("{}/v1/leases/{}", self.vault_url()?, lease.id);
+        let lease = Self::with_tenant(self.http.put(url), Some(&lease.tenant))
+            .json(&self.request(&lease.purpose, ttl))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(lease)
+    }
+
+    /// Give up a lease. Failures are only logged: the lease lapses anyway.
+    pub async fn release(&self, lease: &SnapshotLease) {
+        let released = async {
+            let url = format!("{}/v1/leases/{}", self.vault_url()?, lease.id);
+            Self::with_tenant(self.http.delete(url), Some(&lease.tenant))
+                .send()
+                .await?
+                .error_for_status()?;
+            anyhow::Ok(())
+        }
+        .await;
+        if let Err(e) = released {
+            warn!(lease = %lease.id, snapshot = %lease.snapshot_id, "Failed to release snapshot lease: {:#}", e);
+        }
+    }
+}
+
+/// A template snapshot, `<id>` or `<tenant>/<id>`
+fn parse_template(entry: &str) -> Result<(Option<String>, Uuid)> {
+    let (tenant, id) = match entry.split_once('/') {
+        Some((tenant, id)) => (Some(tenant.to_string()), id),
+        None => (None, entry),
+    };
+    let id = id
+        .parse()
+        .with_context(|| format!("invalid template snapshot {:?}", entry))?;
+    Ok((tenant, id))
+}
+
+/// Template snapshots from the comma-separated `GATEWAY_TEMPLATE_SNAPSHOTS`
+pub fn templates_from_env() -> Result<Vec<(Option<String>, Uuid)>> {
+    std::env::var("GATEWAY_TEMPLATE_SNAPSHOTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(parse_template)
+        .collect()
+}
+
+/// Hold leases on template snapshots, renewing them, and taking them again
+/// if one lapses (e.g. while the vault was unreachable)
+pub fn spawn_templates(leases: Arc<SnapshotLeases>, templates: Vec<(Option<String>, Uuid)>) {
+    if templates.is_empty() {
+        return;
+    }
+    info!(count = templates.len(), "Leasing template snapshots");
+
+    tokio::spawn(async move {
+        let mut held: Vec<Option<SnapshotLease>> = vec![None; templates.len()];
+        let mut ticker = tokio::time::interval(TEMPLATE_RENEW_INTERVAL);
+        loop {
+            ticker.tick().await;
+            for ((tenant, snapshot_id), lease) in templates.iter().zip(held.iter_mut()) {
+                let renewed = match lease.as_ref() {
+                    Some(current) => leases.renew(current, TEMPLATE_LEASE_TTL).await.ok(),
+                    None => None,
+                };
+                *lease = match renewed {
+                    Some(renewed) => Some(renewed),
+                    None => match leases
+                        .acquire(*snapshot_id, tenant.as_deref(), "template", TEMPLATE_LEASE_TTL)
+                        .await
+                    {
+                        Ok(acquired) => Some(acquired),
+                        Err(e) => {
+                            warn!(snapshot = %snapshot_id, "Failed to lease template snapshot: {:#}", e);
+                            None
+                        }
+                    },
+                };
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_template_snapshots_with_and_without_tenant() {
+        let id = Uuid::new_v4();
+        assert_eq!(parse_template(&id.to_string()).unwrap(), (None, id));
+        assert_eq!(
+            parse_template(&format!("acme/{}", id)).unwrap(),
+            (Some("acme".to_string()), id)
+        );
+        assert!(parse_template("acme/latest").is_err());
+    }
+}
@@ -0,0 +1,57 @@
+//! Sandbox console output over HTTP. The response body is streamed as the
+//! runtime reads it, so with `follow` a client can tail a running sandbox's
+//! console until the sandbox is destroyed or the client hangs up.
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use tokio_util::io::ReaderStream;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::AppState;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LogsQuery {
+    /// Keep the response open and stream output as it's written
+    #[serde(default)]
+    follow: bool,
+}
+
+/// Console output of a sandbox from gVisor, Kata or Firecracker, as plain
+/// text in a chunked response
+pub async fn sandbox_logs(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<LogsQuery>,
+) -> Result<Response, StatusCode> {
+    // Preempted sandboxes have no console until they are resumed
+    let id = state.preemption.locate(id).await.ok_or(StatusCode::CONFLICT)?;
+
+    let runtime = state.runtime_registry.runtime_of(id).await.ok_or(StatusCode::NOT_FOUND)?;
+    // Hosted providers have no console to serve
+    if runtime.is_remote() {
+        return Err(StatusCode::NOT_IMPLEMENTED);
+    }
+    match runtime.logs(id, query.follow).await {
+        Ok(reader) => {
+            let body = Body::from_stream(ReaderStream::new(reader));
+            Ok((
+                [
+                    (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+                    (header::CACHE_CONTROL, "no-cache"),
+                ],
+                body,
+            )
+                .into_response())
+        }
+        Err(e) => {
+            error!(sandbox_id = %id, "No logs from runtime: {:#}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
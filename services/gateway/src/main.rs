@@ -5,107 +5,579 @@
 
 use axum::{
     extract::State,
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Json, Router,
 };
+use sandstorm_http::{BreakerSettings, CircuitBreaker, Unavailable};
+use sandstorm_types::metadata::{SandboxLimits, SandboxMetadata};
+use sandstorm_types::provenance::{RunProvenance, RUN_ID_ENV};
+use sandstorm_types::quota::QuotaUsage;
+use sandstorm_types::recording::{SessionKind, SessionRecording};
+use sandstorm_types::sandbox::SandboxOwner;
+use sandstorm_types::security::{QuarantineEnforcement, QuarantineMode};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
-use tower_http::cors::CorsLayer;
-use tracing::{info, error};
+use tracing::{info, error, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use uuid::Uuid;
 
+mod approvals;
+mod benchmark;
+mod cache;
+mod callbacks;
+mod dashboard;
+mod deletion;
+mod dry_run;
+mod edge;
+mod exec_stream;
+mod exit_snapshot;
+mod image_scan;
+mod images;
+mod jobs;
+mod layers;
+mod leases;
+mod logs;
+mod metadata;
+mod metrics;
+mod observability;
+mod openapi;
+mod otlp;
+mod ownership;
+mod preemption;
+mod provenance;
+mod quarantine;
+mod quota;
+mod rate_limit;
+mod reaper;
+mod recording;
 mod runtime;
+mod scaling;
+mod scan;
+mod security;
+mod tenant_quotas;
+mod vault;
+mod warm_pool;
+use cache::ResultCache;
+use exit_snapshot::ExitSnapshot;
+use approvals::Approval;
+use metrics::GatewayMetrics;
+use observability::Observability;
+use ownership::{tenant_from_headers, SandboxOwners};
+use preemption::{Preemption, Preemptor};
+use provenance::{run_id_from_headers, ProvenanceClient, RunLedger};
+use quarantine::QuarantineEnforcer;
+use recording::{user_from_headers, Recorder, RecordingClient, RECORDING_ID_HEADER};
+use scan::Finding;
+use security::SecurityReporter;
+use vault::VaultClient;
 use runtime::{
     firecracker::FirecrackerRuntime,
     gvisor::GvisorRuntime,
+    index::IndexedSandbox,
     kata::KataRuntime,
-    IsolationLevel, RuntimeRegistry, RuntimeType, SandboxConfig, Mount,
+    lifecycle::{InvalidTransition, LifecycleEvents, StateChange},
+    mock::MockRuntime,
+    posture::{self, HostPosture, Readiness},
+    remote::{daytona::DaytonaProvider, e2b::E2bProvider, modal::ModalProvider, RemoteRuntime},
+    capacity::{CapacityReport, HostCapacity},
+    stats::RuntimeStats,
+    Arch, ExecOptions, ExecutionMode, ExitReason, FirecrackerOptions, GvisorOptions, IsolationLevel, OptimizationHint, Priority, RuntimeRegistry, RuntimeType,
+    SandboxConfig, SandboxResult, SandboxRuntime, SandboxStatus, Mount,
 };
 
 #[derive(Debug, Clone)]
 struct AppState {
     runtime_registry: Arc<RuntimeRegistry>,
+    run_ledger: Arc<RunLedger>,
+    /// Tenant and runtime of each sandbox, for services checking reports
+    /// about it
+    owners: Arc<SandboxOwners>,
+    provenance: ProvenanceClient,
+    recordings: RecordingClient,
+    result_cache: Arc<ResultCache>,
+    jobs: Arc<jobs::JobScheduler>,
+    /// Kernels and root filesystems Firecracker VMs boot from
+    images: Arc<images::ImageRegistry>,
+    /// Host prerequisites of each local runtime, checked at startup
+    readiness: Arc<Vec<Readiness>>,
+    benchmarks: Arc<benchmark::Benchmarks>,
+    /// Places runs on edge agents by the collector's capacity feed
+    edge: Arc<edge::EdgeDispatcher>,
+    /// Filesystem snapshots of runs that asked for one when they exit
+    exit_snapshots: Arc<exit_snapshot::ExitSnapshots>,
+    preemption: Arc<Preemptor>,
+    quarantines: Arc<QuarantineEnforcer>,
+    /// Tenants' monthly quotas and usage, from the collector
+    quotas: Arc<quota::QuotaClient>,
+    /// Tenants' limits on sandboxes, vCPUs and memory in use at once
+    tenant_quotas: Arc<tenant_quotas::TenantQuotas>,
+    rate_limiter: Arc<rate_limit::RateLimiter>,
+    /// Demand signals and target sizes of warm pools
+    pool_scaling: Arc<scaling::PoolScaler>,
+    /// Sandboxes booted ahead of the runs they'll be handed to
+    warm_pools: Arc<warm_pool::WarmPools>,
+    /// What workloads can find out about their own sandbox
+    metadata: Arc<metadata::MetadataService>,
+    /// Stats samples and spans of runs that asked for them
+    observer: Arc<observability::Observer>,
+    /// Where runs that asked for one get their result when they finish
+    callbacks: Arc<callbacks::Callbacks>,
+    /// Sensitive runs held until an approver decides on them
+    approvals: Arc<approvals::Approvals>,
+    /// Tenant and sandbox data deletions and their certificates
+    deletions: Arc<deletion::Deletions>,
+    security: SecurityReporter,
+    /// Static checks on submitted code, when enabled
+    code_scanner: Option<Arc<scan::CodeScanner>>,
+    vault: Option<VaultClient>,
+    /// Vault layers unpacked for root filesystems restored from templates
+    layers: Arc<layers::LayerCache>,
+    snapshot_leases: Arc<leases::SnapshotLeases>,
+    metrics: GatewayMetrics,
+    /// `GATEWAY_DEBUG_ERRORS`: explain failed requests in the response body
+    debug_errors: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct HealthResponse {
     status: String,
     version: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 struct RunSandboxRequest {
     code: String,
     language: String,
     isolation_level: IsolationLevel,
     runtime_preference: Option<RuntimeType>,
+    /// Rank runtimes by telemetry instead of the static isolation mapping
+    optimize_for: Option<OptimizationHint>,
+    /// Higher priorities may preempt lower ones when capacity runs out
+    #[serde(default)]
+    priority: Priority,
+    /// `read_only` runs untrusted code with a read-only root, no network
+    /// and a reduced syscall profile
+    #[serde(default)]
+    mode: ExecutionMode,
+    /// Size of the tmpfs scratch space in read-only mode
+    scratch_size_mb: Option<u64>,
+    /// Allow-listed kernel parameters to set in the sandbox
+    #[serde(default)]
+    sysctls: std::collections::HashMap<String, String>,
+    /// runsc platform, network, overlay and debug flags; pins the sandbox
+    /// to gVisor
+    #[serde(default)]
+    gvisor: GvisorOptions,
+    /// CPU template, SMT and CPU brand masking; pins the sandbox to
+    /// Firecracker
+    #[serde(default)]
+    firecracker: FirecrackerOptions,
+    /// Run only on hosts or providers of this CPU architecture
+    arch: Option<Arch>,
     cpu_limit: Option<f64>,
     memory_limit: Option<u64>,
     timeout: Option<u64>,
+    /// Seconds after which the sandbox is destroyed, finished or not
+    ttl_seconds: Option<u64>,
     environment: Option<std::collections::HashMap<String, String>>,
     mounts: Option<Vec<MountRequest>>,
+    /// Upload the sandbox's filesystem to the vault when its command exits,
+    /// and name the snapshot in its status
+    #[serde(default)]
+    auto_snapshot_on_exit: bool,
+    /// Vault snapshot whose stack of layers becomes the root filesystem,
+    /// in place of the language image's
+    #[serde(default)]
+    template_snapshot: Option<Uuid>,
+    /// Free-form labels the workload can read from the metadata service
+    #[serde(default)]
+    labels: std::collections::HashMap<String, String>,
+    /// Output capture, resource sampling, security monitoring and spans
+    /// for this run; all off by default
+    #[serde(default)]
+    observability: Observability,
+    /// URL to POST the run's signed result to once its sandbox exits, times
+    /// out or is destroyed
+    #[serde(default)]
+    callback_url: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 struct MountRequest {
     source: String,
     destination: String,
     read_only: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct RunSandboxResponse {
     sandbox_id: Uuid,
+    run_id: Uuid,
     status: String,
+    /// What the admission scan found in the code, if anything
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    findings: Vec<Finding>,
 }
 
 #[tokio::main]
 async fn main() {
+    // Tracing isn't set up yet to report a bad config through
+    let log_shipping = match sandstorm_logging::ShippingSettings::from_env("GATEWAY") {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Invalid log shipping config: {:#}", e);
+            std::process::exit(1);
+        }
+    };
     tracing_subscriber::registry()
         .with(tracing_subscriber::EnvFilter::new(
             std::env::var("RUST_LOG").unwrap_or_else(|_| "sandstorm_gateway=debug,tower_http=debug".into()),
         ))
         .with(tracing_subscriber::fmt::layer())
+        .with(log_shipping.map(|settings| sandstorm_logging::ShippingLayer::spawn(settings, "gateway")))
         .init();
 
+    let faults = match runtime::fault::FaultConfig::from_env() {
+        Ok(faults) => faults,
+        Err(e) => {
+            error!("Failed to load fault injection config: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
     // Initialize runtime registry
-    let registry = Arc::new(RuntimeRegistry::new());
+    let registry = Arc::new(
+        RuntimeRegistry::new()
+            .with_local_limit(
+                std::env::var("GATEWAY_MAX_LOCAL_SANDBOXES")
+                    .ok()
+                    .and_then(|value| value.parse().ok()),
+            )
+            .with_burst_order(burst_order_from_env())
+            .with_stats(Arc::new(RuntimeStats::from_env()))
+            .with_host_capacity(HostCapacity::from_env())
+            .with_queue_timeout(std::time::Duration::from_secs(
+                std::env::var("GATEWAY_QUEUE_TIMEOUT_SECS")
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(0),
+            ))
+            .with_fault_injection(faults),
+    );
     
     // Initialize and register runtimes based on available binaries
-    if let Err(e) = initialize_runtimes(&registry).await {
-        error!("Failed to initialize runtimes: {}", e);
-        std::process::exit(1);
-    }
+    let metrics = GatewayMetrics::new();
+    let lifecycle_events = LifecycleEvents::new();
+    tokio::spawn(record_state_changes(lifecycle_events.subscribe(), metrics.clone()));
+    let images = match images::ImageRegistry::from_env().await {
+        Ok(images) => Arc::new(images),
+        Err(e) => {
+            error!("Failed to load boot images: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    images::spawn_scans(images.clone(), images.unscanned().await);
+    let readiness = match initialize_runtimes(&registry, &metrics, &lifecycle_events, &images).await {
+        Ok(readiness) => Arc::new(readiness),
+        Err(e) => {
+            error!("Failed to initialize runtimes: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let scheduler = match jobs::JobScheduler::from_env().await {
+        Ok(scheduler) => scheduler,
+        Err(e) => {
+            error!("Failed to load scheduled jobs: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let code_scanner = match scan::CodeScanner::from_env() {
+        Ok(scanner) => scanner.map(Arc::new),
+        Err(e) => {
+            error!("Failed to load code scan rules: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let http_security = match sandstorm_http::HttpSecurity::from_env("GATEWAY") {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("Invalid HTTP security settings: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let template_snapshots = match leases::templates_from_env() {
+        Ok(templates) => templates,
+        Err(e) => {
+            error!("Failed to load GATEWAY_TEMPLATE_SNAPSHOTS: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let pool_scaling = match scaling::PoolScaler::from_env() {
+        Ok(scaler) => Arc::new(scaler),
+        Err(e) => {
+            error!("Invalid warm pool scaling settings: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let warm_pools = match warm_pool::WarmPools::from_env() {
+        Ok(pools) => Arc::new(pools),
+        Err(e) => {
+            error!("Invalid warm pool settings: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let metadata = match metadata::MetadataService::from_env() {
+        Ok(service) => Arc::new(service),
+        Err(e) => {
+            error!("Invalid metadata service settings: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let tenant_quotas = match tenant_quotas::TenantQuotas::from_env() {
+        Ok(quotas) => Arc::new(quotas),
+        Err(e) => {
+            error!("Invalid tenant quota settings: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let rate_limiter = match rate_limit::RateLimiter::from_env() {
+        Ok(limiter) => Arc::new(limiter),
+        Err(e) => {
+            error!("Invalid rate limit settings: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let callbacks = match callbacks::Callbacks::from_env() {
+        Ok(callbacks) => Arc::new(callbacks),
+        Err(e) => {
+            error!("Invalid run callback settings: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let approvals = match approvals::Approvals::from_env().await {
+        Ok(approvals) => Arc::new(approvals),
+        Err(e) => {
+            error!("Invalid run approval settings: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let deletions = match deletion::Deletions::from_env().await {
+        Ok(deletions) => Arc::new(deletions),
+        Err(e) => {
+            error!("Invalid data deletion settings: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Calls to the vault, collector and monitor stop waiting on whichever
+    // of them is down
+    let breaker_settings = match BreakerSettings::from_env("GATEWAY") {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("Invalid circuit breaker settings: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let vault_breaker = CircuitBreaker::new("snapshot-vault", breaker_settings);
+    let collector_breaker = CircuitBreaker::new("telemetry-collector", breaker_settings);
+    let monitor_breaker = CircuitBreaker::new("security-monitor", breaker_settings);
+
+    let vault = match vault::from_env(vault_breaker.clone()).await {
+        Ok(vault) => vault,
+        Err(e) => {
+            error!("Invalid snapshot vault settings: {:#}", e);
+            std::process::exit(1);
+        }
+    };
 
     let state = AppState {
         runtime_registry: registry,
+        run_ledger: Arc::new(RunLedger::new()),
+        owners: Arc::new(SandboxOwners::new()),
+        provenance: ProvenanceClient::from_env(),
+        recordings: RecordingClient::from_env(vault.clone()),
+        result_cache: Arc::new(ResultCache::from_env()),
+        jobs: Arc::new(scheduler),
+        images,
+        readiness,
+        benchmarks: Arc::new(benchmark::Benchmarks::from_env()),
+        edge: Arc::new(edge::EdgeDispatcher::from_env()),
+        exit_snapshots: Arc::new(exit_snapshot::ExitSnapshots::new()),
+        preemption: Arc::new(Preemptor::from_env()),
+        quarantines: Arc::new(QuarantineEnforcer::new()),
+        quotas: Arc::new(quota::QuotaClient::from_env().with_breaker(collector_breaker.clone())),
+        tenant_quotas,
+        rate_limiter,
+        pool_scaling,
+        warm_pools,
+        metadata,
+        observer: Arc::new(observability::Observer::from_env()),
+        callbacks,
+        approvals,
+        deletions,
+        security: SecurityReporter::from_env().with_breaker(monitor_breaker.clone()),
+        code_scanner,
+        vault,
+        layers: Arc::new(layers::LayerCache::from_env()),
+        snapshot_leases: Arc::new(leases::SnapshotLeases::from_env()),
+        metrics,
+        debug_errors: std::env::var("GATEWAY_DEBUG_ERRORS")
+            .is_ok_and(|value| matches!(value.as_str(), "1" | "true")),
     };
+    let shared_metrics = state.metrics.shared.clone();
+    let breakers = [vault_breaker, collector_breaker, monitor_breaker];
+    if let Err(e) = sandstorm_http::register_breakers(shared_metrics.registry(), &breakers) {
+        warn!("Failed to register circuit breaker metrics: {}", e);
+    }
+    jobs::spawn(state.clone());
+    leases::spawn_templates(state.snapshot_leases.clone(), template_snapshots);
+    preemption::spawn(
+        state.preemption.clone(),
+        state.runtime_registry.clone(),
+        state.run_ledger.clone(),
+    );
+    scaling::spawn(state.pool_scaling.clone(), state.metrics.clone());
+    metadata::spawn(state.metadata.clone());
+    reaper::spawn(state.clone());
+    warm_pool::spawn(state.clone());
 
     let app = Router::new()
         .route("/health", get(health))
-        .route("/v1/sandboxes/run", post(run_sandbox))
+        .route("/v1/sandboxes", get(list_sandboxes))
+        .route(
+            "/v1/sandboxes/run",
+            post(run_sandbox).layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                rate_limit::limit_runs,
+            )),
+        )
+        .route("/v1/sandboxes/validate", post(dry_run::validate_sandbox))
         .route("/v1/sandboxes/:id/exec", post(exec_sandbox))
+        .route(
+            "/v1/sandboxes/:id/exec/stream",
+            get(exec_stream::exec_stream).post(exec_stream::exec_lines),
+        )
         .route("/v1/sandboxes/:id/status", get(sandbox_status))
+        .route("/v1/sandboxes/:id/logs", get(logs::sandbox_logs))
+        .route("/v1/sandboxes/:id/stats", get(observability::sandbox_stats))
+        .route("/v1/sandboxes/:id/owner", get(sandbox_owner))
         .route("/v1/sandboxes/:id", delete(destroy_sandbox))
         .route("/v1/sandboxes/:id/snapshot", post(snapshot_sandbox))
+        .route("/v1/sandboxes/:id/pause", post(pause_sandbox))
+        .route("/v1/sandboxes/:id/unpause", post(unpause_sandbox))
+        .route(
+            "/v1/sandboxes/:id/quarantine",
+            put(quarantine_sandbox).delete(release_sandbox),
+        )
+        .route("/v1/sandboxes/:id/provenance", get(sandbox_provenance))
+        .route("/v1/dashboard/sandboxes/:id", get(dashboard::sandbox_dashboard))
+        .route("/v1/sandboxes/:id/recordings", get(list_recordings))
+        .route("/v1/recordings/:id", get(download_recording))
         .route("/v1/sandboxes/resume", post(resume_sandbox))
+        .route("/v1/edge/run", post(edge::run_on_edge))
+        .route("/v1/approvals", get(approvals::list_approvals))
+        .route("/v1/approvals/:id", get(approvals::get_approval))
+        .route("/v1/approvals/:id/approve", post(approvals::approve_run))
+        .route("/v1/approvals/:id/reject", post(approvals::reject_run))
+        .route("/v1/deletions", post(deletion::create_deletion).get(deletion::list_deletions))
+        .route("/v1/deletions/:id", get(deletion::get_deletion))
+        .route("/v1/deletions/:id/retry", post(deletion::retry_deletion))
         .route("/v1/runtimes", get(list_runtimes))
-        .layer(CorsLayer::permissive())
-        .with_state(state);
+        .route("/v1/openapi.json", get(openapi::openapi_json))
+        .route("/v1/images", post(images::register_image).get(images::list_images))
+        .route("/v1/images/gc", post(images::collect_garbage))
+        .route("/v1/images/:id", get(images::get_image).delete(images::delete_image))
+        .route("/v1/images/:id/verify", post(images::verify_image))
+        .route("/v1/images/:id/scan", post(images::scan_image))
+        .route("/v1/capacity", get(capacity))
+        .route("/v1/pools", get(scaling::list_pools))
+        .route("/v1/pools/:template/status", put(scaling::report_pool))
+        .route("/v1/quota", get(quota::quota_usage))
+        .route("/v1/quotas", get(tenant_quotas::tenant_capacity))
+        .route("/v1/credentials/introspect", post(metadata::introspect_credential))
+        .route("/v1/jobs", post(jobs::create_job).get(jobs::list_jobs))
+        .route("/v1/jobs/:id", get(jobs::get_job).delete(jobs::delete_job))
+        .route("/v1/jobs/:id/pause", post(jobs::pause_job))
+        .route("/v1/jobs/:id/resume", post(jobs::resume_job))
+        .route("/v1/jobs/:id/trigger", post(jobs::trigger_job))
+        .route("/v1/jobs/:id/runs", get(jobs::list_job_runs))
+        .route(
+            "/v1/benchmarks",
+            post(benchmark::create_benchmark).get(benchmark::list_benchmarks),
+        )
+        .route("/v1/benchmarks/:id", get(benchmark::get_benchmark))
+        .route("/v1/benchmarks/:id/report", get(benchmark::benchmark_report))
+        .with_state(state)
+        .merge(sandstorm_metrics::metrics_router(shared_metrics.clone()))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_metrics,
+            sandstorm_metrics::track_http,
+        ));
+    let app = http_security.apply(app);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
     info!("Sandstorm Gateway listening on {}", addr);
     
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    match sandstorm_tls::TlsSettings::from_env("GATEWAY") {
+        Ok(Some(tls)) => {
+            info!("mTLS enabled");
+            sandstorm_tls::serve(listener, app, tls).await.unwrap();
+        }
+        Ok(None) => axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .unwrap(),
+        Err(e) => {
+            error!("Invalid TLS configuration: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Count sandbox state changes, for as long as the gateway runs
+async fn record_state_changes(
+    mut changes: tokio::sync::broadcast::Receiver<StateChange>,
+    metrics: GatewayMetrics,
+) {
+    loop {
+        match changes.recv().await {
+            Ok(change) => metrics.state_changed(&change),
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                warn!("Missed {} sandbox state changes", missed);
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        }
+    }
 }
 
-async fn initialize_runtimes(registry: &Arc<RuntimeRegistry>) -> anyhow::Result<()> {
+async fn initialize_runtimes(
+    registry: &Arc<RuntimeRegistry>,
+    metrics: &GatewayMetrics,
+    lifecycle_events: &LifecycleEvents,
+    images: &Arc<images::ImageRegistry>,
+) -> anyhow::Result<Vec<Readiness>> {
+    // Local runtimes are only registered when the host meets their
+    // prerequisites
+    let host = HostPosture::probe();
+    let mut readiness = Vec::new();
+
     // Try to initialize gVisor runtime
     let runsc_paths = vec![
         PathBuf::from("/usr/local/bin/runsc"),
@@ -113,9 +585,17 @@ async fn initialize_runtimes(registry: &Arc<RuntimeRegistry>) -> anyhow::Result<
         PathBuf::from("./bin/runsc"),
     ];
     
+    let mut gvisor = posture::missing(RuntimeType::Gvisor, "runsc");
     for path in runsc_paths {
         if path.exists() {
-            match GvisorRuntime::new(path.clone(), PathBuf::from("/var/lib/sandstorm/gvisor")) {
+            gvisor = host.gvisor(&path);
+            if !gvisor.ready {
+                continue;
+            }
+            match GvisorRuntime::new(path.clone(), PathBuf::from("/var/lib/sandstorm/gvisor"))
+                .and_then(|runtime| runtime.with_defaults(runtime::gvisor::options_from_env()?))
+                .map(|runtime| runtime.with_lifecycle_events(lifecycle_events.clone()))
+            {
                 Ok(runtime) => {
                     registry.register(Arc::new(runtime)).await?;
                     info!("Registered gVisor runtime");
@@ -127,6 +607,7 @@ async fn initialize_runtimes(registry: &Arc<RuntimeRegistry>) -> anyhow::Result<
             }
         }
     }
+    readiness.push(gvisor);
 
     // Try to initialize Kata runtime
     let kata_paths = vec![
@@ -135,10 +616,16 @@ async fn initialize_runtimes(registry: &Arc<RuntimeRegistry>) -> anyhow::Result<
         PathBuf::from("./bin/kata-runtime"),
     ];
     
+    let mut kata = posture::missing(RuntimeType::Kata, "kata-runtime");
     for path in kata_paths {
         if path.exists() {
+            kata = host.kata(&path);
+            if !kata.ready {
+                continue;
+            }
             match KataRuntime::new(path.clone(), PathBuf::from("/var/lib/sandstorm/kata")) {
                 Ok(runtime) => {
+                    let runtime = runtime.with_lifecycle_events(lifecycle_events.clone());
                     registry.register(Arc::new(runtime)).await?;
                     info!("Registered Kata runtime");
                     break;
@@ -149,6 +636,7 @@ async fn initialize_runtimes(registry: &Arc<RuntimeRegistry>) -> anyhow::Result<
             }
         }
     }
+    readiness.push(kata);
 
     // Try to initialize Firecracker runtime
     let firecracker_paths = vec![
@@ -163,19 +651,37 @@ async fn initialize_runtimes(registry: &Arc<RuntimeRegistry>) -> anyhow::Result<
         PathBuf::from("./bin/jailer"),
     ];
     
-    for fc_path in firecracker_paths {
+    let mut firecracker = posture::missing(RuntimeType::Firecracker, "firecracker");
+    'firecracker: for fc_path in firecracker_paths {
         if fc_path.exists() {
             for jailer_path in &jailer_paths {
                 if jailer_path.exists() {
+                    firecracker = host.firecracker(&fc_path, jailer_path);
+                    if !firecracker.ready {
+                        continue;
+                    }
                     match FirecrackerRuntime::new(
                         fc_path.clone(),
                         jailer_path.clone(),
                         PathBuf::from("/var/lib/sandstorm/firecracker")
-                    ) {
+                    )
+                    .and_then(|runtime| runtime.with_defaults(runtime::firecracker::options_from_env()?))
+                    {
                         Ok(runtime) => {
-                            registry.register(Arc::new(runtime)).await?;
+                            let runtime = Arc::new(
+                                runtime
+                                    .with_leak_counter(metrics.tap_leaks())
+                                    .with_lifecycle_events(lifecycle_events.clone())
+                                    .with_images(images.clone()),
+                            );
+                            let swept = runtime.sweep_orphaned_taps().await;
+                            if swept > 0 {
+                                info!("Removed {} TAP device(s) left by a previous run", swept);
+                            }
+                            runtime.clone().spawn_tap_sweeper();
+                            registry.register(runtime).await?;
                             info!("Registered Firecracker runtime");
-                            break;
+                            break 'firecracker;
                         }
                         Err(e) => {
                             error!("Failed to initialize Firecracker runtime: {}", e);
@@ -185,17 +691,73 @@ async fn initialize_runtimes(registry: &Arc<RuntimeRegistry>) -> anyhow::Result<
             }
         }
     }
+    readiness.push(firecracker);
+
+    for runtime in readiness.iter().filter(|runtime| runtime.installed()) {
+        if !runtime.ready {
+            warn!(runtime = ?runtime.runtime, "Runtime not registered: {}", runtime.failures());
+        } else if runtime.checks.iter().any(|check| !check.passed) {
+            warn!(runtime = ?runtime.runtime, "Runtime registered without: {}", runtime.failures());
+        }
+    }
+
+    if let Some(runtime) = MockRuntime::from_env() {
+        registry.register(Arc::new(runtime)).await?;
+        info!("Registered mock runtime; sandboxes will be simulated");
+    }
+
+    // Register hosted providers that have credentials configured
+    if let Some(provider) = E2bProvider::from_env() {
+        registry.register(Arc::new(RemoteRuntime::new(provider))).await?;
+        info!("Registered E2B runtime");
+    }
+    if let Some(provider) = DaytonaProvider::from_env() {
+        registry.register(Arc::new(RemoteRuntime::new(provider))).await?;
+        info!("Registered Daytona runtime");
+    }
+    if let Some(provider) = ModalProvider::from_env() {
+        registry.register(Arc::new(RemoteRuntime::new(provider))).await?;
+        info!("Registered Modal runtime");
+    }
 
     // Check if at least one runtime is registered
     let runtimes = registry.list().await;
     if runtimes.is_empty() {
-        anyhow::bail!("No runtimes could be initialized. Please install at least one runtime (gVisor, Kata, or Firecracker)");
+        anyhow::bail!("No runtimes could be initialized. Please install at least one runtime (gVisor, Kata, or Firecracker) or configure a hosted provider (E2B, Daytona, or Modal), or set SANDSTORM_MOCK_RUNTIME=1");
     }
 
     info!("Initialized {} runtime(s)", runtimes.len());
-    Ok(())
+    Ok(readiness)
+}
+
+/// Burst order from `GATEWAY_BURST_PROVIDERS`, e.g. `daytona,e2b`
+fn burst_order_from_env() -> Vec<RuntimeType> {
+    let Ok(value) = std::env::var("GATEWAY_BURST_PROVIDERS") else {
+        return runtime::DEFAULT_BURST_ORDER.to_vec();
+    };
+
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            match serde_json::from_value(serde_json::Value::String(name.to_string())) {
+                Ok(runtime_type) => Some(runtime_type),
+                Err(_) => {
+                    error!("Ignoring unknown burst provider {:?}", name);
+                    None
+                }
+            }
+        })
+        .collect()
 }
 
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "gateway",
+    responses((status = 200, description = "The gateway is up", body = HealthResponse))
+)]
 async fn health() -> impl IntoResponse {
     Json(HealthResponse {
         status: "ok".to_string(),
@@ -203,138 +765,1050 @@ async fn health() -> impl IntoResponse {
     })
 }
 
+/// Create a sandbox and start the request's code in it
+#[utoipa::path(
+    post,
+    path = "/v1/sandboxes/run",
+    tag = "sandboxes",
+    request_body = RunSandboxRequest,
+    params(
+        ("X-Sandstorm-Tenant" = Option<String>, Header, description = "Tenant the sandbox is run for"),
+        ("X-Sandstorm-Run-Id" = Option<Uuid>, Header, description = "Correlation ID to run under instead of a new one"),
+    ),
+    responses(
+        (status = 200, description = "The sandbox is running", body = RunSandboxResponse),
+        (status = 202, description = "Held until an approver decides on it", body = Approval),
+        (status = 400, description = "The request can't be run as given"),
+        (status = 403, description = "Blocked by the code scan or a template's policy, or larger than a tenant limit"),
+        (status = 429, description = "Over a rate limit, the tenant's monthly quota or its concurrent limits"),
+        (status = 503, description = "No runtime can host the sandbox"),
+    )
+)]
 async fn run_sandbox(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(req): Json<RunSandboxRequest>,
-) -> Result<Json<RunSandboxResponse>, StatusCode> {
-    // Select appropriate runtime based on isolation level and preference
-    let runtime = state.runtime_registry
-        .select_runtime(req.isolation_level, req.runtime_preference)
-        .await
-        .map_err(|e| {
-            error!("Failed to select runtime: {}", e);
-            StatusCode::SERVICE_UNAVAILABLE
+) -> Result<axum::response::Response, axum::response::Response> {
+    // Callers may pass their own correlation ID; otherwise this run starts one
+    let run_id = run_id_from_headers(&headers).unwrap_or_else(Uuid::new_v4);
+    let tenant = tenant_from_headers(&headers);
+    let user = user_from_headers(&headers);
+
+    // Sensitive runs wait for an approver before anything is created, so
+    // check what can be checked now rather than once it is approved
+    let reasons = state.approvals.policy.reasons(&req);
+    if !reasons.is_empty() {
+        check_request(&state, &req).map_err(|e| StartError::Invalid(e).response(state.debug_errors))?;
+        let requester = state.approvals.policy.approver(&headers).map(str::to_string);
+        let approval: Approval = state.approvals.hold(req, reasons, run_id, tenant, user, requester).await.map_err(|e| {
+            error!("Failed to hold run {} for approval: {:#}", run_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
         })?;
+        return Ok((StatusCode::ACCEPTED, Json(approval)).into_response());
+    }
+
+    let trace = otlp::TraceContext::from_headers(&headers, run_id);
+    let response = launch(&state, req, run_id, tenant, trace, user)
+        .await
+        .map_err(|e| e.response(state.debug_errors))?;
+    Ok(Json(response).into_response())
+}
+
+/// Start a run and everything watching it: spans, stats, output capture,
+/// its callback and exit snapshot
+async fn launch(
+    state: &AppState,
+    req: RunSandboxRequest,
+    run_id: Uuid,
+    tenant: Option<String>,
+    trace: otlp::TraceContext,
+    user: String,
+) -> Result<RunSandboxResponse, StartError> {
+    let snapshot_timeout = req.auto_snapshot_on_exit.then_some(req.timeout);
+    let observability = req.observability;
+    let callback = req.callback_url.clone().map(|url| (url, req.timeout));
+    let span_start = chrono::Utc::now();
+
+    let started = start_sandbox(state, req, run_id, tenant.clone()).await;
+    let trace = state.observer.run_span(
+        &observability,
+        trace,
+        span_start,
+        run_id,
+        started.as_ref().map(|started| started.sandbox_id).map_err(ToString::to_string),
+    );
+    let started = started.inspect_err(|e| error!("{}", e))?;
+    let recorder = Recorder::start(
+        started.sandbox_id,
+        Some(run_id),
+        user,
+        SessionKind::Run,
+        started.command.clone(),
+    );
+    observability::watch(
+        state,
+        started.sandbox_id,
+        observability,
+        trace,
+        started.runtime.clone(),
+        Some(recorder),
+    )
+    .await;
+    if let Some((url, timeout)) = callback {
+        callbacks::spawn(state, started.sandbox_id, run_id, url, timeout).await;
+    }
+    if let Some(timeout) = snapshot_timeout {
+        exit_snapshot::spawn(
+            state.clone(),
+            started.runtime.clone(),
+            started.sandbox_id,
+            run_id,
+            timeout,
+            tenant,
+        )
+        .await;
+    }
+
+    Ok(RunSandboxResponse {
+        sandbox_id: started.sandbox_id,
+        run_id,
+        status: "running".to_string(),
+        findings: started.findings,
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+enum StartError {
+    #[error("Invalid sandbox configuration: {0}")]
+    Invalid(anyhow::Error),
+    #[error("Failed to select runtime: {0}")]
+    NoRuntime(anyhow::Error),
+    #[error("Failed to create sandbox: {0}")]
+    Create(anyhow::Error),
+    #[error("Code blocked by scan rules: {}", blocking_rules(.0))]
+    Blocked(Vec<Finding>),
+    #[error("Failed to restore template snapshot: {0}")]
+    Template(anyhow::Error),
+    #[error("Tenant {} has used up its {} quota", .0.tenant, .0.month)]
+    QuotaExhausted(Box<QuotaUsage>),
+    #[error("{0}")]
+    TenantQuota(Box<tenant_quotas::QuotaExceeded>),
+}
+
+/// Run a request from a tenant over quota at low priority on the cheapest
+/// runtime, so it's the first to be preempted
+fn downgrade(req: &mut RunSandboxRequest) {
+    req.priority = Priority::Low;
+    req.optimize_for = Some(OptimizationHint::Cheapest);
+}
+
+fn blocking_rules(findings: &[Finding]) -> String {
+    let mut rules: Vec<_> = findings
+        .iter()
+        .filter(|finding| finding.action == scan::ScanAction::Block)
+        .map(|finding| finding.rule.as_str())
+        .collect();
+    rules.sort_unstable();
+    rules.dedup();
+    rules.join(", ")
+}
+
+impl StartError {
+    fn status(&self) -> StatusCode {
+        match self {
+            StartError::Invalid(_) => StatusCode::BAD_REQUEST,
+            StartError::NoRuntime(_) => StatusCode::SERVICE_UNAVAILABLE,
+            StartError::Create(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            StartError::Blocked(_) => StatusCode::FORBIDDEN,
+            StartError::Template(e) if e.is::<vault::Blocked>() => StatusCode::FORBIDDEN,
+            StartError::Template(e) if Unavailable::find(e).is_some() => StatusCode::SERVICE_UNAVAILABLE,
+            StartError::Template(_) => StatusCode::BAD_GATEWAY,
+            StartError::QuotaExhausted(_) => StatusCode::TOO_MANY_REQUESTS,
+            StartError::TenantQuota(exceeded) => exceeded.status(),
+        }
+    }
+
+    /// The error's status, with the error and any failed runtime command
+    /// (argv, exit code, stderr) as the body when `debug` is set. Blocked
+    /// code always gets its findings back, so callers can fix it, and
+    /// tenants over quota get their usage or remaining capacity, and
+    /// requests refused while the vault is down when to retry.
+    fn response(&self, debug: bool) -> axum::response::Response {
+        if let StartError::Template(cause) = self {
+            if let Some(unavailable) = Unavailable::find(cause) {
+                return unavailable.clone().into_response();
+            }
+        }
+        let cause = match self {
+            StartError::Blocked(findings) => {
+                let body = serde_json::json!({
+                    "error": self.to_string(),
+                    "findings": findings,
+                });
+                return (self.status(), Json(body)).into_response();
+            }
+            StartError::QuotaExhausted(usage) => {
+                let body = serde_json::json!({
+                    "error": self.to_string(),
+                    "usage": usage,
+                });
+                return (self.status(), Json(body)).into_response();
+            }
+            StartError::TenantQuota(exceeded) => {
+                let body = serde_json::json!({
+                    "error": self.to_string(),
+                    "resource": exceeded.resource,
+                    "quota": exceeded.capacity,
+                });
+                return (self.status(), Json(body)).into_response();
+            }
+            _ if !debug => return self.status().into_response(),
+            StartError::Invalid(cause)
+            | StartError::NoRuntime(cause)
+            | StartError::Create(cause)
+            | StartError::Template(cause) => cause,
+        };
+        let body = serde_json::json!({
+            "error": self.to_string(),
+            "command": runtime::command::failure_of(cause),
+        });
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+/// Whether a failed call to another Sandstorm service counts against its
+/// circuit breaker: anything but the service refusing the request, which
+/// is no sign of it being down
+fn service_failed(error: &anyhow::Error) -> bool {
+    !error
+        .downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+        .is_some_and(|status| status.is_client_error())
+}
+
+/// A sandbox started for a run request
+struct Started {
+    sandbox_id: Uuid,
+    runtime: Arc<dyn SandboxRuntime>,
+    /// Command the sandbox runs
+    command: Vec<String>,
+    /// Scan findings that didn't block the run
+    findings: Vec<Finding>,
+}
 
-    // Build sandbox configuration
-    let config = SandboxConfig {
-        id: Uuid::new_v4(),
+/// Create a sandbox for a run request and start its code
+async fn start_sandbox(
+    state: &AppState,
+    mut req: RunSandboxRequest,
+    run_id: Uuid,
+    tenant: Option<String>,
+) -> Result<Started, StartError> {
+    let started = std::time::Instant::now();
+    let registry = &state.runtime_registry;
+    let config_id = Uuid::new_v4();
+    let demand = registry.demand(req.cpu_limit, req.memory_limit);
+    let deadline = std::time::Instant::now() + registry.queue_timeout();
+    check_request(state, &req).map_err(StartError::Invalid)?;
+    let template = scaling::template_of(req.template_snapshot, &req.language);
+    state.pool_scaling.record_request(&template);
+    let monitoring = req.observability.security_monitoring;
+
+    let quota_tenant = tenant.as_deref().unwrap_or(quota::DEFAULT_TENANT);
+    if let Some(usage) = state.quotas.usage(quota_tenant).await {
+        match quota::admission(&usage) {
+            quota::Admission::Reject => return Err(StartError::QuotaExhausted(Box::new(usage))),
+            quota::Admission::Downgrade => {
+                info!(%run_id, tenant = quota_tenant, "Quota used up, downgrading run");
+                downgrade(&mut req);
+            }
+            quota::Admission::Allow => {}
+        }
+    }
+
+    // Scan the code before anything is spent on running it
+    let findings = match &state.code_scanner {
+        Some(scanner) => scanner.scan(&req.language, &req.code),
+        None => Vec::new(),
+    };
+    if !findings.is_empty() {
+        state.metrics.code_scanned(&findings);
+        if let Some(event) = security::code_scan(config_id, Some(run_id), &req.language, &findings) {
+            state.security.report(event, monitoring);
+        }
+        match scan::verdict(&findings) {
+            Some(scan::ScanAction::Block) => return Err(StartError::Blocked(findings)),
+            Some(scan::ScanAction::Warn) => {
+                warn!(%run_id, findings = ?findings, "Submitted code matched scan rules")
+            }
+            _ => {}
+        }
+    }
+
+    let rootfs_layers = match (req.template_snapshot, &state.vault) {
+        (Some(template), Some(vault)) => restore_layers(state, vault, template, tenant.as_deref())
+            .await
+            .map_err(StartError::Template)?,
+        _ => Vec::new(),
+    };
+    let mut config = sandbox_config(&req, config_id, run_id, rootfs_layers);
+    let metadata_token = state
+        .metadata
+        .provision(&mut config.environment)
+        .map_err(StartError::Create)?;
+    if let Some(event) = security::network_violation(&config, Some(run_id)) {
+        let reason = anyhow::anyhow!(event.message.clone());
+        state.security.report(event, monitoring);
+        return Err(StartError::Invalid(reason));
+    }
+
+    // Charge the tenant before waiting for room, so its own sandboxes can't
+    // queue past its limits
+    state
+        .tenant_quotas
+        .reserve(tenant.as_deref(), config_id, demand)
+        .map_err(StartError::TenantQuota)?;
+
+    // A warm sandbox of the run's template skips the boot
+    let warm = state.warm_pools.start(registry, &template, &config).await;
+    let (runtime, sandbox_id) = match warm {
+        Some(started) => {
+            state.metrics.warm_start(&template);
+            started
+        }
+        None => boot(state, &req, &config, demand, deadline, &template).await?,
+    };
+    state.tenant_quotas.rekey(config_id, sandbox_id);
+    state.run_ledger.assign(sandbox_id, run_id).await;
+    if let Some(token) = metadata_token {
+        let metadata = SandboxMetadata {
+            sandbox_id,
+            run_id,
+            tenant: tenant.clone(),
+            runtime_type: runtime.runtime_type(),
+            language: req.language.clone(),
+            isolation_level: req.isolation_level,
+            labels: req.labels.clone(),
+            limits: SandboxLimits {
+                cpu_limit: config.cpu_limit,
+                memory_limit: config.memory_limit,
+                timeout: config.timeout,
+            },
+        };
+        state.metadata.register(token, metadata).await;
+    }
+    state.owners.record(sandbox_id, runtime.runtime_type(), tenant).await;
+    state.result_cache.track(sandbox_id, &config.image, &req.code).await;
+    if let Some(event) =
+        security::privileged_mounts(sandbox_id, runtime.runtime_type(), Some(run_id), &config)
+    {
+        state.security.report(event, monitoring);
+    }
+    if monitoring == Some(true) {
+        state.security.sandbox_started(sandbox_id, security::provider_name(runtime.runtime_type()), run_id);
+    }
+    state
+        .preemption
+        .admit(
+            sandbox_id,
+            req.priority,
+            runtime.runtime_type(),
+            req.isolation_level,
+            demand,
+        )
+        .await;
+    state.metrics.sandbox_started(
+        runtime.runtime_type(),
+        req.isolation_level,
+        req.mode,
+        started.elapsed().as_secs_f64(),
+        &run_id.to_string(),
+    );
+    info!(%sandbox_id, %run_id, priority = ?req.priority, mode = ?req.mode, "Sandbox started");
+
+    Ok(Started {
+        sandbox_id,
+        runtime,
+        command: config.command,
+        findings,
+    })
+}
+
+/// Check what a run request asks for that no runtime decides on
+fn check_request(state: &AppState, req: &RunSandboxRequest) -> anyhow::Result<()> {
+    runtime::sysctl::validate(&req.sysctls)?;
+    if req.auto_snapshot_on_exit && state.vault.is_none() {
+        anyhow::bail!("auto_snapshot_on_exit needs GATEWAY_SNAPSHOT_VAULT_URL");
+    }
+    if req.template_snapshot.is_some() && state.vault.is_none() {
+        anyhow::bail!("template_snapshot needs GATEWAY_SNAPSHOT_VAULT_URL");
+    }
+    if let Some(url) = &req.callback_url {
+        state.callbacks.check(url)?;
+    }
+    if req.ttl_seconds == Some(0) {
+        anyhow::bail!("ttl_seconds must be at least 1");
+    }
+    req.observability.validate(state)
+}
+
+/// Boot a sandbox for a run request: select a runtime that can run the
+/// configuration, claim host resources on it, and create the sandbox.
+/// Releases the tenant's reservation if that fails.
+async fn boot(
+    state: &AppState,
+    req: &RunSandboxRequest,
+    config: &SandboxConfig,
+    demand: runtime::capacity::Demand,
+    deadline: std::time::Instant,
+    template: &str,
+) -> Result<(Arc<dyn SandboxRuntime>, Uuid), StartError> {
+    let registry = &state.runtime_registry;
+    let mut admission = None;
+    let mut queued = None;
+    let runtime = loop {
+        let error = match registry
+            .select_runtime(config, req.optimize_for, demand)
+            .await
+        {
+            Ok(runtime) if registry.reserve(config.id, &runtime, demand).await => break runtime,
+            Ok(runtime) => anyhow::anyhow!(
+                "Host capacity for {:?} was taken by a concurrent request",
+                runtime.runtime_type()
+            ),
+            Err(e) => e,
+        };
+
+        // Out of capacity everywhere: make room by preempting lower-priority
+        // work, and keep the freed slot until this sandbox has taken it
+        if admission.is_none() {
+            let guard = state.preemption.lock_admission().await;
+            if state
+                .preemption
+                .preempt_for(registry, &state.run_ledger, req.priority, req.isolation_level)
+                .await
+            {
+                admission = Some(guard);
+                continue;
+            }
+        }
+        admission = None;
+
+        // Otherwise wait for room, for as long as requests may queue
+        queued.get_or_insert_with(|| state.pool_scaling.queue(template));
+        if !registry.wait_for_room(deadline).await {
+            state.tenant_quotas.release(config.id);
+            return Err(StartError::NoRuntime(error));
+        }
+    };
+
+    drop(queued);
+
+    if let Err(e) = runtime.validate(config) {
+        registry.release(config.id).await;
+        state.tenant_quotas.release(config.id);
+        return Err(StartError::Invalid(e));
+    }
+
+    // Create and start sandbox
+    let sandbox_id = match runtime.create(config).await {
+        Ok(sandbox_id) => sandbox_id,
+        Err(e) => {
+            registry.release(config.id).await;
+            state.tenant_quotas.release(config.id);
+            return Err(StartError::Create(e));
+        }
+    };
+    drop(admission);
+    registry.rekey(config.id, sandbox_id).await;
+    Ok((runtime, sandbox_id))
+}
+
+/// Sandbox configuration for a run request
+fn sandbox_config(
+    req: &RunSandboxRequest,
+    config_id: Uuid,
+    run_id: Uuid,
+    rootfs_layers: Vec<String>,
+) -> SandboxConfig {
+    let mut environment = req.environment.clone().unwrap_or_default();
+    environment.insert(RUN_ID_ENV.to_string(), run_id.to_string());
+
+    SandboxConfig {
+        id: config_id,
         image: format!("sandstorm/{}", req.language),
         command: vec![get_language_command(&req.language), req.code.clone()],
-        environment: req.environment.unwrap_or_default(),
+        environment,
         cpu_limit: req.cpu_limit,
         memory_limit: req.memory_limit,
         timeout: req.timeout,
         isolation_level: req.isolation_level,
         runtime_preference: req.runtime_preference,
         working_dir: Some("/workspace".to_string()),
-        mounts: req.mounts.unwrap_or_default().into_iter()
+        mounts: req.mounts.iter().flatten()
             .map(|m| Mount {
-                source: m.source,
-                destination: m.destination,
-                read_only: m.read_only,
+                source: m.source.clone(),
+                destination: m.destination.clone(),
+                // Nothing on the host is writable from a read-only sandbox
+                read_only: m.read_only || req.mode == ExecutionMode::ReadOnly,
             })
             .collect(),
-    };
-
-    // Create and start sandbox
-    let sandbox_id = runtime.create(&config).await.map_err(|e| {
-        error!("Failed to create sandbox: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+        execution_mode: req.mode,
+        scratch_size: req.scratch_size_mb.map(|mb| mb * 1024 * 1024),
+        sysctls: req.sysctls.clone(),
+        gvisor: req.gvisor,
+        firecracker: req.firecracker,
+        arch: req.arch,
+        rootfs_layers,
+        ttl_seconds: req.ttl_seconds,
+    }
+}
 
-    Ok(Json(RunSandboxResponse {
-        sandbox_id,
-        status: "running".to_string(),
-    }))
+/// Fetch a template snapshot's layers, leased so the vault can't collect
+/// the template while they download
+async fn restore_layers(
+    state: &AppState,
+    vault: &VaultClient,
+    template: Uuid,
+    tenant: Option<&str>,
+) -> anyhow::Result<Vec<String>> {
+    let lease = state
+        .snapshot_leases
+        .acquire(template, tenant, "restore", leases::RESTORE_LEASE_TTL)
+        .await
+        .map_err(|e| warn!("Restoring template {} without a lease: {:#}", template, e))
+        .ok();
+    let layers = state.layers.prepare(vault, template, tenant).await;
+    if let Some(lease) = &lease {
+        state.snapshot_leases.release(lease).await;
+    }
+    layers
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct ExecRequest {
     command: Vec<String>,
     environment: Option<std::collections::HashMap<String, String>>,
+    /// Skip the result cache (as does `Cache-Control: no-cache`)
+    #[serde(default)]
+    no_cache: bool,
+    /// Working directory, user, TTY, timeout and resource limit overrides,
+    /// and stdin
+    #[serde(flatten)]
+    options: ExecOptions,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ExecResponse {
+    #[serde(flatten)]
+    result: SandboxResult,
+    /// Whether the result was served from the result cache
+    cached: bool,
 }
 
+/// Run a command in a sandbox and wait for it to finish
+#[utoipa::path(
+    post,
+    path = "/v1/sandboxes/{id}/exec",
+    tag = "sandboxes",
+    request_body = ExecRequest,
+    params(("id" = Uuid, Path, description = "Sandbox ID")),
+    responses(
+        (status = 200, description = "The command's output and exit", body = ExecResponse),
+        (status = 400, description = "Invalid exec options"),
+        (status = 404, description = "No such sandbox"),
+        (status = 409, description = "The sandbox is preempted or quarantined"),
+        (status = 504, description = "The command ran past its timeout"),
+    )
+)]
 async fn exec_sandbox(
     State(state): State<AppState>,
     axum::extract::Path(id): axum::extract::Path<Uuid>,
+    headers: HeaderMap,
     Json(req): Json<ExecRequest>,
-) -> Result<Json<runtime::SandboxResult>, StatusCode> {
-    // Find which runtime has this sandbox
-    for runtime_type in state.runtime_registry.list().await {
-        if let Ok(runtime) = state.runtime_registry.get(runtime_type).await {
-            match runtime.exec(id, req.command.clone(), req.environment.clone()).await {
-                Ok(result) => return Ok(Json(result)),
-                Err(e) => {
-                    error!("Failed to exec in sandbox {}: {}", id, e);
-                }
+) -> Result<axum::response::Response, StatusCode> {
+    if let Err(e) = req.options.validate() {
+        error!("Invalid exec options for sandbox {}: {}", id, e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // Preempted sandboxes can't run anything until they are resumed, after
+    // which execs go to the sandbox restored in their place
+    let id = state.preemption.locate(id).await.ok_or(StatusCode::CONFLICT)?;
+    if state.quarantines.mode(id).await == Some(QuarantineMode::Freeze) {
+        return Ok(unfinished_exec(StatusCode::CONFLICT, id, ExitReason::Quarantined, 0));
+    }
+
+    let mut recorder = exec_recorder(&state, id, &headers, &req.command, req.options.stdin.as_deref()).await;
+
+    // A different directory, user, terminal, limits or input can change the
+    // output
+    let overridden = req.options.working_dir.is_some()
+        || req.options.user.is_some()
+        || req.options.tty
+        || req.options.limits.is_set()
+        || req.options.stdin.is_some();
+    let bypass_cache = req.no_cache
+        || overridden
+        || headers
+            .get(axum::http::header::CACHE_CONTROL)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.contains("no-cache"));
+    let cache_key = if bypass_cache {
+        None
+    } else {
+        state
+            .result_cache
+            .key(id, &req.command, req.environment.as_ref())
+            .await
+    };
+
+    let cached = match &cache_key {
+        Some(key) => state.result_cache.get(key).await,
+        None => None,
+    };
+    if let Some(mut result) = cached {
+        result.id = id;
+        return Ok(finish_exec(&state, recorder, result, true).into_response());
+    }
+
+    let trace_id = exec_trace_id(&state, id, &headers).await;
+
+    let Some(runtime) = state.runtime_registry.runtime_of(id).await else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let runtime_type = runtime.runtime_type();
+    // Observed under the ID its run started it with
+    let observed = state.preemption.resumed_from(id).await.unwrap_or(id);
+    let span_start = chrono::Utc::now();
+    let started = std::time::Instant::now();
+    let exec = runtime.exec(id, req.command.clone(), req.environment.clone(), &req.options);
+    let outcome = match req.options.timeout_ms {
+        Some(ms) => match tokio::time::timeout(std::time::Duration::from_millis(ms), exec).await {
+            Ok(outcome) => outcome,
+            Err(_) => {
+                error!("Exec in sandbox {} timed out after {}ms", id, ms);
+                // A quarantine freezing the sandbox mid-exec stalls it
+                let exit_reason = match state.quarantines.mode(id).await {
+                    Some(QuarantineMode::Freeze) => ExitReason::Quarantined,
+                    _ => ExitReason::Timeout,
+                };
+                state.metrics.exec_finished(
+                    runtime_type,
+                    exit_reason,
+                    started.elapsed().as_secs_f64(),
+                    trace_id.as_deref(),
+                );
+                state
+                    .observer
+                    .exec_span(observed, &req.command, span_start, Err(format!("timed out after {}ms", ms)))
+                    .await;
+                return Ok(unfinished_exec(StatusCode::GATEWAY_TIMEOUT, id, exit_reason, ms));
+            }
+        },
+        None => exec.await,
+    };
+    match outcome {
+        Ok(result) => {
+            state.metrics.exec_finished(
+                runtime_type,
+                result.exit_reason,
+                started.elapsed().as_secs_f64(),
+                trace_id.as_deref(),
+            );
+            state.observer.exec_span(observed, &req.command, span_start, Ok(&result)).await;
+            if let Some(event) = security::shell_exec(
+                id,
+                runtime_type,
+                state.run_ledger.get(id).await,
+                &req.command,
+                result.exit_code,
+            ) {
+                let monitoring = state.observer.security_monitoring(observed).await;
+                state.security.report(event, monitoring);
+            }
+            if let Some(key) = cache_key {
+                state.result_cache.insert(key, &result).await;
             }
+            if result.exit_reason != ExitReason::Completed {
+                warn!(sandbox_id = %id, exit_code = result.exit_code, exit_reason = ?result.exit_reason, "Exec did not complete");
+            }
+            Ok(finish_exec(&state, recorder.take(), result, false).into_response())
+        }
+        Err(e) if e.is::<runtime::UnsupportedExecOptions>() => {
+            error!("Invalid exec options for sandbox {}: {}", id, e);
+            Err(StatusCode::BAD_REQUEST)
+        }
+        Err(e) => {
+            error!("Failed to exec in sandbox {}: {}", id, e);
+            state.observer.exec_span(observed, &req.command, span_start, Err(e.to_string())).await;
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
-    
-    Err(StatusCode::NOT_FOUND)
 }
 
+/// A recording of an exec session, with the command as typed and any stdin
+/// it's given up front, when recordings are enabled
+async fn exec_recorder(
+    state: &AppState,
+    id: Uuid,
+    headers: &HeaderMap,
+    command: &[String],
+    stdin: Option<&str>,
+) -> Option<Recorder> {
+    if !state.recordings.enabled() {
+        return None;
+    }
+    let mut recorder = Recorder::start(
+        id,
+        state.run_ledger.get(id).await,
+        user_from_headers(headers),
+        SessionKind::Exec,
+        command.to_vec(),
+    );
+    let line = runtime::remote::shell_join(command);
+    recorder.input(format!("{}\r", line).as_bytes());
+    recorder.output(format!("$ {}\n", line).as_bytes());
+    if let Some(stdin) = stdin {
+        recorder.input(stdin.as_bytes());
+    }
+    Some(recorder)
+}
+
+/// Trace ID for an exec's metrics: the caller's, else its run's
+async fn exec_trace_id(state: &AppState, id: Uuid, headers: &HeaderMap) -> Option<String> {
+    match sandstorm_metrics::trace_id(headers) {
+        Some(trace_id) => Some(trace_id),
+        None => state.run_ledger.get(id).await.map(|run_id| run_id.to_string()),
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct SandboxStatusResponse {
+    #[serde(flatten)]
+    status: SandboxStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<Priority>,
+    /// Set once the sandbox has been preempted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preemption: Option<Preemption>,
+    /// Preempted sandbox this one was resumed in place of
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resumed_from: Option<Uuid>,
+    /// Quarantine mode the security monitor put the sandbox in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quarantine: Option<QuarantineMode>,
+    /// Filesystem snapshot of a run started with `auto_snapshot_on_exit`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_snapshot: Option<ExitSnapshot>,
+}
+
+/// Sandboxes on every runtime, filtered by runtime, state, language and
+/// creation time
+#[utoipa::path(
+    get,
+    path = "/v1/sandboxes",
+    tag = "sandboxes",
+    params(runtime::index::SandboxFilter),
+    responses((status = 200, description = "Matching sandboxes", body = Vec<IndexedSandbox>))
+)]
+async fn list_sandboxes(
+    State(state): State<AppState>,
+    axum::extract::Query(filter): axum::extract::Query<runtime::index::SandboxFilter>,
+) -> Json<Vec<IndexedSandbox>> {
+    Json(state.runtime_registry.sandboxes().list(&filter).await)
+}
+
+/// A sandbox's state, resource usage and exit, with its preemption,
+/// quarantine and exit snapshot if it has them
+#[utoipa::path(
+    get,
+    path = "/v1/sandboxes/{id}/status",
+    tag = "sandboxes",
+    params(("id" = Uuid, Path, description = "Sandbox ID")),
+    responses(
+        (status = 200, description = "The sandbox's status", body = SandboxStatusResponse),
+        (status = 404, description = "No such sandbox"),
+    )
+)]
 async fn sandbox_status(
     State(state): State<AppState>,
     axum::extract::Path(id): axum::extract::Path<Uuid>,
-) -> Result<Json<runtime::SandboxStatus>, StatusCode> {
-    // Find which runtime has this sandbox
-    for runtime_type in state.runtime_registry.list().await {
-        if let Ok(runtime) = state.runtime_registry.get(runtime_type).await {
-            match runtime.status(id).await {
-                Ok(status) => return Ok(Json(status)),
-                Err(e) => {
-                    error!("Failed to get status for sandbox {}: {}", id, e);
-                }
-            }
+) -> Result<Json<SandboxStatusResponse>, StatusCode> {
+    status_of(&state, id).await.map(Json)
+}
+
+/// Status of a sandbox from the runtime hosting it, or from its preemption;
+/// 404 if the gateway doesn't know it, 500 if its runtime can't say
+async fn status_of(state: &AppState, id: Uuid) -> Result<SandboxStatusResponse, StatusCode> {
+    let priority = state.preemption.priority(id).await;
+    let preemption = state.preemption.preemption(id).await;
+    let resumed_from = state.preemption.resumed_from(id).await;
+    let exit_snapshot = state.exit_snapshots.get(id).await;
+
+    // A preempted sandbox no longer exists in its runtime
+    if let Some(preemption) = preemption {
+        return Ok(SandboxStatusResponse {
+            status: preemption.status(id),
+            priority,
+            preemption: Some(preemption),
+            resumed_from,
+            quarantine: None,
+            exit_snapshot,
+        });
+    }
+
+    let runtime = state.runtime_registry.runtime_of(id).await.ok_or(StatusCode::NOT_FOUND)?;
+    match runtime.status(id).await {
+        Ok(status) => Ok(SandboxStatusResponse {
+            status,
+            priority,
+            preemption: None,
+            resumed_from,
+            quarantine: state.quarantines.mode(id).await,
+            exit_snapshot,
+        }),
+        Err(e) => {
+            error!("Failed to get status for sandbox {}: {}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
-    
-    Err(StatusCode::NOT_FOUND)
 }
 
+/// Tenant and runtime of a sandbox the gateway created, for services that
+/// receive reports about it
+async fn sandbox_owner(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> Result<Json<SandboxOwner>, StatusCode> {
+    if let Some(owner) = state.owners.get(id).await {
+        return Ok(Json(owner));
+    }
+    // A sandbox resumed after preemption belongs to the owner of the one it
+    // replaced
+    let original = state.preemption.resumed_from(id).await.ok_or(StatusCode::NOT_FOUND)?;
+    let owner = state.owners.get(original).await.ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(SandboxOwner { sandbox_id: id, ..owner }))
+}
+
+/// Response for an exec the gateway gave up on before the command ended
+fn unfinished_exec(
+    status: StatusCode,
+    id: Uuid,
+    exit_reason: ExitReason,
+    duration_ms: u64,
+) -> axum::response::Response {
+    let result = SandboxResult {
+        id,
+        exit_code: -1,
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+        duration_ms,
+        resource_usage: Default::default(),
+        exit_reason,
+    };
+    (status, Json(ExecResponse { result, cached: false })).into_response()
+}
+
+/// Record an exec's output and build its response
+fn finish_exec(
+    state: &AppState,
+    recorder: Option<Recorder>,
+    result: SandboxResult,
+    cached: bool,
+) -> (HeaderMap, Json<ExecResponse>) {
+    let mut headers = HeaderMap::new();
+    if let Some(mut recorder) = recorder {
+        recorder.output(&result.stdout);
+        recorder.output(&result.stderr);
+        if let Ok(value) = recorder.id().to_string().parse() {
+            headers.insert(RECORDING_ID_HEADER, value);
+        }
+        state.recordings.store(recorder, Some(result.exit_code));
+    }
+    (headers, Json(ExecResponse { result, cached }))
+}
+
+/// Destroy a sandbox, or cancel the resume of a preempted one
+#[utoipa::path(
+    delete,
+    path = "/v1/sandboxes/{id}",
+    tag = "sandboxes",
+    params(("id" = Uuid, Path, description = "Sandbox ID")),
+    responses(
+        (status = 204, description = "The sandbox is gone"),
+        (status = 404, description = "No such sandbox"),
+    )
+)]
 async fn destroy_sandbox(
     State(state): State<AppState>,
     axum::extract::Path(id): axum::extract::Path<Uuid>,
 ) -> Result<StatusCode, StatusCode> {
-    // Find which runtime has this sandbox
-    for runtime_type in state.runtime_registry.list().await {
-        if let Ok(runtime) = state.runtime_registry.get(runtime_type).await {
-            match runtime.destroy(id).await {
-                Ok(_) => return Ok(StatusCode::NO_CONTENT),
-                Err(e) => {
-                    error!("Failed to destroy sandbox {}: {}", id, e);
-                }
-            }
+    destroy(&state, id, ExitReason::Destroyed).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Destroy a sandbox and forget everything kept about it. `reason` is
+/// reported to its run's callback if it hadn't finished.
+async fn destroy(state: &AppState, id: Uuid, reason: ExitReason) -> Result<(), StatusCode> {
+    // A preempted sandbox waiting for room only needs its resume cancelling;
+    // a resumed one is destroyed through the sandbox that replaced it
+    let Some(target) = state.preemption.locate(id).await else {
+        callbacks::destroyed(state, id, None, reason).await;
+        state.preemption.release(id).await;
+        return Ok(());
+    };
+
+    let runtime = state.runtime_registry.runtime_of(target).await.ok_or(StatusCode::NOT_FOUND)?;
+    callbacks::destroyed(state, id, Some((target, runtime.as_ref())), reason).await;
+    if let Err(e) = runtime.destroy(target).await {
+        error!("Failed to destroy sandbox {}: {}", id, e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    state.runtime_registry.release(target).await;
+    state.tenant_quotas.release(target);
+    state.tenant_quotas.release(id);
+    state.result_cache.forget(target).await;
+    state.quarantines.forget(target).await;
+    state.preemption.release(target).await;
+    state.preemption.release(id).await;
+    state.owners.forget(target).await;
+    state.owners.forget(id).await;
+    state.metadata.forget(target).await;
+    state.metadata.forget(id).await;
+    state.exit_snapshots.forget(id).await;
+    let monitoring = state.observer.security_monitoring(id).await;
+    state.observer.forget(id).await;
+    state.security.sandbox_destroyed(target, monitoring);
+    Ok(())
+}
+
+async fn quarantine_sandbox(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+    Json(enforcement): Json<QuarantineEnforcement>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let target = state.preemption.locate(id).await.unwrap_or(id);
+    let Some(runtime) = state.runtime_registry.runtime_of(target).await else {
+        return Err((StatusCode::NOT_FOUND, format!("Sandbox {} not found", id)));
+    };
+
+    state
+        .quarantines
+        .apply(target, runtime.as_ref(), enforcement)
+        .await
+        .map_err(|e| {
+            error!("Failed to quarantine sandbox {}: {:#}", id, e);
+            (StatusCode::CONFLICT, format!("{:#}", e))
+        })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn release_sandbox(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let target = state.preemption.locate(id).await.unwrap_or(id);
+    let Some(runtime) = state.runtime_registry.runtime_of(target).await else {
+        return Err((StatusCode::NOT_FOUND, format!("Sandbox {} not found", id)));
+    };
+
+    match state.quarantines.lift(target, runtime.as_ref()).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err((StatusCode::NOT_FOUND, format!("Sandbox {} is not quarantined", id))),
+        Err(e) => {
+            error!("Failed to lift quarantine of sandbox {}: {:#}", id, e);
+            Err((StatusCode::CONFLICT, format!("{:#}", e)))
         }
     }
-    
-    Err(StatusCode::NOT_FOUND)
+}
+
+async fn pause_sandbox(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> Result<Json<SandboxStatusResponse>, (StatusCode, String)> {
+    set_paused(&state, id, true).await
+}
+
+async fn unpause_sandbox(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> Result<Json<SandboxStatusResponse>, (StatusCode, String)> {
+    set_paused(&state, id, false).await
+}
+
+/// Pause a sandbox in place, or let a paused one run again. Unlike a
+/// snapshot, the sandbox keeps its memory and resources while paused.
+async fn set_paused(
+    state: &AppState,
+    id: Uuid,
+    paused: bool,
+) -> Result<Json<SandboxStatusResponse>, (StatusCode, String)> {
+    let Some(target) = state.preemption.locate(id).await else {
+        return Err((StatusCode::CONFLICT, format!("Sandbox {} is preempted", id)));
+    };
+    let Some(runtime) = state.runtime_registry.runtime_of(target).await else {
+        return Err((StatusCode::NOT_FOUND, format!("Sandbox {} not found", id)));
+    };
+    if runtime.is_remote() {
+        return Err((
+            StatusCode::NOT_IMPLEMENTED,
+            format!("{:?} sandboxes can't be paused", runtime.runtime_type()),
+        ));
+    }
+    // A frozen quarantine only ends when the security monitor lifts it
+    if !paused && state.quarantines.mode(target).await == Some(QuarantineMode::Freeze) {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Sandbox {} is frozen by a quarantine", id),
+        ));
+    }
+
+    if let Err(e) = runtime.set_frozen(target, paused).await {
+        if e.is::<InvalidTransition>() {
+            return Err((StatusCode::CONFLICT, e.to_string()));
+        }
+        error!(
+            "Failed to {} sandbox {}: {:#}",
+            if paused { "pause" } else { "unpause" },
+            id,
+            e
+        );
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)));
+    }
+
+    status_of(state, target)
+        .await
+        .map(Json)
+        .map_err(|status| (status, format!("Status of sandbox {} unavailable", id)))
 }
 
 async fn snapshot_sandbox(
     State(state): State<AppState>,
     axum::extract::Path(id): axum::extract::Path<Uuid>,
 ) -> Result<Json<runtime::SandboxSnapshot>, StatusCode> {
-    // Find which runtime has this sandbox
-    for runtime_type in state.runtime_registry.list().await {
-        if let Ok(runtime) = state.runtime_registry.get(runtime_type).await {
-            match runtime.snapshot(id).await {
-                Ok(snapshot) => return Ok(Json(snapshot)),
-                Err(e) => {
-                    error!("Failed to snapshot sandbox {}: {}", id, e);
-                }
+    let runtime = state.runtime_registry.runtime_of(id).await.ok_or(StatusCode::NOT_FOUND)?;
+    match runtime.snapshot(id).await {
+        Ok(mut snapshot) => {
+            if let Some(run_id) = state.run_ledger.get(id).await {
+                snapshot
+                    .metadata
+                    .insert("run_id".to_string(), serde_json::json!(run_id));
             }
+            Ok(Json(snapshot))
+        }
+        Err(e) => {
+            error!("Failed to snapshot sandbox {}: {}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
-    
-    Err(StatusCode::NOT_FOUND)
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ResumeRequest {
     snapshot: runtime::SandboxSnapshot,
+    /// Vault snapshot whose blob becomes the memory state to resume from
+    #[serde(default)]
+    memory_from_vault: Option<Uuid>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -344,7 +1818,60 @@ struct ResumeResponse {
 
 async fn resume_sandbox(
     State(state): State<AppState>,
-    Json(req): Json<ResumeRequest>,
+    headers: HeaderMap,
+    Json(mut req): Json<ResumeRequest>,
+) -> Result<Json<ResumeResponse>, StatusCode> {
+    let tenant = tenant_from_headers(&headers);
+    let lease = match req.memory_from_vault {
+        Some(vault_id) => {
+            let tenant = tenant.as_deref();
+            // Keeps the vault from collecting the snapshot mid-restore. Old
+            // vaults have no leases, so restores go ahead without one.
+            let lease = state
+                .snapshot_leases
+                .acquire(vault_id, tenant, "restore", leases::RESTORE_LEASE_TTL)
+                .await
+                .map_err(|e| warn!("Restoring snapshot {} without a lease: {:#}", vault_id, e))
+                .ok();
+            let memory = match &state.vault {
+                Some(vault) => vault.download_snapshot(vault_id, tenant).await,
+                None => Err(anyhow::anyhow!("GATEWAY_SNAPSHOT_VAULT_URL is not set")),
+            };
+            let memory = match memory {
+                Ok(memory) => memory,
+                Err(e) => {
+                    if let Some(lease) = &lease {
+                        state.snapshot_leases.release(lease).await;
+                    }
+                    if e.is::<vault::Blocked>() {
+                        warn!("{}", e);
+                        return Err(StatusCode::FORBIDDEN);
+                    }
+                    if let Some(unavailable) = Unavailable::find(&e) {
+                        warn!("Not restoring snapshot {}: {}", vault_id, unavailable);
+                        return Err(StatusCode::SERVICE_UNAVAILABLE);
+                    }
+                    error!("Failed to download snapshot {} from vault: {:#}", vault_id, e);
+                    return Err(StatusCode::BAD_GATEWAY);
+                }
+            };
+            req.snapshot.memory_state = Some(memory);
+            lease
+        }
+        None => None,
+    };
+    let resumed = resume_from(&state, req, tenant).await;
+    if let Some(lease) = &lease {
+        state.snapshot_leases.release(lease).await;
+    }
+    resumed
+}
+
+/// Resume a snapshot whose memory, if any, is already in the request
+async fn resume_from(
+    state: &AppState,
+    req: ResumeRequest,
+    tenant: Option<String>,
 ) -> Result<Json<ResumeResponse>, StatusCode> {
     let runtime = state.runtime_registry
         .get(req.snapshot.runtime_type)
@@ -354,42 +1881,149 @@ async fn resume_sandbox(
             StatusCode::SERVICE_UNAVAILABLE
         })?;
 
-    let sandbox_id = runtime.resume(&req.snapshot).await.map_err(|e| {
-        error!("Failed to resume sandbox: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    // Snapshots don't record limits, so resumed sandboxes get the default charge
+    let registry = &state.runtime_registry;
+    let demand = registry.demand(None, None);
+    if let Err(exceeded) = state.tenant_quotas.reserve(tenant.as_deref(), req.snapshot.id, demand) {
+        warn!("Not resuming snapshot {}: {}", req.snapshot.id, exceeded);
+        return Err(exceeded.status());
+    }
+    if !registry.reserve(req.snapshot.id, &runtime, demand).await {
+        state.tenant_quotas.release(req.snapshot.id);
+        error!("No host capacity to resume snapshot {}", req.snapshot.id);
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    let sandbox_id = match runtime.resume(&req.snapshot).await {
+        Ok(sandbox_id) => sandbox_id,
+        Err(e) => {
+            registry.release(req.snapshot.id).await;
+            state.tenant_quotas.release(req.snapshot.id);
+            error!("Failed to resume sandbox: {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    registry.rekey(req.snapshot.id, sandbox_id).await;
+    state.tenant_quotas.rekey(req.snapshot.id, sandbox_id);
+    state.owners.record(sandbox_id, req.snapshot.runtime_type, tenant).await;
+
+    // A resumed sandbox continues the run its snapshot was taken from
+    let run_id = req
+        .snapshot
+        .metadata
+        .get("run_id")
+        .and_then(|value| serde_json::from_value(value.clone()).ok());
+    if let Some(run_id) = run_id {
+        state.run_ledger.assign(sandbox_id, run_id).await;
+    }
 
     Ok(Json(ResumeResponse { sandbox_id }))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+async fn sandbox_provenance(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> Json<RunProvenance> {
+    let run_id = state.run_ledger.get(id).await;
+    Json(state.provenance.lookup(id, run_id).await)
+}
+
+async fn list_recordings(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> Result<Json<Vec<SessionRecording>>, StatusCode> {
+    state.recordings.list(id).await.map(Json).map_err(|e| {
+        error!("Failed to list recordings for sandbox {}: {}", id, e);
+        StatusCode::SERVICE_UNAVAILABLE
+    })
+}
+
+async fn download_recording(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let cast = state
+        .recordings
+        .cast(id)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch recording {}: {}", id, e);
+            StatusCode::SERVICE_UNAVAILABLE
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE, "application/x-asciicast".to_string()),
+            (
+                axum::http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.cast\"", id),
+            ),
+        ],
+        cast,
+    ))
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct ListRuntimesResponse {
     runtimes: Vec<RuntimeInfo>,
+    /// Whether each local runtime's host prerequisites are met, including
+    /// runtimes that weren't registered because they aren't
+    readiness: Vec<Readiness>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct RuntimeInfo {
     runtime_type: RuntimeType,
     supported_isolation_levels: Vec<IsolationLevel>,
+    architectures: Vec<Arch>,
 }
 
+/// Registered runtimes with the isolation levels and architectures they
+/// support, and each local runtime's host readiness
+#[utoipa::path(
+    get,
+    path = "/v1/runtimes",
+    tag = "runtimes",
+    responses((status = 200, description = "Runtimes and readiness", body = ListRuntimesResponse))
+)]
 async fn list_runtimes(State(state): State<AppState>) -> Json<ListRuntimesResponse> {
     let mut runtimes = Vec::new();
     
     for runtime_type in state.runtime_registry.list().await {
-        let supported_isolation_levels = match runtime_type {
-            RuntimeType::Gvisor => vec![IsolationLevel::Standard, IsolationLevel::Strong],
-            RuntimeType::Kata => vec![IsolationLevel::Strong, IsolationLevel::Maximum],
-            RuntimeType::Firecracker => vec![IsolationLevel::Maximum, IsolationLevel::Strong],
+        let Ok(runtime) = state.runtime_registry.get(runtime_type).await else {
+            continue;
         };
+        let supported_isolation_levels = [
+            IsolationLevel::Standard,
+            IsolationLevel::Strong,
+            IsolationLevel::Maximum,
+        ]
+        .into_iter()
+        .filter(|level| runtime.supports_isolation_level(*level))
+        .collect();
         
+        let architectures = [Arch::X86_64, Arch::Aarch64]
+            .into_iter()
+            .filter(|arch| runtime.supports_arch(*arch))
+            .collect();
+
         runtimes.push(RuntimeInfo {
             runtime_type,
             supported_isolation_levels,
+            architectures,
         });
     }
     
-    Json(ListRuntimesResponse { runtimes })
+    Json(ListRuntimesResponse {
+        runtimes,
+        readiness: state.readiness.as_ref().clone(),
+    })
+}
+
+/// Host and runtime headroom, for schedulers deciding where to send work
+async fn capacity(State(state): State<AppState>) -> Json<CapacityReport> {
+    Json(state.runtime_registry.capacity_report().await)
 }
 
 fn get_language_command(language: &str) -> String {
@@ -404,5 +2038,3 @@ fn get_language_command(language: &str) -> String {
         _ => "sh",
     }.to_string()
 }
-
-use axum::routing::delete;
\ No newline at end of file
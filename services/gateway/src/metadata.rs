@@ -0,0 +1,288 @@
+//! A metadata service for workloads, on its own listener at an address only
+//! sandboxes are routed to (a link-local one like `169.254.169.254`). Each
+//! sandbox is given the service's URL and a random token in its environment
+//! when created; presenting the token in the `X-Sandstorm-Metadata-Token`
+//! header gets the workload its sandbox's ID, labels and limits, and
+//! short-lived credentials other services can check with the gateway.
+//! Requiring the header keeps code tricked into fetching a URL from
+//! reading it.
+//!
+//! Credentials are signed with `GATEWAY_METADATA_SIGNING_KEY`, or a key
+//! picked at startup without one, and stop checking out once their sandbox
+//! is destroyed.
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use ring::{hmac, rand::SecureRandom};
+use sandstorm_types::metadata::{
+    CredentialClaims, CredentialRequest, IntrospectRequest, SandboxCredential, SandboxMetadata,
+    METADATA_TOKEN_ENV, METADATA_TOKEN_HEADER, METADATA_URL_ENV,
+};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::AppState;
+
+const DEFAULT_CREDENTIAL_TTL: Duration = Duration::from_secs(900);
+
+#[derive(Debug)]
+pub struct MetadataService {
+    /// Where the service listens; it's off without one
+    addr: Option<SocketAddr>,
+    /// What sandboxes reach `addr` as
+    url: String,
+    credential_ttl: Duration,
+    key: hmac::Key,
+    /// Sandboxes by the token they were given
+    sandboxes: RwLock<HashMap<String, SandboxMetadata>>,
+}
+
+impl MetadataService {
+    /// The service at `GATEWAY_METADATA_ADDR`, reached by sandboxes at
+    /// `GATEWAY_METADATA_URL` (default `http://<addr>`), issuing
+    /// credentials for `GATEWAY_METADATA_CREDENTIAL_TTL_SECS` (default 900)
+    pub fn from_env() -> Result<Self> {
+        let addr = std::env::var("GATEWAY_METADATA_ADDR")
+            .ok()
+            .map(|addr| addr.parse().context("GATEWAY_METADATA_ADDR"))
+            .transpose()?;
+        let url = std::env::var("GATEWAY_METADATA_URL").ok();
+        let credential_ttl = match std::env::var("GATEWAY_METADATA_CREDENTIAL_TTL_SECS") {
+            Ok(secs) => Duration::from_secs(secs.parse().context("GATEWAY_METADATA_CREDENTIAL_TTL_SECS")?),
+            Err(_) => DEFAULT_CREDENTIAL_TTL,
+        };
+        let key = match std::env::var("GATEWAY_METADATA_SIGNING_KEY") {
+            Ok(key) => key.into_bytes(),
+            Err(_) => random_bytes()?,
+        };
+        Ok(Self::new(addr, url, credential_ttl, &key))
+    }
+
+    pub fn new(addr: Option<SocketAddr>, url: Option<String>, credential_ttl: Duration, key: &[u8]) -> Self {
+        let url = url.unwrap_or_else(|| addr.map(|addr| format!("http://{}", addr)).unwrap_or_default());
+        Self {
+            addr,
+            url: url.trim_end_matches('/').to_string(),
+            credential_ttl,
+            key: hmac::Key::new(hmac::HMAC_SHA256, key),
+            sandboxes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Give a sandbox about to be created the service's URL and a token to
+    /// identify itself with, returning the token to register it under
+    pub fn provision(&self, environment: &mut HashMap<String, String>) -> Result<Option<String>> {
+        if self.addr.is_none() {
+            return Ok(None);
+        }
+        let token = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(random_bytes()?);
+        environment.insert(METADATA_URL_ENV.to_string(), self.url.clone());
+        environment.insert(METADATA_TOKEN_ENV.to_string(), token.clone());
+        Ok(Some(token))
+    }
+
+    pub async fn register(&self, token: String, metadata: SandboxMetadata) {
+        self.sandboxes.write().await.insert(token, metadata);
+    }
+
+    /// Stop answering for a destroyed sandbox
+    pub async fn forget(&self, sandbox_id: Uuid) {
+        self.sandboxes
+            .write()
+            .await
+            .retain(|_, metadata| metadata.sandbox_id != sandbox_id);
+    }
+
+    async fn lookup(&self, headers: &HeaderMap) -> Option<SandboxMetadata> {
+        let token = headers.get(METADATA_TOKEN_HEADER)?.to_str().ok()?;
+        self.sandboxes.read().await.get(token).cloned()
+    }
+
+    async fn is_registered(&self, sandbox_id: Uuid) -> bool {
+        self.sandboxes
+            .read()
+            .await
+            .values()
+            .any(|metadata| metadata.sandbox_id == sandbox_id)
+    }
+
+    /// A credential for the sandbox, good until the TTL runs out
+    pub fn issue(&self, metadata: &SandboxMetadata, audience: Option<String>, now: DateTime<Utc>) -> SandboxCredential {
+        let expires_at = now + chrono::Duration::from_std(self.credential_ttl).unwrap_or_default();
+        let claims = CredentialClaims {
+            sandbox_id: metadata.sandbox_id,
+            run_id: metadata.run_id,
+            tenant: metadata.tenant.clone(),
+            audience,
+            expires_at,
+        };
+        let base64 = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        // Claims always serialize
+        let payload = base64.encode(serde_json::to_vec(&claims).unwrap_or_default());
+        let signature = base64.encode(hmac::sign(&self.key, payload.as_bytes()));
+        SandboxCredential {
+            token: format!("{}.{}", payload, signature),
+            expires_at,
+        }
+    }
+
+    /// What a credential vouches for, if it was signed here, hasn't
+    /// expired, and its sandbox is still around
+    pub async fn introspect(&self, token: &str, now: DateTime<Utc>) -> Option<CredentialClaims> {
+        let base64 = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let (payload, signature) = token.split_once('.')?;
+        let signature = base64.decode(signature).ok()?;
+        hmac::verify(&self.key, payload.as_bytes(), &signature).ok()?;
+        let claims: CredentialClaims = serde_json::from_slice(&base64.decode(payload).ok()?).ok()?;
+        if claims.expires_at <= now || !self.is_registered(claims.sandbox_id).await {
+            return None;
+        }
+        Some(claims)
+    }
+}
+
+fn random_bytes() -> Result<Vec<u8>> {
+    let mut bytes = vec![0u8; 32];
+    ring::rand::SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| anyhow::anyhow!("No randomness available"))?;
+    Ok(bytes)
+}
+
+/// Serve the metadata API on its own listener, when it has an address
+pub fn spawn(service: Arc<MetadataService>) {
+    let Some(addr) = service.addr else {
+        return;
+    };
+    let app = Router::new()
+        .route("/v1/metadata", get(sandbox_metadata))
+        .route("/v1/metadata/credentials", post(issue_credential))
+        .with_state(service);
+    tokio::spawn(async move {
+        let listener = match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Metadata service can't listen on {}: {}", addr, e);
+                return;
+            }
+        };
+        info!("Metadata service listening on {}", addr);
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("Metadata service stopped: {}", e);
+        }
+    });
+}
+
+async fn sandbox_metadata(
+    State(service): State<Arc<MetadataService>>,
+    headers: HeaderMap,
+) -> Result<Json<SandboxMetadata>, StatusCode> {
+    service.lookup(&headers).await.map(Json).ok_or(StatusCode::UNAUTHORIZED)
+}
+
+async fn issue_credential(
+    State(service): State<Arc<MetadataService>>,
+    headers: HeaderMap,
+    request: Option<Json<CredentialRequest>>,
+) -> Result<Json<SandboxCredential>, StatusCode> {
+    let metadata = service.lookup(&headers).await.ok_or(StatusCode::UNAUTHORIZED)?;
+    let audience = request.and_then(|Json(request)| request.audience);
+    Ok(Json(service.issue(&metadata, audience, Utc::now())))
+}
+
+/// Check a credential a workload presented to another service
+pub async fn introspect_credential(
+    State(state): State<AppState>,
+    Json(request): Json<IntrospectRequest>,
+) -> Result<Json<CredentialClaims>, StatusCode> {
+    state
+        .metadata
+        .introspect(&request.token, Utc::now())
+        .await
+        .map(Json)
+        .ok_or(StatusCode::UNAUTHORIZED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sandstorm_types::metadata::SandboxLimits;
+    use sandstorm_types::sandbox::{IsolationLevel, RuntimeType};
+
+    fn service() -> MetadataService {
+        let addr = "169.254.169.254:80".parse().ok();
+        MetadataService::new(addr, None, Duration::from_secs(60), b"signing key")
+    }
+
+    fn metadata(sandbox_id: Uuid) -> SandboxMetadata {
+        SandboxMetadata {
+            sandbox_id,
+            run_id: Uuid::new_v4(),
+            tenant: Some("acme".to_string()),
+            runtime_type: RuntimeType::Gvisor,
+            language: "python".to_string(),
+            isolation_level: IsolationLevel::Standard,
+            labels: HashMap::from([("team".to_string(), "data".to_string())]),
+            limits: SandboxLimits::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn sandboxes_are_known_by_their_token() {
+        let service = service();
+        let mut environment = HashMap::new();
+        let token = service.provision(&mut environment).unwrap().unwrap();
+        assert_eq!(environment[METADATA_URL_ENV], "http://169.254.169.254:80");
+        assert_eq!(environment[METADATA_TOKEN_ENV], token);
+
+        let sandbox_id = Uuid::new_v4();
+        service.register(token.clone(), metadata(sandbox_id)).await;
+        let mut headers = HeaderMap::new();
+        headers.insert(METADATA_TOKEN_HEADER, token.parse().unwrap());
+        assert_eq!(service.lookup(&headers).await.unwrap().sandbox_id, sandbox_id);
+
+        headers.insert(METADATA_TOKEN_HEADER, "guessed".parse().unwrap());
+        assert!(service.lookup(&headers).await.is_none());
+
+        // Without an address the service is off
+        let off = MetadataService::new(None, None, DEFAULT_CREDENTIAL_TTL, b"key");
+        assert!(off.provision(&mut HashMap::new()).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn credentials_check_out_until_they_expire_or_the_sandbox_goes() {
+        let service = service();
+        let sandbox_id = Uuid::new_v4();
+        service.register("token".to_string(), metadata(sandbox_id)).await;
+        let now = Utc::now();
+        let credential = service.issue(&metadata(sandbox_id), Some("telemetry".to_string()), now);
+
+        let claims = service.introspect(&credential.token, now).await.unwrap();
+        assert_eq!(claims.sandbox_id, sandbox_id);
+        assert_eq!(claims.audience.as_deref(), Some("telemetry"));
+        assert!(service.introspect(&credential.token, credential.expires_at).await.is_none());
+
+        // Signed by another key, or altered
+        let other = MetadataService::new(None, None, DEFAULT_CREDENTIAL_TTL, b"other key");
+        assert!(other.introspect(&credential.token, now).await.is_none());
+        let (_, signature) = credential.token.split_once('.').unwrap();
+        let forged = service.issue(&metadata(Uuid::new_v4()), None, now).token;
+        let (payload, _) = forged.split_once('.').unwrap();
+        assert!(service.introspect(&format!("{}.{}", payload, signature), now).await.is_none());
+
+        service.forget(sandbox_id).await;
+        assert!(service.introspect(&credential.token, now).await.is_none());
+    }
+}
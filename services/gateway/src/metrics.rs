@@ -0,0 +1,212 @@
+use prometheus::{Counter, CounterVec, GaugeVec};
+use sandstorm_metrics::{ExemplarHistogram, Metrics};
+use serde::Serialize;
+
+use crate::runtime::lifecycle::StateChange;
+use crate::scaling::PoolReport;
+use crate::scan::Finding;
+
+/// Sandbox lifecycle metrics, exported on the gateway's `/metrics`
+#[derive(Debug, Clone)]
+pub struct GatewayMetrics {
+    pub shared: Metrics,
+    sandboxes_started: CounterVec,
+    start_duration: ExemplarHistogram,
+    exec_duration: ExemplarHistogram,
+    exec_exits: CounterVec,
+    scan_findings: CounterVec,
+    tap_leaks: CounterVec,
+    state_changes: CounterVec,
+    pool_demand: GaugeVec,
+    pool_queued: GaugeVec,
+    pool_target: GaugeVec,
+    pool_utilization: GaugeVec,
+    warm_starts: CounterVec,
+    rate_limited: CounterVec,
+    run_callbacks: CounterVec,
+    sandboxes_expired: CounterVec,
+}
+
+impl GatewayMetrics {
+    pub fn new() -> Self {
+        let shared = Metrics::new("gateway");
+        Self {
+            sandboxes_started: shared.counter(
+                "sandboxes_started_total",
+                "Sandboxes started, by runtime, isolation level and execution mode",
+                &["runtime", "isolation_level", "mode"],
+            ),
+            start_duration: shared.histogram(
+                "sandbox_start_duration_seconds",
+                "Time from a run request to a started sandbox, including queueing",
+                &["runtime"],
+                sandstorm_metrics::LATENCY_BUCKETS.to_vec(),
+            ),
+            exec_duration: shared.histogram(
+                "sandbox_exec_duration_seconds",
+                "Time a runtime took to run an exec; cached results are not counted",
+                &["runtime"],
+                sandstorm_metrics::LATENCY_BUCKETS.to_vec(),
+            ),
+            exec_exits: shared.counter(
+                "sandbox_exec_exits_total",
+                "Execs run to an end, by runtime and why the command stopped",
+                &["runtime", "exit_reason"],
+            ),
+            scan_findings: shared.counter(
+                "code_scan_findings_total",
+                "Scan rule matches in submitted code, by rule category and action",
+                &["category", "action"],
+            ),
+            tap_leaks: shared.counter(
+                "tap_devices_leaked_total",
+                "Firecracker TAP devices found without a live sandbox and removed",
+                &[],
+            ),
+            state_changes: shared.counter(
+                "sandbox_state_transitions_total",
+                "Sandbox state changes, by runtime and the states left and entered",
+                &["runtime", "from", "to"],
+            ),
+            pool_demand: shared.gauge(
+                "warm_pool_demand_per_minute",
+                "Run requests per minute over the scaling window, by pool template",
+                &["template"],
+            ),
+            pool_queued: shared.gauge(
+                "warm_pool_queue_depth",
+                "Run requests waiting for capacity, by pool template",
+                &["template"],
+            ),
+            pool_target: shared.gauge(
+                "warm_pool_target_size",
+                "Warm sandboxes the scaling controller wants kept, by pool template",
+                &["template"],
+            ),
+            pool_utilization: shared.gauge(
+                "warm_pool_utilization",
+                "Share of a pool's sandboxes handed out, as its pool manager last reported",
+                &["template"],
+            ),
+            warm_starts: shared.counter(
+                "warm_starts_total",
+                "Runs started in a warm pool sandbox, by pool template",
+                &["template"],
+            ),
+            rate_limited: shared.counter(
+                "run_requests_rate_limited_total",
+                "Run requests turned away over a rate limit, by the limit they hit",
+                &["limit"],
+            ),
+            run_callbacks: shared.counter(
+                "run_callbacks_total",
+                "Run completion callbacks, by whether the receiver took them",
+                &["result"],
+            ),
+            sandboxes_expired: shared.counter(
+                "sandboxes_expired_total",
+                "Sandboxes destroyed by the reaper for outliving their TTL, by runtime",
+                &["runtime"],
+            ),
+            shared,
+        }
+    }
+
+    /// Record a started sandbox; `trace_id` links the latency to its run
+    pub fn sandbox_started(
+        &self,
+        runtime: impl Serialize,
+        isolation_level: impl Serialize,
+        mode: impl Serialize,
+        seconds: f64,
+        trace_id: &str,
+    ) {
+        let runtime = label(runtime);
+        self.sandboxes_started
+            .with_label_values(&[&runtime, &label(isolation_level), &label(mode)])
+            .inc();
+        self.start_duration.observe(&[&runtime], seconds, Some(trace_id));
+    }
+
+    /// Record what the admission scan found in a run's code
+    pub fn code_scanned(&self, findings: &[Finding]) {
+        for finding in findings {
+            self.scan_findings
+                .with_label_values(&[finding.category.as_str(), &label(finding.action)])
+                .inc();
+        }
+    }
+
+    /// Counter for leaked TAP devices, incremented by the Firecracker runtime
+    pub fn tap_leaks(&self) -> Counter {
+        self.tap_leaks.with_label_values(&[])
+    }
+
+    /// Record a run request turned away by the `api_key` or `ip` rate limit
+    pub fn rate_limited(&self, limit: &str) {
+        self.rate_limited.with_label_values(&[limit]).inc();
+    }
+
+    /// Record a run completion callback taken or given up on
+    pub fn callback_delivered(&self, delivered: bool) {
+        let result = if delivered { "delivered" } else { "failed" };
+        self.run_callbacks.with_label_values(&[result]).inc();
+    }
+
+    /// Record a sandbox destroyed past its TTL
+    pub fn sandbox_expired(&self, runtime: impl Serialize) {
+        self.sandboxes_expired.with_label_values(&[&label(runtime)]).inc();
+    }
+
+    /// Record a sandbox changing state
+    pub fn state_changed(&self, change: &StateChange) {
+        self.state_changes
+            .with_label_values(&[&label(change.runtime), &label(change.from), &label(change.to)])
+            .inc();
+    }
+
+    /// Export a pool template's scaling signals
+    pub fn pool_signals(&self, report: &PoolReport) {
+        let labels = [report.template.as_str()];
+        self.pool_demand.with_label_values(&labels).set(report.demand_per_minute);
+        self.pool_queued.with_label_values(&labels).set(report.queued as f64);
+        self.pool_target.with_label_values(&labels).set(report.target as f64);
+        if let Some(utilization) = report.utilization {
+            self.pool_utilization.with_label_values(&labels).set(utilization);
+        }
+    }
+
+    /// Count a run handed a warm sandbox
+    pub fn warm_start(&self, template: &str) {
+        self.warm_starts.with_label_values(&[template]).inc();
+    }
+
+    /// Stop exporting signals for a template the controller dropped
+    pub fn pool_forgotten(&self, template: &str) {
+        for gauge in [&self.pool_demand, &self.pool_queued, &self.pool_target, &self.pool_utilization] {
+            gauge.remove_label_values(&[template]).ok();
+        }
+    }
+
+    pub fn exec_finished(
+        &self,
+        runtime: impl Serialize,
+        exit_reason: impl Serialize,
+        seconds: f64,
+        trace_id: Option<&str>,
+    ) {
+        let runtime = label(runtime);
+        self.exec_exits
+            .with_label_values(&[&runtime, &label(exit_reason)])
+            .inc();
+        self.exec_duration.observe(&[&runtime], seconds, trace_id);
+    }
+}
+
+/// Label value for an enum, as it is named in the API
+fn label(value: impl Serialize) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
@@ -0,0 +1,375 @@
+//! What a run request's `observability` block turns on for its sandbox:
+//! its command's output kept as a session recording, resource usage
+//! sampled on an interval, security monitoring and OTLP spans. Each costs
+//! something per run, so callers choose per run instead of the gateway
+//! paying for all of them on every sandbox.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::otlp::{OtlpExporter, Span, TraceContext};
+use crate::recording::Recorder;
+use crate::runtime::{ResourceUsage, SandboxResult, SandboxRuntime, SandboxState};
+use crate::AppState;
+
+const MAX_STATS_INTERVAL_SECS: u64 = 3600;
+
+/// Samples kept per sandbox; an hour at 5-second intervals
+const MAX_SAMPLES: usize = 720;
+
+/// Output captured from one run before the rest is dropped
+const MAX_CAPTURE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Per-run observability; everything is off unless asked for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Observability {
+    /// Keep the command's output as a session recording of kind `run`
+    pub capture_stdout: bool,
+    /// Sample the sandbox's resource usage this often (1 to 3600 seconds),
+    /// for `GET /v1/sandboxes/{id}/stats`
+    pub stats_interval_secs: Option<u64>,
+    /// Have the security monitor watch the sandbox, and report the
+    /// gateway's own security events about it; unset follows
+    /// `GATEWAY_SECURITY_EVENTS`
+    pub security_monitoring: Option<bool>,
+    /// Send spans for the run and its execs to `GATEWAY_OTLP_ENDPOINT`
+    pub otlp_spans: bool,
+}
+
+impl Observability {
+    /// Check the options against what the gateway is set up to provide
+    pub fn validate(&self, state: &AppState) -> anyhow::Result<()> {
+        if let Some(interval) = self.stats_interval_secs {
+            if !(1..=MAX_STATS_INTERVAL_SECS).contains(&interval) {
+                anyhow::bail!(
+                    "observability.stats_interval_secs must be between 1 and {}",
+                    MAX_STATS_INTERVAL_SECS
+                );
+            }
+        }
+        if self.capture_stdout && !state.recordings.enabled() {
+            anyhow::bail!("observability.capture_stdout needs session recordings and GATEWAY_SNAPSHOT_VAULT_URL");
+        }
+        if self.security_monitoring == Some(true) && !state.security.configured() {
+            anyhow::bail!("observability.security_monitoring needs GATEWAY_SECURITY_MONITOR_URL");
+        }
+        if self.otlp_spans && state.observer.otlp.is_none() {
+            anyhow::bail!("observability.otlp_spans needs GATEWAY_OTLP_ENDPOINT");
+        }
+        Ok(())
+    }
+}
+
+/// A sandbox's resource usage at one moment
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StatsSample {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub usage: ResourceUsage,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RunStats {
+    pub interval_secs: u64,
+    /// Oldest first; only the most recent samples are kept
+    pub samples: Vec<StatsSample>,
+}
+
+#[derive(Debug)]
+struct Observed {
+    options: Observability,
+    /// Where exec spans go, under the run's span
+    trace: Option<TraceContext>,
+    samples: VecDeque<StatsSample>,
+}
+
+/// Observability of the sandboxes whose runs asked for any, by the ID each
+/// sandbox started with
+#[derive(Debug, Default)]
+pub struct Observer {
+    sandboxes: RwLock<HashMap<Uuid, Observed>>,
+    otlp: Option<OtlpExporter>,
+}
+
+impl Observer {
+    pub fn from_env() -> Self {
+        Self {
+            sandboxes: RwLock::default(),
+            otlp: OtlpExporter::from_env(),
+        }
+    }
+
+    /// Send the span of a run's admission and start, for runs that asked
+    /// for spans. Returns where the spans of its execs go.
+    pub fn run_span(
+        &self,
+        options: &Observability,
+        trace: TraceContext,
+        start: DateTime<Utc>,
+        run_id: Uuid,
+        outcome: Result<Uuid, String>,
+    ) -> Option<TraceContext> {
+        let otlp = self.otlp.as_ref().filter(|_| options.otlp_spans)?;
+        let mut span = Span::new(trace, "sandbox.run", start)
+            .attribute("sandstorm.run_id", run_id.to_string());
+        match outcome {
+            Ok(sandbox_id) => span = span.attribute("sandstorm.sandbox_id", sandbox_id.to_string()),
+            Err(error) => span.error = Some(error),
+        }
+        let execs = span.context.child_of(&span.span_id);
+        otlp.export(span);
+        Some(execs)
+    }
+
+    /// Send the span of an exec in a sandbox whose run asked for spans
+    pub async fn exec_span(
+        &self,
+        sandbox_id: Uuid,
+        command: &[String],
+        start: DateTime<Utc>,
+        outcome: Result<&SandboxResult, String>,
+    ) {
+        let Some(otlp) = &self.otlp else {
+            return;
+        };
+        let Some(trace) = self
+            .sandboxes
+            .read()
+            .await
+            .get(&sandbox_id)
+            .and_then(|observed| observed.trace.clone())
+        else {
+            return;
+        };
+        let mut span = Span::new(trace, "sandbox.exec", start)
+            .attribute("sandstorm.sandbox_id", sandbox_id.to_string())
+            .attribute("sandstorm.command", command.join(" "));
+        match outcome {
+            Ok(result) => {
+                span = span
+                    .attribute("sandstorm.exit_code", result.exit_code)
+                    .attribute("sandstorm.exit_reason", format!("{:?}", result.exit_reason));
+            }
+            Err(error) => span.error = Some(error),
+        }
+        otlp.export(span);
+    }
+
+    /// A sandbox's run's choice of security monitoring, if it made one
+    pub async fn security_monitoring(&self, sandbox_id: Uuid) -> Option<bool> {
+        self.sandboxes
+            .read()
+            .await
+            .get(&sandbox_id)
+            .and_then(|observed| observed.options.security_monitoring)
+    }
+
+    /// Resource samples of a sandbox whose run asked for them
+    pub async fn stats(&self, sandbox_id: Uuid) -> Option<RunStats> {
+        let sandboxes = self.sandboxes.read().await;
+        let observed = sandboxes.get(&sandbox_id)?;
+        Some(RunStats {
+            interval_secs: observed.options.stats_interval_secs?,
+            samples: observed.samples.iter().cloned().collect(),
+        })
+    }
+
+    /// Stop observing a destroyed sandbox
+    pub async fn forget(&self, sandbox_id: Uuid) {
+        self.sandboxes.write().await.remove(&sandbox_id);
+    }
+
+    /// Keep a sample; `false` once the sandbox is no longer observed
+    async fn record(&self, sandbox_id: Uuid, sample: StatsSample) -> bool {
+        let mut sandboxes = self.sandboxes.write().await;
+        let Some(observed) = sandboxes.get_mut(&sandbox_id) else {
+            return false;
+        };
+        if observed.samples.len() == MAX_SAMPLES {
+            observed.samples.pop_front();
+        }
+        observed.samples.push_back(sample);
+        true
+    }
+}
+
+/// Start observing a run's new sandbox as its options ask: sampling its
+/// resource usage and capturing its command's output into `recorder`
+pub async fn watch(
+    state: &AppState,
+    sandbox_id: Uuid,
+    options: Observability,
+    trace: Option<TraceContext>,
+    runtime: Arc<dyn SandboxRuntime>,
+    recorder: Option<Recorder>,
+) {
+    if options == Observability::default() {
+        return;
+    }
+    state.observer.sandboxes.write().await.insert(
+        sandbox_id,
+        Observed {
+            options,
+            trace,
+            samples: VecDeque::new(),
+        },
+    );
+
+    if let Some(interval) = options.stats_interval_secs {
+        let state = state.clone();
+        tokio::spawn(async move {
+            sample(&state, sandbox_id, Duration::from_secs(interval)).await;
+        });
+    }
+    if let Some(recorder) = recorder.filter(|_| options.capture_stdout) {
+        let state = state.clone();
+        tokio::spawn(async move {
+            capture(&state, runtime.as_ref(), sandbox_id, recorder).await;
+        });
+    }
+}
+
+/// Sample a sandbox's resource usage until it stops or is destroyed,
+/// following it through preemption
+async fn sample(state: &AppState, sandbox_id: Uuid, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        // Preempted sandboxes use nothing until they are resumed
+        let Some(current) = state.preemption.locate(sandbox_id).await else {
+            continue;
+        };
+        let Some(runtime) = state.runtime_registry.runtime_of(current).await else {
+            break;
+        };
+        let Ok(status) = runtime.status(current).await else {
+            break;
+        };
+        let sample = StatsSample {
+            at: Utc::now(),
+            usage: status.resource_usage,
+        };
+        if !state.observer.record(sandbox_id, sample).await {
+            break;
+        }
+        if matches!(status.state, SandboxState::Stopped | SandboxState::Failed) {
+            break;
+        }
+    }
+    debug!(%sandbox_id, "Stopped sampling resource usage");
+}
+
+/// Follow a sandbox's output into a recording, stored once the output ends
+async fn capture(
+    state: &AppState,
+    runtime: &dyn SandboxRuntime,
+    sandbox_id: Uuid,
+    mut recorder: Recorder,
+) {
+    let mut output = match runtime.logs(sandbox_id, true).await {
+        Ok(output) => output,
+        Err(e) => {
+            warn!("Failed to capture the output of sandbox {}: {:#}", sandbox_id, e);
+            return;
+        }
+    };
+    let mut buf = vec![0; 8192];
+    let mut captured = 0;
+    loop {
+        match output.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) if captured + n > MAX_CAPTURE_BYTES => {
+                warn!(%sandbox_id, "Captured output reached {} bytes; dropping the rest", MAX_CAPTURE_BYTES);
+                break;
+            }
+            Ok(n) => {
+                recorder.output(&buf[..n]);
+                captured += n;
+            }
+            Err(e) => {
+                warn!("Output of sandbox {} ended early: {}", sandbox_id, e);
+                break;
+            }
+        }
+    }
+    let exit_code = match runtime.status(sandbox_id).await {
+        Ok(status) => status.exit_code,
+        Err(_) => None,
+    };
+    state.recordings.store(recorder, exit_code);
+}
+
+/// Resource samples of a sandbox started with `observability.stats_interval_secs`
+#[utoipa::path(
+    get,
+    path = "/v1/sandboxes/{id}/stats",
+    tag = "sandboxes",
+    params(("id" = Uuid, Path, description = "Sandbox ID")),
+    responses(
+        (status = 200, description = "The sandbox's resource samples", body = RunStats),
+        (status = 404, description = "No such sandbox, or its run didn't ask for samples"),
+    )
+)]
+pub async fn sandbox_stats(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<RunStats>, StatusCode> {
+    state.observer.stats(id).await.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn keeps_the_most_recent_samples() {
+        let observer = Observer::default();
+        let sandbox_id = Uuid::new_v4();
+        let sample = |cpu| StatsSample {
+            at: Utc::now(),
+            usage: ResourceUsage {
+                cpu_usage_seconds: cpu,
+                ..Default::default()
+            },
+        };
+        assert!(!observer.record(sandbox_id, sample(0.0)).await);
+
+        let options = Observability {
+            stats_interval_secs: Some(5),
+            ..Default::default()
+        };
+        observer.sandboxes.write().await.insert(
+            sandbox_id,
+            Observed {
+                options,
+                trace: None,
+                samples: VecDeque::new(),
+            },
+        );
+        for i in 0..MAX_SAMPLES + 10 {
+            assert!(observer.record(sandbox_id, sample(i as f64)).await);
+        }
+        let stats = observer.stats(sandbox_id).await.unwrap();
+        assert_eq!(stats.interval_secs, 5);
+        assert_eq!(stats.samples.len(), MAX_SAMPLES);
+        assert_eq!(stats.samples[0].usage.cpu_usage_seconds, 10.0);
+
+        observer.forget(sandbox_id).await;
+        assert!(observer.stats(sandbox_id).await.is_none());
+        assert!(!observer.record(sandbox_id, sample(0.0)).await);
+    }
+}
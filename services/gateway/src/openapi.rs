@@ -0,0 +1,142 @@
+//! OpenAPI 3 document for the gateway's sandbox API, generated from the
+//! handlers and the types they take and return, for SDKs and the dashboard
+//! to be generated from.
+
+use axum::Json;
+use utoipa::OpenApi;
+
+use crate::runtime::{self, index, lifecycle, posture};
+use crate::{approvals, deletion, exit_snapshot, observability, preemption, scan};
+use sandstorm_types::sandbox::*;
+use sandstorm_types::deletion::{RetainedRecord, ServiceDeletion};
+use sandstorm_types::security::{QuarantineMode, Severity};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Sandstorm Gateway",
+        description = "Runs code in isolated sandboxes on gVisor, Kata, Firecracker and hosted providers"
+    ),
+    paths(
+        crate::health,
+        crate::list_sandboxes,
+        crate::run_sandbox,
+        crate::exec_sandbox,
+        crate::sandbox_status,
+        crate::destroy_sandbox,
+        observability::sandbox_stats,
+        approvals::list_approvals,
+        approvals::get_approval,
+        approvals::approve_run,
+        approvals::reject_run,
+        deletion::create_deletion,
+        deletion::list_deletions,
+        deletion::get_deletion,
+        deletion::retry_deletion,
+        crate::list_runtimes,
+    ),
+    components(schemas(
+        crate::HealthResponse,
+        crate::RunSandboxRequest,
+        crate::MountRequest,
+        crate::RunSandboxResponse,
+        crate::ExecRequest,
+        crate::ExecResponse,
+        crate::SandboxStatusResponse,
+        crate::ListRuntimesResponse,
+        crate::RuntimeInfo,
+        runtime::SandboxResult,
+        runtime::SandboxStatus,
+        runtime::SandboxState,
+        runtime::ResourceUsage,
+        runtime::ExitReason,
+        runtime::ExecOptions,
+        runtime::ExecLimits,
+        lifecycle::Transition,
+        index::IndexedSandbox,
+        posture::Readiness,
+        posture::Check,
+        preemption::Preemption,
+        exit_snapshot::ExitSnapshot,
+        observability::Observability,
+        observability::RunStats,
+        observability::StatsSample,
+        approvals::Approval,
+        approvals::ApprovalStatus,
+        approvals::ApprovalReason,
+        approvals::ApprovalRule,
+        approvals::AuditRecord,
+        approvals::AuditAction,
+        approvals::ApprovalDecision,
+        deletion::CreateDeletionRequest,
+        deletion::DeletionCertificate,
+        deletion::DeletionStatus,
+        deletion::FailedDeletion,
+        ServiceDeletion,
+        RetainedRecord,
+        scan::Finding,
+        scan::RuleCategory,
+        scan::ScanAction,
+        IsolationLevel,
+        RuntimeType,
+        OptimizationHint,
+        Priority,
+        Arch,
+        ExecutionMode,
+        GvisorOptions,
+        GvisorPlatform,
+        GvisorNetwork,
+        FirecrackerOptions,
+        CpuTemplate,
+        QuarantineMode,
+        Severity,
+    )),
+    tags(
+        (name = "sandboxes", description = "Running code in sandboxes"),
+        (name = "approvals", description = "Sensitive runs waiting for an approver"),
+        (name = "deletions", description = "Deleting a tenant's or a sandbox's data from every service"),
+        (name = "runtimes", description = "The runtimes sandboxes can run on"),
+        (name = "gateway", description = "The gateway itself"),
+    )
+)]
+pub struct ApiDoc;
+
+/// The gateway's OpenAPI document
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn refs<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(fields) => {
+                if let Some(Value::String(target)) = fields.get("$ref") {
+                    found.push(target);
+                }
+                fields.values().for_each(|field| refs(field, found));
+            }
+            Value::Array(items) => items.iter().for_each(|item| refs(item, found)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn every_schema_referenced_is_defined() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        assert!(document["paths"]["/v1/sandboxes/run"]["post"].is_object());
+        assert!(document["paths"]["/v1/sandboxes/{id}/status"]["get"].is_object());
+
+        let schemas = &document["components"]["schemas"];
+        let mut found = Vec::new();
+        refs(&document, &mut found);
+        assert!(found.contains(&"#/components/schemas/RunSandboxRequest"));
+        for target in found {
+            let name = target.strip_prefix("#/components/schemas/").unwrap();
+            assert!(schemas.get(name).is_some(), "{} is referenced but not defined", name);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Spans for runs that ask for them, sent to an OpenTelemetry collector as
+//! OTLP/HTTP JSON. Only the few spans the gateway records itself are sent,
+//! one per run and one per exec, so there's no SDK or batching: each span is
+//! posted in the background as it ends.
+
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+const DEFAULT_SERVICE_NAME: &str = "sandstorm-gateway";
+
+/// Where a run's spans go in a trace: the caller's trace when the run
+/// request carried a `traceparent`, else a trace of the run's own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// 32 hex digits
+    pub trace_id: String,
+    /// Span the next span is a child of, 16 hex digits
+    pub parent_span_id: Option<String>,
+}
+
+impl TraceContext {
+    /// Context from a `traceparent` header, or a new trace named after the
+    /// run
+    pub fn from_headers(headers: &HeaderMap, run_id: Uuid) -> Self {
+        let hex = |id: &str, len: usize| {
+            id.len() == len
+                && id.chars().all(|c| c.is_ascii_hexdigit())
+                && id.chars().any(|c| c != '0')
+        };
+        let traceparent = headers
+            .get("traceparent")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                let mut fields = value.trim().split('-').skip(1);
+                Some((fields.next()?, fields.next()?))
+            })
+            .filter(|(trace_id, parent_id)| hex(trace_id, 32) && hex(parent_id, 16));
+        match traceparent {
+            Some((trace_id, parent_id)) => Self {
+                trace_id: trace_id.to_ascii_lowercase(),
+                parent_span_id: Some(parent_id.to_ascii_lowercase()),
+            },
+            None => Self::new(run_id),
+        }
+    }
+
+    /// A new trace named after a run
+    pub fn new(run_id: Uuid) -> Self {
+        Self {
+            trace_id: run_id.simple().to_string(),
+            parent_span_id: None,
+        }
+    }
+
+    /// This context under `span_id`, for spans nested in it
+    pub fn child_of(&self, span_id: &str) -> Self {
+        Self {
+            trace_id: self.trace_id.clone(),
+            parent_span_id: Some(span_id.to_string()),
+        }
+    }
+}
+
+/// A finished span
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub context: TraceContext,
+    pub span_id: String,
+    pub name: &'static str,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub attributes: Vec<(&'static str, Value)>,
+    /// Why the operation failed, if it did
+    pub error: Option<String>,
+}
+
+impl Span {
+    pub fn new(context: TraceContext, name: &'static str, start: DateTime<Utc>) -> Self {
+        Self {
+            context,
+            span_id: new_span_id(),
+            name,
+            start,
+            end: Utc::now(),
+            attributes: Vec::new(),
+            error: None,
+        }
+    }
+
+    pub fn attribute(mut self, key: &'static str, value: impl Into<Value>) -> Self {
+        self.attributes.push((key, value.into()));
+        self
+    }
+
+    /// The span as an OTLP JSON span
+    fn to_otlp(&self) -> Value {
+        let attributes: Vec<Value> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| json!({ "key": key, "value": any_value(value) }))
+            .collect();
+        let nanos = |at: DateTime<Utc>| at.timestamp_nanos_opt().unwrap_or_default().to_string();
+        let mut span = json!({
+            "traceId": self.context.trace_id,
+            "spanId": self.span_id,
+            "name": self.name,
+            // SPAN_KIND_SERVER
+            "kind": 2,
+            "startTimeUnixNano": nanos(self.start),
+            "endTimeUnixNano": nanos(self.end),
+            "attributes": attributes,
+            // STATUS_CODE_OK or STATUS_CODE_ERROR
+            "status": match &self.error {
+                Some(message) => json!({ "code": 2, "message": message }),
+                None => json!({ "code": 1 }),
+            },
+        });
+        if let Some(parent) = &self.context.parent_span_id {
+            span["parentSpanId"] = json!(parent);
+        }
+        span
+    }
+}
+
+/// A random span ID, 16 hex digits
+pub fn new_span_id() -> String {
+    format!("{:016x}", Uuid::new_v4().as_u64_pair().0)
+}
+
+fn any_value(value: &Value) -> Value {
+    match value {
+        Value::Bool(value) => json!({ "boolValue": value }),
+        Value::Number(number) if number.is_i64() || number.is_u64() => {
+            json!({ "intValue": number.to_string() })
+        }
+        Value::Number(number) => json!({ "doubleValue": number.as_f64() }),
+        Value::String(value) => json!({ "stringValue": value }),
+        other => json!({ "stringValue": other.to_string() }),
+    }
+}
+
+/// Posts spans to an OTLP/HTTP collector
+#[derive(Debug, Clone)]
+pub struct OtlpExporter {
+    http: reqwest::Client,
+    traces_url: String,
+    service_name: String,
+}
+
+impl OtlpExporter {
+    /// Exporter for the collector at `GATEWAY_OTLP_ENDPOINT` (its base URL,
+    /// as in `http://otel-collector:4318`), naming the service
+    /// `GATEWAY_OTLP_SERVICE_NAME`; `None` when the endpoint isn't set
+    pub fn from_env() -> Option<Self> {
+        let endpoint = std::env::var("GATEWAY_OTLP_ENDPOINT").ok()?;
+        Some(Self {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            traces_url: format!("{}/v1/traces", endpoint.trim_end_matches('/')),
+            service_name: std::env::var("GATEWAY_OTLP_SERVICE_NAME")
+                .unwrap_or_else(|_| DEFAULT_SERVICE_NAME.to_string()),
+        })
+    }
+
+    /// Send a span in the background
+    pub fn export(&self, span: Span) {
+        let body = json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [{
+                        "key": "service.name",
+                        "value": { "stringValue": self.service_name },
+                    }],
+                },
+                "scopeSpans": [{
+                    "scope": { "name": DEFAULT_SERVICE_NAME, "version": env!("CARGO_PKG_VERSION") },
+                    "spans": [span.to_otlp()],
+                }],
+            }],
+        });
+        let (http, url) = (self.http.clone(), self.traces_url.clone());
+        tokio::spawn(async move {
+            let result = http
+                .post(&url)
+                .json(&body)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            match result {
+                Ok(_) => debug!(trace_id = %span.context.trace_id, span = span.name, "Span exported"),
+                Err(e) => warn!("Failed to export {} span to {}: {}", span.name, url, e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_join_the_callers_trace() {
+        let run_id = Uuid::new_v4();
+        let mut headers = HeaderMap::new();
+        let context = TraceContext::from_headers(&headers, run_id);
+        assert_eq!(context.trace_id, run_id.simple().to_string());
+        assert_eq!(context.parent_span_id, None);
+
+        headers.insert(
+            "traceparent",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01".parse().unwrap(),
+        );
+        let context = TraceContext::from_headers(&headers, run_id);
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.parent_span_id.as_deref(), Some("00f067aa0ba902b7"));
+
+        let span = Span::new(context.child_of("b7ad6b7169203331"), "sandbox.exec", Utc::now())
+            .attribute("sandstorm.exit_code", 0)
+            .attribute("sandstorm.cached", false);
+        let otlp = span.to_otlp();
+        assert_eq!(otlp["parentSpanId"], "b7ad6b7169203331");
+        assert_eq!(otlp["spanId"].as_str().unwrap().len(), 16);
+        assert_eq!(otlp["attributes"][0]["value"]["intValue"], "0");
+        assert_eq!(otlp["status"]["code"], 1);
+
+        // All-zero IDs are invalid and start a trace of the run's own
+        headers.insert(
+            "traceparent",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01".parse().unwrap(),
+        );
+        assert_eq!(TraceContext::from_headers(&headers, run_id).parent_span_id, None);
+    }
+}
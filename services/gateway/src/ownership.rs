@@ -0,0 +1,79 @@
+//! Records which tenant each sandbox was created for and which runtime
+//! hosts it, so services receiving reports about a sandbox, like the
+//! security monitor's event ingest, can check who it belongs to.
+
+use axum::http::HeaderMap;
+use sandstorm_types::sandbox::{RuntimeType, SandboxOwner};
+use sandstorm_types::snapshot::TENANT_HEADER;
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+#[derive(Debug, Default)]
+pub struct SandboxOwners {
+    owners: RwLock<HashMap<Uuid, SandboxOwner>>,
+}
+
+impl SandboxOwners {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn record(&self, sandbox_id: Uuid, runtime_type: RuntimeType, tenant: Option<String>) {
+        self.owners.write().await.insert(
+            sandbox_id,
+            SandboxOwner {
+                sandbox_id,
+                tenant,
+                runtime_type,
+            },
+        );
+    }
+
+    pub async fn get(&self, sandbox_id: Uuid) -> Option<SandboxOwner> {
+        self.owners.read().await.get(&sandbox_id).cloned()
+    }
+
+    /// Stop answering for a destroyed sandbox
+    pub async fn forget(&self, sandbox_id: Uuid) {
+        self.owners.write().await.remove(&sandbox_id);
+    }
+}
+
+/// Tenant the caller acts for, if it named one
+pub fn tenant_from_headers(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(TENANT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|tenant| !tenant.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn forgets_destroyed_sandboxes() {
+        let owners = SandboxOwners::new();
+        let id = Uuid::new_v4();
+        owners.record(id, RuntimeType::Gvisor, Some("acme".to_string())).await;
+
+        let owner = owners.get(id).await.unwrap();
+        assert_eq!(owner.tenant.as_deref(), Some("acme"));
+        assert_eq!(owner.runtime_type, RuntimeType::Gvisor);
+
+        owners.forget(id).await;
+        assert!(owners.get(id).await.is_none());
+    }
+
+    #[test]
+    fn ignores_empty_tenants() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(tenant_from_headers(&headers), None);
+        headers.insert(TENANT_HEADER, "".parse().unwrap());
+        assert_eq!(tenant_from_headers(&headers), None);
+        headers.insert(TENANT_HEADER, "acme".parse().unwrap());
+        assert_eq!(tenant_from_headers(&headers).as_deref(), Some("acme"));
+    }
+}
@@ -0,0 +1,555 @@
+use chrono::{DateTime, Utc};
+use sandstorm_types::sandbox::Priority;
+use sandstorm_types::telemetry::{PreemptionAction, PreemptionEvent};
+use serde::Serialize;
+use utoipa::ToSchema;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::provenance::RunLedger;
+use crate::runtime::capacity::Demand;
+use crate::runtime::{
+    ExitReason, IsolationLevel, ResourceUsage, RuntimeRegistry, RuntimeType, SandboxSnapshot, SandboxState,
+    SandboxStatus,
+};
+
+/// How often preempted sandboxes are checked for room to resume
+const RESUME_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A sandbox the gateway started, with what it needs to decide on preemption
+#[derive(Debug, Clone)]
+struct Admitted {
+    priority: Priority,
+    runtime_type: RuntimeType,
+    isolation_level: IsolationLevel,
+    demand: Demand,
+    admitted_at: DateTime<Utc>,
+}
+
+/// A sandbox that was snapshotted and stopped to make room
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Preemption {
+    pub priority: Priority,
+    pub runtime_type: RuntimeType,
+    /// Priority of the request that needed the capacity
+    pub preempted_by: Priority,
+    pub preempted_at: DateTime<Utc>,
+    pub snapshot_id: Uuid,
+    /// Sandbox restored from the snapshot, once there was room again
+    pub resumed_as: Option<Uuid>,
+    pub resumed_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    isolation_level: IsolationLevel,
+    #[serde(skip)]
+    demand: Demand,
+    #[serde(skip)]
+    admitted_at: DateTime<Utc>,
+    #[serde(skip)]
+    run_id: Option<Uuid>,
+    #[serde(skip)]
+    snapshot: SandboxSnapshot,
+}
+
+impl Preemption {
+    /// Status reported for the sandbox after it was stopped
+    pub fn status(&self, sandbox_id: Uuid) -> SandboxStatus {
+        SandboxStatus {
+            id: sandbox_id,
+            state: SandboxState::Preempted,
+            created_at: self.admitted_at,
+            started_at: Some(self.admitted_at),
+            finished_at: Some(self.preempted_at),
+            exit_code: None,
+            resource_usage: ResourceUsage {
+                cpu_usage_seconds: 0.0,
+                memory_usage_bytes: 0,
+                network_rx_bytes: 0,
+                network_tx_bytes: 0,
+            },
+            exit_reason: Some(ExitReason::Preempted),
+            transitions: Vec::new(),
+        }
+    }
+}
+
+/// Tracks sandbox priorities, preempts low-priority sandboxes when a
+/// higher-priority request finds no capacity, and resumes them later
+#[derive(Debug)]
+pub struct Preemptor {
+    http: reqwest::Client,
+    telemetry_url: Option<String>,
+    running: RwLock<HashMap<Uuid, Admitted>>,
+    /// Keyed by the sandbox that was preempted
+    preempted: RwLock<HashMap<Uuid, Preemption>>,
+    /// Resumed sandbox to the sandbox it replaces
+    resumed_from: RwLock<HashMap<Uuid, Uuid>>,
+    /// Held while capacity freed by a preemption is being claimed, so the
+    /// resume loop doesn't hand it straight back to the preempted sandbox
+    admission: Mutex<()>,
+}
+
+impl Preemptor {
+    pub fn new(telemetry_url: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .unwrap_or_default(),
+            telemetry_url: telemetry_url.map(|url| url.trim_end_matches('/').to_string()),
+            running: RwLock::new(HashMap::new()),
+            preempted: RwLock::new(HashMap::new()),
+            resumed_from: RwLock::new(HashMap::new()),
+            admission: Mutex::new(()),
+        }
+    }
+
+    /// Events are reported to the collector at `GATEWAY_TELEMETRY_URL`
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("GATEWAY_TELEMETRY_URL").ok())
+    }
+
+    pub async fn admit(
+        &self,
+        sandbox_id: Uuid,
+        priority: Priority,
+        runtime_type: RuntimeType,
+        isolation_level: IsolationLevel,
+        demand: Demand,
+    ) {
+        self.running.write().await.insert(
+            sandbox_id,
+            Admitted {
+                priority,
+                runtime_type,
+                isolation_level,
+                demand,
+                admitted_at: Utc::now(),
+            },
+        );
+    }
+
+    /// Stop tracking a destroyed sandbox. Returns whether it was waiting to
+    /// be resumed, in which case it will no longer be.
+    pub async fn release(&self, sandbox_id: Uuid) -> bool {
+        self.running.write().await.remove(&sandbox_id);
+        self.resumed_from.write().await.remove(&sandbox_id);
+        self.preempted
+            .write()
+            .await
+            .remove(&sandbox_id)
+            .is_some_and(|preemption| preemption.resumed_as.is_none())
+    }
+
+    pub async fn priority(&self, sandbox_id: Uuid) -> Option<Priority> {
+        if let Some(admitted) = self.running.read().await.get(&sandbox_id) {
+            return Some(admitted.priority);
+        }
+        self.preempted
+            .read()
+            .await
+            .get(&sandbox_id)
+            .map(|preemption| preemption.priority)
+    }
+
+    pub async fn preemption(&self, sandbox_id: Uuid) -> Option<Preemption> {
+        self.preempted.read().await.get(&sandbox_id).cloned()
+    }
+
+    /// Preempted sandbox a resumed sandbox stands in for
+    pub async fn resumed_from(&self, sandbox_id: Uuid) -> Option<Uuid> {
+        self.resumed_from.read().await.get(&sandbox_id).copied()
+    }
+
+    /// Sandbox currently running in place of `sandbox_id`, following resumes;
+    /// `None` while it is preempted and waiting for room
+    pub async fn locate(&self, mut sandbox_id: Uuid) -> Option<Uuid> {
+        let preempted = self.preempted.read().await;
+        while let Some(preemption) = preempted.get(&sandbox_id) {
+            sandbox_id = preemption.resumed_as?;
+        }
+        Some(sandbox_id)
+    }
+
+    /// Hold while claiming capacity a preemption freed
+    pub async fn lock_admission(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.admission.lock().await
+    }
+
+    /// Snapshot and stop the lowest-priority local sandbox below `priority`
+    /// that could make room for a request at `isolation_level`. The most
+    /// recently started candidate goes first, as it has the least work to lose.
+    pub async fn preempt_for(
+        &self,
+        registry: &RuntimeRegistry,
+        run_ledger: &RunLedger,
+        priority: Priority,
+        isolation_level: IsolationLevel,
+    ) -> bool {
+        let mut candidates: Vec<(Uuid, Admitted)> = self
+            .running
+            .read()
+            .await
+            .iter()
+            .filter(|(_, admitted)| admitted.priority < priority)
+            .map(|(id, admitted)| (*id, admitted.clone()))
+            .collect();
+        candidates.sort_by(|(_, a), (_, b)| {
+            a.priority
+                .cmp(&b.priority)
+                .then(b.admitted_at.cmp(&a.admitted_at))
+        });
+
+        for (sandbox_id, admitted) in candidates {
+            let Ok(runtime) = registry.get(admitted.runtime_type).await else {
+                continue;
+            };
+            if runtime.is_remote() || !runtime.supports_isolation_level(isolation_level) {
+                continue;
+            }
+
+            let mut snapshot = match runtime.snapshot(sandbox_id).await {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    warn!("Failed to snapshot sandbox {} for preemption: {}", sandbox_id, e);
+                    continue;
+                }
+            };
+            let run_id = run_ledger.get(sandbox_id).await;
+            if let Some(run_id) = run_id {
+                snapshot
+                    .metadata
+                    .insert("run_id".to_string(), serde_json::json!(run_id));
+            }
+            if let Err(e) = runtime.destroy(sandbox_id).await {
+                warn!("Failed to stop sandbox {} for preemption: {}", sandbox_id, e);
+                continue;
+            }
+            registry.release(sandbox_id).await;
+
+            info!(
+                %sandbox_id,
+                priority = ?admitted.priority,
+                preempted_by = ?priority,
+                "Preempted sandbox"
+            );
+            let preemption = Preemption {
+                priority: admitted.priority,
+                runtime_type: admitted.runtime_type,
+                preempted_by: priority,
+                preempted_at: Utc::now(),
+                snapshot_id: snapshot.id,
+                resumed_as: None,
+                resumed_at: None,
+                isolation_level: admitted.isolation_level,
+                demand: admitted.demand,
+                admitted_at: admitted.admitted_at,
+                run_id,
+                snapshot,
+            };
+            self.report(sandbox_id, &preemption, PreemptionAction::Preempted);
+            self.running.write().await.remove(&sandbox_id);
+            self.preempted.write().await.insert(sandbox_id, preemption);
+            return true;
+        }
+
+        false
+    }
+
+    /// Resume preempted sandboxes, highest priority and longest waiting
+    /// first, while their runtimes have room
+    async fn resume_pending(&self, registry: &RuntimeRegistry, run_ledger: &RunLedger) {
+        let _admission = self.admission.lock().await;
+
+        let mut pending: Vec<(Uuid, Preemption)> = self
+            .preempted
+            .read()
+            .await
+            .iter()
+            .filter(|(_, preemption)| preemption.resumed_as.is_none())
+            .map(|(id, preemption)| (*id, preemption.clone()))
+            .collect();
+        pending.sort_by(|(_, a), (_, b)| {
+            b.priority
+                .cmp(&a.priority)
+                .then(a.preempted_at.cmp(&b.preempted_at))
+        });
+
+        for (sandbox_id, preemption) in pending {
+            if !registry
+                .has_room(preemption.runtime_type, preemption.demand)
+                .await
+            {
+                continue;
+            }
+            let Ok(runtime) = registry.get(preemption.runtime_type).await else {
+                continue;
+            };
+            if !registry
+                .reserve(preemption.snapshot_id, &runtime, preemption.demand)
+                .await
+            {
+                continue;
+            }
+
+            let resumed_id = match runtime.resume(&preemption.snapshot).await {
+                Ok(id) => id,
+                Err(e) => {
+                    registry.release(preemption.snapshot_id).await;
+                    warn!("Failed to resume preempted sandbox {}: {}", sandbox_id, e);
+                    continue;
+                }
+            };
+            registry.rekey(preemption.snapshot_id, resumed_id).await;
+            if let Some(run_id) = preemption.run_id {
+                run_ledger.assign(resumed_id, run_id).await;
+            }
+            self.admit(
+                resumed_id,
+                preemption.priority,
+                preemption.runtime_type,
+                preemption.isolation_level,
+                preemption.demand,
+            )
+            .await;
+            self.resumed_from.write().await.insert(resumed_id, sandbox_id);
+
+            let mut preempted = self.preempted.write().await;
+            let Some(entry) = preempted.get_mut(&sandbox_id) else {
+                // Destroyed while it was being resumed
+                drop(preempted);
+                self.release(resumed_id).await;
+                if let Err(e) = runtime.destroy(resumed_id).await {
+                    warn!("Failed to destroy resumed sandbox {}: {}", resumed_id, e);
+                }
+                registry.release(resumed_id).await;
+                continue;
+            };
+            entry.resumed_as = Some(resumed_id);
+            entry.resumed_at = Some(Utc::now());
+            info!(%sandbox_id, %resumed_id, "Resumed preempted sandbox");
+            self.report(sandbox_id, entry, PreemptionAction::Resumed);
+        }
+    }
+
+    /// Send a preemption event to the telemetry collector in the background
+    fn report(&self, sandbox_id: Uuid, preemption: &Preemption, action: PreemptionAction) {
+        let Some(telemetry_url) = self.telemetry_url.clone() else {
+            return;
+        };
+        let provider = serde_json::to_value(preemption.runtime_type)
+            .ok()
+            .and_then(|value| value.as_str().map(str::to_string))
+            .unwrap_or_default();
+        let event = PreemptionEvent {
+            id: Uuid::new_v4(),
+            sandbox_id,
+            run_id: preemption.run_id,
+            provider,
+            priority: preemption.priority,
+            action,
+            preempted_by: (action == PreemptionAction::Preempted).then_some(preemption.preempted_by),
+            resumed_as: preemption.resumed_as,
+            snapshot_id: preemption.snapshot_id,
+            created_at: Utc::now(),
+        };
+        let http = self.http.clone();
+
+        tokio::spawn(async move {
+            let result = http
+                .post(format!("{}/api/telemetry/preemptions", telemetry_url))
+                .json(&event)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                warn!("Failed to report preemption of {}: {}", event.sandbox_id, e);
+            }
+        });
+    }
+}
+
+/// Start the loop that resumes preempted sandboxes
+pub fn spawn(
+    preemptor: std::sync::Arc<Preemptor>,
+    registry: std::sync::Arc<RuntimeRegistry>,
+    run_ledger: std::sync::Arc<RunLedger>,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RESUME_INTERVAL);
+        loop {
+            interval.tick().await;
+            preemptor.resume_pending(&registry, &run_ledger).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{ExecOptions, SandboxConfig, SandboxResult, SandboxRuntime};
+    use async_trait::async_trait;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    /// Local runtime that only keeps track of which sandboxes exist
+    #[derive(Default)]
+    struct CountingRuntime {
+        sandboxes: std::sync::Mutex<HashSet<Uuid>>,
+    }
+
+    impl CountingRuntime {
+        fn start(&self) -> Uuid {
+            let id = Uuid::new_v4();
+            self.sandboxes.lock().unwrap().insert(id);
+            id
+        }
+    }
+
+    #[async_trait]
+    impl SandboxRuntime for CountingRuntime {
+        fn runtime_type(&self) -> RuntimeType {
+            RuntimeType::Gvisor
+        }
+
+        fn supports_isolation_level(&self, level: IsolationLevel) -> bool {
+            level == IsolationLevel::Standard
+        }
+
+        async fn create(&self, _: &SandboxConfig) -> anyhow::Result<Uuid> {
+            Ok(self.start())
+        }
+
+        async fn exec(
+            &self,
+            _: Uuid,
+            _: Vec<String>,
+            _: Option<HashMap<String, String>>,
+            _: &ExecOptions,
+        ) -> anyhow::Result<SandboxResult> {
+            anyhow::bail!("counting runtime does not run commands")
+        }
+
+        async fn destroy(&self, sandbox_id: Uuid) -> anyhow::Result<()> {
+            self.sandboxes.lock().unwrap().remove(&sandbox_id);
+            Ok(())
+        }
+
+        async fn snapshot(&self, sandbox_id: Uuid) -> anyhow::Result<SandboxSnapshot> {
+            Ok(SandboxSnapshot {
+                id: Uuid::new_v4(),
+                sandbox_id,
+                runtime_type: RuntimeType::Gvisor,
+                timestamp: Utc::now(),
+                filesystem_state: Vec::new(),
+                memory_state: None,
+                metadata: HashMap::new(),
+            })
+        }
+
+        async fn resume(&self, _: &SandboxSnapshot) -> anyhow::Result<Uuid> {
+            Ok(self.start())
+        }
+
+        async fn status(&self, _: Uuid) -> anyhow::Result<SandboxStatus> {
+            anyhow::bail!("counting runtime does not track status")
+        }
+
+        async fn logs(
+            &self,
+            _: Uuid,
+            _: bool,
+        ) -> anyhow::Result<Box<dyn tokio::io::AsyncRead + Send + Unpin>> {
+            anyhow::bail!("counting runtime keeps no logs")
+        }
+
+        async fn active_sandboxes(&self) -> usize {
+            self.sandboxes.lock().unwrap().len()
+        }
+    }
+
+    #[tokio::test]
+    async fn preempts_lower_priority_and_resumes_when_room_frees() {
+        let runtime = Arc::new(CountingRuntime::default());
+        let registry = RuntimeRegistry::new().with_local_limit(Some(1));
+        registry.register(runtime.clone()).await.unwrap();
+        let ledger = RunLedger::new();
+        let preemptor = Preemptor::new(None);
+
+        let low = runtime.start();
+        let run_id = Uuid::new_v4();
+        ledger.assign(low, run_id).await;
+        preemptor
+            .admit(
+                low,
+                Priority::Low,
+                RuntimeType::Gvisor,
+                IsolationLevel::Standard,
+                Demand::default(),
+            )
+            .await;
+
+        // Equal priority never preempts
+        assert!(
+            !preemptor
+                .preempt_for(&registry, &ledger, Priority::Low, IsolationLevel::Standard)
+                .await
+        );
+        assert!(
+            preemptor
+                .preempt_for(&registry, &ledger, Priority::High, IsolationLevel::Standard)
+                .await
+        );
+        assert_eq!(preemptor.locate(low).await, None);
+        let preemption = preemptor.preemption(low).await.unwrap();
+        assert_eq!(preemption.status(low).state, SandboxState::Preempted);
+        assert_eq!(preemption.preempted_by, Priority::High);
+
+        // The high-priority sandbox takes the slot, so nothing resumes yet
+        let high = runtime.start();
+        preemptor.resume_pending(&registry, &ledger).await;
+        assert_eq!(preemptor.locate(low).await, None);
+
+        runtime.destroy(high).await.unwrap();
+        preemptor.release(high).await;
+        preemptor.resume_pending(&registry, &ledger).await;
+
+        let resumed = preemptor.locate(low).await.unwrap();
+        assert_ne!(resumed, low);
+        assert_eq!(preemptor.resumed_from(resumed).await, Some(low));
+        assert_eq!(preemptor.priority(resumed).await, Some(Priority::Low));
+        assert_eq!(ledger.get(resumed).await, Some(run_id));
+    }
+
+    #[tokio::test]
+    async fn releasing_a_preempted_sandbox_cancels_its_resume() {
+        let runtime = Arc::new(CountingRuntime::default());
+        let registry = RuntimeRegistry::new().with_local_limit(Some(1));
+        registry.register(runtime.clone()).await.unwrap();
+        let ledger = RunLedger::new();
+        let preemptor = Preemptor::new(None);
+
+        let low = runtime.start();
+        preemptor
+            .admit(
+                low,
+                Priority::Low,
+                RuntimeType::Gvisor,
+                IsolationLevel::Standard,
+                Demand::default(),
+            )
+            .await;
+        assert!(
+            preemptor
+                .preempt_for(&registry, &ledger, Priority::Normal, IsolationLevel::Standard)
+                .await
+        );
+
+        assert!(preemptor.release(low).await);
+        preemptor.resume_pending(&registry, &ledger).await;
+        assert_eq!(runtime.active_sandboxes().await, 0);
+        assert!(preemptor.preemption(low).await.is_none());
+    }
+}
@@ -0,0 +1,283 @@
+use axum::http::HeaderMap;
+use sandstorm_types::provenance::{RunProvenance, RUN_ID_HEADER};
+use sandstorm_types::security::{SecurityEvent, Severity};
+use sandstorm_types::telemetry::SandboxRun;
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Deserialize, Serialize,
+};
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Maps sandboxes to the gateway run they belong to
+#[derive(Debug, Default)]
+pub struct RunLedger {
+    runs: RwLock<HashMap<Uuid, Uuid>>,
+}
+
+impl RunLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn assign(&self, sandbox_id: Uuid, run_id: Uuid) {
+        self.runs.write().await.insert(sandbox_id, run_id);
+    }
+
+    pub async fn get(&self, sandbox_id: Uuid) -> Option<Uuid> {
+        self.runs.read().await.get(&sandbox_id).copied()
+    }
+}
+
+/// Run ID supplied by the caller, if any
+pub fn run_id_from_headers(headers: &HeaderMap) -> Option<Uuid> {
+    headers
+        .get(RUN_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+/// A sandbox's risk, as the security monitor scores it
+#[derive(Debug, Clone, Serialize)]
+pub struct RiskSummary {
+    /// 0 (quiet) to 100 (quarantined or under sustained attack)
+    pub risk_score: f64,
+    pub window_hours: u32,
+    /// Events within the window, by severity
+    pub recent_events: HashMap<Severity, u64>,
+    pub open_alerts: usize,
+    pub quarantined: bool,
+}
+
+/// The parts of the security monitor's sandbox posture a risk summary uses
+#[derive(Deserialize)]
+struct Posture {
+    risk_score: f64,
+    window_hours: u32,
+    recent_events: HashMap<Severity, u64>,
+    open_alerts: Vec<IgnoredAny>,
+    quarantine: Option<IgnoredAny>,
+}
+
+impl From<Posture> for RiskSummary {
+    fn from(posture: Posture) -> Self {
+        Self {
+            risk_score: posture.risk_score,
+            window_hours: posture.window_hours,
+            recent_events: posture.recent_events,
+            open_alerts: posture.open_alerts.len(),
+            quarantined: posture.quarantine.is_some(),
+        }
+    }
+}
+
+/// What the security monitor and telemetry collector currently know about
+/// a sandbox
+#[derive(Debug, Clone)]
+pub struct SandboxOverview {
+    pub risk: Option<RiskSummary>,
+    /// Newest first
+    pub security_events: Vec<SecurityEvent>,
+    pub runs: Vec<SandboxRun>,
+    pub unavailable: Vec<String>,
+}
+
+/// Queries the telemetry collector, security monitor and snapshot vault for
+/// everything they recorded about a sandbox
+#[derive(Debug, Clone)]
+pub struct ProvenanceClient {
+    http: reqwest::Client,
+    telemetry_url: Option<String>,
+    security_monitor_url: Option<String>,
+    snapshot_vault_url: Option<String>,
+}
+
+impl ProvenanceClient {
+    /// Service URLs come from `GATEWAY_TELEMETRY_URL`,
+    /// `GATEWAY_SECURITY_MONITOR_URL` and `GATEWAY_SNAPSHOT_VAULT_URL`.
+    /// Unset services are reported as unavailable in lookups.
+    pub fn from_env() -> Self {
+        let url = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .map(|value| value.trim_end_matches('/').to_string())
+        };
+
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            telemetry_url: url("GATEWAY_TELEMETRY_URL"),
+            security_monitor_url: url("GATEWAY_SECURITY_MONITOR_URL"),
+            snapshot_vault_url: url("GATEWAY_SNAPSHOT_VAULT_URL"),
+        }
+    }
+
+    pub async fn lookup(&self, sandbox_id: Uuid, run_id: Option<Uuid>) -> RunProvenance {
+        let sandbox = sandbox_id.to_string();
+        let by_sandbox = [("sandbox_id", sandbox.as_str())];
+        let quarantine_query = [("sandbox_id", sandbox.as_str()), ("include_released", "true")];
+        let events_query = [("sandbox_id", sandbox.as_str()), ("limit", "1000")];
+
+        let (runs, security_events, quarantines, snapshots) = tokio::join!(
+            self.fetch("telemetry-collector", &self.telemetry_url, "/api/telemetry/runs", &by_sandbox),
+            self.fetch("security-monitor", &self.security_monitor_url, "/api/events", &events_query),
+            self.fetch("security-monitor", &self.security_monitor_url, "/api/quarantine", &quarantine_query),
+            self.fetch("snapshot-vault", &self.snapshot_vault_url, "/v1/snapshots", &by_sandbox),
+        );
+
+        let mut unavailable = Vec::new();
+        RunProvenance {
+            sandbox_id: sandbox,
+            run_id,
+            runs: or_unavailable(runs, &mut unavailable),
+            security_events: or_unavailable(security_events, &mut unavailable),
+            quarantines: or_unavailable(quarantines, &mut unavailable),
+            snapshots: or_unavailable(snapshots, &mut unavailable),
+            unavailable,
+        }
+    }
+
+    /// The sandbox's risk score, its `events` latest security events, and
+    /// its run history
+    pub async fn overview(&self, sandbox_id: Uuid, events: usize, window_hours: u32) -> SandboxOverview {
+        let sandbox = sandbox_id.to_string();
+        let by_sandbox = [("sandbox_id", sandbox.as_str())];
+        let events = events.to_string();
+        let events_query = [("sandbox_id", sandbox.as_str()), ("limit", events.as_str())];
+        let window_hours = window_hours.to_string();
+        let posture_query = [("window_hours", window_hours.as_str())];
+        let posture_path = format!("/api/sandboxes/{}/posture", sandbox);
+
+        let (posture, security_events, runs) = tokio::join!(
+            self.fetch::<Posture>("security-monitor", &self.security_monitor_url, &posture_path, &posture_query),
+            self.fetch("security-monitor", &self.security_monitor_url, "/api/events", &events_query),
+            self.fetch("telemetry-collector", &self.telemetry_url, "/api/telemetry/runs", &by_sandbox),
+        );
+
+        let mut unavailable = Vec::new();
+        let risk = match posture {
+            Ok(posture) => Some(posture.into()),
+            Err(service) => {
+                unavailable.push(service.to_string());
+                None
+            }
+        };
+        SandboxOverview {
+            risk,
+            security_events: or_unavailable(security_events, &mut unavailable),
+            runs: or_unavailable(runs, &mut unavailable),
+            unavailable,
+        }
+    }
+
+    async fn fetch<T: DeserializeOwned>(
+        &self,
+        service: &'static str,
+        base_url: &Option<String>,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, &'static str> {
+        let Some(base_url) = base_url else {
+            return Err(service);
+        };
+
+        let response = self
+            .http
+            .get(format!("{}{}", base_url, path))
+            .query(query)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        match response {
+            Ok(response) => response.json().await.map_err(|e| {
+                warn!("Invalid provenance response from {}: {}", service, e);
+                service
+            }),
+            Err(e) => {
+                warn!("Provenance lookup against {} failed: {}", service, e);
+                Err(service)
+            }
+        }
+    }
+}
+
+fn or_unavailable<T>(result: Result<Vec<T>, &'static str>, unavailable: &mut Vec<String>) -> Vec<T> {
+    result.unwrap_or_else(|service| {
+        if !unavailable.iter().any(|name| name == service) {
+            unavailable.push(service.to_string());
+        }
+        Vec::new()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lookup_reports_unconfigured_services() {
+        let client = ProvenanceClient {
+            http: reqwest::Client::new(),
+            telemetry_url: None,
+            security_monitor_url: None,
+            snapshot_vault_url: None,
+        };
+
+        let sandbox_id = Uuid::new_v4();
+        let run_id = Uuid::new_v4();
+        let provenance = client.lookup(sandbox_id, Some(run_id)).await;
+
+        assert_eq!(provenance.sandbox_id, sandbox_id.to_string());
+        assert_eq!(provenance.run_id, Some(run_id));
+        assert!(provenance.runs.is_empty());
+        assert_eq!(
+            provenance.unavailable,
+            vec!["telemetry-collector", "security-monitor", "snapshot-vault"]
+        );
+    }
+
+    #[tokio::test]
+    async fn overview_summarizes_posture_and_reports_unconfigured_services() {
+        let posture: Posture = serde_json::from_value(serde_json::json!({
+            "sandbox_id": "sandbox",
+            "monitoring": null,
+            "active_rules": [],
+            "window_hours": 24,
+            "recent_events": {"high": 2, "low": 5},
+            "risk_score": 41.5,
+            "open_alerts": [{"id": "alert_1"}, {"id": "alert_2"}],
+            "quarantine": null
+        }))
+        .unwrap();
+        let risk = RiskSummary::from(posture);
+        assert_eq!(risk.risk_score, 41.5);
+        assert_eq!(risk.recent_events[&Severity::High], 2);
+        assert_eq!(risk.open_alerts, 2);
+        assert!(!risk.quarantined);
+
+        let client = ProvenanceClient {
+            http: reqwest::Client::new(),
+            telemetry_url: None,
+            security_monitor_url: None,
+            snapshot_vault_url: None,
+        };
+        let overview = client.overview(Uuid::new_v4(), 20, 24).await;
+        assert!(overview.risk.is_none());
+        assert_eq!(overview.unavailable, vec!["security-monitor", "telemetry-collector"]);
+    }
+
+    #[tokio::test]
+    async fn ledger_tracks_runs_per_sandbox() {
+        let ledger = RunLedger::new();
+        let (sandbox_id, run_id) = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert_eq!(ledger.get(sandbox_id).await, None);
+        ledger.assign(sandbox_id, run_id).await;
+        assert_eq!(ledger.get(sandbox_id).await, Some(run_id));
+    }
+}
@@ -0,0 +1,281 @@
+//! Enforces the quarantines the security monitor places on sandboxes.
+//! Network modes filter the sandbox's host interface with nftables, and
+//! `freeze` also pauses the sandbox in its runtime.
+
+use anyhow::{bail, Context, Result};
+use sandstorm_types::security::{QuarantineEnforcement, QuarantineMode};
+use std::collections::HashMap;
+use tokio::{
+    process::Command,
+    sync::{Mutex, OnceCell},
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::runtime::SandboxRuntime;
+
+/// nftables table holding the quarantine filters
+const TABLE: &str = "sandstorm_quarantine";
+
+/// Filters for the `bridge` family. Sandbox interfaces are bridge ports, so
+/// filtering by port catches traffic to other sandboxes and to the host
+/// alike. Replacing the table on startup drops filters a previous gateway
+/// left behind along with its quarantine state.
+fn ruleset() -> String {
+    format!(
+        r#"table bridge {table}
+delete table bridge {table}
+table bridge {table} {{
+    set egress_blocked {{ type ifname; }}
+    set isolated {{ type ifname; }}
+    chain forward {{
+        type filter hook forward priority -10; policy accept;
+        iifname @isolated drop
+        oifname @isolated drop
+        iifname @egress_blocked ct state established,related accept
+        iifname @egress_blocked drop
+    }}
+    chain input {{
+        type filter hook input priority -10; policy accept;
+        iifname @isolated drop
+        iifname @egress_blocked ct state established,related accept
+        iifname @egress_blocked drop
+    }}
+    chain output {{
+        type filter hook output priority -10; policy accept;
+        oifname @isolated drop
+    }}
+}}
+"#,
+        table = TABLE
+    )
+}
+
+/// Set of interfaces a mode puts the sandbox's interface in
+fn filter_set(mode: QuarantineMode) -> Option<&'static str> {
+    match mode {
+        QuarantineMode::ObserveOnly => None,
+        QuarantineMode::BlockEgress => Some("egress_blocked"),
+        QuarantineMode::BlockAllNetwork | QuarantineMode::Freeze => Some("isolated"),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Enforced {
+    quarantine_id: String,
+    mode: QuarantineMode,
+    /// Interface filtered for the quarantine, if any
+    interface: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct QuarantineEnforcer {
+    /// Quarantines in force, by sandbox
+    active: Mutex<HashMap<Uuid, Enforced>>,
+    ruleset: OnceCell<()>,
+}
+
+impl QuarantineEnforcer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Put a sandbox in a quarantine mode, replacing any mode it was in
+    pub async fn apply(
+        &self,
+        sandbox_id: Uuid,
+        runtime: &dyn SandboxRuntime,
+        enforcement: QuarantineEnforcement,
+    ) -> Result<()> {
+        let mut active = self.active.lock().await;
+        if let Some(previous) = active.remove(&sandbox_id) {
+            self.undo(sandbox_id, runtime, &previous).await?;
+        }
+
+        let mode = enforcement.mode;
+        let mut interface = None;
+        if let Some(set) = filter_set(mode) {
+            match runtime.network_interface(sandbox_id).await {
+                Some(name) => {
+                    self.update_set("add", set, &name).await?;
+                    interface = Some(name);
+                }
+                // A frozen sandbox sends nothing anyway
+                None if mode == QuarantineMode::Freeze => {}
+                None => bail!(
+                    "{:?} sandboxes have no network interface the gateway can filter; use observe_only or freeze",
+                    runtime.runtime_type()
+                ),
+            }
+        }
+
+        let enforced = Enforced {
+            quarantine_id: enforcement.quarantine_id,
+            mode,
+            interface,
+        };
+        if mode == QuarantineMode::Freeze {
+            if let Err(e) = runtime.set_frozen(sandbox_id, true).await {
+                let mut unfrozen = enforced;
+                unfrozen.mode = QuarantineMode::BlockAllNetwork;
+                self.undo(sandbox_id, runtime, &unfrozen).await?;
+                return Err(e);
+            }
+        }
+
+        info!(
+            %sandbox_id,
+            quarantine_id = %enforced.quarantine_id,
+            mode = %mode,
+            "Quarantine enforced"
+        );
+        active.insert(sandbox_id, enforced);
+        Ok(())
+    }
+
+    /// Let a sandbox out of quarantine. False if it wasn't in one.
+    pub async fn lift(&self, sandbox_id: Uuid, runtime: &dyn SandboxRuntime) -> Result<bool> {
+        let mut active = self.active.lock().await;
+        let Some(enforced) = active.remove(&sandbox_id) else {
+            return Ok(false);
+        };
+        if let Err(e) = self.undo(sandbox_id, runtime, &enforced).await {
+            active.insert(sandbox_id, enforced);
+            return Err(e);
+        }
+
+        info!(%sandbox_id, quarantine_id = %enforced.quarantine_id, "Quarantine lifted");
+        Ok(true)
+    }
+
+    /// Drop the quarantine of a sandbox that was destroyed
+    pub async fn forget(&self, sandbox_id: Uuid) {
+        let Some(enforced) = self.active.lock().await.remove(&sandbox_id) else {
+            return;
+        };
+        if let (Some(set), Some(interface)) = (filter_set(enforced.mode), &enforced.interface) {
+            if let Err(e) = self.update_set("delete", set, interface).await {
+                warn!(%sandbox_id, "Failed to remove quarantine filter: {:#}", e);
+            }
+        }
+    }
+
+    /// Mode a sandbox is quarantined in, if any
+    pub async fn mode(&self, sandbox_id: Uuid) -> Option<QuarantineMode> {
+        self.active.lock().await.get(&sandbox_id).map(|enforced| enforced.mode)
+    }
+
+    async fn undo(&self, sandbox_id: Uuid, runtime: &dyn SandboxRuntime, enforced: &Enforced) -> Result<()> {
+        if enforced.mode == QuarantineMode::Freeze {
+            runtime.set_frozen(sandbox_id, false).await?;
+        }
+        if let (Some(set), Some(interface)) = (filter_set(enforced.mode), &enforced.interface) {
+            self.update_set("delete", set, interface).await?;
+        }
+        Ok(())
+    }
+
+    /// Add an interface to a filter set, or delete it from one
+    async fn update_set(&self, operation: &str, set: &str, interface: &str) -> Result<()> {
+        self.ruleset
+            .get_or_try_init(|| async { nft(&["-f", "-"], Some(&ruleset())).await })
+            .await?;
+        let element = format!("{{ \"{}\" }}", interface);
+        nft(&[operation, "element", "bridge", TABLE, set, &element], None).await
+    }
+}
+
+/// Run `nft`, failing with its error output
+async fn nft(args: &[&str], stdin: Option<&str>) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut child = Command::new("nft")
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .context("Failed to run nft")?;
+    if let Some(mut pipe) = child.stdin.take() {
+        pipe.write_all(stdin.unwrap_or_default().as_bytes()).await?;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!(
+            "nft {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{
+        mock::{MockBehavior, MockRuntime},
+        ExecutionMode, IsolationLevel, SandboxConfig, SandboxState,
+    };
+
+    fn enforcement(mode: QuarantineMode) -> QuarantineEnforcement {
+        QuarantineEnforcement {
+            quarantine_id: "quarantine_1".to_string(),
+            mode,
+        }
+    }
+
+    #[tokio::test]
+    async fn freezes_and_thaws_sandboxes() {
+        let runtime = MockRuntime::new(MockBehavior {
+            delay_ms: 60_000,
+            ..Default::default()
+        });
+        let config = SandboxConfig {
+            id: Uuid::new_v4(),
+            image: "sandstorm/python".to_string(),
+            command: vec!["python".to_string(), "main.py".to_string()],
+            environment: HashMap::new(),
+            cpu_limit: None,
+            memory_limit: None,
+            timeout: None,
+            isolation_level: IsolationLevel::Standard,
+            runtime_preference: None,
+            working_dir: None,
+            mounts: Vec::new(),
+            execution_mode: ExecutionMode::Standard,
+            scratch_size: None,
+            sysctls: HashMap::new(),
+            gvisor: Default::default(),
+            firecracker: Default::default(),
+            arch: None,
+            rootfs_layers: Vec::new(),
+            ttl_seconds: None,
+        };
+        let id = runtime.create(&config).await.unwrap();
+        let enforcer = QuarantineEnforcer::new();
+
+        enforcer.apply(id, &runtime, enforcement(QuarantineMode::ObserveOnly)).await.unwrap();
+        assert_eq!(enforcer.mode(id).await, Some(QuarantineMode::ObserveOnly));
+        assert_eq!(runtime.status(id).await.unwrap().state, SandboxState::Running);
+
+        // The mock runtime has no interface to filter
+        assert!(enforcer.apply(id, &runtime, enforcement(QuarantineMode::BlockEgress)).await.is_err());
+        assert_eq!(enforcer.mode(id).await, None);
+
+        enforcer.apply(id, &runtime, enforcement(QuarantineMode::Freeze)).await.unwrap();
+        assert_eq!(runtime.status(id).await.unwrap().state, SandboxState::Paused);
+
+        assert!(enforcer.lift(id, &runtime).await.unwrap());
+        assert_eq!(runtime.status(id).await.unwrap().state, SandboxState::Running);
+        assert!(!enforcer.lift(id, &runtime).await.unwrap());
+    }
+
+    #[test]
+    fn network_modes_pick_filter_sets() {
+        assert_eq!(filter_set(QuarantineMode::ObserveOnly), None);
+        assert_eq!(filter_set(QuarantineMode::BlockEgress), Some("egress_blocked"));
+        assert_eq!(filter_set(QuarantineMode::Freeze), Some("isolated"));
+        assert!(ruleset().contains("iifname @egress_blocked ct state established,related accept"));
+    }
+}
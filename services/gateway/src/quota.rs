@@ -0,0 +1,242 @@
+//! Admission against tenants' monthly quotas, which the telemetry collector
+//! keeps along with the usage they're measured against. Quotas are checked
+//! from a briefly cached copy of that usage, so a burst of runs can overshoot
+//! a cap by what they use before the cache is refreshed. When the collector
+//! can't be reached runs are admitted as if the tenant had no quota, and
+//! once its breaker opens they stop waiting on it.
+
+use anyhow::Result;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use sandstorm_http::{CircuitBreaker, Unavailable};
+use sandstorm_types::quota::{QuotaAction, QuotaUsage};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+
+use crate::ownership::tenant_from_headers;
+use crate::AppState;
+
+/// How long fetched usage (or a failed fetch) is reused
+const CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Tenant runs are billed to when the request names none, as the collector
+/// records them
+pub const DEFAULT_TENANT: &str = "default";
+
+/// What admission does with a tenant's run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Allow,
+    /// Run it at low priority on the cheapest runtime
+    Downgrade,
+    Reject,
+}
+
+/// Admission for a run against the tenant's usage
+pub fn admission(usage: &QuotaUsage) -> Admission {
+    match &usage.quota {
+        Some(quota) if usage.exhausted => match quota.on_exhausted {
+            QuotaAction::Reject => Admission::Reject,
+            QuotaAction::Downgrade => Admission::Downgrade,
+        },
+        _ => Admission::Allow,
+    }
+}
+
+#[derive(Debug)]
+pub struct QuotaClient {
+    http: reqwest::Client,
+    telemetry_url: Option<String>,
+    cache: RwLock<HashMap<String, (Instant, Option<QuotaUsage>)>>,
+    breaker: Option<CircuitBreaker>,
+}
+
+impl QuotaClient {
+    pub fn new(telemetry_url: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(2))
+                .build()
+                .unwrap_or_default(),
+            telemetry_url: telemetry_url.map(|url| url.trim_end_matches('/').to_string()),
+            cache: RwLock::new(HashMap::new()),
+            breaker: None,
+        }
+    }
+
+    pub fn with_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Some(breaker);
+        self
+    }
+
+    /// Quotas come from the collector at `GATEWAY_TELEMETRY_URL`; without
+    /// one every run is admitted
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("GATEWAY_TELEMETRY_URL").ok())
+    }
+
+    /// A tenant's usage for admission, or `None` when it's unknown
+    pub async fn usage(&self, tenant: &str) -> Option<QuotaUsage> {
+        if let Some((fetched_at, usage)) = self.cache.read().await.get(tenant) {
+            if fetched_at.elapsed() < CACHE_TTL || self.telemetry_url.is_none() {
+                return usage.clone();
+            }
+        }
+
+        let usage = match self.fetch(tenant).await {
+            Ok(usage) => usage,
+            Err(e) => {
+                debug!(tenant, "Quota usage unavailable: {:#}", e);
+                None
+            }
+        };
+        self.record(tenant, usage.clone()).await;
+        usage
+    }
+
+    pub(crate) async fn record(&self, tenant: &str, usage: Option<QuotaUsage>) {
+        self.cache
+            .write()
+            .await
+            .insert(tenant.to_string(), (Instant::now(), usage));
+    }
+
+    /// Current usage from the collector, bypassing the cache; `None` when
+    /// there's no collector to ask
+    pub async fn fetch(&self, tenant: &str) -> Result<Option<QuotaUsage>> {
+        let Some(base_url) = &self.telemetry_url else {
+            return Ok(None);
+        };
+        let mut url = reqwest::Url::parse(base_url)?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid telemetry URL {}", base_url))?
+            .extend(["api", "quotas", tenant, "usage"]);
+
+        let request = async {
+            let usage = self.http.get(url).send().await?.error_for_status()?.json().await?;
+            Ok(Some(usage))
+        };
+        match &self.breaker {
+            Some(breaker) => breaker.call(request, crate::service_failed).await,
+            None => request.await,
+        }
+    }
+}
+
+/// The calling tenant's usage this month against its quota
+pub async fn quota_usage(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<QuotaUsage>, StatusCode> {
+    let tenant = tenant_from_headers(&headers).unwrap_or_else(|| DEFAULT_TENANT.to_string());
+    match state.quotas.fetch(&tenant).await {
+        Ok(Some(usage)) => {
+            state.quotas.record(&tenant, Some(usage.clone())).await;
+            Ok(Json(usage))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) if Unavailable::find(&e).is_some() => Err(StatusCode::SERVICE_UNAVAILABLE),
+        Err(e) => {
+            warn!(tenant, "Failed to fetch quota usage: {:#}", e);
+            Err(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use sandstorm_types::quota::TenantQuota;
+
+    fn usage(compute_seconds: f64, on_exhausted: Option<QuotaAction>) -> QuotaUsage {
+        let now = Utc::now();
+        let quota = on_exhausted.map(|on_exhausted| TenantQuota {
+            tenant: "acme".to_string(),
+            compute_seconds: Some(3600.0),
+            cost: None,
+            on_exhausted,
+            updated_at: now,
+        });
+        QuotaUsage::new(
+            "acme".to_string(),
+            "2024-09".to_string(),
+            (now, now),
+            compute_seconds,
+            1.5,
+            quota,
+        )
+    }
+
+    #[test]
+    fn exhausted_quotas_reject_or_downgrade() {
+        assert_eq!(admission(&usage(3600.0, Some(QuotaAction::Reject))), Admission::Reject);
+        assert_eq!(
+            admission(&usage(4000.0, Some(QuotaAction::Downgrade))),
+            Admission::Downgrade
+        );
+        assert_eq!(admission(&usage(1800.0, Some(QuotaAction::Reject))), Admission::Allow);
+        assert_eq!(admission(&usage(1e9, None)), Admission::Allow);
+    }
+
+    #[test]
+    fn remaining_quota_never_goes_negative() {
+        let over = usage(4000.0, Some(QuotaAction::Reject));
+        assert_eq!(over.remaining_compute_seconds, Some(0.0));
+        assert_eq!(over.remaining_cost, None);
+        assert!(over.exhausted);
+    }
+
+    #[tokio::test]
+    async fn usage_without_collector_admits_everything() {
+        let quotas = QuotaClient::new(None);
+        assert!(quotas.usage("acme").await.is_none());
+
+        quotas
+            .record("acme", Some(usage(3600.0, Some(QuotaAction::Reject))))
+            .await;
+        let usage = quotas.usage("acme").await.unwrap();
+        assert_eq!(admission(&usage), Admission::Reject);
+    }
+
+    #[tokio::test]
+    async fn only_collector_failures_open_its_breaker() {
+        use sandstorm_http::{BreakerSettings, BreakerState};
+
+        let collector = axum::Router::new().route(
+            "/api/quotas/:tenant/usage",
+            axum::routing::get(|axum::extract::Path(tenant): axum::extract::Path<String>| async move {
+                match tenant.as_str() {
+                    "unknown" => StatusCode::NOT_FOUND,
+                    _ => StatusCode::INTERNAL_SERVER_ERROR,
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, collector).await });
+
+        let settings = BreakerSettings {
+            failures: 2,
+            open_for: Duration::from_secs(30),
+        };
+        let breaker = CircuitBreaker::new("telemetry-collector", settings);
+        let quotas = QuotaClient::new(Some(url)).with_breaker(breaker.clone());
+        for _ in 0..3 {
+            assert!(quotas.fetch("unknown").await.is_err());
+        }
+        assert_eq!(breaker.state(), BreakerState::Closed);
+
+        for _ in 0..2 {
+            assert!(quotas.fetch("acme").await.is_err());
+        }
+        let error = quotas.fetch("acme").await.unwrap_err();
+        assert!(Unavailable::find(&error).is_some());
+        assert!(quotas.usage("acme").await.is_none());
+    }
+}
@@ -0,0 +1,406 @@
+//! Token buckets on run requests, per API key and per client IP, so a
+//! client stuck in a loop can't start sandboxes faster than hosts can take
+//! them. Each bucket holds up to `burst` requests and refills at `per_second`;
+//! a request spends one token from every bucket it falls under, and is turned
+//! away untouched when any of them is empty. Only configured keys get a
+//! bucket of their own; any other key is limited by its IP alone, so made-up
+//! keys can't be used to dodge limits.
+
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::AppState;
+
+/// Header clients may present their API key in, besides a bearer token
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// How often buckets that have refilled or gone idle are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Buckets unused this long are dropped even if they haven't refilled
+const IDLE_AFTER: Duration = Duration::from_secs(600);
+
+/// Sustained requests per second and how many may come at once
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+impl Rate {
+    fn new(per_second: f64, burst: Option<f64>) -> Option<Self> {
+        let burst = burst.unwrap_or(per_second.ceil().max(1.0));
+        (per_second > 0.0 && per_second.is_finite() && burst >= 1.0 && burst.is_finite())
+            .then_some(Self { per_second, burst })
+    }
+}
+
+/// What a bucket is kept for
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Client {
+    Key(String),
+    Ip(IpAddr),
+}
+
+impl Client {
+    fn limit(&self) -> &'static str {
+        match self {
+            Client::Key(_) => "api_key",
+            Client::Ip(_) => "ip",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, rate: Rate, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_second).min(rate.burst);
+        self.updated = self.updated.max(now);
+    }
+
+    /// Time until the bucket has a token to spend
+    fn wait(&self, rate: Rate) -> Duration {
+        Duration::from_secs_f64((1.0 - self.tokens).max(0.0) / rate.per_second)
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    /// Each bucket with the rate it refills at, for pruning
+    buckets: HashMap<Client, (Bucket, Rate)>,
+    pruned: Instant,
+}
+
+/// A run request over one of its client's rates
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("Too many run requests for this {limit}, retry in {}s", retry_after_secs(.retry_after))]
+pub struct RateLimited {
+    /// `api_key` or `ip`
+    pub limit: &'static str,
+    pub retry_after: Duration,
+}
+
+impl IntoResponse for RateLimited {
+    fn into_response(self) -> Response {
+        let retry_after = retry_after_secs(&self.retry_after);
+        let body = serde_json::json!({
+            "error": self.to_string(),
+            "limit": self.limit,
+            "retry_after_secs": retry_after,
+        });
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            Json(body),
+        )
+            .into_response()
+    }
+}
+
+/// Whole seconds to wait, rounded up so a retry at that time gets through
+fn retry_after_secs(retry_after: &Duration) -> u64 {
+    retry_after.as_secs_f64().ceil().max(1.0) as u64
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_key: Option<Rate>,
+    per_ip: Option<Rate>,
+    /// Rates for particular API keys, in place of `per_key`
+    keys: HashMap<String, Rate>,
+    /// Further keys limited at `per_key`. Keys in neither are ignored.
+    api_keys: HashSet<String>,
+    /// Take the client IP from the last `X-Forwarded-For` entry
+    trust_forwarded: bool,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        per_key: Option<Rate>,
+        per_ip: Option<Rate>,
+        keys: HashMap<String, Rate>,
+        api_keys: HashSet<String>,
+        trust_forwarded: bool,
+    ) -> Self {
+        Self {
+            per_key,
+            per_ip,
+            keys,
+            api_keys,
+            trust_forwarded,
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }
+    }
+
+    /// Rates from `GATEWAY_RATE_LIMIT_KEY_RPS` and `GATEWAY_RATE_LIMIT_IP_RPS`,
+    /// with bursts from `GATEWAY_RATE_LIMIT_KEY_BURST` and
+    /// `GATEWAY_RATE_LIMIT_IP_BURST`, and `GATEWAY_RATE_LIMIT_KEYS`
+    /// (`key=rps:burst,...`) setting rates for particular keys.
+    /// `GATEWAY_API_KEYS` lists further keys limited at the key rate. Unset
+    /// rates don't limit.
+    pub fn from_env() -> Result<Self> {
+        fn var(name: &str) -> Result<Option<f64>> {
+            std::env::var(name)
+                .ok()
+                .map(|value| value.parse().ok().with_context(|| format!("invalid {}", name)))
+                .transpose()
+        }
+        fn rate(kind: &str) -> Result<Option<Rate>> {
+            let rps = format!("GATEWAY_RATE_LIMIT_{}_RPS", kind);
+            let burst = format!("GATEWAY_RATE_LIMIT_{}_BURST", kind);
+            match (var(&rps)?, var(&burst)?) {
+                (None, None) => Ok(None),
+                (None, Some(_)) => bail!("{} is set without {}", burst, rps),
+                (Some(per_second), burst_value) => Rate::new(per_second, burst_value)
+                    .map(Some)
+                    .with_context(|| format!("{} must be positive and {} at least 1", rps, burst)),
+            }
+        }
+
+        let keys = parse_keys(&std::env::var("GATEWAY_RATE_LIMIT_KEYS").unwrap_or_default())?;
+        let api_keys = std::env::var("GATEWAY_API_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(str::to_string)
+            .collect();
+        let trust_forwarded = std::env::var("GATEWAY_RATE_LIMIT_TRUST_FORWARDED")
+            .is_ok_and(|value| matches!(value.as_str(), "1" | "true"));
+        Ok(Self::new(rate("KEY")?, rate("IP")?, keys, api_keys, trust_forwarded))
+    }
+
+    /// Rate for a configured key. Unknown keys have none, and are left to
+    /// their IP's bucket.
+    fn key_rate(&self, key: &str) -> Option<Rate> {
+        match self.keys.get(key) {
+            Some(rate) => Some(*rate),
+            None if self.api_keys.contains(key) => self.per_key,
+            None => None,
+        }
+    }
+
+    /// Spend a token from each of the client's buckets, or none if any of
+    /// them is empty
+    fn acquire(&self, key: Option<&str>, ip: Option<IpAddr>, now: Instant) -> Result<(), RateLimited> {
+        let clients: Vec<(Client, Rate)> = [
+            key.and_then(|key| Some((Client::Key(key.to_string()), self.key_rate(key)?))),
+            ip.zip(self.per_ip).map(|(ip, rate)| (Client::Ip(ip), rate)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if clients.is_empty() {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if now.saturating_duration_since(buckets.pruned) >= PRUNE_INTERVAL {
+            // A full bucket is no different from a missing one, and an idle
+            // one is close enough
+            buckets.buckets.retain(|_, (bucket, rate)| {
+                let idle = now.saturating_duration_since(bucket.updated);
+                bucket.refill(*rate, now);
+                bucket.tokens < rate.burst && idle < IDLE_AFTER
+            });
+            buckets.pruned = now;
+        }
+
+        let mut limited: Option<RateLimited> = None;
+        for (client, rate) in &clients {
+            let (bucket, _) = buckets.buckets.entry(client.clone()).or_insert_with(|| {
+                let bucket = Bucket {
+                    tokens: rate.burst,
+                    updated: now,
+                };
+                (bucket, *rate)
+            });
+            bucket.refill(*rate, now);
+            let wait = bucket.wait(*rate);
+            if wait > limited.as_ref().map_or(Duration::ZERO, |limited| limited.retry_after) {
+                limited = Some(RateLimited {
+                    limit: client.limit(),
+                    retry_after: wait,
+                });
+            }
+        }
+        if let Some(limited) = limited {
+            return Err(limited);
+        }
+        for (client, _) in &clients {
+            if let Some((bucket, _)) = buckets.buckets.get_mut(client) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Client IP: the connection's peer, or the address the nearest proxy
+    /// put last in `X-Forwarded-For` when that's trusted
+    fn client_ip(&self, headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<IpAddr> {
+        let forwarded = self.trust_forwarded.then(|| {
+            headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .last()
+                .and_then(|ip| ip.trim().parse().ok())
+        });
+        forwarded.flatten().or(peer.map(|peer| peer.ip()))
+    }
+}
+
+/// API key the caller presents, as a bearer token or in `X-API-Key`
+fn api_key_from_headers(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    bearer
+        .or_else(|| headers.get(API_KEY_HEADER).and_then(|value| value.to_str().ok()))
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+/// Per-key rates, `key=rps:burst` separated by commas. An empty burst is
+/// the rate rounded up.
+fn parse_keys(value: &str) -> Result<HashMap<String, Rate>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let parse = || {
+                let (key, rate) = entry.split_once('=')?;
+                let (per_second, burst) = rate.split_once(':').unwrap_or((rate, ""));
+                let burst = match burst.trim() {
+                    "" => None,
+                    burst => Some(burst.parse().ok()?),
+                };
+                let rate = Rate::new(per_second.trim().parse().ok()?, burst)?;
+                Some((key.trim().to_string(), rate))
+            };
+            parse().with_context(|| format!("invalid key rate limit {:?}, expected key=rps:burst", entry))
+        })
+        .collect()
+}
+
+/// Turn away run requests over their API key's or IP's rate with `429 Too
+/// Many Requests` and a `Retry-After`
+pub async fn limit_runs(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let limiter = &state.rate_limiter;
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| *peer);
+    let ip = limiter.client_ip(request.headers(), peer);
+    if let Err(limited) = limiter.acquire(api_key_from_headers(request.headers()), ip, Instant::now()) {
+        tracing::warn!(limit = limited.limit, ip = ?ip, "Run request rate limited");
+        state.metrics.rate_limited(limited.limit);
+        return limited.into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_refill_at_their_rate() {
+        let per_key = Rate::new(1.0, Some(2.0));
+        let per_ip = Rate::new(10.0, None);
+        let keys = parse_keys("batch=0.5:1").unwrap();
+        let api_keys = HashSet::from(["k1".to_string()]);
+        let limiter = RateLimiter::new(per_key, per_ip, keys, api_keys, false);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let start = Instant::now();
+
+        // A key gets its burst, then waits for a refill
+        limiter.acquire(Some("k1"), Some(ip), start).unwrap();
+        limiter.acquire(Some("k1"), Some(ip), start).unwrap();
+        let limited = limiter.acquire(Some("k1"), Some(ip), start).unwrap_err();
+        assert_eq!(limited.limit, "api_key");
+        assert_eq!(retry_after_secs(&limited.retry_after), 1);
+        limiter.acquire(Some("k1"), Some(ip), start + Duration::from_secs(1)).unwrap();
+
+        // Keys have their own buckets and rates, and refused requests spend nothing
+        let later = start + Duration::from_secs(1);
+        limiter.acquire(Some("batch"), Some(ip), later).unwrap();
+        let limited = limiter.acquire(Some("batch"), Some(ip), later).unwrap_err();
+        assert_eq!(retry_after_secs(&limited.retry_after), 2);
+
+        // Requests without a key share their IP's bucket, refilled to 10 and 2 spent since
+        for _ in 0..8 {
+            limiter.acquire(None, Some(ip), later).unwrap();
+        }
+        let limited = limiter.acquire(None, Some(ip), later).unwrap_err();
+        assert_eq!(limited.limit, "ip");
+
+        assert!(parse_keys("batch=0").is_err());
+        assert!(parse_keys("batch").is_err());
+    }
+
+    #[test]
+    fn unknown_keys_share_their_ip_bucket() {
+        let (per_key, per_ip) = (Rate::new(1.0, Some(1.0)), Rate::new(1.0, Some(3.0)));
+        let limiter = RateLimiter::new(per_key, per_ip, HashMap::new(), HashSet::new(), false);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let now = Instant::now();
+
+        // Each made-up key would otherwise get a fresh bucket
+        for key in ["a", "b", "c"] {
+            limiter.acquire(Some(key), Some(ip), now).unwrap();
+        }
+        let limited = limiter.acquire(Some("d"), Some(ip), now).unwrap_err();
+        assert_eq!(limited.limit, "ip");
+        assert_eq!(limiter.buckets.lock().unwrap().buckets.len(), 1);
+    }
+
+    #[test]
+    fn drops_full_and_idle_buckets() {
+        let keys = parse_keys("slow=0.001:2").unwrap();
+        let limiter = RateLimiter::new(None, Rate::new(1.0, None), keys, HashSet::new(), false);
+        let start = Instant::now();
+        let ip = |last: u8| Some(IpAddr::from([10, 0, 0, last]));
+
+        limiter.acquire(Some("slow"), ip(1), start).unwrap();
+        limiter.acquire(None, ip(2), start + PRUNE_INTERVAL / 2).unwrap();
+        assert_eq!(limiter.buckets.lock().unwrap().buckets.len(), 3);
+
+        // The first IP's bucket has refilled; the key's hasn't but is kept
+        limiter.acquire(None, ip(2), start + PRUNE_INTERVAL).unwrap();
+        let clients = |limiter: &RateLimiter| {
+            let buckets = limiter.buckets.lock().unwrap();
+            let mut clients: Vec<_> = buckets.buckets.keys().map(Client::limit).collect();
+            clients.sort();
+            clients
+        };
+        assert_eq!(clients(&limiter), ["api_key", "ip"]);
+
+        // Long unused, it's dropped though still short of its burst
+        limiter.acquire(None, ip(2), start + IDLE_AFTER + PRUNE_INTERVAL).unwrap();
+        assert_eq!(clients(&limiter), ["ip"]);
+    }
+}
@@ -0,0 +1,44 @@
+//! Destroys sandboxes that have outlived their `ttl_seconds`, whichever
+//! runtime hosts them. A sandbox nobody comes back to delete would
+//! otherwise hold its VM or container until the gateway restarts.
+
+use chrono::Utc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::runtime::ExitReason;
+use crate::{destroy, AppState};
+
+/// How often the reaper looks for expired sandboxes
+const TICK: Duration = Duration::from_secs(5);
+
+/// Start the loop that destroys expired sandboxes
+pub fn spawn(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TICK);
+        loop {
+            interval.tick().await;
+            reap(&state).await;
+        }
+    });
+}
+
+/// Destroy every sandbox past its TTL. One that fails to go is tried again
+/// on the next tick.
+async fn reap(state: &AppState) {
+    for sandbox in state.runtime_registry.sandboxes().expired(Utc::now()).await {
+        // A resumed sandbox is destroyed through the one it replaced, so
+        // what was kept under the original ID goes with it
+        let mut original = sandbox.sandbox_id;
+        while let Some(previous) = state.preemption.resumed_from(original).await {
+            original = previous;
+        }
+        match destroy(state, original, ExitReason::Expired).await {
+            Ok(()) => {
+                info!(sandbox_id = %original, runtime = ?sandbox.runtime_type, "Destroyed sandbox past its TTL");
+                state.metrics.sandbox_expired(sandbox.runtime_type);
+            }
+            Err(status) => warn!("Failed to destroy expired sandbox {}: {}", original, status),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use axum::http::HeaderMap;
+use sandstorm_types::recording::{SessionKind, SessionRecording, USER_HEADER};
+use sandstorm_vault_client::Delivery;
+use serde_json::json;
+use std::time::Instant;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::runtime::remote::shell_join;
+use crate::vault::VaultClient;
+
+/// Response header carrying the ID of the recording made for a request
+pub const RECORDING_ID_HEADER: &str = "x-sandstorm-recording-id";
+
+/// User recorded for requests without an `X-Sandstorm-User` header
+pub const ANONYMOUS_USER: &str = "anonymous";
+
+/// User or agent driving a request
+pub fn user_from_headers(headers: &HeaderMap) -> String {
+    headers
+        .get(USER_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .unwrap_or(ANONYMOUS_USER)
+        .to_string()
+}
+
+/// Builds an asciicast v2 recording of one sandbox session
+#[derive(Debug)]
+pub struct Recorder {
+    recording: SessionRecording,
+    started: Instant,
+    width: u16,
+    height: u16,
+    events: Vec<(f64, &'static str, String)>,
+}
+
+impl Recorder {
+    pub fn start(
+        sandbox_id: Uuid,
+        run_id: Option<Uuid>,
+        user: String,
+        kind: SessionKind,
+        command: Vec<String>,
+    ) -> Self {
+        Self {
+            recording: SessionRecording {
+                id: Uuid::new_v4(),
+                sandbox_id: sandbox_id.to_string(),
+                run_id,
+                user,
+                kind,
+                command,
+                exit_code: None,
+                started_at: chrono::Utc::now(),
+                duration_ms: 0,
+                size_bytes: 0,
+            },
+            started: Instant::now(),
+            width: 80,
+            height: 24,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.recording.id
+    }
+
+    /// Keystrokes or command text sent into the sandbox
+    pub fn input(&mut self, data: &[u8]) {
+        self.push("i", String::from_utf8_lossy(data).into_owned());
+    }
+
+    /// Terminal output from the sandbox
+    pub fn output(&mut self, data: &[u8]) {
+        let text = String::from_utf8_lossy(data);
+        let text = match self.recording.kind {
+            // Exec and run output is not written through a PTY, so line
+            // endings need the carriage return a terminal would have added
+            SessionKind::Exec | SessionKind::Run => text.replace("\r\n", "\n").replace('\n', "\r\n"),
+            SessionKind::Attach => text.into_owned(),
+        };
+        self.push("o", text);
+    }
+
+    fn push(&mut self, code: &'static str, data: String) {
+        if !data.is_empty() {
+            self.events
+                .push((self.started.elapsed().as_secs_f64(), code, data));
+        }
+    }
+
+    /// Close the session and render the cast
+    pub fn finish(mut self, exit_code: Option<i32>) -> (SessionRecording, String) {
+        self.recording.exit_code = exit_code;
+        self.recording.duration_ms = self.started.elapsed().as_millis() as u64;
+
+        let header = json!({
+            "version": 2,
+            "width": self.width,
+            "height": self.height,
+            "timestamp": self.recording.started_at.timestamp(),
+            "command": shell_join(&self.recording.command),
+            "title": format!("sandbox {} ({})", self.recording.sandbox_id, self.recording.user),
+            "env": { "TERM": "xterm-256color", "SHELL": "/bin/sh" },
+        });
+
+        let mut cast = header.to_string();
+        cast.push('\n');
+        for (elapsed, code, data) in &self.events {
+            // Round to microseconds like asciinema does
+            let elapsed = (elapsed * 1_000_000.0).round() / 1_000_000.0;
+            cast.push_str(&json!([elapsed, code, data]).to_string());
+            cast.push('\n');
+        }
+
+        self.recording.size_bytes = cast.len() as u64;
+        (self.recording, cast)
+    }
+}
+
+/// Stores recordings in, and fetches them back from, the snapshot vault
+#[derive(Debug, Clone)]
+pub struct RecordingClient {
+    vault: Option<VaultClient>,
+}
+
+impl RecordingClient {
+    /// Recordings go to the gateway's vault; setting
+    /// `GATEWAY_RECORD_SESSIONS=false` turns recording off
+    pub fn from_env(vault: Option<VaultClient>) -> Self {
+        let enabled = std::env::var("GATEWAY_RECORD_SESSIONS")
+            .map(|value| !matches!(value.as_str(), "0" | "false" | "no" | "off"))
+            .unwrap_or(true);
+
+        Self {
+            vault: vault.filter(|_| enabled),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.vault.is_some()
+    }
+
+    /// Upload a finished recording in the background. Recordings the vault
+    /// can't take right now are spooled when a spool is configured.
+    pub fn store(&self, recorder: Recorder, exit_code: Option<i32>) {
+        let Some(vault) = self.vault.clone() else {
+            return;
+        };
+        let (recording, cast) = recorder.finish(exit_code);
+
+        tokio::spawn(async move {
+            let (id, sandbox_id, user) = (recording.id, recording.sandbox_id.clone(), recording.user.clone());
+            match vault.store_recording(recording, cast).await {
+                Ok(Delivery::Stored(_)) => info!(
+                    recording_id = %id,
+                    sandbox_id = %sandbox_id,
+                    user = %user,
+                    "Session recording stored"
+                ),
+                Ok(Delivery::Spooled(_)) => {
+                    info!(recording_id = %id, "Session recording spooled until the vault is reachable")
+                }
+                Err(e) => warn!("Failed to store recording {}: {:#}", id, e),
+            }
+        });
+    }
+
+    /// Recordings the vault holds for a sandbox
+    pub async fn list(&self, sandbox_id: Uuid) -> anyhow::Result<Vec<SessionRecording>> {
+        self.vault()?.recordings(&sandbox_id.to_string()).await
+    }
+
+    /// Raw asciicast for a recording, or `None` if the vault doesn't have it
+    pub async fn cast(&self, recording_id: Uuid) -> anyhow::Result<Option<Vec<u8>>> {
+        self.vault()?.recording_cast(recording_id).await
+    }
+
+    fn vault(&self) -> anyhow::Result<&VaultClient> {
+        self.vault
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Session recording is not configured"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_asciicast_v2() {
+        let mut recorder = Recorder::start(
+            Uuid::new_v4(),
+            None,
+            "alice".to_string(),
+            SessionKind::Exec,
+            vec!["echo".to_string(), "hi there".to_string()],
+        );
+        recorder.input(b"echo 'hi there'\r");
+        recorder.output(b"hi there\n");
+        let (recording, cast) = recorder.finish(Some(0));
+
+        let lines: Vec<serde_json::Value> = cast
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["version"], 2);
+        assert_eq!(lines[0]["command"], "echo 'hi there'");
+        assert_eq!(lines[1][1], "i");
+        assert_eq!(lines[2][1], "o");
+        assert_eq!(lines[2][2], "hi there\r\n");
+
+        assert_eq!(recording.user, "alice");
+        assert_eq!(recording.exit_code, Some(0));
+        assert_eq!(recording.size_bytes, cast.len() as u64);
+    }
+
+    #[test]
+    fn user_defaults_to_anonymous() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(user_from_headers(&headers), "anonymous");
+
+        headers.insert(USER_HEADER, "ci-agent".parse().unwrap());
+        assert_eq!(user_from_headers(&headers), "ci-agent");
+    }
+}
@@ -0,0 +1,50 @@
+//! What differs between the CPU architectures local runtimes run on:
+//! seccomp architecture names and the system calls that exist at all
+
+use anyhow::Result;
+
+use super::Arch;
+
+/// Legacy system calls x86_64 kept and aarch64 never had; their `*at` and
+/// newer variants cover the same ground there
+const X86_64_ONLY_SYSCALLS: &[&str] = &[
+    "access", "arch_prctl", "dup2", "epoll_create", "epoll_wait", "fork", "getdents",
+    "getpgrp", "lstat", "mkdir", "open", "pipe", "poll", "readlink", "rename", "rmdir",
+    "select", "stat", "unlink", "vfork",
+];
+
+/// Architecture of the host, failing on ones sandboxes can't run on
+pub fn detect() -> Result<Arch> {
+    Arch::host().ok_or_else(|| {
+        anyhow::anyhow!("unsupported host architecture {}", std::env::consts::ARCH)
+    })
+}
+
+/// Value of an OCI seccomp profile's `architectures` for sandboxes on `arch`
+pub fn seccomp_architectures(arch: Arch) -> &'static [&'static str] {
+    match arch {
+        Arch::X86_64 => &["SCMP_ARCH_X86_64"],
+        Arch::Aarch64 => &["SCMP_ARCH_AARCH64"],
+    }
+}
+
+/// The system calls in `names` that exist on `arch`
+pub fn syscalls<'a>(arch: Arch, names: &[&'a str]) -> Vec<&'a str> {
+    names
+        .iter()
+        .copied()
+        .filter(|name| arch == Arch::X86_64 || !X86_64_ONLY_SYSCALLS.contains(name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_syscalls_aarch64_lacks() {
+        let names = ["openat", "open", "arch_prctl", "read"];
+        assert_eq!(syscalls(Arch::X86_64, &names), names);
+        assert_eq!(syscalls(Arch::Aarch64, &names), ["openat", "read"]);
+    }
+}
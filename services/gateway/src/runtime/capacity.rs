@@ -0,0 +1,366 @@
+use super::*;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+const MIB: u64 = 1024 * 1024;
+
+/// Host resources a sandbox is accounted for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Demand {
+    pub cpus: f64,
+    pub memory_bytes: u64,
+}
+
+/// Host accounting settings
+#[derive(Debug, Clone)]
+pub struct CapacityConfig {
+    /// vCPUs handed out per host CPU
+    pub cpu_overcommit: f64,
+    /// Memory handed out per byte of host memory
+    pub memory_overcommit: f64,
+    /// Overrides for the detected host size
+    pub host_cpus: Option<f64>,
+    pub host_memory_bytes: Option<u64>,
+    /// Memory kept back for the host itself
+    pub reserved_memory_bytes: u64,
+    /// Charged for sandboxes that don't set their own limits
+    pub default_demand: Demand,
+    /// Filesystem sandboxes are written to
+    pub disk_path: PathBuf,
+    /// New sandboxes are refused below this much free disk
+    pub min_free_disk_bytes: u64,
+}
+
+impl Default for CapacityConfig {
+    fn default() -> Self {
+        Self {
+            cpu_overcommit: 2.0,
+            memory_overcommit: 1.0,
+            host_cpus: None,
+            host_memory_bytes: None,
+            reserved_memory_bytes: 1024 * MIB,
+            default_demand: Demand {
+                cpus: 1.0,
+                memory_bytes: 512 * MIB,
+            },
+            disk_path: PathBuf::from("/var/lib/sandstorm"),
+            min_free_disk_bytes: 1024 * MIB,
+        }
+    }
+}
+
+impl CapacityConfig {
+    /// Settings from `GATEWAY_CPU_OVERCOMMIT`, `GATEWAY_MEMORY_OVERCOMMIT`,
+    /// `GATEWAY_HOST_CPUS`, `GATEWAY_HOST_MEMORY_MB`,
+    /// `GATEWAY_HOST_RESERVED_MEMORY_MB`, `GATEWAY_DEFAULT_SANDBOX_CPUS`,
+    /// `GATEWAY_DEFAULT_SANDBOX_MEMORY_MB`, `GATEWAY_SANDBOX_DISK_PATH` and
+    /// `GATEWAY_MIN_FREE_DISK_MB`
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            std::env::var(name).ok().and_then(|value| value.parse().ok())
+        }
+
+        let defaults = Self::default();
+        Self {
+            cpu_overcommit: var("GATEWAY_CPU_OVERCOMMIT").unwrap_or(defaults.cpu_overcommit),
+            memory_overcommit: var("GATEWAY_MEMORY_OVERCOMMIT")
+                .unwrap_or(defaults.memory_overcommit),
+            host_cpus: var("GATEWAY_HOST_CPUS"),
+            host_memory_bytes: var::<u64>("GATEWAY_HOST_MEMORY_MB").map(|mb| mb * MIB),
+            reserved_memory_bytes: var::<u64>("GATEWAY_HOST_RESERVED_MEMORY_MB")
+                .map(|mb| mb * MIB)
+                .unwrap_or(defaults.reserved_memory_bytes),
+            default_demand: Demand {
+                cpus: var("GATEWAY_DEFAULT_SANDBOX_CPUS").unwrap_or(defaults.default_demand.cpus),
+                memory_bytes: var::<u64>("GATEWAY_DEFAULT_SANDBOX_MEMORY_MB")
+                    .map(|mb| mb * MIB)
+                    .unwrap_or(defaults.default_demand.memory_bytes),
+            },
+            disk_path: std::env::var("GATEWAY_SANDBOX_DISK_PATH")
+                .map(PathBuf::from)
+                .unwrap_or(defaults.disk_path),
+            min_free_disk_bytes: var::<u64>("GATEWAY_MIN_FREE_DISK_MB")
+                .map(|mb| mb * MIB)
+                .unwrap_or(defaults.min_free_disk_bytes),
+        }
+    }
+}
+
+/// CPU and memory reserved by sandboxes on local runtimes, checked against
+/// what the host can hold after overcommit
+#[derive(Debug)]
+pub struct HostCapacity {
+    config: CapacityConfig,
+    cpus: f64,
+    memory_bytes: u64,
+    reservations: RwLock<HashMap<Uuid, Demand>>,
+}
+
+impl HostCapacity {
+    /// Track a host of the given size; `None` when the size is unknown and
+    /// not configured
+    pub fn new(config: CapacityConfig) -> Option<Self> {
+        let cpus = config.host_cpus.or_else(|| {
+            std::thread::available_parallelism()
+                .ok()
+                .map(|cpus| cpus.get() as f64)
+        })?;
+        let memory_bytes = config
+            .host_memory_bytes
+            .or_else(|| meminfo("MemTotal"))?;
+
+        info!(
+            "Host capacity: {} CPUs x{} overcommit, {} MiB memory x{} overcommit",
+            cpus,
+            config.cpu_overcommit,
+            memory_bytes / MIB,
+            config.memory_overcommit
+        );
+        Some(Self {
+            config,
+            cpus,
+            memory_bytes,
+            reservations: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Host tracking unless `GATEWAY_HOST_CAPACITY=false`
+    pub fn from_env() -> Option<Self> {
+        let enabled = std::env::var("GATEWAY_HOST_CAPACITY")
+            .map(|value| !matches!(value.as_str(), "0" | "false" | "no" | "off"))
+            .unwrap_or(true);
+        if !enabled {
+            return None;
+        }
+
+        let capacity = Self::new(CapacityConfig::from_env());
+        if capacity.is_none() {
+            warn!("Could not determine host size; set GATEWAY_HOST_CPUS and GATEWAY_HOST_MEMORY_MB to enable capacity checks");
+        }
+        capacity
+    }
+
+    /// What a sandbox with these limits is charged
+    pub fn demand(&self, cpu_limit: Option<f64>, memory_limit: Option<u64>) -> Demand {
+        Demand {
+            cpus: cpu_limit.unwrap_or(self.config.default_demand.cpus),
+            memory_bytes: memory_limit.unwrap_or(self.config.default_demand.memory_bytes),
+        }
+    }
+
+    fn allocatable_cpus(&self) -> f64 {
+        self.cpus * self.config.cpu_overcommit
+    }
+
+    fn allocatable_memory(&self) -> u64 {
+        (self.memory_bytes.saturating_sub(self.config.reserved_memory_bytes) as f64
+            * self.config.memory_overcommit) as u64
+    }
+
+    fn reserved(reservations: &HashMap<Uuid, Demand>) -> Demand {
+        reservations.values().fold(Demand::default(), |total, demand| Demand {
+            cpus: total.cpus + demand.cpus,
+            memory_bytes: total.memory_bytes + demand.memory_bytes,
+        })
+    }
+
+    fn fits_in(&self, reservations: &HashMap<Uuid, Demand>, demand: Demand) -> bool {
+        let reserved = Self::reserved(reservations);
+        reserved.cpus + demand.cpus <= self.allocatable_cpus()
+            && reserved.memory_bytes + demand.memory_bytes <= self.allocatable_memory()
+            && free_disk(&self.config.disk_path)
+                .is_none_or(|free| free >= self.config.min_free_disk_bytes)
+    }
+
+    pub async fn fits(&self, demand: Demand) -> bool {
+        self.fits_in(&*self.reservations.read().await, demand)
+    }
+
+    /// Reserve resources for a sandbox if they fit
+    pub async fn try_reserve(&self, key: Uuid, demand: Demand) -> bool {
+        let mut reservations = self.reservations.write().await;
+        if !self.fits_in(&reservations, demand) {
+            return false;
+        }
+        reservations.insert(key, demand);
+        true
+    }
+
+    /// Move a reservation made before a sandbox had its final ID
+    pub async fn rekey(&self, from: Uuid, to: Uuid) {
+        let mut reservations = self.reservations.write().await;
+        if let Some(demand) = reservations.remove(&from) {
+            reservations.insert(to, demand);
+        }
+    }
+
+    pub async fn release(&self, key: Uuid) -> bool {
+        self.reservations.write().await.remove(&key).is_some()
+    }
+
+    pub async fn report(&self) -> HostReport {
+        let reservations = self.reservations.read().await;
+        let reserved = Self::reserved(&reservations);
+        let (cpus, memory_bytes) = (self.allocatable_cpus(), self.allocatable_memory());
+
+        HostReport {
+            cpus: ResourceReport {
+                total: self.cpus,
+                overcommit_ratio: self.config.cpu_overcommit,
+                allocatable: cpus,
+                reserved: reserved.cpus,
+                headroom: (cpus - reserved.cpus).max(0.0),
+            },
+            memory_bytes: ResourceReport {
+                total: self.memory_bytes,
+                overcommit_ratio: self.config.memory_overcommit,
+                allocatable: memory_bytes,
+                reserved: reserved.memory_bytes,
+                headroom: memory_bytes.saturating_sub(reserved.memory_bytes),
+            },
+            memory_available_bytes: meminfo("MemAvailable"),
+            disk: DiskReport {
+                path: self.config.disk_path.clone(),
+                free_bytes: free_disk(&self.config.disk_path),
+                min_free_bytes: self.config.min_free_disk_bytes,
+            },
+            sandboxes: reservations.len(),
+            default_demand: self.config.default_demand,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceReport<T> {
+    pub total: T,
+    pub overcommit_ratio: f64,
+    /// `total` after overcommit (and, for memory, the host reservation)
+    pub allocatable: T,
+    /// Charged to running sandboxes
+    pub reserved: T,
+    pub headroom: T,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskReport {
+    pub path: PathBuf,
+    pub free_bytes: Option<u64>,
+    pub min_free_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HostReport {
+    pub cpus: ResourceReport<f64>,
+    pub memory_bytes: ResourceReport<u64>,
+    /// What the kernel reports as available right now, regardless of
+    /// reservations
+    pub memory_available_bytes: Option<u64>,
+    pub disk: DiskReport,
+    /// Sandboxes holding a reservation
+    pub sandboxes: usize,
+    pub default_demand: Demand,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeLoad {
+    pub runtime_type: RuntimeType,
+    pub remote: bool,
+    pub active: usize,
+    /// `GATEWAY_MAX_LOCAL_SANDBOXES`, for local runtimes
+    pub limit: Option<usize>,
+}
+
+/// Capacity headroom served at `/v1/capacity`
+#[derive(Debug, Clone, Serialize)]
+pub struct CapacityReport {
+    /// Absent when host accounting is off
+    pub host: Option<HostReport>,
+    pub runtimes: Vec<RuntimeLoad>,
+    /// Requests waiting for capacity
+    pub queued: usize,
+    pub queue_timeout_secs: u64,
+}
+
+/// A field of `/proc/meminfo`, in bytes
+fn meminfo(field: &str) -> Option<u64> {
+    let contents = std::fs::read_to_string("/proc/meminfo").ok()?;
+    contents.lines().find_map(|line| {
+        let value = line.strip_prefix(field)?.strip_prefix(':')?;
+        let kib: u64 = value.trim().trim_end_matches("kB").trim().parse().ok()?;
+        Some(kib * 1024)
+    })
+}
+
+/// Free space for unprivileged users on the filesystem holding `path`, or
+/// its nearest existing ancestor
+fn free_disk(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    path.ancestors().find_map(|dir| {
+        let dir = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        // SAFETY: `dir` is NUL-terminated and `stat` is a valid out pointer
+        if unsafe { libc::statvfs(dir.as_ptr(), &mut stat) } != 0 {
+            return None;
+        }
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(cpus: f64, memory_mb: u64) -> HostCapacity {
+        HostCapacity::new(CapacityConfig {
+            host_cpus: Some(cpus),
+            host_memory_bytes: Some(memory_mb * MIB),
+            reserved_memory_bytes: 0,
+            min_free_disk_bytes: 0,
+            ..CapacityConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reservations_respect_overcommit() {
+        // 2 CPUs at the default 2x overcommit, no memory overcommit
+        let capacity = host(2.0, 2048);
+        let demand = capacity.demand(Some(1.5), Some(512 * MIB));
+
+        assert!(capacity.try_reserve(Uuid::new_v4(), demand).await);
+        assert!(capacity.try_reserve(Uuid::new_v4(), demand).await);
+        // 4.5 CPUs would exceed the 4 allocatable
+        assert!(!capacity.fits(demand).await);
+        assert!(capacity.fits(capacity.demand(Some(1.0), None)).await);
+        // Memory does not overcommit
+        assert!(!capacity.fits(capacity.demand(Some(0.5), Some(1025 * MIB))).await);
+
+        let report = capacity.report().await;
+        assert_eq!(report.cpus.reserved, 3.0);
+        assert_eq!(report.cpus.headroom, 1.0);
+        assert_eq!(report.memory_bytes.headroom, 1024 * MIB);
+        assert_eq!(report.sandboxes, 2);
+    }
+
+    #[tokio::test]
+    async fn release_and_rekey() {
+        let capacity = host(1.0, 1024);
+        let demand = capacity.demand(Some(2.0), Some(1024 * MIB));
+        let (provisional, sandbox_id) = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(capacity.try_reserve(provisional, demand).await);
+        assert!(!capacity.fits(demand).await);
+
+        capacity.rekey(provisional, sandbox_id).await;
+        assert!(!capacity.release(provisional).await);
+        assert!(capacity.release(sandbox_id).await);
+        assert!(capacity.fits(demand).await);
+    }
+
+    #[test]
+    fn reads_host_memory_and_disk() {
+        assert!(meminfo("MemTotal").is_some_and(|bytes| bytes > 0));
+        assert!(free_disk(Path::new("/definitely/not/a/real/path")).is_some());
+    }
+}
@@ -0,0 +1,304 @@
+//! Runs runtime CLIs (runsc, kata-runtime) and records every invocation:
+//! its full argv, how long it took, its exit code and the tail of its
+//! stderr. Invocations are logged, and a failed one becomes a
+//! [`CommandFailure`] the API can show when `GATEWAY_DEBUG_ERRORS` is set.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::process::{Output, Stdio};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{ChildStdin, Command};
+use tracing::{debug, warn};
+
+use super::{ExecInputReceiver, ExecOutput, ExecOutputSender};
+
+/// Bytes of stderr kept, from the end, where the fatal error usually is
+const STDERR_TAIL_BYTES: usize = 4096;
+
+/// A runtime command that exited unsuccessfully
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandFailure {
+    /// What the command was for, e.g. `create container`
+    pub action: String,
+    pub argv: Vec<String>,
+    pub duration_ms: u64,
+    /// `None` when the command was killed by a signal
+    pub exit_code: Option<i32>,
+    /// The last 4 KiB of stderr
+    pub stderr: String,
+}
+
+impl std::fmt::Display for CommandFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let program = self.argv.first().map(String::as_str).unwrap_or("command");
+        match self.exit_code {
+            Some(code) => write!(f, "Failed to {}: {} exited with {}", self.action, program, code)?,
+            None => write!(f, "Failed to {}: {} was killed", self.action, program)?,
+        }
+        match self.stderr.trim() {
+            "" => Ok(()),
+            stderr => write!(f, ": {}", stderr),
+        }
+    }
+}
+
+impl std::error::Error for CommandFailure {}
+
+/// Run a command to completion, failing with a [`CommandFailure`] if it
+/// exits unsuccessfully
+pub async fn run(cmd: &mut Command, action: &str) -> Result<Output> {
+    let (output, failure) = invoke(cmd, action).await?;
+    match failure {
+        Some(failure) => Err(failure.into()),
+        None => Ok(output),
+    }
+}
+
+/// Run a command whose exit code is a result rather than an error, such as
+/// a workload run with `exec`. Unsuccessful exits are still logged.
+pub async fn output(cmd: &mut Command, action: &str) -> Result<Output> {
+    Ok(invoke(cmd, action).await?.0)
+}
+
+/// Run a command like [`output`], writing what `input` receives to its
+/// stdin and sending its stdout and stderr to `stream` as they're written
+/// as well as returning them. Stdin is closed once `input` is; without
+/// `input` the command gets none, as with `output`.
+pub async fn interact(
+    cmd: &mut Command,
+    action: &str,
+    input: Option<ExecInputReceiver>,
+    stream: Option<&ExecOutputSender>,
+) -> Result<Output> {
+    let argv = argv(cmd);
+    cmd.stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() });
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    let started = Instant::now();
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to {}: could not run {}", action, argv[0]))?;
+
+    let feed = feed(child.stdin.take(), input);
+    let stdout = forward(child.stdout.take(), stream, ExecOutput::Stdout);
+    let stderr = forward(child.stderr.take(), stream, ExecOutput::Stderr);
+    let outputs = async { tokio::try_join!(stdout, stderr, child.wait()) };
+    tokio::pin!(feed, outputs);
+    // A command can exit before its input ends; the rest is dropped
+    let outcome = tokio::select! {
+        outcome = &mut outputs => outcome,
+        () = &mut feed => outputs.await,
+    };
+    let (stdout, stderr, status) =
+        outcome.with_context(|| format!("Failed to {}: lost {}", action, argv[0]))?;
+
+    let output = Output {
+        status,
+        stdout,
+        stderr,
+    };
+    Ok(finished(action, argv, started, output).0)
+}
+
+/// Write input to a command's stdin until the input or the command's
+/// reading ends, then close it
+async fn feed(stdin: Option<ChildStdin>, input: Option<ExecInputReceiver>) {
+    let (Some(mut stdin), Some(mut input)) = (stdin, input) else {
+        return;
+    };
+    while let Some(chunk) = input.recv().await {
+        if stdin.write_all(&chunk).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Read a pipe to the end, sending each chunk on as it arrives if there's a
+/// stream. Nobody listening any more doesn't stop the reading.
+async fn forward(
+    pipe: Option<impl AsyncRead + Unpin>,
+    stream: Option<&ExecOutputSender>,
+    chunk: fn(Vec<u8>) -> ExecOutput,
+) -> std::io::Result<Vec<u8>> {
+    let mut all = Vec::new();
+    let Some(mut pipe) = pipe else {
+        return Ok(all);
+    };
+    let mut buf = [0u8; 8192];
+    loop {
+        let read = pipe.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(all);
+        }
+        all.extend_from_slice(&buf[..read]);
+        if let Some(stream) = stream {
+            stream.send(chunk(buf[..read].to_vec())).await.ok();
+        }
+    }
+}
+
+fn argv(cmd: &Command) -> Vec<String> {
+    let std_cmd = cmd.as_std();
+    std::iter::once(std_cmd.get_program())
+        .chain(std_cmd.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+async fn invoke(cmd: &mut Command, action: &str) -> Result<(Output, Option<CommandFailure>)> {
+    let argv = argv(cmd);
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    let started = Instant::now();
+    let output = cmd
+        .output()
+        .await
+        .with_context(|| format!("Failed to {}: could not run {}", action, argv[0]))?;
+    Ok(finished(action, argv, started, output))
+}
+
+/// Log a finished command, and describe it if it failed
+fn finished(
+    action: &str,
+    argv: Vec<String>,
+    started: Instant,
+    output: Output,
+) -> (Output, Option<CommandFailure>) {
+    let duration_ms = started.elapsed().as_millis() as u64;
+    let exit_code = output.status.code();
+
+    if output.status.success() {
+        debug!(action, ?argv, duration_ms, exit_code, "Runtime command finished");
+        return (output, None);
+    }
+
+    let failure = CommandFailure {
+        action: action.to_string(),
+        argv,
+        duration_ms,
+        exit_code,
+        stderr: tail(&output.stderr),
+    };
+    warn!(
+        action,
+        argv = ?failure.argv,
+        duration_ms,
+        exit_code,
+        stderr = %failure.stderr,
+        "Runtime command failed"
+    );
+    (output, Some(failure))
+}
+
+/// A directory as a tar archive, e.g. a sandbox's root filesystem
+pub async fn tar(dir: &std::path::Path) -> Result<Vec<u8>> {
+    let mut cmd = Command::new("tar");
+    cmd.arg("-C").arg(dir).args(["--numeric-owner", "-cf", "-", "."]);
+    Ok(run(&mut cmd, "archive sandbox filesystem").await?.stdout)
+}
+
+/// The last [`STDERR_TAIL_BYTES`] of output, as text
+fn tail(stderr: &[u8]) -> String {
+    let text = String::from_utf8_lossy(stderr);
+    let mut start = text.len().saturating_sub(STDERR_TAIL_BYTES);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    if start == 0 {
+        text.into_owned()
+    } else {
+        format!("...{}", &text[start..])
+    }
+}
+
+/// The command failure behind an error, if a runtime command caused it
+pub fn failure_of(error: &anyhow::Error) -> Option<&CommandFailure> {
+    error.chain().find_map(|cause| cause.downcast_ref::<CommandFailure>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn records_argv_exit_code_and_stderr_of_failed_commands() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo starting; echo 'no such container' >&2; exit 3"]);
+        let error = run(&mut cmd, "start container").await.unwrap_err();
+
+        let failure = failure_of(&error).unwrap();
+        assert_eq!(failure.argv[0], "sh");
+        assert_eq!(failure.argv.len(), 3);
+        assert_eq!(failure.exit_code, Some(3));
+        assert_eq!(failure.stderr, "no such container\n");
+        assert_eq!(
+            error.to_string(),
+            "Failed to start container: sh exited with 3: no such container"
+        );
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "exit 1"]);
+        assert_eq!(output(&mut cmd, "exec").await.unwrap().status.code(), Some(1));
+
+        assert!(tail(&[b'x'; STDERR_TAIL_BYTES + 10]).starts_with("..."));
+    }
+
+    #[tokio::test]
+    async fn streams_output_while_the_command_runs() {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo first; sleep 0.2; echo oops >&2; echo second; exit 2"]);
+        let run = tokio::spawn(async move { interact(&mut cmd, "exec", None, Some(&sender)).await });
+
+        // The first line arrives before the command has finished
+        assert_eq!(receiver.recv().await, Some(ExecOutput::Stdout(b"first\n".to_vec())));
+        assert!(!run.is_finished());
+
+        let output = run.await.unwrap().unwrap();
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(output.stdout, b"first\nsecond\n");
+        assert_eq!(output.stderr, b"oops\n");
+
+        let mut rest = Vec::new();
+        while let Some(chunk) = receiver.recv().await {
+            rest.push(chunk);
+        }
+        assert!(rest.contains(&ExecOutput::Stderr(b"oops\n".to_vec())));
+        assert!(rest.contains(&ExecOutput::Stdout(b"second\n".to_vec())));
+    }
+
+    #[tokio::test]
+    async fn feeds_input_to_stdin_until_it_closes() {
+        let (input, receiver) = tokio::sync::mpsc::channel(4);
+        let (sender, mut output) = tokio::sync::mpsc::channel(16);
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "read line; echo \"got $line\"; cat"]);
+        let run = tokio::spawn(async move { interact(&mut cmd, "exec", Some(receiver), Some(&sender)).await });
+
+        // Each answer comes back before the next line is sent
+        input.send(b"one\n".to_vec()).await.unwrap();
+        assert_eq!(output.recv().await, Some(ExecOutput::Stdout(b"got one\n".to_vec())));
+        input.send(b"two\n".to_vec()).await.unwrap();
+        assert_eq!(output.recv().await, Some(ExecOutput::Stdout(b"two\n".to_vec())));
+        assert!(!run.is_finished());
+
+        // Closing the input ends `cat`
+        drop(input);
+        let result = run.await.unwrap().unwrap();
+        assert_eq!(result.status.code(), Some(0));
+        assert_eq!(result.stdout, b"got one\ntwo\n");
+    }
+
+    #[tokio::test]
+    async fn commands_exiting_early_leave_input_unread() {
+        let (input, receiver) = tokio::sync::mpsc::channel(4);
+        input.send(b"ignored\n".to_vec()).await.unwrap();
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "exit 0"]);
+        // The input is never closed, but the command doesn't wait for it
+        let result = interact(&mut cmd, "exec", Some(receiver), None).await.unwrap();
+        assert_eq!(result.status.code(), Some(0));
+        drop(input);
+    }
+}
@@ -0,0 +1,190 @@
+//! What the host CPU offers Firecracker's CPU controls: which static CPU
+//! templates it can mask guests down to, and whether it has SMT to give
+//! them. Also builds the custom CPU template that masks the brand string.
+
+use serde_json::json;
+
+use super::{Arch, CpuTemplate};
+
+/// Brand string guests of VMs with a masked brand see
+pub const MASKED_BRAND: &str = "Sandstorm Virtual CPU";
+
+/// CPUID leaves holding the 48-byte brand string, 16 bytes each
+const BRAND_LEAVES: [u32; 3] = [0x8000_0002, 0x8000_0003, 0x8000_0004];
+
+/// Arm's implementer code and the Neoverse V1's part number in `/proc/cpuinfo`
+const ARM_IMPLEMENTER: &str = "0x41";
+const NEOVERSE_V1_PART: &str = "0xd40";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuVendor {
+    Intel,
+    Amd,
+    /// An aarch64 core, and whether it's a Neoverse V1
+    Arm { neoverse_v1: bool },
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostCpu {
+    pub arch: Arch,
+    pub vendor: CpuVendor,
+    /// Whether sibling hyperthreads are online
+    pub smt_active: bool,
+}
+
+impl HostCpu {
+    /// The host's CPU, from `/proc/cpuinfo` and the kernel's SMT control
+    pub fn detect(arch: Arch) -> Self {
+        let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+        let smt_active = std::fs::read_to_string("/sys/devices/system/cpu/smt/active")
+            .is_ok_and(|active| active.trim() == "1");
+        Self {
+            arch,
+            vendor: vendor(arch, &cpuinfo),
+            smt_active,
+        }
+    }
+
+    /// Why `template` can't be used on this host, if it can't
+    pub fn template_unsupported(&self, template: CpuTemplate) -> Option<String> {
+        let supported = match template {
+            CpuTemplate::T2 | CpuTemplate::T2s | CpuTemplate::T2cl | CpuTemplate::C3 => {
+                self.vendor == CpuVendor::Intel
+            }
+            CpuTemplate::T2a => self.vendor == CpuVendor::Amd,
+            CpuTemplate::V1n1 => self.vendor == CpuVendor::Arm { neoverse_v1: true },
+        };
+        let needs = match template {
+            CpuTemplate::T2a => "an AMD host",
+            CpuTemplate::V1n1 => "a Neoverse V1 host",
+            _ => "an Intel host",
+        };
+        (!supported).then(|| format!("CPU template {} needs {}", name(template), needs))
+    }
+}
+
+/// A template's name, as Firecracker and requests spell it
+pub fn name(template: CpuTemplate) -> &'static str {
+    match template {
+        CpuTemplate::T2 => "T2",
+        CpuTemplate::T2s => "T2S",
+        CpuTemplate::T2cl => "T2CL",
+        CpuTemplate::T2a => "T2A",
+        CpuTemplate::C3 => "C3",
+        CpuTemplate::V1n1 => "V1N1",
+    }
+}
+
+/// The CPU vendor `/proc/cpuinfo` describes
+fn vendor(arch: Arch, cpuinfo: &str) -> CpuVendor {
+    let field = |name: &str| {
+        cpuinfo.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            (key.trim() == name).then(|| value.trim())
+        })
+    };
+    match arch {
+        Arch::X86_64 => match field("vendor_id") {
+            Some("GenuineIntel") => CpuVendor::Intel,
+            Some("AuthenticAMD") => CpuVendor::Amd,
+            _ => CpuVendor::Unknown,
+        },
+        Arch::Aarch64 => CpuVendor::Arm {
+            neoverse_v1: field("CPU implementer") == Some(ARM_IMPLEMENTER)
+                && field("CPU part") == Some(NEOVERSE_V1_PART),
+        },
+    }
+}
+
+/// A Firecracker custom CPU template replacing the brand string in CPUID
+/// with [`MASKED_BRAND`]
+pub fn masked_brand_template() -> serde_json::Value {
+    let mut brand = [0u8; 48];
+    brand[..MASKED_BRAND.len()].copy_from_slice(MASKED_BRAND.as_bytes());
+
+    let modifiers: Vec<_> = BRAND_LEAVES
+        .iter()
+        .zip(brand.chunks(16))
+        .map(|(leaf, bytes)| {
+            let registers: Vec<_> = ["eax", "ebx", "ecx", "edx"]
+                .iter()
+                .zip(bytes.chunks(4))
+                .map(|(register, value)| {
+                    let value = u32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+                    json!({ "register": register, "bitmap": format!("0b{:032b}", value) })
+                })
+                .collect();
+            json!({
+                "leaf": format!("{:#x}", leaf),
+                "subleaf": "0x0",
+                "flags": 0,
+                "modifiers": registers
+            })
+        })
+        .collect();
+    json!({ "cpuid_modifiers": modifiers })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(vendor: CpuVendor) -> HostCpu {
+        HostCpu {
+            arch: Arch::X86_64,
+            vendor,
+            smt_active: true,
+        }
+    }
+
+    #[test]
+    fn reads_the_vendor_from_cpuinfo() {
+        let intel = "processor\t: 0\nvendor_id\t: GenuineIntel\ncpu family\t: 6\n";
+        assert_eq!(vendor(Arch::X86_64, intel), CpuVendor::Intel);
+        assert_eq!(vendor(Arch::X86_64, "vendor_id : AuthenticAMD"), CpuVendor::Amd);
+        assert_eq!(vendor(Arch::X86_64, ""), CpuVendor::Unknown);
+
+        let graviton3 = "processor\t: 0\nCPU implementer\t: 0x41\nCPU part\t: 0xd40\n";
+        assert_eq!(
+            vendor(Arch::Aarch64, graviton3),
+            CpuVendor::Arm { neoverse_v1: true }
+        );
+        let graviton2 = "CPU implementer\t: 0x41\nCPU part\t: 0xd0c\n";
+        assert_eq!(
+            vendor(Arch::Aarch64, graviton2),
+            CpuVendor::Arm { neoverse_v1: false }
+        );
+    }
+
+    #[test]
+    fn templates_need_a_matching_host() {
+        let intel = host(CpuVendor::Intel);
+        assert_eq!(intel.template_unsupported(CpuTemplate::C3), None);
+        assert_eq!(intel.template_unsupported(CpuTemplate::T2s), None);
+        assert_eq!(
+            intel.template_unsupported(CpuTemplate::T2a).as_deref(),
+            Some("CPU template T2A needs an AMD host")
+        );
+        assert!(host(CpuVendor::Amd).template_unsupported(CpuTemplate::T2).is_some());
+        assert_eq!(host(CpuVendor::Amd).template_unsupported(CpuTemplate::T2a), None);
+    }
+
+    #[test]
+    fn masks_the_brand_string_across_three_leaves() {
+        let template = masked_brand_template();
+        let leaves = template["cpuid_modifiers"].as_array().unwrap();
+        assert_eq!(leaves.len(), 3);
+        assert_eq!(leaves[0]["leaf"], "0x80000002");
+
+        // "Sand" little-endian in the first register
+        let eax = &leaves[0]["modifiers"][0];
+        assert_eq!(eax["register"], "eax");
+        assert_eq!(
+            eax["bitmap"],
+            format!("0b{:032b}", u32::from_le_bytes(*b"Sand"))
+        );
+        // The rest of the string is NUL padding
+        assert_eq!(leaves[2]["modifiers"][3]["bitmap"], format!("0b{:032b}", 0));
+    }
+}
@@ -0,0 +1,171 @@
+//! Works out why a workload stopped. Runtimes only pass on an exit code,
+//! which is the same -1 or 128+signal whether the kernel OOM-killed the
+//! workload, it crashed, or its VM panicked, so local runtimes read it
+//! alongside the sandbox cgroup's OOM counter and, for VMs, what the guest
+//! kernel logged to its console.
+
+use std::io::SeekFrom;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use uuid::Uuid;
+
+use super::{ExitReason, SandboxState};
+
+/// cgroup v2 mount that sandbox cgroups are created under
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+/// Parent cgroup of every local sandbox
+const CGROUP_PARENT: &str = "sandstorm";
+
+/// Exit code of `timeout(1)` and the wrappers that mimic it
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Lines a guest kernel logs when it OOM-kills a process or panics
+const GUEST_OOM_KILL: &str = "Out of memory: Killed process";
+const GUEST_PANIC: &str = "Kernel panic";
+
+/// `linux.cgroupsPath` for a sandbox's OCI spec, so the gateway knows where
+/// to find its memory events
+pub fn cgroup_path(sandbox_id: Uuid) -> String {
+    format!("/{}/{}", CGROUP_PARENT, sandbox_id)
+}
+
+/// OOM kills in a sandbox's cgroup so far, from `memory.events`; 0 when the
+/// cgroup can't be read
+pub async fn oom_kills(sandbox_id: Uuid) -> u64 {
+    let path = PathBuf::from(CGROUP_ROOT)
+        .join(CGROUP_PARENT)
+        .join(sandbox_id.to_string())
+        .join("memory.events");
+    match tokio::fs::read_to_string(path).await {
+        Ok(events) => parse_oom_kills(&events),
+        Err(_) => 0,
+    }
+}
+
+fn parse_oom_kills(events: &str) -> u64 {
+    events
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill ")?.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Why a workload stopped, from its exit code or the signal that killed it.
+/// `oom_killed` is whether the sandbox saw an OOM kill while it ran.
+pub fn classify(code: Option<i32>, signal: Option<i32>, oom_killed: bool) -> ExitReason {
+    // Shells and runtimes pass a signal on as 128+signal
+    let signal = signal.or_else(|| code.filter(|code| (129..=192).contains(code)).map(|code| code - 128));
+    match (code, signal) {
+        (Some(0), _) => ExitReason::Completed,
+        _ if oom_killed => ExitReason::OomKilled,
+        (Some(TIMEOUT_EXIT_CODE), None) => ExitReason::Timeout,
+        (_, Some(_)) | (None, None) => ExitReason::Crashed,
+        // -1 is what runtimes report when they lost the process
+        (Some(code), None) if code < 0 => ExitReason::Crashed,
+        (Some(_), None) => ExitReason::Completed,
+    }
+}
+
+/// Why a runtime CLI's workload stopped
+pub fn of_status(status: ExitStatus, oom_killed: bool) -> ExitReason {
+    classify(status.code(), status.signal(), oom_killed)
+}
+
+/// Why a workload stopped when only its exit code is known, as with remote
+/// providers
+pub fn of_code(code: i32) -> ExitReason {
+    classify(Some(code), None, false)
+}
+
+/// Why a sandbox in `state` stopped; `None` while it can still run
+pub fn of_state(state: SandboxState, oom_killed: bool) -> Option<ExitReason> {
+    match state {
+        SandboxState::Creating
+        | SandboxState::Starting
+        | SandboxState::Running
+        | SandboxState::Paused
+        | SandboxState::Stopping => None,
+        SandboxState::Preempted => Some(ExitReason::Preempted),
+        _ if oom_killed => Some(ExitReason::OomKilled),
+        SandboxState::Stopped => Some(ExitReason::Completed),
+        SandboxState::Failed => Some(ExitReason::Crashed),
+    }
+}
+
+/// What a VM's guest kernel logged to its console
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GuestEvents {
+    pub oom_killed: bool,
+    pub panicked: bool,
+}
+
+/// Length of a guest console log, to read only what's logged after it
+pub async fn console_len(path: &Path) -> u64 {
+    tokio::fs::metadata(path).await.map(|meta| meta.len()).unwrap_or(0)
+}
+
+/// What the guest logged to its console from `offset` on
+pub async fn guest_events_since(path: &Path, offset: u64) -> GuestEvents {
+    let read = async {
+        let mut file = tokio::fs::File::open(path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut logged = Vec::new();
+        file.read_to_end(&mut logged).await?;
+        std::io::Result::Ok(logged)
+    };
+    match read.await {
+        Ok(logged) => guest_events(&String::from_utf8_lossy(&logged)),
+        Err(_) => GuestEvents::default(),
+    }
+}
+
+fn guest_events(console: &str) -> GuestEvents {
+    GuestEvents {
+        oom_killed: console.contains(GUEST_OOM_KILL),
+        panicked: console.contains(GUEST_PANIC),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_oom_kills_timeouts_and_crashes() {
+        assert_eq!(of_code(0), ExitReason::Completed);
+        assert_eq!(of_code(1), ExitReason::Completed);
+        assert_eq!(of_code(124), ExitReason::Timeout);
+        assert_eq!(of_code(-1), ExitReason::Crashed);
+        // SIGSEGV passed on by a shell, and SIGKILL by the OOM killer
+        assert_eq!(of_code(139), ExitReason::Crashed);
+        assert_eq!(classify(Some(137), None, true), ExitReason::OomKilled);
+        assert_eq!(of_status(ExitStatus::from_raw(libc::SIGKILL), true), ExitReason::OomKilled);
+        assert_eq!(of_status(ExitStatus::from_raw(libc::SIGSEGV), false), ExitReason::Crashed);
+        // An OOM kill of some other process doesn't fail a clean exit
+        assert_eq!(of_status(ExitStatus::from_raw(0), true), ExitReason::Completed);
+
+        assert_eq!(of_state(SandboxState::Running, true), None);
+        assert_eq!(of_state(SandboxState::Stopped, true), Some(ExitReason::OomKilled));
+        assert_eq!(of_state(SandboxState::Failed, false), Some(ExitReason::Crashed));
+        assert_eq!(of_state(SandboxState::Preempted, false), Some(ExitReason::Preempted));
+    }
+
+    #[test]
+    fn reads_oom_kills_and_guest_panics() {
+        let events = "low 0\nhigh 0\nmax 12\noom 3\noom_kill 2\noom_group_kill 0\n";
+        assert_eq!(parse_oom_kills(events), 2);
+        assert_eq!(parse_oom_kills(""), 0);
+
+        let console = "[   12.3] Out of memory: Killed process 412 (python3)\n\
+                       [   13.0] Kernel panic - not syncing: Fatal exception\n";
+        assert_eq!(
+            guest_events(console),
+            GuestEvents {
+                oom_killed: true,
+                panicked: true
+            }
+        );
+        assert_eq!(guest_events("[    0.0] Booting Linux\n"), GuestEvents::default());
+    }
+}
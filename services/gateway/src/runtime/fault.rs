@@ -0,0 +1,261 @@
+//! Failure injection for testing clients against the gateway. Never enable
+//! this in production: injected failures look like real runtime errors.
+
+use super::*;
+use anyhow::{bail, Context};
+use std::sync::atomic::AtomicU64;
+use tracing::{debug, warn};
+
+/// Faults injected into one kind of runtime call
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OperationFaults {
+    /// Fraction of calls, 0–1, that fail without reaching the runtime
+    pub failure_rate: f64,
+    /// Delay added to every call
+    pub latency_ms: u64,
+    /// Extra random delay, up to this much, added on top of `latency_ms`
+    pub jitter_ms: u64,
+}
+
+impl OperationFaults {
+    fn validate(&self, operation: &str) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.failure_rate) {
+            bail!("{}.failure_rate must be between 0 and 1", operation);
+        }
+        Ok(())
+    }
+}
+
+/// Which runtimes get faults, and what kind, from `GATEWAY_FAULTS`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaultConfig {
+    /// Runtimes to wrap; every runtime when empty
+    pub runtimes: Vec<RuntimeType>,
+    pub create: OperationFaults,
+    pub exec: OperationFaults,
+    pub snapshot: OperationFaults,
+    pub resume: OperationFaults,
+    /// Seed for reproducible failure sequences
+    pub seed: Option<u64>,
+}
+
+impl FaultConfig {
+    /// Faults described by the JSON in `GATEWAY_FAULTS`, e.g.
+    /// `{"runtimes": ["gvisor"], "create": {"failure_rate": 0.2}}`; `None`
+    /// when unset
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(value) = std::env::var("GATEWAY_FAULTS") else {
+            return Ok(None);
+        };
+        let config: Self = serde_json::from_str(&value).context("invalid GATEWAY_FAULTS")?;
+        config.create.validate("create")?;
+        config.exec.validate("exec")?;
+        config.snapshot.validate("snapshot")?;
+        config.resume.validate("resume")?;
+        Ok(Some(config))
+    }
+
+    /// Whether sandboxes on this runtime get faults
+    pub fn applies_to(&self, runtime_type: RuntimeType) -> bool {
+        self.runtimes.is_empty() || self.runtimes.contains(&runtime_type)
+    }
+}
+
+/// Runtime wrapper that delays or fails calls before passing them on
+pub struct FaultInjectingRuntime {
+    inner: Arc<dyn SandboxRuntime>,
+    config: FaultConfig,
+    rng: AtomicU64,
+}
+
+impl FaultInjectingRuntime {
+    pub fn new(inner: Arc<dyn SandboxRuntime>, config: FaultConfig) -> Self {
+        let seed = config.seed.unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_nanos() as u64)
+                .unwrap_or_default()
+        });
+        warn!(
+            runtime = ?inner.runtime_type(),
+            "Fault injection enabled; calls to this runtime may fail on purpose"
+        );
+        Self {
+            inner,
+            config,
+            rng: AtomicU64::new(seed),
+        }
+    }
+
+    /// Uniform sample from [0, 1), from a splitmix64 sequence
+    fn sample(&self) -> f64 {
+        let mut z = self
+            .rng
+            .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+            .wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Apply an operation's latency, then fail it if its failure rate says so
+    async fn inject(&self, operation: &str, faults: &OperationFaults) -> Result<()> {
+        let jitter = (self.sample() * faults.jitter_ms as f64) as u64;
+        let delay = Duration::from_millis(faults.latency_ms + jitter);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+
+        if faults.failure_rate > 0.0 && self.sample() < faults.failure_rate {
+            debug!(runtime = ?self.inner.runtime_type(), operation, "Injecting fault");
+            bail!(
+                "injected fault: {} on {:?} failed",
+                operation,
+                self.inner.runtime_type()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SandboxRuntime for FaultInjectingRuntime {
+    fn runtime_type(&self) -> RuntimeType {
+        self.inner.runtime_type()
+    }
+
+    fn supports_isolation_level(&self, level: IsolationLevel) -> bool {
+        self.inner.supports_isolation_level(level)
+    }
+
+    fn supports_execution_mode(&self, mode: ExecutionMode) -> bool {
+        self.inner.supports_execution_mode(mode)
+    }
+
+    fn supports_sysctls(&self) -> bool {
+        self.inner.supports_sysctls()
+    }
+
+    fn supports_rootfs_layers(&self) -> bool {
+        self.inner.supports_rootfs_layers()
+    }
+
+    fn supports_arch(&self, arch: Arch) -> bool {
+        self.inner.supports_arch(arch)
+    }
+
+    fn validate(&self, config: &SandboxConfig) -> Result<()> {
+        self.inner.validate(config)
+    }
+
+    async fn create(&self, config: &SandboxConfig) -> Result<Uuid> {
+        self.inject("create", &self.config.create).await?;
+        self.inner.create(config).await
+    }
+
+    fn supports_warm_start(&self) -> bool {
+        self.inner.supports_warm_start()
+    }
+
+    async fn start_workload(&self, sandbox_id: Uuid, config: &SandboxConfig) -> Result<()> {
+        self.inner.start_workload(sandbox_id, config).await
+    }
+
+    async fn exec(
+        &self,
+        sandbox_id: Uuid,
+        command: Vec<String>,
+        environment: Option<HashMap<String, String>>,
+        options: &ExecOptions,
+    ) -> Result<SandboxResult> {
+        self.inject("exec", &self.config.exec).await?;
+        self.inner.exec(sandbox_id, command, environment, options).await
+    }
+
+    async fn exec_streaming(
+        &self,
+        sandbox_id: Uuid,
+        command: Vec<String>,
+        environment: Option<HashMap<String, String>>,
+        options: &ExecOptions,
+        input: Option<ExecInputReceiver>,
+        output: ExecOutputSender,
+    ) -> Result<SandboxResult> {
+        self.inject("exec", &self.config.exec).await?;
+        self.inner
+            .exec_streaming(sandbox_id, command, environment, options, input, output)
+            .await
+    }
+
+    // Teardown is never faulted, so tests always clean up after themselves
+    async fn destroy(&self, sandbox_id: Uuid) -> Result<()> {
+        self.inner.destroy(sandbox_id).await
+    }
+
+    async fn snapshot(&self, sandbox_id: Uuid) -> Result<SandboxSnapshot> {
+        self.inject("snapshot", &self.config.snapshot).await?;
+        self.inner.snapshot(sandbox_id).await
+    }
+
+    async fn resume(&self, snapshot: &SandboxSnapshot) -> Result<Uuid> {
+        self.inject("resume", &self.config.resume).await?;
+        self.inner.resume(snapshot).await
+    }
+
+    async fn export_filesystem(&self, sandbox_id: Uuid) -> Result<Vec<u8>> {
+        self.inject("snapshot", &self.config.snapshot).await?;
+        self.inner.export_filesystem(sandbox_id).await
+    }
+
+    async fn status(&self, sandbox_id: Uuid) -> Result<SandboxStatus> {
+        self.inner.status(sandbox_id).await
+    }
+
+    async fn set_frozen(&self, sandbox_id: Uuid, frozen: bool) -> Result<()> {
+        self.inner.set_frozen(sandbox_id, frozen).await
+    }
+
+    async fn network_interface(&self, sandbox_id: Uuid) -> Option<String> {
+        self.inner.network_interface(sandbox_id).await
+    }
+
+    async fn logs(&self, sandbox_id: Uuid, follow: bool) -> Result<Box<dyn tokio::io::AsyncRead + Send + Unpin>> {
+        self.inner.logs(sandbox_id, follow).await
+    }
+
+    async fn active_sandboxes(&self) -> usize {
+        self.inner.active_sandboxes().await
+    }
+
+    fn is_remote(&self) -> bool {
+        self.inner.is_remote()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_validates_config() {
+        let config: FaultConfig = serde_json::from_str(
+            r#"{"runtimes": ["kata"], "exec": {"failure_rate": 0.5, "latency_ms": 200}}"#,
+        )
+        .unwrap();
+        assert!(config.applies_to(RuntimeType::Kata));
+        assert!(!config.applies_to(RuntimeType::Gvisor));
+        assert_eq!(config.exec.latency_ms, 200);
+        assert_eq!(config.create, OperationFaults::default());
+        assert!(FaultConfig::default().applies_to(RuntimeType::Modal));
+
+        let rates = OperationFaults {
+            failure_rate: 1.5,
+            ..Default::default()
+        };
+        assert!(rates.validate("create").is_err());
+        assert!(serde_json::from_str::<FaultConfig>(r#"{"crate": {}}"#).is_err());
+    }
+}
@@ -1,11 +1,58 @@
 use super::*;
 use anyhow::{Context, Result};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::process::Stdio;
 use tokio::process::Command;
 use tracing::{error, info, warn};
 
+use super::lifecycle::{Lifecycle, LifecycleEvents};
+use crate::images::{BootSelection, ImageRegistry};
+
+/// How long a launched VM has to open its API socket before it counts as
+/// failed to start
+const API_READY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Kernel and root filesystem booted when none is registered for the
+/// host's architecture. Firecracker boots an uncompressed `vmlinux` on
+/// x86_64 and a PE `Image` on aarch64.
+fn default_images(arch: Arch) -> (&'static str, &'static str) {
+    match arch {
+        Arch::X86_64 => (
+            "/var/lib/firecracker/kernels/vmlinux",
+            "/var/lib/firecracker/images/rootfs.ext4",
+        ),
+        Arch::Aarch64 => (
+            "/var/lib/firecracker/kernels/aarch64/Image",
+            "/var/lib/firecracker/images/aarch64/rootfs.ext4",
+        ),
+    }
+}
+
+/// CPU options for every VM, from `GATEWAY_FIRECRACKER_CPU_TEMPLATE`,
+/// `GATEWAY_FIRECRACKER_SMT` and `GATEWAY_FIRECRACKER_MASK_CPU_BRAND`
+pub fn options_from_env() -> Result<FirecrackerOptions> {
+    fn var<T: serde::de::DeserializeOwned>(name: &str) -> Result<Option<T>> {
+        let Ok(value) = std::env::var(name) else {
+            return Ok(None);
+        };
+        let value = match value.as_str() {
+            "1" | "true" | "yes" | "on" => serde_json::Value::Bool(true),
+            "0" | "false" | "no" | "off" => serde_json::Value::Bool(false),
+            other => serde_json::Value::String(other.to_uppercase()),
+        };
+        serde_json::from_value(value)
+            .map(Some)
+            .with_context(|| format!("invalid {}", name))
+    }
+
+    Ok(FirecrackerOptions {
+        cpu_template: var("GATEWAY_FIRECRACKER_CPU_TEMPLATE")?,
+        smt: var("GATEWAY_FIRECRACKER_SMT")?,
+        mask_cpu_brand: var("GATEWAY_FIRECRACKER_MASK_CPU_BRAND")?,
+    })
+}
+
 /// Firecracker runtime implementation for maximum isolation
 pub struct FirecrackerRuntime {
     /// Path to firecracker binary
@@ -16,6 +63,20 @@ pub struct FirecrackerRuntime {
     base_dir: PathBuf,
     /// Active sandboxes
     sandboxes: RwLock<HashMap<Uuid, SandboxInfo>>,
+    /// Sandboxes with a TAP device set up, or being set up
+    taps: tokio::sync::Mutex<HashSet<Uuid>>,
+    /// Leaked TAP devices found by sweeps
+    leaks: Option<prometheus::Counter>,
+    /// Where sandbox state changes are sent
+    events: LifecycleEvents,
+    /// Registered kernels and root filesystems VMs boot from
+    images: Option<Arc<ImageRegistry>>,
+    /// Architecture of the host, and so of the guest kernels
+    arch: Arch,
+    /// The host's CPU, which CPU templates and SMT depend on
+    cpu: cpu::HostCpu,
+    /// CPU options for VMs whose requests don't set them
+    defaults: FirecrackerOptions,
 }
 
 #[derive(Debug, Clone)]
@@ -23,10 +84,8 @@ struct SandboxInfo {
     pid: u32,
     socket_path: PathBuf,
     root_dir: PathBuf,
-    state: SandboxState,
+    lifecycle: Lifecycle,
     config: SandboxConfig,
-    created_at: chrono::DateTime<chrono::Utc>,
-    started_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 impl FirecrackerRuntime {
@@ -43,116 +102,274 @@ impl FirecrackerRuntime {
         // Create base directory if it doesn't exist
         std::fs::create_dir_all(&base_dir)
             .context("Failed to create base directory")?;
+        let arch = arch::detect()?;
 
         Ok(Self {
             firecracker_bin,
             jailer_bin,
             base_dir,
             sandboxes: RwLock::new(HashMap::new()),
+            taps: tokio::sync::Mutex::new(HashSet::new()),
+            leaks: None,
+            events: LifecycleEvents::new(),
+            images: None,
+            arch,
+            cpu: cpu::HostCpu::detect(arch),
+            defaults: FirecrackerOptions::default(),
         })
     }
 
+    /// Apply these CPU options to VMs whose requests leave them unset.
+    /// Fails if the host can't honour them.
+    pub fn with_defaults(mut self, defaults: FirecrackerOptions) -> Result<Self> {
+        self.check_support(&defaults)?;
+        self.defaults = defaults;
+        Ok(self)
+    }
+
+    /// Fail if the host CPU can't honour these options
+    fn check_support(&self, options: &FirecrackerOptions) -> Result<()> {
+        if let Some(reason) = options
+            .cpu_template
+            .and_then(|template| self.cpu.template_unsupported(template))
+        {
+            anyhow::bail!(reason);
+        }
+        if options.smt == Some(true) {
+            if self.cpu.arch != Arch::X86_64 {
+                anyhow::bail!("SMT is only available to x86_64 guests");
+            }
+            if !self.cpu.smt_active {
+                anyhow::bail!("SMT needs a host with SMT enabled");
+            }
+        }
+        if options.mask_cpu_brand == Some(true) {
+            if self.cpu.arch != Arch::X86_64 {
+                anyhow::bail!("aarch64 guests have no CPU brand string to mask");
+            }
+            if options.cpu_template.is_some() {
+                anyhow::bail!(
+                    "mask_cpu_brand can't be combined with cpu_template; Firecracker applies one CPU template per VM"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The CPU options a sandbox's VM gets. Maximum isolation VMs never
+    /// share cores through SMT unless they ask to.
+    fn effective_options(&self, config: &SandboxConfig) -> FirecrackerOptions {
+        let mut options = config.firecracker.or(self.defaults);
+        if config.isolation_level == IsolationLevel::Maximum && config.firecracker.smt.is_none() {
+            options.smt = Some(false);
+        }
+        options
+    }
+
+    /// Send sandbox state changes to these subscribers
+    pub fn with_lifecycle_events(mut self, events: LifecycleEvents) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Boot VMs from the newest verified images in this registry
+    pub fn with_images(mut self, images: Arc<ImageRegistry>) -> Self {
+        self.images = Some(images);
+        self
+    }
+
+    /// Kernel and root filesystem for a VM running the sandbox's language
+    async fn select_images(&self, config: &SandboxConfig) -> Result<BootSelection> {
+        let Some(images) = &self.images else {
+            return Ok(BootSelection { kernel: None, rootfs: None });
+        };
+        let language = config.image.rsplit('/').next().unwrap_or(&config.image);
+        let selection = images
+            .select(self.arch, language)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        images.pin(config.id, &selection).await;
+        Ok(selection)
+    }
+
     /// Build VM configuration
-    async fn build_vm_config(&self, config: &SandboxConfig) -> Result<serde_json::Value> {
+    async fn build_vm_config(&self, config: &SandboxConfig, images: &BootSelection) -> Result<serde_json::Value> {
         let vcpu_count = config.cpu_limit.map(|cpu| cpu.ceil() as u64).unwrap_or(1);
         let mem_size_mib = config.memory_limit
             .map(|mem| (mem / (1024 * 1024)).max(128))
             .unwrap_or(512);
 
-        Ok(serde_json::json!({
+        let read_only = config.execution_mode == ExecutionMode::ReadOnly;
+        let mut boot_args = "console=ttyS0 reboot=k panic=1 pci=off".to_string();
+        if read_only {
+            boot_args.push(' ');
+            boot_args.push_str(&read_only::kernel_args(config));
+        }
+        if !config.sysctls.is_empty() {
+            boot_args.push(' ');
+            boot_args.push_str(&sysctl::kernel_args(&config.sysctls));
+        }
+
+        let options = self.effective_options(config);
+        let (default_kernel, default_rootfs) = default_images(self.arch);
+        let kernel = images.kernel.as_ref().map_or(PathBuf::from(default_kernel), |(_, path)| path.clone());
+        let rootfs = images.rootfs.as_ref().map_or(PathBuf::from(default_rootfs), |(_, path)| path.clone());
+        let mut vm_config = serde_json::json!({
             "boot-source": {
-                "kernel_image_path": "/var/lib/firecracker/kernels/vmlinux",
-                "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                "kernel_image_path": kernel,
+                "boot_args": boot_args
             },
             "drives": [{
                 "drive_id": "rootfs",
-                "path_on_host": "/var/lib/firecracker/images/rootfs.ext4",
+                "path_on_host": rootfs,
                 "is_root_device": true,
-                "is_read_only": false
+                "is_read_only": read_only
             }],
             "machine-config": {
                 "vcpu_count": vcpu_count,
                 "mem_size_mib": mem_size_mib,
-                "smt": false,
+                "smt": options.smt.unwrap_or(false),
                 "track_dirty_pages": false
             },
-            "network-interfaces": [{
-                "iface_id": "eth0",
-                "guest_mac": "06:00:00:00:00:01",
-                "host_dev_name": format!("tap{}", config.id.simple())
-            }],
             "actions": {
                 "action_type": "InstanceStart"
             }
-        }))
-    }
+        });
 
-    /// Setup networking for the VM
-    async fn setup_networking(&self, sandbox_id: Uuid) -> Result<()> {
-        let tap_name = format!("tap{}", sandbox_id.simple());
-        
-        // Create TAP interface
-        Command::new("ip")
-            .args(["tuntap", "add", &tap_name, "mode", "tap"])
-            .status()
-            .await
-            .context("Failed to create TAP interface")?;
+        if let Some(template) = options.cpu_template {
+            vm_config["machine-config"]["cpu_template"] = serde_json::json!(cpu::name(template));
+        }
 
-        // Bring interface up
-        Command::new("ip")
-            .args(["link", "set", &tap_name, "up"])
-            .status()
-            .await
-            .context("Failed to bring TAP interface up")?;
+        // Read-only VMs get no network device at all
+        if !read_only {
+            vm_config["network-interfaces"] = serde_json::json!([{
+                "iface_id": "eth0",
+                "guest_mac": "06:00:00:00:00:01",
+                "host_dev_name": tap_name(config.id)
+            }]);
+        }
 
-        // Add to bridge
-        Command::new("ip")
-            .args(["link", "set", &tap_name, "master", "virbr0"])
-            .status()
-            .await
-            .context("Failed to add TAP to bridge")?;
+        // A VM booted ahead of its run gets the run's command from the
+        // metadata service once it's handed out
+        if config.command.is_empty() {
+            vm_config["mmds-config"] = serde_json::json!({
+                "version": "V2",
+                "network_interfaces": ["eth0"]
+            });
+        }
 
-        Ok(())
+        Ok(vm_config)
     }
 
-    /// Cleanup networking
-    async fn cleanup_networking(&self, sandbox_id: Uuid) -> Result<()> {
-        let tap_name = format!("tap{}", sandbox_id.simple());
-        
-        Command::new("ip")
-            .args(["link", "delete", &tap_name])
-            .status()
-            .await
-            .ok(); // Ignore errors during cleanup
+    /// Set up the VM's TAP device. Safe to repeat: a leftover device with the
+    /// same name is replaced, and a failed setup removes what it created.
+    async fn setup_networking(&self, sandbox_id: Uuid) -> Result<()> {
+        let tap = tap_name(sandbox_id);
+        // Claim the device first, so a concurrent sweep leaves it alone
+        self.taps.lock().await.insert(sandbox_id);
+        delete_tap(&tap).await?;
+
+        let alias = format!("{}{}", TAP_ALIAS_PREFIX, sandbox_id);
+        let result = async {
+            ip(&["tuntap", "add", "dev", &tap, "mode", "tap"]).await?;
+            ip(&["link", "set", "dev", &tap, "alias", &alias]).await?;
+            ip(&["link", "set", "dev", &tap, "up"]).await?;
+            ip(&["link", "set", "dev", &tap, "master", BRIDGE]).await
+        }
+        .await;
 
+        if let Err(e) = result {
+            self.cleanup_networking(sandbox_id).await;
+            return Err(e.context("Failed to set up TAP device"));
+        }
         Ok(())
     }
-}
 
-#[async_trait]
-impl SandboxRuntime for FirecrackerRuntime {
-    fn runtime_type(&self) -> RuntimeType {
-        RuntimeType::Firecracker
+    /// Remove the VM's TAP device, if it has one. A device that can't be
+    /// removed is left for the next sweep.
+    async fn cleanup_networking(&self, sandbox_id: Uuid) {
+        let tap = tap_name(sandbox_id);
+        if let Err(e) = delete_tap(&tap).await {
+            warn!("Failed to remove TAP device {}, leaving it for the next sweep: {}", tap, e);
+        }
+        self.taps.lock().await.remove(&sandbox_id);
     }
 
-    fn supports_isolation_level(&self, level: IsolationLevel) -> bool {
-        // Firecracker provides maximum isolation through hardware virtualization
-        matches!(level, IsolationLevel::Maximum | IsolationLevel::Strong)
+    /// Count leaked TAP devices on this counter
+    pub fn with_leak_counter(mut self, leaks: prometheus::Counter) -> Self {
+        self.leaks = Some(leaks);
+        self
     }
 
-    async fn create(&self, config: &SandboxConfig) -> Result<Uuid> {
-        let sandbox_id = config.id;
-        let sandbox_dir = self.base_dir.join(sandbox_id.to_string());
-        std::fs::create_dir_all(&sandbox_dir)?;
+    /// Remove TAP devices this gateway created for sandboxes it no longer
+    /// knows, such as those left by a crash or a failed teardown. Returns
+    /// how many were removed.
+    pub async fn sweep_orphaned_taps(&self) -> usize {
+        let devices = match owned_taps().await {
+            Ok(devices) => devices,
+            Err(e) => {
+                warn!("Failed to list TAP devices: {}", e);
+                return 0;
+            }
+        };
 
-        // Setup networking
-        self.setup_networking(sandbox_id).await?;
+        // Held throughout, so no device is claimed mid-sweep
+        let taps = self.taps.lock().await;
+        let mut removed = 0;
+        for (tap, sandbox_id) in orphans(devices, &taps) {
+            if let Some(leaks) = &self.leaks {
+                leaks.inc();
+            }
+            match delete_tap(&tap).await {
+                Ok(_) => {
+                    warn!(%sandbox_id, "Removed leaked TAP device {}", tap);
+                    removed += 1;
+                }
+                Err(e) => error!(%sandbox_id, "Failed to remove leaked TAP device {}: {}", tap, e),
+            }
+        }
+        removed
+    }
+
+    /// Sweep for leaked TAP devices every `GATEWAY_TAP_SWEEP_INTERVAL_SECS`
+    /// (default 300; 0 turns periodic sweeps off)
+    pub fn spawn_tap_sweeper(self: Arc<Self>) {
+        let interval = std::env::var("GATEWAY_TAP_SWEEP_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(300);
+        if interval == 0 {
+            return;
+        }
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                self.sweep_orphaned_taps().await;
+            }
+        });
+    }
+
+    /// Start the VM, returning the jailer's PID and the API socket path
+    async fn launch(&self, config: &SandboxConfig, sandbox_dir: &std::path::Path) -> Result<(u32, PathBuf)> {
+        let sandbox_id = config.id;
 
         // Create socket path
         let socket_path = sandbox_dir.join("firecracker.sock");
         
         // Build VM configuration
-        let vm_config = self.build_vm_config(config).await?;
+        let images = self.select_images(config).await?;
+        let mut vm_config = self.build_vm_config(config, &images).await?;
+        if self.effective_options(config).mask_cpu_brand == Some(true) {
+            let template_path = sandbox_dir.join("cpu-template.json");
+            std::fs::write(
+                &template_path,
+                serde_json::to_string_pretty(&cpu::masked_brand_template())?,
+            )?;
+            vm_config["cpu-config"] = serde_json::json!(template_path);
+        }
         let config_path = sandbox_dir.join("config.json");
         std::fs::write(&config_path, serde_json::to_string_pretty(&vm_config)?)?;
 
@@ -169,21 +386,179 @@ impl SandboxRuntime for FirecrackerRuntime {
             "--config-file", config_path.to_str().unwrap(),
         ]);
 
-        cmd.stdout(Stdio::piped());
+        // The guest's serial console comes out on Firecracker's stdout
+        let console = std::fs::File::create(sandbox_dir.join("console.log"))?;
+        cmd.stdout(console);
         cmd.stderr(Stdio::piped());
 
         let child = cmd.spawn().context("Failed to spawn Firecracker")?;
         let pid = child.id().ok_or_else(|| anyhow::anyhow!("Failed to get PID"))?;
+        Ok((pid, socket_path))
+    }
+
+    /// Wait for a launched VM to open its API socket, failing if its
+    /// process exits first or it takes too long
+    async fn wait_until_ready(pid: u32, socket_path: &std::path::Path) -> Result<()> {
+        let deadline = Instant::now() + API_READY_TIMEOUT;
+        loop {
+            if socket_path.exists() {
+                return Ok(());
+            }
+            if !process_alive(pid) {
+                anyhow::bail!("Firecracker process {} exited before the VM started", pid);
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!("VM API socket did not appear within {:?}", API_READY_TIMEOUT);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+/// Whether a process is still running
+fn process_alive(pid: u32) -> bool {
+    std::path::Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Bridge VM TAP devices are attached to
+const BRIDGE: &str = "virbr0";
+
+/// Interface alias marking a TAP device as this gateway's, followed by the
+/// sandbox ID
+const TAP_ALIAS_PREFIX: &str = "sandstorm:";
+
+/// TAP device name for a sandbox, within the kernel's 15-character limit
+fn tap_name(sandbox_id: Uuid) -> String {
+    format!("tap{}", &sandbox_id.simple().to_string()[..12])
+}
+
+/// Run `ip`, failing with its error output
+async fn ip(args: &[&str]) -> Result<()> {
+    let output = Command::new("ip")
+        .args(args)
+        .output()
+        .await
+        .context("Failed to run ip")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "ip {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Delete a network device if it exists; false if there was none
+async fn delete_tap(tap: &str) -> Result<bool> {
+    if !std::path::Path::new("/sys/class/net").join(tap).exists() {
+        return Ok(false);
+    }
+    ip(&["link", "delete", "dev", tap]).await?;
+    Ok(true)
+}
+
+/// Network devices carrying this gateway's alias, with the sandbox each
+/// was created for
+async fn owned_taps() -> Result<Vec<(String, Uuid)>> {
+    let mut devices = Vec::new();
+    let mut entries = tokio::fs::read_dir("/sys/class/net").await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let Ok(alias) = tokio::fs::read_to_string(entry.path().join("ifalias")).await else {
+            continue;
+        };
+        let Some(sandbox_id) = alias
+            .trim()
+            .strip_prefix(TAP_ALIAS_PREFIX)
+            .and_then(|id| id.parse().ok())
+        else {
+            continue;
+        };
+        devices.push((entry.file_name().to_string_lossy().into_owned(), sandbox_id));
+    }
+    Ok(devices)
+}
+
+/// Devices whose sandbox isn't one of `known`
+fn orphans(devices: Vec<(String, Uuid)>, known: &HashSet<Uuid>) -> Vec<(String, Uuid)> {
+    devices
+        .into_iter()
+        .filter(|(_, sandbox_id)| !known.contains(sandbox_id))
+        .collect()
+}
+
+#[async_trait]
+impl SandboxRuntime for FirecrackerRuntime {
+    fn runtime_type(&self) -> RuntimeType {
+        RuntimeType::Firecracker
+    }
+
+    fn supports_isolation_level(&self, level: IsolationLevel) -> bool {
+        // Firecracker provides maximum isolation through hardware virtualization
+        matches!(level, IsolationLevel::Maximum | IsolationLevel::Strong)
+    }
+
+    fn supports_execution_mode(&self, _mode: ExecutionMode) -> bool {
+        true
+    }
+
+    fn supports_sysctls(&self) -> bool {
+        true
+    }
+
+    fn validate(&self, config: &SandboxConfig) -> Result<()> {
+        if config.isolation_level == IsolationLevel::Maximum && config.firecracker.smt == Some(true) {
+            anyhow::bail!("Maximum isolation sandboxes can't share cores through SMT");
+        }
+        if config.command.is_empty() && config.execution_mode == ExecutionMode::ReadOnly {
+            anyhow::bail!("Read-only VMs have no network for a warm start's metadata service");
+        }
+        self.check_support(&self.effective_options(config))
+    }
+
+    async fn create(&self, config: &SandboxConfig) -> Result<Uuid> {
+        let sandbox_id = config.id;
+        let sandbox_dir = self.base_dir.join(sandbox_id.to_string());
+        std::fs::create_dir_all(&sandbox_dir)?;
+
+        // Setup networking
+        if config.execution_mode != ExecutionMode::ReadOnly {
+            self.setup_networking(sandbox_id).await?;
+        }
+
+        // Don't leave the TAP device or VM directory behind if the VM
+        // doesn't start
+        let mut lifecycle = Lifecycle::new(sandbox_id, RuntimeType::Firecracker, &self.events);
+        let launched = async {
+            lifecycle.transition(SandboxState::Starting)?;
+            let (pid, socket_path) = self.launch(config, &sandbox_dir).await?;
+            Self::wait_until_ready(pid, &socket_path).await?;
+            lifecycle.transition(SandboxState::Running)?;
+            Ok((pid, socket_path))
+        }
+        .await;
+        let (pid, socket_path) = match launched {
+            Ok(launched) => launched,
+            Err(e) => {
+                lifecycle.transition(SandboxState::Failed).ok();
+                self.cleanup_networking(sandbox_id).await;
+                if let Some(images) = &self.images {
+                    images.unpin(sandbox_id).await;
+                }
+                if let Err(e) = tokio::fs::remove_dir_all(&sandbox_dir).await {
+                    error!("Failed to remove sandbox directory: {}", e);
+                }
+                return Err(e);
+            }
+        };
 
         // Store sandbox info
         let info = SandboxInfo {
             pid,
             socket_path,
             root_dir: sandbox_dir,
-            state: SandboxState::Running,
+            lifecycle,
             config: config.clone(),
-            created_at: chrono::Utc::now(),
-            started_at: Some(chrono::Utc::now()),
         };
 
         let mut sandboxes = self.sandboxes.write().await;
@@ -193,17 +568,64 @@ impl SandboxRuntime for FirecrackerRuntime {
         Ok(sandbox_id)
     }
 
+    fn supports_warm_start(&self) -> bool {
+        true
+    }
+
+    async fn start_workload(&self, sandbox_id: Uuid, config: &SandboxConfig) -> Result<()> {
+        let mut sandboxes = self.sandboxes.write().await;
+        let info = sandboxes.get_mut(&sandbox_id)
+            .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
+        if !info.config.command.is_empty() {
+            anyhow::bail!("Sandbox {} is already running its workload", sandbox_id);
+        }
+        if info.lifecycle.state() != SandboxState::Running || !process_alive(info.pid) {
+            anyhow::bail!("Sandbox {} is not running", sandbox_id);
+        }
+
+        // The guest agent polls the metadata service for its workload
+        let workload = serde_json::json!({
+            "sandstorm": {
+                "command": config.command,
+                "environment": config.environment,
+            }
+        });
+        let output = Command::new("curl")
+            .args(["--silent", "--show-error", "--fail", "--unix-socket"])
+            .arg(&info.socket_path)
+            .args(["-X", "PUT", "http://localhost/mmds", "-H", "Content-Type: application/json"])
+            .args(["-d", &workload.to_string()])
+            .output()
+            .await
+            .context("Failed to call the Firecracker API")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to hand sandbox {} its workload: {}",
+                sandbox_id,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        info.config.command = config.command.clone();
+        info.config.environment = config.environment.clone();
+        info!("Started the workload of warm sandbox {}", sandbox_id);
+        Ok(())
+    }
+
     async fn exec(
         &self,
         sandbox_id: Uuid,
         _command: Vec<String>,
         _environment: Option<HashMap<String, String>>,
+        options: &ExecOptions,
     ) -> Result<SandboxResult> {
         let sandboxes = self.sandboxes.read().await;
         let info = sandboxes.get(&sandbox_id)
             .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
+        // Overrides need the guest agent that will run commands in the VM
+        options.check(RuntimeType::Firecracker, ExecSupport::default())?;
 
-        if info.state != SandboxState::Running {
+        if info.lifecycle.state() != SandboxState::Running {
             anyhow::bail!("Sandbox {} is not running", sandbox_id);
         }
 
@@ -227,13 +649,16 @@ impl SandboxRuntime for FirecrackerRuntime {
                 network_rx_bytes: 0,
                 network_tx_bytes: 0,
             },
+            exit_reason: ExitReason::Completed,
         })
     }
 
     async fn destroy(&self, sandbox_id: Uuid) -> Result<()> {
         let mut sandboxes = self.sandboxes.write().await;
         
-        if let Some(info) = sandboxes.remove(&sandbox_id) {
+        if let Some(mut info) = sandboxes.remove(&sandbox_id) {
+            info.lifecycle.transition(SandboxState::Stopping).ok();
+
             // Kill the Firecracker process
             if let Err(e) = Command::new("kill")
                 .args(["-9", &info.pid.to_string()])
@@ -244,13 +669,17 @@ impl SandboxRuntime for FirecrackerRuntime {
             }
 
             // Cleanup networking
-            self.cleanup_networking(sandbox_id).await?;
+            self.cleanup_networking(sandbox_id).await;
+            if let Some(images) = &self.images {
+                images.unpin(sandbox_id).await;
+            }
 
             // Remove sandbox directory
             if let Err(e) = tokio::fs::remove_dir_all(&info.root_dir).await {
                 error!("Failed to remove sandbox directory: {}", e);
             }
 
+            info.lifecycle.transition(SandboxState::Stopped).ok();
             info!("Destroyed Firecracker sandbox {}", sandbox_id);
         }
 
@@ -293,17 +722,62 @@ impl SandboxRuntime for FirecrackerRuntime {
         Ok(new_sandbox_id)
     }
 
+    async fn set_frozen(&self, sandbox_id: Uuid, frozen: bool) -> Result<()> {
+        let mut sandboxes = self.sandboxes.write().await;
+        let info = sandboxes.get_mut(&sandbox_id)
+            .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
+        let target = if frozen { SandboxState::Paused } else { SandboxState::Running };
+        info.lifecycle.check_change(target)?;
+
+        let state = if frozen { "Paused" } else { "Resumed" };
+        let output = Command::new("curl")
+            .args(["--silent", "--show-error", "--fail", "--unix-socket"])
+            .arg(&info.socket_path)
+            .args(["-X", "PATCH", "http://localhost/vm", "-H", "Content-Type: application/json"])
+            .args(["-d", &serde_json::json!({ "state": state }).to_string()])
+            .output()
+            .await
+            .context("Failed to call the Firecracker API")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to set VM state to {}: {}",
+                state,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        info.lifecycle.transition(target)?;
+        info!("VM of sandbox {} {}", sandbox_id, state.to_lowercase());
+        Ok(())
+    }
+
+    async fn network_interface(&self, sandbox_id: Uuid) -> Option<String> {
+        self.taps
+            .lock()
+            .await
+            .contains(&sandbox_id)
+            .then(|| tap_name(sandbox_id))
+    }
+
     async fn status(&self, sandbox_id: Uuid) -> Result<SandboxStatus> {
-        let sandboxes = self.sandboxes.read().await;
-        let info = sandboxes.get(&sandbox_id)
+        let mut sandboxes = self.sandboxes.write().await;
+        let info = sandboxes.get_mut(&sandbox_id)
             .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
 
+        // The gateway only stops VMs by destroying them, so a VM whose
+        // process is gone died on its own
+        let state = if process_alive(info.pid) {
+            info.lifecycle.state()
+        } else {
+            info.lifecycle.observe(SandboxState::Failed)
+        };
+
         Ok(SandboxStatus {
             id: sandbox_id,
-            state: info.state,
-            created_at: info.created_at,
-            started_at: info.started_at,
-            finished_at: None,
+            state,
+            created_at: info.lifecycle.created_at(),
+            started_at: info.lifecycle.started_at(),
+            finished_at: info.lifecycle.finished_at(),
             exit_code: None,
             resource_usage: ResourceUsage {
                 cpu_usage_seconds: 0.0,
@@ -311,22 +785,142 @@ impl SandboxRuntime for FirecrackerRuntime {
                 network_rx_bytes: 0,
                 network_tx_bytes: 0,
             },
+            exit_reason: exit::of_state(state, false),
+            transitions: info.lifecycle.transitions().to_vec(),
         })
     }
 
-    async fn logs(&self, sandbox_id: Uuid, _follow: bool) -> Result<Box<dyn tokio::io::AsyncRead + Send + Unpin>> {
+    async fn logs(&self, sandbox_id: Uuid, follow: bool) -> Result<Box<dyn tokio::io::AsyncRead + Send + Unpin>> {
         let sandboxes = self.sandboxes.read().await;
         let info = sandboxes.get(&sandbox_id)
             .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
 
-        // In a real implementation, we would stream logs from the VM
-        // For now, return an empty reader
+        // The VM's serial console, written by the Firecracker process
         let log_path = info.root_dir.join("console.log");
-        let file = match tokio::fs::File::open(log_path).await {
-            Ok(f) => f,
-            Err(_) => tokio::fs::File::open("/dev/null").await?,
+        if follow {
+            return Ok(Box::new(follow::FollowFile::open(&log_path).await?));
+        }
+        Ok(Box::new(tokio::fs::File::open(log_path).await?))
+    }
+
+    async fn active_sandboxes(&self) -> usize {
+        self.sandboxes.read().await.len()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tap_names_fit_the_kernel_limit() {
+        let sandbox_id = Uuid::new_v4();
+        let tap = tap_name(sandbox_id);
+        assert_eq!(tap.len(), 15);
+        assert_eq!(tap, tap_name(sandbox_id));
+    }
+
+    #[test]
+    fn orphans_are_devices_of_unknown_sandboxes() {
+        let (live, gone) = (Uuid::new_v4(), Uuid::new_v4());
+        let devices = vec![(tap_name(live), live), (tap_name(gone), gone)];
+        assert_eq!(
+            orphans(devices, &HashSet::from([live])),
+            vec![(tap_name(gone), gone)]
+        );
+    }
+
+    fn runtime(vendor: cpu::CpuVendor, defaults: FirecrackerOptions) -> FirecrackerRuntime {
+        FirecrackerRuntime {
+            firecracker_bin: PathBuf::from("firecracker"),
+            jailer_bin: PathBuf::from("jailer"),
+            base_dir: std::env::temp_dir(),
+            sandboxes: RwLock::new(HashMap::new()),
+            taps: tokio::sync::Mutex::new(HashSet::new()),
+            leaks: None,
+            events: LifecycleEvents::new(),
+            images: None,
+            arch: Arch::X86_64,
+            cpu: cpu::HostCpu {
+                arch: Arch::X86_64,
+                vendor,
+                smt_active: true,
+            },
+            defaults,
+        }
+    }
+
+    fn config(isolation_level: IsolationLevel, firecracker: FirecrackerOptions) -> SandboxConfig {
+        SandboxConfig {
+            id: Uuid::new_v4(),
+            image: "sandstorm/python".to_string(),
+            command: vec!["python".to_string()],
+            environment: HashMap::new(),
+            cpu_limit: None,
+            memory_limit: None,
+            timeout: None,
+            isolation_level,
+            runtime_preference: None,
+            working_dir: None,
+            mounts: Vec::new(),
+            execution_mode: ExecutionMode::Standard,
+            scratch_size: None,
+            sysctls: HashMap::new(),
+            gvisor: Default::default(),
+            firecracker,
+            arch: None,
+            rootfs_layers: Vec::new(),
+            ttl_seconds: None,
+        }
+    }
+
+    #[test]
+    fn cpu_options_are_checked_against_the_host() {
+        let intel = runtime(cpu::CpuVendor::Intel, FirecrackerOptions::default());
+        let t2a = FirecrackerOptions {
+            cpu_template: Some(CpuTemplate::T2a),
+            ..Default::default()
         };
-        
-        Ok(Box::new(file))
+        assert!(intel.validate(&config(IsolationLevel::Strong, t2a)).is_err());
+        assert!(runtime(cpu::CpuVendor::Amd, FirecrackerOptions::default())
+            .validate(&config(IsolationLevel::Strong, t2a))
+            .is_ok());
+
+        let smt = FirecrackerOptions {
+            smt: Some(true),
+            ..Default::default()
+        };
+        assert!(intel.validate(&config(IsolationLevel::Strong, smt)).is_ok());
+        assert!(intel.validate(&config(IsolationLevel::Maximum, smt)).is_err());
+
+        let masked_c3 = FirecrackerOptions {
+            cpu_template: Some(CpuTemplate::C3),
+            mask_cpu_brand: Some(true),
+            ..Default::default()
+        };
+        assert!(intel.validate(&config(IsolationLevel::Strong, masked_c3)).is_err());
+        assert!(runtime(cpu::CpuVendor::Intel, FirecrackerOptions::default())
+            .with_defaults(masked_c3)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn maximum_isolation_vms_get_no_smt_from_defaults() {
+        let defaults = FirecrackerOptions {
+            cpu_template: Some(CpuTemplate::T2s),
+            smt: Some(true),
+            mask_cpu_brand: None,
+        };
+        let runtime = runtime(cpu::CpuVendor::Intel, defaults);
+        let images = BootSelection { kernel: None, rootfs: None };
+
+        let strong = config(IsolationLevel::Strong, FirecrackerOptions::default());
+        let vm_config = runtime.build_vm_config(&strong, &images).await.unwrap();
+        assert_eq!(vm_config["machine-config"]["smt"], true);
+        assert_eq!(vm_config["machine-config"]["cpu_template"], "T2S");
+
+        let maximum = config(IsolationLevel::Maximum, FirecrackerOptions::default());
+        let vm_config = runtime.build_vm_config(&maximum, &images).await.unwrap();
+        assert_eq!(vm_config["machine-config"]["smt"], false);
+        assert_eq!(vm_config["machine-config"]["cpu_template"], "T2S");
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,93 @@
+//! Console logs read the way `tail -f` reads them: at the end of the file a
+//! read waits for more to be written instead of finishing. It finishes once
+//! the file is removed, as runtimes do when they destroy a sandbox.
+
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::time::Sleep;
+
+/// How often a follower at the end of the file checks for more
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct FollowFile {
+    file: File,
+    path: PathBuf,
+    /// Set while waiting at the end of the file
+    wait: Option<Pin<Box<Sleep>>>,
+}
+
+impl FollowFile {
+    /// Follow the file at `path` from its start
+    pub async fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            file: File::open(path).await?,
+            path: path.to_path_buf(),
+            wait: None,
+        })
+    }
+}
+
+impl AsyncRead for FollowFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            if let Some(wait) = &mut this.wait {
+                ready!(wait.as_mut().poll(cx));
+                this.wait = None;
+            }
+            let filled = buf.filled().len();
+            ready!(Pin::new(&mut this.file).poll_read(cx, buf))?;
+            if buf.filled().len() > filled || !this.path.exists() {
+                return Poll::Ready(Ok(()));
+            }
+            this.wait = Some(Box::pin(tokio::time::sleep(POLL_INTERVAL)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn reads_what_is_appended_until_the_file_is_removed() {
+        let path = std::env::temp_dir().join(format!("sandstorm-follow-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "booting\n").unwrap();
+        let mut follower = FollowFile::open(&path).await.unwrap();
+
+        let mut buf = [0u8; 64];
+        let n = follower.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"booting\n");
+
+        // A read at the end waits for the next write
+        let appended = tokio::spawn({
+            let path = path.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+                file.write_all(b"ready\n").unwrap();
+            }
+        });
+        let n = follower.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"ready\n");
+        appended.await.unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(follower.read(&mut buf).await.unwrap(), 0);
+    }
+}
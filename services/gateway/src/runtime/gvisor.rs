@@ -4,7 +4,9 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use std::process::Stdio;
 use tokio::process::Command;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+
+use super::lifecycle::{Lifecycle, LifecycleEvents};
 
 /// gVisor (runsc) runtime implementation for standard isolation
 pub struct GvisorRuntime {
@@ -14,18 +16,70 @@ pub struct GvisorRuntime {
     base_dir: PathBuf,
     /// Runtime root directory
     runtime_root: PathBuf,
+    /// Release date of the installed runsc, `YYYYMMDD`, if it reports one
+    release: Option<u32>,
+    /// Flags for sandboxes that don't set their own
+    defaults: GvisorOptions,
+    /// Architecture of the host, which sandboxes' seccomp profiles name
+    arch: Arch,
     /// Active sandboxes
     sandboxes: RwLock<HashMap<Uuid, SandboxInfo>>,
+    /// Where sandbox state changes are sent
+    events: LifecycleEvents,
+}
+
+/// First runsc release the gateway passes `--platform=systrap` to
+const SYSTRAP_SINCE: u32 = 20230417;
+/// First runsc release the gateway passes `--overlay2` to
+const OVERLAY2_SINCE: u32 = 20230214;
+
+/// An option as written in flags and requests, e.g. `systrap`
+fn label(value: impl Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(value)) => value,
+        _ => String::new(),
+    }
+}
+
+/// Release date from `runsc --version` output such as
+/// `runsc version release-20231009.0`
+pub fn parse_release(version: &str) -> Option<u32> {
+    let (_, rest) = version.split_once("release-")?;
+    rest.get(..8)?.parse().ok()
+}
+
+/// Runtime-wide gVisor flags from `GATEWAY_GVISOR_PLATFORM`,
+/// `GATEWAY_GVISOR_NETWORK`, `GATEWAY_GVISOR_OVERLAY` and
+/// `GATEWAY_GVISOR_DEBUG`
+pub fn options_from_env() -> Result<GvisorOptions> {
+    fn var<T: serde::de::DeserializeOwned>(name: &str) -> Result<Option<T>> {
+        let Ok(value) = std::env::var(name) else {
+            return Ok(None);
+        };
+        let value = match value.as_str() {
+            "1" | "true" | "yes" | "on" => serde_json::Value::Bool(true),
+            "0" | "false" | "no" | "off" => serde_json::Value::Bool(false),
+            other => serde_json::Value::String(other.to_string()),
+        };
+        serde_json::from_value(value)
+            .map(Some)
+            .with_context(|| format!("invalid {}", name))
+    }
+
+    Ok(GvisorOptions {
+        platform: var("GATEWAY_GVISOR_PLATFORM")?,
+        network: var("GATEWAY_GVISOR_NETWORK")?,
+        overlay: var("GATEWAY_GVISOR_OVERLAY")?,
+        debug: var("GATEWAY_GVISOR_DEBUG")?,
+    })
 }
 
 #[derive(Debug, Clone)]
 struct SandboxInfo {
     container_id: String,
     bundle_path: PathBuf,
-    state: SandboxState,
+    lifecycle: Lifecycle,
     config: SandboxConfig,
-    created_at: chrono::DateTime<chrono::Utc>,
-    started_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 impl GvisorRuntime {
@@ -44,14 +98,115 @@ impl GvisorRuntime {
         std::fs::create_dir_all(&runtime_root)
             .context("Failed to create runtime root directory")?;
 
+        let release = std::process::Command::new(&runsc_bin)
+            .arg("--version")
+            .output()
+            .ok()
+            .and_then(|output| parse_release(&String::from_utf8_lossy(&output.stdout)));
+        if release.is_none() {
+            warn!("Could not determine the runsc release; gVisor options won't be checked against it");
+        }
+
         Ok(Self {
             runsc_bin,
             base_dir,
             runtime_root,
+            release,
+            defaults: GvisorOptions::default(),
+            arch: arch::detect()?,
             sandboxes: RwLock::new(HashMap::new()),
+            events: LifecycleEvents::new(),
         })
     }
 
+    /// Send sandbox state changes to these subscribers
+    pub fn with_lifecycle_events(mut self, events: LifecycleEvents) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// State of a container as runsc reports it
+    async fn container_state(&self, container_id: &str) -> Result<SandboxState> {
+        let mut cmd = Command::new(&self.runsc_bin);
+        cmd.args([
+            "--root", self.runtime_root.to_str().unwrap(),
+            "state",
+            container_id,
+        ]);
+
+        let output = command::run(&mut cmd, "get gVisor container state").await?;
+        let state_json: serde_json::Value = serde_json::from_slice(&output.stdout)
+            .context("Failed to parse container state")?;
+
+        Ok(match state_json["status"].as_str() {
+            Some("created") => SandboxState::Starting,
+            Some("running") => SandboxState::Running,
+            Some("paused") => SandboxState::Paused,
+            Some("stopped") => SandboxState::Stopped,
+            _ => SandboxState::Failed,
+        })
+    }
+
+    /// Flags for sandboxes that don't set their own; fails if the installed
+    /// runsc can't honour them
+    pub fn with_defaults(mut self, defaults: GvisorOptions) -> Result<Self> {
+        self.check_support(&defaults)?;
+        self.defaults = defaults;
+        Ok(self)
+    }
+
+    /// Fail if the installed runsc or host can't honour these options
+    fn check_support(&self, options: &GvisorOptions) -> Result<()> {
+        let too_old = |since: u32| self.release.is_some_and(|release| release < since);
+        match options.platform {
+            Some(GvisorPlatform::Systrap) if too_old(SYSTRAP_SINCE) => anyhow::bail!(
+                "the systrap platform needs runsc release-{} or later",
+                SYSTRAP_SINCE
+            ),
+            Some(GvisorPlatform::Kvm) if !std::path::Path::new("/dev/kvm").exists() => {
+                anyhow::bail!("the kvm platform needs /dev/kvm, which this host doesn't have")
+            }
+            _ => {}
+        }
+        if options.overlay.is_some() && too_old(OVERLAY2_SINCE) {
+            anyhow::bail!("overlay needs runsc release-{} or later", OVERLAY2_SINCE);
+        }
+        Ok(())
+    }
+
+    /// Options a sandbox runs with: its own, then the runtime's defaults,
+    /// with read-only sandboxes always off the network
+    fn effective_options(&self, config: &SandboxConfig) -> GvisorOptions {
+        let mut options = config.gvisor.or(self.defaults);
+        if config.execution_mode == ExecutionMode::ReadOnly {
+            options.network = Some(GvisorNetwork::None);
+        }
+        options
+    }
+
+    /// Global runsc flags for creating or restoring a sandbox
+    fn sandbox_flags(&self, sandbox_id: Uuid, options: &GvisorOptions) -> Vec<String> {
+        let mut flags = Vec::new();
+        if let Some(platform) = options.platform {
+            flags.push(format!("--platform={}", label(platform)));
+        }
+        if let Some(network) = options.network {
+            flags.push(format!("--network={}", label(network)));
+        }
+        match options.overlay {
+            Some(true) => flags.push("--overlay2=root:memory".to_string()),
+            Some(false) => flags.push("--overlay2=none".to_string()),
+            None => {}
+        }
+        if options.debug == Some(true) {
+            // The trailing slash makes runsc write one file per command
+            let log_dir = self.base_dir.join("logs").join(sandbox_id.to_string());
+            flags.push("--debug".to_string());
+            flags.push(format!("--debug-log={}/", log_dir.display()));
+        }
+        flags
+    }
+
     /// Create OCI runtime spec
     async fn create_oci_spec(&self, config: &SandboxConfig) -> Result<serde_json::Value> {
         let mut env = vec![
@@ -95,7 +250,7 @@ impl GvisorRuntime {
             }));
         }
 
-        Ok(serde_json::json!({
+        let mut spec = serde_json::json!({
             "ociVersion": "1.0.2",
             "process": {
                 "terminal": false,
@@ -127,6 +282,7 @@ impl GvisorRuntime {
             "hostname": format!("sandbox-{}", config.id),
             "mounts": mounts,
             "linux": {
+                "cgroupsPath": exit::cgroup_path(config.id),
                 "resources": {
                     "devices": [{
                         "allow": false,
@@ -149,9 +305,9 @@ impl GvisorRuntime {
                 ],
                 "seccomp": {
                     "defaultAction": "SCMP_ACT_ERRNO",
-                    "architectures": ["SCMP_ARCH_X86_64"],
+                    "architectures": arch::seccomp_architectures(self.arch),
                     "syscalls": [{
-                        "names": [
+                        "names": arch::syscalls(self.arch, &[
                             "accept", "accept4", "access", "arch_prctl", "bind", "brk",
                             "capget", "capset", "clone", "close", "connect", "dup", "dup2",
                             "epoll_create", "epoll_create1", "epoll_ctl", "epoll_wait",
@@ -166,12 +322,18 @@ impl GvisorRuntime {
                             "sendmsg", "sendto", "set_robust_list", "set_tid_address",
                             "setsockopt", "sigaltstack", "socket", "stat", "statfs", "sysinfo",
                             "tgkill", "uname", "unlink", "wait4", "write", "writev"
-                        ],
+                        ]),
                         "action": "SCMP_ACT_ALLOW"
                     }]
                 }
             }
-        }))
+        });
+        if !config.sysctls.is_empty() {
+            spec["linux"]["sysctl"] = serde_json::json!(config.sysctls);
+        }
+        read_only::apply_to_oci_spec(&mut spec, config, self.arch);
+
+        Ok(spec)
     }
 
     /// Create container bundle
@@ -188,6 +350,12 @@ impl GvisorRuntime {
         let spec_path = bundle_path.join("config.json");
         std::fs::write(&spec_path, serde_json::to_string_pretty(&spec)?)?;
 
+        // A root filesystem restored from vault layers replaces the image's
+        if !config.rootfs_layers.is_empty() {
+            overlay::mount(&bundle_path, &rootfs_path, &config.rootfs_layers).await?;
+            return Ok(bundle_path);
+        }
+
         // Extract rootfs from image (simplified - in reality would use proper OCI image handling)
         // For now, create a minimal rootfs
         let dirs = ["bin", "dev", "etc", "home", "lib", "lib64", "proc", "root", "sys", "tmp", "usr", "var"];
@@ -197,97 +365,53 @@ impl GvisorRuntime {
 
         Ok(bundle_path)
     }
-}
-
-#[async_trait]
-impl SandboxRuntime for GvisorRuntime {
-    fn runtime_type(&self) -> RuntimeType {
-        RuntimeType::Gvisor
-    }
-
-    fn supports_isolation_level(&self, level: IsolationLevel) -> bool {
-        // gVisor provides standard to strong isolation through kernel syscall interception
-        matches!(level, IsolationLevel::Standard | IsolationLevel::Strong)
-    }
-
-    async fn create(&self, config: &SandboxConfig) -> Result<Uuid> {
-        let sandbox_id = config.id;
-        let container_id = format!("gvisor-{}", sandbox_id);
-
-        // Create container bundle
-        let bundle_path = self.create_bundle(config).await?;
-
-        // Create container using runsc
-        let mut cmd = Command::new(&self.runsc_bin);
-        cmd.args([
-            "--root", self.runtime_root.to_str().unwrap(),
-            "create",
-            "--bundle", bundle_path.to_str().unwrap(),
-            &container_id,
-        ]);
-
-        cmd.stderr(Stdio::piped());
-        let output = cmd.output().await.context("Failed to create gVisor container")?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("Failed to create container: {}", stderr);
-        }
-
-        // Start the container
-        let mut cmd = Command::new(&self.runsc_bin);
-        cmd.args([
-            "--root", self.runtime_root.to_str().unwrap(),
-            "start",
-            &container_id,
-        ]);
-
-        let output = cmd.output().await.context("Failed to start gVisor container")?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("Failed to start container: {}", stderr);
-        }
 
-        // Store sandbox info
-        let info = SandboxInfo {
-            container_id,
-            bundle_path,
-            state: SandboxState::Running,
-            config: config.clone(),
-            created_at: chrono::Utc::now(),
-            started_at: Some(chrono::Utc::now()),
-        };
-
-        let mut sandboxes = self.sandboxes.write().await;
-        sandboxes.insert(sandbox_id, info);
-
-        info!("Created gVisor sandbox {}", sandbox_id);
-        Ok(sandbox_id)
-    }
-
-    async fn exec(
+    /// Run a command in a sandbox, streaming its output to `stream` if
+    /// there is one
+    async fn run_exec(
         &self,
         sandbox_id: Uuid,
         command: Vec<String>,
         environment: Option<HashMap<String, String>>,
+        options: &ExecOptions,
+        input: Option<ExecInputReceiver>,
+        stream: Option<&ExecOutputSender>,
     ) -> Result<SandboxResult> {
         let sandboxes = self.sandboxes.read().await;
         let info = sandboxes.get(&sandbox_id)
             .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
 
-        if info.state != SandboxState::Running {
+        if info.lifecycle.state() != SandboxState::Running {
             anyhow::bail!("Sandbox {} is not running", sandbox_id);
         }
+        // runsc only gives an exec a terminal when its own stdin is one
+        options.check(
+            RuntimeType::Gvisor,
+            ExecSupport {
+                working_dir: true,
+                user: true,
+                tty: false,
+                limits: true,
+                stdin: true,
+            },
+        )?;
+        options.limits.check(&info.config)?;
 
         let start_time = std::time::Instant::now();
+        let oom_kills = exit::oom_kills(sandbox_id).await;
 
         // Execute command in container
         let mut cmd = Command::new(&self.runsc_bin);
         cmd.args([
             "--root", self.runtime_root.to_str().unwrap(),
             "exec",
-            &info.container_id,
         ]);
+        if let Some(dir) = &options.working_dir {
+            cmd.args(["--cwd", dir]);
+        }
+        if let Some(user) = &options.user {
+            cmd.args(["--user", user]);
+        }
 
         // Add environment variables
         if let Some(env) = environment {
@@ -296,18 +420,25 @@ impl SandboxRuntime for GvisorRuntime {
             }
         }
 
-        // Add command
-        cmd.args(&command);
+        // Add container ID and command, under its own limits if it has any
+        cmd.arg(&info.container_id);
+        cmd.args(options.limits.wrap(command));
 
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
+        // Stop the exec if the gateway gives up on it
+        cmd.kill_on_drop(true);
 
-        let output = cmd.output().await.context("Failed to execute command in container")?;
+        // An exec reading stdin lasts as long as its client keeps it open,
+        // so it mustn't hold up changes to other sandboxes
+        drop(sandboxes);
+        let input = input.or_else(|| options.stdin_input());
+        let output = command::interact(&mut cmd, "exec in gVisor container", input, stream).await?;
         let duration_ms = start_time.elapsed().as_millis() as u64;
+        let oom_killed = exit::oom_kills(sandbox_id).await > oom_kills;
+        let exit_code = output.status.code().unwrap_or(-1);
 
         Ok(SandboxResult {
             id: sandbox_id,
-            exit_code: output.status.code().unwrap_or(-1),
+            exit_code,
             stdout: output.stdout,
             stderr: output.stderr,
             duration_ms,
@@ -317,13 +448,147 @@ impl SandboxRuntime for GvisorRuntime {
                 network_rx_bytes: 0,
                 network_tx_bytes: 0,
             },
+            exit_reason: options.limits.exit_reason(exit_code, exit::of_status(output.status, oom_killed)),
         })
     }
+}
+
+#[async_trait]
+impl SandboxRuntime for GvisorRuntime {
+    fn runtime_type(&self) -> RuntimeType {
+        RuntimeType::Gvisor
+    }
+
+    fn supports_isolation_level(&self, level: IsolationLevel) -> bool {
+        // gVisor provides standard to strong isolation through kernel syscall interception
+        matches!(level, IsolationLevel::Standard | IsolationLevel::Strong)
+    }
+
+    fn supports_execution_mode(&self, _mode: ExecutionMode) -> bool {
+        true
+    }
+
+    fn supports_sysctls(&self) -> bool {
+        true
+    }
+
+    fn supports_rootfs_layers(&self) -> bool {
+        true
+    }
+
+    fn validate(&self, config: &SandboxConfig) -> Result<()> {
+        let requested = config.gvisor.network;
+        if config.execution_mode == ExecutionMode::ReadOnly
+            && requested.is_some_and(|network| network != GvisorNetwork::None)
+        {
+            anyhow::bail!("read-only sandboxes can't have a network");
+        }
+        // Requests may narrow network access, never widen it
+        let allowed = self.defaults.network.unwrap_or(GvisorNetwork::Sandbox);
+        if requested.is_some_and(|network| network > allowed) {
+            anyhow::bail!(
+                "network {} is not allowed; this runtime allows up to {}",
+                label(requested),
+                label(allowed)
+            );
+        }
+        self.check_support(&self.effective_options(config))
+    }
+
+    async fn create(&self, config: &SandboxConfig) -> Result<Uuid> {
+        let sandbox_id = config.id;
+        let container_id = format!("gvisor-{}", sandbox_id);
+        let mut lifecycle = Lifecycle::new(sandbox_id, RuntimeType::Gvisor, &self.events);
+
+        let started = async {
+            // Create container bundle
+            let bundle_path = self.create_bundle(config).await?;
+
+            // Create container using runsc
+            let mut cmd = Command::new(&self.runsc_bin);
+            cmd.args(["--root", self.runtime_root.to_str().unwrap()]);
+            cmd.args(self.sandbox_flags(sandbox_id, &self.effective_options(config)));
+            cmd.args([
+                "create",
+                "--bundle", bundle_path.to_str().unwrap(),
+                &container_id,
+            ]);
+
+            command::run(&mut cmd, "create gVisor container").await?;
+            lifecycle.transition(SandboxState::Starting)?;
+
+            // Start the container
+            let mut cmd = Command::new(&self.runsc_bin);
+            cmd.args([
+                "--root", self.runtime_root.to_str().unwrap(),
+                "start",
+                &container_id,
+            ]);
+
+            command::run(&mut cmd, "start gVisor container").await?;
+
+            // runsc returns once the start is requested; only a running
+            // container counts as started
+            match self.container_state(&container_id).await? {
+                SandboxState::Running => lifecycle.transition(SandboxState::Running)?,
+                state => anyhow::bail!("gVisor container {} is {:?} after starting", container_id, state),
+            }
+            Ok(bundle_path)
+        }
+        .await;
+
+        let bundle_path = match started {
+            Ok(bundle_path) => bundle_path,
+            Err(e) => {
+                lifecycle.transition(SandboxState::Failed).ok();
+                return Err(e);
+            }
+        };
+
+        // Store sandbox info
+        let info = SandboxInfo {
+            container_id,
+            bundle_path,
+            lifecycle,
+            config: config.clone(),
+        };
+
+        let mut sandboxes = self.sandboxes.write().await;
+        sandboxes.insert(sandbox_id, info);
+
+        info!("Created gVisor sandbox {}", sandbox_id);
+        Ok(sandbox_id)
+    }
+
+    async fn exec(
+        &self,
+        sandbox_id: Uuid,
+        command: Vec<String>,
+        environment: Option<HashMap<String, String>>,
+        options: &ExecOptions,
+    ) -> Result<SandboxResult> {
+        self.run_exec(sandbox_id, command, environment, options, None, None).await
+    }
+
+    async fn exec_streaming(
+        &self,
+        sandbox_id: Uuid,
+        command: Vec<String>,
+        environment: Option<HashMap<String, String>>,
+        options: &ExecOptions,
+        input: Option<ExecInputReceiver>,
+        output: ExecOutputSender,
+    ) -> Result<SandboxResult> {
+        self.run_exec(sandbox_id, command, environment, options, input, Some(&output))
+            .await
+    }
 
     async fn destroy(&self, sandbox_id: Uuid) -> Result<()> {
         let mut sandboxes = self.sandboxes.write().await;
         
-        if let Some(info) = sandboxes.remove(&sandbox_id) {
+        if let Some(mut info) = sandboxes.remove(&sandbox_id) {
+            info.lifecycle.transition(SandboxState::Stopping).ok();
+
             // Kill the container
             let mut cmd = Command::new(&self.runsc_bin);
             cmd.args([
@@ -332,7 +597,7 @@ impl SandboxRuntime for GvisorRuntime {
                 &info.container_id,
                 "KILL",
             ]);
-            cmd.output().await.ok();
+            command::run(&mut cmd, "kill gVisor container").await.ok();
 
             // Delete the container
             let mut cmd = Command::new(&self.runsc_bin);
@@ -341,13 +606,20 @@ impl SandboxRuntime for GvisorRuntime {
                 "delete",
                 &info.container_id,
             ]);
-            cmd.output().await.ok();
+            command::run(&mut cmd, "delete gVisor container").await.ok();
+
+            if !info.config.rootfs_layers.is_empty() {
+                if let Err(e) = overlay::unmount(&info.bundle_path.join("rootfs")).await {
+                    error!("Failed to unmount root filesystem layers: {}", e);
+                }
+            }
 
             // Remove bundle directory
             if let Err(e) = tokio::fs::remove_dir_all(&info.bundle_path).await {
                 error!("Failed to remove bundle directory: {}", e);
             }
 
+            info.lifecycle.transition(SandboxState::Stopped).ok();
             info!("Destroyed gVisor sandbox {}", sandbox_id);
         }
 
@@ -366,7 +638,7 @@ impl SandboxRuntime for GvisorRuntime {
             "pause",
             &info.container_id,
         ]);
-        cmd.output().await.context("Failed to pause container")?;
+        command::run(&mut cmd, "pause gVisor container").await?;
 
         // Create checkpoint
         let checkpoint_dir = self.base_dir.join("checkpoints").join(sandbox_id.to_string());
@@ -380,11 +652,7 @@ impl SandboxRuntime for GvisorRuntime {
             &info.container_id,
         ]);
 
-        let output = cmd.output().await.context("Failed to checkpoint container")?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("Failed to checkpoint: {}", stderr);
-        }
+        command::run(&mut cmd, "checkpoint gVisor container").await?;
 
         let snapshot = SandboxSnapshot {
             id: Uuid::new_v4(),
@@ -395,6 +663,7 @@ impl SandboxRuntime for GvisorRuntime {
             memory_state: Some(Vec::new()), // Would read from checkpoint
             metadata: HashMap::from([
                 ("checkpoint_path".to_string(), serde_json::json!(checkpoint_dir.to_str())),
+                ("gvisor".to_string(), serde_json::to_value(self.effective_options(&info.config))?),
             ]),
         };
 
@@ -402,6 +671,16 @@ impl SandboxRuntime for GvisorRuntime {
         Ok(snapshot)
     }
 
+    async fn export_filesystem(&self, sandbox_id: Uuid) -> Result<Vec<u8>> {
+        let rootfs = {
+            let sandboxes = self.sandboxes.read().await;
+            let info = sandboxes.get(&sandbox_id)
+                .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
+            info.bundle_path.join("rootfs")
+        };
+        command::tar(&rootfs).await
+    }
+
     async fn resume(&self, snapshot: &SandboxSnapshot) -> Result<Uuid> {
         // Create new sandbox ID
         let new_sandbox_id = Uuid::new_v4();
@@ -412,56 +691,68 @@ impl SandboxRuntime for GvisorRuntime {
             .and_then(|v| v.as_str())
             .ok_or_else(|| anyhow::anyhow!("Missing checkpoint path in snapshot metadata"))?;
 
+        // Restore with the flags the sandbox was checkpointed with
+        let options = match snapshot.metadata.get("gvisor") {
+            Some(options) => serde_json::from_value(options.clone())
+                .context("Invalid gVisor options in snapshot metadata")?,
+            None => self.defaults,
+        };
+        self.check_support(&options)?;
+
         // Restore from checkpoint
         let mut cmd = Command::new(&self.runsc_bin);
+        cmd.args(["--root", self.runtime_root.to_str().unwrap()]);
+        cmd.args(self.sandbox_flags(new_sandbox_id, &options));
         cmd.args([
-            "--root", self.runtime_root.to_str().unwrap(),
             "restore",
             "--image-path", checkpoint_path,
             "--bundle", self.base_dir.join(new_sandbox_id.to_string()).to_str().unwrap(),
             &container_id,
         ]);
 
-        let output = cmd.output().await.context("Failed to restore container")?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("Failed to restore: {}", stderr);
-        }
+        command::run(&mut cmd, "restore gVisor container").await?;
 
         info!("Resumed gVisor sandbox {} from snapshot {}", new_sandbox_id, snapshot.id);
         Ok(new_sandbox_id)
     }
 
-    async fn status(&self, sandbox_id: Uuid) -> Result<SandboxStatus> {
-        let sandboxes = self.sandboxes.read().await;
-        let info = sandboxes.get(&sandbox_id)
+    async fn set_frozen(&self, sandbox_id: Uuid, frozen: bool) -> Result<()> {
+        let mut sandboxes = self.sandboxes.write().await;
+        let info = sandboxes.get_mut(&sandbox_id)
             .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
+        let target = if frozen { SandboxState::Paused } else { SandboxState::Running };
+        info.lifecycle.check_change(target)?;
 
-        // Get container state
         let mut cmd = Command::new(&self.runsc_bin);
         cmd.args([
             "--root", self.runtime_root.to_str().unwrap(),
-            "state",
+            if frozen { "pause" } else { "resume" },
             &info.container_id,
         ]);
 
-        let output = cmd.output().await.context("Failed to get container state")?;
-        let state_json: serde_json::Value = serde_json::from_slice(&output.stdout)
-            .context("Failed to parse container state")?;
+        let action = if frozen { "pause gVisor container" } else { "resume gVisor container" };
+        command::run(&mut cmd, action).await?;
 
-        let state = match state_json["status"].as_str() {
-            Some("running") => SandboxState::Running,
-            Some("paused") => SandboxState::Paused,
-            Some("stopped") => SandboxState::Stopped,
-            _ => SandboxState::Failed,
-        };
+        info.lifecycle.transition(target)?;
+        info!("{} gVisor sandbox {}", if frozen { "Froze" } else { "Thawed" }, sandbox_id);
+        Ok(())
+    }
+
+    async fn status(&self, sandbox_id: Uuid) -> Result<SandboxStatus> {
+        let mut sandboxes = self.sandboxes.write().await;
+        let info = sandboxes.get_mut(&sandbox_id)
+            .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
+
+        // Get container state
+        let reported = self.container_state(&info.container_id).await?;
+        let state = info.lifecycle.observe(reported);
 
         Ok(SandboxStatus {
             id: sandbox_id,
             state,
-            created_at: info.created_at,
-            started_at: info.started_at,
-            finished_at: None,
+            created_at: info.lifecycle.created_at(),
+            started_at: info.lifecycle.started_at(),
+            finished_at: info.lifecycle.finished_at(),
             exit_code: None,
             resource_usage: ResourceUsage {
                 cpu_usage_seconds: 0.0,
@@ -469,6 +760,8 @@ impl SandboxRuntime for GvisorRuntime {
                 network_rx_bytes: 0,
                 network_tx_bytes: 0,
             },
+            exit_reason: exit::of_state(state, exit::oom_kills(sandbox_id).await > 0),
+            transitions: info.lifecycle.transitions().to_vec(),
         })
     }
 
@@ -496,4 +789,108 @@ impl SandboxRuntime for GvisorRuntime {
 
         Ok(Box::new(stdout))
     }
-}
\ No newline at end of file
+
+    async fn active_sandboxes(&self) -> usize {
+        self.sandboxes.read().await.len()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Runtime backed by a stand-in runsc that reports `version`
+    fn runtime(version: &str) -> GvisorRuntime {
+        let dir = std::env::temp_dir().join(format!("sandstorm-gvisor-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let runsc = dir.join("runsc");
+        std::fs::write(&runsc, format!("#!/bin/sh\necho '{}'\n", version)).unwrap();
+        std::fs::set_permissions(&runsc, std::fs::Permissions::from_mode(0o755)).unwrap();
+        GvisorRuntime::new(runsc, dir.join("state")).unwrap()
+    }
+
+    fn config(mode: ExecutionMode, gvisor: GvisorOptions) -> SandboxConfig {
+        SandboxConfig {
+            id: Uuid::new_v4(),
+            image: "sandstorm/python".to_string(),
+            command: Vec::new(),
+            environment: HashMap::new(),
+            cpu_limit: None,
+            memory_limit: None,
+            timeout: None,
+            isolation_level: IsolationLevel::Standard,
+            runtime_preference: None,
+            working_dir: None,
+            mounts: Vec::new(),
+            execution_mode: mode,
+            scratch_size: None,
+            sysctls: HashMap::new(),
+            gvisor,
+            firecracker: Default::default(),
+            arch: None,
+            rootfs_layers: Vec::new(),
+            ttl_seconds: None,
+        }
+    }
+
+    #[test]
+    fn parses_release_dates() {
+        assert_eq!(parse_release("runsc version release-20231009.0\nspec: 1.1.0\n"), Some(20231009));
+        assert_eq!(parse_release("runsc version VERSION_MISSING\n"), None);
+    }
+
+    #[test]
+    fn builds_flags_from_request_and_defaults() {
+        let runtime = runtime("runsc version release-20240101.0")
+            .with_defaults(GvisorOptions {
+                platform: Some(GvisorPlatform::Ptrace),
+                overlay: Some(true),
+                ..Default::default()
+            })
+            .unwrap();
+        let sandbox = config(
+            ExecutionMode::ReadOnly,
+            GvisorOptions {
+                platform: Some(GvisorPlatform::Systrap),
+                debug: Some(true),
+                ..Default::default()
+            },
+        );
+        runtime.validate(&sandbox).unwrap();
+
+        let flags = runtime.sandbox_flags(sandbox.id, &runtime.effective_options(&sandbox));
+        assert_eq!(&flags[..3], ["--platform=systrap", "--network=none", "--overlay2=root:memory"]);
+        assert_eq!(flags[3], "--debug");
+        assert!(flags[4].ends_with(&format!("/logs/{}/", sandbox.id)));
+    }
+
+    #[test]
+    fn rejects_unsupported_options() {
+        let old = runtime("runsc version release-20221001.0");
+        let systrap = GvisorOptions {
+            platform: Some(GvisorPlatform::Systrap),
+            ..Default::default()
+        };
+        assert!(old.validate(&config(ExecutionMode::Standard, systrap)).is_err());
+        assert!(old
+            .with_defaults(GvisorOptions {
+                overlay: Some(false),
+                ..Default::default()
+            })
+            .is_err());
+
+        // Requests can't widen the network, and read-only means no network
+        let current = runtime("runsc version release-20240101.0");
+        let network = |network| GvisorOptions {
+            network: Some(network),
+            ..Default::default()
+        };
+        assert!(current.validate(&config(ExecutionMode::Standard, network(GvisorNetwork::Host))).is_err());
+        assert!(current.validate(&config(ExecutionMode::ReadOnly, network(GvisorNetwork::Sandbox))).is_err());
+        current.validate(&config(ExecutionMode::Standard, network(GvisorNetwork::None))).unwrap();
+
+        // Without a release to check against, options are passed through
+        let unknown = runtime("runsc version VERSION_MISSING");
+        unknown.validate(&config(ExecutionMode::Standard, systrap)).unwrap();
+    }
+}
@@ -0,0 +1,395 @@
+//! Every sandbox on the registry's runtimes, whichever runtime hosts it, so
+//! sandboxes can be listed without asking each runtime. The registry wraps
+//! the runtimes it's given in an [`IndexedRuntime`], which keeps the index
+//! current as sandboxes are created, resumed, paused and destroyed. States
+//! are as of the last status check, so a sandbox whose workload exited on
+//! its own is listed as running until its status is next asked for.
+
+use super::*;
+use chrono::{DateTime, Utc};
+use utoipa::IntoParams;
+
+/// Images of sandboxes run from a language are `sandstorm/<language>`
+const LANGUAGE_IMAGE_PREFIX: &str = "sandstorm/";
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct IndexedSandbox {
+    pub sandbox_id: Uuid,
+    pub runtime_type: RuntimeType,
+    pub state: SandboxState,
+    /// `None` for sandboxes resumed from a snapshot this gateway didn't take
+    pub image: Option<String>,
+    pub language: Option<String>,
+    pub isolation_level: Option<IsolationLevel>,
+    pub created_at: DateTime<Utc>,
+    /// When the reaper destroys the sandbox, from its `ttl_seconds`
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Which sandboxes a listing includes; unset fields match every sandbox
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SandboxFilter {
+    pub runtime: Option<RuntimeType>,
+    pub state: Option<SandboxState>,
+    pub language: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+impl SandboxFilter {
+    pub fn matches(&self, sandbox: &IndexedSandbox) -> bool {
+        self.runtime.is_none_or(|runtime| sandbox.runtime_type == runtime)
+            && self.state.is_none_or(|state| sandbox.state == state)
+            && self
+                .language
+                .as_ref()
+                .is_none_or(|language| sandbox.language.as_ref() == Some(language))
+            && self.created_after.is_none_or(|after| sandbox.created_at >= after)
+            && self.created_before.is_none_or(|before| sandbox.created_at < before)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SandboxIndex {
+    sandboxes: RwLock<HashMap<Uuid, IndexedSandbox>>,
+}
+
+impl SandboxIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    async fn insert(&self, sandbox: IndexedSandbox) {
+        self.sandboxes.write().await.insert(sandbox.sandbox_id, sandbox);
+    }
+
+    async fn set_state(&self, sandbox_id: Uuid, state: SandboxState) {
+        if let Some(sandbox) = self.sandboxes.write().await.get_mut(&sandbox_id) {
+            sandbox.state = state;
+        }
+    }
+
+    async fn set_expiry(&self, sandbox_id: Uuid, expires_at: Option<DateTime<Utc>>) {
+        if let Some(sandbox) = self.sandboxes.write().await.get_mut(&sandbox_id) {
+            sandbox.expires_at = expires_at;
+        }
+    }
+
+    async fn remove(&self, sandbox_id: Uuid) {
+        self.sandboxes.write().await.remove(&sandbox_id);
+    }
+
+    /// Sandboxes whose TTL has run out by `now`
+    pub async fn expired(&self, now: DateTime<Utc>) -> Vec<IndexedSandbox> {
+        self.sandboxes
+            .read()
+            .await
+            .values()
+            .filter(|sandbox| sandbox.expires_at.is_some_and(|expires_at| expires_at <= now))
+            .cloned()
+            .collect()
+    }
+
+    pub async fn get(&self, sandbox_id: Uuid) -> Option<IndexedSandbox> {
+        self.sandboxes.read().await.get(&sandbox_id).cloned()
+    }
+
+    /// Sandboxes matching `filter`, oldest first
+    pub async fn list(&self, filter: &SandboxFilter) -> Vec<IndexedSandbox> {
+        let mut sandboxes: Vec<_> = self
+            .sandboxes
+            .read()
+            .await
+            .values()
+            .filter(|sandbox| filter.matches(sandbox))
+            .cloned()
+            .collect();
+        sandboxes.sort_by_key(|sandbox| (sandbox.created_at, sandbox.sandbox_id));
+        sandboxes
+    }
+}
+
+/// When a sandbox with this TTL started at `from` expires. A TTL too long
+/// to represent never runs out.
+fn expiry(ttl_seconds: Option<u64>, from: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let ttl = chrono::Duration::try_seconds(i64::try_from(ttl_seconds?).ok()?)?;
+    from.checked_add_signed(ttl)
+}
+
+/// A runtime whose sandboxes are kept in a [`SandboxIndex`]
+pub struct IndexedRuntime {
+    inner: Arc<dyn SandboxRuntime>,
+    index: Arc<SandboxIndex>,
+}
+
+impl IndexedRuntime {
+    pub fn new(inner: Arc<dyn SandboxRuntime>, index: Arc<SandboxIndex>) -> Self {
+        Self { inner, index }
+    }
+}
+
+#[async_trait]
+impl SandboxRuntime for IndexedRuntime {
+    fn runtime_type(&self) -> RuntimeType {
+        self.inner.runtime_type()
+    }
+
+    fn supports_isolation_level(&self, level: IsolationLevel) -> bool {
+        self.inner.supports_isolation_level(level)
+    }
+
+    fn supports_execution_mode(&self, mode: ExecutionMode) -> bool {
+        self.inner.supports_execution_mode(mode)
+    }
+
+    fn supports_sysctls(&self) -> bool {
+        self.inner.supports_sysctls()
+    }
+
+    fn supports_rootfs_layers(&self) -> bool {
+        self.inner.supports_rootfs_layers()
+    }
+
+    fn supports_arch(&self, arch: Arch) -> bool {
+        self.inner.supports_arch(arch)
+    }
+
+    fn validate(&self, config: &SandboxConfig) -> Result<()> {
+        self.inner.validate(config)
+    }
+
+    async fn create(&self, config: &SandboxConfig) -> Result<Uuid> {
+        let sandbox_id = self.inner.create(config).await?;
+        let created_at = Utc::now();
+        self.index
+            .insert(IndexedSandbox {
+                sandbox_id,
+                runtime_type: self.runtime_type(),
+                state: SandboxState::Running,
+                image: Some(config.image.clone()),
+                language: config
+                    .image
+                    .strip_prefix(LANGUAGE_IMAGE_PREFIX)
+                    .map(str::to_string),
+                isolation_level: Some(config.isolation_level),
+                created_at,
+                expires_at: expiry(config.ttl_seconds, created_at),
+            })
+            .await;
+        Ok(sandbox_id)
+    }
+
+    fn supports_warm_start(&self) -> bool {
+        self.inner.supports_warm_start()
+    }
+
+    async fn start_workload(&self, sandbox_id: Uuid, config: &SandboxConfig) -> Result<()> {
+        self.inner.start_workload(sandbox_id, config).await?;
+        // The run's TTL counts from when it starts, not from the boot
+        self.index
+            .set_expiry(sandbox_id, expiry(config.ttl_seconds, Utc::now()))
+            .await;
+        Ok(())
+    }
+
+    async fn exec(
+        &self,
+        sandbox_id: Uuid,
+        command: Vec<String>,
+        environment: Option<HashMap<String, String>>,
+        options: &ExecOptions,
+    ) -> Result<SandboxResult> {
+        self.inner.exec(sandbox_id, command, environment, options).await
+    }
+
+    async fn exec_streaming(
+        &self,
+        sandbox_id: Uuid,
+        command: Vec<String>,
+        environment: Option<HashMap<String, String>>,
+        options: &ExecOptions,
+        input: Option<ExecInputReceiver>,
+        output: ExecOutputSender,
+    ) -> Result<SandboxResult> {
+        self.inner
+            .exec_streaming(sandbox_id, command, environment, options, input, output)
+            .await
+    }
+
+    async fn destroy(&self, sandbox_id: Uuid) -> Result<()> {
+        self.inner.destroy(sandbox_id).await?;
+        self.index.remove(sandbox_id).await;
+        Ok(())
+    }
+
+    async fn snapshot(&self, sandbox_id: Uuid) -> Result<SandboxSnapshot> {
+        self.inner.snapshot(sandbox_id).await
+    }
+
+    async fn resume(&self, snapshot: &SandboxSnapshot) -> Result<Uuid> {
+        let sandbox_id = self.inner.resume(snapshot).await?;
+        // What the snapshotted sandbox ran, if it's still known
+        let original = self.index.get(snapshot.sandbox_id).await;
+        self.index
+            .insert(IndexedSandbox {
+                sandbox_id,
+                runtime_type: self.runtime_type(),
+                state: SandboxState::Running,
+                image: original.as_ref().and_then(|original| original.image.clone()),
+                language: original.as_ref().and_then(|original| original.language.clone()),
+                isolation_level: original.as_ref().and_then(|original| original.isolation_level),
+                created_at: Utc::now(),
+                // A resumed sandbox stands in for the original, and keeps its
+                // lifetime
+                expires_at: original.and_then(|original| original.expires_at),
+            })
+            .await;
+        Ok(sandbox_id)
+    }
+
+    async fn export_filesystem(&self, sandbox_id: Uuid) -> Result<Vec<u8>> {
+        self.inner.export_filesystem(sandbox_id).await
+    }
+
+    async fn status(&self, sandbox_id: Uuid) -> Result<SandboxStatus> {
+        let status = self.inner.status(sandbox_id).await?;
+        self.index.set_state(sandbox_id, status.state).await;
+        Ok(status)
+    }
+
+    async fn set_frozen(&self, sandbox_id: Uuid, frozen: bool) -> Result<()> {
+        self.inner.set_frozen(sandbox_id, frozen).await?;
+        let state = if frozen { SandboxState::Paused } else { SandboxState::Running };
+        self.index.set_state(sandbox_id, state).await;
+        Ok(())
+    }
+
+    async fn network_interface(&self, sandbox_id: Uuid) -> Option<String> {
+        self.inner.network_interface(sandbox_id).await
+    }
+
+    async fn logs(&self, sandbox_id: Uuid, follow: bool) -> Result<Box<dyn tokio::io::AsyncRead + Send + Unpin>> {
+        self.inner.logs(sandbox_id, follow).await
+    }
+
+    async fn active_sandboxes(&self) -> usize {
+        self.inner.active_sandboxes().await
+    }
+
+    fn is_remote(&self) -> bool {
+        self.inner.is_remote()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::mock::{MockBehavior, MockRuntime};
+
+    fn config(language: &str) -> SandboxConfig {
+        SandboxConfig {
+            id: Uuid::new_v4(),
+            image: format!("sandstorm/{}", language),
+            command: vec!["true".to_string()],
+            environment: HashMap::new(),
+            cpu_limit: None,
+            memory_limit: None,
+            timeout: None,
+            isolation_level: IsolationLevel::Standard,
+            runtime_preference: None,
+            working_dir: None,
+            mounts: Vec::new(),
+            execution_mode: ExecutionMode::Standard,
+            scratch_size: None,
+            sysctls: HashMap::new(),
+            gvisor: Default::default(),
+            firecracker: Default::default(),
+            arch: None,
+            rootfs_layers: Vec::new(),
+            ttl_seconds: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn follows_sandboxes_through_their_runtime() {
+        let index = Arc::new(SandboxIndex::new());
+        let behavior = MockBehavior {
+            delay_ms: 0,
+            ..Default::default()
+        };
+        let runtime = IndexedRuntime::new(Arc::new(MockRuntime::new(behavior)), index.clone());
+
+        let python = runtime.create(&config("python")).await.unwrap();
+        let started = Utc::now();
+        let node = runtime.create(&config("node")).await.unwrap();
+        let listed = index.list(&SandboxFilter::default()).await;
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].sandbox_id, python);
+        assert_eq!(listed[0].language.as_deref(), Some("python"));
+        assert_eq!(listed[0].runtime_type, RuntimeType::Mock);
+
+        let filter = |language: &str| SandboxFilter {
+            language: Some(language.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(index.list(&filter("node")).await[0].sandbox_id, node);
+        assert!(index.list(&filter("ruby")).await.is_empty());
+        let recent = SandboxFilter {
+            created_after: Some(started),
+            ..Default::default()
+        };
+        assert_eq!(index.list(&recent).await.len(), 1);
+
+        // Resumed sandboxes keep what the original ran
+        let snapshot = runtime.snapshot(python).await.unwrap();
+        let resumed = runtime.resume(&snapshot).await.unwrap();
+        assert_eq!(index.get(resumed).await.unwrap().language.as_deref(), Some("python"));
+
+        runtime.destroy(python).await.unwrap();
+        assert!(index.get(python).await.is_none());
+
+        // States change as the runtime reports them
+        assert_eq!(runtime.status(node).await.unwrap().state, SandboxState::Stopped);
+        let running = SandboxFilter {
+            state: Some(SandboxState::Running),
+            runtime: Some(RuntimeType::Mock),
+            ..Default::default()
+        };
+        let running: Vec<_> = index.list(&running).await.into_iter().map(|sandbox| sandbox.sandbox_id).collect();
+        assert_eq!(running, [resumed]);
+    }
+
+    #[tokio::test]
+    async fn lists_sandboxes_past_their_ttl() {
+        let index = Arc::new(SandboxIndex::new());
+        let behavior = MockBehavior {
+            delay_ms: 0,
+            ..Default::default()
+        };
+        let runtime = IndexedRuntime::new(Arc::new(MockRuntime::new(behavior)), index.clone());
+
+        let mut short = config("python");
+        short.ttl_seconds = Some(60);
+        let short = runtime.create(&short).await.unwrap();
+        let forever = runtime.create(&config("python")).await.unwrap();
+        let mut huge = config("python");
+        huge.ttl_seconds = Some(u64::MAX);
+        let huge = runtime.create(&huge).await.unwrap();
+        assert!(index.get(forever).await.unwrap().expires_at.is_none());
+        assert!(index.get(huge).await.unwrap().expires_at.is_none());
+
+        assert!(index.expired(Utc::now()).await.is_empty());
+        let later = Utc::now() + chrono::Duration::seconds(61);
+        let expired = |index: Arc<SandboxIndex>| async move {
+            index.expired(later).await.into_iter().map(|sandbox| sandbox.sandbox_id).collect::<Vec<_>>()
+        };
+        assert_eq!(expired(index.clone()).await, [short]);
+
+        // A resumed sandbox expires when the original would have
+        let snapshot = runtime.snapshot(short).await.unwrap();
+        let resumed = runtime.resume(&snapshot).await.unwrap();
+        runtime.destroy(short).await.unwrap();
+        assert_eq!(expired(index).await, [resumed]);
+    }
+}
@@ -2,10 +2,11 @@ use super::*;
 use anyhow::{Context, Result};
 use std::collections::HashMap;
 use std::path::PathBuf;
-use std::process::Stdio;
 use tokio::process::Command;
 use tracing::{error, info, warn};
 
+use super::lifecycle::{Lifecycle, LifecycleEvents};
+
 /// Kata Containers runtime implementation for strong isolation
 pub struct KataRuntime {
     /// Path to kata-runtime binary
@@ -16,16 +17,18 @@ pub struct KataRuntime {
     runtime_root: PathBuf,
     /// Active sandboxes
     sandboxes: RwLock<HashMap<Uuid, SandboxInfo>>,
+    /// Where sandbox state changes are sent
+    events: LifecycleEvents,
+    /// Architecture of the host, which sandboxes' seccomp profiles name
+    arch: Arch,
 }
 
 #[derive(Debug, Clone)]
 struct SandboxInfo {
     container_id: String,
     bundle_path: PathBuf,
-    state: SandboxState,
+    lifecycle: Lifecycle,
     config: SandboxConfig,
-    created_at: chrono::DateTime<chrono::Utc>,
-    started_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 impl KataRuntime {
@@ -49,6 +52,40 @@ impl KataRuntime {
             base_dir,
             runtime_root,
             sandboxes: RwLock::new(HashMap::new()),
+            events: LifecycleEvents::new(),
+            arch: arch::detect()?,
+        })
+    }
+
+    /// Send sandbox state changes to these subscribers
+    pub fn with_lifecycle_events(mut self, events: LifecycleEvents) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// State of a container as kata-runtime reports it; a container it
+    /// can't report on has failed
+    async fn container_state(&self, container_id: &str) -> Result<SandboxState> {
+        let mut cmd = Command::new(&self.kata_bin);
+        cmd.args([
+            "--root", self.runtime_root.to_str().unwrap(),
+            "state",
+            container_id,
+        ]);
+
+        let output = command::output(&mut cmd, "get Kata container state").await?;
+        if !output.status.success() {
+            return Ok(SandboxState::Failed);
+        }
+
+        let state_json: serde_json::Value = serde_json::from_slice(&output.stdout)
+            .context("Failed to parse container state")?;
+        Ok(match state_json["status"].as_str() {
+            Some("created") => SandboxState::Starting,
+            Some("running") => SandboxState::Running,
+            Some("paused") => SandboxState::Paused,
+            Some("stopped") => SandboxState::Stopped,
+            _ => SandboxState::Failed,
         })
     }
 
@@ -132,7 +169,7 @@ impl KataRuntime {
             "true".to_string(),
         );
 
-        Ok(serde_json::json!({
+        let mut spec = serde_json::json!({
             "ociVersion": "1.0.2",
             "process": {
                 "terminal": false,
@@ -171,6 +208,7 @@ impl KataRuntime {
             "hostname": format!("kata-{}", config.id),
             "mounts": mounts,
             "linux": {
+                "cgroupsPath": exit::cgroup_path(config.id),
                 "resources": {
                     "devices": [{
                         "allow": false,
@@ -194,7 +232,13 @@ impl KataRuntime {
                 ]
             },
             "annotations": annotations
-        }))
+        });
+        if !config.sysctls.is_empty() {
+            spec["linux"]["sysctl"] = serde_json::json!(config.sysctls);
+        }
+        read_only::apply_to_oci_spec(&mut spec, config, self.arch);
+
+        Ok(spec)
     }
 
     /// Create container bundle
@@ -211,6 +255,12 @@ impl KataRuntime {
         let spec_path = bundle_path.join("config.json");
         std::fs::write(&spec_path, serde_json::to_string_pretty(&spec)?)?;
 
+        // A root filesystem restored from vault layers replaces the image's
+        if !config.rootfs_layers.is_empty() {
+            overlay::mount(&bundle_path, &rootfs_path, &config.rootfs_layers).await?;
+            return Ok(bundle_path);
+        }
+
         // Extract rootfs from image (simplified - in reality would use proper OCI image handling)
         // For now, create a minimal rootfs
         let dirs = ["bin", "dev", "etc", "home", "lib", "lib64", "proc", "root", "sys", "tmp", "usr", "var"];
@@ -224,91 +274,43 @@ impl KataRuntime {
 
         Ok(bundle_path)
     }
-}
-
-#[async_trait]
-impl SandboxRuntime for KataRuntime {
-    fn runtime_type(&self) -> RuntimeType {
-        RuntimeType::Kata
-    }
-
-    fn supports_isolation_level(&self, level: IsolationLevel) -> bool {
-        // Kata provides strong isolation through lightweight VMs
-        matches!(level, IsolationLevel::Strong | IsolationLevel::Maximum)
-    }
-
-    async fn create(&self, config: &SandboxConfig) -> Result<Uuid> {
-        let sandbox_id = config.id;
-        let container_id = format!("kata-{}", sandbox_id);
-
-        // Create container bundle
-        let bundle_path = self.create_bundle(config).await?;
-
-        // Create container using kata-runtime
-        let mut cmd = Command::new(&self.kata_bin);
-        cmd.args([
-            "--root", self.runtime_root.to_str().unwrap(),
-            "create",
-            "--bundle", bundle_path.to_str().unwrap(),
-            &container_id,
-        ]);
-
-        cmd.env("KATA_RUNTIME_LOG_LEVEL", "debug");
-        cmd.stderr(Stdio::piped());
-        
-        let output = cmd.output().await.context("Failed to create Kata container")?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("Failed to create container: {}", stderr);
-        }
-
-        // Start the container
-        let mut cmd = Command::new(&self.kata_bin);
-        cmd.args([
-            "--root", self.runtime_root.to_str().unwrap(),
-            "start",
-            &container_id,
-        ]);
-
-        let output = cmd.output().await.context("Failed to start Kata container")?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("Failed to start container: {}", stderr);
-        }
-
-        // Store sandbox info
-        let info = SandboxInfo {
-            container_id,
-            bundle_path,
-            state: SandboxState::Running,
-            config: config.clone(),
-            created_at: chrono::Utc::now(),
-            started_at: Some(chrono::Utc::now()),
-        };
-
-        let mut sandboxes = self.sandboxes.write().await;
-        sandboxes.insert(sandbox_id, info);
-
-        info!("Created Kata sandbox {}", sandbox_id);
-        Ok(sandbox_id)
-    }
 
-    async fn exec(
+    /// Run a command in a sandbox, streaming its output to `stream` if
+    /// there is one
+    async fn run_exec(
         &self,
         sandbox_id: Uuid,
         command: Vec<String>,
         environment: Option<HashMap<String, String>>,
+        options: &ExecOptions,
+        input: Option<ExecInputReceiver>,
+        stream: Option<&ExecOutputSender>,
     ) -> Result<SandboxResult> {
         let sandboxes = self.sandboxes.read().await;
         let info = sandboxes.get(&sandbox_id)
             .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
 
-        if info.state != SandboxState::Running {
+        if info.lifecycle.state() != SandboxState::Running {
             anyhow::bail!("Sandbox {} is not running", sandbox_id);
         }
+        options.check(
+            RuntimeType::Kata,
+            ExecSupport {
+                working_dir: true,
+                user: true,
+                tty: true,
+                limits: true,
+                stdin: true,
+            },
+        )?;
+        options.limits.check(&info.config)?;
 
         let start_time = std::time::Instant::now();
+        // The VM's cgroup only sees the VM itself run out of memory; OOM
+        // kills and panics inside the guest show up on its console
+        let console = self.console_log(&info.container_id);
+        let console_offset = exit::console_len(&console).await;
+        let oom_kills = exit::oom_kills(sandbox_id).await;
 
         // Execute command in container
         let mut cmd = Command::new(&self.kata_bin);
@@ -316,6 +318,15 @@ impl SandboxRuntime for KataRuntime {
             "--root", self.runtime_root.to_str().unwrap(),
             "exec",
         ]);
+        if let Some(dir) = &options.working_dir {
+            cmd.args(["--cwd", dir]);
+        }
+        if let Some(user) = &options.user {
+            cmd.args(["--user", user]);
+        }
+        if options.tty {
+            cmd.arg("--tty");
+        }
 
         // Add environment variables
         if let Some(env) = environment {
@@ -324,18 +335,31 @@ impl SandboxRuntime for KataRuntime {
             }
         }
 
-        // Add container ID and command
+        // Add container ID and command, under its own limits if it has any
         cmd.arg(&info.container_id);
-        cmd.args(&command);
+        cmd.args(options.limits.wrap(command));
 
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
+        // Stop the exec if the gateway gives up on it
+        cmd.kill_on_drop(true);
 
-        let output = cmd.output().await.context("Failed to execute command in container")?;
+        // An exec reading stdin lasts as long as its client keeps it open,
+        // so it mustn't hold up changes to other sandboxes
+        let container_id = info.container_id.clone();
+        drop(sandboxes);
+        let input = input.or_else(|| options.stdin_input());
+        let output = command::interact(&mut cmd, "exec in Kata container", input, stream).await?;
         let duration_ms = start_time.elapsed().as_millis() as u64;
+        let exit_code = output.status.code().unwrap_or(-1);
+        let guest = exit::guest_events_since(&console, console_offset).await;
+        let exit_reason = if guest.panicked {
+            ExitReason::Crashed
+        } else {
+            let oom_killed = guest.oom_killed || exit::oom_kills(sandbox_id).await > oom_kills;
+            options.limits.exit_reason(exit_code, exit::of_status(output.status, oom_killed))
+        };
 
         // Get resource usage from VM metrics
-        let resource_usage = self.get_resource_usage(&info.container_id).await
+        let resource_usage = self.get_resource_usage(&container_id).await
             .unwrap_or_else(|_| ResourceUsage {
                 cpu_usage_seconds: duration_ms as f64 / 1000.0,
                 memory_usage_bytes: 0,
@@ -345,18 +369,132 @@ impl SandboxRuntime for KataRuntime {
 
         Ok(SandboxResult {
             id: sandbox_id,
-            exit_code: output.status.code().unwrap_or(-1),
+            exit_code,
             stdout: output.stdout,
             stderr: output.stderr,
             duration_ms,
             resource_usage,
+            exit_reason,
         })
     }
+}
+
+#[async_trait]
+impl SandboxRuntime for KataRuntime {
+    fn runtime_type(&self) -> RuntimeType {
+        RuntimeType::Kata
+    }
+
+    fn supports_isolation_level(&self, level: IsolationLevel) -> bool {
+        // Kata provides strong isolation through lightweight VMs
+        matches!(level, IsolationLevel::Strong | IsolationLevel::Maximum)
+    }
+
+    fn supports_execution_mode(&self, _mode: ExecutionMode) -> bool {
+        true
+    }
+
+    fn supports_sysctls(&self) -> bool {
+        true
+    }
+
+    fn supports_rootfs_layers(&self) -> bool {
+        true
+    }
+
+    async fn create(&self, config: &SandboxConfig) -> Result<Uuid> {
+        let sandbox_id = config.id;
+        let container_id = format!("kata-{}", sandbox_id);
+        let mut lifecycle = Lifecycle::new(sandbox_id, RuntimeType::Kata, &self.events);
+
+        let started = async {
+            // Create container bundle
+            let bundle_path = self.create_bundle(config).await?;
+
+            // Create container using kata-runtime
+            let mut cmd = Command::new(&self.kata_bin);
+            cmd.args([
+                "--root", self.runtime_root.to_str().unwrap(),
+                "create",
+                "--bundle", bundle_path.to_str().unwrap(),
+                &container_id,
+            ]);
+
+            cmd.env("KATA_RUNTIME_LOG_LEVEL", "debug");
+            command::run(&mut cmd, "create Kata container").await?;
+            lifecycle.transition(SandboxState::Starting)?;
+
+            // Start the container
+            let mut cmd = Command::new(&self.kata_bin);
+            cmd.args([
+                "--root", self.runtime_root.to_str().unwrap(),
+                "start",
+                &container_id,
+            ]);
+
+            command::run(&mut cmd, "start Kata container").await?;
+
+            // The VM may still fail to boot after the start is accepted
+            match self.container_state(&container_id).await? {
+                SandboxState::Running => lifecycle.transition(SandboxState::Running)?,
+                state => anyhow::bail!("Kata container {} is {:?} after starting", container_id, state),
+            }
+            Ok(bundle_path)
+        }
+        .await;
+
+        let bundle_path = match started {
+            Ok(bundle_path) => bundle_path,
+            Err(e) => {
+                lifecycle.transition(SandboxState::Failed).ok();
+                return Err(e);
+            }
+        };
+
+        // Store sandbox info
+        let info = SandboxInfo {
+            container_id,
+            bundle_path,
+            lifecycle,
+            config: config.clone(),
+        };
+
+        let mut sandboxes = self.sandboxes.write().await;
+        sandboxes.insert(sandbox_id, info);
+
+        info!("Created Kata sandbox {}", sandbox_id);
+        Ok(sandbox_id)
+    }
+
+    async fn exec(
+        &self,
+        sandbox_id: Uuid,
+        command: Vec<String>,
+        environment: Option<HashMap<String, String>>,
+        options: &ExecOptions,
+    ) -> Result<SandboxResult> {
+        self.run_exec(sandbox_id, command, environment, options, None, None).await
+    }
+
+    async fn exec_streaming(
+        &self,
+        sandbox_id: Uuid,
+        command: Vec<String>,
+        environment: Option<HashMap<String, String>>,
+        options: &ExecOptions,
+        input: Option<ExecInputReceiver>,
+        output: ExecOutputSender,
+    ) -> Result<SandboxResult> {
+        self.run_exec(sandbox_id, command, environment, options, input, Some(&output))
+            .await
+    }
 
     async fn destroy(&self, sandbox_id: Uuid) -> Result<()> {
         let mut sandboxes = self.sandboxes.write().await;
         
-        if let Some(info) = sandboxes.remove(&sandbox_id) {
+        if let Some(mut info) = sandboxes.remove(&sandbox_id) {
+            info.lifecycle.transition(SandboxState::Stopping).ok();
+
             // Stop the container
             let mut cmd = Command::new(&self.kata_bin);
             cmd.args([
@@ -365,7 +503,7 @@ impl SandboxRuntime for KataRuntime {
                 &info.container_id,
                 "KILL",
             ]);
-            cmd.output().await.ok();
+            command::run(&mut cmd, "kill Kata container").await.ok();
 
             // Delete the container
             let mut cmd = Command::new(&self.kata_bin);
@@ -374,13 +512,20 @@ impl SandboxRuntime for KataRuntime {
                 "delete",
                 &info.container_id,
             ]);
-            cmd.output().await.ok();
+            command::run(&mut cmd, "delete Kata container").await.ok();
+
+            if !info.config.rootfs_layers.is_empty() {
+                if let Err(e) = overlay::unmount(&info.bundle_path.join("rootfs")).await {
+                    error!("Failed to unmount root filesystem layers: {}", e);
+                }
+            }
 
             // Remove bundle directory
             if let Err(e) = tokio::fs::remove_dir_all(&info.bundle_path).await {
                 error!("Failed to remove bundle directory: {}", e);
             }
 
+            info.lifecycle.transition(SandboxState::Stopped).ok();
             info!("Destroyed Kata sandbox {}", sandbox_id);
         }
 
@@ -413,6 +558,16 @@ impl SandboxRuntime for KataRuntime {
         Ok(snapshot)
     }
 
+    async fn export_filesystem(&self, sandbox_id: Uuid) -> Result<Vec<u8>> {
+        let rootfs = {
+            let sandboxes = self.sandboxes.read().await;
+            let info = sandboxes.get(&sandbox_id)
+                .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
+            info.bundle_path.join("rootfs")
+        };
+        command::tar(&rootfs).await
+    }
+
     async fn resume(&self, snapshot: &SandboxSnapshot) -> Result<Uuid> {
         // Kata doesn't support live restore out of the box
         // We would need to implement VM restore functionality
@@ -423,33 +578,48 @@ impl SandboxRuntime for KataRuntime {
         Ok(new_sandbox_id)
     }
 
-    async fn status(&self, sandbox_id: Uuid) -> Result<SandboxStatus> {
-        let sandboxes = self.sandboxes.read().await;
-        let info = sandboxes.get(&sandbox_id)
+    async fn set_frozen(&self, sandbox_id: Uuid, frozen: bool) -> Result<()> {
+        let mut sandboxes = self.sandboxes.write().await;
+        let info = sandboxes.get_mut(&sandbox_id)
             .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
+        let target = if frozen { SandboxState::Paused } else { SandboxState::Running };
+        info.lifecycle.check_change(target)?;
 
-        // Get container state
         let mut cmd = Command::new(&self.kata_bin);
         cmd.args([
             "--root", self.runtime_root.to_str().unwrap(),
-            "state",
+            if frozen { "pause" } else { "resume" },
             &info.container_id,
         ]);
 
-        let output = cmd.output().await.context("Failed to get container state")?;
-        
-        let state = if output.status.success() {
-            let state_json: serde_json::Value = serde_json::from_slice(&output.stdout)
-                .context("Failed to parse container state")?;
-
-            match state_json["status"].as_str() {
-                Some("running") => SandboxState::Running,
-                Some("paused") => SandboxState::Paused,
-                Some("stopped") => SandboxState::Stopped,
-                _ => SandboxState::Failed,
+        let action = if frozen { "pause Kata container" } else { "resume Kata container" };
+        command::run(&mut cmd, action).await?;
+
+        info.lifecycle.transition(target)?;
+        info!("{} Kata sandbox {}", if frozen { "Froze" } else { "Thawed" }, sandbox_id);
+        Ok(())
+    }
+
+    async fn status(&self, sandbox_id: Uuid) -> Result<SandboxStatus> {
+        let mut sandboxes = self.sandboxes.write().await;
+        let info = sandboxes.get_mut(&sandbox_id)
+            .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
+
+        // Get container state
+        let reported = self.container_state(&info.container_id).await?;
+        let state = info.lifecycle.observe(reported);
+
+        let exit_reason = match exit::of_state(state, false) {
+            Some(_) => {
+                let guest = exit::guest_events_since(&self.console_log(&info.container_id), 0).await;
+                if guest.panicked {
+                    Some(ExitReason::Crashed)
+                } else {
+                    let oom_killed = guest.oom_killed || exit::oom_kills(sandbox_id).await > 0;
+                    exit::of_state(state, oom_killed)
+                }
             }
-        } else {
-            SandboxState::Failed
+            None => None,
         };
 
         let resource_usage = self.get_resource_usage(&info.container_id).await
@@ -463,24 +633,27 @@ impl SandboxRuntime for KataRuntime {
         Ok(SandboxStatus {
             id: sandbox_id,
             state,
-            created_at: info.created_at,
-            started_at: info.started_at,
-            finished_at: None,
+            created_at: info.lifecycle.created_at(),
+            started_at: info.lifecycle.started_at(),
+            finished_at: info.lifecycle.finished_at(),
             exit_code: None,
             resource_usage,
+            exit_reason,
+            transitions: info.lifecycle.transitions().to_vec(),
         })
     }
 
-    async fn logs(&self, sandbox_id: Uuid, _follow: bool) -> Result<Box<dyn tokio::io::AsyncRead + Send + Unpin>> {
+    async fn logs(&self, sandbox_id: Uuid, follow: bool) -> Result<Box<dyn tokio::io::AsyncRead + Send + Unpin>> {
         let sandboxes = self.sandboxes.read().await;
         let info = sandboxes.get(&sandbox_id)
             .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
 
-        // Get container logs directory
-        let log_dir = self.runtime_root.join("containers").join(&info.container_id);
-        let log_file = log_dir.join("console.log");
+        let log_file = self.console_log(&info.container_id);
 
         if log_file.exists() {
+            if follow {
+                return Ok(Box::new(follow::FollowFile::open(&log_file).await?));
+            }
             let file = tokio::fs::File::open(log_file).await?;
             Ok(Box::new(file))
         } else {
@@ -489,9 +662,18 @@ impl SandboxRuntime for KataRuntime {
             Ok(Box::new(empty))
         }
     }
+
+    async fn active_sandboxes(&self) -> usize {
+        self.sandboxes.read().await.len()
+    }
 }
 
 impl KataRuntime {
+    /// The VM's console output, where the guest kernel logs
+    fn console_log(&self, container_id: &str) -> PathBuf {
+        self.runtime_root.join("containers").join(container_id).join("console.log")
+    }
+
     /// Get resource usage from Kata metrics
     async fn get_resource_usage(&self, _container_id: &str) -> Result<ResourceUsage> {
         // In a real implementation, we would query Kata metrics API
@@ -0,0 +1,243 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use tokio::sync::broadcast;
+use tracing::debug;
+use uuid::Uuid;
+
+use super::{RuntimeType, SandboxState};
+
+/// State changes buffered for slow subscribers before they miss some
+const EVENT_BUFFER: usize = 1024;
+
+impl SandboxState {
+    /// Whether a sandbox in this state may move to `next`. Sandboxes are
+    /// created, started, then run; a running sandbox may be paused and
+    /// resumed, and every sandbox that isn't done can be stopped or fail.
+    /// Running and paused sandboxes may also stop on their own or be
+    /// preempted.
+    pub fn can_become(self, next: SandboxState) -> bool {
+        use SandboxState::*;
+        matches!(
+            (self, next),
+            (Creating, Starting | Stopping | Failed)
+                | (Starting, Running | Stopping | Failed)
+                | (Running, Paused | Stopping | Stopped | Failed | Preempted)
+                | (Paused, Running | Stopping | Stopped | Failed | Preempted)
+                | (Stopping, Stopped | Failed)
+        )
+    }
+
+    /// Whether the sandbox is done and can't change state again
+    pub fn is_terminal(self) -> bool {
+        matches!(self, SandboxState::Stopped | SandboxState::Failed | SandboxState::Preempted)
+    }
+}
+
+/// A state a sandbox entered, and when
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Transition {
+    pub state: SandboxState,
+    pub at: DateTime<Utc>,
+}
+
+/// A sandbox changing state, as sent to [`LifecycleEvents`] subscribers
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StateChange {
+    pub sandbox_id: Uuid,
+    pub runtime: RuntimeType,
+    pub from: SandboxState,
+    pub to: SandboxState,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("sandbox {sandbox_id} can't go from {from:?} to {to:?}")]
+pub struct InvalidTransition {
+    pub sandbox_id: Uuid,
+    pub from: SandboxState,
+    pub to: SandboxState,
+}
+
+/// Broadcasts every state change of the sandboxes of the runtimes sharing it
+#[derive(Debug, Clone)]
+pub struct LifecycleEvents {
+    sender: broadcast::Sender<StateChange>,
+}
+
+impl LifecycleEvents {
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StateChange> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for LifecycleEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A sandbox's current state and the states it went through, with the
+/// transitions between them checked
+#[derive(Debug, Clone)]
+pub struct Lifecycle {
+    sandbox_id: Uuid,
+    runtime: RuntimeType,
+    transitions: Vec<Transition>,
+    events: LifecycleEvents,
+}
+
+impl Lifecycle {
+    /// A sandbox being created now
+    pub fn new(sandbox_id: Uuid, runtime: RuntimeType, events: &LifecycleEvents) -> Self {
+        Self {
+            sandbox_id,
+            runtime,
+            transitions: vec![Transition {
+                state: SandboxState::Creating,
+                at: Utc::now(),
+            }],
+            events: events.clone(),
+        }
+    }
+
+    pub fn state(&self) -> SandboxState {
+        self.transitions.last().map(|transition| transition.state).unwrap_or(SandboxState::Creating)
+    }
+
+    pub fn transitions(&self) -> &[Transition] {
+        &self.transitions
+    }
+
+    pub fn created_at(&self) -> DateTime<Utc> {
+        self.transitions.first().map(|transition| transition.at).unwrap_or_else(Utc::now)
+    }
+
+    /// When the sandbox first started running
+    pub fn started_at(&self) -> Option<DateTime<Utc>> {
+        self.transitions
+            .iter()
+            .find(|transition| transition.state == SandboxState::Running)
+            .map(|transition| transition.at)
+    }
+
+    /// When the sandbox stopped or failed
+    pub fn finished_at(&self) -> Option<DateTime<Utc>> {
+        let last = self.transitions.last()?;
+        last.state.is_terminal().then_some(last.at)
+    }
+
+    /// Fail unless the sandbox is in `to` or may move to it, to check
+    /// before acting on the sandbox
+    pub fn check(&self, to: SandboxState) -> Result<(), InvalidTransition> {
+        let from = self.state();
+        if from == to || from.can_become(to) {
+            Ok(())
+        } else {
+            Err(InvalidTransition {
+                sandbox_id: self.sandbox_id,
+                from,
+                to,
+            })
+        }
+    }
+
+    /// Fail unless the sandbox is in another state it may leave for `to`,
+    /// to check before acting on the sandbox in a way that can't be repeated
+    pub fn check_change(&self, to: SandboxState) -> Result<(), InvalidTransition> {
+        let from = self.state();
+        if from != to && from.can_become(to) {
+            Ok(())
+        } else {
+            Err(InvalidTransition {
+                sandbox_id: self.sandbox_id,
+                from,
+                to,
+            })
+        }
+    }
+
+    /// Move to `to`, recording when and telling subscribers. Moving to the
+    /// current state is a no-op.
+    pub fn transition(&mut self, to: SandboxState) -> Result<(), InvalidTransition> {
+        self.check(to)?;
+        let from = self.state();
+        if from == to {
+            return Ok(());
+        }
+
+        let at = Utc::now();
+        self.transitions.push(Transition { state: to, at });
+        debug!(sandbox_id = %self.sandbox_id, ?from, ?to, "Sandbox changed state");
+        // Nobody listening is fine
+        let _ = self.events.sender.send(StateChange {
+            sandbox_id: self.sandbox_id,
+            runtime: self.runtime,
+            from,
+            to,
+            at,
+        });
+        Ok(())
+    }
+
+    /// Take the state the runtime reports, when the sandbox can be in it
+    /// now. Reports that contradict the recorded history, such as a
+    /// stopped sandbox running again, are ignored and the recorded state
+    /// kept.
+    pub fn observe(&mut self, reported: SandboxState) -> SandboxState {
+        let _ = self.transition(reported);
+        self.state()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn transitions_are_checked_timed_and_broadcast() {
+        let events = LifecycleEvents::new();
+        let mut changes = events.subscribe();
+        let sandbox_id = Uuid::new_v4();
+        let mut lifecycle = Lifecycle::new(sandbox_id, RuntimeType::Gvisor, &events);
+        assert_eq!(lifecycle.state(), SandboxState::Creating);
+
+        // Running is only reached through starting
+        let error = lifecycle.transition(SandboxState::Running).unwrap_err();
+        assert_eq!(error.from, SandboxState::Creating);
+
+        lifecycle.transition(SandboxState::Starting).unwrap();
+        lifecycle.transition(SandboxState::Running).unwrap();
+        lifecycle.transition(SandboxState::Paused).unwrap();
+        lifecycle.transition(SandboxState::Paused).unwrap();
+        // Pausing again isn't a change
+        assert!(lifecycle.check_change(SandboxState::Paused).is_err());
+        lifecycle.check_change(SandboxState::Running).unwrap();
+        lifecycle.transition(SandboxState::Running).unwrap();
+        assert!(lifecycle.started_at().is_some());
+        assert_eq!(lifecycle.finished_at(), None);
+
+        lifecycle.transition(SandboxState::Stopping).unwrap();
+        assert!(lifecycle.transition(SandboxState::Running).is_err());
+        lifecycle.transition(SandboxState::Stopped).unwrap();
+        assert_eq!(lifecycle.finished_at(), lifecycle.transitions().last().map(|transition| transition.at));
+
+        // A stopped sandbox stays stopped, whatever the runtime reports
+        assert_eq!(lifecycle.observe(SandboxState::Running), SandboxState::Stopped);
+        assert_eq!(lifecycle.transitions().len(), 7);
+
+        let first = changes.recv().await.unwrap();
+        assert_eq!((first.sandbox_id, first.from, first.to), (sandbox_id, SandboxState::Creating, SandboxState::Starting));
+        let mut last = first;
+        while let Ok(change) = changes.try_recv() {
+            last = change;
+        }
+        assert_eq!((last.from, last.to), (SandboxState::Stopping, SandboxState::Stopped));
+    }
+}
@@ -0,0 +1,120 @@
+//! Per-exec resource limits, tighter than the sandbox's own, so a
+//! long-lived sandbox can run a short untrusted command without lending it
+//! everything the sandbox may use. The command runs under a `/bin/sh`
+//! wrapper that sets `ulimit`s and hands it to `timeout(1)`, so the limits
+//! hold inside the sandbox whatever runtime hosts it.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{ExitReason, SandboxConfig, UnsupportedExecOptions};
+
+/// `argv[0]` of the wrapper shell, as `ps` in the sandbox shows it
+const WRAPPER_NAME: &str = "sandstorm-exec";
+
+/// Exit code of a command killed by `SIGXCPU` for using up its CPU time
+const CPU_EXCEEDED_EXIT_CODE: i32 = 128 + 24;
+
+/// Seconds `timeout(1)` waits after `SIGTERM` before sending `SIGKILL`
+const KILL_AFTER_SECS: u64 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct ExecLimits {
+    /// CPU time the command may use, in seconds (`RLIMIT_CPU`)
+    pub cpu_seconds: Option<u64>,
+    /// Virtual memory the command may map, in bytes (`RLIMIT_AS`),
+    /// rounded up to a KiB
+    pub memory_bytes: Option<u64>,
+    /// Wall-clock time before the command is stopped inside the sandbox
+    pub time_ms: Option<u64>,
+}
+
+impl ExecLimits {
+    pub fn is_set(&self) -> bool {
+        self.cpu_seconds.is_some() || self.memory_bytes.is_some() || self.time_ms.is_some()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for (limit, value) in [
+            ("cpu_seconds", self.cpu_seconds),
+            ("memory_bytes", self.memory_bytes),
+            ("time_ms", self.time_ms),
+        ] {
+            if value == Some(0) {
+                anyhow::bail!("limits.{} must be greater than 0", limit);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail with [`UnsupportedExecOptions`] if a limit is looser than the
+    /// sandbox's: an exec can only be held to less than its sandbox is
+    pub fn check(&self, config: &SandboxConfig) -> Result<()> {
+        let exceeds = |limit: Option<u64>, bound: Option<u64>| matches!((limit, bound), (Some(limit), Some(bound)) if limit > bound);
+        // CPU time the sandbox could burn through before its timeout
+        let cpu_bound = config
+            .timeout
+            .zip(config.cpu_limit)
+            .map(|(timeout_ms, cpus)| (timeout_ms as f64 / 1000.0 * cpus).ceil() as u64);
+
+        let looser = [
+            ("memory_bytes", exceeds(self.memory_bytes, config.memory_limit), "memory limit"),
+            ("time_ms", exceeds(self.time_ms, config.timeout), "timeout"),
+            ("cpu_seconds", exceeds(self.cpu_seconds, cpu_bound), "CPU time"),
+        ];
+        match looser.iter().find(|(_, looser, _)| *looser) {
+            Some((limit, _, bound)) => Err(UnsupportedExecOptions(format!(
+                "limits.{} exceeds the sandbox's {}",
+                limit, bound
+            ))
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// `command` wrapped to run under these limits; unchanged when none are
+    /// set
+    pub fn wrap(&self, command: Vec<String>) -> Vec<String> {
+        if !self.is_set() {
+            return command;
+        }
+
+        let mut script = Vec::new();
+        if let Some(seconds) = self.cpu_seconds {
+            script.push(format!("ulimit -t {}", seconds));
+        }
+        if let Some(bytes) = self.memory_bytes {
+            script.push(format!("ulimit -v {}", bytes.div_ceil(1024)));
+        }
+        script.push(match self.time_ms {
+            Some(ms) => format!(
+                "exec timeout -k {} {}.{:03} \"$@\"",
+                KILL_AFTER_SECS,
+                ms / 1000,
+                ms % 1000
+            ),
+            None => "exec \"$@\"".to_string(),
+        });
+
+        let mut wrapped = vec![
+            "/bin/sh".to_string(),
+            "-c".to_string(),
+            script.join(" && "),
+            WRAPPER_NAME.to_string(),
+        ];
+        wrapped.extend(command);
+        wrapped
+    }
+
+    /// Why a command run under these limits stopped: running out of CPU
+    /// time is a timeout rather than a crash
+    pub fn exit_reason(&self, exit_code: i32, reason: ExitReason) -> ExitReason {
+        if self.cpu_seconds.is_some() && exit_code == CPU_EXCEEDED_EXIT_CODE && reason == ExitReason::Crashed {
+            ExitReason::Timeout
+        } else {
+            reason
+        }
+    }
+}
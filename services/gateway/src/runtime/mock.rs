@@ -0,0 +1,478 @@
+//! Simulated runtime for CI and local development. Nothing is executed:
+//! sandboxes "run" for a configured time and report configured output.
+
+use super::*;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use tracing::info;
+
+/// What simulated sandboxes report. Each field is read from the gateway's
+/// environment, and a sandbox's own environment can override it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MockBehavior {
+    /// `SANDSTORM_MOCK_STDOUT`
+    pub stdout: String,
+    /// `SANDSTORM_MOCK_STDERR`
+    pub stderr: String,
+    /// `SANDSTORM_MOCK_EXIT_CODE`
+    pub exit_code: i32,
+    /// `SANDSTORM_MOCK_DELAY_MS`: how long each exec takes, and how long a
+    /// sandbox runs before it stops on its own
+    pub delay_ms: u64,
+    /// `SANDSTORM_MOCK_CPU_SECONDS`
+    pub cpu_usage_seconds: f64,
+    /// `SANDSTORM_MOCK_MEMORY_BYTES`
+    pub memory_usage_bytes: u64,
+}
+
+impl Default for MockBehavior {
+    fn default() -> Self {
+        Self {
+            stdout: String::new(),
+            stderr: String::new(),
+            exit_code: 0,
+            delay_ms: 100,
+            cpu_usage_seconds: 0.0,
+            memory_usage_bytes: 0,
+        }
+    }
+}
+
+impl MockBehavior {
+    /// This behavior with any `SANDSTORM_MOCK_*` values in `environment`
+    /// applied on top. Values that don't parse are ignored.
+    pub fn overridden_by(&self, environment: &HashMap<String, String>) -> Self {
+        fn parse<T: std::str::FromStr>(value: Option<&String>, current: T) -> T {
+            value.and_then(|value| value.parse().ok()).unwrap_or(current)
+        }
+
+        Self {
+            stdout: environment
+                .get("SANDSTORM_MOCK_STDOUT")
+                .cloned()
+                .unwrap_or_else(|| self.stdout.clone()),
+            stderr: environment
+                .get("SANDSTORM_MOCK_STDERR")
+                .cloned()
+                .unwrap_or_else(|| self.stderr.clone()),
+            exit_code: parse(environment.get("SANDSTORM_MOCK_EXIT_CODE"), self.exit_code),
+            delay_ms: parse(environment.get("SANDSTORM_MOCK_DELAY_MS"), self.delay_ms),
+            cpu_usage_seconds: parse(
+                environment.get("SANDSTORM_MOCK_CPU_SECONDS"),
+                self.cpu_usage_seconds,
+            ),
+            memory_usage_bytes: parse(
+                environment.get("SANDSTORM_MOCK_MEMORY_BYTES"),
+                self.memory_usage_bytes,
+            ),
+        }
+    }
+
+    fn resource_usage(&self) -> ResourceUsage {
+        ResourceUsage {
+            cpu_usage_seconds: self.cpu_usage_seconds,
+            memory_usage_bytes: self.memory_usage_bytes,
+            network_rx_bytes: 0,
+            network_tx_bytes: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SandboxInfo {
+    config: SandboxConfig,
+    behavior: MockBehavior,
+    created_at: chrono::DateTime<chrono::Utc>,
+    started: Instant,
+    frozen: bool,
+    /// Booted without a command, waiting for a workload
+    parked: bool,
+}
+
+impl SandboxInfo {
+    fn finished(&self) -> bool {
+        !self.parked && self.started.elapsed() >= Duration::from_millis(self.behavior.delay_ms)
+    }
+
+    fn state(&self) -> SandboxState {
+        if self.finished() {
+            SandboxState::Stopped
+        } else if self.frozen {
+            SandboxState::Paused
+        } else {
+            SandboxState::Running
+        }
+    }
+}
+
+/// Runtime that keeps sandboxes in memory and simulates their execution
+pub struct MockRuntime {
+    behavior: MockBehavior,
+    sandboxes: RwLock<HashMap<Uuid, SandboxInfo>>,
+}
+
+impl MockRuntime {
+    pub fn new(behavior: MockBehavior) -> Self {
+        Self {
+            behavior,
+            sandboxes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Mock runtime if `SANDSTORM_MOCK_RUNTIME` is set to anything but
+    /// `0`, `false`, `no` or `off`
+    pub fn from_env() -> Option<Self> {
+        let enabled = std::env::var("SANDSTORM_MOCK_RUNTIME").ok()?;
+        if matches!(enabled.as_str(), "0" | "false" | "no" | "off") {
+            return None;
+        }
+        let environment: HashMap<String, String> = std::env::vars().collect();
+        Some(Self::new(MockBehavior::default().overridden_by(&environment)))
+    }
+
+    async fn start(&self, sandbox_id: Uuid, config: &SandboxConfig) {
+        let info = SandboxInfo {
+            config: config.clone(),
+            behavior: self.behavior.overridden_by(&config.environment),
+            created_at: chrono::Utc::now(),
+            started: Instant::now(),
+            frozen: false,
+            parked: config.command.is_empty(),
+        };
+        self.sandboxes.write().await.insert(sandbox_id, info);
+    }
+
+    async fn info(&self, sandbox_id: Uuid) -> Result<SandboxInfo> {
+        self.sandboxes
+            .read()
+            .await
+            .get(&sandbox_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))
+    }
+}
+
+#[async_trait]
+impl SandboxRuntime for MockRuntime {
+    fn runtime_type(&self) -> RuntimeType {
+        RuntimeType::Mock
+    }
+
+    fn supports_isolation_level(&self, _level: IsolationLevel) -> bool {
+        true
+    }
+
+    fn supports_execution_mode(&self, _mode: ExecutionMode) -> bool {
+        true
+    }
+
+    fn supports_sysctls(&self) -> bool {
+        true
+    }
+
+    fn supports_rootfs_layers(&self) -> bool {
+        true
+    }
+
+    async fn create(&self, config: &SandboxConfig) -> Result<Uuid> {
+        self.start(config.id, config).await;
+        info!("Created mock sandbox {}", config.id);
+        Ok(config.id)
+    }
+
+    fn supports_warm_start(&self) -> bool {
+        true
+    }
+
+    async fn start_workload(&self, sandbox_id: Uuid, config: &SandboxConfig) -> Result<()> {
+        let mut sandboxes = self.sandboxes.write().await;
+        let info = sandboxes
+            .get_mut(&sandbox_id)
+            .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
+        if !info.parked {
+            anyhow::bail!("Sandbox {} is already running its workload", sandbox_id);
+        }
+        info.config.command = config.command.clone();
+        info.config.environment = config.environment.clone();
+        info.behavior = self.behavior.overridden_by(&config.environment);
+        info.started = Instant::now();
+        info.parked = false;
+        Ok(())
+    }
+
+    async fn exec(
+        &self,
+        sandbox_id: Uuid,
+        _command: Vec<String>,
+        environment: Option<HashMap<String, String>>,
+        _options: &ExecOptions,
+    ) -> Result<SandboxResult> {
+        let info = self.info(sandbox_id).await?;
+        let behavior = match environment {
+            Some(environment) => info.behavior.overridden_by(&environment),
+            None => info.behavior,
+        };
+
+        tokio::time::sleep(Duration::from_millis(behavior.delay_ms)).await;
+        Ok(SandboxResult {
+            id: sandbox_id,
+            exit_code: behavior.exit_code,
+            stdout: behavior.stdout.clone().into_bytes(),
+            stderr: behavior.stderr.clone().into_bytes(),
+            duration_ms: behavior.delay_ms,
+            resource_usage: behavior.resource_usage(),
+            exit_reason: exit::of_code(behavior.exit_code),
+        })
+    }
+
+    async fn destroy(&self, sandbox_id: Uuid) -> Result<()> {
+        if self.sandboxes.write().await.remove(&sandbox_id).is_some() {
+            info!("Destroyed mock sandbox {}", sandbox_id);
+        }
+        Ok(())
+    }
+
+    async fn snapshot(&self, sandbox_id: Uuid) -> Result<SandboxSnapshot> {
+        let info = self.info(sandbox_id).await?;
+        Ok(SandboxSnapshot {
+            id: Uuid::new_v4(),
+            sandbox_id,
+            runtime_type: RuntimeType::Mock,
+            timestamp: chrono::Utc::now(),
+            filesystem_state: Vec::new(),
+            memory_state: None,
+            metadata: HashMap::from([(
+                "config".to_string(),
+                serde_json::to_value(&info.config)?,
+            )]),
+        })
+    }
+
+    async fn export_filesystem(&self, sandbox_id: Uuid) -> Result<Vec<u8>> {
+        let info = self.info(sandbox_id).await?;
+        // Mock sandboxes have no filesystem; their configuration stands in
+        Ok(serde_json::to_vec(&info.config)?)
+    }
+
+    async fn resume(&self, snapshot: &SandboxSnapshot) -> Result<Uuid> {
+        let config: SandboxConfig = snapshot
+            .metadata
+            .get("config")
+            .cloned()
+            .map(serde_json::from_value)
+            .transpose()?
+            .context("Missing sandbox config in snapshot metadata")?;
+
+        let sandbox_id = Uuid::new_v4();
+        self.start(sandbox_id, &config).await;
+        info!("Resumed mock sandbox {} from snapshot {}", sandbox_id, snapshot.id);
+        Ok(sandbox_id)
+    }
+
+    async fn status(&self, sandbox_id: Uuid) -> Result<SandboxStatus> {
+        let info = self.info(sandbox_id).await?;
+        let finished = info.finished();
+        Ok(SandboxStatus {
+            id: sandbox_id,
+            state: info.state(),
+            created_at: info.created_at,
+            started_at: Some(info.created_at),
+            finished_at: finished.then(|| {
+                info.created_at + chrono::Duration::milliseconds(info.behavior.delay_ms as i64)
+            }),
+            exit_code: finished.then_some(info.behavior.exit_code),
+            resource_usage: info.behavior.resource_usage(),
+            exit_reason: finished.then(|| exit::of_code(info.behavior.exit_code)),
+            transitions: Vec::new(),
+        })
+    }
+
+    /// Like the real runtimes' pause and resume, only a running sandbox
+    /// can be frozen and only a frozen one thawed
+    async fn set_frozen(&self, sandbox_id: Uuid, frozen: bool) -> Result<()> {
+        let mut sandboxes = self.sandboxes.write().await;
+        let info = sandboxes
+            .get_mut(&sandbox_id)
+            .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))?;
+        let from = info.state();
+        let to = if frozen { SandboxState::Paused } else { SandboxState::Running };
+        if from == to || !from.can_become(to) {
+            return Err(lifecycle::InvalidTransition { sandbox_id, from, to }.into());
+        }
+        info.frozen = frozen;
+        Ok(())
+    }
+
+    async fn logs(&self, sandbox_id: Uuid, _follow: bool) -> Result<Box<dyn tokio::io::AsyncRead + Send + Unpin>> {
+        let info = self.info(sandbox_id).await?;
+        Ok(Box::new(std::io::Cursor::new(info.behavior.stdout.into_bytes())))
+    }
+
+    async fn active_sandboxes(&self) -> usize {
+        self.sandboxes.read().await.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(environment: &[(&str, &str)]) -> SandboxConfig {
+        SandboxConfig {
+            id: Uuid::new_v4(),
+            image: "sandstorm/python".to_string(),
+            command: vec!["python".to_string(), "main.py".to_string()],
+            environment: environment
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            cpu_limit: None,
+            memory_limit: None,
+            timeout: None,
+            isolation_level: IsolationLevel::Maximum,
+            runtime_preference: None,
+            working_dir: None,
+            mounts: Vec::new(),
+            execution_mode: ExecutionMode::Standard,
+            scratch_size: None,
+            sysctls: HashMap::new(),
+            gvisor: Default::default(),
+            firecracker: Default::default(),
+            arch: None,
+            rootfs_layers: Vec::new(),
+            ttl_seconds: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn simulates_configured_execution() {
+        let runtime = MockRuntime::new(MockBehavior {
+            stdout: "hello\n".to_string(),
+            delay_ms: 0,
+            ..Default::default()
+        });
+        let id = runtime
+            .create(&config(&[("SANDSTORM_MOCK_EXIT_CODE", "3")]))
+            .await
+            .unwrap();
+
+        let result = runtime
+            .exec(
+                id,
+                vec!["true".to_string()],
+                Some(HashMap::from([(
+                    "SANDSTORM_MOCK_STDERR".to_string(),
+                    "warning\n".to_string(),
+                )])),
+                &ExecOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.exit_code, 3);
+        assert_eq!(result.stdout, b"hello\n");
+        assert_eq!(result.stderr, b"warning\n");
+
+        let status = runtime.status(id).await.unwrap();
+        assert_eq!(status.state, SandboxState::Stopped);
+        assert_eq!(status.exit_code, Some(3));
+
+        let snapshot = runtime.snapshot(id).await.unwrap();
+        let resumed = runtime.resume(&snapshot).await.unwrap();
+        assert_eq!(runtime.active_sandboxes().await, 2);
+        runtime.destroy(id).await.unwrap();
+        runtime.destroy(resumed).await.unwrap();
+        assert_eq!(runtime.active_sandboxes().await, 0);
+        assert!(runtime.status(id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn streams_buffered_output_once_finished() {
+        let runtime = MockRuntime::new(MockBehavior {
+            stdout: "hello\n".to_string(),
+            stderr: "warning\n".to_string(),
+            delay_ms: 0,
+            ..Default::default()
+        });
+        let id = runtime.create(&config(&[])).await.unwrap();
+
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(4);
+        let result = runtime
+            .exec_streaming(id, vec!["true".to_string()], None, &ExecOptions::default(), None, sender)
+            .await
+            .unwrap();
+        assert_eq!(result.stdout, b"hello\n");
+        assert_eq!(receiver.recv().await, Some(ExecOutput::Stdout(b"hello\n".to_vec())));
+        assert_eq!(receiver.recv().await, Some(ExecOutput::Stderr(b"warning\n".to_vec())));
+        assert_eq!(receiver.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn pauses_and_unpauses_once() {
+        let runtime = MockRuntime::new(MockBehavior {
+            delay_ms: 60_000,
+            ..Default::default()
+        });
+        let id = runtime.create(&config(&[])).await.unwrap();
+
+        runtime.set_frozen(id, true).await.unwrap();
+        assert_eq!(runtime.status(id).await.unwrap().state, SandboxState::Paused);
+
+        // A second pause is refused, and the sandbox stays paused
+        let error = runtime.set_frozen(id, true).await.unwrap_err();
+        let invalid = error.downcast_ref::<lifecycle::InvalidTransition>().unwrap();
+        assert_eq!((invalid.from, invalid.to), (SandboxState::Paused, SandboxState::Paused));
+        assert_eq!(runtime.status(id).await.unwrap().state, SandboxState::Paused);
+
+        runtime.set_frozen(id, false).await.unwrap();
+        assert_eq!(runtime.status(id).await.unwrap().state, SandboxState::Running);
+        assert!(runtime.set_frozen(id, false).await.unwrap_err().is::<lifecycle::InvalidTransition>());
+        assert!(runtime.set_frozen(Uuid::new_v4(), true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn finished_sandboxes_cant_be_paused() {
+        let runtime = MockRuntime::new(MockBehavior {
+            delay_ms: 0,
+            ..Default::default()
+        });
+        let id = runtime.create(&config(&[])).await.unwrap();
+        let error = runtime.set_frozen(id, true).await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<lifecycle::InvalidTransition>().map(|invalid| invalid.from),
+            Some(SandboxState::Stopped)
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_until_delay_elapses() {
+        let runtime = MockRuntime::new(MockBehavior {
+            delay_ms: 60_000,
+            ..Default::default()
+        });
+        let id = runtime.create(&config(&[])).await.unwrap();
+        let status = runtime.status(id).await.unwrap();
+        assert_eq!(status.state, SandboxState::Running);
+        assert_eq!(status.exit_code, None);
+    }
+
+    #[tokio::test]
+    async fn parked_sandboxes_wait_for_their_workload() {
+        let runtime = MockRuntime::new(MockBehavior {
+            delay_ms: 0,
+            ..Default::default()
+        });
+        let mut parked = config(&[]);
+        parked.command = Vec::new();
+        let id = runtime.create(&parked).await.unwrap();
+        assert_eq!(runtime.status(id).await.unwrap().state, SandboxState::Running);
+
+        runtime
+            .start_workload(id, &config(&[("SANDSTORM_MOCK_EXIT_CODE", "2")]))
+            .await
+            .unwrap();
+        let status = runtime.status(id).await.unwrap();
+        assert_eq!(status.state, SandboxState::Stopped);
+        assert_eq!(status.exit_code, Some(2));
+        assert!(runtime.start_workload(id, &config(&[])).await.is_err());
+    }
+}
@@ -1,63 +1,46 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, RwLock};
 use uuid::Uuid;
 use async_trait::async_trait;
 
+pub mod arch;
+pub mod capacity;
+pub mod command;
+pub mod cpu;
+pub mod exit;
+pub mod fault;
 pub mod firecracker;
+pub mod follow;
 pub mod gvisor;
+pub mod index;
 pub mod kata;
+pub mod lifecycle;
+pub mod limits;
+pub mod mock;
+pub mod overlay;
+pub mod posture;
+pub mod read_only;
+pub mod remote;
+pub mod stats;
+pub mod sysctl;
 pub mod test;
 
-/// Isolation level for sandbox execution
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum IsolationLevel {
-    /// Standard isolation using namespaces and cgroups
-    Standard,
-    /// Strong isolation using lightweight VMs or secure containers
-    Strong,
-    /// Maximum isolation using full VMs with hardware virtualization
-    Maximum,
-}
-
-/// Runtime type identifier
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum RuntimeType {
-    Firecracker,
-    Gvisor,
-    Kata,
-}
-
-/// Sandbox configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SandboxConfig {
-    pub id: Uuid,
-    pub image: String,
-    pub command: Vec<String>,
-    pub environment: HashMap<String, String>,
-    pub cpu_limit: Option<f64>,
-    pub memory_limit: Option<u64>, // bytes
-    pub timeout: Option<u64>,       // milliseconds
-    pub isolation_level: IsolationLevel,
-    pub runtime_preference: Option<RuntimeType>,
-    pub working_dir: Option<String>,
-    pub mounts: Vec<Mount>,
-}
-
-/// Mount configuration for sandbox
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Mount {
-    pub source: String,
-    pub destination: String,
-    pub read_only: bool,
-}
+pub use lifecycle::Transition;
+pub use limits::ExecLimits;
+pub use sandstorm_types::sandbox::{
+    Arch, CpuTemplate, ExecutionMode, FirecrackerOptions, GvisorNetwork, GvisorOptions,
+    GvisorPlatform, IsolationLevel, Mount, OptimizationHint, Priority, RuntimeType,
+    SandboxConfig, SandboxSnapshot,
+};
 
 /// Sandbox execution result
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct SandboxResult {
     pub id: Uuid,
     pub exit_code: i32,
@@ -65,10 +48,51 @@ pub struct SandboxResult {
     pub stderr: Vec<u8>,
     pub duration_ms: u64,
     pub resource_usage: ResourceUsage,
+    /// Why the command stopped, beyond what its exit code says
+    #[serde(default)]
+    pub exit_reason: ExitReason,
+}
+
+/// Output of a streamed exec, as the command produces it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecOutput {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+}
+
+/// Where a streamed exec sends its output. A full channel holds the
+/// command up until there's room; a closed one doesn't.
+pub type ExecOutputSender = tokio::sync::mpsc::Sender<ExecOutput>;
+
+/// Where a streamed exec takes its stdin from. Stdin is closed once every
+/// sender has been dropped.
+pub type ExecInputReceiver = tokio::sync::mpsc::Receiver<Vec<u8>>;
+
+/// Why a sandbox's workload stopped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitReason {
+    /// Exited on its own, whatever its exit code
+    #[default]
+    Completed,
+    /// Killed by the kernel for exceeding the sandbox's memory limit
+    OomKilled,
+    /// Stopped for running past its timeout
+    Timeout,
+    /// Killed by a signal, or its VM or runtime died under it
+    Crashed,
+    /// Stopped by the gateway to admit higher-priority work
+    Preempted,
+    /// Frozen by a security quarantine before it could finish
+    Quarantined,
+    /// Destroyed through the API before it finished
+    Destroyed,
+    /// Destroyed by the reaper for outliving its `ttl_seconds`
+    Expired,
 }
 
 /// Resource usage statistics
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct ResourceUsage {
     pub cpu_usage_seconds: f64,
     pub memory_usage_bytes: u64,
@@ -76,18 +100,100 @@ pub struct ResourceUsage {
     pub network_tx_bytes: u64,
 }
 
-/// Sandbox snapshot for stateful operations
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SandboxSnapshot {
-    pub id: Uuid,
-    pub sandbox_id: Uuid,
-    pub runtime_type: RuntimeType,
-    pub timestamp: chrono::DateTime<chrono::Utc>,
-    pub filesystem_state: Vec<u8>,
-    pub memory_state: Option<Vec<u8>>,
-    pub metadata: HashMap<String, serde_json::Value>,
+/// Per-exec overrides of how a command runs
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct ExecOptions {
+    /// Absolute directory to run in, instead of the sandbox's
+    pub working_dir: Option<String>,
+    /// User to run as, `uid` or `uid:gid`
+    pub user: Option<String>,
+    /// Allocate a pseudo-terminal; stdout and stderr then arrive combined
+    pub tty: bool,
+    /// Give up on the exec after this long
+    pub timeout_ms: Option<u64>,
+    /// CPU, memory and time limits tighter than the sandbox's
+    pub limits: ExecLimits,
+    /// Text written to the command's stdin, which is then closed. Without
+    /// it the command's stdin is empty.
+    pub stdin: Option<String>,
 }
 
+impl ExecOptions {
+    /// Check the options are well-formed, whichever runtime runs them
+    pub fn validate(&self) -> Result<()> {
+        if let Some(dir) = &self.working_dir {
+            if !dir.starts_with('/') {
+                anyhow::bail!("working_dir must be an absolute path, got {:?}", dir);
+            }
+        }
+        if let Some(user) = &self.user {
+            let numeric = |id: &str| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit());
+            let valid = match user.split_once(':') {
+                Some((uid, gid)) => numeric(uid) && numeric(gid),
+                None => numeric(user),
+            };
+            if !valid {
+                anyhow::bail!("user must be a uid or uid:gid, got {:?}", user);
+            }
+        }
+        if self.timeout_ms == Some(0) {
+            anyhow::bail!("timeout_ms must be greater than 0");
+        }
+        self.limits.validate()
+    }
+
+    /// [`stdin`](Self::stdin) as exec input, if there is any
+    pub fn stdin_input(&self) -> Option<ExecInputReceiver> {
+        let stdin = self.stdin.as_ref()?;
+        let (sender, receiver) = tokio::sync::mpsc::channel(1);
+        sender.try_send(stdin.clone().into_bytes()).ok();
+        Some(receiver)
+    }
+
+    /// Fail with [`UnsupportedExecOptions`] if these options need anything
+    /// `support` lacks
+    pub fn check(&self, runtime: RuntimeType, support: ExecSupport) -> Result<()> {
+        let unsupported = [
+            ("working_dir", self.working_dir.is_some() && !support.working_dir),
+            ("user", self.user.is_some() && !support.user),
+            ("tty", self.tty && !support.tty),
+            ("limits", self.limits.is_set() && !support.limits),
+            ("stdin", self.stdin.is_some() && !support.stdin),
+        ];
+        match unsupported.iter().find(|(_, unsupported)| *unsupported) {
+            Some((option, _)) => Err(UnsupportedExecOptions(format!(
+                "{} is not supported by the {:?} runtime",
+                option, runtime
+            ))
+            .into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Which [`ExecOptions`] a runtime can honour; timeouts work everywhere
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecSupport {
+    pub working_dir: bool,
+    pub user: bool,
+    pub tty: bool,
+    pub limits: bool,
+    pub stdin: bool,
+}
+
+/// An exec asked for options its sandbox's runtime can't honour
+#[derive(Debug)]
+pub struct UnsupportedExecOptions(pub String);
+
+impl std::fmt::Display for UnsupportedExecOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedExecOptions {}
+
 /// The main trait that all sandbox runtimes must implement
 #[async_trait]
 pub trait SandboxRuntime: Send + Sync {
@@ -97,17 +203,95 @@ pub trait SandboxRuntime: Send + Sync {
     /// Check if the runtime supports the given isolation level
     fn supports_isolation_level(&self, level: IsolationLevel) -> bool;
 
-    /// Create and start a new sandbox
+    /// Check if the runtime can enforce the given execution mode
+    fn supports_execution_mode(&self, mode: ExecutionMode) -> bool {
+        mode == ExecutionMode::Standard
+    }
+
+    /// Check if the runtime can apply per-sandbox sysctls
+    fn supports_sysctls(&self) -> bool {
+        false
+    }
+
+    /// Check if the runtime can stack a root filesystem from host layer
+    /// directories
+    fn supports_rootfs_layers(&self) -> bool {
+        false
+    }
+
+    /// Check if the runtime runs sandboxes on this CPU architecture. Local
+    /// runtimes run them on the host's.
+    fn supports_arch(&self, arch: Arch) -> bool {
+        Arch::host() == Some(arch)
+    }
+
+    /// Reject a configuration this runtime instance can't honour, before
+    /// any resources are spent on it
+    fn validate(&self, _config: &SandboxConfig) -> Result<()> {
+        Ok(())
+    }
+
+    /// Create and start a new sandbox. A configuration without a command
+    /// boots a sandbox that waits for
+    /// [`start_workload`](Self::start_workload), on runtimes that
+    /// [support warm starts](Self::supports_warm_start).
     async fn create(&self, config: &SandboxConfig) -> Result<Uuid>;
 
-    /// Execute a command in an existing sandbox
+    /// Check if the runtime can boot sandboxes ahead of time and hand them
+    /// a run's command later, for warm pools
+    fn supports_warm_start(&self) -> bool {
+        false
+    }
+
+    /// Start a run in a sandbox booted without a command. Only the command
+    /// and environment of `config` apply; the rest is what the sandbox was
+    /// booted with.
+    async fn start_workload(&self, _sandbox_id: Uuid, _config: &SandboxConfig) -> Result<()> {
+        anyhow::bail!("{:?} sandboxes can't be booted ahead of their runs", self.runtime_type())
+    }
+
+    /// Execute a command in an existing sandbox. Runtimes that have the
+    /// sandbox fail with [`UnsupportedExecOptions`] for options they can't
+    /// honour.
     async fn exec(
         &self,
         sandbox_id: Uuid,
         command: Vec<String>,
         environment: Option<HashMap<String, String>>,
+        options: &ExecOptions,
     ) -> Result<SandboxResult>;
 
+    /// Execute a command like [`exec`](Self::exec), sending its output to
+    /// `output` as it's produced. The result still carries all of it.
+    /// Runtimes that can't stream send the output once the command is done,
+    /// and fail with [`UnsupportedExecOptions`] if given `input`; with it,
+    /// `input` is the command's stdin instead of the options' `stdin`.
+    async fn exec_streaming(
+        &self,
+        sandbox_id: Uuid,
+        command: Vec<String>,
+        environment: Option<HashMap<String, String>>,
+        options: &ExecOptions,
+        input: Option<ExecInputReceiver>,
+        output: ExecOutputSender,
+    ) -> Result<SandboxResult> {
+        if input.is_some() {
+            return Err(UnsupportedExecOptions(format!(
+                "streamed stdin is not supported by the {:?} runtime",
+                self.runtime_type()
+            ))
+            .into());
+        }
+        let result = self.exec(sandbox_id, command, environment, options).await?;
+        if !result.stdout.is_empty() {
+            output.send(ExecOutput::Stdout(result.stdout.clone())).await.ok();
+        }
+        if !result.stderr.is_empty() {
+            output.send(ExecOutput::Stderr(result.stderr.clone())).await.ok();
+        }
+        Ok(result)
+    }
+
     /// Stop and remove a sandbox
     async fn destroy(&self, sandbox_id: Uuid) -> Result<()>;
 
@@ -117,15 +301,41 @@ pub trait SandboxRuntime: Send + Sync {
     /// Resume a sandbox from a snapshot
     async fn resume(&self, snapshot: &SandboxSnapshot) -> Result<Uuid>;
 
+    /// The sandbox's root filesystem as a tar archive, without its memory.
+    /// Works once the workload has exited, until the sandbox is destroyed.
+    async fn export_filesystem(&self, _sandbox_id: Uuid) -> Result<Vec<u8>> {
+        anyhow::bail!("{:?} sandboxes can't export their filesystem", self.runtime_type())
+    }
+
     /// Get sandbox status
     async fn status(&self, sandbox_id: Uuid) -> Result<SandboxStatus>;
 
-    /// Stream logs from a sandbox
+    /// Pause every process in a sandbox, or let a paused sandbox run again
+    async fn set_frozen(&self, _sandbox_id: Uuid, _frozen: bool) -> Result<()> {
+        anyhow::bail!("{:?} sandboxes can't be frozen", self.runtime_type())
+    }
+
+    /// Host network interface carrying the sandbox's traffic, when it has
+    /// one the gateway can filter
+    async fn network_interface(&self, _sandbox_id: Uuid) -> Option<String> {
+        None
+    }
+
+    /// Stream a sandbox's console output; with `follow`, keep streaming
+    /// what it writes until it's destroyed
     async fn logs(&self, sandbox_id: Uuid, follow: bool) -> Result<Box<dyn tokio::io::AsyncRead + Send + Unpin>>;
+
+    /// Number of sandboxes currently managed by this runtime
+    async fn active_sandboxes(&self) -> usize;
+
+    /// Whether sandboxes run on a hosted provider rather than this host
+    fn is_remote(&self) -> bool {
+        false
+    }
 }
 
 /// Sandbox status information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct SandboxStatus {
     pub id: Uuid,
     pub state: SandboxState,
@@ -134,28 +344,68 @@ pub struct SandboxStatus {
     pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
     pub exit_code: Option<i32>,
     pub resource_usage: ResourceUsage,
+    /// Why the sandbox stopped; `None` while it can still run
+    #[serde(default)]
+    pub exit_reason: Option<ExitReason>,
+    /// States the sandbox went through, oldest first, for runtimes that
+    /// track them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transitions: Vec<Transition>,
 }
 
-/// Sandbox state
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Sandbox state. See [`SandboxState::can_become`] for the transitions
+/// between them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum SandboxState {
     Creating,
+    /// Created, and its workload being started
+    Starting,
     Running,
     Paused,
+    /// Being torn down
+    Stopping,
     Stopped,
     Failed,
+    /// Snapshotted and stopped by the gateway to admit higher-priority work
+    Preempted,
 }
 
+/// Remote runtimes tried when bursting, unless configured otherwise
+pub const DEFAULT_BURST_ORDER: [RuntimeType; 3] =
+    [RuntimeType::E2b, RuntimeType::Daytona, RuntimeType::Modal];
+
 /// Runtime registry for managing available runtimes
 pub struct RuntimeRegistry {
     runtimes: RwLock<HashMap<RuntimeType, Arc<dyn SandboxRuntime>>>,
+    /// Sandboxes a local runtime may host before requests burst to remote providers
+    local_limit: Option<usize>,
+    /// Remote runtimes to try, in order, when no local runtime can take a sandbox
+    burst_order: Vec<RuntimeType>,
+    /// Telemetry used to rank runtimes for requests with an optimization hint
+    stats: Option<Arc<stats::RuntimeStats>>,
+    /// Host CPU and memory accounting for local runtimes
+    host: Option<capacity::HostCapacity>,
+    /// How long requests wait for capacity before they are rejected
+    queue_timeout: Duration,
+    /// Failures and latency injected into matching runtimes, for testing
+    faults: Option<fault::FaultConfig>,
+    /// Every sandbox on the registered runtimes
+    index: Arc<index::SandboxIndex>,
+    queued: AtomicUsize,
+    released: Notify,
 }
 
 impl std::fmt::Debug for RuntimeRegistry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("RuntimeRegistry")
             .field("runtimes", &"<runtime collection>")
+            .field("local_limit", &self.local_limit)
+            .field("burst_order", &self.burst_order)
+            .field("stats", &self.stats.is_some())
+            .field("host", &self.host)
+            .field("queue_timeout", &self.queue_timeout)
+            .field("faults", &self.faults)
             .finish()
     }
 }
@@ -165,6 +415,162 @@ impl RuntimeRegistry {
     pub fn new() -> Self {
         Self {
             runtimes: RwLock::new(HashMap::new()),
+            local_limit: None,
+            burst_order: DEFAULT_BURST_ORDER.to_vec(),
+            stats: None,
+            host: None,
+            queue_timeout: Duration::ZERO,
+            faults: None,
+            index: Arc::new(index::SandboxIndex::new()),
+            queued: AtomicUsize::new(0),
+            released: Notify::new(),
+        }
+    }
+
+    /// Cap the sandboxes each local runtime hosts; beyond it requests burst
+    pub fn with_local_limit(mut self, limit: Option<usize>) -> Self {
+        self.local_limit = limit;
+        self
+    }
+
+    /// Order in which remote runtimes are tried when bursting
+    pub fn with_burst_order(mut self, order: Vec<RuntimeType>) -> Self {
+        self.burst_order = order;
+        self
+    }
+
+    /// Rank runtimes by telemetry when requests carry an optimization hint
+    pub fn with_stats(mut self, stats: Arc<stats::RuntimeStats>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Account local sandboxes against the host's CPU and memory
+    pub fn with_host_capacity(mut self, host: Option<capacity::HostCapacity>) -> Self {
+        self.host = host;
+        self
+    }
+
+    /// Let requests wait this long for capacity instead of failing at once
+    pub fn with_queue_timeout(mut self, timeout: Duration) -> Self {
+        self.queue_timeout = timeout;
+        self
+    }
+
+    /// Wrap runtimes registered from now on in a [`fault::FaultInjectingRuntime`]
+    pub fn with_fault_injection(mut self, faults: Option<fault::FaultConfig>) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// What a sandbox with these limits is charged against the host
+    pub fn demand(&self, cpu_limit: Option<f64>, memory_limit: Option<u64>) -> capacity::Demand {
+        match &self.host {
+            Some(host) => host.demand(cpu_limit, memory_limit),
+            None => capacity::Demand {
+                cpus: cpu_limit.unwrap_or(0.0),
+                memory_bytes: memory_limit.unwrap_or(0),
+            },
+        }
+    }
+
+    /// Whether a runtime can take another sandbox
+    async fn has_capacity(&self, runtime: &Arc<dyn SandboxRuntime>, demand: capacity::Demand) -> bool {
+        if runtime.is_remote() {
+            return true;
+        }
+        if let Some(limit) = self.local_limit {
+            if runtime.active_sandboxes().await >= limit {
+                return false;
+            }
+        }
+        match &self.host {
+            Some(host) => host.fits(demand).await,
+            None => true,
+        }
+    }
+
+    /// Whether a registered runtime can take another sandbox right now
+    pub async fn has_room(&self, runtime_type: RuntimeType, demand: capacity::Demand) -> bool {
+        match self.get(runtime_type).await {
+            Ok(runtime) => self.has_capacity(&runtime, demand).await,
+            Err(_) => false,
+        }
+    }
+
+    /// Reserve host resources for a sandbox about to start on `runtime`;
+    /// false if another request took them first
+    pub async fn reserve(
+        &self,
+        key: Uuid,
+        runtime: &Arc<dyn SandboxRuntime>,
+        demand: capacity::Demand,
+    ) -> bool {
+        match &self.host {
+            Some(host) if !runtime.is_remote() => host.try_reserve(key, demand).await,
+            _ => true,
+        }
+    }
+
+    /// Move a reservation to the ID the runtime gave the sandbox
+    pub async fn rekey(&self, from: Uuid, to: Uuid) {
+        if let Some(host) = &self.host {
+            host.rekey(from, to).await;
+        }
+    }
+
+    /// Return a stopped sandbox's resources and wake queued requests
+    pub async fn release(&self, key: Uuid) {
+        if let Some(host) = &self.host {
+            host.release(key).await;
+        }
+        self.released.notify_waiters();
+    }
+
+    /// Wait for a sandbox to be released, or a short while, before a queued
+    /// request tries again. False once the request has waited out
+    /// `deadline`.
+    pub async fn wait_for_room(&self, deadline: Instant) -> bool {
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+
+        // Releases aren't the only way room appears (remote runtimes and
+        // sandboxes exiting on their own), so poll as well
+        let wait = (deadline - now).min(Duration::from_secs(1));
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        let _ = tokio::time::timeout(wait, self.released.notified()).await;
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        true
+    }
+
+    pub fn queue_timeout(&self) -> Duration {
+        self.queue_timeout
+    }
+
+    /// Headroom on the host and per runtime
+    pub async fn capacity_report(&self) -> capacity::CapacityReport {
+        let mut runtimes = Vec::new();
+        for (runtime_type, runtime) in self.runtimes.read().await.clone() {
+            let remote = runtime.is_remote();
+            runtimes.push(capacity::RuntimeLoad {
+                runtime_type,
+                remote,
+                active: runtime.active_sandboxes().await,
+                limit: if remote { None } else { self.local_limit },
+            });
+        }
+
+        let host = match &self.host {
+            Some(host) => Some(host.report().await),
+            None => None,
+        };
+        capacity::CapacityReport {
+            host,
+            runtimes,
+            queued: self.queued.load(Ordering::SeqCst),
+            queue_timeout_secs: self.queue_timeout.as_secs(),
         }
     }
 
@@ -177,10 +583,29 @@ impl RuntimeRegistry {
             anyhow::bail!("Runtime {:?} is already registered", runtime_type);
         }
         
+        let runtime = match &self.faults {
+            Some(faults) if faults.applies_to(runtime_type) => {
+                Arc::new(fault::FaultInjectingRuntime::new(runtime, faults.clone()))
+            }
+            _ => runtime,
+        };
+        let runtime = Arc::new(index::IndexedRuntime::new(runtime, self.index.clone()));
         runtimes.insert(runtime_type, runtime);
         Ok(())
     }
 
+    /// Sandboxes on every registered runtime
+    pub fn sandboxes(&self) -> &index::SandboxIndex {
+        &self.index
+    }
+
+    /// The runtime hosting a sandbox, from the index rather than by asking
+    /// each runtime; `None` if no registered runtime has it
+    pub async fn runtime_of(&self, sandbox_id: Uuid) -> Option<Arc<dyn SandboxRuntime>> {
+        let sandbox = self.index.get(sandbox_id).await?;
+        self.get(sandbox.runtime_type).await.ok()
+    }
+
     /// Get a runtime by type
     pub async fn get(&self, runtime_type: RuntimeType) -> Result<Arc<dyn SandboxRuntime>> {
         let runtimes = self.runtimes.read().await;
@@ -190,34 +615,125 @@ impl RuntimeRegistry {
             .ok_or_else(|| anyhow::anyhow!("Runtime {:?} not found", runtime_type))
     }
 
-    /// Select the best runtime for the given isolation level
+    /// Whether a runtime can host a sandbox with this configuration at all
+    fn can_run(runtime: &Arc<dyn SandboxRuntime>, config: &SandboxConfig) -> bool {
+        runtime.supports_isolation_level(config.isolation_level)
+            && runtime.supports_execution_mode(config.execution_mode)
+            && (config.sysctls.is_empty() || runtime.supports_sysctls())
+            && (config.rootfs_layers.is_empty() || runtime.supports_rootfs_layers())
+            && config.arch.is_none_or(|arch| runtime.supports_arch(arch))
+            && (config.gvisor.is_empty()
+                || matches!(runtime.runtime_type(), RuntimeType::Gvisor | RuntimeType::Mock))
+            && (config.firecracker.is_empty()
+                || matches!(runtime.runtime_type(), RuntimeType::Firecracker | RuntimeType::Mock))
+    }
+
+    /// Select the best runtime for a sandbox configuration
     pub async fn select_runtime(
         &self,
-        isolation_level: IsolationLevel,
-        preference: Option<RuntimeType>,
+        config: &SandboxConfig,
+        hint: Option<OptimizationHint>,
+        demand: capacity::Demand,
     ) -> Result<Arc<dyn SandboxRuntime>> {
-        let runtimes = self.runtimes.read().await;
+        let runtimes = self.runtimes.read().await.clone();
+        let isolation_level = config.isolation_level;
 
-        // If a preference is specified and the runtime supports the isolation level, use it
-        if let Some(preferred) = preference {
+        // If a preference is specified and the runtime can run the sandbox, use it
+        if let Some(preferred) = config.runtime_preference {
             if let Some(runtime) = runtimes.get(&preferred) {
-                if runtime.supports_isolation_level(isolation_level) {
+                if Self::can_run(runtime, config) && self.has_capacity(runtime, demand).await
+                {
                     return Ok(runtime.clone());
                 }
             }
         }
 
-        // Otherwise, select based on isolation level
+        // With a hint, pick the best-scoring runtime telemetry knows about
+        if let (Some(hint), Some(stats)) = (hint, &self.stats) {
+            if let Some(runtime) = self
+                .select_by_stats(&runtimes, config, hint, demand, stats)
+                .await
+            {
+                return Ok(runtime);
+            }
+            tracing::debug!(
+                "No telemetry to rank runtimes by, falling back to the isolation level mapping"
+            );
+        }
+
+        // Otherwise, select based on isolation level, unless the request
+        // carries gVisor or Firecracker options
         let runtime_type = match isolation_level {
+            _ if !config.gvisor.is_empty() => RuntimeType::Gvisor,
+            _ if !config.firecracker.is_empty() => RuntimeType::Firecracker,
             IsolationLevel::Standard => RuntimeType::Gvisor,
             IsolationLevel::Strong => RuntimeType::Kata,
             IsolationLevel::Maximum => RuntimeType::Firecracker,
         };
 
-        runtimes
+        // The mock runtime stands in for local runtimes that aren't installed
+        let local = runtimes
             .get(&runtime_type)
-            .cloned()
-            .ok_or_else(|| anyhow::anyhow!("No suitable runtime found for isolation level {:?}", isolation_level))
+            .or_else(|| runtimes.get(&RuntimeType::Mock));
+        if let Some(runtime) = local {
+            if Self::can_run(runtime, config) && self.has_capacity(runtime, demand).await {
+                return Ok(runtime.clone());
+            }
+        }
+
+        // Burst to a hosted provider when the local runtime is missing or full
+        for remote_type in &self.burst_order {
+            if let Some(runtime) = runtimes.get(remote_type) {
+                if Self::can_run(runtime, config) {
+                    tracing::info!(
+                        "Bursting {:?} isolation request to {:?}",
+                        isolation_level,
+                        remote_type
+                    );
+                    return Ok(runtime.clone());
+                }
+            }
+        }
+
+        anyhow::bail!(
+            "No suitable runtime found for isolation level {:?} in {:?} mode",
+            isolation_level,
+            config.execution_mode
+        )
+    }
+
+    async fn select_by_stats(
+        &self,
+        runtimes: &HashMap<RuntimeType, Arc<dyn SandboxRuntime>>,
+        config: &SandboxConfig,
+        hint: OptimizationHint,
+        demand: capacity::Demand,
+        stats: &stats::RuntimeStats,
+    ) -> Option<Arc<dyn SandboxRuntime>> {
+        let mut best: Option<(f64, &Arc<dyn SandboxRuntime>)> = None;
+
+        for (runtime_type, runtime) in runtimes {
+            if !Self::can_run(runtime, config) || !self.has_capacity(runtime, demand).await {
+                continue;
+            }
+            let Some(score) = stats.get(*runtime_type).await.and_then(|s| stats::score(&s, hint)) else {
+                continue;
+            };
+            if best.is_none_or(|(best_score, _)| score < best_score) {
+                best = Some((score, runtime));
+            }
+        }
+
+        best.map(|(score, runtime)| {
+            tracing::info!(
+                "Selected {:?} for {:?} isolation ({:?}, score {:.4})",
+                runtime.runtime_type(),
+                config.isolation_level,
+                hint,
+                score
+            );
+            runtime.clone()
+        })
     }
 
     /// List all registered runtimes
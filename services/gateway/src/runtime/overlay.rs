@@ -0,0 +1,64 @@
+//! Root filesystems stacked with overlayfs. A sandbox configured with
+//! `rootfs_layers` mounts them read-only under a writable upper directory
+//! in its bundle, so the layers themselves are shared by every sandbox
+//! built on them and never change.
+
+use anyhow::Result;
+use std::path::Path;
+use tokio::process::Command;
+
+use super::command;
+
+/// overlayfs mount options for `layers` (lowest first) under `upper`.
+/// overlayfs lists lower directories topmost first.
+pub fn options(layers: &[String], upper: &Path, work: &Path) -> String {
+    let lower: Vec<&str> = layers.iter().rev().map(String::as_str).collect();
+    format!(
+        "lowerdir={},upperdir={},workdir={}",
+        lower.join(":"),
+        upper.display(),
+        work.display()
+    )
+}
+
+/// Mount `layers` at `rootfs`, with the sandbox's writes going to
+/// `upper` and `work` under `bundle`
+pub async fn mount(bundle: &Path, rootfs: &Path, layers: &[String]) -> Result<()> {
+    for layer in layers {
+        if !Path::new(layer).is_dir() {
+            anyhow::bail!("root filesystem layer {} is not a directory", layer);
+        }
+    }
+    let upper = bundle.join("upper");
+    let work = bundle.join("work");
+    std::fs::create_dir_all(&upper)?;
+    std::fs::create_dir_all(&work)?;
+
+    let mut cmd = Command::new("mount");
+    cmd.args(["-t", "overlay", "overlay", "-o", &options(layers, &upper, &work)])
+        .arg(rootfs);
+    command::run(&mut cmd, "mount root filesystem layers").await?;
+    Ok(())
+}
+
+/// Unmount a layered root filesystem, so its bundle can be removed
+pub async fn unmount(rootfs: &Path) -> Result<()> {
+    let mut cmd = Command::new("umount");
+    cmd.arg(rootfs);
+    command::run(&mut cmd, "unmount root filesystem layers").await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_lower_layers_topmost_first() {
+        let layers = vec!["/layers/os".to_string(), "/layers/python".to_string()];
+        assert_eq!(
+            options(&layers, Path::new("/bundle/upper"), Path::new("/bundle/work")),
+            "lowerdir=/layers/python:/layers/os,upperdir=/bundle/upper,workdir=/bundle/work"
+        );
+    }
+}
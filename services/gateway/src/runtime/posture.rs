@@ -0,0 +1,322 @@
+//! Host prerequisites of the local runtimes, checked at startup so a
+//! runtime that can't work on this host is never registered, rather than
+//! failing every sandbox it is picked for
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::process::Command;
+
+use super::{gvisor, RuntimeType};
+
+/// Oldest runsc release, as its `release-YYYYMMDD` date, the gateway drives
+const MIN_RUNSC_RELEASE: u32 = 20230101;
+const MIN_KATA_VERSION: [u64; 3] = [3, 0, 0];
+const MIN_FIRECRACKER_VERSION: [u64; 3] = [1, 4, 0];
+
+const NOT_FOUND: &str = "binary not found";
+
+/// One prerequisite and whether the host meets it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Check {
+    pub name: String,
+    /// Runtimes whose required checks fail are not registered; other
+    /// failures only lose features
+    pub required: bool,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &str, passed: bool, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            required: true,
+            passed,
+            detail: detail.into(),
+        }
+    }
+
+    fn advisory(mut self) -> Self {
+        self.required = false;
+        self
+    }
+}
+
+/// Whether a local runtime's prerequisites are met, as listed by
+/// `GET /v1/runtimes`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Readiness {
+    pub runtime: RuntimeType,
+    pub ready: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Readiness {
+    fn new(runtime: RuntimeType, checks: Vec<Check>) -> Self {
+        Self {
+            runtime,
+            ready: checks.iter().all(|check| check.passed || !check.required),
+            checks,
+        }
+    }
+
+    /// Whether the runtime's binaries were found at all
+    pub fn installed(&self) -> bool {
+        !self.checks.iter().any(|check| check.detail == NOT_FOUND)
+    }
+
+    /// Failed checks, for logging
+    pub fn failures(&self) -> String {
+        self.checks
+            .iter()
+            .filter(|check| !check.passed)
+            .map(|check| format!("{}: {}", check.name, check.detail))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// Host facts shared by the runtimes' checks
+#[derive(Debug, Clone)]
+pub struct HostPosture {
+    /// `/dev/kvm` can be opened for reading and writing
+    pub kvm: Check,
+    pub user_namespaces: Check,
+    /// Sandbox OOM kills are read from cgroup v2 `memory.events`
+    pub cgroup_v2: Check,
+    /// Kata talks to its guest agent over vsock
+    pub vhost_vsock: Check,
+    /// Firecracker VMs are networked through TAP devices
+    pub tun: Check,
+}
+
+impl HostPosture {
+    /// Inspect this host
+    pub fn probe() -> Self {
+        let kvm = match OpenOptions::new().read(true).write(true).open("/dev/kvm") {
+            Ok(_) => Check::new("kvm", true, "/dev/kvm is usable"),
+            Err(e) => Check::new("kvm", false, format!("/dev/kvm: {}", e)),
+        };
+
+        Self {
+            kvm,
+            user_namespaces: user_namespaces(),
+            cgroup_v2: cgroup_v2(),
+            vhost_vsock: module("vhost_vsock", Some("/dev/vhost-vsock")),
+            tun: module("tun", Some("/dev/net/tun")),
+        }
+    }
+
+    pub fn gvisor(&self, runsc_bin: &Path) -> Readiness {
+        let release = version_output(runsc_bin).and_then(|output| {
+            let release = gvisor::parse_release(&output)
+                .ok_or_else(|| format!("unrecognized version {:?}", output.trim()))?;
+            if release < MIN_RUNSC_RELEASE {
+                return Err(format!("release {} is older than {}", release, MIN_RUNSC_RELEASE));
+            }
+            Ok(format!("release {}", release))
+        });
+
+        Readiness::new(
+            RuntimeType::Gvisor,
+            vec![
+                binary("runsc", release),
+                // Only needed when the gateway doesn't run as root
+                self.user_namespaces.clone().advisory(),
+                self.cgroup_v2.clone().advisory(),
+            ],
+        )
+    }
+
+    pub fn kata(&self, kata_bin: &Path) -> Readiness {
+        let version = version_output(kata_bin).and_then(|output| at_least(&output, MIN_KATA_VERSION));
+
+        Readiness::new(
+            RuntimeType::Kata,
+            vec![
+                binary("kata-runtime", version),
+                self.kvm.clone(),
+                self.vhost_vsock.clone(),
+                self.cgroup_v2.clone().advisory(),
+            ],
+        )
+    }
+
+    pub fn firecracker(&self, firecracker_bin: &Path, jailer_bin: &Path) -> Readiness {
+        let firecracker = version_output(firecracker_bin);
+        let version = firecracker
+            .clone()
+            .and_then(|output| at_least(&output, MIN_FIRECRACKER_VERSION));
+        // The jailer only works with the firecracker of its own release
+        let jailer = version_output(jailer_bin).and_then(|output| {
+            let jailer = parse_version(&output);
+            let expected = firecracker.as_deref().ok().and_then(parse_version);
+            match (jailer, expected) {
+                (Some(jailer), Some(expected)) if jailer != expected => Err(format!(
+                    "version {} doesn't match firecracker {}",
+                    display_version(&jailer),
+                    display_version(&expected)
+                )),
+                (Some(jailer), _) => Ok(format!("version {}", display_version(&jailer))),
+                (None, _) => Err(format!("unrecognized version {:?}", output.trim())),
+            }
+        });
+
+        Readiness::new(
+            RuntimeType::Firecracker,
+            vec![
+                binary("firecracker", version),
+                binary("jailer", jailer),
+                self.kvm.clone(),
+                self.tun.clone(),
+                self.cgroup_v2.clone().advisory(),
+            ],
+        )
+    }
+}
+
+/// Readiness of a runtime whose binary isn't installed
+pub fn missing(runtime: RuntimeType, binary: &str) -> Readiness {
+    Readiness::new(runtime, vec![Check::new(binary, false, NOT_FOUND)])
+}
+
+/// Unprivileged user namespaces are enabled, and not switched off by the
+/// Debian or Ubuntu knobs for them
+fn user_namespaces() -> Check {
+    let read = |path: &str| {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|value| value.trim().parse::<u64>().ok())
+    };
+
+    let detail = if read("/proc/sys/user/max_user_namespaces") == Some(0) {
+        Some("user.max_user_namespaces is 0")
+    } else if read("/proc/sys/kernel/unprivileged_userns_clone") == Some(0) {
+        Some("kernel.unprivileged_userns_clone is 0")
+    } else if read("/proc/sys/kernel/apparmor_restrict_unprivileged_userns") == Some(1) {
+        Some("kernel.apparmor_restrict_unprivileged_userns is 1")
+    } else {
+        None
+    };
+    match detail {
+        Some(detail) => Check::new("user_namespaces", false, detail),
+        None => Check::new("user_namespaces", true, "unprivileged user namespaces are enabled"),
+    }
+}
+
+fn cgroup_v2() -> Check {
+    if Path::new("/sys/fs/cgroup/cgroup.controllers").exists() {
+        Check::new("cgroup_v2", true, "/sys/fs/cgroup is cgroup v2")
+    } else {
+        Check::new("cgroup_v2", false, "/sys/fs/cgroup is not cgroup v2; OOM kills won't be reported")
+    }
+}
+
+/// A kernel module is loaded or built in, or the device it provides exists
+fn module(name: &str, device: Option<&str>) -> Check {
+    let builtin = || {
+        let release = std::fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default();
+        std::fs::read_to_string(format!("/lib/modules/{}/modules.builtin", release.trim()))
+            .unwrap_or_default()
+            .lines()
+            .any(|line| line.rsplit('/').next() == Some(&format!("{}.ko", name)))
+    };
+
+    if Path::new("/sys/module").join(name).exists()
+        || device.is_some_and(|device| Path::new(device).exists())
+        || builtin()
+    {
+        Check::new(name, true, "module is available")
+    } else {
+        Check::new(name, false, format!("module is not loaded; try `modprobe {}`", name))
+    }
+}
+
+/// A binary's `--version` output, or why it couldn't be had
+fn version_output(binary: &Path) -> Result<String, String> {
+    let output = Command::new(binary)
+        .arg("--version")
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", binary.display(), e))?;
+    if !output.status.success() {
+        return Err(format!("{} --version exited with {}", binary.display(), output.status));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn binary(name: &str, version: Result<String, String>) -> Check {
+    match version {
+        Ok(detail) => Check::new(name, true, detail),
+        Err(detail) => Check::new(name, false, detail),
+    }
+}
+
+fn at_least(output: &str, minimum: [u64; 3]) -> Result<String, String> {
+    let version = parse_version(output).ok_or_else(|| format!("unrecognized version {:?}", output.trim()))?;
+    if version < minimum {
+        return Err(format!(
+            "version {} is older than {}",
+            display_version(&version),
+            display_version(&minimum)
+        ));
+    }
+    Ok(format!("version {}", display_version(&version)))
+}
+
+/// First `major.minor.patch` in version output such as
+/// `Firecracker v1.7.0` or `kata-runtime  : 3.2.0`
+fn parse_version(output: &str) -> Option<[u64; 3]> {
+    output.split_whitespace().find_map(|word| {
+        let mut parts = word.trim_start_matches('v').split('.');
+        let mut version = [0; 3];
+        for part in &mut version {
+            let digits: String = parts.next()?.chars().take_while(char::is_ascii_digit).collect();
+            *part = digits.parse().ok()?;
+        }
+        Some(version)
+    })
+}
+
+fn display_version(version: &[u64; 3]) -> String {
+    format!("{}.{}.{}", version[0], version[1], version[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(kvm: bool) -> HostPosture {
+        HostPosture {
+            kvm: Check::new("kvm", kvm, ""),
+            user_namespaces: Check::new("user_namespaces", true, ""),
+            cgroup_v2: Check::new("cgroup_v2", false, ""),
+            vhost_vsock: Check::new("vhost_vsock", true, ""),
+            tun: Check::new("tun", true, ""),
+        }
+    }
+
+    #[test]
+    fn parses_runtime_versions() {
+        assert_eq!(parse_version("Firecracker v1.7.0\n\nSupported snapshot data format versions: v1.0.0"), Some([1, 7, 0]));
+        assert_eq!(parse_version("kata-runtime  : 3.2.0\n   commit   : abc"), Some([3, 2, 0]));
+        assert_eq!(parse_version("runsc version release-20240212.0"), None);
+        assert!(at_least("Firecracker v1.3.2", MIN_FIRECRACKER_VERSION).is_err());
+    }
+
+    #[test]
+    fn required_failures_make_runtimes_unready() {
+        let missing = Path::new("/nonexistent/kata-runtime");
+        let readiness = host(true).kata(missing);
+        assert!(!readiness.ready);
+        assert!(readiness.failures().starts_with("kata-runtime: failed to run"));
+        assert!(readiness.failures().contains("cgroup_v2"));
+
+        // cgroup v2 is advisory, KVM is not
+        let checks = vec![Check::new("kata-runtime", true, ""), host(true).cgroup_v2.advisory()];
+        assert!(Readiness::new(RuntimeType::Kata, checks.clone()).ready);
+        let checks = [checks, vec![host(false).kvm]].concat();
+        assert!(!Readiness::new(RuntimeType::Kata, checks).ready);
+    }
+}
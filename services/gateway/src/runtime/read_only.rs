@@ -0,0 +1,195 @@
+//! Hardening shared by the local runtimes for `ExecutionMode::ReadOnly`
+//! sandboxes: read-only root, size-capped tmpfs scratch space, no network
+//! and a reduced syscall profile
+
+use sandstorm_types::sandbox::{Arch, ExecutionMode, SandboxConfig};
+use serde_json::{json, Value};
+
+use super::arch;
+
+/// Scratch space given to each writable tmpfs when the request doesn't size it
+pub const DEFAULT_SCRATCH_SIZE: u64 = 64 * 1024 * 1024;
+
+/// `AF_UNIX`; the only socket family read-only sandboxes may open
+const AF_UNIX: u64 = 1;
+
+/// Syscalls a read-only sandbox may make. Everything needed to run an
+/// interpreter and work with files, but nothing that loads code into the
+/// kernel, traces other processes or changes mounts or namespaces. Socket
+/// calls are allowed since sockets themselves are limited to `AF_UNIX`.
+const ALLOWED_SYSCALLS: &[&str] = &[
+    "accept4", "access", "bind", "connect", "getsockname", "getsockopt", "listen",
+    "recvfrom", "recvmsg", "sendmsg", "sendto", "setsockopt", "shutdown", "arch_prctl", "brk", "capget", "clock_getres", "clock_gettime",
+    "clock_nanosleep", "clone", "clone3", "close", "dup", "dup2", "dup3",
+    "epoll_create", "epoll_create1", "epoll_ctl", "epoll_pwait", "epoll_wait", "eventfd2",
+    "execve", "exit", "exit_group", "faccessat", "faccessat2", "fadvise64", "fchdir",
+    "fchmod", "fcntl", "fdatasync", "flock", "fstat", "fstatfs", "fsync", "ftruncate",
+    "futex", "getcwd", "getdents", "getdents64", "getegid", "geteuid", "getgid",
+    "getgroups", "getpgrp", "getpid", "getppid", "getrandom", "getrlimit", "getrusage",
+    "gettid", "gettimeofday", "getuid", "ioctl", "lseek", "lstat", "madvise", "mkdir",
+    "mkdirat", "mmap", "mprotect", "mremap", "munmap", "nanosleep", "newfstatat",
+    "open", "openat", "pipe", "pipe2", "poll", "ppoll", "prctl", "pread64", "prlimit64",
+    "pselect6", "pwrite64", "read", "readlink", "readlinkat", "readv", "rename",
+    "renameat", "renameat2", "rmdir", "rseq", "rt_sigaction", "rt_sigprocmask",
+    "rt_sigreturn", "sched_getaffinity", "sched_yield", "select", "set_robust_list",
+    "set_tid_address", "sigaltstack", "stat", "statfs", "statx", "sysinfo", "tgkill",
+    "umask", "uname", "unlink", "unlinkat", "vfork", "wait4", "write", "writev",
+];
+
+/// Mount options for a tmpfs scratch mount of the configured size
+fn scratch_options(config: &SandboxConfig) -> Vec<String> {
+    let size_kib = config.scratch_size.unwrap_or(DEFAULT_SCRATCH_SIZE) / 1024;
+    vec![
+        "nosuid".to_string(),
+        "nodev".to_string(),
+        "mode=1777".to_string(),
+        format!("size={}k", size_kib),
+    ]
+}
+
+/// Seccomp profile for read-only sandboxes on `arch`; sockets are limited
+/// to `AF_UNIX`
+pub fn seccomp_profile(arch: Arch) -> Value {
+    json!({
+        "defaultAction": "SCMP_ACT_ERRNO",
+        "architectures": arch::seccomp_architectures(arch),
+        "syscalls": [
+            {
+                "names": arch::syscalls(arch, ALLOWED_SYSCALLS),
+                "action": "SCMP_ACT_ALLOW"
+            },
+            {
+                "names": ["socket", "socketpair"],
+                "action": "SCMP_ACT_ALLOW",
+                "args": [{ "index": 0, "value": AF_UNIX, "op": "SCMP_CMP_EQ" }]
+            }
+        ]
+    })
+}
+
+/// Harden an OCI runtime spec for `arch` in place when the sandbox runs
+/// read-only
+pub fn apply_to_oci_spec(spec: &mut Value, config: &SandboxConfig, arch: Arch) {
+    if config.execution_mode != ExecutionMode::ReadOnly {
+        return;
+    }
+
+    spec["root"]["readonly"] = json!(true);
+
+    // Writable scratch space lives in tmpfs and disappears with the sandbox
+    let mut scratch = vec!["/tmp".to_string()];
+    if let Some(dir) = config.working_dir.as_deref().filter(|dir| *dir != "/" && *dir != "/tmp") {
+        scratch.push(dir.to_string());
+    }
+    if let Some(mounts) = spec["mounts"].as_array_mut() {
+        for destination in scratch {
+            mounts.push(json!({
+                "destination": destination,
+                "type": "tmpfs",
+                "source": "tmpfs",
+                "options": scratch_options(config)
+            }));
+        }
+    }
+
+    // The process keeps no capabilities at all
+    spec["process"]["capabilities"] = json!({
+        "bounding": [],
+        "effective": [],
+        "inheritable": [],
+        "permitted": [],
+        "ambient": []
+    });
+    spec["process"]["noNewPrivileges"] = json!(true);
+
+    // An empty network namespace leaves only loopback, and the seccomp
+    // profile stops anything but Unix sockets being opened
+    spec["linux"]["seccomp"] = seccomp_profile(arch);
+}
+
+/// Guest kernel arguments for a read-only microVM: the root drive is mounted
+/// read-only with a RAM-backed overlay of the scratch size on top
+pub fn kernel_args(config: &SandboxConfig) -> String {
+    let size_mib = config.scratch_size.unwrap_or(DEFAULT_SCRATCH_SIZE) / (1024 * 1024);
+    format!(
+        "ro init=/sbin/overlay-init overlay_root=ram overlay_size={}M",
+        size_mib.max(1)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sandstorm_types::sandbox::IsolationLevel;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn config(mode: ExecutionMode) -> SandboxConfig {
+        SandboxConfig {
+            id: Uuid::new_v4(),
+            image: "sandstorm/python".to_string(),
+            command: vec!["python".to_string()],
+            environment: HashMap::new(),
+            cpu_limit: None,
+            memory_limit: None,
+            timeout: None,
+            isolation_level: IsolationLevel::Strong,
+            runtime_preference: None,
+            working_dir: Some("/workspace".to_string()),
+            mounts: Vec::new(),
+            execution_mode: mode,
+            scratch_size: Some(16 * 1024 * 1024),
+            sysctls: HashMap::new(),
+            gvisor: Default::default(),
+            firecracker: Default::default(),
+            arch: None,
+            rootfs_layers: Vec::new(),
+            ttl_seconds: None,
+        }
+    }
+
+    fn spec() -> Value {
+        json!({
+            "process": { "capabilities": { "bounding": ["CAP_KILL"] } },
+            "root": { "path": "rootfs", "readonly": false },
+            "mounts": [],
+            "linux": {}
+        })
+    }
+
+    #[test]
+    fn hardens_read_only_specs() {
+        let mut hardened = spec();
+        apply_to_oci_spec(&mut hardened, &config(ExecutionMode::ReadOnly), Arch::X86_64);
+
+        assert_eq!(hardened["root"]["readonly"], true);
+        let mounts = hardened["mounts"].as_array().unwrap();
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[1]["destination"], "/workspace");
+        assert!(mounts[0]["options"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("size=16384k")));
+        assert_eq!(hardened["process"]["capabilities"]["bounding"], json!([]));
+
+        let syscalls = hardened["linux"]["seccomp"]["syscalls"].as_array().unwrap();
+        let allowed = syscalls[0]["names"].as_array().unwrap();
+        assert!(!allowed.contains(&json!("socket")));
+        assert!(!allowed.contains(&json!("mount")));
+        assert!(!allowed.contains(&json!("ptrace")));
+        assert_eq!(syscalls[1]["args"][0]["value"], AF_UNIX);
+
+        let mut standard = spec();
+        apply_to_oci_spec(&mut standard, &config(ExecutionMode::Standard), Arch::X86_64);
+        assert_eq!(standard, spec());
+
+        let arm = seccomp_profile(Arch::Aarch64);
+        assert_eq!(arm["architectures"], json!(["SCMP_ARCH_AARCH64"]));
+        assert!(!arm["syscalls"][0]["names"].as_array().unwrap().contains(&json!("open")));
+    }
+
+    #[test]
+    fn sizes_microvm_overlay() {
+        assert!(kernel_args(&config(ExecutionMode::ReadOnly)).ends_with("overlay_size=16M"));
+    }
+}
@@ -0,0 +1,186 @@
+use super::*;
+use serde_json::json;
+
+const DEFAULT_API_URL: &str = "https://api.daytona.io";
+
+/// Daytona workspaces (OCI containers)
+pub struct DaytonaProvider {
+    http: reqwest::Client,
+    api_url: String,
+    api_key: String,
+}
+
+impl DaytonaProvider {
+    pub fn new(api_key: String) -> Self {
+        Self {
+            http: http_client(),
+            api_url: DEFAULT_API_URL.to_string(),
+            api_key,
+        }
+    }
+
+    /// Configured from `DAYTONA_API_KEY`, with an optional `DAYTONA_API_URL`
+    pub fn from_env() -> Option<Self> {
+        let api_key = std::env::var("DAYTONA_API_KEY").ok()?;
+        let mut provider = Self::new(api_key);
+        if let Ok(url) = std::env::var("DAYTONA_API_URL") {
+            provider.api_url = url.trim_end_matches('/').to_string();
+        }
+        Some(provider)
+    }
+
+    fn api(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.http
+            .request(method, format!("{}{}", self.api_url, path))
+            .bearer_auth(&self.api_key)
+    }
+}
+
+#[async_trait]
+impl RemoteProvider for DaytonaProvider {
+    fn runtime_type(&self) -> RuntimeType {
+        RuntimeType::Daytona
+    }
+
+    fn supports_isolation_level(&self, level: IsolationLevel) -> bool {
+        level == IsolationLevel::Standard
+    }
+
+    async fn create(&self, config: &SandboxConfig) -> Result<String> {
+        let mut body = json!({
+            "env": config.environment,
+            "labels": { "sandstorm_id": config.id.to_string() },
+            // Minutes of inactivity before Daytona stops the sandbox
+            "autoStopInterval": timeout_secs(config, 900).div_ceil(60),
+        });
+        if !config.image.is_empty() {
+            body["image"] = json!(config.image);
+        }
+        if let Some(cpu) = config.cpu_limit {
+            body["cpu"] = json!(cpu.ceil() as u64);
+        }
+        if let Some(memory) = config.memory_limit {
+            // Daytona sizes memory in GiB
+            body["memory"] = json!(memory.div_ceil(1024 * 1024 * 1024));
+        }
+
+        let response = self.api(reqwest::Method::POST, "/sandbox").json(&body).send().await?;
+        let created: serde_json::Value = check(response, "Daytona sandbox creation").await?.json().await?;
+
+        Ok(created
+            .get("id")
+            .and_then(|v| v.as_str())
+            .context("Daytona response did not include a sandbox ID")?
+            .to_string())
+    }
+
+    async fn exec(
+        &self,
+        remote_id: &str,
+        command: &[String],
+        environment: &HashMap<String, String>,
+        working_dir: Option<&str>,
+    ) -> Result<RemoteExec> {
+        anyhow::ensure!(!command.is_empty(), "Empty command");
+
+        // The toolbox runs a single shell string, so per-call environment is
+        // passed through `env`
+        let mut line = String::new();
+        if !environment.is_empty() {
+            let mut vars: Vec<_> = environment.iter().collect();
+            vars.sort();
+            let assignments: Vec<String> = vars.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            line.push_str("env ");
+            line.push_str(&shell_join(&assignments));
+            line.push(' ');
+        }
+        line.push_str(&shell_join(command));
+
+        let mut body = json!({ "command": line });
+        if let Some(dir) = working_dir {
+            body["cwd"] = json!(dir);
+        }
+
+        let response = self
+            .api(
+                reqwest::Method::POST,
+                &format!("/toolbox/{}/toolbox/process/execute", remote_id),
+            )
+            .json(&body)
+            .send()
+            .await?;
+        let output: serde_json::Value = check(response, "Daytona command execution").await?.json().await?;
+
+        // Daytona merges stderr into the result
+        Ok(RemoteExec {
+            exit_code: output.get("exitCode").and_then(|v| v.as_i64()).unwrap_or(-1) as i32,
+            stdout: output
+                .get("result")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .as_bytes()
+                .to_vec(),
+            stderr: Vec::new(),
+        })
+    }
+
+    async fn destroy(&self, remote_id: &str) -> Result<()> {
+        let response = self
+            .api(reqwest::Method::DELETE, &format!("/sandbox/{}", remote_id))
+            .query(&[("force", "true")])
+            .send()
+            .await?;
+        check(response, "Daytona sandbox deletion").await?;
+        Ok(())
+    }
+
+    async fn state(&self, remote_id: &str) -> Result<SandboxState> {
+        let response = self
+            .api(reqwest::Method::GET, &format!("/sandbox/{}", remote_id))
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(SandboxState::Stopped);
+        }
+        let body: serde_json::Value = check(response, "Daytona sandbox lookup").await?.json().await?;
+
+        Ok(match body.get("state").and_then(|v| v.as_str()) {
+            Some("creating") | Some("restoring") | Some("starting") | Some("pulling_image") => {
+                SandboxState::Creating
+            }
+            Some("started") => SandboxState::Running,
+            Some("stopped") | Some("stopping") | Some("archived") | Some("destroyed") => {
+                SandboxState::Stopped
+            }
+            _ => SandboxState::Failed,
+        })
+    }
+
+    async fn snapshot(&self, remote_id: &str) -> Result<HashMap<String, serde_json::Value>> {
+        // Stopped Daytona sandboxes keep their filesystem until deleted
+        let response = self
+            .api(reqwest::Method::POST, &format!("/sandbox/{}/stop", remote_id))
+            .send()
+            .await?;
+        check(response, "Daytona sandbox stop").await?;
+
+        Ok(HashMap::from([(
+            "daytona_sandbox_id".to_string(),
+            json!(remote_id),
+        )]))
+    }
+
+    async fn resume(&self, metadata: &HashMap<String, serde_json::Value>) -> Result<String> {
+        let sandbox_id = metadata
+            .get("daytona_sandbox_id")
+            .and_then(|v| v.as_str())
+            .context("Snapshot was not taken from a Daytona sandbox")?;
+
+        let response = self
+            .api(reqwest::Method::POST, &format!("/sandbox/{}/start", sandbox_id))
+            .send()
+            .await?;
+        check(response, "Daytona sandbox start").await?;
+        Ok(sandbox_id.to_string())
+    }
+}
@@ -0,0 +1,290 @@
+use super::*;
+use base64::Engine;
+use serde_json::json;
+
+const DEFAULT_API_URL: &str = "https://api.e2b.dev";
+const DEFAULT_DOMAIN: &str = "e2b.app";
+const DEFAULT_TEMPLATE: &str = "base";
+/// Port the in-sandbox process daemon (envd) listens on
+const ENVD_PORT: u16 = 49983;
+
+/// Connect end-of-stream flag on a response envelope
+const END_STREAM_FLAG: u8 = 0x02;
+
+/// E2B Firecracker microVMs
+pub struct E2bProvider {
+    http: reqwest::Client,
+    api_url: String,
+    domain: String,
+    api_key: String,
+    default_template: String,
+    /// envd access tokens for secured sandboxes, by sandbox ID
+    access_tokens: RwLock<HashMap<String, String>>,
+}
+
+impl E2bProvider {
+    pub fn new(api_key: String) -> Self {
+        Self {
+            http: http_client(),
+            api_url: DEFAULT_API_URL.to_string(),
+            domain: DEFAULT_DOMAIN.to_string(),
+            api_key,
+            default_template: DEFAULT_TEMPLATE.to_string(),
+            access_tokens: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Configured from `E2B_API_KEY`, with optional `E2B_API_URL`,
+    /// `E2B_DOMAIN` and `E2B_TEMPLATE` overrides
+    pub fn from_env() -> Option<Self> {
+        let api_key = std::env::var("E2B_API_KEY").ok()?;
+        let mut provider = Self::new(api_key);
+        if let Ok(url) = std::env::var("E2B_API_URL") {
+            provider.api_url = url.trim_end_matches('/').to_string();
+        }
+        if let Ok(domain) = std::env::var("E2B_DOMAIN") {
+            provider.domain = domain;
+        }
+        if let Ok(template) = std::env::var("E2B_TEMPLATE") {
+            provider.default_template = template;
+        }
+        Some(provider)
+    }
+
+    fn api(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.http
+            .request(method, format!("{}{}", self.api_url, path))
+            .header("X-API-Key", &self.api_key)
+    }
+
+    /// Images are E2B template IDs; anything that looks like a registry
+    /// reference falls back to the default template
+    fn template_for(&self, image: &str) -> String {
+        if image.is_empty() || image.contains(':') || image.contains('/') {
+            self.default_template.clone()
+        } else {
+            image.to_string()
+        }
+    }
+
+    async fn remember_token(&self, sandbox_id: &str, body: &serde_json::Value) {
+        if let Some(token) = body.get("envdAccessToken").and_then(|v| v.as_str()) {
+            self.access_tokens
+                .write()
+                .await
+                .insert(sandbox_id.to_string(), token.to_string());
+        }
+    }
+}
+
+#[async_trait]
+impl RemoteProvider for E2bProvider {
+    fn runtime_type(&self) -> RuntimeType {
+        RuntimeType::E2b
+    }
+
+    fn supports_isolation_level(&self, level: IsolationLevel) -> bool {
+        matches!(level, IsolationLevel::Strong | IsolationLevel::Maximum)
+    }
+
+    async fn create(&self, config: &SandboxConfig) -> Result<String> {
+        let body = json!({
+            "templateID": self.template_for(&config.image),
+            "timeout": timeout_secs(config, 300),
+            "envVars": config.environment,
+            "metadata": { "sandstorm_id": config.id.to_string() },
+        });
+
+        let response = self.api(reqwest::Method::POST, "/sandboxes").json(&body).send().await?;
+        let created: serde_json::Value = check(response, "E2B sandbox creation").await?.json().await?;
+
+        let sandbox_id = created
+            .get("sandboxID")
+            .and_then(|v| v.as_str())
+            .context("E2B response did not include a sandbox ID")?
+            .to_string();
+        self.remember_token(&sandbox_id, &created).await;
+        Ok(sandbox_id)
+    }
+
+    async fn exec(
+        &self,
+        remote_id: &str,
+        command: &[String],
+        environment: &HashMap<String, String>,
+        working_dir: Option<&str>,
+    ) -> Result<RemoteExec> {
+        let (cmd, args) = command.split_first().context("Empty command")?;
+        let mut process = json!({ "cmd": cmd, "args": args, "envs": environment });
+        if let Some(dir) = working_dir {
+            process["cwd"] = json!(dir);
+        }
+        let message = serde_json::to_vec(&json!({ "process": process }))?;
+
+        let mut request = self
+            .http
+            .post(format!(
+                "https://{}-{}.{}/process.Process/Start",
+                ENVD_PORT, remote_id, self.domain
+            ))
+            .header("Content-Type", "application/connect+json")
+            .header("Connect-Protocol-Version", "1")
+            // envd authenticates the sandbox user through basic auth
+            .header("Authorization", "Basic dXNlcjo=")
+            .body(encode_envelope(0, &message));
+        if let Some(token) = self.access_tokens.read().await.get(remote_id) {
+            request = request.header("X-Access-Token", token);
+        }
+
+        let response = check(request.send().await?, "E2B command execution").await?;
+        decode_process_stream(&response.bytes().await?)
+    }
+
+    async fn destroy(&self, remote_id: &str) -> Result<()> {
+        let response = self
+            .api(reqwest::Method::DELETE, &format!("/sandboxes/{}", remote_id))
+            .send()
+            .await?;
+        check(response, "E2B sandbox deletion").await?;
+        self.access_tokens.write().await.remove(remote_id);
+        Ok(())
+    }
+
+    async fn state(&self, remote_id: &str) -> Result<SandboxState> {
+        let response = self
+            .api(reqwest::Method::GET, &format!("/sandboxes/{}", remote_id))
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(SandboxState::Stopped);
+        }
+        let body: serde_json::Value = check(response, "E2B sandbox lookup").await?.json().await?;
+
+        Ok(match body.get("state").and_then(|v| v.as_str()) {
+            Some("running") => SandboxState::Running,
+            Some("paused") => SandboxState::Paused,
+            _ => SandboxState::Failed,
+        })
+    }
+
+    async fn snapshot(&self, remote_id: &str) -> Result<HashMap<String, serde_json::Value>> {
+        let response = self
+            .api(reqwest::Method::POST, &format!("/sandboxes/{}/pause", remote_id))
+            .send()
+            .await?;
+        check(response, "E2B sandbox pause").await?;
+
+        let mut metadata = HashMap::new();
+        metadata.insert("e2b_sandbox_id".to_string(), json!(remote_id));
+        if let Some(token) = self.access_tokens.write().await.remove(remote_id) {
+            metadata.insert("e2b_access_token".to_string(), json!(token));
+        }
+        Ok(metadata)
+    }
+
+    async fn resume(&self, metadata: &HashMap<String, serde_json::Value>) -> Result<String> {
+        let sandbox_id = metadata
+            .get("e2b_sandbox_id")
+            .and_then(|v| v.as_str())
+            .context("Snapshot was not taken from an E2B sandbox")?;
+
+        let response = self
+            .api(reqwest::Method::POST, &format!("/sandboxes/{}/resume", sandbox_id))
+            .json(&json!({ "timeout": 300 }))
+            .send()
+            .await?;
+        let resumed: serde_json::Value = check(response, "E2B sandbox resume").await?.json().await?;
+
+        if let Some(token) = metadata.get("e2b_access_token").and_then(|v| v.as_str()) {
+            self.access_tokens
+                .write()
+                .await
+                .insert(sandbox_id.to_string(), token.to_string());
+        }
+        self.remember_token(sandbox_id, &resumed).await;
+        Ok(sandbox_id.to_string())
+    }
+}
+
+fn encode_envelope(flags: u8, message: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(message.len() + 5);
+    frame.push(flags);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(message);
+    frame
+}
+
+/// Collect stdout, stderr and the exit code from a `Process/Start` stream
+fn decode_process_stream(mut body: &[u8]) -> Result<RemoteExec> {
+    let base64 = base64::engine::general_purpose::STANDARD;
+    let mut result = RemoteExec {
+        exit_code: -1,
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    };
+
+    while !body.is_empty() {
+        anyhow::ensure!(body.len() >= 5, "Truncated E2B stream frame");
+        let flags = body[0];
+        let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+        anyhow::ensure!(body.len() >= 5 + len, "Truncated E2B stream frame");
+        let message: serde_json::Value = serde_json::from_slice(&body[5..5 + len])?;
+        body = &body[5 + len..];
+
+        if flags & END_STREAM_FLAG != 0 {
+            if let Some(error) = message.get("error") {
+                anyhow::bail!("E2B command execution failed: {}", error);
+            }
+            continue;
+        }
+
+        let Some(event) = message.get("event") else {
+            continue;
+        };
+        if let Some(data) = event.get("data") {
+            if let Some(out) = data.get("stdout").and_then(|v| v.as_str()) {
+                result.stdout.extend(base64.decode(out)?);
+            }
+            if let Some(err) = data.get("stderr").and_then(|v| v.as_str()) {
+                result.stderr.extend(base64.decode(err)?);
+            }
+        }
+        if let Some(end) = event.get("end") {
+            result.exit_code = end.get("exitCode").and_then(|v| v.as_i64()).unwrap_or(0) as i32;
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(flags: u8, message: serde_json::Value) -> Vec<u8> {
+        encode_envelope(flags, &serde_json::to_vec(&message).unwrap())
+    }
+
+    #[test]
+    fn decodes_process_stream() {
+        let mut body = frame(0, json!({ "event": { "start": { "pid": 42 } } }));
+        body.extend(frame(0, json!({ "event": { "data": { "stdout": "aGVsbG8K" } } })));
+        body.extend(frame(0, json!({ "event": { "data": { "stderr": "b29wcwo=" } } })));
+        body.extend(frame(0, json!({ "event": { "end": { "exitCode": 3, "exited": true } } })));
+        body.extend(frame(END_STREAM_FLAG, json!({})));
+
+        let result = decode_process_stream(&body).unwrap();
+        assert_eq!(result.stdout, b"hello\n");
+        assert_eq!(result.stderr, b"oops\n");
+        assert_eq!(result.exit_code, 3);
+    }
+
+    #[test]
+    fn surfaces_stream_errors() {
+        let body = frame(
+            END_STREAM_FLAG,
+            json!({ "error": { "code": "not_found", "message": "no such file" } }),
+        );
+        assert!(decode_process_stream(&body).is_err());
+    }
+}
@@ -0,0 +1,355 @@
+use super::*;
+use anyhow::Context;
+use tracing::{error, info};
+
+pub mod daytona;
+pub mod e2b;
+pub mod modal;
+
+/// Output of a command run on a hosted provider
+#[derive(Debug, Clone)]
+pub struct RemoteExec {
+    pub exit_code: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// API calls a hosted sandbox provider has to support. Providers only deal
+/// with their own sandbox IDs; [`RemoteRuntime`] maps them to gateway IDs and
+/// implements [`SandboxRuntime`] on top.
+#[async_trait]
+pub trait RemoteProvider: Send + Sync + 'static {
+    fn runtime_type(&self) -> RuntimeType;
+
+    fn supports_isolation_level(&self, level: IsolationLevel) -> bool;
+
+    /// Provision a sandbox and return the provider's ID for it
+    async fn create(&self, config: &SandboxConfig) -> Result<String>;
+
+    async fn exec(
+        &self,
+        remote_id: &str,
+        command: &[String],
+        environment: &HashMap<String, String>,
+        working_dir: Option<&str>,
+    ) -> Result<RemoteExec>;
+
+    async fn destroy(&self, remote_id: &str) -> Result<()>;
+
+    async fn state(&self, remote_id: &str) -> Result<SandboxState>;
+
+    /// Persist the sandbox; the returned metadata is handed back to `resume`
+    async fn snapshot(&self, remote_id: &str) -> Result<HashMap<String, serde_json::Value>>;
+
+    /// Bring a snapshotted sandbox back and return its provider ID
+    async fn resume(&self, metadata: &HashMap<String, serde_json::Value>) -> Result<String>;
+}
+
+#[derive(Debug, Clone)]
+struct RemoteSandbox {
+    remote_id: String,
+    working_dir: Option<String>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    exit_code: Option<i32>,
+}
+
+/// [`SandboxRuntime`] backed by a hosted provider
+pub struct RemoteRuntime<P> {
+    provider: Arc<P>,
+    /// Active sandboxes
+    sandboxes: Arc<RwLock<HashMap<Uuid, RemoteSandbox>>>,
+}
+
+impl<P: RemoteProvider> RemoteRuntime<P> {
+    pub fn new(provider: P) -> Self {
+        Self {
+            provider: Arc::new(provider),
+            sandboxes: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    async fn get(&self, sandbox_id: Uuid) -> Result<RemoteSandbox> {
+        self.sandboxes
+            .read()
+            .await
+            .get(&sandbox_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Sandbox {} not found", sandbox_id))
+    }
+}
+
+#[async_trait]
+impl<P: RemoteProvider> SandboxRuntime for RemoteRuntime<P> {
+    fn runtime_type(&self) -> RuntimeType {
+        self.provider.runtime_type()
+    }
+
+    fn supports_isolation_level(&self, level: IsolationLevel) -> bool {
+        self.provider.supports_isolation_level(level)
+    }
+
+    /// Hosted providers run x86_64 machines only
+    fn supports_arch(&self, arch: Arch) -> bool {
+        arch == Arch::X86_64
+    }
+
+    async fn create(&self, config: &SandboxConfig) -> Result<Uuid> {
+        let sandbox_id = config.id;
+        let remote_id = self.provider.create(config).await?;
+
+        self.sandboxes.write().await.insert(
+            sandbox_id,
+            RemoteSandbox {
+                remote_id: remote_id.clone(),
+                working_dir: config.working_dir.clone(),
+                created_at: chrono::Utc::now(),
+                finished_at: None,
+                exit_code: None,
+            },
+        );
+
+        // Run the sandbox's entry command in the background, like the local
+        // runtimes do, and record its exit code for status queries
+        if !config.command.is_empty() {
+            let provider = self.provider.clone();
+            let sandboxes = self.sandboxes.clone();
+            let command = config.command.clone();
+            let environment = config.environment.clone();
+            let working_dir = config.working_dir.clone();
+            tokio::spawn(async move {
+                let exit_code = match provider
+                    .exec(&remote_id, &command, &environment, working_dir.as_deref())
+                    .await
+                {
+                    Ok(result) => result.exit_code,
+                    Err(e) => {
+                        error!("Entry command failed in sandbox {}: {}", sandbox_id, e);
+                        -1
+                    }
+                };
+                if let Some(info) = sandboxes.write().await.get_mut(&sandbox_id) {
+                    info.exit_code = Some(exit_code);
+                    info.finished_at = Some(chrono::Utc::now());
+                }
+            });
+        }
+
+        info!(
+            "Created {:?} sandbox {} (provider id {})",
+            self.provider.runtime_type(),
+            sandbox_id,
+            self.get(sandbox_id).await?.remote_id
+        );
+        Ok(sandbox_id)
+    }
+
+    async fn exec(
+        &self,
+        sandbox_id: Uuid,
+        command: Vec<String>,
+        environment: Option<HashMap<String, String>>,
+        options: &ExecOptions,
+    ) -> Result<SandboxResult> {
+        let info = self.get(sandbox_id).await?;
+        options.check(
+            self.provider.runtime_type(),
+            ExecSupport {
+                working_dir: true,
+                ..Default::default()
+            },
+        )?;
+        let start_time = std::time::Instant::now();
+
+        let output = self
+            .provider
+            .exec(
+                &info.remote_id,
+                &command,
+                &environment.unwrap_or_default(),
+                options.working_dir.as_deref().or(info.working_dir.as_deref()),
+            )
+            .await?;
+        let duration_ms = start_time.elapsed().as_millis() as u64;
+
+        Ok(SandboxResult {
+            id: sandbox_id,
+            exit_code: output.exit_code,
+            stdout: output.stdout,
+            stderr: output.stderr,
+            duration_ms,
+            resource_usage: ResourceUsage {
+                cpu_usage_seconds: duration_ms as f64 / 1000.0,
+                memory_usage_bytes: 0, // Not reported by providers
+                network_rx_bytes: 0,
+                network_tx_bytes: 0,
+            },
+            exit_reason: exit::of_code(output.exit_code),
+        })
+    }
+
+    async fn destroy(&self, sandbox_id: Uuid) -> Result<()> {
+        let Some(info) = self.sandboxes.write().await.remove(&sandbox_id) else {
+            anyhow::bail!("Sandbox {} not found", sandbox_id);
+        };
+
+        self.provider.destroy(&info.remote_id).await?;
+        info!("Destroyed {:?} sandbox {}", self.provider.runtime_type(), sandbox_id);
+        Ok(())
+    }
+
+    async fn snapshot(&self, sandbox_id: Uuid) -> Result<SandboxSnapshot> {
+        let info = self.get(sandbox_id).await?;
+        let mut metadata = self.provider.snapshot(&info.remote_id).await?;
+        if let Some(working_dir) = &info.working_dir {
+            metadata.insert("working_dir".to_string(), serde_json::json!(working_dir));
+        }
+
+        // The provider now holds the sandbox state; the gateway ID is released
+        self.sandboxes.write().await.remove(&sandbox_id);
+
+        Ok(SandboxSnapshot {
+            id: Uuid::new_v4(),
+            sandbox_id,
+            runtime_type: self.provider.runtime_type(),
+            timestamp: chrono::Utc::now(),
+            filesystem_state: Vec::new(), // Kept by the provider
+            memory_state: None,
+            metadata,
+        })
+    }
+
+    async fn resume(&self, snapshot: &SandboxSnapshot) -> Result<Uuid> {
+        let remote_id = self
+            .provider
+            .resume(&snapshot.metadata)
+            .await
+            .context("Failed to resume remote sandbox")?;
+        let new_sandbox_id = Uuid::new_v4();
+
+        self.sandboxes.write().await.insert(
+            new_sandbox_id,
+            RemoteSandbox {
+                remote_id,
+                working_dir: snapshot
+                    .metadata
+                    .get("working_dir")
+                    .and_then(|v| v.as_str())
+                    .map(str::to_string),
+                created_at: chrono::Utc::now(),
+                finished_at: None,
+                exit_code: None,
+            },
+        );
+
+        info!(
+            "Resumed {:?} sandbox {} from snapshot {}",
+            self.provider.runtime_type(),
+            new_sandbox_id,
+            snapshot.id
+        );
+        Ok(new_sandbox_id)
+    }
+
+    async fn status(&self, sandbox_id: Uuid) -> Result<SandboxStatus> {
+        let info = self.get(sandbox_id).await?;
+        let state = self.provider.state(&info.remote_id).await?;
+
+        Ok(SandboxStatus {
+            id: sandbox_id,
+            state,
+            created_at: info.created_at,
+            started_at: Some(info.created_at),
+            finished_at: info.finished_at,
+            exit_code: info.exit_code,
+            resource_usage: ResourceUsage {
+                cpu_usage_seconds: 0.0,
+                memory_usage_bytes: 0,
+                network_rx_bytes: 0,
+                network_tx_bytes: 0,
+            },
+            exit_reason: info.exit_code.map(exit::of_code),
+            transitions: Vec::new(),
+        })
+    }
+
+    async fn logs(&self, sandbox_id: Uuid, _follow: bool) -> Result<Box<dyn tokio::io::AsyncRead + Send + Unpin>> {
+        self.get(sandbox_id).await?;
+        anyhow::bail!(
+            "{:?} sandboxes do not support log streaming",
+            self.provider.runtime_type()
+        )
+    }
+
+    async fn active_sandboxes(&self) -> usize {
+        self.sandboxes.read().await.len()
+    }
+
+    fn is_remote(&self) -> bool {
+        true
+    }
+}
+
+/// Shared HTTP client for provider APIs
+pub(crate) fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .connect_timeout(std::time::Duration::from_secs(10))
+        .build()
+        .unwrap_or_default()
+}
+
+/// Turn a non-success provider response into an error carrying its body
+pub(crate) async fn check(response: reqwest::Response, action: &str) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    anyhow::bail!("{} failed with {}: {}", action, status, body.trim())
+}
+
+/// Quote a command for providers that take a single shell string
+pub(crate) fn shell_join(command: &[String]) -> String {
+    command
+        .iter()
+        .map(|arg| {
+            if !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@%+".contains(c))
+            {
+                arg.clone()
+            } else {
+                format!("'{}'", arg.replace('\'', r"'\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Provider timeout in whole seconds from a sandbox timeout in milliseconds
+pub(crate) fn timeout_secs(config: &SandboxConfig, default_secs: u64) -> u64 {
+    config
+        .timeout
+        .map(|ms| ms.div_ceil(1000).max(1))
+        .unwrap_or(default_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_join_quotes_only_when_needed() {
+        let command = vec![
+            "python3".to_string(),
+            "-c".to_string(),
+            "print('hi there')".to_string(),
+            "".to_string(),
+        ];
+        assert_eq!(
+            shell_join(&command),
+            r#"python3 -c 'print('\''hi there'\'')' ''"#
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use super::*;
+use base64::Engine;
+use serde_json::json;
+
+const DEFAULT_IMAGE: &str = "python:3.11-slim";
+
+/// Modal sandboxes.
+///
+/// Modal only exposes sandboxes through its client libraries, so requests go
+/// to a small HTTP bridge (a Modal web endpoint wrapping `modal.Sandbox`)
+/// that serves:
+///
+/// - `POST /sandboxes` `{image, env, cpu, memory, timeout, workdir}` or
+///   `{image_id}` → `{id}`
+/// - `POST /sandboxes/{id}/exec` `{command, env, workdir}` →
+///   `{exit_code, stdout, stderr}` (base64 output)
+/// - `GET /sandboxes/{id}` → `{state}`
+/// - `POST /sandboxes/{id}/snapshot` → `{image_id}`
+/// - `DELETE /sandboxes/{id}`
+pub struct ModalProvider {
+    http: reqwest::Client,
+    bridge_url: String,
+    token_id: String,
+    token_secret: String,
+}
+
+impl ModalProvider {
+    pub fn new(bridge_url: String, token_id: String, token_secret: String) -> Self {
+        Self {
+            http: http_client(),
+            bridge_url: bridge_url.trim_end_matches('/').to_string(),
+            token_id,
+            token_secret,
+        }
+    }
+
+    /// Configured from `MODAL_SANDBOX_API_URL`, `MODAL_TOKEN_ID` and
+    /// `MODAL_TOKEN_SECRET`
+    pub fn from_env() -> Option<Self> {
+        Some(Self::new(
+            std::env::var("MODAL_SANDBOX_API_URL").ok()?,
+            std::env::var("MODAL_TOKEN_ID").ok()?,
+            std::env::var("MODAL_TOKEN_SECRET").ok()?,
+        ))
+    }
+
+    fn api(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.http
+            .request(method, format!("{}{}", self.bridge_url, path))
+            .header("Modal-Key", &self.token_id)
+            .header("Modal-Secret", &self.token_secret)
+    }
+
+    async fn create_from(&self, body: serde_json::Value) -> Result<String> {
+        let response = self.api(reqwest::Method::POST, "/sandboxes").json(&body).send().await?;
+        let created: serde_json::Value = check(response, "Modal sandbox creation").await?.json().await?;
+
+        Ok(created
+            .get("id")
+            .and_then(|v| v.as_str())
+            .context("Modal response did not include a sandbox ID")?
+            .to_string())
+    }
+}
+
+#[async_trait]
+impl RemoteProvider for ModalProvider {
+    fn runtime_type(&self) -> RuntimeType {
+        RuntimeType::Modal
+    }
+
+    fn supports_isolation_level(&self, level: IsolationLevel) -> bool {
+        // Modal runs sandboxes under gVisor
+        level == IsolationLevel::Standard
+    }
+
+    async fn create(&self, config: &SandboxConfig) -> Result<String> {
+        let image = if config.image.is_empty() {
+            DEFAULT_IMAGE
+        } else {
+            &config.image
+        };
+
+        self.create_from(json!({
+            "image": image,
+            "env": config.environment,
+            "cpu": config.cpu_limit,
+            // Modal sizes memory in MiB
+            "memory": config.memory_limit.map(|bytes| bytes.div_ceil(1024 * 1024)),
+            "timeout": timeout_secs(config, 300),
+            "workdir": config.working_dir,
+        }))
+        .await
+    }
+
+    async fn exec(
+        &self,
+        remote_id: &str,
+        command: &[String],
+        environment: &HashMap<String, String>,
+        working_dir: Option<&str>,
+    ) -> Result<RemoteExec> {
+        anyhow::ensure!(!command.is_empty(), "Empty command");
+
+        let response = self
+            .api(reqwest::Method::POST, &format!("/sandboxes/{}/exec", remote_id))
+            .json(&json!({ "command": command, "env": environment, "workdir": working_dir }))
+            .send()
+            .await?;
+        let output: serde_json::Value = check(response, "Modal command execution").await?.json().await?;
+
+        let base64 = base64::engine::general_purpose::STANDARD;
+        let decode = |field: &str| -> Result<Vec<u8>> {
+            match output.get(field).and_then(|v| v.as_str()) {
+                Some(data) => Ok(base64.decode(data)?),
+                None => Ok(Vec::new()),
+            }
+        };
+
+        Ok(RemoteExec {
+            exit_code: output.get("exit_code").and_then(|v| v.as_i64()).unwrap_or(-1) as i32,
+            stdout: decode("stdout")?,
+            stderr: decode("stderr")?,
+        })
+    }
+
+    async fn destroy(&self, remote_id: &str) -> Result<()> {
+        let response = self
+            .api(reqwest::Method::DELETE, &format!("/sandboxes/{}", remote_id))
+            .send()
+            .await?;
+        check(response, "Modal sandbox termination").await?;
+        Ok(())
+    }
+
+    async fn state(&self, remote_id: &str) -> Result<SandboxState> {
+        let response = self
+            .api(reqwest::Method::GET, &format!("/sandboxes/{}", remote_id))
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(SandboxState::Stopped);
+        }
+        let body: serde_json::Value = check(response, "Modal sandbox lookup").await?.json().await?;
+
+        Ok(match body.get("state").and_then(|v| v.as_str()) {
+            Some("creating") => SandboxState::Creating,
+            Some("running") => SandboxState::Running,
+            Some("terminated") => SandboxState::Stopped,
+            _ => SandboxState::Failed,
+        })
+    }
+
+    async fn snapshot(&self, remote_id: &str) -> Result<HashMap<String, serde_json::Value>> {
+        let response = self
+            .api(reqwest::Method::POST, &format!("/sandboxes/{}/snapshot", remote_id))
+            .send()
+            .await?;
+        let body: serde_json::Value = check(response, "Modal sandbox snapshot").await?.json().await?;
+        let image_id = body
+            .get("image_id")
+            .and_then(|v| v.as_str())
+            .context("Modal snapshot did not return an image ID")?;
+
+        // The filesystem now lives in the image, so the sandbox can go
+        self.destroy(remote_id).await?;
+
+        Ok(HashMap::from([(
+            "modal_image_id".to_string(),
+            json!(image_id),
+        )]))
+    }
+
+    async fn resume(&self, metadata: &HashMap<String, serde_json::Value>) -> Result<String> {
+        let image_id = metadata
+            .get("modal_image_id")
+            .and_then(|v| v.as_str())
+            .context("Snapshot was not taken from a Modal sandbox")?;
+
+        self.create_from(json!({
+            "image_id": image_id,
+            "workdir": metadata.get("working_dir"),
+        }))
+        .await
+    }
+}
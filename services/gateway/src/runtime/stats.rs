@@ -0,0 +1,168 @@
+use super::*;
+use sandstorm_types::sandbox::OptimizationHint;
+use sandstorm_types::telemetry::ProviderStats;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// How long fetched stats (or a failed fetch) are reused
+const CACHE_TTL: Duration = Duration::from_secs(60);
+/// Window of runs the collector aggregates over
+const STATS_WINDOW_HOURS: i64 = 24;
+/// Stats from fewer runs than this are too noisy to route on
+const MIN_RUNS: i64 = 5;
+
+/// Cached per-runtime provider stats from the telemetry collector
+#[derive(Debug)]
+pub struct RuntimeStats {
+    http: reqwest::Client,
+    telemetry_url: Option<String>,
+    cache: RwLock<HashMap<RuntimeType, (Instant, Option<ProviderStats>)>>,
+}
+
+impl RuntimeStats {
+    pub fn new(telemetry_url: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(2))
+                .build()
+                .unwrap_or_default(),
+            telemetry_url: telemetry_url.map(|url| url.trim_end_matches('/').to_string()),
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Collector URL comes from `GATEWAY_TELEMETRY_URL`
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("GATEWAY_TELEMETRY_URL").ok())
+    }
+
+    /// Stats for a runtime, or `None` when telemetry is unavailable or has
+    /// too few runs to go on
+    pub async fn get(&self, runtime_type: RuntimeType) -> Option<ProviderStats> {
+        if let Some((fetched_at, stats)) = self.cache.read().await.get(&runtime_type) {
+            if fetched_at.elapsed() < CACHE_TTL || self.telemetry_url.is_none() {
+                return stats.clone();
+            }
+        }
+
+        let stats = self
+            .fetch(runtime_type)
+            .await
+            .filter(|stats| stats.total_runs >= MIN_RUNS);
+        self.record(runtime_type, stats.clone()).await;
+        stats
+    }
+
+    pub(crate) async fn record(&self, runtime_type: RuntimeType, stats: Option<ProviderStats>) {
+        self.cache
+            .write()
+            .await
+            .insert(runtime_type, (Instant::now(), stats));
+    }
+
+    async fn fetch(&self, runtime_type: RuntimeType) -> Option<ProviderStats> {
+        let base_url = self.telemetry_url.as_ref()?;
+        // Telemetry records providers under the runtime's wire name
+        let provider = serde_json::to_value(runtime_type).ok()?;
+        let provider = provider.as_str()?;
+        let start = chrono::Utc::now() - chrono::Duration::hours(STATS_WINDOW_HOURS);
+
+        let response = self
+            .http
+            .get(format!("{}/api/telemetry/provider-stats/{}", base_url, provider))
+            .query(&[("start", start.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        match response {
+            Ok(response) => match response.json().await {
+                Ok(stats) => Some(stats),
+                Err(e) => {
+                    warn!("Invalid provider stats for {}: {}", provider, e);
+                    None
+                }
+            },
+            Err(e) => {
+                debug!("Provider stats for {} unavailable: {}", provider, e);
+                None
+            }
+        }
+    }
+}
+
+/// Expected cost or latency per successful run; lower is better. Runs the
+/// security monitor reported incidents for count as failed, so providers
+/// with elevated incident rates lose out.
+pub fn score(stats: &ProviderStats, hint: OptimizationHint) -> Option<f64> {
+    let success_rate = stats.success_rate * (1.0 - stats.security_incident_rate.clamp(0.0, 1.0));
+    if success_rate <= 0.0 {
+        return None;
+    }
+
+    let metric = match hint {
+        OptimizationHint::Cheapest => stats.avg_cost,
+        OptimizationHint::Fastest => stats.avg_latency,
+    };
+    Some(metric / success_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(avg_cost: f64, avg_latency: f64, success_rate: f64) -> ProviderStats {
+        ProviderStats {
+            avg_latency,
+            avg_cost,
+            success_rate,
+            total_runs: 100,
+            by_accelerator: Vec::new(),
+            security_incident_rate: 0.0,
+        }
+    }
+
+    #[test]
+    fn score_accounts_for_failures() {
+        // Cheaper per run, but half the runs have to be retried
+        let flaky = stats(0.01, 100.0, 0.5);
+        let reliable = stats(0.015, 300.0, 1.0);
+
+        assert!(
+            score(&reliable, OptimizationHint::Cheapest) < score(&flaky, OptimizationHint::Cheapest)
+        );
+        assert!(
+            score(&flaky, OptimizationHint::Fastest) < score(&reliable, OptimizationHint::Fastest)
+        );
+        assert_eq!(score(&stats(0.01, 100.0, 0.0), OptimizationHint::Cheapest), None);
+    }
+
+    #[test]
+    fn score_penalizes_security_incidents() {
+        let clean = stats(0.012, 100.0, 1.0);
+        let incidents = ProviderStats {
+            security_incident_rate: 0.25,
+            ..stats(0.01, 100.0, 1.0)
+        };
+
+        assert!(
+            score(&clean, OptimizationHint::Cheapest) < score(&incidents, OptimizationHint::Cheapest)
+        );
+        let quarantined = ProviderStats {
+            security_incident_rate: 1.0,
+            ..stats(0.01, 100.0, 1.0)
+        };
+        assert_eq!(score(&quarantined, OptimizationHint::Fastest), None);
+    }
+
+    #[tokio::test]
+    async fn get_without_collector_uses_recorded_stats() {
+        let runtime_stats = RuntimeStats::new(None);
+        assert!(runtime_stats.get(RuntimeType::E2b).await.is_none());
+
+        runtime_stats
+            .record(RuntimeType::E2b, Some(stats(0.02, 500.0, 0.9)))
+            .await;
+        assert_eq!(runtime_stats.get(RuntimeType::E2b).await.unwrap().total_runs, 100);
+    }
+}
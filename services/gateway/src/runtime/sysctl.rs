@@ -0,0 +1,95 @@
+//! Allow-list of kernel parameters a sandbox may set. gVisor, Kata and
+//! Firecracker all run their own kernel, so none of these reach the host.
+
+use anyhow::Result;
+use std::collections::HashMap;
+
+/// Settable parameters with the inclusive range of values each accepts
+const ALLOWED: &[(&str, u64, u64)] = &[
+    ("fs.file-max", 1024, 1_048_576),
+    ("fs.mqueue.msg_max", 1, 1024),
+    ("kernel.msgmax", 1024, 65_536),
+    ("kernel.msgmnb", 1024, 1_048_576),
+    ("kernel.shm_rmid_forced", 0, 1),
+    ("net.core.somaxconn", 128, 65_535),
+    ("net.ipv4.ip_unprivileged_port_start", 0, 65_535),
+    ("net.ipv4.tcp_fin_timeout", 1, 120),
+    ("net.ipv4.tcp_keepalive_intvl", 1, 600),
+    ("net.ipv4.tcp_keepalive_probes", 1, 20),
+    ("net.ipv4.tcp_keepalive_time", 1, 7200),
+    ("net.ipv4.tcp_syncookies", 0, 1),
+];
+
+/// Check requested sysctls against the allow-list, naming the first one
+/// that isn't allowed or is out of range
+pub fn validate(sysctls: &HashMap<String, String>) -> Result<()> {
+    let mut keys: Vec<_> = sysctls.keys().collect();
+    keys.sort();
+
+    for key in keys {
+        let Some((_, min, max)) = ALLOWED.iter().find(|(name, _, _)| name == key) else {
+            anyhow::bail!(
+                "sysctl {} is not allowed; allowed keys are {}",
+                key,
+                ALLOWED.iter().map(|(name, _, _)| *name).collect::<Vec<_>>().join(", ")
+            );
+        };
+        let value = &sysctls[key];
+        match value.trim().parse::<u64>() {
+            Ok(parsed) if (*min..=*max).contains(&parsed) => {}
+            _ => anyhow::bail!(
+                "sysctl {} must be an integer between {} and {}, got {:?}",
+                key,
+                min,
+                max,
+                value
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Sysctls as guest kernel command line arguments, for runtimes that boot a
+/// VM directly rather than from an OCI spec
+pub fn kernel_args(sysctls: &HashMap<String, String>) -> String {
+    let mut args: Vec<_> = sysctls
+        .iter()
+        .map(|(key, value)| format!("sysctl.{}={}", key, value.trim()))
+        .collect();
+    args.sort();
+    args.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sysctls(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn accepts_allow_listed_values_in_range() {
+        assert!(validate(&sysctls(&[
+            ("net.ipv4.ip_unprivileged_port_start", "80"),
+            ("fs.file-max", "65536"),
+        ]))
+        .is_ok());
+
+        let error = validate(&sysctls(&[("kernel.panic", "1")])).unwrap_err();
+        assert!(error.to_string().starts_with("sysctl kernel.panic is not allowed"));
+
+        let error = validate(&sysctls(&[("fs.file-max", "99999999")])).unwrap_err();
+        assert!(error.to_string().contains("between 1024 and 1048576"));
+        assert!(validate(&sysctls(&[("net.core.somaxconn", "lots")])).is_err());
+    }
+
+    #[test]
+    fn renders_kernel_args() {
+        let args = kernel_args(&sysctls(&[("net.core.somaxconn", "1024"), ("fs.file-max", "4096")]));
+        assert_eq!(args, "sysctl.fs.file-max=4096 sysctl.net.core.somaxconn=1024");
+    }
+}
@@ -1,7 +1,18 @@
 #[cfg(test)]
 mod tests {
-    use crate::runtime::{IsolationLevel, RuntimeRegistry, RuntimeType, SandboxConfig};
+    use crate::runtime::capacity::{CapacityConfig, Demand, HostCapacity};
+    use crate::runtime::fault::{FaultConfig, OperationFaults};
+    use crate::runtime::stats::RuntimeStats;
+    use crate::runtime::{
+        ExecLimits, ExecOptions, ExecSupport, ExecutionMode, ExitReason, IsolationLevel, OptimizationHint, RuntimeRegistry,
+        RuntimeType, SandboxConfig, SandboxResult, SandboxRuntime, SandboxSnapshot, SandboxStatus,
+        UnsupportedExecOptions,
+    };
+    use sandstorm_types::telemetry::ProviderStats;
+    use anyhow::Result;
+    use async_trait::async_trait;
     use std::collections::HashMap;
+    use std::sync::Arc;
     use uuid::Uuid;
 
     #[tokio::test]
@@ -46,6 +57,14 @@ mod tests {
             runtime_preference: Some(RuntimeType::Gvisor),
             working_dir: Some("/workspace".to_string()),
             mounts: vec![],
+            execution_mode: ExecutionMode::Standard,
+            scratch_size: None,
+            sysctls: HashMap::new(),
+            gvisor: Default::default(),
+            firecracker: Default::default(),
+            arch: None,
+            rootfs_layers: Vec::new(),
+            ttl_seconds: None,
         };
 
         assert_eq!(config.isolation_level, IsolationLevel::Standard);
@@ -77,4 +96,436 @@ mod tests {
         };
         assert_eq!(maximum_runtime, RuntimeType::Firecracker);
     }
-}
\ No newline at end of file
+
+    /// Configuration of a sandbox asking for placement
+    fn request(level: IsolationLevel, preference: Option<RuntimeType>, mode: ExecutionMode) -> SandboxConfig {
+        SandboxConfig {
+            id: Uuid::new_v4(),
+            image: "sandstorm/python".to_string(),
+            command: Vec::new(),
+            environment: HashMap::new(),
+            cpu_limit: None,
+            memory_limit: None,
+            timeout: None,
+            isolation_level: level,
+            runtime_preference: preference,
+            working_dir: None,
+            mounts: Vec::new(),
+            execution_mode: mode,
+            scratch_size: None,
+            sysctls: HashMap::new(),
+            gvisor: Default::default(),
+            firecracker: Default::default(),
+            arch: None,
+            rootfs_layers: Vec::new(),
+            ttl_seconds: None,
+        }
+    }
+
+    /// Runtime that only reports its type, remoteness and load
+    struct StubRuntime {
+        runtime_type: RuntimeType,
+        levels: Vec<IsolationLevel>,
+        remote: bool,
+        active: usize,
+    }
+
+    fn stub(runtime_type: RuntimeType, levels: &[IsolationLevel], remote: bool, active: usize) -> Arc<dyn SandboxRuntime> {
+        Arc::new(StubRuntime {
+            runtime_type,
+            levels: levels.to_vec(),
+            remote,
+            active,
+        })
+    }
+
+    #[async_trait]
+    impl SandboxRuntime for StubRuntime {
+        fn runtime_type(&self) -> RuntimeType {
+            self.runtime_type
+        }
+
+        fn supports_isolation_level(&self, level: IsolationLevel) -> bool {
+            self.levels.contains(&level)
+        }
+
+        fn supports_execution_mode(&self, mode: ExecutionMode) -> bool {
+            // Like the real runtimes, only local ones can run read-only
+            mode == ExecutionMode::Standard || !self.remote
+        }
+
+        fn supports_sysctls(&self) -> bool {
+            !self.remote
+        }
+
+        async fn create(&self, config: &SandboxConfig) -> Result<Uuid> {
+            Ok(config.id)
+        }
+
+        async fn exec(&self, _: Uuid, _: Vec<String>, _: Option<HashMap<String, String>>, _: &ExecOptions) -> Result<SandboxResult> {
+            anyhow::bail!("stub runtime does not run commands")
+        }
+
+        async fn destroy(&self, _: Uuid) -> Result<()> {
+            Ok(())
+        }
+
+        async fn snapshot(&self, _: Uuid) -> Result<SandboxSnapshot> {
+            anyhow::bail!("stub runtime takes no snapshots")
+        }
+
+        async fn resume(&self, _: &SandboxSnapshot) -> Result<Uuid> {
+            anyhow::bail!("stub runtime has no snapshots to resume")
+        }
+
+        async fn status(&self, _: Uuid) -> Result<SandboxStatus> {
+            anyhow::bail!("stub runtime does not track status")
+        }
+
+        async fn logs(&self, _: Uuid, _: bool) -> Result<Box<dyn tokio::io::AsyncRead + Send + Unpin>> {
+            anyhow::bail!("stub runtime keeps no logs")
+        }
+
+        async fn active_sandboxes(&self) -> usize {
+            self.active
+        }
+
+        fn is_remote(&self) -> bool {
+            self.remote
+        }
+    }
+
+    #[tokio::test]
+    async fn test_burst_to_remote_when_local_is_full() {
+        let registry = RuntimeRegistry::new().with_local_limit(Some(2));
+        registry
+            .register(stub(RuntimeType::Gvisor, &[IsolationLevel::Standard], false, 2))
+            .await
+            .unwrap();
+        registry
+            .register(stub(RuntimeType::Modal, &[IsolationLevel::Standard], true, 50))
+            .await
+            .unwrap();
+        registry
+            .register(stub(RuntimeType::E2b, &[IsolationLevel::Strong], true, 0))
+            .await
+            .unwrap();
+
+        // Local gVisor is at its limit, E2B can't do standard isolation
+        let runtime = registry.select_runtime(&request(IsolationLevel::Standard, None, ExecutionMode::Standard), None, Demand::default()).await.unwrap();
+        assert_eq!(runtime.runtime_type(), RuntimeType::Modal);
+
+        // Preferring the full local runtime still bursts
+        let runtime = registry
+            .select_runtime(&request(IsolationLevel::Standard, Some(RuntimeType::Gvisor), ExecutionMode::Standard), None, Demand::default())
+            .await
+            .unwrap();
+        assert_eq!(runtime.runtime_type(), RuntimeType::Modal);
+
+        // No local Kata runtime at all
+        let runtime = registry.select_runtime(&request(IsolationLevel::Strong, None, ExecutionMode::Standard), None, Demand::default()).await.unwrap();
+        assert_eq!(runtime.runtime_type(), RuntimeType::E2b);
+
+        assert!(registry.select_runtime(&request(IsolationLevel::Maximum, None, ExecutionMode::Standard), None, Demand::default()).await.is_err());
+
+        // Hosted providers can't apply sysctls
+        let mut tuned = request(IsolationLevel::Standard, None, ExecutionMode::Standard);
+        tuned.sysctls.insert("net.core.somaxconn".to_string(), "1024".to_string());
+        assert!(registry.select_runtime(&tuned, None, Demand::default()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_local_runtime_preferred_below_limit() {
+        let registry = RuntimeRegistry::new()
+            .with_local_limit(Some(2))
+            .with_burst_order(vec![RuntimeType::Daytona]);
+        registry
+            .register(stub(RuntimeType::Gvisor, &[IsolationLevel::Standard], false, 1))
+            .await
+            .unwrap();
+        registry
+            .register(stub(RuntimeType::Daytona, &[IsolationLevel::Standard], true, 0))
+            .await
+            .unwrap();
+        registry
+            .register(stub(RuntimeType::Modal, &[IsolationLevel::Standard], true, 0))
+            .await
+            .unwrap();
+
+        let runtime = registry.select_runtime(&request(IsolationLevel::Standard, None, ExecutionMode::Standard), None, Demand::default()).await.unwrap();
+        assert_eq!(runtime.runtime_type(), RuntimeType::Gvisor);
+
+        // Remote runtimes can still be requested explicitly
+        let runtime = registry
+            .select_runtime(&request(IsolationLevel::Standard, Some(RuntimeType::Modal), ExecutionMode::Standard), None, Demand::default())
+            .await
+            .unwrap();
+        assert_eq!(runtime.runtime_type(), RuntimeType::Modal);
+    }
+
+    #[tokio::test]
+    async fn test_read_only_requests_stay_local() {
+        let registry = RuntimeRegistry::new().with_local_limit(Some(1));
+        registry
+            .register(stub(RuntimeType::Gvisor, &[IsolationLevel::Standard], false, 0))
+            .await
+            .unwrap();
+        registry
+            .register(stub(RuntimeType::Kata, &[IsolationLevel::Strong], false, 1))
+            .await
+            .unwrap();
+        registry
+            .register(stub(RuntimeType::Modal, &[IsolationLevel::Standard, IsolationLevel::Strong], true, 0))
+            .await
+            .unwrap();
+
+        // A remote preference is ignored, since it can't enforce the mode
+        let runtime = registry
+            .select_runtime(&request(IsolationLevel::Standard, Some(RuntimeType::Modal), ExecutionMode::ReadOnly), None, Demand::default())
+            .await
+            .unwrap();
+        assert_eq!(runtime.runtime_type(), RuntimeType::Gvisor);
+
+        // Kata is full and read-only requests don't burst
+        assert!(registry
+            .select_runtime(&request(IsolationLevel::Strong, None, ExecutionMode::ReadOnly), None, Demand::default())
+            .await
+            .is_err());
+        let runtime = registry
+            .select_runtime(&request(IsolationLevel::Strong, None, ExecutionMode::Standard), None, Demand::default())
+            .await
+            .unwrap();
+        assert_eq!(runtime.runtime_type(), RuntimeType::Modal);
+    }
+
+    #[tokio::test]
+    async fn test_burst_when_host_is_out_of_memory() {
+        let host = HostCapacity::new(CapacityConfig {
+            host_cpus: Some(8.0),
+            host_memory_bytes: Some(4 << 30),
+            reserved_memory_bytes: 0,
+            min_free_disk_bytes: 0,
+            ..CapacityConfig::default()
+        });
+        let registry = RuntimeRegistry::new().with_host_capacity(host);
+        let gvisor = stub(RuntimeType::Gvisor, &[IsolationLevel::Standard], false, 0);
+        registry.register(gvisor.clone()).await.unwrap();
+        registry
+            .register(stub(RuntimeType::E2b, &[IsolationLevel::Standard], true, 0))
+            .await
+            .unwrap();
+
+        let demand = registry.demand(Some(1.0), Some(3 << 30));
+        let runtime = registry.select_runtime(&request(IsolationLevel::Standard, None, ExecutionMode::Standard), None, demand).await.unwrap();
+        assert_eq!(runtime.runtime_type(), RuntimeType::Gvisor);
+        let first = Uuid::new_v4();
+        assert!(registry.reserve(first, &runtime, demand).await);
+
+        // Only 1 GiB left on the host
+        let runtime = registry.select_runtime(&request(IsolationLevel::Standard, None, ExecutionMode::Standard), None, demand).await.unwrap();
+        assert_eq!(runtime.runtime_type(), RuntimeType::E2b);
+        assert!(!registry.reserve(Uuid::new_v4(), &gvisor, demand).await);
+        assert!(!registry.has_room(RuntimeType::Gvisor, demand).await);
+
+        registry.release(first).await;
+        assert!(registry.has_room(RuntimeType::Gvisor, demand).await);
+        let report = registry.capacity_report().await;
+        assert_eq!(report.host.unwrap().sandboxes, 0);
+        assert_eq!(report.runtimes.len(), 2);
+    }
+
+    fn provider_stats(avg_cost: f64, avg_latency: f64) -> ProviderStats {
+        ProviderStats {
+            avg_latency,
+            avg_cost,
+            success_rate: 1.0,
+            total_runs: 50,
+            by_accelerator: Vec::new(),
+            security_incident_rate: 0.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_optimization_hint_uses_telemetry() {
+        let stats = Arc::new(RuntimeStats::new(None));
+        stats.record(RuntimeType::Gvisor, Some(provider_stats(0.002, 900.0))).await;
+        stats.record(RuntimeType::Daytona, Some(provider_stats(0.010, 200.0))).await;
+
+        let registry = RuntimeRegistry::new().with_stats(stats.clone());
+        registry
+            .register(stub(RuntimeType::Gvisor, &[IsolationLevel::Standard], false, 0))
+            .await
+            .unwrap();
+        registry
+            .register(stub(RuntimeType::Daytona, &[IsolationLevel::Standard], true, 0))
+            .await
+            .unwrap();
+        registry
+            .register(stub(RuntimeType::Modal, &[IsolationLevel::Standard], true, 0))
+            .await
+            .unwrap();
+
+        let cheapest = registry
+            .select_runtime(&request(IsolationLevel::Standard, None, ExecutionMode::Standard), Some(OptimizationHint::Cheapest), Demand::default())
+            .await
+            .unwrap();
+        assert_eq!(cheapest.runtime_type(), RuntimeType::Gvisor);
+
+        let fastest = registry
+            .select_runtime(&request(IsolationLevel::Standard, None, ExecutionMode::Standard), Some(OptimizationHint::Fastest), Demand::default())
+            .await
+            .unwrap();
+        assert_eq!(fastest.runtime_type(), RuntimeType::Daytona);
+
+        // Without telemetry for strong isolation, the static mapping applies
+        registry
+            .register(stub(RuntimeType::Kata, &[IsolationLevel::Strong], false, 0))
+            .await
+            .unwrap();
+        let runtime = registry
+            .select_runtime(&request(IsolationLevel::Strong, None, ExecutionMode::Standard), Some(OptimizationHint::Fastest), Demand::default())
+            .await
+            .unwrap();
+        assert_eq!(runtime.runtime_type(), RuntimeType::Kata);
+    }
+
+    #[tokio::test]
+    async fn test_fault_injection_wraps_matching_runtimes() {
+        let registry = RuntimeRegistry::new().with_fault_injection(Some(FaultConfig {
+            runtimes: vec![RuntimeType::Gvisor],
+            create: OperationFaults {
+                failure_rate: 1.0,
+                ..Default::default()
+            },
+            seed: Some(7),
+            ..Default::default()
+        }));
+        registry
+            .register(stub(RuntimeType::Gvisor, &[IsolationLevel::Standard], false, 0))
+            .await
+            .unwrap();
+        registry
+            .register(stub(RuntimeType::Modal, &[IsolationLevel::Standard], true, 0))
+            .await
+            .unwrap();
+
+        let config = request(IsolationLevel::Standard, None, ExecutionMode::Standard);
+        let gvisor = registry.get(RuntimeType::Gvisor).await.unwrap();
+        let error = gvisor.create(&config).await.unwrap_err();
+        assert!(error.to_string().starts_with("injected fault: create"));
+        // Only the configured operations fail
+        gvisor.destroy(config.id).await.unwrap();
+
+        let modal = registry.get(RuntimeType::Modal).await.unwrap();
+        assert_eq!(modal.create(&config).await.unwrap(), config.id);
+    }
+
+    #[tokio::test]
+    async fn test_mock_runtime_stands_in_for_missing_local_runtimes() {
+        use crate::runtime::mock::{MockBehavior, MockRuntime};
+
+        let registry = RuntimeRegistry::new();
+        registry
+            .register(Arc::new(MockRuntime::new(MockBehavior::default())))
+            .await
+            .unwrap();
+        registry
+            .register(stub(RuntimeType::Kata, &[IsolationLevel::Strong], false, 0))
+            .await
+            .unwrap();
+
+        let runtime = registry.select_runtime(&request(IsolationLevel::Maximum, None, ExecutionMode::ReadOnly), None, Demand::default()).await.unwrap();
+        assert_eq!(runtime.runtime_type(), RuntimeType::Mock);
+
+        // Installed runtimes still take their own isolation level
+        let runtime = registry.select_runtime(&request(IsolationLevel::Strong, None, ExecutionMode::Standard), None, Demand::default()).await.unwrap();
+        assert_eq!(runtime.runtime_type(), RuntimeType::Kata);
+    }
+
+    #[tokio::test]
+    async fn test_sandboxes_are_found_on_the_runtime_hosting_them() {
+        let registry = RuntimeRegistry::new();
+        registry
+            .register(stub(RuntimeType::Gvisor, &[IsolationLevel::Standard], false, 0))
+            .await
+            .unwrap();
+        registry
+            .register(stub(RuntimeType::Kata, &[IsolationLevel::Strong], false, 0))
+            .await
+            .unwrap();
+
+        let kata = registry.get(RuntimeType::Kata).await.unwrap();
+        let config = request(IsolationLevel::Strong, None, ExecutionMode::Standard);
+        let sandbox_id = kata.create(&config).await.unwrap();
+        let owner = registry.runtime_of(sandbox_id).await.unwrap();
+        assert_eq!(owner.runtime_type(), RuntimeType::Kata);
+        assert!(registry.runtime_of(Uuid::new_v4()).await.is_none());
+
+        kata.destroy(sandbox_id).await.unwrap();
+        assert!(registry.runtime_of(sandbox_id).await.is_none());
+    }
+
+    #[test]
+    fn test_exec_options_validation() {
+        let options: ExecOptions = serde_json::from_str(r#"{"working_dir": "/srv", "user": "1000:1000", "timeout_ms": 500}"#).unwrap();
+        assert!(options.validate().is_ok());
+        assert!(!options.tty);
+
+        for invalid in [r#"{"working_dir": "srv"}"#, r#"{"user": "root"}"#, r#"{"user": "1000:"}"#, r#"{"timeout_ms": 0}"#] {
+            let options: ExecOptions = serde_json::from_str(invalid).unwrap();
+            assert!(options.validate().is_err(), "{} should be rejected", invalid);
+        }
+
+        let support = ExecSupport { working_dir: true, user: true, tty: false, limits: true, stdin: true };
+        assert!(options.check(RuntimeType::Gvisor, support).is_ok());
+        let tty = ExecOptions { tty: true, ..Default::default() };
+        let err = tty.check(RuntimeType::Gvisor, support).unwrap_err();
+        assert!(err.is::<UnsupportedExecOptions>());
+        assert!(ExecOptions::default().check(RuntimeType::Firecracker, ExecSupport::default()).is_ok());
+
+        let stdin: ExecOptions = serde_json::from_str(r#"{"stdin": "print(1)\n"}"#).unwrap();
+        assert!(stdin.check(RuntimeType::Gvisor, support).is_ok());
+        assert!(stdin.check(RuntimeType::Firecracker, ExecSupport::default()).is_err());
+    }
+
+    #[test]
+    fn test_exec_limits() {
+        let limits = ExecLimits {
+            cpu_seconds: Some(5),
+            memory_bytes: Some(64 * 1024 * 1024 + 1),
+            time_ms: Some(2500),
+        };
+        assert_eq!(
+            limits.wrap(vec!["python3".to_string(), "-c".to_string(), "print(1)".to_string()]),
+            [
+                "/bin/sh",
+                "-c",
+                "ulimit -t 5 && ulimit -v 65537 && exec timeout -k 1 2.500 \"$@\"",
+                "sandstorm-exec",
+                "python3",
+                "-c",
+                "print(1)",
+            ]
+        );
+        assert_eq!(ExecLimits::default().wrap(vec!["true".to_string()]), ["true"]);
+
+        // Limits can only be tighter than the sandbox's
+        let mut config = request(IsolationLevel::Standard, None, ExecutionMode::Standard);
+        assert!(limits.check(&config).is_ok());
+        config.memory_limit = Some(64 * 1024 * 1024);
+        let err = limits.check(&config).unwrap_err();
+        assert!(err.is::<UnsupportedExecOptions>());
+        config.memory_limit = None;
+        config.timeout = Some(10_000);
+        config.cpu_limit = Some(0.25);
+        assert!(limits.check(&config).is_err(), "10s at a quarter CPU is 3 CPU-seconds");
+
+        let options: ExecOptions = serde_json::from_str(r#"{"limits": {"time_ms": 0}}"#).unwrap();
+        assert!(options.validate().is_err());
+        let options = ExecOptions { limits: limits.clone(), ..Default::default() };
+        assert!(options.check(RuntimeType::Firecracker, ExecSupport::default()).is_err());
+
+        assert_eq!(limits.exit_reason(152, ExitReason::Crashed), ExitReason::Timeout);
+        assert_eq!(ExecLimits::default().exit_reason(152, ExitReason::Crashed), ExitReason::Crashed);
+    }
+}
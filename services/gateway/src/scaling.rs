@@ -0,0 +1,558 @@
+//! Warm pool scaling. The gateway keeps demand signals per pool template
+//! (a run's template snapshot, else its language): how many runs asked for
+//! it lately and how many are queued for capacity. A controller turns them
+//! into a target pool size within configured bounds, growing pools as soon
+//! as demand arrives and shrinking them only once it has stayed low for a
+//! cooldown. Pool managers report their pools back, which gives the
+//! utilization signal, and hear of new targets from a webhook.
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::metrics::GatewayMetrics;
+use crate::AppState;
+
+#[derive(Debug, Clone)]
+pub struct ScalingConfig {
+    /// Bounds for templates without their own
+    pub min_size: usize,
+    pub max_size: usize,
+    /// Per-template `(min, max)` bounds
+    pub bounds: HashMap<String, (usize, usize)>,
+    /// How far back run requests count as recent demand
+    pub window: Duration,
+    /// How far ahead a pool covers demand, about the time it takes to boot
+    /// a replacement for a sandbox handed out
+    pub lead: Duration,
+    pub scale_up_cooldown: Duration,
+    pub scale_down_cooldown: Duration,
+    /// How often targets are re-evaluated without new demand
+    pub interval: Duration,
+    /// Where target changes are posted
+    pub webhook_url: Option<String>,
+}
+
+impl Default for ScalingConfig {
+    fn default() -> Self {
+        Self {
+            min_size: 0,
+            max_size: 4,
+            bounds: HashMap::new(),
+            window: Duration::from_secs(300),
+            lead: Duration::from_secs(30),
+            scale_up_cooldown: Duration::from_secs(30),
+            scale_down_cooldown: Duration::from_secs(300),
+            interval: Duration::from_secs(15),
+            webhook_url: None,
+        }
+    }
+}
+
+impl ScalingConfig {
+    /// Settings from `GATEWAY_POOL_MIN_SIZE`, `GATEWAY_POOL_MAX_SIZE`,
+    /// `GATEWAY_POOL_BOUNDS` (`template=min:max,...`),
+    /// `GATEWAY_POOL_DEMAND_WINDOW_SECS`, `GATEWAY_POOL_LEAD_SECS`,
+    /// `GATEWAY_POOL_SCALE_UP_COOLDOWN_SECS`,
+    /// `GATEWAY_POOL_SCALE_DOWN_COOLDOWN_SECS`,
+    /// `GATEWAY_POOL_SCALING_INTERVAL_SECS` and
+    /// `GATEWAY_POOL_SCALING_WEBHOOK_URL`
+    pub fn from_env() -> Result<Self> {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            std::env::var(name).ok().and_then(|value| value.parse().ok())
+        }
+        fn secs(name: &str, default: Duration) -> Duration {
+            var(name).map(Duration::from_secs).unwrap_or(default)
+        }
+
+        let defaults = Self::default();
+        let config = Self {
+            min_size: var("GATEWAY_POOL_MIN_SIZE").unwrap_or(defaults.min_size),
+            max_size: var("GATEWAY_POOL_MAX_SIZE").unwrap_or(defaults.max_size),
+            bounds: parse_bounds(&std::env::var("GATEWAY_POOL_BOUNDS").unwrap_or_default())?,
+            window: secs("GATEWAY_POOL_DEMAND_WINDOW_SECS", defaults.window),
+            lead: secs("GATEWAY_POOL_LEAD_SECS", defaults.lead),
+            scale_up_cooldown: secs("GATEWAY_POOL_SCALE_UP_COOLDOWN_SECS", defaults.scale_up_cooldown),
+            scale_down_cooldown: secs(
+                "GATEWAY_POOL_SCALE_DOWN_COOLDOWN_SECS",
+                defaults.scale_down_cooldown,
+            ),
+            interval: secs("GATEWAY_POOL_SCALING_INTERVAL_SECS", defaults.interval),
+            webhook_url: std::env::var("GATEWAY_POOL_SCALING_WEBHOOK_URL").ok(),
+        };
+        if config.min_size > config.max_size {
+            anyhow::bail!(
+                "GATEWAY_POOL_MIN_SIZE {} is above GATEWAY_POOL_MAX_SIZE {}",
+                config.min_size,
+                config.max_size
+            );
+        }
+        if config.window.is_zero() {
+            anyhow::bail!("GATEWAY_POOL_DEMAND_WINDOW_SECS must be greater than 0");
+        }
+        Ok(config)
+    }
+
+    fn bounds(&self, template: &str) -> (usize, usize) {
+        self.bounds
+            .get(template)
+            .copied()
+            .unwrap_or((self.min_size, self.max_size))
+    }
+}
+
+/// Per-template bounds, `template=min:max` separated by commas
+fn parse_bounds(value: &str) -> Result<HashMap<String, (usize, usize)>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let parse = || {
+                let (template, range) = entry.split_once('=')?;
+                let (min, max) = range.split_once(':')?;
+                let (min, max) = (min.trim().parse().ok()?, max.trim().parse().ok()?);
+                (min <= max).then(|| (template.trim().to_string(), (min, max)))
+            };
+            parse().with_context(|| format!("invalid pool bounds {:?}, expected template=min:max", entry))
+        })
+        .collect()
+}
+
+/// What a pool manager reports about a template's pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolStatus {
+    /// Sandboxes the pool holds, handed out or not
+    pub size: usize,
+    /// Booted sandboxes waiting to be handed out
+    pub idle: usize,
+}
+
+impl PoolStatus {
+    /// Share of the pool handed out and not yet replaced
+    fn utilization(&self) -> Option<f64> {
+        (self.size > 0).then(|| self.size.saturating_sub(self.idle) as f64 / self.size as f64)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Signals {
+    /// When recent run requests arrived, oldest first
+    requests: VecDeque<Instant>,
+    queued: usize,
+    target: usize,
+    scaled_at: Option<Instant>,
+    pool: Option<PoolStatus>,
+}
+
+/// Scaling signals and target of one template's pool, served at
+/// `/v1/pools`
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolReport {
+    pub template: String,
+    pub demand_per_minute: f64,
+    /// Run requests waiting for capacity
+    pub queued: usize,
+    pub target: usize,
+    pub min_size: usize,
+    pub max_size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seconds_since_scaled: Option<u64>,
+    /// As last reported by its pool manager
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool: Option<PoolStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub utilization: Option<f64>,
+}
+
+/// A target change, posted to the scaling webhook
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolScaled {
+    pub template: String,
+    pub from: usize,
+    pub to: usize,
+    pub demand_per_minute: f64,
+    pub queued: usize,
+}
+
+#[derive(Debug)]
+pub struct PoolScaler {
+    config: ScalingConfig,
+    http: reqwest::Client,
+    templates: Mutex<HashMap<String, Signals>>,
+    /// Woken by run requests, so pools grow without waiting for the next
+    /// interval
+    demand: Notify,
+}
+
+impl PoolScaler {
+    pub fn new(config: ScalingConfig) -> Self {
+        // Templates with bounds of their own are kept at their minimum from
+        // the start
+        let templates = config
+            .bounds
+            .iter()
+            .map(|(template, (min, _))| {
+                let signals = Signals {
+                    target: *min,
+                    ..Default::default()
+                };
+                (template.clone(), signals)
+            })
+            .collect();
+        Self {
+            config,
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .unwrap_or_default(),
+            templates: Mutex::new(templates),
+            demand: Notify::new(),
+        }
+    }
+
+    pub fn from_env() -> Result<Self> {
+        Ok(Self::new(ScalingConfig::from_env()?))
+    }
+
+    fn signals<T>(&self, template: &str, f: impl FnOnce(&mut Signals) -> T) -> T {
+        let mut templates = self.templates.lock().unwrap_or_else(|e| e.into_inner());
+        let signals = templates.entry(template.to_string()).or_insert_with(|| Signals {
+            target: self.config.bounds(template).0,
+            ..Default::default()
+        });
+        f(signals)
+    }
+
+    /// Count a run request for `template` towards its demand
+    pub fn record_request(&self, template: &str) {
+        self.signals(template, |signals| signals.requests.push_back(Instant::now()));
+        self.demand.notify_one();
+    }
+
+    /// Count a request for `template` as queued for capacity until the
+    /// guard is dropped
+    pub fn queue(self: &Arc<Self>, template: &str) -> Queued {
+        self.signals(template, |signals| signals.queued += 1);
+        self.demand.notify_one();
+        Queued {
+            scaler: self.clone(),
+            template: template.to_string(),
+        }
+    }
+
+    /// Take in what a pool manager reports about its pool
+    pub fn report(&self, template: &str, status: PoolStatus) {
+        self.signals(template, |signals| signals.pool = Some(status));
+    }
+
+    /// Forget a pool its manager emptied, so the template can drop out
+    pub fn withdraw(&self, template: &str) {
+        let mut templates = self.templates.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(signals) = templates.get_mut(template) {
+            signals.pool = None;
+        }
+    }
+
+    fn describe(&self, template: &str, signals: &Signals, now: Instant) -> PoolReport {
+        let (min_size, max_size) = self.config.bounds(template);
+        let recent = signals
+            .requests
+            .iter()
+            .filter(|at| now.duration_since(**at) <= self.config.window)
+            .count();
+        PoolReport {
+            template: template.to_string(),
+            demand_per_minute: recent as f64 * 60.0 / self.config.window.as_secs_f64(),
+            queued: signals.queued,
+            target: signals.target,
+            min_size,
+            max_size,
+            seconds_since_scaled: signals.scaled_at.map(|at| now.duration_since(at).as_secs()),
+            pool: signals.pool,
+            utilization: signals.pool.and_then(|pool| pool.utilization()),
+        }
+    }
+
+    /// Re-evaluate every template's target, returning the reports, the
+    /// changes made and the templates dropped for lack of demand
+    fn evaluate(&self, now: Instant) -> (Vec<PoolReport>, Vec<PoolScaled>, Vec<String>) {
+        let mut templates = self.templates.lock().unwrap_or_else(|e| e.into_inner());
+        let mut reports = Vec::new();
+        let mut changes = Vec::new();
+        let mut forgotten = Vec::new();
+        templates.retain(|template, signals| {
+            while signals
+                .requests
+                .front()
+                .is_some_and(|at| now.duration_since(*at) > self.config.window)
+            {
+                signals.requests.pop_front();
+            }
+            let (min, max) = self.config.bounds(template);
+            let desired = desired_size(signals.requests.len(), signals.queued, &self.config, (min, max));
+            let target = next_target(signals.target, desired, signals.scaled_at, now, &self.config);
+            if target != signals.target {
+                let report = self.describe(template, signals, now);
+                changes.push(PoolScaled {
+                    template: template.clone(),
+                    from: signals.target,
+                    to: target,
+                    demand_per_minute: report.demand_per_minute,
+                    queued: signals.queued,
+                });
+                signals.target = target;
+                signals.scaled_at = Some(now);
+            }
+
+            // Templates nobody asks for any more are dropped once their
+            // pool is back at the minimum
+            let idle = signals.requests.is_empty()
+                && signals.queued == 0
+                && signals.target == min
+                && signals.pool.is_none()
+                && !self.config.bounds.contains_key(template);
+            if idle {
+                forgotten.push(template.clone());
+                return false;
+            }
+            reports.push(self.describe(template, signals, now));
+            true
+        });
+        reports.sort_by(|a, b| a.template.cmp(&b.template));
+        (reports, changes, forgotten)
+    }
+
+    /// Current signals and targets of every template, as of the last
+    /// evaluation
+    pub fn reports(&self) -> Vec<PoolReport> {
+        let templates = self.templates.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let mut reports: Vec<_> = templates
+            .iter()
+            .map(|(template, signals)| self.describe(template, signals, now))
+            .collect();
+        reports.sort_by(|a, b| a.template.cmp(&b.template));
+        reports
+    }
+
+    /// Post a target change to the webhook, if there is one. Failures are
+    /// only logged: the next change carries the current target anyway.
+    fn notify(&self, change: PoolScaled) {
+        info!(
+            template = %change.template,
+            from = change.from,
+            to = change.to,
+            demand_per_minute = change.demand_per_minute,
+            queued = change.queued,
+            "Warm pool target changed"
+        );
+        let Some(webhook_url) = self.config.webhook_url.clone() else {
+            return;
+        };
+        let http = self.http.clone();
+        tokio::spawn(async move {
+            let result = http
+                .post(&webhook_url)
+                .json(&change)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                warn!(template = %change.template, "Failed to post warm pool target: {}", e);
+            }
+        });
+    }
+}
+
+/// A request waiting for capacity, counted in its template's queue depth
+pub struct Queued {
+    scaler: Arc<PoolScaler>,
+    template: String,
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        self.scaler
+            .signals(&self.template, |signals| signals.queued = signals.queued.saturating_sub(1));
+    }
+}
+
+/// Pool size covering the demand expected over the lead time at the recent
+/// rate, plus what's already queued
+fn desired_size(
+    recent_requests: usize,
+    queued: usize,
+    config: &ScalingConfig,
+    (min, max): (usize, usize),
+) -> usize {
+    let rate = recent_requests as f64 / config.window.as_secs_f64();
+    let expected = (rate * config.lead.as_secs_f64()).ceil() as usize;
+    (expected + queued).clamp(min, max)
+}
+
+/// The target after moving towards `desired`, if the cooldown since the
+/// last change allows it
+fn next_target(
+    current: usize,
+    desired: usize,
+    scaled_at: Option<Instant>,
+    now: Instant,
+    config: &ScalingConfig,
+) -> usize {
+    let cooldown = match desired.cmp(&current) {
+        std::cmp::Ordering::Greater => config.scale_up_cooldown,
+        std::cmp::Ordering::Less => config.scale_down_cooldown,
+        std::cmp::Ordering::Equal => return current,
+    };
+    match scaled_at {
+        Some(at) if now.duration_since(at) < cooldown => current,
+        _ => desired,
+    }
+}
+
+/// Start the controller, which re-evaluates targets every interval and
+/// whenever a run is requested or queues
+pub fn spawn(scaler: Arc<PoolScaler>, metrics: GatewayMetrics) {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(scaler.config.interval) => {}
+                _ = scaler.demand.notified() => {}
+            }
+            let (reports, changes, forgotten) = scaler.evaluate(Instant::now());
+            for report in &reports {
+                metrics.pool_signals(report);
+            }
+            for template in &forgotten {
+                metrics.pool_forgotten(template);
+            }
+            for change in changes {
+                scaler.notify(change);
+            }
+        }
+    });
+}
+
+/// The pool template of a run: its template snapshot, else its language
+pub fn template_of(template_snapshot: Option<uuid::Uuid>, language: &str) -> String {
+    match template_snapshot {
+        Some(snapshot) => snapshot.to_string(),
+        None => language.to_string(),
+    }
+}
+
+pub async fn list_pools(State(state): State<AppState>) -> Json<Vec<PoolReport>> {
+    Json(state.pool_scaling.reports())
+}
+
+/// Where pool managers report a template's pool
+pub async fn report_pool(
+    State(state): State<AppState>,
+    Path(template): Path<String>,
+    Json(status): Json<PoolStatus>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if status.idle > status.size {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("idle {} is more than the pool's size {}", status.idle, status.size),
+        ));
+    }
+    state.pool_scaling.report(&template, status);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ScalingConfig {
+        ScalingConfig {
+            min_size: 1,
+            max_size: 6,
+            window: Duration::from_secs(60),
+            lead: Duration::from_secs(30),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_bounds() {
+        let bounds = parse_bounds("python=1:8, node = 0:2,").unwrap();
+        assert_eq!(bounds["python"], (1, 8));
+        assert_eq!(bounds["node"], (0, 2));
+        assert!(parse_bounds("").unwrap().is_empty());
+        for invalid in ["python", "python=8:1", "python=a:2"] {
+            assert!(parse_bounds(invalid).is_err(), "{} should be rejected", invalid);
+        }
+    }
+
+    #[test]
+    fn sizes_pools_for_the_demand_over_the_lead_time() {
+        let config = config();
+        // 10 a minute is 5 over the 30s lead
+        assert_eq!(desired_size(10, 0, &config, (1, 6)), 5);
+        // Queued requests add to it, up to the maximum
+        assert_eq!(desired_size(10, 3, &config, (1, 6)), 6);
+        // A template nobody uses is kept at its minimum
+        assert_eq!(desired_size(0, 0, &config, (1, 6)), 1);
+        assert_eq!(desired_size(1, 0, &config, (0, 6)), 1);
+    }
+
+    #[test]
+    fn cooldowns_hold_targets() {
+        let config = config();
+        let scaled = Instant::now();
+        let scaled_at = Some(scaled);
+        let after = |secs| scaled + Duration::from_secs(secs);
+        // Up after 30s, down only after 300s
+        assert_eq!(next_target(2, 4, scaled_at, after(60), &config), 4);
+        assert_eq!(next_target(4, 2, scaled_at, after(60), &config), 4);
+        assert_eq!(next_target(4, 2, scaled_at, after(301), &config), 2);
+        assert_eq!(next_target(2, 4, scaled_at, after(10), &config), 2);
+        assert_eq!(next_target(0, 3, None, after(0), &config), 3);
+    }
+
+    #[test]
+    fn queued_requests_grow_the_pool_and_idle_templates_are_forgotten() {
+        let mut config = config();
+        config.bounds.insert("python".to_string(), (2, 4));
+        let scaler = Arc::new(PoolScaler::new(config));
+
+        let queued = scaler.queue("node");
+        scaler.record_request("node");
+        let (reports, changes, _) = scaler.evaluate(Instant::now());
+        let node = reports.iter().find(|report| report.template == "node").unwrap();
+        assert_eq!((node.queued, node.target), (1, 2));
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].from, changes[0].to), (1, 2));
+
+        // Configured templates are kept warm without any demand
+        let python = reports.iter().find(|report| report.template == "python").unwrap();
+        assert_eq!(python.target, 2);
+
+        // Once demand is gone and the cooldown has passed, node shrinks back
+        // and drops out
+        drop(queued);
+        let later = Instant::now() + Duration::from_secs(400);
+        let (reports, changes, forgotten) = scaler.evaluate(later);
+        assert_eq!((changes[0].from, changes[0].to), (2, 1));
+        assert_eq!(forgotten, ["node"]);
+        assert_eq!(reports.len(), 1);
+    }
+
+    #[test]
+    fn utilization_is_the_share_handed_out() {
+        assert_eq!(PoolStatus { size: 4, idle: 1 }.utilization(), Some(0.75));
+        assert_eq!(PoolStatus { size: 0, idle: 0 }.utilization(), None);
+    }
+}
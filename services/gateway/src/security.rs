@@ -0,0 +1,434 @@
+use sandstorm_http::CircuitBreaker;
+use sandstorm_types::sandbox::{ExecutionMode, RuntimeType, SandboxConfig};
+use sandstorm_types::security::{EventType, SecurityEvent, Severity};
+use serde_json::json;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::scan::{self, Finding, ScanAction};
+
+/// Provider recorded on events raised before a runtime has been chosen
+const GATEWAY_PROVIDER: &str = "gateway";
+
+/// Host paths that hand a sandbox control of the host when mounted, besides
+/// the root itself
+const SENSITIVE_PATHS: &[&str] = &[
+    "/boot", "/dev", "/etc", "/proc", "/root", "/run", "/sys", "/var/run", "/var/lib/docker",
+];
+
+/// Interpreters that give an exec an interactive foothold in the sandbox
+const SHELLS: &[&str] = &["ash", "bash", "csh", "dash", "fish", "ksh", "sh", "tcsh", "zsh"];
+
+/// Sends security events the gateway observes itself to the security
+/// monitor, so policies apply before Falco or eBPF attach to a sandbox
+#[derive(Debug, Clone)]
+pub struct SecurityReporter {
+    http: reqwest::Client,
+    monitor_url: Option<String>,
+    /// Whether events are reported for runs that don't choose themselves
+    enabled: bool,
+    breaker: Option<CircuitBreaker>,
+}
+
+impl SecurityReporter {
+    /// Events go to `GATEWAY_SECURITY_MONITOR_URL`; setting
+    /// `GATEWAY_SECURITY_EVENTS=false` turns reporting off for runs that
+    /// don't turn it on in their `observability` block
+    pub fn from_env() -> Self {
+        let enabled = std::env::var("GATEWAY_SECURITY_EVENTS")
+            .map(|value| !matches!(value.as_str(), "0" | "false" | "no" | "off"))
+            .unwrap_or(true);
+
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            monitor_url: std::env::var("GATEWAY_SECURITY_MONITOR_URL")
+                .ok()
+                .map(|url| url.trim_end_matches('/').to_string()),
+            enabled,
+            breaker: None,
+        }
+    }
+
+    /// Drop calls while the monitor is down instead of waiting on it
+    pub fn with_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Some(breaker);
+        self
+    }
+
+    /// Whether a monitor is configured to report to
+    pub fn configured(&self) -> bool {
+        self.monitor_url.is_some()
+    }
+
+    /// Monitor URL when events should go to it: per a run's own choice of
+    /// `monitoring`, else per `GATEWAY_SECURITY_EVENTS`
+    fn monitor_for(&self, monitoring: Option<bool>) -> Option<String> {
+        self.monitor_url
+            .clone()
+            .filter(|_| monitoring.unwrap_or(self.enabled))
+    }
+
+    /// Post an event to the monitor's ingest API in the background, unless
+    /// the run it's about turned `monitoring` off
+    pub fn report(&self, event: SecurityEvent, monitoring: Option<bool>) {
+        let Some(monitor_url) = self.monitor_for(monitoring) else {
+            return;
+        };
+        let request = self.http.post(format!("{}/api/events", monitor_url)).json(&event);
+        let breaker = self.breaker.clone();
+
+        tokio::spawn(async move {
+            let result = send(breaker, request).await;
+
+            match result {
+                Ok(_) => debug!(
+                    event_id = %event.id,
+                    sandbox_id = %event.sandbox_id,
+                    event_type = %event.event_type,
+                    "Security event reported"
+                ),
+                Err(e) => warn!("Failed to report security event {}: {:#}", event.id, e),
+            }
+        });
+    }
+
+    /// Ask the monitor to watch a sandbox whose run turned monitoring on,
+    /// in the background
+    pub fn sandbox_started(&self, sandbox_id: Uuid, provider: String, run_id: Uuid) {
+        let Some(monitor_url) = self.monitor_for(Some(true)) else {
+            return;
+        };
+        let request = self
+            .http
+            .post(format!("{}/api/monitor/sandbox/{}/start", monitor_url, sandbox_id))
+            .json(&json!({ "provider": provider, "run_id": run_id }));
+        let breaker = self.breaker.clone();
+
+        tokio::spawn(async move {
+            let result = send(breaker, request).await;
+
+            match result {
+                Ok(_) => debug!(%sandbox_id, "Security monitor watching sandbox"),
+                Err(e) => warn!("Failed to start security monitoring of sandbox {}: {:#}", sandbox_id, e),
+            }
+        });
+    }
+
+    /// Tell the monitor a sandbox is gone, in the background, so it stops
+    /// watching it
+    pub fn sandbox_destroyed(&self, sandbox_id: Uuid, monitoring: Option<bool>) {
+        let Some(monitor_url) = self.monitor_for(monitoring) else {
+            return;
+        };
+        let request = self
+            .http
+            .post(format!("{}/api/monitor/sandbox/{}/stop", monitor_url, sandbox_id));
+        let breaker = self.breaker.clone();
+
+        tokio::spawn(async move {
+            let result = send(breaker, request).await;
+
+            match result {
+                Ok(_) => debug!(%sandbox_id, "Security monitor told of destroyed sandbox"),
+                Err(e) => warn!("Failed to tell the security monitor sandbox {} is gone: {:#}", sandbox_id, e),
+            }
+        });
+    }
+}
+
+/// Send a request to the monitor, through its breaker if there is one
+async fn send(breaker: Option<CircuitBreaker>, request: reqwest::RequestBuilder) -> anyhow::Result<()> {
+    let call = async {
+        request.send().await?.error_for_status()?;
+        Ok(())
+    };
+    match breaker {
+        Some(breaker) => breaker.call(call, crate::service_failed).await,
+        None => call.await,
+    }
+}
+
+fn event(
+    event_type: EventType,
+    severity: Severity,
+    sandbox_id: Uuid,
+    provider: String,
+    run_id: Option<Uuid>,
+    message: String,
+    details: serde_json::Value,
+) -> SecurityEvent {
+    SecurityEvent {
+        id: Uuid::new_v4().to_string(),
+        event_type,
+        severity,
+        timestamp: chrono::Utc::now(),
+        sandbox_id: sandbox_id.to_string(),
+        provider,
+        message,
+        details,
+        metadata: Some(json!({ "source": "gateway" })),
+        falco_rule: None,
+        ebpf_trace: None,
+        run_id,
+        occurrences: 1,
+        last_seen: None,
+    }
+}
+
+/// Name a runtime goes by in events and vault records, e.g. `gvisor`
+pub fn provider_name(runtime_type: RuntimeType) -> String {
+    serde_json::to_value(runtime_type)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Whether a host path is, or lies under, one of the sensitive paths
+fn is_sensitive(source: &str) -> bool {
+    let source = source.trim_end_matches('/');
+    source.is_empty()
+        || SENSITIVE_PATHS
+            .iter()
+            .any(|path| source == *path || source.starts_with(&format!("{}/", path)))
+}
+
+/// Whether a host mount is writable, or exposes a sensitive path or socket
+pub fn is_privileged_mount(source: &str, read_only: bool) -> bool {
+    !read_only || is_sensitive(source) || is_socket(source)
+}
+
+fn is_socket(source: &str) -> bool {
+    source.ends_with(".sock") || source.ends_with(".socket")
+}
+
+/// Reason a sandbox breaks its network policy, checked before it is created.
+/// Read-only sandboxes have no network, so handing them a host socket would
+/// give them a way out anyway.
+pub fn network_violation(config: &SandboxConfig, run_id: Option<Uuid>) -> Option<SecurityEvent> {
+    if config.execution_mode != ExecutionMode::ReadOnly {
+        return None;
+    }
+    let sockets: Vec<_> = config
+        .mounts
+        .iter()
+        .filter(|mount| is_socket(&mount.source))
+        .map(|mount| mount.source.clone())
+        .collect();
+    if sockets.is_empty() {
+        return None;
+    }
+
+    Some(event(
+        EventType::PolicyViolation,
+        Severity::High,
+        config.id,
+        config.runtime_preference.map(provider_name).unwrap_or_else(|| GATEWAY_PROVIDER.to_string()),
+        run_id,
+        format!(
+            "Read-only sandbox requested host socket mounts: {}",
+            sockets.join(", ")
+        ),
+        json!({
+            "policy": "network",
+            "execution_mode": config.execution_mode,
+            "sockets": sockets,
+        }),
+    ))
+}
+
+/// Event for a sandbox created with writable or sensitive host mounts
+pub fn privileged_mounts(
+    sandbox_id: Uuid,
+    runtime_type: RuntimeType,
+    run_id: Option<Uuid>,
+    config: &SandboxConfig,
+) -> Option<SecurityEvent> {
+    let flagged: Vec<_> = config
+        .mounts
+        .iter()
+        .filter(|mount| is_privileged_mount(&mount.source, mount.read_only))
+        .collect();
+    if flagged.is_empty() {
+        return None;
+    }
+
+    let severity = if flagged
+        .iter()
+        .any(|mount| is_sensitive(&mount.source) || is_socket(&mount.source))
+    {
+        Severity::High
+    } else {
+        Severity::Medium
+    };
+    Some(event(
+        EventType::PrivilegeEscalation,
+        severity,
+        sandbox_id,
+        provider_name(runtime_type),
+        run_id,
+        format!("Sandbox created with {} privileged host mount(s)", flagged.len()),
+        json!({
+            "mounts": flagged
+                .iter()
+                .map(|mount| json!({
+                    "source": mount.source,
+                    "destination": mount.destination,
+                    "read_only": mount.read_only,
+                }))
+                .collect::<Vec<_>>(),
+        }),
+    ))
+}
+
+/// Event for code the admission scan flagged; a policy violation when the
+/// run was blocked
+pub fn code_scan(
+    sandbox_id: Uuid,
+    run_id: Option<Uuid>,
+    language: &str,
+    findings: &[Finding],
+) -> Option<SecurityEvent> {
+    let severity = findings.iter().map(|finding| finding.severity).max()?;
+    let blocked = scan::verdict(findings) == Some(ScanAction::Block);
+    let event_type = if blocked {
+        EventType::PolicyViolation
+    } else {
+        EventType::SuspiciousBehavior
+    };
+    let mut rules: Vec<_> = findings.iter().map(|finding| finding.rule.as_str()).collect();
+    rules.sort_unstable();
+    rules.dedup();
+
+    Some(event(
+        event_type,
+        severity,
+        sandbox_id,
+        GATEWAY_PROVIDER.to_string(),
+        run_id,
+        format!(
+            "Submitted {} code {} scan rules: {}",
+            language,
+            if blocked { "blocked by" } else { "matched" },
+            rules.join(", ")
+        ),
+        json!({
+            "policy": "code_scan",
+            "language": language,
+            "blocked": blocked,
+            "findings": findings,
+        }),
+    ))
+}
+
+/// Event for an exec that starts a shell
+pub fn shell_exec(
+    sandbox_id: Uuid,
+    runtime_type: RuntimeType,
+    run_id: Option<Uuid>,
+    command: &[String],
+    exit_code: i32,
+) -> Option<SecurityEvent> {
+    let binary = command.first()?;
+    let name = binary.rsplit('/').next().unwrap_or(binary);
+    if !SHELLS.contains(&name) {
+        return None;
+    }
+
+    Some(event(
+        EventType::ProcessSpawn,
+        Severity::Medium,
+        sandbox_id,
+        provider_name(runtime_type),
+        run_id,
+        format!("Shell {} executed in sandbox", binary),
+        json!({
+            "executable": binary,
+            "command": command,
+            "exit_code": exit_code,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sandstorm_types::sandbox::{IsolationLevel, Mount};
+    use std::collections::HashMap;
+
+    fn config(mode: ExecutionMode, mounts: &[(&str, bool)]) -> SandboxConfig {
+        SandboxConfig {
+            id: Uuid::new_v4(),
+            image: "sandstorm/python".to_string(),
+            command: Vec::new(),
+            environment: HashMap::new(),
+            cpu_limit: None,
+            memory_limit: None,
+            timeout: None,
+            isolation_level: IsolationLevel::Standard,
+            runtime_preference: None,
+            working_dir: None,
+            mounts: mounts
+                .iter()
+                .map(|(source, read_only)| Mount {
+                    source: source.to_string(),
+                    destination: "/mnt".to_string(),
+                    read_only: *read_only,
+                })
+                .collect(),
+            execution_mode: mode,
+            scratch_size: None,
+            sysctls: HashMap::new(),
+            gvisor: Default::default(),
+            firecracker: Default::default(),
+            arch: None,
+            rootfs_layers: Vec::new(),
+            ttl_seconds: None,
+        }
+    }
+
+    #[test]
+    fn flags_privileged_mounts() {
+        let id = Uuid::new_v4();
+        let safe = config(ExecutionMode::Standard, &[("/data/models", true)]);
+        assert!(privileged_mounts(id, RuntimeType::Gvisor, None, &safe).is_none());
+
+        let writable = config(ExecutionMode::Standard, &[("/data/out", false)]);
+        let event = privileged_mounts(id, RuntimeType::Gvisor, None, &writable).unwrap();
+        assert_eq!(event.severity, Severity::Medium);
+        assert_eq!(event.provider, "gvisor");
+
+        let host = config(ExecutionMode::Standard, &[("/etc/", true), ("/data", true)]);
+        let event = privileged_mounts(id, RuntimeType::Kata, None, &host).unwrap();
+        assert_eq!(event.severity, Severity::High);
+        assert_eq!(event.details["mounts"].as_array().unwrap().len(), 1);
+
+        assert!(!is_sensitive("/etcetera"));
+        assert!(is_sensitive("/var/run/docker.sock"));
+    }
+
+    #[test]
+    fn flags_shell_execs() {
+        let id = Uuid::new_v4();
+        let shell = vec!["/bin/bash".to_string(), "-c".to_string(), "id".to_string()];
+        let event = shell_exec(id, RuntimeType::Firecracker, None, &shell, 0).unwrap();
+        assert_eq!(event.event_type, EventType::ProcessSpawn);
+
+        let python = vec!["python3".to_string(), "main.py".to_string()];
+        assert!(shell_exec(id, RuntimeType::Firecracker, None, &python, 0).is_none());
+        assert!(shell_exec(id, RuntimeType::Firecracker, None, &[], 0).is_none());
+    }
+
+    #[test]
+    fn host_sockets_break_read_only_network_policy() {
+        let mounts = [("/var/run/docker.sock", true)];
+        assert!(network_violation(&config(ExecutionMode::Standard, &mounts), None).is_none());
+
+        let event = network_violation(&config(ExecutionMode::ReadOnly, &mounts), None).unwrap();
+        assert_eq!(event.event_type, EventType::PolicyViolation);
+        assert_eq!(event.provider, "gateway");
+        assert_eq!(event.details["policy"], "network");
+    }
+}
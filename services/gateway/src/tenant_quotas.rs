@@ -0,0 +1,295 @@
+//! Limits on what each tenant has running at once: sandboxes, vCPUs and
+//! memory. A sandbox is charged what host capacity accounting charges it,
+//! its own limits or the defaults, from when it's admitted until it's
+//! destroyed, including while it's preempted and waiting to resume. Unlike
+//! the monthly quotas in [`crate::quota`], these are enforced by the gateway
+//! alone and free up as soon as sandboxes go.
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::ownership::tenant_from_headers;
+use crate::quota::DEFAULT_TENANT;
+use crate::runtime::capacity::Demand;
+use crate::AppState;
+
+const MIB: u64 = 1024 * 1024;
+
+/// A tenant's limits; `None` is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct TenantLimits {
+    pub sandboxes: Option<usize>,
+    pub cpus: Option<f64>,
+    pub memory_bytes: Option<u64>,
+}
+
+/// What a tenant has running
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct TenantUsage {
+    pub sandboxes: usize,
+    pub cpus: f64,
+    pub memory_bytes: u64,
+}
+
+/// A tenant's limits, what it uses of them and what's left
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TenantCapacity {
+    pub tenant: String,
+    pub limits: TenantLimits,
+    pub used: TenantUsage,
+    /// `None` where the tenant is unlimited
+    pub remaining: TenantLimits,
+}
+
+/// A sandbox that would take a tenant over one of its limits
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("Tenant {} is limited to {limit} {resource}, {used} in use and {requested} requested", .capacity.tenant)]
+pub struct QuotaExceeded {
+    /// `sandboxes`, `cpus` or `memory_bytes`
+    pub resource: &'static str,
+    pub limit: f64,
+    pub used: f64,
+    pub requested: f64,
+    pub capacity: TenantCapacity,
+}
+
+impl QuotaExceeded {
+    /// `403` for a sandbox larger than the limit itself, which no amount of
+    /// waiting lets in; `429` otherwise
+    pub fn status(&self) -> StatusCode {
+        if self.requested > self.limit {
+            StatusCode::FORBIDDEN
+        } else {
+            StatusCode::TOO_MANY_REQUESTS
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TenantQuotas {
+    defaults: TenantLimits,
+    tenants: HashMap<String, TenantLimits>,
+    /// Tenant and charge of each admitted sandbox
+    charges: Mutex<HashMap<Uuid, (String, Demand)>>,
+}
+
+impl TenantQuotas {
+    pub fn new(defaults: TenantLimits, tenants: HashMap<String, TenantLimits>) -> Self {
+        Self {
+            defaults,
+            tenants,
+            charges: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Limits for every tenant from `GATEWAY_TENANT_MAX_SANDBOXES`,
+    /// `GATEWAY_TENANT_MAX_CPUS` and `GATEWAY_TENANT_MAX_MEMORY_MB`, with
+    /// `GATEWAY_TENANT_QUOTAS` (`tenant=sandboxes:cpus:memory_mb,...`)
+    /// overriding them per tenant
+    pub fn from_env() -> Result<Self> {
+        fn var<T: std::str::FromStr>(name: &str) -> Result<Option<T>> {
+            std::env::var(name)
+                .ok()
+                .map(|value| value.parse().ok().with_context(|| format!("invalid {}", name)))
+                .transpose()
+        }
+
+        let defaults = TenantLimits {
+            sandboxes: var("GATEWAY_TENANT_MAX_SANDBOXES")?,
+            cpus: var("GATEWAY_TENANT_MAX_CPUS")?,
+            memory_bytes: var::<u64>("GATEWAY_TENANT_MAX_MEMORY_MB")?.map(|mb| mb * MIB),
+        };
+        let tenants = parse_tenants(&std::env::var("GATEWAY_TENANT_QUOTAS").unwrap_or_default(), defaults)?;
+        Ok(Self::new(defaults, tenants))
+    }
+
+    pub fn limits(&self, tenant: &str) -> TenantLimits {
+        self.tenants.get(tenant).copied().unwrap_or(self.defaults)
+    }
+
+    fn usage_in(charges: &HashMap<Uuid, (String, Demand)>, tenant: &str) -> TenantUsage {
+        charges
+            .values()
+            .filter(|(owner, _)| owner == tenant)
+            .fold(TenantUsage::default(), |used, (_, demand)| TenantUsage {
+                sandboxes: used.sandboxes + 1,
+                cpus: used.cpus + demand.cpus,
+                memory_bytes: used.memory_bytes + demand.memory_bytes,
+            })
+    }
+
+    fn capacity_of(&self, tenant: &str, used: TenantUsage) -> TenantCapacity {
+        let limits = self.limits(tenant);
+        TenantCapacity {
+            tenant: tenant.to_string(),
+            limits,
+            used,
+            remaining: TenantLimits {
+                sandboxes: limits.sandboxes.map(|limit| limit.saturating_sub(used.sandboxes)),
+                cpus: limits.cpus.map(|limit| (limit - used.cpus).max(0.0)),
+                memory_bytes: limits.memory_bytes.map(|limit| limit.saturating_sub(used.memory_bytes)),
+            },
+        }
+    }
+
+    pub fn capacity(&self, tenant: &str) -> TenantCapacity {
+        let charges = self.charges.lock().unwrap_or_else(|e| e.into_inner());
+        self.capacity_of(tenant, Self::usage_in(&charges, tenant))
+    }
+
+    /// Charge a tenant for a sandbox if it stays within its limits. `None`
+    /// is the default tenant.
+    pub fn reserve(&self, tenant: Option<&str>, key: Uuid, demand: Demand) -> Result<(), Box<QuotaExceeded>> {
+        let tenant = tenant.unwrap_or(DEFAULT_TENANT);
+        let mut charges = self.charges.lock().unwrap_or_else(|e| e.into_inner());
+        let used = Self::usage_in(&charges, tenant);
+        let limits = self.limits(tenant);
+        let exceeded = [
+            ("sandboxes", limits.sandboxes.map(|limit| limit as f64), used.sandboxes as f64, 1.0),
+            ("cpus", limits.cpus, used.cpus, demand.cpus),
+            (
+                "memory_bytes",
+                limits.memory_bytes.map(|limit| limit as f64),
+                used.memory_bytes as f64,
+                demand.memory_bytes as f64,
+            ),
+        ]
+        .into_iter()
+        .find_map(|(resource, limit, used, requested)| {
+            limit
+                .filter(|limit| used + requested > *limit)
+                .map(|limit| (resource, limit, used, requested))
+        });
+        if let Some((resource, limit, used_of, requested)) = exceeded {
+            return Err(Box::new(QuotaExceeded {
+                resource,
+                limit,
+                used: used_of,
+                requested,
+                capacity: self.capacity_of(tenant, used),
+            }));
+        }
+        charges.insert(key, (tenant.to_string(), demand));
+        Ok(())
+    }
+
+    /// Move a charge made before a sandbox had its final ID
+    pub fn rekey(&self, from: Uuid, to: Uuid) {
+        let mut charges = self.charges.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(charge) = charges.remove(&from) {
+            charges.insert(to, charge);
+        }
+    }
+
+    pub fn release(&self, key: Uuid) -> bool {
+        self.charges
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&key)
+            .is_some()
+    }
+}
+
+/// Per-tenant limits, `tenant=sandboxes:cpus:memory_mb` separated by
+/// commas. An empty field keeps the default for every tenant.
+fn parse_tenants(value: &str, defaults: TenantLimits) -> Result<HashMap<String, TenantLimits>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let parse = || {
+                let (tenant, limits) = entry.split_once('=')?;
+                let mut fields = limits.split(':').map(str::trim);
+                let (sandboxes, cpus, memory_mb) = (fields.next()?, fields.next()?, fields.next()?);
+                if fields.next().is_some() {
+                    return None;
+                }
+                fn field<T: std::str::FromStr>(value: &str, default: Option<T>) -> Option<Option<T>> {
+                    if value.is_empty() {
+                        Some(default)
+                    } else {
+                        value.parse().ok().map(Some)
+                    }
+                }
+                let limits = TenantLimits {
+                    sandboxes: field(sandboxes, defaults.sandboxes)?,
+                    cpus: field(cpus, defaults.cpus)?,
+                    memory_bytes: match memory_mb {
+                        "" => defaults.memory_bytes,
+                        mb => Some(mb.parse::<u64>().ok()? * MIB),
+                    },
+                };
+                Some((tenant.trim().to_string(), limits))
+            };
+            parse().with_context(|| {
+                format!("invalid tenant quota {:?}, expected tenant=sandboxes:cpus:memory_mb", entry)
+            })
+        })
+        .collect()
+}
+
+/// The calling tenant's limits on what it runs at once, and what's left
+pub async fn tenant_capacity(State(state): State<AppState>, headers: HeaderMap) -> Json<TenantCapacity> {
+    let tenant = tenant_from_headers(&headers).unwrap_or_else(|| DEFAULT_TENANT.to_string());
+    Json(state.tenant_quotas.capacity(&tenant))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn demand(cpus: f64, memory_mb: u64) -> Demand {
+        Demand {
+            cpus,
+            memory_bytes: memory_mb * MIB,
+        }
+    }
+
+    #[test]
+    fn tenants_are_held_to_their_limits() {
+        let defaults = TenantLimits {
+            sandboxes: Some(2),
+            cpus: Some(4.0),
+            memory_bytes: None,
+        };
+        let tenants = parse_tenants("acme=10::1024", defaults).unwrap();
+        let quotas = TenantQuotas::new(defaults, tenants);
+
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        quotas.reserve(None, first, demand(1.0, 512)).unwrap();
+        quotas.reserve(None, second, demand(1.0, 512)).unwrap();
+        let exceeded = quotas.reserve(None, Uuid::new_v4(), demand(1.0, 512)).unwrap_err();
+        assert_eq!(exceeded.resource, "sandboxes");
+        assert_eq!(exceeded.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(exceeded.capacity.remaining.sandboxes, Some(0));
+
+        // Destroyed sandboxes free their share, under whatever ID they ended up with
+        let sandbox_id = Uuid::new_v4();
+        quotas.rekey(second, sandbox_id);
+        assert!(quotas.release(sandbox_id));
+        assert_eq!(quotas.capacity(DEFAULT_TENANT).used.sandboxes, 1);
+
+        // Other tenants have their own limits, keeping defaults they don't set
+        let acme = Some("acme");
+        quotas.reserve(acme, Uuid::new_v4(), demand(2.0, 512)).unwrap();
+        let exceeded = quotas.reserve(acme, Uuid::new_v4(), demand(2.0, 2048)).unwrap_err();
+        assert_eq!(exceeded.resource, "memory_bytes");
+        assert_eq!(exceeded.status(), StatusCode::FORBIDDEN);
+        let capacity = quotas.capacity("acme");
+        assert_eq!(capacity.limits.cpus, Some(4.0));
+        assert_eq!(capacity.remaining.cpus, Some(2.0));
+        assert_eq!(capacity.remaining.memory_bytes, Some(512 * MIB));
+
+        assert!(parse_tenants("acme=1:2", defaults).is_err());
+        assert!(parse_tenants("acme=one::", defaults).is_err());
+    }
+}
@@ -0,0 +1,49 @@
+//! The gateway's snapshot vault client. Restores download snapshot blobs
+//! and recordings are uploaded through `sandstorm-vault-client`, which
+//! verifies and retries every chunk and, with a spool directory configured,
+//! keeps uploads on disk while the vault is unreachable.
+
+use anyhow::Result;
+use sandstorm_http::CircuitBreaker;
+use sandstorm_vault_client::Spool;
+use std::time::Duration;
+use tracing::info;
+
+pub use sandstorm_vault_client::{Blocked, VaultClient};
+
+/// Client for `GATEWAY_SNAPSHOT_VAULT_URL`, or `None` when it is unset.
+///
+/// - `GATEWAY_VAULT_DOWNLOAD_CONCURRENCY`: chunks transferred at once
+///   (default 8)
+/// - `GATEWAY_VAULT_SPOOL_DIR`: where uploads wait while the vault is
+///   unreachable; without it they fail
+/// - `GATEWAY_VAULT_SPOOL_REPLAY_SECS`: how often spooled uploads are
+///   retried (default 30)
+///
+/// Calls go through `breaker`. Starts replaying the spool in the
+/// background.
+pub async fn from_env(breaker: CircuitBreaker) -> Result<Option<VaultClient>> {
+    let Ok(url) = std::env::var("GATEWAY_SNAPSHOT_VAULT_URL") else {
+        return Ok(None);
+    };
+    let concurrency = std::env::var("GATEWAY_VAULT_DOWNLOAD_CONCURRENCY")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|&value: &usize| value > 0)
+        .unwrap_or(8);
+    let mut client = VaultClient::new(&url)
+        .with_concurrency(concurrency)
+        .with_breaker(breaker);
+
+    if let Ok(dir) = std::env::var("GATEWAY_VAULT_SPOOL_DIR") {
+        client = client.with_spool(Spool::open(&dir).await?);
+        let replay_secs = std::env::var("GATEWAY_VAULT_SPOOL_REPLAY_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|&value: &u64| value > 0)
+            .unwrap_or(30);
+        client.spawn_replay(Duration::from_secs(replay_secs));
+        info!("Spooling vault uploads to {} while the vault is unreachable", dir);
+    }
+    Ok(Some(client))
+}
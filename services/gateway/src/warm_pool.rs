@@ -0,0 +1,368 @@
+//! Warm sandbox pools. With `GATEWAY_WARM_POOL_RUNTIME` set, the gateway
+//! boots sandboxes for each language ahead of demand, as many as the
+//! scaling controller's target for the language, and starts a run in one
+//! of them instead of booting a sandbox for it. A warm sandbox runs nothing
+//! until a run hands it a command; pools refill in the background as
+//! sandboxes are handed out.
+
+use anyhow::{bail, Context, Result};
+use sandstorm_types::sandbox::{ExecutionMode, IsolationLevel, RuntimeType, SandboxConfig};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::runtime::{RuntimeRegistry, SandboxRuntime};
+use crate::scaling::{PoolScaler, PoolStatus};
+use crate::AppState;
+
+/// How often pools are checked against their targets between hand-outs
+const TICK: Duration = Duration::from_secs(5);
+
+/// How long a pool waits to boot again after a boot failed
+const BOOT_RETRY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct WarmPoolConfig {
+    /// Runtime warm sandboxes boot on
+    pub runtime: RuntimeType,
+    /// Isolation warm sandboxes boot with. Runs asking for no more than
+    /// this can take one.
+    pub isolation_level: IsolationLevel,
+}
+
+impl WarmPoolConfig {
+    /// Read `GATEWAY_WARM_POOL_RUNTIME`, without which no pools are kept,
+    /// and `GATEWAY_WARM_POOL_ISOLATION` (default `strong`)
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(runtime) = std::env::var("GATEWAY_WARM_POOL_RUNTIME") else {
+            return Ok(None);
+        };
+        let runtime = serde_json::from_value(serde_json::Value::String(runtime.clone()))
+            .with_context(|| format!("GATEWAY_WARM_POOL_RUNTIME: unknown runtime {:?}", runtime))?;
+        let isolation_level = match std::env::var("GATEWAY_WARM_POOL_ISOLATION") {
+            Ok(level) => serde_json::from_value(serde_json::Value::String(level.clone()))
+                .with_context(|| {
+                    format!("GATEWAY_WARM_POOL_ISOLATION: unknown isolation level {:?}", level)
+                })?,
+            Err(_) => IsolationLevel::Strong,
+        };
+        Ok(Some(Self {
+            runtime,
+            isolation_level,
+        }))
+    }
+
+    /// Configuration of a warm sandbox for a language, with no command
+    fn sandbox_config(&self, language: &str) -> SandboxConfig {
+        SandboxConfig {
+            id: Uuid::new_v4(),
+            image: format!("sandstorm/{}", language),
+            command: Vec::new(),
+            environment: HashMap::new(),
+            cpu_limit: None,
+            memory_limit: None,
+            timeout: None,
+            isolation_level: self.isolation_level,
+            runtime_preference: Some(self.runtime),
+            working_dir: Some("/workspace".to_string()),
+            mounts: Vec::new(),
+            execution_mode: ExecutionMode::Standard,
+            scratch_size: None,
+            sysctls: HashMap::new(),
+            gvisor: Default::default(),
+            firecracker: Default::default(),
+            arch: None,
+            rootfs_layers: Vec::new(),
+            ttl_seconds: None,
+        }
+    }
+
+    /// Whether a run can start in a warm sandbox of its image: it mustn't
+    /// ask for anything fixed when the sandbox booted
+    fn fits(&self, config: &SandboxConfig) -> bool {
+        config.runtime_preference.is_none_or(|runtime| runtime == self.runtime)
+            && strength(config.isolation_level) <= strength(self.isolation_level)
+            && config.cpu_limit.is_none()
+            && config.memory_limit.is_none()
+            && config.mounts.is_empty()
+            && config.execution_mode == ExecutionMode::Standard
+            && config.scratch_size.is_none()
+            && config.sysctls.is_empty()
+            && config.gvisor.is_empty()
+            && config.firecracker.is_empty()
+            && config.arch.is_none()
+            && config.rootfs_layers.is_empty()
+    }
+}
+
+fn strength(level: IsolationLevel) -> u8 {
+    match level {
+        IsolationLevel::Standard => 0,
+        IsolationLevel::Strong => 1,
+        IsolationLevel::Maximum => 2,
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pool {
+    /// Booted sandboxes waiting for a run, oldest first
+    idle: VecDeque<Uuid>,
+    booting: usize,
+    failed_at: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct WarmPools {
+    config: Option<WarmPoolConfig>,
+    /// Pools by language
+    pools: Mutex<HashMap<String, Pool>>,
+    /// Woken when a sandbox is handed out, so its pool refills right away
+    handed_out: Notify,
+}
+
+impl WarmPools {
+    pub fn new(config: Option<WarmPoolConfig>) -> Self {
+        Self {
+            config,
+            pools: Mutex::new(HashMap::new()),
+            handed_out: Notify::new(),
+        }
+    }
+
+    pub fn from_env() -> Result<Self> {
+        Ok(Self::new(WarmPoolConfig::from_env()?))
+    }
+
+    /// Start a run's command in a warm sandbox of its template, if the pool
+    /// has one and the run fits it. The sandbox keeps the host resources
+    /// reserved when it booted.
+    pub async fn start(
+        &self,
+        registry: &RuntimeRegistry,
+        template: &str,
+        config: &SandboxConfig,
+    ) -> Option<(Arc<dyn SandboxRuntime>, Uuid)> {
+        let pool_config = self.config.as_ref().filter(|pool| pool.fits(config))?;
+        let runtime = registry.get(pool_config.runtime).await.ok()?;
+        while let Some(sandbox_id) = self.take(template, &config.image) {
+            self.handed_out.notify_one();
+            match runtime.start_workload(sandbox_id, config).await {
+                Ok(()) => return Some((runtime, sandbox_id)),
+                Err(e) => {
+                    warn!(%sandbox_id, template, "Warm sandbox couldn't start its run: {:#}", e);
+                    discard(registry, &runtime, sandbox_id).await;
+                }
+            }
+        }
+        None
+    }
+
+    /// Take the oldest idle sandbox of a language's pool
+    fn take(&self, template: &str, image: &str) -> Option<Uuid> {
+        // Pools boot their language's image only
+        if image != format!("sandstorm/{}", template) {
+            return None;
+        }
+        self.pools.lock().unwrap().get_mut(template)?.idle.pop_front()
+    }
+
+    /// Bring every pool to its target: boot what's missing, destroy idle
+    /// sandboxes above target, and report each pool to the scaler
+    async fn refill(self: &Arc<Self>, registry: &Arc<RuntimeRegistry>, scaler: &PoolScaler) {
+        // Template snapshots are restored per run and can't be pooled
+        let targets: HashMap<String, usize> = scaler
+            .reports()
+            .into_iter()
+            .filter(|report| report.template.parse::<Uuid>().is_err())
+            .map(|report| (report.template, report.target))
+            .collect();
+
+        let mut boots = Vec::new();
+        let mut surplus = Vec::new();
+        {
+            let mut pools = self.pools.lock().unwrap();
+            for template in targets.keys() {
+                pools.entry(template.clone()).or_default();
+            }
+            pools.retain(|template, pool| {
+                let target = targets.get(template).copied().unwrap_or(0);
+                while pool.idle.len() > target {
+                    surplus.extend(pool.idle.pop_front());
+                }
+                let backing_off = pool.failed_at.is_some_and(|at| at.elapsed() < BOOT_RETRY);
+                if !backing_off {
+                    let missing = target.saturating_sub(pool.idle.len() + pool.booting);
+                    pool.booting += missing;
+                    boots.extend(std::iter::repeat_n(template.clone(), missing));
+                }
+
+                let status = PoolStatus {
+                    size: pool.idle.len() + pool.booting,
+                    idle: pool.idle.len(),
+                };
+                if status.size == 0 && target == 0 {
+                    scaler.withdraw(template);
+                    return false;
+                }
+                scaler.report(template, status);
+                true
+            });
+        }
+
+        for template in boots {
+            let (pools, registry) = (self.clone(), registry.clone());
+            tokio::spawn(async move { pools.boot(&registry, template).await });
+        }
+        if let Some(config) = &self.config {
+            if let Ok(runtime) = registry.get(config.runtime).await {
+                for sandbox_id in surplus {
+                    discard(registry, &runtime, sandbox_id).await;
+                }
+            }
+        }
+    }
+
+    /// Boot one sandbox into a language's pool
+    async fn boot(&self, registry: &RuntimeRegistry, template: String) {
+        let Some(pool_config) = &self.config else {
+            return;
+        };
+        let config = pool_config.sandbox_config(&template);
+        let booted = async {
+            let runtime = registry.get(pool_config.runtime).await?;
+            if !runtime.supports_warm_start() {
+                bail!("{:?} sandboxes can't be booted ahead of their runs", pool_config.runtime);
+            }
+            runtime.validate(&config)?;
+            let demand = registry.demand(None, None);
+            if !registry.has_room(pool_config.runtime, demand).await
+                || !registry.reserve(config.id, &runtime, demand).await
+            {
+                bail!("no host capacity for a warm sandbox");
+            }
+            match runtime.create(&config).await {
+                Ok(sandbox_id) => {
+                    registry.rekey(config.id, sandbox_id).await;
+                    Ok(sandbox_id)
+                }
+                Err(e) => {
+                    registry.release(config.id).await;
+                    Err(e)
+                }
+            }
+        }
+        .await;
+
+        let mut pools = self.pools.lock().unwrap();
+        let pool = pools.entry(template.clone()).or_default();
+        pool.booting -= 1;
+        match booted {
+            Ok(sandbox_id) => {
+                info!(%sandbox_id, template, "Booted warm sandbox");
+                pool.idle.push_back(sandbox_id);
+                pool.failed_at = None;
+            }
+            Err(e) => {
+                warn!(template, "Couldn't boot a warm sandbox: {:#}", e);
+                pool.failed_at = Some(Instant::now());
+            }
+        }
+    }
+}
+
+/// Destroy a warm sandbox and return its resources
+async fn discard(registry: &RuntimeRegistry, runtime: &Arc<dyn SandboxRuntime>, sandbox_id: Uuid) {
+    if let Err(e) = runtime.destroy(sandbox_id).await {
+        warn!(%sandbox_id, "Failed to destroy warm sandbox: {:#}", e);
+    }
+    registry.release(sandbox_id).await;
+}
+
+/// Start the loop that keeps pools at their targets
+pub fn spawn(state: AppState) {
+    if state.warm_pools.config.is_none() {
+        return;
+    }
+    tokio::spawn(async move {
+        loop {
+            state
+                .warm_pools
+                .refill(&state.runtime_registry, &state.pool_scaling)
+                .await;
+            tokio::select! {
+                _ = tokio::time::sleep(TICK) => {}
+                _ = state.warm_pools.handed_out.notified() => {}
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::mock::{MockBehavior, MockRuntime};
+    use crate::runtime::SandboxState;
+    use crate::scaling::ScalingConfig;
+
+    fn run_config(language: &str) -> SandboxConfig {
+        let mut config = WarmPoolConfig {
+            runtime: RuntimeType::Mock,
+            isolation_level: IsolationLevel::Strong,
+        }
+        .sandbox_config(language);
+        config.command = vec!["python".to_string(), "-c".to_string(), "print(1)".to_string()];
+        config.environment.insert("SANDSTORM_MOCK_EXIT_CODE".to_string(), "3".to_string());
+        config.runtime_preference = None;
+        config.isolation_level = IsolationLevel::Standard;
+        config
+    }
+
+    #[tokio::test]
+    async fn hands_out_warm_sandboxes_to_fitting_runs_and_refills() {
+        let registry = Arc::new(RuntimeRegistry::new());
+        let runtime = MockRuntime::new(MockBehavior {
+            delay_ms: 0,
+            ..Default::default()
+        });
+        registry.register(Arc::new(runtime)).await.unwrap();
+        let scaler = PoolScaler::new(ScalingConfig {
+            min_size: 2,
+            max_size: 4,
+            ..Default::default()
+        });
+        scaler.record_request("python");
+        let pools = Arc::new(WarmPools::new(Some(WarmPoolConfig {
+            runtime: RuntimeType::Mock,
+            isolation_level: IsolationLevel::Strong,
+        })));
+
+        let idle = |pools: &WarmPools| pools.pools.lock().unwrap()["python"].idle.len();
+        pools.refill(&registry, &scaler).await;
+        while idle(&pools) < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // Runs asking for something fixed at boot get no warm sandbox
+        let mut limited = run_config("python");
+        limited.memory_limit = Some(256 * 1024 * 1024);
+        assert!(pools.start(&registry, "python", &limited).await.is_none());
+        assert!(pools.start(&registry, "node", &run_config("node")).await.is_none());
+
+        let (runtime, sandbox_id) = pools.start(&registry, "python", &run_config("python")).await.unwrap();
+        assert_eq!(idle(&pools), 1);
+        let status = runtime.status(sandbox_id).await.unwrap();
+        assert_eq!(status.state, SandboxState::Stopped);
+        assert_eq!(status.exit_code, Some(3));
+
+        pools.refill(&registry, &scaler).await;
+        while idle(&pools) < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        pools.refill(&registry, &scaler).await;
+        let report = scaler.reports().into_iter().find(|r| r.template == "python").unwrap();
+        assert_eq!(report.pool, Some(PoolStatus { size: 2, idle: 2 }));
+    }
+}
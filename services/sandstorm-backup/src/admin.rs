@@ -0,0 +1,176 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde_json::json;
+use tracing::error;
+
+use crate::{BackupError, Backups, Trigger};
+
+/// Backup routes: `GET/POST /backups`, `POST /backups/:id/verify` and
+/// `POST /backups/:id/restore`.
+///
+/// When `admin_token` is set every route requires
+/// `Authorization: Bearer <token>`.
+pub fn backup_router(backups: Backups, admin_token: Option<String>) -> Router {
+    Router::new()
+        .route("/backups", get(list_backups).post(create_backup))
+        .route("/backups/:id/verify", post(verify_backup))
+        .route("/backups/:id/restore", post(restore_backup))
+        .with_state(BackupState {
+            backups,
+            admin_token,
+        })
+}
+
+#[derive(Clone)]
+struct BackupState {
+    backups: Backups,
+    admin_token: Option<String>,
+}
+
+impl BackupState {
+    fn authorize(&self, headers: &HeaderMap) -> Result<(), StatusCode> {
+        let Some(token) = &self.admin_token else {
+            return Ok(());
+        };
+
+        let presented = headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        if presented == Some(token.as_str()) {
+            Ok(())
+        } else {
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+impl IntoResponse for BackupError {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            BackupError::NotFound(_) => StatusCode::NOT_FOUND,
+            BackupError::Integrity { .. } | BackupError::SchemaMismatch { .. } => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            BackupError::Other(e) => {
+                error!("Backup operation failed: {:#}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        (status, Json(json!({ "error": self.to_string() }))).into_response()
+    }
+}
+
+async fn list_backups(State(state): State<BackupState>, headers: HeaderMap) -> Response {
+    if let Err(status) = state.authorize(&headers) {
+        return status.into_response();
+    }
+    match state.backups.list().await {
+        Ok(manifests) => Json(manifests).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn create_backup(State(state): State<BackupState>, headers: HeaderMap) -> Response {
+    if let Err(status) = state.authorize(&headers) {
+        return status.into_response();
+    }
+    match state.backups.create(Trigger::Manual).await {
+        Ok(manifest) => (StatusCode::CREATED, Json(manifest)).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn verify_backup(
+    State(state): State<BackupState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(status) = state.authorize(&headers) {
+        return status.into_response();
+    }
+    match state.backups.verify(&id).await {
+        Ok((manifest, _)) => Json(manifest).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn restore_backup(
+    State(state): State<BackupState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(status) = state.authorize(&headers) {
+        return status.into_response();
+    }
+    match state.backups.restore(&id).await {
+        Ok(manifest) => Json(manifest).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BackupFiles, BackupSource, ObjectStore};
+    use axum::body::Body;
+    use axum::http::Request;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    struct Empty;
+
+    #[async_trait::async_trait]
+    impl BackupSource for Empty {
+        async fn export(&self) -> anyhow::Result<BackupFiles> {
+            Ok(BackupFiles::new())
+        }
+
+        async fn restore(&self, _files: &BackupFiles) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn requires_admin_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let backups = Backups::new(
+            "test",
+            Arc::new(Empty),
+            ObjectStore::Filesystem(dir.path().to_path_buf()),
+            3,
+        );
+        let app = backup_router(backups, Some("secret".to_string()));
+
+        let request = |token: Option<&str>| {
+            let mut builder = Request::builder().method("POST").uri("/backups");
+            if let Some(token) = token {
+                builder = builder.header("authorization", format!("Bearer {}", token));
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+        let response = app.clone().oneshot(request(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app.clone().oneshot(request(Some("secret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/backups/nope/restore")
+                    .header("authorization", "Bearer secret")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,449 @@
+//! Disaster-recovery backups of Sandstorm service state.
+//!
+//! A service describes its state as a [`BackupSource`]: a consistent export
+//! to a set of named files, and a restore from the same files. [`Backups`]
+//! writes each export to an [`ObjectStore`] with a manifest of file sizes and
+//! SHA-256 checksums, and checks every file against the manifest before
+//! anything is restored, so a damaged backup is refused rather than half
+//! applied.
+//!
+//! Objects are laid out as `<service>/<backup id>/manifest.json` and
+//! `<service>/<backup id>/<file>`, with an index of all backups at
+//! `<service>/index.json`.
+
+mod admin;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod store;
+
+pub use admin::backup_router;
+#[cfg(feature = "postgres")]
+pub use postgres::PgBackup;
+pub use store::ObjectStore;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+/// Files of one backup, by name
+pub type BackupFiles = BTreeMap<String, Vec<u8>>;
+
+/// State a service can export and restore
+#[async_trait]
+pub trait BackupSource: Send + Sync + 'static {
+    /// Export a consistent snapshot of the service's state
+    async fn export(&self) -> anyhow::Result<BackupFiles>;
+
+    /// Replace the service's state with an export
+    async fn restore(&self, files: &BackupFiles) -> anyhow::Result<()>;
+
+    /// Version of the layout the export is in. Backups are only restored
+    /// into a service on the same version.
+    async fn schema_version(&self) -> anyhow::Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+/// Why a backup was taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    Manual,
+    Scheduled,
+    /// Taken automatically before a restore replaced the state
+    PreRestore,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub name: String,
+    pub size_bytes: u64,
+    pub sha256: String,
+}
+
+/// Description of one backup, stored next to its files
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub id: String,
+    pub service: String,
+    pub created_at: DateTime<Utc>,
+    pub trigger: Trigger,
+    pub schema_version: Option<String>,
+    pub files: Vec<ManifestFile>,
+}
+
+impl Manifest {
+    pub fn size_bytes(&self) -> u64 {
+        self.files.iter().map(|file| file.size_bytes).sum()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    #[error("backup {0} not found")]
+    NotFound(String),
+    #[error("backup {id} failed verification: {reason}")]
+    Integrity { id: String, reason: String },
+    #[error("backup {id} has schema version {backup:?} but the service is on {current:?}")]
+    SchemaMismatch {
+        id: String,
+        backup: Option<String>,
+        current: Option<String>,
+    },
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Backups of one service in an object store
+#[derive(Clone)]
+pub struct Backups {
+    service: String,
+    source: Arc<dyn BackupSource>,
+    store: ObjectStore,
+    keep: usize,
+    /// Serialises writers of the index
+    lock: Arc<Mutex<()>>,
+}
+
+impl std::fmt::Debug for Backups {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Backups")
+            .field("service", &self.service)
+            .field("keep", &self.keep)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Backups {
+    /// Backups are kept until more than `keep` exist, oldest first
+    pub fn new(
+        service: &str,
+        source: Arc<dyn BackupSource>,
+        store: ObjectStore,
+        keep: usize,
+    ) -> Self {
+        Self {
+            service: service.to_string(),
+            source,
+            store,
+            keep: keep.max(1),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Backups configured by `<PREFIX>_BACKUP_URL` (and `_BACKUP_TOKEN`,
+    /// `_BACKUP_KEEP`), or `None` when no URL is set
+    pub fn from_env(
+        service: &str,
+        prefix: &str,
+        source: Arc<dyn BackupSource>,
+    ) -> anyhow::Result<Option<Self>> {
+        let var = |name: &str| std::env::var(format!("{}_BACKUP_{}", prefix, name)).ok();
+        let Some(url) = var("URL") else {
+            return Ok(None);
+        };
+        let store = ObjectStore::from_url(&url, var("TOKEN"))?;
+        let keep = var("KEEP")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(14);
+        Ok(Some(Self::new(service, source, store, keep)))
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    /// Export the service's state and upload it, pruning old backups
+    pub async fn create(&self, trigger: Trigger) -> Result<Manifest, BackupError> {
+        let _guard = self.lock.lock().await;
+        self.create_locked(trigger).await
+    }
+
+    async fn create_locked(&self, trigger: Trigger) -> Result<Manifest, BackupError> {
+        let created_at = Utc::now();
+        let id = format!(
+            "{}-{}",
+            created_at.format("%Y%m%dT%H%M%SZ"),
+            &uuid::Uuid::new_v4().simple().to_string()[..8]
+        );
+        let schema_version = self.source.schema_version().await?;
+        let files = self.source.export().await?;
+
+        let mut manifest = Manifest {
+            id: id.clone(),
+            service: self.service.clone(),
+            created_at,
+            trigger,
+            schema_version,
+            files: Vec::new(),
+        };
+        for (name, data) in files {
+            manifest.files.push(ManifestFile {
+                name: name.clone(),
+                size_bytes: data.len() as u64,
+                sha256: sha256(&data),
+            });
+            self.store.put(&self.file_key(&id, &name), data).await?;
+        }
+        // The manifest goes last: a backup without one was never completed
+        self.store
+            .put(
+                &self.manifest_key(&id),
+                serde_json::to_vec_pretty(&manifest).map_err(anyhow::Error::from)?,
+            )
+            .await?;
+
+        let mut index = self.read_index().await?;
+        index.push(manifest.clone());
+        let expired = index.len().saturating_sub(self.keep);
+        let pruned: Vec<_> = index.drain(..expired).collect();
+        self.write_index(&index).await?;
+        for old in pruned {
+            if let Err(e) = self.delete_objects(&old).await {
+                warn!(backup_id = %old.id, "Failed to delete expired backup: {}", e);
+            }
+        }
+
+        info!(
+            backup_id = %manifest.id,
+            trigger = ?trigger,
+            size_bytes = manifest.size_bytes(),
+            "Backup created"
+        );
+        Ok(manifest)
+    }
+
+    /// Backups in the store, oldest first
+    pub async fn list(&self) -> Result<Vec<Manifest>, BackupError> {
+        self.read_index().await
+    }
+
+    /// Download a backup and check every file against its manifest
+    pub async fn verify(&self, id: &str) -> Result<(Manifest, BackupFiles), BackupError> {
+        let manifest = self.manifest(id).await?;
+        let mut files = BackupFiles::new();
+        for file in &manifest.files {
+            let data = self
+                .store
+                .get(&self.file_key(id, &file.name))
+                .await?
+                .ok_or_else(|| integrity(id, format!("{} is missing", file.name)))?;
+            if data.len() as u64 != file.size_bytes {
+                return Err(integrity(
+                    id,
+                    format!(
+                        "{} is {} bytes, expected {}",
+                        file.name,
+                        data.len(),
+                        file.size_bytes
+                    ),
+                ));
+            }
+            if sha256(&data) != file.sha256 {
+                return Err(integrity(
+                    id,
+                    format!("{} does not match its checksum", file.name),
+                ));
+            }
+            files.insert(file.name.clone(), data);
+        }
+        Ok((manifest, files))
+    }
+
+    /// Verify a backup, then replace the service's state with it. The
+    /// current state is backed up first.
+    pub async fn restore(&self, id: &str) -> Result<Manifest, BackupError> {
+        let _guard = self.lock.lock().await;
+        let (manifest, files) = self.verify(id).await?;
+
+        let current = self.source.schema_version().await?;
+        if manifest.schema_version != current {
+            return Err(BackupError::SchemaMismatch {
+                id: id.to_string(),
+                backup: manifest.schema_version,
+                current,
+            });
+        }
+
+        let safety = self.create_locked(Trigger::PreRestore).await?;
+        self.source.restore(&files).await?;
+        warn!(
+            backup_id = %manifest.id,
+            pre_restore_backup_id = %safety.id,
+            "Service state restored from backup"
+        );
+        Ok(manifest)
+    }
+
+    async fn manifest(&self, id: &str) -> Result<Manifest, BackupError> {
+        let data = self
+            .store
+            .get(&self.manifest_key(id))
+            .await?
+            .ok_or_else(|| BackupError::NotFound(id.to_string()))?;
+        let manifest: Manifest = serde_json::from_slice(&data)
+            .map_err(|e| integrity(id, format!("unreadable manifest: {}", e)))?;
+        if manifest.id != id || manifest.service != self.service {
+            return Err(integrity(
+                id,
+                "manifest belongs to another backup".to_string(),
+            ));
+        }
+        Ok(manifest)
+    }
+
+    async fn read_index(&self) -> Result<Vec<Manifest>, BackupError> {
+        match self.store.get(&self.index_key()).await? {
+            Some(data) => Ok(serde_json::from_slice(&data).map_err(anyhow::Error::from)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn write_index(&self, index: &[Manifest]) -> Result<(), BackupError> {
+        let data = serde_json::to_vec_pretty(index).map_err(anyhow::Error::from)?;
+        Ok(self.store.put(&self.index_key(), data).await?)
+    }
+
+    async fn delete_objects(&self, manifest: &Manifest) -> anyhow::Result<()> {
+        self.store.delete(&self.manifest_key(&manifest.id)).await?;
+        for file in &manifest.files {
+            self.store
+                .delete(&self.file_key(&manifest.id, &file.name))
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn index_key(&self) -> String {
+        format!("{}/index.json", self.service)
+    }
+
+    fn manifest_key(&self, id: &str) -> String {
+        format!("{}/{}/manifest.json", self.service, id)
+    }
+
+    fn file_key(&self, id: &str, name: &str) -> String {
+        format!("{}/{}/{}", self.service, id, name)
+    }
+}
+
+fn integrity(id: &str, reason: String) -> BackupError {
+    BackupError::Integrity {
+        id: id.to_string(),
+        reason,
+    }
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Take a backup every `<PREFIX>_BACKUP_INTERVAL_SECS` (default daily); an
+/// interval of 0 turns scheduled backups off
+pub fn spawn_schedule(backups: Backups, prefix: &str) {
+    let interval = std::env::var(format!("{}_BACKUP_INTERVAL_SECS", prefix))
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(24 * 60 * 60);
+    if interval == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        // The first tick fires immediately; don't back up on every restart
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            if let Err(e) = backups.create(Trigger::Scheduled).await {
+                error!(service = %backups.service, "Scheduled backup failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Source holding its state in memory
+    #[derive(Default)]
+    struct MemorySource {
+        state: Mutex<BackupFiles>,
+    }
+
+    #[async_trait]
+    impl BackupSource for MemorySource {
+        async fn export(&self) -> anyhow::Result<BackupFiles> {
+            Ok(self.state.lock().await.clone())
+        }
+
+        async fn restore(&self, files: &BackupFiles) -> anyhow::Result<()> {
+            *self.state.lock().await = files.clone();
+            Ok(())
+        }
+    }
+
+    async fn set(source: &MemorySource, value: &str) {
+        *source.state.lock().await =
+            BackupFiles::from([("state.json".to_string(), value.as_bytes().to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn restores_verified_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = Arc::new(MemorySource::default());
+        let backups = Backups::new(
+            "collector",
+            source.clone(),
+            ObjectStore::Filesystem(dir.path().to_path_buf()),
+            2,
+        );
+
+        set(&source, "v1").await;
+        let first = backups.create(Trigger::Manual).await.unwrap();
+        set(&source, "v2").await;
+        backups.restore(&first.id).await.unwrap();
+        assert_eq!(source.export().await.unwrap()["state.json"], b"v1");
+
+        // The restore backed up v2 first, and only the newest two are kept
+        let listed = backups.list().await.unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[1].trigger, Trigger::PreRestore);
+        assert!(matches!(
+            backups.restore("missing").await,
+            Err(BackupError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn refuses_damaged_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = Arc::new(MemorySource::default());
+        let store = ObjectStore::Filesystem(dir.path().to_path_buf());
+        let backups = Backups::new("vault", source.clone(), store.clone(), 5);
+
+        set(&source, "good").await;
+        let manifest = backups.create(Trigger::Manual).await.unwrap();
+        store
+            .put(
+                &format!("vault/{}/state.json", manifest.id),
+                b"evil".to_vec(),
+            )
+            .await
+            .unwrap();
+
+        set(&source, "current").await;
+        assert!(matches!(
+            backups.restore(&manifest.id).await,
+            Err(BackupError::Integrity { .. })
+        ));
+        assert_eq!(source.export().await.unwrap()["state.json"], b"current");
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use sqlx::{PgPool, Row};
+
+use crate::{BackupFiles, BackupSource};
+
+/// Rows restored per statement
+const RESTORE_BATCH: usize = 1000;
+
+/// Postgres tables exported as JSON lines, one file per table
+pub struct PgBackup {
+    pool: PgPool,
+    tables: &'static [&'static str],
+}
+
+impl PgBackup {
+    /// `tables` are restored in order, so referenced tables must come before
+    /// the tables that reference them
+    pub fn new(pool: PgPool, tables: &'static [&'static str]) -> Self {
+        Self { pool, tables }
+    }
+}
+
+fn file_name(table: &str) -> String {
+    format!("{}.jsonl", table)
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[async_trait]
+impl BackupSource for PgBackup {
+    async fn export(&self) -> Result<BackupFiles> {
+        // One repeatable-read transaction sees every table at the same
+        // instant, without blocking writers
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .execute(&mut *tx)
+            .await?;
+
+        let mut files = BackupFiles::new();
+        for table in self.tables {
+            let rows = sqlx::query(&format!(
+                "SELECT row_to_json(backup_row.*)::text FROM {} backup_row",
+                quote(table)
+            ))
+            .fetch_all(&mut *tx)
+            .await
+            .with_context(|| format!("failed to export {}", table))?;
+
+            let mut data = Vec::new();
+            for row in rows {
+                data.extend_from_slice(row.try_get::<String, _>(0)?.as_bytes());
+                data.push(b'\n');
+            }
+            files.insert(file_name(table), data);
+        }
+        tx.commit().await?;
+        Ok(files)
+    }
+
+    async fn restore(&self, files: &BackupFiles) -> Result<()> {
+        for table in self.tables {
+            if !files.contains_key(&file_name(table)) {
+                bail!("backup has no data for table {}", table);
+            }
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let tables: Vec<_> = self.tables.iter().map(|table| quote(table)).collect();
+        sqlx::query(&format!("TRUNCATE {} CASCADE", tables.join(", ")))
+            .execute(&mut *tx)
+            .await?;
+
+        for table in self.tables {
+            let data = std::str::from_utf8(&files[&file_name(table)])
+                .with_context(|| format!("{} is not UTF-8", file_name(table)))?;
+            let lines: Vec<_> = data.lines().filter(|line| !line.is_empty()).collect();
+            for batch in lines.chunks(RESTORE_BATCH) {
+                sqlx::query(&format!(
+                    "INSERT INTO {0} SELECT * FROM json_populate_recordset(NULL::{0}, $1::json)",
+                    quote(table)
+                ))
+                .bind(format!("[{}]", batch.join(",")))
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("failed to restore {}", table))?;
+            }
+
+            // Serial columns continue after the restored rows
+            let serials = sqlx::query(
+                "SELECT column_name::text FROM information_schema.columns \
+                 WHERE table_schema = current_schema() AND table_name = $1 \
+                 AND column_default LIKE 'nextval(%'",
+            )
+            .bind(table)
+            .fetch_all(&mut *tx)
+            .await?;
+            for row in serials {
+                let column: String = row.try_get(0)?;
+                sqlx::query(&format!(
+                    "SELECT setval(pg_get_serial_sequence($1, $2), COALESCE(MAX({}), 0) + 1, false) FROM {}",
+                    quote(&column),
+                    quote(table)
+                ))
+                .bind(table)
+                .bind(&column)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Latest applied sqlx migration
+    async fn schema_version(&self) -> Result<Option<String>> {
+        let version: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(version.map(|version| version.to_string()))
+    }
+}
@@ -0,0 +1,153 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+
+/// Where backups are written: a local directory (`file:///var/backups`) or
+/// an HTTP object store that accepts `PUT`, `GET` and `DELETE` on object URLs
+/// (`https://backups.example.com/sandstorm`)
+#[derive(Debug, Clone)]
+pub enum ObjectStore {
+    Filesystem(PathBuf),
+    Http {
+        client: reqwest::Client,
+        base_url: String,
+        token: Option<String>,
+    },
+}
+
+impl ObjectStore {
+    /// Store for a `file://` or `http(s)://` URL; `token` is sent as a bearer
+    /// token to HTTP stores
+    pub fn from_url(url: &str, token: Option<String>) -> Result<Self> {
+        if let Some(path) = url.strip_prefix("file://") {
+            return Ok(Self::Filesystem(PathBuf::from(path)));
+        }
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(Self::Http {
+                client: reqwest::Client::builder()
+                    .timeout(std::time::Duration::from_secs(300))
+                    .build()?,
+                base_url: url.trim_end_matches('/').to_string(),
+                token,
+            });
+        }
+        bail!(
+            "backup URL must start with file://, http:// or https://, got {}",
+            url
+        )
+    }
+
+    pub async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        match self {
+            Self::Filesystem(root) => {
+                let path = object_path(root, key)?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
+                // Write aside and rename, so readers never see a partial object
+                let partial = path.with_extension("partial");
+                fs::write(&partial, data).await?;
+                fs::rename(&partial, &path).await?;
+            }
+            Self::Http { .. } => {
+                self.request(reqwest::Method::PUT, key)
+                    .body(data)
+                    .send()
+                    .await?
+                    .error_for_status()
+                    .with_context(|| format!("failed to upload {}", key))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Object contents, or `None` if there is no such object
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::Filesystem(root) => match fs::read(object_path(root, key)?).await {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            Self::Http { .. } => {
+                let response = self.request(reqwest::Method::GET, key).send().await?;
+                if response.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                let response = response
+                    .error_for_status()
+                    .with_context(|| format!("failed to download {}", key))?;
+                Ok(Some(response.bytes().await?.to_vec()))
+            }
+        }
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<()> {
+        match self {
+            Self::Filesystem(root) => match fs::remove_file(object_path(root, key)?).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            },
+            Self::Http { .. } => {
+                let response = self.request(reqwest::Method::DELETE, key).send().await?;
+                if response.status() != reqwest::StatusCode::NOT_FOUND {
+                    response
+                        .error_for_status()
+                        .with_context(|| format!("failed to delete {}", key))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, key: &str) -> reqwest::RequestBuilder {
+        let Self::Http {
+            client,
+            base_url,
+            token,
+        } = self
+        else {
+            unreachable!("only HTTP stores send requests");
+        };
+        let request = client.request(method, format!("{}/{}", base_url, key));
+        match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+/// Path of an object under a filesystem store, refusing keys that would
+/// escape it
+fn object_path(root: &Path, key: &str) -> Result<PathBuf> {
+    let relative = Path::new(key);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        bail!("invalid object key {}", key);
+    }
+    Ok(root.join(relative))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn filesystem_store_round_trips_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let store =
+            ObjectStore::from_url(&format!("file://{}", dir.path().display()), None).unwrap();
+
+        assert_eq!(store.get("svc/a.json").await.unwrap(), None);
+        store.put("svc/a.json", b"{}".to_vec()).await.unwrap();
+        assert_eq!(store.get("svc/a.json").await.unwrap(), Some(b"{}".to_vec()));
+        store.delete("svc/a.json").await.unwrap();
+        store.delete("svc/a.json").await.unwrap();
+        assert_eq!(store.get("svc/a.json").await.unwrap(), None);
+
+        assert!(store.put("../escape", Vec::new()).await.is_err());
+        assert!(ObjectStore::from_url("s3://bucket", None).is_err());
+    }
+}
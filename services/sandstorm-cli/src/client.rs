@@ -0,0 +1,93 @@
+use anyhow::{bail, Context, Result};
+use reqwest::{Client, Method, RequestBuilder, Response};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// HTTP client bound to a single service base URL
+pub struct ServiceClient {
+    http: Client,
+    base_url: String,
+    service: &'static str,
+}
+
+impl ServiceClient {
+    pub fn new(http: Client, base_url: &str, service: &'static str) -> Self {
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            service,
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}{}", self.base_url, path))
+    }
+
+    /// Send a request and fail with the service's error body on non-2xx
+    pub async fn send(&self, builder: RequestBuilder) -> Result<Response> {
+        let response = builder
+            .send()
+            .await
+            .with_context(|| format!("failed to reach {} at {}", self.service, self.base_url))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            if body.is_empty() {
+                bail!("{} returned {}", self.service, status);
+            }
+            bail!("{} returned {}: {}", self.service, status, body);
+        }
+
+        Ok(response)
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        self.get_with_query(path, &()).await
+    }
+
+    pub async fn get_with_query<T: DeserializeOwned, Q: Serialize + ?Sized>(
+        &self,
+        path: &str,
+        query: &Q,
+    ) -> Result<T> {
+        let response = self
+            .send(self.request(Method::GET, path).query(query))
+            .await?;
+        Ok(response.json().await?)
+    }
+
+    pub async fn post<T: DeserializeOwned, B: Serialize + ?Sized>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T> {
+        let response = self
+            .send(self.request(Method::POST, path).json(body))
+            .await?;
+        Ok(response.json().await?)
+    }
+
+    /// POST without a body, decoding the JSON response
+    pub async fn post_no_body<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let response = self.send(self.request(Method::POST, path)).await?;
+        Ok(response.json().await?)
+    }
+
+    /// POST without a body, ignoring any response body
+    pub async fn post_empty(&self, path: &str) -> Result<()> {
+        self.send(self.request(Method::POST, path)).await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, path: &str) -> Result<()> {
+        self.send(self.request(Method::DELETE, path)).await?;
+        Ok(())
+    }
+
+    /// GET returning the raw response for streaming or binary bodies
+    pub async fn get_raw<Q: Serialize + ?Sized>(&self, path: &str, query: &Q) -> Result<Response> {
+        self.send(self.request(Method::GET, path).query(query))
+            .await
+    }
+}
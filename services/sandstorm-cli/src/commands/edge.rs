@@ -0,0 +1,119 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clap::Subcommand;
+use serde::Deserialize;
+
+use super::Services;
+use crate::output;
+
+#[derive(Debug, Subcommand)]
+pub enum AgentsCommand {
+    /// Show status of every edge agent
+    List,
+    /// Show recent sandbox runs for an agent
+    Runs {
+        agent_id: String,
+        #[arg(long, default_value_t = 20)]
+        limit: i64,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct AgentOverview {
+    agent_id: String,
+    status: String,
+    version: String,
+    queue_depth: i32,
+    running: i32,
+    completed: i32,
+    failed: i32,
+    cpu_percent: Option<f64>,
+    memory_percent: Option<f64>,
+    last_heartbeat: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AgentRun {
+    sandbox_id: String,
+    provider: String,
+    language: String,
+    duration_ms: i64,
+    exit_code: i32,
+    finished_at: DateTime<Utc>,
+}
+
+pub async fn handle(services: &Services, command: AgentsCommand) -> Result<()> {
+    match command {
+        AgentsCommand::List => {
+            let value = services.collector.get("/api/edge/agents/overview").await?;
+            output::print(services.output, value, |agents: Vec<AgentOverview>| {
+                let rows = agents
+                    .into_iter()
+                    .map(|agent| {
+                        vec![
+                            agent.agent_id,
+                            agent.status,
+                            agent.version,
+                            agent.queue_depth.to_string(),
+                            agent.running.to_string(),
+                            format!("{}/{}", agent.completed, agent.failed),
+                            percent(agent.cpu_percent),
+                            percent(agent.memory_percent),
+                            agent.last_heartbeat.to_rfc3339(),
+                        ]
+                    })
+                    .collect();
+                output::table(
+                    &[
+                        "AGENT",
+                        "STATUS",
+                        "VERSION",
+                        "QUEUED",
+                        "RUNNING",
+                        "OK/FAIL",
+                        "CPU",
+                        "MEM",
+                        "HEARTBEAT",
+                    ],
+                    rows,
+                );
+            })
+        }
+        AgentsCommand::Runs { agent_id, limit } => {
+            let value = services
+                .collector
+                .get_with_query(
+                    &format!("/api/edge/agents/{}/runs", agent_id),
+                    &[("limit", limit)],
+                )
+                .await?;
+            output::print(services.output, value, |runs: Vec<AgentRun>| {
+                let rows = runs
+                    .into_iter()
+                    .map(|run| {
+                        vec![
+                            run.finished_at.to_rfc3339(),
+                            run.sandbox_id,
+                            run.provider,
+                            run.language,
+                            format!("{}ms", run.duration_ms),
+                            run.exit_code.to_string(),
+                        ]
+                    })
+                    .collect();
+                output::table(
+                    &[
+                        "FINISHED", "SANDBOX", "PROVIDER", "LANGUAGE", "DURATION", "EXIT",
+                    ],
+                    rows,
+                );
+            })
+        }
+    }
+}
+
+fn percent(value: Option<f64>) -> String {
+    value
+        .map(|value| format!("{:.0}%", value))
+        .unwrap_or_else(|| "-".to_string())
+}
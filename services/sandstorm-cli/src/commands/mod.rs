@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::Client;
+use sandstorm_types::{recording::USER_HEADER, snapshot::TENANT_HEADER};
+use std::collections::HashMap;
+
+use crate::client::ServiceClient;
+use crate::config::Profile;
+use crate::output::OutputFormat;
+
+pub mod edge;
+pub mod recording;
+pub mod sandbox;
+pub mod security;
+pub mod snapshot;
+
+/// Clients for every service in the selected profile
+pub struct Services {
+    pub gateway: ServiceClient,
+    pub vault: ServiceClient,
+    pub monitor: ServiceClient,
+    pub collector: ServiceClient,
+    pub output: OutputFormat,
+}
+
+impl Services {
+    pub fn new(profile: &Profile, output: OutputFormat) -> Self {
+        // Tag requests with the local user so gateway session recordings
+        // show who ran what
+        let mut headers = HeaderMap::new();
+        if let Some(user) = std::env::var("USER")
+            .ok()
+            .and_then(|user| HeaderValue::from_str(&user).ok())
+        {
+            headers.insert(USER_HEADER, user);
+        }
+        let http = Client::builder()
+            .default_headers(headers.clone())
+            .build()
+            .unwrap_or_default();
+        // Only the vault is tenant aware
+        if let Some(tenant) = profile
+            .tenant
+            .as_deref()
+            .and_then(|tenant| HeaderValue::from_str(tenant).ok())
+        {
+            headers.insert(TENANT_HEADER, tenant);
+        }
+        let vault_http = Client::builder()
+            .default_headers(headers)
+            .build()
+            .unwrap_or_default();
+        Self {
+            gateway: ServiceClient::new(http.clone(), &profile.gateway_url, "gateway"),
+            vault: ServiceClient::new(vault_http, &profile.vault_url, "snapshot vault"),
+            monitor: ServiceClient::new(http.clone(), &profile.monitor_url, "security monitor"),
+            collector: ServiceClient::new(http, &profile.collector_url, "telemetry collector"),
+            output,
+        }
+    }
+}
+
+/// Parse repeated `KEY=VALUE` environment arguments
+pub fn parse_env(pairs: &[String]) -> Result<Option<HashMap<String, String>>> {
+    if pairs.is_empty() {
+        return Ok(None);
+    }
+    parse_pairs(pairs, "environment variable").map(Some)
+}
+
+/// Parse repeated `KEY=VALUE` arguments, naming `what` they are in errors
+pub fn parse_pairs(pairs: &[String], what: &str) -> Result<HashMap<String, String>> {
+    pairs
+        .iter()
+        .map(|pair| {
+            pair.split_once('=')
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .with_context(|| format!("invalid {} '{}', expected KEY=VALUE", what, pair))
+        })
+        .collect()
+}
@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use sandstorm_types::recording::SessionRecording;
+use serde::Serialize;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use super::Services;
+use crate::output;
+
+#[derive(Debug, Subcommand)]
+pub enum RecordingCommand {
+    /// List session recordings stored in the vault
+    List {
+        #[arg(long)]
+        sandbox_id: Option<String>,
+        #[arg(long)]
+        run_id: Option<Uuid>,
+        #[arg(long)]
+        user: Option<String>,
+    },
+    /// Download a recording as an asciicast file (play with `asciinema play`)
+    Download {
+        id: Uuid,
+        /// Destination file
+        #[arg(long, short = 'f')]
+        out: PathBuf,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct ListQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sandbox_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    run_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+}
+
+pub async fn handle(services: &Services, command: RecordingCommand) -> Result<()> {
+    match command {
+        RecordingCommand::List {
+            sandbox_id,
+            run_id,
+            user,
+        } => {
+            let value = services
+                .vault
+                .get_with_query(
+                    "/v1/recordings",
+                    &ListQuery {
+                        sandbox_id,
+                        run_id,
+                        user,
+                    },
+                )
+                .await?;
+
+            output::print(
+                services.output,
+                value,
+                |recordings: Vec<SessionRecording>| {
+                    let rows = recordings
+                        .into_iter()
+                        .map(|recording| {
+                            vec![
+                                recording.id.to_string(),
+                                recording.sandbox_id,
+                                recording.user,
+                                format!("{:?}", recording.kind).to_lowercase(),
+                                recording.command.join(" "),
+                                output::opt(&recording.exit_code),
+                                recording.started_at.to_rfc3339(),
+                            ]
+                        })
+                        .collect();
+                    output::table(
+                        &["ID", "SANDBOX", "USER", "KIND", "COMMAND", "EXIT", "STARTED"],
+                        rows,
+                    );
+                },
+            )
+        }
+        RecordingCommand::Download { id, out } => {
+            let response = services
+                .vault
+                .get_raw(&format!("/v1/recordings/{}/cast", id), &())
+                .await?;
+            let bytes = response.bytes().await?;
+            tokio::fs::write(&out, &bytes)
+                .await
+                .with_context(|| format!("failed to write {}", out.display()))?;
+            eprintln!("wrote {} bytes to {}", bytes.len(), out.display());
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use sandstorm_types::provenance::RunProvenance;
+use sandstorm_types::sandbox::{
+    ExecutionMode, GvisorNetwork, GvisorOptions, GvisorPlatform, IsolationLevel,
+    OptimizationHint, Priority, RuntimeType, SandboxSnapshot,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use super::{parse_env, parse_pairs, Services};
+use crate::output::{self, OutputFormat};
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Language runtime (python, javascript, go, shell, ...)
+    #[arg(long, short)]
+    language: String,
+    /// Code to run
+    #[arg(long, short, conflicts_with = "file", required_unless_present = "file")]
+    code: Option<String>,
+    /// Read the code from a file
+    #[arg(long, short)]
+    file: Option<PathBuf>,
+    /// Isolation level: standard, strong or maximum
+    #[arg(long, default_value = "standard", value_parser = parse_serde::<IsolationLevel>)]
+    isolation: IsolationLevel,
+    /// Preferred runtime: gvisor, kata, firecracker, e2b, modal, daytona or mock
+    #[arg(long, value_parser = parse_serde::<RuntimeType>)]
+    runtime: Option<RuntimeType>,
+    /// Let the gateway pick a runtime by telemetry: cheapest or fastest
+    #[arg(long, value_parser = parse_serde::<OptimizationHint>)]
+    optimize: Option<OptimizationHint>,
+    /// Scheduling priority: low, normal or high; higher priorities may
+    /// preempt lower ones when the gateway is out of capacity
+    #[arg(long, default_value = "normal", value_parser = parse_serde::<Priority>)]
+    priority: Priority,
+    /// Run untrusted code with a read-only root filesystem, no network and a
+    /// reduced syscall profile
+    #[arg(long)]
+    read_only: bool,
+    /// Size of the writable scratch space in read-only mode, in MiB
+    #[arg(long, requires = "read_only")]
+    scratch_mb: Option<u64>,
+    /// CPU limit in cores
+    #[arg(long)]
+    cpu: Option<f64>,
+    /// Memory limit in MiB
+    #[arg(long)]
+    memory_mb: Option<u64>,
+    /// Timeout in milliseconds
+    #[arg(long)]
+    timeout_ms: Option<u64>,
+    /// Environment variables as KEY=VALUE
+    #[arg(long = "env", short = 'e')]
+    env: Vec<String>,
+    /// Kernel parameters as KEY=VALUE, e.g. net.core.somaxconn=1024; the
+    /// gateway only accepts an allow-listed set
+    #[arg(long = "sysctl")]
+    sysctls: Vec<String>,
+    /// gVisor platform: ptrace, kvm or systrap
+    #[arg(long, value_parser = parse_serde::<GvisorPlatform>)]
+    gvisor_platform: Option<GvisorPlatform>,
+    /// gVisor network: none, sandbox or host
+    #[arg(long, value_parser = parse_serde::<GvisorNetwork>)]
+    gvisor_network: Option<GvisorNetwork>,
+    /// Keep gVisor root filesystem writes in memory (true or false)
+    #[arg(long)]
+    gvisor_overlay: Option<bool>,
+    /// Write runsc debug logs for the sandbox
+    #[arg(long)]
+    gvisor_debug: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExecArgs {
+    sandbox_id: Uuid,
+    /// Environment variables as KEY=VALUE
+    #[arg(long = "env", short = 'e')]
+    env: Vec<String>,
+    /// Run the command even if the gateway has a cached result for it
+    #[arg(long)]
+    no_cache: bool,
+    /// Absolute directory to run the command in
+    #[arg(long)]
+    workdir: Option<String>,
+    /// User to run as, uid or uid:gid
+    #[arg(long)]
+    user: Option<String>,
+    /// Allocate a pseudo-terminal (stdout and stderr are combined)
+    #[arg(long)]
+    tty: bool,
+    /// Give up on the command after this many milliseconds
+    #[arg(long)]
+    timeout_ms: Option<u64>,
+    /// Command and arguments
+    #[arg(last = true, required = true)]
+    command: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct LogsArgs {
+    sandbox_id: Uuid,
+    /// Keep streaming new output
+    #[arg(long, short)]
+    follow: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct IdArgs {
+    sandbox_id: Uuid,
+}
+
+#[derive(Debug, Args)]
+pub struct ResumeArgs {
+    /// Snapshot JSON written by `sandstorm snapshot create`
+    file: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct RunResponse {
+    sandbox_id: Uuid,
+    run_id: Option<Uuid>,
+    status: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExecResponse {
+    exit_code: i32,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    duration_ms: u64,
+    #[serde(default)]
+    cached: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusResponse {
+    id: Uuid,
+    state: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+    started_at: Option<chrono::DateTime<chrono::Utc>>,
+    finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    exit_code: Option<i32>,
+    priority: Option<String>,
+    preemption: Option<PreemptionInfo>,
+    resumed_from: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PreemptionInfo {
+    preempted_by: String,
+    preempted_at: chrono::DateTime<chrono::Utc>,
+    resumed_as: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResumeResponse {
+    sandbox_id: Uuid,
+}
+
+/// Parse a clap argument through the type's serde representation so the CLI
+/// accepts exactly the values the services do.
+fn parse_serde<T: serde::de::DeserializeOwned>(value: &str) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| format!("unsupported value '{}'", value))
+}
+
+pub async fn run(services: &Services, args: RunArgs) -> Result<()> {
+    let code = match (args.code, args.file) {
+        (Some(code), _) => code,
+        (None, Some(path)) => tokio::fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("failed to read {}", path.display()))?,
+        (None, None) => unreachable!("clap requires --code or --file"),
+    };
+
+    let body = json!({
+        "code": code,
+        "language": args.language,
+        "isolation_level": args.isolation,
+        "runtime_preference": args.runtime,
+        "optimize_for": args.optimize,
+        "priority": args.priority,
+        "mode": if args.read_only { ExecutionMode::ReadOnly } else { ExecutionMode::Standard },
+        "scratch_size_mb": args.scratch_mb,
+        "cpu_limit": args.cpu,
+        "memory_limit": args.memory_mb.map(|mb| mb * 1024 * 1024),
+        "timeout": args.timeout_ms,
+        "environment": parse_env(&args.env)?,
+        "sysctls": parse_pairs(&args.sysctls, "sysctl")?,
+        "gvisor": GvisorOptions {
+            platform: args.gvisor_platform,
+            network: args.gvisor_network,
+            overlay: args.gvisor_overlay,
+            debug: args.gvisor_debug.then_some(true),
+        },
+    });
+
+    let value = services.gateway.post("/v1/sandboxes/run", &body).await?;
+    output::print(services.output, value, |response: RunResponse| {
+        println!(
+            "{}  {}  run {}",
+            response.sandbox_id,
+            response.status,
+            output::opt(&response.run_id)
+        );
+    })
+}
+
+pub async fn exec(services: &Services, args: ExecArgs) -> Result<()> {
+    let body = json!({
+        "command": args.command,
+        "environment": parse_env(&args.env)?,
+        "no_cache": args.no_cache,
+        "working_dir": args.workdir,
+        "user": args.user,
+        "tty": args.tty,
+        "timeout_ms": args.timeout_ms,
+    });
+
+    let value: serde_json::Value = services
+        .gateway
+        .post(&format!("/v1/sandboxes/{}/exec", args.sandbox_id), &body)
+        .await?;
+
+    if services.output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(());
+    }
+
+    let response: ExecResponse = serde_json::from_value(value)?;
+    tokio::io::stdout().write_all(&response.stdout).await?;
+    tokio::io::stderr().write_all(&response.stderr).await?;
+    eprintln!(
+        "exit code {} in {}ms{}",
+        response.exit_code,
+        response.duration_ms,
+        if response.cached { " (cached)" } else { "" }
+    );
+    if response.exit_code != 0 {
+        std::process::exit(response.exit_code);
+    }
+    Ok(())
+}
+
+pub async fn logs(services: &Services, args: LogsArgs) -> Result<()> {
+    let mut response = services
+        .gateway
+        .get_raw(
+            &format!("/v1/sandboxes/{}/logs", args.sandbox_id),
+            &[("follow", args.follow)],
+        )
+        .await?;
+
+    let mut stdout = tokio::io::stdout();
+    while let Some(chunk) = response.chunk().await? {
+        stdout.write_all(&chunk).await?;
+        stdout.flush().await?;
+    }
+    Ok(())
+}
+
+pub async fn status(services: &Services, args: IdArgs) -> Result<()> {
+    let value = services
+        .gateway
+        .get(&format!("/v1/sandboxes/{}/status", args.sandbox_id))
+        .await?;
+
+    output::print(services.output, value, |status: StatusResponse| {
+        println!("id:          {}", status.id);
+        println!("state:       {}", status.state);
+        println!("created_at:  {}", status.created_at);
+        println!("started_at:  {}", output::opt(&status.started_at));
+        println!("finished_at: {}", output::opt(&status.finished_at));
+        println!("exit_code:   {}", output::opt(&status.exit_code));
+        println!("priority:    {}", output::opt(&status.priority));
+        if let Some(preemption) = &status.preemption {
+            println!(
+                "preempted:   {} (by {} priority work)",
+                preemption.preempted_at, preemption.preempted_by
+            );
+            println!("resumed_as:  {}", output::opt(&preemption.resumed_as));
+        }
+        if let Some(resumed_from) = status.resumed_from {
+            println!("resumed_from: {}", resumed_from);
+        }
+    })
+}
+
+pub async fn destroy(services: &Services, args: IdArgs) -> Result<()> {
+    services
+        .gateway
+        .delete(&format!("/v1/sandboxes/{}", args.sandbox_id))
+        .await?;
+
+    if services.output == OutputFormat::Json {
+        println!(
+            "{}",
+            json!({ "sandbox_id": args.sandbox_id, "destroyed": true })
+        );
+    } else {
+        println!("destroyed {}", args.sandbox_id);
+    }
+    Ok(())
+}
+
+pub async fn resume(services: &Services, args: ResumeArgs) -> Result<()> {
+    let contents = tokio::fs::read(&args.file)
+        .await
+        .with_context(|| format!("failed to read {}", args.file.display()))?;
+    let snapshot: SandboxSnapshot = serde_json::from_slice(&contents)
+        .with_context(|| format!("{} is not a sandbox snapshot", args.file.display()))?;
+
+    let value = services
+        .gateway
+        .post("/v1/sandboxes/resume", &json!({ "snapshot": snapshot }))
+        .await?;
+
+    output::print(services.output, value, |response: ResumeResponse| {
+        println!("{}", response.sandbox_id);
+    })
+}
+
+pub async fn provenance(services: &Services, args: IdArgs) -> Result<()> {
+    let value = services
+        .gateway
+        .get(&format!("/v1/sandboxes/{}/provenance", args.sandbox_id))
+        .await?;
+
+    output::print(services.output, value, |provenance: RunProvenance| {
+        println!("sandbox: {}", provenance.sandbox_id);
+        println!("run:     {}", output::opt(&provenance.run_id));
+        if !provenance.unavailable.is_empty() {
+            println!("unavailable: {}", provenance.unavailable.join(", "));
+        }
+
+        println!("\nRuns");
+        output::table(
+            &["TIME", "PROVIDER", "EXIT", "DURATION_MS", "RUN"],
+            provenance
+                .runs
+                .into_iter()
+                .map(|run| {
+                    vec![
+                        run.created_at.to_rfc3339(),
+                        run.provider,
+                        run.exit_code.to_string(),
+                        run.duration_ms.to_string(),
+                        output::opt(&run.run_id),
+                    ]
+                })
+                .collect(),
+        );
+
+        println!("\nSecurity events");
+        output::table(
+            &["TIME", "SEVERITY", "TYPE", "MESSAGE"],
+            provenance
+                .security_events
+                .into_iter()
+                .map(|event| {
+                    vec![
+                        event.timestamp.to_rfc3339(),
+                        event.severity.to_string(),
+                        event.event_type.to_string(),
+                        event.message,
+                    ]
+                })
+                .collect(),
+        );
+
+        println!("\nQuarantines");
+        output::table(
+            &["ID", "STARTED", "ENDED", "REASON"],
+            provenance
+                .quarantines
+                .into_iter()
+                .map(|record| {
+                    vec![
+                        record.id,
+                        record.start_time.to_rfc3339(),
+                        output::opt(&record.end_time),
+                        record.reason,
+                    ]
+                })
+                .collect(),
+        );
+
+        println!("\nSnapshots");
+        output::table(
+            &["ID", "CREATED", "SIZE"],
+            provenance
+                .snapshots
+                .into_iter()
+                .map(|snapshot| {
+                    vec![
+                        snapshot.id.to_string(),
+                        snapshot.created_at.to_rfc3339(),
+                        snapshot.size_bytes.to_string(),
+                    ]
+                })
+                .collect(),
+        );
+    })
+}
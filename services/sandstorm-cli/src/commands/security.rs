@@ -0,0 +1,93 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clap::{Args, Subcommand};
+use sandstorm_types::security::{EventType, QuarantineRecord, SecurityEvent, Severity};
+use serde::Serialize;
+
+use super::Services;
+use crate::output::{self, OutputFormat};
+
+#[derive(Debug, Args, Serialize)]
+pub struct EventsArgs {
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sandbox_id: Option<String>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event_type: Option<EventType>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    severity: Option<Severity>,
+    /// Only events at or after this RFC 3339 timestamp
+    #[arg(long)]
+    #[serde(rename = "start_time", skip_serializing_if = "Option::is_none")]
+    since: Option<DateTime<Utc>>,
+    #[arg(long, default_value_t = 50)]
+    limit: u32,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum QuarantineCommand {
+    /// List active quarantines
+    List,
+    /// Release a quarantine
+    Release { id: String },
+}
+
+pub async fn events(services: &Services, args: EventsArgs) -> Result<()> {
+    let value = services
+        .monitor
+        .get_with_query("/api/events", &args)
+        .await?;
+    output::print(services.output, value, |events: Vec<SecurityEvent>| {
+        let rows = events
+            .into_iter()
+            .map(|event| {
+                vec![
+                    event.timestamp.to_rfc3339(),
+                    event.severity.to_string(),
+                    event.event_type.to_string(),
+                    event.sandbox_id,
+                    event.message,
+                ]
+            })
+            .collect();
+        output::table(&["TIME", "SEVERITY", "TYPE", "SANDBOX", "MESSAGE"], rows);
+    })
+}
+
+pub async fn quarantine(services: &Services, command: QuarantineCommand) -> Result<()> {
+    match command {
+        QuarantineCommand::List => {
+            let value = services.monitor.get("/api/quarantine").await?;
+            output::print(services.output, value, |records: Vec<QuarantineRecord>| {
+                let rows = records
+                    .into_iter()
+                    .map(|record| {
+                        vec![
+                            record.id,
+                            record.sandbox_id,
+                            record.mode.to_string(),
+                            record.start_time.to_rfc3339(),
+                            record.auto_release.to_string(),
+                            record.reason,
+                        ]
+                    })
+                    .collect();
+                output::table(&["ID", "SANDBOX", "MODE", "SINCE", "AUTO_RELEASE", "REASON"], rows);
+            })
+        }
+        QuarantineCommand::Release { id } => {
+            services
+                .monitor
+                .post_empty(&format!("/api/quarantine/{}/release", id))
+                .await?;
+            if services.output == OutputFormat::Json {
+                println!("{}", serde_json::json!({ "id": id, "released": true }));
+            } else {
+                println!("released {}", id);
+            }
+            Ok(())
+        }
+    }
+}
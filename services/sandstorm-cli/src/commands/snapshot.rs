@@ -0,0 +1,139 @@
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use sandstorm_types::snapshot::SnapshotMetadata;
+use serde::Serialize;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use super::Services;
+use crate::output;
+
+#[derive(Debug, Subcommand)]
+pub enum SnapshotCommand {
+    /// Snapshot a running sandbox through the gateway
+    Create {
+        sandbox_id: Uuid,
+        /// Write the snapshot to a file (usable with `sandstorm resume`)
+        #[arg(long, short = 'f')]
+        out: Option<PathBuf>,
+    },
+    /// List snapshots stored in the vault
+    List {
+        #[arg(long)]
+        sandbox_id: Option<String>,
+        #[arg(long)]
+        provider: Option<String>,
+    },
+    /// Show snapshot metadata from the vault
+    Show { id: Uuid },
+    /// Download a snapshot blob from the vault
+    Download {
+        id: Uuid,
+        /// Destination file
+        #[arg(long, short = 'f')]
+        out: PathBuf,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct ListQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sandbox_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provider: Option<String>,
+}
+
+pub async fn handle(services: &Services, command: SnapshotCommand) -> Result<()> {
+    match command {
+        SnapshotCommand::Create { sandbox_id, out } => {
+            let value: serde_json::Value = services
+                .gateway
+                .post_no_body(&format!("/v1/sandboxes/{}/snapshot", sandbox_id))
+                .await?;
+
+            match out {
+                Some(path) => {
+                    tokio::fs::write(&path, serde_json::to_vec_pretty(&value)?)
+                        .await
+                        .with_context(|| format!("failed to write {}", path.display()))?;
+                    eprintln!("snapshot written to {}", path.display());
+                }
+                None => println!("{}", serde_json::to_string_pretty(&value)?),
+            }
+            Ok(())
+        }
+        SnapshotCommand::List {
+            sandbox_id,
+            provider,
+        } => {
+            let value = services
+                .vault
+                .get_with_query(
+                    "/v1/snapshots",
+                    &ListQuery {
+                        sandbox_id,
+                        provider,
+                    },
+                )
+                .await?;
+
+            output::print(
+                services.output,
+                value,
+                |mut snapshots: Vec<SnapshotMetadata>| {
+                    snapshots.sort_by_key(|snapshot| snapshot.created_at);
+                    let rows = snapshots
+                        .into_iter()
+                        .map(|snapshot| {
+                            vec![
+                                snapshot.id.to_string(),
+                                snapshot.sandbox_id,
+                                snapshot.provider,
+                                snapshot.size_bytes.to_string(),
+                                if snapshot.has_blob { "yes" } else { "no" }.to_string(),
+                                snapshot.created_at.to_rfc3339(),
+                            ]
+                        })
+                        .collect();
+                    output::table(
+                        &["ID", "SANDBOX", "PROVIDER", "BYTES", "BLOB", "CREATED"],
+                        rows,
+                    );
+                },
+            )
+        }
+        SnapshotCommand::Show { id } => {
+            let value = services.vault.get(&format!("/v1/snapshots/{}", id)).await?;
+            output::print(services.output, value, |snapshot: SnapshotMetadata| {
+                println!("id:              {}", snapshot.id);
+                println!("sandbox_id:      {}", snapshot.sandbox_id);
+                println!("provider:        {}", snapshot.provider);
+                println!("filesystem_hash: {}", snapshot.filesystem_hash);
+                println!("memory_hash:     {}", output::opt(&snapshot.memory_hash));
+                println!("size_bytes:      {}", snapshot.size_bytes);
+                println!("has_blob:        {}", snapshot.has_blob);
+                let validated = snapshot.validation.map(|validation| {
+                    match (validation.format, validation.size_verified) {
+                        (Some(format), true) => format!("{}, size", format.as_str()),
+                        (Some(format), false) => format.as_str().to_string(),
+                        (None, _) => "size".to_string(),
+                    }
+                });
+                println!("validated:       {}", output::opt(&validated));
+                println!("created_at:      {}", snapshot.created_at);
+            })
+        }
+        SnapshotCommand::Download { id, out } => {
+            let response = services
+                .vault
+                .get_raw(&format!("/v1/snapshots/{}/data", id), &())
+                .await?;
+            let bytes = response.bytes().await?;
+            tokio::fs::write(&out, &bytes)
+                .await
+                .with_context(|| format!("failed to write {}", out.display()))?;
+            eprintln!("wrote {} bytes to {}", bytes.len(), out.display());
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Endpoints for one Sandstorm deployment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default = "default_gateway_url")]
+    pub gateway_url: String,
+    #[serde(default = "default_vault_url")]
+    pub vault_url: String,
+    #[serde(default = "default_monitor_url")]
+    pub monitor_url: String,
+    #[serde(default = "default_collector_url")]
+    pub collector_url: String,
+    /// Tenant to act for in the snapshot vault
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            gateway_url: default_gateway_url(),
+            vault_url: default_vault_url(),
+            monitor_url: default_monitor_url(),
+            collector_url: default_collector_url(),
+            tenant: None,
+        }
+    }
+}
+
+fn default_gateway_url() -> String {
+    "http://localhost:3000".to_string()
+}
+
+fn default_vault_url() -> String {
+    "http://localhost:8083".to_string()
+}
+
+fn default_monitor_url() -> String {
+    "http://localhost:8081".to_string()
+}
+
+fn default_collector_url() -> String {
+    "http://localhost:8082".to_string()
+}
+
+/// Contents of `~/.config/sandstorm/config.toml`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CliConfig {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+}
+
+impl CliConfig {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("sandstorm").join("config.toml"))
+    }
+
+    /// Load the config file; a missing file yields an empty config
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path.map(Path::to_path_buf).or_else(Self::default_path) {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents)
+                .with_context(|| format!("failed to parse {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    /// Resolve the profile to use. An explicitly requested profile must exist;
+    /// otherwise fall back to the configured default, then built-in defaults.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile> {
+        if let Some(name) = name {
+            return self
+                .profiles
+                .get(name)
+                .cloned()
+                .with_context(|| format!("profile '{}' not found", name));
+        }
+
+        Ok(self
+            .default_profile
+            .as_deref()
+            .and_then(|name| self.profiles.get(name))
+            .cloned()
+            .unwrap_or_default())
+    }
+}
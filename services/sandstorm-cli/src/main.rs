@@ -0,0 +1,316 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+mod client;
+mod commands;
+mod config;
+mod output;
+
+use crate::commands::{edge, recording, sandbox, security, snapshot, Services};
+use crate::config::{CliConfig, Profile};
+use crate::output::OutputFormat;
+
+/// Operate a Sandstorm deployment from the command line
+#[derive(Debug, Parser)]
+#[command(name = "sandstorm", version, about)]
+struct Cli {
+    /// Profile from the config file to use
+    #[arg(long, short = 'p', global = true, env = "SANDSTORM_PROFILE")]
+    profile: Option<String>,
+
+    /// Config file (defaults to ~/.config/sandstorm/config.toml)
+    #[arg(long, global = true, env = "SANDSTORM_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Output format
+    #[arg(long, short = 'o', global = true, value_enum, default_value = "text")]
+    output: OutputFormat,
+
+    /// Override the gateway URL from the profile
+    #[arg(long, global = true, env = "SANDSTORM_GATEWAY_URL")]
+    gateway_url: Option<String>,
+
+    /// Override the snapshot vault URL from the profile
+    #[arg(long, global = true, env = "SANDSTORM_VAULT_URL")]
+    vault_url: Option<String>,
+
+    /// Override the security monitor URL from the profile
+    #[arg(long, global = true, env = "SANDSTORM_MONITOR_URL")]
+    monitor_url: Option<String>,
+
+    /// Override the telemetry collector URL from the profile
+    #[arg(long, global = true, env = "SANDSTORM_COLLECTOR_URL")]
+    collector_url: Option<String>,
+
+    /// Override the snapshot vault tenant from the profile
+    #[arg(long, global = true, env = "SANDSTORM_TENANT")]
+    tenant: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Start a sandbox running a piece of code
+    Run(sandbox::RunArgs),
+    /// Execute a command in a running sandbox
+    Exec(sandbox::ExecArgs),
+    /// Print sandbox logs
+    Logs(sandbox::LogsArgs),
+    /// Show sandbox status
+    Status(sandbox::IdArgs),
+    /// Destroy a sandbox
+    Destroy(sandbox::IdArgs),
+    /// Resume a sandbox from a snapshot file written by `snapshot create`
+    Resume(sandbox::ResumeArgs),
+    /// Show everything recorded about a sandbox across services
+    Provenance(sandbox::IdArgs),
+    /// Create, list and download snapshots
+    #[command(subcommand)]
+    Snapshot(snapshot::SnapshotCommand),
+    /// List and download session recordings
+    #[command(subcommand)]
+    Recording(recording::RecordingCommand),
+    /// Query security events
+    Events(security::EventsArgs),
+    /// List and release quarantined sandboxes
+    #[command(subcommand)]
+    Quarantine(security::QuarantineCommand),
+    /// Inspect edge agents
+    #[command(subcommand)]
+    Agents(edge::AgentsCommand),
+    /// List profiles from the config file
+    Profiles,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(cli).await {
+        eprintln!("error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let config = CliConfig::load(cli.config.as_deref())?;
+
+    if let Command::Profiles = cli.command {
+        return list_profiles(&config, cli.output);
+    }
+
+    let profile = resolve_profile(&cli, &config)?;
+    let services = Services::new(&profile, cli.output);
+
+    match cli.command {
+        Command::Run(args) => sandbox::run(&services, args).await,
+        Command::Exec(args) => sandbox::exec(&services, args).await,
+        Command::Logs(args) => sandbox::logs(&services, args).await,
+        Command::Status(args) => sandbox::status(&services, args).await,
+        Command::Destroy(args) => sandbox::destroy(&services, args).await,
+        Command::Resume(args) => sandbox::resume(&services, args).await,
+        Command::Provenance(args) => sandbox::provenance(&services, args).await,
+        Command::Snapshot(command) => snapshot::handle(&services, command).await,
+        Command::Recording(command) => recording::handle(&services, command).await,
+        Command::Events(args) => security::events(&services, args).await,
+        Command::Quarantine(command) => security::quarantine(&services, command).await,
+        Command::Agents(command) => edge::handle(&services, command).await,
+        Command::Profiles => unreachable!("handled above"),
+    }
+}
+
+/// The selected profile from the config file, with URLs and the tenant
+/// overridden by flags or their environment variables
+fn resolve_profile(cli: &Cli, config: &CliConfig) -> Result<Profile> {
+    let mut profile = config.profile(cli.profile.as_deref())?;
+    if let Some(url) = &cli.gateway_url {
+        profile.gateway_url = url.clone();
+    }
+    if let Some(url) = &cli.vault_url {
+        profile.vault_url = url.clone();
+    }
+    if let Some(url) = &cli.monitor_url {
+        profile.monitor_url = url.clone();
+    }
+    if let Some(url) = &cli.collector_url {
+        profile.collector_url = url.clone();
+    }
+    if cli.tenant.is_some() {
+        profile.tenant = cli.tenant.clone();
+    }
+    Ok(profile)
+}
+
+fn list_profiles(config: &CliConfig, format: OutputFormat) -> Result<()> {
+    let value = serde_json::to_value(config)?;
+    output::print(format, value, |config: CliConfig| {
+        let mut names: Vec<_> = config.profiles.keys().cloned().collect();
+        names.sort();
+        let rows = names
+            .into_iter()
+            .map(|name| {
+                let profile = &config.profiles[&name];
+                let marker = if config.default_profile.as_deref() == Some(name.as_str()) {
+                    "*"
+                } else {
+                    ""
+                };
+                vec![
+                    format!("{}{}", name, marker),
+                    profile.gateway_url.clone(),
+                    profile.vault_url.clone(),
+                    profile.monitor_url.clone(),
+                    profile.collector_url.clone(),
+                ]
+            })
+            .collect();
+        output::table(
+            &["PROFILE", "GATEWAY", "VAULT", "MONITOR", "COLLECTOR"],
+            rows,
+        );
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::error::ErrorKind;
+    use std::sync::Mutex;
+
+    /// Serializes tests that read or set `SANDSTORM_*` variables, which clap
+    /// consults while parsing
+    static ENV: Mutex<()> = Mutex::new(());
+
+    const ENV_VARS: &[&str] = &[
+        "SANDSTORM_PROFILE",
+        "SANDSTORM_CONFIG",
+        "SANDSTORM_GATEWAY_URL",
+        "SANDSTORM_VAULT_URL",
+        "SANDSTORM_MONITOR_URL",
+        "SANDSTORM_COLLECTOR_URL",
+        "SANDSTORM_TENANT",
+    ];
+
+    /// Parse with only the given `SANDSTORM_*` variables set
+    fn parse(args: &[&str], env: &[(&str, &str)]) -> Result<Cli, clap::Error> {
+        for var in ENV_VARS {
+            std::env::remove_var(var);
+        }
+        for (var, value) in env {
+            std::env::set_var(var, value);
+        }
+        let cli = Cli::try_parse_from(std::iter::once("sandstorm").chain(args.iter().copied()));
+        for (var, _) in env {
+            std::env::remove_var(var);
+        }
+        cli
+    }
+
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("sandstorm-cli-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn parses_commands_and_global_flags() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+
+        let cli = parse(&["run", "-l", "python", "-c", "print(1)", "-o", "json", "-p", "prod"], &[]).unwrap();
+        assert!(matches!(cli.command, Command::Run(_)));
+        assert_eq!(cli.output, OutputFormat::Json);
+        assert_eq!(cli.profile.as_deref(), Some("prod"));
+
+        let cli = parse(&["snapshot", "download", "00000000-0000-0000-0000-000000000001", "-f", "out.bin"], &[]).unwrap();
+        match cli.command {
+            Command::Snapshot(snapshot::SnapshotCommand::Download { out, .. }) => {
+                assert_eq!(out, PathBuf::from("out.bin"))
+            }
+            other => panic!("parsed {:?}", other),
+        }
+        assert_eq!(cli.output, OutputFormat::Text);
+        assert!(matches!(parse(&["profiles"], &[]).unwrap().command, Command::Profiles));
+
+        let rejected = [
+            (&["run", "-l", "python"][..], ErrorKind::MissingRequiredArgument),
+            (&["run", "-l", "python", "-c", "1", "-f", "a.py"], ErrorKind::ArgumentConflict),
+            (&["run", "-l", "python", "-c", "1", "--scratch-mb", "64"], ErrorKind::MissingRequiredArgument),
+            (&["run", "-l", "python", "-c", "1", "--isolation", "extreme"], ErrorKind::ValueValidation),
+            (&["status", "not-a-uuid"], ErrorKind::ValueValidation),
+            (&["snapshot"], ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand),
+            (&["frobnicate"], ErrorKind::InvalidSubcommand),
+        ];
+        for (args, kind) in rejected {
+            assert_eq!(parse(args, &[]).unwrap_err().kind(), kind, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn flags_win_over_environment_over_config_file() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let path = config_file(
+            "precedence",
+            r#"
+            default_profile = "staging"
+
+            [profiles.staging]
+            gateway_url = "http://staging-gateway"
+            vault_url = "http://staging-vault"
+            tenant = "staging-tenant"
+
+            [profiles.prod]
+            gateway_url = "http://prod-gateway"
+            "#,
+        );
+        let config = CliConfig::load(Some(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let resolve = |args: &[&str], env: &[(&str, &str)]| {
+            let args: Vec<&str> = args.iter().copied().chain(["profiles"]).collect();
+            resolve_profile(&parse(&args, env).unwrap(), &config).unwrap()
+        };
+
+        // The file's default profile, with built-in defaults for what it leaves out
+        let profile = resolve(&[], &[]);
+        assert_eq!(profile.gateway_url, "http://staging-gateway");
+        assert_eq!(profile.monitor_url, "http://localhost:8081");
+        assert_eq!(profile.tenant.as_deref(), Some("staging-tenant"));
+
+        // The environment over the file
+        let env = [
+            ("SANDSTORM_GATEWAY_URL", "http://env-gateway"),
+            ("SANDSTORM_TENANT", "env-tenant"),
+            ("SANDSTORM_PROFILE", "prod"),
+        ];
+        let profile = resolve(&[], &env);
+        assert_eq!(profile.gateway_url, "http://env-gateway");
+        assert_eq!(profile.vault_url, "http://localhost:8083");
+        assert_eq!(profile.tenant.as_deref(), Some("env-tenant"));
+
+        // Flags over the environment
+        let profile = resolve(&["--gateway-url", "http://flag-gateway", "--profile", "staging"], &env);
+        assert_eq!(profile.gateway_url, "http://flag-gateway");
+        assert_eq!(profile.vault_url, "http://staging-vault");
+        assert_eq!(profile.tenant.as_deref(), Some("env-tenant"));
+
+        // A profile asked for by name must exist
+        let cli = parse(&["profiles"], &[("SANDSTORM_PROFILE", "missing")]).unwrap();
+        let error = resolve_profile(&cli, &config).unwrap_err();
+        assert_eq!(error.to_string(), "profile 'missing' not found");
+    }
+
+    #[test]
+    fn missing_config_file_uses_built_in_defaults() {
+        let config = CliConfig::load(Some(&std::env::temp_dir().join("sandstorm-cli-absent.toml"))).unwrap();
+        let profile = config.profile(None).unwrap();
+        assert_eq!(profile.gateway_url, "http://localhost:3000");
+        assert_eq!(profile.collector_url, "http://localhost:8082");
+        assert!(profile.tenant.is_none());
+
+        let path = config_file("invalid", "profiles = 3");
+        let error = CliConfig::load(Some(&path)).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.to_string().starts_with("failed to parse"), "{}", error);
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::de::DeserializeOwned;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable text
+    Text,
+    /// Pretty-printed JSON, suitable for piping into jq
+    Json,
+}
+
+/// Print a service response as-is for JSON output, or decode it into `T`
+/// and hand it to `text` for human output. Keeping the raw value means JSON
+/// output never drops fields the CLI does not know about.
+pub fn print<T: DeserializeOwned>(
+    format: OutputFormat,
+    value: serde_json::Value,
+    text: impl FnOnce(T),
+) -> Result<()> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&value)?),
+        OutputFormat::Text => text(serde_json::from_value(value)?),
+    }
+    Ok(())
+}
+
+/// Print rows as left-aligned columns
+pub fn table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.len());
+        }
+    }
+
+    let render = |cells: Vec<String>| {
+        cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| format!("{:width$}", cell, width = widths[i]))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    println!(
+        "{}",
+        render(headers.iter().map(|h| h.to_string()).collect())
+    );
+    for row in rows {
+        println!("{}", render(row));
+    }
+}
+
+pub fn opt<T: ToString>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map(T::to_string)
+        .unwrap_or_else(|| "-".to_string())
+}
@@ -0,0 +1,110 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde_json::json;
+
+use crate::handle::{ConfigHandle, ReloadError};
+use crate::ServiceConfig;
+
+/// Admin routes: `GET /config`, `POST /config/reload`, `GET /config/audit`.
+///
+/// When `admin_token` is set every route requires
+/// `Authorization: Bearer <token>`.
+pub fn admin_router<T: ServiceConfig>(
+    handle: ConfigHandle<T>,
+    admin_token: Option<String>,
+) -> Router {
+    Router::new()
+        .route("/config", get(show_config::<T>))
+        .route("/config/reload", post(reload_config::<T>))
+        .route("/config/audit", get(audit_log::<T>))
+        .with_state(AdminState {
+            handle,
+            admin_token,
+        })
+}
+
+struct AdminState<T> {
+    handle: ConfigHandle<T>,
+    admin_token: Option<String>,
+}
+
+impl<T> Clone for AdminState<T> {
+    fn clone(&self) -> Self {
+        Self {
+            handle: self.handle.clone(),
+            admin_token: self.admin_token.clone(),
+        }
+    }
+}
+
+impl<T> AdminState<T> {
+    fn authorize(&self, headers: &HeaderMap) -> Result<(), StatusCode> {
+        let Some(token) = &self.admin_token else {
+            return Ok(());
+        };
+
+        let presented = headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        if presented == Some(token.as_str()) {
+            Ok(())
+        } else {
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+async fn show_config<T: ServiceConfig>(
+    State(state): State<AdminState<T>>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(status) = state.authorize(&headers) {
+        return status.into_response();
+    }
+    Json(state.handle.redacted()).into_response()
+}
+
+async fn reload_config<T: ServiceConfig>(
+    State(state): State<AdminState<T>>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(status) = state.authorize(&headers) {
+        return status.into_response();
+    }
+
+    let actor = headers
+        .get("x-sandstorm-actor")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    match state.handle.reload("http", actor).await {
+        Ok(outcome) => Json(outcome).into_response(),
+        Err(ReloadError::Invalid(problems)) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": "invalid configuration", "problems": problems })),
+        )
+            .into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": error.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+async fn audit_log<T: ServiceConfig>(
+    State(state): State<AdminState<T>>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(status) = state.authorize(&headers) {
+        return status.into_response();
+    }
+    Json(state.handle.audit_log()).into_response()
+}
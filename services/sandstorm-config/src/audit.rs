@@ -0,0 +1,142 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+
+const REDACTED: &str = "<redacted>";
+
+/// One applied configuration change
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    /// What triggered the reload: `startup`, `sighup` or `http`
+    pub trigger: String,
+    /// Caller identity when known (e.g. the mTLS peer)
+    pub actor: Option<String>,
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldChange {
+    pub key: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+/// Bounded in-memory audit trail
+#[derive(Debug)]
+pub(crate) struct AuditLog {
+    entries: VecDeque<AuditEntry>,
+    capacity: usize,
+}
+
+impl AuditLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, entry: AuditEntry) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.entries.iter().rev().cloned().collect()
+    }
+}
+
+/// Flatten a JSON value into dotted keys so nested settings diff cleanly
+fn flatten(prefix: &str, value: &Value, out: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, value, out);
+            }
+        }
+        _ => {
+            out.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+/// Field-level differences between two configs, with secrets redacted
+pub(crate) fn diff(old: &Value, new: &Value, secret_keys: &[&str]) -> Vec<FieldChange> {
+    let mut old_fields = BTreeMap::new();
+    let mut new_fields = BTreeMap::new();
+    flatten("", old, &mut old_fields);
+    flatten("", new, &mut new_fields);
+
+    let mut keys: Vec<&String> = old_fields.keys().chain(new_fields.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter(|key| old_fields.get(*key) != new_fields.get(*key))
+        .map(|key| {
+            let secret = is_secret(key, secret_keys);
+            let show = |value: Option<&Value>| {
+                value.map(|value| {
+                    if secret {
+                        Value::String(REDACTED.to_string())
+                    } else {
+                        value.clone()
+                    }
+                })
+            };
+            FieldChange {
+                key: key.clone(),
+                old: show(old_fields.get(key)),
+                new: show(new_fields.get(key)),
+            }
+        })
+        .collect()
+}
+
+fn is_secret(key: &str, secret_keys: &[&str]) -> bool {
+    secret_keys
+        .iter()
+        .any(|secret| key == *secret || key.starts_with(&format!("{}.", secret)))
+}
+
+/// Copy of `value` with secret keys replaced
+pub(crate) fn redact(value: &Value, secret_keys: &[&str]) -> Value {
+    let mut value = value.clone();
+    if let Value::Object(map) = &mut value {
+        for key in secret_keys {
+            if let Some(field) = map.get_mut(*key) {
+                if !field.is_null() {
+                    *field = Value::String(REDACTED.to_string());
+                }
+            }
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn diff_reports_nested_changes_and_redacts_secrets() {
+        let old = json!({ "limits": { "cpu": 2, "memory": 512 }, "token": "a", "port": 1 });
+        let new = json!({ "limits": { "cpu": 4, "memory": 512 }, "token": "b", "port": 1 });
+
+        let changes = diff(&old, &new, &["token"]);
+        let keys: Vec<_> = changes.iter().map(|c| c.key.as_str()).collect();
+        assert_eq!(keys, vec!["limits.cpu", "token"]);
+        assert_eq!(changes[0].new, Some(json!(4)));
+        assert_eq!(changes[1].new, Some(json!(REDACTED)));
+    }
+}
@@ -0,0 +1,262 @@
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::audit::{self, AuditEntry, AuditLog, FieldChange};
+use crate::sources::Sources;
+use crate::ServiceConfig;
+
+const AUDIT_CAPACITY: usize = 200;
+
+#[derive(Debug, Error)]
+pub enum ReloadError {
+    #[error("failed to load configuration: {0:#}")]
+    Load(#[from] anyhow::Error),
+    #[error("invalid configuration: {}", .0.join("; "))]
+    Invalid(Vec<String>),
+}
+
+/// Result of a successful reload
+#[derive(Debug, Clone, Serialize)]
+pub struct ReloadOutcome {
+    pub changes: Vec<FieldChange>,
+    /// Changed keys that only take effect after a restart
+    pub restart_required: Vec<String>,
+}
+
+/// Shared, reloadable view of a service's configuration.
+///
+/// Cheap to clone. Readers call [`ConfigHandle::current`] on every use (or
+/// [`ConfigHandle::subscribe`] for long-running tasks) so reloads take effect
+/// without restarting.
+pub struct ConfigHandle<T> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+    sources: Sources,
+    http: reqwest::Client,
+    current: watch::Sender<Arc<T>>,
+    audit: Mutex<AuditLog>,
+    reload_lock: tokio::sync::Mutex<()>,
+}
+
+impl<T> Clone for ConfigHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: ServiceConfig> ConfigHandle<T> {
+    /// Load and validate the initial configuration
+    pub async fn load(sources: Sources) -> Result<Self, ReloadError> {
+        let http = reqwest::Client::new();
+        let config: T = sources.load(&http).await?;
+        config.validate().map_err(ReloadError::Invalid)?;
+
+        let (current, _) = watch::channel(Arc::new(config));
+        Ok(Self {
+            inner: Arc::new(Inner {
+                sources,
+                http,
+                current,
+                audit: Mutex::new(AuditLog::new(AUDIT_CAPACITY)),
+                reload_lock: tokio::sync::Mutex::new(()),
+            }),
+        })
+    }
+
+    /// Handle around a fixed value, for tests and tools that skip loading
+    pub fn fixed(config: T) -> Self {
+        let (current, _) = watch::channel(Arc::new(config));
+        Self {
+            inner: Arc::new(Inner {
+                sources: Sources::default(),
+                http: reqwest::Client::new(),
+                current,
+                audit: Mutex::new(AuditLog::new(AUDIT_CAPACITY)),
+                reload_lock: tokio::sync::Mutex::new(()),
+            }),
+        }
+    }
+
+    pub fn current(&self) -> Arc<T> {
+        self.inner.current.borrow().clone()
+    }
+
+    /// Receiver notified whenever a reload changes the configuration
+    pub fn subscribe(&self) -> watch::Receiver<Arc<T>> {
+        self.inner.current.subscribe()
+    }
+
+    /// Re-read every source and swap in the result if it validates
+    pub async fn reload(
+        &self,
+        trigger: &str,
+        actor: Option<String>,
+    ) -> Result<ReloadOutcome, ReloadError> {
+        let _guard = self.inner.reload_lock.lock().await;
+
+        let config: T = self.inner.sources.load(&self.inner.http).await?;
+        if let Err(problems) = config.validate() {
+            warn!(?problems, trigger, "rejected invalid configuration");
+            return Err(ReloadError::Invalid(problems));
+        }
+
+        let old = serde_json::to_value(&*self.current()).map_err(anyhow::Error::from)?;
+        let new = serde_json::to_value(&config).map_err(anyhow::Error::from)?;
+        let changes = audit::diff(&old, &new, T::SECRET_KEYS);
+
+        let restart_required = changes
+            .iter()
+            .filter(|change| {
+                T::STATIC_KEYS
+                    .iter()
+                    .any(|key| change.key == *key || change.key.starts_with(&format!("{}.", key)))
+            })
+            .map(|change| change.key.clone())
+            .collect();
+
+        if !changes.is_empty() {
+            for change in &changes {
+                info!(
+                    target: "config_audit",
+                    trigger,
+                    actor = actor.as_deref().unwrap_or("-"),
+                    key = %change.key,
+                    old = ?change.old,
+                    new = ?change.new,
+                    "configuration changed"
+                );
+            }
+            self.inner
+                .audit
+                .lock()
+                .expect("audit log lock poisoned")
+                .push(AuditEntry {
+                    timestamp: chrono::Utc::now(),
+                    trigger: trigger.to_string(),
+                    actor,
+                    changes: changes.clone(),
+                });
+            self.inner.current.send_replace(Arc::new(config));
+        }
+
+        Ok(ReloadOutcome {
+            changes,
+            restart_required,
+        })
+    }
+
+    /// Applied changes, newest first
+    pub fn audit_log(&self) -> Vec<AuditEntry> {
+        self.inner
+            .audit
+            .lock()
+            .expect("audit log lock poisoned")
+            .entries()
+    }
+
+    /// Current configuration as JSON with secrets redacted
+    pub fn redacted(&self) -> serde_json::Value {
+        serde_json::to_value(&*self.current())
+            .map(|value| audit::redact(&value, T::SECRET_KEYS))
+            .unwrap_or_default()
+    }
+
+    /// Reload whenever the process receives SIGHUP
+    #[cfg(unix)]
+    pub fn spawn_sighup_reloader(&self) {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let handle = self.clone();
+        tokio::spawn(async move {
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(error) => {
+                    warn!(
+                        ?error,
+                        "failed to install SIGHUP handler, config reload via signal disabled"
+                    );
+                    return;
+                }
+            };
+
+            while hangup.recv().await.is_some() {
+                match handle.reload("sighup", None).await {
+                    Ok(outcome) => info!(
+                        changes = outcome.changes.len(),
+                        restart_required = ?outcome.restart_required,
+                        "configuration reloaded on SIGHUP"
+                    ),
+                    Err(error) => warn!(%error, "configuration reload on SIGHUP failed"),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    struct TestConfig {
+        port: u16,
+        retention_days: u32,
+        token: String,
+    }
+
+    impl ServiceConfig for TestConfig {
+        const STATIC_KEYS: &'static [&'static str] = &["port"];
+        const SECRET_KEYS: &'static [&'static str] = &["token"];
+
+        fn validate(&self) -> Result<(), Vec<String>> {
+            if self.retention_days == 0 {
+                return Err(vec!["retention_days must be positive".into()]);
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn reload_applies_valid_and_rejects_invalid_changes() {
+        let path =
+            std::env::temp_dir().join(format!("sandstorm-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "port = 80\nretention_days = 7\ntoken = \"a\"\n").unwrap();
+
+        let handle: ConfigHandle<TestConfig> = ConfigHandle::load(Sources {
+            file: Some(path.clone()),
+            remote_url: None,
+            env_prefix: Some("SANDSTORM_CONFIG_TEST".into()),
+        })
+        .await
+        .unwrap();
+        assert_eq!(handle.current().retention_days, 7);
+
+        std::fs::write(&path, "port = 81\nretention_days = 30\ntoken = \"b\"\n").unwrap();
+        let outcome = handle.reload("test", Some("tester".into())).await.unwrap();
+        assert_eq!(outcome.changes.len(), 3);
+        assert_eq!(outcome.restart_required, vec!["port".to_string()]);
+        assert_eq!(handle.current().retention_days, 30);
+
+        let audit = handle.audit_log();
+        assert_eq!(audit.len(), 1);
+        let token = audit[0].changes.iter().find(|c| c.key == "token").unwrap();
+        assert_ne!(token.new, Some(serde_json::json!("b")));
+
+        std::fs::write(&path, "port = 81\nretention_days = 0\ntoken = \"b\"\n").unwrap();
+        assert!(matches!(
+            handle.reload("test", None).await,
+            Err(ReloadError::Invalid(_))
+        ));
+        assert_eq!(handle.current().retention_days, 30);
+
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,40 @@
+//! Layered, hot-reloadable configuration shared by the Sandstorm services.
+//!
+//! A service describes its settings as a serde struct implementing
+//! [`ServiceConfig`]. Values are merged from (lowest to highest priority):
+//! the struct's `Default`, an optional config file, an optional remote JSON
+//! document and environment variables. The merged result is validated before
+//! it replaces the running configuration, so a bad edit never takes effect.
+//!
+//! Reloads are triggered by `SIGHUP` or `POST /config/reload`, and every
+//! applied change is recorded in an audit log exposed at `GET /config/audit`.
+
+mod admin;
+mod audit;
+mod handle;
+mod sources;
+
+pub use admin::admin_router;
+pub use audit::{AuditEntry, FieldChange};
+pub use handle::{ConfigHandle, ReloadError, ReloadOutcome};
+pub use sources::Sources;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Settings struct for one service
+pub trait ServiceConfig:
+    Serialize + DeserializeOwned + Default + Clone + Send + Sync + 'static
+{
+    /// Keys only read at startup. Changes are applied and audited, but the
+    /// reload response flags them as needing a restart.
+    const STATIC_KEYS: &'static [&'static str] = &[];
+
+    /// Keys whose values are never returned by the admin API or written to
+    /// the audit log.
+    const SECRET_KEYS: &'static [&'static str] = &[];
+
+    /// Check invariants, returning every problem found
+    fn validate(&self) -> Result<(), Vec<String>> {
+        Ok(())
+    }
+}
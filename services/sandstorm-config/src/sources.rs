@@ -0,0 +1,68 @@
+use anyhow::{Context, Result};
+use config::{Config, Environment, File, FileFormat};
+use std::path::PathBuf;
+
+use crate::ServiceConfig;
+
+/// Where a service's configuration comes from
+#[derive(Debug, Clone, Default)]
+pub struct Sources {
+    /// Config file; any format the `config` crate understands, extension optional
+    pub file: Option<PathBuf>,
+    /// URL returning a JSON object, fetched on every (re)load
+    pub remote_url: Option<String>,
+    /// Environment prefix, e.g. `TELEMETRY` for `TELEMETRY_PORT`. Without a
+    /// prefix, variables are matched by bare name (`PORT`). Nested keys use
+    /// `__` as separator.
+    pub env_prefix: Option<String>,
+}
+
+impl Sources {
+    /// Standard setup: `<PREFIX>_CONFIG_FILE` overrides `default_file`, and
+    /// `<PREFIX>_CONFIG_URL` enables the remote source.
+    pub fn from_env(env_prefix: Option<&str>, default_file: Option<&str>) -> Self {
+        let var = |name: &str| match env_prefix {
+            Some(prefix) => std::env::var(format!("{}_{}", prefix, name)).ok(),
+            None => std::env::var(name).ok(),
+        };
+
+        Self {
+            file: var("CONFIG_FILE")
+                .or_else(|| default_file.map(str::to_string))
+                .map(PathBuf::from),
+            remote_url: var("CONFIG_URL"),
+            env_prefix: env_prefix.map(str::to_string),
+        }
+    }
+
+    pub(crate) async fn load<T: ServiceConfig>(&self, http: &reqwest::Client) -> Result<T> {
+        let mut builder = Config::builder().add_source(
+            Config::try_from(&T::default()).context("failed to serialize default config")?,
+        );
+
+        if let Some(file) = &self.file {
+            let name = file.to_string_lossy();
+            builder = builder.add_source(File::with_name(&name).required(false));
+        }
+
+        if let Some(url) = &self.remote_url {
+            let body = http
+                .get(url)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .with_context(|| format!("failed to fetch remote config from {}", url))?
+                .text()
+                .await?;
+            builder = builder.add_source(File::from_str(&body, FileFormat::Json));
+        }
+
+        let env = match &self.env_prefix {
+            Some(prefix) => Environment::with_prefix(prefix).prefix_separator("_"),
+            None => Environment::default(),
+        };
+        builder = builder.add_source(env.separator("__").try_parsing(true));
+
+        Ok(builder.build()?.try_deserialize()?)
+    }
+}
@@ -0,0 +1,458 @@
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{GaugeVec, IntCounterVec, Opts, Registry};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Paths no breaker sheds, so a service can still say how it is doing
+const ALWAYS_SERVED: &[&str] = &["/health", "/metrics"];
+
+/// When a breaker opens, and for how long
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakerSettings {
+    /// Consecutive failures that open the breaker
+    pub failures: u32,
+    /// How long the breaker stays open before letting a call probe the
+    /// dependency
+    pub open_for: Duration,
+}
+
+impl Default for BreakerSettings {
+    fn default() -> Self {
+        Self {
+            failures: 5,
+            open_for: Duration::from_secs(30),
+        }
+    }
+}
+
+impl BreakerSettings {
+    /// Read `<PREFIX>_BREAKER_FAILURES` (default 5) and
+    /// `<PREFIX>_BREAKER_OPEN_SECS` (default 30)
+    pub fn from_env(prefix: &str) -> Result<Self> {
+        let var = |name: &str| -> Result<Option<u64>> {
+            let name = format!("{}_{}", prefix, name);
+            match std::env::var(&name) {
+                Ok(value) => {
+                    let value: u64 = value.parse().with_context(|| format!("invalid {}", name))?;
+                    anyhow::ensure!(value > 0, "{} must be at least 1", name);
+                    Ok(Some(value))
+                }
+                Err(_) => Ok(None),
+            }
+        };
+        let defaults = Self::default();
+        Ok(Self {
+            failures: var("BREAKER_FAILURES")?.map_or(defaults.failures, |value| value as u32),
+            open_for: var("BREAKER_OPEN_SECS")?.map_or(defaults.open_for, Duration::from_secs),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Calls go through
+    Closed,
+    /// The dependency is considered down and calls are refused
+    Open,
+    /// One call is probing whether the dependency is back
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half_open",
+        }
+    }
+
+    /// Value of the state gauge
+    fn gauge(self) -> f64 {
+        match self {
+            BreakerState::Closed => 0.0,
+            BreakerState::HalfOpen => 1.0,
+            BreakerState::Open => 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Tracking {
+    /// Failures since the last success
+    failures: u32,
+    opened_at: Option<Instant>,
+    /// When the call probing a half-open breaker was let through
+    probe_started: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    dependency: String,
+    settings: BreakerSettings,
+    tracking: Mutex<Tracking>,
+    shed: AtomicU64,
+}
+
+/// Circuit breaker for one dependency. After `failures` failed calls in a
+/// row it opens, and calls are refused with [`Unavailable`] instead of
+/// waiting on a dependency that is down. Once `open_for` has passed, one
+/// call is let through: its success closes the breaker, its failure opens
+/// it again. Clones share their state.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    inner: Arc<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(dependency: &str, settings: BreakerSettings) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                dependency: dependency.to_string(),
+                settings,
+                tracking: Mutex::new(Tracking::default()),
+                shed: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn dependency(&self) -> &str {
+        &self.inner.dependency
+    }
+
+    fn tracking(&self) -> std::sync::MutexGuard<'_, Tracking> {
+        self.inner.tracking.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn state(&self) -> BreakerState {
+        let tracking = self.tracking();
+        match tracking.opened_at {
+            None => BreakerState::Closed,
+            Some(opened_at) if opened_at.elapsed() >= self.inner.settings.open_for => BreakerState::HalfOpen,
+            Some(_) => BreakerState::Open,
+        }
+    }
+
+    /// Calls refused so far
+    pub fn shed(&self) -> u64 {
+        self.inner.shed.load(Ordering::Relaxed)
+    }
+
+    /// Let a call through, or refuse it while the breaker is open. A
+    /// half-open breaker lets one call through at a time to probe the
+    /// dependency; a probe that never reports back is replaced after
+    /// another `open_for`.
+    pub fn admit(&self) -> Result<(), Unavailable> {
+        let open_for = self.inner.settings.open_for;
+        let mut tracking = self.tracking();
+        let Some(opened_at) = tracking.opened_at else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let probing = tracking
+            .probe_started
+            .is_some_and(|started| now.duration_since(started) < open_for);
+        if now.duration_since(opened_at) >= open_for && !probing {
+            tracking.probe_started = Some(now);
+            return Ok(());
+        }
+
+        drop(tracking);
+        self.inner.shed.fetch_add(1, Ordering::Relaxed);
+        let retry_after = open_for.saturating_sub(now.duration_since(opened_at));
+        Err(Unavailable {
+            dependency: self.inner.dependency.clone(),
+            // A refused probe still has to wait for the one under way
+            retry_after: retry_after.max(Duration::from_secs(1)),
+        })
+    }
+
+    /// Record a call the dependency answered
+    pub fn succeeded(&self) {
+        let mut tracking = self.tracking();
+        if tracking.opened_at.is_some() {
+            tracing::info!(dependency = %self.inner.dependency, "Dependency is back, closing its circuit breaker");
+        }
+        *tracking = Tracking::default();
+    }
+
+    /// Record a call that failed because of the dependency
+    pub fn failed(&self) {
+        let mut tracking = self.tracking();
+        tracking.failures = tracking.failures.saturating_add(1);
+        tracking.probe_started = None;
+        let reopen = tracking.opened_at.is_some();
+        if reopen || tracking.failures >= self.inner.settings.failures {
+            if !reopen {
+                tracing::warn!(
+                    dependency = %self.inner.dependency,
+                    failures = tracking.failures,
+                    "Opening circuit breaker"
+                );
+            }
+            tracking.opened_at = Some(Instant::now());
+        }
+    }
+
+    /// Run a call through the breaker. Errors for which `counts` is false,
+    /// like the dependency refusing a bad request, don't count against it.
+    pub async fn call<T>(
+        &self,
+        call: impl std::future::Future<Output = Result<T>>,
+        counts: impl FnOnce(&anyhow::Error) -> bool,
+    ) -> Result<T> {
+        self.admit()?;
+        match call.await {
+            Ok(value) => {
+                self.succeeded();
+                Ok(value)
+            }
+            Err(e) => {
+                if counts(&e) {
+                    self.failed();
+                } else {
+                    self.succeeded();
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+/// A call refused because its dependency's breaker is open. Answers with
+/// 503 Service Unavailable and a `Retry-After` for when the breaker will
+/// next let a call through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unavailable {
+    pub dependency: String,
+    pub retry_after: Duration,
+}
+
+impl Unavailable {
+    /// The refusal behind an error, if a breaker refused it
+    pub fn find(error: &anyhow::Error) -> Option<&Unavailable> {
+        error.chain().find_map(|cause| cause.downcast_ref())
+    }
+
+    fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0)
+    }
+}
+
+impl std::fmt::Display for Unavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is unavailable, retry in {}s", self.dependency, self.retry_after_secs())
+    }
+}
+
+impl std::error::Error for Unavailable {}
+
+impl IntoResponse for Unavailable {
+    fn into_response(self) -> Response {
+        let retry_after = self.retry_after_secs();
+        let body = Json(serde_json::json!({
+            "error": self.to_string(),
+            "dependency": self.dependency,
+            "retry_after_seconds": retry_after,
+        }));
+        let mut response = (StatusCode::SERVICE_UNAVAILABLE, body).into_response();
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        response
+    }
+}
+
+/// Response extension a handler sets when it failed because of the
+/// dependency a [`LoadShedding`] guards, so the failure counts against it
+#[derive(Debug, Clone, Copy)]
+pub struct DependencyFailed;
+
+/// Requests a service can't serve without a dependency. While the
+/// dependency's breaker is open they are answered with 503 and
+/// `Retry-After` by [`shed_load`], except `/health`, `/metrics` and the
+/// exempted routes, which work without it.
+#[derive(Debug, Clone)]
+pub struct LoadShedding {
+    breaker: CircuitBreaker,
+    exempt: Arc<Vec<(Method, String)>>,
+}
+
+impl LoadShedding {
+    pub fn new(breaker: CircuitBreaker) -> Self {
+        Self {
+            breaker,
+            exempt: Arc::new(Vec::new()),
+        }
+    }
+
+    /// Serve a route even while the dependency is down, as one that keeps
+    /// working without it
+    pub fn exempt(mut self, method: Method, path: &str) -> Self {
+        Arc::make_mut(&mut self.exempt).push((method, path.to_string()));
+        self
+    }
+
+    fn is_exempt(&self, method: &Method, path: &str) -> bool {
+        ALWAYS_SERVED.iter().any(|served| path == *served || path.starts_with(&format!("{}/", served)))
+            || self.exempt.iter().any(|(m, p)| m == method && p == path)
+    }
+}
+
+/// Middleware shedding requests while a dependency is down; see
+/// [`LoadShedding`]. Responses carrying [`DependencyFailed`] count as
+/// failures of the dependency and successful ones as successes.
+pub async fn shed_load(State(shedding): State<LoadShedding>, request: Request, next: Next) -> Response {
+    if shedding.is_exempt(request.method(), request.uri().path()) {
+        return next.run(request).await;
+    }
+    if let Err(unavailable) = shedding.breaker.admit() {
+        return unavailable.into_response();
+    }
+    let response = next.run(request).await;
+    if response.extensions().get::<DependencyFailed>().is_some() {
+        shedding.breaker.failed();
+    } else if response.status().is_success() {
+        shedding.breaker.succeeded();
+    }
+    response
+}
+
+/// Export the state of every breaker as `sandstorm_dependency_breaker_state`
+/// (0 closed, 1 half-open, 2 open) and the calls each refused as
+/// `sandstorm_dependency_requests_shed_total`, labelled by `dependency`
+pub fn register_breakers(registry: &Registry, breakers: &[CircuitBreaker]) -> prometheus::Result<()> {
+    let state = GaugeVec::new(
+        Opts::new(
+            "dependency_breaker_state",
+            "Circuit breaker state by dependency: 0 closed, 1 half-open, 2 open",
+        ),
+        &["dependency"],
+    )?;
+    let shed = IntCounterVec::new(
+        Opts::new(
+            "dependency_requests_shed_total",
+            "Calls refused while a dependency's circuit breaker was open",
+        ),
+        &["dependency"],
+    )?;
+    registry.register(Box::new(BreakerCollector {
+        breakers: breakers.to_vec(),
+        state,
+        shed,
+    }))
+}
+
+/// Reads the breakers when scraped
+struct BreakerCollector {
+    breakers: Vec<CircuitBreaker>,
+    state: GaugeVec,
+    shed: IntCounterVec,
+}
+
+impl Collector for BreakerCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.state.desc().into_iter().chain(self.shed.desc()).collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        for breaker in &self.breakers {
+            let labels = [breaker.dependency()];
+            self.state.with_label_values(&labels).set(breaker.state().gauge());
+            let shed = self.shed.with_label_values(&labels);
+            shed.inc_by(breaker.shed().saturating_sub(shed.get()));
+        }
+        self.state.collect().into_iter().chain(self.shed.collect()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn breaker(open_for: Duration) -> CircuitBreaker {
+        CircuitBreaker::new("postgres", BreakerSettings { failures: 2, open_for })
+    }
+
+    #[test]
+    fn opens_after_failures_and_probes_once_open_for_has_passed() {
+        let breaker = breaker(Duration::from_millis(50));
+        breaker.failed();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        breaker.succeeded();
+        breaker.failed();
+        assert!(breaker.admit().is_ok());
+        breaker.failed();
+        assert_eq!(breaker.state(), BreakerState::Open);
+        let refused = breaker.admit().unwrap_err();
+        assert_eq!(refused.dependency, "postgres");
+        assert_eq!(refused.retry_after, Duration::from_secs(1));
+        assert_eq!(breaker.shed(), 1);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert!(breaker.admit().is_ok());
+        // Only one probe at a time, and its failure opens the breaker again
+        assert!(breaker.admit().is_err());
+        breaker.failed();
+        assert_eq!(breaker.state(), BreakerState::Open);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.admit().is_ok());
+        breaker.succeeded();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert!(breaker.admit().is_ok());
+    }
+
+    #[tokio::test]
+    async fn sheds_requests_with_retry_after_while_open() {
+        let breaker = breaker(Duration::from_secs(30));
+        let shedding = LoadShedding::new(breaker.clone()).exempt(Method::POST, "/events");
+        let app = Router::new()
+            .route("/events", get(|| async { "events" }).post(|| async { "queued" }))
+            .route("/health", get(|| async { "ok" }))
+            .route(
+                "/broken",
+                get(|| async {
+                    let mut response = StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                    response.extensions_mut().insert(DependencyFailed);
+                    response
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(shedding, shed_load));
+        let send = |method: Method, uri: &str| {
+            let request = Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(request)
+        };
+
+        for _ in 0..2 {
+            let response = send(Method::GET, "/broken").await.unwrap();
+            assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        assert_eq!(breaker.state(), BreakerState::Open);
+
+        let response = send(Method::GET, "/events").await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+        assert_eq!(send(Method::POST, "/events").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(send(Method::GET, "/health").await.unwrap().status(), StatusCode::OK);
+
+        let registry = Registry::new();
+        register_breakers(&registry, &[breaker]).unwrap();
+        let families = registry.gather();
+        let state = families.iter().find(|family| family.get_name() == "dependency_breaker_state").unwrap();
+        assert_eq!(state.get_metric()[0].get_gauge().get_value(), 2.0);
+    }
+}
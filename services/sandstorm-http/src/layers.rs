@@ -0,0 +1,144 @@
+use axum::http::{header, HeaderName, HeaderValue};
+use axum::Router;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::set_header::SetResponseHeaderLayer;
+
+use crate::settings::{CorsOrigins, HttpSecurity, Mode};
+
+/// How long browsers may cache a preflight response
+const PREFLIGHT_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// Headers every response gets, unless the handler set them itself. The
+/// services serve JSON, so nothing may frame or embed their responses.
+const SECURITY_HEADERS: &[(HeaderName, &str)] = &[
+    (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+    (header::X_FRAME_OPTIONS, "DENY"),
+    (header::REFERRER_POLICY, "no-referrer"),
+    (header::CONTENT_SECURITY_POLICY, "default-src 'none'; frame-ancestors 'none'"),
+];
+
+/// Sent in production, where services sit behind TLS
+const HSTS: &str = "max-age=31536000; includeSubDomains";
+
+/// Whether an origin is a page served from this machine
+fn is_localhost(origin: &HeaderValue) -> bool {
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+    let Some(host) = origin
+        .strip_prefix("http://")
+        .or_else(|| origin.strip_prefix("https://"))
+    else {
+        return false;
+    };
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => host,
+    };
+    matches!(host, "localhost" | "127.0.0.1" | "[::1]")
+}
+
+impl HttpSecurity {
+    /// The CORS layer for these settings
+    pub fn cors(&self) -> CorsLayer {
+        let origins = match &self.origins {
+            CorsOrigins::List(origins) => AllowOrigin::list(origins.clone()),
+            CorsOrigins::Localhost => AllowOrigin::predicate(|origin, _| is_localhost(origin)),
+            CorsOrigins::Any => return CorsLayer::permissive(),
+        };
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(self.methods.clone())
+            .allow_headers(self.headers.clone())
+            .max_age(PREFLIGHT_MAX_AGE)
+    }
+
+    /// Wrap a router with CORS and, unless turned off, the security headers
+    pub fn apply<S>(&self, router: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let mut router = router;
+        if self.security_headers {
+            for (name, value) in SECURITY_HEADERS {
+                router = router.layer(SetResponseHeaderLayer::if_not_present(
+                    name.clone(),
+                    HeaderValue::from_static(value),
+                ));
+            }
+            if self.mode == Mode::Production {
+                router = router.layer(SetResponseHeaderLayer::if_not_present(
+                    header::STRICT_TRANSPORT_SECURITY,
+                    HeaderValue::from_static(HSTS),
+                ));
+            }
+        }
+        // Outermost, so preflights are answered before anything else runs
+        router.layer(self.cors())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode},
+        routing::get,
+    };
+    use tower::ServiceExt;
+
+    fn settings(origins: CorsOrigins, mode: Mode) -> HttpSecurity {
+        HttpSecurity {
+            mode,
+            origins,
+            methods: vec![Method::GET, Method::POST],
+            headers: vec![header::CONTENT_TYPE],
+            security_headers: true,
+        }
+    }
+
+    async fn get_from(settings: &HttpSecurity, origin: &str) -> axum::response::Response {
+        let app = settings.apply(Router::new().route("/", get(|| async { "ok" })));
+        app.oneshot(
+            Request::builder()
+                .uri("/")
+                .header(header::ORIGIN, origin)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn allows_only_configured_origins_and_sets_security_headers() {
+        let allowed = HeaderValue::from_static("https://dashboard.example.com");
+        let settings = settings(CorsOrigins::List(vec![allowed.clone()]), Mode::Production);
+
+        let response = get_from(&settings, "https://dashboard.example.com").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], allowed);
+        assert_eq!(response.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(response.headers()[header::STRICT_TRANSPORT_SECURITY], HSTS);
+
+        let response = get_from(&settings, "https://evil.example.com").await;
+        assert!(!response.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn development_allows_localhost_without_hsts() {
+        let settings = settings(CorsOrigins::Localhost, Mode::Development);
+
+        let response = get_from(&settings, "http://localhost:5173").await;
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "http://localhost:5173");
+        assert!(!response.headers().contains_key(header::STRICT_TRANSPORT_SECURITY));
+
+        let response = get_from(&settings, "http://localhost.example.com").await;
+        assert!(!response.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        assert!(is_localhost(&HeaderValue::from_static("https://127.0.0.1")));
+        assert!(is_localhost(&HeaderValue::from_static("http://[::1]:3000")));
+        assert!(!is_localhost(&HeaderValue::from_static("http://localhost:80.evil.com")));
+    }
+}
@@ -0,0 +1,25 @@
+//! CORS and security headers shared by the Sandstorm services.
+//!
+//! Each service reads [`HttpSecurity`] from `<PREFIX>_CORS_*` and
+//! `<PREFIX>_SECURITY_HEADERS` environment variables and wraps its router
+//! with [`HttpSecurity::apply`]. `SANDSTORM_ENV=production` switches every
+//! service to strict defaults: only configured origins may make
+//! cross-origin requests, the permissive development flag is refused, and
+//! responses carry `Strict-Transport-Security`.
+//!
+//! In development, the default, browsers may call a service from any
+//! `localhost` origin, so a local dashboard works without configuration.
+//!
+//! [`CircuitBreaker`] guards calls to a service's dependencies, and
+//! [`shed_load`] answers requests that need a dependency that is down with
+//! 503 and `Retry-After` instead of letting them pile up on it.
+
+mod breaker;
+mod layers;
+mod settings;
+
+pub use breaker::{
+    register_breakers, shed_load, BreakerSettings, BreakerState, CircuitBreaker, DependencyFailed,
+    LoadShedding, Unavailable,
+};
+pub use settings::{CorsOrigins, HttpSecurity, Mode};
@@ -0,0 +1,184 @@
+use anyhow::{bail, Context, Result};
+use axum::http::{HeaderName, HeaderValue, Method};
+
+/// Methods allowed cross-origin unless configured otherwise
+const DEFAULT_METHODS: &[&str] = &["GET", "POST", "PUT", "DELETE", "OPTIONS"];
+
+/// Request headers allowed cross-origin unless configured otherwise: the
+/// standard ones plus the Sandstorm headers callers set
+const DEFAULT_HEADERS: &[&str] = &[
+    "authorization",
+    "content-type",
+    "traceparent",
+    "x-sandstorm-run-id",
+    "x-sandstorm-tenant",
+    "x-sandstorm-user",
+];
+
+/// Deployment mode, from `SANDSTORM_ENV`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Development,
+    Production,
+}
+
+impl Mode {
+    /// `SANDSTORM_ENV=production` (or `prod`); anything else is development
+    pub fn from_env() -> Self {
+        match std::env::var("SANDSTORM_ENV").as_deref() {
+            Ok("production" | "prod") => Mode::Production,
+            _ => Mode::Development,
+        }
+    }
+}
+
+/// Origins allowed to make cross-origin requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorsOrigins {
+    /// Exactly these origins
+    List(Vec<HeaderValue>),
+    /// `http(s)://localhost` and `127.0.0.1` on any port
+    Localhost,
+    /// Any origin; development only
+    Any,
+}
+
+/// CORS and security header settings for one service
+#[derive(Debug, Clone)]
+pub struct HttpSecurity {
+    pub mode: Mode,
+    pub origins: CorsOrigins,
+    pub methods: Vec<Method>,
+    pub headers: Vec<HeaderName>,
+    /// Add the standard security headers to responses
+    pub security_headers: bool,
+}
+
+impl HttpSecurity {
+    /// Read settings from `<PREFIX>_CORS_*` and `<PREFIX>_SECURITY_HEADERS`
+    /// environment variables, in the mode `SANDSTORM_ENV` sets.
+    ///
+    /// - `<PREFIX>_CORS_ORIGINS`: comma-separated origins allowed to call the
+    ///   service. Unset means `localhost` origins in development and none in
+    ///   production.
+    /// - `<PREFIX>_CORS_PERMISSIVE=true`: allow any origin, method and
+    ///   header. Refused in production.
+    /// - `<PREFIX>_CORS_METHODS`, `<PREFIX>_CORS_HEADERS`: comma-separated
+    ///   methods and request headers allowed cross-origin.
+    /// - `<PREFIX>_SECURITY_HEADERS=false`: leave responses' headers alone.
+    pub fn from_env(prefix: &str) -> Result<Self> {
+        let var = |name: &str| std::env::var(format!("{}_{}", prefix, name)).ok();
+        let flag = |name: &str| var(name).map(|value| matches!(value.as_str(), "1" | "true" | "yes" | "on"));
+        Self::new(
+            Mode::from_env(),
+            prefix,
+            var("CORS_ORIGINS").as_deref(),
+            flag("CORS_PERMISSIVE").unwrap_or(false),
+            var("CORS_METHODS").as_deref(),
+            var("CORS_HEADERS").as_deref(),
+            flag("SECURITY_HEADERS").unwrap_or(true),
+        )
+    }
+
+    fn new(
+        mode: Mode,
+        prefix: &str,
+        origins: Option<&str>,
+        permissive: bool,
+        methods: Option<&str>,
+        headers: Option<&str>,
+        security_headers: bool,
+    ) -> Result<Self> {
+        if permissive && mode == Mode::Production {
+            bail!("{}_CORS_PERMISSIVE is not allowed with SANDSTORM_ENV=production", prefix);
+        }
+
+        let origins = match origins {
+            _ if permissive => CorsOrigins::Any,
+            Some(origins) => CorsOrigins::List(
+                list(origins)
+                    .map(|origin| {
+                        if origin == "*" {
+                            bail!("{0}_CORS_ORIGINS can't be *; use {0}_CORS_PERMISSIVE in development", prefix);
+                        }
+                        HeaderValue::from_str(origin.trim_end_matches('/'))
+                            .with_context(|| format!("invalid origin {:?} in {}_CORS_ORIGINS", origin, prefix))
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            None if mode == Mode::Production => CorsOrigins::List(Vec::new()),
+            None => CorsOrigins::Localhost,
+        };
+
+        let methods = list(methods.unwrap_or(&DEFAULT_METHODS.join(",")))
+            .map(|method| {
+                Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                    .with_context(|| format!("invalid method {:?} in {}_CORS_METHODS", method, prefix))
+            })
+            .collect::<Result<_>>()?;
+        let headers = list(headers.unwrap_or(&DEFAULT_HEADERS.join(",")))
+            .map(|header| {
+                HeaderName::from_bytes(header.to_ascii_lowercase().as_bytes())
+                    .with_context(|| format!("invalid header {:?} in {}_CORS_HEADERS", header, prefix))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            mode,
+            origins,
+            methods,
+            headers,
+            security_headers,
+        })
+    }
+}
+
+fn list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|item| !item.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_depend_on_mode() {
+        let dev = HttpSecurity::new(Mode::Development, "TEST", None, false, None, None, true).unwrap();
+        assert_eq!(dev.origins, CorsOrigins::Localhost);
+        assert_eq!(dev.methods.len(), DEFAULT_METHODS.len());
+
+        let prod = HttpSecurity::new(Mode::Production, "TEST", None, false, None, None, true).unwrap();
+        assert_eq!(prod.origins, CorsOrigins::List(Vec::new()));
+
+        let permissive = HttpSecurity::new(Mode::Development, "TEST", None, true, None, None, true).unwrap();
+        assert_eq!(permissive.origins, CorsOrigins::Any);
+        let error = HttpSecurity::new(Mode::Production, "TEST", None, true, None, None, true).unwrap_err();
+        assert!(error.to_string().contains("TEST_CORS_PERMISSIVE"));
+    }
+
+    #[test]
+    fn parses_configured_origins_methods_and_headers() {
+        let settings = HttpSecurity::new(
+            Mode::Production,
+            "TEST",
+            Some("https://dashboard.example.com/, https://ops.example.com"),
+            false,
+            Some("get,post"),
+            Some("Content-Type"),
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.origins,
+            CorsOrigins::List(vec![
+                HeaderValue::from_static("https://dashboard.example.com"),
+                HeaderValue::from_static("https://ops.example.com"),
+            ])
+        );
+        assert_eq!(settings.methods, vec![Method::GET, Method::POST]);
+        assert_eq!(settings.headers, vec![HeaderName::from_static("content-type")]);
+
+        assert!(HttpSecurity::new(Mode::Development, "TEST", Some("*"), false, None, None, true).is_err());
+        assert!(HttpSecurity::new(Mode::Development, "TEST", None, false, Some("GET POST"), None, true).is_err());
+    }
+}
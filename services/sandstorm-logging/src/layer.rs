@@ -0,0 +1,228 @@
+use chrono::Utc;
+use sandstorm_types::logs::LogRecord;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::settings::ShippingSettings;
+use crate::sink;
+
+/// Targets never shipped: the HTTP stack the shipper itself posts with,
+/// whose events would otherwise be shipped in turn, and the shipper's own
+/// complaints about the sink
+const EXCLUDED_TARGETS: &[&str] = &["sandstorm_logging", "reqwest", "hyper", "h2", "rustls"];
+
+/// Tracing layer that ships events to the configured sink. Add it next to
+/// the service's formatting layer; it sees whatever the subscriber's filter
+/// lets through.
+pub struct ShippingLayer {
+    service: String,
+    level: Level,
+    debug_sample_rate: f64,
+    /// Debug and trace events seen, to sample every n-th
+    verbose_seen: AtomicU64,
+    queue: mpsc::Sender<LogRecord>,
+    /// Records dropped because the queue was full
+    dropped: Arc<AtomicU64>,
+}
+
+impl ShippingLayer {
+    /// Start shipping `service`'s logs. Must be called inside a Tokio
+    /// runtime, which runs the task that sends batches.
+    pub fn spawn(settings: ShippingSettings, service: &str) -> Self {
+        let (layer, records) = Self::new(&settings, service);
+        tokio::spawn(sink::run(settings, service.to_string(), records, layer.dropped.clone()));
+        layer
+    }
+
+    fn new(settings: &ShippingSettings, service: &str) -> (Self, mpsc::Receiver<LogRecord>) {
+        let (queue, records) = mpsc::channel(settings.queue_size.max(1));
+        let layer = Self {
+            service: service.to_string(),
+            level: settings.level,
+            debug_sample_rate: settings.debug_sample_rate,
+            verbose_seen: AtomicU64::new(0),
+            queue,
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        (layer, records)
+    }
+
+    /// Whether to ship this debug or trace event: an even
+    /// `debug_sample_rate` share of them, without needing randomness
+    fn sample(&self) -> bool {
+        let seen = self.verbose_seen.fetch_add(1, Ordering::Relaxed) as f64;
+        ((seen + 1.0) * self.debug_sample_rate).floor() > (seen * self.debug_sample_rate).floor()
+    }
+}
+
+impl fmt::Debug for ShippingLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShippingLayer")
+            .field("service", &self.service)
+            .field("level", &self.level)
+            .field("debug_sample_rate", &self.debug_sample_rate)
+            .finish()
+    }
+}
+
+/// Fields recorded on a span, kept in its extensions for the events inside it
+struct SpanFields(Map<String, Value>);
+
+impl<S> Layer<S> for ShippingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = Map::new();
+        attrs.record(&mut JsonVisitor(&mut fields));
+        span.extensions_mut().insert(SpanFields(fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(fields) = extensions.get_mut::<SpanFields>() {
+            values.record(&mut JsonVisitor(&mut fields.0));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        // More verbose levels compare greater
+        if *metadata.level() > self.level {
+            return;
+        }
+        let target = metadata.target();
+        if EXCLUDED_TARGETS
+            .iter()
+            .any(|excluded| target == *excluded || target.starts_with(&format!("{}::", excluded)))
+        {
+            return;
+        }
+        if *metadata.level() >= Level::DEBUG && !self.sample() {
+            return;
+        }
+
+        // Outer spans first, so inner spans and the event itself win
+        let mut fields = Map::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(span_fields) = span.extensions().get::<SpanFields>() {
+                    fields.extend(span_fields.0.clone());
+                }
+            }
+        }
+        event.record(&mut JsonVisitor(&mut fields));
+
+        let mut take = |name: &str| match fields.remove(name) {
+            Some(Value::String(value)) => Some(value),
+            Some(other) => Some(other.to_string()),
+            None => None,
+        };
+        let record = LogRecord {
+            timestamp: Utc::now(),
+            level: metadata.level().as_str().to_lowercase(),
+            target: target.to_string(),
+            message: take("message").unwrap_or_default(),
+            sandbox_id: take("sandbox_id"),
+            run_id: take("run_id"),
+            fields,
+        };
+        if self.queue.try_send(record).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Records fields as JSON, keeping numbers and booleans typed
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_string(), Value::from(format!("{:?}", value)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::LogSink;
+    use std::time::Duration;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn settings(debug_sample_rate: f64) -> ShippingSettings {
+        ShippingSettings {
+            sink: LogSink::Telemetry,
+            url: "http://localhost:3001".to_string(),
+            token: None,
+            level: Level::DEBUG,
+            debug_sample_rate,
+            batch_size: 100,
+            flush_interval: Duration::from_secs(1),
+            queue_size: 100,
+        }
+    }
+
+    #[test]
+    fn picks_up_correlation_fields_from_spans() {
+        let (layer, mut records) = ShippingLayer::new(&settings(1.0), "gateway");
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("run", run_id = "r-1", sandbox_id = tracing::field::Empty);
+            let _entered = span.enter();
+            span.record("sandbox_id", "s-1");
+            tracing::info!(target: "sandstorm_gateway", exit_code = 0, "Sandbox finished");
+            tracing::trace!(target: "sandstorm_gateway", "too verbose");
+            tracing::debug!(target: "hyper::client", "the shipper's own request");
+        });
+
+        let record = records.try_recv().unwrap();
+        assert_eq!(record.message, "Sandbox finished");
+        assert_eq!((record.sandbox_id.as_deref(), record.run_id.as_deref()), (Some("s-1"), Some("r-1")));
+        assert_eq!(record.fields.get("exit_code"), Some(&Value::from(0)));
+        assert_eq!(record.level, "info");
+        assert!(records.try_recv().is_err());
+    }
+
+    #[test]
+    fn samples_verbose_events_evenly() {
+        let (layer, _records) = ShippingLayer::new(&settings(0.25), "gateway");
+        assert_eq!((0..100).filter(|_| layer.sample()).count(), 25);
+        let (layer, _records) = ShippingLayer::new(&settings(0.0), "gateway");
+        assert!(!(0..100).any(|_| layer.sample()));
+    }
+}
@@ -0,0 +1,18 @@
+//! Log shipping shared by the Sandstorm services.
+//!
+//! Each service adds a [`ShippingLayer`] to its tracing subscriber when
+//! `<PREFIX>_LOG_SINK` is set. Events are turned into structured
+//! [`LogRecord`](sandstorm_types::logs::LogRecord)s, with `sandbox_id` and
+//! `run_id` picked up from the event or any span it happened in, and posted
+//! in batches to Loki's push API or the telemetry collector. Debug and
+//! trace events are sampled so a chatty module can't flood the sink.
+//!
+//! Shipping never blocks logging: records go through a bounded queue and
+//! are dropped, and counted, when the sink falls behind.
+
+mod layer;
+mod settings;
+mod sink;
+
+pub use layer::ShippingLayer;
+pub use settings::{LogSink, ShippingSettings};
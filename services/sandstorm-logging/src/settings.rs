@@ -0,0 +1,106 @@
+use anyhow::{bail, Context, Result};
+use std::time::Duration;
+use tracing::Level;
+
+/// Where logs are shipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogSink {
+    /// Loki's `/loki/api/v1/push`
+    Loki,
+    /// The telemetry collector's `/api/telemetry/logs`
+    Telemetry,
+}
+
+impl LogSink {
+    pub fn path(self) -> &'static str {
+        match self {
+            LogSink::Loki => "/loki/api/v1/push",
+            LogSink::Telemetry => "/api/telemetry/logs",
+        }
+    }
+}
+
+/// Log shipping settings for one service
+#[derive(Debug, Clone)]
+pub struct ShippingSettings {
+    pub sink: LogSink,
+    /// Base URL of the sink, e.g. `http://loki:3100`
+    pub url: String,
+    /// Sent as `Authorization: Bearer <token>` when set
+    pub token: Option<String>,
+    /// Most verbose level shipped
+    pub level: Level,
+    /// Fraction of debug and trace events shipped
+    pub debug_sample_rate: f64,
+    /// Records per request
+    pub batch_size: usize,
+    /// Longest a record waits before its batch is sent
+    pub flush_interval: Duration,
+    /// Records held for the sink before new ones are dropped
+    pub queue_size: usize,
+}
+
+impl ShippingSettings {
+    /// Read settings from `<PREFIX>_LOG_*` environment variables.
+    ///
+    /// Returns `None` when `<PREFIX>_LOG_SINK` is unset, i.e. logs are only
+    /// written locally.
+    ///
+    /// - `<PREFIX>_LOG_SINK`: `loki` or `telemetry`
+    /// - `<PREFIX>_LOG_SINK_URL`: base URL of the sink (required)
+    /// - `<PREFIX>_LOG_SINK_TOKEN`: bearer token for the sink
+    /// - `<PREFIX>_LOG_SHIP_LEVEL`: most verbose level shipped (default
+    ///   `debug`)
+    /// - `<PREFIX>_LOG_DEBUG_SAMPLE_RATE`: fraction of debug and trace events
+    ///   shipped, 0 to 1 (default 0.1)
+    /// - `<PREFIX>_LOG_BATCH_SIZE`, `<PREFIX>_LOG_FLUSH_SECS`: records per
+    ///   request (default 500) and longest wait before sending (default 5)
+    pub fn from_env(prefix: &str) -> Result<Option<Self>> {
+        let var = |name: &str| std::env::var(format!("{}_LOG_{}", prefix, name)).ok();
+        fn parse<T: std::str::FromStr>(prefix: &str, name: &str, value: Option<String>, default: T) -> Result<T> {
+            match value {
+                Some(value) => value
+                    .parse()
+                    .ok()
+                    .with_context(|| format!("invalid {}_LOG_{}: {:?}", prefix, name, value)),
+                None => Ok(default),
+            }
+        }
+
+        let sink = match var("SINK").as_deref() {
+            None | Some("") => return Ok(None),
+            Some("loki") => LogSink::Loki,
+            Some("telemetry") => LogSink::Telemetry,
+            Some(other) => bail!("{}_LOG_SINK must be loki or telemetry, not {:?}", prefix, other),
+        };
+        let url = var("SINK_URL")
+            .with_context(|| format!("{}_LOG_SINK_URL is required with {}_LOG_SINK", prefix, prefix))?
+            .trim_end_matches('/')
+            .to_string();
+
+        let debug_sample_rate: f64 = parse(prefix, "DEBUG_SAMPLE_RATE", var("DEBUG_SAMPLE_RATE"), 0.1)?;
+        if !(0.0..=1.0).contains(&debug_sample_rate) {
+            bail!("{}_LOG_DEBUG_SAMPLE_RATE must be between 0 and 1", prefix);
+        }
+        let batch_size = parse(prefix, "BATCH_SIZE", var("BATCH_SIZE"), 500usize)?;
+        if batch_size == 0 {
+            bail!("{}_LOG_BATCH_SIZE must be positive", prefix);
+        }
+
+        let flush_secs = parse(prefix, "FLUSH_SECS", var("FLUSH_SECS"), 5)?;
+        if flush_secs == 0 {
+            bail!("{}_LOG_FLUSH_SECS must be positive", prefix);
+        }
+
+        Ok(Some(Self {
+            sink,
+            url,
+            token: var("SINK_TOKEN"),
+            level: parse(prefix, "SHIP_LEVEL", var("SHIP_LEVEL"), Level::DEBUG)?,
+            debug_sample_rate,
+            batch_size,
+            flush_interval: Duration::from_secs(flush_secs),
+            queue_size: 10_000,
+        }))
+    }
+}
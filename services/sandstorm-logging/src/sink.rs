@@ -0,0 +1,165 @@
+use sandstorm_types::logs::{LogBatch, LogRecord};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::settings::{LogSink, ShippingSettings};
+
+/// Attempts at sending a batch before it is dropped
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Collect queued records into batches and send them, until the layer is
+/// dropped
+pub(crate) async fn run(
+    settings: ShippingSettings,
+    service: String,
+    mut records: mpsc::Receiver<LogRecord>,
+    dropped: Arc<AtomicU64>,
+) {
+    let http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default();
+    let instance = instance();
+    let mut batch = Vec::with_capacity(settings.batch_size);
+    let mut flush = tokio::time::interval(settings.flush_interval);
+
+    loop {
+        let closed = tokio::select! {
+            record = records.recv() => match record {
+                Some(record) => {
+                    batch.push(record);
+                    if batch.len() < settings.batch_size {
+                        continue;
+                    }
+                    false
+                }
+                None => true,
+            },
+            _ = flush.tick() => false,
+        };
+
+        let lost = dropped.swap(0, Ordering::Relaxed);
+        if lost > 0 {
+            warn!("Dropped {} log record(s) while the log sink was behind", lost);
+        }
+        if !batch.is_empty() {
+            let body = body(settings.sink, &service, &instance, std::mem::take(&mut batch));
+            send(&http, &settings, &body).await;
+        }
+        if closed {
+            break;
+        }
+    }
+}
+
+/// Post a batch, retrying failures that may be temporary
+async fn send(http: &reqwest::Client, settings: &ShippingSettings, body: &Value) {
+    let url = format!("{}{}", settings.url, settings.sink.path());
+    let mut backoff = Duration::from_millis(500);
+    for attempt in 1..=MAX_ATTEMPTS {
+        let mut request = http.post(&url).json(body);
+        if let Some(token) = &settings.token {
+            request = request.bearer_auth(token);
+        }
+        let error = match request.send().await {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) if response.status().is_client_error() => {
+                warn!(status = %response.status(), "Log sink rejected a batch; dropping it");
+                return;
+            }
+            Ok(response) => format!("log sink answered {}", response.status()),
+            Err(e) => format!("failed to reach log sink: {}", e),
+        };
+        if attempt == MAX_ATTEMPTS {
+            warn!("Dropping a log batch: {}", error);
+            return;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+/// Request body for the sink. Loki gets one stream per level, labelled
+/// with the service and instance; sandbox and run IDs stay in the JSON
+/// lines, since labels with that many values would swamp Loki's index.
+fn body(sink: LogSink, service: &str, instance: &str, records: Vec<LogRecord>) -> Value {
+    match sink {
+        LogSink::Telemetry => json!(LogBatch {
+            batch_id: Uuid::new_v4(),
+            service: service.to_string(),
+            instance: instance.to_string(),
+            records,
+        }),
+        LogSink::Loki => {
+            let mut streams: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+            for record in records {
+                let nanos = record.timestamp.timestamp_nanos_opt().unwrap_or_default();
+                let line = serde_json::to_string(&record).unwrap_or_default();
+                streams
+                    .entry(record.level.clone())
+                    .or_default()
+                    .push(json!([nanos.to_string(), line]));
+            }
+            let streams: Vec<Value> = streams
+                .into_iter()
+                .map(|(level, values)| {
+                    json!({
+                        "stream": {"service": service, "instance": instance, "level": level},
+                        "values": values,
+                    })
+                })
+                .collect();
+            json!({ "streams": streams })
+        }
+    }
+}
+
+/// Host name the service runs on, to tell replicas apart
+fn instance() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn groups_loki_streams_by_level() {
+        let record = |level: &str, message: &str| LogRecord {
+            timestamp: Utc.timestamp_opt(1_700_000_000, 5).unwrap(),
+            level: level.to_string(),
+            target: "sandstorm_gateway".to_string(),
+            message: message.to_string(),
+            sandbox_id: Some("s-1".to_string()),
+            run_id: None,
+            fields: Default::default(),
+        };
+        let body = body(
+            LogSink::Loki,
+            "gateway",
+            "host-1",
+            vec![record("info", "one"), record("warn", "two"), record("info", "three")],
+        );
+
+        let streams = body["streams"].as_array().unwrap();
+        assert_eq!(streams.len(), 2);
+        assert_eq!(streams[0]["stream"], json!({"service": "gateway", "instance": "host-1", "level": "info"}));
+        let values = streams[0]["values"].as_array().unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0][0], "1700000000000000005");
+        let line: LogRecord = serde_json::from_str(values[1][1].as_str().unwrap()).unwrap();
+        assert_eq!((line.message.as_str(), line.sandbox_id.as_deref()), ("three", Some("s-1")));
+    }
+}
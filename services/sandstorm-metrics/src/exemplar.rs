@@ -0,0 +1,111 @@
+use prometheus::HistogramVec;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The observation a bucket last recorded, with the trace it belonged to
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Exemplar {
+    pub trace_id: String,
+    pub value: f64,
+    pub timestamp: f64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Series {
+    pub label_names: Vec<String>,
+    pub buckets: Vec<f64>,
+    /// Latest exemplar per label values, one slot per bucket plus `+Inf`
+    pub exemplars: HashMap<Vec<String>, Vec<Option<Exemplar>>>,
+}
+
+/// Exemplars of every histogram in a registry, keyed by full metric name
+#[derive(Debug, Default)]
+pub(crate) struct ExemplarStore {
+    series: Mutex<HashMap<String, Series>>,
+}
+
+impl ExemplarStore {
+    fn define(&self, name: &str, label_names: &[&str], buckets: &[f64]) {
+        self.series.lock().unwrap().insert(
+            name.to_string(),
+            Series {
+                label_names: label_names.iter().map(|name| name.to_string()).collect(),
+                buckets: buckets.to_vec(),
+                exemplars: HashMap::new(),
+            },
+        );
+    }
+
+    fn record(&self, name: &str, labels: &[&str], value: f64, trace_id: &str) {
+        let mut series = self.series.lock().unwrap();
+        let Some(series) = series.get_mut(name) else {
+            return;
+        };
+        let bucket = series
+            .buckets
+            .iter()
+            .position(|upper| value <= *upper)
+            .unwrap_or(series.buckets.len());
+        let slots = series
+            .exemplars
+            .entry(labels.iter().map(|label| label.to_string()).collect())
+            .or_insert_with(|| vec![None; series.buckets.len() + 1]);
+        slots[bucket] = Some(Exemplar {
+            trace_id: trace_id.to_string(),
+            value,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs_f64())
+                .unwrap_or_default(),
+        });
+    }
+
+    /// Run `f` over the stored series for a metric, if it has any
+    pub fn with_series<T>(&self, name: &str, f: impl FnOnce(&Series) -> T) -> Option<T> {
+        self.series.lock().unwrap().get(name).map(f)
+    }
+}
+
+/// Histogram that remembers, per bucket, the trace ID of its latest
+/// observation
+#[derive(Clone)]
+pub struct ExemplarHistogram {
+    inner: HistogramVec,
+    name: String,
+    store: Arc<ExemplarStore>,
+}
+
+impl ExemplarHistogram {
+    pub(crate) fn new(
+        inner: HistogramVec,
+        name: String,
+        label_names: &[&str],
+        buckets: Vec<f64>,
+        store: Arc<ExemplarStore>,
+    ) -> Self {
+        store.define(&name, label_names, &buckets);
+        Self { inner, name, store }
+    }
+
+    /// Record a value, keeping `trace_id` as the bucket's exemplar
+    pub fn observe(&self, labels: &[&str], value: f64, trace_id: Option<&str>) {
+        self.inner.with_label_values(labels).observe(value);
+        if let Some(trace_id) = trace_id.filter(|id| !id.is_empty()) {
+            self.store.record(&self.name, labels, value, trace_id);
+        }
+    }
+
+    /// The underlying histogram, for reads
+    pub fn inner(&self) -> &HistogramVec {
+        &self.inner
+    }
+}
+
+impl std::fmt::Debug for ExemplarHistogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExemplarHistogram")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
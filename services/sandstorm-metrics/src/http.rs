@@ -0,0 +1,133 @@
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{header, HeaderMap};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use std::time::Instant;
+
+use crate::openmetrics::{OPENMETRICS_FORMAT, TEXT_FORMAT};
+use crate::Metrics;
+
+/// Header carrying a plain trace ID when there is no W3C `traceparent`
+const TRACE_ID_HEADER: &str = "x-trace-id";
+
+/// Trace ID of a request, from its W3C `traceparent` header or `X-Trace-Id`
+pub fn trace_id(headers: &HeaderMap) -> Option<String> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+    };
+
+    // traceparent: version-traceid-parentid-flags
+    let from_traceparent = header("traceparent")
+        .and_then(|value| value.split('-').nth(1))
+        .filter(|id| id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit()))
+        .filter(|id| id.chars().any(|c| c != '0'));
+    from_traceparent
+        .or_else(|| header(TRACE_ID_HEADER).filter(|id| !id.is_empty() && id.len() <= 64))
+        .map(str::to_string)
+}
+
+/// Middleware recording request counts and latency, with the request's
+/// trace ID as exemplar
+pub async fn track_http(State(metrics): State<Metrics>, request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+    let trace_id = trace_id(request.headers());
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+    metrics.observe_http(
+        &route,
+        &method,
+        response.status().as_u16(),
+        started.elapsed().as_secs_f64(),
+        trace_id.as_deref(),
+    );
+    response
+}
+
+/// `GET /metrics`, in OpenMetrics format when the scraper accepts it
+pub fn metrics_router(metrics: Metrics) -> Router {
+    Router::new()
+        .route("/metrics", get(export))
+        .with_state(metrics)
+}
+
+async fn export(State(metrics): State<Metrics>, headers: HeaderMap) -> impl IntoResponse {
+    let openmetrics = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/openmetrics-text"));
+    let content_type = if openmetrics { OPENMETRICS_FORMAT } else { TEXT_FORMAT };
+    ([(header::CONTENT_TYPE, content_type)], metrics.render(openmetrics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use tower::ServiceExt;
+
+    #[test]
+    fn reads_trace_ids() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(trace_id(&headers), None);
+
+        headers.insert(TRACE_ID_HEADER, "abc123".parse().unwrap());
+        assert_eq!(trace_id(&headers).as_deref(), Some("abc123"));
+
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap(),
+        );
+        assert_eq!(
+            trace_id(&headers).as_deref(),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+
+        headers.insert(
+            "traceparent",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01".parse().unwrap(),
+        );
+        assert_eq!(trace_id(&headers).as_deref(), Some("abc123"));
+    }
+
+    #[tokio::test]
+    async fn records_requests_by_route() {
+        let metrics = Metrics::new("test");
+        let app = Router::new()
+            .route("/items/:id", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(metrics.clone(), track_http))
+            .merge(metrics_router(metrics.clone()));
+
+        let request = Request::builder()
+            .uri("/items/42")
+            .header("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::OK);
+
+        let request = Request::builder()
+            .uri("/metrics")
+            .header(header::ACCEPT, "application/openmetrics-text; version=1.0.0")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], OPENMETRICS_FORMAT);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains(
+            "sandstorm_http_requests_total{method=\"GET\",route=\"/items/:id\",status=\"200\",service=\"test\"} 1"
+        ));
+        assert!(text.contains("trace_id=\"4bf92f3577b34da6a3ce929d0e0e4736\""));
+    }
+}
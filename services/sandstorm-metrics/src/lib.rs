@@ -0,0 +1,210 @@
+//! Prometheus metrics shared by the Sandstorm services.
+//!
+//! Every service builds one [`Metrics`] and registers its metrics through it,
+//! so names follow the same conventions across the stack:
+//!
+//! - Names are `sandstorm_<subsystem>_<name>_<unit>` in snake case. The
+//!   `sandstorm_` prefix is added by the registry.
+//! - Units are base units: `_seconds`, `_bytes`, and `_ratio` for fractions
+//!   in 0–1. Counters end in `_total`.
+//! - Every series carries a constant `service` label naming the exporter, so
+//!   one dashboard can cover the stack and filter by service.
+//! - HTTP traffic is recorded by [`track_http`] as
+//!   `sandstorm_http_requests_total` and
+//!   `sandstorm_http_request_duration_seconds`, labelled by matched route
+//!   rather than raw path.
+//!
+//! Latency histograms made with [`Metrics::histogram`] keep the trace ID of
+//! the latest observation in each bucket and expose it as an exemplar when
+//! scraped in OpenMetrics format.
+//!
+//! With the `remote-write` feature, [`RemoteWriter`] pushes the same metrics
+//! to a Prometheus remote-write endpoint for deployments nothing scrapes.
+
+mod exemplar;
+mod http;
+mod openmetrics;
+#[cfg(feature = "remote-write")]
+mod remote_write;
+
+pub use exemplar::ExemplarHistogram;
+pub use http::{metrics_router, trace_id, track_http};
+pub use openmetrics::{OPENMETRICS_FORMAT, TEXT_FORMAT};
+#[cfg(feature = "remote-write")]
+pub use remote_write::{PushOutcome, RemoteWriteConfig, RemoteWriter};
+
+use prometheus::{CounterVec, GaugeVec, HistogramOpts, Opts, Registry};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Prefix added to every metric name
+pub const NAMESPACE: &str = "sandstorm";
+
+/// Default buckets for request and operation latencies, in seconds
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Metric registry for one service
+#[derive(Clone)]
+pub struct Metrics {
+    service: String,
+    registry: Registry,
+    exemplars: Arc<exemplar::ExemplarStore>,
+    http_requests: CounterVec,
+    http_duration: ExemplarHistogram,
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics")
+            .field("service", &self.service)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Metrics {
+    /// Registry for `service`, with the shared HTTP metrics registered
+    pub fn new(service: &str) -> Self {
+        let registry = Registry::new_custom(
+            Some(NAMESPACE.to_string()),
+            Some(HashMap::from([("service".to_string(), service.to_string())])),
+        )
+        .expect("namespace is not empty");
+        let exemplars = Arc::new(exemplar::ExemplarStore::default());
+
+        let http_requests = Self::register_counter(
+            &registry,
+            "http_requests_total",
+            "HTTP requests handled",
+            &["route", "method", "status"],
+        );
+        let http_duration = Self::register_histogram(
+            &registry,
+            &exemplars,
+            "http_request_duration_seconds",
+            "HTTP request latency",
+            &["route", "method"],
+            LATENCY_BUCKETS.to_vec(),
+        );
+
+        Self {
+            service: service.to_string(),
+            registry,
+            exemplars,
+            http_requests,
+            http_duration,
+        }
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Register a counter; `name` should end in `_total`
+    pub fn counter(&self, name: &str, help: &str, labels: &[&str]) -> CounterVec {
+        Self::register_counter(&self.registry, name, help, labels)
+    }
+
+    /// Register a gauge
+    pub fn gauge(&self, name: &str, help: &str, labels: &[&str]) -> GaugeVec {
+        let gauge = GaugeVec::new(Opts::new(name, help), labels).expect("valid gauge");
+        self.registry
+            .register(Box::new(gauge.clone()))
+            .expect("gauge registered once");
+        gauge
+    }
+
+    /// Register a histogram that records trace-ID exemplars
+    pub fn histogram(
+        &self,
+        name: &str,
+        help: &str,
+        labels: &[&str],
+        buckets: Vec<f64>,
+    ) -> ExemplarHistogram {
+        Self::register_histogram(&self.registry, &self.exemplars, name, help, labels, buckets)
+    }
+
+    /// Record one handled HTTP request
+    pub fn observe_http(
+        &self,
+        route: &str,
+        method: &str,
+        status: u16,
+        seconds: f64,
+        trace_id: Option<&str>,
+    ) {
+        self.http_requests
+            .with_label_values(&[route, method, &status.to_string()])
+            .inc();
+        self.http_duration.observe(&[route, method], seconds, trace_id);
+    }
+
+    /// Render every metric, in OpenMetrics format (with exemplars) or the
+    /// classic Prometheus text format
+    pub fn render(&self, openmetrics: bool) -> String {
+        let families = self.registry.gather();
+        if openmetrics {
+            openmetrics::render(&families, &self.exemplars)
+        } else {
+            openmetrics::render_text(&families)
+        }
+    }
+
+    fn register_counter(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> CounterVec {
+        let counter = CounterVec::new(Opts::new(name, help), labels).expect("valid counter");
+        registry
+            .register(Box::new(counter.clone()))
+            .expect("counter registered once");
+        counter
+    }
+
+    fn register_histogram(
+        registry: &Registry,
+        exemplars: &Arc<exemplar::ExemplarStore>,
+        name: &str,
+        help: &str,
+        labels: &[&str],
+        buckets: Vec<f64>,
+    ) -> ExemplarHistogram {
+        let histogram = prometheus::HistogramVec::new(
+            HistogramOpts::new(name, help).buckets(buckets.clone()),
+            labels,
+        )
+        .expect("valid histogram");
+        registry
+            .register(Box::new(histogram.clone()))
+            .expect("histogram registered once");
+        ExemplarHistogram::new(
+            histogram,
+            format!("{}_{}", NAMESPACE, name),
+            labels,
+            buckets,
+            exemplars.clone(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_prefixed_and_labelled_by_service() {
+        let metrics = Metrics::new("snapshot-vault");
+        metrics
+            .counter("snapshots_stored_total", "Snapshots stored", &["kind"])
+            .with_label_values(&["full"])
+            .inc();
+
+        let text = metrics.render(false);
+        assert!(text.contains(
+            "sandstorm_snapshots_stored_total{kind=\"full\",service=\"snapshot-vault\"} 1"
+        ));
+    }
+}
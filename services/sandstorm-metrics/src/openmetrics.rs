@@ -0,0 +1,199 @@
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+use prometheus::{Encoder, TextEncoder};
+use std::fmt::Write;
+
+use crate::exemplar::{Exemplar, ExemplarStore};
+
+/// Content type of the OpenMetrics exposition format, which carries exemplars
+pub const OPENMETRICS_FORMAT: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Content type of the classic Prometheus text format
+pub const TEXT_FORMAT: &str = prometheus::TEXT_FORMAT;
+
+pub(crate) fn render_text(families: &[MetricFamily]) -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(families, &mut buffer)
+        .unwrap_or_default();
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+pub(crate) fn render(families: &[MetricFamily], store: &ExemplarStore) -> String {
+    let mut out = String::new();
+    for family in families {
+        let name = family.get_name();
+        let (kind, family_name) = match family.get_field_type() {
+            MetricType::COUNTER => ("counter", name.strip_suffix("_total").unwrap_or(name)),
+            MetricType::GAUGE => ("gauge", name),
+            MetricType::HISTOGRAM => ("histogram", name),
+            MetricType::SUMMARY => ("summary", name),
+            MetricType::UNTYPED => ("unknown", name),
+        };
+        let _ = writeln!(out, "# TYPE {} {}", family_name, kind);
+        let _ = writeln!(out, "# HELP {} {}", family_name, escape_help(family.get_help()));
+
+        for metric in family.get_metric() {
+            let labels = metric.get_label();
+            match family.get_field_type() {
+                MetricType::COUNTER => {
+                    sample(&mut out, &format!("{}_total", family_name), labels, None, metric.get_counter().get_value());
+                }
+                MetricType::GAUGE => {
+                    sample(&mut out, name, labels, None, metric.get_gauge().get_value());
+                }
+                MetricType::UNTYPED => {
+                    sample(&mut out, name, labels, None, metric.get_untyped().get_value());
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    sample(&mut out, &format!("{}_count", name), labels, None, summary.get_sample_count() as f64);
+                    sample(&mut out, &format!("{}_sum", name), labels, None, summary.get_sample_sum());
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    let exemplars = store
+                        .with_series(name, |series| {
+                            let key: Vec<String> = series
+                                .label_names
+                                .iter()
+                                .map(|label| {
+                                    labels
+                                        .iter()
+                                        .find(|pair| pair.get_name() == label)
+                                        .map(|pair| pair.get_value().to_string())
+                                        .unwrap_or_default()
+                                })
+                                .collect();
+                            series.exemplars.get(&key).cloned()
+                        })
+                        .flatten()
+                        .unwrap_or_default();
+
+                    let bucket_name = format!("{}_bucket", name);
+                    let buckets = histogram.get_bucket();
+                    for (index, bucket) in buckets.iter().enumerate() {
+                        let le = ("le", format_value(bucket.get_upper_bound()));
+                        sample_with(
+                            &mut out,
+                            &bucket_name,
+                            labels,
+                            Some(le),
+                            bucket.get_cumulative_count() as f64,
+                            exemplars.get(index).and_then(Option::as_ref),
+                        );
+                    }
+                    sample_with(
+                        &mut out,
+                        &bucket_name,
+                        labels,
+                        Some(("le", "+Inf".to_string())),
+                        histogram.get_sample_count() as f64,
+                        exemplars.get(buckets.len()).and_then(Option::as_ref),
+                    );
+                    sample(&mut out, &format!("{}_count", name), labels, None, histogram.get_sample_count() as f64);
+                    sample(&mut out, &format!("{}_sum", name), labels, None, histogram.get_sample_sum());
+                }
+            }
+        }
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+fn sample(out: &mut String, name: &str, labels: &[LabelPair], extra: Option<(&str, String)>, value: f64) {
+    sample_with(out, name, labels, extra, value, None);
+}
+
+fn sample_with(
+    out: &mut String,
+    name: &str,
+    labels: &[LabelPair],
+    extra: Option<(&str, String)>,
+    value: f64,
+    exemplar: Option<&Exemplar>,
+) {
+    out.push_str(name);
+    let mut pairs: Vec<(&str, String)> = labels
+        .iter()
+        .map(|pair| (pair.get_name(), pair.get_value().to_string()))
+        .collect();
+    pairs.extend(extra);
+    if !pairs.is_empty() {
+        let rendered: Vec<_> = pairs
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape_label(value)))
+            .collect();
+        let _ = write!(out, "{{{}}}", rendered.join(","));
+    }
+    let _ = write!(out, " {}", format_value(value));
+    if let Some(exemplar) = exemplar {
+        let _ = write!(
+            out,
+            " # {{trace_id=\"{}\"}} {} {:.3}",
+            escape_label(&exemplar.trace_id),
+            format_value(exemplar.value),
+            exemplar.timestamp
+        );
+    }
+    out.push('\n');
+}
+
+fn format_value(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else if value.is_nan() {
+        "NaN".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn escape_help(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Metrics;
+
+    #[test]
+    fn renders_exemplars_in_openmetrics() {
+        let metrics = Metrics::new("gateway");
+        let latency = metrics.histogram(
+            "sandbox_start_duration_seconds",
+            "Time to start a sandbox",
+            &["runtime"],
+            vec![0.1, 1.0],
+        );
+        latency.observe(&["gvisor"], 0.5, Some("4bf92f3577b34da6a3ce929d0e0e4736"));
+        latency.observe(&["gvisor"], 0.05, None);
+        metrics
+            .counter("sandboxes_started_total", "Sandboxes started", &["runtime"])
+            .with_label_values(&["gvisor"])
+            .inc();
+
+        let text = metrics.render(true);
+        assert!(text.contains("# TYPE sandstorm_sandboxes_started counter\n"));
+        assert!(text.contains("sandstorm_sandboxes_started_total{runtime=\"gvisor\",service=\"gateway\"} 1\n"));
+        assert!(text.contains(
+            "sandstorm_sandbox_start_duration_seconds_bucket{runtime=\"gvisor\",service=\"gateway\",le=\"0.1\"} 1\n"
+        ));
+        assert!(text.contains(
+            "sandstorm_sandbox_start_duration_seconds_bucket{runtime=\"gvisor\",service=\"gateway\",le=\"1\"} 2 # {trace_id=\"4bf92f3577b34da6a3ce929d0e0e4736\"} 0.5 "
+        ));
+        assert!(text.contains("le=\"+Inf\"} 2\n"));
+        assert!(text.ends_with("# EOF\n"));
+
+        // The classic format has no exemplars
+        assert!(!metrics.render(false).contains("trace_id"));
+    }
+}
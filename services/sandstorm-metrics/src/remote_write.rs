@@ -0,0 +1,412 @@
+//! Prometheus remote-write export, for services whose metrics can't be
+//! scraped (agents behind NAT, short-lived hosts). Each push sends the
+//! current value of every series; since counters and histograms are
+//! cumulative, a push that is lost only costs resolution, not counts.
+
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+use prometheus::CounterVec;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::Metrics;
+
+/// First wait before retrying a failed request; doubled on every retry
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Longest wait between retries
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Where and how to push metrics
+#[derive(Debug, Clone)]
+pub struct RemoteWriteConfig {
+    /// Remote-write endpoint, e.g. `https://prometheus.example.com/api/v1/write`
+    pub url: String,
+    /// Sent as `Authorization: Bearer <token>` when set
+    pub bearer_token: Option<String>,
+    /// Samples per request; larger pushes are split into several requests
+    pub max_samples_per_request: usize,
+    /// Retries of a request that failed with a network error, a 5xx or a 429
+    pub max_retries: u32,
+}
+
+/// Samples sent and given up on by one [`RemoteWriter::push`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PushOutcome {
+    pub sent: usize,
+    pub failed: usize,
+    /// Why the last failed request was given up on
+    pub error: Option<String>,
+}
+
+/// Pushes a service's metrics to a remote-write endpoint. Counts the
+/// samples it sends and fails to send as
+/// `sandstorm_remote_write_samples_total{outcome}`.
+#[derive(Debug, Clone)]
+pub struct RemoteWriter {
+    metrics: Metrics,
+    http: reqwest::Client,
+    samples: CounterVec,
+}
+
+impl RemoteWriter {
+    pub fn new(metrics: &Metrics) -> Self {
+        let samples = metrics.counter(
+            "remote_write_samples_total",
+            "Samples pushed to the remote-write endpoint, by outcome",
+            &["outcome"],
+        );
+        Self {
+            metrics: metrics.clone(),
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()
+                .unwrap_or_default(),
+            samples,
+        }
+    }
+
+    /// Push the current value of every metric, in batches of at most
+    /// `max_samples_per_request` samples. A batch that still fails after
+    /// its retries is dropped; the next push carries newer values.
+    pub async fn push(&self, config: &RemoteWriteConfig) -> PushOutcome {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as i64)
+            .unwrap_or_default();
+        let series = series(&self.metrics.registry().gather(), timestamp);
+
+        let mut outcome = PushOutcome::default();
+        for batch in series.chunks(config.max_samples_per_request.max(1)) {
+            match self.send(config, batch).await {
+                Ok(()) => outcome.sent += batch.len(),
+                Err(e) => {
+                    outcome.failed += batch.len();
+                    outcome.error = Some(e);
+                }
+            }
+        }
+        self.samples
+            .with_label_values(&["sent"])
+            .inc_by(outcome.sent as f64);
+        self.samples
+            .with_label_values(&["failed"])
+            .inc_by(outcome.failed as f64);
+        outcome
+    }
+
+    /// Send one batch, retrying with exponential backoff. Other 4xx
+    /// responses mean the endpoint rejected the data and are not retried.
+    async fn send(&self, config: &RemoteWriteConfig, batch: &[Series]) -> Result<(), String> {
+        let body = snap::raw::Encoder::new()
+            .compress_vec(&encode(batch))
+            .map_err(|e| format!("failed to compress samples: {}", e))?;
+
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let mut request = self
+                .http
+                .post(&config.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/x-protobuf")
+                .header(reqwest::header::CONTENT_ENCODING, "snappy")
+                .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+                .body(body.clone());
+            if let Some(token) = &config.bearer_token {
+                request = request.bearer_auth(token);
+            }
+
+            let error = match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => {
+                    let status = response.status();
+                    let message = format!(
+                        "remote write answered {}: {}",
+                        status,
+                        response.text().await.unwrap_or_default().trim()
+                    );
+                    if status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+                        return Err(message);
+                    }
+                    message
+                }
+                Err(e) => format!("remote write failed: {}", e),
+            };
+            if attempt >= config.max_retries {
+                return Err(error);
+            }
+            attempt += 1;
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+/// One sample of one series, labels sorted by name with `__name__` among them
+#[derive(Debug, Clone, PartialEq)]
+struct Series {
+    labels: Vec<(String, String)>,
+    value: f64,
+    timestamp: i64,
+}
+
+/// Flatten gathered families into series, the way the text format names
+/// them: histograms and summaries become their `_bucket`/quantile, `_sum`
+/// and `_count` series
+fn series(families: &[MetricFamily], timestamp: i64) -> Vec<Series> {
+    let mut out = Vec::new();
+    for family in families {
+        let name = family.get_name();
+        for metric in family.get_metric() {
+            let mut push = |suffix: &str, extra: Option<(&str, String)>, value: f64| {
+                out.push(Series {
+                    labels: labels(&format!("{}{}", name, suffix), metric.get_label(), extra),
+                    value,
+                    timestamp,
+                });
+            };
+            match family.get_field_type() {
+                MetricType::COUNTER => push("", None, metric.get_counter().get_value()),
+                MetricType::GAUGE => push("", None, metric.get_gauge().get_value()),
+                MetricType::UNTYPED => push("", None, metric.get_untyped().get_value()),
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for quantile in summary.get_quantile() {
+                        let label = ("quantile", quantile.get_quantile().to_string());
+                        push("", Some(label), quantile.get_value());
+                    }
+                    push("_sum", None, summary.get_sample_sum());
+                    push("_count", None, summary.get_sample_count() as f64);
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    for bucket in histogram.get_bucket() {
+                        let le = ("le", bucket.get_upper_bound().to_string());
+                        push("_bucket", Some(le), bucket.get_cumulative_count() as f64);
+                    }
+                    let inf = ("le", "+Inf".to_string());
+                    push("_bucket", Some(inf), histogram.get_sample_count() as f64);
+                    push("_sum", None, histogram.get_sample_sum());
+                    push("_count", None, histogram.get_sample_count() as f64);
+                }
+            }
+        }
+    }
+    out
+}
+
+fn labels(name: &str, pairs: &[LabelPair], extra: Option<(&str, String)>) -> Vec<(String, String)> {
+    let mut labels: Vec<(String, String)> = pairs
+        .iter()
+        .map(|pair| (pair.get_name().to_string(), pair.get_value().to_string()))
+        .chain(extra.map(|(label, value)| (label.to_string(), value)))
+        .collect();
+    labels.push(("__name__".to_string(), name.to_string()));
+    // Receivers require labels sorted by name
+    labels.sort();
+    labels
+}
+
+/// Encode series as a protobuf `prometheus.WriteRequest`:
+///
+/// ```text
+/// WriteRequest { repeated TimeSeries timeseries = 1; }
+/// TimeSeries   { repeated Label labels = 1; repeated Sample samples = 2; }
+/// Label        { string name = 1; string value = 2; }
+/// Sample       { double value = 1; int64 timestamp = 2; }
+/// ```
+fn encode(series: &[Series]) -> Vec<u8> {
+    let mut request = Vec::new();
+    for one in series {
+        let mut timeseries = Vec::new();
+        for (name, value) in &one.labels {
+            let mut label = Vec::new();
+            bytes_field(&mut label, 1, name.as_bytes());
+            bytes_field(&mut label, 2, value.as_bytes());
+            bytes_field(&mut timeseries, 1, &label);
+        }
+        let mut sample = Vec::new();
+        varint(&mut sample, 1 << 3 | 1);
+        sample.extend_from_slice(&one.value.to_le_bytes());
+        varint(&mut sample, 2 << 3);
+        varint(&mut sample, one.timestamp as u64);
+        bytes_field(&mut timeseries, 2, &sample);
+        bytes_field(&mut request, 1, &timeseries);
+    }
+    request
+}
+
+/// A length-delimited field: strings and embedded messages
+fn bytes_field(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    varint(out, field << 3 | 2);
+    varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flattens_histograms_into_sorted_series() {
+        let metrics = Metrics::new("telemetry-collector");
+        let latency = metrics.histogram("ingest_duration_seconds", "Ingest latency", &["kind"], vec![0.1, 1.0]);
+        latency.observe(&["run"], 0.5, None);
+
+        let series = series(&metrics.registry().gather(), 1_700_000_000_000);
+        let bucket = series
+            .iter()
+            .find(|one| {
+                one.labels.contains(&("__name__".to_string(), "sandstorm_ingest_duration_seconds_bucket".to_string()))
+                    && one.labels.contains(&("le".to_string(), "+Inf".to_string()))
+            })
+            .unwrap();
+        assert_eq!(bucket.value, 1.0);
+        assert_eq!(
+            bucket.labels.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(),
+            ["__name__", "kind", "le", "service"]
+        );
+    }
+
+    #[test]
+    fn encodes_write_requests() {
+        let series = [Series {
+            labels: vec![("__name__".to_string(), "up".to_string())],
+            value: 1.0,
+            timestamp: 300,
+        }];
+        let label = [&[0x0a, 8][..], b"__name__", &[0x12, 2], b"up"].concat();
+        let sample = [&[0x09][..], &1.0f64.to_le_bytes(), &[0x10, 0xac, 0x02]].concat();
+        let timeseries = [&[0x0a, label.len() as u8][..], &label, &[0x12, sample.len() as u8], &sample].concat();
+        let request = [&[0x0a, timeseries.len() as u8][..], &timeseries].concat();
+        assert_eq!(encode(&series), request);
+    }
+
+    #[tokio::test]
+    async fn retries_unavailable_endpoints() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let seen = requests.clone();
+        let app = axum::Router::new().route(
+            "/api/v1/write",
+            axum::routing::post(move |body: axum::body::Bytes| async move {
+                assert!(snap::raw::Decoder::new().decompress_vec(&body).is_ok());
+                if seen.fetch_add(1, Ordering::SeqCst) == 0 {
+                    axum::http::StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    axum::http::StatusCode::NO_CONTENT
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let metrics = Metrics::new("security-monitor");
+        metrics.observe_http("/health", "GET", 200, 0.001, None);
+        let writer = RemoteWriter::new(&metrics);
+        let config = RemoteWriteConfig {
+            url: format!("http://{}/api/v1/write", address),
+            bearer_token: None,
+            max_samples_per_request: 1_000,
+            max_retries: 1,
+        };
+        let outcome = writer.push(&config).await;
+        assert_eq!((outcome.failed, outcome.error), (0, None));
+        assert!(outcome.sent > 0);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    /// An endpoint answering every request with `status`, counting the
+    /// requests and the bearer tokens they carried
+    async fn endpoint(
+        status: axum::http::StatusCode,
+    ) -> (String, std::sync::Arc<std::sync::Mutex<Vec<Option<String>>>>) {
+        use std::sync::{Arc, Mutex};
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let app = axum::Router::new().route(
+            "/api/v1/write",
+            axum::routing::post(move |headers: axum::http::HeaderMap| async move {
+                let token = headers
+                    .get(axum::http::header::AUTHORIZATION)
+                    .and_then(|value| value.to_str().ok())
+                    .map(String::from);
+                seen.lock().unwrap().push(token);
+                status
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{}/api/v1/write", address), requests)
+    }
+
+    #[tokio::test]
+    async fn splits_pushes_into_batches() {
+        let (url, requests) = endpoint(axum::http::StatusCode::NO_CONTENT).await;
+        let metrics = Metrics::new("security-monitor");
+        metrics.observe_http("/health", "GET", 200, 0.001, None);
+        let total = series(&metrics.registry().gather(), 0).len();
+
+        let writer = RemoteWriter::new(&metrics);
+        let config = RemoteWriteConfig {
+            url,
+            bearer_token: Some("push-token".to_string()),
+            max_samples_per_request: 5,
+            max_retries: 0,
+        };
+        let outcome = writer.push(&config).await;
+        assert_eq!(outcome.failed, 0);
+        // The writer's own counter appears once it has pushed
+        assert!(outcome.sent >= total);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), outcome.sent.div_ceil(5));
+        assert!(requests
+            .iter()
+            .all(|token| token.as_deref() == Some("Bearer push-token")));
+    }
+
+    #[tokio::test]
+    async fn rejected_batches_are_not_retried() {
+        let (url, requests) = endpoint(axum::http::StatusCode::BAD_REQUEST).await;
+        let metrics = Metrics::new("security-monitor");
+        metrics.observe_http("/health", "GET", 200, 0.001, None);
+
+        let writer = RemoteWriter::new(&metrics);
+        let config = RemoteWriteConfig {
+            url,
+            bearer_token: None,
+            max_samples_per_request: 1_000,
+            max_retries: 3,
+        };
+        let outcome = writer.push(&config).await;
+        assert_eq!(outcome.sent, 0);
+        assert!(outcome.failed > 0);
+        assert!(outcome.error.unwrap().starts_with("remote write answered 400"));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn varints_use_seven_bits_per_byte() {
+        let encoded = |value| {
+            let mut out = Vec::new();
+            varint(&mut out, value);
+            out
+        };
+        assert_eq!(encoded(0), [0x00]);
+        assert_eq!(encoded(127), [0x7f]);
+        assert_eq!(encoded(300), [0xac, 0x02]);
+        assert_eq!(encoded(u64::MAX).len(), 10);
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Identity presented by a verified client certificate
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerIdentity {
+    /// `spiffe://` URI SAN, if the certificate is an X.509-SVID
+    pub spiffe_id: Option<String>,
+    /// DNS SANs
+    pub dns_names: Vec<String>,
+    /// Subject common name, used as a fallback name
+    pub common_name: Option<String>,
+}
+
+impl PeerIdentity {
+    pub fn from_der(der: &[u8]) -> Result<Self> {
+        let (_, cert) =
+            X509Certificate::from_der(der).context("failed to parse peer certificate")?;
+
+        let mut identity = PeerIdentity {
+            common_name: cert
+                .subject()
+                .iter_common_name()
+                .next()
+                .and_then(|cn| cn.as_str().ok())
+                .map(str::to_string),
+            ..Default::default()
+        };
+
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::URI(uri) if uri.starts_with("spiffe://") => {
+                        identity.spiffe_id = Some(uri.to_string());
+                    }
+                    GeneralName::DNSName(dns) => identity.dns_names.push(dns.to_string()),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(identity)
+    }
+
+    /// Whether any of this identity's names matches an allowlist entry.
+    /// Entries ending in `*` match by prefix.
+    pub fn matches(&self, pattern: &str) -> bool {
+        let matches_name = |name: &str| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern,
+        };
+
+        self.spiffe_id.as_deref().is_some_and(matches_name)
+            || self.dns_names.iter().any(|name| matches_name(name))
+            || self.common_name.as_deref().is_some_and(matches_name)
+    }
+
+    /// Empty allowlists accept every verified peer
+    pub fn is_allowed(&self, allowed: &[String]) -> bool {
+        allowed.is_empty() || allowed.iter().any(|pattern| self.matches(pattern))
+    }
+
+    /// Best name for logging
+    pub fn name(&self) -> &str {
+        self.spiffe_id
+            .as_deref()
+            .or(self.dns_names.first().map(String::as_str))
+            .or(self.common_name.as_deref())
+            .unwrap_or("<anonymous>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, KeyPair, SanType};
+
+    fn cert_with_sans(sans: Vec<SanType>) -> Vec<u8> {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.subject_alt_names = sans;
+        let key = KeyPair::generate().unwrap();
+        params.self_signed(&key).unwrap().der().to_vec()
+    }
+
+    #[test]
+    fn extracts_spiffe_id_and_dns_names() {
+        let der = cert_with_sans(vec![
+            SanType::URI("spiffe://sandstorm/gateway".try_into().unwrap()),
+            SanType::DnsName("gateway.sandstorm.svc".try_into().unwrap()),
+        ]);
+        let identity = PeerIdentity::from_der(&der).unwrap();
+
+        assert_eq!(
+            identity.spiffe_id.as_deref(),
+            Some("spiffe://sandstorm/gateway")
+        );
+        assert_eq!(
+            identity.dns_names,
+            vec!["gateway.sandstorm.svc".to_string()]
+        );
+        assert_eq!(identity.name(), "spiffe://sandstorm/gateway");
+    }
+
+    #[test]
+    fn allowlist_supports_exact_and_prefix_matches() {
+        let identity = PeerIdentity {
+            spiffe_id: Some("spiffe://sandstorm/edge/agent-7".to_string()),
+            ..Default::default()
+        };
+
+        assert!(identity.is_allowed(&[]));
+        assert!(identity.is_allowed(&["spiffe://sandstorm/edge/*".to_string()]));
+        assert!(!identity.is_allowed(&["spiffe://sandstorm/edge".to_string()]));
+        assert!(!identity.is_allowed(&["spiffe://sandstorm/gateway".to_string()]));
+    }
+}
@@ -0,0 +1,19 @@
+//! Mutual TLS for traffic between Sandstorm services.
+//!
+//! Services load their certificate, key and trust bundle either from explicit
+//! files or from a directory maintained by `spiffe-helper`. Material is
+//! re-read when the files change so short-lived certificates rotate without a
+//! restart. Servers require a client certificate signed by the trust bundle
+//! and can further restrict callers to an allowlist of SPIFFE IDs or DNS
+//! names; the verified identity is attached to each request as a
+//! [`PeerIdentity`] extension.
+
+mod identity;
+mod server;
+mod settings;
+mod store;
+
+pub use identity::PeerIdentity;
+pub use server::serve;
+pub use settings::{CertSource, TlsSettings};
+pub use store::CertStore;
@@ -0,0 +1,221 @@
+use anyhow::Result;
+use axum::extract::{ConnectInfo, Request};
+use axum::Router;
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+use tracing::{debug, warn};
+
+use crate::identity::PeerIdentity;
+use crate::settings::TlsSettings;
+use crate::store::CertStore;
+
+/// Serve `app` over mutual TLS.
+///
+/// Connections whose client certificate does not match
+/// `settings.allowed_peers` are closed right after the handshake. Accepted
+/// requests carry the caller's [`PeerIdentity`] and its address, as
+/// [`ConnectInfo`], as extensions.
+pub async fn serve(listener: TcpListener, app: Router, settings: TlsSettings) -> Result<()> {
+    let store = CertStore::load(settings.source.clone())?;
+    store.spawn_reloader(settings.reload_interval);
+
+    let acceptor = TlsAcceptor::from(Arc::new(store.server_config()?));
+    let allowed_peers = Arc::new(settings.allowed_peers);
+
+    loop {
+        let (tcp, remote_addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(error) => {
+                warn!(?error, "failed to accept connection");
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let allowed_peers = allowed_peers.clone();
+        let app = app.clone();
+
+        tokio::spawn(async move {
+            let tls = match acceptor.accept(tcp).await {
+                Ok(tls) => tls,
+                Err(error) => {
+                    debug!(%remote_addr, ?error, "TLS handshake failed");
+                    return;
+                }
+            };
+
+            let identity = match tls
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(|cert| PeerIdentity::from_der(cert))
+            {
+                Some(Ok(identity)) => identity,
+                Some(Err(error)) => {
+                    warn!(%remote_addr, ?error, "rejecting peer with unreadable certificate");
+                    return;
+                }
+                None => {
+                    warn!(%remote_addr, "rejecting peer without client certificate");
+                    return;
+                }
+            };
+
+            if !identity.is_allowed(&allowed_peers) {
+                warn!(%remote_addr, peer = identity.name(), "rejecting peer not in allowlist");
+                return;
+            }
+
+            let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+                request.extensions_mut().insert(identity.clone());
+                request.extensions_mut().insert(ConnectInfo(remote_addr));
+                app.clone().oneshot(request)
+            });
+
+            if let Err(error) = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(tls), service)
+                .await
+            {
+                debug!(%remote_addr, ?error, "connection closed with error");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::CertSource;
+    use axum::routing::get;
+    use axum::Extension;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair, SanType};
+    use rustls::pki_types::ServerName;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+
+    struct Ca {
+        cert: rcgen::Certificate,
+        key: KeyPair,
+    }
+
+    fn ca() -> Ca {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key = KeyPair::generate().unwrap();
+        Ca {
+            cert: params.self_signed(&key).unwrap(),
+            key,
+        }
+    }
+
+    /// Write a leaf signed by `ca` into `dir` using the spiffe-helper layout
+    fn write_svid(dir: &Path, ca: &Ca, spiffe_id: &str) -> CertSource {
+        std::fs::create_dir_all(dir).unwrap();
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        params
+            .subject_alt_names
+            .push(SanType::URI(spiffe_id.try_into().unwrap()));
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &ca.cert, &ca.key).unwrap();
+
+        std::fs::write(dir.join("svid.pem"), cert.pem()).unwrap();
+        std::fs::write(dir.join("svid_key.pem"), key.serialize_pem()).unwrap();
+        std::fs::write(dir.join("svid_bundle.pem"), ca.cert.pem()).unwrap();
+        CertSource::Spiffe {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("sandstorm-tls-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    async fn request(addr: std::net::SocketAddr, client: &CertSource) -> Option<String> {
+        let store = CertStore::load(client.clone()).unwrap();
+        let connector = TlsConnector::from(Arc::new(store.client_config().unwrap()));
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut tls = connector
+            .connect(ServerName::try_from("localhost").unwrap(), tcp)
+            .await
+            .ok()?;
+
+        tls.write_all(b"GET /whoami HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .ok()?;
+        let mut response = String::new();
+        tls.read_to_string(&mut response).await.ok()?;
+        response.split("\r\n\r\n").nth(1).map(str::to_string)
+    }
+
+    #[tokio::test]
+    async fn enforces_peer_allowlist_and_exposes_identity() {
+        let root = temp_dir("allowlist");
+        let ca = ca();
+        let server = write_svid(&root.join("server"), &ca, "spiffe://sandstorm/collector");
+        let agent = write_svid(&root.join("agent"), &ca, "spiffe://sandstorm/edge/agent-1");
+        let gateway = write_svid(&root.join("gateway"), &ca, "spiffe://sandstorm/gateway");
+
+        let app = Router::new().route(
+            "/whoami",
+            get(|Extension(peer): Extension<PeerIdentity>| async move { peer.name().to_string() }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(
+            listener,
+            app,
+            TlsSettings {
+                source: server,
+                allowed_peers: vec!["spiffe://sandstorm/edge/*".to_string()],
+                reload_interval: Duration::from_secs(60),
+            },
+        ));
+
+        assert_eq!(
+            request(addr, &agent).await.as_deref(),
+            Some("spiffe://sandstorm/edge/agent-1")
+        );
+        assert_eq!(request(addr, &gateway).await, None);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn rejects_certificates_from_another_ca() {
+        let root = temp_dir("foreign-ca");
+        let server = write_svid(&root.join("server"), &ca(), "spiffe://sandstorm/vault");
+        let intruder = write_svid(&root.join("intruder"), &ca(), "spiffe://sandstorm/gateway");
+        // The intruder trusts the server, but the server does not trust the intruder's CA
+        std::fs::copy(
+            root.join("server").join("svid_bundle.pem"),
+            root.join("intruder").join("svid_bundle.pem"),
+        )
+        .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(
+            listener,
+            Router::new().route("/whoami", get(|| async { "ok" })),
+            TlsSettings {
+                source: server,
+                allowed_peers: Vec::new(),
+                reload_interval: Duration::from_secs(60),
+            },
+        ));
+
+        assert_eq!(request(addr, &intruder).await, None);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
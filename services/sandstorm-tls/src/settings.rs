@@ -0,0 +1,107 @@
+use anyhow::{bail, Context, Result};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Where certificate material is loaded from
+#[derive(Debug, Clone)]
+pub enum CertSource {
+    /// Explicit PEM files
+    Files {
+        cert: PathBuf,
+        key: PathBuf,
+        ca: PathBuf,
+    },
+    /// Directory kept up to date by spiffe-helper (`svid.pem`, `svid_key.pem`,
+    /// `svid_bundle.pem`)
+    Spiffe { dir: PathBuf },
+}
+
+impl CertSource {
+    pub fn cert_path(&self) -> PathBuf {
+        match self {
+            CertSource::Files { cert, .. } => cert.clone(),
+            CertSource::Spiffe { dir } => dir.join("svid.pem"),
+        }
+    }
+
+    pub fn key_path(&self) -> PathBuf {
+        match self {
+            CertSource::Files { key, .. } => key.clone(),
+            CertSource::Spiffe { dir } => dir.join("svid_key.pem"),
+        }
+    }
+
+    pub fn ca_path(&self) -> PathBuf {
+        match self {
+            CertSource::Files { ca, .. } => ca.clone(),
+            CertSource::Spiffe { dir } => dir.join("svid_bundle.pem"),
+        }
+    }
+}
+
+/// mTLS settings for one service
+#[derive(Debug, Clone)]
+pub struct TlsSettings {
+    pub source: CertSource,
+    /// SPIFFE IDs or DNS names allowed to connect. Entries ending in `*` match
+    /// by prefix. Empty means any certificate signed by the trust bundle.
+    pub allowed_peers: Vec<String>,
+    /// How often certificate files are checked for changes
+    pub reload_interval: Duration,
+}
+
+impl TlsSettings {
+    /// Read settings from `<PREFIX>_TLS_*` environment variables.
+    ///
+    /// Returns `None` when neither `<PREFIX>_TLS_CERT` nor
+    /// `<PREFIX>_TLS_SPIFFE_DIR` is set, i.e. TLS is disabled.
+    pub fn from_env(prefix: &str) -> Result<Option<Self>> {
+        let var = |name: &str| std::env::var(format!("{}_TLS_{}", prefix, name)).ok();
+
+        let source = match (var("SPIFFE_DIR"), var("CERT")) {
+            (Some(_), Some(_)) => {
+                bail!("{prefix}_TLS_SPIFFE_DIR and {prefix}_TLS_CERT are mutually exclusive")
+            }
+            (Some(dir), None) => CertSource::Spiffe { dir: dir.into() },
+            (None, Some(cert)) => CertSource::Files {
+                cert: cert.into(),
+                key: var("KEY")
+                    .with_context(|| {
+                        format!("{}_TLS_KEY is required with {}_TLS_CERT", prefix, prefix)
+                    })?
+                    .into(),
+                ca: var("CA")
+                    .with_context(|| {
+                        format!("{}_TLS_CA is required with {}_TLS_CERT", prefix, prefix)
+                    })?
+                    .into(),
+            },
+            (None, None) => return Ok(None),
+        };
+
+        let allowed_peers = var("ALLOWED_PEERS")
+            .map(|peers| {
+                peers
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|peer| !peer.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let reload_interval = match var("RELOAD_SECS") {
+            Some(secs) => Duration::from_secs(
+                secs.parse()
+                    .with_context(|| format!("invalid {}_TLS_RELOAD_SECS", prefix))?,
+            ),
+            None => Duration::from_secs(30),
+        };
+
+        Ok(Some(Self {
+            source,
+            allowed_peers,
+            reload_interval,
+        }))
+    }
+}
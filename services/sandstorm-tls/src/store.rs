@@ -0,0 +1,298 @@
+use anyhow::{bail, Context, Result};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::{ResolvesClientCert, WebPkiServerVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{
+    ClientConfig, DigitallySignedStruct, DistinguishedName, RootCertStore, ServerConfig,
+    SignatureScheme,
+};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use crate::settings::CertSource;
+
+/// Certificate, key and trust bundle for a service, reloaded from disk when
+/// the underlying files change.
+///
+/// The store backs both server and client TLS configs, so connections opened
+/// after a rotation pick up the new material while existing ones keep going.
+pub struct CertStore {
+    source: CertSource,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<Arc<Material>>,
+}
+
+struct Material {
+    certified_key: Arc<CertifiedKey>,
+    client_verifier: Arc<dyn ClientCertVerifier>,
+    server_verifier: Arc<WebPkiServerVerifier>,
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl std::fmt::Debug for CertStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertStore")
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+impl CertStore {
+    pub fn load(source: CertSource) -> Result<Arc<Self>> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let material = Material::read(&source, &provider)?;
+        info!(cert = %source.cert_path().display(), "loaded TLS certificate");
+
+        Ok(Arc::new(Self {
+            source,
+            provider,
+            current: RwLock::new(Arc::new(material)),
+        }))
+    }
+
+    fn material(&self) -> Arc<Material> {
+        self.current
+            .read()
+            .expect("cert store lock poisoned")
+            .clone()
+    }
+
+    /// Re-read certificate material if any file changed since the last load.
+    /// On failure the previous material stays in use.
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        if modified_times(&self.source) == self.material().modified {
+            return Ok(false);
+        }
+
+        let material = Material::read(&self.source, &self.provider)?;
+        *self.current.write().expect("cert store lock poisoned") = Arc::new(material);
+        info!(cert = %self.source.cert_path().display(), "reloaded rotated TLS certificate");
+        Ok(true)
+    }
+
+    /// Poll the certificate files for changes in the background
+    pub fn spawn_reloader(self: &Arc<Self>, interval: Duration) {
+        let store = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(error) = store.reload_if_changed() {
+                    warn!(
+                        ?error,
+                        "failed to reload TLS certificate, keeping previous one"
+                    );
+                }
+            }
+        });
+    }
+
+    /// Server config that requires a client certificate signed by the trust bundle
+    pub fn server_config(self: &Arc<Self>) -> Result<ServerConfig> {
+        let mut config = ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()?
+            .with_client_cert_verifier(Arc::new(ReloadingClientVerifier(self.clone())))
+            .with_cert_resolver(Arc::new(ReloadingResolver(self.clone())));
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(config)
+    }
+
+    /// Client config presenting this service's certificate and trusting the
+    /// same bundle for server certificates
+    pub fn client_config(self: &Arc<Self>) -> Result<ClientConfig> {
+        Ok(ClientConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(ReloadingServerVerifier(self.clone())))
+            .with_client_cert_resolver(Arc::new(ReloadingResolver(self.clone()))))
+    }
+}
+
+impl Material {
+    fn read(source: &CertSource, provider: &Arc<CryptoProvider>) -> Result<Self> {
+        // Capture mtimes first so a write racing with the read triggers another reload
+        let modified = modified_times(source);
+
+        let chain = read_certs(&source.cert_path())?;
+        if chain.is_empty() {
+            bail!("no certificates found in {}", source.cert_path().display());
+        }
+        let key = read_key(&source.key_path())?;
+        let signing_key = provider
+            .key_provider
+            .load_private_key(key)
+            .context("unsupported private key")?;
+
+        let mut roots = RootCertStore::empty();
+        for cert in read_certs(&source.ca_path())? {
+            roots.add(cert).context("invalid CA certificate")?;
+        }
+        if roots.is_empty() {
+            bail!("no CA certificates found in {}", source.ca_path().display());
+        }
+        let roots = Arc::new(roots);
+
+        Ok(Self {
+            certified_key: Arc::new(CertifiedKey::new(chain, signing_key)),
+            client_verifier: WebPkiClientVerifier::builder_with_provider(
+                roots.clone(),
+                provider.clone(),
+            )
+            .build()?,
+            server_verifier: WebPkiServerVerifier::builder_with_provider(roots, provider.clone())
+                .build()?,
+            modified,
+        })
+    }
+}
+
+fn modified_times(source: &CertSource) -> Vec<Option<SystemTime>> {
+    [source.cert_path(), source.key_path(), source.ca_path()]
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let pem = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    rustls_pemfile::certs(&mut pem.as_slice())
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("invalid PEM in {}", path.display()))
+}
+
+fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let pem = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    rustls_pemfile::private_key(&mut pem.as_slice())
+        .with_context(|| format!("invalid PEM in {}", path.display()))?
+        .with_context(|| format!("no private key found in {}", path.display()))
+}
+
+#[derive(Debug)]
+struct ReloadingResolver(Arc<CertStore>);
+
+impl ResolvesServerCert for ReloadingResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.material().certified_key.clone())
+    }
+}
+
+impl ResolvesClientCert for ReloadingResolver {
+    fn resolve(
+        &self,
+        _root_hint_subjects: &[&[u8]],
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.material().certified_key.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug)]
+struct ReloadingClientVerifier(Arc<CertStore>);
+
+impl ClientCertVerifier for ReloadingClientVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        // Hints would have to outlive a rotation; clients only carry one identity anyway
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        self.0
+            .material()
+            .client_verifier
+            .verify_client_cert(end_entity, intermediates, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0
+            .material()
+            .client_verifier
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0
+            .material()
+            .client_verifier
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.material().client_verifier.supported_verify_schemes()
+    }
+}
+
+#[derive(Debug)]
+struct ReloadingServerVerifier(Arc<CertStore>);
+
+impl ServerCertVerifier for ReloadingServerVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.0.material().server_verifier.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0
+            .material()
+            .server_verifier
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0
+            .material()
+            .server_verifier
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.material().server_verifier.supported_verify_schemes()
+    }
+}
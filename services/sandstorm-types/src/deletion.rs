@@ -0,0 +1,60 @@
+//! Data deletion on behalf of a tenant or for particular sandboxes, as for
+//! a GDPR erasure request. The gateway sends one [`DeletionRequest`] to each
+//! service holding sandbox data; each answers with a [`ServiceDeletion`]
+//! saying what it removed and what it had to keep.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::Schema;
+
+/// Whose data to delete: everything recorded for `tenant`, and everything
+/// recorded about any of `sandbox_ids`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DeletionRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub sandbox_ids: Vec<String>,
+}
+
+impl DeletionRequest {
+    /// Whether the request names nothing to delete
+    pub fn is_empty(&self) -> bool {
+        self.tenant.as_deref().is_none_or(str::is_empty) && self.sandbox_ids.is_empty()
+    }
+}
+
+/// A record a service kept despite a deletion request, and why
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RetainedRecord {
+    /// What kind of record, e.g. `snapshot`
+    pub kind: String,
+    pub id: String,
+    /// e.g. `legal_hold`
+    pub reason: String,
+}
+
+/// What one service deleted for a request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ServiceDeletion {
+    pub service: String,
+    /// Records removed, by table or kind
+    pub removed: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub retained: Vec<RetainedRecord>,
+    /// Sandboxes the deleted records belonged to, including any found
+    /// through the tenant, so other services can be asked about them too
+    #[serde(default)]
+    pub sandbox_ids: Vec<String>,
+    pub completed_at: DateTime<Utc>,
+}
+
+impl Schema for ServiceDeletion {
+    const NAME: &'static str = "sandstorm.service_deletion";
+    const VERSION: u32 = 1;
+}
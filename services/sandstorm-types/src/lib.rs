@@ -0,0 +1,19 @@
+//! Models shared between the Sandstorm Rust services.
+//!
+//! Every type that crosses a service boundary (over HTTP or on disk) lives
+//! here so the gateway, security monitor, snapshot vault and telemetry
+//! collector agree on a single wire format.
+
+pub mod deletion;
+pub mod logs;
+pub mod metadata;
+pub mod provenance;
+pub mod quota;
+pub mod recording;
+pub mod sandbox;
+pub mod schema;
+pub mod security;
+pub mod snapshot;
+pub mod telemetry;
+
+pub use schema::{Schema, SchemaError, Versioned};
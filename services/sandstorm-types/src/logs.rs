@@ -0,0 +1,44 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::Schema;
+
+/// One structured log line a service shipped
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogRecord {
+    pub timestamp: DateTime<Utc>,
+    /// `error`, `warn`, `info`, `debug` or `trace`
+    pub level: String,
+    /// Module the line was logged from
+    pub target: String,
+    pub message: String,
+    /// Sandbox the line is about, from the event or an enclosing span
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox_id: Option<String>,
+    /// Gateway run the line is about, from the event or an enclosing span
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    /// Every other field of the event and its spans
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub fields: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Batch of [`LogRecord`]s a service posts to the collector's
+/// `/api/telemetry/logs`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogBatch {
+    /// Retries resend the same id, so the collector stores a batch once
+    pub batch_id: Uuid,
+    /// `gateway`, `security-monitor`, `snapshot-vault` or
+    /// `telemetry-collector`
+    pub service: String,
+    /// Host the service runs on
+    pub instance: String,
+    pub records: Vec<LogRecord>,
+}
+
+impl Schema for LogBatch {
+    const NAME: &'static str = "sandstorm.log_batch";
+    const VERSION: u32 = 1;
+}
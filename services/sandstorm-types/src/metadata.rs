@@ -0,0 +1,92 @@
+//! What the gateway's metadata service tells workloads about their own
+//! sandbox, and the short-lived credentials it issues them so they can call
+//! other services without secrets baked into their image.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::sandbox::{IsolationLevel, RuntimeType};
+use crate::Schema;
+
+/// Environment variable telling the workload where the gateway's metadata
+/// service is
+pub const METADATA_URL_ENV: &str = "SANDSTORM_METADATA_URL";
+
+/// Environment variable holding the token a workload identifies its
+/// sandbox to the metadata service with
+pub const METADATA_TOKEN_ENV: &str = "SANDSTORM_METADATA_TOKEN";
+
+/// HTTP header carrying the metadata token
+pub const METADATA_TOKEN_HEADER: &str = "x-sandstorm-metadata-token";
+
+/// What the metadata service tells a workload about its own sandbox
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SandboxMetadata {
+    pub sandbox_id: Uuid,
+    pub run_id: Uuid,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    pub runtime_type: RuntimeType,
+    pub language: String,
+    pub isolation_level: IsolationLevel,
+    /// Labels from the run request
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    pub limits: SandboxLimits,
+}
+
+impl Schema for SandboxMetadata {
+    const NAME: &'static str = "sandstorm.sandbox_metadata";
+    const VERSION: u32 = 1;
+}
+
+/// Limits the sandbox was created with; `None` where the runtime's default
+/// applies
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SandboxLimits {
+    pub cpu_limit: Option<f64>,
+    /// Bytes
+    pub memory_limit: Option<u64>,
+    /// Seconds
+    pub timeout: Option<u64>,
+}
+
+/// A workload's request for a credential
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CredentialRequest {
+    /// Service the credential is meant for, checked by that service
+    #[serde(default)]
+    pub audience: Option<String>,
+}
+
+/// A short-lived credential naming the sandbox it was issued to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SandboxCredential {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// What a credential vouches for, as the gateway's introspection answers it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CredentialClaims {
+    pub sandbox_id: Uuid,
+    pub run_id: Uuid,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub audience: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Schema for CredentialClaims {
+    const NAME: &'static str = "sandstorm.credential_claims";
+    const VERSION: u32 = 1;
+}
+
+/// A service's request to check a credential presented to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntrospectRequest {
+    pub token: String,
+}
@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::security::{QuarantineRecord, SecurityEvent};
+use crate::snapshot::SnapshotMetadata;
+use crate::telemetry::SandboxRun;
+use crate::Schema;
+
+/// HTTP header carrying the run ID between services
+pub const RUN_ID_HEADER: &str = "x-sandstorm-run-id";
+
+/// Environment variable exposing the run ID inside the sandbox
+pub const RUN_ID_ENV: &str = "SANDSTORM_RUN_ID";
+
+/// Everything the services recorded about one sandbox, gathered by the
+/// gateway's provenance lookup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunProvenance {
+    pub sandbox_id: String,
+    pub run_id: Option<Uuid>,
+    pub runs: Vec<SandboxRun>,
+    pub security_events: Vec<SecurityEvent>,
+    pub quarantines: Vec<QuarantineRecord>,
+    pub snapshots: Vec<SnapshotMetadata>,
+    /// Services that could not be queried; their sections are empty
+    #[serde(default)]
+    pub unavailable: Vec<String>,
+}
+
+impl Schema for RunProvenance {
+    const NAME: &'static str = "sandstorm.run_provenance";
+    const VERSION: u32 = 1;
+}
@@ -0,0 +1,85 @@
+//! Monthly compute and cost quotas per tenant. The telemetry collector
+//! keeps the quotas and the usage they're measured against; the gateway
+//! checks them when admitting runs.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::Schema;
+
+/// What the gateway does with a run from a tenant whose quota is used up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaAction {
+    /// Refuse the run
+    #[default]
+    Reject,
+    /// Run it at low priority on the cheapest runtime, so it's the first
+    /// to be preempted
+    Downgrade,
+}
+
+/// A tenant's caps for a calendar month (UTC); unset caps are unlimited
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TenantQuota {
+    pub tenant: String,
+    /// Sandbox run time, in seconds
+    pub compute_seconds: Option<f64>,
+    /// Cost as reported by the runs
+    pub cost: Option<f64>,
+    #[serde(default)]
+    pub on_exhausted: QuotaAction,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A tenant's usage over the current month, against its quota if it has one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    pub tenant: String,
+    /// `YYYY-MM`
+    pub month: String,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub compute_seconds: f64,
+    pub cost: f64,
+    pub quota: Option<TenantQuota>,
+    /// Left under the quota's caps, never below zero; unset when uncapped
+    pub remaining_compute_seconds: Option<f64>,
+    pub remaining_cost: Option<f64>,
+    /// Whether any cap has been reached
+    pub exhausted: bool,
+}
+
+impl QuotaUsage {
+    /// Usage measured against `quota`
+    pub fn new(
+        tenant: String,
+        month: String,
+        period: (DateTime<Utc>, DateTime<Utc>),
+        compute_seconds: f64,
+        cost: f64,
+        quota: Option<TenantQuota>,
+    ) -> Self {
+        let remaining = |cap: Option<f64>, used: f64| cap.map(|cap| (cap - used).max(0.0));
+        let remaining_compute_seconds =
+            remaining(quota.as_ref().and_then(|quota| quota.compute_seconds), compute_seconds);
+        let remaining_cost = remaining(quota.as_ref().and_then(|quota| quota.cost), cost);
+        Self {
+            tenant,
+            month,
+            period_start: period.0,
+            period_end: period.1,
+            compute_seconds,
+            cost,
+            quota,
+            exhausted: remaining_compute_seconds == Some(0.0) || remaining_cost == Some(0.0),
+            remaining_compute_seconds,
+            remaining_cost,
+        }
+    }
+}
+
+impl Schema for QuotaUsage {
+    const NAME: &'static str = "sandstorm.quota_usage";
+    const VERSION: u32 = 1;
+}
@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::Schema;
+
+/// HTTP header naming the user or agent acting on a sandbox
+pub const USER_HEADER: &str = "x-sandstorm-user";
+
+/// How the recorded session reached the sandbox
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionKind {
+    /// One-shot command through the exec API
+    Exec,
+    /// Interactive terminal attached to the sandbox
+    Attach,
+    /// Output of the run's own command, for runs that capture it
+    Run,
+}
+
+/// Metadata for an asciicast v2 recording of a sandbox session, stored by
+/// the snapshot vault next to the cast itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionRecording {
+    pub id: Uuid,
+    pub sandbox_id: String,
+    /// Gateway run the sandbox belongs to, when known
+    #[serde(default)]
+    pub run_id: Option<Uuid>,
+    /// Who drove the session, from the `X-Sandstorm-User` header
+    pub user: String,
+    pub kind: SessionKind,
+    /// Command that was executed, or the shell attached to
+    pub command: Vec<String>,
+    pub exit_code: Option<i32>,
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    /// Size of the cast file
+    #[serde(default)]
+    pub size_bytes: u64,
+}
+
+impl Schema for SessionRecording {
+    const NAME: &'static str = "sandstorm.session_recording";
+    const VERSION: u32 = 1;
+}
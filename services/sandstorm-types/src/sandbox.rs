@@ -0,0 +1,309 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::Schema;
+
+/// Isolation level for sandbox execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum IsolationLevel {
+    /// Standard isolation using namespaces and cgroups
+    Standard,
+    /// Strong isolation using lightweight VMs or secure containers
+    Strong,
+    /// Maximum isolation using full VMs with hardware virtualization
+    Maximum,
+}
+
+/// Runtime type identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum RuntimeType {
+    Firecracker,
+    Gvisor,
+    Kata,
+    /// Hosted E2B sandboxes
+    E2b,
+    /// Hosted Modal sandboxes
+    Modal,
+    /// Hosted Daytona sandboxes
+    Daytona,
+    /// Simulated sandboxes for tests and local development
+    Mock,
+}
+
+/// What to optimize for when several runtimes can host a sandbox
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum OptimizationHint {
+    /// Lowest expected cost per successful run
+    Cheapest,
+    /// Lowest expected latency per successful run
+    Fastest,
+}
+
+/// Scheduling priority; when capacity runs out, sandboxes may be preempted
+/// to admit work of a strictly higher priority
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Batch work that may be snapshotted and resumed later
+    Low,
+    #[default]
+    Normal,
+    /// Latency-sensitive work that may preempt anything below it
+    High,
+}
+
+/// CPU architecture a sandbox runs on, named as by `uname -m`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum Arch {
+    #[serde(rename = "x86_64", alias = "amd64")]
+    X86_64,
+    #[serde(rename = "aarch64", alias = "arm64")]
+    Aarch64,
+}
+
+impl Arch {
+    /// Architecture this process was built for, if sandboxes can run on it
+    pub fn host() -> Option<Self> {
+        Self::parse(std::env::consts::ARCH)
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "x86_64" | "amd64" => Some(Self::X86_64),
+            "aarch64" | "arm64" => Some(Self::Aarch64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::X86_64 => "x86_64",
+            Self::Aarch64 => "aarch64",
+        }
+    }
+}
+
+impl std::fmt::Display for Arch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How much a sandbox may change and reach
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionMode {
+    #[default]
+    Standard,
+    /// For untrusted code: read-only root filesystem with size-capped tmpfs
+    /// scratch space, no outbound network and a reduced syscall profile
+    ReadOnly,
+}
+
+/// How gVisor intercepts a sandbox's system calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum GvisorPlatform {
+    /// Works everywhere, slowest
+    Ptrace,
+    /// Needs `/dev/kvm`; fastest on bare metal
+    Kvm,
+    /// Fast without virtualization; runsc's default in recent releases
+    Systrap,
+}
+
+/// Network stack of a gVisor sandbox, from least to most exposed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum GvisorNetwork {
+    /// No network at all, not even loopback
+    None,
+    /// gVisor's own network stack (runsc's default)
+    Sandbox,
+    /// The host's network stack, unisolated
+    Host,
+}
+
+/// runsc flags for a sandbox. Unset fields fall back to the runtime's
+/// configured defaults, then to runsc's own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(default, deny_unknown_fields)]
+pub struct GvisorOptions {
+    pub platform: Option<GvisorPlatform>,
+    pub network: Option<GvisorNetwork>,
+    /// Keep root filesystem writes in a memory-backed overlay
+    pub overlay: Option<bool>,
+    /// Write runsc debug logs for the sandbox
+    pub debug: Option<bool>,
+}
+
+impl GvisorOptions {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// These options, with unset fields taken from `defaults`
+    pub fn or(self, defaults: Self) -> Self {
+        Self {
+            platform: self.platform.or(defaults.platform),
+            network: self.network.or(defaults.network),
+            overlay: self.overlay.or(defaults.overlay),
+            debug: self.debug.or(defaults.debug),
+        }
+    }
+}
+
+/// Firecracker static CPU template: the CPUID and MSRs guests see are
+/// masked down to a baseline, hiding the host's CPU model and features
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "UPPERCASE")]
+pub enum CpuTemplate {
+    /// Intel Skylake, Cascade Lake and Ice Lake hosts, presented as a T2
+    /// instance
+    T2,
+    /// T2 with the speculation mitigations of a Skylake host
+    T2s,
+    /// T2 for Intel Cascade Lake and Ice Lake hosts only
+    T2cl,
+    /// T2 for AMD Milan hosts
+    T2a,
+    /// Intel Cascade Lake and Ice Lake hosts, presented as a C3 instance
+    C3,
+    /// Neoverse V1 hosts, presented as Neoverse N1
+    V1n1,
+}
+
+/// CPU side-channel controls for a Firecracker VM. Unset fields fall back
+/// to the runtime's configured defaults, then to Firecracker's own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(default, deny_unknown_fields)]
+pub struct FirecrackerOptions {
+    pub cpu_template: Option<CpuTemplate>,
+    /// Give the guest sibling hyperthreads; off by default
+    pub smt: Option<bool>,
+    /// Replace the host CPU's brand string with a generic one
+    pub mask_cpu_brand: Option<bool>,
+}
+
+impl FirecrackerOptions {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// These options, with unset fields taken from `defaults`
+    pub fn or(self, defaults: Self) -> Self {
+        Self {
+            cpu_template: self.cpu_template.or(defaults.cpu_template),
+            smt: self.smt.or(defaults.smt),
+            mask_cpu_brand: self.mask_cpu_brand.or(defaults.mask_cpu_brand),
+        }
+    }
+}
+
+/// Sandbox configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SandboxConfig {
+    pub id: Uuid,
+    pub image: String,
+    pub command: Vec<String>,
+    pub environment: HashMap<String, String>,
+    pub cpu_limit: Option<f64>,
+    pub memory_limit: Option<u64>, // bytes
+    pub timeout: Option<u64>,      // milliseconds
+    pub isolation_level: IsolationLevel,
+    pub runtime_preference: Option<RuntimeType>,
+    pub working_dir: Option<String>,
+    pub mounts: Vec<Mount>,
+    #[serde(default)]
+    pub execution_mode: ExecutionMode,
+    /// Size of each tmpfs scratch mount in read-only mode, in bytes
+    #[serde(default)]
+    pub scratch_size: Option<u64>,
+    /// Kernel parameters set inside the sandbox, e.g. `net.core.somaxconn`;
+    /// the gateway only accepts an allow-listed set
+    #[serde(default)]
+    pub sysctls: HashMap<String, String>,
+    /// runsc flags; only gVisor can run sandboxes that set any
+    #[serde(default)]
+    pub gvisor: GvisorOptions,
+    /// CPU template and SMT controls; only Firecracker can run sandboxes
+    /// that set any
+    #[serde(default)]
+    pub firecracker: FirecrackerOptions,
+    /// Architecture the sandbox must run on; any when unset
+    #[serde(default)]
+    pub arch: Option<Arch>,
+    /// Host directories stacked with overlayfs into the root filesystem,
+    /// lowest first; the image's root filesystem when empty
+    #[serde(default)]
+    pub rootfs_layers: Vec<String>,
+    /// Seconds after creation at which the gateway destroys the sandbox,
+    /// whatever it is doing; it lives until destroyed when unset
+    #[serde(default)]
+    pub ttl_seconds: Option<u64>,
+}
+
+impl Schema for SandboxConfig {
+    const NAME: &'static str = "sandstorm.sandbox_config";
+    const VERSION: u32 = 1;
+}
+
+/// Mount configuration for sandbox
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mount {
+    pub source: String,
+    pub destination: String,
+    pub read_only: bool,
+}
+
+/// Sandbox snapshot for stateful operations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SandboxSnapshot {
+    pub id: Uuid,
+    pub sandbox_id: Uuid,
+    pub runtime_type: RuntimeType,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub filesystem_state: Vec<u8>,
+    pub memory_state: Option<Vec<u8>>,
+    pub metadata: HashMap<String, serde_json::Value>,
+}
+
+impl Schema for SandboxSnapshot {
+    const NAME: &'static str = "sandstorm.sandbox_snapshot";
+    const VERSION: u32 = 1;
+}
+
+/// Who a sandbox belongs to and where it runs, as the gateway recorded it
+/// when creating the sandbox
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SandboxOwner {
+    pub sandbox_id: Uuid,
+    /// Tenant the sandbox was created for; `None` for sandboxes created
+    /// without one, like scheduled job runs
+    #[serde(default)]
+    pub tenant: Option<String>,
+    pub runtime_type: RuntimeType,
+}
+
+impl Schema for SandboxOwner {
+    const NAME: &'static str = "sandstorm.sandbox_owner";
+    const VERSION: u32 = 1;
+}
@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Name and version tag of a shared model.
+///
+/// Bump `VERSION` whenever a change is not backwards compatible for readers
+/// (a field is removed, renamed or changes meaning).
+pub trait Schema {
+    const NAME: &'static str;
+    const VERSION: u32;
+}
+
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    #[error("expected schema {expected}, got {found}")]
+    WrongSchema {
+        expected: &'static str,
+        found: String,
+    },
+    #[error("schema {name} version {found} is newer than supported version {supported}")]
+    UnsupportedVersion {
+        name: &'static str,
+        found: u32,
+        supported: u32,
+    },
+}
+
+/// Envelope tagging a payload with its schema name and version
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Versioned<T> {
+    pub schema: String,
+    pub version: u32,
+    pub data: T,
+}
+
+impl<T: Schema> Versioned<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            schema: T::NAME.to_string(),
+            version: T::VERSION,
+            data,
+        }
+    }
+
+    /// Unwrap the payload, rejecting other schemas and versions newer than
+    /// this build understands.
+    pub fn into_inner(self) -> Result<T, SchemaError> {
+        if self.schema != T::NAME {
+            return Err(SchemaError::WrongSchema {
+                expected: T::NAME,
+                found: self.schema,
+            });
+        }
+        if self.version > T::VERSION {
+            return Err(SchemaError::UnsupportedVersion {
+                name: T::NAME,
+                found: self.version,
+                supported: T::VERSION,
+            });
+        }
+        Ok(self.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Probe {
+        value: u32,
+    }
+
+    impl Schema for Probe {
+        const NAME: &'static str = "sandstorm.probe";
+        const VERSION: u32 = 2;
+    }
+
+    #[test]
+    fn round_trips_through_envelope() {
+        let json = serde_json::to_string(&Versioned::new(Probe { value: 7 })).unwrap();
+        let envelope: Versioned<Probe> = serde_json::from_str(&json).unwrap();
+        assert_eq!(envelope.version, 2);
+        assert_eq!(envelope.into_inner().unwrap(), Probe { value: 7 });
+    }
+
+    #[test]
+    fn rejects_newer_versions_and_other_schemas() {
+        let newer = Versioned {
+            schema: Probe::NAME.to_string(),
+            version: 3,
+            data: Probe { value: 1 },
+        };
+        assert!(matches!(
+            newer.into_inner(),
+            Err(SchemaError::UnsupportedVersion { found: 3, .. })
+        ));
+
+        let other = Versioned {
+            schema: "sandstorm.other".to_string(),
+            version: 1,
+            data: Probe { value: 1 },
+        };
+        assert!(matches!(
+            other.into_inner(),
+            Err(SchemaError::WrongSchema { .. })
+        ));
+    }
+}
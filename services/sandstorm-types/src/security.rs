@@ -0,0 +1,422 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::Schema;
+
+/// Security event raised by Falco or the eBPF probes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityEvent {
+    pub id: String,
+    pub event_type: EventType,
+    pub severity: Severity,
+    pub timestamp: DateTime<Utc>,
+    pub sandbox_id: String,
+    pub provider: String,
+    pub message: String,
+    pub details: serde_json::Value,
+    pub metadata: Option<serde_json::Value>,
+    pub falco_rule: Option<String>,
+    pub ebpf_trace: Option<String>,
+    /// Gateway run that produced the event, when known
+    #[serde(default)]
+    pub run_id: Option<Uuid>,
+    /// Identical events this record stands for, when the monitor aggregated
+    /// repeats of it
+    #[serde(default = "one")]
+    pub occurrences: u64,
+    /// When the last aggregated repeat happened
+    #[serde(default)]
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+impl Schema for SecurityEvent {
+    const NAME: &'static str = "sandstorm.security_event";
+    // Version 2 added aggregated occurrences. Version 3 limits severity to
+    // the four levels and event types to known or registered names.
+    const VERSION: u32 = 3;
+}
+
+fn one() -> u64 {
+    1
+}
+
+/// How serious a security event is. Levels are ordered, so a rule for
+/// `high` also matches `critical`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[serde(alias = "debug", alias = "info", alias = "informational", alias = "notice")]
+    Low,
+    #[serde(alias = "warn", alias = "warning")]
+    Medium,
+    #[serde(alias = "error")]
+    High,
+    #[serde(alias = "alert", alias = "emergency")]
+    Critical,
+}
+
+impl Severity {
+    pub const ALL: [Severity; 4] = [Severity::Low, Severity::Medium, Severity::High, Severity::Critical];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Severity {
+    type Err = UnknownSeverity;
+
+    /// Case-insensitive, accepting the same aliases as deserialization
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(value.trim().to_lowercase()))
+            .map_err(|_| UnknownSeverity(value.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("unknown severity {0:?}; expected low, medium, high or critical")]
+pub struct UnknownSeverity(pub String);
+
+/// What a security event is about. Names outside the built-in types are
+/// `Custom`; the security monitor only accepts custom types registered with
+/// it, so a misspelt type is rejected instead of matching nothing.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum EventType {
+    FileAccess,
+    NetworkActivity,
+    ProcessSpawn,
+    PrivilegeEscalation,
+    SuspiciousBehavior,
+    PolicyViolation,
+    /// Host activity attributed to a sandbox that should only have happened
+    /// inside it
+    SandboxEscape,
+    /// A stored snapshot no longer matches what was written, reported by
+    /// the vault's scrubber for the sandbox it was taken from
+    SnapshotCorruption,
+    Custom(String),
+}
+
+impl EventType {
+    pub const BUILT_IN: [EventType; 8] = [
+        EventType::FileAccess,
+        EventType::NetworkActivity,
+        EventType::ProcessSpawn,
+        EventType::PrivilegeEscalation,
+        EventType::SuspiciousBehavior,
+        EventType::PolicyViolation,
+        EventType::SandboxEscape,
+        EventType::SnapshotCorruption,
+    ];
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            EventType::FileAccess => "file_access",
+            EventType::NetworkActivity => "network_activity",
+            EventType::ProcessSpawn => "process_spawn",
+            EventType::PrivilegeEscalation => "privilege_escalation",
+            EventType::SuspiciousBehavior => "suspicious_behavior",
+            EventType::PolicyViolation => "policy_violation",
+            EventType::SandboxEscape => "sandbox_escape",
+            EventType::SnapshotCorruption => "snapshot_corruption",
+            EventType::Custom(name) => name,
+        }
+    }
+
+    pub fn is_custom(&self) -> bool {
+        matches!(self, EventType::Custom(_))
+    }
+}
+
+impl std::fmt::Display for EventType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for EventType {
+    type Err = InvalidEventType;
+
+    /// Names are case-insensitive, and `-` or spaces stand for `_`. Custom
+    /// names may use lowercase letters, digits, `_` and `.`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let name = value.trim().to_lowercase().replace(['-', ' '], "_");
+        Ok(match name.as_str() {
+            "file_access" | "file" => EventType::FileAccess,
+            "network_activity" | "network" => EventType::NetworkActivity,
+            "process_spawn" | "process" | "exec" => EventType::ProcessSpawn,
+            "privilege_escalation" | "privesc" => EventType::PrivilegeEscalation,
+            "suspicious_behavior" | "suspicious_behaviour" => EventType::SuspiciousBehavior,
+            "policy_violation" => EventType::PolicyViolation,
+            "sandbox_escape" | "escape" => EventType::SandboxEscape,
+            "snapshot_corruption" => EventType::SnapshotCorruption,
+            _ if !name.is_empty()
+                && name.len() <= 100
+                && name
+                    .bytes()
+                    .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'_' || byte == b'.') =>
+            {
+                EventType::Custom(name)
+            }
+            _ => return Err(InvalidEventType(value.to_string())),
+        })
+    }
+}
+
+impl TryFrom<String> for EventType {
+    type Error = InvalidEventType;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<EventType> for String {
+    fn from(event_type: EventType) -> Self {
+        event_type.as_str().to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid event type {0:?}; names use lowercase letters, digits, '_' and '.'")]
+pub struct InvalidEventType(pub String);
+
+/// A sandbox isolated by the security monitor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantineRecord {
+    pub id: String,
+    pub sandbox_id: String,
+    pub reason: String,
+    pub triggered_by: SecurityEvent,
+    pub start_time: DateTime<Utc>,
+    pub end_time: Option<DateTime<Utc>>,
+    pub auto_release: bool,
+    /// Conditions any one of which lets the monitor lift the quarantine on
+    /// its own; set when `auto_release` is
+    pub release_conditions: Option<Vec<ReleaseCondition>>,
+    #[serde(default)]
+    pub run_id: Option<Uuid>,
+    /// How far the sandbox is cut off
+    #[serde(default)]
+    pub mode: QuarantineMode,
+    /// Operator who approved the release, for `manual_approval`
+    #[serde(default)]
+    pub approved_by: Option<String>,
+    /// When a scan of the sandbox last passed during the quarantine, for
+    /// `scan_passed`
+    #[serde(default)]
+    pub scan_passed_at: Option<DateTime<Utc>>,
+    /// Condition the quarantine was released on; unset while it's active
+    /// and for releases an operator forced
+    #[serde(default)]
+    pub released_on: Option<ReleaseCondition>,
+}
+
+impl Schema for QuarantineRecord {
+    const NAME: &'static str = "sandstorm.quarantine_record";
+    // Version 2 added the quarantine mode. Version 3 made release conditions
+    // typed and added approval, scans and the condition released on.
+    const VERSION: u32 = 3;
+}
+
+/// A condition under which a quarantine may be lifted automatically
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReleaseCondition {
+    /// The sandbox reported no critical events for this long, and has been
+    /// quarantined at least as long
+    NoCriticalEvents { minutes: u32 },
+    /// An operator approved the release
+    ManualApproval,
+    /// A scan of the sandbox passed after it was quarantined
+    ScanPassed,
+    /// The quarantine has lasted this long
+    TimeElapsed { minutes: u32 },
+}
+
+/// How a quarantined sandbox is cut off. Modes are ordered from least to
+/// most restrictive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum QuarantineMode {
+    /// Recorded and watched, but the sandbox keeps running as before
+    ObserveOnly,
+    /// New outbound connections are dropped; replies to inbound ones pass
+    BlockEgress,
+    /// All traffic to and from the sandbox is dropped
+    BlockAllNetwork,
+    /// The sandbox is paused and its network cut
+    #[default]
+    Freeze,
+}
+
+impl QuarantineMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuarantineMode::ObserveOnly => "observe_only",
+            QuarantineMode::BlockEgress => "block_egress",
+            QuarantineMode::BlockAllNetwork => "block_all_network",
+            QuarantineMode::Freeze => "freeze",
+        }
+    }
+}
+
+impl std::fmt::Display for QuarantineMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for QuarantineMode {
+    type Err = UnknownQuarantineMode;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(value.trim().to_lowercase()))
+            .map_err(|_| UnknownQuarantineMode(value.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("unknown quarantine mode {0:?}; expected observe_only, block_egress, block_all_network or freeze")]
+pub struct UnknownQuarantineMode(pub String);
+
+/// Sent by the security monitor to the gateway's
+/// `PUT /v1/sandboxes/:id/quarantine` to enforce a quarantine
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuarantineEnforcement {
+    pub quarantine_id: String,
+    pub mode: QuarantineMode,
+}
+
+/// HMAC-SHA256 of a [`SecuritySignalBatch`] request, as `sha256=<hex>`. The
+/// MAC covers the timestamp header's value, a `.`, then the request body.
+pub const SIGNATURE_HEADER: &str = "x-sandstorm-signature";
+
+/// Unix time, in seconds, at which a signed request was sent
+pub const SIGNATURE_TIMESTAMP_HEADER: &str = "x-sandstorm-timestamp";
+
+/// Security incidents of one sandbox since the monitor last forwarded it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecuritySignal {
+    pub sandbox_id: String,
+    pub provider: String,
+    /// Image the sandbox ran, when its events carry one
+    #[serde(default)]
+    pub image: Option<String>,
+    #[serde(default)]
+    pub run_id: Option<Uuid>,
+    /// Events a policy alerted on or quarantined for
+    pub violations: u64,
+    /// Quarantines started
+    pub quarantines: u64,
+    pub max_severity: Severity,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Signed batch of [`SecuritySignal`]s the security monitor posts to the
+/// collector's `/api/telemetry/security-signals`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecuritySignalBatch {
+    /// Retries resend the same id, so the collector stores a batch once
+    pub batch_id: Uuid,
+    pub sent_at: DateTime<Utc>,
+    pub signals: Vec<SecuritySignal>,
+}
+
+impl Schema for SecuritySignalBatch {
+    const NAME: &'static str = "sandstorm.security_signal_batch";
+    const VERSION: u32 = 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_severities_and_event_types() {
+        assert_eq!("Warning".parse::<Severity>(), Ok(Severity::Medium));
+        assert_eq!(serde_json::from_str::<Severity>("\"emergency\"").unwrap(), Severity::Critical);
+        assert!(serde_json::from_str::<Severity>("\"crtical\"").is_err());
+        assert!(Severity::High > Severity::Medium);
+        assert_eq!(serde_json::to_string(&Severity::Low).unwrap(), "\"low\"");
+
+        assert_eq!("File-Access".parse::<EventType>(), Ok(EventType::FileAccess));
+        assert_eq!(
+            serde_json::from_str::<EventType>("\"gpu.memory_read\"").unwrap(),
+            EventType::Custom("gpu.memory_read".to_string())
+        );
+        assert!("file access!".parse::<EventType>().is_err());
+        assert_eq!(serde_json::to_string(&EventType::ProcessSpawn).unwrap(), "\"process_spawn\"");
+    }
+
+    #[test]
+    fn quarantine_records_default_to_freezing() {
+        let record = serde_json::json!({
+            "id": "quarantine_1",
+            "sandbox_id": "sandbox_1",
+            "reason": "Rule 'Auto-Quarantine Critical Events' triggered",
+            "triggered_by": {
+                "id": "event_1",
+                "event_type": "privilege_escalation",
+                "severity": "critical",
+                "timestamp": "2025-01-01T00:00:00Z",
+                "sandbox_id": "sandbox_1",
+                "provider": "gateway",
+                "message": "setuid binary executed",
+                "details": {},
+                "metadata": null,
+                "falco_rule": null,
+                "ebpf_trace": null
+            },
+            "start_time": "2025-01-01T00:00:00Z",
+            "end_time": null,
+            "auto_release": false,
+            "release_conditions": null
+        });
+        let record: QuarantineRecord = serde_json::from_value(record).unwrap();
+        assert_eq!(record.mode, QuarantineMode::Freeze);
+        assert_eq!(record.released_on, None);
+
+        assert_eq!("Block_Egress".parse::<QuarantineMode>(), Ok(QuarantineMode::BlockEgress));
+        assert!(QuarantineMode::ObserveOnly < QuarantineMode::BlockAllNetwork);
+    }
+
+    #[test]
+    fn release_conditions_are_tagged_by_type() {
+        let conditions: Vec<ReleaseCondition> = serde_json::from_value(serde_json::json!([
+            {"type": "no_critical_events", "minutes": 30},
+            {"type": "manual_approval"},
+            {"type": "time_elapsed", "minutes": 1440}
+        ]))
+        .unwrap();
+        assert_eq!(
+            conditions,
+            [
+                ReleaseCondition::NoCriticalEvents { minutes: 30 },
+                ReleaseCondition::ManualApproval,
+                ReleaseCondition::TimeElapsed { minutes: 1440 },
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(ReleaseCondition::ScanPassed).unwrap(),
+            serde_json::json!({"type": "scan_passed"})
+        );
+        assert!(serde_json::from_value::<ReleaseCondition>(serde_json::json!("manual approval")).is_err());
+    }
+}
@@ -0,0 +1,405 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::Schema;
+
+/// Header naming the tenant a request acts for, in the vault, or reports
+/// sandbox runs for, in the telemetry collector
+pub const TENANT_HEADER: &str = "x-sandstorm-tenant";
+
+/// Tenant of requests without an `X-Sandstorm-Tenant` header, and of
+/// snapshots and runs stored before tenants existed
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Header carrying the hex SHA-256 of a chunk sent to an upload
+pub const CHUNK_SHA256_HEADER: &str = "x-sandstorm-chunk-sha256";
+
+/// Metadata the snapshot vault keeps for every stored snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotMetadata {
+    pub id: Uuid,
+    pub sandbox_id: String,
+    pub provider: String,
+    pub filesystem_hash: String,
+    pub memory_hash: Option<String>,
+    pub size_bytes: u64,
+    pub created_at: DateTime<Utc>,
+    pub metadata: serde_json::Value,
+    pub has_blob: bool,
+    /// Gateway run the snapshot was taken from, when known
+    #[serde(default)]
+    pub run_id: Option<Uuid>,
+    /// Tenant that owns the snapshot
+    #[serde(default = "default_tenant")]
+    pub tenant: String,
+    /// How the blob is encrypted; `None` for plaintext blobs
+    #[serde(default)]
+    pub encryption: Option<BlobEncryption>,
+    /// Where the blob is stored
+    #[serde(default)]
+    pub tier: StorageTier,
+    /// Pinned blobs are never moved to cold storage
+    #[serde(default)]
+    pub pinned: bool,
+    /// When the blob was last downloaded
+    #[serde(default)]
+    pub accessed_at: Option<DateTime<Utc>>,
+    /// Checks the blob passed when it was stored; `None` when it wasn't
+    /// validated
+    #[serde(default)]
+    pub validation: Option<BlobValidation>,
+    /// Latest scan of the blob before a restore; `None` until one runs
+    #[serde(default)]
+    pub scan: Option<BlobScan>,
+    /// Snapshot this one is layered on. Its blob holds only the files that
+    /// differ from the base's stack, so templates sharing a base store the
+    /// base once.
+    #[serde(default)]
+    pub base_layer: Option<Uuid>,
+    /// Held snapshots can't be deleted, by their tenant, garbage collection
+    /// or a data deletion request, until the hold is lifted
+    #[serde(default)]
+    pub legal_hold: bool,
+}
+
+/// Format a snapshot blob declares, checked by the vault when it is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlobFormat {
+    /// ext4 filesystem image
+    Ext4,
+    /// CRIU image directory, as a tar archive
+    CriuImages,
+    /// Snapshot manifest JSON, as served by the vault
+    VaultManifest,
+}
+
+impl BlobFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlobFormat::Ext4 => "ext4",
+            BlobFormat::CriuImages => "criu_images",
+            BlobFormat::VaultManifest => "vault_manifest",
+        }
+    }
+}
+
+/// Result of validating a blob on admission
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlobValidation {
+    /// Format the blob was verified to be, if one was declared
+    pub format: Option<BlobFormat>,
+    /// Whether the blob's size matched a declared `size_bytes`
+    pub size_verified: bool,
+    pub validated_at: DateTime<Utc>,
+}
+
+/// Result of running the vault's scanners over a blob before it is restored
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlobScan {
+    /// Fingerprint of the scanners and their rules; the blob is scanned
+    /// again once it changes
+    pub ruleset: String,
+    /// Whether restores of the snapshot are refused
+    pub blocked: bool,
+    pub indicators: Vec<ScanIndicator>,
+    pub scanned_at: DateTime<Utc>,
+}
+
+/// Something a scanner found in a blob
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScanIndicator {
+    /// Scanner that found it, e.g. `hash_list` or `yara`
+    pub scanner: String,
+    /// Rule or listed hash that matched
+    pub rule: String,
+    /// Where in the blob, e.g. an archive member; `None` for the whole blob
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+}
+
+/// Storage tier holding a snapshot blob
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageTier {
+    /// The vault's local disk
+    #[default]
+    Hot,
+    /// Object storage, restored to local disk when read
+    Cold,
+}
+
+impl StorageTier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageTier::Hot => "hot",
+            StorageTier::Cold => "cold",
+        }
+    }
+}
+
+fn default_tenant() -> String {
+    DEFAULT_TENANT.to_string()
+}
+
+/// Envelope encryption of a snapshot blob. The blob is sealed with its own
+/// data key, which is stored here wrapped by the owning tenant's key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlobEncryption {
+    /// Generation of the tenant key that wraps the data key
+    pub key_generation: u32,
+    /// Base64 of the wrapped data key
+    pub wrapped_key: String,
+    /// Plaintext bytes per separately sealed segment; `None` when the blob
+    /// was sealed whole
+    #[serde(default)]
+    pub segment_size: Option<u64>,
+}
+
+/// How to fetch a snapshot blob in independently verifiable chunks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub snapshot_id: Uuid,
+    pub size_bytes: u64,
+    /// Size of every chunk but the last
+    pub chunk_size: u64,
+    pub chunks: Vec<ChunkInfo>,
+}
+
+/// One chunk of a snapshot blob
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkInfo {
+    pub index: u64,
+    /// Byte offset of the chunk in the blob
+    pub offset: u64,
+    pub size: u64,
+    /// Hex SHA-256 of the chunk
+    pub sha256: String,
+}
+
+/// Body of a request to start a chunked upload
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadRequest {
+    pub size_bytes: u64,
+    /// Hex SHA-256 of the whole blob, checked when the upload completes
+    pub sha256: String,
+}
+
+/// A chunked snapshot upload the vault is receiving. Chunks may arrive in
+/// any order and be sent again; an interrupted upload resumes by sending
+/// the chunks missing from `received`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadSession {
+    pub id: Uuid,
+    /// Tenant the snapshot will belong to
+    pub tenant: String,
+    pub size_bytes: u64,
+    /// Hex SHA-256 of the whole blob
+    pub sha256: String,
+    /// Size of every chunk but the last
+    pub chunk_size: u64,
+    /// Indexes of the chunks stored so far, in order
+    pub received: Vec<u64>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl UploadSession {
+    pub fn chunk_count(&self) -> u64 {
+        self.size_bytes.div_ceil(self.chunk_size)
+    }
+
+    /// Size chunk `index` must have
+    pub fn chunk_len(&self, index: u64) -> u64 {
+        self.chunk_size.min(self.size_bytes.saturating_sub(index * self.chunk_size))
+    }
+
+    /// Chunks still to be sent
+    pub fn missing(&self) -> Vec<u64> {
+        (0..self.chunk_count())
+            .filter(|index| self.received.binary_search(index).is_err())
+            .collect()
+    }
+}
+
+/// Body of a request to finish an upload: the snapshot's fields, without
+/// the blob the upload carried
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotUpload {
+    pub sandbox_id: String,
+    pub provider: String,
+    pub filesystem_hash: String,
+    #[serde(default)]
+    pub memory_hash: Option<String>,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+    /// Format to verify the blob against
+    #[serde(default)]
+    pub format: Option<BlobFormat>,
+    #[serde(default)]
+    pub run_id: Option<Uuid>,
+    /// Keep the blob on local disk regardless of the tiering policy
+    #[serde(default)]
+    pub pinned: bool,
+    /// Snapshot the blob is layered on
+    #[serde(default)]
+    pub base_layer: Option<Uuid>,
+}
+
+/// Body of an edge agent's registration of a snapshot it took while it
+/// couldn't reach the vault, to be relayed once it can
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelayRegistration {
+    pub size_bytes: u64,
+    /// Hex SHA-256 of the whole blob
+    pub sha256: String,
+    /// When the agent took the snapshot
+    pub taken_at: DateTime<Utc>,
+    /// Fields the snapshot is stored with once its blob arrives
+    pub snapshot: SnapshotUpload,
+}
+
+/// How far a relayed snapshot has got
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum RelayState {
+    /// Registered; no chunks have arrived
+    Pending,
+    /// Some chunks have arrived
+    Uploading,
+    /// In the vault as `snapshot_id`; the agent may drop its copy
+    Stored { snapshot_id: Uuid },
+    /// The blob was rejected; registering the snapshot again starts over
+    Failed { error: String },
+}
+
+/// A snapshot an edge agent is relaying to the vault through a chunked
+/// upload, keyed by the agent and its own ID for the snapshot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelayTransfer {
+    pub agent_id: String,
+    /// The agent's ID for the snapshot
+    pub local_id: String,
+    /// Tenant the snapshot will belong to
+    pub tenant: String,
+    pub size_bytes: u64,
+    pub sha256: String,
+    pub taken_at: DateTime<Utc>,
+    pub snapshot: SnapshotUpload,
+    #[serde(flatten)]
+    pub state: RelayState,
+    /// Upload the agent sends chunks to, until the snapshot is stored. An
+    /// upload left unfinished for a day is replaced by a new one.
+    #[serde(default)]
+    pub upload_id: Option<Uuid>,
+    pub chunk_count: u64,
+    pub chunks_received: u64,
+    pub registered_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Relayed snapshots of one edge agent, by state
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RelayAgentSummary {
+    pub agent_id: String,
+    pub pending: u64,
+    pub uploading: u64,
+    pub stored: u64,
+    pub failed: u64,
+    /// Bytes of the snapshots not stored yet
+    pub bytes_outstanding: u64,
+    /// Last time any of the agent's transfers changed
+    #[serde(default)]
+    pub last_activity: Option<DateTime<Utc>>,
+}
+
+/// A holder's claim on a snapshot it may resume. The vault's garbage
+/// collection never deletes a snapshot with an unexpired lease.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotLease {
+    pub id: Uuid,
+    pub snapshot_id: Uuid,
+    /// Tenant of the snapshot
+    pub tenant: String,
+    /// Who holds the lease, e.g. a gateway instance
+    pub holder: String,
+    /// Why, e.g. `template`, `warm_pool` or `restore`
+    pub purpose: String,
+    pub created_at: DateTime<Utc>,
+    /// Renew before this, or the lease lapses
+    pub expires_at: DateTime<Utc>,
+}
+
+impl SnapshotLease {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+}
+
+/// Body of a request to take or renew a lease
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LeaseRequest {
+    #[serde(default)]
+    pub holder: String,
+    #[serde(default)]
+    pub purpose: String,
+    /// How long the lease lasts from now; the vault's default when `None`
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+}
+
+/// A tenant's JSON Schema for snapshot `metadata`, and the metadata fields
+/// the vault indexes for queries
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetadataSchema {
+    pub tenant: String,
+    /// Schema the `metadata` of the tenant's new snapshots must match
+    pub schema: serde_json::Value,
+    /// Top-level metadata fields snapshots can be listed by
+    pub indexes: Vec<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Body of a request to set a tenant's metadata schema
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetadataSchemaRequest {
+    pub schema: serde_json::Value,
+    /// Each field needs a scalar `type` under the schema's `properties`
+    #[serde(default)]
+    pub indexes: Vec<String>,
+}
+
+impl Schema for SnapshotMetadata {
+    const NAME: &'static str = "sandstorm.snapshot_metadata";
+    // Versions 2 and 3 added blob encryption and cold storage, which older
+    // vaults would ignore. Version 4 seals encrypted blobs in segments, which
+    // older vaults can't open. Version 5 records admission validation.
+    // Version 6 records pre-restore scans, which older vaults would ignore
+    // and restore blocked snapshots.
+    const VERSION: u32 = 6;
+}
+
+impl Schema for SnapshotManifest {
+    const NAME: &'static str = "sandstorm.snapshot_manifest";
+    const VERSION: u32 = 1;
+}
+
+impl Schema for UploadSession {
+    const NAME: &'static str = "sandstorm.upload_session";
+    const VERSION: u32 = 1;
+}
+
+impl Schema for RelayTransfer {
+    const NAME: &'static str = "sandstorm.relay_transfer";
+    const VERSION: u32 = 1;
+}
+
+impl Schema for SnapshotLease {
+    const NAME: &'static str = "sandstorm.snapshot_lease";
+    const VERSION: u32 = 1;
+}
+
+impl Schema for MetadataSchema {
+    const NAME: &'static str = "sandstorm.metadata_schema";
+    const VERSION: u32 = 1;
+}
@@ -0,0 +1,452 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::sandbox::{IsolationLevel, Priority, RuntimeType};
+use crate::snapshot::DEFAULT_TENANT;
+use crate::Schema;
+
+/// A single sandbox execution as recorded by the telemetry collector
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SandboxRun {
+    pub id: Uuid,
+    pub sandbox_id: String,
+    pub provider: String,
+    pub language: String,
+    pub exit_code: i32,
+    pub duration_ms: i64,
+    pub cost: f64,
+    pub cpu_requested: Option<f64>,
+    pub memory_requested: Option<i32>,
+    pub has_gpu: bool,
+    pub timeout_ms: Option<i64>,
+    pub success: bool,
+    pub cpu_percent: Option<f64>,
+    pub memory_mb: Option<f64>,
+    pub network_rx_bytes: Option<i64>,
+    pub network_tx_bytes: Option<i64>,
+    pub agent_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub gpu_type: Option<String>,
+    pub gpu_count: Option<i32>,
+    pub gpu_utilization_percent: Option<f64>,
+    pub gpu_memory_used_mb: Option<f64>,
+    pub gpu_seconds: Option<f64>,
+    pub queued_ms: Option<i64>,
+    pub provision_ms: Option<i64>,
+    pub exec_ms: Option<i64>,
+    pub teardown_ms: Option<i64>,
+    /// Gateway run this execution belongs to, when known
+    #[serde(default)]
+    pub run_id: Option<Uuid>,
+    /// Price of the run from the collector's rate catalog, unset when the
+    /// provider had no rates then. `cost` is what the reporter claimed.
+    #[serde(default)]
+    pub estimated_cost: Option<f64>,
+    /// Tenant the run is billed to
+    #[serde(default = "default_tenant")]
+    pub tenant: String,
+    /// Why a failed run failed: `oom`, `timeout`, `nonzero_exit`,
+    /// `provider_error` or `infra_error`. Unset for successful runs.
+    #[serde(default)]
+    pub failure_class: Option<String>,
+    /// Attribution labels such as `team`, `project` or `job`, as a JSON
+    /// object of strings
+    #[serde(default = "empty_labels")]
+    pub labels: serde_json::Value,
+}
+
+impl Schema for SandboxRun {
+    const NAME: &'static str = "sandstorm.sandbox_run";
+    // Version 2 added the catalog estimate, version 3 the tenant, version 4
+    // the failure class, version 5 the labels
+    const VERSION: u32 = 5;
+}
+
+fn default_tenant() -> String {
+    DEFAULT_TENANT.to_string()
+}
+
+fn empty_labels() -> serde_json::Value {
+    serde_json::Value::Object(Default::default())
+}
+
+/// Aggregate outcomes for one provider over a time window, as served by the
+/// collector's `/api/telemetry/provider-stats/:provider`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderStats {
+    /// Mean run duration in milliseconds
+    pub avg_latency: f64,
+    pub avg_cost: f64,
+    /// Fraction of runs that succeeded, from 0.0 to 1.0
+    pub success_rate: f64,
+    pub total_runs: i64,
+    #[serde(default)]
+    pub by_accelerator: Vec<AcceleratorStats>,
+    /// Fraction of runs whose sandbox the security monitor reported
+    /// violations or quarantines for, from 0.0 to 1.0
+    #[serde(default)]
+    pub security_incident_rate: f64,
+}
+
+impl Schema for ProviderStats {
+    const NAME: &'static str = "sandstorm.provider_stats";
+    // Version 2 added the security incident rate
+    const VERSION: u32 = 2;
+}
+
+/// [`ProviderStats`] for runs on a single accelerator type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcceleratorStats {
+    /// GPU type, or "none" for CPU-only runs
+    pub accelerator: String,
+    pub avg_latency: f64,
+    pub avg_cost: f64,
+    pub success_rate: f64,
+    pub total_runs: i64,
+    pub avg_gpu_utilization_percent: Option<f64>,
+    pub avg_gpu_memory_used_mb: Option<f64>,
+    pub total_gpu_seconds: f64,
+}
+
+/// What happened to a sandbox in a [`PreemptionEvent`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreemptionAction {
+    /// Snapshotted and stopped to admit higher-priority work
+    Preempted,
+    /// Restored from its preemption snapshot
+    Resumed,
+}
+
+/// A sandbox being preempted by the gateway, or resumed afterwards
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreemptionEvent {
+    pub id: Uuid,
+    /// Sandbox that was preempted
+    pub sandbox_id: Uuid,
+    pub run_id: Option<Uuid>,
+    pub provider: String,
+    pub priority: Priority,
+    pub action: PreemptionAction,
+    /// Priority of the request that needed the capacity, for preemptions
+    pub preempted_by: Option<Priority>,
+    /// Sandbox restored from the snapshot, for resumes
+    pub resumed_as: Option<Uuid>,
+    pub snapshot_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Schema for PreemptionEvent {
+    const NAME: &'static str = "sandstorm.preemption_event";
+    const VERSION: u32 = 1;
+}
+
+/// An edge agent's load and spare capacity as of its latest heartbeat, as
+/// served in the collector's `/v1/edge/capacity` feed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EdgeCapacity {
+    pub agent_id: String,
+    pub agent_name: Option<String>,
+    /// URL the agent takes runs on
+    pub endpoint: Option<String>,
+    pub status: String,
+    pub queue_depth: u32,
+    pub running: u32,
+    /// Percent of the agent's CPU left, when it reports usage
+    pub cpu_headroom_percent: Option<f64>,
+    /// Percent of the agent's memory left, when it reports usage
+    pub memory_headroom_percent: Option<f64>,
+    pub last_heartbeat: DateTime<Utc>,
+    /// Whether the agent can take a run: recently heard from, reachable,
+    /// running and not out of CPU or memory
+    pub schedulable: bool,
+    /// Why the agent can't take a run, when it can't
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl EdgeCapacity {
+    /// Sandboxes running or queued on the agent, plus its busier resource
+    /// as a fraction, so agents with equal work are told apart by how
+    /// loaded their hosts are. Lower is better.
+    pub fn load(&self) -> f64 {
+        let used = [self.cpu_headroom_percent, self.memory_headroom_percent]
+            .into_iter()
+            .flatten()
+            .map(|headroom| (100.0 - headroom).clamp(0.0, 100.0))
+            .fold(0.0, f64::max);
+        (self.running + self.queue_depth) as f64 + used / 100.0
+    }
+}
+
+/// Every edge agent heard from, least loaded first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EdgeCapacityFeed {
+    pub generated_at: DateTime<Utc>,
+    pub agents: Vec<EdgeCapacity>,
+}
+
+impl Schema for EdgeCapacityFeed {
+    const NAME: &'static str = "sandstorm.edge_capacity_feed";
+    const VERSION: u32 = 1;
+}
+
+/// Distribution of a benchmark timing, in milliseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub mean: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl LatencySummary {
+    /// Summary of the samples, or `None` when there are none
+    pub fn of(samples: &[f64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let percentile = |p: f64| sorted[((sorted.len() - 1) as f64 * p).round() as usize];
+        Some(Self {
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+/// One benchmark workload measured on one runtime at one isolation level,
+/// as recorded by the gateway's `/v1/benchmarks` and stored by the collector
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkResult {
+    /// Benchmark run the measurement belongs to
+    pub benchmark_id: Uuid,
+    pub runtime: RuntimeType,
+    pub isolation_level: IsolationLevel,
+    pub workload: String,
+    /// Sandboxes started for the workload
+    pub iterations: u32,
+    /// Iterations whose sandbox failed to start or whose workload failed
+    pub failures: u32,
+    /// Time to create and start a sandbox; `None` if none started
+    pub cold_start_ms: Option<LatencySummary>,
+    /// Time to run the workload in a started sandbox
+    pub exec_ms: Option<LatencySummary>,
+    /// Workload executions completed per second with several running at
+    /// once in a warm sandbox
+    pub execs_per_sec: Option<f64>,
+    /// Last failure, if any
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Schema for BenchmarkResult {
+    const NAME: &'static str = "sandstorm.benchmark_result";
+    const VERSION: u32 = 1;
+}
+
+/// How one runtime and isolation level did on a workload, against the best
+/// of those measured. The `*_vs_best` factors are 1.0 for the best and grow
+/// for slower ones (or, for throughput, ones doing less).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkStanding {
+    pub runtime: RuntimeType,
+    pub isolation_level: IsolationLevel,
+    /// Results the standing averages over
+    pub samples: usize,
+    pub cold_start_p50_ms: Option<f64>,
+    pub exec_p50_ms: Option<f64>,
+    pub execs_per_sec: Option<f64>,
+    pub cold_start_vs_best: Option<f64>,
+    pub exec_vs_best: Option<f64>,
+    pub throughput_vs_best: Option<f64>,
+    /// Failed iterations over all iterations
+    pub failure_rate: f64,
+}
+
+/// Runtimes and isolation levels ranked on one workload, fastest cold start
+/// plus execution first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkComparison {
+    pub workload: String,
+    pub standings: Vec<BenchmarkStanding>,
+}
+
+impl BenchmarkComparison {
+    /// Compare results per workload, averaging those of the same runtime and
+    /// isolation level (e.g. from several benchmark runs)
+    pub fn from_results(results: &[BenchmarkResult]) -> Vec<Self> {
+        let mut groups: Vec<(&str, RuntimeType, IsolationLevel, Vec<&BenchmarkResult>)> = Vec::new();
+        for result in results {
+            let key = (result.workload.as_str(), result.runtime, result.isolation_level);
+            match groups
+                .iter_mut()
+                .find(|(workload, runtime, level, _)| (*workload, *runtime, *level) == key)
+            {
+                Some(group) => group.3.push(result),
+                None => groups.push((key.0, key.1, key.2, vec![result])),
+            }
+        }
+
+        let mut comparisons: Vec<Self> = Vec::new();
+        for (workload, runtime, isolation_level, results) in groups {
+            let mean = |value: fn(&BenchmarkResult) -> Option<f64>| {
+                let values: Vec<f64> = results.iter().filter_map(|result| value(result)).collect();
+                (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+            };
+            let iterations: u32 = results.iter().map(|result| result.iterations).sum();
+            let failures: u32 = results.iter().map(|result| result.failures).sum();
+            let standing = BenchmarkStanding {
+                runtime,
+                isolation_level,
+                samples: results.len(),
+                cold_start_p50_ms: mean(|result| result.cold_start_ms.map(|summary| summary.p50)),
+                exec_p50_ms: mean(|result| result.exec_ms.map(|summary| summary.p50)),
+                execs_per_sec: mean(|result| result.execs_per_sec),
+                cold_start_vs_best: None,
+                exec_vs_best: None,
+                throughput_vs_best: None,
+                failure_rate: if iterations == 0 {
+                    0.0
+                } else {
+                    f64::from(failures) / f64::from(iterations)
+                },
+            };
+            match comparisons.iter_mut().find(|comparison| comparison.workload == workload) {
+                Some(comparison) => comparison.standings.push(standing),
+                None => comparisons.push(Self {
+                    workload: workload.to_string(),
+                    standings: vec![standing],
+                }),
+            }
+        }
+
+        for comparison in &mut comparisons {
+            comparison.rank();
+        }
+        comparisons.sort_by(|a, b| a.workload.cmp(&b.workload));
+        comparisons
+    }
+
+    fn rank(&mut self) {
+        let best = |value: fn(&BenchmarkStanding) -> Option<f64>, highest: bool| {
+            self.standings
+                .iter()
+                .filter_map(value)
+                .filter(|value| *value > 0.0)
+                .reduce(|a, b| if (b > a) == highest { b } else { a })
+        };
+        let best_cold_start = best(|standing| standing.cold_start_p50_ms, false);
+        let best_exec = best(|standing| standing.exec_p50_ms, false);
+        let best_throughput = best(|standing| standing.execs_per_sec, true);
+
+        for standing in &mut self.standings {
+            standing.cold_start_vs_best = standing
+                .cold_start_p50_ms
+                .zip(best_cold_start)
+                .map(|(value, best)| value / best);
+            standing.exec_vs_best = standing.exec_p50_ms.zip(best_exec).map(|(value, best)| value / best);
+            standing.throughput_vs_best = standing
+                .execs_per_sec
+                .zip(best_throughput)
+                .filter(|(value, _)| *value > 0.0)
+                .map(|(value, best)| best / value);
+        }
+        // Standings that never started a sandbox go last
+        let total = |standing: &BenchmarkStanding| {
+            standing
+                .cold_start_p50_ms
+                .map(|cold_start| cold_start + standing.exec_p50_ms.unwrap_or(0.0))
+                .unwrap_or(f64::INFINITY)
+        };
+        self.standings.sort_by(|a, b| total(a).total_cmp(&total(b)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(runtime: RuntimeType, cold_start: f64, exec: f64, throughput: f64) -> BenchmarkResult {
+        let summary = |value: f64| LatencySummary {
+            mean: value,
+            p50: value,
+            p95: value,
+            min: value,
+            max: value,
+        };
+        BenchmarkResult {
+            benchmark_id: Uuid::new_v4(),
+            runtime,
+            isolation_level: IsolationLevel::Strong,
+            workload: "python-startup".to_string(),
+            iterations: 2,
+            failures: 0,
+            cold_start_ms: Some(summary(cold_start)),
+            exec_ms: Some(summary(exec)),
+            execs_per_sec: Some(throughput),
+            error: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn benchmark_comparisons_rank_against_the_best() {
+        let results = [
+            result(RuntimeType::Kata, 800.0, 40.0, 10.0),
+            result(RuntimeType::Gvisor, 300.0, 60.0, 20.0),
+            result(RuntimeType::Gvisor, 500.0, 60.0, 20.0),
+        ];
+        let comparisons = BenchmarkComparison::from_results(&results);
+        assert_eq!(comparisons.len(), 1);
+
+        let standings = &comparisons[0].standings;
+        assert_eq!(standings[0].runtime, RuntimeType::Gvisor);
+        assert_eq!(standings[0].samples, 2);
+        assert_eq!(standings[0].cold_start_p50_ms, Some(400.0));
+        assert_eq!(standings[0].cold_start_vs_best, Some(1.0));
+        assert_eq!(standings[0].exec_vs_best, Some(1.5));
+        assert_eq!(standings[1].cold_start_vs_best, Some(2.0));
+        assert_eq!(standings[1].throughput_vs_best, Some(2.0));
+    }
+
+    #[test]
+    fn edge_load_counts_work_then_the_busier_resource() {
+        let capacity = EdgeCapacity {
+            agent_id: "edge-1".to_string(),
+            agent_name: None,
+            endpoint: Some("http://edge-1:8080".to_string()),
+            status: "running".to_string(),
+            queue_depth: 1,
+            running: 2,
+            cpu_headroom_percent: Some(70.0),
+            memory_headroom_percent: Some(40.0),
+            last_heartbeat: Utc::now(),
+            schedulable: true,
+            reason: None,
+        };
+        assert!((capacity.load() - 3.6).abs() < 1e-9);
+
+        let unreported = EdgeCapacity {
+            cpu_headroom_percent: None,
+            memory_headroom_percent: None,
+            ..capacity
+        };
+        assert_eq!(unreported.load(), 3.0);
+    }
+
+    #[test]
+    fn latency_summaries_pick_percentiles() {
+        let summary = LatencySummary::of(&[5.0, 1.0, 3.0, 2.0, 4.0]).unwrap();
+        assert_eq!(summary.p50, 3.0);
+        assert_eq!(summary.p95, 5.0);
+        assert_eq!(summary.mean, 3.0);
+        assert!(LatencySummary::of(&[]).is_none());
+    }
+}
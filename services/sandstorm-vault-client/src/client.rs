@@ -0,0 +1,644 @@
+use anyhow::{bail, Context, Result};
+use sandstorm_types::{
+    recording::SessionRecording,
+    snapshot::{
+        SnapshotManifest, SnapshotMetadata, SnapshotUpload, UploadRequest, UploadSession,
+        CHUNK_SHA256_HEADER, TENANT_HEADER,
+    },
+};
+use sandstorm_http::CircuitBreaker;
+use serde_json::json;
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::{sync::Semaphore, task::JoinSet};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::errors::{check, is_transient, Blocked};
+use crate::hash::{sha256_hex, verify_chunk};
+use crate::spool::{Pending, Spool};
+
+/// Attempts per request before a transfer fails
+const ATTEMPTS: u32 = 3;
+
+/// What became of an upload
+#[derive(Debug)]
+pub enum Delivery<T> {
+    /// The vault stored it
+    Stored(T),
+    /// The vault couldn't be reached; the transfer waits in the spool
+    /// under this ID
+    Spooled(Uuid),
+}
+
+#[derive(Debug, Clone)]
+pub struct VaultClient {
+    http: reqwest::Client,
+    url: String,
+    /// Chunks transferred at once
+    concurrency: usize,
+    spool: Option<Spool>,
+    breaker: Option<CircuitBreaker>,
+}
+
+impl VaultClient {
+    /// Client for the vault at `url`, transferring 8 chunks at once and
+    /// without a spool
+    pub fn new(url: &str) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(120))
+                .build()
+                .unwrap_or_default(),
+            url: url.trim_end_matches('/').to_string(),
+            concurrency: 8,
+            spool: None,
+            breaker: None,
+        }
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Spool uploads the vault can't take instead of failing them
+    pub fn with_spool(mut self, spool: Spool) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+
+    /// Refuse calls while the vault is down instead of waiting on it, and
+    /// spool uploads straight away
+    pub fn with_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Some(breaker);
+        self
+    }
+
+    pub fn breaker(&self) -> Option<&CircuitBreaker> {
+        self.breaker.as_ref()
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str, tenant: Option<&str>) -> reqwest::RequestBuilder {
+        let request = self.http.request(method, format!("{}{}", self.url, path));
+        match tenant {
+            Some(tenant) => request.header(TENANT_HEADER, tenant),
+            None => request,
+        }
+    }
+
+    /// Run a call to the vault through the breaker, if there is one. Only
+    /// transient failures count against the vault.
+    async fn guarded<T>(&self, call: impl Future<Output = Result<T>>) -> Result<T> {
+        match &self.breaker {
+            Some(breaker) => breaker.call(call, is_transient).await,
+            None => call.await,
+        }
+    }
+
+    /// A snapshot's blob, reassembled from its chunks
+    pub async fn download_snapshot(&self, snapshot_id: Uuid, tenant: Option<&str>) -> Result<Vec<u8>> {
+        self.guarded(self.fetch_snapshot(snapshot_id, tenant)).await
+    }
+
+    async fn fetch_snapshot(&self, snapshot_id: Uuid, tenant: Option<&str>) -> Result<Vec<u8>> {
+        let started = std::time::Instant::now();
+        let response = self
+            .request(reqwest::Method::GET, &format!("/v1/snapshots/{}/manifest", snapshot_id), tenant)
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::FORBIDDEN {
+            let refusal: serde_json::Value = response.json().await.unwrap_or_default();
+            return Err(Blocked {
+                snapshot_id,
+                indicators: refusal["indicators"].clone(),
+            }
+            .into());
+        }
+        let manifest: SnapshotManifest = check(response).await?.json().await?;
+
+        let semaphore = Arc::new(Semaphore::new(self.concurrency));
+        let mut tasks = JoinSet::new();
+        for info in manifest.chunks.iter().cloned() {
+            let request = self.request(
+                reqwest::Method::GET,
+                &format!("/v1/snapshots/{}/chunks/{}", snapshot_id, info.index),
+                tenant,
+            );
+            let semaphore = semaphore.clone();
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await?;
+                let data = retry(&format!("chunk {}", info.index), || async {
+                    let request = request.try_clone().context("chunk request can't be retried")?;
+                    let data = check(request.send().await?).await?.bytes().await?;
+                    verify_chunk(&info, &data)?;
+                    Ok(data.to_vec())
+                })
+                .await
+                .with_context(|| format!("failed to fetch chunk {}", info.index))?;
+                anyhow::Ok((info, data))
+            });
+        }
+
+        let mut blob = vec![0; manifest.size_bytes as usize];
+        while let Some(result) = tasks.join_next().await {
+            let (info, data) = result??;
+            let end = (info.offset + info.size) as usize;
+            if end > blob.len() {
+                bail!("chunk {} ends past the end of snapshot {}", info.index, snapshot_id);
+            }
+            blob[info.offset as usize..end].copy_from_slice(&data);
+        }
+
+        info!(
+            snapshot_id = %snapshot_id,
+            bytes = manifest.size_bytes,
+            chunks = manifest.chunks.len(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "Snapshot downloaded from vault"
+        );
+        Ok(blob)
+    }
+
+    /// A snapshot's stack of layers, its bottom layer first and the
+    /// snapshot itself last
+    pub async fn snapshot_layers(&self, snapshot_id: Uuid, tenant: Option<&str>) -> Result<Vec<SnapshotMetadata>> {
+        self.guarded(async {
+            let response = self
+                .request(reqwest::Method::GET, &format!("/v1/snapshots/{}/layers", snapshot_id), tenant)
+                .send()
+                .await?;
+            Ok(check(response).await?.json().await?)
+        })
+        .await
+    }
+
+    /// Store a snapshot and its blob, sent in chunks. If the vault can't be
+    /// reached and a spool is configured, the upload is spooled and resumes
+    /// from the chunks already sent when it is replayed.
+    pub async fn upload_snapshot(
+        &self,
+        upload: SnapshotUpload,
+        blob: Vec<u8>,
+        tenant: Option<&str>,
+    ) -> Result<Delivery<SnapshotMetadata>> {
+        let blob = Arc::new(blob);
+        let mut upload_id = None;
+        let sent = self.guarded(self.send_snapshot(&upload, blob.clone(), tenant, &mut upload_id)).await;
+        let error = match sent {
+            Ok(metadata) => return Ok(Delivery::Stored(metadata)),
+            Err(e) => e,
+        };
+        match &self.spool {
+            Some(spool) if is_transient(&error) => {
+                let pending = Pending::Snapshot {
+                    upload,
+                    tenant: tenant.map(str::to_string),
+                    upload_id,
+                };
+                let entry = spool.push(pending, &blob).await?;
+                warn!(spool_id = %entry.id, "Spooled snapshot upload: {:#}", error);
+                Ok(Delivery::Spooled(entry.id))
+            }
+            _ => Err(error),
+        }
+    }
+
+    /// Send the chunks the vault's upload `upload_id` is missing, starting
+    /// a new upload when there is none, and complete it. `upload_id` is set
+    /// as soon as an upload exists, so a failed transfer can resume.
+    async fn send_snapshot(
+        &self,
+        upload: &SnapshotUpload,
+        blob: Arc<Vec<u8>>,
+        tenant: Option<&str>,
+        upload_id: &mut Option<Uuid>,
+    ) -> Result<SnapshotMetadata> {
+        let sha256 = sha256_hex(&blob);
+        let resumed = match *upload_id {
+            Some(id) => self
+                .upload_session(id, tenant)
+                .await?
+                .filter(|session| session.size_bytes == blob.len() as u64 && session.sha256 == sha256),
+            None => None,
+        };
+        let session = match resumed {
+            Some(session) => {
+                info!(upload = %session.id, missing = session.missing().len(), "Resuming snapshot upload");
+                session
+            }
+            None => {
+                let request = UploadRequest {
+                    size_bytes: blob.len() as u64,
+                    sha256,
+                };
+                let session: UploadSession = retry("upload start", || async {
+                    let response = self
+                        .request(reqwest::Method::POST, "/v1/uploads", tenant)
+                        .json(&request)
+                        .send()
+                        .await?;
+                    Ok(check(response).await?.json().await?)
+                })
+                .await?;
+                *upload_id = Some(session.id);
+                session
+            }
+        };
+
+        let semaphore = Arc::new(Semaphore::new(self.concurrency));
+        let mut tasks = JoinSet::new();
+        for index in session.missing() {
+            let start = (index * session.chunk_size) as usize;
+            let end = start + session.chunk_len(index) as usize;
+            let request = self.request(
+                reqwest::Method::PUT,
+                &format!("/v1/uploads/{}/chunks/{}", session.id, index),
+                tenant,
+            );
+            let (blob, semaphore) = (blob.clone(), semaphore.clone());
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await?;
+                let chunk = &blob[start..end];
+                let hash = sha256_hex(chunk);
+                retry(&format!("chunk {}", index), || async {
+                    let request = request.try_clone().context("chunk request can't be retried")?;
+                    check(request.header(CHUNK_SHA256_HEADER, &hash).body(chunk.to_vec()).send().await?).await?;
+                    Ok(())
+                })
+                .await
+                .with_context(|| format!("failed to send chunk {}", index))
+            });
+        }
+        while let Some(result) = tasks.join_next().await {
+            result??;
+        }
+
+        retry("upload completion", || async {
+            let response = self
+                .request(reqwest::Method::POST, &format!("/v1/uploads/{}/complete", session.id), tenant)
+                .json(upload)
+                .send()
+                .await?;
+            Ok(check(response).await?.json().await?)
+        })
+        .await
+    }
+
+    /// An upload the vault still holds, or `None` if it finished or was
+    /// dropped
+    async fn upload_session(&self, id: Uuid, tenant: Option<&str>) -> Result<Option<UploadSession>> {
+        let response = self
+            .request(reqwest::Method::GET, &format!("/v1/uploads/{}", id), tenant)
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(check(response).await?.json().await?))
+    }
+
+    /// Store a session recording, spooling it if the vault can't be reached
+    /// and a spool is configured
+    pub async fn store_recording(
+        &self,
+        recording: SessionRecording,
+        cast: String,
+    ) -> Result<Delivery<SessionRecording>> {
+        let error = match self.guarded(self.send_recording(&recording, &cast)).await {
+            Ok(stored) => return Ok(Delivery::Stored(stored)),
+            Err(e) => e,
+        };
+        match &self.spool {
+            Some(spool) if is_transient(&error) => {
+                let entry = spool.push(Pending::Recording { recording }, cast.as_bytes()).await?;
+                warn!(spool_id = %entry.id, "Spooled session recording: {:#}", error);
+                Ok(Delivery::Spooled(entry.id))
+            }
+            _ => Err(error),
+        }
+    }
+
+    async fn send_recording(&self, recording: &SessionRecording, cast: &str) -> Result<SessionRecording> {
+        let body = json!({ "recording": recording, "cast": cast });
+        retry("recording", || async {
+            let response = self
+                .request(reqwest::Method::POST, "/v1/recordings", None)
+                .json(&body)
+                .send()
+                .await?;
+            Ok(check(response).await?.json().await?)
+        })
+        .await
+    }
+
+    /// Recordings the vault holds for a sandbox
+    pub async fn recordings(&self, sandbox_id: &str) -> Result<Vec<SessionRecording>> {
+        self.guarded(async {
+            let response = self
+                .request(reqwest::Method::GET, "/v1/recordings", None)
+                .query(&[("sandbox_id", sandbox_id)])
+                .send()
+                .await?;
+            Ok(check(response).await?.json().await?)
+        })
+        .await
+    }
+
+    /// Raw asciicast of a recording, or `None` if the vault doesn't have it
+    pub async fn recording_cast(&self, recording_id: Uuid) -> Result<Option<Vec<u8>>> {
+        self.guarded(async {
+            let response = self
+                .request(reqwest::Method::GET, &format!("/v1/recordings/{}/cast", recording_id), None)
+                .send()
+                .await?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            Ok(Some(check(response).await?.bytes().await?.to_vec()))
+        })
+        .await
+    }
+
+    /// Deliver spooled transfers, oldest first, stopping at the first one
+    /// the vault still can't take. Transfers the vault refuses are dropped.
+    /// Returns how many were delivered.
+    pub async fn replay_spool(&self) -> Result<usize> {
+        let Some(spool) = &self.spool else {
+            return Ok(0);
+        };
+        let mut delivered = 0;
+        for mut entry in spool.entries().await? {
+            let data = spool.data(entry.id).await?;
+            let result = match &mut entry.pending {
+                Pending::Snapshot {
+                    upload,
+                    tenant,
+                    upload_id,
+                } => self
+                    .guarded(self.send_snapshot(upload, Arc::new(data), tenant.as_deref(), upload_id))
+                    .await
+                    .map(|metadata| info!(snapshot_id = %metadata.id, "Spooled snapshot stored")),
+                Pending::Recording { recording } => self
+                    .guarded(self.send_recording(recording, &String::from_utf8_lossy(&data)))
+                    .await
+                    .map(|recording| info!(recording_id = %recording.id, "Spooled recording stored")),
+            };
+            match result {
+                Ok(()) => {
+                    spool.remove(entry.id).await?;
+                    delivered += 1;
+                }
+                Err(e) if is_transient(&e) => {
+                    // Keeps the upload the chunks went to for next time
+                    spool.save(&entry).await?;
+                    return Err(e.context("vault still unreachable"));
+                }
+                Err(e) => {
+                    warn!(spool_id = %entry.id, "Dropping spooled transfer: {:#}", e);
+                    spool.remove(entry.id).await?;
+                }
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Replay the spool every `interval` in the background. Does nothing
+    /// without a spool. Must be called inside a Tokio runtime.
+    pub fn spawn_replay(&self, interval: Duration) {
+        if self.spool.is_none() {
+            return;
+        }
+        let client = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match client.replay_spool().await {
+                    Ok(0) => {}
+                    Ok(delivered) => info!(delivered, "Delivered spooled transfers to the vault"),
+                    Err(e) => warn!("Spool replay stopped: {:#}", e),
+                }
+            }
+        });
+    }
+}
+
+/// Run `attempt` until it succeeds, the vault refuses it, or it has failed
+/// [`ATTEMPTS`] times, backing off between tries
+async fn retry<T, F, Fut>(what: &str, mut attempt: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut backoff = Duration::from_millis(500);
+    let mut tries = 1;
+    loop {
+        match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(e) if tries < ATTEMPTS && is_transient(&e) => {
+                warn!("Retrying {} (attempt {}): {:#}", what, tries, e);
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                tries += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Bytes,
+        extract::{Path, State},
+        http::{HeaderMap, StatusCode},
+        routing::{get, post, put},
+        Json, Router,
+    };
+    use sandstorm_types::snapshot::ChunkInfo;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    async fn serve(app: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn reassembles_chunks_and_retries_corrupt_ones() {
+        let blob: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let chunks: Vec<ChunkInfo> = blob
+            .chunks(4096)
+            .enumerate()
+            .map(|(index, chunk)| ChunkInfo {
+                index: index as u64,
+                offset: index as u64 * 4096,
+                size: chunk.len() as u64,
+                sha256: sha256_hex(chunk),
+            })
+            .collect();
+        let manifest = SnapshotManifest {
+            snapshot_id: Uuid::new_v4(),
+            size_bytes: blob.len() as u64,
+            chunk_size: 4096,
+            chunks,
+        };
+
+        // The first fetch of chunk 1 comes back corrupted
+        let corrupted = Arc::new(AtomicBool::new(false));
+        let served = blob.clone();
+        let app = Router::new()
+            .route(
+                "/v1/snapshots/:id/manifest",
+                get(move || async move { Json(manifest) }),
+            )
+            .route(
+                "/v1/snapshots/:id/chunks/:index",
+                get(move |Path((_, index)): Path<(Uuid, usize)>| async move {
+                    let mut chunk = served.chunks(4096).nth(index).unwrap().to_vec();
+                    if index == 1 && !corrupted.swap(true, Ordering::SeqCst) {
+                        chunk[0] ^= 0xff;
+                    }
+                    chunk
+                }),
+            );
+
+        let client = VaultClient::new(&serve(app).await).with_concurrency(2);
+        let downloaded = client.download_snapshot(Uuid::new_v4(), Some("acme")).await.unwrap();
+        assert_eq!(downloaded, blob);
+    }
+
+    /// Vault holding a single upload in memory, with chunk 2 unreachable
+    /// while `down` is set
+    #[derive(Clone, Default)]
+    struct FakeVault {
+        session: Arc<Mutex<Option<UploadSession>>>,
+        chunks: Arc<Mutex<BTreeMap<u64, Vec<u8>>>>,
+        sends: Arc<Mutex<Vec<u64>>>,
+        down: Arc<AtomicBool>,
+    }
+
+    async fn start(State(vault): State<FakeVault>, Json(request): Json<UploadRequest>) -> Json<UploadSession> {
+        let session = UploadSession {
+            id: Uuid::new_v4(),
+            tenant: "acme".into(),
+            size_bytes: request.size_bytes,
+            sha256: request.sha256,
+            chunk_size: 4,
+            received: Vec::new(),
+            created_at: chrono::Utc::now(),
+        };
+        *vault.session.lock().unwrap() = Some(session.clone());
+        Json(session)
+    }
+
+    async fn session(State(vault): State<FakeVault>) -> Json<UploadSession> {
+        Json(vault.session.lock().unwrap().clone().unwrap())
+    }
+
+    async fn chunk(
+        State(vault): State<FakeVault>,
+        Path((_, index)): Path<(Uuid, u64)>,
+        headers: HeaderMap,
+        data: Bytes,
+    ) -> StatusCode {
+        vault.sends.lock().unwrap().push(index);
+        if index == 2 && vault.down.load(Ordering::SeqCst) {
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+        assert_eq!(headers[CHUNK_SHA256_HEADER], sha256_hex(&data));
+        vault.chunks.lock().unwrap().insert(index, data.to_vec());
+        let mut session = vault.session.lock().unwrap();
+        let session = session.as_mut().unwrap();
+        session.received.push(index);
+        session.received.sort();
+        StatusCode::OK
+    }
+
+    async fn complete(State(vault): State<FakeVault>, Json(upload): Json<SnapshotUpload>) -> Json<serde_json::Value> {
+        let blob: Vec<u8> = vault.chunks.lock().unwrap().values().flatten().copied().collect();
+        Json(json!({
+            "id": Uuid::new_v4(),
+            "sandbox_id": upload.sandbox_id,
+            "provider": upload.provider,
+            "filesystem_hash": upload.filesystem_hash,
+            "memory_hash": null,
+            "size_bytes": blob.len(),
+            "created_at": chrono::Utc::now(),
+            "metadata": {},
+            "has_blob": true,
+        }))
+    }
+
+    #[tokio::test]
+    async fn spools_uploads_and_resumes_them() {
+        let vault = FakeVault::default();
+        vault.down.store(true, Ordering::SeqCst);
+        let app = Router::new()
+            .route("/v1/uploads", post(start))
+            .route("/v1/uploads/:id", get(session))
+            .route("/v1/uploads/:id/chunks/:index", put(chunk))
+            .route("/v1/uploads/:id/complete", post(complete))
+            .with_state(vault.clone());
+
+        let dir = std::env::temp_dir().join(format!("vault-spool-{}", Uuid::new_v4()));
+        let spool = Spool::open(&dir).await.unwrap();
+        let client = VaultClient::new(&serve(app).await)
+            .with_concurrency(1)
+            .with_spool(spool.clone());
+        let upload = SnapshotUpload {
+            sandbox_id: "sandbox".into(),
+            provider: "firecracker".into(),
+            filesystem_hash: "hash".into(),
+            memory_hash: None,
+            metadata: None,
+            format: None,
+            run_id: None,
+            pinned: false,
+            base_layer: None,
+        };
+        let blob = b"0123456789".to_vec();
+
+        let delivery = client.upload_snapshot(upload, blob.clone(), Some("acme")).await.unwrap();
+        assert!(matches!(delivery, Delivery::Spooled(_)));
+        assert!(client.replay_spool().await.is_err());
+        assert_eq!(spool.entries().await.unwrap().len(), 1);
+
+        vault.down.store(false, Ordering::SeqCst);
+        assert_eq!(client.replay_spool().await.unwrap(), 1);
+        assert!(spool.entries().await.unwrap().is_empty());
+
+        // Chunks 0 and 1 went once; only chunk 2 was sent again
+        let sends = vault.sends.lock().unwrap().clone();
+        assert_eq!(sends.iter().filter(|&&index| index != 2).count(), 2);
+        let stored: Vec<u8> = vault.chunks.lock().unwrap().values().flatten().copied().collect();
+        assert_eq!(stored, blob);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_calls_once_the_breaker_opens() {
+        use sandstorm_http::{BreakerSettings, Unavailable};
+
+        let settings = BreakerSettings {
+            failures: 1,
+            open_for: Duration::from_secs(30),
+        };
+        let client = VaultClient::new("http://127.0.0.1:1")
+            .with_breaker(CircuitBreaker::new("snapshot-vault", settings));
+        let error = client.snapshot_layers(Uuid::new_v4(), None).await.unwrap_err();
+        assert!(Unavailable::find(&error).is_none());
+
+        let error = client.snapshot_layers(Uuid::new_v4(), None).await.unwrap_err();
+        assert_eq!(Unavailable::find(&error).unwrap().dependency, "snapshot-vault");
+        assert!(is_transient(&error));
+    }
+}
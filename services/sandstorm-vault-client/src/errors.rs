@@ -0,0 +1,53 @@
+use uuid::Uuid;
+
+/// The vault refused a restore because scanning the snapshot found
+/// indicators
+#[derive(Debug)]
+pub struct Blocked {
+    pub snapshot_id: Uuid,
+    pub indicators: serde_json::Value,
+}
+
+impl std::fmt::Display for Blocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "snapshot {} is blocked by the vault's scan: {}", self.snapshot_id, self.indicators)
+    }
+}
+
+impl std::error::Error for Blocked {}
+
+/// The vault answered a request with a 4xx; sending it again won't help
+#[derive(Debug)]
+pub struct Rejected {
+    pub status: reqwest::StatusCode,
+    /// Response body, usually the vault's reason
+    pub message: String,
+}
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "vault rejected the request ({}): {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Whether a failed transfer may succeed later: anything but the vault
+/// refusing it
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    !error.is::<Rejected>() && !error.is::<Blocked>()
+}
+
+/// Pass successful responses through; turn the rest into errors, 4xx into
+/// [`Rejected`]
+pub(crate) async fn check(response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    if status.is_client_error() {
+        let message = response.text().await.unwrap_or_default();
+        return Err(Rejected { status, message }.into());
+    }
+    anyhow::bail!("vault answered {}", status)
+}
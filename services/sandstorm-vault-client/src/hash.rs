@@ -0,0 +1,20 @@
+use anyhow::{bail, Result};
+use sandstorm_types::snapshot::ChunkInfo;
+use sha2::{Digest, Sha256};
+
+/// Hex SHA-256 of `data`, as the vault writes hashes in manifests and
+/// expects them in uploads
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Check a downloaded chunk against its manifest entry
+pub fn verify_chunk(info: &ChunkInfo, data: &[u8]) -> Result<()> {
+    if data.len() as u64 != info.size {
+        bail!("expected {} bytes, got {}", info.size, data.len());
+    }
+    if sha256_hex(data) != info.sha256 {
+        bail!("hash mismatch");
+    }
+    Ok(())
+}
@@ -0,0 +1,23 @@
+//! Client for the snapshot vault's blob transfers, shared by everything
+//! that moves snapshots or recordings in or out of the vault.
+//!
+//! Blobs go both ways in chunks, each checked against its SHA-256:
+//! downloads follow the vault's manifest, several chunks at a time, and
+//! uploads send chunks to an upload session that remembers what arrived,
+//! so an interrupted upload resumes instead of starting over. Requests that
+//! fail for reasons that may pass (connection errors, timeouts, 5xx, a
+//! corrupted chunk) are retried with backoff.
+//!
+//! With a [`Spool`] configured, an upload or recording that still can't
+//! reach the vault is written to local disk instead of failing, and
+//! [`VaultClient::spawn_replay`] delivers it once the vault is back.
+
+mod client;
+mod errors;
+mod hash;
+mod spool;
+
+pub use client::{Delivery, VaultClient};
+pub use errors::{is_transient, Blocked, Rejected};
+pub use hash::{sha256_hex, verify_chunk};
+pub use spool::{Pending, Spool, SpoolEntry};
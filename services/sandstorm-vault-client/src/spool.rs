@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use sandstorm_types::{recording::SessionRecording, snapshot::SnapshotUpload};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::fs;
+use tracing::warn;
+use uuid::Uuid;
+
+/// A transfer waiting in the spool for the vault to come back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Pending {
+    Snapshot {
+        upload: SnapshotUpload,
+        #[serde(default)]
+        tenant: Option<String>,
+        /// Vault upload already holding some of the chunks, resumed on
+        /// replay
+        #[serde(default)]
+        upload_id: Option<Uuid>,
+    },
+    Recording {
+        recording: SessionRecording,
+    },
+}
+
+/// A spooled transfer. Its blob or cast is kept next to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpoolEntry {
+    pub id: Uuid,
+    pub spooled_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub pending: Pending,
+}
+
+/// Directory holding transfers the vault couldn't take, as `<id>.json` and
+/// `<id>.data` pairs
+#[derive(Debug, Clone)]
+pub struct Spool {
+    dir: PathBuf,
+}
+
+impl Spool {
+    pub async fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("failed to create spool directory {}", dir.display()))?;
+        Ok(Self { dir })
+    }
+
+    fn entry_path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn data_path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.data", id))
+    }
+
+    pub async fn push(&self, pending: Pending, data: &[u8]) -> Result<SpoolEntry> {
+        let entry = SpoolEntry {
+            id: Uuid::new_v4(),
+            spooled_at: Utc::now(),
+            pending,
+        };
+        // The data goes first, so every entry found has its data
+        fs::write(self.data_path(entry.id), data).await?;
+        self.save(&entry).await?;
+        Ok(entry)
+    }
+
+    /// Record progress on an entry, e.g. the upload its chunks went to
+    pub async fn save(&self, entry: &SpoolEntry) -> Result<()> {
+        let path = self.entry_path(entry.id);
+        // Write aside and rename, so a crash never leaves a partial file
+        let partial = path.with_extension("partial");
+        fs::write(&partial, serde_json::to_vec(entry)?).await?;
+        fs::rename(&partial, &path).await?;
+        Ok(())
+    }
+
+    /// Spooled transfers, oldest first
+    pub async fn entries(&self) -> Result<Vec<SpoolEntry>> {
+        let mut entries = Vec::new();
+        let mut dir = fs::read_dir(&self.dir).await?;
+        while let Some(file) = dir.next_entry().await? {
+            let path = file.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            match serde_json::from_slice::<SpoolEntry>(&fs::read(&path).await?) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!("Skipping unreadable spool entry {}: {}", path.display(), e),
+            }
+        }
+        entries.sort_by_key(|entry| entry.spooled_at);
+        Ok(entries)
+    }
+
+    pub async fn data(&self, id: Uuid) -> Result<Vec<u8>> {
+        let path = self.data_path(id);
+        fs::read(&path)
+            .await
+            .with_context(|| format!("failed to read {}", path.display()))
+    }
+
+    pub async fn remove(&self, id: Uuid) -> Result<()> {
+        for path in [self.entry_path(id), self.data_path(id)] {
+            match fs::remove_file(&path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
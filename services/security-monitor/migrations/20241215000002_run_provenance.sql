@@ -0,0 +1,6 @@
+-- Link events and quarantines to the gateway run that produced them
+ALTER TABLE security_events ADD COLUMN IF NOT EXISTS run_id UUID;
+ALTER TABLE quarantine_records ADD COLUMN IF NOT EXISTS run_id UUID;
+
+CREATE INDEX IF NOT EXISTS idx_security_events_run_id ON security_events(run_id);
+CREATE INDEX IF NOT EXISTS idx_quarantine_records_run_id ON quarantine_records(run_id);
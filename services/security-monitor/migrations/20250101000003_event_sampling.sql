@@ -0,0 +1,3 @@
+-- Aggregated events stand for every identical event in their window
+ALTER TABLE security_events ADD COLUMN IF NOT EXISTS occurrences BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE security_events ADD COLUMN IF NOT EXISTS last_seen TIMESTAMPTZ;
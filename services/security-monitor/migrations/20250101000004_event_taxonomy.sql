@@ -0,0 +1,77 @@
+-- Custom event types events and rules may use, besides the built-in ones
+CREATE TABLE IF NOT EXISTS event_types (
+    name VARCHAR(100) PRIMARY KEY,
+    description TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Severities were free-form; fold the aliases into the four levels and treat
+-- anything unrecognized as medium, as Falco priorities always were
+CREATE FUNCTION pg_temp.canonical_severity(value TEXT) RETURNS TEXT AS $$
+    SELECT CASE lower(trim(value))
+        WHEN 'low' THEN 'low'
+        WHEN 'debug' THEN 'low'
+        WHEN 'info' THEN 'low'
+        WHEN 'informational' THEN 'low'
+        WHEN 'notice' THEN 'low'
+        WHEN 'high' THEN 'high'
+        WHEN 'error' THEN 'high'
+        WHEN 'critical' THEN 'critical'
+        WHEN 'alert' THEN 'critical'
+        WHEN 'emergency' THEN 'critical'
+        ELSE 'medium'
+    END
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- Event types become lowercase names of letters, digits, '_' and '.'
+CREATE FUNCTION pg_temp.canonical_event_type(value TEXT) RETURNS TEXT AS $$
+    SELECT CASE name
+        WHEN 'file' THEN 'file_access'
+        WHEN 'network' THEN 'network_activity'
+        WHEN 'process' THEN 'process_spawn'
+        WHEN 'exec' THEN 'process_spawn'
+        WHEN 'privesc' THEN 'privilege_escalation'
+        WHEN 'suspicious_behaviour' THEN 'suspicious_behavior'
+        ELSE COALESCE(NULLIF(name, ''), 'policy_violation')
+    END
+    FROM (SELECT left(regexp_replace(lower(trim(value)), '[^a-z0-9_.]', '_', 'g'), 100) AS name) AS normalized
+$$ LANGUAGE SQL IMMUTABLE;
+
+UPDATE security_events
+SET severity = pg_temp.canonical_severity(severity),
+    event_type = pg_temp.canonical_event_type(event_type);
+
+UPDATE alerts SET severity = pg_temp.canonical_severity(severity);
+
+UPDATE quarantine_records
+SET triggered_by = jsonb_set(
+    jsonb_set(
+        triggered_by,
+        '{severity}',
+        to_jsonb(pg_temp.canonical_severity(triggered_by->>'severity'))
+    ),
+    '{event_type}',
+    to_jsonb(pg_temp.canonical_event_type(triggered_by->>'event_type'))
+);
+
+UPDATE security_rules
+SET condition = jsonb_set(condition, '{severity}', to_jsonb(pg_temp.canonical_severity(condition->>'severity')))
+WHERE condition->>'severity' IS NOT NULL;
+
+UPDATE security_rules
+SET condition = jsonb_set(condition, '{event_type}', to_jsonb(pg_temp.canonical_event_type(condition->>'event_type')))
+WHERE condition->>'event_type' IS NOT NULL;
+
+-- Custom types already in use stay valid
+INSERT INTO event_types (name, description)
+SELECT DISTINCT event_type, 'Registered from existing events'
+FROM security_events
+WHERE event_type NOT IN (
+    'file_access',
+    'network_activity',
+    'process_spawn',
+    'privilege_escalation',
+    'suspicious_behavior',
+    'policy_violation'
+)
+ON CONFLICT (name) DO NOTHING;
@@ -0,0 +1,2 @@
+-- How each quarantine cuts its sandbox off; earlier quarantines froze it
+ALTER TABLE quarantine_records ADD COLUMN IF NOT EXISTS mode VARCHAR(32) NOT NULL DEFAULT 'freeze';
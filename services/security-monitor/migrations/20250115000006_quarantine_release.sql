@@ -0,0 +1,12 @@
+-- Typed release conditions, the approvals and scans they wait on, and the
+-- condition each quarantine was released on. Earlier conditions were free
+-- text nothing evaluated.
+UPDATE quarantine_records SET release_conditions = NULL, auto_release = FALSE
+    WHERE jsonb_typeof(release_conditions) = 'array'
+      AND EXISTS (
+          SELECT 1 FROM jsonb_array_elements(release_conditions) AS condition
+          WHERE jsonb_typeof(condition) = 'string'
+      );
+ALTER TABLE quarantine_records ADD COLUMN IF NOT EXISTS approved_by VARCHAR(255);
+ALTER TABLE quarantine_records ADD COLUMN IF NOT EXISTS scan_passed_at TIMESTAMPTZ;
+ALTER TABLE quarantine_records ADD COLUMN IF NOT EXISTS released_on JSONB;
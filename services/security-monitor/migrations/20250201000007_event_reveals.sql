@@ -0,0 +1,11 @@
+-- Who revealed the details of an event stored encrypted, and why. Kept
+-- after the event itself is cleaned up.
+CREATE TABLE IF NOT EXISTS event_reveals (
+    id VARCHAR(255) PRIMARY KEY,
+    event_id VARCHAR(255) NOT NULL,
+    actor VARCHAR(255) NOT NULL,
+    reason TEXT NOT NULL,
+    revealed_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_reveals_event_id ON event_reveals (event_id);
@@ -0,0 +1,22 @@
+-- Legal exports: signed, hash-chained archives of a sandbox's or a time
+-- range's events, alerts and quarantines. Kept after retention cleanup
+-- deletes the rows they were made from.
+CREATE TABLE IF NOT EXISTS event_exports (
+    id VARCHAR(255) PRIMARY KEY,
+    status VARCHAR(20) NOT NULL,
+    sandbox_id VARCHAR(255),
+    start_time TIMESTAMPTZ,
+    end_time TIMESTAMPTZ NOT NULL,
+    requested_by VARCHAR(255) NOT NULL,
+    reason TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    finished_at TIMESTAMPTZ,
+    records BIGINT NOT NULL DEFAULT 0,
+    head_hash VARCHAR(64),
+    signature TEXT,
+    error TEXT,
+    archive BYTEA
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_exports_created_at ON event_exports (created_at);
+CREATE INDEX IF NOT EXISTS idx_event_exports_running ON event_exports (status) WHERE status = 'running';
@@ -0,0 +1,19 @@
+-- Runs of rules' follow-up actions (webhooks, gateway freezes, deep traces,
+-- ...) and how each ended after its retries
+CREATE TABLE IF NOT EXISTS action_runs (
+    id VARCHAR(255) PRIMARY KEY,
+    action VARCHAR(50) NOT NULL,
+    rule VARCHAR(255) NOT NULL,
+    sandbox_id VARCHAR(255) NOT NULL,
+    event_id VARCHAR(255),
+    status VARCHAR(20) NOT NULL,
+    attempts INTEGER NOT NULL,
+    parameters JSONB NOT NULL,
+    result JSONB,
+    error TEXT,
+    started_at TIMESTAMPTZ NOT NULL,
+    finished_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_action_runs_finished_at ON action_runs (finished_at);
+CREATE INDEX IF NOT EXISTS idx_action_runs_sandbox_id ON action_runs (sandbox_id);
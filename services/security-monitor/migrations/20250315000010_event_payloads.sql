@@ -0,0 +1,35 @@
+-- Large fields of event details shared by many events, stored once and
+-- keyed by the SHA-256 of their value. An event's details_refs maps each
+-- field moved out of its details to the payload holding its value.
+CREATE TABLE IF NOT EXISTS event_payloads (
+    hash VARCHAR(64) PRIMARY KEY,
+    body JSONB NOT NULL,
+    -- Bumped at most hourly as events reuse the payload, for cleanup
+    last_used TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_payloads_last_used ON event_payloads (last_used);
+
+ALTER TABLE security_events ADD COLUMN IF NOT EXISTS details_refs JSONB;
+
+-- An event's details with the fields moved out into payloads put back
+CREATE OR REPLACE FUNCTION event_details(details JSONB, refs JSONB) RETURNS JSONB AS $$
+    SELECT CASE
+        WHEN refs IS NULL THEN details
+        ELSE details || COALESCE(
+            (SELECT jsonb_object_agg(r.key, p.body)
+             FROM jsonb_each_text(refs) r
+             JOIN event_payloads p ON p.hash = r.value),
+            '{}'::jsonb
+        )
+    END
+$$ LANGUAGE SQL STABLE;
+
+DROP VIEW IF EXISTS recent_security_events;
+CREATE VIEW recent_security_events AS
+SELECT id, event_type, severity, timestamp, sandbox_id, provider, message,
+       event_details(details, details_refs) AS details, metadata, falco_rule,
+       ebpf_trace, created_at, run_id, occurrences, last_seen
+FROM security_events
+WHERE timestamp > NOW() - INTERVAL '24 hours'
+ORDER BY timestamp DESC;
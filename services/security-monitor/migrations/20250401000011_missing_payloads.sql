@@ -0,0 +1,22 @@
+-- Put details back together, logging refs to payloads that are gone. The
+-- field is kept, holding the missing hash, rather than silently dropped.
+CREATE OR REPLACE FUNCTION event_details(details JSONB, refs JSONB) RETURNS JSONB AS $$
+DECLARE
+    ref RECORD;
+    body JSONB;
+    assembled JSONB := details;
+BEGIN
+    IF refs IS NULL THEN
+        RETURN details;
+    END IF;
+    FOR ref IN SELECT key, value FROM jsonb_each_text(refs) LOOP
+        SELECT p.body INTO body FROM event_payloads p WHERE p.hash = ref.value;
+        IF NOT FOUND THEN
+            RAISE WARNING 'event details field % refers to missing payload %', ref.key, ref.value;
+            body := jsonb_build_object('missing_payload', ref.value);
+        END IF;
+        assembled := assembled || jsonb_build_object(ref.key, body);
+    END LOOP;
+    RETURN assembled;
+END
+$$ LANGUAGE plpgsql STABLE;
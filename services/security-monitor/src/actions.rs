@@ -0,0 +1,570 @@
+//! Follow-up actions a rule runs when it matches, besides the verdict in its
+//! `action`: webhooks, gateway freezes and network blocks, deep traces and
+//! incidents. Each kind has a handler in the [`ActionRegistry`]. Actions run
+//! in the background, are retried with backoff, and every run is recorded
+//! with its outcome.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::ebpf::PROGRAMS;
+use crate::models::*;
+use crate::AppState;
+
+/// Attempts after the first for actions that don't set `retries`
+const DEFAULT_RETRIES: u32 = 2;
+
+/// Longest wait between attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ActionError {
+    /// The action can't run as things are, so it isn't retried
+    #[error("{0}")]
+    Unavailable(String),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Failed(#[from] anyhow::Error),
+}
+
+#[async_trait]
+pub trait ActionHandler: Send + Sync {
+    fn description(&self) -> &'static str;
+
+    /// Check an action's parameters when a policy using it is saved
+    fn check(&self, _action: &RuleAction) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Run the action once for an event a rule matched, returning what it
+    /// did for the run's record
+    async fn run(
+        &self,
+        state: &AppState,
+        event: &SecurityEvent,
+        rule: &str,
+        action: &RuleAction,
+    ) -> Result<Value, ActionError>;
+}
+
+/// Handlers of every action kind rules can use, by kind
+pub struct ActionRegistry {
+    handlers: HashMap<&'static str, Arc<dyn ActionHandler>>,
+}
+
+impl ActionRegistry {
+    /// Registry holding the built-in actions
+    pub fn new() -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap_or_default();
+        let mut registry = Self {
+            handlers: HashMap::new(),
+        };
+        registry.register("webhook", Arc::new(Webhook { http: http.clone() }));
+        registry.register("gateway_freeze", Arc::new(GatewayFreeze));
+        registry.register("network_block", Arc::new(NetworkBlock));
+        registry.register("deep_trace", Arc::new(DeepTrace));
+        registry.register("open_incident", Arc::new(OpenIncident { http }));
+        registry
+    }
+
+    pub fn register(&mut self, kind: &'static str, handler: Arc<dyn ActionHandler>) {
+        self.handlers.insert(kind, handler);
+    }
+
+    /// Every registered kind, by name
+    pub fn list(&self) -> Vec<ActionKindInfo> {
+        let mut kinds: Vec<_> = self
+            .handlers
+            .iter()
+            .map(|(name, handler)| ActionKindInfo {
+                name,
+                description: handler.description(),
+            })
+            .collect();
+        kinds.sort_by_key(|kind| kind.name);
+        kinds
+    }
+
+    /// Every action a policy's rules run must have a handler that accepts
+    /// its parameters
+    pub fn check_policy(&self, policy: &SecurityPolicy) -> Result<(), String> {
+        policy
+            .rules
+            .iter()
+            .flat_map(|rule| rule.actions.iter().map(move |spec| (rule, &spec.action)))
+            .try_for_each(|(rule, action)| {
+                let handler = self
+                    .handlers
+                    .get(action.kind())
+                    .ok_or_else(|| format!("rule {} uses unknown action {}", rule.name, action.kind()))?;
+                handler
+                    .check(action)
+                    .map_err(|problem| format!("rule {}: {}", rule.name, problem))
+            })
+    }
+}
+
+/// Run the actions an event's matched rules call for in the background,
+/// each on its own
+pub fn spawn(state: &AppState, event: &SecurityEvent, event_id: Option<String>, actions: Vec<TriggeredAction>) {
+    for triggered in actions {
+        let state = state.clone();
+        let event = event.clone();
+        let event_id = event_id.clone();
+        tokio::spawn(async move {
+            let run = execute(&state, &event, event_id, &triggered).await;
+            state.metrics_collector.record_action(&run);
+            match run.status {
+                ActionStatus::Succeeded => info!(
+                    action = %run.action,
+                    rule = %run.rule,
+                    sandbox_id = %run.sandbox_id,
+                    attempts = run.attempts,
+                    "Rule action ran"
+                ),
+                ActionStatus::Failed => error!(
+                    action = %run.action,
+                    rule = %run.rule,
+                    sandbox_id = %run.sandbox_id,
+                    attempts = run.attempts,
+                    "Rule action failed: {}",
+                    run.error.as_deref().unwrap_or_default()
+                ),
+            }
+            if let Err(e) = state.event_store.store_action_run(&run).await {
+                error!("Failed to record run of action {}: {:#}", run.action, e);
+            }
+        });
+    }
+}
+
+/// Run an action until it succeeds, can't run, or is out of retries
+async fn execute(
+    state: &AppState,
+    event: &SecurityEvent,
+    event_id: Option<String>,
+    triggered: &TriggeredAction,
+) -> ActionRun {
+    let spec = &triggered.spec;
+    let kind = spec.action.kind();
+    let retries = spec.retries.unwrap_or(DEFAULT_RETRIES);
+    let started_at = Utc::now();
+
+    let mut attempts = 0;
+    let outcome = loop {
+        attempts += 1;
+        let result = match state.actions.handlers.get(kind) {
+            Some(handler) => handler.run(state, event, &triggered.rule, &spec.action).await,
+            None => Err(ActionError::Unavailable(format!("no handler for action {}", kind))),
+        };
+        match result {
+            Ok(result) => break Ok(result),
+            Err(ActionError::Unavailable(reason)) => break Err(reason),
+            Err(e) if attempts > retries => break Err(format!("{:#}", e)),
+            Err(e) => {
+                let backoff = backoff(attempts);
+                warn!(
+                    action = kind,
+                    rule = %triggered.rule,
+                    sandbox_id = %event.sandbox_id,
+                    "Rule action failed, retrying in {:?}: {:#}",
+                    backoff,
+                    e
+                );
+                tokio::time::sleep(backoff).await;
+            }
+        }
+    };
+
+    let (status, result, error) = match outcome {
+        Ok(result) => (ActionStatus::Succeeded, Some(result), None),
+        Err(error) => (ActionStatus::Failed, None, Some(error)),
+    };
+    ActionRun {
+        id: Uuid::new_v4().to_string(),
+        action: kind.to_string(),
+        rule: triggered.rule.clone(),
+        sandbox_id: event.sandbox_id.clone(),
+        event_id,
+        status,
+        attempts,
+        parameters: serde_json::to_value(spec).unwrap_or_default(),
+        result,
+        error,
+        started_at,
+        finished_at: Utc::now(),
+    }
+}
+
+/// Wait after the `attempts`th failed attempt: a second, doubled each time
+fn backoff(attempts: u32) -> Duration {
+    Duration::from_secs(1 << (attempts.max(1) - 1).min(5)).min(MAX_BACKOFF)
+}
+
+/// A handler was given an action of another kind
+fn unexpected(action: &RuleAction) -> ActionError {
+    ActionError::Unavailable(format!("handler can't run a {} action", action.kind()))
+}
+
+struct Webhook {
+    http: reqwest::Client,
+}
+
+#[async_trait]
+impl ActionHandler for Webhook {
+    fn description(&self) -> &'static str {
+        "POST the event and the rule it matched to a URL"
+    }
+
+    fn check(&self, action: &RuleAction) -> Result<(), String> {
+        let RuleAction::Webhook { url, .. } = action else {
+            return Ok(());
+        };
+        match reqwest::Url::parse(url) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(()),
+            _ => Err(format!("webhook url {:?} is not an http(s) URL", url)),
+        }
+    }
+
+    async fn run(
+        &self,
+        _state: &AppState,
+        event: &SecurityEvent,
+        rule: &str,
+        action: &RuleAction,
+    ) -> Result<Value, ActionError> {
+        let RuleAction::Webhook { url, headers } = action else {
+            return Err(unexpected(action));
+        };
+        let mut request = self.http.post(url).json(&json!({
+            "rule": rule,
+            "event": event,
+        }));
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?.error_for_status()?;
+        Ok(json!({ "status": response.status().as_u16() }))
+    }
+}
+
+struct GatewayFreeze;
+
+#[async_trait]
+impl ActionHandler for GatewayFreeze {
+    fn description(&self) -> &'static str {
+        "Pause the sandbox and cut its network at the gateway"
+    }
+
+    async fn run(
+        &self,
+        state: &AppState,
+        event: &SecurityEvent,
+        rule: &str,
+        _action: &RuleAction,
+    ) -> Result<Value, ActionError> {
+        hold(state, event, rule, QuarantineMode::Freeze).await
+    }
+}
+
+struct NetworkBlock;
+
+#[async_trait]
+impl ActionHandler for NetworkBlock {
+    fn description(&self) -> &'static str {
+        "Cut the sandbox's network, or only its new outbound connections, at the gateway"
+    }
+
+    async fn run(
+        &self,
+        state: &AppState,
+        event: &SecurityEvent,
+        rule: &str,
+        action: &RuleAction,
+    ) -> Result<Value, ActionError> {
+        let RuleAction::NetworkBlock { egress_only } = action else {
+            return Err(unexpected(action));
+        };
+        let mode = if *egress_only {
+            QuarantineMode::BlockEgress
+        } else {
+            QuarantineMode::BlockAllNetwork
+        };
+        hold(state, event, rule, mode).await
+    }
+}
+
+/// Have the gateway hold a sandbox in a quarantine mode. An active
+/// quarantine at least as strict is enforced again rather than recording
+/// another, which keeps retries and repeat matches to one record.
+async fn hold(state: &AppState, event: &SecurityEvent, rule: &str, mode: QuarantineMode) -> Result<Value, ActionError> {
+    let Some(gateway_url) = state.config.current().gateway_url.clone() else {
+        return Err(ActionError::Unavailable("gateway_url is not set".to_string()));
+    };
+
+    let active = state
+        .quarantine_manager
+        .strictest_active(&event.sandbox_id)
+        .filter(|record| record.mode >= mode);
+    let record = match active {
+        Some(record) => record,
+        None => {
+            let record = state
+                .quarantine_manager
+                .quarantine(
+                    &event.sandbox_id,
+                    &format!("Rule '{}' triggered", rule),
+                    event,
+                    mode,
+                    crate::release_conditions(state, Vec::new()),
+                )
+                .await?;
+            warn!(
+                sandbox_id = %event.sandbox_id,
+                quarantine_id = %record.id,
+                mode = %record.mode,
+                "Sandbox quarantined"
+            );
+            record
+        }
+    };
+    state.gateway.enforce(&gateway_url, &record).await?;
+    Ok(json!({
+        "quarantine_id": record.id,
+        "mode": record.mode,
+    }))
+}
+
+struct DeepTrace;
+
+#[async_trait]
+impl ActionHandler for DeepTrace {
+    fn description(&self) -> &'static str {
+        "Attach every eBPF program to the sandbox and store all its events for a while"
+    }
+
+    fn check(&self, action: &RuleAction) -> Result<(), String> {
+        match action {
+            RuleAction::DeepTrace { duration_secs: 0 } => Err("deep trace duration_secs must be positive".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn run(
+        &self,
+        state: &AppState,
+        event: &SecurityEvent,
+        _rule: &str,
+        action: &RuleAction,
+    ) -> Result<Value, ActionError> {
+        let RuleAction::DeepTrace { duration_secs } = action else {
+            return Err(unexpected(action));
+        };
+        let until = Utc::now() + chrono::Duration::seconds(*duration_secs as i64);
+
+        let (ebpf, programs, tracing) = {
+            let Some(monitor) = state.sandbox_monitors.get(&event.sandbox_id) else {
+                return Err(ActionError::Unavailable(format!(
+                    "sandbox {} is not monitored",
+                    event.sandbox_id
+                )));
+            };
+            (
+                monitor.ebpf_monitor.clone(),
+                monitor.ebpf_programs.clone(),
+                monitor.deep_trace_until.is_some(),
+            )
+        };
+
+        // A trace under way already has every program attached
+        let added: Vec<String> = PROGRAMS
+            .iter()
+            .filter(|program| !programs.iter().any(|attached| attached == *program))
+            .map(|program| program.to_string())
+            .collect();
+        if let (Some(ebpf), false) = (&ebpf, tracing) {
+            ebpf.attach_programs(&added).await?;
+        }
+
+        let until = {
+            let Some(mut monitor) = state.sandbox_monitors.get_mut(&event.sandbox_id) else {
+                return Err(ActionError::Unavailable(format!(
+                    "sandbox {} stopped being monitored",
+                    event.sandbox_id
+                )));
+            };
+            let until = monitor.deep_trace_until.map_or(until, |current| current.max(until));
+            monitor.deep_trace_until = Some(until);
+            until
+        };
+        tokio::spawn(end_deep_trace(state.clone(), event.sandbox_id.clone(), until));
+        Ok(json!({
+            "until": until,
+            "ebpf_programs_added": if ebpf.is_some() { added } else { Vec::new() },
+        }))
+    }
+}
+
+/// Put a sandbox's probes back as its profile had them once its deep trace
+/// ends, unless a later trace extended it
+async fn end_deep_trace(state: AppState, sandbox_id: String, until: DateTime<Utc>) {
+    tokio::time::sleep((until - Utc::now()).to_std().unwrap_or_default()).await;
+
+    let (ebpf, programs) = {
+        let Some(mut monitor) = state.sandbox_monitors.get_mut(&sandbox_id) else {
+            return;
+        };
+        if monitor.deep_trace_until != Some(until) {
+            return;
+        }
+        monitor.deep_trace_until = None;
+        (monitor.ebpf_monitor.clone(), monitor.ebpf_programs.clone())
+    };
+    if let Some(ebpf) = ebpf {
+        let restored = async {
+            ebpf.detach_programs().await?;
+            ebpf.attach_programs(&programs).await
+        };
+        if let Err(e) = restored.await {
+            error!(sandbox_id = %sandbox_id, "Failed to restore eBPF programs after deep trace: {:#}", e);
+        }
+    }
+    info!(sandbox_id = %sandbox_id, "Deep trace ended");
+}
+
+struct OpenIncident {
+    http: reqwest::Client,
+}
+
+#[async_trait]
+impl ActionHandler for OpenIncident {
+    fn description(&self) -> &'static str {
+        "Open an incident at incident_url"
+    }
+
+    async fn run(
+        &self,
+        state: &AppState,
+        event: &SecurityEvent,
+        rule: &str,
+        action: &RuleAction,
+    ) -> Result<Value, ActionError> {
+        let RuleAction::OpenIncident { title, severity } = action else {
+            return Err(unexpected(action));
+        };
+        let config = state.config.current();
+        let Some(url) = config.incident_url.clone() else {
+            return Err(ActionError::Unavailable("incident_url is not set".to_string()));
+        };
+
+        let mut request = self.http.post(url).json(&json!({
+            "title": title.clone().unwrap_or_else(|| rule.to_string()),
+            "severity": severity.unwrap_or(event.severity),
+            "sandbox_id": event.sandbox_id,
+            "run_id": event.run_id,
+            "rule": rule,
+            "event": event,
+        }));
+        if let Some(token) = &config.incident_token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await?.error_for_status()?;
+        let status = response.status().as_u16();
+        // The incident as the tracker describes it, when it answers in JSON
+        Ok(response
+            .json::<Value>()
+            .await
+            .unwrap_or_else(|_| json!({ "status": status })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(actions: Vec<RuleAction>) -> SecurityPolicy {
+        let mut policy: SecurityPolicy = serde_json::from_value(json!({
+            "id": "policy_actions",
+            "name": "Actions",
+            "description": "",
+            "enabled": true,
+            "tier": "basic",
+            "rules": [{
+                "id": "rule_actions",
+                "name": "Escalate",
+                "description": "",
+                "condition": { "event_type": "privilege_escalation" },
+                "action": "alert"
+            }],
+            "created_at": Utc::now(),
+            "updated_at": Utc::now()
+        }))
+        .unwrap();
+        policy.rules[0].actions = actions
+            .into_iter()
+            .map(|action| RuleActionSpec { action, retries: None })
+            .collect();
+        policy
+    }
+
+    #[test]
+    fn lists_the_built_in_kinds_by_name() {
+        let names: Vec<&str> = ActionRegistry::new().list().into_iter().map(|kind| kind.name).collect();
+        assert_eq!(
+            names,
+            ["deep_trace", "gateway_freeze", "network_block", "open_incident", "webhook"]
+        );
+    }
+
+    #[test]
+    fn policies_are_checked_against_the_handlers() {
+        let registry = ActionRegistry::new();
+        let webhook = |url: &str| RuleAction::Webhook {
+            url: url.to_string(),
+            headers: Default::default(),
+        };
+
+        let valid = policy(vec![
+            webhook("https://hooks.example.com/security"),
+            RuleAction::NetworkBlock { egress_only: true },
+            RuleAction::DeepTrace { duration_secs: 60 },
+        ]);
+        assert_eq!(registry.check_policy(&valid), Ok(()));
+
+        assert_eq!(
+            registry.check_policy(&policy(vec![webhook("ftp://files.example.com")])),
+            Err("rule Escalate: webhook url \"ftp://files.example.com\" is not an http(s) URL".to_string())
+        );
+        assert_eq!(
+            registry.check_policy(&policy(vec![RuleAction::DeepTrace { duration_secs: 0 }])),
+            Err("rule Escalate: deep trace duration_secs must be positive".to_string())
+        );
+
+        let mut without_incidents = ActionRegistry::new();
+        without_incidents.handlers.remove("open_incident");
+        let incident = policy(vec![RuleAction::OpenIncident {
+            title: None,
+            severity: None,
+        }]);
+        assert_eq!(
+            without_incidents.check_policy(&incident),
+            Err("rule Escalate uses unknown action open_incident".to_string())
+        );
+    }
+
+    #[test]
+    fn retries_back_off_exponentially_up_to_a_limit() {
+        let waits: Vec<u64> = (1..=8).map(|attempts| backoff(attempts).as_secs()).collect();
+        assert_eq!(waits, [1, 2, 4, 8, 16, 30, 30, 30]);
+    }
+}
@@ -0,0 +1,260 @@
+//! Event and alert writes held back while Postgres is down. Policies keep
+//! being evaluated and acted on; the records they produce wait here, oldest
+//! first, and are written once the database breaker lets calls through.
+
+use anyhow::Result;
+use prometheus::CounterVec;
+use sandstorm_http::{CircuitBreaker, Unavailable};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::models::*;
+use crate::storage::{self, EventStore};
+
+/// Writes held at most. The oldest are dropped to make room past this.
+const MAX_QUEUED: usize = 10_000;
+
+/// A record waiting to be written
+enum Write {
+    /// An event, with the id it was answered with
+    Event { id: String, event: Box<SecurityEvent> },
+    Alert(Alert),
+}
+
+impl Write {
+    fn kind(&self) -> &'static str {
+        match self {
+            Write::Event { .. } => "event",
+            Write::Alert(_) => "alert",
+        }
+    }
+
+    async fn apply(&self, store: &EventStore) -> Result<()> {
+        match self {
+            Write::Event { id, event } => store.store_event(id, event).await,
+            Write::Alert(alert) => store.store_alert(alert).await,
+        }
+    }
+}
+
+/// What became of a write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Written,
+    /// Held until the database is back
+    Queued,
+}
+
+pub struct WriteBacklog {
+    breaker: CircuitBreaker,
+    queued: Mutex<VecDeque<Write>>,
+    /// Writes by kind and outcome: queued, written, dropped
+    writes: CounterVec,
+}
+
+impl WriteBacklog {
+    pub fn new(breaker: CircuitBreaker, writes: CounterVec) -> Self {
+        Self {
+            breaker,
+            queued: Mutex::new(VecDeque::new()),
+            writes,
+        }
+    }
+
+    /// Breaker guarding the event database
+    pub fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    /// Writes waiting for the database
+    pub fn len(&self) -> usize {
+        self.queued.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.lock().unwrap().is_empty()
+    }
+
+    /// Store an event, or queue it if the database can't be reached.
+    /// Returns the event's id either way.
+    pub async fn store_event(&self, store: &EventStore, event: &SecurityEvent) -> Result<(String, Outcome)> {
+        let id = Uuid::new_v4().to_string();
+        let write = Write::Event { id: id.clone(), event: Box::new(event.clone()) };
+        Ok((id, self.write(store, write).await?))
+    }
+
+    /// Store an alert, or queue it if the database can't be reached
+    pub async fn store_alert(&self, store: &EventStore, alert: &Alert) -> Result<Outcome> {
+        self.write(store, Write::Alert(alert.clone())).await
+    }
+
+    async fn write(&self, store: &EventStore, write: Write) -> Result<Outcome> {
+        match self.breaker.call(write.apply(store), storage::unreachable).await {
+            Ok(()) => Ok(Outcome::Written),
+            Err(e) if Unavailable::find(&e).is_some() || storage::unreachable(&e) => {
+                self.push(write);
+                Ok(Outcome::Queued)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Queue a write, dropping the oldest if the backlog is full
+    fn push(&self, write: Write) {
+        self.count(write.kind(), "queued");
+        let dropped = {
+            let mut queued = self.queued.lock().unwrap();
+            let dropped = if queued.len() >= MAX_QUEUED { queued.pop_front() } else { None };
+            queued.push_back(write);
+            dropped
+        };
+        if let Some(dropped) = dropped {
+            warn!("Write backlog is full; dropped the oldest {}", dropped.kind());
+            self.count(dropped.kind(), "dropped");
+        }
+    }
+
+    fn count(&self, kind: &str, outcome: &str) {
+        self.writes.with_label_values(&[kind, outcome]).inc();
+    }
+
+    /// Write queued records in order until the backlog is empty or the
+    /// database fails again. Records the database rejects are dropped.
+    /// Returns the number written.
+    pub async fn flush(&self, store: &EventStore) -> usize {
+        let mut written = 0;
+        loop {
+            let Some(write) = self.queued.lock().unwrap().pop_front() else {
+                break;
+            };
+            match self.breaker.call(write.apply(store), storage::unreachable).await {
+                Ok(()) => {
+                    self.count(write.kind(), "written");
+                    written += 1;
+                }
+                Err(e) if Unavailable::find(&e).is_some() || storage::unreachable(&e) => {
+                    self.queued.lock().unwrap().push_front(write);
+                    break;
+                }
+                Err(e) => {
+                    warn!("Dropped a queued {} the database rejected: {:#}", write.kind(), e);
+                    self.count(write.kind(), "dropped");
+                }
+            }
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::Fixture;
+    use prometheus::Opts;
+    use sandstorm_http::{BreakerSettings, BreakerState};
+    use std::time::Duration;
+
+    /// The migrated database the query macros are checked against
+    async fn store() -> EventStore {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must name a migrated database");
+        EventStore::new(&url).await.unwrap()
+    }
+
+    fn backlog(open_for: Duration) -> WriteBacklog {
+        let settings = BreakerSettings { failures: 2, open_for };
+        let writes = CounterVec::new(Opts::new("deferred_writes_total", "Deferred writes"), &["kind", "outcome"]).unwrap();
+        WriteBacklog::new(CircuitBreaker::new("postgres", settings), writes)
+    }
+
+    fn event() -> SecurityEvent {
+        Fixture::ProcessSpawn {
+            command: "/bin/sh".to_string(),
+            args: Vec::new(),
+        }
+        .event(&format!("backlog-{}", Uuid::new_v4()), None)
+    }
+
+    fn count(backlog: &WriteBacklog, outcome: &str) -> f64 {
+        backlog.writes.with_label_values(&["event", outcome]).get()
+    }
+
+    #[tokio::test]
+    async fn writes_go_straight_through_a_closed_breaker() {
+        let store = store().await;
+        let backlog = backlog(Duration::from_secs(30));
+
+        let (id, outcome) = backlog.store_event(&store, &event()).await.unwrap();
+        assert_eq!(outcome, Outcome::Written);
+        assert!(store.get_event(&id).await.unwrap().is_some());
+        assert!(backlog.is_empty());
+        assert_eq!(backlog.breaker().state(), BreakerState::Closed);
+    }
+
+    #[tokio::test]
+    async fn writes_wait_while_open_and_flush_once_half_open() {
+        let store = store().await;
+        let backlog = backlog(Duration::from_millis(100));
+        backlog.breaker().failed();
+        backlog.breaker().failed();
+        assert_eq!(backlog.breaker().state(), BreakerState::Open);
+
+        let (first, outcome) = backlog.store_event(&store, &event()).await.unwrap();
+        assert_eq!(outcome, Outcome::Queued);
+        let (second, _) = backlog.store_event(&store, &event()).await.unwrap();
+        assert_eq!(backlog.len(), 2);
+        assert!(store.get_event(&first).await.unwrap().is_none());
+
+        // Nothing is written while the breaker is open
+        assert_eq!(backlog.flush(&store).await, 0);
+        assert_eq!(backlog.len(), 2);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(backlog.breaker().state(), BreakerState::HalfOpen);
+        assert_eq!(backlog.flush(&store).await, 2);
+        assert_eq!(backlog.breaker().state(), BreakerState::Closed);
+        assert!(store.get_event(&first).await.unwrap().is_some());
+        assert!(store.get_event(&second).await.unwrap().is_some());
+        assert_eq!((count(&backlog, "queued"), count(&backlog, "written")), (2.0, 2.0));
+    }
+
+    #[tokio::test]
+    async fn writes_the_database_rejects_are_dropped() {
+        let store = store().await;
+        let backlog = backlog(Duration::from_secs(30));
+        let (id, later) = (Uuid::new_v4().to_string(), Uuid::new_v4().to_string());
+        let queued = Box::new(event());
+        backlog.push(Write::Event { id: id.clone(), event: queued.clone() });
+        // The same id again breaks the events' primary key
+        backlog.push(Write::Event { id, event: queued.clone() });
+        backlog.push(Write::Event { id: later.clone(), event: queued });
+
+        // The rejected write doesn't hold up the ones behind it
+        assert_eq!(backlog.flush(&store).await, 2);
+        assert!(backlog.is_empty());
+        assert!(store.get_event(&later).await.unwrap().is_some());
+        assert_eq!(count(&backlog, "dropped"), 1.0);
+        assert_eq!(backlog.breaker().state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn a_full_backlog_drops_the_oldest_write() {
+        let backlog = backlog(Duration::from_secs(30));
+        let event = Box::new(event());
+        for n in 0..=MAX_QUEUED {
+            backlog.push(Write::Event {
+                id: n.to_string(),
+                event: event.clone(),
+            });
+        }
+        assert_eq!(backlog.len(), MAX_QUEUED);
+        assert_eq!(count(&backlog, "dropped"), 1.0);
+        let oldest = backlog.queued.lock().unwrap().front().map(|write| match write {
+            Write::Event { id, .. } => id.clone(),
+            Write::Alert(alert) => alert.id.clone(),
+        });
+        assert_eq!(oldest.as_deref(), Some("1"));
+    }
+}
+
@@ -3,11 +3,14 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing::{error, info, warn};
 
-use crate::models::SecurityEvent;
+use crate::models::{EventType, SecurityEvent, Severity};
 
 // In a real implementation, this would use libbpf-rs
 // For now, we'll create a mock implementation
 
+/// Programs a monitoring profile can attach
+pub const PROGRAMS: &[&str] = &["file_monitor", "network_monitor", "process_monitor", "privilege_monitor"];
+
 pub struct EbpfMonitor {
     sandbox_id: String,
     programs: Arc<RwLock<Vec<EbpfProgram>>>,
@@ -30,32 +33,29 @@ impl EbpfMonitor {
         })
     }
 
-    pub async fn attach_programs(&self) -> Result<()> {
+    /// Attach the named programs from [`PROGRAMS`]; unknown names are
+    /// skipped
+    pub async fn attach_programs(&self, names: &[String]) -> Result<()> {
         let mut programs = self.programs.write().await;
-        
+
         // Mock programs for different monitoring aspects
-        let default_programs = vec![
-            EbpfProgram {
-                id: "file_monitor".to_string(),
-                program_type: "tracepoint".to_string(),
-                attach_point: "syscalls:sys_enter_openat".to_string(),
+        let selected = names.iter().filter_map(|name| {
+            let (program_type, attach_point) = match name.as_str() {
+                "file_monitor" => ("tracepoint", "syscalls:sys_enter_openat"),
+                "network_monitor" => ("xdp", "eth0"),
+                "process_monitor" => ("tracepoint", "sched:sched_process_exec"),
+                "privilege_monitor" => ("kprobe", "commit_creds"),
+                _ => return None,
+            };
+            Some(EbpfProgram {
+                id: name.clone(),
+                program_type: program_type.to_string(),
+                attach_point: attach_point.to_string(),
                 loaded: false,
-            },
-            EbpfProgram {
-                id: "network_monitor".to_string(),
-                program_type: "xdp".to_string(),
-                attach_point: "eth0".to_string(),
-                loaded: false,
-            },
-            EbpfProgram {
-                id: "process_monitor".to_string(),
-                program_type: "tracepoint".to_string(),
-                attach_point: "sched:sched_process_exec".to_string(),
-                loaded: false,
-            },
-        ];
+            })
+        });
 
-        for mut program in default_programs {
+        for mut program in selected {
             match self.load_program(&mut program).await {
                 Ok(_) => {
                     info!("Loaded eBPF program: {}", program.id);
@@ -160,8 +160,8 @@ impl EbpfMonitor {
     fn create_file_access_event(sandbox_id: &str) -> SecurityEvent {
         SecurityEvent {
             id: uuid::Uuid::new_v4().to_string(),
-            event_type: "file_access".to_string(),
-            severity: "medium".to_string(),
+            event_type: EventType::FileAccess,
+            severity: Severity::Medium,
             timestamp: chrono::Utc::now(),
             sandbox_id: sandbox_id.to_string(),
             provider: "custom".to_string(),
@@ -178,14 +178,17 @@ impl EbpfMonitor {
             })),
             falco_rule: None,
             ebpf_trace: Some("file_monitor".to_string()),
+            run_id: None,
+            occurrences: 1,
+            last_seen: None,
         }
     }
 
     fn create_network_event(sandbox_id: &str) -> SecurityEvent {
         SecurityEvent {
             id: uuid::Uuid::new_v4().to_string(),
-            event_type: "network_activity".to_string(),
-            severity: "low".to_string(),
+            event_type: EventType::NetworkActivity,
+            severity: Severity::Low,
             timestamp: chrono::Utc::now(),
             sandbox_id: sandbox_id.to_string(),
             provider: "custom".to_string(),
@@ -201,14 +204,17 @@ impl EbpfMonitor {
             })),
             falco_rule: None,
             ebpf_trace: Some("network_monitor".to_string()),
+            run_id: None,
+            occurrences: 1,
+            last_seen: None,
         }
     }
 
     fn create_process_event(sandbox_id: &str) -> SecurityEvent {
         SecurityEvent {
             id: uuid::Uuid::new_v4().to_string(),
-            event_type: "process_spawn".to_string(),
-            severity: "medium".to_string(),
+            event_type: EventType::ProcessSpawn,
+            severity: Severity::Medium,
             timestamp: chrono::Utc::now(),
             sandbox_id: sandbox_id.to_string(),
             provider: "custom".to_string(),
@@ -225,6 +231,9 @@ impl EbpfMonitor {
             })),
             falco_rule: None,
             ebpf_trace: Some("process_monitor".to_string()),
+            run_id: None,
+            occurrences: 1,
+            last_seen: None,
         }
     }
 }
\ No newline at end of file
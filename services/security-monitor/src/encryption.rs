@@ -0,0 +1,131 @@
+//! Column encryption of event details and metadata. Sealed values are kept
+//! in their JSONB columns as `{"sealed": "<base64 nonce and ciphertext>"}`,
+//! bound to their event's ID so they can't be moved to another row. Values
+//! stored before a key was configured are read as they are.
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{json, Value};
+
+const SEALED_FIELD: &str = "sealed";
+
+pub struct FieldCipher {
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+impl FieldCipher {
+    /// Cipher for a base64-encoded 32-byte key
+    pub fn from_base64(key: &str) -> Result<Self> {
+        let key = STANDARD
+            .decode(key.trim())
+            .context("event_encryption_key is not valid base64")?;
+        let key = UnboundKey::new(&AES_256_GCM, &key)
+            .map_err(|_| anyhow!("event_encryption_key must be 32 bytes"))?;
+        Ok(Self {
+            key: LessSafeKey::new(key),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    pub fn seal(&self, event_id: &str, value: &Value) -> Result<Value> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("failed to generate a nonce"))?;
+        let mut sealed = serde_json::to_vec(value)?;
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(event_id.as_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| anyhow!("failed to encrypt event {}", event_id))?;
+
+        let mut encoded = nonce.to_vec();
+        encoded.extend_from_slice(&sealed);
+        Ok(json!({ SEALED_FIELD: STANDARD.encode(encoded) }))
+    }
+
+    /// The value `seal` was given, or `value` itself if it isn't sealed
+    pub fn open(&self, event_id: &str, value: Value) -> Result<Value> {
+        let Some(sealed) = sealed(&value) else {
+            return Ok(value);
+        };
+        let mut sealed = STANDARD
+            .decode(sealed)
+            .with_context(|| format!("sealed field of event {} is not valid base64", event_id))?;
+        if sealed.len() < NONCE_LEN {
+            anyhow::bail!("sealed field of event {} is truncated", event_id);
+        }
+        let mut ciphertext = sealed.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&sealed)
+            .map_err(|_| anyhow!("sealed field of event {} is truncated", event_id))?;
+        let plaintext = self
+            .key
+            .open_in_place(nonce, Aad::from(event_id.as_bytes()), &mut ciphertext)
+            .map_err(|_| anyhow!("failed to decrypt event {}; wrong key?", event_id))?;
+        Ok(serde_json::from_slice(plaintext)?)
+    }
+}
+
+/// The encoded ciphertext of a sealed value
+fn sealed(value: &Value) -> Option<&str> {
+    let map = value.as_object()?;
+    if map.len() != 1 {
+        return None;
+    }
+    map.get(SEALED_FIELD)?.as_str()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher(byte: u8) -> FieldCipher {
+        FieldCipher::from_base64(&STANDARD.encode([byte; 32])).unwrap()
+    }
+
+    #[test]
+    fn opens_what_it_sealed() {
+        let cipher = cipher(1);
+        let details = json!({ "command": "/bin/sh", "args": ["-c", "id"] });
+
+        let value = cipher.seal("event-1", &details).unwrap();
+        assert!(sealed(&value).is_some());
+        assert!(!value.to_string().contains("/bin/sh"));
+        assert_eq!(cipher.open("event-1", value.clone()).unwrap(), details);
+
+        // Nonces are fresh for every seal
+        assert_ne!(cipher.seal("event-1", &details).unwrap(), value);
+    }
+
+    #[test]
+    fn refuses_another_key_or_event() {
+        let sealed = cipher(1).seal("event-1", &json!({ "a": 1 })).unwrap();
+
+        let error = cipher(2).open("event-1", sealed.clone()).unwrap_err();
+        assert_eq!(error.to_string(), "failed to decrypt event event-1; wrong key?");
+        assert!(cipher(1).open("event-2", sealed).is_err());
+
+        let truncated = json!({ SEALED_FIELD: STANDARD.encode([0u8; 4]) });
+        assert!(cipher(1).open("event-1", truncated).is_err());
+    }
+
+    #[test]
+    fn reads_values_that_were_never_sealed() {
+        let cipher = cipher(1);
+        for value in [json!({ "a": 1 }), json!({ "sealed": "x", "other": 1 }), json!({ "sealed": 1 }), Value::Null] {
+            assert_eq!(cipher.open("event-1", value.clone()).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn keys_must_be_32_bytes_of_base64() {
+        assert!(FieldCipher::from_base64("not base64!").is_err());
+        let error = FieldCipher::from_base64(&STANDARD.encode([0u8; 16])).err().unwrap();
+        assert_eq!(error.to_string(), "event_encryption_key must be 32 bytes");
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::Result;
+
+use crate::models::*;
+
+/// Asks the gateway running a sandbox to enforce or lift its quarantine
+pub struct GatewayEnforcer {
+    http: reqwest::Client,
+}
+
+impl GatewayEnforcer {
+    pub fn new() -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    pub async fn enforce(&self, gateway_url: &str, record: &QuarantineRecord) -> Result<()> {
+        self.http
+            .put(quarantine_url(gateway_url, &record.sandbox_id))
+            .json(&QuarantineEnforcement {
+                quarantine_id: record.id.clone(),
+                mode: record.mode,
+            })
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    pub async fn lift(&self, gateway_url: &str, record: &QuarantineRecord) -> Result<()> {
+        self.http
+            .delete(quarantine_url(gateway_url, &record.sandbox_id))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Whether the gateway still knows a sandbox
+    pub async fn sandbox_exists(&self, gateway_url: &str, sandbox_id: &str) -> Result<bool> {
+        let response = self
+            .http
+            .get(format!(
+                "{}/v1/sandboxes/{}/status",
+                gateway_url.trim_end_matches('/'),
+                sandbox_id
+            ))
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        response.error_for_status()?;
+        Ok(true)
+    }
+}
+
+fn quarantine_url(gateway_url: &str, sandbox_id: &str) -> String {
+    format!(
+        "{}/v1/sandboxes/{}/quarantine",
+        gateway_url.trim_end_matches('/'),
+        sandbox_id
+    )
+}
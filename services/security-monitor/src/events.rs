@@ -5,7 +5,7 @@ use std::collections::HashMap;
 
 use crate::models::*;
 
-pub use SecurityEvent;
+pub use crate::models::SecurityEvent;
 
 pub struct EventAggregator;
 
@@ -51,7 +51,7 @@ impl EventAggregator {
                     EventPattern {
                         event_type: event.event_type.clone(),
                         count: 1,
-                        severity: event.severity.clone(),
+                        severity: event.severity,
                         sandboxes: vec![event.sandbox_id.clone()],
                         first_seen: event.timestamp,
                         last_seen: event.timestamp,
@@ -80,7 +80,7 @@ impl EventAggregator {
         for event in events {
             let key = format!("{}:{}", event.event_type, event.sandbox_id);
             if let Some(&count) = event_counts.get(&key) {
-                if count > 10 || event.severity == "critical" {
+                if count > 10 || event.severity == Severity::Critical {
                     anomalies.push(event.clone());
                 }
             }
@@ -149,7 +149,7 @@ impl EventAggregator {
         for (sandbox_id, events) in sandbox_events {
             let high_severity_events: Vec<_> = events
                 .into_iter()
-                .filter(|e| e.severity == "high" || e.severity == "critical")
+                .filter(|e| e.severity >= Severity::High)
                 .collect();
 
             if high_severity_events.len() > 1 {
@@ -169,9 +169,9 @@ impl EventAggregator {
         
         // Known attack patterns
         let attack_patterns = vec![
-            vec!["file_access", "process_spawn", "privilege_escalation"],
-            vec!["file_access", "network_activity"],
-            vec!["network_activity", "process_spawn", "network_activity"],
+            vec![EventType::FileAccess, EventType::ProcessSpawn, EventType::PrivilegeEscalation],
+            vec![EventType::FileAccess, EventType::NetworkActivity],
+            vec![EventType::NetworkActivity, EventType::ProcessSpawn, EventType::NetworkActivity],
         ];
 
         // Group events by sandbox and sort by time
@@ -200,7 +200,7 @@ impl EventAggregator {
         groups
     }
 
-    fn find_sequence(&self, events: &[SecurityEvent], sequence: &[&str]) -> Option<Vec<SecurityEvent>> {
+    fn find_sequence(&self, events: &[SecurityEvent], sequence: &[EventType]) -> Option<Vec<SecurityEvent>> {
         let mut matched = Vec::new();
         let mut sequence_index = 0;
 
@@ -1,26 +1,28 @@
 use anyhow::Result;
 use std::process::{Command, Stdio};
+use std::sync::Arc;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Child;
 use tokio::sync::RwLock;
 use tracing::{error, info, warn};
 
-use crate::models::SecurityEvent;
+use crate::models::{EventType, SecurityEvent, Severity};
 
 pub struct FalcoIntegration {
     sandbox_id: String,
-    rules_path: String,
+    /// Rule files, loaded in order
+    rules_paths: Vec<String>,
     process: RwLock<Option<Child>>,
-    event_handlers: RwLock<Vec<Box<dyn Fn(SecurityEvent) + Send + Sync>>>,
+    event_handlers: Arc<RwLock<Vec<Box<dyn Fn(SecurityEvent) + Send + Sync>>>>,
 }
 
 impl FalcoIntegration {
-    pub fn new(sandbox_id: &str, rules_path: &str) -> Result<Self> {
+    pub fn new(sandbox_id: &str, rules_paths: Vec<String>) -> Result<Self> {
         Ok(Self {
             sandbox_id: sandbox_id.to_string(),
-            rules_path: rules_path.to_string(),
+            rules_paths,
             process: RwLock::new(None),
-            event_handlers: RwLock::new(Vec::new()),
+            event_handlers: Arc::new(RwLock::new(Vec::new())),
         })
     }
 
@@ -37,8 +39,10 @@ impl FalcoIntegration {
         cmd.args(&[
             "-o", "json_output=true",
             "-o", "json_include_output_property=true",
-            "-r", &self.rules_path,
         ]);
+        for rules_path in &self.rules_paths {
+            cmd.arg("-r").arg(rules_path);
+        }
 
         let mut child = tokio::process::Command::from(cmd)
             .stdout(Stdio::piped())
@@ -121,14 +125,8 @@ impl FalcoIntegration {
             .map(|dt| dt.with_timezone(&chrono::Utc))
             .unwrap_or_else(|_| chrono::Utc::now());
 
-        // Map Falco priority to our severity levels
-        let severity = match priority.to_lowercase().as_str() {
-            "emergency" | "alert" | "critical" => "critical",
-            "error" => "high",
-            "warning" => "medium",
-            "notice" | "informational" | "debug" => "low",
-            _ => "medium",
-        };
+        // Falco priorities are aliases of our severity levels
+        let severity = priority.parse().unwrap_or(Severity::Medium);
 
         // Map rule to event type
         let event_type = Self::map_rule_to_event_type(rule);
@@ -150,7 +148,7 @@ impl FalcoIntegration {
         Some(SecurityEvent {
             id: uuid::Uuid::new_v4().to_string(),
             event_type,
-            severity: severity.to_string(),
+            severity,
             timestamp,
             sandbox_id: sandbox_id.to_string(),
             provider: "custom".to_string(),
@@ -159,23 +157,26 @@ impl FalcoIntegration {
             metadata,
             falco_rule: Some(rule.to_string()),
             ebpf_trace: None,
+            run_id: None,
+            occurrences: 1,
+            last_seen: None,
         })
     }
 
-    fn map_rule_to_event_type(rule: &str) -> String {
+    fn map_rule_to_event_type(rule: &str) -> EventType {
         // Map common Falco rules to our event types
         if rule.contains("Write below etc") || rule.contains("Read sensitive file") {
-            "file_access".to_string()
+            EventType::FileAccess
         } else if rule.contains("Outbound Connection") || rule.contains("Inbound Connection") {
-            "network_activity".to_string()
+            EventType::NetworkActivity
         } else if rule.contains("Spawned Process") || rule.contains("Run shell") {
-            "process_spawn".to_string()
+            EventType::ProcessSpawn
         } else if rule.contains("Sudo") || rule.contains("Change thread namespace") {
-            "privilege_escalation".to_string()
+            EventType::PrivilegeEscalation
         } else if rule.contains("Container escape") || rule.contains("Crypto mining") {
-            "suspicious_behavior".to_string()
+            EventType::SuspiciousBehavior
         } else {
-            "policy_violation".to_string()
+            EventType::PolicyViolation
         }
     }
 }
@@ -0,0 +1,181 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::models::{EventType, SecurityEvent, Severity};
+
+/// Sandbox fixture events come from unless a test names one
+pub const FIXTURE_SANDBOX: &str = "sandbox-fixture";
+
+/// Synthetic events for policy tests, shaped like the ones the eBPF and
+/// Falco integrations report so rule patterns see realistic JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "fixture", rename_all = "snake_case")]
+pub enum Fixture {
+    /// A file opened by a process in the sandbox
+    FileAccess {
+        path: String,
+        #[serde(default = "default_open_flags")]
+        flags: String,
+        #[serde(default = "default_executable")]
+        executable: String,
+    },
+    /// An outbound connection
+    NetworkActivity {
+        destination_ip: String,
+        port: u16,
+        #[serde(default = "default_protocol")]
+        protocol: String,
+    },
+    /// A process started in the sandbox
+    ProcessSpawn {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// A process gaining another user's privileges, root by default
+    PrivilegeEscalation {
+        #[serde(default = "default_executable")]
+        executable: String,
+        #[serde(default = "default_uid")]
+        from_uid: u32,
+        #[serde(default)]
+        to_uid: u32,
+    },
+    SuspiciousBehavior {
+        description: String,
+    },
+    PolicyViolation {
+        description: String,
+    },
+    /// An event of any type with the given details
+    Custom {
+        event_type: EventType,
+        /// Defaults to naming the event type
+        #[serde(default)]
+        message: String,
+        #[serde(default)]
+        details: serde_json::Value,
+    },
+}
+
+fn default_open_flags() -> String {
+    "O_RDONLY".to_string()
+}
+
+fn default_executable() -> String {
+    "/bin/sh".to_string()
+}
+
+fn default_protocol() -> String {
+    "TCP".to_string()
+}
+
+fn default_uid() -> u32 {
+    1000
+}
+
+impl Fixture {
+    pub fn event_type(&self) -> EventType {
+        match self {
+            Fixture::FileAccess { .. } => EventType::FileAccess,
+            Fixture::NetworkActivity { .. } => EventType::NetworkActivity,
+            Fixture::ProcessSpawn { .. } => EventType::ProcessSpawn,
+            Fixture::PrivilegeEscalation { .. } => EventType::PrivilegeEscalation,
+            Fixture::SuspiciousBehavior { .. } => EventType::SuspiciousBehavior,
+            Fixture::PolicyViolation { .. } => EventType::PolicyViolation,
+            Fixture::Custom { event_type, .. } => event_type.clone(),
+        }
+    }
+
+    /// Severity the monitor's own sources give such an event
+    pub fn default_severity(&self) -> Severity {
+        match self {
+            Fixture::NetworkActivity { .. } => Severity::Low,
+            Fixture::PrivilegeEscalation { .. } | Fixture::SuspiciousBehavior { .. } => {
+                Severity::High
+            }
+            _ => Severity::Medium,
+        }
+    }
+
+    /// The event, from `sandbox_id` at `severity`
+    pub fn event(&self, sandbox_id: &str, severity: Option<Severity>) -> SecurityEvent {
+        let (message, details, metadata, ebpf_trace) = match self {
+            Fixture::FileAccess {
+                path,
+                flags,
+                executable,
+            } => (
+                format!("File access to {}", path),
+                json!({ "syscall": "openat", "filename": path, "flags": flags }),
+                Some(json!({ "pid": 1234, "uid": 1000, "executable": executable })),
+                Some("file_monitor"),
+            ),
+            Fixture::NetworkActivity {
+                destination_ip,
+                port,
+                protocol,
+            } => (
+                format!("Connection to {}:{}", destination_ip, port),
+                json!({ "protocol": protocol, "bytes": 0 }),
+                Some(json!({
+                    "sourceIp": "10.0.0.1",
+                    "destinationIp": destination_ip,
+                    "port": port
+                })),
+                Some("network_monitor"),
+            ),
+            Fixture::ProcessSpawn { command, args } => (
+                format!("Process {} started", command),
+                json!({ "command": command, "args": args }),
+                Some(json!({ "pid": 5678, "ppid": 1234, "uid": 1000, "executable": command })),
+                Some("process_monitor"),
+            ),
+            Fixture::PrivilegeEscalation {
+                executable,
+                from_uid,
+                to_uid,
+            } => (
+                format!("{} changed uid from {} to {}", executable, from_uid, to_uid),
+                json!({ "syscall": "setuid", "from_uid": from_uid, "to_uid": to_uid }),
+                Some(json!({ "pid": 5678, "uid": from_uid, "executable": executable })),
+                Some("privilege_monitor"),
+            ),
+            Fixture::SuspiciousBehavior { description }
+            | Fixture::PolicyViolation { description } => {
+                (description.clone(), json!({ "description": description }), None, None)
+            }
+            Fixture::Custom {
+                event_type,
+                message,
+                details,
+            } => {
+                let message = if message.is_empty() {
+                    format!("{} event", event_type)
+                } else {
+                    message.clone()
+                };
+                (message, details.clone(), None, None)
+            }
+        };
+
+        SecurityEvent {
+            id: Uuid::new_v4().to_string(),
+            event_type: self.event_type(),
+            severity: severity.unwrap_or_else(|| self.default_severity()),
+            timestamp: Utc::now(),
+            sandbox_id: sandbox_id.to_string(),
+            provider: "fixture".to_string(),
+            message,
+            details,
+            metadata,
+            falco_rule: None,
+            ebpf_trace: ebpf_trace.map(str::to_string),
+            run_id: None,
+            occurrences: 1,
+            last_seen: None,
+        }
+    }
+}
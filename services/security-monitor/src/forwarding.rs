@@ -0,0 +1,267 @@
+use anyhow::Result;
+use chrono::Utc;
+use dashmap::DashMap;
+use ring::hmac;
+use sandstorm_types::security::{
+    SecuritySignal, SecuritySignalBatch, SIGNATURE_HEADER, SIGNATURE_TIMESTAMP_HEADER,
+};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::models::*;
+
+/// Sandboxes with unsent incidents kept at most. Incidents of further
+/// sandboxes are dropped until the collector catches up.
+const MAX_PENDING: usize = 10_000;
+
+/// Summarizes policy violations and quarantines per sandbox and forwards
+/// them to the telemetry collector in signed batches, so routing can
+/// penalize providers and images with elevated incident rates
+pub struct SignalForwarder {
+    http: reqwest::Client,
+    /// Incidents not yet sent, by sandbox
+    pending: DashMap<String, SecuritySignal>,
+    /// A batch the collector did not accept, resent with the same id
+    unsent: Mutex<Option<SecuritySignalBatch>>,
+}
+
+impl SignalForwarder {
+    pub fn new() -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            pending: DashMap::new(),
+            unsent: Mutex::new(None),
+        }
+    }
+
+    /// Tally an event a policy acted on. Returns false when the event was
+    /// dropped because too many sandboxes have unsent incidents.
+    pub fn record(&self, event: &SecurityEvent, action: &str) -> bool {
+        if action == "allow" {
+            return true;
+        }
+        if self.pending.len() >= MAX_PENDING && !self.pending.contains_key(&event.sandbox_id) {
+            return false;
+        }
+
+        let quarantines = u64::from(action == "quarantine");
+        self.pending
+            .entry(event.sandbox_id.clone())
+            .and_modify(|signal| {
+                signal.violations += event.occurrences;
+                signal.quarantines += quarantines;
+                signal.max_severity = signal.max_severity.max(event.severity);
+                signal.first_seen = signal.first_seen.min(event.timestamp);
+                signal.last_seen = signal.last_seen.max(event.timestamp);
+                signal.run_id = signal.run_id.or(event.run_id);
+                if signal.image.is_none() {
+                    signal.image = image(event);
+                }
+            })
+            .or_insert_with(|| SecuritySignal {
+                sandbox_id: event.sandbox_id.clone(),
+                provider: event.provider.clone(),
+                image: image(event),
+                run_id: event.run_id,
+                violations: event.occurrences,
+                quarantines,
+                max_severity: event.severity,
+                first_seen: event.timestamp,
+                last_seen: event.timestamp,
+            });
+        true
+    }
+
+    /// Send the next batch: the one the collector last refused, or up to
+    /// `max_signals` sandboxes whose incidents are oldest. The rest wait for
+    /// later batches. Returns the number of signals in the batch, none when
+    /// there was nothing to send, and whether the collector accepted it.
+    pub async fn flush(
+        &self,
+        telemetry_url: &str,
+        key: &hmac::Key,
+        max_signals: usize,
+    ) -> (usize, Result<()>) {
+        let batch = match self.unsent.lock().unwrap().take() {
+            Some(batch) => batch,
+            None => match self.next_batch(max_signals) {
+                Some(batch) => batch,
+                None => return (0, Ok(())),
+            },
+        };
+
+        let result = self.send(telemetry_url, key, &batch).await;
+        let signals = batch.signals.len();
+        if result.is_err() {
+            *self.unsent.lock().unwrap() = Some(batch);
+        }
+        (signals, result)
+    }
+
+    fn next_batch(&self, max_signals: usize) -> Option<SecuritySignalBatch> {
+        let mut oldest: Vec<_> = self
+            .pending
+            .iter()
+            .map(|signal| (signal.first_seen, signal.key().clone()))
+            .collect();
+        oldest.sort();
+
+        let signals: Vec<_> = oldest
+            .into_iter()
+            .take(max_signals)
+            .filter_map(|(_, sandbox_id)| {
+                self.pending.remove(&sandbox_id).map(|(_, signal)| signal)
+            })
+            .collect();
+        (!signals.is_empty()).then(|| SecuritySignalBatch {
+            batch_id: Uuid::new_v4(),
+            sent_at: Utc::now(),
+            signals,
+        })
+    }
+
+    async fn send(
+        &self,
+        telemetry_url: &str,
+        key: &hmac::Key,
+        batch: &SecuritySignalBatch,
+    ) -> Result<()> {
+        let body = serde_json::to_vec(batch)?;
+        let timestamp = Utc::now().timestamp();
+        self.http
+            .post(format!(
+                "{}/api/telemetry/security-signals",
+                telemetry_url.trim_end_matches('/')
+            ))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, sign(key, timestamp, &body))
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Sandboxes with incidents waiting to be sent
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Image the event's sandbox runs, from the event metadata
+fn image(event: &SecurityEvent) -> Option<String> {
+    event
+        .metadata
+        .as_ref()?
+        .get("image")?
+        .as_str()
+        .map(String::from)
+}
+
+/// `sha256=<hex>` HMAC of `<timestamp>.<body>`
+fn sign(key: &hmac::Key, timestamp: i64, body: &[u8]) -> String {
+    let mut context = hmac::Context::with_key(key);
+    context.update(timestamp.to_string().as_bytes());
+    context.update(b".");
+    context.update(body);
+    let hex: String = context
+        .sign()
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256={}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::Fixture;
+    use serde_json::json;
+
+    fn event(sandbox_id: &str, severity: Severity, minutes_ago: i64) -> SecurityEvent {
+        let mut event = Fixture::ProcessSpawn {
+            command: "/usr/bin/nc".to_string(),
+            args: Vec::new(),
+        }
+        .event(sandbox_id, Some(severity));
+        event.timestamp = Utc::now() - chrono::Duration::minutes(minutes_ago);
+        event
+    }
+
+    #[test]
+    fn incidents_are_summarized_per_sandbox() {
+        let forwarder = SignalForwarder::new();
+        let first = event("sandbox-a", Severity::Medium, 10);
+        let mut second = event("sandbox-a", Severity::Critical, 5);
+        second.occurrences = 3;
+        second.metadata = Some(json!({ "image": "python:3.12" }));
+
+        assert!(forwarder.record(&first, "allow"));
+        assert_eq!(forwarder.pending(), 0);
+        assert!(forwarder.record(&first, "deny"));
+        assert!(forwarder.record(&second, "quarantine"));
+        assert_eq!(forwarder.pending(), 1);
+
+        let signal = forwarder.pending.get("sandbox-a").unwrap().clone();
+        assert_eq!(signal.violations, 1 + 3);
+        assert_eq!(signal.quarantines, 1);
+        assert_eq!(signal.max_severity, Severity::Critical);
+        assert_eq!(signal.first_seen, first.timestamp);
+        assert_eq!(signal.last_seen, second.timestamp);
+        assert_eq!(signal.image.as_deref(), Some("python:3.12"));
+    }
+
+    #[test]
+    fn batches_take_the_oldest_incidents_first() {
+        let forwarder = SignalForwarder::new();
+        forwarder.record(&event("sandbox-new", Severity::Low, 1), "alert");
+        forwarder.record(&event("sandbox-old", Severity::Low, 30), "alert");
+        forwarder.record(&event("sandbox-mid", Severity::Low, 10), "alert");
+
+        let batch = forwarder.next_batch(2).unwrap();
+        let sandboxes: Vec<&str> = batch.signals.iter().map(|signal| signal.sandbox_id.as_str()).collect();
+        assert_eq!(sandboxes, ["sandbox-old", "sandbox-mid"]);
+        assert_eq!(forwarder.pending(), 1);
+        assert_eq!(forwarder.next_batch(2).unwrap().signals.len(), 1);
+        assert!(forwarder.next_batch(2).is_none());
+    }
+
+    #[tokio::test]
+    async fn refused_batches_are_resent_unchanged() {
+        let forwarder = SignalForwarder::new();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"secret");
+        forwarder.record(&event("sandbox-a", Severity::High, 1), "deny");
+
+        // Nothing listens on port 1
+        let (sent, result) = forwarder.flush("http://127.0.0.1:1", &key, 10).await;
+        assert_eq!(sent, 1);
+        assert!(result.is_err());
+        let batch_id = forwarder.unsent.lock().unwrap().as_ref().unwrap().batch_id;
+
+        // A newer incident waits behind the refused batch
+        forwarder.record(&event("sandbox-b", Severity::High, 1), "deny");
+        let (sent, result) = forwarder.flush("http://127.0.0.1:1", &key, 10).await;
+        assert_eq!(sent, 1);
+        assert!(result.is_err());
+        assert_eq!(forwarder.unsent.lock().unwrap().as_ref().unwrap().batch_id, batch_id);
+        assert_eq!(forwarder.pending(), 1);
+    }
+
+    #[test]
+    fn signatures_cover_the_timestamp_and_body() {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"secret");
+        let signature = sign(&key, 1_700_000_000, b"{}");
+        let hex = signature.strip_prefix("sha256=").unwrap();
+        let bytes: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        assert!(hmac::verify(&key, b"1700000000.{}", &bytes).is_ok());
+        assert_ne!(signature, sign(&key, 1_700_000_001, b"{}"));
+    }
+}
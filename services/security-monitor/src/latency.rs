@@ -0,0 +1,142 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::models::SecurityEvent;
+
+/// Stages an event passes through between the sandbox and the action taken
+/// on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Received,
+    Evaluated,
+    Stored,
+    Actioned,
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Received => "received",
+            Stage::Evaluated => "evaluated",
+            Stage::Stored => "stored",
+            Stage::Actioned => "actioned",
+        }
+    }
+}
+
+/// When an event reached each stage, from when it was generated in the
+/// sandbox
+#[derive(Debug, Clone, Serialize)]
+pub struct DetectionTimeline {
+    pub generated_at: DateTime<Utc>,
+    pub received_at: DateTime<Utc>,
+    pub evaluated_at: Option<DateTime<Utc>>,
+    /// `None` when sampling kept the event from being stored on its own
+    pub stored_at: Option<DateTime<Utc>>,
+    pub actioned_at: Option<DateTime<Utc>>,
+}
+
+impl DetectionTimeline {
+    /// Start the timeline of an event that just arrived
+    pub fn received(event: &SecurityEvent) -> Self {
+        Self {
+            generated_at: event.timestamp,
+            received_at: Utc::now(),
+            evaluated_at: None,
+            stored_at: None,
+            actioned_at: None,
+        }
+    }
+
+    pub fn mark(&mut self, stage: Stage) {
+        let now = Some(Utc::now());
+        match stage {
+            Stage::Received => {}
+            Stage::Evaluated => self.evaluated_at = now,
+            Stage::Stored => self.stored_at = now,
+            Stage::Actioned => self.actioned_at = now,
+        }
+    }
+
+    /// Seconds from generation to each stage reached. Events stamped ahead
+    /// of the monitor's clock count as detected instantly.
+    pub fn latencies(&self) -> Vec<(Stage, f64)> {
+        [
+            (Stage::Received, Some(self.received_at)),
+            (Stage::Evaluated, self.evaluated_at),
+            (Stage::Stored, self.stored_at),
+            (Stage::Actioned, self.actioned_at),
+        ]
+        .into_iter()
+        .filter_map(|(stage, at)| Some((stage, seconds_between(self.generated_at, at?))))
+        .collect()
+    }
+
+    /// Seconds the monitor took from receiving the event to acting on it
+    pub fn response_time(&self) -> Option<f64> {
+        Some(seconds_between(self.received_at, self.actioned_at?))
+    }
+}
+
+fn seconds_between(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    (to - from)
+        .to_std()
+        .map(|elapsed| elapsed.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn timeline(generated_at: DateTime<Utc>, received_at: DateTime<Utc>) -> DetectionTimeline {
+        DetectionTimeline {
+            generated_at,
+            received_at,
+            evaluated_at: None,
+            stored_at: None,
+            actioned_at: None,
+        }
+    }
+
+    #[test]
+    fn latencies_cover_only_the_stages_reached() {
+        let generated = Utc::now() - Duration::seconds(10);
+        let mut timeline = timeline(generated, generated + Duration::milliseconds(1500));
+        timeline.evaluated_at = Some(generated + Duration::seconds(2));
+        timeline.actioned_at = Some(generated + Duration::seconds(4));
+
+        let latencies = timeline.latencies();
+        let stages: Vec<&str> = latencies.iter().map(|(stage, _)| stage.as_str()).collect();
+        assert_eq!(stages, ["received", "evaluated", "actioned"]);
+        assert_eq!(latencies[0].1, 1.5);
+        assert_eq!(latencies[1].1, 2.0);
+        assert_eq!(latencies[2].1, 4.0);
+        assert_eq!(timeline.response_time(), Some(2.5));
+    }
+
+    #[test]
+    fn events_from_ahead_of_the_clock_count_as_instant() {
+        let received = Utc::now();
+        let timeline = timeline(received + Duration::seconds(30), received);
+        assert_eq!(timeline.latencies(), vec![(Stage::Received, 0.0)]);
+        assert_eq!(timeline.response_time(), None);
+    }
+
+    #[test]
+    fn marking_records_each_stage_once_reached() {
+        let generated = Utc::now();
+        let mut timeline = timeline(generated, generated);
+        timeline.mark(Stage::Evaluated);
+        timeline.mark(Stage::Stored);
+        assert!(timeline.evaluated_at.is_some());
+        assert!(timeline.stored_at.is_some());
+        assert!(timeline.actioned_at.is_none());
+
+        // Receipt is fixed when the timeline starts
+        let received_at = timeline.received_at;
+        timeline.mark(Stage::Received);
+        assert_eq!(timeline.received_at, received_at);
+    }
+}
@@ -1,111 +1,259 @@
 use anyhow::Result;
 use axum::{
     extract::{Query, State, WebSocketUpgrade},
+    http::HeaderMap,
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Json, Router,
 };
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 use std::{net::SocketAddr, sync::Arc, time::Duration};
 use tokio::time::interval;
-use tower_http::cors::CorsLayer;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use uuid::Uuid;
 
+mod actions;
+mod backlog;
 mod config;
 mod ebpf;
+mod encryption;
+mod enforcement;
 mod events;
+mod exports;
 mod falco;
+mod fixtures;
+mod forwarding;
+mod latency;
 mod metrics;
 mod models;
+mod payloads;
 mod policies;
+mod profiles;
 mod quarantine;
+mod redaction;
+mod replay;
+mod sampling;
 mod storage;
+mod taxonomy;
+mod verification;
+mod watchdog;
 mod websocket;
 
 use crate::{
+    actions::ActionRegistry,
+    backlog::{Outcome, WriteBacklog},
     config::Config,
     ebpf::EbpfMonitor,
+    encryption::FieldCipher,
+    enforcement::GatewayEnforcer,
     events::{EventAggregator, SecurityEvent},
     falco::FalcoIntegration,
+    forwarding::SignalForwarder,
+    latency::{DetectionTimeline, Stage},
     metrics::MetricsCollector,
     models::*,
-    policies::PolicyEngine,
-    quarantine::QuarantineManager,
+    policies::{risk_score, PolicyEngine, DEFAULT_TIER},
+    profiles::{ProfileRegistry, DEFAULT_PROFILE},
+    quarantine::{satisfied_condition, QuarantineManager},
+    redaction::Redactor,
+    replay::ReplayManager,
+    sampling::{Decision, Sampler},
     storage::EventStore,
+    taxonomy::EventTypeRegistry,
+    verification::{Mismatch, OwnershipVerifier, VerificationMode},
+    watchdog::HostWatchdog,
     websocket::WebSocketManager,
 };
+use sandstorm_config::ConfigHandle;
+use sandstorm_metrics::{RemoteWriteConfig, RemoteWriter};
+use sandstorm_types::deletion::{DeletionRequest, RetainedRecord, ServiceDeletion};
+use sandstorm_types::provenance::RUN_ID_HEADER;
+use sandstorm_types::snapshot::TENANT_HEADER;
 
 #[derive(Clone)]
 struct AppState {
-    config: Arc<Config>,
+    config: ConfigHandle<Config>,
     event_store: Arc<EventStore>,
+    /// Event and alert writes waiting for the database
+    write_backlog: Arc<WriteBacklog>,
     policy_engine: Arc<PolicyEngine>,
+    profiles: Arc<ProfileRegistry>,
     quarantine_manager: Arc<QuarantineManager>,
+    gateway: Arc<GatewayEnforcer>,
+    verifier: Arc<OwnershipVerifier>,
+    redactor: Arc<Redactor>,
+    forwarder: Arc<SignalForwarder>,
     metrics_collector: Arc<MetricsCollector>,
     ws_manager: Arc<WebSocketManager>,
     event_aggregator: Arc<EventAggregator>,
+    sampler: Arc<Sampler>,
+    replays: Arc<ReplayManager>,
+    event_types: Arc<EventTypeRegistry>,
+    actions: Arc<ActionRegistry>,
     sandbox_monitors: Arc<DashMap<String, SandboxMonitor>>,
 }
 
 struct SandboxMonitor {
     sandbox_id: String,
+    run_id: Option<Uuid>,
     provider: String,
+    /// Policy tier whose sampling rules apply
+    tier: String,
+    profile: String,
+    /// The profile's sampling rules, which take precedence over the tier's
+    sampling: Vec<SamplingRule>,
     start_time: chrono::DateTime<chrono::Utc>,
-    ebpf_monitor: Option<EbpfMonitor>,
+    ebpf_monitor: Option<Arc<EbpfMonitor>>,
+    /// Programs the profile attaches, which a deep trace adds to
+    ebpf_programs: Vec<String>,
     falco_integration: Option<FalcoIntegration>,
+    /// Set while a rule's deep trace action has every program attached and
+    /// every event stored
+    deep_trace_until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl SandboxMonitor {
+    fn status(&self) -> MonitoringStatus {
+        MonitoringStatus {
+            sandbox_id: self.sandbox_id.clone(),
+            provider: self.provider.clone(),
+            tier: self.tier.clone(),
+            profile: self.profile.clone(),
+            start_time: self.start_time,
+            uptime_seconds: chrono::Utc::now()
+                .signed_duration_since(self.start_time)
+                .num_seconds() as u64,
+            ebpf_active: self.ebpf_monitor.is_some(),
+            falco_active: self.falco_integration.is_some(),
+            deep_trace_until: self.deep_trace_until,
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter("security_monitor=debug,tower_http=debug")
+    let log_shipping = sandstorm_logging::ShippingSettings::from_env("SECURITY_MONITOR")?;
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::new("security_monitor=debug,tower_http=debug"))
+        .with(tracing_subscriber::fmt::layer())
+        .with(log_shipping.map(|settings| sandstorm_logging::ShippingLayer::spawn(settings, "security-monitor")))
         .init();
 
     // Load configuration
-    let config = Arc::new(Config::from_env()?);
+    let config = Config::load().await?;
+    config.spawn_sighup_reloader();
     info!("Loaded configuration");
+    let startup = config.current();
 
     // Initialize storage
-    let event_store = Arc::new(EventStore::new(&config.database_url).await?);
+    let mut event_store = EventStore::new(&startup.database_url).await?;
+    if let Some(key) = &startup.event_encryption_key {
+        event_store = event_store.with_cipher(FieldCipher::from_base64(key)?);
+        info!("Event details and metadata are encrypted at rest");
+    }
+    let event_store = Arc::new(event_store);
     event_store.run_migrations().await?;
+    let interrupted = event_store.fail_interrupted_exports().await?;
+    if interrupted > 0 {
+        warn!("Marked {} legal exports interrupted by a restart as failed", interrupted);
+    }
     info!("Initialized event store");
 
     // Initialize components
     let policy_engine = Arc::new(PolicyEngine::new());
     let quarantine_manager = Arc::new(QuarantineManager::new());
     let metrics_collector = Arc::new(MetricsCollector::new());
+    let shared_metrics = metrics_collector.shared().clone();
+    let database_breaker = sandstorm_http::CircuitBreaker::new(
+        "postgres",
+        sandstorm_http::BreakerSettings::from_env("SECURITY_MONITOR_DB")?,
+    );
+    sandstorm_http::register_breakers(shared_metrics.registry(), std::slice::from_ref(&database_breaker))?;
+    let write_backlog = Arc::new(WriteBacklog::new(
+        database_breaker.clone(),
+        metrics_collector.deferred_writes(),
+    ));
     let ws_manager = Arc::new(WebSocketManager::new());
     let event_aggregator = Arc::new(EventAggregator::new());
+    let sampler = Arc::new(Sampler::new());
+    let event_types = Arc::new(EventTypeRegistry::new());
     let sandbox_monitors = Arc::new(DashMap::new());
 
+    // Load registered event types
+    event_types.load(event_store.list_event_types().await?);
+
     // Load default policies
     policy_engine.load_default_policies().await?;
 
     let state = AppState {
         config: config.clone(),
         event_store,
+        write_backlog,
         policy_engine,
+        profiles: Arc::new(ProfileRegistry::new()),
         quarantine_manager,
+        gateway: Arc::new(GatewayEnforcer::new()),
+        verifier: Arc::new(OwnershipVerifier::new()),
+        redactor: Arc::new(Redactor::new()),
+        forwarder: Arc::new(SignalForwarder::new()),
         metrics_collector,
         ws_manager,
         event_aggregator,
+        sampler,
+        replays: Arc::new(ReplayManager::new()),
+        event_types,
+        actions: Arc::new(ActionRegistry::new()),
         sandbox_monitors,
     };
 
+    // Disaster-recovery backups of the event database
+    let backups = sandstorm_backup::Backups::from_env(
+        "security-monitor",
+        "SECURITY_MONITOR",
+        Arc::new(sandstorm_backup::PgBackup::new(
+            state.event_store.pool().clone(),
+            storage::BACKUP_TABLES,
+        )),
+    )?;
+
     // Start background tasks
     tokio::spawn(metrics_task(state.clone()));
     tokio::spawn(aggregation_task(state.clone()));
+    tokio::spawn(sampling_task(state.clone()));
     tokio::spawn(cleanup_task(state.clone()));
+    tokio::spawn(release_task(state.clone()));
+    tokio::spawn(forwarding_task(state.clone()));
+    tokio::spawn(remote_write_task(state.clone()));
+    tokio::spawn(watchdog_task(state.clone()));
+    tokio::spawn(backlog_task(state.clone()));
+    if let Some(backups) = &backups {
+        sandstorm_backup::spawn_schedule(backups.clone(), "SECURITY_MONITOR");
+        info!("Backups enabled");
+    }
 
     // Build router
+    let http_security = sandstorm_http::HttpSecurity::from_env("SECURITY_MONITOR")?;
     let app = Router::new()
         // Event endpoints
         .route("/api/events", post(capture_event))
         .route("/api/events", get(list_events))
         .route("/api/events/aggregate", get(aggregate_events))
+        .route("/api/events/:id/reveal", post(reveal_event))
+        .route(
+            "/api/events/reevaluate",
+            post(reevaluate_events).get(list_reevaluations),
+        )
+        .route("/api/events/reevaluate/:id", get(get_reevaluation))
+
+        // Legal export endpoints
+        .route("/api/exports", post(create_export).get(list_exports))
+        .route("/api/exports/:id", get(download_export))
+        
+        // Data deletion
+        .route("/api/deletions", post(delete_data))
         
         // Policy endpoints
         .route("/api/policies", post(create_policy))
@@ -113,16 +261,34 @@ async fn main() -> Result<()> {
         .route("/api/policies/:id", get(get_policy))
         .route("/api/policies/:id", put(update_policy))
         .route("/api/policies/:id", delete(delete_policy))
+        .route("/api/policies/:id/test", post(test_policy))
+        .route("/api/actions", get(list_actions))
+        .route("/api/actions/runs", get(list_action_runs))
+        
+        // Event taxonomy endpoints
+        .route("/api/event-types", post(register_event_type))
+        .route("/api/event-types", get(list_event_types))
+        .route("/api/event-types/:name", delete(unregister_event_type))
         
         // Quarantine endpoints
         .route("/api/quarantine", post(quarantine_sandbox))
         .route("/api/quarantine/:id/release", post(release_quarantine))
+        .route("/api/quarantine/:id/approve", post(approve_release))
+        .route("/api/quarantine/:id/scan", post(report_scan))
         .route("/api/quarantine", get(list_quarantines))
         
         // Monitoring endpoints
+        .route("/api/monitor/profiles", get(list_profiles))
+        .route(
+            "/api/monitor/profiles/:name",
+            get(get_profile).put(put_profile).delete(delete_profile),
+        )
+        .route("/api/monitor/sandboxes", get(list_monitors))
+        .route("/api/monitor/sandboxes/stop", post(bulk_stop_monitoring))
         .route("/api/monitor/sandbox/:id/start", post(start_monitoring))
         .route("/api/monitor/sandbox/:id/stop", post(stop_monitoring))
         .route("/api/monitor/sandbox/:id/status", get(monitoring_status))
+        .route("/api/sandboxes/:id/posture", get(sandbox_posture))
         
         // Dashboard endpoints
         .route("/api/dashboard/metrics", get(get_metrics))
@@ -132,18 +298,43 @@ async fn main() -> Result<()> {
         // Health check
         .route("/health", get(health_check))
         
+        .with_state(state)
+        
+        // Shed requests while the database is down; events are still
+        // evaluated, and stored once it is back
+        .layer(axum::middleware::from_fn_with_state(
+            sandstorm_http::LoadShedding::new(database_breaker)
+                .exempt(axum::http::Method::POST, "/api/events"),
+            sandstorm_http::shed_load,
+        ))
+        
         // Metrics endpoint
-        .route("/metrics", get(prometheus_metrics))
+        .merge(sandstorm_metrics::metrics_router(shared_metrics.clone()))
+        
+        // Runtime configuration
+        .merge(sandstorm_config::admin_router(config, startup.admin_token.clone()))
+        
+        // Backup and restore
+        .merge(
+            backups
+                .map(|backups| sandstorm_backup::backup_router(backups, startup.admin_token.clone()))
+                .unwrap_or_default(),
+        )
         
-        .layer(CorsLayer::permissive())
-        .with_state(state);
+        .layer(axum::middleware::from_fn_with_state(shared_metrics, sandstorm_metrics::track_http));
+    let app = http_security.apply(app);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
+    let addr = SocketAddr::from(([0, 0, 0, 0], startup.port));
     info!("Starting security monitor on {}", addr);
 
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await?;
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    match sandstorm_tls::TlsSettings::from_env("SECURITY_MONITOR")? {
+        Some(tls) => {
+            info!("mTLS enabled");
+            sandstorm_tls::serve(listener, app, tls).await?;
+        }
+        None => axum::serve(listener, app).await?,
+    }
 
     Ok(())
 }
@@ -151,72 +342,487 @@ async fn main() -> Result<()> {
 // Event handlers
 async fn capture_event(
     State(state): State<AppState>,
-    Json(event): Json<SecurityEvent>,
+    headers: HeaderMap,
+    Json(mut event): Json<SecurityEvent>,
 ) -> Result<Json<EventResponse>, AppError> {
-    // Store event
-    let event_id = state.event_store.store_event(&event).await?;
-    
+    let timeline = DetectionTimeline::received(&event);
+    state.event_types.check(&event.event_type).map_err(AppError::BadRequest)?;
+    let flagged = verify_ownership(&state, &headers, &mut event).await?;
+
+    // Link the event to its gateway run: explicit field, then header, then
+    // the run the sandbox is being monitored under
+    if event.run_id.is_none() {
+        event.run_id = headers
+            .get(RUN_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+    }
+
+    let trace_id = sandstorm_metrics::trace_id(&headers);
+    ingest(&state, event, timeline, flagged, trace_id).await.map(Json)
+}
+
+/// Evaluate, store and act on an event, whether reported to the monitor or
+/// raised by it. Flagged events are stored but acted on by nothing.
+async fn ingest(
+    state: &AppState,
+    mut event: SecurityEvent,
+    mut timeline: DetectionTimeline,
+    flagged: bool,
+    trace_id: Option<String>,
+) -> Result<EventResponse, AppError> {
+    if event.run_id.is_none() {
+        event.run_id = state
+            .sandbox_monitors
+            .get(&event.sandbox_id)
+            .and_then(|monitor| monitor.run_id);
+    }
+
     // Update metrics
     state.metrics_collector.record_event(&event);
     
     // Evaluate policies
-    let evaluation = state.policy_engine.evaluate(&event).await?;
+    let mut evaluation = state.policy_engine.evaluate(&event).await?;
+    timeline.mark(Stage::Evaluated);
+
+    // Flagged events are kept in full for investigation, but nothing is done
+    // to a sandbox on their say-so
+    if flagged {
+        evaluation.action = "flagged".to_string();
+    }
+
+    // Policies saw the event as reported; nothing after this does
+    let redacted = state.redactor.redact(&state.config.current(), &mut event);
+    if redacted > 0 {
+        debug!("Redacted {} values of event {}", redacted, event.id);
+    }
+
+    // Only events no policy acts on are sampled, and none from sandboxes
+    // under a deep trace; the rest are kept in full
+    let deep_trace = state
+        .sandbox_monitors
+        .get(&event.sandbox_id)
+        .is_some_and(|monitor| monitor.deep_trace_until.is_some());
+    let decision = if evaluation.action == "allow" && !deep_trace {
+        let (tier, profile_rule) = state
+            .sandbox_monitors
+            .get(&event.sandbox_id)
+            .map(|monitor| {
+                let rule = monitor.sampling.iter().find(|rule| rule.event_type == event.event_type).cloned();
+                (monitor.tier.clone(), rule)
+            })
+            .unwrap_or_else(|| (DEFAULT_TIER.to_string(), None));
+        let rule = profile_rule.or_else(|| state.policy_engine.sampling_rule(&tier, &event.event_type));
+        state.sampler.decide(&event, rule.as_ref())
+    } else {
+        Decision::Store { window: None }
+    };
+
+    // Store event, or queue it while the database is down
+    let mut queued = false;
+    let (event_id, sampling) = match decision {
+        Decision::Store { window } => {
+            let (event_id, outcome) = state.write_backlog.store_event(&state.event_store, &event).await?;
+            match outcome {
+                Outcome::Written => timeline.mark(Stage::Stored),
+                Outcome::Queued => queued = true,
+            }
+            if let Some(window) = window {
+                state.sampler.opened(&window, &event_id);
+            }
+            (Some(event_id), None)
+        }
+        Decision::Aggregated { event_id } => {
+            state.metrics_collector.record_sampled_out(&event, "aggregated");
+            (event_id, Some("aggregated"))
+        }
+        Decision::SampledOut => {
+            state.metrics_collector.record_sampled_out(&event, "sampled");
+            (None, Some("sampled_out"))
+        }
+    };
+    state.event_store.conceal(&mut event);
     
     // Take action based on policy
+    if !flagged {
+        take_action(state, &event, &evaluation).await?;
+        actions::spawn(state, &event, event_id.clone(), evaluation.actions.clone());
+        timeline.mark(Stage::Actioned);
+        if !state.forwarder.record(&event, &evaluation.action) {
+            state.metrics_collector.record_signals_forwarded("dropped", 1);
+        }
+    }
+
+    let trace_id = trace_id.or_else(|| event.run_id.map(|run_id| run_id.to_string()));
+    state
+        .metrics_collector
+        .record_detection(&timeline, &evaluation.action, trace_id.as_deref());
+    
+    // Broadcast event to dashboard, unless sampling kept it out of storage
+    if sampling.is_none() {
+        state.ws_manager.broadcast_event(&event).await;
+    }
+
+    Ok(EventResponse {
+        event_id,
+        sampling,
+        queued,
+        action_taken: evaluation.action,
+        matched_rules: evaluation.matched_rules,
+        timeline,
+    })
+}
+
+/// Check an event's sandbox with the gateway that created it. Returns
+/// whether the event was flagged; rejected events fail with `Forbidden`.
+/// Events pass unchecked with verification off, without a gateway, or when
+/// the gateway can't answer.
+async fn verify_ownership(
+    state: &AppState,
+    headers: &HeaderMap,
+    event: &mut SecurityEvent,
+) -> Result<bool, AppError> {
+    let config = state.config.current();
+    let Some(gateway_url) = config.gateway_url.as_deref() else {
+        return Ok(false);
+    };
+    if config.event_verification == VerificationMode::Off {
+        return Ok(false);
+    }
+
+    let tenant = headers.get(TENANT_HEADER).and_then(|value| value.to_str().ok());
+    let mismatch = match state.verifier.verify(gateway_url, event, tenant).await {
+        Ok(Some(mismatch)) => mismatch,
+        Ok(None) => return Ok(false),
+        Err(e) => {
+            warn!("Could not verify sandbox {} of event {}: {:#}", event.sandbox_id, event.id, e);
+            return Ok(false);
+        }
+    };
+    let reason = mismatch.describe(event, tenant);
+    state
+        .metrics_collector
+        .record_unverified(&mismatch, config.event_verification);
+
+    if config.event_verification == VerificationMode::Reject {
+        warn!("Rejected event {}: {}", event.id, reason);
+        return Err(AppError::Forbidden(reason));
+    }
+    warn!("Flagged event {}: {}", event.id, reason);
+    flag(event, &mismatch, reason);
+    Ok(true)
+}
+
+/// Mark an event with why its sandbox didn't check out
+fn flag(event: &mut SecurityEvent, mismatch: &Mismatch, reason: String) {
+    let verification = serde_json::json!({
+        "status": mismatch.as_str(),
+        "reason": reason,
+    });
+    match event.metadata.as_mut().and_then(|metadata| metadata.as_object_mut()) {
+        Some(metadata) => {
+            metadata.insert("verification".to_string(), verification);
+        }
+        None => event.metadata = Some(serde_json::json!({ "verification": verification })),
+    }
+}
+
+/// Raise the alert or quarantine a policy evaluation calls for. False when
+/// the action has nothing to do after the fact, like `deny` or `allow`.
+async fn take_action(
+    state: &AppState,
+    event: &SecurityEvent,
+    evaluation: &PolicyEvaluation,
+) -> Result<bool> {
     match evaluation.action.as_str() {
         "quarantine" => {
             let record = state.quarantine_manager.quarantine(
                 &event.sandbox_id,
                 &evaluation.reason,
-                &event,
+                event,
+                evaluation.quarantine_mode.unwrap_or_default(),
+                release_conditions(state, evaluation.release_conditions.clone()),
             ).await?;
             
             warn!(
                 sandbox_id = %event.sandbox_id,
                 quarantine_id = %record.id,
+                mode = %record.mode,
                 "Sandbox quarantined"
             );
+            enforce_quarantine(state, &record).await;
+            Ok(true)
         }
         "alert" => {
-            state.ws_manager.broadcast_alert(Alert {
+            let alert = Alert {
                 id: Uuid::new_v4().to_string(),
-                severity: event.severity.clone(),
+                severity: event.severity,
                 message: event.message.clone(),
                 timestamp: chrono::Utc::now(),
                 sandbox_id: Some(event.sandbox_id.clone()),
                 acknowledged: false,
-            }).await;
+            };
+            state.write_backlog.store_alert(&state.event_store, &alert).await?;
+            state.ws_manager.broadcast_alert(alert).await;
+            Ok(true)
         }
-        _ => {}
+        _ => Ok(false),
     }
-    
-    // Broadcast event to dashboard
-    state.ws_manager.broadcast_event(&event).await;
-    
-    Ok(Json(EventResponse {
-        event_id,
-        action_taken: evaluation.action,
-        matched_rules: evaluation.matched_rules,
-    }))
 }
 
 async fn list_events(
     State(state): State<AppState>,
     Query(params): Query<EventQuery>,
 ) -> Result<Json<Vec<SecurityEvent>>, AppError> {
-    let events = state.event_store.list_events(params).await?;
+    let mut events = state.event_store.list_events(params).await?;
+    for event in &mut events {
+        state.event_store.conceal(event);
+    }
     Ok(Json(events))
 }
 
+/// An event's details and metadata as stored, decrypted if need be. Requires
+/// the admin token, and each reveal is logged with its actor and reason.
+async fn reveal_event(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    Json(request): Json<RevealRequest>,
+) -> Result<Json<SecurityEvent>, AppError> {
+    let actor = require_admin(&state, &headers, "revealing events")?;
+    if request.reason.trim().is_empty() {
+        return Err(AppError::BadRequest("a reason is required".to_string()));
+    }
+
+    let event = state
+        .event_store
+        .get_event(&id)
+        .await?
+        .ok_or(AppError::NotFound("Event not found".to_string()))?;
+    state.event_store.record_reveal(&id, actor, &request.reason).await?;
+    warn!(event_id = %id, actor = %actor, reason = %request.reason, "Event details revealed");
+    Ok(Json(event))
+}
+
+/// Check the admin token, returning the actor named in `X-Sandstorm-Actor`
+fn require_admin<'a>(state: &AppState, headers: &'a HeaderMap, action: &str) -> Result<&'a str, AppError> {
+    let Some(admin_token) = state.config.current().admin_token.clone() else {
+        return Err(AppError::Forbidden(format!("{} requires admin_token to be set", action)));
+    };
+    let presented = headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if presented != Some(admin_token.as_str()) {
+        return Err(AppError::Unauthorized);
+    }
+    Ok(headers
+        .get("x-sandstorm-actor")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("unknown"))
+}
+
+async fn reevaluate_events(
+    State(state): State<AppState>,
+    Json(request): Json<ReevaluateRequest>,
+) -> Result<(axum::http::StatusCode, Json<Reevaluation>), AppError> {
+    if request.end_time.is_some_and(|end_time| end_time < request.start_time) {
+        return Err(AppError::BadRequest("end_time is before start_time".to_string()));
+    }
+
+    let run = state.replays.start(request);
+    info!(
+        reevaluation_id = %run.id,
+        apply_actions = run.request.apply_actions,
+        "Re-evaluating stored events"
+    );
+    tokio::spawn(reevaluation_task(state.clone(), run.id.clone(), run.request.clone()));
+    Ok((axum::http::StatusCode::ACCEPTED, Json(run)))
+}
+
+async fn list_reevaluations(State(state): State<AppState>) -> Json<Vec<Reevaluation>> {
+    Json(state.replays.list())
+}
+
+async fn get_reevaluation(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> Result<Json<Reevaluation>, AppError> {
+    let run = state
+        .replays
+        .get(&id)
+        .ok_or(AppError::NotFound("Re-evaluation not found".to_string()))?;
+    Ok(Json(run))
+}
+
+/// Start a legal export. Requires the admin token and a reason, both kept
+/// with the export.
+async fn create_export(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<ExportRequest>,
+) -> Result<(axum::http::StatusCode, Json<LegalExport>), AppError> {
+    let requested_by = require_admin(&state, &headers, "exporting events")?.to_string();
+    let Some(key) = state.config.current().export_signing_key.clone() else {
+        return Err(AppError::Forbidden("exporting events requires export_signing_key to be set".to_string()));
+    };
+    if request.reason.trim().is_empty() {
+        return Err(AppError::BadRequest("a reason is required".to_string()));
+    }
+    let now = chrono::Utc::now();
+    let end_time = request.end_time.unwrap_or(now);
+    if request.start_time.is_some_and(|start_time| end_time < start_time) {
+        return Err(AppError::BadRequest("end_time is before start_time".to_string()));
+    }
+
+    let export = LegalExport {
+        id: Uuid::new_v4().to_string(),
+        status: ExportStatus::Running,
+        sandbox_id: request.sandbox_id,
+        start_time: request.start_time,
+        end_time,
+        requested_by,
+        reason: request.reason,
+        created_at: now,
+        finished_at: None,
+        records: 0,
+        head_hash: None,
+        signature: None,
+        error: None,
+    };
+    state.event_store.store_export(&export).await?;
+    warn!(
+        export_id = %export.id,
+        actor = %export.requested_by,
+        reason = %export.reason,
+        sandbox_id = ?export.sandbox_id,
+        "Legal export started"
+    );
+    tokio::spawn(export_task(state.clone(), export.clone(), key));
+    Ok((axum::http::StatusCode::ACCEPTED, Json(export)))
+}
+
+async fn export_task(state: AppState, export: LegalExport, key: String) {
+    let result = exports::build(&state.event_store, &export, key.as_bytes()).await;
+    match &result {
+        Ok(archive) => info!(export_id = %export.id, records = archive.records, "Legal export completed"),
+        Err(e) => error!(export_id = %export.id, "Legal export failed: {:#}", e),
+    }
+    if let Err(e) = state.event_store.finish_export(&export.id, &result).await {
+        error!(export_id = %export.id, "Failed to record legal export: {:#}", e);
+    }
+}
+
+/// Legal exports, newest first. Requires the admin token.
+async fn list_exports(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<LegalExport>>, AppError> {
+    require_admin(&state, &headers, "exporting events")?;
+    Ok(Json(state.event_store.list_exports().await?))
+}
+
+/// A completed export's archive, or `409` with the export while it's
+/// running or if it failed. Requires the admin token.
+async fn download_export(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> Result<axum::response::Response, AppError> {
+    let actor = require_admin(&state, &headers, "exporting events")?;
+    let export = state
+        .event_store
+        .get_export(&id)
+        .await?
+        .ok_or(AppError::NotFound("Export not found".to_string()))?;
+    if export.status != ExportStatus::Completed {
+        return Ok((axum::http::StatusCode::CONFLICT, Json(export)).into_response());
+    }
+    let archive = state
+        .event_store
+        .export_archive(&id)
+        .await?
+        .ok_or(AppError::NotFound("Export archive not found".to_string()))?;
+    warn!(export_id = %id, actor = %actor, "Legal export downloaded");
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE, "application/x-ndjson".to_string()),
+            (
+                axum::http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"sandstorm-export-{}.jsonl\"", id),
+            ),
+        ],
+        archive,
+    )
+        .into_response())
+}
+
+/// Delete what a data deletion request covers. Events and the rest are kept
+/// by sandbox only, so a tenant's sandboxes have to be named; exports of
+/// them are legal-hold packages and are kept and reported.
+async fn delete_data(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<DeletionRequest>,
+) -> Result<Json<ServiceDeletion>, AppError> {
+    let actor = require_admin(&state, &headers, "deleting data")?.to_string();
+    if request.is_empty() {
+        return Err(AppError::BadRequest("a deletion needs a tenant or sandbox_ids".to_string()));
+    }
+
+    let exports = state.event_store.list_exports().await?;
+    let covers = |export: &LegalExport| {
+        export.sandbox_id.as_ref().is_none_or(|sandbox_id| request.sandbox_ids.contains(sandbox_id))
+    };
+    if let Some(export) = exports
+        .iter()
+        .find(|export| export.status == ExportStatus::Running && covers(export))
+    {
+        return Err(AppError::Conflict(format!("export {} is still reading these sandboxes", export.id)));
+    }
+
+    let removed = state.event_store.delete_sandbox_data(&request.sandbox_ids).await?;
+    let retained = exports
+        .iter()
+        .filter(|export| {
+            export.status == ExportStatus::Completed
+                && export.sandbox_id.as_ref().is_some_and(|sandbox_id| request.sandbox_ids.contains(sandbox_id))
+        })
+        .map(|export| RetainedRecord {
+            kind: "export".to_string(),
+            id: export.id.clone(),
+            reason: "legal_hold".to_string(),
+        })
+        .collect();
+    warn!(
+        actor = %actor,
+        tenant = ?request.tenant,
+        sandboxes = request.sandbox_ids.len(),
+        removed = ?removed,
+        "Deleted data on request"
+    );
+
+    Ok(Json(ServiceDeletion {
+        service: "security-monitor".to_string(),
+        removed,
+        retained,
+        sandbox_ids: request.sandbox_ids,
+        completed_at: chrono::Utc::now(),
+    }))
+}
+
 async fn aggregate_events(
     State(state): State<AppState>,
     Query(params): Query<AggregationQuery>,
 ) -> Result<Json<AggregationResult>, AppError> {
-    let events = state.event_store.list_events(EventQuery {
+    let mut events = state.event_store.list_events(EventQuery {
         start_time: params.start_time,
         end_time: params.end_time,
         ..Default::default()
     }).await?;
+    for event in &mut events {
+        state.event_store.conceal(event);
+    }
     
     let result = state.event_aggregator.aggregate(
         &events,
@@ -231,6 +837,10 @@ async fn create_policy(
     State(state): State<AppState>,
     Json(policy): Json<SecurityPolicy>,
 ) -> Result<Json<PolicyResponse>, AppError> {
+    state.event_types.check_policy(&policy).map_err(AppError::BadRequest)?;
+    state.actions.check_policy(&policy).map_err(AppError::BadRequest)?;
+    check_policy_profile(&state, &policy)?;
+    check_policy_tests(&state, &policy)?;
     let policy_id = state.policy_engine.add_policy(policy).await?;
     Ok(Json(PolicyResponse { policy_id }))
 }
@@ -256,10 +866,88 @@ async fn update_policy(
     axum::extract::Path(id): axum::extract::Path<String>,
     Json(policy): Json<SecurityPolicy>,
 ) -> Result<Json<PolicyResponse>, AppError> {
+    state.event_types.check_policy(&policy).map_err(AppError::BadRequest)?;
+    state.actions.check_policy(&policy).map_err(AppError::BadRequest)?;
+    check_policy_profile(&state, &policy)?;
+    check_policy_tests(&state, &policy)?;
     state.policy_engine.update_policy(&id, policy).await?;
     Ok(Json(PolicyResponse { policy_id: id }))
 }
 
+/// Action kinds rules can use
+async fn list_actions(State(state): State<AppState>) -> Json<Vec<ActionKindInfo>> {
+    Json(state.actions.list())
+}
+
+async fn list_action_runs(
+    State(state): State<AppState>,
+    Query(params): Query<ActionRunQuery>,
+) -> Result<Json<Vec<ActionRun>>, AppError> {
+    let runs = state.event_store.list_action_runs(&params).await?;
+    Ok(Json(runs))
+}
+
+/// A policy can only name a profile that exists
+fn check_policy_profile(state: &AppState, policy: &SecurityPolicy) -> Result<(), AppError> {
+    match &policy.monitoring_profile {
+        Some(profile) if state.profiles.get(profile).is_none() => Err(AppError::BadRequest(format!(
+            "unknown monitoring profile {}",
+            profile
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// A policy is only saved if it passes its own tests
+fn check_policy_tests(state: &AppState, policy: &SecurityPolicy) -> Result<(), AppError> {
+    let report = run_policy_tests(state, policy, &policy.tests)?;
+    let failed: Vec<String> = report
+        .results
+        .into_iter()
+        .filter(|result| !result.passed)
+        .map(|result| format!("{} ({})", result.name, result.failures.join("; ")))
+        .collect();
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::BadRequest(format!("policy fails its tests: {}", failed.join(", "))))
+    }
+}
+
+fn run_policy_tests(
+    state: &AppState,
+    policy: &SecurityPolicy,
+    cases: &[PolicyTestCase],
+) -> Result<PolicyTestReport, AppError> {
+    for case in cases {
+        let event_type = match &case.event {
+            TestEvent::Fixture { fixture, .. } => fixture.event_type(),
+            TestEvent::Event(event) => event.event_type.clone(),
+        };
+        state
+            .event_types
+            .check(&event_type)
+            .map_err(|e| AppError::BadRequest(format!("test {}: {}", case.name, e)))?;
+    }
+    Ok(state.policy_engine.run_tests(policy, cases)?)
+}
+
+/// Run fixture events through a policy alone and check what it does about
+/// each. Runs the policy's own tests when the request has no cases.
+async fn test_policy(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    Json(request): Json<PolicyTestRequest>,
+) -> Result<Json<PolicyTestReport>, AppError> {
+    let policy = state.policy_engine.get_policy(&id).await?
+        .ok_or(AppError::NotFound("Policy not found".to_string()))?;
+    let cases = if request.cases.is_empty() { &policy.tests } else { &request.cases };
+    if cases.is_empty() {
+        return Err(AppError::BadRequest("policy has no tests; send some cases".to_string()));
+    }
+    Ok(Json(run_policy_tests(&state, &policy, cases)?))
+}
+
 async fn delete_policy(
     State(state): State<AppState>,
     axum::extract::Path(id): axum::extract::Path<String>,
@@ -268,6 +956,49 @@ async fn delete_policy(
     Ok(())
 }
 
+// Event taxonomy handlers
+async fn register_event_type(
+    State(state): State<AppState>,
+    Json(request): Json<EventTypeRequest>,
+) -> Result<Json<EventTypeInfo>, AppError> {
+    let info = state.event_types.register(request).map_err(AppError::BadRequest)?;
+    state.event_store.store_event_type(&info).await?;
+    Ok(Json(info))
+}
+
+async fn list_event_types(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<EventTypeInfo>>, AppError> {
+    Ok(Json(state.event_types.list()))
+}
+
+async fn unregister_event_type(
+    State(state): State<AppState>,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> Result<(), AppError> {
+    let event_type: EventType = name
+        .parse()
+        .map_err(|e: sandstorm_types::security::InvalidEventType| AppError::BadRequest(e.to_string()))?;
+    if let Some(policy) = state.policy_engine.policy_using(&event_type) {
+        return Err(AppError::BadRequest(format!(
+            "event type {} is used by policy {}",
+            event_type, policy
+        )));
+    }
+    if let Some(profile) = state.profiles.profile_using(&event_type) {
+        return Err(AppError::BadRequest(format!(
+            "event type {} is used by monitoring profile {}",
+            event_type, profile
+        )));
+    }
+    state
+        .event_types
+        .unregister(&event_type)
+        .ok_or(AppError::NotFound("Event type not registered".to_string()))?;
+    state.event_store.delete_event_type(&event_type).await?;
+    Ok(())
+}
+
 // Quarantine handlers
 async fn quarantine_sandbox(
     State(state): State<AppState>,
@@ -277,23 +1008,122 @@ async fn quarantine_sandbox(
         &request.sandbox_id,
         &request.reason,
         &request.triggering_event,
+        request.mode,
+        release_conditions(&state, request.release_conditions),
     ).await?;
+    enforce_quarantine(&state, &record).await;
     
     Ok(Json(record))
 }
 
+/// Release conditions for a new quarantine. With `quarantine_auto_release`
+/// set, quarantines that name none are released after
+/// `quarantine_max_duration_hours`.
+fn release_conditions(state: &AppState, conditions: Vec<ReleaseCondition>) -> Vec<ReleaseCondition> {
+    let config = state.config.current();
+    if conditions.is_empty() && config.quarantine_auto_release {
+        return vec![ReleaseCondition::TimeElapsed {
+            minutes: config.quarantine_max_duration_hours * 60,
+        }];
+    }
+    conditions
+}
+
+/// Release a quarantine regardless of its conditions
 async fn release_quarantine(
     State(state): State<AppState>,
     axum::extract::Path(id): axum::extract::Path<String>,
 ) -> Result<(), AppError> {
-    state.quarantine_manager.release(&id).await?;
+    let record = state.quarantine_manager.release(&id, None).await?
+        .ok_or(AppError::NotFound("Quarantine not found".to_string()))?;
+    lift_quarantine(&state, &record).await;
     Ok(())
 }
 
+async fn approve_release(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    Json(request): Json<ApprovalRequest>,
+) -> Result<Json<QuarantineRecord>, AppError> {
+    let record = state
+        .quarantine_manager
+        .approve(&id, &request.approved_by)
+        .ok_or(AppError::NotFound("Active quarantine not found".to_string()))?;
+    info!(quarantine_id = %id, approved_by = %request.approved_by, "Quarantine release approved");
+    Ok(Json(try_release(&state, record).await?))
+}
+
+async fn report_scan(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    Json(report): Json<ScanReport>,
+) -> Result<Json<QuarantineRecord>, AppError> {
+    let record = state
+        .quarantine_manager
+        .record_scan(&id, report.passed)
+        .ok_or(AppError::NotFound("Active quarantine not found".to_string()))?;
+    Ok(Json(try_release(&state, record).await?))
+}
+
+/// Release a quarantine if one of its conditions holds. Returns the record
+/// as it stands afterwards.
+async fn try_release(state: &AppState, record: QuarantineRecord) -> Result<QuarantineRecord> {
+    if !record.auto_release {
+        return Ok(record);
+    }
+    let Some(condition) = satisfied_condition(&record, &state.event_store, chrono::Utc::now()).await? else {
+        return Ok(record);
+    };
+    let Some(released) = state.quarantine_manager.release(&record.id, Some(condition)).await? else {
+        return Ok(record);
+    };
+    info!(
+        sandbox_id = %released.sandbox_id,
+        quarantine_id = %released.id,
+        condition = ?condition,
+        "Quarantine released"
+    );
+    lift_quarantine(state, &released).await;
+    Ok(released)
+}
+
+/// Have the gateway restore a released sandbox's access
+async fn lift_quarantine(state: &AppState, record: &QuarantineRecord) {
+    let Some(gateway_url) = state.config.current().gateway_url.clone() else {
+        return;
+    };
+    if let Err(e) = state.gateway.lift(&gateway_url, record).await {
+        error!(
+            sandbox_id = %record.sandbox_id,
+            quarantine_id = %record.id,
+            "Failed to lift quarantine at the gateway: {:#}",
+            e
+        );
+    }
+}
+
+/// Have the gateway enforce a quarantine's mode. Without a gateway
+/// configured the quarantine is only recorded.
+async fn enforce_quarantine(state: &AppState, record: &QuarantineRecord) {
+    let Some(gateway_url) = state.config.current().gateway_url.clone() else {
+        return;
+    };
+    if let Err(e) = state.gateway.enforce(&gateway_url, record).await {
+        error!(
+            sandbox_id = %record.sandbox_id,
+            quarantine_id = %record.id,
+            mode = %record.mode,
+            "Failed to enforce quarantine at the gateway: {:#}",
+            e
+        );
+    }
+}
+
 async fn list_quarantines(
     State(state): State<AppState>,
+    Query(params): Query<QuarantineQuery>,
 ) -> Result<Json<Vec<QuarantineRecord>>, AppError> {
-    let records = state.quarantine_manager.list_active().await?;
+    let records = state.quarantine_manager.list(&params).await?;
     Ok(Json(records))
 }
 
@@ -303,37 +1133,69 @@ async fn start_monitoring(
     axum::extract::Path(sandbox_id): axum::extract::Path<String>,
     Json(request): Json<MonitoringRequest>,
 ) -> Result<Json<MonitoringResponse>, AppError> {
+    let tier = request.tier.unwrap_or_else(|| DEFAULT_TIER.to_string());
+    // The request's profile, then the tier's, then the default
+    let profile_name = request
+        .profile
+        .or_else(|| state.policy_engine.profile_for_tier(&tier))
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+    let mut profile = state
+        .profiles
+        .get(&profile_name)
+        .ok_or_else(|| AppError::BadRequest(format!("unknown monitoring profile {}", profile_name)))?;
+    if let Some(programs) = request.ebpf_programs {
+        profiles::check_programs(&programs).map_err(AppError::BadRequest)?;
+        profile.ebpf_programs = programs;
+    }
+    if let Some(rules) = request.falco_rules {
+        profile.falco_rules.push(rules);
+    }
+
     let mut monitor = SandboxMonitor {
         sandbox_id: sandbox_id.clone(),
+        run_id: request.run_id,
         provider: request.provider,
+        tier,
+        profile: profile.name.clone(),
+        sampling: profile.sampling.clone(),
         start_time: chrono::Utc::now(),
         ebpf_monitor: None,
+        ebpf_programs: profile.ebpf_programs.clone(),
         falco_integration: None,
+        deep_trace_until: None,
     };
+    let config = state.config.current();
     
-    // Initialize eBPF monitoring if enabled
-    if state.config.ebpf_enabled {
+    // Initialize eBPF monitoring if enabled and the profile attaches anything
+    if config.ebpf_enabled && !profile.ebpf_programs.is_empty() {
         let ebpf = EbpfMonitor::new(&sandbox_id)?;
-        ebpf.attach_programs().await?;
-        monitor.ebpf_monitor = Some(ebpf);
+        ebpf.attach_programs(&profile.ebpf_programs).await?;
+        monitor.ebpf_monitor = Some(Arc::new(ebpf));
     }
     
     // Initialize Falco integration if enabled
-    if state.config.falco_enabled {
-        let falco = FalcoIntegration::new(&sandbox_id, &state.config.falco_rules_path)?;
+    if config.falco_enabled {
+        let rules = profiles::falco_rule_files(&config.falco_rules_path, &profile);
+        let falco = FalcoIntegration::new(&sandbox_id, rules)?;
         falco.start().await?;
         monitor.falco_integration = Some(falco);
     }
     
+    let monitors_active = [
+        monitor.ebpf_monitor.as_ref().map(|_| "ebpf"),
+        monitor.falco_integration.as_ref().map(|_| "falco"),
+    ]
+    .into_iter()
+    .flatten()
+    .map(String::from)
+    .collect();
     state.sandbox_monitors.insert(sandbox_id.clone(), monitor);
     
     Ok(Json(MonitoringResponse {
         sandbox_id,
         status: "monitoring".to_string(),
-        monitors_active: vec![
-            if state.config.ebpf_enabled { Some("ebpf") } else { None },
-            if state.config.falco_enabled { Some("falco") } else { None },
-        ].into_iter().flatten().map(String::from).collect(),
+        profile: profile.name,
+        monitors_active,
     }))
 }
 
@@ -341,19 +1203,141 @@ async fn stop_monitoring(
     State(state): State<AppState>,
     axum::extract::Path(sandbox_id): axum::extract::Path<String>,
 ) -> Result<(), AppError> {
-    if let Some((_, mut monitor)) = state.sandbox_monitors.remove(&sandbox_id) {
-        if let Some(ebpf) = monitor.ebpf_monitor.take() {
-            ebpf.detach_programs().await?;
-        }
-        
-        if let Some(falco) = monitor.falco_integration.take() {
-            falco.stop().await?;
-        }
+    stop_monitor(&state, &sandbox_id).await?;
+    Ok(())
+}
+
+/// Detach a sandbox's probes and forget it. Returns whether it was
+/// monitored; stopping an unmonitored sandbox is a no-op.
+async fn stop_monitor(state: &AppState, sandbox_id: &str) -> anyhow::Result<bool> {
+    state.sampler.forget(sandbox_id);
+    state.verifier.forget(sandbox_id);
+    let Some((_, mut monitor)) = state.sandbox_monitors.remove(sandbox_id) else {
+        return Ok(false);
+    };
+    if let Some(ebpf) = monitor.ebpf_monitor.take() {
+        ebpf.detach_programs().await?;
     }
-    
+    if let Some(falco) = monitor.falco_integration.take() {
+        falco.stop().await?;
+    }
+    Ok(true)
+}
+
+async fn list_profiles(State(state): State<AppState>) -> Json<Vec<MonitoringProfile>> {
+    Json(state.profiles.list())
+}
+
+async fn get_profile(
+    State(state): State<AppState>,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> Result<Json<MonitoringProfile>, AppError> {
+    let profile = state
+        .profiles
+        .get(&name)
+        .ok_or(AppError::NotFound("Monitoring profile not found".to_string()))?;
+    Ok(Json(profile))
+}
+
+/// Create or replace a profile. Sandboxes already monitored keep the
+/// profile they started with.
+async fn put_profile(
+    State(state): State<AppState>,
+    axum::extract::Path(name): axum::extract::Path<String>,
+    Json(mut profile): Json<MonitoringProfile>,
+) -> Result<Json<MonitoringProfile>, AppError> {
+    profile.name = name;
+    profiles::check_programs(&profile.ebpf_programs).map_err(AppError::BadRequest)?;
+    profile
+        .sampling
+        .iter()
+        .try_for_each(|rule| state.event_types.check(&rule.event_type))
+        .map_err(AppError::BadRequest)?;
+    state.profiles.upsert(profile.clone());
+    Ok(Json(profile))
+}
+
+async fn delete_profile(
+    State(state): State<AppState>,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> Result<(), AppError> {
+    if name == DEFAULT_PROFILE {
+        return Err(AppError::BadRequest(format!(
+            "{} is the default monitoring profile",
+            name
+        )));
+    }
+    if let Some(policy) = state.policy_engine.policy_with_profile(&name) {
+        return Err(AppError::BadRequest(format!(
+            "monitoring profile {} is used by policy {}",
+            name, policy
+        )));
+    }
+    state
+        .profiles
+        .remove(&name)
+        .ok_or(AppError::NotFound("Monitoring profile not found".to_string()))?;
     Ok(())
 }
 
+/// Monitored sandboxes matching the filters, longest-monitored first
+async fn list_monitors(
+    State(state): State<AppState>,
+    Query(query): Query<MonitorQuery>,
+) -> Json<Vec<MonitoringStatus>> {
+    let mut monitors: Vec<MonitoringStatus> = state
+        .sandbox_monitors
+        .iter()
+        .map(|entry| entry.value().status())
+        .filter(|status| query.matches(status))
+        .collect();
+    monitors.sort_by(|a, b| a.start_time.cmp(&b.start_time).then_with(|| a.sandbox_id.cmp(&b.sandbox_id)));
+    Json(monitors)
+}
+
+/// Stop monitoring the listed sandboxes and those matching the filters
+async fn bulk_stop_monitoring(
+    State(state): State<AppState>,
+    Json(request): Json<BulkStopRequest>,
+) -> Result<Json<BulkStopResponse>, AppError> {
+    if request.sandbox_ids.is_empty() && request.filter.is_empty() {
+        return Err(AppError::BadRequest(
+            "sandbox_ids or a filter is required".to_string(),
+        ));
+    }
+
+    let mut targets = request.sandbox_ids;
+    if !request.filter.is_empty() {
+        targets.extend(
+            state
+                .sandbox_monitors
+                .iter()
+                .filter(|entry| request.filter.matches(&entry.value().status()))
+                .map(|entry| entry.key().clone()),
+        );
+    }
+    targets.sort();
+    targets.dedup();
+
+    let mut response = BulkStopResponse::default();
+    for sandbox_id in targets {
+        match stop_monitor(&state, &sandbox_id).await {
+            Ok(true) => response.stopped.push(sandbox_id),
+            Ok(false) => {}
+            Err(e) => response.failed.push(StopFailure {
+                error: format!("{:#}", e),
+                sandbox_id,
+            }),
+        }
+    }
+    info!(
+        stopped = response.stopped.len(),
+        failed = response.failed.len(),
+        "Bulk stopped sandbox monitors"
+    );
+    Ok(Json(response))
+}
+
 async fn monitoring_status(
     State(state): State<AppState>,
     axum::extract::Path(sandbox_id): axum::extract::Path<String>,
@@ -361,15 +1345,47 @@ async fn monitoring_status(
     let monitor = state.sandbox_monitors.get(&sandbox_id)
         .ok_or(AppError::NotFound("Monitor not found".to_string()))?;
     
-    Ok(Json(MonitoringStatus {
-        sandbox_id: monitor.sandbox_id.clone(),
-        provider: monitor.provider.clone(),
-        start_time: monitor.start_time,
-        uptime_seconds: chrono::Utc::now()
-            .signed_duration_since(monitor.start_time)
-            .num_seconds() as u64,
-        ebpf_active: monitor.ebpf_monitor.is_some(),
-        falco_active: monitor.falco_integration.is_some(),
+    Ok(Json(monitor.status()))
+}
+
+async fn sandbox_posture(
+    State(state): State<AppState>,
+    axum::extract::Path(sandbox_id): axum::extract::Path<String>,
+    Query(params): Query<PostureQuery>,
+) -> Result<Json<SandboxPosture>, AppError> {
+    let window_hours = params.window_hours.unwrap_or(24);
+    let since = chrono::Utc::now() - chrono::Duration::hours(window_hours as i64);
+
+    let (recent_events, open_alerts) = tokio::try_join!(
+        state.event_store.count_events_by_severity(&sandbox_id, since),
+        state.event_store.list_alerts(AlertQuery {
+            sandbox_id: Some(sandbox_id.clone()),
+            acknowledged: Some(false),
+            ..Default::default()
+        }),
+    )?;
+    let quarantine = state
+        .quarantine_manager
+        .list(&QuarantineQuery {
+            sandbox_id: Some(sandbox_id.clone()),
+            ..Default::default()
+        })
+        .await?
+        .into_iter()
+        .next();
+
+    Ok(Json(SandboxPosture {
+        monitoring: state
+            .sandbox_monitors
+            .get(&sandbox_id)
+            .map(|monitor| monitor.status()),
+        active_rules: state.policy_engine.active_rules(),
+        window_hours,
+        risk_score: risk_score(&recent_events, open_alerts.len(), quarantine.is_some()),
+        recent_events,
+        open_alerts,
+        quarantine,
+        sandbox_id,
     }))
 }
 
@@ -405,12 +1421,6 @@ async fn health_check() -> &'static str {
     "OK"
 }
 
-async fn prometheus_metrics(
-    State(state): State<AppState>,
-) -> Result<String, AppError> {
-    Ok(state.metrics_collector.export_prometheus())
-}
-
 // Background tasks
 async fn metrics_task(state: AppState) {
     let mut interval = interval(Duration::from_secs(60));
@@ -439,6 +1449,212 @@ async fn aggregation_task(state: AppState) {
     }
 }
 
+/// Write the repeat counts of closed aggregation windows
+async fn sampling_task(state: AppState) {
+    let mut interval = interval(Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+
+        for flush in state.sampler.expired(chrono::Utc::now()) {
+            if let Err(e) = state
+                .event_store
+                .record_occurrences(&flush.event_id, flush.occurrences, flush.last_seen)
+                .await
+            {
+                error!("Failed to record repeats of event {}: {}", flush.event_id, e);
+            }
+        }
+    }
+}
+
+/// Forward incident summaries to the telemetry collector, one batch per
+/// interval at most
+async fn forwarding_task(state: AppState) {
+    loop {
+        let config = state.config.current();
+        tokio::time::sleep(Duration::from_secs(config.telemetry_forward_interval_secs)).await;
+
+        let (Some(telemetry_url), Some(signing_key)) = (&config.telemetry_url, &config.telemetry_signing_key) else {
+            continue;
+        };
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, signing_key.as_bytes());
+        let (signals, result) = state
+            .forwarder
+            .flush(telemetry_url, &key, config.telemetry_max_signals)
+            .await;
+        match result {
+            Ok(()) if signals == 0 => {}
+            Ok(()) => {
+                state.metrics_collector.record_signals_forwarded("sent", signals as u64);
+                debug!(signals, pending = state.forwarder.pending(), "Forwarded security signals");
+            }
+            Err(e) => {
+                state.metrics_collector.record_signals_forwarded("failed", signals as u64);
+                warn!("Failed to forward {} security signals: {}", signals, e);
+            }
+        }
+    }
+}
+
+/// Write events and alerts queued while the database was down
+async fn backlog_task(state: AppState) {
+    let mut interval = interval(Duration::from_secs(5));
+
+    loop {
+        interval.tick().await;
+        let open = state.write_backlog.breaker().state() == sandstorm_http::BreakerState::Open;
+        if state.write_backlog.is_empty() || open {
+            continue;
+        }
+
+        let written = state.write_backlog.flush(&state.event_store).await;
+        if written > 0 {
+            info!(written, queued = state.write_backlog.len(), "Wrote queued events and alerts");
+        }
+    }
+}
+
+/// Scan the host for sandbox escapes while the watchdog is enabled, and
+/// raise an event for each one found
+async fn watchdog_task(state: AppState) {
+    let watchdog = Arc::new(std::sync::Mutex::new(HostWatchdog::new()));
+    loop {
+        let config = state.config.current();
+        tokio::time::sleep(Duration::from_secs(config.host_watchdog_interval_secs)).await;
+        if !config.host_watchdog_enabled {
+            continue;
+        }
+
+        let scan = {
+            let watchdog = watchdog.clone();
+            let state_dirs = config.host_watchdog_state_dirs.clone();
+            let audit_log = config.host_watchdog_audit_log.clone();
+            tokio::task::spawn_blocking(move || match watchdog.lock() {
+                Ok(mut watchdog) => watchdog.scan(&state_dirs, audit_log.as_deref().map(std::path::Path::new)),
+                Err(_) => Vec::new(),
+            })
+        };
+        let findings = match scan.await {
+            Ok(findings) => findings,
+            Err(e) => {
+                error!("Host watchdog scan failed: {}", e);
+                continue;
+            }
+        };
+
+        for finding in findings {
+            let event = finding.into_event(None);
+            error!(sandbox_id = %event.sandbox_id, "{}", event.message);
+            let timeline = DetectionTimeline::received(&event);
+            if let Err(e) = ingest(&state, event, timeline, false, None).await {
+                error!("Failed to record sandbox escape event: {}", e);
+            }
+        }
+    }
+}
+
+/// Push metrics to the configured remote-write endpoint, if any
+async fn remote_write_task(state: AppState) {
+    let writer = RemoteWriter::new(state.metrics_collector.shared());
+    loop {
+        let config = state.config.current();
+        tokio::time::sleep(Duration::from_secs(config.remote_write_interval_secs)).await;
+
+        let Some(url) = &config.remote_write_url else {
+            continue;
+        };
+        let outcome = writer
+            .push(&RemoteWriteConfig {
+                url: url.clone(),
+                bearer_token: config.remote_write_token.clone(),
+                max_samples_per_request: config.remote_write_max_samples,
+                max_retries: config.remote_write_max_retries,
+            })
+            .await;
+        match outcome.error {
+            None => debug!(samples = outcome.sent, "Pushed metrics"),
+            Some(e) => warn!(
+                "Failed to push {} of {} metric samples: {}",
+                outcome.failed,
+                outcome.sent + outcome.failed,
+                e
+            ),
+        }
+    }
+}
+
+/// Events read from storage at a time while re-evaluating
+const REEVALUATION_PAGE_SIZE: u32 = 500;
+
+/// Run the current policies over the stored events of a re-evaluation
+async fn reevaluation_task(state: AppState, id: String, request: ReevaluateRequest) {
+    let result = reevaluate(&state, &id, &request).await;
+    match &result {
+        Ok(()) => info!(reevaluation_id = %id, "Re-evaluation completed"),
+        Err(e) => error!(reevaluation_id = %id, "Re-evaluation failed: {:#}", e),
+    }
+    state.replays.finish(&id, result);
+}
+
+async fn reevaluate(state: &AppState, id: &str, request: &ReevaluateRequest) -> Result<()> {
+    let mut offset = 0;
+    loop {
+        let events = state
+            .event_store
+            .list_events(EventQuery {
+                sandbox_id: request.sandbox_id.clone(),
+                event_type: request.event_type.clone(),
+                start_time: Some(request.start_time),
+                end_time: request.end_time,
+                limit: Some(REEVALUATION_PAGE_SIZE),
+                offset: Some(offset),
+                ..Default::default()
+            })
+            .await?;
+
+        let scanned = events.len();
+        for mut event in events {
+            let evaluation = state.policy_engine.evaluate(&event).await?;
+            if evaluation.action == "allow" {
+                continue;
+            }
+            state.event_store.conceal(&mut event);
+
+            // Sandboxes already in quarantine aren't quarantined again
+            let applied = request.apply_actions
+                && !(evaluation.action == "quarantine"
+                    && state.quarantine_manager.is_quarantined(&event.sandbox_id).await)
+                && take_action(state, &event, &evaluation).await?;
+            if request.apply_actions {
+                actions::spawn(state, &event, Some(event.id.clone()), evaluation.actions.clone());
+            }
+            state.replays.matched(id, &event, &evaluation, applied);
+        }
+        state.replays.scanned(id, scanned as u64);
+
+        if scanned < REEVALUATION_PAGE_SIZE as usize {
+            return Ok(());
+        }
+        offset += REEVALUATION_PAGE_SIZE;
+    }
+}
+
+/// Release quarantines whose conditions have come to hold
+async fn release_task(state: AppState) {
+    let mut interval = interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+
+        for record in state.quarantine_manager.auto_releasable() {
+            if let Err(e) = try_release(&state, record).await {
+                error!("Failed to evaluate quarantine release conditions: {:#}", e);
+            }
+        }
+    }
+}
+
 async fn cleanup_task(state: AppState) {
     let mut interval = interval(Duration::from_secs(3600)); // 1 hour
     
@@ -452,20 +1668,41 @@ async fn cleanup_task(state: AppState) {
             Ok(count) => info!("Cleaned up {} old events", count),
             Err(e) => error!("Failed to cleanup events: {}", e),
         }
-        
-        // Check for stale sandbox monitors
-        let stale_threshold = chrono::Utc::now() - chrono::Duration::hours(24);
-        let mut to_remove = Vec::new();
-        
-        for entry in state.sandbox_monitors.iter() {
-            if entry.value().start_time < stale_threshold {
-                to_remove.push(entry.key().clone());
-            }
+
+        match state.event_store.cleanup_old_action_runs(30).await {
+            Ok(count) => info!("Cleaned up {} old action runs", count),
+            Err(e) => error!("Failed to cleanup action runs: {}", e),
+        }
+
+        // Forget day-old re-evaluation reports
+        let count = state.replays.cleanup(24);
+        if count > 0 {
+            info!("Cleaned up {} re-evaluation reports", count);
         }
         
-        for sandbox_id in to_remove {
-            warn!("Removing stale monitor for sandbox {}", sandbox_id);
-            state.sandbox_monitors.remove(&sandbox_id);
+        // The gateway reports destroyed sandboxes as they go; catch the
+        // reports that were lost by asking it about every monitored sandbox
+        if let Some(gateway_url) = state.config.current().gateway_url.clone() {
+            let monitored: Vec<String> = state
+                .sandbox_monitors
+                .iter()
+                .map(|entry| entry.key().clone())
+                .collect();
+            for sandbox_id in monitored {
+                match state.gateway.sandbox_exists(&gateway_url, &sandbox_id).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        warn!("Stopping monitor for sandbox {}, which the gateway no longer has", sandbox_id);
+                        if let Err(e) = stop_monitor(&state, &sandbox_id).await {
+                            error!("Failed to stop monitor for sandbox {}: {:#}", sandbox_id, e);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Failed to check sandbox {} with the gateway: {:#}", sandbox_id, e);
+                        break;
+                    }
+                }
+            }
         }
     }
 }
@@ -473,8 +1710,20 @@ async fn cleanup_task(state: AppState) {
 // Error handling
 #[derive(Debug, thiserror::Error)]
 enum AppError {
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+
+    #[error("Unauthorized")]
+    Unauthorized,
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
     #[error("Not found: {0}")]
     NotFound(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
     
     #[error("Database error: {0}")]
     Database(#[from] sqlx::Error),
@@ -485,11 +1734,32 @@ enum AppError {
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
+        let database_down = match &self {
+            AppError::Database(e) => storage::connection_failed(e),
+            AppError::Internal(e) => storage::unreachable(e),
+            _ => false,
+        };
         let (status, message) = match self {
+            AppError::BadRequest(msg) => (
+                axum::http::StatusCode::BAD_REQUEST,
+                msg,
+            ),
+            AppError::Unauthorized => (
+                axum::http::StatusCode::UNAUTHORIZED,
+                "Unauthorized".to_string(),
+            ),
+            AppError::Forbidden(msg) => (
+                axum::http::StatusCode::FORBIDDEN,
+                msg,
+            ),
             AppError::NotFound(msg) => (
                 axum::http::StatusCode::NOT_FOUND,
                 msg,
             ),
+            AppError::Conflict(msg) => (
+                axum::http::StatusCode::CONFLICT,
+                msg,
+            ),
             AppError::Database(e) => (
                 axum::http::StatusCode::INTERNAL_SERVER_ERROR,
                 format!("Database error: {}", e),
@@ -500,6 +1770,11 @@ impl IntoResponse for AppError {
             ),
         };
         
-        (status, Json(serde_json::json!({ "error": message }))).into_response()
+        let mut response = (status, Json(serde_json::json!({ "error": message }))).into_response();
+        if database_down {
+            // Counts against the database breaker in `shed_load`
+            response.extensions_mut().insert(sandstorm_http::DependencyFailed);
+        }
+        response
     }
 }
\ No newline at end of file
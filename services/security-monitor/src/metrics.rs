@@ -1,133 +1,206 @@
 use anyhow::Result;
-use prometheus::{Counter, Gauge, Histogram, Registry, Encoder, TextEncoder};
+use prometheus::core::Collector;
+use prometheus::{CounterVec, GaugeVec};
+use sandstorm_metrics::{ExemplarHistogram, Metrics};
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
 
+use crate::latency::{DetectionTimeline, Stage};
 use crate::models::*;
+use crate::verification::{Mismatch, VerificationMode};
+
+/// Buckets for detection latency, which includes the time an event spends
+/// in Falco or eBPF pipelines before it arrives
+const DETECTION_BUCKETS: &[f64] = &[
+    0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0,
+];
 
 pub struct MetricsCollector {
-    registry: Registry,
-    events_total: Counter,
-    events_by_type: Arc<RwLock<HashMap<String, Counter>>>,
-    events_by_severity: Arc<RwLock<HashMap<String, Counter>>>,
-    quarantined_sandboxes: Gauge,
-    active_monitors: Gauge,
-    policy_violations: Counter,
-    response_time: Histogram,
+    shared: Metrics,
+    events_total: CounterVec,
+    events_sampled_out: CounterVec,
+    quarantined_sandboxes: GaugeVec,
+    active_monitors: GaugeVec,
+    policy_violations: CounterVec,
+    response_time: ExemplarHistogram,
+    detection_latency: ExemplarHistogram,
+    signals_forwarded: CounterVec,
+    events_unverified: CounterVec,
+    rule_actions: CounterVec,
+    rule_action_attempts: CounterVec,
+    deferred_writes: CounterVec,
 }
 
 impl MetricsCollector {
     pub fn new() -> Self {
-        let registry = Registry::new();
-        
-        let events_total = Counter::new(
+        let shared = Metrics::new("security-monitor");
+
+        let events_total = shared.counter(
             "security_events_total",
-            "Total number of security events processed"
-        ).unwrap();
-        
-        let quarantined_sandboxes = Gauge::new(
-            "quarantined_sandboxes",
-            "Number of currently quarantined sandboxes"
-        ).unwrap();
-        
-        let active_monitors = Gauge::new(
-            "active_monitors",
-            "Number of active sandbox monitors"
-        ).unwrap();
-        
-        let policy_violations = Counter::new(
-            "policy_violations_total",
-            "Total number of policy violations"
-        ).unwrap();
-        
-        let response_time = Histogram::with_opts(
-            prometheus::HistogramOpts::new(
-                "security_response_time_seconds",
-                "Time taken to process security events"
-            ).buckets(vec![0.001, 0.01, 0.1, 1.0, 10.0])
-        ).unwrap();
-
-        registry.register(Box::new(events_total.clone())).unwrap();
-        registry.register(Box::new(quarantined_sandboxes.clone())).unwrap();
-        registry.register(Box::new(active_monitors.clone())).unwrap();
-        registry.register(Box::new(policy_violations.clone())).unwrap();
-        registry.register(Box::new(response_time.clone())).unwrap();
+            "Total number of security events processed",
+            &["event_type", "severity"],
+        );
+
+        let events_sampled_out = shared.counter(
+            "security_events_sampled_out_total",
+            "Events not stored as records of their own, by sampling mode",
+            &["event_type", "mode"],
+        );
+
+        let quarantined_sandboxes = shared.gauge(
+            "security_quarantined_sandboxes",
+            "Number of currently quarantined sandboxes",
+            &[],
+        );
+
+        let active_monitors = shared.gauge(
+            "security_active_monitors",
+            "Number of active sandbox monitors",
+            &[],
+        );
+
+        let policy_violations = shared.counter(
+            "security_policy_violations_total",
+            "Total number of policy violations",
+            &[],
+        );
+
+        let response_time = shared.histogram(
+            "security_response_time_seconds",
+            "Time taken to process security events",
+            &["action"],
+            sandstorm_metrics::LATENCY_BUCKETS.to_vec(),
+        );
+
+        let detection_latency = shared.histogram(
+            "security_detection_latency_seconds",
+            "Time from an event being generated in the sandbox to each stage of handling it",
+            &["action", "stage"],
+            DETECTION_BUCKETS.to_vec(),
+        );
+
+        let signals_forwarded = shared.counter(
+            "security_signals_forwarded_total",
+            "Per-sandbox incident summaries forwarded to the telemetry collector",
+            &["outcome"], // outcome: sent, failed; dropped counts events past the pending limit
+        );
+
+        let events_unverified = shared.counter(
+            "security_events_unverified_total",
+            "Events whose sandbox the gateway didn't know, or knew under another tenant or runtime",
+            &["reason", "mode"],
+        );
+
+        let rule_actions = shared.counter(
+            "security_rule_actions_total",
+            "Runs of rules' follow-up actions, by kind and final status",
+            &["action", "status"],
+        );
+
+        let rule_action_attempts = shared.counter(
+            "security_rule_action_attempts_total",
+            "Attempts at rules' follow-up actions, counting retries",
+            &["action"],
+        );
+
+        let deferred_writes = shared.counter(
+            "security_deferred_writes_total",
+            "Event and alert writes held back while the database was down, by what became of them",
+            &["kind", "outcome"], // outcome: queued, written, dropped
+        );
 
         Self {
-            registry,
+            shared,
             events_total,
-            events_by_type: Arc::new(RwLock::new(HashMap::new())),
-            events_by_severity: Arc::new(RwLock::new(HashMap::new())),
+            events_sampled_out,
             quarantined_sandboxes,
             active_monitors,
             policy_violations,
             response_time,
+            detection_latency,
+            signals_forwarded,
+            events_unverified,
+            rule_actions,
+            rule_action_attempts,
+            deferred_writes,
         }
     }
 
+    /// Registry behind `/metrics` and the HTTP middleware
+    pub fn shared(&self) -> &Metrics {
+        &self.shared
+    }
+
+    /// Counter the write backlog tallies its writes in
+    pub fn deferred_writes(&self) -> CounterVec {
+        self.deferred_writes.clone()
+    }
+
     pub fn record_event(&self, event: &SecurityEvent) {
-        self.events_total.inc();
-        
-        // Record event type
-        tokio::spawn({
-            let event_type = event.event_type.clone();
-            let events_by_type = self.events_by_type.clone();
-            let registry = self.registry.clone();
-            
-            async move {
-                let mut counters = events_by_type.write().await;
-                if !counters.contains_key(&event_type) {
-                    let counter = Counter::new(
-                        format!("security_events_by_type_{}", event_type),
-                        format!("Number of {} events", event_type)
-                    ).unwrap();
-                    registry.register(Box::new(counter.clone())).unwrap();
-                    counters.insert(event_type.clone(), counter);
-                }
-                if let Some(counter) = counters.get(&event_type) {
-                    counter.inc();
-                }
-            }
-        });
-
-        // Record severity
-        tokio::spawn({
-            let severity = event.severity.clone();
-            let events_by_severity = self.events_by_severity.clone();
-            let registry = self.registry.clone();
-            
-            async move {
-                let mut counters = events_by_severity.write().await;
-                if !counters.contains_key(&severity) {
-                    let counter = Counter::new(
-                        format!("security_events_by_severity_{}", severity),
-                        format!("Number of {} severity events", severity)
-                    ).unwrap();
-                    registry.register(Box::new(counter.clone())).unwrap();
-                    counters.insert(severity.clone(), counter);
-                }
-                if let Some(counter) = counters.get(&severity) {
-                    counter.inc();
-                }
-            }
-        });
+        self.events_total
+            .with_label_values(&[event.event_type.as_str(), event.severity.as_str()])
+            .inc();
+    }
+
+    /// Count an event that was aggregated into another record or dropped
+    pub fn record_sampled_out(&self, event: &SecurityEvent, mode: &str) {
+        self.events_sampled_out
+            .with_label_values(&[event.event_type.as_str(), mode])
+            .inc();
     }
 
     pub fn record_policy_violation(&self) {
-        self.policy_violations.inc();
+        self.policy_violations.with_label_values(&[]).inc();
+    }
+
+    /// Record how long an event took to reach each stage, and how long the
+    /// monitor took to act on it, linked to its trace
+    pub fn record_detection(&self, timeline: &DetectionTimeline, action: &str, trace_id: Option<&str>) {
+        for (stage, latency) in timeline.latencies() {
+            self.detection_latency
+                .observe(&[action, stage.as_str()], latency, trace_id);
+        }
+        if let Some(response_time) = timeline.response_time() {
+            self.response_time.observe(&[action], response_time, trace_id);
+        }
     }
 
-    pub fn record_response_time(&self, duration: f64) {
-        self.response_time.observe(duration);
+    /// Count incident summaries sent to or refused by the telemetry
+    /// collector, or events dropped before they were summarized
+    pub fn record_signals_forwarded(&self, outcome: &str, count: u64) {
+        self.signals_forwarded
+            .with_label_values(&[outcome])
+            .inc_by(count as f64);
+    }
+
+    /// Count an event that failed verification, by why and by whether it
+    /// was flagged or rejected
+    pub fn record_unverified(&self, mismatch: &Mismatch, mode: VerificationMode) {
+        let mode = match mode {
+            VerificationMode::Off => "off",
+            VerificationMode::Flag => "flag",
+            VerificationMode::Reject => "reject",
+        };
+        self.events_unverified
+            .with_label_values(&[mismatch.as_str(), mode])
+            .inc();
+    }
+
+    /// Count a finished run of a rule's action and the attempts it took
+    pub fn record_action(&self, run: &ActionRun) {
+        self.rule_actions
+            .with_label_values(&[run.action.as_str(), run.status.as_str()])
+            .inc();
+        self.rule_action_attempts
+            .with_label_values(&[run.action.as_str()])
+            .inc_by(run.attempts as f64);
     }
 
     pub fn set_quarantined_count(&self, count: f64) {
-        self.quarantined_sandboxes.set(count);
+        self.quarantined_sandboxes.with_label_values(&[]).set(count);
     }
 
     pub fn set_active_monitors(&self, count: f64) {
-        self.active_monitors.set(count);
+        self.active_monitors.with_label_values(&[]).set(count);
     }
 
     pub async fn get_dashboard_metrics(
@@ -135,37 +208,100 @@ impl MetricsCollector {
         _time_range: Option<String>,
         _granularity: Option<String>,
     ) -> Result<DashboardMetrics> {
-        let events_by_type_counters = self.events_by_type.read().await;
-        let events_by_severity_counters = self.events_by_severity.read().await;
-        
         let mut events_by_type = HashMap::new();
-        for (event_type, counter) in events_by_type_counters.iter() {
-            events_by_type.insert(event_type.clone(), counter.get() as u64);
-        }
-        
         let mut events_by_severity = HashMap::new();
-        for (severity, counter) in events_by_severity_counters.iter() {
-            events_by_severity.insert(severity.clone(), counter.get() as u64);
+        let mut total_events = 0;
+        for family in self.events_total.collect() {
+            for metric in family.get_metric() {
+                let count = metric.get_counter().get_value() as u64;
+                total_events += count;
+                for label in metric.get_label() {
+                    let counts = match label.get_name() {
+                        "event_type" => &mut events_by_type,
+                        "severity" => &mut events_by_severity,
+                        _ => continue,
+                    };
+                    *counts.entry(label.get_value().to_string()).or_insert(0) += count;
+                }
+            }
         }
 
+        let (response_count, response_sum) = self
+            .response_time
+            .inner()
+            .collect()
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .map(|metric| metric.get_histogram())
+            .fold((0, 0.0), |(count, sum), histogram| {
+                (count + histogram.get_sample_count(), sum + histogram.get_sample_sum())
+            });
+        let quarantined_sandboxes = self.quarantined_sandboxes.with_label_values(&[]).get() as u64;
+        let active_monitors = self.active_monitors.with_label_values(&[]).get() as u64;
+
         Ok(DashboardMetrics {
-            total_events: self.events_total.get() as u64,
+            total_events,
+            compliance_score: self.calculate_compliance_score(total_events),
+            realtime_metrics: RealtimeMetrics {
+                events_per_second: total_events as f64 / 60.0, // Rough estimate
+                active_sandboxes: active_monitors,
+                quarantined_sandboxes,
+                critical_events: events_by_severity.get(Severity::Critical.as_str()).cloned().unwrap_or(0),
+            },
             events_by_type,
             events_by_severity,
-            quarantined_sandboxes: self.quarantined_sandboxes.get() as u64,
-            policy_violations: self.policy_violations.get() as u64,
-            compliance_score: self.calculate_compliance_score(),
-            avg_response_time_ms: self.response_time.get_sample_sum() * 1000.0 / self.response_time.get_sample_count() as f64,
-            active_monitors: self.active_monitors.get() as u64,
-            realtime_metrics: RealtimeMetrics {
-                events_per_second: self.events_total.get() / 60.0, // Rough estimate
-                active_sandboxes: self.active_monitors.get() as u64,
-                quarantined_sandboxes: self.quarantined_sandboxes.get() as u64,
-                critical_events: events_by_severity.get("critical").cloned().unwrap_or(0),
+            quarantined_sandboxes,
+            policy_violations: self.policy_violations.with_label_values(&[]).get() as u64,
+            avg_response_time_ms: if response_count == 0 {
+                0.0
+            } else {
+                response_sum * 1000.0 / response_count as f64
             },
+            active_monitors,
+            detection_latency: self.detection_percentiles(),
         })
     }
 
+    /// End-to-end detection latency by action, estimated from the histogram
+    fn detection_percentiles(&self) -> HashMap<String, LatencyPercentiles> {
+        let mut percentiles = HashMap::new();
+        for family in self.detection_latency.inner().collect() {
+            for metric in family.get_metric() {
+                let label = |name: &str| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .find(|label| label.get_name() == name)
+                        .map(|label| label.get_value().to_string())
+                };
+                if label("stage").as_deref() != Some(Stage::Actioned.as_str()) {
+                    continue;
+                }
+                let Some(action) = label("action") else {
+                    continue;
+                };
+
+                let histogram = metric.get_histogram();
+                let buckets: Vec<_> = histogram
+                    .get_bucket()
+                    .iter()
+                    .map(|bucket| (bucket.get_upper_bound(), bucket.get_cumulative_count()))
+                    .collect();
+                let count = histogram.get_sample_count();
+                percentiles.insert(
+                    action,
+                    LatencyPercentiles {
+                        count,
+                        p50_ms: quantile(&buckets, count, 0.50) * 1000.0,
+                        p95_ms: quantile(&buckets, count, 0.95) * 1000.0,
+                        p99_ms: quantile(&buckets, count, 0.99) * 1000.0,
+                    },
+                );
+            }
+        }
+        percentiles
+    }
+
     pub async fn collect_system_metrics(&self) -> Result<()> {
         // Collect system-level metrics
         // This would include CPU, memory, disk usage, etc.
@@ -173,21 +309,37 @@ impl MetricsCollector {
         Ok(())
     }
 
-    pub fn export_prometheus(&self) -> String {
-        let encoder = TextEncoder::new();
-        let metric_families = self.registry.gather();
-        encoder.encode_to_string(&metric_families).unwrap_or_default()
-    }
+    fn calculate_compliance_score(&self, total_events: u64) -> f64 {
+        let violations = self.policy_violations.with_label_values(&[]).get();
 
-    fn calculate_compliance_score(&self) -> f64 {
-        let total_events = self.events_total.get();
-        let violations = self.policy_violations.get();
-        
-        if total_events == 0.0 {
+        if total_events == 0 {
             return 100.0;
         }
-        
-        let violation_rate = violations / total_events;
+
+        let violation_rate = violations / total_events as f64;
         (100.0 - (violation_rate * 100.0)).max(0.0)
     }
-}
\ No newline at end of file
+}
+
+/// Quantile of a histogram from its cumulative buckets, interpolating
+/// linearly within the bucket it falls in, as Prometheus'
+/// `histogram_quantile` does. Values past the last bucket report its bound.
+fn quantile(buckets: &[(f64, u64)], count: u64, q: f64) -> f64 {
+    if count == 0 {
+        return 0.0;
+    }
+    let rank = q * count as f64;
+    let mut lower = (0.0, 0);
+    for &(upper_bound, cumulative) in buckets {
+        if cumulative as f64 >= rank {
+            let (lower_bound, below) = lower;
+            let in_bucket = (cumulative - below) as f64;
+            if in_bucket == 0.0 {
+                return upper_bound;
+            }
+            return lower_bound + (upper_bound - lower_bound) * (rank - below as f64) / in_bucket;
+        }
+        lower = (upper_bound, cumulative);
+    }
+    lower.0
+}
@@ -2,20 +2,12 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SecurityEvent {
-    pub id: String,
-    pub event_type: String,
-    pub severity: String,
-    pub timestamp: DateTime<Utc>,
-    pub sandbox_id: String,
-    pub provider: String,
-    pub message: String,
-    pub details: serde_json::Value,
-    pub metadata: Option<serde_json::Value>,
-    pub falco_rule: Option<String>,
-    pub ebpf_trace: Option<String>,
-}
+use crate::fixtures::Fixture;
+
+pub use sandstorm_types::security::{
+    EventType, QuarantineEnforcement, QuarantineMode, QuarantineRecord, ReleaseCondition,
+    SecurityEvent, Severity,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SecurityPolicy {
@@ -25,10 +17,61 @@ pub struct SecurityPolicy {
     pub enabled: bool,
     pub tier: String,
     pub rules: Vec<SecurityRule>,
+    /// How allowed events of noisy types are stored for sandboxes on this
+    /// policy's tier
+    #[serde(default)]
+    pub sampling: Vec<SamplingRule>,
+    /// Monitoring profile of sandboxes on this policy's tier, unless their
+    /// start request picks one
+    #[serde(default)]
+    pub monitoring_profile: Option<String>,
+    /// Checks the policy must pass before it's saved
+    #[serde(default)]
+    pub tests: Vec<PolicyTestCase>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// What watches a monitored sandbox: the eBPF programs attached to it, the
+/// Falco rule sets loaded for it, and how its noisy events are sampled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MonitoringProfile {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Programs from the eBPF catalog (`file_monitor`, `network_monitor`,
+    /// `process_monitor`, `privilege_monitor`)
+    #[serde(default)]
+    pub ebpf_programs: Vec<String>,
+    /// Falco rule files loaded after the base rules; relative paths are
+    /// resolved against the base rules' directory
+    #[serde(default)]
+    pub falco_rules: Vec<String>,
+    /// Sampling for the profile's sandboxes, taking precedence over their
+    /// tier's policies
+    #[serde(default)]
+    pub sampling: Vec<SamplingRule>,
+}
+
+/// Storage rule for one event type. Events a policy acts on are always
+/// stored in full.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SamplingRule {
+    pub event_type: EventType,
+    #[serde(flatten)]
+    pub mode: SamplingMode,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum SamplingMode {
+    /// Store one event in every `one_in`, per sandbox
+    Sample { one_in: u64 },
+    /// Store the first of identical events within `window_ms`, and count
+    /// the rest into it
+    Aggregate { window_ms: u64 },
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SecurityRule {
     pub id: String,
@@ -36,34 +79,178 @@ pub struct SecurityRule {
     pub description: String,
     pub condition: RuleCondition,
     pub action: String,
+    #[serde(default)]
+    pub parameters: ActionParameters,
     pub notifications: Option<Vec<String>>,
+    /// Run when the rule matches, besides its `action`
+    #[serde(default)]
+    pub actions: Vec<RuleActionSpec>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RuleCondition {
-    pub event_type: Option<String>,
-    pub severity: Option<String>,
-    pub pattern: Option<String>,
-    pub threshold: Option<u32>,
-    pub time_window_ms: Option<u64>,
+/// Tunes the rule's action
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ActionParameters {
+    /// How a `quarantine` action cuts the sandbox off (default: freeze)
+    pub quarantine_mode: Option<QuarantineMode>,
+    /// When a `quarantine` action's quarantine may be lifted automatically
+    #[serde(default)]
+    pub release_conditions: Vec<ReleaseCondition>,
+}
+
+/// A follow-up action of a rule and how often it's retried
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleActionSpec {
+    #[serde(flatten)]
+    pub action: RuleAction,
+    /// Attempts after the first one fails (default: 2). Actions that can't
+    /// run at all, like a deep trace of an unmonitored sandbox, aren't
+    /// retried.
+    #[serde(default)]
+    pub retries: Option<u32>,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleAction {
+    /// POST the event and the rule it matched to a URL
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: std::collections::BTreeMap<String, String>,
+    },
+    /// Pause the sandbox and cut its network at the gateway, recorded as a
+    /// `freeze` quarantine
+    GatewayFreeze,
+    /// Cut the sandbox's network at the gateway, recorded as a
+    /// `block_all_network` quarantine, or `block_egress` with `egress_only`
+    NetworkBlock {
+        #[serde(default)]
+        egress_only: bool,
+    },
+    /// Attach every eBPF program to the sandbox and store all its events,
+    /// unsampled, for a while
+    DeepTrace {
+        #[serde(default = "default_trace_secs")]
+        duration_secs: u64,
+    },
+    /// Open an incident at `incident_url`
+    OpenIncident {
+        /// Defaults to the rule's name
+        #[serde(default)]
+        title: Option<String>,
+        /// Defaults to the event's severity
+        #[serde(default)]
+        severity: Option<Severity>,
+    },
+}
+
+fn default_trace_secs() -> u64 {
+    600
+}
+
+impl RuleAction {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RuleAction::Webhook { .. } => "webhook",
+            RuleAction::GatewayFreeze => "gateway_freeze",
+            RuleAction::NetworkBlock { .. } => "network_block",
+            RuleAction::DeepTrace { .. } => "deep_trace",
+            RuleAction::OpenIncident { .. } => "open_incident",
+        }
+    }
+}
+
+/// An action a matched rule calls for
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct QuarantineRecord {
+pub struct TriggeredAction {
+    /// Name of the rule
+    pub rule: String,
+    #[serde(flatten)]
+    pub spec: RuleActionSpec,
+}
+
+/// An action kind rules can use
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionKindInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionStatus {
+    Succeeded,
+    Failed,
+}
+
+impl ActionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActionStatus::Succeeded => "succeeded",
+            ActionStatus::Failed => "failed",
+        }
+    }
+}
+
+impl std::str::FromStr for ActionStatus {
+    type Err = UnknownActionStatus;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(value.to_string()))
+            .map_err(|_| UnknownActionStatus(value.to_string()))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("unknown action status {0:?}")]
+pub struct UnknownActionStatus(pub String);
+
+/// One run of a rule's action, with every attempt it took
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionRun {
     pub id: String,
+    /// The action's kind
+    pub action: String,
+    pub rule: String,
     pub sandbox_id: String,
-    pub reason: String,
-    pub triggered_by: SecurityEvent,
-    pub start_time: DateTime<Utc>,
-    pub end_time: Option<DateTime<Utc>>,
-    pub auto_release: bool,
-    pub release_conditions: Option<Vec<String>>,
+    /// The event that triggered it, when it was stored
+    pub event_id: Option<String>,
+    pub status: ActionStatus,
+    pub attempts: u32,
+    /// The action as the rule gives it
+    pub parameters: serde_json::Value,
+    /// What the action reported, like the quarantine it recorded
+    pub result: Option<serde_json::Value>,
+    /// Why the last attempt failed
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+}
+
+/// Filters for action runs; unset fields match every run
+#[derive(Debug, Default, Deserialize)]
+pub struct ActionRunQuery {
+    pub sandbox_id: Option<String>,
+    pub action: Option<String>,
+    pub status: Option<ActionStatus>,
+    /// Defaults to 100
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleCondition {
+    pub event_type: Option<EventType>,
+    /// Lowest severity the rule matches
+    pub severity: Option<Severity>,
+    pub pattern: Option<String>,
+    pub threshold: Option<u32>,
+    pub time_window_ms: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Alert {
     pub id: String,
-    pub severity: String,
+    pub severity: Severity,
     pub message: String,
     pub timestamp: DateTime<Utc>,
     pub sandbox_id: Option<String>,
@@ -79,9 +266,9 @@ pub struct AggregationResult {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EventPattern {
-    pub event_type: String,
+    pub event_type: EventType,
     pub count: u64,
-    pub severity: String,
+    pub severity: Severity,
     pub sandboxes: Vec<String>,
     pub first_seen: DateTime<Utc>,
     pub last_seen: DateTime<Utc>,
@@ -105,6 +292,17 @@ pub struct DashboardMetrics {
     pub avg_response_time_ms: f64,
     pub active_monitors: u64,
     pub realtime_metrics: RealtimeMetrics,
+    /// Time from events being generated to their action being taken, by
+    /// action
+    pub detection_latency: std::collections::HashMap<String, LatencyPercentiles>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyPercentiles {
+    pub count: u64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -119,8 +317,9 @@ pub struct RealtimeMetrics {
 #[derive(Debug, Deserialize)]
 pub struct EventQuery {
     pub sandbox_id: Option<String>,
-    pub event_type: Option<String>,
-    pub severity: Option<String>,
+    pub run_id: Option<Uuid>,
+    pub event_type: Option<EventType>,
+    pub severity: Option<Severity>,
     pub start_time: Option<DateTime<Utc>>,
     pub end_time: Option<DateTime<Utc>>,
     pub limit: Option<u32>,
@@ -131,6 +330,7 @@ impl Default for EventQuery {
     fn default() -> Self {
         Self {
             sandbox_id: None,
+            run_id: None,
             event_type: None,
             severity: None,
             start_time: None,
@@ -154,10 +354,11 @@ pub struct MetricsQuery {
     pub granularity: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 pub struct AlertQuery {
+    pub sandbox_id: Option<String>,
     pub acknowledged: Option<bool>,
-    pub severity: Option<String>,
+    pub severity: Option<Severity>,
     pub limit: Option<u32>,
 }
 
@@ -166,20 +367,73 @@ pub struct QuarantineRequest {
     pub sandbox_id: String,
     pub reason: String,
     pub triggering_event: SecurityEvent,
+    #[serde(default)]
+    pub mode: QuarantineMode,
+    /// Lift the quarantine automatically once any of these holds
+    #[serde(default)]
+    pub release_conditions: Vec<ReleaseCondition>,
+}
+
+/// Why an operator needs an event's details in the clear
+#[derive(Debug, Deserialize)]
+pub struct RevealRequest {
+    pub reason: String,
+}
+
+/// An operator's approval of a quarantine's release
+#[derive(Debug, Deserialize)]
+pub struct ApprovalRequest {
+    pub approved_by: String,
+}
+
+/// Outcome of a scan of a quarantined sandbox
+#[derive(Debug, Deserialize)]
+pub struct ScanReport {
+    pub passed: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct QuarantineQuery {
+    pub sandbox_id: Option<String>,
+    pub run_id: Option<Uuid>,
+    /// Include released quarantines (default: active only)
+    #[serde(default)]
+    pub include_released: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct MonitoringRequest {
     pub provider: String,
+    /// Policy tier whose sampling rules apply to the sandbox's events
+    /// (default `basic`)
+    #[serde(default)]
+    pub tier: Option<String>,
+    /// Monitoring profile to use instead of the tier's
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Gateway run ID attached to every event raised for this sandbox
+    #[serde(default)]
+    pub run_id: Option<Uuid>,
+    /// eBPF programs to attach instead of the profile's
     pub ebpf_programs: Option<Vec<String>>,
+    /// Falco rule file to load on top of the profile's
     pub falco_rules: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct EventResponse {
-    pub event_id: String,
+    /// Stored record holding the event; `None` when it was sampled out
+    pub event_id: Option<String>,
+    /// `aggregated` or `sampled_out` when the event wasn't stored on its own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampling: Option<&'static str>,
+    /// Set when the database was down and the event waits to be stored
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub queued: bool,
     pub action_taken: String,
     pub matched_rules: Vec<String>,
+    /// When the event reached each stage of handling
+    pub timeline: crate::latency::DetectionTimeline,
 }
 
 #[derive(Debug, Serialize)]
@@ -191,6 +445,8 @@ pub struct PolicyResponse {
 pub struct MonitoringResponse {
     pub sandbox_id: String,
     pub status: String,
+    /// Monitoring profile the sandbox was started with
+    pub profile: String,
     pub monitors_active: Vec<String>,
 }
 
@@ -198,10 +454,118 @@ pub struct MonitoringResponse {
 pub struct MonitoringStatus {
     pub sandbox_id: String,
     pub provider: String,
+    pub tier: String,
+    pub profile: String,
     pub start_time: DateTime<Utc>,
     pub uptime_seconds: u64,
     pub ebpf_active: bool,
     pub falco_active: bool,
+    /// When a deep trace started by a rule's action ends
+    pub deep_trace_until: Option<DateTime<Utc>>,
+}
+
+/// Filters for monitored sandboxes; unset fields match every sandbox
+#[derive(Debug, Default, Deserialize)]
+pub struct MonitorQuery {
+    pub provider: Option<String>,
+    pub profile: Option<String>,
+    pub ebpf_active: Option<bool>,
+    pub falco_active: Option<bool>,
+    /// Only sandboxes monitored for at least this long
+    pub min_uptime_secs: Option<u64>,
+    /// Only sandboxes monitored for at most this long
+    pub max_uptime_secs: Option<u64>,
+}
+
+impl MonitorQuery {
+    pub fn is_empty(&self) -> bool {
+        self.provider.is_none()
+            && self.profile.is_none()
+            && self.ebpf_active.is_none()
+            && self.falco_active.is_none()
+            && self.min_uptime_secs.is_none()
+            && self.max_uptime_secs.is_none()
+    }
+
+    pub fn matches(&self, status: &MonitoringStatus) -> bool {
+        self.provider.as_ref().is_none_or(|provider| &status.provider == provider)
+            && self.profile.as_ref().is_none_or(|profile| &status.profile == profile)
+            && self.ebpf_active.is_none_or(|active| status.ebpf_active == active)
+            && self.falco_active.is_none_or(|active| status.falco_active == active)
+            && self.min_uptime_secs.is_none_or(|min| status.uptime_seconds >= min)
+            && self.max_uptime_secs.is_none_or(|max| status.uptime_seconds <= max)
+    }
+}
+
+/// Sandboxes to stop monitoring: the listed ones, and those matching the
+/// filters. At least one of the two is required.
+#[derive(Debug, Default, Deserialize)]
+pub struct BulkStopRequest {
+    #[serde(default)]
+    pub sandbox_ids: Vec<String>,
+    #[serde(flatten)]
+    pub filter: MonitorQuery,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BulkStopResponse {
+    pub stopped: Vec<String>,
+    pub failed: Vec<StopFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StopFailure {
+    pub sandbox_id: String,
+    pub error: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostureQuery {
+    /// How far back event counts go (default 24 hours)
+    pub window_hours: Option<u32>,
+}
+
+/// Everything the dashboard shows about one sandbox's security
+#[derive(Debug, Serialize)]
+pub struct SandboxPosture {
+    pub sandbox_id: String,
+    /// `None` when the sandbox isn't being monitored
+    pub monitoring: Option<MonitoringStatus>,
+    pub active_rules: Vec<AppliedRule>,
+    pub window_hours: u32,
+    /// Events within the window, by severity
+    pub recent_events: std::collections::HashMap<Severity, u64>,
+    /// 0 (quiet) to 100 (quarantined or under sustained attack)
+    pub risk_score: f64,
+    pub open_alerts: Vec<Alert>,
+    /// The sandbox's active quarantine, if any
+    pub quarantine: Option<QuarantineRecord>,
+}
+
+/// A rule of an enabled policy, which every event is checked against
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedRule {
+    pub policy_id: String,
+    pub rule_id: String,
+    pub name: String,
+    pub action: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventTypeRequest {
+    pub name: EventType,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// An event type events and rules may use
+#[derive(Debug, Clone, Serialize)]
+pub struct EventTypeInfo {
+    pub name: EventType,
+    pub description: Option<String>,
+    pub built_in: bool,
+    /// When a custom type was registered
+    pub created_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -210,4 +574,187 @@ pub struct PolicyEvaluation {
     pub reason: String,
     pub matched_rules: Vec<String>,
     pub confidence: f64,
-}
\ No newline at end of file
+    /// Strictest mode among the matched quarantine rules
+    pub quarantine_mode: Option<QuarantineMode>,
+    /// Release conditions of the quarantine rule whose mode applies
+    #[serde(default)]
+    pub release_conditions: Vec<ReleaseCondition>,
+    /// Follow-up actions of every matched rule, in the order they matched
+    #[serde(default)]
+    pub actions: Vec<TriggeredAction>,
+}
+
+/// An event and what a policy should do about it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyTestCase {
+    pub name: String,
+    pub event: TestEvent,
+    pub expect: PolicyExpectation,
+}
+
+/// A test's event, generated from a fixture or given in full
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TestEvent {
+    Fixture {
+        #[serde(flatten)]
+        fixture: Fixture,
+        /// Defaults to the fixture's usual severity
+        #[serde(default)]
+        severity: Option<Severity>,
+        #[serde(default)]
+        sandbox_id: Option<String>,
+    },
+    Event(Box<SecurityEvent>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyExpectation {
+    /// `allow`, `alert`, `deny` or `quarantine`
+    pub action: String,
+    /// Names of exactly the rules that should match, in any order (default:
+    /// not checked)
+    #[serde(default)]
+    pub matched_rules: Option<Vec<String>>,
+    #[serde(default)]
+    pub quarantine_mode: Option<QuarantineMode>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PolicyTestRequest {
+    /// Cases to run instead of the policy's own `tests`
+    #[serde(default)]
+    pub cases: Vec<PolicyTestCase>,
+}
+
+/// Outcome of running test cases against a policy
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyTestReport {
+    pub policy_id: String,
+    pub passed: bool,
+    pub results: Vec<PolicyTestResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyTestResult {
+    pub name: String,
+    pub passed: bool,
+    /// Where the evaluation differs from the expectation
+    pub failures: Vec<String>,
+    pub event: SecurityEvent,
+    pub evaluation: PolicyEvaluation,
+}
+
+/// Re-run the current policies over events stored in a time range
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReevaluateRequest {
+    pub start_time: DateTime<Utc>,
+    /// Defaults to when the request arrives
+    pub end_time: Option<DateTime<Utc>>,
+    pub sandbox_id: Option<String>,
+    pub event_type: Option<EventType>,
+    /// Raise alerts and quarantine sandboxes for matches as if the events
+    /// had just arrived (default: only report them)
+    #[serde(default)]
+    pub apply_actions: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReevaluationStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// A re-evaluation and its report so far
+#[derive(Debug, Clone, Serialize)]
+pub struct Reevaluation {
+    pub id: String,
+    pub status: ReevaluationStatus,
+    pub request: ReevaluateRequest,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub events_scanned: u64,
+    /// Matching events per action
+    pub actions: std::collections::HashMap<String, u64>,
+    /// Events the current policies act on, oldest first. Only the first
+    /// matches are listed; `actions` counts them all.
+    pub matches: Vec<ReevaluationMatch>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReevaluationMatch {
+    pub event_id: String,
+    pub sandbox_id: String,
+    pub event_type: EventType,
+    pub severity: Severity,
+    pub timestamp: DateTime<Utc>,
+    pub action: String,
+    pub matched_rules: Vec<String>,
+    /// Whether the action was taken
+    pub applied: bool,
+}
+
+/// Package a sandbox's or a time range's records for legal hold
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportRequest {
+    pub sandbox_id: Option<String>,
+    pub start_time: Option<DateTime<Utc>>,
+    /// Defaults to when the request arrives
+    pub end_time: Option<DateTime<Utc>>,
+    /// Why the records are exported, kept with the export
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+impl ExportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportStatus::Running => "running",
+            ExportStatus::Completed => "completed",
+            ExportStatus::Failed => "failed",
+        }
+    }
+}
+
+impl std::str::FromStr for ExportStatus {
+    type Err = UnknownExportStatus;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(value.to_string()))
+            .map_err(|_| UnknownExportStatus(value.to_string()))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("unknown export status {0:?}")]
+pub struct UnknownExportStatus(pub String);
+
+/// A legal export and, once completed, what its archive holds
+#[derive(Debug, Clone, Serialize)]
+pub struct LegalExport {
+    pub id: String,
+    pub status: ExportStatus,
+    pub sandbox_id: Option<String>,
+    pub start_time: Option<DateTime<Utc>>,
+    pub end_time: DateTime<Utc>,
+    pub requested_by: String,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Records in the archive
+    pub records: u64,
+    /// Hash of the archive's last record, which the signature covers
+    pub head_hash: Option<String>,
+    pub signature: Option<String>,
+    pub error: Option<String>,
+}
@@ -0,0 +1,114 @@
+//! Deduplicated event details. Events about the same sandbox tend to carry
+//! the same large sub-objects (process trees, container info, policy
+//! context) over and over, so each large field of an event's details is
+//! stored once in `event_payloads`, keyed by the SHA-256 of its value, and
+//! the event keeps only a reference to it in `details_refs`. The
+//! `event_details()` SQL function puts details back together on read.
+
+use ring::digest;
+use serde_json::{Map, Value};
+
+use crate::exports::hex;
+
+/// Fields whose serialized value is smaller than this stay in the event
+const MIN_SHARED_BYTES: usize = 256;
+
+/// An event's details split for storage
+#[derive(Debug, Default)]
+pub struct SplitDetails {
+    /// Fields kept in the event's own `details`
+    pub inline: Value,
+    /// Field name to payload hash, for `details_refs`; `None` when nothing
+    /// was moved out
+    pub refs: Option<Value>,
+    /// Payloads to store, by hash
+    pub payloads: Vec<(String, Value)>,
+}
+
+/// Move the large fields of an event's details out into payloads. Details
+/// that aren't an object are kept as they are.
+pub fn split(details: &Value) -> SplitDetails {
+    let Value::Object(fields) = details else {
+        return SplitDetails {
+            inline: details.clone(),
+            ..Default::default()
+        };
+    };
+
+    let mut inline = Map::new();
+    let mut refs = Map::new();
+    let mut payloads = Vec::new();
+    for (name, value) in fields {
+        // Only objects and arrays repeat wholesale; scalars are cheap
+        let shareable = matches!(value, Value::Object(_) | Value::Array(_));
+        let bytes = serde_json::to_vec(value).unwrap_or_default();
+        if !shareable || bytes.len() < MIN_SHARED_BYTES {
+            inline.insert(name.clone(), value.clone());
+            continue;
+        }
+        // serde_json keeps object keys sorted, so equal values hash alike
+        let hash = hex(digest::digest(&digest::SHA256, &bytes).as_ref());
+        refs.insert(name.clone(), Value::String(hash.clone()));
+        payloads.push((hash, value.clone()));
+    }
+
+    SplitDetails {
+        inline: Value::Object(inline),
+        refs: (!refs.is_empty()).then_some(Value::Object(refs)),
+        payloads,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// An array whose serialized form is `bytes` long
+    fn array_of(bytes: usize) -> Value {
+        // `["…"]` adds four bytes to the string
+        json!(["x".repeat(bytes - 4)])
+    }
+
+    #[test]
+    fn moves_out_large_objects_and_arrays_only() {
+        assert_eq!(serde_json::to_vec(&array_of(MIN_SHARED_BYTES)).unwrap().len(), MIN_SHARED_BYTES);
+        let details = json!({
+            "below": array_of(MIN_SHARED_BYTES - 1),
+            "at": array_of(MIN_SHARED_BYTES),
+            "text": "y".repeat(1000),
+        });
+
+        let split = split(&details);
+        assert_eq!(
+            split.inline,
+            json!({ "below": array_of(MIN_SHARED_BYTES - 1), "text": "y".repeat(1000) })
+        );
+        let refs = split.refs.unwrap();
+        let hash = refs["at"].as_str().unwrap();
+        assert_eq!(refs.as_object().unwrap().len(), 1);
+        assert_eq!(split.payloads, vec![(hash.to_string(), array_of(MIN_SHARED_BYTES))]);
+    }
+
+    #[test]
+    fn identical_fields_share_a_payload() {
+        let tree = json!({ "pid": 1, "children": array_of(MIN_SHARED_BYTES) });
+        let reordered = json!({ "children": array_of(MIN_SHARED_BYTES), "pid": 1 });
+        let first = split(&json!({ "tree": tree, "n": 1 }));
+        let second = split(&json!({ "tree": reordered, "n": 2 }));
+        let other = split(&json!({ "tree": { "pid": 2, "children": array_of(MIN_SHARED_BYTES) } }));
+
+        assert_eq!(first.refs, second.refs);
+        assert_eq!(first.payloads, second.payloads);
+        assert_ne!(first.refs, other.refs);
+    }
+
+    #[test]
+    fn keeps_details_that_are_not_objects() {
+        let details = array_of(1000);
+        let split = split(&details);
+        assert_eq!(split.inline, details);
+        assert!(split.refs.is_none());
+        assert!(split.payloads.is_empty());
+    }
+}
@@ -1,10 +1,15 @@
 use anyhow::Result;
 use dashmap::DashMap;
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 use tracing::info;
 
+use crate::fixtures::{Fixture, FIXTURE_SANDBOX};
 use crate::models::*;
 
+/// Tier of sandboxes monitored without one, and of events from sandboxes
+/// that aren't monitored
+pub const DEFAULT_TIER: &str = "basic";
+
 pub struct PolicyEngine {
     policies: Arc<DashMap<String, SecurityPolicy>>,
 }
@@ -30,30 +35,76 @@ impl PolicyEngine {
                     name: "Block Critical File Access".to_string(),
                     description: "Prevent access to critical system files".to_string(),
                     condition: RuleCondition {
-                        event_type: Some("file_access".to_string()),
+                        event_type: Some(EventType::FileAccess),
                         severity: None,
                         pattern: Some("(/etc/passwd|/etc/shadow|/root/.*)".to_string()),
                         threshold: None,
                         time_window_ms: None,
                     },
                     action: "deny".to_string(),
+                    parameters: ActionParameters::default(),
                     notifications: None,
+                    actions: Vec::new(),
                 },
                 SecurityRule {
                     id: "rule_basic_2".to_string(),
                     name: "Alert on Privilege Escalation".to_string(),
                     description: "Alert when privilege escalation is detected".to_string(),
                     condition: RuleCondition {
-                        event_type: Some("privilege_escalation".to_string()),
+                        event_type: Some(EventType::PrivilegeEscalation),
                         severity: None,
                         pattern: None,
                         threshold: None,
                         time_window_ms: None,
                     },
                     action: "alert".to_string(),
+                    parameters: ActionParameters::default(),
                     notifications: None,
+                    actions: Vec::new(),
                 },
             ],
+            // Reads on hot paths repeat constantly; keep one record per
+            // file every ten seconds
+            sampling: vec![SamplingRule {
+                event_type: EventType::FileAccess,
+                mode: SamplingMode::Aggregate { window_ms: 10_000 },
+            }],
+            monitoring_profile: Some("basic".to_string()),
+            tests: vec![
+                test_case(
+                    "Reading /etc/shadow is denied",
+                    Fixture::FileAccess {
+                        path: "/etc/shadow".to_string(),
+                        flags: "O_RDONLY".to_string(),
+                        executable: "/bin/cat".to_string(),
+                    },
+                    None,
+                    "deny",
+                    &["Block Critical File Access"],
+                ),
+                test_case(
+                    "Writing to /tmp is allowed",
+                    Fixture::FileAccess {
+                        path: "/tmp/build/output.log".to_string(),
+                        flags: "O_WRONLY|O_CREAT".to_string(),
+                        executable: "/usr/bin/python3".to_string(),
+                    },
+                    None,
+                    "allow",
+                    &[],
+                ),
+                test_case(
+                    "Becoming root raises an alert",
+                    Fixture::PrivilegeEscalation {
+                        executable: "/usr/bin/sudo".to_string(),
+                        from_uid: 1000,
+                        to_uid: 0,
+                    },
+                    None,
+                    "alert",
+                    &["Alert on Privilege Escalation"],
+                ),
+            ],
             created_at: chrono::Utc::now(),
             updated_at: chrono::Utc::now(),
         };
@@ -72,29 +123,68 @@ impl PolicyEngine {
                     description: "Automatically quarantine sandboxes with critical security events".to_string(),
                     condition: RuleCondition {
                         event_type: None,
-                        severity: Some("critical".to_string()),
+                        severity: Some(Severity::Critical),
                         pattern: None,
                         threshold: None,
                         time_window_ms: None,
                     },
                     action: "quarantine".to_string(),
+                    parameters: ActionParameters::default(),
                     notifications: Some(vec!["security-ops@company.com".to_string()]),
+                    actions: Vec::new(),
                 },
                 SecurityRule {
                     id: "rule_shield_2".to_string(),
                     name: "Block Suspicious Behavior".to_string(),
                     description: "Block and quarantine suspicious behavior patterns".to_string(),
                     condition: RuleCondition {
-                        event_type: Some("suspicious_behavior".to_string()),
+                        event_type: Some(EventType::SuspiciousBehavior),
                         severity: None,
                         pattern: None,
                         threshold: None,
                         time_window_ms: None,
                     },
                     action: "quarantine".to_string(),
+                    parameters: ActionParameters::default(),
                     notifications: None,
+                    actions: Vec::new(),
                 },
             ],
+            // Shield keeps every event
+            sampling: Vec::new(),
+            monitoring_profile: Some("shield".to_string()),
+            tests: vec![
+                test_case(
+                    "Critical events quarantine the sandbox",
+                    Fixture::ProcessSpawn {
+                        command: "/usr/bin/nc".to_string(),
+                        args: vec!["-e".to_string(), "/bin/sh".to_string()],
+                    },
+                    Some(Severity::Critical),
+                    "quarantine",
+                    &["Auto-Quarantine Critical Events"],
+                ),
+                test_case(
+                    "Suspicious behavior quarantines the sandbox",
+                    Fixture::SuspiciousBehavior {
+                        description: "Crypto miner signature in process memory".to_string(),
+                    },
+                    None,
+                    "quarantine",
+                    &["Block Suspicious Behavior"],
+                ),
+                test_case(
+                    "Ordinary network traffic is allowed",
+                    Fixture::NetworkActivity {
+                        destination_ip: "151.101.0.223".to_string(),
+                        port: 443,
+                        protocol: "TCP".to_string(),
+                    },
+                    None,
+                    "allow",
+                    &[],
+                ),
+            ],
             created_at: chrono::Utc::now(),
             updated_at: chrono::Utc::now(),
         };
@@ -131,36 +221,151 @@ impl PolicyEngine {
         Ok(self.policies.iter().map(|p| p.clone()).collect())
     }
 
-    pub async fn evaluate(&self, event: &SecurityEvent) -> Result<PolicyEvaluation> {
-        let mut matched_rules = Vec::new();
-        let mut final_action = "allow".to_string();
-        let mut final_reason = String::new();
-        let mut confidence = 0.0;
+    /// Sampling rule for an event type on a tier, from any enabled policy of
+    /// that tier that has one
+    pub fn sampling_rule(&self, tier: &str, event_type: &EventType) -> Option<SamplingRule> {
+        self.policies
+            .iter()
+            .filter(|policy| policy.enabled && policy.tier == tier)
+            .find_map(|policy| {
+                policy
+                    .sampling
+                    .iter()
+                    .find(|rule| rule.event_type == *event_type)
+                    .cloned()
+            })
+    }
+
+    /// Monitoring profile for sandboxes on a tier, from the enabled policy
+    /// of that tier with the lowest ID that names one
+    pub fn profile_for_tier(&self, tier: &str) -> Option<String> {
+        let mut policies: Vec<(String, String)> = self
+            .policies
+            .iter()
+            .filter(|policy| policy.enabled && policy.tier == tier)
+            .filter_map(|policy| Some((policy.id.clone(), policy.monitoring_profile.clone()?)))
+            .collect();
+        policies.sort();
+        policies.into_iter().next().map(|(_, profile)| profile)
+    }
 
+    /// A policy that names a monitoring profile
+    pub fn policy_with_profile(&self, profile: &str) -> Option<String> {
+        self.policies
+            .iter()
+            .find(|policy| policy.monitoring_profile.as_deref() == Some(profile))
+            .map(|policy| policy.id.clone())
+    }
+
+    /// A policy whose rules or sampling refer to an event type
+    pub fn policy_using(&self, event_type: &EventType) -> Option<String> {
+        self.policies
+            .iter()
+            .find(|policy| {
+                policy
+                    .rules
+                    .iter()
+                    .any(|rule| rule.condition.event_type.as_ref() == Some(event_type))
+                    || policy.sampling.iter().any(|rule| rule.event_type == *event_type)
+            })
+            .map(|policy| policy.id.clone())
+    }
+
+    /// Rules of every enabled policy, ordered by policy and rule
+    pub fn active_rules(&self) -> Vec<AppliedRule> {
+        let mut rules: Vec<_> = self
+            .policies
+            .iter()
+            .filter(|policy| policy.enabled)
+            .flat_map(|policy| {
+                policy
+                    .rules
+                    .iter()
+                    .map(|rule| AppliedRule {
+                        policy_id: policy.id.clone(),
+                        rule_id: rule.id.clone(),
+                        name: rule.name.clone(),
+                        action: rule.action.clone(),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        rules.sort_by(|a, b| (&a.policy_id, &a.rule_id).cmp(&(&b.policy_id, &b.rule_id)));
+        rules
+    }
+
+    pub async fn evaluate(&self, event: &SecurityEvent) -> Result<PolicyEvaluation> {
+        let mut evaluation = allowed();
         for policy in self.policies.iter() {
             if !policy.enabled {
                 continue;
             }
+            self.apply_rules(event, &policy.rules, &mut evaluation)?;
+        }
+        Ok(evaluation)
+    }
+
+    /// What one policy alone would do about an event, enabled or not
+    pub fn evaluate_policy(&self, policy: &SecurityPolicy, event: &SecurityEvent) -> Result<PolicyEvaluation> {
+        let mut evaluation = allowed();
+        self.apply_rules(event, &policy.rules, &mut evaluation)?;
+        Ok(evaluation)
+    }
 
-            for rule in &policy.rules {
-                if self.matches_rule(event, rule)? {
-                    matched_rules.push(rule.name.clone());
-                    
-                    // Use the most restrictive action
-                    if self.is_more_restrictive(&rule.action, &final_action) {
-                        final_action = rule.action.clone();
-                        final_reason = format!("Rule '{}' triggered", rule.name);
-                        confidence = 0.9; // High confidence for rule matches
+    fn apply_rules(&self, event: &SecurityEvent, rules: &[SecurityRule], evaluation: &mut PolicyEvaluation) -> Result<()> {
+        for rule in rules {
+            if self.matches_rule(event, rule)? {
+                evaluation.matched_rules.push(rule.name.clone());
+                evaluation.actions.extend(rule.actions.iter().map(|spec| TriggeredAction {
+                    rule: rule.name.clone(),
+                    spec: spec.clone(),
+                }));
+                if rule.action == "quarantine" {
+                    let mode = rule.parameters.quarantine_mode.unwrap_or_default();
+                    if Some(mode) > evaluation.quarantine_mode {
+                        evaluation.quarantine_mode = Some(mode);
+                        evaluation.release_conditions = rule.parameters.release_conditions.clone();
                     }
                 }
+
+                // Use the most restrictive action
+                if self.is_more_restrictive(&rule.action, &evaluation.action) {
+                    evaluation.action = rule.action.clone();
+                    evaluation.reason = format!("Rule '{}' triggered", rule.name);
+                    evaluation.confidence = 0.9; // High confidence for rule matches
+                }
             }
         }
+        Ok(())
+    }
 
-        Ok(PolicyEvaluation {
-            action: final_action,
-            reason: final_reason,
-            matched_rules,
-            confidence,
+    /// Run test cases against a policy alone
+    pub fn run_tests(&self, policy: &SecurityPolicy, cases: &[PolicyTestCase]) -> Result<PolicyTestReport> {
+        let mut results = Vec::with_capacity(cases.len());
+        for case in cases {
+            let event = match &case.event {
+                TestEvent::Fixture {
+                    fixture,
+                    severity,
+                    sandbox_id,
+                } => fixture.event(sandbox_id.as_deref().unwrap_or(FIXTURE_SANDBOX), *severity),
+                TestEvent::Event(event) => (**event).clone(),
+            };
+            let evaluation = self.evaluate_policy(policy, &event)?;
+            let failures = unmet(&case.expect, &evaluation);
+            results.push(PolicyTestResult {
+                name: case.name.clone(),
+                passed: failures.is_empty(),
+                failures,
+                event,
+                evaluation,
+            });
+        }
+
+        Ok(PolicyTestReport {
+            policy_id: policy.id.clone(),
+            passed: results.iter().all(|result| result.passed),
+            results,
         })
     }
 
@@ -175,8 +380,8 @@ impl PolicyEngine {
         }
 
         // Check severity
-        if let Some(ref severity) = condition.severity {
-            if !self.is_severity_match(&event.severity, severity) {
+        if let Some(severity) = condition.severity {
+            if event.severity < severity {
                 return Ok(false);
             }
         }
@@ -199,29 +404,6 @@ impl PolicyEngine {
         Ok(true)
     }
 
-    fn is_severity_match(&self, event_severity: &str, rule_severity: &str) -> bool {
-        let severity_levels = [
-            ("low", 1),
-            ("medium", 2),
-            ("high", 3),
-            ("critical", 4),
-        ];
-
-        let event_level = severity_levels
-            .iter()
-            .find(|(s, _)| *s == event_severity)
-            .map(|(_, l)| *l)
-            .unwrap_or(0);
-
-        let rule_level = severity_levels
-            .iter()
-            .find(|(s, _)| *s == rule_severity)
-            .map(|(_, l)| *l)
-            .unwrap_or(0);
-
-        event_level >= rule_level
-    }
-
     fn is_more_restrictive(&self, action1: &str, action2: &str) -> bool {
         let restrictiveness = [
             ("allow", 0),
@@ -244,4 +426,248 @@ impl PolicyEngine {
 
         level1 > level2
     }
-}
\ No newline at end of file
+}
+
+/// A test of a fixture event and the action and rules expected for it
+fn test_case(
+    name: &str,
+    fixture: Fixture,
+    severity: Option<Severity>,
+    action: &str,
+    matched_rules: &[&str],
+) -> PolicyTestCase {
+    PolicyTestCase {
+        name: name.to_string(),
+        event: TestEvent::Fixture {
+            fixture,
+            severity,
+            sandbox_id: None,
+        },
+        expect: PolicyExpectation {
+            action: action.to_string(),
+            matched_rules: Some(matched_rules.iter().map(|rule| rule.to_string()).collect()),
+            quarantine_mode: None,
+        },
+    }
+}
+
+/// Evaluation of an event no rule matched
+fn allowed() -> PolicyEvaluation {
+    PolicyEvaluation {
+        action: "allow".to_string(),
+        reason: String::new(),
+        matched_rules: Vec::new(),
+        confidence: 0.0,
+        quarantine_mode: None,
+        release_conditions: Vec::new(),
+        actions: Vec::new(),
+    }
+}
+
+/// How an evaluation falls short of a test's expectation
+fn unmet(expect: &PolicyExpectation, evaluation: &PolicyEvaluation) -> Vec<String> {
+    let mut failures = Vec::new();
+    if evaluation.action != expect.action {
+        failures.push(format!("expected action {}, got {}", expect.action, evaluation.action));
+    }
+    if let Some(expected) = &expect.matched_rules {
+        let mut expected = expected.clone();
+        let mut matched = evaluation.matched_rules.clone();
+        expected.sort();
+        matched.sort();
+        if expected != matched {
+            failures.push(format!("expected rules {:?} to match, got {:?}", expected, matched));
+        }
+    }
+    if expect.quarantine_mode.is_some() && expect.quarantine_mode != evaluation.quarantine_mode {
+        failures.push(format!(
+            "expected quarantine mode {:?}, got {:?}",
+            expect.quarantine_mode, evaluation.quarantine_mode
+        ));
+    }
+    failures
+}
+
+/// Risk of a sandbox from its recent events by severity and its open alerts,
+/// from 0 to 100. Quarantined sandboxes are at 100.
+pub fn risk_score(events_by_severity: &HashMap<Severity, u64>, open_alerts: usize, quarantined: bool) -> f64 {
+    if quarantined {
+        return 100.0;
+    }
+    let weighted: f64 = events_by_severity
+        .iter()
+        .map(|(severity, count)| {
+            let weight = match severity {
+                Severity::Low => 1.0,
+                Severity::Medium => 3.0,
+                Severity::High => 10.0,
+                Severity::Critical => 25.0,
+            };
+            weight * *count as f64
+        })
+        .sum::<f64>()
+        + 10.0 * open_alerts as f64;
+
+    // Rises quickly for the first serious events, then levels off below 100
+    let score = 100.0 * (1.0 - (-weighted / 100.0).exp());
+    (score * 10.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A policy denying reads of `/etc/passwd` and nothing else
+    fn passwd_policy() -> SecurityPolicy {
+        SecurityPolicy {
+            id: "policy_passwd".to_string(),
+            name: "Passwd".to_string(),
+            description: String::new(),
+            enabled: true,
+            tier: "basic".to_string(),
+            rules: vec![SecurityRule {
+                id: "rule_passwd".to_string(),
+                name: "Block Passwd".to_string(),
+                description: String::new(),
+                condition: RuleCondition {
+                    event_type: Some(EventType::FileAccess),
+                    severity: None,
+                    pattern: Some("/etc/passwd".to_string()),
+                    threshold: None,
+                    time_window_ms: None,
+                },
+                action: "deny".to_string(),
+                parameters: ActionParameters::default(),
+                notifications: None,
+                actions: Vec::new(),
+            }],
+            sampling: Vec::new(),
+            monitoring_profile: None,
+            tests: Vec::new(),
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }
+    }
+
+    fn file_access(path: &str) -> Fixture {
+        Fixture::FileAccess {
+            path: path.to_string(),
+            flags: "O_RDONLY".to_string(),
+            executable: "/bin/cat".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn default_policies_pass_their_tests() {
+        let engine = PolicyEngine::new();
+        engine.load_default_policies().await.unwrap();
+        for policy in engine.list_policies().await.unwrap() {
+            assert!(!policy.tests.is_empty(), "{}", policy.id);
+            let report = engine.run_tests(&policy, &policy.tests).unwrap();
+            let failures: Vec<_> = report.results.iter().flat_map(|result| &result.failures).collect();
+            assert!(report.passed, "{}: {:?}", policy.id, failures);
+        }
+    }
+
+    #[test]
+    fn reports_where_a_policy_falls_short() {
+        let engine = PolicyEngine::new();
+        let policy = passwd_policy();
+        let cases = vec![
+            test_case("passwd is denied", file_access("/etc/passwd"), None, "deny", &["Block Passwd"]),
+            test_case("shadow is denied", file_access("/etc/shadow"), None, "deny", &["Block Shadow"]),
+            PolicyTestCase {
+                name: "passwd quarantines".to_string(),
+                event: TestEvent::Fixture {
+                    fixture: file_access("/etc/passwd"),
+                    severity: Some(Severity::Critical),
+                    sandbox_id: Some("sandbox-1".to_string()),
+                },
+                expect: PolicyExpectation {
+                    action: "deny".to_string(),
+                    matched_rules: None,
+                    quarantine_mode: Some(QuarantineMode::default()),
+                },
+            },
+        ];
+
+        let report = engine.run_tests(&policy, &cases).unwrap();
+        assert!(!report.passed);
+        assert_eq!(report.policy_id, "policy_passwd");
+        let outcomes: Vec<_> = report.results.iter().map(|result| (result.name.as_str(), result.passed)).collect();
+        assert_eq!(outcomes, [("passwd is denied", true), ("shadow is denied", false), ("passwd quarantines", false)]);
+
+        assert_eq!(
+            report.results[1].failures,
+            [
+                "expected action deny, got allow".to_string(),
+                "expected rules [\"Block Shadow\"] to match, got []".to_string(),
+            ]
+        );
+        assert_eq!(report.results[2].failures.len(), 1);
+        assert!(report.results[2].failures[0].starts_with("expected quarantine mode"));
+        assert_eq!(report.results[2].event.sandbox_id, "sandbox-1");
+        assert_eq!(report.results[2].event.severity, Severity::Critical);
+    }
+
+    #[test]
+    fn runs_cases_written_as_json() {
+        let cases: Vec<PolicyTestCase> = serde_json::from_value(serde_json::json!([
+            {
+                "name": "passwd read",
+                "event": { "fixture": "file_access", "path": "/etc/passwd" },
+                "expect": { "action": "deny", "matched_rules": ["Block Passwd"] }
+            },
+            {
+                "name": "outbound connection",
+                "event": { "fixture": "network_activity", "destination_ip": "10.1.2.3", "port": 22 },
+                "expect": { "action": "allow" }
+            }
+        ]))
+        .unwrap();
+
+        let report = PolicyEngine::new().run_tests(&passwd_policy(), &cases).unwrap();
+        assert!(report.passed, "{:?}", report.results);
+        assert_eq!(report.results[0].event.sandbox_id, FIXTURE_SANDBOX);
+        assert_eq!(report.results[1].event.severity, Severity::Low);
+    }
+
+    #[tokio::test]
+    async fn active_rules_come_from_enabled_policies_in_order() {
+        let engine = PolicyEngine::new();
+        engine.load_default_policies().await.unwrap();
+        let mut disabled = passwd_policy();
+        disabled.enabled = false;
+        engine.add_policy(disabled).await.unwrap();
+
+        let rules: Vec<(String, String)> = engine
+            .active_rules()
+            .into_iter()
+            .map(|rule| (rule.policy_id, rule.rule_id))
+            .collect();
+        let mut sorted = rules.clone();
+        sorted.sort();
+        assert_eq!(rules, sorted);
+        assert!(rules.iter().any(|(policy, _)| policy == "policy_basic"));
+        assert!(rules.iter().all(|(policy, _)| policy != "policy_passwd"));
+    }
+
+    #[test]
+    fn risk_score_rises_with_severity_and_alerts() {
+        let counts = |pairs: &[(Severity, u64)]| pairs.iter().copied().collect::<HashMap<_, _>>();
+
+        assert_eq!(risk_score(&HashMap::new(), 0, false), 0.0);
+        assert_eq!(risk_score(&HashMap::new(), 0, true), 100.0);
+
+        let low = risk_score(&counts(&[(Severity::Low, 5)]), 0, false);
+        let critical = risk_score(&counts(&[(Severity::Critical, 5)]), 0, false);
+        assert!(0.0 < low && low < critical, "{} {}", low, critical);
+
+        // Open alerts add to the risk of the same events
+        assert!(risk_score(&counts(&[(Severity::Low, 5)]), 2, false) > low);
+
+        // A flood of events levels off at the top of the scale
+        let flood = risk_score(&counts(&[(Severity::Critical, 10_000)]), 50, false);
+        assert!((99.9..=100.0).contains(&flood), "{}", flood);
+    }
+}
@@ -0,0 +1,180 @@
+//! Monitoring profiles. A profile picks the eBPF programs attached to a
+//! sandbox, the Falco rule sets loaded for it and the sampling of its noisy
+//! events. Policies name the profile for their tier, and a start request
+//! can pick another.
+
+use dashmap::DashMap;
+use std::path::Path;
+use tracing::warn;
+
+use crate::ebpf::PROGRAMS;
+use crate::models::*;
+
+/// Profile of sandboxes whose tier has no policy naming one
+pub const DEFAULT_PROFILE: &str = "basic";
+
+pub struct ProfileRegistry {
+    profiles: DashMap<String, MonitoringProfile>,
+}
+
+impl ProfileRegistry {
+    /// Registry holding the `basic`, `shield` and `maximum` profiles
+    pub fn new() -> Self {
+        let registry = Self {
+            profiles: DashMap::new(),
+        };
+        for profile in default_profiles() {
+            registry.profiles.insert(profile.name.clone(), profile);
+        }
+        registry
+    }
+
+    pub fn get(&self, name: &str) -> Option<MonitoringProfile> {
+        self.profiles.get(name).map(|profile| profile.clone())
+    }
+
+    /// Every profile, by name
+    pub fn list(&self) -> Vec<MonitoringProfile> {
+        let mut profiles: Vec<_> = self.profiles.iter().map(|profile| profile.clone()).collect();
+        profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        profiles
+    }
+
+    pub fn upsert(&self, profile: MonitoringProfile) {
+        self.profiles.insert(profile.name.clone(), profile);
+    }
+
+    pub fn remove(&self, name: &str) -> Option<MonitoringProfile> {
+        self.profiles.remove(name).map(|(_, profile)| profile)
+    }
+
+    /// A profile whose sampling refers to an event type
+    pub fn profile_using(&self, event_type: &EventType) -> Option<String> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.sampling.iter().any(|rule| rule.event_type == *event_type))
+            .map(|profile| profile.name.clone())
+    }
+}
+
+/// Every program a profile or start request names must be in the eBPF
+/// catalog
+pub fn check_programs(programs: &[String]) -> Result<(), String> {
+    match programs.iter().find(|program| !PROGRAMS.contains(&program.as_str())) {
+        Some(unknown) => Err(format!(
+            "unknown eBPF program {}; expected one of {}",
+            unknown,
+            PROGRAMS.join(", ")
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Falco rule files for a profile: the base rules, then the profile's, with
+/// relative paths resolved against the base rules' directory. Profile rule
+/// files missing from this host are skipped, so Falco still starts.
+pub fn falco_rule_files(base: &str, profile: &MonitoringProfile) -> Vec<String> {
+    let dir = Path::new(base).parent().unwrap_or(Path::new("/"));
+    let mut files = vec![base.to_string()];
+    for rules in &profile.falco_rules {
+        let path = dir.join(rules);
+        if path.exists() {
+            files.push(path.to_string_lossy().into_owned());
+        } else {
+            warn!("Falco rules {} of profile {} not found; skipping", path.display(), profile.name);
+        }
+    }
+    files
+}
+
+fn default_profiles() -> Vec<MonitoringProfile> {
+    vec![
+        MonitoringProfile {
+            name: "basic".to_string(),
+            description: "File and process activity, with network events sampled".to_string(),
+            ebpf_programs: vec!["file_monitor".to_string(), "process_monitor".to_string()],
+            falco_rules: Vec::new(),
+            // Falco still reports connections; one in ten is plenty at this
+            // tier
+            sampling: vec![SamplingRule {
+                event_type: EventType::NetworkActivity,
+                mode: SamplingMode::Sample { one_in: 10 },
+            }],
+        },
+        MonitoringProfile {
+            name: "shield".to_string(),
+            description: "File, process and network activity, with the shield rule set".to_string(),
+            ebpf_programs: vec![
+                "file_monitor".to_string(),
+                "network_monitor".to_string(),
+                "process_monitor".to_string(),
+            ],
+            falco_rules: vec!["rules.d/sandstorm-shield.yaml".to_string()],
+            sampling: Vec::new(),
+        },
+        MonitoringProfile {
+            name: "maximum".to_string(),
+            description: "Every probe and rule set, every event kept".to_string(),
+            ebpf_programs: PROGRAMS.iter().map(|program| program.to_string()).collect(),
+            falco_rules: vec![
+                "rules.d/sandstorm-shield.yaml".to_string(),
+                "rules.d/sandstorm-maximum.yaml".to_string(),
+            ],
+            sampling: Vec::new(),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn default_profiles_use_catalogued_programs() {
+        let registry = ProfileRegistry::new();
+        let names: Vec<String> = registry.list().into_iter().map(|profile| profile.name).collect();
+        assert_eq!(names, ["basic", "maximum", "shield"]);
+        assert!(registry.get(DEFAULT_PROFILE).is_some());
+        for profile in registry.list() {
+            assert_eq!(check_programs(&profile.ebpf_programs), Ok(()), "{}", profile.name);
+        }
+    }
+
+    #[test]
+    fn unknown_programs_are_rejected() {
+        let programs = vec!["file_monitor".to_string(), "gpu_monitor".to_string()];
+        let error = check_programs(&programs).unwrap_err();
+        assert!(error.starts_with("unknown eBPF program gpu_monitor; expected one of file_monitor"), "{}", error);
+    }
+
+    #[test]
+    fn finds_profiles_sampling_an_event_type() {
+        let registry = ProfileRegistry::new();
+        assert_eq!(registry.profile_using(&EventType::NetworkActivity), Some("basic".to_string()));
+        assert_eq!(registry.profile_using(&EventType::FileAccess), None);
+
+        registry.remove("basic");
+        assert_eq!(registry.profile_using(&EventType::NetworkActivity), None);
+    }
+
+    #[test]
+    fn falco_rules_missing_on_the_host_are_skipped() {
+        let dir = std::env::temp_dir().join(format!("falco-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("rules.d")).unwrap();
+        std::fs::write(dir.join("rules.d/sandstorm-shield.yaml"), "[]").unwrap();
+        let base = dir.join("falco_rules.yaml").to_string_lossy().into_owned();
+
+        let profile = ProfileRegistry::new().get("maximum").unwrap();
+        let files = falco_rule_files(&base, &profile);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            files,
+            [
+                base.clone(),
+                dir.join("rules.d/sandstorm-shield.yaml").to_string_lossy().into_owned(),
+            ]
+        );
+    }
+}
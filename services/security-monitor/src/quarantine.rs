@@ -1,9 +1,11 @@
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use std::sync::Arc;
 use uuid::Uuid;
 
 use crate::models::*;
+use crate::storage::EventStore;
 
 pub struct QuarantineManager {
     quarantines: Arc<DashMap<String, QuarantineRecord>>,
@@ -21,6 +23,8 @@ impl QuarantineManager {
         sandbox_id: &str,
         reason: &str,
         triggering_event: &SecurityEvent,
+        mode: QuarantineMode,
+        release_conditions: Vec<ReleaseCondition>,
     ) -> Result<QuarantineRecord> {
         let record = QuarantineRecord {
             id: Uuid::new_v4().to_string(),
@@ -29,33 +33,74 @@ impl QuarantineManager {
             triggered_by: triggering_event.clone(),
             start_time: chrono::Utc::now(),
             end_time: None,
-            auto_release: false,
-            release_conditions: None,
+            auto_release: !release_conditions.is_empty(),
+            release_conditions: (!release_conditions.is_empty()).then_some(release_conditions),
+            run_id: triggering_event.run_id,
+            mode,
+            approved_by: None,
+            scan_passed_at: None,
+            released_on: None,
         };
 
         self.quarantines.insert(record.id.clone(), record.clone());
         
-        // In a real implementation, this would also:
-        // 1. Stop the sandbox
-        // 2. Isolate network access
-        // 3. Preserve sandbox state for analysis
-        // 4. Notify security team
+        // The gateway enforces the mode; in a real implementation this
+        // would also:
+        // 1. Preserve sandbox state for analysis
+        // 2. Notify security team
         
         Ok(record)
     }
 
-    pub async fn release(&self, quarantine_id: &str) -> Result<()> {
-        if let Some(mut record) = self.quarantines.get_mut(quarantine_id) {
-            record.end_time = Some(chrono::Utc::now());
-            
-            // In a real implementation, this would also:
-            // 1. Restore sandbox access
-            // 2. Re-enable network
-            // 3. Apply any remediation actions
-            // 4. Log the release
+    /// Released record, if the quarantine exists. `released_on` is the
+    /// condition that allowed it, unset when an operator forces the release.
+    pub async fn release(
+        &self,
+        quarantine_id: &str,
+        released_on: Option<ReleaseCondition>,
+    ) -> Result<Option<QuarantineRecord>> {
+        let Some(mut record) = self.quarantines.get_mut(quarantine_id) else {
+            return Ok(None);
+        };
+        record.end_time = Some(chrono::Utc::now());
+        record.released_on = released_on;
+
+        // The gateway restores access; in a real implementation this would
+        // also:
+        // 1. Apply any remediation actions
+        // 2. Log the release
+
+        Ok(Some(record.clone()))
+    }
+
+    /// Record an operator's approval of an active quarantine's release
+    pub fn approve(&self, quarantine_id: &str, approved_by: &str) -> Option<QuarantineRecord> {
+        let mut record = self.quarantines.get_mut(quarantine_id)?;
+        if record.end_time.is_some() {
+            return None;
         }
-        
-        Ok(())
+        record.approved_by = Some(approved_by.to_string());
+        Some(record.clone())
+    }
+
+    /// Record a scan of an active quarantine's sandbox; a failed scan
+    /// withdraws an earlier pass
+    pub fn record_scan(&self, quarantine_id: &str, passed: bool) -> Option<QuarantineRecord> {
+        let mut record = self.quarantines.get_mut(quarantine_id)?;
+        if record.end_time.is_some() {
+            return None;
+        }
+        record.scan_passed_at = passed.then(Utc::now);
+        Some(record.clone())
+    }
+
+    /// Active quarantines that may be released automatically
+    pub fn auto_releasable(&self) -> Vec<QuarantineRecord> {
+        self.quarantines
+            .iter()
+            .filter(|entry| entry.auto_release && entry.end_time.is_none())
+            .map(|entry| entry.clone())
+            .collect()
     }
 
     pub async fn is_quarantined(&self, sandbox_id: &str) -> bool {
@@ -64,13 +109,32 @@ impl QuarantineManager {
             .any(|entry| entry.sandbox_id == sandbox_id && entry.end_time.is_none())
     }
 
-    pub async fn list_active(&self) -> Result<Vec<QuarantineRecord>> {
-        Ok(self
-            .quarantines
+    /// The sandbox's active quarantine in the most restrictive mode
+    pub fn strictest_active(&self, sandbox_id: &str) -> Option<QuarantineRecord> {
+        self.quarantines
             .iter()
-            .filter(|entry| entry.end_time.is_none())
+            .filter(|entry| entry.sandbox_id == sandbox_id && entry.end_time.is_none())
+            .max_by_key(|entry| entry.mode)
             .map(|entry| entry.clone())
-            .collect())
+    }
+
+    /// Quarantines matching the query, newest first
+    pub async fn list(&self, query: &QuarantineQuery) -> Result<Vec<QuarantineRecord>> {
+        let mut records: Vec<QuarantineRecord> = self
+            .quarantines
+            .iter()
+            .filter(|entry| query.include_released || entry.end_time.is_none())
+            .filter(|entry| {
+                query
+                    .sandbox_id
+                    .as_ref()
+                    .is_none_or(|sandbox_id| &entry.sandbox_id == sandbox_id)
+            })
+            .filter(|entry| query.run_id.is_none_or(|run_id| entry.run_id == Some(run_id)))
+            .map(|entry| entry.value().clone())
+            .collect();
+        records.sort_by_key(|record| std::cmp::Reverse(record.start_time));
+        Ok(records)
     }
 
     pub async fn get_record(&self, quarantine_id: &str) -> Option<QuarantineRecord> {
@@ -101,4 +165,201 @@ impl QuarantineManager {
 
         Ok(removed)
     }
-}
\ No newline at end of file
+}
+
+/// First of a quarantine's release conditions that holds at `now`, if any
+pub async fn satisfied_condition(
+    record: &QuarantineRecord,
+    event_store: &EventStore,
+    now: DateTime<Utc>,
+) -> Result<Option<ReleaseCondition>> {
+    let quarantined_for = now - record.start_time;
+    for condition in record.release_conditions.iter().flatten() {
+        let satisfied = match *condition {
+            ReleaseCondition::ManualApproval => record.approved_by.is_some(),
+            ReleaseCondition::ScanPassed => record.scan_passed_at.is_some(),
+            ReleaseCondition::TimeElapsed { minutes } => {
+                quarantined_for >= chrono::Duration::minutes(minutes.into())
+            }
+            ReleaseCondition::NoCriticalEvents { minutes } => {
+                let quiet_for = chrono::Duration::minutes(minutes.into());
+                quarantined_for >= quiet_for
+                    && event_store
+                        .count_events_by_severity(&record.sandbox_id, now - quiet_for)
+                        .await?
+                        .get(&Severity::Critical)
+                        .copied()
+                        .unwrap_or(0)
+                        == 0
+            }
+        };
+        if satisfied {
+            return Ok(Some(*condition));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{Fixture, FIXTURE_SANDBOX};
+
+    fn trigger(sandbox_id: &str) -> SecurityEvent {
+        Fixture::SuspiciousBehavior {
+            description: "Crypto miner signature in process memory".to_string(),
+        }
+        .event(sandbox_id, Some(Severity::Critical))
+    }
+
+    async fn quarantine(
+        manager: &QuarantineManager,
+        sandbox_id: &str,
+        mode: QuarantineMode,
+    ) -> QuarantineRecord {
+        manager
+            .quarantine(sandbox_id, "test", &trigger(sandbox_id), mode, Vec::new())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn strictest_active_mode_wins() {
+        let manager = QuarantineManager::new();
+        assert!(manager.strictest_active(FIXTURE_SANDBOX).is_none());
+
+        quarantine(&manager, FIXTURE_SANDBOX, QuarantineMode::BlockEgress).await;
+        let freeze = quarantine(&manager, FIXTURE_SANDBOX, QuarantineMode::Freeze).await;
+        quarantine(&manager, FIXTURE_SANDBOX, QuarantineMode::ObserveOnly).await;
+        quarantine(&manager, "sandbox-other", QuarantineMode::BlockAllNetwork).await;
+        assert_eq!(
+            manager.strictest_active(FIXTURE_SANDBOX).map(|record| record.id),
+            Some(freeze.id.clone())
+        );
+
+        // Releasing the freeze falls back to the strictest one left
+        manager.release(&freeze.id, None).await.unwrap();
+        assert_eq!(
+            manager.strictest_active(FIXTURE_SANDBOX).map(|record| record.mode),
+            Some(QuarantineMode::BlockEgress)
+        );
+        assert!(manager.is_quarantined(FIXTURE_SANDBOX).await);
+    }
+
+    #[tokio::test]
+    async fn lists_active_quarantines_newest_first() {
+        let manager = QuarantineManager::new();
+        let first = quarantine(&manager, FIXTURE_SANDBOX, QuarantineMode::BlockEgress).await;
+        let second = quarantine(&manager, FIXTURE_SANDBOX, QuarantineMode::Freeze).await;
+        quarantine(&manager, "sandbox-other", QuarantineMode::Freeze).await;
+        manager.release(&first.id, None).await.unwrap();
+
+        let query = |include_released| QuarantineQuery {
+            sandbox_id: Some(FIXTURE_SANDBOX.to_string()),
+            run_id: None,
+            include_released,
+        };
+        let ids = |records: Vec<QuarantineRecord>| {
+            records.into_iter().map(|record| record.id).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(manager.list(&query(false)).await.unwrap()), vec![second.id.clone()]);
+        assert_eq!(ids(manager.list(&query(true)).await.unwrap()), vec![second.id, first.id]);
+    }
+
+    #[tokio::test]
+    async fn released_quarantines_take_no_approval_or_scan() {
+        let manager = QuarantineManager::new();
+        let record = quarantine(&manager, FIXTURE_SANDBOX, QuarantineMode::Freeze).await;
+        let released = manager.release(&record.id, None).await.unwrap().unwrap();
+        assert!(released.end_time.is_some());
+        assert!(!manager.is_quarantined(FIXTURE_SANDBOX).await);
+
+        assert!(manager.approve(&record.id, "alice").is_none());
+        assert!(manager.record_scan(&record.id, true).is_none());
+        assert!(manager.release("missing", None).await.unwrap().is_none());
+    }
+
+    fn active(release_conditions: Vec<ReleaseCondition>, minutes_ago: i64) -> QuarantineRecord {
+        let sandbox_id = format!("release-{}", Uuid::new_v4());
+        QuarantineRecord {
+            id: Uuid::new_v4().to_string(),
+            triggered_by: trigger(&sandbox_id),
+            sandbox_id,
+            reason: "test".to_string(),
+            start_time: Utc::now() - chrono::Duration::minutes(minutes_ago),
+            end_time: None,
+            auto_release: true,
+            release_conditions: Some(release_conditions),
+            run_id: None,
+            mode: QuarantineMode::Freeze,
+            approved_by: None,
+            scan_passed_at: None,
+            released_on: None,
+        }
+    }
+
+    /// The migrated database the query macros are checked against
+    async fn store() -> EventStore {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must name a migrated database");
+        EventStore::new(&url).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn approvals_and_scans_release_once_recorded() {
+        let store = store().await;
+        let manager = QuarantineManager::new();
+        let record = active(vec![ReleaseCondition::ManualApproval, ReleaseCondition::ScanPassed], 0);
+        manager.quarantines.insert(record.id.clone(), record.clone());
+        assert_eq!(satisfied_condition(&record, &store, Utc::now()).await.unwrap(), None);
+
+        let scanned = manager.record_scan(&record.id, true).unwrap();
+        assert_eq!(
+            satisfied_condition(&scanned, &store, Utc::now()).await.unwrap(),
+            Some(ReleaseCondition::ScanPassed)
+        );
+        // A failed scan withdraws the pass
+        let failed = manager.record_scan(&record.id, false).unwrap();
+        assert_eq!(satisfied_condition(&failed, &store, Utc::now()).await.unwrap(), None);
+
+        // Conditions are checked in the order the rule lists them
+        manager.record_scan(&record.id, true);
+        let approved = manager.approve(&record.id, "alice").unwrap();
+        assert_eq!(
+            satisfied_condition(&approved, &store, Utc::now()).await.unwrap(),
+            Some(ReleaseCondition::ManualApproval)
+        );
+    }
+
+    #[tokio::test]
+    async fn time_conditions_wait_out_the_quarantine() {
+        let store = store().await;
+        let elapsed = ReleaseCondition::TimeElapsed { minutes: 30 };
+        let record = active(vec![elapsed], 10);
+        assert_eq!(satisfied_condition(&record, &store, Utc::now()).await.unwrap(), None);
+        let later = Utc::now() + chrono::Duration::minutes(25);
+        assert_eq!(satisfied_condition(&record, &store, later).await.unwrap(), Some(elapsed));
+    }
+
+    #[tokio::test]
+    async fn critical_events_hold_the_quarantine() {
+        let store = store().await;
+        let quiet = ReleaseCondition::NoCriticalEvents { minutes: 15 };
+
+        // Not quarantined long enough to have been quiet for that long
+        let recent = active(vec![quiet], 5);
+        assert_eq!(satisfied_condition(&recent, &store, Utc::now()).await.unwrap(), None);
+
+        let record = active(vec![quiet], 60);
+        assert_eq!(satisfied_condition(&record, &store, Utc::now()).await.unwrap(), Some(quiet));
+
+        let mut critical = trigger(&record.sandbox_id);
+        critical.timestamp = Utc::now() - chrono::Duration::minutes(2);
+        store.store_event(&Uuid::new_v4().to_string(), &critical).await.unwrap();
+        assert_eq!(satisfied_condition(&record, &store, Utc::now()).await.unwrap(), None);
+
+        // Once the critical event is older than the quiet period, it no
+        // longer counts
+        let later = Utc::now() + chrono::Duration::minutes(20);
+        assert_eq!(satisfied_condition(&record, &store, later).await.unwrap(), Some(quiet));
+    }
+}
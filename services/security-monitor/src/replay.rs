@@ -0,0 +1,201 @@
+use anyhow::Result;
+use chrono::Utc;
+use dashmap::DashMap;
+use uuid::Uuid;
+
+use crate::models::*;
+
+/// Matches listed in a re-evaluation report
+const MAX_LISTED_MATCHES: usize = 1000;
+
+/// Tracks re-evaluations of stored events, which run in the background
+pub struct ReplayManager {
+    runs: DashMap<String, Reevaluation>,
+}
+
+impl ReplayManager {
+    pub fn new() -> Self {
+        Self {
+            runs: DashMap::new(),
+        }
+    }
+
+    /// Register a re-evaluation, pinning its end to now when open-ended so
+    /// events arriving meanwhile don't shift the pages being read
+    pub fn start(&self, mut request: ReevaluateRequest) -> Reevaluation {
+        let now = Utc::now();
+        request.end_time.get_or_insert(now);
+
+        let run = Reevaluation {
+            id: Uuid::new_v4().to_string(),
+            status: ReevaluationStatus::Running,
+            request,
+            started_at: now,
+            finished_at: None,
+            events_scanned: 0,
+            actions: Default::default(),
+            matches: Vec::new(),
+            error: None,
+        };
+        self.runs.insert(run.id.clone(), run.clone());
+        run
+    }
+
+    pub fn scanned(&self, id: &str, events: u64) {
+        if let Some(mut run) = self.runs.get_mut(id) {
+            run.events_scanned += events;
+        }
+    }
+
+    /// Add an event the current policies act on to the report
+    pub fn matched(&self, id: &str, event: &SecurityEvent, evaluation: &PolicyEvaluation, applied: bool) {
+        let Some(mut run) = self.runs.get_mut(id) else {
+            return;
+        };
+        *run.actions.entry(evaluation.action.clone()).or_insert(0) += 1;
+        if run.matches.len() < MAX_LISTED_MATCHES {
+            run.matches.push(ReevaluationMatch {
+                event_id: event.id.clone(),
+                sandbox_id: event.sandbox_id.clone(),
+                event_type: event.event_type.clone(),
+                severity: event.severity,
+                timestamp: event.timestamp,
+                action: evaluation.action.clone(),
+                matched_rules: evaluation.matched_rules.clone(),
+                applied,
+            });
+        }
+    }
+
+    pub fn finish(&self, id: &str, result: Result<()>) {
+        if let Some(mut run) = self.runs.get_mut(id) {
+            run.finished_at = Some(Utc::now());
+            match result {
+                Ok(()) => run.status = ReevaluationStatus::Completed,
+                Err(e) => {
+                    run.status = ReevaluationStatus::Failed;
+                    run.error = Some(format!("{:#}", e));
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Reevaluation> {
+        self.runs.get(id).map(|run| run.clone())
+    }
+
+    /// Re-evaluations, newest first
+    pub fn list(&self) -> Vec<Reevaluation> {
+        let mut runs: Vec<Reevaluation> = self.runs.iter().map(|run| run.value().clone()).collect();
+        runs.sort_by_key(|run| std::cmp::Reverse(run.started_at));
+        runs
+    }
+
+    /// Drop reports of re-evaluations that finished over `retention_hours` ago
+    pub fn cleanup(&self, retention_hours: i64) -> usize {
+        let cutoff = Utc::now() - chrono::Duration::hours(retention_hours);
+        let before = self.runs.len();
+        self.runs
+            .retain(|_, run| run.finished_at.is_none_or(|finished_at| finished_at >= cutoff));
+        before - self.runs.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{Fixture, FIXTURE_SANDBOX};
+
+    fn request() -> ReevaluateRequest {
+        ReevaluateRequest {
+            start_time: Utc::now() - chrono::Duration::hours(1),
+            end_time: None,
+            sandbox_id: None,
+            event_type: None,
+            apply_actions: false,
+        }
+    }
+
+    fn evaluation(action: &str) -> PolicyEvaluation {
+        PolicyEvaluation {
+            action: action.to_string(),
+            reason: String::new(),
+            matched_rules: vec!["Block Passwd".to_string()],
+            confidence: 1.0,
+            quarantine_mode: None,
+            release_conditions: Vec::new(),
+            actions: Vec::new(),
+        }
+    }
+
+    fn event() -> SecurityEvent {
+        Fixture::ProcessSpawn {
+            command: "/bin/sh".to_string(),
+            args: Vec::new(),
+        }
+        .event(FIXTURE_SANDBOX, None)
+    }
+
+    #[test]
+    fn open_ended_runs_are_pinned_to_their_start() {
+        let manager = ReplayManager::new();
+        let run = manager.start(request());
+        assert_eq!(run.request.end_time, Some(run.started_at));
+        assert_eq!(run.status, ReevaluationStatus::Running);
+
+        let end_time = Utc::now() - chrono::Duration::minutes(5);
+        let bounded = manager.start(ReevaluateRequest {
+            end_time: Some(end_time),
+            ..request()
+        });
+        assert_eq!(bounded.request.end_time, Some(end_time));
+    }
+
+    #[test]
+    fn reports_count_every_match_but_list_a_bounded_number() {
+        let manager = ReplayManager::new();
+        let run = manager.start(request());
+        let event = event();
+        for _ in 0..MAX_LISTED_MATCHES + 5 {
+            manager.matched(&run.id, &event, &evaluation("deny"), false);
+        }
+        manager.matched(&run.id, &event, &evaluation("alert"), false);
+        manager.scanned(&run.id, 2000);
+        manager.scanned(&run.id, 500);
+
+        let report = manager.get(&run.id).unwrap();
+        assert_eq!(report.events_scanned, 2500);
+        assert_eq!(report.actions["deny"], MAX_LISTED_MATCHES as u64 + 5);
+        assert_eq!(report.actions["alert"], 1);
+        assert_eq!(report.matches.len(), MAX_LISTED_MATCHES);
+    }
+
+    #[test]
+    fn failed_runs_keep_their_error() {
+        let manager = ReplayManager::new();
+        let run = manager.start(request());
+        manager.finish(&run.id, Err(anyhow::anyhow!("database unavailable")));
+
+        let report = manager.get(&run.id).unwrap();
+        assert_eq!(report.status, ReevaluationStatus::Failed);
+        assert_eq!(report.error.as_deref(), Some("database unavailable"));
+        assert!(report.finished_at.is_some());
+    }
+
+    #[test]
+    fn cleanup_drops_only_long_finished_runs() {
+        let manager = ReplayManager::new();
+        let running = manager.start(request());
+        let recent = manager.start(request());
+        let old = manager.start(request());
+        manager.finish(&recent.id, Ok(()));
+        manager.finish(&old.id, Ok(()));
+        manager.runs.get_mut(&old.id).unwrap().finished_at = Some(Utc::now() - chrono::Duration::hours(48));
+
+        assert_eq!(manager.cleanup(24), 1);
+        assert!(manager.get(&old.id).is_none());
+        assert_eq!(manager.get(&recent.id).unwrap().status, ReevaluationStatus::Completed);
+        assert!(manager.get(&running.id).is_some());
+        assert_eq!(manager.list().len(), 2);
+    }
+}
@@ -0,0 +1,320 @@
+use chrono::{DateTime, Duration, Utc};
+use dashmap::{mapref::entry::Entry, DashMap};
+use std::sync::Mutex;
+
+use crate::models::*;
+
+/// What to do with an event its policies allow
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    /// Store it. `window` is set when it opens an aggregation window, and
+    /// must be handed to [`Sampler::opened`] once the event is stored.
+    Store { window: Option<String> },
+    /// Counted into the stored record `event_id`
+    Aggregated { event_id: Option<String> },
+    /// Dropped by 1-in-N sampling
+    SampledOut,
+}
+
+/// Repeats to write into a stored event's record
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flush {
+    pub event_id: String,
+    pub occurrences: u64,
+    pub last_seen: DateTime<Utc>,
+}
+
+struct Window {
+    /// Record the repeats are counted into, once it is stored
+    event_id: Option<String>,
+    closes: DateTime<Utc>,
+    /// Events in the window, including the stored one
+    occurrences: u64,
+    last_seen: DateTime<Utc>,
+}
+
+impl Window {
+    /// Repeats to write, if the window had any and its event was stored
+    fn flush(&self) -> Option<Flush> {
+        let event_id = self.event_id.clone()?;
+        (self.occurrences > 1).then_some(Flush {
+            event_id,
+            occurrences: self.occurrences,
+            last_seen: self.last_seen,
+        })
+    }
+}
+
+/// Applies sampling rules to events before they are stored
+pub struct Sampler {
+    /// Events seen per sandbox and event type, for 1-in-N sampling
+    seen: DashMap<(String, EventType), u64>,
+    /// Open aggregation windows, by event identity
+    windows: DashMap<String, Window>,
+    /// Repeats of windows replaced before they were flushed
+    closed: Mutex<Vec<Flush>>,
+}
+
+impl Sampler {
+    pub fn new() -> Self {
+        Self {
+            seen: DashMap::new(),
+            windows: DashMap::new(),
+            closed: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn decide(&self, event: &SecurityEvent, rule: Option<&SamplingRule>) -> Decision {
+        let Some(rule) = rule else {
+            return Decision::Store { window: None };
+        };
+
+        match rule.mode {
+            SamplingMode::Sample { one_in } => {
+                let mut seen = self
+                    .seen
+                    .entry((event.sandbox_id.clone(), event.event_type.clone()))
+                    .or_insert(0);
+                *seen += 1;
+                if (*seen - 1).is_multiple_of(one_in.max(1)) {
+                    Decision::Store { window: None }
+                } else {
+                    Decision::SampledOut
+                }
+            }
+            SamplingMode::Aggregate { window_ms } => {
+                let key = identity(event);
+                let now = Utc::now();
+                let window = Window {
+                    event_id: None,
+                    closes: now + Duration::milliseconds(window_ms as i64),
+                    occurrences: 1,
+                    last_seen: event.timestamp,
+                };
+
+                match self.windows.entry(key.clone()) {
+                    Entry::Occupied(mut entry) if entry.get().closes > now => {
+                        let open = entry.get_mut();
+                        open.occurrences += 1;
+                        open.last_seen = open.last_seen.max(event.timestamp);
+                        Decision::Aggregated {
+                            event_id: open.event_id.clone(),
+                        }
+                    }
+                    Entry::Occupied(mut entry) => {
+                        // Closed but not flushed yet
+                        let closed = entry.insert(window);
+                        if let Some(flush) = closed.flush() {
+                            self.closed.lock().unwrap().push(flush);
+                        }
+                        Decision::Store { window: Some(key) }
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert(window);
+                        Decision::Store { window: Some(key) }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Record the stored event a window counts its repeats into
+    pub fn opened(&self, window: &str, event_id: &str) {
+        if let Some(mut window) = self.windows.get_mut(window) {
+            window.event_id = Some(event_id.to_string());
+        }
+    }
+
+    /// Close windows that have ended, returning the repeats to write for
+    /// those that had any
+    pub fn expired(&self, now: DateTime<Utc>) -> Vec<Flush> {
+        let mut flushes = std::mem::take(&mut *self.closed.lock().unwrap());
+        self.windows.retain(|_, window| {
+            if window.closes > now {
+                return true;
+            }
+            flushes.extend(window.flush());
+            false
+        });
+        flushes
+    }
+
+    /// Drop sampling state for a sandbox that is no longer monitored
+    pub fn forget(&self, sandbox_id: &str) {
+        self.seen.retain(|(sandbox, _), _| sandbox != sandbox_id);
+    }
+}
+
+/// Events are identical when they agree on everything but their ID, time
+/// and attached metadata
+fn identity(event: &SecurityEvent) -> String {
+    format!(
+        "{}\0{}\0{}\0{}\0{}",
+        event.sandbox_id, event.event_type, event.severity, event.message, event.details
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{Fixture, FIXTURE_SANDBOX};
+    use crate::policies::PolicyEngine;
+
+    fn file_access(sandbox_id: &str, path: &str) -> SecurityEvent {
+        Fixture::FileAccess {
+            path: path.to_string(),
+            flags: "O_RDONLY".to_string(),
+            executable: "/bin/cat".to_string(),
+        }
+        .event(sandbox_id, None)
+    }
+
+    fn rule(mode: SamplingMode) -> SamplingRule {
+        SamplingRule {
+            event_type: EventType::FileAccess,
+            mode,
+        }
+    }
+
+    #[tokio::test]
+    async fn default_tiers_aggregate_or_keep_everything() {
+        let engine = PolicyEngine::new();
+        engine.load_default_policies().await.unwrap();
+        let sampler = Sampler::new();
+        let event = file_access(FIXTURE_SANDBOX, "/var/lib/app/data");
+
+        // Basic aggregates repeated reads
+        let basic = engine.sampling_rule("basic", &EventType::FileAccess);
+        assert_eq!(basic, Some(rule(SamplingMode::Aggregate { window_ms: 10_000 })));
+        assert!(matches!(
+            sampler.decide(&event, basic.as_ref()),
+            Decision::Store { window: Some(_) }
+        ));
+        assert!(matches!(
+            sampler.decide(&event, basic.as_ref()),
+            Decision::Aggregated { .. }
+        ));
+
+        // Shield has no rules, so every event is stored on its own
+        let shield = engine.sampling_rule("shield", &EventType::FileAccess);
+        assert_eq!(shield, None);
+        for _ in 0..3 {
+            assert_eq!(
+                sampler.decide(&event, shield.as_ref()),
+                Decision::Store { window: None }
+            );
+        }
+    }
+
+    #[test]
+    fn sampling_stores_one_in_n_per_sandbox() {
+        let sampler = Sampler::new();
+        let one_in_three = rule(SamplingMode::Sample { one_in: 3 });
+        let first = file_access("sandbox-a", "/tmp/a");
+        let second = file_access("sandbox-b", "/tmp/a");
+        let stored = Decision::Store { window: None };
+
+        let decisions: Vec<Decision> = (0..6)
+            .map(|_| sampler.decide(&first, Some(&one_in_three)))
+            .collect();
+        assert_eq!(
+            decisions,
+            vec![
+                stored.clone(),
+                Decision::SampledOut,
+                Decision::SampledOut,
+                stored.clone(),
+                Decision::SampledOut,
+                Decision::SampledOut,
+            ]
+        );
+
+        // Another sandbox counts from its own first event
+        assert_eq!(sampler.decide(&second, Some(&one_in_three)), stored);
+
+        // Forgetting a sandbox starts its count over
+        sampler.forget("sandbox-a");
+        assert_eq!(sampler.decide(&first, Some(&one_in_three)), stored);
+    }
+
+    #[test]
+    fn sampling_one_in_zero_keeps_everything() {
+        let sampler = Sampler::new();
+        let zero = rule(SamplingMode::Sample { one_in: 0 });
+        let event = file_access(FIXTURE_SANDBOX, "/tmp/a");
+        for _ in 0..3 {
+            assert_eq!(sampler.decide(&event, Some(&zero)), Decision::Store { window: None });
+        }
+    }
+
+    #[test]
+    fn aggregation_counts_repeats_into_the_stored_event() {
+        let sampler = Sampler::new();
+        let aggregate = rule(SamplingMode::Aggregate { window_ms: 60_000 });
+        let event = file_access(FIXTURE_SANDBOX, "/tmp/a");
+        let other = file_access(FIXTURE_SANDBOX, "/tmp/b");
+
+        let Decision::Store { window: Some(window) } = sampler.decide(&event, Some(&aggregate)) else {
+            panic!("the first event should open a window");
+        };
+        // Repeats before the event is stored have no record to point at
+        assert_eq!(
+            sampler.decide(&event, Some(&aggregate)),
+            Decision::Aggregated { event_id: None }
+        );
+        sampler.opened(&window, "event-1");
+        assert_eq!(
+            sampler.decide(&event, Some(&aggregate)),
+            Decision::Aggregated {
+                event_id: Some("event-1".to_string())
+            }
+        );
+        // A different event opens a window of its own
+        assert!(matches!(
+            sampler.decide(&other, Some(&aggregate)),
+            Decision::Store { window: Some(_) }
+        ));
+
+        // Nothing is flushed while the windows are open
+        assert!(sampler.expired(Utc::now()).is_empty());
+
+        // Once closed, only the window with repeats has anything to write
+        let later = Utc::now() + Duration::minutes(2);
+        let flushes = sampler.expired(later);
+        assert_eq!(flushes.len(), 1);
+        assert_eq!(flushes[0].event_id, "event-1");
+        assert_eq!(flushes[0].occurrences, 3);
+        assert!(sampler.expired(later).is_empty());
+    }
+
+    #[test]
+    fn a_closed_window_is_flushed_when_replaced() {
+        let sampler = Sampler::new();
+        let aggregate = rule(SamplingMode::Aggregate { window_ms: 0 });
+        let event = file_access(FIXTURE_SANDBOX, "/tmp/a");
+
+        let Decision::Store { window: Some(window) } = sampler.decide(&event, Some(&aggregate)) else {
+            panic!("the first event should open a window");
+        };
+        sampler.opened(&window, "event-1");
+        // A repeat counted while the window was open
+        sampler.windows.get_mut(&window).unwrap().occurrences += 1;
+        std::thread::sleep(std::time::Duration::from_millis(2));
+
+        // The next event opens a new window; the old one's repeats are kept
+        // until the next sweep
+        assert!(matches!(
+            sampler.decide(&event, Some(&aggregate)),
+            Decision::Store { window: Some(_) }
+        ));
+        let flushes = sampler.expired(Utc::now() - Duration::minutes(1));
+        assert_eq!(
+            flushes,
+            vec![Flush {
+                event_id: "event-1".to_string(),
+                occurrences: 2,
+                last_seen: event.timestamp,
+            }]
+        );
+    }
+}
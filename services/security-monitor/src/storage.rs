@@ -1,18 +1,82 @@
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use sqlx::{postgres::PgPool, PgConnection, Row};
+use sqlx::{postgres::{PgPool, PgRow}, PgConnection, Row};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use uuid::Uuid;
 
+use crate::encryption::FieldCipher;
+use crate::exports::Archive;
 use crate::models::*;
+use crate::payloads;
+
+/// Tables included in backups, in restore order
+pub const BACKUP_TABLES: &[&str] = &[
+    "security_policies",
+    "security_rules",
+    "event_payloads",
+    "security_events",
+    "quarantine_records",
+    "alerts",
+    "compliance_reports",
+    "provenance_records",
+    "metrics_aggregations",
+    "event_types",
+    "event_reveals",
+    "event_exports",
+    "action_runs",
+];
+
+/// Stands in for the details and metadata of events read back while column
+/// encryption is on; `POST /api/events/:id/reveal` returns them
+pub const CONCEALED: &str = "[ENCRYPTED]";
+
+/// Whether an error means Postgres couldn't be reached, rather than that it
+/// refused a query
+pub fn unreachable(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<sqlx::Error>().is_some_and(connection_failed)
+}
+
+pub fn connection_failed(error: &sqlx::Error) -> bool {
+    matches!(
+        error,
+        sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed
+    )
+}
 
 pub struct EventStore {
     pool: PgPool,
+    /// Encrypts event details and metadata at rest when set
+    cipher: Option<FieldCipher>,
 }
 
 impl EventStore {
     pub async fn new(database_url: &str) -> Result<Self> {
         let pool = PgPool::connect(database_url).await?;
-        Ok(Self { pool })
+        Ok(Self { pool, cipher: None })
+    }
+
+    pub fn with_cipher(mut self, cipher: FieldCipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Hide an event's details and metadata if they are encrypted at rest,
+    /// before it leaves the monitor other than through a reveal
+    pub fn conceal(&self, event: &mut SecurityEvent) {
+        if self.cipher.is_some() {
+            event.details = serde_json::Value::String(CONCEALED.to_string());
+            if event.metadata.is_some() {
+                event.metadata = Some(serde_json::Value::String(CONCEALED.to_string()));
+            }
+        }
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
     }
 
     pub async fn run_migrations(&self) -> Result<()> {
@@ -20,40 +84,74 @@ impl EventStore {
         Ok(())
     }
 
-    pub async fn store_event(&self, event: &SecurityEvent) -> Result<String> {
-        let event_id = Uuid::new_v4().to_string();
-        
-        sqlx::query!(
+    /// Store an event. Large fields of its details go to shared payloads,
+    /// unless they are encrypted: ciphertexts never repeat.
+    pub async fn store_event(&self, event_id: &str, event: &SecurityEvent) -> Result<()> {
+        let (split, metadata) = match &self.cipher {
+            Some(cipher) => (
+                payloads::SplitDetails {
+                    inline: cipher.seal(event_id, &event.details)?,
+                    ..Default::default()
+                },
+                event
+                    .metadata
+                    .as_ref()
+                    .map(|metadata| cipher.seal(event_id, metadata))
+                    .transpose()?,
+            ),
+            None => (payloads::split(&event.details), event.metadata.clone()),
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let now = Utc::now();
+        for (hash, body) in &split.payloads {
+            // Reuse only bumps last_used hourly, so hot payloads aren't
+            // rewritten by every event
+            sqlx::query(
+                "INSERT INTO event_payloads (hash, body, last_used) VALUES ($1, $2, $3)
+                 ON CONFLICT (hash) DO UPDATE SET last_used = EXCLUDED.last_used
+                 WHERE event_payloads.last_used < EXCLUDED.last_used - INTERVAL '1 hour'",
+            )
+            .bind(hash)
+            .bind(body)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query(
             r#"
             INSERT INTO security_events (
                 id, event_type, severity, timestamp, sandbox_id, provider,
-                message, details, metadata, falco_rule, ebpf_trace
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                message, details, details_refs, metadata, falco_rule, ebpf_trace,
+                run_id, occurrences, last_seen
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
             "#,
-            event_id,
-            event.event_type,
-            event.severity,
-            event.timestamp,
-            event.sandbox_id,
-            event.provider,
-            event.message,
-            &event.details,
-            &event.metadata,
-            event.falco_rule,
-            event.ebpf_trace
         )
-        .execute(&self.pool)
+        .bind(event_id)
+        .bind(event.event_type.as_str())
+        .bind(event.severity.as_str())
+        .bind(event.timestamp)
+        .bind(&event.sandbox_id)
+        .bind(&event.provider)
+        .bind(&event.message)
+        .bind(&split.inline)
+        .bind(&split.refs)
+        .bind(&metadata)
+        .bind(&event.falco_rule)
+        .bind(&event.ebpf_trace)
+        .bind(event.run_id)
+        .bind(event.occurrences as i64)
+        .bind(event.last_seen)
+        .execute(&mut *tx)
         .await?;
+        tx.commit().await?;
 
-        Ok(event_id)
+        Ok(())
     }
 
     pub async fn list_events(&self, query: EventQuery) -> Result<Vec<SecurityEvent>> {
-        let mut sql = String::from(
-            "SELECT id, event_type, severity, timestamp, sandbox_id, provider, 
-             message, details, metadata, falco_rule, ebpf_trace 
-             FROM security_events WHERE 1=1"
-        );
+        let mut sql = format!("SELECT {} FROM security_events WHERE 1=1", EVENT_COLUMNS);
         
         let mut bind_count = 0;
         
@@ -62,6 +160,11 @@ impl EventStore {
             sql.push_str(&format!(" AND sandbox_id = ${}", bind_count));
         }
         
+        if query.run_id.is_some() {
+            bind_count += 1;
+            sql.push_str(&format!(" AND run_id = ${}", bind_count));
+        }
+        
         if query.event_type.is_some() {
             bind_count += 1;
             sql.push_str(&format!(" AND event_type = ${}", bind_count));
@@ -99,11 +202,14 @@ impl EventStore {
         if let Some(ref sandbox_id) = query.sandbox_id {
             query_builder = query_builder.bind(sandbox_id);
         }
+        if let Some(run_id) = query.run_id {
+            query_builder = query_builder.bind(run_id);
+        }
         if let Some(ref event_type) = query.event_type {
-            query_builder = query_builder.bind(event_type);
+            query_builder = query_builder.bind(event_type.as_str());
         }
-        if let Some(ref severity) = query.severity {
-            query_builder = query_builder.bind(severity);
+        if let Some(severity) = query.severity {
+            query_builder = query_builder.bind(severity.as_str());
         }
         if let Some(start_time) = query.start_time {
             query_builder = query_builder.bind(start_time);
@@ -121,32 +227,114 @@ impl EventStore {
         let rows = query_builder.fetch_all(&self.pool).await?;
         
         let events = rows
-            .into_iter()
-            .map(|row| SecurityEvent {
-                id: row.get("id"),
-                event_type: row.get("event_type"),
-                severity: row.get("severity"),
-                timestamp: row.get("timestamp"),
-                sandbox_id: row.get("sandbox_id"),
-                provider: row.get("provider"),
-                message: row.get("message"),
-                details: row.get("details"),
-                metadata: row.get("metadata"),
-                falco_rule: row.get("falco_rule"),
-                ebpf_trace: row.get("ebpf_trace"),
-            })
-            .collect();
+            .iter()
+            .map(|row| self.event_from_row(row))
+            .collect::<Result<Vec<_>>>()?;
 
         Ok(events)
     }
 
+    /// A stored event, with its details and metadata decrypted
+    pub async fn get_event(&self, event_id: &str) -> Result<Option<SecurityEvent>> {
+        let row = sqlx::query(&format!("SELECT {} FROM security_events WHERE id = $1", EVENT_COLUMNS))
+        .bind(event_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|row| self.event_from_row(&row)).transpose()
+    }
+
+    fn event_from_row(&self, row: &PgRow) -> Result<SecurityEvent> {
+        let id: String = row.get("id");
+        let mut details: serde_json::Value = row.get("details");
+        let mut metadata: Option<serde_json::Value> = row.get("metadata");
+        if let Some(cipher) = &self.cipher {
+            details = cipher.open(&id, details)?;
+            metadata = metadata.map(|metadata| cipher.open(&id, metadata)).transpose()?;
+        }
+
+        Ok(SecurityEvent {
+            id,
+            event_type: parse_column(row, "event_type")?,
+            severity: parse_column(row, "severity")?,
+            timestamp: row.get("timestamp"),
+            sandbox_id: row.get("sandbox_id"),
+            provider: row.get("provider"),
+            message: row.get("message"),
+            details,
+            metadata,
+            falco_rule: row.get("falco_rule"),
+            ebpf_trace: row.get("ebpf_trace"),
+            run_id: row.get("run_id"),
+            occurrences: row.get::<i64, _>("occurrences") as u64,
+            last_seen: row.get("last_seen"),
+        })
+    }
+
+    /// Record who revealed an event's details, and why
+    pub async fn record_reveal(&self, event_id: &str, actor: &str, reason: &str) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO event_reveals (id, event_id, actor, reason, revealed_at) VALUES ($1, $2, $3, $4, $5)",
+            Uuid::new_v4().to_string(),
+            event_id,
+            actor,
+            reason,
+            Utc::now()
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Fold repeats of a stored event into its record
+    pub async fn record_occurrences(
+        &self,
+        event_id: &str,
+        occurrences: u64,
+        last_seen: DateTime<Utc>,
+    ) -> Result<()> {
+        sqlx::query!(
+            "UPDATE security_events SET occurrences = $1, last_seen = $2 WHERE id = $3",
+            occurrences as i64,
+            last_seen,
+            event_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Events of a sandbox since `since` by severity, counting aggregated
+    /// repeats
+    pub async fn count_events_by_severity(
+        &self,
+        sandbox_id: &str,
+        since: DateTime<Utc>,
+    ) -> Result<HashMap<Severity, u64>> {
+        let rows = sqlx::query(
+            "SELECT severity, SUM(occurrences)::BIGINT AS count FROM security_events
+             WHERE sandbox_id = $1 AND timestamp >= $2 GROUP BY severity",
+        )
+        .bind(sandbox_id)
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| Ok((parse_column(&row, "severity")?, row.get::<i64, _>("count") as u64)))
+            .collect()
+    }
+
     pub async fn store_quarantine(&self, record: &QuarantineRecord) -> Result<()> {
         sqlx::query!(
             r#"
             INSERT INTO quarantine_records (
                 id, sandbox_id, reason, triggered_by, start_time, end_time,
-                auto_release, release_conditions
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                auto_release, release_conditions, run_id, mode, approved_by,
+                scan_passed_at, released_on
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
             "#,
             record.id,
             record.sandbox_id,
@@ -155,7 +343,12 @@ impl EventStore {
             record.start_time,
             record.end_time,
             record.auto_release,
-            serde_json::to_value(&record.release_conditions)?
+            serde_json::to_value(&record.release_conditions)?,
+            record.run_id,
+            record.mode.as_str(),
+            record.approved_by,
+            record.scan_passed_at,
+            serde_json::to_value(record.released_on)?
         )
         .execute(&self.pool)
         .await?;
@@ -195,9 +388,14 @@ impl EventStore {
                 let triggered_by: SecurityEvent = serde_json::from_value(triggered_by)?;
                 
                 let release_conditions: Option<serde_json::Value> = row.get("release_conditions");
-                let release_conditions: Option<Vec<String>> = release_conditions
+                let release_conditions: Option<Vec<ReleaseCondition>> = release_conditions
                     .map(|v| serde_json::from_value(v))
                     .transpose()?;
+                let released_on: Option<serde_json::Value> = row.get("released_on");
+                let released_on: Option<ReleaseCondition> = released_on
+                    .map(|v| serde_json::from_value(v))
+                    .transpose()?
+                    .flatten();
 
                 Ok(QuarantineRecord {
                     id: row.get("id"),
@@ -208,6 +406,11 @@ impl EventStore {
                     end_time: row.get("end_time"),
                     auto_release: row.get("auto_release"),
                     release_conditions,
+                    run_id: row.get("run_id"),
+                    mode: parse_column(&row, "mode")?,
+                    approved_by: row.get("approved_by"),
+                    scan_passed_at: row.get("scan_passed_at"),
+                    released_on,
                 })
             })
             .collect::<Result<Vec<_>>>()?;
@@ -223,7 +426,7 @@ impl EventStore {
             ) VALUES ($1, $2, $3, $4, $5, $6)
             "#,
             alert.id,
-            alert.severity,
+            alert.severity.as_str(),
             alert.message,
             alert.timestamp,
             alert.sandbox_id,
@@ -243,6 +446,11 @@ impl EventStore {
         
         let mut bind_count = 0;
         
+        if query.sandbox_id.is_some() {
+            bind_count += 1;
+            sql.push_str(&format!(" AND sandbox_id = ${}", bind_count));
+        }
+        
         if let Some(acknowledged) = query.acknowledged {
             bind_count += 1;
             sql.push_str(&format!(" AND acknowledged = ${}", bind_count));
@@ -262,11 +470,14 @@ impl EventStore {
 
         let mut query_builder = sqlx::query(&sql);
         
+        if let Some(ref sandbox_id) = query.sandbox_id {
+            query_builder = query_builder.bind(sandbox_id);
+        }
         if let Some(acknowledged) = query.acknowledged {
             query_builder = query_builder.bind(acknowledged);
         }
-        if let Some(ref severity) = query.severity {
-            query_builder = query_builder.bind(severity);
+        if let Some(severity) = query.severity {
+            query_builder = query_builder.bind(severity.as_str());
         }
         if let Some(limit) = query.limit {
             query_builder = query_builder.bind(limit as i64);
@@ -276,15 +487,17 @@ impl EventStore {
         
         let alerts = rows
             .into_iter()
-            .map(|row| Alert {
-                id: row.get("id"),
-                severity: row.get("severity"),
-                message: row.get("message"),
-                timestamp: row.get("timestamp"),
-                sandbox_id: row.get("sandbox_id"),
-                acknowledged: row.get("acknowledged"),
+            .map(|row| {
+                Ok(Alert {
+                    id: row.get("id"),
+                    severity: parse_column(&row, "severity")?,
+                    message: row.get("message"),
+                    timestamp: row.get("timestamp"),
+                    sandbox_id: row.get("sandbox_id"),
+                    acknowledged: row.get("acknowledged"),
+                })
             })
-            .collect();
+            .collect::<Result<Vec<_>>>()?;
 
         Ok(alerts)
     }
@@ -300,6 +513,50 @@ impl EventStore {
         Ok(())
     }
 
+    /// Register a custom event type, or update its description
+    pub async fn store_event_type(&self, info: &EventTypeInfo) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO event_types (name, description, created_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (name) DO UPDATE SET description = EXCLUDED.description
+            "#,
+            info.name.as_str(),
+            info.description,
+            info.created_at
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_event_type(&self, event_type: &EventType) -> Result<()> {
+        sqlx::query!("DELETE FROM event_types WHERE name = $1", event_type.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Registered custom event types
+    pub async fn list_event_types(&self) -> Result<Vec<EventTypeInfo>> {
+        let rows = sqlx::query("SELECT name, description, created_at FROM event_types ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(EventTypeInfo {
+                    name: parse_column(&row, "name")?,
+                    description: row.get("description"),
+                    built_in: false,
+                    created_at: row.get("created_at"),
+                })
+            })
+            .collect()
+    }
+
     pub async fn aggregate_old_events(&self) -> Result<u64> {
         // This would implement event aggregation logic
         // For now, just return 0
@@ -309,13 +566,344 @@ impl EventStore {
     pub async fn cleanup_old_events(&self, retention_days: i32) -> Result<u64> {
         let cutoff = Utc::now() - chrono::Duration::days(retention_days as i64);
         
-        let result = sqlx::query!(
-            "DELETE FROM security_events WHERE timestamp < $1",
-            cutoff
+        // Rows a running export covers are kept until it has read them
+        let result = sqlx::query(
+            "DELETE FROM security_events e WHERE e.timestamp < $1 AND NOT EXISTS (
+                 SELECT 1 FROM event_exports x WHERE x.status = 'running'
+                 AND (x.sandbox_id IS NULL OR x.sandbox_id = e.sandbox_id)
+                 AND (x.start_time IS NULL OR e.timestamp >= x.start_time)
+                 AND e.timestamp <= x.end_time
+             )",
+        )
+        .bind(cutoff)
+        .execute(&self.pool)
+        .await?;
+
+        // Payloads go once no event refers to them. Ones used in the last
+        // day are kept, so a payload isn't deleted between an event storing
+        // it and the event itself being written.
+        sqlx::query(
+            "DELETE FROM event_payloads p WHERE p.last_used < $1 AND NOT EXISTS (
+                 SELECT 1 FROM security_events e, jsonb_each_text(e.details_refs) r
+                 WHERE r.value = p.hash
+             )",
         )
+        .bind(Utc::now() - chrono::Duration::days(1))
         .execute(&self.pool)
         .await?;
 
         Ok(result.rows_affected())
     }
-}
\ No newline at end of file
+
+    pub async fn store_export(&self, export: &LegalExport) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO event_exports (
+                 id, status, sandbox_id, start_time, end_time, requested_by,
+                 reason, created_at
+             ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(&export.id)
+        .bind(export.status.as_str())
+        .bind(&export.sandbox_id)
+        .bind(export.start_time)
+        .bind(export.end_time)
+        .bind(&export.requested_by)
+        .bind(&export.reason)
+        .bind(export.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record how an export ended, keeping its archive if it was built
+    pub async fn finish_export(&self, export_id: &str, result: &Result<Archive>) -> Result<()> {
+        let query = match result {
+            Ok(archive) => sqlx::query(
+                "UPDATE event_exports SET status = 'completed', finished_at = $2,
+                 records = $3, head_hash = $4, signature = $5, archive = $6 WHERE id = $1",
+            )
+            .bind(export_id)
+            .bind(Utc::now())
+            .bind(archive.records as i64)
+            .bind(&archive.head_hash)
+            .bind(&archive.signature)
+            .bind(&archive.contents),
+            Err(e) => sqlx::query(
+                "UPDATE event_exports SET status = 'failed', finished_at = $2, error = $3 WHERE id = $1",
+            )
+            .bind(export_id)
+            .bind(Utc::now())
+            .bind(format!("{:#}", e)),
+        };
+        query.execute(&self.pool).await?;
+
+        Ok(())
+    }
+
+    /// Fail exports a restart interrupted, so they stop holding back
+    /// retention cleanup
+    pub async fn fail_interrupted_exports(&self) -> Result<u64> {
+        let result = sqlx::query(
+            "UPDATE event_exports SET status = 'failed', finished_at = $1,
+             error = 'interrupted by a restart' WHERE status = 'running'",
+        )
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn get_export(&self, export_id: &str) -> Result<Option<LegalExport>> {
+        let row = sqlx::query(&format!("SELECT {} FROM event_exports WHERE id = $1", EXPORT_COLUMNS))
+            .bind(export_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.map(|row| export_from_row(&row)).transpose()
+    }
+
+    /// Exports, newest first
+    pub async fn list_exports(&self) -> Result<Vec<LegalExport>> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM event_exports ORDER BY created_at DESC",
+            EXPORT_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(export_from_row).collect()
+    }
+
+    /// A completed export's archive
+    pub async fn export_archive(&self, export_id: &str) -> Result<Option<Vec<u8>>> {
+        let archive: Option<Option<Vec<u8>>> = sqlx::query_scalar("SELECT archive FROM event_exports WHERE id = $1")
+            .bind(export_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(archive.flatten())
+    }
+
+    pub async fn store_action_run(&self, run: &ActionRun) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO action_runs (
+                 id, action, rule, sandbox_id, event_id, status, attempts,
+                 parameters, result, error, started_at, finished_at
+             ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+        )
+        .bind(&run.id)
+        .bind(&run.action)
+        .bind(&run.rule)
+        .bind(&run.sandbox_id)
+        .bind(&run.event_id)
+        .bind(run.status.as_str())
+        .bind(run.attempts as i32)
+        .bind(&run.parameters)
+        .bind(&run.result)
+        .bind(&run.error)
+        .bind(run.started_at)
+        .bind(run.finished_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Action runs matching the query, newest first
+    pub async fn list_action_runs(&self, query: &ActionRunQuery) -> Result<Vec<ActionRun>> {
+        let rows = sqlx::query(
+            "SELECT * FROM action_runs
+             WHERE ($1::text IS NULL OR sandbox_id = $1)
+               AND ($2::text IS NULL OR action = $2)
+               AND ($3::text IS NULL OR status = $3)
+             ORDER BY finished_at DESC LIMIT $4",
+        )
+        .bind(&query.sandbox_id)
+        .bind(&query.action)
+        .bind(query.status.map(|status| status.as_str()))
+        .bind(query.limit.unwrap_or(100) as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(ActionRun {
+                    id: row.get("id"),
+                    action: row.get("action"),
+                    rule: row.get("rule"),
+                    sandbox_id: row.get("sandbox_id"),
+                    event_id: row.get("event_id"),
+                    status: parse_column(row, "status")?,
+                    attempts: row.get::<i32, _>("attempts") as u32,
+                    parameters: row.get("parameters"),
+                    result: row.get("result"),
+                    error: row.get("error"),
+                    started_at: row.get("started_at"),
+                    finished_at: row.get("finished_at"),
+                })
+            })
+            .collect()
+    }
+
+    pub async fn cleanup_old_action_runs(&self, retention_days: i32) -> Result<u64> {
+        let cutoff = Utc::now() - chrono::Duration::days(retention_days as i64);
+        let result = sqlx::query("DELETE FROM action_runs WHERE finished_at < $1")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Delete the events, alerts, quarantines, action runs, compliance
+    /// reports and provenance records of some sandboxes, with the payloads
+    /// only their events used. Returns the rows removed by table.
+    pub async fn delete_sandbox_data(&self, sandbox_ids: &[String]) -> Result<BTreeMap<String, u64>> {
+        let mut tx = self.pool.begin().await?;
+        let mut removed = BTreeMap::new();
+
+        let refs: Vec<Option<serde_json::Value>> =
+            sqlx::query_scalar("DELETE FROM security_events WHERE sandbox_id = ANY($1) RETURNING details_refs")
+                .bind(sandbox_ids)
+                .fetch_all(&mut *tx)
+                .await?;
+        removed.insert("security_events".to_string(), refs.len() as u64);
+
+        let hashes: Vec<String> = refs
+            .iter()
+            .flatten()
+            .filter_map(|refs| refs.as_object())
+            .flat_map(|refs| refs.values().filter_map(|hash| hash.as_str().map(str::to_string)))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let payloads = sqlx::query(
+            "DELETE FROM event_payloads p WHERE p.hash = ANY($1) AND NOT EXISTS (
+                 SELECT 1 FROM security_events e, jsonb_each_text(e.details_refs) r
+                 WHERE r.value = p.hash
+             )",
+        )
+        .bind(&hashes)
+        .execute(&mut *tx)
+        .await?;
+        removed.insert("event_payloads".to_string(), payloads.rows_affected());
+
+        for table in [
+            "alerts",
+            "quarantine_records",
+            "action_runs",
+            "compliance_reports",
+            "provenance_records",
+        ] {
+            let deleted = sqlx::query(&format!("DELETE FROM {} WHERE sandbox_id = ANY($1)", table))
+                .bind(sandbox_ids)
+                .execute(&mut *tx)
+                .await?;
+            removed.insert(table.to_string(), deleted.rows_affected());
+        }
+
+        tx.commit().await?;
+        Ok(removed)
+    }
+}
+
+/// Columns of an event, with its details put back together
+const EVENT_COLUMNS: &str = "id, event_type, severity, timestamp, sandbox_id, provider, message,
+    event_details(details, details_refs) AS details, metadata, falco_rule, ebpf_trace, run_id,
+    occurrences, last_seen";
+
+/// Columns of an export, without its archive
+const EXPORT_COLUMNS: &str = "id, status, sandbox_id, start_time, end_time, requested_by, reason,
+    created_at, finished_at, records, head_hash, signature, error";
+
+fn export_from_row(row: &PgRow) -> Result<LegalExport> {
+    Ok(LegalExport {
+        id: row.get("id"),
+        status: parse_column(row, "status")?,
+        sandbox_id: row.get("sandbox_id"),
+        start_time: row.get("start_time"),
+        end_time: row.get("end_time"),
+        requested_by: row.get("requested_by"),
+        reason: row.get("reason"),
+        created_at: row.get("created_at"),
+        finished_at: row.get("finished_at"),
+        records: row.get::<i64, _>("records") as u64,
+        head_hash: row.get("head_hash"),
+        signature: row.get("signature"),
+        error: row.get("error"),
+    })
+}
+
+/// A text column holding a severity or event type. Rows are normalized by
+/// the taxonomy migration, so a value that doesn't parse is corrupt.
+fn parse_column<T>(row: &sqlx::postgres::PgRow, column: &str) -> Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let value: String = row.get(column);
+    Ok(value.parse()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::Fixture;
+    use serde_json::json;
+
+    /// The migrated database the query macros are checked against
+    async fn store() -> EventStore {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must name a migrated database");
+        EventStore::new(&url).await.unwrap()
+    }
+
+    fn event(sandbox_id: &str, details: serde_json::Value) -> SecurityEvent {
+        Fixture::Custom {
+            event_type: EventType::ProcessSpawn,
+            message: String::new(),
+            details,
+        }
+        .event(sandbox_id, None)
+    }
+
+    #[tokio::test]
+    async fn shared_details_are_stored_once_and_reassembled() {
+        let store = store().await;
+        let sandbox_id = format!("payloads-{}", Uuid::new_v4());
+        // Naming the sandbox keeps the payload this test's own
+        let tree = json!({ "pid": 1, "children": vec![sandbox_id.clone(); 8] });
+        let first = event(&sandbox_id, json!({ "tree": tree, "small": ["sh"], "n": 1 }));
+        let second = event(&sandbox_id, json!({ "tree": tree, "small": ["sh"], "n": 2 }));
+        let ids = [Uuid::new_v4().to_string(), Uuid::new_v4().to_string()];
+        store.store_event(&ids[0], &first).await.unwrap();
+        store.store_event(&ids[1], &second).await.unwrap();
+
+        for (id, event) in ids.iter().zip([&first, &second]) {
+            let stored = store.get_event(id).await.unwrap().unwrap();
+            assert_eq!(stored.details, event.details);
+        }
+
+        let refs: Vec<Option<serde_json::Value>> =
+            sqlx::query_scalar("SELECT details_refs FROM security_events WHERE sandbox_id = $1")
+                .bind(&sandbox_id)
+                .fetch_all(store.pool())
+                .await
+                .unwrap();
+        assert_eq!(refs.len(), 2);
+        assert_eq!(refs[0], refs[1]);
+        let refs = refs[0].clone().unwrap();
+        assert_eq!(refs.as_object().unwrap().keys().collect::<Vec<_>>(), ["tree"]);
+        let payloads: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM event_payloads WHERE hash = $1")
+            .bind(refs["tree"].as_str())
+            .fetch_one(store.pool())
+            .await
+            .unwrap();
+        assert_eq!(payloads, 1);
+
+        let removed = store.delete_sandbox_data(&[sandbox_id]).await.unwrap();
+        assert_eq!(removed["security_events"], 2);
+        assert_eq!(removed["event_payloads"], 1);
+    }
+}
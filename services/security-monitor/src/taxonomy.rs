@@ -0,0 +1,190 @@
+use chrono::Utc;
+use dashmap::DashMap;
+
+use crate::models::*;
+
+/// Custom event types that events and rules may use, besides the built-in
+/// ones
+pub struct EventTypeRegistry {
+    custom: DashMap<EventType, EventTypeInfo>,
+}
+
+impl EventTypeRegistry {
+    pub fn new() -> Self {
+        Self {
+            custom: DashMap::new(),
+        }
+    }
+
+    /// Restore registrations loaded from storage
+    pub fn load(&self, registered: Vec<EventTypeInfo>) {
+        for info in registered {
+            self.custom.insert(info.name.clone(), info);
+        }
+    }
+
+    /// Register a custom type, or update its description
+    pub fn register(&self, request: EventTypeRequest) -> Result<EventTypeInfo, String> {
+        if !request.name.is_custom() {
+            return Err(format!("{} is a built-in event type", request.name));
+        }
+        let created_at = self
+            .custom
+            .get(&request.name)
+            .and_then(|info| info.created_at)
+            .unwrap_or_else(Utc::now);
+        let info = EventTypeInfo {
+            name: request.name,
+            description: request.description,
+            built_in: false,
+            created_at: Some(created_at),
+        };
+        self.custom.insert(info.name.clone(), info.clone());
+        Ok(info)
+    }
+
+    pub fn unregister(&self, event_type: &EventType) -> Option<EventTypeInfo> {
+        self.custom.remove(event_type).map(|(_, info)| info)
+    }
+
+    /// Built-in types, then custom ones by name
+    pub fn list(&self) -> Vec<EventTypeInfo> {
+        let mut custom: Vec<_> = self.custom.iter().map(|info| info.clone()).collect();
+        custom.sort_by(|a, b| a.name.cmp(&b.name));
+
+        EventType::BUILT_IN
+            .iter()
+            .map(|event_type| EventTypeInfo {
+                name: event_type.clone(),
+                description: None,
+                built_in: true,
+                created_at: None,
+            })
+            .chain(custom)
+            .collect()
+    }
+
+    /// Reject custom types that aren't registered, suggesting the closest
+    /// known name
+    pub fn check(&self, event_type: &EventType) -> Result<(), String> {
+        if !event_type.is_custom() || self.custom.contains_key(event_type) {
+            return Ok(());
+        }
+
+        let name = event_type.as_str();
+        let closest = self
+            .list()
+            .into_iter()
+            .map(|info| info.name)
+            .min_by_key(|known| edit_distance(name, known.as_str()))
+            .filter(|known| edit_distance(name, known.as_str()) <= 3);
+        Err(match closest {
+            Some(known) => format!("unknown event type {}; did you mean {}?", name, known),
+            None => format!("unknown event type {}; register it at /api/event-types first", name),
+        })
+    }
+
+    /// Every event type a policy's rules and sampling refer to must be known
+    pub fn check_policy(&self, policy: &SecurityPolicy) -> Result<(), String> {
+        policy
+            .rules
+            .iter()
+            .filter_map(|rule| rule.condition.event_type.as_ref())
+            .chain(policy.sampling.iter().map(|rule| &rule.event_type))
+            .try_for_each(|event_type| self.check(event_type))
+    }
+}
+
+/// Levenshtein distance between two names
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom(name: &str) -> EventType {
+        EventType::Custom(name.to_string())
+    }
+
+    fn request(name: &str, description: Option<&str>) -> EventTypeRequest {
+        EventTypeRequest {
+            name: custom(name),
+            description: description.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn registering_keeps_the_first_creation_time() {
+        let registry = EventTypeRegistry::new();
+        let first = registry.register(request("gpu.abuse", None)).unwrap();
+        let updated = registry
+            .register(request("gpu.abuse", Some("Mining on a GPU sandbox")))
+            .unwrap();
+        assert_eq!(updated.created_at, first.created_at);
+        assert_eq!(updated.description.as_deref(), Some("Mining on a GPU sandbox"));
+        assert!(!updated.built_in);
+
+        let rejected = registry.register(EventTypeRequest {
+            name: EventType::FileAccess,
+            description: None,
+        });
+        assert_eq!(rejected.unwrap_err(), "file_access is a built-in event type");
+    }
+
+    #[test]
+    fn lists_built_in_types_then_custom_ones_by_name() {
+        let registry = EventTypeRegistry::new();
+        registry.register(request("zeta", None)).unwrap();
+        registry.register(request("alpha", None)).unwrap();
+
+        let names: Vec<EventType> = registry.list().into_iter().map(|info| info.name).collect();
+        assert_eq!(names.len(), EventType::BUILT_IN.len() + 2);
+        assert_eq!(&names[..EventType::BUILT_IN.len()], &EventType::BUILT_IN[..]);
+        assert_eq!(&names[EventType::BUILT_IN.len()..], &[custom("alpha"), custom("zeta")]);
+
+        registry.unregister(&custom("alpha"));
+        assert_eq!(registry.list().len(), EventType::BUILT_IN.len() + 1);
+    }
+
+    #[test]
+    fn unknown_custom_types_are_rejected_with_a_suggestion() {
+        let registry = EventTypeRegistry::new();
+        registry.register(request("gpu.abuse", None)).unwrap();
+
+        assert_eq!(registry.check(&EventType::ProcessSpawn), Ok(()));
+        assert_eq!(registry.check(&custom("gpu.abuse")), Ok(()));
+        assert_eq!(
+            registry.check(&custom("gpu.abuze")),
+            Err("unknown event type gpu.abuze; did you mean gpu.abuse?".to_string())
+        );
+        assert_eq!(
+            registry.check(&custom("file_acess")),
+            Err("unknown event type file_acess; did you mean file_access?".to_string())
+        );
+        assert_eq!(
+            registry.check(&custom("kernel.module")),
+            Err("unknown event type kernel.module; register it at /api/event-types first".to_string())
+        );
+    }
+
+    #[test]
+    fn edit_distance_counts_single_character_edits() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("file_access", "file_acess"), 1);
+    }
+}
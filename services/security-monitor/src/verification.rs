@@ -0,0 +1,266 @@
+//! Checks that an ingested event refers to a sandbox the gateway created,
+//! for the tenant the reporter acts for, on the runtime the event names.
+//! Without this, anyone able to post events could pin violations on
+//! another tenant's sandbox and get it quarantined.
+
+use anyhow::Result;
+use dashmap::DashMap;
+use sandstorm_types::sandbox::SandboxOwner;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::models::*;
+
+/// How long the gateway's answer about a sandbox is reused
+const OWNER_TTL: Duration = Duration::from_secs(60);
+
+/// Shorter for sandboxes the gateway didn't know, which may have been
+/// created since
+const UNKNOWN_TTL: Duration = Duration::from_secs(5);
+
+/// Provider of events the gateway reports before picking a runtime
+const GATEWAY_PROVIDER: &str = "gateway";
+
+/// What ingest does with events that fail verification
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationMode {
+    /// Take events as they come
+    Off,
+    /// Store the event marked with why it failed, but take no action on it
+    #[default]
+    Flag,
+    /// Refuse the event
+    Reject,
+}
+
+/// Why an event failed verification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The gateway has no such sandbox
+    UnknownSandbox,
+    /// The sandbox belongs to a different tenant than the reporter's
+    ForeignTenant,
+    /// The sandbox runs on a different runtime than the event names
+    WrongProvider { runtime: String },
+}
+
+impl Mismatch {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mismatch::UnknownSandbox => "unknown_sandbox",
+            Mismatch::ForeignTenant => "foreign_tenant",
+            Mismatch::WrongProvider { .. } => "provider_mismatch",
+        }
+    }
+
+    pub fn describe(&self, event: &SecurityEvent, tenant: Option<&str>) -> String {
+        match self {
+            Mismatch::UnknownSandbox => format!("sandbox {} is not known to the gateway", event.sandbox_id),
+            Mismatch::ForeignTenant => format!(
+                "sandbox {} does not belong to tenant {}",
+                event.sandbox_id,
+                tenant.unwrap_or_default()
+            ),
+            Mismatch::WrongProvider { runtime } => format!(
+                "sandbox {} runs on {}, not {}",
+                event.sandbox_id, runtime, event.provider
+            ),
+        }
+    }
+}
+
+/// Looks sandboxes up in the gateway that created them, caching the answers
+pub struct OwnershipVerifier {
+    http: reqwest::Client,
+    owners: DashMap<String, (Instant, Option<SandboxOwner>)>,
+}
+
+impl OwnershipVerifier {
+    pub fn new() -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .unwrap_or_default(),
+            owners: DashMap::new(),
+        }
+    }
+
+    /// Check an event reported by `tenant`, or by an agent acting for no
+    /// tenant in particular, whose events only need a known sandbox on the
+    /// right runtime
+    pub async fn verify(
+        &self,
+        gateway_url: &str,
+        event: &SecurityEvent,
+        tenant: Option<&str>,
+    ) -> Result<Option<Mismatch>> {
+        let Some(owner) = self.owner(gateway_url, &event.sandbox_id).await? else {
+            return Ok(Some(Mismatch::UnknownSandbox));
+        };
+        if let Some(tenant) = tenant {
+            if owner.tenant.as_deref() != Some(tenant) {
+                return Ok(Some(Mismatch::ForeignTenant));
+            }
+        }
+        let runtime = serde_json::to_value(owner.runtime_type)?
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        if event.provider != runtime && event.provider != GATEWAY_PROVIDER {
+            return Ok(Some(Mismatch::WrongProvider { runtime }));
+        }
+        Ok(None)
+    }
+
+    /// Drop the cached answer for a sandbox, e.g. once it is destroyed
+    pub fn forget(&self, sandbox_id: &str) {
+        self.owners.remove(sandbox_id);
+    }
+
+    async fn owner(&self, gateway_url: &str, sandbox_id: &str) -> Result<Option<SandboxOwner>> {
+        if let Some(entry) = self.owners.get(sandbox_id) {
+            let (fetched_at, owner) = entry.value();
+            let ttl = if owner.is_some() { OWNER_TTL } else { UNKNOWN_TTL };
+            if fetched_at.elapsed() < ttl {
+                return Ok(owner.clone());
+            }
+        }
+
+        // Not a gateway sandbox ID at all; no need to ask
+        if sandbox_id.parse::<uuid::Uuid>().is_err() {
+            return Ok(None);
+        }
+        let response = self
+            .http
+            .get(format!(
+                "{}/v1/sandboxes/{}/owner",
+                gateway_url.trim_end_matches('/'),
+                sandbox_id
+            ))
+            .send()
+            .await?;
+        let owner = if response.status() == reqwest::StatusCode::NOT_FOUND {
+            None
+        } else {
+            Some(response.error_for_status()?.json::<SandboxOwner>().await?)
+        };
+        self.owners
+            .insert(sandbox_id.to_string(), (Instant::now(), owner.clone()));
+        Ok(owner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::Fixture;
+    use sandstorm_types::sandbox::RuntimeType;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    /// A gateway that knows one gVisor sandbox of tenant `acme`, counting
+    /// the lookups it answers
+    async fn gateway(sandbox_id: Uuid) -> (String, Arc<AtomicUsize>) {
+        use axum::{extract::Path, http::StatusCode, Json};
+
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let seen = lookups.clone();
+        let app = axum::Router::new().route(
+            "/v1/sandboxes/:id/owner",
+            axum::routing::get(move |Path(id): Path<Uuid>| async move {
+                seen.fetch_add(1, Ordering::SeqCst);
+                if id != sandbox_id {
+                    return Err(StatusCode::NOT_FOUND);
+                }
+                Ok(Json(SandboxOwner {
+                    sandbox_id,
+                    tenant: Some("acme".to_string()),
+                    runtime_type: RuntimeType::Gvisor,
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{}/", address), lookups)
+    }
+
+    fn event(sandbox_id: &str, provider: &str) -> SecurityEvent {
+        let mut event = Fixture::ProcessSpawn {
+            command: "/bin/sh".to_string(),
+            args: Vec::new(),
+        }
+        .event(sandbox_id, None);
+        event.provider = provider.to_string();
+        event
+    }
+
+    #[tokio::test]
+    async fn events_must_match_the_sandbox_owner() {
+        let sandbox_id = Uuid::new_v4();
+        let (url, _) = gateway(sandbox_id).await;
+        let verifier = OwnershipVerifier::new();
+        let id = sandbox_id.to_string();
+
+        let verify = |event: SecurityEvent, tenant: Option<&'static str>| {
+            let verifier = &verifier;
+            let url = url.clone();
+            async move { verifier.verify(&url, &event, tenant).await.unwrap() }
+        };
+        assert_eq!(verify(event(&id, "gvisor"), Some("acme")).await, None);
+        assert_eq!(verify(event(&id, "gateway"), Some("acme")).await, None);
+        // Agents report for no tenant in particular
+        assert_eq!(verify(event(&id, "gvisor"), None).await, None);
+        assert_eq!(
+            verify(event(&id, "gvisor"), Some("globex")).await,
+            Some(Mismatch::ForeignTenant)
+        );
+        assert_eq!(
+            verify(event(&id, "firecracker"), Some("acme")).await,
+            Some(Mismatch::WrongProvider {
+                runtime: "gvisor".to_string()
+            })
+        );
+        assert_eq!(
+            verify(event(&Uuid::new_v4().to_string(), "gvisor"), Some("acme")).await,
+            Some(Mismatch::UnknownSandbox)
+        );
+    }
+
+    #[tokio::test]
+    async fn gateway_answers_are_cached_until_forgotten() {
+        let sandbox_id = Uuid::new_v4();
+        let (url, lookups) = gateway(sandbox_id).await;
+        let verifier = OwnershipVerifier::new();
+        let known = event(&sandbox_id.to_string(), "gvisor");
+        let unknown = event(&Uuid::new_v4().to_string(), "gvisor");
+
+        for _ in 0..3 {
+            verifier.verify(&url, &known, None).await.unwrap();
+            verifier.verify(&url, &unknown, None).await.unwrap();
+        }
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+
+        verifier.forget(&known.sandbox_id);
+        verifier.verify(&url, &known, None).await.unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn ids_the_gateway_could_not_have_issued_are_not_looked_up() {
+        let (url, lookups) = gateway(Uuid::new_v4()).await;
+        let verifier = OwnershipVerifier::new();
+        let forged = event("../../admin", "gvisor");
+
+        let mismatch = verifier.verify(&url, &forged, Some("acme")).await.unwrap();
+        assert_eq!(mismatch, Some(Mismatch::UnknownSandbox));
+        assert_eq!(
+            mismatch.unwrap().describe(&forged, Some("acme")),
+            "sandbox ../../admin is not known to the gateway"
+        );
+        assert_eq!(lookups.load(Ordering::SeqCst), 0);
+    }
+}
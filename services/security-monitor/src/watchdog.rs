@@ -0,0 +1,529 @@
+//! Host watchdog for sandbox escapes. Sandbox monitors watch what happens
+//! inside a sandbox; the watchdog watches the host around them, where a
+//! sandbox's processes and files should never turn up. It reports:
+//!
+//! - processes started by a runtime (runsc, Kata's shim and hypervisors,
+//!   Firecracker and its jailer) that aren't part of the runtime and run
+//!   outside every sandbox cgroup
+//! - mounts of a sandbox's files on host paths outside the runtimes' state
+//!   directories
+//!
+//! Processes are found by scanning `/proc`, and from the audit log when one
+//! is configured, which catches execs that exit between scans. Findings are
+//! tied to their sandbox by the sandbox ID the runtimes put in command
+//! lines, cgroup paths and state paths.
+
+use chrono::Utc;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::models::{EventType, SecurityEvent, Severity};
+
+/// Audit rule key the watchdog reads exec records for, as in
+/// `auditctl -a always,exit -F arch=b64 -S execve -k sandstorm-escape`
+pub const AUDIT_KEY: &str = "sandstorm-escape";
+
+/// Parent of every sandbox cgroup the gateway creates
+const SANDBOX_CGROUP_PARENT: &str = "/sandstorm/";
+
+/// Parents followed looking for the runtime that started a process
+const MAX_ANCESTORS: usize = 64;
+
+/// Executables of the runtimes, and the runtime each belongs to
+const RUNTIME_EXECUTABLES: &[(&str, &str)] = &[
+    ("runsc", "gvisor"),
+    ("kata-runtime", "kata"),
+    ("containerd-shim-kata-v2", "kata"),
+    ("qemu-system-x86_64", "kata"),
+    ("qemu-system-aarch64", "kata"),
+    ("cloud-hypervisor", "kata"),
+    ("virtiofsd", "kata"),
+    ("jailer", "firecracker"),
+    ("firecracker", "firecracker"),
+];
+
+/// Where the gateway and the runtimes keep sandbox state
+pub fn default_state_dirs() -> Vec<String> {
+    ["/var/lib/sandstorm", "/run/runsc", "/run/kata-containers", "/run/vc", "/run/netns"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+/// A possible escape, tied to the sandbox it came from
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub sandbox_id: Uuid,
+    /// Runtime the sandbox runs on, when known
+    pub runtime: Option<&'static str>,
+    pub message: String,
+    pub details: serde_json::Value,
+}
+
+impl Finding {
+    /// A critical `sandbox_escape` event for the finding
+    pub fn into_event(self, run_id: Option<Uuid>) -> SecurityEvent {
+        SecurityEvent {
+            id: Uuid::new_v4().to_string(),
+            event_type: EventType::SandboxEscape,
+            severity: Severity::Critical,
+            timestamp: Utc::now(),
+            sandbox_id: self.sandbox_id.to_string(),
+            provider: self.runtime.unwrap_or("host").to_string(),
+            message: self.message,
+            details: self.details,
+            metadata: None,
+            falco_rule: None,
+            ebpf_trace: None,
+            run_id,
+            occurrences: 1,
+            last_seen: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Process {
+    pid: u32,
+    ppid: u32,
+    /// Clock ticks after boot the process started, telling a reused PID
+    /// apart; 0 for processes known only from the audit log
+    start_time: u64,
+    exe: String,
+    /// cgroup v2 path, empty when unknown
+    cgroup: String,
+}
+
+#[derive(Debug, Clone)]
+struct Mount {
+    id: u64,
+    root: String,
+    mount_point: String,
+    fs_type: String,
+    source: String,
+    options: String,
+}
+
+#[derive(Default)]
+pub struct HostWatchdog {
+    /// Processes already checked, by PID and start time
+    seen: HashSet<(u32, u64)>,
+    /// Mounts present at the first scan or checked since, by mount ID
+    mounts: Option<HashSet<u64>>,
+    /// How far into the audit log records have been read
+    audit_offset: Option<u64>,
+}
+
+impl HostWatchdog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Possible escapes since the last scan. Mounts present at the first
+    /// scan are taken as the host's own; processes are checked whenever
+    /// they were started.
+    pub fn scan(&mut self, state_dirs: &[String], audit_log: Option<&Path>) -> Vec<Finding> {
+        let processes = processes();
+        let mut findings = Vec::new();
+
+        if let Some(audit_log) = audit_log {
+            for exec in self.audit_execs(audit_log) {
+                // Don't report it again if it's still running
+                if let Some(process) = processes.get(&exec.pid) {
+                    self.seen.insert((process.pid, process.start_time));
+                }
+                findings.extend(process_finding(&exec, &processes, "audit"));
+            }
+        }
+        for process in processes.values() {
+            if self.seen.insert((process.pid, process.start_time)) {
+                findings.extend(process_finding(process, &processes, "procfs"));
+            }
+        }
+        self.seen.retain(|(pid, start_time)| {
+            processes
+                .get(pid)
+                .is_some_and(|process| process.start_time == *start_time)
+        });
+
+        findings.extend(self.mount_findings(state_dirs));
+        findings
+    }
+
+    fn mount_findings(&mut self, state_dirs: &[String]) -> Vec<Finding> {
+        let mounts = mounts();
+        let current: HashSet<u64> = mounts.iter().map(|mount| mount.id).collect();
+        let Some(known) = &mut self.mounts else {
+            self.mounts = Some(current);
+            return Vec::new();
+        };
+
+        let mut findings = Vec::new();
+        for mount in &mounts {
+            if !known.insert(mount.id) || inside(&mount.mount_point, state_dirs) {
+                continue;
+            }
+            // A sandbox's own directories are named after it; mounts named
+            // after their source, like a kubelet's pod volumes, aren't it
+            let Some(sandbox_id) = [&mount.root, &mount.source, &mount.options]
+                .into_iter()
+                .find_map(|text| sandbox_id_in(text))
+                .filter(|id| sandbox_id_in(&mount.mount_point) != Some(*id))
+            else {
+                continue;
+            };
+            findings.push(Finding {
+                sandbox_id,
+                runtime: None,
+                message: format!(
+                    "Possible sandbox escape: files of sandbox {} mounted on host path {}",
+                    sandbox_id, mount.mount_point
+                ),
+                details: serde_json::json!({
+                    "signal": "host_mount",
+                    "source": "mountinfo",
+                    "mount_point": mount.mount_point,
+                    "mount_source": mount.source,
+                    "root": mount.root,
+                    "fs_type": mount.fs_type,
+                }),
+            });
+        }
+        known.retain(|id| current.contains(id));
+        findings
+    }
+
+    /// Exec records added to the audit log since the last read. The first
+    /// read starts at the end of the log, and a log that shrank was rotated
+    /// and is read from the start.
+    fn audit_execs(&mut self, path: &Path) -> Vec<Process> {
+        let mut file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Cannot read audit log {}: {}", path.display(), e);
+                return Vec::new();
+            }
+        };
+        let len = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        let offset = match self.audit_offset {
+            Some(offset) if offset <= len => offset,
+            Some(_) => 0,
+            None => len,
+        };
+
+        let mut text = String::new();
+        if file.seek(SeekFrom::Start(offset)).is_err() || file.read_to_string(&mut text).is_err() {
+            return Vec::new();
+        }
+        // A line still being written is read next time
+        let complete = text.rfind('\n').map_or(0, |end| end + 1);
+        self.audit_offset = Some(offset + complete as u64);
+        text[..complete].lines().filter_map(audit_exec).collect()
+    }
+}
+
+/// A process started by a runtime that is neither the runtime's nor in a
+/// sandbox cgroup
+fn process_finding(process: &Process, processes: &HashMap<u32, Process>, source: &str) -> Option<Finding> {
+    if runtime_of(&process.exe).is_some() || process.cgroup.starts_with(SANDBOX_CGROUP_PARENT) {
+        return None;
+    }
+
+    let mut parent = processes.get(&process.ppid);
+    for _ in 0..MAX_ANCESTORS {
+        let ancestor = parent?;
+        let Some(runtime) = runtime_of(&ancestor.exe) else {
+            parent = processes.get(&ancestor.ppid);
+            continue;
+        };
+
+        let Some(sandbox_id) = sandbox_id_in(&cmdline(ancestor.pid)).or_else(|| sandbox_id_in(&ancestor.cgroup))
+        else {
+            warn!(
+                pid = process.pid,
+                executable = %process.exe,
+                runtime_pid = ancestor.pid,
+                "Process started outside any sandbox by {} with no sandbox ID", ancestor.exe
+            );
+            return None;
+        };
+        return Some(Finding {
+            sandbox_id,
+            runtime: Some(runtime),
+            message: format!(
+                "Possible sandbox escape: {} started on the host by {} (pid {})",
+                process.exe, ancestor.exe, ancestor.pid
+            ),
+            details: serde_json::json!({
+                "signal": "host_process",
+                "source": source,
+                "pid": process.pid,
+                "ppid": process.ppid,
+                "executable": process.exe,
+                "cgroup": process.cgroup,
+                "runtime_pid": ancestor.pid,
+                "runtime_executable": ancestor.exe,
+            }),
+        });
+    }
+    None
+}
+
+/// The runtime an executable belongs to
+fn runtime_of(exe: &str) -> Option<&'static str> {
+    let exe = exe.trim_end_matches(" (deleted)");
+    let name = exe.rsplit('/').next().unwrap_or(exe);
+    RUNTIME_EXECUTABLES
+        .iter()
+        .find(|(executable, _)| *executable == name)
+        .map(|(_, runtime)| *runtime)
+}
+
+/// The first sandbox ID in a command line or path
+fn sandbox_id_in(text: &str) -> Option<Uuid> {
+    text.char_indices()
+        .filter_map(|(start, _)| text.get(start..start + 36))
+        .find_map(|candidate| Uuid::parse_str(candidate).ok())
+}
+
+/// Whether a path is one of these directories or below one
+fn inside(path: &str, dirs: &[String]) -> bool {
+    dirs.iter().any(|dir| {
+        let dir = dir.trim_end_matches('/');
+        path == dir || path.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/'))
+    })
+}
+
+fn proc_dir(pid: u32) -> PathBuf {
+    Path::new("/proc").join(pid.to_string())
+}
+
+/// Every process on the host with an executable, by PID
+fn processes() -> HashMap<u32, Process> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return HashMap::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .filter_map(process)
+        .map(|process| (process.pid, process))
+        .collect()
+}
+
+fn process(pid: u32) -> Option<Process> {
+    let dir = proc_dir(pid);
+    let stat = std::fs::read_to_string(dir.join("stat")).ok()?;
+    // Fields after the command name, which may itself hold spaces: the
+    // state, then the parent PID; the start time is the 22nd field
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let ppid = fields.get(1)?.parse().ok()?;
+    let start_time = fields.get(19)?.parse().ok()?;
+    // Kernel threads have no executable
+    let exe = std::fs::read_link(dir.join("exe")).ok()?;
+    Some(Process {
+        pid,
+        ppid,
+        start_time,
+        exe: exe.to_string_lossy().into_owned(),
+        cgroup: cgroup(&dir),
+    })
+}
+
+/// A process's cgroup v2 path
+fn cgroup(dir: &Path) -> String {
+    std::fs::read_to_string(dir.join("cgroup"))
+        .ok()
+        .and_then(|cgroups| {
+            cgroups
+                .lines()
+                .find_map(|line| line.strip_prefix("0::").map(String::from))
+        })
+        .unwrap_or_default()
+}
+
+fn cmdline(pid: u32) -> String {
+    std::fs::read(proc_dir(pid).join("cmdline"))
+        .map(|cmdline| String::from_utf8_lossy(&cmdline).replace('\0', " "))
+        .unwrap_or_default()
+}
+
+/// Mounts in the host's mount namespace, which is init's
+fn mounts() -> Vec<Mount> {
+    std::fs::read_to_string("/proc/1/mountinfo")
+        .map(|mountinfo| mountinfo.lines().filter_map(mount).collect())
+        .unwrap_or_default()
+}
+
+/// One `mountinfo` line: ID, parent, device, root, mount point and options,
+/// optional fields, then `-`, the filesystem type, source and superblock
+/// options
+fn mount(line: &str) -> Option<Mount> {
+    let (mount, filesystem) = line.split_once(" - ")?;
+    let mount: Vec<&str> = mount.split_whitespace().collect();
+    let filesystem: Vec<&str> = filesystem.split_whitespace().collect();
+    Some(Mount {
+        id: mount.first()?.parse().ok()?,
+        root: mount.get(3)?.to_string(),
+        mount_point: mount.get(4)?.to_string(),
+        fs_type: filesystem.first()?.to_string(),
+        source: filesystem.get(1)?.to_string(),
+        options: filesystem.get(2).unwrap_or(&"").to_string(),
+    })
+}
+
+/// An exec recorded under [`AUDIT_KEY`]
+fn audit_exec(line: &str) -> Option<Process> {
+    if !line.starts_with("type=SYSCALL") {
+        return None;
+    }
+    let field = |name: &str| {
+        line.split_whitespace()
+            .find_map(|field| field.strip_prefix(name)?.strip_prefix('='))
+            .map(|value| value.trim_matches('"'))
+    };
+    if field("key")? != AUDIT_KEY {
+        return None;
+    }
+    let pid = field("pid")?.parse().ok()?;
+    Some(Process {
+        pid,
+        ppid: field("ppid")?.parse().ok()?,
+        start_time: 0,
+        exe: field("exe")?.to_string(),
+        cgroup: cgroup(&proc_dir(pid)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SANDBOX: &str = "5f0c6a8e-3d2b-4c1a-9e7f-0123456789ab";
+
+    /// A PID no test host will have, so `/proc` lookups come back empty
+    const GONE: u32 = 4_000_000_000;
+
+    fn process(pid: u32, ppid: u32, exe: &str, cgroup: &str) -> Process {
+        Process {
+            pid,
+            ppid,
+            start_time: 0,
+            exe: exe.to_string(),
+            cgroup: cgroup.to_string(),
+        }
+    }
+
+    #[test]
+    fn recognizes_runtime_executables() {
+        assert_eq!(runtime_of("/usr/local/bin/runsc"), Some("gvisor"));
+        assert_eq!(runtime_of("/opt/kata/bin/qemu-system-x86_64 (deleted)"), Some("kata"));
+        assert_eq!(runtime_of("jailer"), Some("firecracker"));
+        assert_eq!(runtime_of("/usr/bin/runsc-helper"), None);
+    }
+
+    #[test]
+    fn finds_sandbox_ids_and_state_paths() {
+        assert_eq!(
+            sandbox_id_in(&format!("runsc --root /run/runsc start {}", SANDBOX)),
+            Some(SANDBOX.parse().unwrap())
+        );
+        assert_eq!(sandbox_id_in("/usr/bin/sh -c true"), None);
+
+        let dirs = default_state_dirs();
+        assert!(inside("/run/runsc", &dirs));
+        assert!(inside(&format!("/var/lib/sandstorm/{}/rootfs", SANDBOX), &dirs));
+        assert!(!inside("/run/runsc-other", &dirs));
+        assert!(!inside("/home/user", &dirs));
+    }
+
+    #[test]
+    fn flags_processes_a_runtime_starts_outside_the_sandbox() {
+        let runtime_cgroup = format!("/system.slice/runsc-{}.scope", SANDBOX);
+        let processes: HashMap<u32, Process> = [
+            process(GONE, 1, "/usr/local/bin/runsc", &runtime_cgroup),
+            process(GONE - 1, GONE, "/usr/bin/bash", &runtime_cgroup),
+        ]
+        .into_iter()
+        .map(|process| (process.pid, process))
+        .collect();
+
+        let escaped = process(GONE - 2, GONE - 1, "/usr/bin/curl", "/user.slice");
+        let finding = process_finding(&escaped, &processes, "proc").unwrap();
+        assert_eq!(finding.sandbox_id.to_string(), SANDBOX);
+        assert_eq!(finding.runtime, Some("gvisor"));
+        assert_eq!(finding.details["runtime_pid"], GONE);
+
+        let event = finding.into_event(None);
+        assert_eq!(event.event_type, EventType::SandboxEscape);
+        assert_eq!(event.severity, Severity::Critical);
+        assert_eq!(event.provider, "gvisor");
+
+        // Processes inside a sandbox cgroup, the runtime's own helpers and
+        // processes no runtime started are left alone
+        let contained = process(GONE - 2, GONE - 1, "/usr/bin/curl", &format!("/sandstorm/{}", SANDBOX));
+        assert!(process_finding(&contained, &processes, "proc").is_none());
+        let helper = process(GONE - 2, GONE, "/usr/bin/runsc", "/user.slice");
+        assert!(process_finding(&helper, &processes, "proc").is_none());
+        let unrelated = process(GONE - 2, 1, "/usr/bin/curl", "/user.slice");
+        assert!(process_finding(&unrelated, &processes, "proc").is_none());
+    }
+
+    #[test]
+    fn parses_mountinfo_and_audit_records() {
+        let line = format!(
+            "812 29 0:52 /{} /mnt/exfil rw,relatime shared:1 - overlay overlay rw,lowerdir=/l",
+            SANDBOX
+        );
+        let parsed = mount(&line).unwrap();
+        assert_eq!(parsed.id, 812);
+        assert_eq!(parsed.root, format!("/{}", SANDBOX));
+        assert_eq!(parsed.mount_point, "/mnt/exfil");
+        assert_eq!((parsed.fs_type.as_str(), parsed.source.as_str()), ("overlay", "overlay"));
+        assert!(mount("not a mount").is_none());
+
+        let record = format!(
+            "type=SYSCALL msg=audit(1700000000.123:42): arch=c000003e syscall=59 success=yes ppid=10 pid={} exe=\"/usr/bin/curl\" key=\"{}\"",
+            GONE, AUDIT_KEY
+        );
+        let exec = audit_exec(&record).unwrap();
+        assert_eq!((exec.pid, exec.ppid, exec.exe.as_str()), (GONE, 10, "/usr/bin/curl"));
+        assert!(audit_exec(&record.replace(AUDIT_KEY, "other")).is_none());
+        assert!(audit_exec(&record.replace("type=SYSCALL", "type=EXECVE")).is_none());
+    }
+
+    #[test]
+    fn reads_only_complete_new_audit_records() {
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!("audit-{}.log", Uuid::new_v4()));
+        let record = |pid: u32| {
+            format!(
+                "type=SYSCALL msg=audit(1700000000.123:42): ppid=1 pid={} exe=\"/usr/bin/id\" key=\"{}\"\n",
+                pid, AUDIT_KEY
+            )
+        };
+        std::fs::write(&path, record(GONE)).unwrap();
+
+        let mut watchdog = HostWatchdog::new();
+        // The first read starts at the end of the log
+        assert!(watchdog.audit_execs(&path).is_empty());
+
+        let mut log = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+        let partial = record(GONE - 1);
+        log.write_all(&partial.as_bytes()[..20]).unwrap();
+        assert!(watchdog.audit_execs(&path).is_empty());
+        log.write_all(&partial.as_bytes()[20..]).unwrap();
+        let pids: Vec<u32> = watchdog.audit_execs(&path).iter().map(|exec| exec.pid).collect();
+        assert_eq!(pids, [GONE - 1]);
+
+        // A rotated log is read from the start
+        std::fs::write(&path, record(GONE - 2)).unwrap();
+        let pids: Vec<u32> = watchdog.audit_execs(&path).iter().map(|exec| exec.pid).collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(pids, [GONE - 2]);
+    }
+}
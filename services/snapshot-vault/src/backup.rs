@@ -0,0 +1,65 @@
+use anyhow::Context;
+use sandstorm_backup::{BackupFiles, BackupSource};
+use sandstorm_types::{recording::SessionRecording, snapshot::SnapshotMetadata, Versioned};
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::{recordings::RecordingStore, ListQuery, SnapshotVault};
+
+const SNAPSHOTS_FILE: &str = "snapshots.json";
+const RECORDINGS_FILE: &str = "recordings.json";
+
+/// Snapshot and recording metadata. Blobs and casts are large, immutable and
+/// addressed by ID, so they are left to the vault's own storage replication.
+pub struct VaultBackup {
+    pub vault: Arc<SnapshotVault>,
+    pub recordings: Arc<RecordingStore>,
+}
+
+#[async_trait::async_trait]
+impl BackupSource for VaultBackup {
+    async fn export(&self) -> anyhow::Result<BackupFiles> {
+        let snapshots: Vec<_> = self
+            .vault
+            .list(&ListQuery::default(), None, None)
+            .await
+            .into_iter()
+            .map(Versioned::new)
+            .collect();
+        let recordings: Vec<_> = self.recordings.all().await.into_iter().map(Versioned::new).collect();
+
+        Ok(BackupFiles::from([
+            (SNAPSHOTS_FILE.to_string(), serde_json::to_vec(&snapshots)?),
+            (RECORDINGS_FILE.to_string(), serde_json::to_vec(&recordings)?),
+        ]))
+    }
+
+    async fn restore(&self, files: &BackupFiles) -> anyhow::Result<()> {
+        let snapshots = decode::<SnapshotMetadata>(files, SNAPSHOTS_FILE)?;
+        let recordings = decode::<SessionRecording>(files, RECORDINGS_FILE)?;
+
+        let missing = self.vault.replace_all(snapshots).await?;
+        if !missing.is_empty() {
+            warn!(snapshots = ?missing, "Restored snapshots whose blobs are missing");
+        }
+        let missing = self.recordings.replace_all(recordings).await?;
+        if !missing.is_empty() {
+            warn!(recordings = ?missing, "Restored recordings whose casts are missing");
+        }
+        Ok(())
+    }
+}
+
+fn decode<T>(files: &BackupFiles, name: &str) -> anyhow::Result<Vec<T>>
+where
+    T: sandstorm_types::Schema + serde::de::DeserializeOwned,
+{
+    let data = files
+        .get(name)
+        .with_context(|| format!("backup has no {}", name))?;
+    serde_json::from_slice::<Vec<Versioned<T>>>(data)
+        .with_context(|| format!("failed to decode {}", name))?
+        .into_iter()
+        .map(|envelope| Ok(envelope.into_inner()?))
+        .collect()
+}
@@ -0,0 +1,174 @@
+//! Chunked snapshot downloads. A manifest lists a blob's chunks with their
+//! hashes, so clients can fetch large blobs in parallel and verify each
+//! chunk as it arrives.
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{HeaderMap, Response, StatusCode},
+    Json,
+};
+use sandstorm_types::snapshot::{ChunkInfo, SnapshotManifest};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{keys::SEGMENT_OVERHEAD, tenant, AppState, SnapshotVault, VaultError};
+
+/// Size of every chunk but the last. Encrypted blobs are sealed in segments
+/// of the same size, so a chunk decrypts on its own.
+pub const CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Manifest of a (plaintext) blob
+pub fn manifest_of(snapshot_id: Uuid, blob: &[u8]) -> SnapshotManifest {
+    let chunks = blob
+        .chunks(CHUNK_SIZE as usize)
+        .enumerate()
+        .map(|(index, chunk)| ChunkInfo {
+            index: index as u64,
+            offset: index as u64 * CHUNK_SIZE,
+            size: chunk.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(chunk)),
+        })
+        .collect();
+    SnapshotManifest {
+        snapshot_id,
+        size_bytes: blob.len() as u64,
+        chunk_size: CHUNK_SIZE,
+        chunks,
+    }
+}
+
+/// Keep a blob's manifest next to it
+pub async fn write_manifest(vault: &SnapshotVault, manifest: &SnapshotManifest) -> anyhow::Result<()> {
+    fs::write(
+        vault.manifest_path(manifest.snapshot_id),
+        serde_json::to_vec(manifest)?,
+    )
+    .await?;
+    Ok(())
+}
+
+/// A snapshot's manifest. Blobs stored before manifests existed are read in
+/// full once to build theirs.
+async fn manifest(vault: &SnapshotVault, id: Uuid, tenant: &str) -> Result<SnapshotManifest, VaultError> {
+    vault.get(id, tenant).await.ok_or(VaultError::NotFound)?;
+    match fs::read(vault.manifest_path(id)).await {
+        Ok(contents) => Ok(serde_json::from_slice(&contents).map_err(anyhow::Error::from)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let manifest = manifest_of(id, &vault.get_blob(id, tenant).await?);
+            write_manifest(vault, &manifest).await?;
+            Ok(manifest)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// One decrypted chunk of a blob
+async fn chunk(vault: &SnapshotVault, id: Uuid, tenant: &str, index: u64) -> Result<Vec<u8>, VaultError> {
+    let manifest = manifest(vault, id, tenant).await?;
+    let info = manifest
+        .chunks
+        .get(index as usize)
+        .ok_or(VaultError::NotFound)?;
+    let meta = vault.get(id, tenant).await.ok_or(VaultError::NotFound)?;
+
+    let Some(encryption) = &meta.encryption else {
+        return vault.read_local(&meta, Some((info.offset, info.size))).await;
+    };
+    if encryption.segment_size != Some(manifest.chunk_size) {
+        // Sealed whole, so the chunk can only be cut from the full blob
+        let blob = vault.get_blob(id, tenant).await?;
+        return Ok(blob[info.offset as usize..(info.offset + info.size) as usize].to_vec());
+    }
+
+    let keyring = vault.keyring.as_ref().ok_or_else(|| {
+        anyhow::anyhow!("snapshot {} is encrypted but SNAPSHOT_VAULT_MASTER_KEY is not set", id)
+    })?;
+    let stride = manifest.chunk_size + SEGMENT_OVERHEAD;
+    let sealed = vault
+        .read_local(&meta, Some((index * stride, info.size + SEGMENT_OVERHEAD)))
+        .await?;
+    let last = index + 1 == manifest.chunks.len() as u64;
+    Ok(keyring.open_segment(tenant, id, encryption, index, last, &sealed)?)
+}
+
+pub async fn get_manifest(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<SnapshotManifest>, VaultError> {
+    let tenant = tenant(&headers)?;
+    state.vault.clear_for_restore(id, &tenant).await?;
+    let manifest = manifest(&state.vault, id, &tenant).await?;
+    // Fetching the manifest starts a download, which keeps the blob hot
+    state.vault.touch(id).await?;
+    Ok(Json(manifest))
+}
+
+pub async fn download_chunk(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((id, index)): Path<(Uuid, u64)>,
+) -> Result<Response<Body>, VaultError> {
+    let tenant = tenant(&headers)?;
+    state.vault.clear_for_restore(id, &tenant).await?;
+    let bytes = chunk(&state.vault, id, &tenant, index).await?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/octet-stream")
+        .body(Body::from(bytes))
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keys::Keyring, CreateSnapshotRequest};
+    use base64::Engine;
+
+    #[tokio::test]
+    async fn encrypted_blobs_download_chunk_by_chunk() {
+        let dir = std::env::temp_dir().join(format!("vault-chunks-{}", Uuid::new_v4()));
+        let keyring = Keyring::new([3; 32], dir.join("keys")).await.unwrap();
+        let vault = SnapshotVault::new(&dir, Some(keyring), None, Default::default(), None).await.unwrap();
+
+        let blob: Vec<u8> = (0..CHUNK_SIZE * 5 / 2).map(|i| (i % 251) as u8).collect();
+        let stored = vault
+            .store(
+                CreateSnapshotRequest {
+                    sandbox_id: "sandbox".to_string(),
+                    provider: "firecracker".to_string(),
+                    filesystem_hash: "hash".to_string(),
+                    memory_hash: None,
+                    size_bytes: None,
+                    metadata: None,
+                    data: Some(base64::engine::general_purpose::STANDARD.encode(&blob)),
+                    format: None,
+                    run_id: None,
+                    pinned: false,
+                    base_layer: None,
+                },
+                "acme".into(),
+            )
+            .await
+            .unwrap();
+
+        let manifest = manifest(&vault, stored.id, "acme").await.unwrap();
+        assert_eq!(manifest.size_bytes, blob.len() as u64);
+        assert_eq!(manifest.chunks.len(), 3);
+        assert_eq!(manifest.chunks[2].size, CHUNK_SIZE / 2);
+
+        let mut reassembled = Vec::new();
+        for info in &manifest.chunks {
+            let data = chunk(&vault, stored.id, "acme", info.index).await.unwrap();
+            assert_eq!(format!("{:x}", Sha256::digest(&data)), info.sha256);
+            reassembled.extend(data);
+        }
+        assert_eq!(reassembled, blob);
+        assert!(chunk(&vault, stored.id, "acme", 3).await.is_err());
+        assert!(chunk(&vault, stored.id, "globex", 0).await.is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
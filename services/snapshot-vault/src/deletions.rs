@@ -0,0 +1,231 @@
+//! Data deletion requests, and the legal holds that exempt snapshots from
+//! them. A request removes every snapshot of its tenant or of its sandboxes,
+//! and the session recordings of those sandboxes. Snapshots under legal hold,
+//! leased snapshots and the base layers of either are kept and reported.
+
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    Json,
+};
+use chrono::Utc;
+use sandstorm_types::{
+    deletion::{DeletionRequest, RetainedRecord, ServiceDeletion},
+    snapshot::SnapshotMetadata,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::recordings::RecordingStore;
+use crate::{authorize, tenant, AppState, SnapshotVault, VaultError};
+
+impl SnapshotVault {
+    /// Place or lift a legal hold on a snapshot
+    async fn set_legal_hold(&self, id: Uuid, tenant: &str, held: bool) -> Result<SnapshotMetadata, VaultError> {
+        let mut index = self.index.write().await;
+        let metadata = index
+            .get_mut(&id)
+            .filter(|meta| meta.tenant == tenant)
+            .ok_or(VaultError::NotFound)?;
+        metadata.legal_hold = held;
+        self.write_metadata(metadata).await?;
+        Ok(metadata.clone())
+    }
+}
+
+/// Delete the snapshots and recordings a request covers
+async fn erase(
+    vault: &SnapshotVault,
+    recordings: &RecordingStore,
+    request: &DeletionRequest,
+) -> Result<ServiceDeletion, VaultError> {
+    let mut sandbox_ids: BTreeSet<String> = request.sandbox_ids.iter().cloned().collect();
+    let covered: Vec<_> = vault
+        .index
+        .read()
+        .await
+        .values()
+        .filter(|meta| {
+            request.tenant.as_deref() == Some(meta.tenant.as_str()) || sandbox_ids.contains(&meta.sandbox_id)
+        })
+        .map(|meta| (meta.id, meta.tenant.clone(), meta.sandbox_id.clone()))
+        .collect();
+    let mut pending = Vec::new();
+    for (id, tenant, sandbox_id) in covered {
+        // Recordings of a tenant's sandboxes go with its snapshots
+        sandbox_ids.insert(sandbox_id);
+        pending.push((id, tenant));
+    }
+
+    let mut removed = BTreeMap::new();
+    let mut retained = Vec::new();
+    let retain = |id: Uuid, reason: &str| RetainedRecord {
+        kind: "snapshot".to_string(),
+        id: id.to_string(),
+        reason: reason.to_string(),
+    };
+    // Each pass deletes the snapshots nothing is layered on any more, so
+    // stacks go from the top down. What is left once a pass deletes nothing
+    // is under a snapshot that has to stay.
+    loop {
+        let attempted = pending.len();
+        let mut layered = Vec::new();
+        for (id, tenant) in std::mem::take(&mut pending) {
+            match vault.delete(id, &tenant).await {
+                Ok(()) => *removed.entry("snapshots".to_string()).or_insert(0) += 1,
+                Err(VaultError::Layered(_)) => layered.push((id, tenant)),
+                Err(VaultError::LegalHold(_)) => retained.push(retain(id, "legal_hold")),
+                Err(VaultError::Leased(_)) => retained.push(retain(id, "leased")),
+                Err(VaultError::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if layered.is_empty() || layered.len() == attempted {
+            retained.extend(layered.into_iter().map(|(id, _)| retain(id, "base_layer")));
+            break;
+        }
+        pending = layered;
+    }
+
+    for recording in recordings.all().await {
+        if !sandbox_ids.contains(&recording.sandbox_id) {
+            continue;
+        }
+        match recordings.delete(recording.id).await {
+            Ok(()) => *removed.entry("recordings".to_string()).or_insert(0) += 1,
+            Err(VaultError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(ServiceDeletion {
+        service: "snapshot-vault".to_string(),
+        removed,
+        retained,
+        sandbox_ids: sandbox_ids.into_iter().collect(),
+        completed_at: Utc::now(),
+    })
+}
+
+/// Delete what a data deletion request covers. Requires the admin token.
+pub async fn delete_data(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<DeletionRequest>,
+) -> Result<Json<ServiceDeletion>, VaultError> {
+    authorize(&state, &headers)?;
+    if request.is_empty() {
+        return Err(VaultError::Invalid("a deletion needs a tenant or sandbox_ids".into()));
+    }
+
+    let deletion = erase(&state.vault, &state.recordings, &request).await?;
+    info!(
+        tenant = ?request.tenant,
+        sandboxes = deletion.sandbox_ids.len(),
+        removed = ?deletion.removed,
+        retained = deletion.retained.len(),
+        "deleted data on request"
+    );
+    state.metrics.observe_tiers(&state.vault).await;
+    Ok(Json(deletion))
+}
+
+/// Place a legal hold on a snapshot. Requires the admin token.
+pub async fn place_legal_hold(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<SnapshotMetadata>, VaultError> {
+    authorize(&state, &headers)?;
+    let metadata = state.vault.set_legal_hold(id, &tenant(&headers)?, true).await?;
+    info!(snapshot = %id, "placed legal hold");
+    Ok(Json(metadata))
+}
+
+/// Lift a legal hold. Requires the admin token.
+pub async fn lift_legal_hold(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<SnapshotMetadata>, VaultError> {
+    authorize(&state, &headers)?;
+    let metadata = state.vault.set_legal_hold(id, &tenant(&headers)?, false).await?;
+    info!(snapshot = %id, "lifted legal hold");
+    Ok(Json(metadata))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateSnapshotRequest;
+    use base64::Engine;
+    use sandstorm_types::recording::{SessionKind, SessionRecording};
+
+    fn request(sandbox_id: &str, base_layer: Option<Uuid>) -> CreateSnapshotRequest {
+        CreateSnapshotRequest {
+            sandbox_id: sandbox_id.to_string(),
+            provider: "kata".to_string(),
+            filesystem_hash: "hash".to_string(),
+            memory_hash: None,
+            size_bytes: None,
+            metadata: None,
+            data: Some(base64::engine::general_purpose::STANDARD.encode(b"layer")),
+            format: None,
+            run_id: None,
+            pinned: false,
+            base_layer,
+        }
+    }
+
+    #[tokio::test]
+    async fn deletes_a_tenants_snapshots_and_recordings_except_held_ones() {
+        let dir = std::env::temp_dir().join(format!("vault-deletions-{}", Uuid::new_v4()));
+        let vault = SnapshotVault::new(&dir, None, None, Default::default(), None).await.unwrap();
+        let recordings = RecordingStore::new(dir.join("recordings")).await.unwrap();
+
+        let base = vault.store(request("a", None), "acme".into()).await.unwrap();
+        let top = vault.store(request("a", Some(base.id)), "acme".into()).await.unwrap();
+        let held = vault.store(request("b", None), "acme".into()).await.unwrap();
+        let on_held = vault.store(request("b", Some(held.id)), "acme".into()).await.unwrap();
+        let other = vault.store(request("c", None), "globex".into()).await.unwrap();
+        vault.set_legal_hold(held.id, "acme", true).await.unwrap();
+        assert!(matches!(vault.delete(held.id, "acme").await, Err(VaultError::LegalHold(_))));
+
+        let recording = |sandbox_id: &str| SessionRecording {
+            id: Uuid::new_v4(),
+            sandbox_id: sandbox_id.to_string(),
+            run_id: None,
+            user: "alice".to_string(),
+            kind: SessionKind::Run,
+            command: vec!["python".to_string()],
+            exit_code: Some(0),
+            started_at: Utc::now(),
+            duration_ms: 10,
+            size_bytes: 0,
+        };
+        recordings.replace_all(vec![recording("a"), recording("c")]).await.unwrap();
+
+        let request = DeletionRequest {
+            tenant: Some("acme".to_string()),
+            sandbox_ids: Vec::new(),
+        };
+        let deletion = erase(&vault, &recordings, &request).await.unwrap();
+        assert_eq!(deletion.removed["snapshots"], 3);
+        assert_eq!(deletion.removed["recordings"], 1);
+        assert_eq!(deletion.retained, vec![RetainedRecord {
+            kind: "snapshot".to_string(),
+            id: held.id.to_string(),
+            reason: "legal_hold".to_string(),
+        }]);
+        assert_eq!(deletion.sandbox_ids, ["a", "b"]);
+        for id in [base.id, top.id, on_held.id] {
+            assert!(vault.get(id, "acme").await.is_none());
+        }
+        assert!(vault.get(held.id, "acme").await.is_some());
+        assert!(vault.get(other.id, "globex").await.is_some());
+        assert_eq!(recordings.all().await.len(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
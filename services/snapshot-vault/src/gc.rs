@@ -0,0 +1,165 @@
+//! Garbage collection of snapshots nobody has used within the retention
+//! period. Pinned snapshots, snapshots under legal hold or with an unexpired
+//! lease and base layers of other snapshots are kept however old they are.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use std::{sync::Arc, time::Duration};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{SnapshotVault, VaultError, VaultMetrics};
+
+/// How long unused snapshots are kept
+pub struct Retention {
+    keep_for: chrono::Duration,
+}
+
+impl Retention {
+    /// Retention of `SNAPSHOT_VAULT_RETENTION_DAYS`; `None` when unset, in
+    /// which case snapshots are kept until deleted
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(value) = std::env::var("SNAPSHOT_VAULT_RETENTION_DAYS") else {
+            return Ok(None);
+        };
+        let days: i64 = value
+            .parse()
+            .ok()
+            .filter(|days| *days > 0)
+            .context("SNAPSHOT_VAULT_RETENTION_DAYS must be a positive number of days")?;
+        Ok(Some(Self::new(chrono::Duration::days(days))))
+    }
+
+    pub fn new(keep_for: chrono::Duration) -> Self {
+        Self { keep_for }
+    }
+
+    /// Delete every unpinned, unleased snapshot nothing is layered on, last
+    /// used before the retention period. Returns the snapshots deleted.
+    pub async fn collect(&self, vault: &SnapshotVault, metrics: &VaultMetrics) -> Vec<Uuid> {
+        let cutoff = Utc::now() - self.keep_for;
+        let candidates: Vec<_> = vault
+            .index
+            .read()
+            .await
+            .values()
+            .filter(|meta| !meta.pinned && !meta.legal_hold && meta.accessed_at.unwrap_or(meta.created_at) < cutoff)
+            .map(|meta| (meta.id, meta.tenant.clone(), meta.tier))
+            .collect();
+
+        let mut deleted = Vec::new();
+        for (id, tenant, tier) in candidates {
+            // `delete` refuses leased snapshots while holding the lease table
+            match vault.delete(id, &tenant).await {
+                Ok(()) => {
+                    metrics.snapshots_collected.with_label_values(&[tier.as_str()]).inc();
+                    deleted.push(id);
+                }
+                Err(VaultError::Leased(_) | VaultError::Layered(_) | VaultError::NotFound) => {}
+                Err(e) => warn!(snapshot = %id, "failed to collect snapshot: {}", e),
+            }
+        }
+        deleted
+    }
+}
+
+/// Every `SNAPSHOT_VAULT_GC_INTERVAL_SECS` (default hourly), drop lapsed
+/// leases, then delete snapshots past `retention`, if set
+pub fn spawn(vault: Arc<SnapshotVault>, retention: Option<Retention>, metrics: VaultMetrics) {
+    let interval = std::env::var("SNAPSHOT_VAULT_GC_INTERVAL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(3600);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            match vault.leases.expire().await {
+                Ok(expired) if !expired.is_empty() => {
+                    info!(count = expired.len(), "dropped lapsed snapshot leases")
+                }
+                Ok(_) => {}
+                Err(e) => warn!("failed to drop lapsed snapshot leases: {:#}", e),
+            }
+            match vault.uploads.expire().await {
+                Ok(expired) if !expired.is_empty() => {
+                    info!(count = expired.len(), "dropped abandoned uploads")
+                }
+                Ok(_) => {}
+                Err(e) => warn!("failed to drop abandoned uploads: {:#}", e),
+            }
+            if let Some(retention) = &retention {
+                let deleted = retention.collect(&vault, &metrics).await;
+                if !deleted.is_empty() {
+                    info!(count = deleted.len(), "deleted snapshots past retention");
+                }
+            }
+            metrics.observe_tiers(&vault).await;
+            metrics.observe_leases(&vault).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateSnapshotRequest;
+    use sandstorm_types::snapshot::LeaseRequest;
+
+    fn request() -> CreateSnapshotRequest {
+        CreateSnapshotRequest {
+            sandbox_id: "sandbox".to_string(),
+            provider: "kata".to_string(),
+            filesystem_hash: "hash".to_string(),
+            memory_hash: None,
+            size_bytes: None,
+            metadata: None,
+            data: None,
+            format: None,
+            run_id: None,
+            pinned: false,
+            base_layer: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_leased_snapshots_until_their_leases_lapse() {
+        let dir = std::env::temp_dir().join(format!("vault-gc-{}", Uuid::new_v4()));
+        let vault = SnapshotVault::new(&dir, None, None, Default::default(), None).await.unwrap();
+        let metrics = VaultMetrics::new();
+        let retention = Retention::new(chrono::Duration::zero());
+
+        let leased = vault.store(request(), "default".into()).await.unwrap();
+        let idle = vault.store(request(), "default".into()).await.unwrap();
+        let request = LeaseRequest {
+            holder: "gateway".to_string(),
+            purpose: "template".to_string(),
+            ttl_secs: None,
+        };
+        let lease = vault
+            .leases
+            .grant(&vault, leased.id, "default".into(), request, chrono::Duration::hours(1))
+            .await
+            .unwrap();
+
+        assert_eq!(retention.collect(&vault, &metrics).await, vec![idle.id]);
+        assert!(vault.get(leased.id, "default").await.is_some());
+        assert!(matches!(
+            vault.delete(leased.id, "default").await,
+            Err(VaultError::Leased(_))
+        ));
+
+        // Once the lease lapses the snapshot goes
+        vault
+            .leases
+            .renew(lease.id, "default", -chrono::Duration::seconds(1))
+            .await
+            .unwrap();
+        assert_eq!(retention.collect(&vault, &metrics).await, vec![leased.id]);
+        assert_eq!(vault.leases.expire().await.unwrap().len(), 1);
+        assert_eq!(metrics.snapshots_collected.with_label_values(&["hot"]).get(), 2.0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
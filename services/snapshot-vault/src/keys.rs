@@ -0,0 +1,316 @@
+//! Per-tenant envelope encryption of snapshot blobs.
+//!
+//! Every blob is sealed with its own data key. The data key is wrapped by a
+//! key derived from the vault's master key, the owning tenant and a key
+//! generation, so one tenant's key can never open another tenant's blobs.
+//! Rotating a tenant's key re-wraps its data keys and leaves blobs untouched.
+//!
+//! Blobs are sealed in fixed-size segments, each with its own nonce, so one
+//! chunk of a blob can be decrypted without reading the rest. Each segment is
+//! bound to its position and to whether it is the last, so segments can't be
+//! reordered or dropped.
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use hkdf::Hkdf;
+use sandstorm_types::snapshot::BlobEncryption;
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use tokio::{fs, sync::RwLock};
+use uuid::Uuid;
+
+const NONCE_LEN: usize = 12;
+
+/// Bytes a sealed segment adds to its plaintext: the nonce and the tag
+pub const SEGMENT_OVERHEAD: u64 = NONCE_LEN as u64 + 16;
+
+/// Derives tenant keys and tracks each tenant's current key generation
+pub struct Keyring {
+    master: [u8; 32],
+    path: PathBuf,
+    generations: RwLock<HashMap<String, u32>>,
+}
+
+impl Keyring {
+    /// Keyring for the base64-encoded 32-byte `SNAPSHOT_VAULT_MASTER_KEY`,
+    /// keeping key generations in a `keys` directory under `root`; `None`
+    /// when unset
+    pub async fn from_env(root: &Path) -> Result<Option<Self>> {
+        let Ok(encoded) = std::env::var("SNAPSHOT_VAULT_MASTER_KEY") else {
+            return Ok(None);
+        };
+        let master = base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .context("SNAPSHOT_VAULT_MASTER_KEY must be base64")?
+            .try_into()
+            .map_err(|_| anyhow!("SNAPSHOT_VAULT_MASTER_KEY must be 32 bytes"))?;
+        Self::new(master, root.join("keys")).await.map(Some)
+    }
+
+    pub async fn new(master: [u8; 32], dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir).await?;
+        let path = dir.join("generations.json");
+        let generations = match fs::read(&path).await {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .with_context(|| format!("failed to load {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            master,
+            path,
+            generations: RwLock::new(generations),
+        })
+    }
+
+    /// Generation of the key new blobs are wrapped with; tenants start at 1
+    pub async fn generation(&self, tenant: &str) -> u32 {
+        self.generations
+            .read()
+            .await
+            .get(tenant)
+            .copied()
+            .unwrap_or(1)
+    }
+
+    /// Make `generation` the tenant's current key generation
+    pub async fn set_generation(&self, tenant: &str, generation: u32) -> Result<()> {
+        let mut generations = self.generations.write().await;
+        generations.insert(tenant.to_string(), generation);
+        // Write aside and rename, so a crash never leaves a partial file
+        let partial = self.path.with_extension("partial");
+        fs::write(&partial, serde_json::to_vec_pretty(&*generations)?).await?;
+        fs::rename(&partial, &self.path).await?;
+        Ok(())
+    }
+
+    /// Encrypt a tenant's blob under a new data key, in segments of
+    /// `segment_size` bytes
+    pub async fn seal(
+        &self,
+        tenant: &str,
+        snapshot_id: Uuid,
+        blob: &[u8],
+        segment_size: u64,
+    ) -> Result<(Vec<u8>, BlobEncryption)> {
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        let cipher = Aes256Gcm::new(&data_key);
+        let segments: Vec<_> = blob.chunks(segment_size as usize).collect();
+        // An empty blob is still one (empty) segment
+        let segments = if segments.is_empty() { vec![blob] } else { segments };
+
+        let mut sealed = Vec::with_capacity(blob.len() + segments.len() * SEGMENT_OVERHEAD as usize);
+        for (index, segment) in segments.iter().enumerate() {
+            let last = index + 1 == segments.len();
+            sealed.extend(encrypt(
+                &cipher,
+                segment,
+                &segment_binding(snapshot_id, index as u64, last),
+            )?);
+        }
+
+        let generation = self.generation(tenant).await;
+        Ok((
+            sealed,
+            BlobEncryption {
+                key_generation: generation,
+                wrapped_key: self.wrap(tenant, snapshot_id, generation, &data_key)?,
+                segment_size: Some(segment_size),
+            },
+        ))
+    }
+
+    /// Decrypt a blob sealed by [`Keyring::seal`]
+    pub fn open(
+        &self,
+        tenant: &str,
+        snapshot_id: Uuid,
+        encryption: &BlobEncryption,
+        sealed: &[u8],
+    ) -> Result<Vec<u8>> {
+        let cipher = Aes256Gcm::new(&self.unwrap(tenant, snapshot_id, encryption)?);
+        let Some(segment_size) = encryption.segment_size else {
+            return decrypt(&cipher, sealed, snapshot_id.as_bytes())
+                .with_context(|| format!("blob of snapshot {} failed to decrypt", snapshot_id));
+        };
+
+        let segments: Vec<_> = sealed
+            .chunks((segment_size + SEGMENT_OVERHEAD) as usize)
+            .collect();
+        if segments.is_empty() {
+            bail!("blob of snapshot {} is empty", snapshot_id);
+        }
+        let mut blob = Vec::with_capacity(sealed.len());
+        for (index, segment) in segments.iter().enumerate() {
+            let last = index + 1 == segments.len();
+            blob.extend(
+                decrypt(&cipher, segment, &segment_binding(snapshot_id, index as u64, last))
+                    .with_context(|| format!("blob of snapshot {} failed to decrypt", snapshot_id))?,
+            );
+        }
+        Ok(blob)
+    }
+
+    /// Decrypt one segment of a blob sealed in segments
+    pub fn open_segment(
+        &self,
+        tenant: &str,
+        snapshot_id: Uuid,
+        encryption: &BlobEncryption,
+        index: u64,
+        last: bool,
+        sealed: &[u8],
+    ) -> Result<Vec<u8>> {
+        if encryption.segment_size.is_none() {
+            bail!("blob of snapshot {} was sealed whole", snapshot_id);
+        }
+        let cipher = Aes256Gcm::new(&self.unwrap(tenant, snapshot_id, encryption)?);
+        decrypt(&cipher, sealed, &segment_binding(snapshot_id, index, last)).with_context(|| {
+            format!("segment {} of snapshot {} failed to decrypt", index, snapshot_id)
+        })
+    }
+
+    /// Wrap a blob's data key again, under another generation of its
+    /// tenant's key
+    pub fn rewrap(
+        &self,
+        tenant: &str,
+        snapshot_id: Uuid,
+        encryption: &BlobEncryption,
+        generation: u32,
+    ) -> Result<BlobEncryption> {
+        let data_key = self.unwrap(tenant, snapshot_id, encryption)?;
+        Ok(BlobEncryption {
+            key_generation: generation,
+            wrapped_key: self.wrap(tenant, snapshot_id, generation, &data_key)?,
+            segment_size: encryption.segment_size,
+        })
+    }
+
+    fn tenant_key(&self, tenant: &str, generation: u32) -> Aes256Gcm {
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(b"sandstorm-vault"), &self.master)
+            .expand(format!("tenant:{}:{}", tenant, generation).as_bytes(), &mut key)
+            .expect("32 bytes is a valid HKDF output length");
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+    }
+
+    fn wrap(
+        &self,
+        tenant: &str,
+        snapshot_id: Uuid,
+        generation: u32,
+        data_key: &Key<Aes256Gcm>,
+    ) -> Result<String> {
+        let wrapped = encrypt(
+            &self.tenant_key(tenant, generation),
+            data_key,
+            &binding(tenant, snapshot_id),
+        )?;
+        Ok(base64::engine::general_purpose::STANDARD.encode(wrapped))
+    }
+
+    fn unwrap(
+        &self,
+        tenant: &str,
+        snapshot_id: Uuid,
+        encryption: &BlobEncryption,
+    ) -> Result<Key<Aes256Gcm>> {
+        let wrapped = base64::engine::general_purpose::STANDARD
+            .decode(&encryption.wrapped_key)
+            .context("wrapped key is not base64")?;
+        let data_key = decrypt(
+            &self.tenant_key(tenant, encryption.key_generation),
+            &wrapped,
+            &binding(tenant, snapshot_id),
+        )
+        .with_context(|| format!("data key of snapshot {} failed to unwrap", snapshot_id))?;
+        if data_key.len() != 32 {
+            bail!("data key of snapshot {} has the wrong length", snapshot_id);
+        }
+        Ok(*Key::<Aes256Gcm>::from_slice(&data_key))
+    }
+}
+
+/// Ties a wrapped data key to its tenant and snapshot, so it can't be copied
+/// into another snapshot's metadata
+fn binding(tenant: &str, snapshot_id: Uuid) -> Vec<u8> {
+    format!("{}/{}", tenant, snapshot_id).into_bytes()
+}
+
+/// Ties a segment to its snapshot, its position, and whether it ends the blob
+fn segment_binding(snapshot_id: Uuid, index: u64, last: bool) -> Vec<u8> {
+    [
+        snapshot_id.as_bytes().as_slice(),
+        &index.to_be_bytes(),
+        &[last as u8],
+    ]
+    .concat()
+}
+
+/// Encrypt under a random nonce, returned in front of the ciphertext
+fn encrypt(cipher: &Aes256Gcm, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: plaintext, aad })
+        .map_err(|_| anyhow!("encryption failed"))?;
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+fn decrypt(cipher: &Aes256Gcm, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        bail!("ciphertext is too short");
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .map_err(|_| anyhow!("authentication failed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn keys_are_scoped_to_tenants_and_survive_rotation() {
+        let dir = std::env::temp_dir().join(format!("vault-keys-{}", Uuid::new_v4()));
+        let keyring = Keyring::new([7; 32], dir.clone()).await.unwrap();
+        let id = Uuid::new_v4();
+
+        let (sealed, encryption) = keyring.seal("acme", id, b"filesystem", 4).await.unwrap();
+        assert_eq!(sealed.len() as u64, 10 + 3 * SEGMENT_OVERHEAD);
+        assert_eq!(keyring.open("acme", id, &encryption, &sealed).unwrap(), b"filesystem");
+        let stride = (4 + SEGMENT_OVERHEAD) as usize;
+        let middle = &sealed[stride..2 * stride];
+        assert_eq!(keyring.open_segment("acme", id, &encryption, 1, false, middle).unwrap(), b"syst");
+        assert!(keyring.open_segment("acme", id, &encryption, 1, true, middle).is_err());
+        // Dropping the final segment is detected
+        assert!(keyring.open("acme", id, &encryption, &sealed[..2 * stride]).is_err());
+        assert!(keyring.open("globex", id, &encryption, &sealed).is_err());
+        assert!(keyring.open("acme", Uuid::new_v4(), &encryption, &sealed).is_err());
+
+        let rotated = keyring.rewrap("acme", id, &encryption, 2).unwrap();
+        keyring.set_generation("acme", 2).await.unwrap();
+        assert_eq!(rotated.key_generation, 2);
+        assert_ne!(rotated.wrapped_key, encryption.wrapped_key);
+        assert_eq!(keyring.open("acme", id, &rotated, &sealed).unwrap(), b"filesystem");
+
+        let reloaded = Keyring::new([7; 32], dir.clone()).await.unwrap();
+        assert_eq!(reloaded.generation("acme").await, 2);
+        assert_eq!(reloaded.generation("globex").await, 1);
+        assert!(Keyring::new([8; 32], dir.clone())
+            .await
+            .unwrap()
+            .open("acme", id, &rotated, &sealed)
+            .is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
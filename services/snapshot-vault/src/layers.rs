@@ -0,0 +1,140 @@
+//! Layered snapshots. A snapshot stored with a `base_layer` holds only what
+//! it adds to its base (a language runtime on an OS, a project on a
+//! runtime), so the fifty templates of a team share one copy of the
+//! gigabytes underneath them. A snapshot's stack is its base's stack with
+//! the snapshot on top; the gateway restores one by fetching each layer and
+//! stacking them with overlayfs.
+//!
+//! Snapshots can only be layered on an existing snapshot of their tenant,
+//! and a snapshot others are layered on can't be deleted, so a stack never
+//! loses a layer.
+
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    Json,
+};
+use sandstorm_types::snapshot::SnapshotMetadata;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::{tenant, AppState, SnapshotVault, VaultError};
+
+/// Fail unless `base` is a snapshot of `tenant` with a blob to layer on
+pub fn check_base(
+    index: &HashMap<Uuid, SnapshotMetadata>,
+    base: Uuid,
+    tenant: &str,
+) -> Result<(), VaultError> {
+    match index.get(&base).filter(|meta| meta.tenant == tenant) {
+        None => Err(VaultError::Invalid(format!("base layer {} not found", base))),
+        Some(meta) if !meta.has_blob => {
+            Err(VaultError::Invalid(format!("base layer {} has no blob", base)))
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+/// Whether any snapshot is layered directly on `id`
+pub fn has_dependents(index: &HashMap<Uuid, SnapshotMetadata>, id: Uuid) -> bool {
+    index.values().any(|meta| meta.base_layer == Some(id))
+}
+
+impl SnapshotVault {
+    /// A snapshot's stack: its bottom layer first and the snapshot itself
+    /// last
+    pub async fn stack(&self, id: Uuid, tenant: &str) -> Result<Vec<SnapshotMetadata>, VaultError> {
+        let index = self.index.read().await;
+        let mut stack: Vec<SnapshotMetadata> = Vec::new();
+        let mut next = Some(id);
+        while let Some(layer) = next {
+            let Some(meta) = index.get(&layer).filter(|meta| meta.tenant == tenant) else {
+                return Err(match stack.last() {
+                    None => VaultError::NotFound,
+                    // Only a restored backup can lose a layer
+                    Some(above) => anyhow::anyhow!(
+                        "base layer {} of snapshot {} is missing",
+                        layer,
+                        above.id
+                    )
+                    .into(),
+                });
+            };
+            if stack.iter().any(|below| below.id == layer) {
+                return Err(anyhow::anyhow!("snapshot {} is layered on itself", layer).into());
+            }
+            stack.push(meta.clone());
+            next = meta.base_layer;
+        }
+        stack.reverse();
+        Ok(stack)
+    }
+}
+
+pub async fn get_layers(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<SnapshotMetadata>>, VaultError> {
+    Ok(Json(state.vault.stack(id, &tenant(&headers)?).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateSnapshotRequest;
+    use base64::Engine;
+
+    fn request(data: &[u8], base_layer: Option<Uuid>) -> CreateSnapshotRequest {
+        CreateSnapshotRequest {
+            sandbox_id: "sandbox".to_string(),
+            provider: "gvisor".to_string(),
+            filesystem_hash: "hash".to_string(),
+            memory_hash: None,
+            size_bytes: None,
+            metadata: None,
+            data: Some(base64::engine::general_purpose::STANDARD.encode(data)),
+            format: None,
+            run_id: None,
+            pinned: false,
+            base_layer,
+        }
+    }
+
+    #[tokio::test]
+    async fn stacks_keep_their_layers() {
+        let dir = std::env::temp_dir().join(format!("vault-layers-{}", Uuid::new_v4()));
+        let vault = SnapshotVault::new(&dir, None, None, Default::default(), None).await.unwrap();
+
+        let os = vault.store(request(b"debian", None), "acme".into()).await.unwrap();
+        let python = vault.store(request(b"python", Some(os.id)), "acme".into()).await.unwrap();
+        let project = vault.store(request(b"project", Some(python.id)), "acme".into()).await.unwrap();
+
+        let stack = vault.stack(project.id, "acme").await.unwrap();
+        let ids: Vec<_> = stack.iter().map(|meta| meta.id).collect();
+        assert_eq!(ids, [os.id, python.id, project.id]);
+        assert!(matches!(vault.stack(project.id, "globex").await, Err(VaultError::NotFound)));
+
+        // Bases must exist, belong to the tenant and have a blob
+        assert!(matches!(
+            vault.store(request(b"other", Some(python.id)), "globex".into()).await,
+            Err(VaultError::Invalid(_))
+        ));
+        let mut empty = request(b"", None);
+        empty.data = None;
+        let empty = vault.store(empty, "acme".into()).await.unwrap();
+        assert!(matches!(
+            vault.store(request(b"other", Some(empty.id)), "acme".into()).await,
+            Err(VaultError::Invalid(_))
+        ));
+
+        // Layers are deleted top down
+        assert!(matches!(vault.delete(os.id, "acme").await, Err(VaultError::Layered(_))));
+        assert!(matches!(vault.delete(python.id, "acme").await, Err(VaultError::Layered(_))));
+        vault.delete(project.id, "acme").await.unwrap();
+        vault.delete(python.id, "acme").await.unwrap();
+        vault.delete(os.id, "acme").await.unwrap();
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
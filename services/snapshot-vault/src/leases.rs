@@ -0,0 +1,264 @@
+//! Leases on snapshots. Gateways lease snapshots they may resume (templates,
+//! warm pools, restores in flight) and renew the leases while they need
+//! them; garbage collection skips leased snapshots, and deleting one is
+//! refused, so a restore never loses its snapshot halfway through.
+//!
+//! Granting a lease and deleting a snapshot both hold the lease table, so a
+//! lease is either granted before a delete checks for it or refused because
+//! the snapshot is gone.
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use sandstorm_types::{
+    snapshot::{LeaseRequest, SnapshotLease},
+    Versioned,
+};
+use serde::Deserialize;
+use std::{collections::HashMap, path::PathBuf};
+use tokio::{
+    fs,
+    sync::{RwLock, RwLockReadGuard},
+};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{tenant, AppState, SnapshotVault, VaultError};
+
+/// Lease length when a request doesn't ask for one
+const DEFAULT_TTL_SECS: u64 = 3600;
+/// Longest lease granted in one go; holders renew to keep it longer
+const MAX_TTL_SECS: u64 = 7 * 24 * 3600;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LeaseQuery {
+    snapshot_id: Option<Uuid>,
+    holder: Option<String>,
+}
+
+/// Every lease, kept in `leases/leases.json` under the vault path
+pub struct Leases {
+    path: PathBuf,
+    leases: RwLock<HashMap<Uuid, SnapshotLease>>,
+}
+
+impl Leases {
+    pub async fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir).await?;
+        let path = dir.join("leases.json");
+        let leases = match fs::read(&path).await {
+            Ok(contents) => serde_json::from_slice::<Vec<Versioned<SnapshotLease>>>(&contents)
+                .with_context(|| format!("failed to load {}", path.display()))?
+                .into_iter()
+                .map(|envelope| Ok(envelope.into_inner()?))
+                .map(|lease: anyhow::Result<SnapshotLease>| lease.map(|lease| (lease.id, lease)))
+                .collect::<anyhow::Result<_>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            leases: RwLock::new(leases),
+        })
+    }
+
+    async fn save(&self, leases: &HashMap<Uuid, SnapshotLease>) -> anyhow::Result<()> {
+        let envelopes: Vec<_> = leases.values().cloned().map(Versioned::new).collect();
+        // Write aside and rename, so a crash never leaves a partial file
+        let partial = self.path.with_extension("partial");
+        fs::write(&partial, serde_json::to_vec_pretty(&envelopes)?).await?;
+        fs::rename(&partial, &self.path).await?;
+        Ok(())
+    }
+
+    /// The lease table, held while a snapshot is deleted so no lease can be
+    /// granted on it meanwhile
+    pub async fn hold(&self) -> RwLockReadGuard<'_, HashMap<Uuid, SnapshotLease>> {
+        self.leases.read().await
+    }
+
+    /// Unexpired leases matching the query, restricted to `tenant` when given
+    pub async fn list(&self, query: &LeaseQuery, tenant: Option<&str>) -> Vec<SnapshotLease> {
+        let now = Utc::now();
+        let mut leases: Vec<_> = self
+            .leases
+            .read()
+            .await
+            .values()
+            .filter(|lease| !lease.is_expired(now))
+            .filter(|lease| tenant.is_none_or(|tenant| lease.tenant == tenant))
+            .filter(|lease| query.snapshot_id.is_none_or(|id| lease.snapshot_id == id))
+            .filter(|lease| query.holder.as_ref().is_none_or(|holder| &lease.holder == holder))
+            .cloned()
+            .collect();
+        leases.sort_by_key(|lease| lease.expires_at);
+        leases
+    }
+
+    /// Lease a snapshot of `tenant` for `ttl`
+    pub async fn grant(
+        &self,
+        vault: &SnapshotVault,
+        snapshot_id: Uuid,
+        tenant: String,
+        request: LeaseRequest,
+        ttl: chrono::Duration,
+    ) -> Result<SnapshotLease, VaultError> {
+        let mut leases = self.leases.write().await;
+        vault.get(snapshot_id, &tenant).await.ok_or(VaultError::NotFound)?;
+        let now = Utc::now();
+        let lease = SnapshotLease {
+            id: Uuid::new_v4(),
+            snapshot_id,
+            tenant,
+            holder: request.holder,
+            purpose: request.purpose,
+            created_at: now,
+            expires_at: now + ttl,
+        };
+        leases.insert(lease.id, lease.clone());
+        self.save(&leases).await?;
+        Ok(lease)
+    }
+
+    /// Extend an unexpired lease to `ttl` from now
+    pub async fn renew(
+        &self,
+        id: Uuid,
+        tenant: &str,
+        ttl: chrono::Duration,
+    ) -> Result<SnapshotLease, VaultError> {
+        let mut leases = self.leases.write().await;
+        let now = Utc::now();
+        let lease = leases
+            .get_mut(&id)
+            .filter(|lease| lease.tenant == tenant && !lease.is_expired(now))
+            .ok_or(VaultError::NotFound)?;
+        lease.expires_at = now + ttl;
+        let lease = lease.clone();
+        self.save(&leases).await?;
+        Ok(lease)
+    }
+
+    pub async fn release(&self, id: Uuid, tenant: &str) -> Result<(), VaultError> {
+        let mut leases = self.leases.write().await;
+        if leases.get(&id).is_none_or(|lease| lease.tenant != tenant) {
+            return Err(VaultError::NotFound);
+        }
+        leases.remove(&id);
+        self.save(&leases).await?;
+        Ok(())
+    }
+
+    /// Drop lapsed leases, returning them
+    pub async fn expire(&self) -> anyhow::Result<Vec<SnapshotLease>> {
+        let now = Utc::now();
+        let mut leases = self.leases.write().await;
+        let expired: Vec<_> = leases
+            .values()
+            .filter(|lease| lease.is_expired(now))
+            .cloned()
+            .collect();
+        if !expired.is_empty() {
+            for lease in &expired {
+                leases.remove(&lease.id);
+            }
+            self.save(&leases).await?;
+        }
+        Ok(expired)
+    }
+}
+
+/// Whether a lease table holds an unexpired lease on a snapshot
+pub fn is_leased(leases: &HashMap<Uuid, SnapshotLease>, snapshot_id: Uuid, now: DateTime<Utc>) -> bool {
+    leases
+        .values()
+        .any(|lease| lease.snapshot_id == snapshot_id && !lease.is_expired(now))
+}
+
+fn ttl(request: &LeaseRequest) -> Result<chrono::Duration, VaultError> {
+    let secs = request.ttl_secs.unwrap_or(DEFAULT_TTL_SECS);
+    if !(1..=MAX_TTL_SECS).contains(&secs) {
+        return Err(VaultError::Invalid(format!(
+            "ttl_secs must be between 1 and {}",
+            MAX_TTL_SECS
+        )));
+    }
+    Ok(chrono::Duration::seconds(secs as i64))
+}
+
+/// Lease a snapshot of the requesting tenant
+pub async fn create_lease(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(snapshot_id): Path<Uuid>,
+    Json(request): Json<LeaseRequest>,
+) -> Result<(StatusCode, Json<SnapshotLease>), VaultError> {
+    let tenant = tenant(&headers)?;
+    if request.holder.is_empty() {
+        return Err(VaultError::Invalid("holder is required".into()));
+    }
+    let ttl = ttl(&request)?;
+    let lease = state
+        .vault
+        .leases
+        .grant(&state.vault, snapshot_id, tenant, request, ttl)
+        .await?;
+
+    state.metrics.observe_leases(&state.vault).await;
+    info!(lease = %lease.id, snapshot = %snapshot_id, holder = %lease.holder, "snapshot leased");
+    Ok((StatusCode::CREATED, Json(lease)))
+}
+
+/// Leases of the requesting tenant (`snapshot_id`, `holder`)
+pub async fn list_leases(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<LeaseQuery>,
+) -> Result<Json<Vec<SnapshotLease>>, VaultError> {
+    let tenant = tenant(&headers)?;
+    Ok(Json(state.vault.leases.list(&query, Some(&tenant)).await))
+}
+
+/// Leases on one snapshot
+pub async fn list_snapshot_leases(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(snapshot_id): Path<Uuid>,
+) -> Result<Json<Vec<SnapshotLease>>, VaultError> {
+    let tenant = tenant(&headers)?;
+    state.vault.get(snapshot_id, &tenant).await.ok_or(VaultError::NotFound)?;
+    let query = LeaseQuery {
+        snapshot_id: Some(snapshot_id),
+        holder: None,
+    };
+    Ok(Json(state.vault.leases.list(&query, Some(&tenant)).await))
+}
+
+/// Extend a lease to `ttl_secs` from now. A lapsed lease can't be renewed,
+/// since its snapshot may already be gone; take a new one instead.
+pub async fn renew_lease(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    Json(request): Json<LeaseRequest>,
+) -> Result<Json<SnapshotLease>, VaultError> {
+    let tenant = tenant(&headers)?;
+    let ttl = ttl(&request)?;
+    Ok(Json(state.vault.leases.renew(id, &tenant, ttl).await?))
+}
+
+/// Give up a lease
+pub async fn release_lease(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, VaultError> {
+    state.vault.leases.release(id, &tenant(&headers)?).await?;
+    state.metrics.observe_leases(&state.vault).await;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,38 +1,202 @@
 use anyhow::Context;
+use base64::Engine;
 use axum::{
     body::Body,
-    extract::{Path, Query, State},
-    http::{Response, StatusCode},
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::{HeaderMap, Response, StatusCode},
     response::IntoResponse,
-    routing::{delete, get, post},
+    routing::{get, post},
     Json, Router,
 };
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::Utc;
+use serde::Deserialize;
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     net::SocketAddr,
-    path::{Path, PathBuf},
+    path::PathBuf,
     sync::Arc,
 };
 use thiserror::Error;
-use tokio::{fs, io::AsyncWriteExt, sync::RwLock};
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
+use prometheus::{CounterVec, GaugeVec};
+use sandstorm_http::Unavailable;
+use sandstorm_metrics::{ExemplarHistogram, Metrics};
+use sandstorm_types::{
+    provenance::RUN_ID_HEADER,
+    snapshot::{BlobFormat, BlobScan, SnapshotMetadata, StorageTier, DEFAULT_TENANT, TENANT_HEADER},
+    Versioned,
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::RwLock,
+};
+use tower_http::trace::TraceLayer;
 use tracing::{error, info};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use uuid::Uuid;
 
+mod backup;
+mod chunks;
+mod deletions;
+mod gc;
+mod keys;
+mod layers;
+mod leases;
+mod recordings;
+mod relay;
+mod scanning;
+mod schemas;
+mod scrub;
+mod tiering;
+mod uploads;
+mod validation;
+use keys::Keyring;
+use leases::Leases;
+use recordings::RecordingStore;
+use relay::Relay;
+use scanning::Scanners;
+use schemas::MetadataSchemas;
+use tiering::Tiering;
+use uploads::Uploads;
+use validation::{Rejection, ValidationPolicy};
+
 #[derive(Clone)]
 struct AppState {
     vault: Arc<SnapshotVault>,
+    recordings: Arc<RecordingStore>,
+    metrics: VaultMetrics,
+    admin_token: Option<String>,
+    scrubber: Arc<scrub::Scrubber>,
+}
+
+#[derive(Clone)]
+struct VaultMetrics {
+    shared: Metrics,
+    snapshots_stored: CounterVec,
+    snapshot_bytes_stored: CounterVec,
+    snapshot_store_duration: ExemplarHistogram,
+    tier_snapshots: GaugeVec,
+    tier_bytes: GaugeVec,
+    tier_moves: CounterVec,
+    validation_rejections: CounterVec,
+    snapshots_collected: CounterVec,
+    leases: GaugeVec,
+    scans: CounterVec,
+    scrubbed: CounterVec,
+    relayed_snapshots: CounterVec,
+}
+
+impl VaultMetrics {
+    fn new() -> Self {
+        let shared = Metrics::new("snapshot-vault");
+        Self {
+            snapshots_stored: shared.counter(
+                "snapshots_stored_total",
+                "Snapshots written to the vault",
+                &["provider"],
+            ),
+            snapshot_bytes_stored: shared.counter(
+                "snapshot_stored_bytes_total",
+                "Snapshot blob bytes written to the vault",
+                &["provider"],
+            ),
+            snapshot_store_duration: shared.histogram(
+                "snapshot_store_duration_seconds",
+                "Time to decode and persist a snapshot",
+                &["provider"],
+                sandstorm_metrics::LATENCY_BUCKETS.to_vec(),
+            ),
+            tier_snapshots: shared.gauge(
+                "snapshot_tier_snapshots",
+                "Snapshot blobs held in each storage tier",
+                &["tier"],
+            ),
+            tier_bytes: shared.gauge(
+                "snapshot_tier_bytes",
+                "Snapshot blob bytes held in each storage tier",
+                &["tier"],
+            ),
+            tier_moves: shared.counter(
+                "snapshot_tier_moves_total",
+                "Snapshot blobs moved between storage tiers, by destination",
+                &["tier"],
+            ),
+            validation_rejections: shared.counter(
+                "snapshot_validation_rejections_total",
+                "Snapshot blobs rejected on admission, by reason",
+                &["reason"],
+            ),
+            snapshots_collected: shared.counter(
+                "snapshots_collected_total",
+                "Snapshots deleted by garbage collection, by the tier their blob was in",
+                &["tier"],
+            ),
+            leases: shared.gauge(
+                "snapshot_leases",
+                "Unexpired leases on snapshots",
+                &[],
+            ),
+            scans: shared.counter(
+                "snapshot_scans_total",
+                "Snapshot blobs scanned before a restore, by verdict",
+                &["verdict"],
+            ),
+            scrubbed: shared.counter(
+                "snapshot_scrubbed_blobs_total",
+                "Snapshot blobs checked by the scrubber, by result",
+                &["result"],
+            ),
+            relayed_snapshots: shared.counter(
+                "snapshot_relay_transfers_total",
+                "Snapshots relayed from edge agents, by whether they were stored or failed",
+                &["state"],
+            ),
+            shared,
+        }
+    }
+
+    /// Refresh the per-tier usage gauges from the vault's index
+    async fn observe_tiers(&self, vault: &SnapshotVault) {
+        let mut usage = HashMap::from([(StorageTier::Hot, (0, 0)), (StorageTier::Cold, (0, 0))]);
+        for meta in vault.index.read().await.values().filter(|meta| meta.has_blob) {
+            let (snapshots, bytes) = usage.entry(meta.tier).or_default();
+            *snapshots += 1;
+            *bytes += meta.size_bytes;
+        }
+        for (tier, (snapshots, bytes)) in usage {
+            self.tier_snapshots
+                .with_label_values(&[tier.as_str()])
+                .set(snapshots as f64);
+            self.tier_bytes
+                .with_label_values(&[tier.as_str()])
+                .set(bytes as f64);
+        }
+    }
+
+    async fn observe_leases(&self, vault: &SnapshotVault) {
+        let leases = vault.leases.list(&Default::default(), None).await;
+        self.leases.with_label_values(&[]).set(leases.len() as f64);
+    }
 }
 
 #[derive(Debug, Error)]
 enum VaultError {
-    #[error("snapshot not found")]
+    #[error("not found")]
     NotFound,
     #[error("invalid request: {0}")]
     Invalid(String),
+    #[error("unauthorized")]
+    Unauthorized,
+    #[error("rejected: {0}")]
+    Rejected(Rejection),
+    #[error("snapshot {0} is leased")]
+    Leased(Uuid),
+    #[error("snapshot {0} is the base layer of other snapshots")]
+    Layered(Uuid),
+    #[error("snapshot {0} is under legal hold")]
+    LegalHold(Uuid),
+    #[error("snapshot {0} is blocked by its scan")]
+    Blocked(Uuid, Box<BlobScan>),
     #[error(transparent)]
     Io(#[from] std::io::Error),
     #[error(transparent)]
@@ -41,9 +205,32 @@ enum VaultError {
 
 impl IntoResponse for VaultError {
     fn into_response(self) -> axum::response::Response {
+        // Cold storage being down is worth retrying
+        if let VaultError::Other(e) = &self {
+            if let Some(unavailable) = Unavailable::find(e) {
+                return unavailable.clone().into_response();
+            }
+        }
         match &self {
             VaultError::NotFound => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
             VaultError::Invalid(_) => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+            VaultError::Unauthorized => (StatusCode::UNAUTHORIZED, self.to_string()).into_response(),
+            VaultError::Rejected(rejection) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, Json(rejection)).into_response()
+            }
+            VaultError::Leased(_) | VaultError::Layered(_) | VaultError::LegalHold(_) => {
+                (StatusCode::CONFLICT, self.to_string()).into_response()
+            }
+            VaultError::Blocked(id, scan) => (
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({
+                    "reason": "scan_blocked",
+                    "snapshot_id": id,
+                    "indicators": scan.indicators,
+                    "scanned_at": scan.scanned_at,
+                })),
+            )
+                .into_response(),
             VaultError::Io(_) | VaultError::Other(_) => {
                 error!(error = ?self, "snapshot vault error");
                 (StatusCode::INTERNAL_SERVER_ERROR, "internal error").into_response()
@@ -52,19 +239,6 @@ impl IntoResponse for VaultError {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct SnapshotMetadata {
-    id: Uuid,
-    sandbox_id: String,
-    provider: String,
-    filesystem_hash: String,
-    memory_hash: Option<String>,
-    size_bytes: u64,
-    created_at: DateTime<Utc>,
-    metadata: serde_json::Value,
-    has_blob: bool,
-}
-
 #[derive(Debug, Deserialize)]
 struct CreateSnapshotRequest {
     sandbox_id: String,
@@ -74,31 +248,114 @@ struct CreateSnapshotRequest {
     size_bytes: Option<u64>,
     metadata: Option<serde_json::Value>,
     data: Option<String>, // base64 encoded blob
+    /// Format to verify the blob against
+    #[serde(default)]
+    format: Option<BlobFormat>,
+    run_id: Option<Uuid>,
+    /// Keep the blob on local disk regardless of the tiering policy
+    #[serde(default)]
+    pinned: bool,
+    /// Snapshot of the same tenant the blob is layered on
+    #[serde(default)]
+    base_layer: Option<Uuid>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 struct ListQuery {
     sandbox_id: Option<String>,
+    run_id: Option<Uuid>,
     provider: Option<String>,
+    /// Snapshots layered directly on this one
+    base_layer: Option<Uuid>,
+    /// `meta.<field>` parameters filter on indexed metadata fields; others
+    /// are ignored
+    #[serde(flatten)]
+    params: HashMap<String, String>,
+}
+
+impl ListQuery {
+    fn metadata_filters(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params
+            .iter()
+            .filter_map(|(name, value)| Some((name.strip_prefix("meta.")?, value.as_str())))
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct RotatedKey {
+    tenant: String,
+    key_generation: u32,
+    /// Snapshots whose data keys were re-wrapped
+    rewrapped: usize,
 }
 
 struct SnapshotVault {
     root: PathBuf,
     index: RwLock<HashMap<Uuid, SnapshotMetadata>>,
+    /// Encrypts new blobs when a master key is configured
+    keyring: Option<Keyring>,
+    /// Moves idle blobs to cold storage when configured
+    tiering: Option<Tiering>,
+    /// Which blobs are checked before they are stored
+    validation: ValidationPolicy,
+    /// Holders' claims on snapshots, which keep them from being deleted
+    leases: Leases,
+    /// Scan blobs before they are restored when configured
+    scanners: Option<Scanners>,
+    /// Tenants' metadata schemas and the indexes they declare
+    schemas: MetadataSchemas,
+    /// Chunked uploads in progress
+    uploads: Uploads,
+    /// Snapshots edge agents are relaying through chunked uploads
+    relay: Relay,
 }
 
 impl SnapshotVault {
-    async fn new<P: AsRef<Path>>(root: P) -> anyhow::Result<Self> {
+    async fn new<P: AsRef<std::path::Path>>(
+        root: P,
+        keyring: Option<Keyring>,
+        tiering: Option<Tiering>,
+        validation: ValidationPolicy,
+        scanners: Option<Scanners>,
+    ) -> anyhow::Result<Self> {
         let root = root.as_ref().to_path_buf();
         fs::create_dir_all(&root).await?;
         let index = Self::load_index(&root).await?;
-        Ok(Self {
+        let leases = Leases::new(root.join("leases")).await?;
+        let schemas = MetadataSchemas::new(root.join("schemas")).await?;
+        let uploads = Uploads::new(root.join("uploads")).await?;
+        let relay = Relay::new(root.join("relay")).await?;
+        let vault = Self {
             root,
             index: RwLock::new(index),
-        })
+            keyring,
+            tiering,
+            validation,
+            leases,
+            scanners,
+            schemas,
+            uploads,
+            relay,
+        };
+        vault.schemas.rebuild(&vault).await;
+        Ok(vault)
+    }
+
+    fn blob_path(&self, id: Uuid) -> PathBuf {
+        self.root.join(format!("{}.blob", id))
+    }
+
+    fn manifest_path(&self, id: Uuid) -> PathBuf {
+        self.root.join(format!("{}.chunks", id))
     }
 
-    async fn load_index(root: &Path) -> anyhow::Result<HashMap<Uuid, SnapshotMetadata>> {
+    async fn write_metadata(&self, metadata: &SnapshotMetadata) -> anyhow::Result<()> {
+        let serialized = serde_json::to_vec_pretty(&Versioned::new(metadata.clone()))?;
+        fs::write(self.root.join(format!("{}.json", metadata.id)), serialized).await?;
+        Ok(())
+    }
+
+    async fn load_index(root: &std::path::Path) -> anyhow::Result<HashMap<Uuid, SnapshotMetadata>> {
         let mut entries = HashMap::new();
         let mut dir = fs::read_dir(root).await?;
 
@@ -106,7 +363,8 @@ impl SnapshotVault {
             let path = item.path();
             if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
                 let contents = fs::read(&path).await?;
-                let metadata: SnapshotMetadata = serde_json::from_slice(&contents)?;
+                let metadata = Self::decode_metadata(&contents)
+                    .with_context(|| format!("failed to load {}", path.display()))?;
                 entries.insert(metadata.id, metadata);
             }
         }
@@ -114,20 +372,69 @@ impl SnapshotVault {
         Ok(entries)
     }
 
-    async fn store(&self, request: CreateSnapshotRequest) -> anyhow::Result<SnapshotMetadata> {
+    /// Index files are written as versioned envelopes; bare metadata files
+    /// from before schema tagging are still accepted.
+    fn decode_metadata(contents: &[u8]) -> anyhow::Result<SnapshotMetadata> {
+        match serde_json::from_slice::<Versioned<SnapshotMetadata>>(contents) {
+            Ok(envelope) => Ok(envelope.into_inner()?),
+            Err(_) => Ok(serde_json::from_slice(contents)?),
+        }
+    }
+
+    async fn store(&self, mut request: CreateSnapshotRequest, tenant: String) -> Result<SnapshotMetadata, VaultError> {
+        let blob = match request.data.take() {
+            Some(blob) => Some(
+                base64::engine::general_purpose::STANDARD
+                    .decode(blob)
+                    .context("failed to decode snapshot data")?,
+            ),
+            None => None,
+        };
+        self.store_blob(request, blob, tenant).await
+    }
+
+    /// Store a snapshot with a blob already decoded, e.g. from a chunked
+    /// upload, in place of the request's `data`
+    async fn store_blob(
+        &self,
+        request: CreateSnapshotRequest,
+        blob: Option<Vec<u8>>,
+        tenant: String,
+    ) -> Result<SnapshotMetadata, VaultError> {
         let id = Uuid::new_v4();
         let now = Utc::now();
-        let blob_path = self.root.join(format!("{}.blob", id));
-        let meta_path = self.root.join(format!("{}.json", id));
+        let blob_path = self.blob_path(id);
 
         let mut size_bytes = request.size_bytes.unwrap_or(0);
         let mut has_blob = false;
+        let mut encryption = None;
+        let mut validation = None;
 
-        if let Some(blob) = request.data {
-            let data = base64::decode(blob).context("failed to decode snapshot data")?;
+        let metadata = request.metadata.unwrap_or_else(|| serde_json::json!({}));
+        self.schemas
+            .validate(&tenant, &metadata)
+            .await
+            .map_err(VaultError::Rejected)?;
+        if let Some(base) = request.base_layer {
+            layers::check_base(&*self.index.read().await, base, &tenant)?;
+        }
+
+        if let Some(data) = blob {
+            validation = validation::validate(self.validation, request.format, request.size_bytes, &data)
+                .map_err(VaultError::Rejected)?;
+            size_bytes = data.len() as u64;
+            chunks::write_manifest(self, &chunks::manifest_of(id, &data)).await?;
+            let data = match &self.keyring {
+                Some(keyring) => {
+                    let (sealed, sealed_with) =
+                        keyring.seal(&tenant, id, &data, chunks::CHUNK_SIZE).await?;
+                    encryption = Some(sealed_with);
+                    sealed
+                }
+                None => data,
+            };
             let mut file = fs::File::create(&blob_path).await?;
             file.write_all(&data).await?;
-            size_bytes = data.len() as u64;
             has_blob = true;
         }
 
@@ -139,51 +446,116 @@ impl SnapshotVault {
             memory_hash: request.memory_hash,
             size_bytes,
             created_at: now,
-            metadata: request.metadata.unwrap_or_else(|| serde_json::json!({})),
+            metadata,
             has_blob,
+            run_id: request.run_id,
+            tenant,
+            encryption,
+            tier: StorageTier::Hot,
+            pinned: request.pinned,
+            accessed_at: None,
+            validation,
+            scan: None,
+            base_layer: request.base_layer,
+            legal_hold: false,
         };
 
-        let serialized = serde_json::to_vec_pretty(&metadata)?;
-        fs::write(&meta_path, serialized).await?;
-
-        self.index.write().await.insert(id, metadata.clone());
+        // Checked again under the index lock, which deletes hold too, so the
+        // base can't be deleted before the snapshot is indexed
+        let mut index = self.index.write().await;
+        if let Some(base) = metadata.base_layer {
+            if let Err(e) = layers::check_base(&index, base, &metadata.tenant) {
+                drop(index);
+                for path in [blob_path, self.manifest_path(id)] {
+                    if fs::metadata(&path).await.is_ok() {
+                        fs::remove_file(path).await?;
+                    }
+                }
+                return Err(e);
+            }
+        }
+        self.write_metadata(&metadata).await?;
+        index.insert(id, metadata.clone());
+        drop(index);
+        self.schemas.insert(&metadata).await;
 
         Ok(metadata)
     }
 
-    async fn list(&self, query: &ListQuery) -> Vec<SnapshotMetadata> {
+    /// Snapshots matching `query`, restricted to `tenant` and to `ids` when
+    /// given
+    async fn list(
+        &self,
+        query: &ListQuery,
+        tenant: Option<&str>,
+        ids: Option<&BTreeSet<Uuid>>,
+    ) -> Vec<SnapshotMetadata> {
         let index = self.index.read().await;
-        index
-            .values()
+        let candidates: Box<dyn Iterator<Item = &SnapshotMetadata>> = match ids {
+            Some(ids) => Box::new(ids.iter().filter_map(|id| index.get(id))),
+            None => Box::new(index.values()),
+        };
+        candidates
             .filter(|meta| {
+                if tenant.is_some_and(|tenant| meta.tenant != tenant) {
+                    return false;
+                }
                 if let Some(sandbox_id) = &query.sandbox_id {
                     if &meta.sandbox_id != sandbox_id {
                         return false;
                     }
                 }
+                if query.run_id.is_some() && meta.run_id != query.run_id {
+                    return false;
+                }
                 if let Some(provider) = &query.provider {
                     if &meta.provider != provider {
                         return false;
                     }
                 }
+                if query.base_layer.is_some() && meta.base_layer != query.base_layer {
+                    return false;
+                }
                 true
             })
             .cloned()
             .collect()
     }
 
-    async fn get(&self, id: Uuid) -> Option<SnapshotMetadata> {
-        self.index.read().await.get(&id).cloned()
+    /// A snapshot, if it exists and belongs to `tenant`
+    async fn get(&self, id: Uuid, tenant: &str) -> Option<SnapshotMetadata> {
+        self.index
+            .read()
+            .await
+            .get(&id)
+            .filter(|meta| meta.tenant == tenant)
+            .cloned()
     }
 
-    async fn delete(&self, id: Uuid) -> anyhow::Result<()> {
+    /// Delete a snapshot and its blob, unless it is under legal hold, leased
+    /// or other snapshots are layered on it
+    async fn delete(&self, id: Uuid, tenant: &str) -> Result<(), VaultError> {
         let meta_path = self.root.join(format!("{}.json", id));
-        let blob_path = self.root.join(format!("{}.blob", id));
+        let blob_path = self.blob_path(id);
 
+        let leases = self.leases.hold().await;
         let mut index = self.index.write().await;
-        if index.remove(&id).is_none() {
-            return Err(VaultError::NotFound.into());
+        let Some(metadata) = index.get(&id).filter(|meta| meta.tenant == tenant) else {
+            return Err(VaultError::NotFound);
+        };
+        if metadata.legal_hold {
+            return Err(VaultError::LegalHold(id));
         }
+        if leases::is_leased(&leases, id, Utc::now()) {
+            return Err(VaultError::Leased(id));
+        }
+        if layers::has_dependents(&index, id) {
+            return Err(VaultError::Layered(id));
+        }
+        let cold = metadata.tier == StorageTier::Cold;
+        let metadata = index.remove(&id).expect("snapshot is in the index");
+        drop(index);
+        self.schemas.remove(&metadata).await;
 
         if fs::metadata(&meta_path).await.is_ok() {
             fs::remove_file(meta_path).await?;
@@ -191,22 +563,188 @@ impl SnapshotVault {
         if fs::metadata(&blob_path).await.is_ok() {
             fs::remove_file(blob_path).await?;
         }
+        if fs::metadata(self.manifest_path(id)).await.is_ok() {
+            fs::remove_file(self.manifest_path(id)).await?;
+        }
+        if cold {
+            match &self.tiering {
+                Some(tiering) => tiering.delete(id).await?,
+                None => error!(snapshot = %id, "cold blob left behind: SNAPSHOT_VAULT_COLD_URL is not set"),
+            }
+        }
 
         Ok(())
     }
 
-    async fn get_blob(&self, id: Uuid) -> Result<Vec<u8>, VaultError> {
-        let meta = self.get(id).await.ok_or(VaultError::NotFound)?;
+    /// Pin or unpin a snapshot. Pinning a cold snapshot brings its blob back
+    /// to local disk.
+    async fn set_pinned(&self, id: Uuid, tenant: &str, pinned: bool) -> Result<SnapshotMetadata, VaultError> {
+        let metadata = self.get(id, tenant).await.ok_or(VaultError::NotFound)?;
+        if pinned && metadata.tier == StorageTier::Cold {
+            self.restore(id).await?;
+        }
+
+        let mut index = self.index.write().await;
+        let metadata = index.get_mut(&id).ok_or(VaultError::NotFound)?;
+        metadata.pinned = pinned;
+        self.write_metadata(metadata).await?;
+        Ok(metadata.clone())
+    }
+
+    async fn restore(&self, id: Uuid) -> anyhow::Result<()> {
+        let tiering = self.tiering.as_ref().with_context(|| {
+            format!("snapshot {} is in cold storage but SNAPSHOT_VAULT_COLD_URL is not set", id)
+        })?;
+        tiering.restore(self, id).await
+    }
+
+    /// Replace the metadata of every snapshot, as when restoring a backup.
+    /// Blobs are left in place; returns the snapshots whose blob is missing.
+    async fn replace_all(&self, snapshots: Vec<SnapshotMetadata>) -> anyhow::Result<Vec<Uuid>> {
+        let mut index = self.index.write().await;
+        for id in index.keys() {
+            let meta_path = self.root.join(format!("{}.json", id));
+            if fs::metadata(&meta_path).await.is_ok() {
+                fs::remove_file(meta_path).await?;
+            }
+        }
+
+        let mut restored = HashMap::new();
+        let mut missing = Vec::new();
+        for metadata in snapshots {
+            self.write_metadata(&metadata).await?;
+            // Cold blobs live in the object store, not on local disk
+            if metadata.has_blob
+                && metadata.tier == StorageTier::Hot
+                && fs::metadata(self.blob_path(metadata.id)).await.is_err()
+            {
+                missing.push(metadata.id);
+            }
+            restored.insert(metadata.id, metadata);
+        }
+        *index = restored;
+        drop(index);
+        self.schemas.rebuild(self).await;
+
+        Ok(missing)
+    }
+
+    async fn get_blob(&self, id: Uuid, tenant: &str) -> Result<Vec<u8>, VaultError> {
+        let meta = self.get(id, tenant).await.ok_or(VaultError::NotFound)?;
+        let data = self.read_local(&meta, None).await?;
+        self.touch(id).await?;
+
+        match (&meta.encryption, &self.keyring) {
+            (None, _) => Ok(data),
+            (Some(encryption), Some(keyring)) => Ok(keyring.open(tenant, id, encryption, &data)?),
+            (Some(_), None) => Err(anyhow::anyhow!(
+                "snapshot {} is encrypted but SNAPSHOT_VAULT_MASTER_KEY is not set",
+                id
+            )
+            .into()),
+        }
+    }
+
+    /// Read a stored blob, or `len` bytes of it from `offset`, restoring it
+    /// from cold storage first if it has moved there
+    async fn read_local(
+        &self,
+        meta: &SnapshotMetadata,
+        range: Option<(u64, u64)>,
+    ) -> Result<Vec<u8>, VaultError> {
         if !meta.has_blob {
             return Err(VaultError::Invalid("snapshot has no blob".into()));
         }
-        let data = fs::read(self.root.join(format!("{}.blob", id))).await?;
-        Ok(data)
+        if meta.tier == StorageTier::Cold {
+            self.restore(meta.id).await?;
+        }
+        match read_file(&self.blob_path(meta.id), range).await {
+            // Moved to cold storage since the metadata was read
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && self.tiering.is_some() => {
+                self.restore(meta.id).await?;
+                Ok(read_file(&self.blob_path(meta.id), range).await?)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// Record a read, which keeps a blob in the hot tier
+    async fn touch(&self, id: Uuid) -> anyhow::Result<()> {
+        if let Some(metadata) = self.index.write().await.get_mut(&id) {
+            metadata.accessed_at = Some(Utc::now());
+            self.write_metadata(metadata).await?;
+        }
+        Ok(())
+    }
+
+    /// Move a tenant to a new key generation, re-wrapping the data keys of
+    /// its encrypted blobs. Blobs themselves are not rewritten.
+    async fn rotate_key(&self, tenant: &str) -> Result<RotatedKey, VaultError> {
+        let keyring = self
+            .keyring
+            .as_ref()
+            .ok_or_else(|| VaultError::Invalid("snapshot encryption is not enabled".into()))?;
+
+        // Holding the index serializes rotations. A snapshot stored
+        // concurrently may keep the old generation, which still opens it.
+        let mut index = self.index.write().await;
+        let generation = keyring.generation(tenant).await + 1;
+        let mut rewrapped = 0;
+        for metadata in index.values_mut().filter(|meta| meta.tenant == tenant) {
+            let Some(encryption) = &metadata.encryption else {
+                continue;
+            };
+            let mut updated = metadata.clone();
+            updated.encryption = Some(keyring.rewrap(tenant, updated.id, encryption, generation)?);
+            self.write_metadata(&updated).await?;
+            *metadata = updated;
+            rewrapped += 1;
+        }
+        keyring.set_generation(tenant, generation).await?;
+
+        Ok(RotatedKey {
+            tenant: tenant.to_string(),
+            key_generation: generation,
+            rewrapped,
+        })
     }
 }
 
+async fn read_file(path: &std::path::Path, range: Option<(u64, u64)>) -> std::io::Result<Vec<u8>> {
+    let Some((offset, len)) = range else {
+        return fs::read(path).await;
+    };
+    let mut file = fs::File::open(path).await?;
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    let mut data = vec![0; len as usize];
+    file.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+/// Tenant a request acts for, from the `X-Sandstorm-Tenant` header
+fn tenant(headers: &HeaderMap) -> Result<String, VaultError> {
+    let Some(value) = headers.get(TENANT_HEADER) else {
+        return Ok(DEFAULT_TENANT.to_string());
+    };
+    value
+        .to_str()
+        .ok()
+        .filter(|tenant| valid_tenant(tenant))
+        .map(str::to_string)
+        .ok_or_else(|| VaultError::Invalid("invalid tenant".into()))
+}
+
+/// Tenant names are 1–64 letters, digits, `.`, `_` or `-`
+fn valid_tenant(tenant: &str) -> bool {
+    (1..=64).contains(&tenant.len())
+        && tenant
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let log_shipping = sandstorm_logging::ShippingSettings::from_env("SNAPSHOT_VAULT")?;
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
@@ -217,14 +755,69 @@ async fn main() -> anyhow::Result<()> {
                 .with_target(false)
                 .with_ansi(false),
         )
+        .with(log_shipping.map(|settings| sandstorm_logging::ShippingLayer::spawn(settings, "snapshot-vault")))
         .init();
 
     let storage_root =
         std::env::var("SNAPSHOT_VAULT_PATH").unwrap_or_else(|_| "./data/snapshots".to_string());
-    let vault = Arc::new(SnapshotVault::new(storage_root).await?);
+    let keyring = Keyring::from_env(std::path::Path::new(&storage_root)).await?;
+    if keyring.is_some() {
+        info!("snapshot blob encryption enabled");
+    }
+    let metrics = VaultMetrics::new();
+    let tiering = Tiering::from_env(metrics.tier_moves.clone())?;
+    let tiered = tiering.is_some();
+    if let Some(tiering) = &tiering {
+        sandstorm_http::register_breakers(metrics.shared.registry(), &[tiering.breaker().clone()])?;
+    }
+    let validation = ValidationPolicy::from_env()?;
+    let scanners = Scanners::from_env(std::path::Path::new(&storage_root), metrics.scans.clone()).await?;
+    if scanners.is_some() {
+        info!("snapshot scanning before restore enabled");
+    }
+    let vault = Arc::new(
+        SnapshotVault::new(&storage_root, keyring, tiering, validation, scanners).await?,
+    );
+    metrics.observe_tiers(&vault).await;
+    if tiered {
+        tiering::spawn_migrations(vault.clone(), metrics.clone());
+        info!("cold storage tiering enabled");
+    }
+    let retention = gc::Retention::from_env()?;
+    if retention.is_some() {
+        info!("snapshot retention enabled");
+    }
+    metrics.observe_leases(&vault).await;
+    gc::spawn(vault.clone(), retention, metrics.clone());
+    let scrubber = Arc::new(scrub::Scrubber::from_env(metrics.scrubbed.clone())?);
+    scrub::spawn(vault.clone(), scrubber.clone())?;
+    let recordings =
+        Arc::new(RecordingStore::new(PathBuf::from(&storage_root).join("recordings")).await?);
 
-    let state = AppState { vault };
+    let backups = sandstorm_backup::Backups::from_env(
+        "snapshot-vault",
+        "SNAPSHOT_VAULT",
+        Arc::new(backup::VaultBackup {
+            vault: vault.clone(),
+            recordings: recordings.clone(),
+        }),
+    )?;
+    if let Some(backups) = &backups {
+        sandstorm_backup::spawn_schedule(backups.clone(), "SNAPSHOT_VAULT");
+        info!("backups enabled");
+    }
 
+    let shared_metrics = metrics.shared.clone();
+    let admin_token = std::env::var("SNAPSHOT_VAULT_ADMIN_TOKEN").ok();
+    let state = AppState {
+        vault,
+        recordings,
+        metrics,
+        admin_token: admin_token.clone(),
+        scrubber,
+    };
+
+    let http_security = sandstorm_http::HttpSecurity::from_env("SNAPSHOT_VAULT")?;
     let app = Router::new()
         .route("/health", get(health))
         .route("/v1/snapshots", post(create_snapshot).get(list_snapshots))
@@ -233,9 +826,81 @@ async fn main() -> anyhow::Result<()> {
             get(get_snapshot).delete(delete_snapshot),
         )
         .route("/v1/snapshots/:id/data", get(download_snapshot))
-        .layer(CorsLayer::permissive())
-        .layer(TraceLayer::new_for_http())
-        .with_state(state);
+        .route("/v1/snapshots/:id/layers", get(layers::get_layers))
+        .route("/v1/snapshots/:id/manifest", get(chunks::get_manifest))
+        .route("/v1/snapshots/:id/chunks/:index", get(chunks::download_chunk))
+        .route("/v1/snapshots/:id/scan", post(scanning::scan_snapshot))
+        .route(
+            "/v1/snapshots/:id/pin",
+            axum::routing::put(pin_snapshot).delete(unpin_snapshot),
+        )
+        .route(
+            "/v1/snapshots/:id/legal-hold",
+            axum::routing::put(deletions::place_legal_hold).delete(deletions::lift_legal_hold),
+        )
+        .route("/v1/deletions", post(deletions::delete_data))
+        .route(
+            "/v1/snapshots/:id/leases",
+            post(leases::create_lease).get(leases::list_snapshot_leases),
+        )
+        .route("/v1/uploads", post(uploads::start_upload))
+        .route(
+            "/v1/uploads/:id",
+            get(uploads::get_upload).delete(uploads::abort_upload),
+        )
+        .route(
+            "/v1/uploads/:id/chunks/:index",
+            axum::routing::put(uploads::put_chunk)
+                .layer(DefaultBodyLimit::max(chunks::CHUNK_SIZE as usize)),
+        )
+        .route("/v1/uploads/:id/complete", post(uploads::complete_upload))
+        .route("/v1/relay/agents", get(relay::list_agents))
+        .route("/v1/relay/agents/:agent/snapshots", get(relay::list_transfers))
+        .route(
+            "/v1/relay/agents/:agent/snapshots/:local_id",
+            axum::routing::put(relay::register_transfer)
+                .get(relay::get_transfer)
+                .delete(relay::delete_transfer),
+        )
+        .route(
+            "/v1/relay/agents/:agent/snapshots/:local_id/complete",
+            post(relay::complete_transfer),
+        )
+        .route("/v1/leases", get(leases::list_leases))
+        .route(
+            "/v1/leases/:id",
+            axum::routing::put(leases::renew_lease).delete(leases::release_lease),
+        )
+        .route("/v1/tenants/:tenant/keys/rotate", post(rotate_tenant_key))
+        .route("/v1/scrub", post(scrub::run_scrub))
+        .route("/v1/scrub/reports", get(scrub::list_reports))
+        .route(
+            "/v1/tenants/:tenant/metadata-schema",
+            get(schemas::get_schema)
+                .put(schemas::put_schema)
+                .delete(schemas::delete_schema),
+        )
+        .route(
+            "/v1/recordings",
+            post(recordings::create_recording).get(recordings::list_recordings),
+        )
+        .route(
+            "/v1/recordings/:id",
+            get(recordings::get_recording).delete(recordings::delete_recording),
+        )
+        .route("/v1/recordings/:id/cast", get(recordings::download_recording))
+        .with_state(state)
+        .merge(sandstorm_metrics::metrics_router(shared_metrics.clone()))
+        .merge(
+            backups
+                .map(|backups| sandstorm_backup::backup_router(backups, admin_token))
+                .unwrap_or_default(),
+        )
+        .layer(axum::middleware::from_fn_with_state(
+            shared_metrics,
+            sandstorm_metrics::track_http,
+        ));
+    let app = http_security.apply(app).layer(TraceLayer::new_for_http());
 
     let port: u16 = std::env::var("SNAPSHOT_VAULT_PORT")
         .ok()
@@ -245,7 +910,13 @@ async fn main() -> anyhow::Result<()> {
     info!("snapshot vault listening on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    match sandstorm_tls::TlsSettings::from_env("SNAPSHOT_VAULT")? {
+        Some(tls) => {
+            info!("mTLS enabled");
+            sandstorm_tls::serve(listener, app, tls).await?;
+        }
+        None => axum::serve(listener, app).await?,
+    }
 
     Ok(())
 }
@@ -256,33 +927,97 @@ async fn health() -> impl IntoResponse {
 
 async fn create_snapshot(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<CreateSnapshotRequest>,
 ) -> Result<Json<SnapshotMetadata>, VaultError> {
-    let metadata = state.vault.store(payload).await.map_err(VaultError::from)?;
-    Ok(Json(metadata))
+    Ok(Json(store_snapshot(&state, &headers, payload, None).await?))
+}
+
+/// Store a snapshot posted whole or assembled from a chunked upload, and
+/// count it
+async fn store_snapshot(
+    state: &AppState,
+    headers: &HeaderMap,
+    mut payload: CreateSnapshotRequest,
+    blob: Option<Vec<u8>>,
+) -> Result<SnapshotMetadata, VaultError> {
+    if payload.run_id.is_none() {
+        payload.run_id = headers
+            .get(RUN_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+    }
+    let trace_id = sandstorm_metrics::trace_id(headers)
+        .or_else(|| payload.run_id.map(|run_id| run_id.to_string()));
+    let tenant = tenant(headers)?;
+    let started = std::time::Instant::now();
+    let stored = match blob {
+        Some(blob) => state.vault.store_blob(payload, Some(blob), tenant).await,
+        None => state.vault.store(payload, tenant).await,
+    };
+    let metadata = match stored {
+        Err(VaultError::Rejected(rejection)) => {
+            state
+                .metrics
+                .validation_rejections
+                .with_label_values(&[rejection.reason.as_str()])
+                .inc();
+            return Err(VaultError::Rejected(rejection));
+        }
+        result => result?,
+    };
+
+    let labels = [metadata.provider.as_str()];
+    state.metrics.snapshot_store_duration.observe(
+        &labels,
+        started.elapsed().as_secs_f64(),
+        trace_id.as_deref(),
+    );
+    state.metrics.snapshots_stored.with_label_values(&labels).inc();
+    if metadata.has_blob {
+        state
+            .metrics
+            .snapshot_bytes_stored
+            .with_label_values(&labels)
+            .inc_by(metadata.size_bytes as f64);
+        state.metrics.observe_tiers(&state.vault).await;
+    }
+    Ok(metadata)
 }
 
 async fn list_snapshots(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Query(query): Query<ListQuery>,
 ) -> Result<Json<Vec<SnapshotMetadata>>, VaultError> {
-    let metas = state.vault.list(&query).await;
+    let tenant = tenant(&headers)?;
+    let ids = state.vault.schemas.lookup(&tenant, query.metadata_filters()).await?;
+    let metas = state.vault.list(&query, Some(&tenant), ids.as_ref()).await;
     Ok(Json(metas))
 }
 
 async fn get_snapshot(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Path(id): Path<Uuid>,
 ) -> Result<Json<SnapshotMetadata>, VaultError> {
-    let meta = state.vault.get(id).await.ok_or(VaultError::NotFound)?;
+    let meta = state
+        .vault
+        .get(id, &tenant(&headers)?)
+        .await
+        .ok_or(VaultError::NotFound)?;
     Ok(Json(meta))
 }
 
 async fn download_snapshot(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Path(id): Path<Uuid>,
 ) -> Result<Response<Body>, VaultError> {
-    let bytes = state.vault.get_blob(id).await?;
+    let tenant = tenant(&headers)?;
+    state.vault.clear_for_restore(id, &tenant).await?;
+    let bytes = state.vault.get_blob(id, &tenant).await?;
+    state.metrics.observe_tiers(&state.vault).await;
     Ok(Response::builder()
         .status(StatusCode::OK)
         .header("content-type", "application/octet-stream")
@@ -292,8 +1027,64 @@ async fn download_snapshot(
 
 async fn delete_snapshot(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Path(id): Path<Uuid>,
 ) -> Result<StatusCode, VaultError> {
-    state.vault.delete(id).await.map_err(VaultError::from)?;
+    state.vault.delete(id, &tenant(&headers)?).await?;
+    state.metrics.observe_tiers(&state.vault).await;
     Ok(StatusCode::NO_CONTENT)
 }
+
+async fn pin_snapshot(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<SnapshotMetadata>, VaultError> {
+    let metadata = state.vault.set_pinned(id, &tenant(&headers)?, true).await?;
+    state.metrics.observe_tiers(&state.vault).await;
+    Ok(Json(metadata))
+}
+
+async fn unpin_snapshot(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<SnapshotMetadata>, VaultError> {
+    Ok(Json(state.vault.set_pinned(id, &tenant(&headers)?, false).await?))
+}
+
+/// Require `Authorization: Bearer <token>` when `SNAPSHOT_VAULT_ADMIN_TOKEN`
+/// is set
+fn authorize(state: &AppState, headers: &HeaderMap) -> Result<(), VaultError> {
+    if let Some(token) = &state.admin_token {
+        let presented = headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if presented != Some(token.as_str()) {
+            return Err(VaultError::Unauthorized);
+        }
+    }
+    Ok(())
+}
+
+/// Rotate a tenant's key. Requires the admin token.
+async fn rotate_tenant_key(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(tenant): Path<String>,
+) -> Result<Json<RotatedKey>, VaultError> {
+    authorize(&state, &headers)?;
+    if !valid_tenant(&tenant) {
+        return Err(VaultError::Invalid("invalid tenant".into()));
+    }
+
+    let rotated = state.vault.rotate_key(&tenant).await?;
+    info!(
+        tenant = %rotated.tenant,
+        key_generation = rotated.key_generation,
+        rewrapped = rotated.rewrapped,
+        "rotated tenant key"
+    );
+    Ok(Json(rotated))
+}
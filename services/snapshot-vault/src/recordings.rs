@@ -0,0 +1,219 @@
+use anyhow::Context;
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{Response, StatusCode},
+    Json,
+};
+use sandstorm_types::{recording::SessionRecording, Versioned};
+use serde::Deserialize;
+use std::{collections::HashMap, path::PathBuf};
+use tokio::{fs, sync::RwLock};
+use uuid::Uuid;
+
+use crate::{AppState, VaultError};
+
+/// Content type for asciicast v2 files
+const ASCIICAST_CONTENT_TYPE: &str = "application/x-asciicast";
+
+#[derive(Debug, Deserialize)]
+pub struct CreateRecordingRequest {
+    recording: SessionRecording,
+    /// asciicast v2 document
+    cast: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RecordingQuery {
+    sandbox_id: Option<String>,
+    run_id: Option<Uuid>,
+    user: Option<String>,
+}
+
+/// Session recordings, kept in a `recordings` directory under the vault root
+pub struct RecordingStore {
+    root: PathBuf,
+    index: RwLock<HashMap<Uuid, SessionRecording>>,
+}
+
+impl RecordingStore {
+    pub async fn new(root: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&root).await?;
+
+        let mut index = HashMap::new();
+        let mut dir = fs::read_dir(&root).await?;
+        while let Some(item) = dir.next_entry().await? {
+            let path = item.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
+                let contents = fs::read(&path).await?;
+                let recording = serde_json::from_slice::<Versioned<SessionRecording>>(&contents)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|envelope| Ok(envelope.into_inner()?))
+                    .with_context(|| format!("failed to load {}", path.display()))?;
+                index.insert(recording.id, recording);
+            }
+        }
+
+        Ok(Self {
+            root,
+            index: RwLock::new(index),
+        })
+    }
+
+    async fn store(&self, request: CreateRecordingRequest) -> Result<SessionRecording, VaultError> {
+        let mut recording = request.recording;
+        if !request.cast.starts_with('{') {
+            return Err(VaultError::Invalid("cast must be an asciicast v2 document".into()));
+        }
+        if self.index.read().await.contains_key(&recording.id) {
+            return Err(VaultError::Invalid(format!(
+                "recording {} already exists",
+                recording.id
+            )));
+        }
+
+        recording.size_bytes = request.cast.len() as u64;
+        fs::write(self.root.join(format!("{}.cast", recording.id)), &request.cast).await?;
+        fs::write(
+            self.root.join(format!("{}.json", recording.id)),
+            serde_json::to_vec_pretty(&Versioned::new(recording.clone()))
+                .map_err(anyhow::Error::from)?,
+        )
+        .await?;
+
+        self.index
+            .write()
+            .await
+            .insert(recording.id, recording.clone());
+        Ok(recording)
+    }
+
+    async fn list(&self, query: &RecordingQuery) -> Vec<SessionRecording> {
+        let index = self.index.read().await;
+        let mut recordings: Vec<_> = index
+            .values()
+            .filter(|recording| {
+                if let Some(sandbox_id) = &query.sandbox_id {
+                    if &recording.sandbox_id != sandbox_id {
+                        return false;
+                    }
+                }
+                if query.run_id.is_some() && recording.run_id != query.run_id {
+                    return false;
+                }
+                if let Some(user) = &query.user {
+                    if &recording.user != user {
+                        return false;
+                    }
+                }
+                true
+            })
+            .cloned()
+            .collect();
+        recordings.sort_by_key(|recording| recording.started_at);
+        recordings
+    }
+
+    async fn get(&self, id: Uuid) -> Option<SessionRecording> {
+        self.index.read().await.get(&id).cloned()
+    }
+
+    pub async fn all(&self) -> Vec<SessionRecording> {
+        self.list(&RecordingQuery::default()).await
+    }
+
+    /// Replace the metadata of every recording, as when restoring a backup.
+    /// Casts are left in place; returns the recordings whose cast is missing.
+    pub async fn replace_all(&self, recordings: Vec<SessionRecording>) -> anyhow::Result<Vec<Uuid>> {
+        let mut index = self.index.write().await;
+        for id in index.keys() {
+            let path = self.root.join(format!("{}.json", id));
+            if fs::metadata(&path).await.is_ok() {
+                fs::remove_file(path).await?;
+            }
+        }
+
+        let mut restored = HashMap::new();
+        let mut missing = Vec::new();
+        for recording in recordings {
+            fs::write(
+                self.root.join(format!("{}.json", recording.id)),
+                serde_json::to_vec_pretty(&Versioned::new(recording.clone()))?,
+            )
+            .await?;
+            if fs::metadata(self.root.join(format!("{}.cast", recording.id)))
+                .await
+                .is_err()
+            {
+                missing.push(recording.id);
+            }
+            restored.insert(recording.id, recording);
+        }
+        *index = restored;
+
+        Ok(missing)
+    }
+
+    pub async fn delete(&self, id: Uuid) -> Result<(), VaultError> {
+        if self.index.write().await.remove(&id).is_none() {
+            return Err(VaultError::NotFound);
+        }
+
+        for ext in ["json", "cast"] {
+            let path = self.root.join(format!("{}.{}", id, ext));
+            if fs::metadata(&path).await.is_ok() {
+                fs::remove_file(path).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub async fn create_recording(
+    State(state): State<AppState>,
+    Json(payload): Json<CreateRecordingRequest>,
+) -> Result<(StatusCode, Json<SessionRecording>), VaultError> {
+    let recording = state.recordings.store(payload).await?;
+    Ok((StatusCode::CREATED, Json(recording)))
+}
+
+pub async fn list_recordings(
+    State(state): State<AppState>,
+    Query(query): Query<RecordingQuery>,
+) -> Json<Vec<SessionRecording>> {
+    Json(state.recordings.list(&query).await)
+}
+
+pub async fn get_recording(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<SessionRecording>, VaultError> {
+    let recording = state.recordings.get(id).await.ok_or(VaultError::NotFound)?;
+    Ok(Json(recording))
+}
+
+pub async fn download_recording(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Response<Body>, VaultError> {
+    state.recordings.get(id).await.ok_or(VaultError::NotFound)?;
+    let cast = fs::read(state.recordings.root.join(format!("{}.cast", id))).await?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", ASCIICAST_CONTENT_TYPE)
+        .header(
+            "content-disposition",
+            format!("attachment; filename=\"{}.cast\"", id),
+        )
+        .body(Body::from(cast))
+        .unwrap())
+}
+
+pub async fn delete_recording(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, VaultError> {
+    state.recordings.delete(id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,532 @@
+//! Snapshots relayed from edge agents. An agent that took snapshots while
+//! cut off from the vault registers each one here when it can, and gets a
+//! chunked upload to send the blob through whenever it is connected; an
+//! interrupted transfer resumes from the chunks that arrived. Completing
+//! the transfer stores the snapshot as if it had been uploaded directly.
+//!
+//! The vault keeps every transfer, by agent, in `relay/transfers.json`
+//! under the vault path, so operators can see what each agent still has
+//! to send.
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use chrono::Utc;
+use sandstorm_types::{
+    snapshot::{
+        RelayAgentSummary, RelayRegistration, RelayState, RelayTransfer, UploadRequest,
+        UploadSession,
+    },
+    Versioned,
+};
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::PathBuf};
+use tokio::{fs, sync::Mutex};
+use tracing::{info, warn};
+
+use crate::{store_snapshot, tenant, uploads, AppState, VaultError};
+
+type Key = (String, String);
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TransferQuery {
+    /// Only transfers in this state, e.g. `uploading`
+    state: Option<String>,
+}
+
+/// Every relayed snapshot, by agent and the agent's ID for it
+pub struct Relay {
+    path: PathBuf,
+    /// Held across a whole registration or completion, so an agent
+    /// retrying one can't start two uploads for the same snapshot
+    transfers: Mutex<BTreeMap<Key, RelayTransfer>>,
+}
+
+impl Relay {
+    pub async fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir).await?;
+        let path = dir.join("transfers.json");
+        let transfers = match fs::read(&path).await {
+            Ok(contents) => serde_json::from_slice::<Vec<Versioned<RelayTransfer>>>(&contents)
+                .with_context(|| format!("failed to load {}", path.display()))?
+                .into_iter()
+                .map(|envelope| {
+                    let transfer = envelope.into_inner()?;
+                    Ok(((transfer.agent_id.clone(), transfer.local_id.clone()), transfer))
+                })
+                .collect::<anyhow::Result<_>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            transfers: Mutex::new(transfers),
+        })
+    }
+
+    async fn save(&self, transfers: &BTreeMap<Key, RelayTransfer>) -> anyhow::Result<()> {
+        let envelopes: Vec<_> = transfers.values().cloned().map(Versioned::new).collect();
+        // Write aside and rename, so a crash never leaves a partial file
+        let partial = self.path.with_extension("partial");
+        fs::write(&partial, serde_json::to_vec_pretty(&envelopes)?).await?;
+        fs::rename(&partial, &self.path).await?;
+        Ok(())
+    }
+
+    /// Register a snapshot an agent holds, or pick up its transfer again.
+    /// Returns the transfer and whether it is new. A snapshot registered
+    /// again with a different blob is refused unless its transfer failed.
+    pub async fn register(
+        &self,
+        uploads: &uploads::Uploads,
+        tenant: String,
+        agent_id: &str,
+        local_id: &str,
+        registration: RelayRegistration,
+    ) -> Result<(RelayTransfer, bool), VaultError> {
+        check_id("agent ID", agent_id)?;
+        check_id("snapshot ID", local_id)?;
+        let sha256 = registration.sha256.to_lowercase();
+        let mut transfers = self.transfers.lock().await;
+        let key = (agent_id.to_string(), local_id.to_string());
+
+        if let Some(transfer) = transfers.get(&key).filter(|transfer| transfer.tenant == tenant) {
+            let same_blob = transfer.sha256 == sha256 && transfer.size_bytes == registration.size_bytes;
+            match transfer.state {
+                RelayState::Failed { .. } => {}
+                _ if !same_blob => {
+                    return Err(VaultError::Invalid(format!(
+                        "snapshot {} of agent {} was registered with a different blob",
+                        local_id, agent_id
+                    )))
+                }
+                _ => {
+                    let mut transfer = transfer.clone();
+                    if refresh(uploads, &mut transfer).await? {
+                        transfers.insert(key, transfer.clone());
+                        self.save(&transfers).await?;
+                    }
+                    return Ok((transfer, false));
+                }
+            }
+            if let Some(upload_id) = transfer.upload_id {
+                uploads.remove(upload_id).await?;
+            }
+        } else if transfers.contains_key(&key) {
+            return Err(VaultError::Invalid(format!(
+                "snapshot {} of agent {} belongs to another tenant",
+                local_id, agent_id
+            )));
+        }
+
+        let session = uploads
+            .start(
+                tenant.clone(),
+                UploadRequest {
+                    size_bytes: registration.size_bytes,
+                    sha256,
+                },
+            )
+            .await?;
+        let now = Utc::now();
+        let transfer = RelayTransfer {
+            agent_id: agent_id.to_string(),
+            local_id: local_id.to_string(),
+            tenant,
+            size_bytes: session.size_bytes,
+            sha256: session.sha256.clone(),
+            taken_at: registration.taken_at,
+            snapshot: registration.snapshot,
+            state: RelayState::Pending,
+            upload_id: Some(session.id),
+            chunk_count: session.chunk_count(),
+            chunks_received: 0,
+            registered_at: now,
+            updated_at: now,
+        };
+        transfers.insert(key, transfer.clone());
+        self.save(&transfers).await?;
+        info!(agent = agent_id, snapshot = local_id, upload = %session.id, "relayed snapshot registered");
+        Ok((transfer, true))
+    }
+
+    /// A transfer, with the progress of its upload
+    pub async fn get(
+        &self,
+        uploads: &uploads::Uploads,
+        tenant: &str,
+        agent_id: &str,
+        local_id: &str,
+    ) -> Result<RelayTransfer, VaultError> {
+        let mut transfers = self.transfers.lock().await;
+        let key = (agent_id.to_string(), local_id.to_string());
+        let transfer = transfers
+            .get_mut(&key)
+            .filter(|transfer| transfer.tenant == tenant)
+            .ok_or(VaultError::NotFound)?;
+        if refresh(uploads, transfer).await? {
+            let transfer = transfer.clone();
+            self.save(&transfers).await?;
+            return Ok(transfer);
+        }
+        Ok(transfer.clone())
+    }
+
+    /// An agent's transfers, optionally in one state, oldest snapshot first
+    pub async fn list(
+        &self,
+        uploads: &uploads::Uploads,
+        tenant: &str,
+        agent_id: &str,
+        state: Option<&str>,
+    ) -> Result<Vec<RelayTransfer>, VaultError> {
+        let mut transfers = self.transfers.lock().await;
+        let mut changed = false;
+        let mut listed = Vec::new();
+        for transfer in transfers
+            .values_mut()
+            .filter(|transfer| transfer.agent_id == agent_id && transfer.tenant == tenant)
+        {
+            changed |= refresh(uploads, transfer).await?;
+            if state.is_none_or(|state| state_name(&transfer.state) == state) {
+                listed.push(transfer.clone());
+            }
+        }
+        if changed {
+            self.save(&transfers).await?;
+        }
+        listed.sort_by(|a, b| a.taken_at.cmp(&b.taken_at).then_with(|| a.local_id.cmp(&b.local_id)));
+        Ok(listed)
+    }
+
+    /// Each agent's transfers by state, from the last recorded progress
+    pub async fn summaries(&self, tenant: &str) -> Vec<RelayAgentSummary> {
+        let transfers = self.transfers.lock().await;
+        let mut summaries: BTreeMap<&str, RelayAgentSummary> = BTreeMap::new();
+        for transfer in transfers.values().filter(|transfer| transfer.tenant == tenant) {
+            let summary = summaries
+                .entry(&transfer.agent_id)
+                .or_insert_with(|| RelayAgentSummary {
+                    agent_id: transfer.agent_id.clone(),
+                    ..Default::default()
+                });
+            match transfer.state {
+                RelayState::Pending => summary.pending += 1,
+                RelayState::Uploading => summary.uploading += 1,
+                RelayState::Stored { .. } => summary.stored += 1,
+                RelayState::Failed { .. } => summary.failed += 1,
+            }
+            if !matches!(transfer.state, RelayState::Stored { .. }) {
+                summary.bytes_outstanding += transfer.size_bytes;
+            }
+            summary.last_activity = summary.last_activity.max(Some(transfer.updated_at));
+        }
+        summaries.into_values().collect()
+    }
+
+    /// Record how a transfer's completion went, dropping its upload
+    async fn finish(
+        &self,
+        uploads: &uploads::Uploads,
+        key: &Key,
+        state: RelayState,
+    ) -> Result<RelayTransfer, VaultError> {
+        let mut transfers = self.transfers.lock().await;
+        let transfer = transfers.get_mut(key).ok_or(VaultError::NotFound)?;
+        if let Some(upload_id) = transfer.upload_id.take() {
+            uploads.remove(upload_id).await?;
+        }
+        transfer.state = state;
+        transfer.updated_at = Utc::now();
+        let transfer = transfer.clone();
+        self.save(&transfers).await?;
+        Ok(transfer)
+    }
+
+    /// Forget a transfer, dropping its upload if it has one
+    pub async fn remove(
+        &self,
+        uploads: &uploads::Uploads,
+        tenant: &str,
+        agent_id: &str,
+        local_id: &str,
+    ) -> Result<(), VaultError> {
+        let mut transfers = self.transfers.lock().await;
+        let key = (agent_id.to_string(), local_id.to_string());
+        match transfers.get(&key) {
+            Some(transfer) if transfer.tenant == tenant => {}
+            _ => return Err(VaultError::NotFound),
+        }
+        if let Some(upload_id) = transfers.remove(&key).and_then(|transfer| transfer.upload_id) {
+            uploads.remove(upload_id).await?;
+        }
+        self.save(&transfers).await?;
+        Ok(())
+    }
+}
+
+/// Bring a transfer up to date with its upload, starting a new upload if
+/// the old one expired. Returns whether anything changed.
+async fn refresh(uploads: &uploads::Uploads, transfer: &mut RelayTransfer) -> Result<bool, VaultError> {
+    if matches!(transfer.state, RelayState::Stored { .. } | RelayState::Failed { .. }) {
+        return Ok(false);
+    }
+    let session = match transfer.upload_id {
+        Some(upload_id) => match uploads.get(upload_id, &transfer.tenant).await {
+            Ok(session) => Some(session),
+            Err(VaultError::NotFound) => None,
+            Err(e) => return Err(e),
+        },
+        None => None,
+    };
+    let session: UploadSession = match session {
+        Some(session) => session,
+        None => {
+            warn!(
+                agent = %transfer.agent_id,
+                snapshot = %transfer.local_id,
+                "relay upload expired; starting over"
+            );
+            let request = UploadRequest {
+                size_bytes: transfer.size_bytes,
+                sha256: transfer.sha256.clone(),
+            };
+            let session = uploads.start(transfer.tenant.clone(), request).await?;
+            transfer.upload_id = Some(session.id);
+            session
+        }
+    };
+
+    let received = session.received.len() as u64;
+    let state = if received == 0 {
+        RelayState::Pending
+    } else {
+        RelayState::Uploading
+    };
+    if transfer.chunks_received == received && transfer.state == state && transfer.upload_id == Some(session.id) {
+        return Ok(false);
+    }
+    transfer.chunks_received = received;
+    transfer.state = state;
+    transfer.updated_at = Utc::now();
+    Ok(true)
+}
+
+fn state_name(state: &RelayState) -> &'static str {
+    match state {
+        RelayState::Pending => "pending",
+        RelayState::Uploading => "uploading",
+        RelayState::Stored { .. } => "stored",
+        RelayState::Failed { .. } => "failed",
+    }
+}
+
+/// Agent and snapshot IDs are 1–128 letters, digits, `.`, `_` or `-`
+fn check_id(what: &str, id: &str) -> Result<(), VaultError> {
+    let valid = (1..=128).contains(&id.len())
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'));
+    if valid {
+        Ok(())
+    } else {
+        Err(VaultError::Invalid(format!("invalid {}", what)))
+    }
+}
+
+pub async fn list_agents(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<RelayAgentSummary>>, VaultError> {
+    let tenant = tenant(&headers)?;
+    Ok(Json(state.vault.relay.summaries(&tenant).await))
+}
+
+pub async fn list_transfers(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(agent_id): Path<String>,
+    Query(query): Query<TransferQuery>,
+) -> Result<Json<Vec<RelayTransfer>>, VaultError> {
+    let tenant = tenant(&headers)?;
+    let vault = &state.vault;
+    let transfers = vault
+        .relay
+        .list(&vault.uploads, &tenant, &agent_id, query.state.as_deref())
+        .await?;
+    Ok(Json(transfers))
+}
+
+/// Register a snapshot for relay, or resume its transfer
+pub async fn register_transfer(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((agent_id, local_id)): Path<(String, String)>,
+    Json(registration): Json<RelayRegistration>,
+) -> Result<(StatusCode, Json<RelayTransfer>), VaultError> {
+    let tenant = tenant(&headers)?;
+    let vault = &state.vault;
+    let (transfer, created) = vault
+        .relay
+        .register(&vault.uploads, tenant, &agent_id, &local_id, registration)
+        .await?;
+    let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+    Ok((status, Json(transfer)))
+}
+
+pub async fn get_transfer(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((agent_id, local_id)): Path<(String, String)>,
+) -> Result<Json<RelayTransfer>, VaultError> {
+    let tenant = tenant(&headers)?;
+    let vault = &state.vault;
+    Ok(Json(vault.relay.get(&vault.uploads, &tenant, &agent_id, &local_id).await?))
+}
+
+/// Store a relayed snapshot once all its chunks have arrived. A blob that
+/// doesn't match its hash, or that the vault rejects, fails the transfer.
+pub async fn complete_transfer(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((agent_id, local_id)): Path<(String, String)>,
+) -> Result<Json<RelayTransfer>, VaultError> {
+    let tenant = tenant(&headers)?;
+    let vault = &state.vault;
+    let transfer = vault.relay.get(&vault.uploads, &tenant, &agent_id, &local_id).await?;
+    let upload_id = match (&transfer.state, transfer.upload_id) {
+        (RelayState::Stored { .. }, _) => return Ok(Json(transfer)),
+        (RelayState::Failed { error }, _) => return Err(VaultError::Invalid(error.clone())),
+        (_, Some(upload_id)) => upload_id,
+        (_, None) => return Err(VaultError::NotFound),
+    };
+    if transfer.chunks_received < transfer.chunk_count {
+        return Err(VaultError::Invalid(format!(
+            "{} of {} chunks have arrived",
+            transfer.chunks_received, transfer.chunk_count
+        )));
+    }
+
+    let key = (agent_id, local_id);
+    let stored = match vault.uploads.assemble(upload_id, &tenant).await {
+        Ok(blob) => {
+            let request = uploads::snapshot_request(transfer.snapshot.clone(), blob.len() as u64);
+            store_snapshot(&state, &headers, request, Some(blob)).await
+        }
+        Err(e) => Err(e),
+    };
+    // A bad blob won't get better; anything else may pass on a retry
+    let (state_after, failure) = match stored {
+        Ok(metadata) => (RelayState::Stored { snapshot_id: metadata.id }, None),
+        Err(e @ (VaultError::Invalid(_) | VaultError::Rejected(_))) => {
+            (RelayState::Failed { error: e.to_string() }, Some(e))
+        }
+        Err(e) => return Err(e),
+    };
+    state
+        .metrics
+        .relayed_snapshots
+        .with_label_values(&[state_name(&state_after)])
+        .inc();
+    let transfer = vault.relay.finish(&vault.uploads, &key, state_after).await?;
+    info!(agent = %key.0, snapshot = %key.1, state = state_name(&transfer.state), "relay transfer finished");
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(Json(transfer)),
+    }
+}
+
+pub async fn delete_transfer(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((agent_id, local_id)): Path<(String, String)>,
+) -> Result<StatusCode, VaultError> {
+    let tenant = tenant(&headers)?;
+    let vault = &state.vault;
+    vault.relay.remove(&vault.uploads, &tenant, &agent_id, &local_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sandstorm_types::snapshot::SnapshotUpload;
+    use sha2::{Digest, Sha256};
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn tracks_an_agents_transfer_across_restarts() {
+        let dir = std::env::temp_dir().join(format!("vault-relay-{}", Uuid::new_v4()));
+        let uploads = uploads::Uploads::new(dir.join("uploads")).await.unwrap();
+        let relay = Relay::new(dir.join("relay")).await.unwrap();
+
+        let blob = b"edge snapshot".to_vec();
+        let registration = RelayRegistration {
+            size_bytes: blob.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(&blob)),
+            taken_at: Utc::now(),
+            snapshot: SnapshotUpload {
+                sandbox_id: "sb-1".into(),
+                provider: "edge".into(),
+                filesystem_hash: "fs".into(),
+                memory_hash: None,
+                metadata: None,
+                format: None,
+                run_id: None,
+                pinned: false,
+                base_layer: None,
+            },
+        };
+        let (transfer, created) = relay
+            .register(&uploads, "acme".into(), "agent-1", "snap-1", registration.clone())
+            .await
+            .unwrap();
+        assert!(created);
+        assert_eq!(transfer.state, RelayState::Pending);
+        assert!(relay.register(&uploads, "acme".into(), "../agent", "snap-1", registration.clone()).await.is_err());
+
+        let upload_id = transfer.upload_id.unwrap();
+        let hash = format!("{:x}", Sha256::digest(&blob));
+        uploads.put_chunk(upload_id, "acme", 0, &hash, &blob).await.unwrap();
+
+        // A restarted vault still knows the transfer, and re-registering
+        // resumes it rather than starting over
+        let relay = Relay::new(dir.join("relay")).await.unwrap();
+        let (transfer, created) = relay
+            .register(&uploads, "acme".into(), "agent-1", "snap-1", registration.clone())
+            .await
+            .unwrap();
+        assert!(!created);
+        assert_eq!(transfer.upload_id, Some(upload_id));
+        assert_eq!(transfer.state, RelayState::Uploading);
+        assert_eq!(transfer.chunks_received, 1);
+
+        let different = RelayRegistration {
+            sha256: "0".repeat(64),
+            ..registration
+        };
+        assert!(relay.register(&uploads, "acme".into(), "agent-1", "snap-1", different).await.is_err());
+        assert!(relay.get(&uploads, "globex", "agent-1", "snap-1").await.is_err());
+
+        let summaries = relay.summaries("acme").await;
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].uploading, 1);
+        assert_eq!(summaries[0].bytes_outstanding, blob.len() as u64);
+
+        let key = ("agent-1".to_string(), "snap-1".to_string());
+        let snapshot_id = Uuid::new_v4();
+        let transfer = relay.finish(&uploads, &key, RelayState::Stored { snapshot_id }).await.unwrap();
+        assert_eq!(transfer.upload_id, None);
+        assert!(uploads.get(upload_id, "acme").await.is_err());
+        let stored = relay.list(&uploads, "acme", "agent-1", Some("stored")).await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(relay.summaries("acme").await[0].bytes_outstanding, 0);
+
+        relay.remove(&uploads, "acme", "agent-1", "snap-1").await.unwrap();
+        assert!(relay.list(&uploads, "acme", "agent-1", None).await.unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,420 @@
+//! Scans snapshot blobs before they are restored. Registered scanners look
+//! for known-bad hashes and YARA rule matches in the filesystem image, and
+//! depending on `SNAPSHOT_VAULT_SCAN` a snapshot with indicators is only
+//! flagged or is blocked, in which case restoring it is refused.
+//!
+//! A blob is scanned the first time it is requested for a restore. The
+//! verdict is kept in its metadata, so later restores don't scan again
+//! until the rules or the policy change.
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    Json,
+};
+use chrono::Utc;
+use prometheus::CounterVec;
+use sandstorm_types::snapshot::{BlobScan, ScanIndicator, SnapshotMetadata};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+use tokio::{fs, sync::Mutex};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{tenant, validation::tar_entries, AppState, SnapshotVault, VaultError};
+
+/// Longest a YARA scan of one blob may take
+const YARA_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// What the vault does with indicators, from `SNAPSHOT_VAULT_SCAN`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanPolicy {
+    /// Indicators are recorded and logged; restores go ahead (`warn`)
+    Warn,
+    /// Snapshots with indicators can't be restored (`block`)
+    Block,
+}
+
+impl ScanPolicy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ScanPolicy::Warn => "warn",
+            ScanPolicy::Block => "block",
+        }
+    }
+}
+
+/// A check run over a decrypted blob
+#[async_trait]
+pub trait Scanner: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Changes whenever the scanner's rules do
+    fn fingerprint(&self) -> &str;
+
+    async fn scan(&self, metadata: &SnapshotMetadata, blob: &[u8]) -> Result<Vec<ScanIndicator>>;
+}
+
+/// Known-bad SHA-256 hashes, matched against the whole blob, the hashes the
+/// snapshot declared, and each member of a tar archive blob
+pub struct HashList {
+    /// Hash to the label it was listed with, or itself
+    hashes: HashMap<String, String>,
+    fingerprint: String,
+}
+
+impl HashList {
+    /// One hex SHA-256 per line, optionally followed by a label. `#` starts
+    /// a comment.
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut hashes = HashMap::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some(hash) = line.split_whitespace().next() else {
+                continue;
+            };
+            let hash = hash.to_ascii_lowercase();
+            if hash.len() != 64 || !hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                bail!("line {} is not a SHA-256 hash", index + 1);
+            }
+            let label = line[line.find(char::is_whitespace).unwrap_or(line.len())..].trim();
+            let label = if label.is_empty() { hash.clone() } else { label.to_string() };
+            hashes.insert(hash, label);
+        }
+        Ok(Self {
+            hashes,
+            fingerprint: format!("{:x}", Sha256::digest(contents)),
+        })
+    }
+
+    fn check(&self, hash: &str, location: Option<&str>, found: &mut Vec<ScanIndicator>) {
+        let hash = hash.strip_prefix("sha256:").unwrap_or(hash).to_ascii_lowercase();
+        if let Some(label) = self.hashes.get(&hash) {
+            found.push(ScanIndicator {
+                scanner: self.name().to_string(),
+                rule: label.clone(),
+                location: location.map(str::to_string),
+            });
+        }
+    }
+}
+
+#[async_trait]
+impl Scanner for HashList {
+    fn name(&self) -> &'static str {
+        "hash_list"
+    }
+
+    fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    async fn scan(&self, metadata: &SnapshotMetadata, blob: &[u8]) -> Result<Vec<ScanIndicator>> {
+        let mut found = Vec::new();
+        self.check(&format!("{:x}", Sha256::digest(blob)), None, &mut found);
+        self.check(&metadata.filesystem_hash, Some("filesystem_hash"), &mut found);
+        if let Some(memory_hash) = &metadata.memory_hash {
+            self.check(memory_hash, Some("memory_hash"), &mut found);
+        }
+        // Blobs that aren't archives end the walk at their first "header"
+        for (name, data) in tar_entries(blob).map_while(Result::ok) {
+            self.check(&format!("{:x}", Sha256::digest(data)), Some(name), &mut found);
+        }
+        Ok(found)
+    }
+}
+
+/// YARA rules, run with the `yara` command line tool over the blob
+pub struct Yara {
+    binary: String,
+    rules: PathBuf,
+    /// Where decrypted blobs are written for `yara` to read
+    scratch: PathBuf,
+    fingerprint: String,
+}
+
+impl Yara {
+    pub async fn new(binary: String, rules: PathBuf, scratch: PathBuf) -> Result<Self> {
+        let contents = fs::read(&rules)
+            .await
+            .with_context(|| format!("failed to read YARA rules {}", rules.display()))?;
+        fs::create_dir_all(&scratch).await?;
+        Ok(Self {
+            fingerprint: format!("{:x}", Sha256::digest(&contents)),
+            binary,
+            rules,
+            scratch,
+        })
+    }
+}
+
+#[async_trait]
+impl Scanner for Yara {
+    fn name(&self) -> &'static str {
+        "yara"
+    }
+
+    fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    async fn scan(&self, metadata: &SnapshotMetadata, blob: &[u8]) -> Result<Vec<ScanIndicator>> {
+        let image = self.scratch.join(format!("{}.img", metadata.id));
+        fs::write(&image, blob).await?;
+        let output = tokio::time::timeout(
+            YARA_TIMEOUT,
+            tokio::process::Command::new(&self.binary)
+                .arg("--no-warnings")
+                .arg(&self.rules)
+                .arg(&image)
+                .kill_on_drop(true)
+                .output(),
+        )
+        .await;
+        fs::remove_file(&image).await.ok();
+
+        let output = output
+            .context("yara timed out")?
+            .with_context(|| format!("failed to run {}", self.binary))?;
+        if !output.status.success() {
+            bail!("yara failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        // One `<rule> <file>` line per matching rule
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .map(|rule| ScanIndicator {
+                scanner: self.name().to_string(),
+                rule: rule.to_string(),
+                location: None,
+            })
+            .collect())
+    }
+}
+
+/// The registered scanners and what their indicators do
+pub struct Scanners {
+    policy: ScanPolicy,
+    scanners: Vec<Box<dyn Scanner>>,
+    /// Fingerprint of the policy and every scanner's rules
+    ruleset: String,
+    /// Scans run one at a time, so concurrent restores of a blob scan it once
+    scanning: Mutex<()>,
+    /// Scans run, by verdict
+    scans: CounterVec,
+}
+
+impl Scanners {
+    /// Scanning with the policy in `SNAPSHOT_VAULT_SCAN` (`warn` or
+    /// `block`), the hash list at `SNAPSHOT_VAULT_SCAN_HASHES` and the YARA
+    /// rules at `SNAPSHOT_VAULT_SCAN_YARA`; `None` when the policy is unset
+    /// or `off`
+    pub async fn from_env(root: &std::path::Path, scans: CounterVec) -> Result<Option<Self>> {
+        let policy = match std::env::var("SNAPSHOT_VAULT_SCAN").as_deref() {
+            Err(_) | Ok("off") => return Ok(None),
+            Ok("warn") => ScanPolicy::Warn,
+            Ok("block") => ScanPolicy::Block,
+            Ok(other) => bail!("invalid SNAPSHOT_VAULT_SCAN {:?}; expected off, warn or block", other),
+        };
+
+        let mut scanners: Vec<Box<dyn Scanner>> = Vec::new();
+        if let Ok(path) = std::env::var("SNAPSHOT_VAULT_SCAN_HASHES") {
+            let contents = fs::read_to_string(&path)
+                .await
+                .with_context(|| format!("failed to read SNAPSHOT_VAULT_SCAN_HASHES file {}", path))?;
+            let hashes = HashList::parse(&contents)
+                .with_context(|| format!("invalid SNAPSHOT_VAULT_SCAN_HASHES file {}", path))?;
+            scanners.push(Box::new(hashes));
+        }
+        if let Ok(rules) = std::env::var("SNAPSHOT_VAULT_SCAN_YARA") {
+            let binary = std::env::var("SNAPSHOT_VAULT_YARA_BIN").unwrap_or_else(|_| "yara".to_string());
+            scanners.push(Box::new(Yara::new(binary, rules.into(), root.join("scans")).await?));
+        }
+        if scanners.is_empty() {
+            bail!("SNAPSHOT_VAULT_SCAN is set but neither SNAPSHOT_VAULT_SCAN_HASHES nor SNAPSHOT_VAULT_SCAN_YARA is");
+        }
+        Ok(Some(Self::new(policy, scanners, scans)))
+    }
+
+    pub fn new(policy: ScanPolicy, scanners: Vec<Box<dyn Scanner>>, scans: CounterVec) -> Self {
+        let mut ruleset = Sha256::new();
+        ruleset.update(policy.as_str());
+        for scanner in &scanners {
+            ruleset.update(format!("\n{}:{}", scanner.name(), scanner.fingerprint()));
+        }
+        Self {
+            policy,
+            scanners,
+            ruleset: format!("{:x}", ruleset.finalize())[..16].to_string(),
+            scanning: Mutex::new(()),
+            scans,
+        }
+    }
+}
+
+impl SnapshotVault {
+    /// Refuse to restore a blocked snapshot. Scans its blob first if it has
+    /// no verdict under the current rules.
+    pub async fn clear_for_restore(&self, id: Uuid, tenant: &str) -> Result<(), VaultError> {
+        let Some(scanners) = &self.scanners else {
+            return Ok(());
+        };
+        let meta = self.get(id, tenant).await.ok_or(VaultError::NotFound)?;
+        if !meta.has_blob {
+            return Ok(());
+        }
+        let scan = match meta.scan.filter(|scan| scan.ruleset == scanners.ruleset) {
+            Some(scan) => scan,
+            None => self.scan(id, tenant, false).await?,
+        };
+        if scan.blocked {
+            return Err(VaultError::Blocked(id, Box::new(scan)));
+        }
+        Ok(())
+    }
+
+    /// Run every scanner over a snapshot's blob and record the verdict.
+    /// Unless `force`d, a verdict under the current rules is reused.
+    pub async fn scan(&self, id: Uuid, tenant: &str, force: bool) -> Result<BlobScan, VaultError> {
+        let scanners = self
+            .scanners
+            .as_ref()
+            .ok_or_else(|| VaultError::Invalid("snapshot scanning is not enabled".into()))?;
+        let _scanning = scanners.scanning.lock().await;
+
+        // Another restore may have scanned it while this one waited
+        let meta = self.get(id, tenant).await.ok_or(VaultError::NotFound)?;
+        if let Some(scan) = meta.scan.as_ref().filter(|scan| !force && scan.ruleset == scanners.ruleset) {
+            return Ok(scan.clone());
+        }
+
+        let blob = self.get_blob(id, tenant).await?;
+        let mut indicators = Vec::new();
+        for scanner in &scanners.scanners {
+            let found = scanner
+                .scan(&meta, &blob)
+                .await
+                .with_context(|| format!("{} scan of snapshot {} failed", scanner.name(), id))?;
+            indicators.extend(found);
+        }
+        let scan = BlobScan {
+            ruleset: scanners.ruleset.clone(),
+            blocked: scanners.policy == ScanPolicy::Block && !indicators.is_empty(),
+            indicators,
+            scanned_at: Utc::now(),
+        };
+
+        let verdict = match (&scan.indicators[..], scan.blocked) {
+            ([], _) => "clean",
+            (_, false) => "flagged",
+            (_, true) => "blocked",
+        };
+        scanners.scans.with_label_values(&[verdict]).inc();
+        if scan.indicators.is_empty() {
+            info!(snapshot = %id, "snapshot scanned clean");
+        } else {
+            let rules: Vec<_> = scan.indicators.iter().map(|found| found.rule.as_str()).collect();
+            warn!(snapshot = %id, blocked = scan.blocked, rules = ?rules, "snapshot scan found indicators");
+        }
+
+        let mut index = self.index.write().await;
+        if let Some(metadata) = index.get_mut(&id) {
+            metadata.scan = Some(scan.clone());
+            self.write_metadata(metadata).await?;
+        }
+        Ok(scan)
+    }
+}
+
+/// Scan a snapshot now, with the current rules, and return the verdict
+pub async fn scan_snapshot(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<BlobScan>, VaultError> {
+    let tenant = tenant(&headers)?;
+    let meta = state.vault.get(id, &tenant).await.ok_or(VaultError::NotFound)?;
+    if !meta.has_blob {
+        return Err(VaultError::Invalid("snapshot has no blob".into()));
+    }
+    Ok(Json(state.vault.scan(id, &tenant, true).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateSnapshotRequest;
+    use base64::Engine;
+    use prometheus::Opts;
+
+    async fn store(vault: &SnapshotVault, blob: &[u8]) -> Uuid {
+        vault
+            .store(
+                CreateSnapshotRequest {
+                    sandbox_id: "sandbox".to_string(),
+                    provider: "gvisor".to_string(),
+                    filesystem_hash: "hash".to_string(),
+                    memory_hash: None,
+                    size_bytes: None,
+                    metadata: None,
+                    data: Some(base64::engine::general_purpose::STANDARD.encode(blob)),
+                    format: None,
+                    run_id: None,
+                    pinned: false,
+                    base_layer: None,
+                },
+                "acme".into(),
+            )
+            .await
+            .unwrap()
+            .id
+    }
+
+    #[test]
+    fn hash_lists_parse_labels_and_comments() {
+        let bad = format!("{:x}", Sha256::digest(b"payload"));
+        let list = HashList::parse(&format!("# known droppers\n{} dropper-v2\n\n{}\n", bad, "AB".repeat(32))).unwrap();
+        assert_eq!(list.hashes[&bad], "dropper-v2");
+        assert_eq!(list.hashes[&"ab".repeat(32)], "ab".repeat(32));
+        assert!(HashList::parse("not-a-hash").is_err());
+    }
+
+    #[tokio::test]
+    async fn blocked_snapshots_are_refused_until_rules_change() {
+        let dir = std::env::temp_dir().join(format!("vault-scanning-{}", Uuid::new_v4()));
+        let scans = CounterVec::new(Opts::new("scans", "scans"), &["verdict"]).unwrap();
+        let bad = format!("{:x}", Sha256::digest(b"payload"));
+        let hashes = HashList::parse(&format!("{} dropper", bad)).unwrap();
+        let scanners = Scanners::new(ScanPolicy::Block, vec![Box::new(hashes)], scans.clone());
+        let vault = SnapshotVault::new(&dir, None, None, Default::default(), Some(scanners))
+            .await
+            .unwrap();
+
+        let infected = store(&vault, b"payload").await;
+        let clean = store(&vault, b"something else").await;
+
+        match vault.clear_for_restore(infected, "acme").await {
+            Err(VaultError::Blocked(id, scan)) => {
+                assert_eq!(id, infected);
+                assert_eq!(scan.indicators[0].rule, "dropper");
+            }
+            other => panic!("expected a blocked snapshot, got {:?}", other),
+        }
+        assert!(vault.clear_for_restore(clean, "acme").await.is_ok());
+        assert!(vault.get(infected, "acme").await.unwrap().scan.unwrap().blocked);
+
+        // Verdicts are reused until the rules change
+        assert!(vault.clear_for_restore(infected, "acme").await.is_err());
+        assert_eq!(scans.with_label_values(&["blocked"]).get(), 1.0);
+        assert_eq!(scans.with_label_values(&["clean"]).get(), 1.0);
+
+        let mut vault = vault;
+        let hashes = HashList::parse(&"ab".repeat(32)).unwrap();
+        vault.scanners = Some(Scanners::new(ScanPolicy::Block, vec![Box::new(hashes)], scans));
+        assert!(vault.clear_for_restore(infected, "acme").await.is_ok());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
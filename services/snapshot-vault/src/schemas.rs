@@ -0,0 +1,454 @@
+//! Per-tenant schemas for snapshot `metadata`. A tenant with a schema can't
+//! store snapshots whose metadata doesn't match it, and the top-level fields
+//! its schema declares as indexes are kept in memory by value, so listing
+//! snapshots by them doesn't scan every snapshot.
+//!
+//! Index fields have a scalar type in the schema, and query values are
+//! parsed as that type: `meta.build=42` finds the integer 42, not the
+//! string `"42"`.
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use chrono::Utc;
+use jsonschema::JSONSchema;
+use sandstorm_types::{
+    snapshot::{MetadataSchema, MetadataSchemaRequest, SnapshotMetadata},
+    Versioned,
+};
+use serde_json::Value;
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+};
+use tokio::{fs, sync::RwLock};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+    authorize, valid_tenant,
+    validation::{RejectReason, Rejection},
+    AppState, SnapshotVault, VaultError,
+};
+
+/// Schema errors reported when metadata is rejected
+const MAX_ERRORS: usize = 5;
+
+/// Type an index field holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldType {
+    String,
+    Integer,
+    Number,
+    Boolean,
+}
+
+impl FieldType {
+    fn of(name: &str) -> Option<Self> {
+        match name {
+            "string" => Some(FieldType::String),
+            "integer" => Some(FieldType::Integer),
+            "number" => Some(FieldType::Number),
+            "boolean" => Some(FieldType::Boolean),
+            _ => None,
+        }
+    }
+
+    /// A query value as this type
+    fn parse(&self, raw: &str) -> Option<Value> {
+        match self {
+            FieldType::String => Some(Value::String(raw.to_string())),
+            FieldType::Integer => raw.parse::<i64>().ok().map(Value::from),
+            FieldType::Number => raw
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(Value::Number),
+            FieldType::Boolean => raw.parse::<bool>().ok().map(Value::Bool),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            FieldType::String => "a string",
+            FieldType::Integer => "an integer",
+            FieldType::Number => "a number",
+            FieldType::Boolean => "true or false",
+        }
+    }
+}
+
+/// Key a scalar is indexed under; numbers that are equal get the same key
+/// however they were written
+fn index_key(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(format!("s:{}", value)),
+        Value::Bool(value) => Some(format!("b:{}", value)),
+        Value::Number(number) => {
+            let number = number.as_f64()?;
+            Some(if number.fract() == 0.0 && number.abs() < 9.0e15 {
+                format!("n:{}", number as i64)
+            } else {
+                format!("n:{}", number)
+            })
+        }
+        _ => None,
+    }
+}
+
+struct TenantSchema {
+    definition: MetadataSchema,
+    compiled: JSONSchema,
+    types: HashMap<String, FieldType>,
+    /// Field to value key to the snapshots holding it
+    index: HashMap<String, HashMap<String, BTreeSet<Uuid>>>,
+}
+
+impl TenantSchema {
+    fn new(definition: MetadataSchema) -> Result<Self, String> {
+        let compiled = JSONSchema::compile(&definition.schema).map_err(|e| format!("invalid schema: {}", e))?;
+        let mut types = HashMap::new();
+        for field in &definition.indexes {
+            let declared = definition.schema["properties"][field]["type"].as_str();
+            let field_type = declared.and_then(FieldType::of).ok_or_else(|| {
+                format!(
+                    "index field {} needs a string, integer, number or boolean type under properties",
+                    field
+                )
+            })?;
+            types.insert(field.clone(), field_type);
+        }
+        Ok(Self {
+            index: types.keys().map(|field| (field.clone(), HashMap::new())).collect(),
+            definition,
+            compiled,
+            types,
+        })
+    }
+
+    fn insert(&mut self, meta: &SnapshotMetadata) {
+        for (field, values) in &mut self.index {
+            if let Some(key) = meta.metadata.get(field).and_then(index_key) {
+                values.entry(key).or_default().insert(meta.id);
+            }
+        }
+    }
+
+    fn remove(&mut self, meta: &SnapshotMetadata) {
+        for (field, values) in &mut self.index {
+            let Some(key) = meta.metadata.get(field).and_then(index_key) else {
+                continue;
+            };
+            if let Some(ids) = values.get_mut(&key) {
+                ids.remove(&meta.id);
+                if ids.is_empty() {
+                    values.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+/// Every tenant's schema, kept in `schemas/<tenant>.json` under the vault
+/// path, with its indexes.
+///
+/// The vault never takes this lock while holding its snapshot index; the
+/// schemas are updated after the index is, and rebuilt from it.
+pub struct MetadataSchemas {
+    dir: PathBuf,
+    tenants: RwLock<HashMap<String, TenantSchema>>,
+}
+
+impl MetadataSchemas {
+    pub async fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir).await?;
+        let mut tenants = HashMap::new();
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let contents = fs::read(&path).await?;
+            let definition = serde_json::from_slice::<Versioned<MetadataSchema>>(&contents)
+                .with_context(|| format!("failed to load {}", path.display()))?
+                .into_inner()?;
+            let schema = TenantSchema::new(definition)
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("failed to load {}", path.display()))?;
+            tenants.insert(schema.definition.tenant.clone(), schema);
+        }
+        Ok(Self {
+            dir,
+            tenants: RwLock::new(tenants),
+        })
+    }
+
+    fn path(&self, tenant: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", tenant))
+    }
+
+    pub async fn get(&self, tenant: &str) -> Option<MetadataSchema> {
+        self.tenants
+            .read()
+            .await
+            .get(tenant)
+            .map(|schema| schema.definition.clone())
+    }
+
+    /// Check metadata against its tenant's schema, if the tenant has one
+    pub async fn validate(&self, tenant: &str, metadata: &Value) -> Result<(), Rejection> {
+        let tenants = self.tenants.read().await;
+        let Some(schema) = tenants.get(tenant) else {
+            return Ok(());
+        };
+        let Err(errors) = schema.compiled.validate(metadata) else {
+            return Ok(());
+        };
+        let errors: Vec<_> = errors
+            .take(MAX_ERRORS)
+            .map(|error| match error.instance_path.to_string() {
+                path if path.is_empty() => error.to_string(),
+                path => format!("{}: {}", path, error),
+            })
+            .collect();
+        Err(Rejection {
+            reason: RejectReason::MetadataInvalid,
+            format: None,
+            message: errors.join("; "),
+        })
+    }
+
+    /// Set a tenant's schema and index the tenant's snapshots by it
+    pub async fn set(&self, vault: &SnapshotVault, definition: MetadataSchema) -> Result<(), VaultError> {
+        let mut schema = TenantSchema::new(definition).map_err(VaultError::Invalid)?;
+        let mut tenants = self.tenants.write().await;
+        let serialized = serde_json::to_vec_pretty(&Versioned::new(schema.definition.clone()))
+            .map_err(anyhow::Error::from)?;
+        fs::write(self.path(&schema.definition.tenant), serialized).await?;
+
+        for meta in vault.index.read().await.values() {
+            if meta.tenant == schema.definition.tenant {
+                schema.insert(meta);
+            }
+        }
+        tenants.insert(schema.definition.tenant.clone(), schema);
+        Ok(())
+    }
+
+    pub async fn delete(&self, tenant: &str) -> Result<(), VaultError> {
+        let mut tenants = self.tenants.write().await;
+        if tenants.remove(tenant).is_none() {
+            return Err(VaultError::NotFound);
+        }
+        fs::remove_file(self.path(tenant)).await?;
+        Ok(())
+    }
+
+    /// Index a stored snapshot
+    pub async fn insert(&self, meta: &SnapshotMetadata) {
+        if let Some(schema) = self.tenants.write().await.get_mut(&meta.tenant) {
+            schema.insert(meta);
+        }
+    }
+
+    /// Drop a deleted snapshot from the indexes
+    pub async fn remove(&self, meta: &SnapshotMetadata) {
+        if let Some(schema) = self.tenants.write().await.get_mut(&meta.tenant) {
+            schema.remove(meta);
+        }
+    }
+
+    /// Re-index every snapshot, as after a backup is restored
+    pub async fn rebuild(&self, vault: &SnapshotVault) {
+        let mut tenants = self.tenants.write().await;
+        for schema in tenants.values_mut() {
+            schema.index.values_mut().for_each(HashMap::clear);
+        }
+        for meta in vault.index.read().await.values() {
+            if let Some(schema) = tenants.get_mut(&meta.tenant) {
+                schema.insert(meta);
+            }
+        }
+    }
+
+    /// Snapshots of `tenant` whose indexed fields equal every filter, or
+    /// `None` when there are no filters
+    pub async fn lookup<'a>(
+        &self,
+        tenant: &str,
+        filters: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Option<BTreeSet<Uuid>>, VaultError> {
+        let tenants = self.tenants.read().await;
+        let mut matching: Option<BTreeSet<Uuid>> = None;
+        for (field, raw) in filters {
+            let schema = tenants.get(tenant).ok_or_else(|| {
+                VaultError::Invalid(format!("tenant {} has no metadata schema to query by", tenant))
+            })?;
+            let field_type = schema.types.get(field).ok_or_else(|| {
+                let mut indexed: Vec<_> = schema.types.keys().map(String::as_str).collect();
+                indexed.sort_unstable();
+                VaultError::Invalid(format!(
+                    "{} is not an indexed metadata field (indexed: {})",
+                    field,
+                    indexed.join(", ")
+                ))
+            })?;
+            let key = field_type
+                .parse(raw)
+                .as_ref()
+                .and_then(index_key)
+                .ok_or_else(|| VaultError::Invalid(format!("meta.{} must be {}", field, field_type.as_str())))?;
+
+            let ids = schema.index[field].get(&key).cloned().unwrap_or_default();
+            matching = Some(match matching {
+                Some(matching) => matching.intersection(&ids).copied().collect(),
+                None => ids,
+            });
+        }
+        Ok(matching)
+    }
+}
+
+/// A tenant's metadata schema
+pub async fn get_schema(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(tenant): Path<String>,
+) -> Result<Json<MetadataSchema>, VaultError> {
+    authorize(&state, &headers)?;
+    let schema = state.vault.schemas.get(&tenant).await.ok_or(VaultError::NotFound)?;
+    Ok(Json(schema))
+}
+
+/// Set a tenant's metadata schema. Snapshots already stored aren't
+/// validated against it, but are indexed by it.
+pub async fn put_schema(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(tenant): Path<String>,
+    Json(request): Json<MetadataSchemaRequest>,
+) -> Result<Json<MetadataSchema>, VaultError> {
+    authorize(&state, &headers)?;
+    if !valid_tenant(&tenant) {
+        return Err(VaultError::Invalid("invalid tenant".into()));
+    }
+    let definition = MetadataSchema {
+        tenant,
+        schema: request.schema,
+        indexes: request.indexes,
+        updated_at: Utc::now(),
+    };
+    state.vault.schemas.set(&state.vault, definition.clone()).await?;
+    info!(tenant = %definition.tenant, indexes = ?definition.indexes, "metadata schema set");
+    Ok(Json(definition))
+}
+
+/// Remove a tenant's metadata schema and its indexes
+pub async fn delete_schema(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(tenant): Path<String>,
+) -> Result<StatusCode, VaultError> {
+    authorize(&state, &headers)?;
+    state.vault.schemas.delete(&tenant).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CreateSnapshotRequest, ListQuery};
+
+    fn request(metadata: Value) -> CreateSnapshotRequest {
+        CreateSnapshotRequest {
+            sandbox_id: "sandbox".to_string(),
+            provider: "gvisor".to_string(),
+            filesystem_hash: "hash".to_string(),
+            memory_hash: None,
+            size_bytes: None,
+            metadata: Some(metadata),
+            data: None,
+            format: None,
+            run_id: None,
+            pinned: false,
+            base_layer: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn validates_metadata_and_queries_typed_indexes() {
+        let dir = std::env::temp_dir().join(format!("vault-schemas-{}", Uuid::new_v4()));
+        let vault = SnapshotVault::new(&dir, None, None, Default::default(), None).await.unwrap();
+        let old = vault
+            .store(request(serde_json::json!({ "template": "python-3.12", "build": 7 })), "acme".into())
+            .await
+            .unwrap();
+
+        let schema = serde_json::json!({
+            "type": "object",
+            "required": ["template"],
+            "properties": {
+                "template": { "type": "string" },
+                "branch": { "type": "string" },
+                "build": { "type": "integer" },
+                "tags": { "type": "array" },
+            },
+        });
+        let definition = |indexes: &[&str]| MetadataSchema {
+            tenant: "acme".to_string(),
+            schema: schema.clone(),
+            indexes: indexes.iter().map(|field| field.to_string()).collect(),
+            updated_at: Utc::now(),
+        };
+        assert!(vault.schemas.set(&vault, definition(&["tags"])).await.is_err());
+        vault.schemas.set(&vault, definition(&["template", "branch", "build"])).await.unwrap();
+
+        let rejected = vault
+            .store(request(serde_json::json!({ "branch": "main" })), "acme".into())
+            .await;
+        assert!(matches!(
+            rejected,
+            Err(VaultError::Rejected(Rejection { reason: RejectReason::MetadataInvalid, .. }))
+        ));
+        // Other tenants have no schema
+        vault.store(request(serde_json::json!({})), "globex".into()).await.unwrap();
+
+        let main = vault
+            .store(
+                request(serde_json::json!({ "template": "python-3.12", "branch": "main", "build": 8 })),
+                "acme".into(),
+            )
+            .await
+            .unwrap();
+        vault
+            .store(request(serde_json::json!({ "template": "node-20", "branch": "main" })), "acme".into())
+            .await
+            .unwrap();
+
+        let find = |filters: Vec<(&'static str, &'static str)>| {
+            let vault = &vault;
+            async move {
+                let ids = vault.schemas.lookup("acme", filters).await?;
+                let found = vault.list(&ListQuery::default(), Some("acme"), ids.as_ref()).await;
+                Ok::<_, VaultError>(found.into_iter().map(|meta| meta.id).collect::<Vec<_>>())
+            }
+        };
+        assert_eq!(find(vec![("template", "python-3.12"), ("branch", "main")]).await.unwrap(), [main.id]);
+        assert_eq!(find(vec![("build", "7")]).await.unwrap(), [old.id]);
+        assert!(find(vec![("build", "seven")]).await.is_err());
+        assert!(find(vec![("sandbox", "x")]).await.is_err());
+
+        vault.delete(main.id, "acme").await.unwrap();
+        assert!(find(vec![("branch", "main"), ("template", "python-3.12")]).await.unwrap().is_empty());
+
+        // Schemas survive a restart
+        let reloaded = MetadataSchemas::new(dir.join("schemas")).await.unwrap();
+        assert_eq!(reloaded.get("acme").await.unwrap().indexes.len(), 3);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,454 @@
+//! Scrubbing: re-reading stored blobs against the chunk hashes recorded when
+//! they were written, so corruption on disk is found before a restore trips
+//! over it. A blob that fails is replaced from the replica store, when one is
+//! configured and its copy checks out. Each pass ends in a report, and each
+//! corrupt blob is raised with the security monitor as a
+//! `snapshot_corruption` event for the sandbox it was taken from.
+//!
+//! Only hot blobs are scrubbed; cold ones are left to the object store's own
+//! checks, since reading them would bring them back to local disk.
+
+use anyhow::{Context, Result};
+use axum::{extract::State, http::HeaderMap, Json};
+use chrono::{DateTime, Utc};
+use prometheus::CounterVec;
+use sandstorm_backup::ObjectStore;
+use sandstorm_types::{
+    security::{EventType, SecurityEvent, Severity},
+    snapshot::{ChunkInfo, SnapshotManifest, SnapshotMetadata, StorageTier},
+};
+use serde::Serialize;
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+use tokio::{fs, sync::Mutex, sync::RwLock};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{authorize, chunks, tiering, AppState, SnapshotVault, VaultError};
+
+/// Reports kept for `GET /v1/scrub/reports`
+const KEPT_REPORTS: usize = 30;
+
+/// What was wrong with a blob
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "problem", rename_all = "snake_case")]
+pub enum Problem {
+    /// The blob file is gone though its snapshot says it has one
+    Missing,
+    /// The blob no longer decrypts, so its contents can't be checked
+    Undecryptable,
+    /// These chunks hash differently than when the blob was written
+    Mismatch { chunks: Vec<u64> },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScrubFinding {
+    pub snapshot_id: Uuid,
+    pub tenant: String,
+    pub sandbox_id: String,
+    #[serde(flatten)]
+    pub problem: Problem,
+    /// Replaced with the replica's copy, which checked out
+    pub repaired: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScrubReport {
+    pub id: Uuid,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// Blobs read and compared with their manifest
+    pub checked: usize,
+    /// Hot blobs without a manifest to compare with, or encrypted ones
+    /// without the master key
+    pub skipped: usize,
+    pub corrupt: usize,
+    pub repaired: usize,
+    pub findings: Vec<ScrubFinding>,
+}
+
+/// How one blob checked out
+enum Outcome {
+    Intact,
+    Skipped,
+    Corrupt(Problem),
+}
+
+pub struct Scrubber {
+    /// Copies of the vault's blobs kept by storage replication, under the
+    /// same keys as the cold tier
+    replica: Option<ObjectStore>,
+    http: reqwest::Client,
+    monitor_url: Option<String>,
+    webhook_url: Option<String>,
+    /// Blobs checked, by result
+    results: CounterVec,
+    /// Latest reports, oldest first
+    reports: RwLock<VecDeque<ScrubReport>>,
+    /// Held while a pass runs, so passes don't overlap
+    running: Mutex<()>,
+}
+
+impl Scrubber {
+    /// Repairs from `SNAPSHOT_VAULT_REPLICA_URL` (with
+    /// `SNAPSHOT_VAULT_REPLICA_TOKEN`), alerting
+    /// `SNAPSHOT_VAULT_SECURITY_MONITOR_URL` and
+    /// `SNAPSHOT_VAULT_SCRUB_WEBHOOK_URL` when set
+    pub fn from_env(results: CounterVec) -> Result<Self> {
+        let replica = std::env::var("SNAPSHOT_VAULT_REPLICA_URL")
+            .ok()
+            .map(|url| ObjectStore::from_url(&url, std::env::var("SNAPSHOT_VAULT_REPLICA_TOKEN").ok()))
+            .transpose()
+            .context("invalid SNAPSHOT_VAULT_REPLICA_URL")?;
+        Ok(Self::new(
+            replica,
+            std::env::var("SNAPSHOT_VAULT_SECURITY_MONITOR_URL").ok(),
+            std::env::var("SNAPSHOT_VAULT_SCRUB_WEBHOOK_URL").ok(),
+            results,
+        ))
+    }
+
+    pub fn new(
+        replica: Option<ObjectStore>,
+        monitor_url: Option<String>,
+        webhook_url: Option<String>,
+        results: CounterVec,
+    ) -> Self {
+        Self {
+            replica,
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            monitor_url: monitor_url.map(|url| url.trim_end_matches('/').to_string()),
+            webhook_url,
+            results,
+            reports: RwLock::new(VecDeque::new()),
+            running: Mutex::new(()),
+        }
+    }
+
+    /// Check every hot blob, repair what the replica can, and report
+    pub async fn scrub(&self, vault: &SnapshotVault) -> ScrubReport {
+        let _running = self.running.lock().await;
+        let started_at = Utc::now();
+        let candidates: Vec<_> = vault
+            .index
+            .read()
+            .await
+            .values()
+            .filter(|meta| meta.has_blob && meta.tier == StorageTier::Hot)
+            .cloned()
+            .collect();
+
+        let (mut checked, mut skipped) = (0, 0);
+        let mut findings = Vec::new();
+        for meta in candidates {
+            let outcome = match check(vault, &meta).await {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    warn!(snapshot = %meta.id, "failed to scrub blob: {:#}", e);
+                    Outcome::Skipped
+                }
+            };
+            let problem = match outcome {
+                Outcome::Intact => {
+                    checked += 1;
+                    self.results.with_label_values(&["intact"]).inc();
+                    continue;
+                }
+                Outcome::Skipped => {
+                    skipped += 1;
+                    self.results.with_label_values(&["skipped"]).inc();
+                    continue;
+                }
+                Outcome::Corrupt(problem) => problem,
+            };
+            checked += 1;
+            let repaired = match self.repair(vault, &meta).await {
+                Ok(repaired) => repaired,
+                Err(e) => {
+                    warn!(snapshot = %meta.id, "failed to repair blob from the replica: {:#}", e);
+                    false
+                }
+            };
+            let result = if repaired { "repaired" } else { "corrupt" };
+            self.results.with_label_values(&[result]).inc();
+            error!(snapshot = %meta.id, ?problem, repaired, "corrupt snapshot blob");
+            let finding = ScrubFinding {
+                snapshot_id: meta.id,
+                tenant: meta.tenant.clone(),
+                sandbox_id: meta.sandbox_id.clone(),
+                problem,
+                repaired,
+            };
+            self.raise(&finding);
+            findings.push(finding);
+        }
+
+        let report = ScrubReport {
+            id: Uuid::new_v4(),
+            started_at,
+            finished_at: Utc::now(),
+            checked,
+            skipped,
+            corrupt: findings.len(),
+            repaired: findings.iter().filter(|finding| finding.repaired).count(),
+            findings,
+        };
+        info!(
+            checked = report.checked,
+            skipped = report.skipped,
+            corrupt = report.corrupt,
+            repaired = report.repaired,
+            "scrubbed snapshot blobs"
+        );
+        if report.corrupt > 0 {
+            self.notify(&report).await;
+        }
+        let mut reports = self.reports.write().await;
+        if reports.len() == KEPT_REPORTS {
+            reports.pop_front();
+        }
+        reports.push_back(report.clone());
+        report
+    }
+
+    /// Put the replica's copy of a blob in place of the local one, if the
+    /// copy is intact
+    async fn repair(&self, vault: &SnapshotVault, meta: &SnapshotMetadata) -> Result<bool> {
+        let Some(replica) = &self.replica else {
+            return Ok(false);
+        };
+        let Some(copy) = replica.get(&tiering::cold_key(meta.id)).await? else {
+            return Ok(false);
+        };
+        let Some(manifest) = manifest(vault, meta.id).await? else {
+            return Ok(false);
+        };
+        if !matches!(verify(vault, meta, &manifest, &copy), Outcome::Intact) {
+            return Ok(false);
+        }
+        // Write aside and rename, so readers never see a partial blob
+        let partial = vault.blob_path(meta.id).with_extension("partial");
+        fs::write(&partial, copy).await?;
+        fs::rename(&partial, vault.blob_path(meta.id)).await?;
+        Ok(true)
+    }
+
+    /// Raise a corrupt blob with the security monitor, in the background
+    fn raise(&self, finding: &ScrubFinding) {
+        let Some(monitor_url) = self.monitor_url.clone() else {
+            return;
+        };
+        let (severity, state) = if finding.repaired {
+            (Severity::Medium, "repaired from the replica")
+        } else {
+            (Severity::High, "not repaired")
+        };
+        let event = SecurityEvent {
+            id: Uuid::new_v4().to_string(),
+            event_type: EventType::SnapshotCorruption,
+            severity,
+            timestamp: Utc::now(),
+            sandbox_id: finding.sandbox_id.clone(),
+            provider: "snapshot-vault".to_string(),
+            message: format!("Snapshot {} blob is corrupt, {}", finding.snapshot_id, state),
+            details: serde_json::to_value(finding).unwrap_or_default(),
+            metadata: None,
+            falco_rule: None,
+            ebpf_trace: None,
+            run_id: None,
+            occurrences: 1,
+            last_seen: None,
+        };
+        let http = self.http.clone();
+        tokio::spawn(async move {
+            let result = http
+                .post(format!("{}/api/events", monitor_url))
+                .json(&event)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                warn!("failed to report snapshot corruption event {}: {}", event.id, e);
+            }
+        });
+    }
+
+    /// Post a report that found corruption to the webhook
+    async fn notify(&self, report: &ScrubReport) {
+        let Some(url) = &self.webhook_url else {
+            return;
+        };
+        let result = self
+            .http
+            .post(url)
+            .json(report)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            warn!("failed to post scrub report {}: {}", report.id, e);
+        }
+    }
+
+    pub async fn reports(&self) -> Vec<ScrubReport> {
+        self.reports.read().await.iter().cloned().collect()
+    }
+}
+
+/// Read a hot blob and compare it with its manifest
+async fn check(vault: &SnapshotVault, meta: &SnapshotMetadata) -> Result<Outcome> {
+    let Some(manifest) = manifest(vault, meta.id).await? else {
+        return Ok(Outcome::Skipped);
+    };
+    match fs::read(vault.blob_path(meta.id)).await {
+        Ok(blob) => Ok(verify(vault, meta, &manifest, &blob)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            // Moved to cold storage or deleted since the pass began
+            let current = vault.index.read().await.get(&meta.id).map(|meta| meta.tier);
+            match current {
+                Some(StorageTier::Hot) => Ok(Outcome::Corrupt(Problem::Missing)),
+                _ => Ok(Outcome::Skipped),
+            }
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A blob's manifest; `None` for blobs stored before manifests existed
+async fn manifest(vault: &SnapshotVault, id: Uuid) -> Result<Option<SnapshotManifest>> {
+    match fs::read(vault.manifest_path(id)).await {
+        Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Compare a blob as stored (sealed, if encrypted) with its manifest
+fn verify(vault: &SnapshotVault, meta: &SnapshotMetadata, manifest: &SnapshotManifest, stored: &[u8]) -> Outcome {
+    let plain = match (&meta.encryption, &vault.keyring) {
+        (None, _) => stored.to_vec(),
+        (Some(encryption), Some(keyring)) => match keyring.open(&meta.tenant, meta.id, encryption, stored) {
+            Ok(plain) => plain,
+            Err(_) => return Outcome::Corrupt(Problem::Undecryptable),
+        },
+        (Some(_), None) => return Outcome::Skipped,
+    };
+    let actual = chunks::manifest_of(meta.id, &plain);
+    let longest = actual.chunks.len().max(manifest.chunks.len());
+    let mismatched: Vec<u64> = (0..longest)
+        .filter(|&index| {
+            let hash = |chunks: &[ChunkInfo]| {
+                chunks.get(index).map(|chunk| chunk.sha256.clone())
+            };
+            hash(&actual.chunks) != hash(&manifest.chunks)
+        })
+        .map(|index| index as u64)
+        .collect();
+    if mismatched.is_empty() {
+        Outcome::Intact
+    } else {
+        Outcome::Corrupt(Problem::Mismatch { chunks: mismatched })
+    }
+}
+
+/// Scrub every `SNAPSHOT_VAULT_SCRUB_INTERVAL_SECS`; no periodic passes when
+/// it's unset
+pub fn spawn(vault: Arc<SnapshotVault>, scrubber: Arc<Scrubber>) -> Result<()> {
+    let Ok(value) = std::env::var("SNAPSHOT_VAULT_SCRUB_INTERVAL_SECS") else {
+        return Ok(());
+    };
+    let interval: u64 = value
+        .parse()
+        .ok()
+        .filter(|secs| *secs > 0)
+        .context("SNAPSHOT_VAULT_SCRUB_INTERVAL_SECS must be a positive number of seconds")?;
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        // The first tick is immediate; the first pass waits a whole interval
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            scrubber.scrub(&vault).await;
+        }
+    });
+    info!(interval_secs = interval, "snapshot scrubbing enabled");
+    Ok(())
+}
+
+/// Run a pass now and answer with its report. Requires the admin token.
+pub async fn run_scrub(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<ScrubReport>, VaultError> {
+    authorize(&state, &headers)?;
+    Ok(Json(state.scrubber.scrub(&state.vault).await))
+}
+
+/// Reports of the latest passes, oldest first. Requires the admin token.
+pub async fn list_reports(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<ScrubReport>>, VaultError> {
+    authorize(&state, &headers)?;
+    Ok(Json(state.scrubber.reports().await))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateSnapshotRequest;
+    use base64::Engine;
+
+    fn request(data: &[u8]) -> CreateSnapshotRequest {
+        CreateSnapshotRequest {
+            sandbox_id: "sandbox".to_string(),
+            provider: "kata".to_string(),
+            filesystem_hash: "hash".to_string(),
+            memory_hash: None,
+            size_bytes: None,
+            metadata: None,
+            data: Some(base64::engine::general_purpose::STANDARD.encode(data)),
+            format: None,
+            run_id: None,
+            pinned: false,
+            base_layer: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn finds_corrupt_blobs_and_repairs_them_from_the_replica() {
+        let dir = std::env::temp_dir().join(format!("vault-scrub-{}", Uuid::new_v4()));
+        let replica = ObjectStore::from_url(&format!("file://{}", dir.join("replica").display()), None).unwrap();
+        let vault = SnapshotVault::new(dir.join("hot"), None, None, Default::default(), None).await.unwrap();
+        let results = CounterVec::new(prometheus::Opts::new("scrubbed", "scrubbed"), &["result"]).unwrap();
+
+        let intact = vault.store(request(b"intact"), "default".into()).await.unwrap();
+        let replicated = vault.store(request(b"replicated"), "default".into()).await.unwrap();
+        let lost = vault.store(request(b"lost"), "default".into()).await.unwrap();
+        replica.put(&tiering::cold_key(replicated.id), b"replicated".to_vec()).await.unwrap();
+        std::fs::write(vault.blob_path(replicated.id), b"bit-rotted").unwrap();
+        std::fs::remove_file(vault.blob_path(lost.id)).unwrap();
+
+        let scrubber = Scrubber::new(Some(replica), None, None, results.clone());
+        let report = scrubber.scrub(&vault).await;
+        assert_eq!((report.checked, report.corrupt, report.repaired), (3, 2, 1));
+        let finding = |id| report.findings.iter().find(|finding| finding.snapshot_id == id).unwrap();
+        assert_eq!(finding(replicated.id).problem, Problem::Mismatch { chunks: vec![0] });
+        assert!(finding(replicated.id).repaired);
+        assert_eq!(finding(lost.id).problem, Problem::Missing);
+        assert!(!finding(lost.id).repaired);
+        assert!(report.findings.iter().all(|finding| finding.snapshot_id != intact.id));
+
+        // The repaired blob reads back as written, and checks out next time
+        assert_eq!(vault.get_blob(replicated.id, "default").await.unwrap(), b"replicated");
+        let report = scrubber.scrub(&vault).await;
+        assert_eq!((report.checked, report.corrupt, report.repaired), (3, 1, 0));
+        assert_eq!(scrubber.reports().await.len(), 2);
+        assert_eq!(results.with_label_values(&["repaired"]).get(), 1.0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
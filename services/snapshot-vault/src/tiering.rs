@@ -0,0 +1,242 @@
+//! Moves snapshot blobs between the vault's local disk (the hot tier) and an
+//! object store (the cold tier). Blobs nobody has read for a while go cold;
+//! reading a cold blob brings it back to local disk first. Calls to the
+//! object store go through a circuit breaker: while it's open, reads of cold
+//! blobs are refused with 503 and migration passes stop early.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use prometheus::CounterVec;
+use sandstorm_backup::ObjectStore;
+use sandstorm_http::{BreakerSettings, CircuitBreaker, Unavailable};
+use sandstorm_types::snapshot::StorageTier;
+use std::{sync::Arc, time::Duration};
+use tokio::fs;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{SnapshotVault, VaultMetrics};
+
+/// When blobs move to cold storage, and where to
+pub struct Tiering {
+    cold: ObjectStore,
+    /// Blobs unread for this long move to the cold tier
+    hot_for: chrono::Duration,
+    /// Blobs moved, by destination tier
+    moves: CounterVec,
+    breaker: CircuitBreaker,
+}
+
+impl Tiering {
+    /// Tiering to the store at `SNAPSHOT_VAULT_COLD_URL` (`file://` or
+    /// `http(s)://`, with `SNAPSHOT_VAULT_COLD_TOKEN` as bearer token) after
+    /// `SNAPSHOT_VAULT_HOT_DAYS` (default 7); `None` when no URL is set. The
+    /// breaker reads `SNAPSHOT_VAULT_COLD_BREAKER_FAILURES` and
+    /// `SNAPSHOT_VAULT_COLD_BREAKER_OPEN_SECS`.
+    pub fn from_env(moves: CounterVec) -> Result<Option<Self>> {
+        let Ok(url) = std::env::var("SNAPSHOT_VAULT_COLD_URL") else {
+            return Ok(None);
+        };
+        let cold = ObjectStore::from_url(&url, std::env::var("SNAPSHOT_VAULT_COLD_TOKEN").ok())
+            .context("invalid SNAPSHOT_VAULT_COLD_URL")?;
+        let hot_days = match std::env::var("SNAPSHOT_VAULT_HOT_DAYS") {
+            Ok(value) => value.parse().context("invalid SNAPSHOT_VAULT_HOT_DAYS")?,
+            Err(_) => 7,
+        };
+        let breaker = CircuitBreaker::new("cold-storage", BreakerSettings::from_env("SNAPSHOT_VAULT_COLD")?);
+        Ok(Some(Self {
+            breaker,
+            ..Self::new(cold, chrono::Duration::days(hot_days), moves)
+        }))
+    }
+
+    pub fn new(cold: ObjectStore, hot_for: chrono::Duration, moves: CounterVec) -> Self {
+        Self {
+            cold,
+            hot_for,
+            moves,
+            breaker: CircuitBreaker::new("cold-storage", BreakerSettings::default()),
+        }
+    }
+
+    pub fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    /// Move every hot blob that is unpinned and hasn't been read within the
+    /// policy's window to the cold tier. Returns the snapshots moved.
+    pub async fn migrate(&self, vault: &SnapshotVault) -> Vec<Uuid> {
+        let cutoff = Utc::now() - self.hot_for;
+        let candidates: Vec<_> = vault
+            .index
+            .read()
+            .await
+            .values()
+            .filter(|meta| meta.has_blob && meta.tier == StorageTier::Hot && !meta.pinned)
+            .map(|meta| (meta.id, last_used(meta.accessed_at, meta.created_at)))
+            .filter(|(_, used)| *used < cutoff)
+            .collect();
+
+        let mut moved = Vec::new();
+        for (id, used) in candidates {
+            match self.move_to_cold(vault, id, used).await {
+                Ok(true) => moved.push(id),
+                Ok(false) => {}
+                Err(e) if Unavailable::find(&e).is_some() => {
+                    warn!("stopping migration to cold storage: {:#}", e);
+                    break;
+                }
+                Err(e) => warn!(snapshot = %id, "failed to move blob to cold storage: {:#}", e),
+            }
+        }
+        moved
+    }
+
+    /// Upload a blob, then switch its metadata to the cold tier and remove
+    /// the local copy. Returns false if the snapshot was read, pinned or
+    /// deleted during the upload, in which case it stays hot.
+    async fn move_to_cold(&self, vault: &SnapshotVault, id: Uuid, used: DateTime<Utc>) -> Result<bool> {
+        let data = fs::read(vault.blob_path(id)).await?;
+        self.breaker.call(self.cold.put(&cold_key(id), data), |_| true).await?;
+
+        let mut index = vault.index.write().await;
+        let unchanged = index.get(&id).is_some_and(|meta| {
+            meta.tier == StorageTier::Hot
+                && !meta.pinned
+                && last_used(meta.accessed_at, meta.created_at) == used
+        });
+        if !unchanged {
+            drop(index);
+            self.cold.delete(&cold_key(id)).await?;
+            return Ok(false);
+        }
+        let metadata = index.get_mut(&id).expect("checked above");
+        metadata.tier = StorageTier::Cold;
+        vault.write_metadata(metadata).await?;
+        drop(index);
+
+        // Readers that saw the blob as hot restore it from the cold copy
+        fs::remove_file(vault.blob_path(id)).await?;
+        self.moves.with_label_values(&[StorageTier::Cold.as_str()]).inc();
+        Ok(true)
+    }
+
+    /// Bring a cold blob back to local disk
+    pub async fn restore(&self, vault: &SnapshotVault, id: Uuid) -> Result<()> {
+        let data = self
+            .breaker
+            .call(self.cold.get(&cold_key(id)), |_| true)
+            .await?
+            .with_context(|| format!("cold storage has no blob for snapshot {}", id))?;
+        // Write aside and rename, so readers never see a partial blob
+        let partial = vault.blob_path(id).with_extension("partial");
+        fs::write(&partial, data).await?;
+        fs::rename(&partial, vault.blob_path(id)).await?;
+
+        let mut index = vault.index.write().await;
+        let Some(metadata) = index.get_mut(&id) else {
+            return Ok(());
+        };
+        if metadata.tier == StorageTier::Hot {
+            // Restored concurrently by another reader
+            return Ok(());
+        }
+        metadata.tier = StorageTier::Hot;
+        vault.write_metadata(metadata).await?;
+        drop(index);
+
+        if let Err(e) = self.cold.delete(&cold_key(id)).await {
+            warn!(snapshot = %id, "failed to delete restored blob from cold storage: {:#}", e);
+        }
+        self.moves.with_label_values(&[StorageTier::Hot.as_str()]).inc();
+        Ok(())
+    }
+
+    /// Delete a snapshot's cold blob, if it has one
+    pub async fn delete(&self, id: Uuid) -> Result<()> {
+        self.cold.delete(&cold_key(id)).await
+    }
+}
+
+/// Run a migration pass every `SNAPSHOT_VAULT_TIERING_INTERVAL_SECS`
+/// (default hourly)
+pub fn spawn_migrations(vault: Arc<SnapshotVault>, metrics: VaultMetrics) {
+    let interval = std::env::var("SNAPSHOT_VAULT_TIERING_INTERVAL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(3600);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            let Some(tiering) = &vault.tiering else {
+                return;
+            };
+            let moved = tiering.migrate(&vault).await;
+            if !moved.is_empty() {
+                info!(count = moved.len(), "moved snapshot blobs to cold storage");
+            }
+            metrics.observe_tiers(&vault).await;
+        }
+    });
+}
+
+fn last_used(accessed_at: Option<DateTime<Utc>>, created_at: DateTime<Utc>) -> DateTime<Utc> {
+    accessed_at.unwrap_or(created_at)
+}
+
+/// Where a blob is kept in the cold tier, and in the replica store
+pub fn cold_key(id: Uuid) -> String {
+    format!("snapshots/{}.blob", id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateSnapshotRequest;
+    use base64::Engine;
+
+    fn request(data: &[u8], pinned: bool) -> CreateSnapshotRequest {
+        CreateSnapshotRequest {
+            sandbox_id: "sandbox".to_string(),
+            provider: "kata".to_string(),
+            filesystem_hash: "hash".to_string(),
+            memory_hash: None,
+            size_bytes: None,
+            metadata: None,
+            data: Some(base64::engine::general_purpose::STANDARD.encode(data)),
+            format: None,
+            run_id: None,
+            pinned,
+            base_layer: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn idle_blobs_go_cold_and_come_back_when_read() {
+        let dir = std::env::temp_dir().join(format!("vault-tiering-{}", Uuid::new_v4()));
+        let cold = ObjectStore::from_url(&format!("file://{}", dir.join("cold").display()), None).unwrap();
+        let moves = CounterVec::new(prometheus::Opts::new("moves", "moves"), &["tier"]).unwrap();
+        let tiering = Tiering::new(cold, chrono::Duration::zero(), moves.clone());
+        let vault = SnapshotVault::new(dir.join("hot"), None, Some(tiering), Default::default(), None).await.unwrap();
+
+        let idle = vault.store(request(b"idle", false), "default".into()).await.unwrap();
+        let pinned = vault.store(request(b"pinned", true), "default".into()).await.unwrap();
+
+        let tiering = vault.tiering.as_ref().unwrap();
+        assert_eq!(tiering.migrate(&vault).await, vec![idle.id]);
+        assert!(!vault.blob_path(idle.id).exists());
+        assert!(vault.blob_path(pinned.id).exists());
+        assert_eq!(vault.get(idle.id, "default").await.unwrap().tier, StorageTier::Cold);
+        assert_eq!(moves.with_label_values(&["cold"]).get(), 1.0);
+
+        assert_eq!(vault.get_blob(idle.id, "default").await.unwrap(), b"idle");
+        let restored = vault.get(idle.id, "default").await.unwrap();
+        assert_eq!(restored.tier, StorageTier::Hot);
+        assert!(restored.accessed_at.is_some());
+        assert_eq!(moves.with_label_values(&["hot"]).get(), 1.0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
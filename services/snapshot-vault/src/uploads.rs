@@ -0,0 +1,325 @@
+//! Chunked snapshot uploads. A client starts an upload with the blob's size
+//! and hash, sends its chunks, each checked against its own hash, and
+//! completes the upload with the snapshot's fields. Received chunks are
+//! kept on disk with the upload, so a client that lost its connection, or
+//! restarted, asks which chunks arrived and sends only the rest.
+//!
+//! Uploads nobody completes are dropped by garbage collection after a day.
+
+use anyhow::Context;
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use chrono::Utc;
+use sandstorm_types::{
+    snapshot::{
+        SnapshotMetadata, SnapshotUpload, UploadRequest, UploadSession, CHUNK_SHA256_HEADER,
+    },
+    Versioned,
+};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, path::PathBuf};
+use tokio::{fs, sync::Mutex};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{chunks::CHUNK_SIZE, store_snapshot, tenant, AppState, CreateSnapshotRequest, VaultError};
+
+/// How long an upload may stay incomplete before it is dropped
+const UPLOAD_TTL_HOURS: i64 = 24;
+
+/// Uploads in progress, each in its own directory under `uploads/` in the
+/// vault path
+pub struct Uploads {
+    root: PathBuf,
+    /// Held while a chunk is recorded, so concurrent chunks don't lose each
+    /// other's updates
+    sessions: Mutex<HashMap<Uuid, UploadSession>>,
+}
+
+impl Uploads {
+    pub async fn new(root: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&root).await?;
+        let mut sessions = HashMap::new();
+        let mut entries = fs::read_dir(&root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path().join("session.json");
+            let contents = match fs::read(&path).await {
+                Ok(contents) => contents,
+                // Removed halfway; expiry finishes the job
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let session: UploadSession = serde_json::from_slice::<Versioned<UploadSession>>(&contents)
+                .with_context(|| format!("failed to load {}", path.display()))?
+                .into_inner()?;
+            sessions.insert(session.id, session);
+        }
+        Ok(Self {
+            root,
+            sessions: Mutex::new(sessions),
+        })
+    }
+
+    fn dir(&self, id: Uuid) -> PathBuf {
+        self.root.join(id.to_string())
+    }
+
+    fn chunk_path(&self, id: Uuid, index: u64) -> PathBuf {
+        self.dir(id).join(format!("{}.chunk", index))
+    }
+
+    async fn save(&self, session: &UploadSession) -> anyhow::Result<()> {
+        let path = self.dir(session.id).join("session.json");
+        // Write aside and rename, so a crash never leaves a partial file
+        let partial = path.with_extension("partial");
+        fs::write(&partial, serde_json::to_vec(&Versioned::new(session.clone()))?).await?;
+        fs::rename(&partial, &path).await?;
+        Ok(())
+    }
+
+    pub async fn start(&self, tenant: String, request: UploadRequest) -> Result<UploadSession, VaultError> {
+        if request.sha256.len() != 64 || !request.sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(VaultError::Invalid("sha256 must be a hex SHA-256".into()));
+        }
+        let session = UploadSession {
+            id: Uuid::new_v4(),
+            tenant,
+            size_bytes: request.size_bytes,
+            sha256: request.sha256.to_lowercase(),
+            chunk_size: CHUNK_SIZE,
+            received: Vec::new(),
+            created_at: Utc::now(),
+        };
+        fs::create_dir_all(self.dir(session.id)).await?;
+        self.save(&session).await?;
+        self.sessions.lock().await.insert(session.id, session.clone());
+        Ok(session)
+    }
+
+    pub async fn get(&self, id: Uuid, tenant: &str) -> Result<UploadSession, VaultError> {
+        self.sessions
+            .lock()
+            .await
+            .get(&id)
+            .filter(|session| session.tenant == tenant)
+            .cloned()
+            .ok_or(VaultError::NotFound)
+    }
+
+    /// Keep chunk `index`, if it is the size the upload expects and matches
+    /// `sha256`
+    pub async fn put_chunk(
+        &self,
+        id: Uuid,
+        tenant: &str,
+        index: u64,
+        sha256: &str,
+        data: &[u8],
+    ) -> Result<UploadSession, VaultError> {
+        let session = self.get(id, tenant).await?;
+        if index >= session.chunk_count() {
+            return Err(VaultError::Invalid(format!(
+                "upload {} has {} chunks",
+                id,
+                session.chunk_count()
+            )));
+        }
+        if data.len() as u64 != session.chunk_len(index) {
+            return Err(VaultError::Invalid(format!(
+                "chunk {} must be {} bytes, not {}",
+                index,
+                session.chunk_len(index),
+                data.len()
+            )));
+        }
+        if !format!("{:x}", Sha256::digest(data)).eq_ignore_ascii_case(sha256) {
+            return Err(VaultError::Invalid(format!("chunk {} does not match its hash", index)));
+        }
+
+        let path = self.chunk_path(id, index);
+        let partial = path.with_extension("partial");
+        fs::write(&partial, data).await?;
+        fs::rename(&partial, &path).await?;
+
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions.get_mut(&id).ok_or(VaultError::NotFound)?;
+        if let Err(position) = session.received.binary_search(&index) {
+            session.received.insert(position, index);
+            self.save(session).await?;
+        }
+        Ok(session.clone())
+    }
+
+    /// The uploaded blob, once every chunk has arrived and the whole matches
+    /// the upload's hash
+    pub async fn assemble(&self, id: Uuid, tenant: &str) -> Result<Vec<u8>, VaultError> {
+        let session = self.get(id, tenant).await?;
+        let missing = session.missing();
+        if !missing.is_empty() {
+            return Err(VaultError::Invalid(format!(
+                "upload {} is missing {} chunk(s)",
+                id,
+                missing.len()
+            )));
+        }
+        let mut blob = Vec::with_capacity(session.size_bytes as usize);
+        for index in 0..session.chunk_count() {
+            blob.extend(fs::read(self.chunk_path(id, index)).await?);
+        }
+        if format!("{:x}", Sha256::digest(&blob)) != session.sha256 {
+            return Err(VaultError::Invalid(format!("upload {} does not match its hash", id)));
+        }
+        Ok(blob)
+    }
+
+    pub async fn remove(&self, id: Uuid) -> anyhow::Result<()> {
+        self.sessions.lock().await.remove(&id);
+        match fs::remove_dir_all(self.dir(id)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Drop uploads started more than a day ago. Returns the uploads
+    /// dropped.
+    pub async fn expire(&self) -> anyhow::Result<Vec<Uuid>> {
+        let cutoff = Utc::now() - chrono::Duration::hours(UPLOAD_TTL_HOURS);
+        let stale: Vec<Uuid> = self
+            .sessions
+            .lock()
+            .await
+            .values()
+            .filter(|session| session.created_at < cutoff)
+            .map(|session| session.id)
+            .collect();
+        for id in &stale {
+            self.remove(*id).await?;
+        }
+        Ok(stale)
+    }
+}
+
+pub async fn start_upload(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<UploadRequest>,
+) -> Result<(StatusCode, Json<UploadSession>), VaultError> {
+    let tenant = tenant(&headers)?;
+    let session = state.vault.uploads.start(tenant, request).await?;
+    Ok((StatusCode::CREATED, Json(session)))
+}
+
+pub async fn get_upload(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<UploadSession>, VaultError> {
+    let tenant = tenant(&headers)?;
+    Ok(Json(state.vault.uploads.get(id, &tenant).await?))
+}
+
+pub async fn put_chunk(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((id, index)): Path<(Uuid, u64)>,
+    data: Bytes,
+) -> Result<Json<UploadSession>, VaultError> {
+    let tenant = tenant(&headers)?;
+    let sha256 = headers
+        .get(CHUNK_SHA256_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| VaultError::Invalid(format!("{} is required", CHUNK_SHA256_HEADER)))?;
+    let session = state.vault.uploads.put_chunk(id, &tenant, index, sha256, &data).await?;
+    Ok(Json(session))
+}
+
+/// Store the uploaded blob as a snapshot and drop the upload
+pub async fn complete_upload(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    Json(upload): Json<SnapshotUpload>,
+) -> Result<Json<SnapshotMetadata>, VaultError> {
+    let tenant = tenant(&headers)?;
+    let blob = state.vault.uploads.assemble(id, &tenant).await?;
+    let request = snapshot_request(upload, blob.len() as u64);
+    let metadata = store_snapshot(&state, &headers, request, Some(blob)).await?;
+    state.vault.uploads.remove(id).await?;
+    info!(upload = %id, snapshot = %metadata.id, "chunked upload completed");
+    Ok(Json(metadata))
+}
+
+/// Request to store an uploaded blob of `size_bytes` with the upload's
+/// snapshot fields
+pub fn snapshot_request(upload: SnapshotUpload, size_bytes: u64) -> CreateSnapshotRequest {
+    CreateSnapshotRequest {
+        sandbox_id: upload.sandbox_id,
+        provider: upload.provider,
+        filesystem_hash: upload.filesystem_hash,
+        memory_hash: upload.memory_hash,
+        size_bytes: Some(size_bytes),
+        metadata: upload.metadata,
+        data: None,
+        format: upload.format,
+        run_id: upload.run_id,
+        pinned: upload.pinned,
+        base_layer: upload.base_layer,
+    }
+}
+
+pub async fn abort_upload(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, VaultError> {
+    let tenant = tenant(&headers)?;
+    state.vault.uploads.get(id, &tenant).await?;
+    state.vault.uploads.remove(id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resumes_from_the_chunks_received() {
+        let dir = std::env::temp_dir().join(format!("vault-uploads-{}", Uuid::new_v4()));
+        let uploads = Uploads::new(dir.clone()).await.unwrap();
+
+        let blob: Vec<u8> = (0..CHUNK_SIZE * 3 / 2).map(|i| (i % 251) as u8).collect();
+        let session = uploads
+            .start(
+                "acme".into(),
+                UploadRequest {
+                    size_bytes: blob.len() as u64,
+                    sha256: format!("{:x}", Sha256::digest(&blob)),
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(session.missing(), vec![0, 1]);
+
+        let chunk = |index: u64| &blob[(index * CHUNK_SIZE) as usize..blob.len().min(((index + 1) * CHUNK_SIZE) as usize)];
+        let hash = |data: &[u8]| format!("{:x}", Sha256::digest(data));
+        let tail = chunk(1);
+        uploads.put_chunk(session.id, "acme", 1, &hash(tail), tail).await.unwrap();
+        assert!(uploads.put_chunk(session.id, "acme", 0, &hash(tail), chunk(0)).await.is_err());
+        assert!(uploads.put_chunk(session.id, "globex", 0, &hash(chunk(0)), chunk(0)).await.is_err());
+        assert!(uploads.assemble(session.id, "acme").await.is_err());
+
+        // A restarted vault still knows what it received
+        let uploads = Uploads::new(dir.clone()).await.unwrap();
+        assert_eq!(uploads.get(session.id, "acme").await.unwrap().missing(), vec![0]);
+        uploads.put_chunk(session.id, "acme", 0, &hash(chunk(0)), chunk(0)).await.unwrap();
+        assert_eq!(uploads.assemble(session.id, "acme").await.unwrap(), blob);
+
+        uploads.remove(session.id).await.unwrap();
+        assert!(uploads.get(session.id, "acme").await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
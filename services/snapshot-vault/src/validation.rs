@@ -0,0 +1,312 @@
+//! Admission checks for snapshot blobs. A blob can declare its format and
+//! size when it is stored; the vault verifies both before writing anything
+//! and rejects blobs that don't match.
+
+use anyhow::{bail, Context};
+use chrono::Utc;
+use sandstorm_types::snapshot::{BlobFormat, BlobValidation, SnapshotManifest};
+use serde::Serialize;
+
+/// ext4 superblock, which starts 1024 bytes into the image
+const EXT4_SUPERBLOCK: usize = 1024;
+const EXT4_MAGIC: u16 = 0xef53;
+/// `INCOMPAT_64BIT`: block counts have a high word
+const EXT4_FEATURE_64BIT: u32 = 0x80;
+
+/// Header every CRIU image starts with: the common magic, then the
+/// inventory's own
+const CRIU_IMG_COMMON_MAGIC: u32 = 0x5456_4319;
+const CRIU_INVENTORY_MAGIC: u32 = 0x5831_3116;
+
+/// Which blobs the vault validates, from `SNAPSHOT_VAULT_VALIDATION`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValidationPolicy {
+    /// Nothing is checked (`off`)
+    Off,
+    /// Declared formats and sizes are checked (`declared`)
+    #[default]
+    Declared,
+    /// Like `declared`, and blobs must declare a format (`strict`)
+    Strict,
+}
+
+impl ValidationPolicy {
+    pub fn from_env() -> anyhow::Result<Self> {
+        match std::env::var("SNAPSHOT_VAULT_VALIDATION").as_deref() {
+            Err(_) | Ok("declared") => Ok(Self::Declared),
+            Ok("off") => Ok(Self::Off),
+            Ok("strict") => Ok(Self::Strict),
+            Ok(other) => bail!(
+                "invalid SNAPSHOT_VAULT_VALIDATION {:?}; expected off, declared or strict",
+                other
+            ),
+        }
+    }
+}
+
+/// Why a blob was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    /// The policy is strict and the blob declared no format
+    FormatRequired,
+    /// The blob isn't the size it declared
+    SizeMismatch,
+    /// The blob isn't in the format it declared
+    FormatMismatch,
+    /// The snapshot's metadata doesn't match its tenant's schema
+    MetadataInvalid,
+}
+
+impl RejectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::FormatRequired => "format_required",
+            RejectReason::SizeMismatch => "size_mismatch",
+            RejectReason::FormatMismatch => "format_mismatch",
+            RejectReason::MetadataInvalid => "metadata_invalid",
+        }
+    }
+}
+
+/// A snapshot that failed validation, returned to the client as `422`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rejection {
+    pub reason: RejectReason,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<BlobFormat>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.reason.as_str(), self.message)
+    }
+}
+
+/// Check a blob against its declared format and size. Returns what was
+/// verified, or `None` when nothing was checked.
+pub fn validate(
+    policy: ValidationPolicy,
+    format: Option<BlobFormat>,
+    declared_size: Option<u64>,
+    blob: &[u8],
+) -> Result<Option<BlobValidation>, Rejection> {
+    if policy == ValidationPolicy::Off {
+        return Ok(None);
+    }
+    if policy == ValidationPolicy::Strict && format.is_none() {
+        return Err(Rejection {
+            reason: RejectReason::FormatRequired,
+            format: None,
+            message: "blobs must declare a format".to_string(),
+        });
+    }
+    if format.is_none() && declared_size.is_none() {
+        return Ok(None);
+    }
+    if let Some(declared) = declared_size {
+        if declared != blob.len() as u64 {
+            return Err(Rejection {
+                reason: RejectReason::SizeMismatch,
+                format,
+                message: format!("declared {} bytes but got {}", declared, blob.len()),
+            });
+        }
+    }
+    if let Some(format) = format {
+        let checked = match format {
+            BlobFormat::Ext4 => check_ext4(blob),
+            BlobFormat::CriuImages => check_criu_images(blob),
+            BlobFormat::VaultManifest => check_manifest(blob),
+        };
+        checked.map_err(|e| Rejection {
+            reason: RejectReason::FormatMismatch,
+            format: Some(format),
+            message: format!("not a valid {} blob: {:#}", format.as_str(), e),
+        })?;
+    }
+
+    Ok(Some(BlobValidation {
+        format,
+        size_verified: declared_size.is_some(),
+        validated_at: Utc::now(),
+    }))
+}
+
+/// The superblock's magic, and an image at least as large as the
+/// filesystem it describes
+fn check_ext4(blob: &[u8]) -> anyhow::Result<()> {
+    let superblock = blob
+        .get(EXT4_SUPERBLOCK..EXT4_SUPERBLOCK + 1024)
+        .context("too short for a superblock")?;
+    let u32_at = |offset: usize| u32::from_le_bytes(superblock[offset..offset + 4].try_into().unwrap());
+
+    let magic = u16::from_le_bytes([superblock[0x38], superblock[0x39]]);
+    if magic != EXT4_MAGIC {
+        bail!("bad superblock magic {:#06x}", magic);
+    }
+    let log_block_size = u32_at(0x18);
+    if log_block_size > 6 {
+        bail!("block size 2^{} is out of range", 10 + log_block_size);
+    }
+    let mut blocks = u32_at(0x04) as u64;
+    if u32_at(0x60) & EXT4_FEATURE_64BIT != 0 {
+        blocks |= (u32_at(0x150) as u64) << 32;
+    }
+    let needed = blocks.saturating_mul(1024 << log_block_size);
+    if needed > blob.len() as u64 {
+        bail!("filesystem needs {} bytes but the image has {}", needed, blob.len());
+    }
+    Ok(())
+}
+
+/// A tar archive containing a CRIU `inventory.img`
+fn check_criu_images(blob: &[u8]) -> anyhow::Result<()> {
+    for entry in tar_entries(blob) {
+        let (name, data) = entry?;
+        if name.rsplit('/').next() == Some("inventory.img") {
+            if data.len() < 8
+                || u32::from_le_bytes(data[..4].try_into().unwrap()) != CRIU_IMG_COMMON_MAGIC
+                || u32::from_le_bytes(data[4..8].try_into().unwrap()) != CRIU_INVENTORY_MAGIC
+            {
+                bail!("{} has no CRIU inventory header", name);
+            }
+            return Ok(());
+        }
+    }
+    bail!("no inventory.img in the archive")
+}
+
+/// Names and contents of a ustar archive's members, up to its end marker
+pub fn tar_entries(blob: &[u8]) -> impl Iterator<Item = anyhow::Result<(&str, &[u8])>> {
+    let mut offset = 0;
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let header = blob.get(offset..offset + 512)?;
+        if header.iter().all(|&byte| byte == 0) {
+            return None;
+        }
+        let entry = (|| {
+            if &header[257..262] != b"ustar" {
+                bail!("not a tar archive");
+            }
+            let name = std::str::from_utf8(&header[..100])?.trim_end_matches('\0');
+            let size = std::str::from_utf8(&header[124..136])?
+                .trim_matches(|c: char| c == '\0' || c == ' ');
+            let size = usize::from_str_radix(size, 8).with_context(|| format!("bad size for {}", name))?;
+            let data = blob
+                .get(offset + 512..offset + 512 + size)
+                .with_context(|| format!("{} is truncated", name))?;
+            offset += 512 + size.div_ceil(512) * 512;
+            Ok((name, data))
+        })();
+        done = entry.is_err();
+        Some(entry)
+    })
+}
+
+/// Manifest JSON whose chunks cover its blob exactly, in order
+fn check_manifest(blob: &[u8]) -> anyhow::Result<()> {
+    let manifest: SnapshotManifest = serde_json::from_slice(blob)?;
+    let mut offset = 0;
+    for (index, chunk) in manifest.chunks.iter().enumerate() {
+        if chunk.index != index as u64 || chunk.offset != offset {
+            bail!("chunk {} is out of place", index);
+        }
+        if chunk.size == 0 || chunk.size > manifest.chunk_size {
+            bail!("chunk {} has size {}", index, chunk.size);
+        }
+        if chunk.sha256.len() != 64 || !chunk.sha256.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            bail!("chunk {} has a malformed hash", index);
+        }
+        offset += chunk.size;
+    }
+    if offset != manifest.size_bytes {
+        bail!("chunks cover {} bytes of {}", offset, manifest.size_bytes);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ext4_image(blocks: u32) -> Vec<u8> {
+        let mut image = vec![0; 4096];
+        let superblock = &mut image[EXT4_SUPERBLOCK..];
+        superblock[0x04..0x08].copy_from_slice(&blocks.to_le_bytes());
+        superblock[0x38..0x3a].copy_from_slice(&EXT4_MAGIC.to_le_bytes());
+        image
+    }
+
+    fn tar_entry(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = vec![0; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+        header[257..263].copy_from_slice(b"ustar\0");
+        header.extend(data);
+        header.resize(512 + data.len().div_ceil(512) * 512, 0);
+        header
+    }
+
+    #[test]
+    fn checks_declared_formats() {
+        let policy = ValidationPolicy::Declared;
+        let ext4 = Some(BlobFormat::Ext4);
+        let validation = validate(policy, ext4, Some(4096), &ext4_image(4)).unwrap().unwrap();
+        assert_eq!(validation.format, ext4);
+        assert!(validation.size_verified);
+
+        let rejection = validate(policy, ext4, None, &ext4_image(8)).unwrap_err();
+        assert_eq!(rejection.reason, RejectReason::FormatMismatch);
+        assert!(rejection.message.contains("needs 8192 bytes"));
+        let mut corrupt = ext4_image(4);
+        corrupt[EXT4_SUPERBLOCK + 0x38] = 0;
+        assert!(validate(policy, ext4, None, &corrupt).is_err());
+
+        let mut inventory = CRIU_IMG_COMMON_MAGIC.to_le_bytes().to_vec();
+        inventory.extend(CRIU_INVENTORY_MAGIC.to_le_bytes());
+        let mut images = tar_entry("checkpoint/pages-1.img", &[7; 700]);
+        images.extend(tar_entry("checkpoint/inventory.img", &inventory));
+        images.extend([0; 1024]);
+        let criu = Some(BlobFormat::CriuImages);
+        assert!(validate(policy, criu, None, &images).is_ok());
+        let without_inventory = tar_entry("checkpoint/pages-1.img", &[7; 700]);
+        assert!(validate(policy, criu, None, &without_inventory).is_err());
+
+        let manifest = serde_json::json!({
+            "snapshot_id": uuid::Uuid::new_v4(),
+            "size_bytes": 10,
+            "chunk_size": 8,
+            "chunks": [
+                { "index": 0, "offset": 0, "size": 8, "sha256": "ab".repeat(32) },
+                { "index": 1, "offset": 8, "size": 2, "sha256": "cd".repeat(32) },
+            ],
+        });
+        let mut manifest = serde_json::to_vec(&manifest).unwrap();
+        let format = Some(BlobFormat::VaultManifest);
+        assert!(validate(policy, format, None, &manifest).is_ok());
+        manifest.truncate(manifest.len() - 1);
+        assert!(validate(policy, format, None, &manifest).is_err());
+    }
+
+    #[test]
+    fn policy_decides_what_is_checked() {
+        let rejection = validate(ValidationPolicy::Declared, None, Some(5), b"four").unwrap_err();
+        assert_eq!(rejection.reason, RejectReason::SizeMismatch);
+
+        let validation = validate(ValidationPolicy::Declared, None, Some(4), b"four").unwrap().unwrap();
+        assert_eq!(validation.format, None);
+        assert!(validation.size_verified);
+        assert_eq!(validate(ValidationPolicy::Declared, None, None, b"four"), Ok(None));
+
+        let rejection = validate(ValidationPolicy::Strict, None, None, b"four").unwrap_err();
+        assert_eq!(rejection.reason, RejectReason::FormatRequired);
+
+        assert_eq!(validate(ValidationPolicy::Off, Some(BlobFormat::Ext4), Some(5), b"four"), Ok(None));
+    }
+}
@@ -0,0 +1,10 @@
+ALTER TABLE sandbox_runs
+    ADD COLUMN IF NOT EXISTS gpu_type VARCHAR(64),
+    ADD COLUMN IF NOT EXISTS gpu_count INTEGER,
+    ADD COLUMN IF NOT EXISTS gpu_utilization_percent DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS gpu_memory_used_mb DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS gpu_seconds DOUBLE PRECISION;
+
+CREATE INDEX IF NOT EXISTS idx_sandbox_runs_provider_gpu_type
+    ON sandbox_runs(provider, gpu_type)
+    WHERE has_gpu;
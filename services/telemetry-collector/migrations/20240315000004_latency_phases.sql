@@ -0,0 +1,5 @@
+ALTER TABLE sandbox_runs
+    ADD COLUMN IF NOT EXISTS queued_ms BIGINT,
+    ADD COLUMN IF NOT EXISTS provision_ms BIGINT,
+    ADD COLUMN IF NOT EXISTS exec_ms BIGINT,
+    ADD COLUMN IF NOT EXISTS teardown_ms BIGINT;
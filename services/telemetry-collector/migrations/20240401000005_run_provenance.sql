@@ -0,0 +1,5 @@
+ALTER TABLE sandbox_runs
+    ADD COLUMN IF NOT EXISTS run_id UUID;
+
+CREATE INDEX IF NOT EXISTS idx_sandbox_runs_run_id ON sandbox_runs(run_id);
+CREATE INDEX IF NOT EXISTS idx_sandbox_runs_sandbox_id ON sandbox_runs(sandbox_id);
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS preemption_events (
+    id UUID PRIMARY KEY,
+    sandbox_id UUID NOT NULL,
+    run_id UUID,
+    provider VARCHAR(50) NOT NULL,
+    priority VARCHAR(16) NOT NULL,
+    action VARCHAR(16) NOT NULL,
+    preempted_by VARCHAR(16),
+    resumed_as UUID,
+    snapshot_id UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_preemption_events_sandbox_id ON preemption_events(sandbox_id);
+CREATE INDEX IF NOT EXISTS idx_preemption_events_run_id ON preemption_events(run_id);
+CREATE INDEX IF NOT EXISTS idx_preemption_events_created_at ON preemption_events(created_at);
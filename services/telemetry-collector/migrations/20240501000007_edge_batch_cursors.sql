@@ -0,0 +1,10 @@
+-- High-water mark of each edge agent's batch streams, so agents can resend
+-- exactly the batches lost while offline
+CREATE TABLE IF NOT EXISTS edge_agent_cursors (
+    agent_id VARCHAR(255) NOT NULL,
+    stream VARCHAR(16) NOT NULL,
+    session_id VARCHAR(64) NOT NULL,
+    sequence BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (agent_id, stream)
+);
@@ -0,0 +1,14 @@
+-- Provider prices, each in effect from its effective_from until a later rate
+-- for the same unit, so historical runs can be priced at the rates of their day
+CREATE TABLE IF NOT EXISTS provider_rates (
+    id UUID PRIMARY KEY,
+    provider VARCHAR(50) NOT NULL,
+    unit VARCHAR(16) NOT NULL,
+    rate DOUBLE PRECISION NOT NULL CHECK (rate >= 0),
+    effective_from TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (provider, unit, effective_from)
+);
+
+-- Cost of each run at catalog rates, next to the cost the reporter claimed
+ALTER TABLE sandbox_runs ADD COLUMN IF NOT EXISTS estimated_cost DOUBLE PRECISION;
@@ -0,0 +1,20 @@
+-- Tenant each run is billed to; runs recorded before tenants existed belong
+-- to the default tenant
+ALTER TABLE sandbox_runs ADD COLUMN IF NOT EXISTS tenant VARCHAR(255) NOT NULL DEFAULT 'default';
+CREATE INDEX IF NOT EXISTS idx_sandbox_runs_tenant_created ON sandbox_runs(tenant, created_at);
+
+-- Generated monthly usage reports, kept for download
+CREATE TABLE IF NOT EXISTS usage_reports (
+    id UUID PRIMARY KEY,
+    -- NULL for reports covering every tenant
+    tenant VARCHAR(255),
+    month VARCHAR(7) NOT NULL,
+    format VARCHAR(8) NOT NULL,
+    status VARCHAR(16) NOT NULL,
+    error TEXT,
+    content BYTEA,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_usage_reports_created ON usage_reports(created_at);
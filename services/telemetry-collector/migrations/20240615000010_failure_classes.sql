@@ -0,0 +1,19 @@
+-- Root cause of each failed run, set by the collector's classifier
+ALTER TABLE sandbox_runs ADD COLUMN IF NOT EXISTS failure_class VARCHAR(32);
+
+-- Classify earlier failures from what the table holds; the result payloads
+-- the classifier also reads weren't kept
+UPDATE sandbox_runs
+SET failure_class = CASE
+    WHEN exit_code = 124 OR duration_ms >= timeout_ms THEN 'timeout'
+    WHEN memory_requested > 0 AND memory_mb >= 0.95 * memory_requested THEN 'oom'
+    WHEN exit_code = 125 THEN 'infra_error'
+    WHEN exit_code < 0 THEN 'provider_error'
+    WHEN exit_code = 137 THEN 'oom'
+    ELSE 'nonzero_exit'
+END
+WHERE NOT success AND failure_class IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_sandbox_runs_failure_class
+    ON sandbox_runs(provider, language, failure_class)
+    WHERE failure_class IS NOT NULL;
@@ -0,0 +1,19 @@
+-- Runtime benchmarks measured by the gateway, one row per workload, runtime
+-- and isolation level of a benchmark run
+CREATE TABLE IF NOT EXISTS benchmark_results (
+    id BIGSERIAL PRIMARY KEY,
+    benchmark_id UUID NOT NULL,
+    runtime VARCHAR(32) NOT NULL,
+    isolation_level VARCHAR(16) NOT NULL,
+    workload VARCHAR(64) NOT NULL,
+    iterations INTEGER NOT NULL,
+    failures INTEGER NOT NULL,
+    cold_start_ms JSONB,
+    exec_ms JSONB,
+    execs_per_sec DOUBLE PRECISION,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_benchmark_results_benchmark_id ON benchmark_results(benchmark_id);
+CREATE INDEX IF NOT EXISTS idx_benchmark_results_workload ON benchmark_results(workload, created_at);
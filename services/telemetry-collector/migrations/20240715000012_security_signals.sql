@@ -0,0 +1,22 @@
+-- Per-sandbox security incident summaries forwarded by the security
+-- monitor, one row per sandbox in each batch
+CREATE TABLE IF NOT EXISTS security_signals (
+    id BIGSERIAL PRIMARY KEY,
+    batch_id UUID NOT NULL,
+    sandbox_id VARCHAR(255) NOT NULL,
+    run_id UUID,
+    provider VARCHAR(64) NOT NULL,
+    image TEXT,
+    violations BIGINT NOT NULL,
+    quarantines BIGINT NOT NULL,
+    max_severity VARCHAR(16) NOT NULL,
+    first_seen TIMESTAMPTZ NOT NULL,
+    last_seen TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Resent batches are stored once
+    UNIQUE (batch_id, sandbox_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_security_signals_sandbox_id ON security_signals(sandbox_id);
+CREATE INDEX IF NOT EXISTS idx_security_signals_provider ON security_signals(provider, last_seen);
+CREATE INDEX IF NOT EXISTS idx_security_signals_image ON security_signals(image, last_seen);
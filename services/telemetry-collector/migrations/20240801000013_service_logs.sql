@@ -0,0 +1,23 @@
+-- Structured logs shipped by the Sandstorm services, one row per record
+CREATE TABLE IF NOT EXISTS service_logs (
+    id BIGSERIAL PRIMARY KEY,
+    batch_id UUID NOT NULL,
+    -- Position of the record in its batch
+    position INTEGER NOT NULL,
+    service VARCHAR(64) NOT NULL,
+    instance VARCHAR(255) NOT NULL,
+    timestamp TIMESTAMPTZ NOT NULL,
+    level VARCHAR(8) NOT NULL,
+    target TEXT NOT NULL,
+    message TEXT NOT NULL,
+    sandbox_id VARCHAR(255),
+    run_id VARCHAR(255),
+    fields JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Resent batches are stored once
+    UNIQUE (batch_id, position)
+);
+
+CREATE INDEX IF NOT EXISTS idx_service_logs_service ON service_logs(service, timestamp);
+CREATE INDEX IF NOT EXISTS idx_service_logs_sandbox_id ON service_logs(sandbox_id, timestamp);
+CREATE INDEX IF NOT EXISTS idx_service_logs_run_id ON service_logs(run_id, timestamp);
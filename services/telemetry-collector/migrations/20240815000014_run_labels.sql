@@ -0,0 +1,10 @@
+-- Attribution labels (team, project, job, ...) set by whoever reported the
+-- run, for splitting spend between internal customers
+ALTER TABLE sandbox_runs ADD COLUMN IF NOT EXISTS labels JSONB NOT NULL DEFAULT '{}';
+CREATE INDEX IF NOT EXISTS idx_sandbox_runs_labels ON sandbox_runs USING GIN (labels jsonb_path_ops);
+
+-- Usage reports can break each tenant's usage down by a label's values and
+-- cover only runs carrying given labels
+ALTER TABLE usage_reports
+    ADD COLUMN IF NOT EXISTS group_by_label VARCHAR(63),
+    ADD COLUMN IF NOT EXISTS labels JSONB NOT NULL DEFAULT '{}';
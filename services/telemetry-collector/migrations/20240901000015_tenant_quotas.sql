@@ -0,0 +1,9 @@
+-- Monthly caps per tenant, enforced by the gateway at admission. NULL caps
+-- are unlimited.
+CREATE TABLE IF NOT EXISTS tenant_quotas (
+    tenant VARCHAR(255) PRIMARY KEY,
+    compute_seconds DOUBLE PRECISION,
+    cost DOUBLE PRECISION,
+    on_exhausted VARCHAR(16) NOT NULL DEFAULT 'reject',
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
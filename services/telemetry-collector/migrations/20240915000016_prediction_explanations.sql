@@ -0,0 +1,3 @@
+-- Per-feature contributions (SHAP values or similar) behind a prediction,
+-- for auditing routing decisions
+ALTER TABLE predictions ADD COLUMN IF NOT EXISTS explanation JSONB;
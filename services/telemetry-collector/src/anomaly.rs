@@ -0,0 +1,417 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::FromRow;
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+use crate::db::Database;
+use crate::error::AppResult;
+use crate::models::{AnomalyKind, EdgeAnomaly};
+use crate::AppState;
+
+/// Minimum z-score (own history or fleet) before a signal is considered abnormal
+const Z_SCORE_THRESHOLD: f64 = 3.0;
+/// Cap so flat baselines produce a finite, JSON-serializable score
+const MAX_Z_SCORE: f64 = 99.0;
+/// Absolute floors so tiny deviations on very stable agents don't page anyone
+const MIN_FAILURE_RATE: f64 = 0.10;
+const MIN_QUEUE_GROWTH_PER_MIN: f64 = 1.0;
+const MIN_MEMORY_PERCENT: f64 = 80.0;
+/// How long an alert for the same agent/kind is suppressed after firing
+const ALERT_COOLDOWN_MINUTES: i64 = 30;
+/// Agents reporting before the fleet is a meaningful baseline
+const MIN_FLEET_SIZE: usize = 3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DetectionParams {
+    pub window: Duration,
+    pub baseline: Duration,
+}
+
+impl Default for DetectionParams {
+    fn default() -> Self {
+        Self {
+            window: Duration::minutes(15),
+            baseline: Duration::hours(24),
+        }
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct MetricSample {
+    agent_id: String,
+    recorded_at: DateTime<Utc>,
+    queue_depth: Option<f64>,
+    completed: Option<f64>,
+    failed: Option<f64>,
+    memory_percent: Option<f64>,
+}
+
+/// Per-agent signal values for one time slice
+#[derive(Debug, Default, Clone, Copy)]
+struct Signals {
+    failure_rate: Option<f64>,
+    queue_growth_per_min: Option<f64>,
+    memory_percent: Option<f64>,
+}
+
+impl Signals {
+    fn get(&self, kind: AnomalyKind) -> Option<f64> {
+        match kind {
+            AnomalyKind::FailureRate => self.failure_rate,
+            AnomalyKind::QueueGrowth => self.queue_growth_per_min,
+            AnomalyKind::MemoryPressure => self.memory_percent,
+        }
+    }
+}
+
+/// Scan recent edge agent metrics and flag agents that deviate from their own
+/// history or from the rest of the fleet.
+pub async fn detect(db: &Database, params: DetectionParams) -> AppResult<Vec<EdgeAnomaly>> {
+    let now = Utc::now();
+    let window_start = now - params.window;
+    let baseline_start = window_start - params.baseline;
+
+    let samples = sqlx::query_as::<_, MetricSample>(
+        r#"
+        SELECT
+            agent_id,
+            recorded_at,
+            (payload->>'queueDepth')::FLOAT8 AS queue_depth,
+            (payload->>'completed')::FLOAT8 AS completed,
+            (payload->>'failed')::FLOAT8 AS failed,
+            CASE
+                WHEN (payload->'system'->'memory'->>'totalMB')::FLOAT8 > 0
+                THEN (payload->'system'->'memory'->>'usedMB')::FLOAT8
+                     / (payload->'system'->'memory'->>'totalMB')::FLOAT8 * 100.0
+            END AS memory_percent
+        FROM edge_agent_metrics
+        WHERE recorded_at >= $1
+        ORDER BY agent_id, recorded_at
+        "#,
+    )
+    .bind(baseline_start)
+    .fetch_all(db.pool())
+    .await?;
+
+    let mut by_agent: HashMap<String, Vec<MetricSample>> = HashMap::new();
+    for sample in samples {
+        by_agent.entry(sample.agent_id.clone()).or_default().push(sample);
+    }
+
+    // Current window signals per agent, plus window-sized slices of each agent's history
+    let mut current: HashMap<String, Signals> = HashMap::new();
+    let mut history: HashMap<String, Vec<Signals>> = HashMap::new();
+
+    for (agent_id, samples) in &by_agent {
+        let (recent, older): (Vec<&MetricSample>, Vec<&MetricSample>) =
+            samples.iter().partition(|s| s.recorded_at >= window_start);
+
+        if recent.is_empty() {
+            continue;
+        }
+        current.insert(agent_id.clone(), compute_signals(&recent));
+
+        let mut slices: Vec<Signals> = Vec::new();
+        let mut slice: Vec<&MetricSample> = Vec::new();
+        let mut slice_start = older.first().map(|s| s.recorded_at);
+        for sample in older {
+            if let Some(start) = slice_start {
+                if sample.recorded_at - start >= params.window {
+                    slices.push(compute_signals(&slice));
+                    slice.clear();
+                    slice_start = Some(sample.recorded_at);
+                }
+            }
+            slice.push(sample);
+        }
+        if !slice.is_empty() {
+            slices.push(compute_signals(&slice));
+        }
+        history.insert(agent_id.clone(), slices);
+    }
+
+    Ok(score(&current, &history, now))
+}
+
+/// Agents whose current signals stand out from their own history or from
+/// the rest of the fleet
+fn score(
+    current: &HashMap<String, Signals>,
+    history: &HashMap<String, Vec<Signals>>,
+    now: DateTime<Utc>,
+) -> Vec<EdgeAnomaly> {
+    let mut anomalies = Vec::new();
+    for kind in [
+        AnomalyKind::FailureRate,
+        AnomalyKind::QueueGrowth,
+        AnomalyKind::MemoryPressure,
+    ] {
+        let fleet_values: Vec<f64> = current.values().filter_map(|s| s.get(kind)).collect();
+        let fleet = mean_and_stddev(&fleet_values);
+
+        for (agent_id, signals) in current {
+            let Some(observed) = signals.get(kind) else {
+                continue;
+            };
+            if observed < absolute_floor(kind) {
+                continue;
+            }
+
+            let own_values: Vec<f64> = history
+                .get(agent_id)
+                .map(|slices| slices.iter().filter_map(|s| s.get(kind)).collect())
+                .unwrap_or_default();
+            let own = mean_and_stddev(&own_values);
+
+            let agent_z_score = own.and_then(|(mean, stddev)| z_score(observed, mean, stddev));
+            // A fleet baseline is meaningless with only one or two agents reporting
+            let fleet_z_score = if fleet_values.len() >= MIN_FLEET_SIZE {
+                fleet.and_then(|(mean, stddev)| z_score(observed, mean, stddev))
+            } else {
+                None
+            };
+
+            let abnormal = [agent_z_score, fleet_z_score]
+                .iter()
+                .flatten()
+                .any(|z| *z >= Z_SCORE_THRESHOLD);
+
+            if abnormal {
+                anomalies.push(EdgeAnomaly {
+                    agent_id: agent_id.clone(),
+                    kind,
+                    observed,
+                    agent_baseline: own.map(|(mean, _)| mean),
+                    fleet_baseline: fleet.map(|(mean, _)| mean),
+                    agent_z_score,
+                    fleet_z_score,
+                    detected_at: now,
+                });
+            }
+        }
+    }
+
+    anomalies.sort_by(|a, b| a.agent_id.cmp(&b.agent_id));
+    anomalies
+}
+
+fn compute_signals(samples: &[&MetricSample]) -> Signals {
+    let (Some(first), Some(last)) = (samples.first(), samples.last()) else {
+        return Signals::default();
+    };
+
+    // completed/failed are cumulative counters reported by the agent
+    let failure_rate = match (first.completed, first.failed, last.completed, last.failed) {
+        (Some(c0), Some(f0), Some(c1), Some(f1)) => {
+            let completed = (c1 - c0).max(0.0);
+            let failed = (f1 - f0).max(0.0);
+            let total = completed + failed;
+            (total > 0.0).then(|| failed / total)
+        }
+        _ => None,
+    };
+
+    let minutes = (last.recorded_at - first.recorded_at).num_seconds() as f64 / 60.0;
+    let queue_growth_per_min = match (first.queue_depth, last.queue_depth) {
+        (Some(q0), Some(q1)) if minutes > 0.0 => Some((q1 - q0) / minutes),
+        _ => None,
+    };
+
+    let memory: Vec<f64> = samples.iter().filter_map(|s| s.memory_percent).collect();
+    let memory_percent = mean_and_stddev(&memory).map(|(mean, _)| mean);
+
+    Signals {
+        failure_rate,
+        queue_growth_per_min,
+        memory_percent,
+    }
+}
+
+fn absolute_floor(kind: AnomalyKind) -> f64 {
+    match kind {
+        AnomalyKind::FailureRate => MIN_FAILURE_RATE,
+        AnomalyKind::QueueGrowth => MIN_QUEUE_GROWTH_PER_MIN,
+        AnomalyKind::MemoryPressure => MIN_MEMORY_PERCENT,
+    }
+}
+
+fn mean_and_stddev(values: &[f64]) -> Option<(f64, f64)> {
+    if values.is_empty() {
+        return None;
+    }
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    Some((mean, variance.sqrt()))
+}
+
+fn z_score(value: f64, mean: f64, stddev: f64) -> Option<f64> {
+    if stddev > f64::EPSILON {
+        Some(((value - mean) / stddev).min(MAX_Z_SCORE))
+    } else if value > mean {
+        // A flat baseline that is suddenly exceeded is as abnormal as it gets
+        Some(MAX_Z_SCORE)
+    } else {
+        None
+    }
+}
+
+/// Periodically run the detector and push newly detected anomalies to the
+/// configured webhook. The webhook and interval are re-read from the live
+/// configuration on every tick.
+pub async fn alerting_task(state: AppState) {
+    let client = reqwest::Client::new();
+    let mut last_alerted: HashMap<(String, AnomalyKind), DateTime<Utc>> = HashMap::new();
+    let mut webhook_configured = true;
+
+    loop {
+        let config = state.config.current();
+        tokio::time::sleep(std::time::Duration::from_secs(
+            config.anomaly_check_interval_secs,
+        ))
+        .await;
+
+        let Some(webhook_url) = config.anomaly_webhook_url.clone() else {
+            if webhook_configured {
+                info!("No anomaly webhook configured, edge anomaly alerting disabled");
+                webhook_configured = false;
+            }
+            continue;
+        };
+        webhook_configured = true;
+
+        let anomalies = match detect(&state.db, DetectionParams::default()).await {
+            Ok(anomalies) => anomalies,
+            Err(error) => {
+                warn!(?error, "edge anomaly detection failed");
+                continue;
+            }
+        };
+
+        let now = Utc::now();
+        // Expired cooldowns suppress nothing, and agents come and go
+        let cooldown = Duration::minutes(ALERT_COOLDOWN_MINUTES);
+        last_alerted.retain(|_, at| now - *at < cooldown);
+        let fresh: Vec<&EdgeAnomaly> = anomalies
+            .iter()
+            .filter(|anomaly| !last_alerted.contains_key(&(anomaly.agent_id.clone(), anomaly.kind)))
+            .collect();
+
+        if fresh.is_empty() {
+            continue;
+        }
+
+        let payload = serde_json::json!({
+            "type": "edge_agent_anomaly",
+            "timestamp": now,
+            "anomalies": fresh,
+        });
+
+        match client.post(&webhook_url).json(&payload).send().await {
+            // Only a delivered alert starts the cooldown, so failed ones are retried
+            Ok(response) if response.status().is_success() => {
+                info!(count = fresh.len(), "sent edge anomaly alert");
+                for anomaly in &fresh {
+                    last_alerted.insert((anomaly.agent_id.clone(), anomaly.kind), now);
+                }
+            }
+            Ok(response) => warn!(status = %response.status(), "edge anomaly webhook rejected alert"),
+            Err(error) => warn!(?error, "failed to deliver edge anomaly alert"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(minute: i64, completed: f64, failed: f64, queue_depth: f64) -> MetricSample {
+        MetricSample {
+            agent_id: "agent".to_string(),
+            recorded_at: DateTime::<Utc>::UNIX_EPOCH + Duration::minutes(minute),
+            queue_depth: Some(queue_depth),
+            completed: Some(completed),
+            failed: Some(failed),
+            memory_percent: Some(50.0),
+        }
+    }
+
+    fn signals(samples: &[MetricSample]) -> Signals {
+        compute_signals(&samples.iter().collect::<Vec<_>>())
+    }
+
+    fn failing(rate: f64) -> Signals {
+        Signals {
+            failure_rate: Some(rate),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn flat_baselines_give_finite_scores() {
+        assert_eq!(mean_and_stddev(&[]), None);
+        assert_eq!(mean_and_stddev(&[0.2; 5]), Some((0.2, 0.0)));
+
+        // (value, mean, stddev, expected)
+        let cases = [
+            (0.2, 0.2, 0.0, None),
+            (0.1, 0.2, 0.0, None),
+            (0.5, 0.2, 0.0, Some(MAX_Z_SCORE)),
+            (0.4, 0.2, 0.1, Some(2.0)),
+            (1e9, 0.0, 1.0, Some(MAX_Z_SCORE)),
+        ];
+        for (value, mean, stddev, expected) in cases {
+            let z = z_score(value, mean, stddev);
+            assert!(z.is_none_or(f64::is_finite));
+            match (z, expected) {
+                (Some(z), Some(expected)) => assert!((z - expected).abs() < 1e-9, "{} vs {}", z, expected),
+                (z, expected) => assert_eq!(z, expected, "{}", value),
+            }
+        }
+    }
+
+    #[test]
+    fn signals_come_from_counter_and_queue_changes() {
+        let busy = signals(&[sample(0, 100.0, 10.0, 4.0), sample(10, 130.0, 20.0, 24.0)]);
+        assert_eq!(busy.failure_rate, Some(0.25));
+        assert_eq!(busy.queue_growth_per_min, Some(2.0));
+        assert_eq!(busy.memory_percent, Some(50.0));
+
+        // An agent restart resets its counters; that's not a burst of failures
+        let reset = signals(&[sample(0, 500.0, 40.0, 0.0), sample(10, 20.0, 1.0, 0.0)]);
+        assert_eq!(reset.failure_rate, None);
+        assert_eq!(signals(&[]).failure_rate, None);
+        assert_eq!(signals(&[sample(0, 1.0, 1.0, 1.0)]).queue_growth_per_min, None);
+    }
+
+    #[test]
+    fn flags_a_clear_outlier_against_the_fleet() {
+        // One agent in a dozen can only stand three deviations out of a fleet that includes it
+        let mut current: HashMap<String, Signals> = (0..11)
+            .map(|agent| (format!("agent-{:02}", agent), failing(0.11 + 0.01 * (agent % 2) as f64)))
+            .collect();
+        current.insert("outlier".to_string(), failing(0.9));
+        let anomalies = score(&current, &HashMap::new(), Utc::now());
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].agent_id, "outlier");
+        assert_eq!(anomalies[0].kind, AnomalyKind::FailureRate);
+        assert!(anomalies[0].fleet_z_score.is_some_and(|z| z >= Z_SCORE_THRESHOLD));
+        assert_eq!(anomalies[0].agent_z_score, None);
+    }
+
+    #[test]
+    fn small_fleets_are_only_compared_with_their_own_history() {
+        let current: HashMap<String, Signals> =
+            [("a".to_string(), failing(0.1)), ("b".to_string(), failing(0.9))].into();
+        assert!(score(&current, &HashMap::new(), Utc::now()).is_empty());
+
+        // Against a steady history of its own, the jump still stands out
+        let history: HashMap<String, Vec<Signals>> =
+            [("b".to_string(), vec![failing(0.1), failing(0.12), failing(0.11)])].into();
+        let anomalies = score(&current, &history, Utc::now());
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].agent_id, "b");
+        assert_eq!(anomalies[0].fleet_z_score, None);
+        assert!(anomalies[0].agent_z_score.is_some_and(|z| z >= Z_SCORE_THRESHOLD));
+    }
+}
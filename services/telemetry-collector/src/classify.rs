@@ -0,0 +1,198 @@
+use crate::models::FailureClass;
+
+/// Exit status of `timeout(1)` and of runtimes that enforce time limits the
+/// same way
+const EXIT_TIMED_OUT: i32 = 124;
+/// Container runtimes exit with this when they fail before the workload
+/// starts
+const EXIT_RUNTIME_ERROR: i32 = 125;
+/// SIGKILL, which is what the kernel's OOM killer sends
+const EXIT_KILLED: i32 = 137;
+/// Bytes of stderr scanned, from the end, where the fatal error usually is
+const STDERR_TAIL_BYTES: usize = 8 * 1024;
+
+const OOM_PATTERNS: &[&str] = &[
+    "out of memory",
+    "oomkilled",
+    "cannot allocate memory",
+    "memoryerror",
+    "javascript heap out of memory",
+];
+const TIMEOUT_PATTERNS: &[&str] = &["timed out", "timeout exceeded", "deadline exceeded"];
+const PROVIDER_PATTERNS: &[&str] = &[
+    "rate limit",
+    "quota exceeded",
+    "too many requests",
+    "service unavailable",
+    "bad gateway",
+    "provider error",
+];
+const INFRA_PATTERNS: &[&str] = &[
+    "no space left on device",
+    "errimagepull",
+    "imagepullbackoff",
+    "failed to pull image",
+    "oci runtime",
+    "failed to create container",
+    "connection refused",
+];
+
+/// What a run reported, as far as the classifier cares
+pub struct RunOutcome<'a> {
+    pub exit_code: i32,
+    pub duration_ms: i64,
+    pub timeout_ms: Option<i64>,
+    pub memory_mb: Option<f64>,
+    pub memory_requested: Option<i32>,
+    /// The provider's result payload
+    pub result: &'a serde_json::Value,
+}
+
+/// Why a run failed, or `None` when it succeeded. Explicit evidence (hitting
+/// the time or memory limit, or an error message naming the cause) wins over
+/// what the exit code suggests; anything else is the workload's own failure.
+pub fn classify(run: &RunOutcome<'_>) -> Option<FailureClass> {
+    if run.exit_code == 0 {
+        return None;
+    }
+    let text = error_text(run.result);
+    let mentions = |patterns: &[&str]| patterns.iter().any(|pattern| text.contains(pattern));
+
+    let hit_time_limit = run
+        .timeout_ms
+        .is_some_and(|timeout_ms| run.duration_ms >= timeout_ms);
+    let hit_memory_limit = match (run.memory_mb, run.memory_requested) {
+        (Some(used), Some(limit)) if limit > 0 => used >= 0.95 * f64::from(limit),
+        _ => false,
+    };
+
+    let class = if hit_time_limit || run.exit_code == EXIT_TIMED_OUT {
+        FailureClass::Timeout
+    } else if hit_memory_limit || mentions(OOM_PATTERNS) {
+        FailureClass::Oom
+    } else if mentions(INFRA_PATTERNS) || run.exit_code == EXIT_RUNTIME_ERROR {
+        FailureClass::InfraError
+    } else if mentions(PROVIDER_PATTERNS) || provider_failed(run.result) || run.exit_code < 0 {
+        FailureClass::ProviderError
+    } else if mentions(TIMEOUT_PATTERNS) {
+        FailureClass::Timeout
+    } else if run.exit_code == EXIT_KILLED {
+        FailureClass::Oom
+    } else {
+        FailureClass::NonzeroExit
+    };
+    Some(class)
+}
+
+/// Whether the result carries an error from the provider itself rather than
+/// output of the workload
+fn provider_failed(result: &serde_json::Value) -> bool {
+    ["providerError", "provider_error"]
+        .iter()
+        .any(|key| result.get(key).is_some_and(|value| !value.is_null()))
+}
+
+/// Lowercased error messages of a result: the tail of stderr, any `error`
+/// or `message` field, and error-level log lines
+fn error_text(result: &serde_json::Value) -> String {
+    let mut text = String::new();
+    if let Some(stderr) = result.get("stderr").and_then(|value| value.as_str()) {
+        let mut start = stderr.len().saturating_sub(STDERR_TAIL_BYTES);
+        while !stderr.is_char_boundary(start) {
+            start += 1;
+        }
+        text.push_str(&stderr[start..]);
+    }
+    for key in ["error", "message", "providerError", "provider_error"] {
+        match result.get(key) {
+            Some(serde_json::Value::String(message)) => {
+                text.push('\n');
+                text.push_str(message);
+            }
+            Some(value @ serde_json::Value::Object(_)) => {
+                text.push('\n');
+                text.push_str(&value.to_string());
+            }
+            _ => {}
+        }
+    }
+    let logs = result.get("logs").and_then(|logs| logs.as_array());
+    for log in logs.into_iter().flatten() {
+        if log.get("level").and_then(|level| level.as_str()) == Some("error") {
+            if let Some(message) = log.get("message").and_then(|message| message.as_str()) {
+                text.push('\n');
+                text.push_str(message);
+            }
+        }
+    }
+    text.to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn run(exit_code: i32, result: &serde_json::Value) -> RunOutcome<'_> {
+        RunOutcome {
+            exit_code,
+            duration_ms: 1_000,
+            timeout_ms: Some(30_000),
+            memory_mb: Some(100.0),
+            memory_requested: Some(512),
+            result,
+        }
+    }
+
+    #[test]
+    fn classifies_by_evidence_then_exit_code() {
+        let cases: Vec<(&str, i32, serde_json::Value, Option<FailureClass>)> = vec![
+            ("success", 0, json!({ "stderr": "out of memory" }), None),
+            ("timeout exit", EXIT_TIMED_OUT, json!({}), Some(FailureClass::Timeout)),
+            ("timeout message", 1, json!({ "error": "Deadline exceeded" }), Some(FailureClass::Timeout)),
+            ("oom message", 1, json!({ "stderr": "FATAL ERROR: JavaScript heap out of memory" }), Some(FailureClass::Oom)),
+            ("killed", EXIT_KILLED, json!({}), Some(FailureClass::Oom)),
+            ("image pull", 1, json!({ "message": "ErrImagePull: not found" }), Some(FailureClass::InfraError)),
+            ("runtime exit", EXIT_RUNTIME_ERROR, json!({}), Some(FailureClass::InfraError)),
+            ("rate limited", 1, json!({ "logs": [{ "level": "error", "message": "429 Too Many Requests" }] }), Some(FailureClass::ProviderError)),
+            ("provider field", 1, json!({ "providerError": { "code": 42 } }), Some(FailureClass::ProviderError)),
+            ("negative exit", -1, json!({}), Some(FailureClass::ProviderError)),
+            // Nothing names a cause: the workload's own failure
+            ("unknown", 3, json!({ "stderr": "AssertionError: expected 2", "providerError": null }), Some(FailureClass::NonzeroExit)),
+            ("info logs only", 1, json!({ "logs": [{ "level": "info", "message": "timed out waiting" }] }), Some(FailureClass::NonzeroExit)),
+        ];
+
+        for (name, exit_code, result, expected) in &cases {
+            assert_eq!(classify(&run(*exit_code, result)), *expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn limits_win_over_what_the_output_says() {
+        let result = json!({ "stderr": "connection refused" });
+
+        let mut timed_out = run(1, &result);
+        timed_out.duration_ms = 30_000;
+        assert_eq!(classify(&timed_out), Some(FailureClass::Timeout));
+
+        let mut out_of_memory = run(1, &result);
+        out_of_memory.memory_mb = Some(500.0);
+        assert_eq!(classify(&out_of_memory), Some(FailureClass::Oom));
+
+        // Without limits to compare with, the message decides
+        let mut unlimited = run(1, &result);
+        unlimited.timeout_ms = None;
+        unlimited.memory_requested = None;
+        unlimited.duration_ms = 60_000;
+        assert_eq!(classify(&unlimited), Some(FailureClass::InfraError));
+    }
+
+    #[test]
+    fn scans_only_the_end_of_stderr() {
+        let early = format!("out of memory\n{}", "é".repeat(STDERR_TAIL_BYTES));
+        assert_eq!(classify(&run(1, &json!({ "stderr": early }))), Some(FailureClass::NonzeroExit));
+
+        let late = format!("{}\nout of memory", "é".repeat(STDERR_TAIL_BYTES));
+        assert_eq!(classify(&run(1, &json!({ "stderr": late }))), Some(FailureClass::Oom));
+    }
+}
@@ -1,31 +1,102 @@
 use anyhow::Result;
-use serde::Deserialize;
-use config::{Config as ConfigBuilder, ConfigError, Environment, File};
+use sandstorm_config::{ConfigHandle, ServiceConfig, Sources};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pub port: u16,
     pub database_url: String,
     pub max_training_data_age_days: i64,
     pub metrics_retention_days: i64,
+    /// Webhook that receives edge agent anomaly alerts; alerting is off when unset
+    pub anomaly_webhook_url: Option<String>,
+    pub anomaly_check_interval_secs: u64,
+    /// Bearer token required by the `/config` admin endpoints when set
+    pub admin_token: Option<String>,
+    /// How far a reported run cost may be off from its catalog estimate,
+    /// relative to the larger of the two, before the run is flagged
+    pub cost_divergence_tolerance: f64,
+    /// Shared secret the security monitor signs forwarded incident batches
+    /// with; they are refused when unset
+    pub security_signal_key: Option<String>,
+    /// Prometheus remote-write endpoint metrics are pushed to, for
+    /// deployments nothing scrapes; nothing is pushed when unset
+    pub remote_write_url: Option<String>,
+    /// Bearer token sent with every push
+    pub remote_write_token: Option<String>,
+    pub remote_write_interval_secs: u64,
+    /// Samples per request; larger pushes are split
+    pub remote_write_max_samples: usize,
+    /// Retries of a failed request before its samples are dropped
+    pub remote_write_max_retries: u32,
 }
 
-impl Config {
-    pub fn load() -> Result<Self> {
-        let config = ConfigBuilder::builder()
-            // Start with default values
-            .set_default("port", 8082)?
-            .set_default("max_training_data_age_days", 30)?
-            .set_default("metrics_retention_days", 90)?
-            
-            // Add in settings from config file
-            .add_source(File::with_name("config/telemetry").required(false))
-            
-            // Add in settings from environment
-            .add_source(Environment::with_prefix("TELEMETRY").separator("_"))
-            
-            .build()?;
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: 8082,
+            database_url: String::new(),
+            max_training_data_age_days: 30,
+            metrics_retention_days: 90,
+            anomaly_webhook_url: None,
+            anomaly_check_interval_secs: 60,
+            admin_token: None,
+            cost_divergence_tolerance: 0.25,
+            security_signal_key: None,
+            remote_write_url: None,
+            remote_write_token: None,
+            remote_write_interval_secs: 30,
+            remote_write_max_samples: 2000,
+            remote_write_max_retries: 3,
+        }
+    }
+}
 
-        Ok(config.try_deserialize()?)
+impl ServiceConfig for Config {
+    const STATIC_KEYS: &'static [&'static str] = &["port", "database_url", "admin_token"];
+    const SECRET_KEYS: &'static [&'static str] = &[
+        "database_url",
+        "admin_token",
+        "security_signal_key",
+        "remote_write_token",
+    ];
+
+    fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+        if self.database_url.is_empty() {
+            problems.push("database_url must be set".to_string());
+        }
+        if self.max_training_data_age_days <= 0 {
+            problems.push("max_training_data_age_days must be positive".to_string());
+        }
+        if self.metrics_retention_days <= 0 {
+            problems.push("metrics_retention_days must be positive".to_string());
+        }
+        if self.anomaly_check_interval_secs == 0 {
+            problems.push("anomaly_check_interval_secs must be positive".to_string());
+        }
+        if !(self.cost_divergence_tolerance > 0.0 && self.cost_divergence_tolerance <= 1.0) {
+            problems.push("cost_divergence_tolerance must be in (0, 1]".to_string());
+        }
+        if self.remote_write_interval_secs == 0 {
+            problems.push("remote_write_interval_secs must be positive".to_string());
+        }
+        if self.remote_write_max_samples == 0 {
+            problems.push("remote_write_max_samples must be positive".to_string());
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
     }
-}
\ No newline at end of file
+}
+
+impl Config {
+    /// Load from `config/telemetry` (or `TELEMETRY_CONFIG_FILE`), an optional
+    /// `TELEMETRY_CONFIG_URL` and `TELEMETRY_*` environment variables.
+    pub async fn load() -> Result<ConfigHandle<Self>> {
+        let sources = Sources::from_env(Some("TELEMETRY"), Some("config/telemetry"));
+        Ok(ConfigHandle::load(sources).await?)
+    }
+}
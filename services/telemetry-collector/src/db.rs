@@ -2,6 +2,22 @@ use anyhow::Result;
 use sqlx::{postgres::PgPoolOptions, PgPool};
 use tracing::info;
 
+/// Tables included in backups, in restore order
+pub const BACKUP_TABLES: &[&str] = &[
+    "sandbox_runs",
+    "training_data",
+    "predictions",
+    "edge_agent_status",
+    "edge_agent_metrics",
+    "edge_agent_runs",
+    "edge_agent_cursors",
+    "preemption_events",
+    "provider_rates",
+    "usage_reports",
+    "benchmark_results",
+    "security_signals",
+];
+
 #[derive(Clone)]
 pub struct Database {
     pool: PgPool,
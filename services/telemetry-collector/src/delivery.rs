@@ -0,0 +1,208 @@
+use axum::http::HeaderMap;
+use sqlx::{Postgres, Transaction};
+
+use crate::db::Database;
+use crate::error::{AppError, AppResult};
+use crate::models::{EdgeBatchAck, EdgeStreamCursor};
+
+/// Header naming the agent a sequenced batch comes from
+pub const AGENT_ID_HEADER: &str = "x-agent-id";
+
+/// Batch streams an agent numbers separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Status,
+    Metrics,
+    Logs,
+}
+
+impl Stream {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stream::Status => "status",
+            Stream::Metrics => "metrics",
+            Stream::Logs => "logs",
+        }
+    }
+}
+
+/// Position of a sequenced batch. Each agent session numbers the batches of
+/// a stream from 1, and the collector stores them strictly in order, so its
+/// high-water mark tells the agent exactly what to resend.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub agent_id: String,
+    pub session_id: String,
+    pub sequence: i64,
+}
+
+impl Delivery {
+    /// The batch's position, if the agent sent one
+    pub fn from_batch(
+        headers: &HeaderMap,
+        session_id: Option<&str>,
+        sequence: Option<i64>,
+    ) -> AppResult<Option<Self>> {
+        let (session_id, sequence) = match (session_id, sequence) {
+            (None, None) => return Ok(None),
+            (Some(session_id), Some(sequence)) => (session_id, sequence),
+            _ => {
+                return Err(AppError::Validation(
+                    "sessionId and sequence must be sent together".to_string(),
+                ))
+            }
+        };
+        if session_id.is_empty() || session_id.len() > 64 {
+            return Err(AppError::Validation(
+                "sessionId must be 1 to 64 characters".to_string(),
+            ));
+        }
+        if sequence < 1 {
+            return Err(AppError::Validation("sequence starts at 1".to_string()));
+        }
+        let agent_id = headers
+            .get(AGENT_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| {
+                AppError::Validation("sequenced batches need an X-Agent-ID header".to_string())
+            })?;
+
+        Ok(Some(Self {
+            agent_id: agent_id.to_string(),
+            session_id: session_id.to_string(),
+            sequence,
+        }))
+    }
+
+    /// Lock the stream's cursor for the rest of the transaction and check
+    /// the batch against it. Returns the reply for a batch already stored;
+    /// batches past the next expected one are refused so the agent resends
+    /// the gap first.
+    async fn admit(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        stream: Stream,
+    ) -> AppResult<Option<EdgeBatchAck>> {
+        sqlx::query!(
+            r#"
+            INSERT INTO edge_agent_cursors (agent_id, stream, session_id, sequence)
+            VALUES ($1, $2, '', 0)
+            ON CONFLICT (agent_id, stream) DO NOTHING
+            "#,
+            self.agent_id,
+            stream.as_str()
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        let cursor = sqlx::query!(
+            r#"
+            SELECT session_id, sequence FROM edge_agent_cursors
+            WHERE agent_id = $1 AND stream = $2
+            FOR UPDATE
+            "#,
+            self.agent_id,
+            stream.as_str()
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+
+        // A new session starts over from nothing stored
+        let acknowledged = if cursor.session_id == self.session_id {
+            cursor.sequence
+        } else {
+            0
+        };
+        if self.sequence <= acknowledged {
+            return Ok(Some(EdgeBatchAck {
+                acknowledged: Some(acknowledged),
+                duplicate: true,
+            }));
+        }
+        if self.sequence > acknowledged + 1 {
+            return Err(AppError::Conflict(format!(
+                "expected {} batch {} of session {}, got {}",
+                stream.as_str(),
+                acknowledged + 1,
+                self.session_id,
+                self.sequence
+            )));
+        }
+        Ok(None)
+    }
+
+    /// Move the stream's cursor to this batch
+    async fn advance(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        stream: Stream,
+    ) -> AppResult<EdgeBatchAck> {
+        sqlx::query!(
+            r#"
+            UPDATE edge_agent_cursors
+            SET session_id = $3, sequence = $4, updated_at = NOW()
+            WHERE agent_id = $1 AND stream = $2
+            "#,
+            self.agent_id,
+            stream.as_str(),
+            self.session_id,
+            self.sequence
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(EdgeBatchAck {
+            acknowledged: Some(self.sequence),
+            duplicate: false,
+        })
+    }
+}
+
+/// Check a batch before storing it. `Some` is the reply for a batch stored
+/// before, which must not be stored again.
+pub async fn admit(
+    tx: &mut Transaction<'_, Postgres>,
+    stream: Stream,
+    delivery: Option<&Delivery>,
+) -> AppResult<Option<EdgeBatchAck>> {
+    match delivery {
+        Some(delivery) => delivery.admit(tx, stream).await,
+        None => Ok(None),
+    }
+}
+
+/// Record a stored batch and commit it with its cursor
+pub async fn commit(
+    mut tx: Transaction<'_, Postgres>,
+    stream: Stream,
+    delivery: Option<&Delivery>,
+) -> AppResult<EdgeBatchAck> {
+    let ack = match delivery {
+        Some(delivery) => delivery.advance(&mut tx, stream).await?,
+        None => EdgeBatchAck {
+            acknowledged: None,
+            duplicate: false,
+        },
+    };
+    tx.commit().await?;
+    Ok(ack)
+}
+
+/// Cursors of an agent's streams that have seen sequenced batches
+pub async fn cursors(db: &Database, agent_id: &str) -> AppResult<Vec<EdgeStreamCursor>> {
+    let cursors = sqlx::query_as!(
+        EdgeStreamCursor,
+        r#"
+        SELECT stream, session_id, sequence, updated_at
+        FROM edge_agent_cursors
+        WHERE agent_id = $1 AND sequence > 0
+        ORDER BY stream
+        "#,
+        agent_id
+    )
+    .fetch_all(db.pool())
+    .await?;
+
+    Ok(cursors)
+}
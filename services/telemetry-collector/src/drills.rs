@@ -0,0 +1,180 @@
+//! Failover drills: take a provider down over a past window and work out
+//! where its runs would have gone and what they'd have cost there. Nothing
+//! live changes; the gateway goes on routing as before.
+
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::db::Database;
+use crate::error::AppResult;
+use crate::models::{FailoverDrillReport, FailoverDrillRequest, FailoverTarget, SandboxRun};
+use crate::pricing::PriceCatalog;
+
+/// Runs of the downed provider read per database round trip
+const PAGE_SIZE: i64 = 1000;
+
+const DEFAULT_LOOKBACK_DAYS: i64 = 7;
+const DEFAULT_MIN_SUCCESS_RATE: f64 = 0.9;
+
+/// A provider that could take over runs, from its traffic before the outage
+#[derive(Debug, Default)]
+struct Candidate {
+    runs: i64,
+    successes: f64,
+    /// Average reported cost per run, for runs without and with a GPU
+    avg_cost: [Option<f64>; 2],
+}
+
+impl Candidate {
+    fn success_rate(&self) -> f64 {
+        if self.runs == 0 {
+            0.0
+        } else {
+            self.successes / self.runs as f64
+        }
+    }
+}
+
+/// Replay the downed provider's runs between `start` and `end`, sending
+/// each to the healthy provider that would have run it cheapest. Only
+/// providers that ran the same kind of work (with or without a GPU) during
+/// the lookback are considered.
+pub async fn failover(
+    db: &Database,
+    request: &FailoverDrillRequest,
+    end: DateTime<Utc>,
+) -> AppResult<FailoverDrillReport> {
+    let lookback = Duration::days(request.lookback_days.unwrap_or(DEFAULT_LOOKBACK_DAYS));
+    let min_success_rate = request.min_success_rate.unwrap_or(DEFAULT_MIN_SUCCESS_RATE);
+    let catalog = PriceCatalog::load(db, None).await?;
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            provider,
+            has_gpu,
+            COUNT(*) AS "runs!",
+            AVG(cost)::FLOAT8 AS "avg_cost!",
+            SUM(CASE WHEN success THEN 1.0 ELSE 0.0 END)::FLOAT8 AS "successes!"
+        FROM sandbox_runs
+        WHERE provider <> $1 AND created_at >= $2 AND created_at < $3
+        GROUP BY provider, has_gpu
+        "#,
+        request.provider,
+        request.start - lookback,
+        request.start
+    )
+    .fetch_all(db.pool())
+    .await?;
+    let mut candidates: HashMap<String, Candidate> = HashMap::new();
+    for row in rows {
+        let candidate = candidates.entry(row.provider).or_default();
+        candidate.runs += row.runs;
+        candidate.successes += row.successes;
+        candidate.avg_cost[usize::from(row.has_gpu)] = Some(row.avg_cost);
+    }
+    candidates.retain(|_, candidate| candidate.success_rate() >= min_success_rate);
+
+    let mut report = FailoverDrillReport {
+        provider: request.provider.clone(),
+        start: request.start,
+        end,
+        runs: 0,
+        rerouted_runs: 0,
+        unroutable_runs: 0,
+        original_cost: 0.0,
+        rerouted_cost: 0.0,
+        cost_delta: 0.0,
+        targets: Vec::new(),
+    };
+    let mut targets: HashMap<&str, FailoverTarget> = HashMap::new();
+    let mut after: Option<(DateTime<Utc>, Uuid)> = None;
+    loop {
+        let runs = sqlx::query_as!(
+            SandboxRun,
+            r#"
+            SELECT * FROM sandbox_runs
+            WHERE provider = $1
+              AND created_at >= $2
+              AND created_at < $3
+              AND ($4::TIMESTAMPTZ IS NULL OR (created_at, id) > ($4, $5::UUID))
+            ORDER BY created_at, id
+            LIMIT $6
+            "#,
+            request.provider,
+            request.start,
+            end,
+            after.map(|(created_at, _)| created_at),
+            after.map(|(_, id)| id),
+            PAGE_SIZE
+        )
+        .fetch_all(db.pool())
+        .await?;
+        let Some(last) = runs.last() else {
+            break;
+        };
+        after = Some((last.created_at, last.id));
+
+        for run in &runs {
+            report.runs += 1;
+            let Some((provider, candidate, rerouted_cost)) = cheapest(&candidates, &catalog, run) else {
+                report.unroutable_runs += 1;
+                continue;
+            };
+            let original_cost = run.estimated_cost.unwrap_or(run.cost);
+            let target = targets.entry(provider).or_insert_with(|| FailoverTarget {
+                provider: provider.to_string(),
+                success_rate: candidate.success_rate(),
+                ..Default::default()
+            });
+            target.runs += 1;
+            target.gpu_runs += u64::from(run.has_gpu);
+            target.original_cost += original_cost;
+            target.rerouted_cost += rerouted_cost;
+        }
+
+        if (runs.len() as i64) < PAGE_SIZE {
+            break;
+        }
+    }
+
+    let mut targets: Vec<FailoverTarget> = targets.into_values().collect();
+    for target in &mut targets {
+        target.cost_delta = target.rerouted_cost - target.original_cost;
+        report.rerouted_runs += target.runs;
+        report.original_cost += target.original_cost;
+        report.rerouted_cost += target.rerouted_cost;
+    }
+    targets.sort_by(|a, b| b.runs.cmp(&a.runs).then_with(|| a.provider.cmp(&b.provider)));
+    report.targets = targets;
+    report.cost_delta = report.rerouted_cost - report.original_cost;
+    Ok(report)
+}
+
+/// The candidate that would have run a run cheapest, and at what cost: the
+/// catalog's estimate at its rates, or its average for that kind of run
+/// when it has none. Ties go to the busier provider.
+fn cheapest<'a>(
+    candidates: &'a HashMap<String, Candidate>,
+    catalog: &PriceCatalog,
+    run: &SandboxRun,
+) -> Option<(&'a str, &'a Candidate, f64)> {
+    candidates
+        .iter()
+        .filter_map(|(provider, candidate)| {
+            let average = candidate.avg_cost[usize::from(run.has_gpu)]?;
+            let moved = SandboxRun {
+                provider: provider.clone(),
+                ..run.clone()
+            };
+            let cost = catalog.estimate(&moved).unwrap_or(average);
+            Some((provider.as_str(), candidate, cost))
+        })
+        .min_by(|(a, a_candidate, a_cost), (b, b_candidate, b_cost)| {
+            a_cost
+                .total_cmp(b_cost)
+                .then_with(|| b_candidate.runs.cmp(&a_candidate.runs))
+                .then_with(|| a.cmp(b))
+        })
+}
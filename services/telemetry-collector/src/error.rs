@@ -3,6 +3,7 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
+use sandstorm_http::DependencyFailed;
 use serde_json::json;
 use thiserror::Error;
 
@@ -11,28 +12,57 @@ pub enum AppError {
     #[error("Database error: {0}")]
     Database(#[from] sqlx::Error),
     
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+    
     #[error("Validation error: {0}")]
     Validation(String),
     
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    
     #[error("Not found: {0}")]
     NotFound(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
     
     #[error("Internal server error: {0}")]
     Internal(String),
 }
 
+/// Whether a database error means Postgres couldn't be reached, rather than
+/// that a query was wrong
+fn unreachable(e: &sqlx::Error) -> bool {
+    matches!(
+        e,
+        sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed
+    )
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let database_down = matches!(&self, AppError::Database(e) if unreachable(e));
         let (status, error_message) = match self {
             AppError::Database(e) => {
                 tracing::error!("Database error: {:?}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, "Database error occurred")
+                (StatusCode::INTERNAL_SERVER_ERROR, "Database error occurred".to_string())
+            }
+            AppError::Serialization(e) => {
+                tracing::error!("Serialization error: {:?}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Serialization error occurred".to_string())
             }
-            AppError::Validation(msg) => (StatusCode::BAD_REQUEST, msg.as_str()),
-            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg.as_str()),
+            AppError::Validation(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
+            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            AppError::Conflict(msg) => (StatusCode::CONFLICT, msg),
             AppError::Internal(msg) => {
                 tracing::error!("Internal error: {}", msg);
-                (StatusCode::INTERNAL_SERVER_ERROR, msg.as_str())
+                (StatusCode::INTERNAL_SERVER_ERROR, msg)
             }
         };
 
@@ -40,7 +70,12 @@ impl IntoResponse for AppError {
             "error": error_message,
         }));
 
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        if database_down {
+            // Counts against the database breaker in `shed_load`
+            response.extensions_mut().insert(DependencyFailed);
+        }
+        response
     }
 }
 
@@ -0,0 +1,344 @@
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use sqlx::FromRow;
+
+use crate::db::Database;
+use crate::error::{AppError, AppResult};
+use crate::models::{Forecast, ForecastMetric, ForecastPoint};
+
+/// Hours of history needed to fit even the daily pattern
+const MIN_HISTORY_HOURS: usize = 48;
+const DAY_HOURS: usize = 24;
+const WEEK_HOURS: usize = 7 * DAY_HOURS;
+
+#[derive(Debug, Clone)]
+pub struct ForecastParams {
+    pub metric: ForecastMetric,
+    /// Only sandboxes of this provider; not for edge slots, which cover
+    /// the whole fleet
+    pub provider: Option<String>,
+    pub horizon_hours: usize,
+    pub history_days: i64,
+    pub confidence: f64,
+    /// Capacity to compare the forecast against, in the metric's units
+    pub capacity: Option<f64>,
+}
+
+#[derive(Debug, FromRow)]
+struct HourlyValue {
+    hour: DateTime<Utc>,
+    value: Option<f64>,
+}
+
+/// Project a metric's hourly series over the horizon from the last
+/// `history_days` of complete hours
+pub async fn forecast(db: &Database, params: &ForecastParams) -> AppResult<Forecast> {
+    let history_end = Utc::now()
+        .duration_trunc(Duration::hours(1))
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let history_start = history_end - Duration::days(params.history_days);
+    let history = hourly_series(db, params, history_start, history_end).await?;
+    project(params, history, history_start, history_end)
+}
+
+/// Fit the hourly history from `history_start` up to `history_end` and
+/// project it past the end
+fn project(
+    params: &ForecastParams,
+    mut history: Vec<f64>,
+    mut history_start: DateTime<Utc>,
+    history_end: DateTime<Utc>,
+) -> AppResult<Forecast> {
+    // Hours before the first activity predate the data rather than show
+    // demand, and would drag the trend down
+    let first_active = history.iter().position(|value| *value > 0.0).unwrap_or(0);
+    history.drain(..first_active);
+    history_start += Duration::hours(first_active as i64);
+
+    if history.iter().filter(|value| **value > 0.0).count() < MIN_HISTORY_HOURS {
+        return Err(AppError::Validation(format!(
+            "forecasting needs {} hours with activity in the last {} days",
+            MIN_HISTORY_HOURS, params.history_days
+        )));
+    }
+
+    // A weekly pattern needs two weeks to tell it from noise
+    let season = if history.len() >= 2 * WEEK_HOURS {
+        WEEK_HOURS
+    } else {
+        DAY_HOURS
+    };
+    let model = SeasonalModel::fit(&history, season);
+    let z = z_score(params.confidence);
+
+    let points: Vec<ForecastPoint> = (0..params.horizon_hours)
+        .map(|ahead| {
+            let value = model.predict(history.len() + ahead).max(0.0);
+            // Uncertainty grows with each season projected past the history
+            let spread = z * model.residual_sd * (1.0 + ahead as f64 / season as f64).sqrt();
+            ForecastPoint {
+                timestamp: history_end + Duration::hours(ahead as i64),
+                value,
+                lower: (value - spread).max(0.0),
+                upper: value + spread,
+            }
+        })
+        .collect();
+    let peak = points
+        .iter()
+        .max_by(|a, b| a.value.total_cmp(&b.value))
+        .cloned();
+    let capacity_exceeded_at = params.capacity.and_then(|capacity| {
+        points
+            .iter()
+            .find(|point| point.upper > capacity)
+            .map(|point| point.timestamp)
+    });
+
+    Ok(Forecast {
+        metric: params.metric,
+        provider: params.provider.clone(),
+        season_hours: season,
+        history_start,
+        history_end,
+        confidence: params.confidence,
+        points,
+        peak,
+        capacity_exceeded_at,
+    })
+}
+
+/// One value per hour from `start` to `end`, zero for hours without data
+async fn hourly_series(
+    db: &Database,
+    params: &ForecastParams,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> AppResult<Vec<f64>> {
+    let query = match params.metric {
+        ForecastMetric::Sandboxes => {
+            r#"
+            SELECT date_trunc('hour', created_at) AS hour, COUNT(*)::FLOAT8 AS value
+            FROM sandbox_runs
+            WHERE created_at >= $1 AND created_at < $2
+              AND ($3::TEXT IS NULL OR provider = $3)
+            GROUP BY 1
+            "#
+        }
+        // Sandbox-hours started in each hour, counted in full against the
+        // hour the run started
+        ForecastMetric::Concurrency => {
+            r#"
+            SELECT date_trunc('hour', created_at) AS hour,
+                   SUM(duration_ms)::FLOAT8 / 3600000.0 AS value
+            FROM sandbox_runs
+            WHERE created_at >= $1 AND created_at < $2
+              AND ($3::TEXT IS NULL OR provider = $3)
+            GROUP BY 1
+            "#
+        }
+        ForecastMetric::EdgeSlots => {
+            r#"
+            SELECT hour, SUM(slots) AS value
+            FROM (
+                SELECT date_trunc('hour', recorded_at) AS hour,
+                       agent_id,
+                       AVG(COALESCE((payload->>'running')::FLOAT8, 0)
+                           + COALESCE((payload->>'queueDepth')::FLOAT8, 0)) AS slots
+                FROM edge_agent_metrics
+                WHERE recorded_at >= $1 AND recorded_at < $2
+                GROUP BY 1, 2
+            ) per_agent
+            GROUP BY hour
+            "#
+        }
+    };
+    let mut query = sqlx::query_as::<_, HourlyValue>(query).bind(start).bind(end);
+    if params.metric != ForecastMetric::EdgeSlots {
+        query = query.bind(params.provider.as_deref());
+    }
+    let rows = query.fetch_all(db.pool()).await?;
+
+    let mut series = vec![0.0; (end - start).num_hours().max(0) as usize];
+    for row in rows {
+        let index = (row.hour - start).num_hours();
+        if let Some(slot) = usize::try_from(index).ok().and_then(|i| series.get_mut(i)) {
+            *slot = row.value.unwrap_or(0.0);
+        }
+    }
+    Ok(series)
+}
+
+/// Linear trend plus a repeating seasonal offset, fitted by least squares
+#[derive(Debug)]
+struct SeasonalModel {
+    intercept: f64,
+    slope: f64,
+    seasonal: Vec<f64>,
+    residual_sd: f64,
+}
+
+impl SeasonalModel {
+    fn fit(history: &[f64], season: usize) -> Self {
+        let (intercept, slope) = linear_fit(history.iter().copied().enumerate());
+
+        // Average offset from the trend at each position in the season,
+        // centred so the offsets don't shift the trend
+        let mut sums = vec![0.0; season];
+        let mut counts = vec![0usize; season];
+        for (t, value) in history.iter().enumerate() {
+            sums[t % season] += value - (intercept + slope * t as f64);
+            counts[t % season] += 1;
+        }
+        let mut seasonal: Vec<f64> = sums
+            .iter()
+            .zip(&counts)
+            .map(|(sum, count)| if *count == 0 { 0.0 } else { sum / *count as f64 })
+            .collect();
+        let mean = seasonal.iter().sum::<f64>() / season as f64;
+        seasonal.iter_mut().for_each(|offset| *offset -= mean);
+
+        // Refit the trend without the seasonal swings
+        let (intercept, slope) = linear_fit(
+            history
+                .iter()
+                .enumerate()
+                .map(|(t, value)| (t, value - seasonal[t % season])),
+        );
+        let mut model = Self {
+            intercept,
+            slope,
+            seasonal,
+            residual_sd: 0.0,
+        };
+
+        let squared: f64 = history
+            .iter()
+            .enumerate()
+            .map(|(t, value)| (value - model.predict(t)).powi(2))
+            .sum();
+        let degrees_of_freedom = history.len().saturating_sub(season + 1).max(1);
+        model.residual_sd = (squared / degrees_of_freedom as f64).sqrt();
+        model
+    }
+
+    fn predict(&self, t: usize) -> f64 {
+        self.intercept + self.slope * t as f64 + self.seasonal[t % self.seasonal.len()]
+    }
+}
+
+/// Intercept and slope of the least-squares line through the points
+fn linear_fit(points: impl Iterator<Item = (usize, f64)> + Clone) -> (f64, f64) {
+    let n = points.clone().count() as f64;
+    if n == 0.0 {
+        return (0.0, 0.0);
+    }
+    let mean_t = points.clone().map(|(t, _)| t as f64).sum::<f64>() / n;
+    let mean_y = points.clone().map(|(_, y)| y).sum::<f64>() / n;
+    let (covariance, variance) = points.fold((0.0, 0.0), |(cov, var), (t, y)| {
+        let dt = t as f64 - mean_t;
+        (cov + dt * (y - mean_y), var + dt * dt)
+    });
+    let slope = if variance == 0.0 { 0.0 } else { covariance / variance };
+    (mean_y - slope * mean_t, slope)
+}
+
+/// Half-width of a two-sided normal interval with this coverage, in standard
+/// deviations (Abramowitz and Stegun 26.2.23, accurate to 5e-4)
+fn z_score(confidence: f64) -> f64 {
+    let tail = (1.0 - confidence) / 2.0;
+    let t = (-2.0 * tail.ln()).sqrt();
+    t - (2.515517 + 0.802853 * t + 0.010328 * t * t)
+        / (1.0 + 1.432788 * t + 0.189269 * t * t + 0.001308 * t * t * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn params(capacity: Option<f64>) -> ForecastParams {
+        ForecastParams {
+            metric: ForecastMetric::Sandboxes,
+            provider: None,
+            horizon_hours: 24,
+            history_days: 14,
+            confidence: 0.95,
+            capacity,
+        }
+    }
+
+    fn project_hours(history: Vec<f64>, capacity: Option<f64>) -> AppResult<Forecast> {
+        let end = Utc.with_ymd_and_hms(2024, 3, 15, 0, 0, 0).unwrap();
+        let start = end - Duration::hours(history.len() as i64);
+        project(&params(capacity), history, start, end)
+    }
+
+    #[test]
+    fn projects_flat_trending_and_daily_history() {
+        // (name, history, expected value per hour ahead, tolerance, season)
+        type Case = (&'static str, Vec<f64>, fn(usize) -> f64, f64, usize);
+        fn office_hours(t: usize) -> f64 {
+            if (9..17).contains(&(t % DAY_HOURS)) { 20.0 } else { 5.0 }
+        }
+        let cases: [Case; 4] = [
+            ("flat", vec![10.0; 72], |_| 10.0, 1e-6, DAY_HOURS),
+            (
+                "trending",
+                (0..72).map(|t| 5.0 + 0.5 * t as f64).collect(),
+                |ahead| 41.0 + 0.5 * ahead as f64,
+                1e-6,
+                DAY_HOURS,
+            ),
+            // The trend soaks up a little of the daily swing
+            ("daily", (0..72).map(office_hours).collect(), office_hours, 0.5, DAY_HOURS),
+            ("two weeks", vec![3.0; 2 * WEEK_HOURS], |_| 3.0, 1e-6, WEEK_HOURS),
+        ];
+
+        for (name, history, expected, tolerance, season) in cases {
+            let forecast = project_hours(history, None).unwrap();
+            assert_eq!(forecast.season_hours, season, "{}", name);
+            assert_eq!(forecast.points.len(), 24, "{}", name);
+            for (ahead, point) in forecast.points.iter().enumerate() {
+                assert!((point.value - expected(ahead)).abs() < tolerance, "{} at +{}h: {}", name, ahead, point.value);
+                assert!(point.lower <= point.value && point.value <= point.upper, "{}", name);
+            }
+            assert_eq!(forecast.points[0].timestamp, forecast.history_end, "{}", name);
+        }
+
+        // An exact fit leaves no uncertainty
+        let forecast = project_hours(vec![10.0; 72], None).unwrap();
+        assert!(forecast.points.iter().all(|point| point.upper - point.lower < 1e-6));
+    }
+
+    #[test]
+    fn needs_enough_active_history() {
+        for history in [Vec::new(), vec![0.0; 200], vec![1.0; MIN_HISTORY_HOURS - 1]] {
+            assert!(matches!(project_hours(history, None), Err(AppError::Validation(_))));
+        }
+
+        // Quiet hours before the first activity aren't history
+        let mut history = vec![0.0; 100];
+        history.extend([4.0; MIN_HISTORY_HOURS]);
+        let forecast = project_hours(history, None).unwrap();
+        assert_eq!(forecast.history_end - forecast.history_start, Duration::hours(MIN_HISTORY_HOURS as i64));
+        assert!((forecast.points[0].value - 4.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn bands_widen_and_flag_capacity() {
+        let noisy: Vec<f64> = (0..96).map(|t| (8 + 4 * (t % 2) + t % 5) as f64).collect();
+        let forecast = project_hours(noisy, Some(15.0)).unwrap();
+        let widths: Vec<f64> = forecast.points.iter().map(|point| point.upper - point.lower).collect();
+        assert!(widths[0] > 0.0);
+        assert!(widths.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert!(forecast.points.iter().all(|point| point.lower >= 0.0 && point.lower <= point.value));
+
+        let exceeded = forecast.points.iter().find(|point| point.upper > 15.0).map(|point| point.timestamp);
+        assert!(exceeded.is_some());
+        assert_eq!(forecast.capacity_exceeded_at, exceeded);
+        assert!(forecast.peak.is_some());
+
+        assert!((z_score(0.95) - 1.96).abs() < 1e-3);
+        assert!((z_score(0.8) - 1.2816).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,521 @@
+//! GraphQL API for dashboards at `/api/graphql`. One schema covers sandbox
+//! runs, provider stats, model predictions and edge agents, so a dashboard
+//! panel asks for the fields it shows instead of the collector growing a
+//! REST aggregation endpoint per panel. Lists are Relay connections, newest
+//! first (agents by ID), paged forward with `first` and an opaque `after`
+//! cursor.
+
+use async_graphql::{
+    connection::{self, Connection, Edge, OpaqueCursor},
+    Context, EmptyMutation, EmptySubscription, Object, SimpleObject,
+};
+use axum::{extract::State, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    db::Database,
+    error::AppError,
+    handlers,
+    models::{AcceleratorStats, EdgeAgentOverview, EdgeAgentRunSummary, Prediction, SandboxRun},
+    AppState,
+};
+
+/// Page size when a query doesn't give `first`
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+/// Deepest selection a query may nest
+const MAX_DEPTH: usize = 10;
+
+pub type DashboardSchema = async_graphql::Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+pub fn schema(db: Database) -> DashboardSchema {
+    async_graphql::Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(db)
+        .limit_depth(MAX_DEPTH)
+        .finish()
+}
+
+pub async fn execute(
+    State(state): State<AppState>,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    Json(state.graphql.execute(request).await)
+}
+
+/// The schema in SDL, for client code generation
+pub async fn sdl(State(state): State<AppState>) -> String {
+    state.graphql.sdl()
+}
+
+/// Position of a row in a newest-first page
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PageKey {
+    created_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+type Page<Node> = Connection<OpaqueCursor<PageKey>, Node>;
+
+/// Rows in a page of `first`, at most [`MAX_PAGE_SIZE`]
+fn page_size(first: Option<usize>) -> usize {
+    first.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE)
+}
+
+/// Turn a page's rows, fetched one past `size`, into a connection
+fn paginate<Row, Node>(
+    mut rows: Vec<Row>,
+    size: usize,
+    has_previous_page: bool,
+    key: impl Fn(&Row) -> PageKey,
+) -> Page<Node>
+where
+    Node: From<Row> + async_graphql::OutputType,
+{
+    let has_next_page = rows.len() > size;
+    rows.truncate(size);
+    let mut page = Connection::new(has_previous_page, has_next_page);
+    page.edges = rows
+        .into_iter()
+        .map(|row| Edge::new(OpaqueCursor(key(&row)), Node::from(row)))
+        .collect();
+    page
+}
+
+/// Internal errors are logged and reported to clients without detail, as
+/// the REST endpoints do
+fn query_error(error: AppError) -> async_graphql::Error {
+    match error {
+        AppError::Validation(message) | AppError::NotFound(message) => message.into(),
+        error => {
+            tracing::error!("GraphQL query failed: {}", error);
+            "internal error".into()
+        }
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Recorded sandbox runs, newest first
+    #[allow(clippy::too_many_arguments)]
+    async fn runs(
+        &self,
+        ctx: &Context<'_>,
+        provider: Option<String>,
+        tenant: Option<String>,
+        sandbox_id: Option<String>,
+        run_id: Option<Uuid>,
+        after: Option<String>,
+        first: Option<i32>,
+    ) -> async_graphql::Result<Page<Run>> {
+        let db = ctx.data::<Database>()?;
+        connection::query(after, None, first, None, |after, _, first, _| async move {
+            let after: Option<OpaqueCursor<PageKey>> = after;
+            let size = page_size(first);
+            let rows = sqlx::query_as!(
+                SandboxRun,
+                r#"
+                SELECT * FROM sandbox_runs
+                WHERE ($1::TEXT IS NULL OR provider = $1)
+                  AND ($2::TEXT IS NULL OR tenant = $2)
+                  AND ($3::TEXT IS NULL OR sandbox_id = $3)
+                  AND ($4::UUID IS NULL OR run_id = $4)
+                  AND ($5::TIMESTAMPTZ IS NULL OR (created_at, id) < ($5, $6::UUID))
+                ORDER BY created_at DESC, id DESC
+                LIMIT $7
+                "#,
+                provider,
+                tenant,
+                sandbox_id,
+                run_id,
+                after.as_ref().map(|key| key.created_at),
+                after.as_ref().map(|key| key.id),
+                size as i64 + 1
+            )
+            .fetch_all(db.pool())
+            .await
+            .map_err(|e| query_error(e.into()))?;
+            Ok::<_, async_graphql::Error>(paginate(rows, size, after.is_some(), |run: &SandboxRun| {
+                PageKey {
+                    created_at: run.created_at,
+                    id: run.id,
+                }
+            }))
+        })
+        .await
+    }
+
+    /// A provider's run statistics between `start` and `end` (default
+    /// now). The accelerator breakdown is only computed when selected.
+    async fn provider_stats(
+        &self,
+        ctx: &Context<'_>,
+        provider: String,
+        start: DateTime<Utc>,
+        end: Option<DateTime<Utc>>,
+    ) -> async_graphql::Result<ProviderStats> {
+        let db = ctx.data::<Database>()?;
+        let by_accelerator = ctx.look_ahead().field("byAccelerator").exists();
+        let end = end.unwrap_or_else(Utc::now);
+        let stats = handlers::telemetry::provider_stats(db, &provider, start, end, by_accelerator)
+            .await
+            .map_err(query_error)?;
+        Ok(ProviderStats(stats))
+    }
+
+    /// Routing model predictions, newest first
+    async fn predictions(
+        &self,
+        ctx: &Context<'_>,
+        model_version: Option<String>,
+        provider: Option<String>,
+        after: Option<String>,
+        first: Option<i32>,
+    ) -> async_graphql::Result<Page<PredictionNode>> {
+        let db = ctx.data::<Database>()?;
+        connection::query(after, None, first, None, |after, _, first, _| async move {
+            let after: Option<OpaqueCursor<PageKey>> = after;
+            let size = page_size(first);
+            let rows = sqlx::query_as!(
+                Prediction,
+                r#"
+                SELECT id, provider, predicted_cost, predicted_latency, confidence,
+                       model_version, actual_cost, actual_latency, actual_success, created_at
+                FROM predictions
+                WHERE ($1::TEXT IS NULL OR model_version = $1)
+                  AND ($2::TEXT IS NULL OR provider = $2)
+                  AND ($3::TIMESTAMPTZ IS NULL OR (created_at, id) < ($3, $4::UUID))
+                ORDER BY created_at DESC, id DESC
+                LIMIT $5
+                "#,
+                model_version,
+                provider,
+                after.as_ref().map(|key| key.created_at),
+                after.as_ref().map(|key| key.id),
+                size as i64 + 1
+            )
+            .fetch_all(db.pool())
+            .await
+            .map_err(|e| query_error(e.into()))?;
+            Ok::<_, async_graphql::Error>(paginate(
+                rows,
+                size,
+                after.is_some(),
+                |prediction: &Prediction| PageKey {
+                    created_at: prediction.created_at,
+                    id: prediction.id,
+                },
+            ))
+        })
+        .await
+    }
+
+    /// Edge agents from their latest heartbeat, by agent ID
+    async fn edge_agents(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        first: Option<i32>,
+    ) -> async_graphql::Result<Connection<OpaqueCursor<String>, EdgeAgent>> {
+        let db = ctx.data::<Database>()?;
+        connection::query(after, None, first, None, |after, _, first, _| async move {
+            let after: Option<OpaqueCursor<String>> = after;
+            let size = page_size(first);
+            let mut agents = handlers::edge::agent_overviews(
+                db,
+                after.as_ref().map(|id| id.as_str()),
+                Some(size as i64 + 1),
+            )
+            .await
+            .map_err(query_error)?;
+            let has_next_page = agents.len() > size;
+            agents.truncate(size);
+            let mut page = Connection::new(after.is_some(), has_next_page);
+            page.edges = agents
+                .into_iter()
+                .map(|agent| Edge::new(OpaqueCursor(agent.agent_id.clone()), EdgeAgent(agent)))
+                .collect();
+            Ok::<_, async_graphql::Error>(page)
+        })
+        .await
+    }
+}
+
+/// A recorded sandbox run
+#[derive(SimpleObject)]
+pub struct Run {
+    id: Uuid,
+    sandbox_id: String,
+    run_id: Option<Uuid>,
+    tenant: String,
+    provider: String,
+    language: String,
+    agent_id: Option<String>,
+    exit_code: i32,
+    success: bool,
+    /// `oom`, `timeout`, `nonzero_exit`, `provider_error` or
+    /// `infra_error`; unset for successful runs
+    failure_class: Option<String>,
+    duration_ms: i64,
+    queued_ms: Option<i64>,
+    provision_ms: Option<i64>,
+    exec_ms: Option<i64>,
+    teardown_ms: Option<i64>,
+    timeout_ms: Option<i64>,
+    /// What the reporter claimed the run cost
+    cost: f64,
+    /// Price from the rate catalog, when the provider had rates
+    estimated_cost: Option<f64>,
+    cpu_requested: Option<f64>,
+    memory_requested: Option<i32>,
+    cpu_percent: Option<f64>,
+    memory_mb: Option<f64>,
+    network_rx_bytes: Option<i64>,
+    network_tx_bytes: Option<i64>,
+    has_gpu: bool,
+    gpu_type: Option<String>,
+    gpu_count: Option<i32>,
+    gpu_utilization_percent: Option<f64>,
+    gpu_memory_used_mb: Option<f64>,
+    gpu_seconds: Option<f64>,
+    /// Attribution labels, as an object of strings
+    labels: serde_json::Value,
+    created_at: DateTime<Utc>,
+}
+
+impl From<SandboxRun> for Run {
+    fn from(run: SandboxRun) -> Self {
+        Self {
+            id: run.id,
+            sandbox_id: run.sandbox_id,
+            run_id: run.run_id,
+            tenant: run.tenant,
+            provider: run.provider,
+            language: run.language,
+            agent_id: run.agent_id,
+            exit_code: run.exit_code,
+            success: run.success,
+            failure_class: run.failure_class,
+            duration_ms: run.duration_ms,
+            queued_ms: run.queued_ms,
+            provision_ms: run.provision_ms,
+            exec_ms: run.exec_ms,
+            teardown_ms: run.teardown_ms,
+            timeout_ms: run.timeout_ms,
+            cost: run.cost,
+            estimated_cost: run.estimated_cost,
+            cpu_requested: run.cpu_requested,
+            memory_requested: run.memory_requested,
+            cpu_percent: run.cpu_percent,
+            memory_mb: run.memory_mb,
+            network_rx_bytes: run.network_rx_bytes,
+            network_tx_bytes: run.network_tx_bytes,
+            has_gpu: run.has_gpu,
+            gpu_type: run.gpu_type,
+            gpu_count: run.gpu_count,
+            gpu_utilization_percent: run.gpu_utilization_percent,
+            gpu_memory_used_mb: run.gpu_memory_used_mb,
+            gpu_seconds: run.gpu_seconds,
+            labels: run.labels,
+            created_at: run.created_at,
+        }
+    }
+}
+
+/// A routing model's prediction and, once reported, what actually happened
+#[derive(SimpleObject)]
+#[graphql(name = "Prediction")]
+pub struct PredictionNode {
+    id: Uuid,
+    provider: String,
+    model_version: String,
+    predicted_cost: f64,
+    predicted_latency: f64,
+    confidence: f64,
+    actual_cost: Option<f64>,
+    actual_latency: Option<f64>,
+    actual_success: Option<bool>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<Prediction> for PredictionNode {
+    fn from(prediction: Prediction) -> Self {
+        Self {
+            id: prediction.id,
+            provider: prediction.provider,
+            model_version: prediction.model_version,
+            predicted_cost: prediction.predicted_cost,
+            predicted_latency: prediction.predicted_latency,
+            confidence: prediction.confidence,
+            actual_cost: prediction.actual_cost,
+            actual_latency: prediction.actual_latency,
+            actual_success: prediction.actual_success,
+            created_at: prediction.created_at,
+        }
+    }
+}
+
+pub struct ProviderStats(crate::models::ProviderStats);
+
+#[Object]
+impl ProviderStats {
+    /// Mean run duration in milliseconds
+    async fn avg_latency(&self) -> f64 {
+        self.0.avg_latency
+    }
+
+    async fn avg_cost(&self) -> f64 {
+        self.0.avg_cost
+    }
+
+    /// Fraction of runs that succeeded, from 0.0 to 1.0
+    async fn success_rate(&self) -> f64 {
+        self.0.success_rate
+    }
+
+    async fn total_runs(&self) -> i64 {
+        self.0.total_runs
+    }
+
+    /// Fraction of runs whose sandbox had security incidents
+    async fn security_incident_rate(&self) -> f64 {
+        self.0.security_incident_rate
+    }
+
+    async fn by_accelerator(&self) -> Vec<Accelerator> {
+        self.0.by_accelerator.iter().cloned().map(Accelerator).collect()
+    }
+}
+
+/// Provider stats for runs on one accelerator type
+pub struct Accelerator(AcceleratorStats);
+
+#[Object]
+impl Accelerator {
+    /// GPU type, or "none" for CPU-only runs
+    async fn accelerator(&self) -> &str {
+        &self.0.accelerator
+    }
+
+    async fn avg_latency(&self) -> f64 {
+        self.0.avg_latency
+    }
+
+    async fn avg_cost(&self) -> f64 {
+        self.0.avg_cost
+    }
+
+    async fn success_rate(&self) -> f64 {
+        self.0.success_rate
+    }
+
+    async fn total_runs(&self) -> i64 {
+        self.0.total_runs
+    }
+
+    async fn avg_gpu_utilization_percent(&self) -> Option<f64> {
+        self.0.avg_gpu_utilization_percent
+    }
+
+    async fn avg_gpu_memory_used_mb(&self) -> Option<f64> {
+        self.0.avg_gpu_memory_used_mb
+    }
+
+    async fn total_gpu_seconds(&self) -> f64 {
+        self.0.total_gpu_seconds
+    }
+}
+
+pub struct EdgeAgent(EdgeAgentOverview);
+
+#[Object]
+impl EdgeAgent {
+    async fn agent_id(&self) -> &str {
+        &self.0.agent_id
+    }
+
+    async fn agent_name(&self) -> Option<&str> {
+        self.0.agent_name.as_deref()
+    }
+
+    async fn status(&self) -> &str {
+        &self.0.status
+    }
+
+    async fn version(&self) -> &str {
+        &self.0.version
+    }
+
+    async fn queue_depth(&self) -> i32 {
+        self.0.queue_depth
+    }
+
+    async fn running(&self) -> i32 {
+        self.0.running
+    }
+
+    async fn completed(&self) -> i32 {
+        self.0.completed
+    }
+
+    async fn failed(&self) -> i32 {
+        self.0.failed
+    }
+
+    async fn cpu_percent(&self) -> Option<f64> {
+        self.0.cpu_percent
+    }
+
+    async fn memory_percent(&self) -> Option<f64> {
+        self.0.memory_percent
+    }
+
+    async fn last_heartbeat(&self) -> DateTime<Utc> {
+        self.0.last_heartbeat
+    }
+
+    async fn public_endpoint(&self) -> Option<&str> {
+        self.0.public_endpoint.as_deref()
+    }
+
+    /// The agent's most recent run
+    async fn latest_run(&self) -> Option<EdgeRun> {
+        self.0.sandbox_run.as_ref().map(EdgeRun::from)
+    }
+}
+
+/// A run on an edge agent
+#[derive(SimpleObject)]
+pub struct EdgeRun {
+    sandbox_id: String,
+    provider: String,
+    language: String,
+    duration_ms: i64,
+    exit_code: i32,
+    cpu_percent: Option<f64>,
+    memory_mb: Option<f64>,
+    network_rx_bytes: Option<i64>,
+    network_tx_bytes: Option<i64>,
+    finished_at: DateTime<Utc>,
+}
+
+impl From<&EdgeAgentRunSummary> for EdgeRun {
+    fn from(run: &EdgeAgentRunSummary) -> Self {
+        Self {
+            sandbox_id: run.sandbox_id.clone(),
+            provider: run.provider.clone(),
+            language: run.language.clone(),
+            duration_ms: run.duration_ms,
+            exit_code: run.exit_code,
+            cpu_percent: run.cpu_percent,
+            memory_mb: run.memory_mb,
+            network_rx_bytes: run.network_rx_bytes,
+            network_tx_bytes: run.network_tx_bytes,
+            finished_at: run.finished_at,
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::telemetry::{enum_name, parse_enum};
+use crate::{
+    error::{AppError, AppResult},
+    models::*,
+    AppState,
+};
+
+#[derive(Deserialize)]
+pub struct BenchmarksQuery {
+    benchmark_id: Option<Uuid>,
+    workload: Option<String>,
+    runtime: Option<String>,
+    since: Option<DateTime<Utc>>,
+    limit: Option<i64>,
+}
+
+/// Record the results of a gateway benchmark run
+pub async fn store_benchmark_results(
+    State(state): State<AppState>,
+    Json(results): Json<Vec<BenchmarkResult>>,
+) -> AppResult<StatusCode> {
+    if results.is_empty() {
+        return Err(AppError::Validation("no benchmark results".to_string()));
+    }
+
+    let mut tx = state.db.pool().begin().await?;
+    for result in &results {
+        sqlx::query!(
+            r#"
+            INSERT INTO benchmark_results (
+                benchmark_id, runtime, isolation_level, workload, iterations, failures,
+                cold_start_ms, exec_ms, execs_per_sec, error, created_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            "#,
+            result.benchmark_id,
+            enum_name(&result.runtime)?,
+            enum_name(&result.isolation_level)?,
+            result.workload,
+            result.iterations as i32,
+            result.failures as i32,
+            result.cold_start_ms.map(serde_json::to_value).transpose()?,
+            result.exec_ms.map(serde_json::to_value).transpose()?,
+            result.execs_per_sec,
+            result.error,
+            result.created_at
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(StatusCode::CREATED)
+}
+
+async fn benchmark_results(
+    state: &AppState,
+    query: &BenchmarksQuery,
+    limit: i64,
+) -> AppResult<Vec<BenchmarkResult>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT benchmark_id, runtime, isolation_level, workload, iterations, failures,
+               cold_start_ms, exec_ms, execs_per_sec, error, created_at
+        FROM benchmark_results
+        WHERE ($1::UUID IS NULL OR benchmark_id = $1)
+          AND ($2::TEXT IS NULL OR workload = $2)
+          AND ($3::TEXT IS NULL OR runtime = $3)
+          AND ($4::TIMESTAMPTZ IS NULL OR created_at >= $4)
+        ORDER BY created_at DESC, id DESC
+        LIMIT $5
+        "#,
+        query.benchmark_id,
+        query.workload,
+        query.runtime,
+        query.since,
+        limit
+    )
+    .fetch_all(state.db.pool())
+    .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(BenchmarkResult {
+                benchmark_id: row.benchmark_id,
+                runtime: parse_enum(row.runtime)?,
+                isolation_level: parse_enum(row.isolation_level)?,
+                workload: row.workload,
+                iterations: row.iterations.max(0) as u32,
+                failures: row.failures.max(0) as u32,
+                cold_start_ms: row.cold_start_ms.map(serde_json::from_value).transpose()?,
+                exec_ms: row.exec_ms.map(serde_json::from_value).transpose()?,
+                execs_per_sec: row.execs_per_sec,
+                error: row.error,
+                created_at: row.created_at,
+            })
+        })
+        .collect()
+}
+
+/// Stored benchmark results, newest first
+pub async fn list_benchmark_results(
+    State(state): State<AppState>,
+    Query(query): Query<BenchmarksQuery>,
+) -> AppResult<Json<Vec<BenchmarkResult>>> {
+    let limit = query.limit.unwrap_or(100).min(1000);
+    Ok(Json(benchmark_results(&state, &query, limit).await?))
+}
+
+/// Runtimes and isolation levels ranked per workload, averaging every
+/// matching result
+pub async fn compare_benchmarks(
+    State(state): State<AppState>,
+    Query(query): Query<BenchmarksQuery>,
+) -> AppResult<Json<Vec<BenchmarkComparison>>> {
+    let limit = query.limit.unwrap_or(1000).min(10_000);
+    let results = benchmark_results(&state, &query, limit).await?;
+    Ok(Json(BenchmarkComparison::from_results(&results)))
+}
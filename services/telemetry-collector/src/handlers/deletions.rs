@@ -0,0 +1,96 @@
+use axum::{extract::State, http::HeaderMap, Json};
+use chrono::Utc;
+use sandstorm_types::deletion::{DeletionRequest, ServiceDeletion};
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::info;
+
+use crate::{
+    error::{AppError, AppResult},
+    AppState,
+};
+
+/// Tables recording sandboxes, with the column naming the sandbox
+const SANDBOX_TABLES: &[(&str, &str)] = &[
+    ("sandbox_runs", "sandbox_id"),
+    ("edge_agent_runs", "sandbox_id"),
+    ("preemption_events", "sandbox_id"),
+    ("security_signals", "sandbox_id"),
+    ("service_logs", "sandbox_id"),
+];
+
+/// Tables keyed by tenant
+const TENANT_TABLES: &[&str] = &["usage_reports", "tenant_quotas"];
+
+/// Delete everything recorded for a tenant or about some sandboxes: the
+/// tenant's runs, usage reports and quota, and the runs, edge runs,
+/// preemptions, security signals and logs of its sandboxes and the
+/// request's. Requires the admin token.
+pub async fn delete_data(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<DeletionRequest>,
+) -> AppResult<Json<ServiceDeletion>> {
+    let admin_token = state.config.current().admin_token.clone();
+    let presented = headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if admin_token.is_none() || presented != admin_token.as_deref() {
+        return Err(AppError::Unauthorized(
+            "deleting data requires the admin token".to_string(),
+        ));
+    }
+    if request.is_empty() {
+        return Err(AppError::Validation(
+            "a deletion needs a tenant or sandbox_ids".to_string(),
+        ));
+    }
+
+    let mut tx = state.db.pool().begin().await?;
+    let mut sandbox_ids: BTreeSet<String> = request.sandbox_ids.iter().cloned().collect();
+    if let Some(tenant) = &request.tenant {
+        let tenant_sandboxes: Vec<String> =
+            sqlx::query_scalar("SELECT DISTINCT sandbox_id FROM sandbox_runs WHERE tenant = $1")
+                .bind(tenant)
+                .fetch_all(&mut *tx)
+                .await?;
+        sandbox_ids.extend(tenant_sandboxes);
+    }
+    let sandbox_ids: Vec<String> = sandbox_ids.into_iter().collect();
+
+    let mut removed = BTreeMap::new();
+    for (table, column) in SANDBOX_TABLES {
+        let deleted = sqlx::query(&format!(
+            "DELETE FROM {} WHERE {}::text = ANY($1)",
+            table, column
+        ))
+        .bind(&sandbox_ids)
+        .execute(&mut *tx)
+        .await?;
+        removed.insert(table.to_string(), deleted.rows_affected());
+    }
+    if let Some(tenant) = &request.tenant {
+        for table in TENANT_TABLES {
+            let deleted = sqlx::query(&format!("DELETE FROM {} WHERE tenant = $1", table))
+                .bind(tenant)
+                .execute(&mut *tx)
+                .await?;
+            removed.insert(table.to_string(), deleted.rows_affected());
+        }
+    }
+    tx.commit().await?;
+
+    info!(
+        tenant = ?request.tenant,
+        sandboxes = sandbox_ids.len(),
+        removed = ?removed,
+        "Deleted data on request"
+    );
+    Ok(Json(ServiceDeletion {
+        service: "telemetry-collector".to_string(),
+        removed,
+        retained: Vec::new(),
+        sandbox_ids,
+        completed_at: Utc::now(),
+    }))
+}
@@ -0,0 +1,48 @@
+use axum::{extract::State, Json};
+use chrono::Utc;
+
+use crate::{
+    drills,
+    error::{AppError, AppResult},
+    models::*,
+    AppState,
+};
+
+/// Rehearse a provider outage: where the provider's runs during the window
+/// would have been rerouted, and the cost difference
+pub async fn failover_drill(
+    State(state): State<AppState>,
+    Json(request): Json<FailoverDrillRequest>,
+) -> AppResult<Json<FailoverDrillReport>> {
+    if request.provider.is_empty() {
+        return Err(AppError::Validation("provider is required".to_string()));
+    }
+    let end = request.end.unwrap_or_else(Utc::now);
+    if end <= request.start {
+        return Err(AppError::Validation("end must be after start".to_string()));
+    }
+    if request.lookback_days.is_some_and(|days| !(1..=90).contains(&days)) {
+        return Err(AppError::Validation(
+            "lookback_days must be between 1 and 90".to_string(),
+        ));
+    }
+    if request
+        .min_success_rate
+        .is_some_and(|rate| !(0.0..=1.0).contains(&rate))
+    {
+        return Err(AppError::Validation(
+            "min_success_rate must be between 0 and 1".to_string(),
+        ));
+    }
+
+    let report = drills::failover(&state.db, &request, end).await?;
+    tracing::info!(
+        provider = %report.provider,
+        runs = report.runs,
+        rerouted = report.rerouted_runs,
+        unroutable = report.unroutable_runs,
+        cost_delta = report.cost_delta,
+        "Failover drill finished"
+    );
+    Ok(Json(report))
+}
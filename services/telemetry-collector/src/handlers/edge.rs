@@ -1,6 +1,6 @@
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     Json,
 };
 use chrono::{DateTime, Utc};
@@ -11,11 +11,14 @@ use tracing::{debug, info, warn};
 use uuid::Uuid;
 
 use crate::{
+    anomaly::{self, DetectionParams},
+    db::Database,
+    delivery::{self, Delivery, Stream},
     error::AppResult,
     models::{
-        EdgeAgentMetricsDto, EdgeAgentOverview, EdgeAgentRunRecord, EdgeAgentRunSummary,
-        EdgeAgentStatusDto, EdgeAgentStatusRecord, EdgeLogBatchRequest, EdgeMetricsBatchRequest,
-        EdgeStatusBatchRequest,
+        EdgeAgentOverview, EdgeAgentRunRecord, EdgeAgentRunSummary, EdgeAnomaly, EdgeBatchAck,
+        EdgeCapacity, EdgeCapacityFeed, EdgeLogBatchRequest, EdgeMetricsBatchRequest,
+        EdgeStatusBatchRequest, EdgeStreamCursor,
     },
     AppState,
 };
@@ -26,10 +29,37 @@ pub struct RunsQuery {
     pub since: Option<DateTime<Utc>>,
 }
 
+/// Agents report every 30 seconds by default; one silent for three
+/// heartbeats is taken to be gone
+const DEFAULT_CAPACITY_MAX_AGE_SECS: i64 = 90;
+
+/// Agents with less CPU or memory than this left take no more runs
+const DEFAULT_MIN_HEADROOM_PERCENT: f64 = 10.0;
+
+#[derive(Debug, Deserialize)]
+pub struct CapacityQuery {
+    pub max_age_secs: Option<i64>,
+    pub min_headroom_percent: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnomalyQuery {
+    pub window_minutes: Option<i64>,
+    pub baseline_hours: Option<i64>,
+}
+
 pub async fn ingest_status(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<EdgeStatusBatchRequest>,
-) -> AppResult<StatusCode> {
+) -> AppResult<(StatusCode, Json<EdgeBatchAck>)> {
+    let delivery =
+        Delivery::from_batch(&headers, payload.session_id.as_deref(), payload.sequence)?;
+    let mut tx = state.db.pool().begin().await?;
+    if let Some(ack) = delivery::admit(&mut tx, Stream::Status, delivery.as_ref()).await? {
+        return Ok((StatusCode::OK, Json(ack)));
+    }
+
     for item in payload.items {
         let payload_json = serde_json::to_value(&item)?;
         let queue_depth = extract_number(&item.sandboxes, "queued").unwrap_or(0.0);
@@ -84,17 +114,26 @@ pub async fn ingest_status(
             public_endpoint,
             payload_json
         )
-        .execute(state.db.pool())
+        .execute(&mut *tx)
         .await?;
     }
 
-    Ok(StatusCode::ACCEPTED)
+    let ack = delivery::commit(tx, Stream::Status, delivery.as_ref()).await?;
+    Ok((StatusCode::ACCEPTED, Json(ack)))
 }
 
 pub async fn ingest_metrics(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<EdgeMetricsBatchRequest>,
-) -> AppResult<StatusCode> {
+) -> AppResult<(StatusCode, Json<EdgeBatchAck>)> {
+    let delivery =
+        Delivery::from_batch(&headers, payload.session_id.as_deref(), payload.sequence)?;
+    let mut tx = state.db.pool().begin().await?;
+    if let Some(ack) = delivery::admit(&mut tx, Stream::Metrics, delivery.as_ref()).await? {
+        return Ok((StatusCode::OK, Json(ack)));
+    }
+
     for entry in payload.items {
         let payload_json = serde_json::to_value(&entry)?;
         let cpu_percent = entry
@@ -120,7 +159,7 @@ pub async fn ingest_metrics(
             entry.timestamp,
             payload_json
         )
-        .execute(state.db.pool())
+        .execute(&mut *tx)
         .await?;
 
         sqlx::query!(
@@ -145,7 +184,7 @@ pub async fn ingest_metrics(
             memory_percent,
             entry.timestamp
         )
-        .execute(state.db.pool())
+        .execute(&mut *tx)
         .await?;
 
         if let Some(sandbox_run) = entry.sandbox_run.as_ref() {
@@ -172,7 +211,7 @@ pub async fn ingest_metrics(
                         summary.network_tx_bytes,
                         summary.finished_at
                     )
-                    .execute(state.db.pool())
+                    .execute(&mut *tx)
                     .await?;
                 }
                 Err(error) => warn!(
@@ -183,10 +222,22 @@ pub async fn ingest_metrics(
         }
     }
 
-    Ok(StatusCode::ACCEPTED)
+    let ack = delivery::commit(tx, Stream::Metrics, delivery.as_ref()).await?;
+    Ok((StatusCode::ACCEPTED, Json(ack)))
 }
 
-pub async fn ingest_logs(Json(payload): Json<EdgeLogBatchRequest>) -> AppResult<StatusCode> {
+pub async fn ingest_logs(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<EdgeLogBatchRequest>,
+) -> AppResult<(StatusCode, Json<EdgeBatchAck>)> {
+    let delivery =
+        Delivery::from_batch(&headers, payload.session_id.as_deref(), payload.sequence)?;
+    let mut tx = state.db.pool().begin().await?;
+    if let Some(ack) = delivery::admit(&mut tx, Stream::Logs, delivery.as_ref()).await? {
+        return Ok((StatusCode::OK, Json(ack)));
+    }
+
     for log in payload.items {
         match log.level.as_str() {
             "error" => {
@@ -197,10 +248,21 @@ pub async fn ingest_logs(Json(payload): Json<EdgeLogBatchRequest>) -> AppResult<
             _ => debug!(message = %log.message, context = ?log.context, "edge agent log"),
         }
     }
-    Ok(StatusCode::ACCEPTED)
+    let ack = delivery::commit(tx, Stream::Logs, delivery.as_ref()).await?;
+    Ok((StatusCode::ACCEPTED, Json(ack)))
 }
 
 pub async fn list_agents(State(state): State<AppState>) -> AppResult<Json<Vec<EdgeAgentOverview>>> {
+    Ok(Json(agent_overviews(&state.db, None, None).await?))
+}
+
+/// Agents ordered by ID with their latest run, starting after agent
+/// `after` and up to `limit` of them
+pub(crate) async fn agent_overviews(
+    db: &Database,
+    after: Option<&str>,
+    limit: Option<i64>,
+) -> AppResult<Vec<EdgeAgentOverview>> {
     let rows = sqlx::query(
         r#"
         SELECT
@@ -234,10 +296,14 @@ pub async fn list_agents(State(state): State<AppState>) -> AppResult<Json<Vec<Ed
             ORDER BY finished_at DESC
             LIMIT 1
         ) r ON TRUE
+        WHERE $1::TEXT IS NULL OR s.agent_id > $1
         ORDER BY s.agent_id
+        LIMIT $2
         "#,
     )
-    .fetch_all(state.db.pool())
+    .bind(after)
+    .bind(limit)
+    .fetch_all(db.pool())
     .await?;
 
     let mut agents = Vec::with_capacity(rows.len());
@@ -278,7 +344,100 @@ pub async fn list_agents(State(state): State<AppState>) -> AppResult<Json<Vec<Ed
         });
     }
 
-    Ok(Json(agents))
+    Ok(agents)
+}
+
+/// Each agent's load and headroom from its latest heartbeat, least loaded
+/// first, for schedulers placing runs on the edge fleet
+pub async fn capacity_feed(
+    State(state): State<AppState>,
+    Query(query): Query<CapacityQuery>,
+) -> AppResult<Json<EdgeCapacityFeed>> {
+    let max_age = chrono::Duration::seconds(
+        query
+            .max_age_secs
+            .unwrap_or(DEFAULT_CAPACITY_MAX_AGE_SECS)
+            .clamp(1, 24 * 3600),
+    );
+    let min_headroom = query
+        .min_headroom_percent
+        .unwrap_or(DEFAULT_MIN_HEADROOM_PERCENT)
+        .clamp(0.0, 100.0);
+
+    let rows = sqlx::query(
+        r#"
+        SELECT agent_id, agent_name, status, queue_depth, running, cpu_percent,
+               memory_percent, last_heartbeat, public_endpoint
+        FROM edge_agent_status
+        "#,
+    )
+    .fetch_all(state.db.pool())
+    .await?;
+
+    let now = Utc::now();
+    let mut agents = Vec::with_capacity(rows.len());
+    for row in rows {
+        let cpu_percent: Option<f64> = row.try_get("cpu_percent")?;
+        let memory_percent: Option<f64> = row.try_get("memory_percent")?;
+        let mut capacity = EdgeCapacity {
+            agent_id: row.try_get("agent_id")?,
+            agent_name: row.try_get("agent_name")?,
+            endpoint: row.try_get("public_endpoint")?,
+            status: row.try_get("status")?,
+            queue_depth: row.try_get::<i32, _>("queue_depth")?.max(0) as u32,
+            running: row.try_get::<i32, _>("running")?.max(0) as u32,
+            cpu_headroom_percent: cpu_percent.map(|used| (100.0 - used).clamp(0.0, 100.0)),
+            memory_headroom_percent: memory_percent.map(|used| (100.0 - used).clamp(0.0, 100.0)),
+            last_heartbeat: row.try_get("last_heartbeat")?,
+            schedulable: false,
+            reason: None,
+        };
+        capacity.reason = unschedulable(&capacity, now - max_age, min_headroom);
+        capacity.schedulable = capacity.reason.is_none();
+        agents.push(capacity);
+    }
+
+    agents.sort_by(|a, b| {
+        b.schedulable
+            .cmp(&a.schedulable)
+            .then(a.load().total_cmp(&b.load()))
+            .then(a.agent_id.cmp(&b.agent_id))
+    });
+
+    Ok(Json(EdgeCapacityFeed {
+        generated_at: now,
+        agents,
+    }))
+}
+
+/// Why an agent can't take a run, if it can't
+fn unschedulable(
+    capacity: &EdgeCapacity,
+    stale_before: DateTime<Utc>,
+    min_headroom: f64,
+) -> Option<String> {
+    if capacity.last_heartbeat < stale_before {
+        return Some("stale_heartbeat".to_string());
+    }
+    if capacity.status != "running" {
+        return Some(format!("status_{}", capacity.status));
+    }
+    if capacity.endpoint.is_none() {
+        return Some("no_endpoint".to_string());
+    }
+    if capacity
+        .cpu_headroom_percent
+        .is_some_and(|headroom| headroom < min_headroom)
+    {
+        return Some("cpu_exhausted".to_string());
+    }
+    if capacity
+        .memory_headroom_percent
+        .is_some_and(|headroom| headroom < min_headroom)
+    {
+        return Some("memory_exhausted".to_string());
+    }
+    None
 }
 
 pub async fn list_agent_runs(
@@ -327,6 +486,34 @@ pub async fn list_agent_runs(
     Ok(Json(runs))
 }
 
+pub async fn list_agent_cursors(
+    State(state): State<AppState>,
+    Path(agent_id): Path<String>,
+) -> AppResult<Json<Vec<EdgeStreamCursor>>> {
+    let cursors = delivery::cursors(&state.db, &agent_id).await?;
+    Ok(Json(cursors))
+}
+
+pub async fn list_anomalies(
+    State(state): State<AppState>,
+    Query(query): Query<AnomalyQuery>,
+) -> AppResult<Json<Vec<EdgeAnomaly>>> {
+    let defaults = DetectionParams::default();
+    let params = DetectionParams {
+        window: query
+            .window_minutes
+            .map(|minutes| chrono::Duration::minutes(minutes.clamp(1, 24 * 60)))
+            .unwrap_or(defaults.window),
+        baseline: query
+            .baseline_hours
+            .map(|hours| chrono::Duration::hours(hours.clamp(1, 7 * 24)))
+            .unwrap_or(defaults.baseline),
+    };
+
+    let anomalies = anomaly::detect(&state.db, params).await?;
+    Ok(Json(anomalies))
+}
+
 fn extract_number(value: &serde_json::Value, field: &str) -> Option<f64> {
     value.get(field).and_then(|v| v.as_f64())
 }
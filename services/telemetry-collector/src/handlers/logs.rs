@@ -0,0 +1,120 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::*,
+    AppState,
+};
+
+#[derive(Deserialize)]
+pub struct LogsQuery {
+    service: Option<String>,
+    sandbox_id: Option<String>,
+    run_id: Option<String>,
+    level: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    limit: Option<i64>,
+}
+
+/// Store a batch of structured logs shipped by a service. Resent batches
+/// are stored once. Nothing is logged here, so a collector shipping its
+/// own logs to itself doesn't feed on them.
+pub async fn ingest_logs(
+    State(state): State<AppState>,
+    Json(batch): Json<LogBatch>,
+) -> AppResult<StatusCode> {
+    if batch.service.trim().is_empty() {
+        return Err(AppError::Validation("service must not be empty".to_string()));
+    }
+
+    let mut tx = state.db.pool().begin().await?;
+    for (position, record) in batch.records.iter().enumerate() {
+        sqlx::query!(
+            r#"
+            INSERT INTO service_logs (
+                batch_id, position, service, instance, timestamp, level,
+                target, message, sandbox_id, run_id, fields
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (batch_id, position) DO NOTHING
+            "#,
+            batch.batch_id,
+            position as i32,
+            batch.service,
+            batch.instance,
+            record.timestamp,
+            record.level,
+            record.target,
+            record.message,
+            record.sandbox_id,
+            record.run_id,
+            serde_json::Value::Object(record.fields.clone())
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Stored logs, newest first, e.g. everything every service logged about
+/// one sandbox
+pub async fn list_logs(
+    State(state): State<AppState>,
+    Query(query): Query<LogsQuery>,
+) -> AppResult<Json<Vec<ServiceLog>>> {
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT service, instance, timestamp, level, target, message, sandbox_id, run_id, fields
+        FROM service_logs
+        WHERE ($1::TEXT IS NULL OR service = $1)
+          AND ($2::TEXT IS NULL OR sandbox_id = $2)
+          AND ($3::TEXT IS NULL OR run_id = $3)
+          AND ($4::TEXT IS NULL OR level = $4)
+          AND ($5::TIMESTAMPTZ IS NULL OR timestamp >= $5)
+          AND ($6::TIMESTAMPTZ IS NULL OR timestamp <= $6)
+        ORDER BY timestamp DESC, id DESC
+        LIMIT $7
+        "#,
+        query.service,
+        query.sandbox_id,
+        query.run_id,
+        query.level,
+        query.since,
+        query.until,
+        limit
+    )
+    .fetch_all(state.db.pool())
+    .await?;
+
+    Ok(Json(
+        rows.into_iter()
+            .map(|row| ServiceLog {
+                service: row.service,
+                instance: row.instance,
+                record: LogRecord {
+                    timestamp: row.timestamp,
+                    level: row.level,
+                    target: row.target,
+                    message: row.message,
+                    sandbox_id: row.sandbox_id,
+                    run_id: row.run_id,
+                    fields: match row.fields {
+                        serde_json::Value::Object(fields) => fields,
+                        _ => Default::default(),
+                    },
+                },
+            })
+            .collect(),
+    ))
+}
@@ -1,4 +1,11 @@
+pub mod benchmarks;
+pub mod deletions;
+pub mod drills;
 pub mod edge;
 pub mod health;
-pub mod metrics;
+pub mod logs;
+pub mod pricing;
+pub mod quotas;
+pub mod reports;
+pub mod security;
 pub mod telemetry;
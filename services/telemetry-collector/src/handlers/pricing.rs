@@ -0,0 +1,232 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::*,
+    pricing::{self, PriceCatalog},
+    AppState,
+};
+
+/// Runs re-priced per database round trip
+const RECOMPUTE_PAGE_SIZE: i64 = 1000;
+
+#[derive(Deserialize)]
+pub struct RatesQuery {
+    provider: Option<String>,
+    /// Only the rates in effect at this time
+    at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct DivergencesQuery {
+    provider: Option<String>,
+    since: Option<DateTime<Utc>>,
+    /// Defaults to the configured `cost_divergence_tolerance`
+    tolerance: Option<f64>,
+    limit: Option<i64>,
+}
+
+/// Add a rate to the catalog. Rates are never changed in place; a new rate
+/// with a later `effective_from` supersedes the old one from then on.
+pub async fn add_rate(
+    State(state): State<AppState>,
+    Json(request): Json<PriceRateRequest>,
+) -> AppResult<(StatusCode, Json<PriceRate>)> {
+    if request.provider.is_empty() {
+        return Err(AppError::Validation("provider is required".to_string()));
+    }
+    if !request.rate.is_finite() || request.rate < 0.0 {
+        return Err(AppError::Validation(
+            "rate must be a non-negative number".to_string(),
+        ));
+    }
+    let rate = PriceRate {
+        id: Uuid::new_v4(),
+        provider: request.provider,
+        unit: request.unit,
+        rate: request.rate,
+        effective_from: request.effective_from.unwrap_or_else(Utc::now),
+        created_at: Utc::now(),
+    };
+
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO provider_rates (id, provider, unit, rate, effective_from, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (provider, unit, effective_from) DO NOTHING
+        "#,
+        rate.id,
+        rate.provider,
+        rate.unit.as_str(),
+        rate.rate,
+        rate.effective_from,
+        rate.created_at
+    )
+    .execute(state.db.pool())
+    .await?;
+    if inserted.rows_affected() == 0 {
+        return Err(AppError::Conflict(format!(
+            "{} already has a {} rate effective from {}",
+            rate.provider,
+            rate.unit.as_str(),
+            rate.effective_from
+        )));
+    }
+
+    Ok((StatusCode::CREATED, Json(rate)))
+}
+
+/// Catalog rates, or just those in effect at a given time
+pub async fn list_rates(
+    State(state): State<AppState>,
+    Query(query): Query<RatesQuery>,
+) -> AppResult<Json<Vec<PriceRate>>> {
+    let catalog = PriceCatalog::load(&state.db, query.provider.as_deref()).await?;
+    let mut providers: Vec<&String> = catalog.providers().collect();
+    providers.sort();
+
+    let rates = providers
+        .into_iter()
+        .flat_map(|provider| match query.at {
+            Some(at) => catalog.rates_at(provider, at),
+            None => catalog.history(provider).iter().collect(),
+        })
+        .cloned()
+        .collect();
+    Ok(Json(rates))
+}
+
+/// Re-price recorded runs from the catalog as it stands, after rates were
+/// added or corrected
+pub async fn recompute_costs(
+    State(state): State<AppState>,
+    Json(request): Json<RecomputeRequest>,
+) -> AppResult<Json<RecomputeReport>> {
+    let catalog = PriceCatalog::load(&state.db, request.provider.as_deref()).await?;
+    let tolerance = state.config.current().cost_divergence_tolerance;
+    let until = request.until.unwrap_or_else(Utc::now);
+    let mut report = RecomputeReport::default();
+    let mut after: Option<(DateTime<Utc>, Uuid)> = None;
+
+    loop {
+        let runs = sqlx::query_as!(
+            SandboxRun,
+            r#"
+            SELECT * FROM sandbox_runs
+            WHERE ($1::TEXT IS NULL OR provider = $1)
+              AND ($2::TIMESTAMPTZ IS NULL OR created_at >= $2)
+              AND created_at < $3
+              AND ($4::TIMESTAMPTZ IS NULL OR (created_at, id) > ($4, $5::UUID))
+            ORDER BY created_at, id
+            LIMIT $6
+            "#,
+            request.provider,
+            request.since,
+            until,
+            after.map(|(created_at, _)| created_at),
+            after.map(|(_, id)| id),
+            RECOMPUTE_PAGE_SIZE
+        )
+        .fetch_all(state.db.pool())
+        .await?;
+        let Some(last) = runs.last() else {
+            break;
+        };
+        after = Some((last.created_at, last.id));
+
+        let mut ids = Vec::with_capacity(runs.len());
+        let mut estimates = Vec::with_capacity(runs.len());
+        for run in &runs {
+            let estimate = catalog.estimate(run);
+            match estimate {
+                Some(estimate) => {
+                    report.runs_repriced += 1;
+                    if pricing::divergence(run.cost, estimate) > tolerance {
+                        report.runs_divergent += 1;
+                    }
+                }
+                None => report.runs_unpriced += 1,
+            }
+            ids.push(run.id);
+            estimates.push(estimate);
+        }
+
+        // Unpriced runs get a NULL estimate, which the query macros can't
+        // bind inside an array
+        sqlx::query(
+            r#"
+            UPDATE sandbox_runs
+            SET estimated_cost = priced.estimated_cost
+            FROM UNNEST($1::UUID[], $2::FLOAT8[]) AS priced (id, estimated_cost)
+            WHERE sandbox_runs.id = priced.id
+            "#,
+        )
+        .bind(&ids)
+        .bind(&estimates)
+        .execute(state.db.pool())
+        .await?;
+
+        if (runs.len() as i64) < RECOMPUTE_PAGE_SIZE {
+            break;
+        }
+    }
+
+    Ok(Json(report))
+}
+
+/// Priced runs whose reported cost is off from the catalog estimate by more
+/// than the tolerance, newest first
+pub async fn list_divergences(
+    State(state): State<AppState>,
+    Query(query): Query<DivergencesQuery>,
+) -> AppResult<Json<Vec<CostDivergence>>> {
+    let tolerance = query
+        .tolerance
+        .unwrap_or_else(|| state.config.current().cost_divergence_tolerance);
+    if !(0.0..=1.0).contains(&tolerance) {
+        return Err(AppError::Validation(
+            "tolerance must be between 0 and 1".to_string(),
+        ));
+    }
+    let limit = query.limit.unwrap_or(100).min(1000);
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, sandbox_id, provider, cost, estimated_cost AS "estimated_cost!", created_at
+        FROM sandbox_runs
+        WHERE estimated_cost IS NOT NULL
+          AND ($1::TEXT IS NULL OR provider = $1)
+          AND ($2::TIMESTAMPTZ IS NULL OR created_at >= $2)
+          AND ABS(cost - estimated_cost) > $3 * GREATEST(ABS(cost), ABS(estimated_cost))
+        ORDER BY created_at DESC
+        LIMIT $4
+        "#,
+        query.provider,
+        query.since,
+        tolerance,
+        limit
+    )
+    .fetch_all(state.db.pool())
+    .await?;
+
+    let divergences = rows
+        .into_iter()
+        .map(|row| CostDivergence {
+            divergence: pricing::divergence(row.cost, row.estimated_cost),
+            id: row.id,
+            sandbox_id: row.sandbox_id,
+            provider: row.provider,
+            cost: row.cost,
+            estimated_cost: row.estimated_cost,
+            created_at: row.created_at,
+        })
+        .collect();
+    Ok(Json(divergences))
+}
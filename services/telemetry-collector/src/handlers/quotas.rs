@@ -0,0 +1,170 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+
+use super::telemetry::{enum_name, parse_enum};
+use crate::{
+    db::Database,
+    error::{AppError, AppResult},
+    models::*,
+    reports, AppState,
+};
+
+/// Every tenant's quota
+pub async fn list_quotas(State(state): State<AppState>) -> AppResult<Json<Vec<TenantQuota>>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT tenant, compute_seconds, cost, on_exhausted, updated_at
+        FROM tenant_quotas
+        ORDER BY tenant
+        "#
+    )
+    .fetch_all(state.db.pool())
+    .await?;
+
+    let quotas = rows
+        .into_iter()
+        .map(|row| {
+            Ok(TenantQuota {
+                tenant: row.tenant,
+                compute_seconds: row.compute_seconds,
+                cost: row.cost,
+                on_exhausted: parse_enum(row.on_exhausted)?,
+                updated_at: row.updated_at,
+            })
+        })
+        .collect::<AppResult<_>>()?;
+    Ok(Json(quotas))
+}
+
+pub async fn get_quota(
+    State(state): State<AppState>,
+    Path(tenant): Path<String>,
+) -> AppResult<Json<TenantQuota>> {
+    let quota = load_quota(&state.db, &tenant)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("{} has no quota", tenant)))?;
+    Ok(Json(quota))
+}
+
+/// Set a tenant's caps, replacing any it had. They apply to the current
+/// month's usage straight away.
+pub async fn put_quota(
+    State(state): State<AppState>,
+    Path(tenant): Path<String>,
+    Json(request): Json<QuotaRequest>,
+) -> AppResult<Json<TenantQuota>> {
+    for (name, cap) in [
+        ("compute_seconds", request.compute_seconds),
+        ("cost", request.cost),
+    ] {
+        if cap.is_some_and(|cap| !cap.is_finite() || cap < 0.0) {
+            return Err(AppError::Validation(format!(
+                "{} must be a non-negative number",
+                name
+            )));
+        }
+    }
+    let quota = TenantQuota {
+        tenant,
+        compute_seconds: request.compute_seconds,
+        cost: request.cost,
+        on_exhausted: request.on_exhausted,
+        updated_at: Utc::now(),
+    };
+
+    sqlx::query!(
+        r#"
+        INSERT INTO tenant_quotas (tenant, compute_seconds, cost, on_exhausted, updated_at)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (tenant) DO UPDATE SET
+            compute_seconds = EXCLUDED.compute_seconds,
+            cost = EXCLUDED.cost,
+            on_exhausted = EXCLUDED.on_exhausted,
+            updated_at = EXCLUDED.updated_at
+        "#,
+        quota.tenant,
+        quota.compute_seconds,
+        quota.cost,
+        enum_name(&quota.on_exhausted)?,
+        quota.updated_at
+    )
+    .execute(state.db.pool())
+    .await?;
+
+    Ok(Json(quota))
+}
+
+pub async fn delete_quota(
+    State(state): State<AppState>,
+    Path(tenant): Path<String>,
+) -> AppResult<StatusCode> {
+    let deleted = sqlx::query!("DELETE FROM tenant_quotas WHERE tenant = $1", tenant)
+        .execute(state.db.pool())
+        .await?;
+    if deleted.rows_affected() == 0 {
+        return Err(AppError::NotFound(format!("{} has no quota", tenant)));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// A tenant's usage so far this month against its quota. Tenants without a
+/// quota get their usage with nothing remaining unset.
+pub async fn get_quota_usage(
+    State(state): State<AppState>,
+    Path(tenant): Path<String>,
+) -> AppResult<Json<QuotaUsage>> {
+    let month = Utc::now().format("%Y-%m").to_string();
+    let (period_start, period_end) = reports::month_period(&month)?;
+    let usage = sqlx::query!(
+        r#"
+        SELECT
+            COALESCE(SUM(duration_ms), 0)::FLOAT8 / 1000.0 AS "compute_seconds!",
+            COALESCE(SUM(cost), 0) AS "cost!"
+        FROM sandbox_runs
+        WHERE tenant = $1 AND created_at >= $2 AND created_at < $3
+        "#,
+        tenant,
+        period_start,
+        period_end
+    )
+    .fetch_one(state.db.pool())
+    .await?;
+    let quota = load_quota(&state.db, &tenant).await?;
+
+    Ok(Json(QuotaUsage::new(
+        tenant,
+        month,
+        (period_start, period_end),
+        usage.compute_seconds,
+        usage.cost,
+        quota,
+    )))
+}
+
+async fn load_quota(db: &Database, tenant: &str) -> AppResult<Option<TenantQuota>> {
+    let row = sqlx::query!(
+        r#"
+        SELECT tenant, compute_seconds, cost, on_exhausted, updated_at
+        FROM tenant_quotas
+        WHERE tenant = $1
+        "#,
+        tenant
+    )
+    .fetch_optional(db.pool())
+    .await?;
+
+    row.map(|row| {
+        Ok(TenantQuota {
+            tenant: row.tenant,
+            compute_seconds: row.compute_seconds,
+            cost: row.cost,
+            on_exhausted: parse_enum(row.on_exhausted)?,
+            updated_at: row.updated_at,
+        })
+    })
+    .transpose()
+}
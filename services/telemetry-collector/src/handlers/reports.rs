@@ -0,0 +1,245 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use super::telemetry::{enum_name, parse_enum};
+use crate::{
+    error::{AppError, AppResult},
+    labels,
+    models::*,
+    reports, AppState,
+};
+
+#[derive(Deserialize)]
+pub struct ReportsQuery {
+    tenant: Option<String>,
+    limit: Option<i64>,
+}
+
+/// A `usage_reports` row, without the report itself
+struct ReportRow {
+    id: Uuid,
+    tenant: Option<String>,
+    month: String,
+    format: String,
+    status: String,
+    error: Option<String>,
+    created_at: DateTime<Utc>,
+    completed_at: Option<DateTime<Utc>>,
+    group_by_label: Option<String>,
+    labels: serde_json::Value,
+}
+
+impl TryFrom<ReportRow> for UsageReport {
+    type Error = AppError;
+
+    fn try_from(row: ReportRow) -> AppResult<Self> {
+        let status = parse_enum(row.status)?;
+        Ok(UsageReport {
+            download_url: (status == ReportStatus::Completed)
+                .then(|| format!("/api/reports/usage/{}/download", row.id)),
+            id: row.id,
+            tenant: row.tenant,
+            month: row.month,
+            format: parse_enum(row.format)?,
+            status,
+            error: row.error,
+            created_at: row.created_at,
+            completed_at: row.completed_at,
+            group_by_label: row.group_by_label,
+            labels: serde_json::from_value(row.labels)?,
+        })
+    }
+}
+
+/// Start generating a monthly usage report. Poll it until it's completed,
+/// then fetch it from its `download_url`.
+pub async fn create_usage_report(
+    State(state): State<AppState>,
+    Json(request): Json<UsageReportRequest>,
+) -> AppResult<(StatusCode, Json<UsageReport>)> {
+    reports::month_period(&request.month)?;
+    let group_by_label = request.group_by_label.filter(|key| !key.is_empty());
+    if let Some(key) = group_by_label.as_deref() {
+        labels::check_key(key)?;
+    }
+    let label_filter = labels::to_json(&request.labels)?;
+
+    let report = UsageReport {
+        id: Uuid::new_v4(),
+        tenant: request.tenant.filter(|tenant| !tenant.is_empty()),
+        month: request.month,
+        format: request.format,
+        status: ReportStatus::Pending,
+        error: None,
+        created_at: Utc::now(),
+        completed_at: None,
+        download_url: None,
+        group_by_label,
+        labels: request.labels,
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO usage_reports (
+            id, tenant, month, format, status, created_at, group_by_label, labels
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        report.id,
+        report.tenant,
+        report.month,
+        enum_name(&report.format)?,
+        enum_name(&report.status)?,
+        report.created_at,
+        report.group_by_label,
+        label_filter
+    )
+    .execute(state.db.pool())
+    .await?;
+
+    tokio::spawn(generate_usage_report(state, report.clone()));
+    Ok((StatusCode::ACCEPTED, Json(report)))
+}
+
+async fn generate_usage_report(state: AppState, report: UsageReport) {
+    let generated = async {
+        let content = reports::usage(
+            &state.db,
+            &report.month,
+            report.tenant.as_deref(),
+            report.group_by_label.as_deref(),
+            &report.labels,
+        )
+        .await?;
+        reports::render(&content, report.format)
+    }
+    .await;
+
+    let (status, content, message) = match generated {
+        Ok(content) => {
+            info!(report_id = %report.id, month = %report.month, "Usage report generated");
+            (ReportStatus::Completed, Some(content), None)
+        }
+        Err(e) => {
+            error!(report_id = %report.id, "Failed to generate usage report: {}", e);
+            (ReportStatus::Failed, None, Some(e.to_string()))
+        }
+    };
+    let stored = sqlx::query!(
+        r#"
+        UPDATE usage_reports
+        SET status = $2, content = $3, error = $4, completed_at = NOW()
+        WHERE id = $1
+        "#,
+        report.id,
+        enum_name(&status).unwrap_or_default(),
+        content,
+        message
+    )
+    .execute(state.db.pool())
+    .await;
+    if let Err(e) = stored {
+        error!(report_id = %report.id, "Failed to store usage report: {}", e);
+    }
+}
+
+/// Usage reports, newest first
+pub async fn list_usage_reports(
+    State(state): State<AppState>,
+    Query(query): Query<ReportsQuery>,
+) -> AppResult<Json<Vec<UsageReport>>> {
+    let limit = query.limit.unwrap_or(100).min(1000);
+
+    let rows = sqlx::query_as!(
+        ReportRow,
+        r#"
+        SELECT id, tenant, month, format, status, error, created_at, completed_at,
+               group_by_label, labels
+        FROM usage_reports
+        WHERE ($1::TEXT IS NULL OR tenant = $1)
+        ORDER BY created_at DESC
+        LIMIT $2
+        "#,
+        query.tenant,
+        limit
+    )
+    .fetch_all(state.db.pool())
+    .await?;
+
+    let reports = rows
+        .into_iter()
+        .map(UsageReport::try_from)
+        .collect::<AppResult<_>>()?;
+    Ok(Json(reports))
+}
+
+pub async fn get_usage_report(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> AppResult<Json<UsageReport>> {
+    let row = sqlx::query_as!(
+        ReportRow,
+        r#"
+        SELECT id, tenant, month, format, status, error, created_at, completed_at,
+               group_by_label, labels
+        FROM usage_reports
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(state.db.pool())
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Usage report {} not found", id)))?;
+
+    Ok(Json(row.try_into()?))
+}
+
+/// The generated report, as an attachment
+pub async fn download_usage_report(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    let row = sqlx::query!(
+        r#"
+        SELECT tenant, month, format, status, content
+        FROM usage_reports
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(state.db.pool())
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Usage report {} not found", id)))?;
+
+    let Some(content) = row.content else {
+        return Err(AppError::Conflict(format!(
+            "Usage report {} is {}",
+            id, row.status
+        )));
+    };
+    let format: ReportFormat = parse_enum(row.format.clone())?;
+    let filename = format!(
+        "sandstorm-usage-{}-{}.{}",
+        row.tenant.as_deref().unwrap_or("all").replace(['"', '/', '\\'], "_"),
+        row.month,
+        row.format
+    );
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, reports::content_type(format).to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        content,
+    ))
+}
@@ -0,0 +1,202 @@
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use chrono::Utc;
+use ring::hmac;
+use sandstorm_types::security::{SIGNATURE_HEADER, SIGNATURE_TIMESTAMP_HEADER};
+
+use crate::{
+    error::{AppError, AppResult},
+    models::*,
+    AppState,
+};
+
+/// How far a signed request's timestamp may be from the collector's clock
+const MAX_SIGNATURE_SKEW_SECS: i64 = 300;
+
+/// Store a signed batch of per-sandbox incident summaries from the security
+/// monitor. Resent batches are stored once.
+pub async fn ingest_security_signals(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> AppResult<StatusCode> {
+    let Some(signing_key) = state.config.current().security_signal_key.clone() else {
+        return Err(AppError::Unauthorized(
+            "security signals are not accepted without a signing key".to_string(),
+        ));
+    };
+    verify_signature(&signing_key, &headers, &body)?;
+
+    let batch: SecuritySignalBatch = serde_json::from_slice(&body)
+        .map_err(|e| AppError::Validation(format!("invalid security signal batch: {}", e)))?;
+
+    let mut tx = state.db.pool().begin().await?;
+    for signal in &batch.signals {
+        sqlx::query!(
+            r#"
+            INSERT INTO security_signals (
+                batch_id, sandbox_id, run_id, provider, image, violations,
+                quarantines, max_severity, first_seen, last_seen
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT (batch_id, sandbox_id) DO NOTHING
+            "#,
+            batch.batch_id,
+            signal.sandbox_id,
+            signal.run_id,
+            signal.provider,
+            signal.image,
+            signal.violations as i64,
+            signal.quarantines as i64,
+            signal.max_severity.as_str(),
+            signal.first_seen,
+            signal.last_seen
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Check the `sha256=<hex>` HMAC over `<timestamp>.<body>`, and that the
+/// timestamp is recent, so captured requests can't be replayed later
+fn verify_signature(signing_key: &str, headers: &HeaderMap, body: &[u8]) -> AppResult<()> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| AppError::Unauthorized(format!("missing {} header", name)))
+    };
+    let timestamp = header(SIGNATURE_TIMESTAMP_HEADER)?;
+    let signature = header(SIGNATURE_HEADER)?
+        .strip_prefix("sha256=")
+        .and_then(decode_hex)
+        .ok_or_else(|| AppError::Unauthorized("malformed signature".to_string()))?;
+
+    let sent_at: i64 = timestamp
+        .parse()
+        .map_err(|_| AppError::Unauthorized("malformed signature timestamp".to_string()))?;
+    if (Utc::now().timestamp() - sent_at).abs() > MAX_SIGNATURE_SKEW_SECS {
+        return Err(AppError::Unauthorized("signature timestamp is too old".to_string()));
+    }
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, signing_key.as_bytes());
+    let signed = [timestamp.as_bytes(), b".", body].concat();
+    hmac::verify(&key, &signed, &signature)
+        .map_err(|_| AppError::Unauthorized("invalid signature".to_string()))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Security incidents per provider and image over a time window
+pub async fn get_security_incidents(
+    State(state): State<AppState>,
+    Query(time_range): Query<TimeRange>,
+) -> AppResult<Json<Vec<SecurityIncidents>>> {
+    let end = time_range.end.unwrap_or_else(Utc::now);
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            provider,
+            image,
+            COUNT(DISTINCT sandbox_id) as "sandboxes!",
+            SUM(violations)::BIGINT as "violations!",
+            SUM(quarantines)::BIGINT as "quarantines!"
+        FROM security_signals
+        WHERE last_seen >= $1
+          AND first_seen <= $2
+        GROUP BY provider, image
+        ORDER BY 3 DESC
+        "#,
+        time_range.start,
+        end
+    )
+    .fetch_all(state.db.pool())
+    .await?;
+
+    Ok(Json(
+        rows.into_iter()
+            .map(|row| SecurityIncidents {
+                provider: row.provider,
+                image: row.image,
+                sandboxes: row.sandboxes,
+                violations: row.violations,
+                quarantines: row.quarantines,
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "signal-key";
+    const BODY: &[u8] = br#"[{"sandbox_id":"sb-1"}]"#;
+
+    fn signed_headers(key: &str, body: &[u8]) -> HeaderMap {
+        let timestamp = Utc::now().timestamp().to_string();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+        let tag = hmac::sign(&key, &[timestamp.as_bytes(), b".", body].concat());
+        let hex: String = tag.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(SIGNATURE_TIMESTAMP_HEADER, timestamp.parse().unwrap());
+        headers.insert(SIGNATURE_HEADER, format!("sha256={}", hex).parse().unwrap());
+        headers
+    }
+
+    fn rejection(result: AppResult<()>) -> String {
+        match result {
+            Err(AppError::Unauthorized(reason)) => reason,
+            other => panic!("expected an unauthorized error, got {:?}", other.map_err(|e| e.to_string())),
+        }
+    }
+
+    #[test]
+    fn accepts_a_fresh_signature() {
+        assert!(verify_signature(KEY, &signed_headers(KEY, BODY), BODY).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_tampered_body_or_other_key() {
+        let headers = signed_headers(KEY, BODY);
+        let tampered = br#"[{"sandbox_id":"sb-2"}]"#;
+        assert_eq!(rejection(verify_signature(KEY, &headers, tampered)), "invalid signature");
+        assert_eq!(rejection(verify_signature("other-key", &headers, BODY)), "invalid signature");
+    }
+
+    #[test]
+    fn rejects_a_stale_timestamp() {
+        let mut headers = signed_headers(KEY, BODY);
+        let stale = Utc::now().timestamp() - MAX_SIGNATURE_SKEW_SECS - 60;
+        headers.insert(SIGNATURE_TIMESTAMP_HEADER, stale.to_string().parse().unwrap());
+        assert_eq!(rejection(verify_signature(KEY, &headers, BODY)), "signature timestamp is too old");
+    }
+
+    #[test]
+    fn rejects_signatures_that_are_not_hex() {
+        for signature in ["sha256=abc", "sha256=zz", "sha256=0g", "md5=00", ""] {
+            let mut headers = signed_headers(KEY, BODY);
+            headers.insert(SIGNATURE_HEADER, signature.parse().unwrap());
+            assert_eq!(rejection(verify_signature(KEY, &headers, BODY)), "malformed signature", "{:?}", signature);
+        }
+        assert_eq!(decode_hex("00ff7A"), Some(vec![0x00, 0xff, 0x7a]));
+        assert_eq!(decode_hex("é0"), None);
+    }
+}
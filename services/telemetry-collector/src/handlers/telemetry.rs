@@ -1,16 +1,26 @@
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     Json,
 };
-use chrono::{DateTime, Duration, Utc};
+use sandstorm_types::{
+    provenance::RUN_ID_HEADER,
+    snapshot::{DEFAULT_TENANT, TENANT_HEADER},
+};
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
-use tracing::error;
+use tracing::warn;
 use uuid::Uuid;
 
 use crate::{
+    classify::{self, RunOutcome},
+    db::Database,
     error::{AppError, AppResult},
+    forecast::{self, ForecastParams},
+    labels,
+    metrics::MIB,
     models::*,
+    pricing::{self, PriceCatalog},
     AppState,
 };
 
@@ -20,12 +30,48 @@ pub struct TrainingDataQuery {
     limit: Option<i64>,
 }
 
+#[derive(Deserialize)]
+pub struct RunsQuery {
+    sandbox_id: Option<String>,
+    run_id: Option<Uuid>,
+    limit: Option<i64>,
+}
+
 pub async fn track_sandbox_run(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(request): Json<SandboxRunRequest>,
 ) -> AppResult<Json<SandboxRun>> {
+    let run_id = request.run_id.or_else(|| {
+        headers
+            .get(RUN_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+    });
+    let tenant = request
+        .tenant
+        .clone()
+        .or_else(|| {
+            headers
+                .get(TENANT_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        })
+        .filter(|tenant| !tenant.is_empty())
+        .unwrap_or_else(|| DEFAULT_TENANT.to_string());
+    let labels = labels::to_json(&request.labels)?;
     let timestamp = request.timestamp.unwrap_or_else(Utc::now);
-    let sandbox_run = SandboxRun {
+    let gpu = request.gpu.as_ref();
+    if let Some(gpu) = gpu {
+        check_gpu(gpu)?;
+    }
+    check_phases(&request)?;
+    let gpu_seconds = gpu.map(|gpu| {
+        gpu.gpu_seconds.unwrap_or_else(|| {
+            gpu.count.unwrap_or(1) as f64 * request.duration_ms as f64 / 1000.0
+        })
+    });
+    let mut sandbox_run = SandboxRun {
         id: Uuid::new_v4(),
         sandbox_id: request.sandbox_id,
         provider: request.provider.clone(),
@@ -35,7 +81,7 @@ pub async fn track_sandbox_run(
         cost: request.cost,
         cpu_requested: request.cpu_requested,
         memory_requested: request.memory_requested,
-        has_gpu: request.has_gpu,
+        has_gpu: request.has_gpu || gpu.is_some(),
         timeout_ms: request.timeout_ms,
         success: request.exit_code == 0,
         cpu_percent: request.cpu_percent,
@@ -44,7 +90,71 @@ pub async fn track_sandbox_run(
         network_tx_bytes: request.network_tx_bytes,
         agent_id: request.agent_id.clone(),
         created_at: timestamp,
+        gpu_type: gpu.map(|gpu| gpu.gpu_type.clone()),
+        gpu_count: gpu.and_then(|gpu| gpu.count),
+        gpu_utilization_percent: gpu.and_then(|gpu| gpu.utilization_percent),
+        gpu_memory_used_mb: gpu.and_then(|gpu| gpu.memory_used_mb),
+        gpu_seconds,
+        queued_ms: request.queued_ms,
+        provision_ms: request.provision_ms,
+        exec_ms: request.exec_ms,
+        teardown_ms: request.teardown_ms,
+        run_id,
+        estimated_cost: None,
+        tenant,
+        failure_class: None,
+        labels,
     };
+    sandbox_run.failure_class = classify::classify(&RunOutcome {
+        exit_code: sandbox_run.exit_code,
+        duration_ms: sandbox_run.duration_ms,
+        timeout_ms: sandbox_run.timeout_ms,
+        memory_mb: sandbox_run.memory_mb,
+        memory_requested: sandbox_run.memory_requested,
+        result: &request.result,
+    })
+    .map(|class| class.as_str().to_string());
+
+    // Price the run from the catalog to check the cost it reports
+    let catalog = PriceCatalog::load(&state.db, Some(&sandbox_run.provider)).await?;
+    sandbox_run.estimated_cost = catalog.estimate(&sandbox_run);
+    if let Some(estimate) = sandbox_run.estimated_cost {
+        let divergence = pricing::divergence(sandbox_run.cost, estimate);
+        if divergence > state.config.current().cost_divergence_tolerance {
+            warn!(
+                sandbox_id = %sandbox_run.sandbox_id,
+                provider = %sandbox_run.provider,
+                cost = sandbox_run.cost,
+                estimated_cost = estimate,
+                "Reported run cost diverges from the pricing catalog"
+            );
+            state
+                .metrics
+                .sandbox_cost_divergent_total
+                .with_label_values(&[&sandbox_run.provider])
+                .inc();
+        }
+    }
+
+    // Exemplars link latency buckets to the trace that reported the run, or
+    // to the run itself when the agent sent no trace context
+    let trace_id = sandstorm_metrics::trace_id(&headers).or_else(|| run_id.map(|id| id.to_string()));
+    let trace_id = trace_id.as_deref();
+
+    for (phase, value) in [
+        ("queued", sandbox_run.queued_ms),
+        ("provision", sandbox_run.provision_ms),
+        ("exec", sandbox_run.exec_ms),
+        ("teardown", sandbox_run.teardown_ms),
+    ] {
+        if let Some(ms) = value {
+            state.metrics.sandbox_phase_duration.observe(
+                &[&sandbox_run.provider, phase],
+                ms as f64 / 1000.0,
+                trace_id,
+            );
+        }
+    }
 
     // Update metrics
     state
@@ -57,17 +167,46 @@ pub async fn track_sandbox_run(
         ])
         .inc();
 
-    state
-        .metrics
-        .sandbox_run_duration
-        .with_label_values(&[&sandbox_run.provider, &sandbox_run.language])
-        .observe(sandbox_run.duration_ms as f64);
+    if let Some(class) = sandbox_run.failure_class.as_deref() {
+        state
+            .metrics
+            .sandbox_failures_total
+            .with_label_values(&[&sandbox_run.provider, &sandbox_run.language, class])
+            .inc();
+    }
+
+    state.metrics.sandbox_run_duration.observe(
+        &[&sandbox_run.provider, &sandbox_run.language],
+        sandbox_run.duration_ms as f64 / 1000.0,
+        trace_id,
+    );
 
     state
         .metrics
         .sandbox_run_cost
-        .with_label_values(&[&sandbox_run.provider])
-        .observe(sandbox_run.cost);
+        .observe(&[&sandbox_run.provider], sandbox_run.cost, trace_id);
+
+    if let Some(gpu_type) = sandbox_run.gpu_type.as_deref() {
+        let labels = [sandbox_run.provider.as_str(), gpu_type];
+        if let Some(utilization) = sandbox_run.gpu_utilization_percent {
+            state
+                .metrics
+                .gpu_utilization
+                .observe(&labels, utilization / 100.0, trace_id);
+        }
+        if let Some(memory_mb) = sandbox_run.gpu_memory_used_mb {
+            state
+                .metrics
+                .gpu_memory_used
+                .observe(&labels, memory_mb * MIB, trace_id);
+        }
+        if let Some(gpu_seconds) = sandbox_run.gpu_seconds {
+            state
+                .metrics
+                .gpu_seconds
+                .observe(&labels, gpu_seconds, trace_id);
+        }
+    }
 
     // Store in database
     let result = sqlx::query_as!(
@@ -76,9 +215,13 @@ pub async fn track_sandbox_run(
         INSERT INTO sandbox_runs (
             id, sandbox_id, provider, language, exit_code, duration_ms, 
             cost, cpu_requested, memory_requested, has_gpu, timeout_ms, 
-            success, cpu_percent, memory_mb, network_rx_bytes, network_tx_bytes, agent_id, created_at
+            success, cpu_percent, memory_mb, network_rx_bytes, network_tx_bytes, agent_id, created_at,
+            gpu_type, gpu_count, gpu_utilization_percent, gpu_memory_used_mb, gpu_seconds,
+            queued_ms, provision_ms, exec_ms, teardown_ms, run_id, estimated_cost, tenant,
+            failure_class, labels
         )
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18,
+                $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30, $31, $32)
         RETURNING *
         "#,
         sandbox_run.id,
@@ -98,7 +241,21 @@ pub async fn track_sandbox_run(
         sandbox_run.network_rx_bytes,
         sandbox_run.network_tx_bytes,
         sandbox_run.agent_id,
-        sandbox_run.created_at
+        sandbox_run.created_at,
+        sandbox_run.gpu_type,
+        sandbox_run.gpu_count,
+        sandbox_run.gpu_utilization_percent,
+        sandbox_run.gpu_memory_used_mb,
+        sandbox_run.gpu_seconds,
+        sandbox_run.queued_ms,
+        sandbox_run.provision_ms,
+        sandbox_run.exec_ms,
+        sandbox_run.teardown_ms,
+        sandbox_run.run_id,
+        sandbox_run.estimated_cost,
+        sandbox_run.tenant,
+        sandbox_run.failure_class,
+        sandbox_run.labels
     )
     .fetch_one(state.db.pool())
     .await?;
@@ -132,6 +289,174 @@ pub async fn track_sandbox_run(
     Ok(Json(result))
 }
 
+/// Reject GPU readings no device reports, before they reach the
+/// histograms and the accelerator averages
+fn check_gpu(gpu: &GpuUsage) -> AppResult<()> {
+    let invalid = |message: &str| Err(AppError::Validation(message.to_string()));
+    if gpu.count.is_some_and(|count| count < 1) {
+        return invalid("gpu.count must be at least 1");
+    }
+    if gpu.utilization_percent.is_some_and(|percent| !(0.0..=100.0).contains(&percent)) {
+        return invalid("gpu.utilization_percent must be between 0 and 100");
+    }
+    if gpu.memory_used_mb.is_some_and(|mb| !(mb >= 0.0 && mb.is_finite())) {
+        return invalid("gpu.memory_used_mb must not be negative");
+    }
+    if gpu.gpu_seconds.is_some_and(|seconds| !(seconds >= 0.0 && seconds.is_finite())) {
+        return invalid("gpu.gpu_seconds must not be negative");
+    }
+    Ok(())
+}
+
+/// Reject negative phase timings. Phases a run doesn't report are left out
+/// of the phase histogram and the latency breakdown.
+fn check_phases(request: &SandboxRunRequest) -> AppResult<()> {
+    for (field, value) in [
+        ("queued_ms", request.queued_ms),
+        ("provision_ms", request.provision_ms),
+        ("exec_ms", request.exec_ms),
+        ("teardown_ms", request.teardown_ms),
+    ] {
+        if value.is_some_and(|ms| ms < 0) {
+            return Err(AppError::Validation(format!("{} must not be negative", field)));
+        }
+    }
+    Ok(())
+}
+
+/// Recorded runs for a sandbox and/or gateway run ID, newest first
+pub async fn list_runs(
+    State(state): State<AppState>,
+    Query(query): Query<RunsQuery>,
+) -> AppResult<Json<Vec<SandboxRun>>> {
+    if query.sandbox_id.is_none() && query.run_id.is_none() {
+        return Err(AppError::Validation(
+            "sandbox_id or run_id is required".to_string(),
+        ));
+    }
+    let limit = query.limit.unwrap_or(100).min(1000);
+
+    let runs = sqlx::query_as!(
+        SandboxRun,
+        r#"
+        SELECT * FROM sandbox_runs
+        WHERE ($1::TEXT IS NULL OR sandbox_id = $1)
+          AND ($2::UUID IS NULL OR run_id = $2)
+        ORDER BY created_at DESC
+        LIMIT $3
+        "#,
+        query.sandbox_id,
+        query.run_id,
+        limit
+    )
+    .fetch_all(state.db.pool())
+    .await?;
+
+    Ok(Json(runs))
+}
+
+#[derive(Deserialize)]
+pub struct PreemptionsQuery {
+    sandbox_id: Option<Uuid>,
+    run_id: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+/// Wire name of a unit enum such as `Priority`, as stored in the database
+pub(crate) fn enum_name<T: serde::Serialize>(value: &T) -> AppResult<String> {
+    serde_json::to_value(value)?
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| AppError::Internal("expected a unit enum".to_string()))
+}
+
+pub(crate) fn parse_enum<T: serde::de::DeserializeOwned>(name: String) -> AppResult<T> {
+    Ok(serde_json::from_value(serde_json::Value::String(name))?)
+}
+
+/// Record a sandbox being preempted or resumed by the gateway
+pub async fn track_preemption(
+    State(state): State<AppState>,
+    Json(event): Json<PreemptionEvent>,
+) -> AppResult<StatusCode> {
+    let priority = enum_name(&event.priority)?;
+    let action = enum_name(&event.action)?;
+    let preempted_by = event.preempted_by.as_ref().map(enum_name).transpose()?;
+
+    state
+        .metrics
+        .sandbox_preemptions_total
+        .with_label_values(&[&event.provider, &priority, &action])
+        .inc();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO preemption_events (
+            id, sandbox_id, run_id, provider, priority, action,
+            preempted_by, resumed_as, snapshot_id, created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        "#,
+        event.id,
+        event.sandbox_id,
+        event.run_id,
+        event.provider,
+        priority,
+        action,
+        preempted_by,
+        event.resumed_as,
+        event.snapshot_id,
+        event.created_at
+    )
+    .execute(state.db.pool())
+    .await?;
+
+    Ok(StatusCode::CREATED)
+}
+
+/// Preemption events, newest first, optionally for one sandbox or run
+pub async fn list_preemptions(
+    State(state): State<AppState>,
+    Query(query): Query<PreemptionsQuery>,
+) -> AppResult<Json<Vec<PreemptionEvent>>> {
+    let limit = query.limit.unwrap_or(100).min(1000);
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT * FROM preemption_events
+        WHERE ($1::UUID IS NULL OR sandbox_id = $1)
+          AND ($2::UUID IS NULL OR run_id = $2)
+        ORDER BY created_at DESC
+        LIMIT $3
+        "#,
+        query.sandbox_id,
+        query.run_id,
+        limit
+    )
+    .fetch_all(state.db.pool())
+    .await?;
+
+    let events = rows
+        .into_iter()
+        .map(|row| {
+            Ok(PreemptionEvent {
+                id: row.id,
+                sandbox_id: row.sandbox_id,
+                run_id: row.run_id,
+                provider: row.provider,
+                priority: parse_enum(row.priority)?,
+                action: parse_enum(row.action)?,
+                preempted_by: row.preempted_by.map(parse_enum).transpose()?,
+                resumed_as: row.resumed_as,
+                snapshot_id: row.snapshot_id,
+                created_at: row.created_at,
+            })
+        })
+        .collect::<AppResult<_>>()?;
+
+    Ok(Json(events))
+}
+
 pub async fn get_training_data(
     State(state): State<AppState>,
     Query(query): Query<TrainingDataQuery>,
@@ -204,40 +529,340 @@ pub async fn get_provider_stats(
     Query(time_range): Query<TimeRange>,
 ) -> AppResult<Json<ProviderStats>> {
     let end = time_range.end.unwrap_or_else(Utc::now);
+    let stats = provider_stats(&state.db, &provider, time_range.start, end, true).await?;
+    Ok(Json(stats))
+}
 
+/// A provider's run statistics between `start` and `end`, broken down by
+/// accelerator only when `by_accelerator` is set
+pub(crate) async fn provider_stats(
+    db: &Database,
+    provider: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    by_accelerator: bool,
+) -> AppResult<ProviderStats> {
     let stats = sqlx::query!(
         r#"
         SELECT 
             AVG(duration_ms)::FLOAT8 as avg_latency,
             AVG(cost)::FLOAT8 as avg_cost,
             AVG(CASE WHEN success THEN 1.0 ELSE 0.0 END)::FLOAT8 as success_rate,
-            COUNT(*) as total_runs
+            COUNT(*) as total_runs,
+            AVG(CASE WHEN EXISTS (
+                SELECT 1 FROM security_signals
+                WHERE security_signals.sandbox_id = sandbox_runs.sandbox_id
+            ) THEN 1.0 ELSE 0.0 END)::FLOAT8 as security_incident_rate
         FROM sandbox_runs
         WHERE provider = $1 
           AND created_at >= $2 
           AND created_at <= $3
         "#,
         provider,
-        time_range.start,
+        start,
         end
     )
-    .fetch_one(state.db.pool())
+    .fetch_one(db.pool())
     .await?;
 
-    Ok(Json(ProviderStats {
+    if !by_accelerator {
+        return Ok(ProviderStats {
+            avg_latency: stats.avg_latency.unwrap_or(0.0),
+            avg_cost: stats.avg_cost.unwrap_or(0.0),
+            success_rate: stats.success_rate.unwrap_or(0.0),
+            total_runs: stats.total_runs.unwrap_or(0),
+            by_accelerator: Vec::new(),
+            security_incident_rate: stats.security_incident_rate.unwrap_or(0.0),
+        });
+    }
+
+    let accelerators = sqlx::query!(
+        r#"
+        SELECT
+            COALESCE(gpu_type, 'none') as "accelerator!",
+            AVG(duration_ms)::FLOAT8 as avg_latency,
+            AVG(cost)::FLOAT8 as avg_cost,
+            AVG(CASE WHEN success THEN 1.0 ELSE 0.0 END)::FLOAT8 as success_rate,
+            COUNT(*) as total_runs,
+            AVG(gpu_utilization_percent)::FLOAT8 as avg_gpu_utilization_percent,
+            AVG(gpu_memory_used_mb)::FLOAT8 as avg_gpu_memory_used_mb,
+            SUM(gpu_seconds)::FLOAT8 as total_gpu_seconds
+        FROM sandbox_runs
+        WHERE provider = $1
+          AND created_at >= $2
+          AND created_at <= $3
+        GROUP BY COALESCE(gpu_type, 'none')
+        ORDER BY total_runs DESC
+        "#,
+        provider,
+        start,
+        end
+    )
+    .fetch_all(db.pool())
+    .await?;
+
+    let by_accelerator = accelerators
+        .into_iter()
+        .map(|row| AcceleratorStats {
+            accelerator: row.accelerator,
+            avg_latency: row.avg_latency.unwrap_or(0.0),
+            avg_cost: row.avg_cost.unwrap_or(0.0),
+            success_rate: row.success_rate.unwrap_or(0.0),
+            total_runs: row.total_runs.unwrap_or(0),
+            avg_gpu_utilization_percent: row.avg_gpu_utilization_percent,
+            avg_gpu_memory_used_mb: row.avg_gpu_memory_used_mb,
+            total_gpu_seconds: row.total_gpu_seconds.unwrap_or(0.0),
+        })
+        .collect();
+
+    Ok(ProviderStats {
         avg_latency: stats.avg_latency.unwrap_or(0.0),
         avg_cost: stats.avg_cost.unwrap_or(0.0),
         success_rate: stats.success_rate.unwrap_or(0.0),
         total_runs: stats.total_runs.unwrap_or(0),
-    }))
+        by_accelerator,
+        security_incident_rate: stats.security_incident_rate.unwrap_or(0.0),
+    })
 }
 
+#[derive(Deserialize)]
+pub struct ForecastQuery {
+    #[serde(default)]
+    metric: ForecastMetric,
+    provider: Option<String>,
+    horizon_hours: Option<usize>,
+    history_days: Option<i64>,
+    confidence: Option<f64>,
+    capacity: Option<f64>,
+}
+
+/// Hourly forecast of sandbox demand or edge capacity, with confidence bands
+pub async fn get_forecast(
+    State(state): State<AppState>,
+    Query(query): Query<ForecastQuery>,
+) -> AppResult<Json<Forecast>> {
+    let params = ForecastParams {
+        metric: query.metric,
+        provider: query.provider,
+        horizon_hours: query.horizon_hours.unwrap_or(168),
+        history_days: query.history_days.unwrap_or(28),
+        confidence: query.confidence.unwrap_or(0.9),
+        capacity: query.capacity,
+    };
+    if params.metric == ForecastMetric::EdgeSlots && params.provider.is_some() {
+        return Err(AppError::Validation(
+            "edge_slots covers the whole edge fleet and takes no provider".to_string(),
+        ));
+    }
+    if !(1..=720).contains(&params.horizon_hours) {
+        return Err(AppError::Validation(
+            "horizon_hours must be between 1 and 720".to_string(),
+        ));
+    }
+    if !(2..=90).contains(&params.history_days) {
+        return Err(AppError::Validation(
+            "history_days must be between 2 and 90".to_string(),
+        ));
+    }
+    if !(params.confidence > 0.0 && params.confidence < 1.0) {
+        return Err(AppError::Validation(
+            "confidence must be between 0 and 1".to_string(),
+        ));
+    }
+
+    Ok(Json(forecast::forecast(&state.db, &params).await?))
+}
+
+#[derive(Deserialize)]
+pub struct FailureBreakdownQuery {
+    start: DateTime<Utc>,
+    end: Option<DateTime<Utc>>,
+    provider: Option<String>,
+    language: Option<String>,
+}
+
+/// Failed runs per provider and language, by root cause
+pub async fn get_failure_breakdown(
+    State(state): State<AppState>,
+    Query(query): Query<FailureBreakdownQuery>,
+) -> AppResult<Json<Vec<FailureBreakdown>>> {
+    let end = query.end.unwrap_or_else(Utc::now);
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            provider,
+            language,
+            failure_class,
+            COUNT(*) AS "runs!"
+        FROM sandbox_runs
+        WHERE created_at >= $1
+          AND created_at <= $2
+          AND ($3::TEXT IS NULL OR provider = $3)
+          AND ($4::TEXT IS NULL OR language = $4)
+        GROUP BY provider, language, failure_class
+        ORDER BY provider, language, COUNT(*) DESC
+        "#,
+        query.start,
+        end,
+        query.provider,
+        query.language
+    )
+    .fetch_all(state.db.pool())
+    .await?;
+
+    let mut breakdowns: Vec<FailureBreakdown> = Vec::new();
+    for row in rows {
+        let same_group = breakdowns.last().is_some_and(|breakdown| {
+            breakdown.provider == row.provider && breakdown.language == row.language
+        });
+        if !same_group {
+            breakdowns.push(FailureBreakdown {
+                provider: row.provider.clone(),
+                language: row.language.clone(),
+                total_runs: 0,
+                failures: 0,
+                by_class: Vec::new(),
+            });
+        }
+        let Some(breakdown) = breakdowns.last_mut() else {
+            continue;
+        };
+        breakdown.total_runs += row.runs;
+        // Successful runs have no class
+        if let Some(class) = row.failure_class {
+            breakdown.failures += row.runs;
+            breakdown.by_class.push(FailureClassCount {
+                class,
+                count: row.runs,
+                share: 0.0,
+            });
+        }
+    }
+    for breakdown in &mut breakdowns {
+        for class in &mut breakdown.by_class {
+            class.share = class.count as f64 / breakdown.failures as f64;
+        }
+    }
+
+    Ok(Json(breakdowns))
+}
+
+#[derive(Deserialize)]
+pub struct LatencyBreakdownQuery {
+    start: DateTime<Utc>,
+    end: Option<DateTime<Utc>>,
+    provider: Option<String>,
+}
+
+pub async fn get_latency_breakdown(
+    State(state): State<AppState>,
+    Query(query): Query<LatencyBreakdownQuery>,
+) -> AppResult<Json<Vec<LatencyBreakdown>>> {
+    let end = query.end.unwrap_or_else(Utc::now);
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            provider,
+            COUNT(*) as total_runs,
+            COUNT(*) FILTER (
+                WHERE queued_ms IS NOT NULL OR provision_ms IS NOT NULL
+                   OR exec_ms IS NOT NULL OR teardown_ms IS NOT NULL
+            ) as instrumented_runs,
+            AVG(queued_ms)::FLOAT8 as avg_queued,
+            PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY queued_ms) as p50_queued,
+            PERCENTILE_CONT(0.95) WITHIN GROUP (ORDER BY queued_ms) as p95_queued,
+            SUM(queued_ms)::FLOAT8 as sum_queued,
+            AVG(provision_ms)::FLOAT8 as avg_provision,
+            PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY provision_ms) as p50_provision,
+            PERCENTILE_CONT(0.95) WITHIN GROUP (ORDER BY provision_ms) as p95_provision,
+            SUM(provision_ms)::FLOAT8 as sum_provision,
+            AVG(exec_ms)::FLOAT8 as avg_exec,
+            PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY exec_ms) as p50_exec,
+            PERCENTILE_CONT(0.95) WITHIN GROUP (ORDER BY exec_ms) as p95_exec,
+            SUM(exec_ms)::FLOAT8 as sum_exec,
+            AVG(teardown_ms)::FLOAT8 as avg_teardown,
+            PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY teardown_ms) as p50_teardown,
+            PERCENTILE_CONT(0.95) WITHIN GROUP (ORDER BY teardown_ms) as p95_teardown,
+            SUM(teardown_ms)::FLOAT8 as sum_teardown
+        FROM sandbox_runs
+        WHERE created_at >= $1
+          AND created_at <= $2
+          AND ($3::TEXT IS NULL OR provider = $3)
+        GROUP BY provider
+        ORDER BY provider
+        "#,
+        query.start,
+        end,
+        query.provider
+    )
+    .fetch_all(state.db.pool())
+    .await?;
+
+    let breakdowns = rows
+        .into_iter()
+        .map(|row| {
+            let [queued, provision, exec, teardown] = phase_shares([
+                row.sum_queued,
+                row.sum_provision,
+                row.sum_exec,
+                row.sum_teardown,
+            ]);
+
+            LatencyBreakdown {
+                provider: row.provider,
+                total_runs: row.total_runs.unwrap_or(0),
+                instrumented_runs: row.instrumented_runs.unwrap_or(0),
+                queued: PhaseStats {
+                    avg_ms: row.avg_queued,
+                    p50_ms: row.p50_queued,
+                    p95_ms: row.p95_queued,
+                    share: queued,
+                },
+                provision: PhaseStats {
+                    avg_ms: row.avg_provision,
+                    p50_ms: row.p50_provision,
+                    p95_ms: row.p95_provision,
+                    share: provision,
+                },
+                exec: PhaseStats {
+                    avg_ms: row.avg_exec,
+                    p50_ms: row.p50_exec,
+                    p95_ms: row.p95_exec,
+                    share: exec,
+                },
+                teardown: PhaseStats {
+                    avg_ms: row.avg_teardown,
+                    p50_ms: row.p50_teardown,
+                    p95_ms: row.p95_teardown,
+                    share: teardown,
+                },
+            }
+        })
+        .collect();
+
+    Ok(Json(breakdowns))
+}
+
+/// Each phase's share of the time summed over all phases: `None` for phases
+/// no run reported, and for every phase when no time was reported at all
+fn phase_shares(sums: [Option<f64>; 4]) -> [Option<f64>; 4] {
+    let total: f64 = sums.iter().flatten().sum();
+    sums.map(|sum| sum.filter(|_| total > 0.0).map(|sum| sum / total))
+}
+
+/// Contributions an explanation may carry
+const MAX_CONTRIBUTIONS: usize = 256;
+const MAX_FEATURE_LEN: usize = 128;
+
 pub async fn track_prediction(
     State(state): State<AppState>,
     Json(request): Json<PredictionRequest>,
-) -> AppResult<StatusCode> {
+) -> AppResult<(StatusCode, Json<Prediction>)> {
+    let explanation = request.explanation.map(check_explanation).transpose()?;
     let prediction = Prediction {
-        id: Uuid::new_v4(),
+        id: request.id.unwrap_or_else(Uuid::new_v4),
         provider: request.prediction.provider.clone(),
         predicted_cost: request.prediction.predicted_cost,
         predicted_latency: request.prediction.predicted_latency,
@@ -258,32 +883,31 @@ pub async fn track_prediction(
 
     if let Some(actual) = &request.actual {
         // Calculate prediction errors
-        let cost_error =
-            ((actual.cost - prediction.predicted_cost).abs() / actual.cost * 100.0).min(100.0);
+        let cost_error = ((actual.cost - prediction.predicted_cost).abs() / actual.cost).min(1.0);
         let latency_error =
-            ((actual.latency - prediction.predicted_latency).abs() / actual.latency * 100.0)
-                .min(100.0);
+            ((actual.latency - prediction.predicted_latency).abs() / actual.latency).min(1.0);
 
         state
             .metrics
             .prediction_errors
-            .with_label_values(&[&prediction.model_version, "cost"])
-            .observe(cost_error);
+            .observe(&[&prediction.model_version, "cost"], cost_error, None);
 
         state
             .metrics
             .prediction_errors
-            .with_label_values(&[&prediction.model_version, "latency"])
-            .observe(latency_error);
+            .observe(&[&prediction.model_version, "latency"], latency_error, None);
     }
 
-    sqlx::query!(
+    let explanation = explanation.as_ref().map(serde_json::to_value).transpose()?;
+    let inserted = sqlx::query!(
         r#"
         INSERT INTO predictions (
             id, provider, predicted_cost, predicted_latency, confidence,
-            model_version, actual_cost, actual_latency, actual_success, created_at
+            model_version, actual_cost, actual_latency, actual_success, created_at,
+            explanation
         )
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        ON CONFLICT (id) DO NOTHING
         "#,
         prediction.id,
         prediction.provider,
@@ -294,12 +918,98 @@ pub async fn track_prediction(
         prediction.actual_cost,
         prediction.actual_latency,
         prediction.actual_success,
-        prediction.created_at
+        prediction.created_at,
+        explanation
     )
     .execute(state.db.pool())
     .await?;
+    if inserted.rows_affected() == 0 {
+        return Err(AppError::Conflict(format!(
+            "Prediction {} is already tracked",
+            prediction.id
+        )));
+    }
 
-    Ok(StatusCode::CREATED)
+    Ok((StatusCode::CREATED, Json(prediction)))
+}
+
+/// Check an explanation's contributions and put the largest first
+fn check_explanation(mut explanation: PredictionExplanation) -> AppResult<PredictionExplanation> {
+    if explanation.contributions.len() > MAX_CONTRIBUTIONS {
+        return Err(AppError::Validation(format!(
+            "an explanation may have at most {} contributions, got {}",
+            MAX_CONTRIBUTIONS,
+            explanation.contributions.len()
+        )));
+    }
+    if explanation.base_value.is_some_and(|value| !value.is_finite()) {
+        return Err(AppError::Validation("base_value must be a finite number".to_string()));
+    }
+    let mut seen = std::collections::HashSet::new();
+    for contribution in &explanation.contributions {
+        if contribution.feature.is_empty() || contribution.feature.len() > MAX_FEATURE_LEN {
+            return Err(AppError::Validation(format!(
+                "feature names must be 1-{} bytes",
+                MAX_FEATURE_LEN
+            )));
+        }
+        if !contribution.contribution.is_finite() {
+            return Err(AppError::Validation(format!(
+                "contribution of {} must be a finite number",
+                contribution.feature
+            )));
+        }
+        if !seen.insert((&contribution.feature, &contribution.output)) {
+            return Err(AppError::Validation(format!(
+                "{} contributes to the same output more than once",
+                contribution.feature
+            )));
+        }
+    }
+    explanation
+        .contributions
+        .sort_by(|a, b| b.contribution.abs().total_cmp(&a.contribution.abs()));
+    Ok(explanation)
+}
+
+/// A prediction with the feature contributions behind it, for auditing a
+/// routing decision
+pub async fn get_prediction_explanation(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> AppResult<Json<ExplainedPrediction>> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, provider, predicted_cost, predicted_latency, confidence,
+               model_version, actual_cost, actual_latency, actual_success, created_at,
+               explanation
+        FROM predictions
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(state.db.pool())
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Prediction {} not found", id)))?;
+    let explanation = row.explanation.ok_or_else(|| {
+        AppError::NotFound(format!("Prediction {} was tracked without an explanation", id))
+    })?;
+
+    Ok(Json(ExplainedPrediction {
+        prediction: Prediction {
+            id: row.id,
+            provider: row.provider,
+            predicted_cost: row.predicted_cost,
+            predicted_latency: row.predicted_latency,
+            confidence: row.confidence,
+            model_version: row.model_version,
+            actual_cost: row.actual_cost,
+            actual_latency: row.actual_latency,
+            actual_success: row.actual_success,
+            created_at: row.created_at,
+        },
+        explanation: serde_json::from_value(explanation)?,
+    }))
 }
 
 pub async fn get_model_performance(
@@ -345,3 +1055,145 @@ pub async fn get_model_performance(
         provider_accuracy: performance.provider_accuracy.unwrap_or(0.0),
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gpu(utilization_percent: Option<f64>, memory_used_mb: Option<f64>) -> GpuUsage {
+        GpuUsage {
+            gpu_type: "a100".to_string(),
+            count: Some(1),
+            utilization_percent,
+            memory_used_mb,
+            gpu_seconds: None,
+        }
+    }
+
+    fn validation(result: AppResult<()>) -> String {
+        match result {
+            Err(AppError::Validation(reason)) => reason,
+            other => panic!("expected a validation error, got {:?}", other.map_err(|e| e.to_string())),
+        }
+    }
+
+    #[test]
+    fn gpu_readings_must_be_physical() {
+        assert!(check_gpu(&gpu(Some(0.0), Some(0.0))).is_ok());
+        assert!(check_gpu(&gpu(Some(100.0), Some(40_960.0))).is_ok());
+        assert!(check_gpu(&gpu(None, None)).is_ok());
+
+        for percent in [-0.5, 100.5, f64::NAN] {
+            assert_eq!(
+                validation(check_gpu(&gpu(Some(percent), None))),
+                "gpu.utilization_percent must be between 0 and 100"
+            );
+        }
+        assert_eq!(
+            validation(check_gpu(&gpu(None, Some(-1.0)))),
+            "gpu.memory_used_mb must not be negative"
+        );
+        let idle = GpuUsage {
+            count: Some(0),
+            ..gpu(None, None)
+        };
+        assert_eq!(validation(check_gpu(&idle)), "gpu.count must be at least 1");
+    }
+
+    #[test]
+    fn phase_timings_must_not_be_negative() {
+        let mut request: SandboxRunRequest = serde_json::from_value(serde_json::json!({
+            "sandbox_id": "sandbox",
+            "provider": "e2b",
+            "language": "python",
+            "exit_code": 0,
+            "duration_ms": 1500,
+            "cost": 0.001,
+            "has_gpu": false,
+            "spec": {},
+            "result": {},
+            "queued_ms": 0,
+            "exec_ms": 1200
+        }))
+        .unwrap();
+        assert!(check_phases(&request).is_ok());
+
+        request.teardown_ms = Some(-5);
+        assert_eq!(validation(check_phases(&request)), "teardown_ms must not be negative");
+    }
+
+    #[test]
+    fn phase_shares_split_the_reported_time() {
+        assert_eq!(
+            phase_shares([Some(100.0), Some(300.0), Some(600.0), None]),
+            [Some(0.1), Some(0.3), Some(0.6), None]
+        );
+        // Runs that only report zero-length phases have no shares to give
+        assert_eq!(phase_shares([Some(0.0), None, Some(0.0), None]), [None; 4]);
+        assert_eq!(phase_shares([None; 4]), [None; 4]);
+    }
+
+    /// The migrated database the query macros are checked against
+    async fn database() -> Database {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must name a migrated database");
+        Database::new(&url).await.unwrap()
+    }
+
+    async fn insert_run(db: &Database, provider: &str, gpu: Option<(&str, f64, f64)>, success: bool) {
+        sqlx::query(
+            "INSERT INTO sandbox_runs (
+                id, sandbox_id, provider, language, exit_code, duration_ms, cost, has_gpu, success,
+                gpu_type, gpu_utilization_percent, gpu_memory_used_mb, gpu_seconds
+            ) VALUES ($1, 'sandbox', $2, 'python', 0, 2000, 0.5, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(provider)
+        .bind(gpu.is_some())
+        .bind(success)
+        .bind(gpu.map(|(gpu_type, _, _)| gpu_type))
+        .bind(gpu.map(|(_, utilization, _)| utilization))
+        .bind(gpu.map(|(_, _, memory_mb)| memory_mb))
+        .bind(gpu.map(|_| 2.0))
+        .execute(db.pool())
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn provider_stats_break_runs_down_by_accelerator() {
+        let db = database().await;
+        let provider = format!("gpu-{}", &Uuid::new_v4().simple().to_string()[..12]);
+        insert_run(&db, &provider, Some(("a100", 80.0, 1000.0)), true).await;
+        insert_run(&db, &provider, Some(("a100", 40.0, 3000.0)), false).await;
+        insert_run(&db, &provider, Some(("t4", 10.0, 500.0)), true).await;
+        for _ in 0..3 {
+            insert_run(&db, &provider, None, true).await;
+        }
+
+        let start = Utc::now() - chrono::Duration::minutes(5);
+        let end = Utc::now() + chrono::Duration::minutes(5);
+        let stats = provider_stats(&db, &provider, start, end, true).await.unwrap();
+        assert_eq!(stats.total_runs, 6);
+
+        let accelerators: Vec<(&str, i64)> = stats
+            .by_accelerator
+            .iter()
+            .map(|stats| (stats.accelerator.as_str(), stats.total_runs))
+            .collect();
+        assert_eq!(accelerators, [("none", 3), ("a100", 2), ("t4", 1)]);
+
+        let a100 = &stats.by_accelerator[1];
+        assert_eq!(a100.avg_gpu_utilization_percent, Some(60.0));
+        assert_eq!(a100.avg_gpu_memory_used_mb, Some(2000.0));
+        assert_eq!(a100.total_gpu_seconds, 4.0);
+        assert_eq!(a100.success_rate, 0.5);
+
+        let cpu_only = &stats.by_accelerator[0];
+        assert_eq!(cpu_only.avg_gpu_utilization_percent, None);
+        assert_eq!(cpu_only.total_gpu_seconds, 0.0);
+
+        let summary = provider_stats(&db, &provider, start, end, false).await.unwrap();
+        assert!(summary.by_accelerator.is_empty());
+    }
+}
+
@@ -0,0 +1,48 @@
+use std::collections::BTreeMap;
+
+use crate::error::{AppError, AppResult};
+
+/// Labels a run can carry
+const MAX_LABELS: usize = 16;
+const MAX_KEY_LEN: usize = 63;
+const MAX_VALUE_LEN: usize = 255;
+
+/// Check a label key: lowercase letters, digits, `-`, `_`, `.` and `/`,
+/// starting with a letter or digit
+pub fn check_key(key: &str) -> AppResult<()> {
+    let valid = !key.is_empty()
+        && key.len() <= MAX_KEY_LEN
+        && key.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-_./".contains(c));
+    if valid {
+        Ok(())
+    } else {
+        Err(AppError::Validation(format!(
+            "label key {:?} must be 1-{} lowercase letters, digits, '-', '_', '.' or '/'",
+            key, MAX_KEY_LEN
+        )))
+    }
+}
+
+/// Check a run's labels and turn them into the JSON object stored with it
+pub fn to_json(labels: &BTreeMap<String, String>) -> AppResult<serde_json::Value> {
+    if labels.len() > MAX_LABELS {
+        return Err(AppError::Validation(format!(
+            "at most {} labels are allowed, got {}",
+            MAX_LABELS,
+            labels.len()
+        )));
+    }
+    for (key, value) in labels {
+        check_key(key)?;
+        if value.is_empty() || value.len() > MAX_VALUE_LEN {
+            return Err(AppError::Validation(format!(
+                "label {} must have a value of 1-{} bytes",
+                key, MAX_VALUE_LEN
+            )));
+        }
+    }
+    Ok(serde_json::to_value(labels)?)
+}
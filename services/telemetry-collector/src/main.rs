@@ -3,60 +3,103 @@ use axum::{
     routing::{get, post},
     Router,
 };
-use std::net::SocketAddr;
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
-use tracing::{info, Level};
+use std::{net::SocketAddr, sync::Arc};
+use tower_http::trace::TraceLayer;
+use tracing::info;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod anomaly;
+mod classify;
 mod config;
 mod db;
+mod delivery;
+mod drills;
 mod error;
+mod forecast;
+mod graphql;
 mod handlers;
+mod labels;
 mod metrics;
 mod models;
+mod pricing;
+mod reports;
 
 use crate::config::Config;
 use crate::db::Database;
+use sandstorm_config::ConfigHandle;
 use crate::metrics::Metrics;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: Database,
-    pub config: Config,
+    pub config: ConfigHandle<Config>,
     pub metrics: Metrics,
+    pub graphql: graphql::DashboardSchema,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Initialize tracing
+    let log_shipping = sandstorm_logging::ShippingSettings::from_env("TELEMETRY")?;
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "telemetry_collector=debug,tower_http=debug".into()),
         )
         .with(tracing_subscriber::fmt::layer())
+        .with(log_shipping.map(|settings| sandstorm_logging::ShippingLayer::spawn(settings, "telemetry-collector")))
         .init();
 
     // Load configuration
-    let config = Config::load()?;
+    let config = Config::load().await?;
+    config.spawn_sighup_reloader();
     info!("Loaded configuration");
+    let startup = config.current();
 
     // Initialize database
-    let db = Database::new(&config.database_url).await?;
+    let db = Database::new(&startup.database_url).await?;
     db.run_migrations().await?;
     info!("Connected to database and ran migrations");
+    let database_breaker = sandstorm_http::CircuitBreaker::new(
+        "postgres",
+        sandstorm_http::BreakerSettings::from_env("TELEMETRY_DB")?,
+    );
 
     // Initialize metrics
     let metrics = Metrics::new();
+    let shared_metrics = metrics.shared.clone();
+    sandstorm_http::register_breakers(shared_metrics.registry(), std::slice::from_ref(&database_breaker))?;
 
     // Create app state
     let state = AppState {
+        graphql: graphql::schema(db.clone()),
         db,
         config: config.clone(),
         metrics,
     };
 
+    // Start edge anomaly alerting
+    tokio::spawn(anomaly::alerting_task(state.clone()));
+
+    // Push metrics where nothing scrapes the collector
+    tokio::spawn(metrics::remote_write_task(state.clone()));
+
+    // Disaster-recovery backups of the telemetry database
+    let backups = sandstorm_backup::Backups::from_env(
+        "telemetry-collector",
+        "TELEMETRY",
+        Arc::new(sandstorm_backup::PgBackup::new(
+            state.db.pool().clone(),
+            db::BACKUP_TABLES,
+        )),
+    )?;
+    if let Some(backups) = &backups {
+        sandstorm_backup::spawn_schedule(backups.clone(), "TELEMETRY");
+        info!("Backups enabled");
+    }
+
     // Build application
+    let http_security = sandstorm_http::HttpSecurity::from_env("TELEMETRY")?;
     let app = Router::new()
         // Health check
         .route("/health", get(handlers::health::health_check))
@@ -66,6 +109,11 @@ async fn main() -> Result<()> {
             "/api/telemetry/sandbox-run",
             post(handlers::telemetry::track_sandbox_run),
         )
+        .route("/api/telemetry/runs", get(handlers::telemetry::list_runs))
+        .route(
+            "/api/telemetry/preemptions",
+            post(handlers::telemetry::track_preemption).get(handlers::telemetry::list_preemptions),
+        )
         .route(
             "/api/telemetry/training-data",
             get(handlers::telemetry::get_training_data),
@@ -79,19 +127,110 @@ async fn main() -> Result<()> {
             "/api/telemetry/provider-stats/:provider",
             get(handlers::telemetry::get_provider_stats),
         )
+        .route(
+            "/api/telemetry/latency-breakdown",
+            get(handlers::telemetry::get_latency_breakdown),
+        )
+        .route(
+            "/api/telemetry/failures",
+            get(handlers::telemetry::get_failure_breakdown),
+        )
+        .route(
+            "/api/telemetry/forecast",
+            get(handlers::telemetry::get_forecast),
+        )
+        // Security incidents forwarded by the security monitor
+        .route(
+            "/api/telemetry/security-signals",
+            post(handlers::security::ingest_security_signals),
+        )
+        .route(
+            "/api/telemetry/security-incidents",
+            get(handlers::security::get_security_incidents),
+        )
+        // Structured logs shipped by the services
+        .route(
+            "/api/telemetry/logs",
+            post(handlers::logs::ingest_logs).get(handlers::logs::list_logs),
+        )
+        // Runtime benchmarks
+        .route(
+            "/api/telemetry/benchmarks",
+            post(handlers::benchmarks::store_benchmark_results)
+                .get(handlers::benchmarks::list_benchmark_results),
+        )
+        .route(
+            "/api/telemetry/benchmarks/compare",
+            get(handlers::benchmarks::compare_benchmarks),
+        )
         // Model performance tracking
         .route(
             "/api/telemetry/predictions",
             post(handlers::telemetry::track_prediction),
         )
+        .route(
+            "/api/telemetry/predictions/:id/explanation",
+            get(handlers::telemetry::get_prediction_explanation),
+        )
         .route(
             "/api/telemetry/model-performance/:version",
             get(handlers::telemetry::get_model_performance),
         )
+        // Pricing catalog
+        .route(
+            "/api/pricing/rates",
+            post(handlers::pricing::add_rate).get(handlers::pricing::list_rates),
+        )
+        .route(
+            "/api/pricing/recompute",
+            post(handlers::pricing::recompute_costs),
+        )
+        .route(
+            "/api/pricing/divergences",
+            get(handlers::pricing::list_divergences),
+        )
+        // Failover drills
+        .route(
+            "/api/drills/failover",
+            post(handlers::drills::failover_drill),
+        )
+        // Usage reports
+        .route(
+            "/api/reports/usage",
+            post(handlers::reports::create_usage_report)
+                .get(handlers::reports::list_usage_reports),
+        )
+        .route(
+            "/api/reports/usage/:id",
+            get(handlers::reports::get_usage_report),
+        )
+        .route(
+            "/api/reports/usage/:id/download",
+            get(handlers::reports::download_usage_report),
+        )
+        // Tenant quotas
+        .route("/api/quotas", get(handlers::quotas::list_quotas))
+        .route(
+            "/api/quotas/:tenant",
+            get(handlers::quotas::get_quota)
+                .put(handlers::quotas::put_quota)
+                .delete(handlers::quotas::delete_quota),
+        )
+        .route(
+            "/api/quotas/:tenant/usage",
+            get(handlers::quotas::get_quota_usage),
+        )
+        // Data deletion
+        .route("/api/deletions", post(handlers::deletions::delete_data))
         // Edge agent ingestion
         .route("/v1/edge/status", post(handlers::edge::ingest_status))
         .route("/v1/edge/metrics", post(handlers::edge::ingest_metrics))
         .route("/v1/edge/logs", post(handlers::edge::ingest_logs))
+        .route("/v1/edge/capacity", get(handlers::edge::capacity_feed))
+        .route(
+            "/v1/edge/agents/:id/cursors",
+            get(handlers::edge::list_agent_cursors),
+        )
         // Edge agent queries
         .route(
             "/api/edge/agents/overview",
@@ -101,19 +240,50 @@ async fn main() -> Result<()> {
             "/api/edge/agents/:id/runs",
             get(handlers::edge::list_agent_runs),
         )
-        // Metrics endpoint for Prometheus
-        .route("/metrics", get(handlers::metrics::metrics_handler))
+        .route("/api/edge/anomalies", get(handlers::edge::list_anomalies))
+        // Dashboard queries
+        .route(
+            "/api/graphql",
+            post(graphql::execute).get(graphql::sdl),
+        )
         // Add middleware
-        .layer(CorsLayer::permissive())
-        .layer(TraceLayer::new_for_http())
-        .with_state(state);
+        .with_state(state)
+        // Shed requests while the database is down
+        .layer(axum::middleware::from_fn_with_state(
+            sandstorm_http::LoadShedding::new(database_breaker),
+            sandstorm_http::shed_load,
+        ))
+        // Metrics endpoint for Prometheus
+        .merge(sandstorm_metrics::metrics_router(shared_metrics.clone()))
+        // Runtime configuration
+        .merge(sandstorm_config::admin_router(
+            config,
+            startup.admin_token.clone(),
+        ))
+        // Backup and restore
+        .merge(
+            backups
+                .map(|backups| sandstorm_backup::backup_router(backups, startup.admin_token.clone()))
+                .unwrap_or_default(),
+        )
+        .layer(axum::middleware::from_fn_with_state(
+            shared_metrics,
+            sandstorm_metrics::track_http,
+        ));
+    let app = http_security.apply(app).layer(TraceLayer::new_for_http());
 
     // Start server
-    let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
+    let addr = SocketAddr::from(([0, 0, 0, 0], startup.port));
     info!("Starting telemetry collector on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    match sandstorm_tls::TlsSettings::from_env("TELEMETRY")? {
+        Some(tls) => {
+            info!("mTLS enabled");
+            sandstorm_tls::serve(listener, app, tls).await?;
+        }
+        None => axum::serve(listener, app).await?,
+    }
 
     Ok(())
 }
@@ -1,96 +1,163 @@
-use prometheus::{
-    Counter, CounterVec, Encoder, Histogram, HistogramOpts, HistogramVec, Opts, Registry,
-    TextEncoder,
-};
-use std::sync::Arc;
+use prometheus::CounterVec;
+use sandstorm_metrics::{ExemplarHistogram, RemoteWriteConfig, RemoteWriter};
+use tracing::{debug, warn};
 
+use crate::AppState;
+
+/// Bytes in a megabyte, the unit agents report GPU memory in
+pub const MIB: f64 = 1024.0 * 1024.0;
+
+/// Collector metrics, registered under the shared `sandstorm_` naming scheme
 #[derive(Clone)]
 pub struct Metrics {
     pub sandbox_runs_total: CounterVec,
-    pub sandbox_run_duration: HistogramVec,
-    pub sandbox_run_cost: HistogramVec,
+    pub sandbox_failures_total: CounterVec,
+    pub sandbox_run_duration: ExemplarHistogram,
+    pub sandbox_run_cost: ExemplarHistogram,
+    pub sandbox_cost_divergent_total: CounterVec,
+    pub sandbox_phase_duration: ExemplarHistogram,
+    pub sandbox_preemptions_total: CounterVec,
+    pub gpu_utilization: ExemplarHistogram,
+    pub gpu_memory_used: ExemplarHistogram,
+    pub gpu_seconds: ExemplarHistogram,
     pub predictions_total: CounterVec,
-    pub prediction_errors: HistogramVec,
-    pub api_requests_total: CounterVec,
-    pub api_request_duration: HistogramVec,
-    registry: Arc<Registry>,
+    pub prediction_errors: ExemplarHistogram,
+    pub shared: sandstorm_metrics::Metrics,
 }
 
 impl Metrics {
     pub fn new() -> Self {
-        let registry = Registry::new();
+        let shared = sandstorm_metrics::Metrics::new("telemetry-collector");
 
         // Sandbox run metrics
-        let sandbox_runs_total = CounterVec::new(
-            Opts::new("sandbox_runs_total", "Total number of sandbox runs"),
+        let sandbox_runs_total = shared.counter(
+            "sandbox_runs_total",
+            "Total number of sandbox runs",
             &["provider", "language", "success"],
-        )
-        .unwrap();
+        );
 
-        let sandbox_run_duration = HistogramVec::new(
-            HistogramOpts::new("sandbox_run_duration_ms", "Sandbox run duration in milliseconds"),
+        let sandbox_failures_total = shared.counter(
+            "sandbox_failures_total",
+            "Failed sandbox runs by root cause",
+            &["provider", "language", "class"], // class: oom, timeout, nonzero_exit, provider_error, infra_error
+        );
+
+        let sandbox_run_duration = shared.histogram(
+            "sandbox_run_duration_seconds",
+            "Sandbox run duration in seconds",
             &["provider", "language"],
-        )
-        .unwrap();
+            sandstorm_metrics::LATENCY_BUCKETS.to_vec(),
+        );
 
-        let sandbox_run_cost = HistogramVec::new(
-            HistogramOpts::new("sandbox_run_cost", "Sandbox run cost"),
+        let sandbox_run_cost = shared.histogram(
+            "sandbox_run_cost_dollars",
+            "Sandbox run cost in US dollars",
             &["provider"],
-        )
-        .unwrap();
+            prometheus::exponential_buckets(0.0001, 4.0, 10).unwrap(),
+        );
+
+        let sandbox_cost_divergent_total = shared.counter(
+            "sandbox_cost_divergent_total",
+            "Runs whose reported cost is off from the pricing catalog estimate",
+            &["provider"],
+        );
+
+        let sandbox_phase_duration = shared.histogram(
+            "sandbox_phase_duration_seconds",
+            "Sandbox lifecycle phase duration in seconds",
+            &["provider", "phase"], // phase: queued, provision, exec, teardown
+            prometheus::exponential_buckets(0.005, 2.5, 10).unwrap(),
+        );
+
+        let sandbox_preemptions_total = shared.counter(
+            "sandbox_preemptions_total",
+            "Sandboxes preempted for higher-priority work, and resumed afterwards",
+            &["provider", "priority", "action"], // action: preempted, resumed
+        );
+
+        // Accelerator metrics
+        let gpu_utilization = shared.histogram(
+            "sandbox_gpu_utilization_ratio",
+            "GPU utilization per run, from 0 to 1",
+            &["provider", "gpu_type"],
+            vec![0.05, 0.1, 0.25, 0.5, 0.75, 0.9, 1.0],
+        );
+
+        let gpu_memory_used = shared.histogram(
+            "sandbox_gpu_memory_used_bytes",
+            "GPU memory used per run in bytes",
+            &["provider", "gpu_type"],
+            prometheus::exponential_buckets(256.0 * MIB, 2.0, 9).unwrap(),
+        );
+
+        let gpu_seconds = shared.histogram(
+            "sandbox_gpu_seconds",
+            "GPU-seconds consumed per run",
+            &["provider", "gpu_type"],
+            prometheus::exponential_buckets(1.0, 4.0, 8).unwrap(),
+        );
 
         // Prediction metrics
-        let predictions_total = CounterVec::new(
-            Opts::new("predictions_total", "Total number of predictions made"),
+        let predictions_total = shared.counter(
+            "predictions_total",
+            "Total number of predictions made",
             &["model_version", "provider"],
-        )
-        .unwrap();
+        );
 
-        let prediction_errors = HistogramVec::new(
-            HistogramOpts::new("prediction_error_percentage", "Prediction error percentage"),
+        let prediction_errors = shared.histogram(
+            "prediction_error_ratio",
+            "Relative prediction error, capped at 1",
             &["model_version", "metric_type"], // metric_type: cost or latency
-        )
-        .unwrap();
-
-        // API metrics
-        let api_requests_total = CounterVec::new(
-            Opts::new("api_requests_total", "Total number of API requests"),
-            &["endpoint", "method", "status"],
-        )
-        .unwrap();
-
-        let api_request_duration = HistogramVec::new(
-            HistogramOpts::new("api_request_duration_seconds", "API request duration in seconds"),
-            &["endpoint", "method"],
-        )
-        .unwrap();
-
-        // Register all metrics
-        registry.register(Box::new(sandbox_runs_total.clone())).unwrap();
-        registry.register(Box::new(sandbox_run_duration.clone())).unwrap();
-        registry.register(Box::new(sandbox_run_cost.clone())).unwrap();
-        registry.register(Box::new(predictions_total.clone())).unwrap();
-        registry.register(Box::new(prediction_errors.clone())).unwrap();
-        registry.register(Box::new(api_requests_total.clone())).unwrap();
-        registry.register(Box::new(api_request_duration.clone())).unwrap();
+            vec![0.01, 0.05, 0.1, 0.25, 0.5, 0.75, 1.0],
+        );
 
         Self {
             sandbox_runs_total,
+            sandbox_failures_total,
             sandbox_run_duration,
             sandbox_run_cost,
+            sandbox_cost_divergent_total,
+            sandbox_phase_duration,
+            sandbox_preemptions_total,
+            gpu_utilization,
+            gpu_memory_used,
+            gpu_seconds,
             predictions_total,
             prediction_errors,
-            api_requests_total,
-            api_request_duration,
-            registry: Arc::new(registry),
+            shared,
         }
     }
+}
 
-    pub fn export(&self) -> String {
-        let encoder = TextEncoder::new();
-        let metric_families = self.registry.gather();
-        let mut buffer = Vec::new();
-        encoder.encode(&metric_families, &mut buffer).unwrap();
-        String::from_utf8(buffer).unwrap()
+/// Push metrics to the configured remote-write endpoint, if any
+pub async fn remote_write_task(state: AppState) {
+    let writer = RemoteWriter::new(&state.metrics.shared);
+    loop {
+        let config = state.config.current();
+        tokio::time::sleep(std::time::Duration::from_secs(
+            config.remote_write_interval_secs,
+        ))
+        .await;
+
+        let Some(url) = config.remote_write_url.clone() else {
+            continue;
+        };
+        let outcome = writer
+            .push(&RemoteWriteConfig {
+                url,
+                bearer_token: config.remote_write_token.clone(),
+                max_samples_per_request: config.remote_write_max_samples,
+                max_retries: config.remote_write_max_retries,
+            })
+            .await;
+        match outcome.error {
+            None => debug!(samples = outcome.sent, "pushed metrics"),
+            Some(error) => warn!(
+                failed = outcome.failed,
+                sent = outcome.sent,
+                %error,
+                "metrics remote write failed"
+            ),
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,29 +1,16 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
-pub struct SandboxRun {
-    pub id: Uuid,
-    pub sandbox_id: String,
-    pub provider: String,
-    pub language: String,
-    pub exit_code: i32,
-    pub duration_ms: i64,
-    pub cost: f64,
-    pub cpu_requested: Option<f64>,
-    pub memory_requested: Option<i32>,
-    pub has_gpu: bool,
-    pub timeout_ms: Option<i64>,
-    pub success: bool,
-    pub cpu_percent: Option<f64>,
-    pub memory_mb: Option<f64>,
-    pub network_rx_bytes: Option<i64>,
-    pub network_tx_bytes: Option<i64>,
-    pub agent_id: Option<String>,
-    pub created_at: DateTime<Utc>,
-}
+pub use sandstorm_types::logs::{LogBatch, LogRecord};
+pub use sandstorm_types::quota::{QuotaAction, QuotaUsage, TenantQuota};
+pub use sandstorm_types::security::SecuritySignalBatch;
+pub use sandstorm_types::telemetry::{
+    AcceleratorStats, BenchmarkComparison, BenchmarkResult, EdgeCapacity, EdgeCapacityFeed,
+    PreemptionEvent, ProviderStats, SandboxRun,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SandboxRunRequest {
@@ -51,6 +38,45 @@ pub struct SandboxRunRequest {
     pub agent_id: Option<String>,
     #[serde(default)]
     pub timestamp: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub gpu: Option<GpuUsage>,
+    /// Time spent waiting for scheduling before provisioning started
+    #[serde(default)]
+    pub queued_ms: Option<i64>,
+    /// Time spent provisioning the sandbox (cold start)
+    #[serde(default)]
+    pub provision_ms: Option<i64>,
+    /// Time spent executing the workload
+    #[serde(default)]
+    pub exec_ms: Option<i64>,
+    /// Time spent tearing the sandbox down
+    #[serde(default)]
+    pub teardown_ms: Option<i64>,
+    /// Gateway run ID; the `X-Sandstorm-Run-Id` header is used when absent
+    #[serde(default)]
+    pub run_id: Option<Uuid>,
+    /// Tenant billed for the run; the `X-Sandstorm-Tenant` header is used
+    /// when absent, then the default tenant
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Attribution labels such as `team`, `project` or `job`
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Accelerator usage reported alongside a sandbox run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GpuUsage {
+    pub gpu_type: String,
+    #[serde(default)]
+    pub count: Option<i32>,
+    #[serde(default)]
+    pub utilization_percent: Option<f64>,
+    #[serde(default)]
+    pub memory_used_mb: Option<f64>,
+    /// Accelerator-seconds consumed; derived from duration and count when omitted
+    #[serde(default)]
+    pub gpu_seconds: Option<f64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -87,11 +113,51 @@ pub struct Prediction {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PredictionRequest {
+    /// ID to track the prediction under, so the router can refer to it
+    /// later; a new one when unset
+    #[serde(default)]
+    pub id: Option<Uuid>,
     pub prediction: PredictionData,
     pub actual: Option<ActualData>,
+    /// Why the model predicted what it did
+    #[serde(default)]
+    pub explanation: Option<PredictionExplanation>,
     pub timestamp: DateTime<Utc>,
 }
 
+/// Each feature's contribution to a model's output (SHAP values or
+/// similar), on top of the output's base value
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PredictionExplanation {
+    /// The model's output before any feature is accounted for
+    #[serde(default)]
+    pub base_value: Option<f64>,
+    /// Largest contributions first, whatever their sign
+    pub contributions: Vec<FeatureContribution>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeatureContribution {
+    pub feature: String,
+    /// The feature's value as the model saw it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<serde_json::Value>,
+    /// How far the feature moved the output, up or down
+    pub contribution: f64,
+    /// Output the contribution is to (`cost`, `latency`, ...), for models
+    /// with more than one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+/// A prediction and the explanation it was tracked with
+#[derive(Debug, Serialize)]
+pub struct ExplainedPrediction {
+    pub prediction: Prediction,
+    #[serde(flatten)]
+    pub explanation: PredictionExplanation,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PredictionData {
     pub provider: String,
@@ -109,11 +175,24 @@ pub struct ActualData {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct ProviderStats {
-    pub avg_latency: f64,
-    pub avg_cost: f64,
-    pub success_rate: f64,
+pub struct LatencyBreakdown {
+    pub provider: String,
     pub total_runs: i64,
+    /// Runs that reported at least one phase timing
+    pub instrumented_runs: i64,
+    pub queued: PhaseStats,
+    pub provision: PhaseStats,
+    pub exec: PhaseStats,
+    pub teardown: PhaseStats,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PhaseStats {
+    pub avg_ms: Option<f64>,
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    /// Share of the summed phase time spent in this phase
+    pub share: Option<f64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -130,6 +209,26 @@ pub struct TimeRange {
     pub end: Option<DateTime<Utc>>,
 }
 
+/// A stored log record and the service instance that shipped it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServiceLog {
+    pub service: String,
+    pub instance: String,
+    #[serde(flatten)]
+    pub record: LogRecord,
+}
+
+/// Incidents the security monitor reported for one provider and image
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SecurityIncidents {
+    pub provider: String,
+    pub image: Option<String>,
+    /// Sandboxes with at least one violation or quarantine
+    pub sandboxes: i64,
+    pub violations: i64,
+    pub quarantines: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct EdgeAgentRunSummary {
@@ -211,6 +310,13 @@ pub struct EdgeAgentLogDto {
 pub struct EdgeStatusBatchRequest {
     pub items: Vec<EdgeAgentStatusDto>,
     pub timestamp: DateTime<Utc>,
+    /// Agent session the sequence number belongs to, for acknowledged
+    /// delivery
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// Position of the batch in its stream, from 1
+    #[serde(default)]
+    pub sequence: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -218,6 +324,13 @@ pub struct EdgeStatusBatchRequest {
 pub struct EdgeMetricsBatchRequest {
     pub items: Vec<EdgeAgentMetricsDto>,
     pub timestamp: DateTime<Utc>,
+    /// Agent session the sequence number belongs to, for acknowledged
+    /// delivery
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// Position of the batch in its stream, from 1
+    #[serde(default)]
+    pub sequence: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -225,31 +338,35 @@ pub struct EdgeMetricsBatchRequest {
 pub struct EdgeLogBatchRequest {
     pub items: Vec<EdgeAgentLogDto>,
     pub timestamp: DateTime<Utc>,
+    /// Agent session the sequence number belongs to, for acknowledged
+    /// delivery
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// Position of the batch in its stream, from 1
+    #[serde(default)]
+    pub sequence: Option<i64>,
 }
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
-pub struct EdgeAgentStatusRecord {
-    pub agent_id: String,
-    pub agent_name: Option<String>,
-    pub status: String,
-    pub version: String,
-    pub queue_depth: i32,
-    pub running: i32,
-    pub completed: i32,
-    pub failed: i32,
-    pub cpu_percent: Option<f64>,
-    pub memory_percent: Option<f64>,
-    pub last_heartbeat: DateTime<Utc>,
-    pub public_endpoint: Option<String>,
-    pub payload: serde_json::Value,
+/// Reply to an edge batch
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EdgeBatchAck {
+    /// Highest sequence number of the session now stored; batches up to it
+    /// can be dropped. Unset for batches sent without one.
+    pub acknowledged: Option<i64>,
+    /// The batch had been stored before and was ignored
+    pub duplicate: bool,
 }
 
+/// How far the collector has stored one of an agent's batch streams
 #[derive(Debug, Serialize, Deserialize, FromRow)]
-pub struct EdgeAgentMetricsRecord {
-    pub id: Uuid,
-    pub agent_id: String,
-    pub recorded_at: DateTime<Utc>,
-    pub payload: serde_json::Value,
+#[serde(rename_all = "camelCase")]
+pub struct EdgeStreamCursor {
+    pub stream: String,
+    pub session_id: String,
+    /// Highest sequence number stored; agents retransmit from the next one
+    pub sequence: i64,
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -267,3 +384,391 @@ pub struct EdgeAgentRunRecord {
     pub network_tx_bytes: Option<i64>,
     pub finished_at: DateTime<Utc>,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyKind {
+    FailureRate,
+    QueueGrowth,
+    MemoryPressure,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EdgeAnomaly {
+    pub agent_id: String,
+    pub kind: AnomalyKind,
+    /// Value observed over the detection window
+    pub observed: f64,
+    /// Mean of the agent's own history (if enough samples exist)
+    pub agent_baseline: Option<f64>,
+    /// Mean of the same signal across the fleet over the detection window
+    pub fleet_baseline: Option<f64>,
+    pub agent_z_score: Option<f64>,
+    pub fleet_z_score: Option<f64>,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// What a catalog rate is charged per
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceUnit {
+    /// Each run
+    Run,
+    /// Each second of run duration
+    Second,
+    /// Each requested CPU core per second
+    CpuSecond,
+    /// Each requested GB of memory per second
+    GbSecond,
+    /// Each accelerator-second
+    GpuSecond,
+    /// Each GB sent from the sandbox
+    EgressGb,
+}
+
+impl PriceUnit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PriceUnit::Run => "run",
+            PriceUnit::Second => "second",
+            PriceUnit::CpuSecond => "cpu_second",
+            PriceUnit::GbSecond => "gb_second",
+            PriceUnit::GpuSecond => "gpu_second",
+            PriceUnit::EgressGb => "egress_gb",
+        }
+    }
+
+    pub fn parse(unit: &str) -> Option<Self> {
+        match unit {
+            "run" => Some(PriceUnit::Run),
+            "second" => Some(PriceUnit::Second),
+            "cpu_second" => Some(PriceUnit::CpuSecond),
+            "gb_second" => Some(PriceUnit::GbSecond),
+            "gpu_second" => Some(PriceUnit::GpuSecond),
+            "egress_gb" => Some(PriceUnit::EgressGb),
+            _ => None,
+        }
+    }
+}
+
+/// A provider's price for one unit, in US dollars, from `effective_from`
+/// until a later rate for the same unit takes over
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriceRate {
+    pub id: Uuid,
+    pub provider: String,
+    pub unit: PriceUnit,
+    pub rate: f64,
+    pub effective_from: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PriceRateRequest {
+    pub provider: String,
+    pub unit: PriceUnit,
+    pub rate: f64,
+    /// Defaults to now
+    #[serde(default)]
+    pub effective_from: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecomputeRequest {
+    pub provider: Option<String>,
+    /// Runs created from here on (default: all)
+    pub since: Option<DateTime<Utc>>,
+    /// Runs created before here (default: now)
+    pub until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RecomputeReport {
+    pub runs_repriced: u64,
+    /// Runs of providers with no rates in effect when they ran
+    pub runs_unpriced: u64,
+    /// Priced runs whose reported cost diverges from the estimate
+    pub runs_divergent: u64,
+}
+
+/// A run whose reported cost is off from its catalog estimate
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CostDivergence {
+    pub id: Uuid,
+    pub sandbox_id: String,
+    pub provider: String,
+    pub cost: f64,
+    pub estimated_cost: f64,
+    /// Relative difference from the estimate
+    pub divergence: f64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFormat {
+    Json,
+    Csv,
+    Pdf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportStatus {
+    Pending,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UsageReportRequest {
+    /// Calendar month to report on, as `YYYY-MM` in UTC
+    pub month: String,
+    /// Report on one tenant only (default: every tenant)
+    #[serde(default)]
+    pub tenant: Option<String>,
+    #[serde(default = "default_report_format")]
+    pub format: ReportFormat,
+    /// Also break each tenant's usage down by this label's values
+    #[serde(default)]
+    pub group_by_label: Option<String>,
+    /// Report only on runs carrying all of these labels
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+fn default_report_format() -> ReportFormat {
+    ReportFormat::Json
+}
+
+/// A usage report generation job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageReport {
+    pub id: Uuid,
+    pub tenant: Option<String>,
+    pub month: String,
+    pub format: ReportFormat,
+    pub status: ReportStatus,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    /// Where to fetch the report once it's completed
+    pub download_url: Option<String>,
+    pub group_by_label: Option<String>,
+    pub labels: BTreeMap<String, String>,
+}
+
+/// A tenant's usage over a report's month
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TenantUsage {
+    pub tenant: String,
+    pub sandboxes: i64,
+    pub compute_seconds: f64,
+    /// Cost as reported by the runs
+    pub cost: f64,
+    /// Sum of the catalog estimates of runs that have one
+    pub estimated_cost: Option<f64>,
+    pub failures: i64,
+    /// Fraction of sandboxes that failed, from 0.0 to 1.0
+    pub failure_rate: f64,
+    /// Usage by provider, busiest first
+    pub providers: Vec<ProviderUsage>,
+    /// Usage by value of the report's `group_by_label`, busiest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<LabelUsage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderUsage {
+    pub provider: String,
+    pub sandboxes: i64,
+    /// Fraction of the tenant's sandboxes run on this provider
+    pub share: f64,
+    pub compute_seconds: f64,
+    pub cost: f64,
+    pub estimated_cost: Option<f64>,
+    pub failures: i64,
+    pub failure_rate: f64,
+}
+
+/// A tenant's usage by runs sharing a value of a label
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LabelUsage {
+    /// Value of the label, unset for runs without it
+    pub value: Option<String>,
+    pub sandboxes: i64,
+    /// Fraction of the tenant's sandboxes carrying this value
+    pub share: f64,
+    pub compute_seconds: f64,
+    pub cost: f64,
+    pub estimated_cost: Option<f64>,
+    pub failures: i64,
+    pub failure_rate: f64,
+}
+
+/// Content of a JSON usage report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageReportContent {
+    pub month: String,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub generated_at: DateTime<Utc>,
+    /// Label the tenants' usage is broken down by
+    #[serde(default)]
+    pub group_by_label: Option<String>,
+    /// Labels every counted run carries
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    pub tenants: Vec<TenantUsage>,
+}
+
+/// Hourly series the capacity forecast can project
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForecastMetric {
+    /// Sandboxes started per hour
+    #[default]
+    Sandboxes,
+    /// Average sandboxes running at once, for provider concurrency quotas
+    Concurrency,
+    /// Sandboxes running or queued across the edge fleet, for edge hosts
+    EdgeSlots,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForecastPoint {
+    /// Start of the hour
+    pub timestamp: DateTime<Utc>,
+    pub value: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Forecast {
+    pub metric: ForecastMetric,
+    pub provider: Option<String>,
+    /// Length of the repeating pattern fitted, 24 or 168 hours
+    pub season_hours: usize,
+    pub history_start: DateTime<Utc>,
+    pub history_end: DateTime<Utc>,
+    /// Probability each actual value falls within its point's band
+    pub confidence: f64,
+    pub points: Vec<ForecastPoint>,
+    /// Point with the highest forecast value
+    pub peak: Option<ForecastPoint>,
+    /// First point whose upper bound exceeds the `capacity` asked about
+    pub capacity_exceeded_at: Option<DateTime<Utc>>,
+}
+
+/// Root cause of a failed sandbox run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureClass {
+    /// Killed for exceeding its memory
+    Oom,
+    /// Ran into its time limit
+    Timeout,
+    /// The workload itself exited with an error
+    NonzeroExit,
+    /// The provider failed or refused the run
+    ProviderError,
+    /// The sandbox couldn't be set up, e.g. image pulls or disk space
+    InfraError,
+}
+
+impl FailureClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureClass::Oom => "oom",
+            FailureClass::Timeout => "timeout",
+            FailureClass::NonzeroExit => "nonzero_exit",
+            FailureClass::ProviderError => "provider_error",
+            FailureClass::InfraError => "infra_error",
+        }
+    }
+}
+
+/// Failed runs of one provider and language, by root cause
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FailureBreakdown {
+    pub provider: String,
+    pub language: String,
+    pub total_runs: i64,
+    pub failures: i64,
+    pub by_class: Vec<FailureClassCount>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FailureClassCount {
+    pub class: String,
+    pub count: i64,
+    /// Share of the failures with this cause
+    pub share: f64,
+}
+
+/// A tenant's monthly caps; omitted caps are unlimited
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuotaRequest {
+    #[serde(default)]
+    pub compute_seconds: Option<f64>,
+    #[serde(default)]
+    pub cost: Option<f64>,
+    #[serde(default)]
+    pub on_exhausted: QuotaAction,
+}
+
+/// A provider outage to rehearse against the traffic of a past window
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FailoverDrillRequest {
+    /// The provider taken down
+    pub provider: String,
+    /// When the simulated outage starts
+    pub start: DateTime<Utc>,
+    /// When it ends (default: now)
+    #[serde(default)]
+    pub end: Option<DateTime<Utc>>,
+    /// Days before `start` whose traffic shows which providers could take
+    /// over and what they charge (default: 7)
+    #[serde(default)]
+    pub lookback_days: Option<i64>,
+    /// Providers whose runs succeeded less often than this over the
+    /// lookback aren't failed over to (default: 0.9)
+    #[serde(default)]
+    pub min_success_rate: Option<f64>,
+}
+
+/// Where a provider's traffic would have gone during a simulated outage,
+/// and what it would have cost
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FailoverDrillReport {
+    pub provider: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Runs of the provider during the outage
+    pub runs: u64,
+    pub rerouted_runs: u64,
+    /// Runs no other healthy provider could have taken, such as GPU runs
+    /// when no other provider ran GPUs
+    pub unroutable_runs: u64,
+    /// Cost of the rerouted runs where they ran
+    pub original_cost: f64,
+    /// Their cost where they'd have gone
+    pub rerouted_cost: f64,
+    pub cost_delta: f64,
+    /// Providers taking the traffic, busiest first
+    pub targets: Vec<FailoverTarget>,
+}
+
+/// The share of a drill's traffic one provider would have taken
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FailoverTarget {
+    pub provider: String,
+    pub runs: u64,
+    pub gpu_runs: u64,
+    pub original_cost: f64,
+    pub rerouted_cost: f64,
+    pub cost_delta: f64,
+    /// Its success rate over the lookback
+    pub success_rate: f64,
+}
@@ -0,0 +1,175 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+use crate::db::Database;
+use crate::error::{AppError, AppResult};
+use crate::models::{PriceRate, PriceUnit, SandboxRun};
+
+/// Provider rates, from which runs are priced at the rates in effect when
+/// they ran
+pub struct PriceCatalog {
+    /// Rates by provider, oldest first
+    rates: HashMap<String, Vec<PriceRate>>,
+}
+
+impl PriceCatalog {
+    /// Load the rates of one provider, or of all of them
+    pub async fn load(db: &Database, provider: Option<&str>) -> AppResult<Self> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT * FROM provider_rates
+            WHERE ($1::TEXT IS NULL OR provider = $1)
+            ORDER BY effective_from
+            "#,
+            provider
+        )
+        .fetch_all(db.pool())
+        .await?;
+
+        let mut rates: HashMap<String, Vec<PriceRate>> = HashMap::new();
+        for row in rows {
+            let unit = PriceUnit::parse(&row.unit)
+                .ok_or_else(|| AppError::Internal(format!("unknown price unit {}", row.unit)))?;
+            rates.entry(row.provider.clone()).or_default().push(PriceRate {
+                id: row.id,
+                provider: row.provider,
+                unit,
+                rate: row.rate,
+                effective_from: row.effective_from,
+                created_at: row.created_at,
+            });
+        }
+        Ok(Self { rates })
+    }
+
+    pub fn providers(&self) -> impl Iterator<Item = &String> {
+        self.rates.keys()
+    }
+
+    /// Every rate a provider has had, oldest first
+    pub fn history(&self, provider: &str) -> &[PriceRate] {
+        self.rates.get(provider).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// The latest rate of each unit a provider charged at `at`
+    pub fn rates_at(&self, provider: &str, at: DateTime<Utc>) -> Vec<&PriceRate> {
+        let mut current: HashMap<PriceUnit, &PriceRate> = HashMap::new();
+        for rate in self.rates.get(provider).into_iter().flatten() {
+            if rate.effective_from > at {
+                break;
+            }
+            current.insert(rate.unit, rate);
+        }
+        let mut rates: Vec<&PriceRate> = current.into_values().collect();
+        rates.sort_by_key(|rate| rate.unit.as_str());
+        rates
+    }
+
+    /// What a run costs at the rates in effect when it ran, or `None` when
+    /// its provider had no rates yet. Usage a run didn't report counts as
+    /// none, except CPU, which counts as one core.
+    pub fn estimate(&self, run: &SandboxRun) -> Option<f64> {
+        let rates = self.rates_at(&run.provider, run.created_at);
+        if rates.is_empty() {
+            return None;
+        }
+
+        let seconds = run.duration_ms as f64 / 1000.0;
+        let memory_gb = run
+            .memory_requested
+            .map(f64::from)
+            .or(run.memory_mb)
+            .unwrap_or(0.0)
+            / 1024.0;
+        let estimate = rates
+            .iter()
+            .map(|rate| {
+                let quantity = match rate.unit {
+                    PriceUnit::Run => 1.0,
+                    PriceUnit::Second => seconds,
+                    PriceUnit::CpuSecond => run.cpu_requested.unwrap_or(1.0) * seconds,
+                    PriceUnit::GbSecond => memory_gb * seconds,
+                    PriceUnit::GpuSecond => run.gpu_seconds.unwrap_or(0.0),
+                    PriceUnit::EgressGb => run.network_tx_bytes.unwrap_or(0) as f64 / 1e9,
+                };
+                rate.rate * quantity
+            })
+            .sum();
+        Some(estimate)
+    }
+}
+
+/// How far a reported cost is off from its estimate, relative to the larger
+/// of the two: 0 when they agree, 1 when one of them is zero
+pub fn divergence(cost: f64, estimate: f64) -> f64 {
+    let scale = cost.abs().max(estimate.abs());
+    if scale == 0.0 {
+        0.0
+    } else {
+        (cost - estimate).abs() / scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    fn day(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, day, 0, 0, 0).unwrap()
+    }
+
+    fn rate(unit: PriceUnit, rate: f64, effective_from: DateTime<Utc>) -> PriceRate {
+        PriceRate {
+            id: Uuid::new_v4(),
+            provider: "e2b".to_string(),
+            unit,
+            rate,
+            effective_from,
+            created_at: effective_from,
+        }
+    }
+
+    #[test]
+    fn looks_up_the_rates_in_effect_at_a_time() {
+        let catalog = PriceCatalog {
+            rates: HashMap::from([(
+                "e2b".to_string(),
+                vec![
+                    rate(PriceUnit::Second, 0.001, day(1)),
+                    rate(PriceUnit::Run, 0.05, day(5)),
+                    rate(PriceUnit::Second, 0.002, day(10)),
+                ],
+            )]),
+        };
+        let rates_at = |at| {
+            catalog
+                .rates_at("e2b", at)
+                .into_iter()
+                .map(|rate| (rate.unit, rate.rate))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(catalog.rates_at("e2b", day(1) - chrono::Duration::seconds(1)).is_empty());
+        assert_eq!(rates_at(day(1)), [(PriceUnit::Second, 0.001)]);
+        assert_eq!(rates_at(day(7)), [(PriceUnit::Run, 0.05), (PriceUnit::Second, 0.001)]);
+        assert_eq!(rates_at(day(20)), [(PriceUnit::Run, 0.05), (PriceUnit::Second, 0.002)]);
+        assert!(catalog.rates_at("modal", day(20)).is_empty());
+    }
+
+    #[test]
+    fn divergence_is_relative_to_the_larger_cost() {
+        for (cost, estimate, expected) in [
+            (0.0, 0.0, 0.0),
+            (1.0, 1.0, 0.0),
+            (1.0, 0.0, 1.0),
+            (0.0, 2.0, 1.0),
+            (0.8, 1.0, 0.2),
+            (1.0, 0.8, 0.2),
+        ] {
+            let divergence = divergence(cost, estimate);
+            assert!((divergence - expected).abs() < 1e-9, "{} vs {}: {}", cost, estimate, divergence);
+        }
+    }
+}
@@ -0,0 +1,510 @@
+use chrono::{DateTime, Months, NaiveDate, Utc};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+use crate::db::Database;
+use crate::error::{AppError, AppResult};
+use crate::models::{LabelUsage, ProviderUsage, ReportFormat, TenantUsage, UsageReportContent};
+
+/// Start and end of a `YYYY-MM` month in UTC
+pub fn month_period(month: &str) -> AppResult<(DateTime<Utc>, DateTime<Utc>)> {
+    let invalid = || AppError::Validation(format!("month must be YYYY-MM, got {}", month));
+    if month.len() != 7 {
+        return Err(invalid());
+    }
+    let start = NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d")
+        .map_err(|_| invalid())?;
+    let end = start.checked_add_months(Months::new(1)).ok_or_else(invalid)?;
+    Ok((
+        start.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc(),
+        end.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc(),
+    ))
+}
+
+/// Usage of one tenant, or of every tenant, over a month, counting only
+/// runs carrying all of `labels` and breaking each tenant's usage down by
+/// the values of `group_by_label`
+pub async fn usage(
+    db: &Database,
+    month: &str,
+    tenant: Option<&str>,
+    group_by_label: Option<&str>,
+    labels: &BTreeMap<String, String>,
+) -> AppResult<UsageReportContent> {
+    let (period_start, period_end) = month_period(month)?;
+    let label_filter = serde_json::to_value(labels)?;
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            tenant,
+            provider,
+            COUNT(*) AS "sandboxes!",
+            COALESCE(SUM(duration_ms), 0)::FLOAT8 / 1000.0 AS "compute_seconds!",
+            COALESCE(SUM(cost), 0) AS "cost!",
+            SUM(estimated_cost) AS estimated_cost,
+            COUNT(*) FILTER (WHERE NOT success) AS "failures!"
+        FROM sandbox_runs
+        WHERE created_at >= $1 AND created_at < $2
+          AND ($3::TEXT IS NULL OR tenant = $3)
+          AND labels @> $4
+        GROUP BY tenant, provider
+        ORDER BY tenant, COUNT(*) DESC, provider
+        "#,
+        period_start,
+        period_end,
+        tenant,
+        label_filter
+    )
+    .fetch_all(db.pool())
+    .await?;
+
+    let mut tenants: Vec<TenantUsage> = Vec::new();
+    for row in rows {
+        if tenants.last().is_none_or(|usage| usage.tenant != row.tenant) {
+            tenants.push(TenantUsage {
+                tenant: row.tenant.clone(),
+                sandboxes: 0,
+                compute_seconds: 0.0,
+                cost: 0.0,
+                estimated_cost: None,
+                failures: 0,
+                failure_rate: 0.0,
+                providers: Vec::new(),
+                labels: Vec::new(),
+            });
+        }
+        let Some(usage) = tenants.last_mut() else {
+            continue;
+        };
+        usage.sandboxes += row.sandboxes;
+        usage.compute_seconds += row.compute_seconds;
+        usage.cost += row.cost;
+        if let Some(estimate) = row.estimated_cost {
+            *usage.estimated_cost.get_or_insert(0.0) += estimate;
+        }
+        usage.failures += row.failures;
+        usage.providers.push(ProviderUsage {
+            provider: row.provider,
+            sandboxes: row.sandboxes,
+            share: 0.0,
+            compute_seconds: row.compute_seconds,
+            cost: row.cost,
+            estimated_cost: row.estimated_cost,
+            failures: row.failures,
+            failure_rate: ratio(row.failures, row.sandboxes),
+        });
+    }
+
+    if let Some(key) = group_by_label {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                tenant AS "tenant!",
+                labels ->> $5 AS value,
+                COUNT(*) AS "sandboxes!",
+                COALESCE(SUM(duration_ms), 0)::FLOAT8 / 1000.0 AS "compute_seconds!",
+                COALESCE(SUM(cost), 0) AS "cost!",
+                SUM(estimated_cost) AS estimated_cost,
+                COUNT(*) FILTER (WHERE NOT success) AS "failures!"
+            FROM sandbox_runs
+            WHERE created_at >= $1 AND created_at < $2
+              AND ($3::TEXT IS NULL OR tenant = $3)
+              AND labels @> $4
+            GROUP BY 1, 2
+            ORDER BY 1, 3 DESC, 2
+            "#,
+            period_start,
+            period_end,
+            tenant,
+            label_filter,
+            key
+        )
+        .fetch_all(db.pool())
+        .await?;
+
+        for row in rows {
+            let Some(usage) = tenants.iter_mut().find(|usage| usage.tenant == row.tenant) else {
+                continue;
+            };
+            usage.labels.push(LabelUsage {
+                value: row.value,
+                sandboxes: row.sandboxes,
+                share: 0.0,
+                compute_seconds: row.compute_seconds,
+                cost: row.cost,
+                estimated_cost: row.estimated_cost,
+                failures: row.failures,
+                failure_rate: ratio(row.failures, row.sandboxes),
+            });
+        }
+    }
+
+    for usage in &mut tenants {
+        usage.failure_rate = ratio(usage.failures, usage.sandboxes);
+        for provider in &mut usage.providers {
+            provider.share = ratio(provider.sandboxes, usage.sandboxes);
+        }
+        for label in &mut usage.labels {
+            label.share = ratio(label.sandboxes, usage.sandboxes);
+        }
+    }
+
+    Ok(UsageReportContent {
+        month: month.to_string(),
+        period_start,
+        period_end,
+        generated_at: Utc::now(),
+        group_by_label: group_by_label.map(str::to_string),
+        labels: labels.clone(),
+        tenants,
+    })
+}
+
+fn ratio(part: i64, whole: i64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+/// A report in a download format
+pub fn render(content: &UsageReportContent, format: ReportFormat) -> AppResult<Vec<u8>> {
+    Ok(match format {
+        ReportFormat::Json => serde_json::to_vec_pretty(content)?,
+        ReportFormat::Csv => csv(content).into_bytes(),
+        ReportFormat::Pdf => pdf(&text_lines(content)),
+    })
+}
+
+pub fn content_type(format: ReportFormat) -> &'static str {
+    match format {
+        ReportFormat::Json => "application/json",
+        ReportFormat::Csv => "text/csv",
+        ReportFormat::Pdf => "application/pdf",
+    }
+}
+
+/// A tenant's usage as one row alongside its providers
+fn total(usage: &TenantUsage, name: &str) -> ProviderUsage {
+    ProviderUsage {
+        provider: name.to_string(),
+        sandboxes: usage.sandboxes,
+        share: 1.0,
+        compute_seconds: usage.compute_seconds,
+        cost: usage.cost,
+        estimated_cost: usage.estimated_cost,
+        failures: usage.failures,
+        failure_rate: usage.failure_rate,
+    }
+}
+
+/// Usage of the runs sharing a label value as one row alongside a
+/// tenant's providers
+fn by_label(label: &LabelUsage, name: &str) -> ProviderUsage {
+    ProviderUsage {
+        provider: name.to_string(),
+        sandboxes: label.sandboxes,
+        share: label.share,
+        compute_seconds: label.compute_seconds,
+        cost: label.cost,
+        estimated_cost: label.estimated_cost,
+        failures: label.failures,
+        failure_rate: label.failure_rate,
+    }
+}
+
+/// How a label value is shown, `(none)` for runs without the label
+fn label_value(label: &LabelUsage) -> &str {
+    label.value.as_deref().unwrap_or("(none)")
+}
+
+/// One row per tenant and provider, then the tenant's total under provider
+/// `all`, for loading into billing spreadsheets. Reports grouped by a label
+/// add a row per value of it, under provider `all` with the value in the
+/// `label` column.
+fn csv(content: &UsageReportContent) -> String {
+    let mut out = String::from(
+        "month,tenant,provider,sandboxes,share,compute_seconds,cost,estimated_cost,failures,failure_rate,label\n",
+    );
+    for usage in &content.tenants {
+        let providers = usage.providers.iter().cloned().map(|row| (row, String::new()));
+        let total = (total(usage, "all"), String::new());
+        let labels = usage
+            .labels
+            .iter()
+            .map(|label| (by_label(label, "all"), label_value(label).to_string()));
+        for (row, label) in providers.chain([total]).chain(labels) {
+            let _ = writeln!(
+                out,
+                "{},{},{},{},{:.4},{:.3},{:.6},{},{},{:.4},{}",
+                content.month,
+                csv_field(&usage.tenant),
+                csv_field(&row.provider),
+                row.sandboxes,
+                row.share,
+                row.compute_seconds,
+                row.cost,
+                row.estimated_cost
+                    .map(|cost| format!("{:.6}", cost))
+                    .unwrap_or_default(),
+                row.failures,
+                row.failure_rate,
+                csv_field(&label)
+            );
+        }
+    }
+    out
+}
+
+/// Quote a field holding a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// The report as fixed-width text, for the PDF
+fn text_lines(content: &UsageReportContent) -> Vec<String> {
+    let mut lines = vec![
+        format!("Sandstorm usage report for {}", content.month),
+        format!(
+            "Period {} to {}, generated {}",
+            content.period_start.format("%Y-%m-%d"),
+            content.period_end.format("%Y-%m-%d"),
+            content.generated_at.format("%Y-%m-%d %H:%M UTC")
+        ),
+        String::new(),
+    ];
+    if !content.labels.is_empty() {
+        let labels: Vec<String> = content
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        lines.insert(2, format!("Runs labelled {}", labels.join(", ")));
+    }
+    if content.tenants.is_empty() {
+        lines.push("No sandboxes ran in this period.".to_string());
+    }
+    for usage in &content.tenants {
+        lines.push(format!("Tenant {}", usage.tenant));
+        let providers: Vec<ProviderUsage> = usage
+            .providers
+            .iter()
+            .cloned()
+            .chain([total(usage, "Total")])
+            .collect();
+        table(&mut lines, "Provider", &providers);
+        if let Some(key) = content.group_by_label.as_deref() {
+            let labels: Vec<ProviderUsage> = usage
+                .labels
+                .iter()
+                .map(|label| by_label(label, label_value(label)))
+                .collect();
+            lines.push(String::new());
+            table(&mut lines, key, &labels);
+        }
+        lines.push(String::new());
+    }
+    lines
+}
+
+/// Usage rows under a heading naming what their first column is
+fn table(lines: &mut Vec<String>, heading: &str, rows: &[ProviderUsage]) {
+    lines.push(format!(
+        "  {:<20} {:>9} {:>7} {:>12} {:>12} {:>12} {:>8}",
+        heading.chars().take(20).collect::<String>(),
+        "Sandboxes",
+        "Share",
+        "Compute (s)",
+        "Cost ($)",
+        "Catalog ($)",
+        "Failed"
+    ));
+    for row in rows {
+        lines.push(format!(
+            "  {:<20} {:>9} {:>6.1}% {:>12.0} {:>12.2} {:>12} {:>7.1}%",
+            row.provider.chars().take(20).collect::<String>(),
+            row.sandboxes,
+            row.share * 100.0,
+            row.compute_seconds,
+            row.cost,
+            row.estimated_cost
+                .map(|cost| format!("{:.2}", cost))
+                .unwrap_or_else(|| "-".to_string()),
+            row.failure_rate * 100.0
+        ));
+    }
+}
+
+/// Lines of text per PDF page
+const PDF_PAGE_LINES: usize = 60;
+
+/// A minimal PDF of text lines in Courier on A4 portrait pages
+fn pdf(lines: &[String]) -> Vec<u8> {
+    let pages: Vec<&[String]> = if lines.is_empty() {
+        vec![&[]]
+    } else {
+        lines.chunks(PDF_PAGE_LINES).collect()
+    };
+
+    // Objects 1 and 2 are the catalog and page tree, 3 the font, then a
+    // page and its content stream for each page
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..pages.len())
+                .map(|page| format!("{} 0 R", 4 + page * 2))
+                .collect::<Vec<_>>()
+                .join(" "),
+            pages.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Courier >>".to_string(),
+    ];
+    for (page, page_lines) in pages.iter().enumerate() {
+        let mut stream = String::from("BT /F1 8 Tf 11 TL 36 806 Td\n");
+        for line in page_lines.iter() {
+            let _ = writeln!(stream, "({}) '", pdf_escape(line));
+        }
+        stream.push_str("ET");
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 595 842] /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            5 + page * 2
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}\nendstream",
+            stream.len(),
+            stream
+        ));
+    }
+
+    let mut out = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        let _ = write!(out, "{} 0 obj\n{}\nendobj\n", index + 1, object);
+    }
+    let xref = out.len();
+    let _ = write!(out, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        let _ = writeln!(out, "{:010} 00000 n ", offset);
+    }
+    let _ = write!(
+        out,
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    );
+    out.into_bytes()
+}
+
+/// Escape a line for a PDF string, replacing what Courier can't show
+fn pdf_escape(line: &str) -> String {
+    line.chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() { c } else { '?' })
+        .fold(String::with_capacity(line.len()), |mut out, c| {
+            if matches!(c, '(' | ')' | '\\') {
+                out.push('\\');
+            }
+            out.push(c);
+            out
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn provider(provider: &str, sandboxes: i64, cost: f64, estimated_cost: Option<f64>, failures: i64) -> ProviderUsage {
+        ProviderUsage {
+            provider: provider.to_string(),
+            sandboxes,
+            share: sandboxes as f64 / 4.0,
+            compute_seconds: sandboxes as f64 * 30.0,
+            cost,
+            estimated_cost,
+            failures,
+            failure_rate: ratio(failures, sandboxes),
+        }
+    }
+
+    fn content() -> UsageReportContent {
+        let (period_start, period_end) = month_period("2024-03").unwrap();
+        UsageReportContent {
+            month: "2024-03".to_string(),
+            period_start,
+            period_end,
+            generated_at: Utc.with_ymd_and_hms(2024, 4, 1, 6, 0, 0).unwrap(),
+            group_by_label: Some("team".to_string()),
+            labels: BTreeMap::new(),
+            tenants: vec![TenantUsage {
+                tenant: "acme, inc".to_string(),
+                sandboxes: 4,
+                compute_seconds: 120.0,
+                cost: 1.5,
+                estimated_cost: Some(1.25),
+                failures: 1,
+                failure_rate: 0.25,
+                providers: vec![
+                    provider("e2b", 3, 1.0, Some(1.25), 1),
+                    provider("modal", 1, 0.5, None, 0),
+                ],
+                labels: vec![LabelUsage {
+                    value: None,
+                    sandboxes: 4,
+                    share: 1.0,
+                    compute_seconds: 120.0,
+                    cost: 1.5,
+                    estimated_cost: None,
+                    failures: 1,
+                    failure_rate: 0.25,
+                }],
+            }],
+        }
+    }
+
+    #[test]
+    fn csv_has_a_row_per_provider_then_totals_and_labels() {
+        let csv = String::from_utf8(render(&content(), ReportFormat::Csv).unwrap()).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines, [
+            "month,tenant,provider,sandboxes,share,compute_seconds,cost,estimated_cost,failures,failure_rate,label",
+            "2024-03,\"acme, inc\",e2b,3,0.7500,90.000,1.000000,1.250000,1,0.3333,",
+            "2024-03,\"acme, inc\",modal,1,0.2500,30.000,0.500000,,0,0.0000,",
+            "2024-03,\"acme, inc\",all,4,1.0000,120.000,1.500000,1.250000,1,0.2500,",
+            "2024-03,\"acme, inc\",all,4,1.0000,120.000,1.500000,,1,0.2500,(none)",
+        ]);
+    }
+
+    #[test]
+    fn pdf_cross_references_point_at_their_objects() {
+        // Enough lines for two pages
+        let mut lines = text_lines(&content());
+        lines.extend((0..PDF_PAGE_LINES).map(|line| format!("line ({})", line)));
+        let pdf = String::from_utf8(pdf(&lines)).unwrap();
+        assert!(pdf.starts_with("%PDF-1.4\n"));
+        assert!(pdf.contains("/Count 2"));
+
+        let startxref: usize = pdf
+            .rsplit("startxref\n")
+            .next()
+            .and_then(|tail| tail.lines().next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(pdf[startxref..].starts_with("xref\n0 8\n0000000000 65535 f \n"));
+
+        let entries: Vec<&str> = pdf[startxref..].lines().skip(3).take(7).collect();
+        for (index, entry) in entries.iter().enumerate() {
+            assert_eq!(entry.len(), 19, "{:?}", entry);
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(pdf[offset..].starts_with(&format!("{} 0 obj\n", index + 1)), "object {}", index + 1);
+        }
+        assert!(pdf.contains("(line \\(0\\)) '"));
+    }
+}